@@ -0,0 +1,112 @@
+//! Carga una wallet a partir de su private key y le envia amount satoshis a una direccion, pagando fee.
+//! Con --dry-run solo muestra la transaccion planificada, sin firmarla ni enviarla.
+//! Uso: cargo run --example send_payment -- <archivo de config> <private key> <direccion> <amount> <fee> [--dry-run]
+
+use std::{env, process, time::Duration};
+
+use bitcoin::{
+    crash_report::{exit_code, EXIT_CONFIG_ERROR},
+    error::CustomError,
+    node_handle::NodeHandle,
+    rpc::RpcCall,
+    send_many::{SendManyRequest, SendManyResult},
+    wallet::addresses_from_privkey,
+};
+
+const DRY_RUN_FLAG: &str = "--dry-run";
+const SYNC_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let dry_run = args.iter().any(|arg| arg == DRY_RUN_FLAG);
+    let args: Vec<&String> = args.iter().filter(|arg| *arg != DRY_RUN_FLAG).collect();
+    let [_, config_path, privkey, address, amount, fee] = args.as_slice() else {
+        println!("Usage: send_payment <config file> <private key> <address> <amount> <fee> [{DRY_RUN_FLAG}]");
+        process::exit(EXIT_CONFIG_ERROR);
+    };
+    let (Ok(amount), Ok(fee)) = (amount.parse::<u64>(), fee.parse::<u64>()) else {
+        println!("ERROR: amount and fee must be satoshis");
+        process::exit(EXIT_CONFIG_ERROR);
+    };
+
+    if let Err(error) = run(config_path, privkey, address, amount, fee, dry_run) {
+        println!("ERROR: {error}");
+        process::exit(exit_code(&error));
+    }
+}
+
+/// Arranca el nodo, agrega la wallet de la private key (si no estaba) y la deja activa. Una vez sincronizado
+/// planifica el envio y, si no es dry_run, lo envia por el mismo camino que el RPC sendtoaddress.
+fn run(
+    config_path: &str,
+    privkey: &str,
+    address: &str,
+    amount: u64,
+    fee: u64,
+    dry_run: bool,
+) -> Result<(), CustomError> {
+    let node = NodeHandle::from_config_file(config_path)?;
+    let result = send(&node, privkey, address, amount, fee, dry_run);
+    node.stop()?;
+    result
+}
+
+fn send(
+    node: &NodeHandle,
+    privkey: &str,
+    address: &str,
+    amount: u64,
+    fee: u64,
+    dry_run: bool,
+) -> Result<(), CustomError> {
+    let (compressed, uncompressed) = addresses_from_privkey(privkey)?;
+    let mut node_state = node.node_state_ref.lock()?;
+    let wallet = node_state
+        .get_wallets()
+        .iter()
+        .find(|wallet| wallet.pubkey == compressed || wallet.pubkey == uncompressed)
+        .map(|wallet| wallet.pubkey.clone());
+    let wallet = match wallet {
+        Some(wallet) => wallet,
+        None => {
+            node_state.append_wallet(
+                String::from("payments"),
+                compressed.clone(),
+                privkey.to_string(),
+            )?;
+            compressed
+        }
+    };
+    node_state.change_wallet(wallet.clone())?;
+    drop(node_state);
+
+    println!("Waiting for the node to sync...");
+    if !node.wait_until(SYNC_TIMEOUT, |node_state| node_state.is_synced())? {
+        return Err(CustomError::NodeNotSynced);
+    }
+
+    let mut request = SendManyRequest::new(vec![(address.to_string(), amount)], fee);
+    request.dry_run = true;
+    let plan = match node.node_state_ref.lock()?.send_many(request)? {
+        SendManyResult::Planned(plan) => plan,
+        SendManyResult::Sent { .. } => unreachable!("dry_run requests are only planned"),
+    };
+    println!(
+        "{wallet} -> {address}: {amount} sats, {} inputs, fee {} sats ({:.1} sat/byte), change {} sats",
+        plan.inputs.len(),
+        plan.fee,
+        plan.fee_rate(),
+        plan.change
+    );
+    if dry_run {
+        return Ok(());
+    }
+
+    let txid = node.rpc(RpcCall::SendToAddress {
+        address: address.to_string(),
+        amount,
+        fee,
+    })?;
+    println!("Sent {txid}");
+    Ok(())
+}
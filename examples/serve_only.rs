@@ -0,0 +1,46 @@
+//! Corre un nodo que acepta conexiones entrantes sobre un store ya preparado (por ejemplo con --import-utxo),
+//! mostrando su estado periodicamente. Termina con Ctrl-C.
+//! Uso: cargo run --example serve_only -- <archivo de config>
+
+use std::{env, process, thread, time::Duration};
+
+use bitcoin::{
+    config::Config,
+    console_status::format_status_line,
+    crash_report::{exit_code, EXIT_CONFIG_ERROR},
+    error::CustomError,
+    node_handle::NodeHandle,
+};
+
+const STATUS_INTERVAL: Duration = Duration::from_secs(10);
+const STATUS_WIDTH: usize = 120;
+
+fn main() {
+    let Some(config_path) = env::args().nth(1) else {
+        println!("Usage: serve_only <config file>");
+        process::exit(EXIT_CONFIG_ERROR);
+    };
+
+    if let Err(error) = run(&config_path) {
+        println!("ERROR: {error}");
+        process::exit(exit_code(&error));
+    }
+}
+
+/// Arranca el nodo si el config acepta conexiones entrantes y muestra su linea de estado cada STATUS_INTERVAL.
+fn run(config_path: &str) -> Result<(), CustomError> {
+    let config = Config::from_file(config_path)?;
+    if !config.listens() {
+        return Err(CustomError::ConfigInvalid(String::from(
+            "serve_only needs a node that accepts connections (LISTEN=true, without CLIENT_ONLY nor SPV_MODE)",
+        )));
+    }
+
+    let node = NodeHandle::start(&config)?;
+    println!("Serving on port {}", config.port);
+    loop {
+        let snapshot = node.node_state_ref.lock()?.get_status_snapshot()?;
+        println!("{}", format_status_line(&snapshot, STATUS_WIDTH));
+        thread::sleep(STATUS_INTERVAL);
+    }
+}
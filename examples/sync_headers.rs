@@ -0,0 +1,53 @@
+//! Se conecta a los peers del config, sincroniza los headers y muestra el ultimo.
+//! Uso: cargo run --example sync_headers -- <archivo de config>
+
+use std::{env, process, time::Duration};
+
+use bitcoin::{
+    console_status::SyncPhase,
+    crash_report::{exit_code, EXIT_CONFIG_ERROR},
+    error::CustomError,
+    node_handle::NodeHandle,
+    structs::block_header::display_hash,
+};
+
+const SYNC_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+fn main() {
+    let Some(config_path) = env::args().nth(1) else {
+        println!("Usage: sync_headers <config file>");
+        process::exit(EXIT_CONFIG_ERROR);
+    };
+
+    match run(&config_path, SYNC_TIMEOUT) {
+        Ok((height, hash)) => println!("Headers synced, tip at height {height}: {hash}"),
+        Err(error) => {
+            println!("ERROR: {error}");
+            process::exit(exit_code(&error));
+        }
+    }
+}
+
+/// Arranca el nodo del config, espera a que termine la descarga de headers y lo termina.
+/// Devuelve la altura y el hash del ultimo header, o CustomError::NodeNotSynced si no termina en timeout.
+pub fn run(config_path: &str, timeout: Duration) -> Result<(usize, String), CustomError> {
+    let node = NodeHandle::from_config_file(config_path)?;
+    let synced = node.wait_until(timeout, |node_state| {
+        node_state
+            .get_status_snapshot()
+            .is_ok_and(|status| status.phase != SyncPhase::Headers)
+    });
+
+    let tip = match synced {
+        Ok(true) => {
+            let node_state = node.node_state_ref.lock()?;
+            let height = node_state.get_status_snapshot()?.tip_height;
+            let hash = node_state.get_last_header_hash().unwrap_or_default();
+            Ok((height, display_hash(&hash)))
+        }
+        Ok(false) => Err(CustomError::NodeNotSynced),
+        Err(error) => Err(error),
+    };
+    node.stop()?;
+    tip
+}
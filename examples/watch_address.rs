@@ -0,0 +1,92 @@
+//! Sigue una direccion como wallet watch-only y muestra los pagos a medida que llegan,
+//! primero como pendientes y despues confirmados. Termina con Ctrl-C.
+//! Uso: cargo run --example watch_address -- <archivo de config> <direccion>
+
+use std::{collections::HashSet, env, process, time::Duration};
+
+use bitcoin::{
+    crash_report::{exit_code, EXIT_CONFIG_ERROR},
+    error::CustomError,
+    gui::init::GUIEvents,
+    node_handle::NodeHandle,
+    node_state::NodeState,
+    structs::{block_header::display_hash, movement::Movement},
+};
+
+const EVENTS_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let [_, config_path, address] = args.as_slice() else {
+        println!("Usage: watch_address <config file> <address>");
+        process::exit(EXIT_CONFIG_ERROR);
+    };
+
+    if let Err(error) = run(config_path, address) {
+        println!("ERROR: {error}");
+        process::exit(exit_code(&error));
+    }
+}
+
+/// Arranca el nodo, agrega la direccion como wallet watch-only (si no estaba) y la deja activa.
+/// Despues muestra los movimientos nuevos cada vez que el nodo avisa que cambio alguna wallet.
+fn run(config_path: &str, address: &str) -> Result<(), CustomError> {
+    let node = NodeHandle::from_config_file(config_path)?;
+    let mut node_state = node.node_state_ref.lock()?;
+    if !node_state
+        .get_wallets()
+        .iter()
+        .any(|wallet| wallet.pubkey == address)
+    {
+        node_state.append_watch_only_wallet(String::from("watched"), address.to_string())?;
+    }
+    node_state.change_wallet(address.to_string())?;
+    drop(node_state);
+    println!("Watching {address}");
+
+    let mut shown = HashSet::new();
+    print_new_movements(&node, &mut shown)?;
+    loop {
+        match node.next_event(EVENTS_TIMEOUT)? {
+            Some(GUIEvents::WalletsUpdated { affected })
+                if affected.iter().any(|pubkey| pubkey == address) => {}
+            Some(GUIEvents::NewPendingTx) | Some(GUIEvents::NodeStateReady) => {}
+            _ => continue,
+        }
+        print_new_movements(&node, &mut shown)?;
+    }
+}
+
+/// Muestra los movimientos de la wallet activa que todavia no se mostraron. Cada movimiento se muestra
+/// una vez como pendiente y otra al confirmarse.
+fn print_new_movements(
+    node: &NodeHandle,
+    shown: &mut HashSet<(Vec<u8>, bool)>,
+) -> Result<(), CustomError> {
+    let mut node_state = node.node_state_ref.lock()?;
+    for movement in wallet_movements(&mut node_state)? {
+        let confirmed = movement.block_hash.is_some();
+        if shown.insert((movement.tx_hash.clone(), confirmed)) {
+            let status = if confirmed { "confirmed" } else { "pending" };
+            println!(
+                "{} {} sats ({status})",
+                display_hash(&movement.tx_hash),
+                movement.value
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Devuelve los movimientos del historial de la wallet activa seguidos de sus transacciones pendientes.
+fn wallet_movements(node_state: &mut NodeState) -> Result<Vec<Movement>, CustomError> {
+    let mut movements: Vec<Movement> = node_state
+        .get_active_wallet_history()?
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|view| !view.is_abandoned())
+        .map(|view| view.movement)
+        .collect();
+    movements.extend(node_state.get_active_wallet_pending_txs()?);
+    Ok(movements)
+}
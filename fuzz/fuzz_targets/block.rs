@@ -0,0 +1,11 @@
+#![no_main]
+
+use bitcoin::{message::Message, messages::block::Block};
+use libfuzzer_sys::fuzz_target;
+
+// Todo bloque que se parsea correctamente tiene que volver a serializarse en los mismos bytes.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(block) = Block::parse(data.to_vec()) {
+        assert_eq!(block.serialize(), data);
+    }
+});
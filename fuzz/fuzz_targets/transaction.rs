@@ -0,0 +1,11 @@
+#![no_main]
+
+use bitcoin::{message::Message, messages::transaction::Transaction};
+use libfuzzer_sys::fuzz_target;
+
+// Toda transaccion que se parsea correctamente tiene que volver a serializarse en los mismos bytes.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(transaction) = Transaction::parse(data.to_vec()) {
+        assert_eq!(transaction.serialize(), data);
+    }
+});
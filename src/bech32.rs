@@ -0,0 +1,282 @@
+use crate::error::CustomError;
+
+/// Prefijo (human readable part) de las direcciones segwit de testnet.
+pub const TESTNET_HRP: &str = "tb";
+
+/// Largo en bytes del witness program de una direccion P2WPKH, el hash160 de la public key.
+pub const P2WPKH_PROGRAM_LEN: usize = 20;
+
+/// Caracteres de la codificacion bech32, cada uno representa 5 bits.
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Constante del checksum de bech32 (BIP 173), la que usan las direcciones de witness version 0.
+const BECH32_CONST: u32 = 1;
+
+/// Constante del checksum de bech32m (BIP 350), la que usan las direcciones de witness version 1 a 16.
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+
+/// Generador del polinomio del checksum.
+const GENERATOR: [u32; 5] = [
+    0x3b6a_57b2,
+    0x2650_8e6d,
+    0x1ea1_19fa,
+    0x3d42_33dd,
+    0x2a14_62b3,
+];
+
+/// Cantidad de caracteres del checksum, al final de la direccion.
+const CHECKSUM_LEN: usize = 6;
+
+/// Largo maximo de una direccion bech32.
+const MAX_ADDRESS_LEN: usize = 90;
+
+/// Devuelve true si la direccion parece una direccion segwit de testnet (empieza con "tb1"), sin validarla.
+pub fn is_segwit_address(address: &str) -> bool {
+    address.to_lowercase().starts_with("tb1")
+}
+
+/// Decodifica una direccion segwit (BIP 173 y BIP 350) con el prefijo hrp.
+/// Devuelve la witness version y el witness program.
+/// Devuelve CustomError::Validation si mezcla mayusculas y minusculas, tiene otro prefijo, caracteres invalidos,
+/// el checksum no coincide (bech32 para la version 0 y bech32m para las demas) o el witness program tiene un largo invalido.
+pub fn decode_segwit_address(address: &str, hrp: &str) -> Result<(u8, Vec<u8>), CustomError> {
+    if address.len() > MAX_ADDRESS_LEN {
+        return Err(invalid("Segwit address is too long"));
+    }
+    if address.chars().any(|c| c.is_ascii_lowercase())
+        && address.chars().any(|c| c.is_ascii_uppercase())
+    {
+        return Err(invalid("Segwit address mixes upper and lower case"));
+    }
+    let address = address.to_lowercase();
+    let Some((address_hrp, data)) = address.rsplit_once('1') else { return Err(invalid("Segwit address has no separator")) };
+    if address_hrp != hrp {
+        return Err(invalid("Address is not a testnet segwit address"));
+    }
+    if data.len() < CHECKSUM_LEN + 1 {
+        return Err(invalid("Segwit address is too short"));
+    }
+
+    let values = data
+        .bytes()
+        .map(|c| CHARSET.iter().position(|charset_c| *charset_c == c))
+        .collect::<Option<Vec<usize>>>()
+        .ok_or_else(|| invalid("Segwit address has invalid characters"))?;
+    let values: Vec<u8> = values.into_iter().map(|value| value as u8).collect();
+
+    let mut checked = hrp_expand(hrp);
+    checked.extend(&values);
+    let version = values[0];
+    let expected_const = match version {
+        0 => BECH32_CONST,
+        _ => BECH32M_CONST,
+    };
+    if polymod(&checked) != expected_const {
+        return Err(invalid("Segwit address checksum does not match"));
+    }
+
+    let program = convert_bits(&values[1..values.len() - CHECKSUM_LEN], 5, 8, false)
+        .ok_or_else(|| invalid("Segwit address has an invalid witness program"))?;
+    if version > 16 || !(2..=40).contains(&program.len()) {
+        return Err(invalid("Segwit address has an invalid witness program"));
+    }
+    if version == 0 && program.len() != P2WPKH_PROGRAM_LEN && program.len() != 32 {
+        return Err(invalid("Segwit address has an invalid witness program"));
+    }
+    Ok((version, program))
+}
+
+/// Codifica una direccion segwit con el prefijo hrp, la witness version y el witness program.
+/// Es la inversa de decode_segwit_address.
+pub fn encode_segwit_address(hrp: &str, version: u8, program: &[u8]) -> String {
+    let mut values = vec![version];
+    values.extend(convert_bits(program, 8, 5, true).unwrap_or_default());
+
+    let mut checked = hrp_expand(hrp);
+    checked.extend(&values);
+    checked.extend([0; CHECKSUM_LEN]);
+    let checksum_const = match version {
+        0 => BECH32_CONST,
+        _ => BECH32M_CONST,
+    };
+    let checksum = polymod(&checked) ^ checksum_const;
+    values.extend((0..CHECKSUM_LEN).map(|i| ((checksum >> (5 * (5 - i))) & 31) as u8));
+
+    let data: String = values
+        .iter()
+        .map(|value| CHARSET[*value as usize] as char)
+        .collect();
+    format!("{hrp}1{data}")
+}
+
+/// Devuelve el witness program (el hash160 de la public key) de una direccion P2WPKH de testnet.
+/// Devuelve CustomError::Validation si no es una direccion segwit valida o si es de otro tipo (por ejemplo P2WSH).
+pub fn p2wpkh_program(address: &str) -> Result<Vec<u8>, CustomError> {
+    match decode_segwit_address(address, TESTNET_HRP)? {
+        (0, program) if program.len() == P2WPKH_PROGRAM_LEN => Ok(program),
+        _ => Err(invalid("Only P2WPKH segwit addresses are supported")),
+    }
+}
+
+fn invalid(reason: &str) -> CustomError {
+    CustomError::Validation(reason.to_string())
+}
+
+/// Calcula el checksum de bech32 de los valores de 5 bits.
+fn polymod(values: &[u8]) -> u32 {
+    let mut checksum: u32 = 1;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x01ff_ffff) << 5) ^ *value as u32;
+        for (index, generator) in GENERATOR.iter().enumerate() {
+            if (top >> index) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+/// Expande el prefijo para el checksum: los 3 bits altos de cada caracter, un 0 y los 5 bits bajos de cada caracter.
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut expanded: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    expanded.push(0);
+    expanded.extend(hrp.bytes().map(|c| c & 31));
+    expanded
+}
+
+/// Reagrupa valores de from bits en valores de to bits.
+/// Si pad es true completa el ultimo valor con ceros, si no devuelve None si sobran bits distintos de cero o mas de from.
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut accumulator: u32 = 0;
+    let mut bits = 0;
+    let max_value = (1 << to) - 1;
+    let max_accumulator = (1 << (from + to - 1)) - 1;
+    let mut converted = vec![];
+    for value in data {
+        let value = *value as u32;
+        if value >> from != 0 {
+            return None;
+        }
+        accumulator = ((accumulator << from) | value) & max_accumulator;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            converted.push(((accumulator >> bits) & max_value) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            converted.push(((accumulator << (to - bits)) & max_value) as u8);
+        }
+    } else if bits >= from || ((accumulator << (to - bits)) & max_value) != 0 {
+        return None;
+    }
+    Some(converted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    #[test]
+    fn decode_bip173_testnet_vectors() {
+        let (version, program) =
+            decode_segwit_address("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx", TESTNET_HRP)
+                .unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(hex(&program), "751e76e8199196d454941c45d1b3a323f1433bd6");
+
+        let (version, program) = decode_segwit_address(
+            "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+            TESTNET_HRP,
+        )
+        .unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(
+            hex(&program),
+            "1863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262"
+        );
+
+        let (version, program) = decode_segwit_address(
+            "tb1qqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesrxh6hy",
+            TESTNET_HRP,
+        )
+        .unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(
+            hex(&program),
+            "000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433"
+        );
+    }
+
+    #[test]
+    fn decode_accepts_upper_case_addresses() {
+        let lower =
+            decode_segwit_address("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx", TESTNET_HRP);
+        let upper =
+            decode_segwit_address("TB1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KXPJZSX", TESTNET_HRP);
+        assert_eq!(lower.unwrap(), upper.unwrap());
+    }
+
+    #[test]
+    fn decode_rejects_invalid_addresses() {
+        for address in [
+            // mezcla mayusculas y minusculas
+            "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sL5k7",
+            // direccion de mainnet
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+            // checksum invalido
+            "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsy",
+            // caracter fuera del charset
+            "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsb",
+            // sin datos
+            "tb1",
+            "invalid_address",
+        ] {
+            assert!(
+                decode_segwit_address(address, TESTNET_HRP).is_err(),
+                "{address}"
+            );
+        }
+    }
+
+    #[test]
+    fn encode_is_the_inverse_of_decode() {
+        let program =
+            decode_segwit_address("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx", TESTNET_HRP)
+                .unwrap()
+                .1;
+        assert_eq!(
+            encode_segwit_address(TESTNET_HRP, 0, &program),
+            "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"
+        );
+
+        let taproot = [7; 32];
+        let encoded = encode_segwit_address(TESTNET_HRP, 1, &taproot);
+        assert_eq!(
+            decode_segwit_address(&encoded, TESTNET_HRP).unwrap(),
+            (1, taproot.to_vec())
+        );
+    }
+
+    #[test]
+    fn p2wpkh_program_only_accepts_p2wpkh_addresses() {
+        assert_eq!(
+            hex(&p2wpkh_program("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx").unwrap()),
+            "751e76e8199196d454941c45d1b3a323f1433bd6"
+        );
+        assert!(
+            p2wpkh_program("tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7")
+                .is_err()
+        );
+        assert!(is_segwit_address(
+            "TB1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KXPJZSX"
+        ));
+        assert!(!is_segwit_address("mscatccDgq7azndWHFTzvEuZuywCsUvTRu"));
+    }
+}
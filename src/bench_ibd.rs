@@ -0,0 +1,404 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    net::{TcpListener, TcpStream},
+    process,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use gtk::glib::{self, Priority};
+
+use crate::{
+    error::CustomError,
+    logger::Log,
+    message::{Message, MessageHeader, MESSAGE_HEADER_SIZE},
+    messages::{block::Block, get_data::GetData, headers::Headers},
+    node_state::NodeState,
+    structs::inventory::{Inventory, InventoryType},
+    synthetic_chain::{SyntheticChain, SYNTHETIC_MIN_DIFFICULTY_BITS},
+};
+
+/// Flag con el que se corre el benchmark del IBD en lugar del nodo.
+pub const BENCH_IBD_FLAG: &str = "--bench-ibd";
+
+const SYNTHETIC_SOURCE_PREFIX: &str = "synthetic:";
+const MIN_HEADERS_PER_SEC_FLAG: &str = "--assert-min-headers-per-sec";
+const MIN_BLOCKS_PER_SEC_FLAG: &str = "--assert-min-blocks-per-sec";
+const MIN_MB_PER_SEC_FLAG: &str = "--assert-min-mb-per-sec";
+
+const BENCH_USAGE: &str = "usage: --bench-ibd synthetic:<n_headers>,<n_blocks>,<avg_block_kb> \
+[--assert-min-headers-per-sec <n>] [--assert-min-blocks-per-sec <n>] [--assert-min-mb-per-sec <n>]";
+
+/// Cantidad de headers por mensaje headers, como los envia un peer.
+const HEADERS_BATCH: usize = 2000;
+
+/// Cantidad de bloques que se piden en cada getdata al peer simulado.
+const GETDATA_BATCH: usize = 16;
+
+/// BenchSource es el origen de los datos con los que se mide el IBD.
+/// - Synthetic: Cadena generada en memoria (ver SyntheticChain).
+pub enum BenchSource {
+    Synthetic {
+        n_headers: usize,
+        n_blocks: usize,
+        avg_block_kb: usize,
+    },
+}
+
+impl BenchSource {
+    /// Parsea el origen con el formato synthetic:<n_headers>,<n_blocks>,<avg_block_kb>.
+    /// Devuelve CustomError::InvalidBenchArguments si tiene otro formato (por ejemplo, una captura de trafico,
+    /// que todavia no se soporta).
+    pub fn parse(source: &str) -> Result<Self, CustomError> {
+        let values = source
+            .strip_prefix(SYNTHETIC_SOURCE_PREFIX)
+            .ok_or(CustomError::InvalidBenchArguments)?
+            .split(',')
+            .map(|value| value.trim().parse::<usize>())
+            .collect::<Result<Vec<usize>, _>>()
+            .map_err(|_| CustomError::InvalidBenchArguments)?;
+
+        match values[..] {
+            [n_headers, n_blocks, avg_block_kb] => Ok(Self::Synthetic {
+                n_headers,
+                n_blocks,
+                avg_block_kb,
+            }),
+            _ => Err(CustomError::InvalidBenchArguments),
+        }
+    }
+}
+
+#[derive(Default)]
+/// BenchThresholds son los minimos que tiene que cumplir el benchmark (flags --assert-min-*), para detectar regresiones.
+pub struct BenchThresholds {
+    pub min_headers_per_sec: Option<f64>,
+    pub min_blocks_per_sec: Option<f64>,
+    pub min_mb_per_sec: Option<f64>,
+}
+
+/// IbdBenchReport es el resultado del benchmark del IBD.
+/// Los elementos son:
+/// - headers: Cantidad de headers agregados.
+/// - blocks: Cantidad de bloques descargados del peer simulado.
+/// - bytes: Bytes recibidos en los mensajes block.
+/// - generate_time: Tiempo que llevo generar los datos.
+/// - headers_time: Tiempo que llevo agregar los headers.
+/// - blocks_time: Tiempo que llevo descargar y guardar los bloques.
+/// - utxo_time: Tiempo que llevo generar las UTXO al terminar la descarga.
+/// - total_time: Tiempo total del benchmark.
+/// - peak_rss_kb: Maximo de memoria residente del proceso, si se puede obtener.
+pub struct IbdBenchReport {
+    pub headers: usize,
+    pub blocks: usize,
+    pub bytes: usize,
+    pub generate_time: Duration,
+    pub headers_time: Duration,
+    pub blocks_time: Duration,
+    pub utxo_time: Duration,
+    pub total_time: Duration,
+    pub peak_rss_kb: Option<u64>,
+}
+
+impl IbdBenchReport {
+    pub fn headers_per_sec(&self) -> f64 {
+        rate(self.headers as f64, self.headers_time)
+    }
+
+    pub fn blocks_per_sec(&self) -> f64 {
+        rate(self.blocks as f64, self.blocks_time)
+    }
+
+    pub fn mb_per_sec(&self) -> f64 {
+        rate(self.bytes as f64 / 1_000_000.0, self.blocks_time)
+    }
+
+    /// Devuelve el resumen del benchmark, una linea clave=valor por medida.
+    pub fn summary(&self) -> String {
+        let mut lines = vec![
+            format!("headers={}", self.headers),
+            format!("blocks={}", self.blocks),
+            format!("bytes={}", self.bytes),
+            format!("headers_per_sec={:.2}", self.headers_per_sec()),
+            format!("blocks_per_sec={:.2}", self.blocks_per_sec()),
+            format!("mb_per_sec={:.2}", self.mb_per_sec()),
+            format!("generate_secs={:.3}", self.generate_time.as_secs_f64()),
+            format!("headers_secs={:.3}", self.headers_time.as_secs_f64()),
+            format!("blocks_secs={:.3}", self.blocks_time.as_secs_f64()),
+            format!("utxo_secs={:.3}", self.utxo_time.as_secs_f64()),
+            format!("total_secs={:.3}", self.total_time.as_secs_f64()),
+        ];
+        if let Some(peak_rss_kb) = self.peak_rss_kb {
+            lines.push(format!("peak_rss_kb={peak_rss_kb}"));
+        }
+        lines.join("\n")
+    }
+
+    /// Devuelve una descripcion de cada umbral que no se cumplio.
+    pub fn failed_thresholds(&self, thresholds: &BenchThresholds) -> Vec<String> {
+        let measures = [
+            (
+                "headers_per_sec",
+                self.headers_per_sec(),
+                thresholds.min_headers_per_sec,
+            ),
+            (
+                "blocks_per_sec",
+                self.blocks_per_sec(),
+                thresholds.min_blocks_per_sec,
+            ),
+            ("mb_per_sec", self.mb_per_sec(), thresholds.min_mb_per_sec),
+        ];
+        measures
+            .iter()
+            .filter_map(|(name, value, min)| match min {
+                Some(min) if value < min => Some(format!("{name}={value:.2} below {min}")),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Corre el benchmark con los argumentos de la linea de comandos (ver BENCH_USAGE) e imprime el resumen.
+/// Devuelve el codigo de salida del proceso: 1 si el benchmark fallo o no cumplio algun umbral.
+pub fn run_from_args(args: &[String]) -> i32 {
+    let (source, thresholds) = match parse_args(args) {
+        Ok(parsed) => parsed,
+        Err(error) => {
+            println!("ERROR: {error}\n{BENCH_USAGE}");
+            return 1;
+        }
+    };
+
+    let store_path = env::temp_dir()
+        .join(format!("bench-ibd-{}", process::id()))
+        .to_string_lossy()
+        .to_string();
+    let report = match run_ibd_bench(&source, &store_path) {
+        Ok(report) => report,
+        Err(error) => {
+            println!("ERROR: {}", error.with_sources());
+            return 1;
+        }
+    };
+
+    println!("{}", report.summary());
+    let failed = report.failed_thresholds(&thresholds);
+    for failure in &failed {
+        println!("FAILED: {failure}");
+    }
+    i32::from(!failed.is_empty())
+}
+
+fn parse_args(args: &[String]) -> Result<(BenchSource, BenchThresholds), CustomError> {
+    let mut source = None;
+    let mut thresholds = BenchThresholds::default();
+    let mut args = args.iter().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(CustomError::InvalidBenchArguments)?;
+        match flag.as_str() {
+            BENCH_IBD_FLAG => source = Some(BenchSource::parse(value)?),
+            MIN_HEADERS_PER_SEC_FLAG => thresholds.min_headers_per_sec = Some(parse_min(value)?),
+            MIN_BLOCKS_PER_SEC_FLAG => thresholds.min_blocks_per_sec = Some(parse_min(value)?),
+            MIN_MB_PER_SEC_FLAG => thresholds.min_mb_per_sec = Some(parse_min(value)?),
+            _ => return Err(CustomError::InvalidBenchArguments),
+        }
+    }
+    let source = source.ok_or(CustomError::InvalidBenchArguments)?;
+    Ok((source, thresholds))
+}
+
+fn parse_min(value: &str) -> Result<f64, CustomError> {
+    value
+        .parse()
+        .map_err(|_| CustomError::InvalidBenchArguments)
+}
+
+/// Corre el IBD completo sobre los datos de source con un NodeState nuevo en store_path, sin interfaz grafica:
+/// agrega los headers en mensajes de HEADERS_BATCH, descarga los bloques de un peer simulado en el mismo proceso
+/// y genera las UTXO. El store se borra al terminar.
+pub fn run_ibd_bench(
+    source: &BenchSource,
+    store_path: &str,
+) -> Result<IbdBenchReport, CustomError> {
+    let started = Instant::now();
+    let BenchSource::Synthetic {
+        n_headers,
+        n_blocks,
+        avg_block_kb,
+    } = *source;
+    let chain = SyntheticChain::generate(
+        n_headers,
+        n_blocks,
+        avg_block_kb,
+        SYNTHETIC_MIN_DIFFICULTY_BITS,
+    )?;
+    let generate_time = started.elapsed();
+
+    let _ = fs::remove_dir_all(store_path);
+    let (logger_sender, logger_receiver) = mpsc::channel::<Log>();
+    let logs = thread::spawn(move || for _ in logger_receiver {});
+    let (gui_sender, gui_receiver) = glib::MainContext::channel(Priority::default());
+    let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path.to_string())?;
+    // la cadena sintetica tiene la dificultad de regtest
+    node_state_ref.lock()?.set_regtest(true);
+
+    let result = run_phases(&node_state_ref, chain);
+
+    drop(node_state_ref);
+    drop(gui_receiver);
+    let _ = logs.join();
+    fs::remove_dir_all(store_path)?;
+
+    let (headers_time, blocks_time, utxo_time, bytes) = result?;
+    Ok(IbdBenchReport {
+        headers: n_headers,
+        blocks: n_blocks,
+        bytes,
+        generate_time,
+        headers_time,
+        blocks_time,
+        utxo_time,
+        total_time: started.elapsed(),
+        peak_rss_kb: peak_rss_kb(),
+    })
+}
+
+/// Corre las fases del IBD y devuelve sus tiempos (headers, bloques, UTXO) y los bytes de bloques recibidos.
+/// Las UTXO se generan al agregar el ultimo bloque, por lo que el tiempo de esa llamada se cuenta como de las UTXO.
+fn run_phases(
+    node_state_ref: &Arc<Mutex<NodeState>>,
+    chain: SyntheticChain,
+) -> Result<(Duration, Duration, Duration, usize), CustomError> {
+    let mut node_state = node_state_ref.lock()?;
+
+    let started = Instant::now();
+    for batch in chain.headers.chunks(HEADERS_BATCH) {
+        node_state.append_headers(&Headers {
+            headers: batch.to_vec(),
+        })?;
+    }
+    // como un peer, si el ultimo mensaje estaba completo responde con uno vacio
+    if chain.headers.len() % HEADERS_BATCH == 0 {
+        node_state.append_headers(&Headers { headers: vec![] })?;
+    }
+    let headers_time = started.elapsed();
+
+    let block_hashes: Vec<Vec<u8>> = chain
+        .blocks
+        .iter()
+        .map(|block| block.header.hash().clone())
+        .collect();
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let mut stream = TcpStream::connect(listener.local_addr()?)?;
+    let peer = thread::spawn(move || serve_blocks(listener, chain.blocks));
+
+    let started = Instant::now();
+    let mut utxo_time = Duration::ZERO;
+    let mut bytes = 0;
+    for batch in block_hashes.chunks(GETDATA_BATCH) {
+        let inventories = batch
+            .iter()
+            .map(|hash| Inventory::new(InventoryType::Block, hash.clone()))
+            .collect();
+        GetData::new(inventories).send(&mut stream)?;
+
+        for _ in batch {
+            let header = MessageHeader::read(&mut stream)?;
+            let block = Block::read_with_header(&mut stream, &header)?;
+            block.create_merkle_root()?;
+            bytes += MESSAGE_HEADER_SIZE + header.payload_size as usize;
+
+            let was_synced = node_state.is_synced();
+            let appended = Instant::now();
+            node_state.append_block(block.header.hash().clone(), &block)?;
+            if !was_synced && node_state.is_synced() {
+                utxo_time += appended.elapsed();
+            }
+        }
+    }
+    let blocks_time = started.elapsed() - utxo_time;
+
+    drop(stream);
+    peer.join()
+        .map_err(|_| CustomError::CannotConnectToNode)??;
+
+    if !node_state.is_synced() {
+        return Err(CustomError::NodeNotSynced);
+    }
+    Ok((headers_time, blocks_time, utxo_time, bytes))
+}
+
+/// Peer simulado: acepta una conexion y responde cada getdata con los bloques pedidos, hasta que se cierra la conexion.
+fn serve_blocks(listener: TcpListener, blocks: Vec<Block>) -> Result<(), CustomError> {
+    let (mut stream, _) = listener.accept()?;
+    let blocks: HashMap<Vec<u8>, Block> = blocks
+        .into_iter()
+        .map(|block| (block.header.hash().clone(), block))
+        .collect();
+
+    while let Ok(header) = MessageHeader::read(&mut stream) {
+        let get_data = GetData::read_with_header(&mut stream, &header)?;
+        for inventory in get_data.get_inventories() {
+            if let Some(block) = blocks.get(&inventory.hash) {
+                block.send(&mut stream)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Cantidad de elementos por segundo en el tiempo recibido.
+fn rate(count: f64, time: Duration) -> f64 {
+    count / time.as_secs_f64().max(f64::EPSILON)
+}
+
+/// Devuelve el maximo de memoria residente del proceso en kilobytes (VmHWM), solo disponible en Linux.
+fn peak_rss_kb() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bench_source_parse() {
+        assert!(matches!(
+            BenchSource::parse("synthetic:100, 20,4"),
+            Ok(BenchSource::Synthetic {
+                n_headers: 100,
+                n_blocks: 20,
+                avg_block_kb: 4
+            })
+        ));
+        assert!(BenchSource::parse("synthetic:100,20").is_err());
+        assert!(BenchSource::parse("capture.bin").is_err());
+    }
+
+    #[test]
+    fn bench_ibd_on_tiny_synthetic_chain() {
+        let source = BenchSource::parse("synthetic:8,3,1").unwrap();
+        let report = run_ibd_bench(&source, "tests/store_bench_ibd").unwrap();
+
+        assert_eq!(report.headers, 8);
+        assert_eq!(report.blocks, 3);
+        assert!(report.bytes > 3000);
+        assert!(report.summary().contains("blocks_per_sec="));
+        assert!(report
+            .failed_thresholds(&BenchThresholds::default())
+            .is_empty());
+
+        let thresholds = BenchThresholds {
+            min_blocks_per_sec: Some(f64::MAX),
+            ..Default::default()
+        };
+        assert_eq!(report.failed_thresholds(&thresholds).len(), 1);
+    }
+}
@@ -0,0 +1,385 @@
+use bitcoin_hashes::{hash160, hmac, sha256d, sha512, Hash, HashEngine};
+use secp256k1::{PublicKey, Scalar, Secp256k1};
+
+use crate::{error::CustomError, wallet::address_from_pubkey_hash};
+
+/// Version de las extended public keys de mainnet (xpub).
+pub const XPUB_VERSION: [u8; 4] = [0x04, 0x88, 0xb2, 0x1e];
+
+/// Version de las extended public keys de testnet (tpub).
+pub const TPUB_VERSION: [u8; 4] = [0x04, 0x35, 0x87, 0xcf];
+
+/// Primer indice de las derivaciones hardened, que no se pueden hacer desde una extended public key.
+pub const HARDENED_INDEX: u32 = 0x8000_0000;
+
+/// Cadena de las direcciones de recepcion (m/0/i) de una cuenta.
+pub const EXTERNAL_CHAIN: u32 = 0;
+
+/// Cadena de las direcciones de cambio (m/1/i) de una cuenta.
+pub const CHANGE_CHAIN: u32 = 1;
+
+/// Cantidad de direcciones sin usar que se derivan despues de la ultima usada de cada cadena si el config no indica
+/// XPUB_GAP_LIMIT.
+pub const DEFAULT_XPUB_GAP_LIMIT: u32 = 20;
+
+/// Largo en bytes de una extended public key decodificada, sin el checksum.
+const EXTENDED_KEY_LEN: usize = 78;
+
+#[derive(Clone, Debug, PartialEq)]
+/// ExtendedPubKey es una extended public key de BIP 32, de la que se derivan public keys hijas sin la private key.
+/// Los elementos son:
+/// - version: Version de la red (XPUB_VERSION o TPUB_VERSION).
+/// - depth: Profundidad de la key en el arbol de derivacion (0 para la master).
+/// - parent_fingerprint: Primeros 4 bytes del hash160 de la public key padre.
+/// - child_number: Indice con el que se derivo la key de su padre.
+/// - chain_code: Chain code de la key, necesario para derivar a sus hijas.
+/// - public_key: Public key de la key.
+pub struct ExtendedPubKey {
+    pub version: [u8; 4],
+    pub depth: u8,
+    pub parent_fingerprint: [u8; 4],
+    pub child_number: u32,
+    pub chain_code: [u8; 32],
+    pub public_key: PublicKey,
+}
+
+impl ExtendedPubKey {
+    /// Lee una extended public key codificada en base58 con checksum (xpub o tpub).
+    /// Devuelve CustomError::Validation si no es base58, su largo o checksum no son validos, la version no es la de
+    /// una extended public key o la public key no es un punto valido.
+    pub fn parse(encoded: &str) -> Result<Self, CustomError> {
+        let decoded = bs58::decode(encoded.trim())
+            .into_vec()
+            .map_err(|_| CustomError::Validation(String::from("Xpub is not valid base58")))?;
+        if decoded.len() != EXTENDED_KEY_LEN + 4 {
+            return Err(CustomError::Validation(String::from(
+                "Xpub has an invalid length",
+            )));
+        }
+        let (payload, checksum) = decoded.split_at(EXTENDED_KEY_LEN);
+        if sha256d::Hash::hash(payload)[..4] != *checksum {
+            return Err(CustomError::Validation(String::from(
+                "Xpub checksum does not match",
+            )));
+        }
+
+        let mut version = [0; 4];
+        version.copy_from_slice(&payload[..4]);
+        if version != XPUB_VERSION && version != TPUB_VERSION {
+            return Err(CustomError::Validation(String::from(
+                "Key is not an extended public key (xpub or tpub)",
+            )));
+        }
+        let mut parent_fingerprint = [0; 4];
+        parent_fingerprint.copy_from_slice(&payload[5..9]);
+        let mut child_number = [0; 4];
+        child_number.copy_from_slice(&payload[9..13]);
+        let mut chain_code = [0; 32];
+        chain_code.copy_from_slice(&payload[13..45]);
+        let public_key = PublicKey::from_slice(&payload[45..])
+            .map_err(|_| CustomError::Validation(String::from("Xpub has an invalid public key")))?;
+
+        Ok(Self {
+            version,
+            depth: payload[4],
+            parent_fingerprint,
+            child_number: u32::from_be_bytes(child_number),
+            chain_code,
+            public_key,
+        })
+    }
+
+    /// Codifica la extended public key en base58 con checksum. Es la inversa de parse.
+    pub fn encode(&self) -> String {
+        let mut payload = self.version.to_vec();
+        payload.push(self.depth);
+        payload.extend(self.parent_fingerprint);
+        payload.extend(self.child_number.to_be_bytes());
+        payload.extend(self.chain_code);
+        payload.extend(self.public_key.serialize());
+        let checksum = sha256d::Hash::hash(&payload)[..4].to_vec();
+        payload.extend(checksum);
+        bs58::encode(payload).into_string()
+    }
+
+    /// Deriva la extended public key hija con el indice recibido (CKDpub de BIP 32).
+    /// Devuelve CustomError::Validation si el indice es hardened, o en el caso (muy improbable) de que el indice
+    /// no genere una key valida, en el que BIP 32 indica pasar al siguiente.
+    pub fn derive_child(&self, index: u32) -> Result<Self, CustomError> {
+        if index >= HARDENED_INDEX {
+            return Err(CustomError::Validation(String::from(
+                "Hardened keys cannot be derived from an xpub",
+            )));
+        }
+        let mut engine = hmac::HmacEngine::<sha512::Hash>::new(&self.chain_code);
+        engine.input(&self.public_key.serialize());
+        engine.input(&index.to_be_bytes());
+        let result = hmac::Hmac::<sha512::Hash>::from_engine(engine).to_byte_array();
+
+        let mut tweak = [0; 32];
+        tweak.copy_from_slice(&result[..32]);
+        let invalid_child =
+            || CustomError::Validation(format!("Index {index} does not derive a valid key"));
+        let tweak = Scalar::from_be_bytes(tweak).map_err(|_| invalid_child())?;
+        let public_key = self
+            .public_key
+            .add_exp_tweak(&Secp256k1::verification_only(), &tweak)
+            .map_err(|_| invalid_child())?;
+        let mut chain_code = [0; 32];
+        chain_code.copy_from_slice(&result[32..]);
+
+        Ok(Self {
+            version: self.version,
+            depth: self.depth.saturating_add(1),
+            parent_fingerprint: self.fingerprint(),
+            child_number: index,
+            chain_code,
+            public_key,
+        })
+    }
+
+    /// Devuelve los primeros 4 bytes del hash160 de la public key, que identifican a la key como padre de sus hijas.
+    pub fn fingerprint(&self) -> [u8; 4] {
+        let mut fingerprint = [0; 4];
+        fingerprint.copy_from_slice(&self.pubkey_hash()[..4]);
+        fingerprint
+    }
+
+    /// Devuelve el hash160 de la public key comprimida, el que se usa en los outputs P2PKH.
+    pub fn pubkey_hash(&self) -> Vec<u8> {
+        hash160::Hash::hash(&self.public_key.serialize())
+            .to_byte_array()
+            .to_vec()
+    }
+
+    /// Devuelve la direccion P2PKH de testnet de la public key.
+    pub fn address(&self) -> String {
+        address_from_pubkey_hash(&self.pubkey_hash())
+    }
+}
+
+#[derive(Clone, Debug)]
+/// XpubAccount es una cuenta watch-only de BIP 32: a partir de una extended public key se derivan las direcciones de
+/// recepcion (m/0/i) y de cambio (m/1/i), manteniendo en cada cadena gap_limit direcciones sin usar despues de la
+/// ultima que tuvo actividad.
+/// Los elementos son:
+/// - xpub: Extended public key de la cuenta, como la ingreso el usuario.
+/// - gap_limit: Cantidad de direcciones sin usar que se derivan despues de la ultima usada.
+/// - chains: Extended public keys de las cadenas de recepcion y de cambio.
+/// - derived: Hashes de las public keys derivadas de cada cadena, en orden de indice.
+pub struct XpubAccount {
+    pub xpub: String,
+    pub gap_limit: u32,
+    chains: [ExtendedPubKey; 2],
+    derived: [Vec<Vec<u8>>; 2],
+}
+
+impl XpubAccount {
+    /// Crea la cuenta derivando las primeras gap_limit direcciones de cada cadena.
+    /// Devuelve CustomError::Validation si el xpub no es valido, no es de testnet (tpub) o gap_limit es 0.
+    pub fn new(xpub: &str, gap_limit: u32) -> Result<Self, CustomError> {
+        Self::restore(xpub, gap_limit, gap_limit, gap_limit)
+    }
+
+    /// Crea la cuenta derivando la cantidad de direcciones de cada cadena que se habia alcanzado (ver derived_counts).
+    pub fn restore(
+        xpub: &str,
+        gap_limit: u32,
+        external_count: u32,
+        change_count: u32,
+    ) -> Result<Self, CustomError> {
+        if gap_limit == 0 {
+            return Err(CustomError::Validation(String::from(
+                "Gap limit must be at least 1",
+            )));
+        }
+        let account_key = ExtendedPubKey::parse(xpub)?;
+        if account_key.version != TPUB_VERSION {
+            return Err(CustomError::Validation(String::from(
+                "Only testnet extended public keys (tpub) are supported",
+            )));
+        }
+        let mut account = Self {
+            xpub: xpub.trim().to_string(),
+            gap_limit,
+            chains: [
+                account_key.derive_child(EXTERNAL_CHAIN)?,
+                account_key.derive_child(CHANGE_CHAIN)?,
+            ],
+            derived: [vec![], vec![]],
+        };
+        account.derive_up_to(EXTERNAL_CHAIN, external_count.max(gap_limit))?;
+        account.derive_up_to(CHANGE_CHAIN, change_count.max(gap_limit))?;
+        Ok(account)
+    }
+
+    /// Devuelve la cantidad de direcciones derivadas de la cadena de recepcion y de la de cambio.
+    pub fn derived_counts(&self) -> (u32, u32) {
+        (
+            self.derived[EXTERNAL_CHAIN as usize].len() as u32,
+            self.derived[CHANGE_CHAIN as usize].len() as u32,
+        )
+    }
+
+    /// Devuelve los hashes de las public keys de todas las direcciones derivadas, primero las de recepcion.
+    pub fn pubkey_hashes(&self) -> Vec<Vec<u8>> {
+        self.derived.concat()
+    }
+
+    /// Devuelve todas las direcciones derivadas, primero las de recepcion.
+    pub fn addresses(&self) -> Vec<String> {
+        self.pubkey_hashes()
+            .iter()
+            .map(|pubkey_hash| address_from_pubkey_hash(pubkey_hash))
+            .collect()
+    }
+
+    /// Devuelve la primera direccion de recepcion (m/0/0), que identifica a la cuenta.
+    pub fn first_address(&self) -> String {
+        address_from_pubkey_hash(&self.derived[EXTERNAL_CHAIN as usize][0])
+    }
+
+    /// Deriva mas direcciones en las cadenas en las que alguna de las direcciones usadas (por hash de su public key)
+    /// esta a menos de gap_limit direcciones del final, hasta volver a tener gap_limit sin usar despues de ella.
+    /// Los hashes que no son de la cuenta se ignoran.
+    /// Devuelve los hashes de las direcciones nuevas.
+    pub fn extend_gap(&mut self, used: &[Vec<u8>]) -> Result<Vec<Vec<u8>>, CustomError> {
+        let mut new_hashes = vec![];
+        for chain in [EXTERNAL_CHAIN, CHANGE_CHAIN] {
+            let derived = &self.derived[chain as usize];
+            let Some(last_used) = derived
+                .iter()
+                .rposition(|pubkey_hash| used.contains(pubkey_hash))
+            else {
+                continue;
+            };
+            let previous_count = derived.len();
+            self.derive_up_to(chain, last_used as u32 + 1 + self.gap_limit)?;
+            new_hashes.extend_from_slice(&self.derived[chain as usize][previous_count..]);
+        }
+        Ok(new_hashes)
+    }
+
+    /// Deriva direcciones de la cadena hasta tener count.
+    fn derive_up_to(&mut self, chain: u32, count: u32) -> Result<(), CustomError> {
+        let chain = chain as usize;
+        while (self.derived[chain].len() as u32) < count {
+            let index = self.derived[chain].len() as u32;
+            let key = self.chains[chain].derive_child(index)?;
+            self.derived[chain].push(key.pubkey_hash());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // extended public keys del vector de prueba 1 de BIP 32
+    const M_0H: &str = "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw";
+    const M_0H_1: &str = "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ";
+    const M_0H_1_2H_2: &str = "xpub6FHa3pjLCk84BayeJxFW2SP4XRrFd1JYnxeLeU8EqN3vDfZmbqBqaGJAyiLjTAwm6ZLRQUMv1ZACTj37sR62cfN7fe5JnJ7dh8zL4fiyLHV";
+    const M_0H_1_2H_2_1000000000: &str = "xpub6H1LXWLaKsWFhvm6RVpEL9P4KfRZSW7abD2ttkWP3SSQvnyA8FSVqNTEcYFgJS2UaFcxupHiYkro49S8yGasTvXEYBVPamhGW6cFJodrTHy";
+
+    // M_0H con la version de testnet
+    const TPUB: &str = "tpubD8eQVK4Kdxg3gHrF62jGP7dKVCoYiEB8dFSpuTawkL5YxTus5j5pf83vaKnii4bc6v2NVEy81P2gYrJczYne3QNNwMTS53p5uzDyHvnw2jm";
+
+    #[test]
+    fn parse_and_encode_round_trip() {
+        let key = ExtendedPubKey::parse(M_0H).unwrap();
+        assert_eq!(key.version, XPUB_VERSION);
+        assert_eq!(key.depth, 1);
+        assert_eq!(key.child_number, HARDENED_INDEX);
+        assert_eq!(key.encode(), M_0H);
+    }
+
+    #[test]
+    fn derive_child_matches_bip32_test_vectors() {
+        let key = ExtendedPubKey::parse(M_0H).unwrap();
+        let child = key.derive_child(1).unwrap();
+        assert_eq!(child.encode(), M_0H_1);
+        assert_eq!(child.parent_fingerprint, key.fingerprint());
+
+        let key = ExtendedPubKey::parse(M_0H_1_2H_2).unwrap();
+        assert_eq!(
+            key.derive_child(1_000_000_000).unwrap().encode(),
+            M_0H_1_2H_2_1000000000
+        );
+    }
+
+    #[test]
+    fn hardened_derivation_is_rejected() {
+        let key = ExtendedPubKey::parse(M_0H).unwrap();
+        assert!(matches!(
+            key.derive_child(HARDENED_INDEX),
+            Err(CustomError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_xpubs_are_rejected() {
+        let mut bad_checksum = M_0H.to_string();
+        bad_checksum.pop();
+        bad_checksum.push('x');
+        assert!(ExtendedPubKey::parse(&bad_checksum).is_err());
+        assert!(ExtendedPubKey::parse("mrSeAixYDXL17YZm6VEcxn9REZ6jK8DDgL").is_err());
+        assert!(ExtendedPubKey::parse("not base58 0OIl").is_err());
+        assert!(XpubAccount::new(TPUB, 0).is_err());
+        // las direcciones derivadas son de testnet, por lo que no se aceptan xpubs de mainnet
+        assert!(matches!(
+            XpubAccount::new(M_0H, 3),
+            Err(CustomError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn account_derives_external_and_change_addresses() {
+        let account = XpubAccount::new(TPUB, 3).unwrap();
+        assert_eq!(account.derived_counts(), (3, 3));
+        assert_eq!(
+            account.addresses(),
+            vec![
+                "mrSeAixYDXL17YZm6VEcxn9REZ6jK8DDgL",
+                "mqXQcixBZXWbBCtdU1Vrn2ufk3kTwMd7Gp",
+                "msYJDb6RrztKjdARFKW4zWuLXb8jyj7cmr",
+                "mxbowegjDcM35ZxR64Pc3WrVv8pYEG2P9U",
+                "mjnu98977o3rdAu5aWyLBN1UZeWeBoBc46",
+                "n49KsS3anSMYHBsucjY2cGUp9rT8UMUxus",
+            ]
+        );
+        assert_eq!(
+            account.first_address(),
+            "mrSeAixYDXL17YZm6VEcxn9REZ6jK8DDgL"
+        );
+    }
+
+    #[test]
+    fn account_extends_gap_after_activity_near_the_edge() {
+        let mut account = XpubAccount::new(TPUB, 3).unwrap();
+        let hashes = account.pubkey_hashes();
+
+        // la primera direccion deja 2 sin usar despues, por lo que se deriva una mas
+        assert_eq!(account.extend_gap(&[hashes[0].clone()]).unwrap().len(), 1);
+        assert_eq!(account.derived_counts(), (4, 3));
+        assert!(account.extend_gap(&[hashes[0].clone()]).unwrap().is_empty());
+
+        // la ultima de las primeras 3 de recepcion obliga a derivar hasta la 6, la cadena de cambio no cambia
+        let new_hashes = account.extend_gap(&[hashes[2].clone()]).unwrap();
+        assert_eq!(new_hashes.len(), 2);
+        assert_eq!(account.derived_counts(), (6, 3));
+        assert_eq!(
+            new_hashes[1],
+            ExtendedPubKey::parse(TPUB)
+                .unwrap()
+                .derive_child(EXTERNAL_CHAIN)
+                .unwrap()
+                .derive_child(5)
+                .unwrap()
+                .pubkey_hash()
+        );
+
+        let restored = XpubAccount::restore(TPUB, 3, 6, 3).unwrap();
+        assert_eq!(restored.pubkey_hashes(), account.pubkey_hashes());
+    }
+}
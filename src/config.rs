@@ -1,12 +1,121 @@
+use std::fmt::Display;
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
 use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::str::FromStr;
 
-use crate::error::CustomError;
+use crate::{
+    bip32::DEFAULT_XPUB_GAP_LIMIT,
+    error::CustomError,
+    logger::LogLevel,
+    loops::{
+        pending_blocks_task::DEFAULT_PENDING_BLOCKS_POLL,
+        ping_loop::{DEFAULT_PING_INTERVAL, DEFAULT_PING_TIMEOUT},
+        tx_propagation_loop::{DEFAULT_BROADCAST_PEERS, DEFAULT_TX_ECHO_TIMEOUT},
+    },
+    messages::{
+        transaction::{DEFAULT_MAX_RELAY_TX_IO, MAX_RELAY_TX_SIZE},
+        version::{DEFAULT_USER_AGENT, MAX_USER_AGENT_LENGTH},
+    },
+    pricing::PriceSource,
+    private_network::{parse_private_network_key, PrivateNetwork, PRIVATE_NETWORK_KEY_LEN},
+    rpc::DEFAULT_RPC_PORT,
+    rpc_auth::{RpcAccess, RpcCredentials},
+    send_many::{
+        DEFAULT_DUST_THRESHOLD, DEFAULT_MIN_SPEND_CONFIRMATIONS, MAX_MIN_SPEND_CONFIRMATIONS,
+    },
+    services::ServiceCapabilities,
+    states::{
+        pending_blocks_state::{DEFAULT_MAX_NOTFOUND_RETRIES, DEFAULT_STALE_BLOCK_TIMEOUT},
+        pending_txs_state::DEFAULT_REBROADCAST_BLOCKS,
+        utxo_state::DEFAULT_UTXO_SNAPSHOT_INTERVAL,
+    },
+    webhooks::WebhookUrl,
+};
 
-#[derive(Debug)]
+/// Valores que tiene que incluir el archivo de configuracion.
+const REQUIRED_VALUES: [&str; 3] = ["SEED", "PROTOCOL_VERSION", "PORT"];
+
+/// Nombres de todos los valores que se leen del archivo de configuracion, el resto se ignoran.
+const KNOWN_VALUES: [&str; 38] = [
+    "SEED",
+    "PROTOCOL_VERSION",
+    "PORT",
+    "LOG",
+    "LOG_LEVEL",
+    "NPEERS",
+    "STORE_PATH",
+    "CLIENT_ONLY",
+    "DUST_THRESHOLD",
+    "MIN_SPEND_CONFIRMATIONS",
+    "ALERT_THRESHOLD_SATS",
+    "PING_INTERVAL",
+    "PING_INTERVAL_SECS",
+    "PING_TIMEOUT",
+    "STALE_BLOCK_TIMEOUT_SECS",
+    "MAX_NOTFOUND_RETRIES",
+    "PENDING_BLOCKS_POLL_SECS",
+    "BROADCAST_PEERS",
+    "TX_ECHO_TIMEOUT",
+    "MAX_RELAY_TX_IO",
+    "REBROADCAST_BLOCKS",
+    "LISTEN",
+    "SPV_MODE",
+    "PRICE_SOURCE",
+    "UTXO_SNAPSHOT_INTERVAL",
+    "PRIVATE_NETWORK_KEY",
+    "PRIVATE_PEERS",
+    "RPC_AUTH",
+    "RPC_WHITELIST",
+    "RPC_BIND",
+    "RPC_ALLOW_EXTERNAL",
+    "RPC_PORT",
+    "NOTIFY_TOKEN",
+    "WEBHOOK_URL",
+    "WEBHOOK_SECRET",
+    "USER_AGENT",
+    "XPUB_GAP_LIMIT",
+    "REGTEST",
+];
+
+/// Archivo de log si el config no indica LOG.
+pub const DEFAULT_LOG_FILE: &str = "log.txt";
+
+/// Cantidad de peers a los que se conecta el nodo si el config no indica NPEERS.
+pub const DEFAULT_NPEERS: u8 = 8;
+
+/// Cantidad maxima de peers que se puede indicar en NPEERS.
+pub const MAX_NPEERS: u8 = 64;
+
+/// Version del protocolo minima que acepta PROTOCOL_VERSION, la primera con sendheaders (BIP 130).
+pub const MIN_PROTOCOL_VERSION: i32 = 70012;
+
+/// Cantidad maxima de segundos de los intervalos y timeouts del config (PING_INTERVAL, PING_TIMEOUT,
+/// STALE_BLOCK_TIMEOUT_SECS, PENDING_BLOCKS_POLL_SECS y TX_ECHO_TIMEOUT).
+pub const MAX_TIMEOUT_SECS: u64 = 3600;
+
+/// Cantidad maxima que se puede indicar en MAX_RELAY_TX_IO: una transaccion de MAX_RELAY_TX_SIZE bytes
+/// no llega a tener tantos inputs (de al menos 41 bytes) ni outputs (de al menos 9 bytes) si son mas de esto.
+pub const MAX_RELAY_TX_IO_LIMIT: usize = MAX_RELAY_TX_SIZE / 9;
+
+/// Cantidad maxima de bloques que se puede indicar en REBROADCAST_BLOCKS, un dia de bloques.
+pub const MAX_REBROADCAST_BLOCKS: usize = 144;
+
+/// Cantidad maxima de reintentos que se puede indicar en MAX_NOTFOUND_RETRIES.
+pub const MAX_NOTFOUND_RETRIES_LIMIT: u32 = 100;
+
+/// Cantidad maxima de direcciones sin usar por cadena que se puede indicar en XPUB_GAP_LIMIT.
+pub const MAX_XPUB_GAP_LIMIT: u32 = 1000;
+
+/// Cantidad maxima de satoshis que se puede indicar en ALERT_THRESHOLD_SATS, los 21 millones de bitcoins que van a existir.
+pub const MAX_ALERT_THRESHOLD_SATS: u64 = 2_100_000_000_000_000;
+
+/// Direccion en la que escuchan el RPC y el socket de notificaciones si el config no indica RPC_BIND.
+pub const DEFAULT_RPC_BIND: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+#[derive(Debug, Clone)]
 
 /// Config es una estructura que contiene los valores de configuracion del nodo.
 /// Estos valores se leen de un archivo de configuracion.
@@ -14,25 +123,90 @@ use crate::error::CustomError;
 /// - seed: semilla DNS para obtener direcciones IP.
 /// - protocol_version: version del protocolo.
 /// - port: puerto en el que escucha el nodo.
+/// - log_file: archivo de log, por defecto DEFAULT_LOG_FILE (opcional).
+/// - log_level: logs que se registran, error o info, por defecto info (opcional).
+/// - npeers: cantidad de peers a los que se conecta el nodo, por defecto DEFAULT_NPEERS (opcional).
+/// - client_only: indica si el nodo no sirve bloques a otros nodos, por defecto false (opcional).
+/// - store_path: carpeta donde se guardan los datos del nodo, por defecto store (opcional).
+/// - dust_threshold: valor minimo del cambio de las transacciones que creamos (opcional).
+/// - min_spend_confirmations: confirmaciones minimas de las UTXO que gastan las transacciones que creamos, entre 1 y 6 (opcional).
+/// - alert_threshold_sats: monto en satoshis a partir del cual un pago recibido por cualquier wallet genera una alerta, 0 las desactiva (opcional).
+/// - ping_interval: segundos entre los pings que enviamos a cada peer, PING_INTERVAL o PING_INTERVAL_SECS (opcional).
+/// - ping_timeout: segundos que tiene un peer para responder un ping antes de desconectarlo (opcional).
+/// - stale_block_timeout_secs: segundos tras los cuales se vuelve a pedir un bloque que no se recibio (opcional).
+/// - max_notfound_retries: veces que se vuelve a pedir un bloque al que los peers responden notfound antes de dejar de pedirlo (opcional).
+/// - pending_blocks_poll_secs: segundos entre revisiones de los bloques pendientes mientras hay alguno (opcional).
+/// - broadcast_peers: cantidad de peers a los que se envian las transacciones que creamos para verificar que se propaguen, 0 las envia a todos sin verificar (opcional).
+/// - tx_echo_timeout: segundos que se espera a que otro peer anuncie una transaccion que creamos antes de reenviarla (opcional).
+/// - max_relay_tx_io: cantidad maxima de inputs, y de outputs, de las transacciones que se aceptan de los peers (opcional).
+/// - rebroadcast_blocks: cantidad de bloques sin confirmarse tras los cuales se vuelve a enviar una transaccion que creamos (opcional).
+/// - listen: indica si se aceptan conexiones entrantes en el puerto, por defecto si no es client_only (opcional).
+/// - spv_mode: indica si el nodo funciona como cliente SPV, pidiendo solo las transacciones de sus wallets con filtros bloom (opcional).
+/// - price_source: origen de la cotizacion de BTC en moneda fiat que se muestra en la interfaz grafica, manual:<valor> o file:<path> (opcional).
+/// - utxo_snapshot_interval: cada cuantos bloques se guarda un snapshot completo de las UTXO, entre snapshots solo se agrega al journal (opcional).
+/// - private_network_key: clave compartida en hexadecimal (32 bytes) con la que se autentican las conexiones entrantes, todas deben conocerla (opcional).
+/// - private_peers: direcciones ip:puerto separadas por comas de los nodos de la red privada, a los que se llama autenticandose con private_network_key (opcional).
+/// - rpc_auth: usuario:password que se exige con HTTP basic auth en el RPC, si no se genera una cookie por sesion en el store (opcional).
+/// - rpc_whitelist: metodos RPC permitidos separados por comas, el resto se rechazan, por defecto todos (opcional).
+/// - rpc_bind: direccion en la que escuchan el RPC y el socket de notificaciones, por defecto DEFAULT_RPC_BIND (opcional).
+/// - rpc_allow_external: permite que rpc_bind no sea una direccion local, por defecto false (opcional).
+/// - rpc_port: puerto en el que escucha el RPC en rpc_bind, por defecto DEFAULT_RPC_PORT (opcional).
+/// - notify_token: token que deben enviar los clientes del socket de notificaciones antes de suscribirse (opcional).
+/// - webhook_url: direccion http:// a la que se envian con un POST los eventos de las wallets (opcional).
+/// - webhook_secret: clave con la que se firma el body de cada evento del webhook con HMAC-SHA256 (opcional).
+/// - user_agent: user agent con el que el nodo se presenta en el mensaje version, por defecto DEFAULT_USER_AGENT (opcional).
+/// - xpub_gap_limit: direcciones sin usar que se derivan en cada cadena de las cuentas xpub importadas, por defecto DEFAULT_XPUB_GAP_LIMIT (opcional).
+/// - regtest: indica que el nodo corre en una red regtest privada, lo que habilita getblocktemplate y submitblock en el RPC (opcional).
 pub struct Config {
     pub seed: String,
     pub protocol_version: i32,
     pub port: u16,
     pub log_file: String,
+    pub log_level: LogLevel,
     pub npeers: u8,
     pub client_only: bool,
     pub store_path: String,
+    pub dust_threshold: u64,
+    pub min_spend_confirmations: u32,
+    pub alert_threshold_sats: u64,
+    pub ping_interval: u64,
+    pub ping_timeout: u64,
+    pub stale_block_timeout_secs: u64,
+    pub max_notfound_retries: u32,
+    pub pending_blocks_poll_secs: u64,
+    pub broadcast_peers: usize,
+    pub tx_echo_timeout: u64,
+    pub max_relay_tx_io: usize,
+    pub rebroadcast_blocks: usize,
+    pub listen: Option<bool>,
+    pub spv_mode: bool,
+    pub price_source: Option<PriceSource>,
+    pub utxo_snapshot_interval: u32,
+    pub private_network_key: Option<Vec<u8>>,
+    pub private_peers: Vec<SocketAddr>,
+    pub rpc_auth: Option<RpcCredentials>,
+    pub rpc_whitelist: Option<Vec<String>>,
+    pub rpc_bind: IpAddr,
+    pub rpc_allow_external: bool,
+    pub rpc_port: u16,
+    pub notify_token: Option<String>,
+    pub webhook_url: Option<WebhookUrl>,
+    pub webhook_secret: Option<String>,
+    pub user_agent: String,
+    pub xpub_gap_limit: u32,
+    pub regtest: bool,
 }
 
 impl Config {
     /// Lee un archivo de configuracion y devuelve un Config con los valores leidos.
     /// El archivo de configuracion debe tener el siguiente formato:
     /// {NOMBRE}={VALOR}
-    /// Debe incluir todos los valores econtrados en la estructura Config.
+    /// Las lineas vacias y las que empiezan con # (comentarios) se ignoran, al igual que los nombres desconocidos.
+    /// SEED, PROTOCOL_VERSION y PORT son obligatorios, el resto de los valores tienen un valor por defecto.
     /// Devuelve CustomError si:
     /// - No se pudo encontrar el archivo.
-    /// - El archivo tiene un formato invalido.
-    /// - El archivo no contiene todos los valores requeridos.
+    /// - El archivo tiene lineas con formato o valores invalidos, o le faltan valores obligatorios (ConfigInvalid con todos
+    ///   los problemas encontrados, indicando el nombre del valor y el numero de linea).
     pub fn from_file(path: &str) -> Result<Self, CustomError> {
         let file = File::open(path).map_err(|_| CustomError::ConfigMissingFile)?;
         Self::from_reader(file)
@@ -41,8 +215,8 @@ impl Config {
     /// Crea un config a partir de cualquier implementacion del trait Read
     /// con el contenido en el formato mencionado en la documentacion de from_file.
     /// Devuelve CustomError si:
-    /// - El contenido tiene un formato invalido.
-    /// - El contenido no contiene todos los valores requeridos.
+    /// - El contenido tiene lineas con formato o valores invalidos.
+    /// - El contenido no contiene todos los valores obligatorios.
     /// - No se pudo leer el contenido.
     fn from_reader<T: Read>(content: T) -> Result<Config, CustomError> {
         let reader = BufReader::new(content);
@@ -51,79 +225,475 @@ impl Config {
             seed: String::new(),
             protocol_version: 0,
             port: 0,
-            log_file: String::new(),
-            npeers: 0,
+            log_file: String::from(DEFAULT_LOG_FILE),
+            log_level: LogLevel::Info,
+            npeers: DEFAULT_NPEERS,
             client_only: false,
             store_path: String::from("store"),
+            dust_threshold: DEFAULT_DUST_THRESHOLD,
+            min_spend_confirmations: DEFAULT_MIN_SPEND_CONFIRMATIONS,
+            alert_threshold_sats: 0,
+            ping_interval: DEFAULT_PING_INTERVAL,
+            ping_timeout: DEFAULT_PING_TIMEOUT,
+            stale_block_timeout_secs: DEFAULT_STALE_BLOCK_TIMEOUT,
+            max_notfound_retries: DEFAULT_MAX_NOTFOUND_RETRIES,
+            pending_blocks_poll_secs: DEFAULT_PENDING_BLOCKS_POLL,
+            broadcast_peers: DEFAULT_BROADCAST_PEERS,
+            tx_echo_timeout: DEFAULT_TX_ECHO_TIMEOUT,
+            max_relay_tx_io: DEFAULT_MAX_RELAY_TX_IO,
+            rebroadcast_blocks: DEFAULT_REBROADCAST_BLOCKS,
+            listen: None,
+            spv_mode: false,
+            price_source: None,
+            utxo_snapshot_interval: DEFAULT_UTXO_SNAPSHOT_INTERVAL,
+            private_network_key: None,
+            private_peers: vec![],
+            rpc_auth: None,
+            rpc_whitelist: None,
+            rpc_bind: DEFAULT_RPC_BIND,
+            rpc_allow_external: false,
+            rpc_port: DEFAULT_RPC_PORT,
+            notify_token: None,
+            webhook_url: None,
+            webhook_secret: None,
+            user_agent: String::from(DEFAULT_USER_AGENT),
+            xpub_gap_limit: DEFAULT_XPUB_GAP_LIMIT,
+            regtest: false,
         };
 
-        for line in reader.lines() {
-            let current_line = line.map_err(|_| CustomError::ConfigInvalid)?;
-
-            let setting: Vec<&str> = current_line.split('=').collect();
+        let mut problems = vec![];
+        let mut found_names = vec![];
+        for (index, line) in reader.lines().enumerate() {
+            let line_number = index + 1;
+            let current_line = line.map_err(|_| {
+                CustomError::ConfigInvalid(format!("line {line_number}: cannot be read"))
+            })?;
+            let current_line = current_line.trim();
+            if current_line.is_empty() || current_line.starts_with('#') {
+                continue;
+            }
 
-            // ['KEY', 'VALUE'].len() == 2
-            if setting.len() != 2 {
-                return Err(CustomError::ConfigInvalid);
+            let Some((name, value)) = current_line.split_once('=') else {
+                problems.push(format!(
+                    "line {line_number}: expected NAME=VALUE, found '{current_line}'"
+                ));
+                continue;
+            };
+            let (name, value) = (name.trim(), value.trim());
+            found_names.push(name.to_string());
+            if let Err(problem) = config.load_setting(name, value) {
+                problems.push(format!("line {line_number}: {problem}"));
             }
-            Self::load_setting(&mut config, setting[0], setting[1])?;
         }
 
-        Self::check_required_values(&config)?;
-
-        Ok(config)
-    }
-
-    /// Verifica que todos los valores requeridos esten cargados en el config.
-    fn check_required_values(config: &Config) -> Result<(), CustomError> {
-        if config.seed.is_empty() {
-            return Err(CustomError::ConfigMissingValue);
-        }
-        if config.protocol_version == 0 {
-            return Err(CustomError::ConfigMissingValue);
-        }
-        if config.port == 0 {
-            return Err(CustomError::ConfigMissingValue);
+        for name in REQUIRED_VALUES {
+            if !found_names.iter().any(|found| found == name) {
+                problems.push(format!("missing required value {name}"));
+            }
         }
-        if config.log_file.is_empty() {
-            return Err(CustomError::ConfigMissingValue);
+        if !config.rpc_bind.is_loopback() && !config.rpc_allow_external {
+            problems.push(format!(
+                "RPC_BIND {} is not a local address, set RPC_ALLOW_EXTERNAL=true to accept RPC connections from other interfaces",
+                config.rpc_bind
+            ));
         }
-        if config.npeers == 0 {
-            return Err(CustomError::ConfigMissingValue);
+
+        if !problems.is_empty() {
+            return Err(CustomError::ConfigInvalid(problems.join("\n")));
         }
-        Ok(())
+        Ok(config)
     }
 
-    /// Carga un "value" en el config en base al "name" que recibe.
-    /// Devuelve CustomError si:
-    /// - El "name" no es un nombre valido.
-    /// - El "value" no se pudo convertir al tipo esperado.
-    fn load_setting(&mut self, name: &str, value: &str) -> Result<(), CustomError> {
+    /// Carga un "value" en el config en base al "name" que recibe. Los nombres desconocidos se ignoran.
+    /// Devuelve la descripcion del problema si:
+    /// - El "value" esta vacio.
+    /// - El "value" no se pudo convertir al tipo esperado o esta fuera de rango.
+    fn load_setting(&mut self, name: &str, value: &str) -> Result<(), String> {
+        if value.is_empty() && is_known_value(name) {
+            return Err(format!("empty value for {name}"));
+        }
         match name {
             "SEED" => self.seed = String::from(value),
             "PROTOCOL_VERSION" => {
-                self.protocol_version =
-                    i32::from_str(value).map_err(|_| CustomError::ConfigErrorReadingValue)?
-            }
-            "PORT" => {
-                self.port =
-                    u16::from_str(value).map_err(|_| CustomError::ConfigErrorReadingValue)?
+                let protocol_version = parse_value(name, value)?;
+                if protocol_version < MIN_PROTOCOL_VERSION {
+                    return Err(format!(
+                        "{name} must be at least {MIN_PROTOCOL_VERSION}, found '{value}'"
+                    ));
+                }
+                self.protocol_version = protocol_version
             }
+            "PORT" => self.port = parse_in_range(name, value, 1, u16::MAX)?,
             "LOG" => self.log_file = String::from(value),
-            "NPEERS" => {
-                self.npeers =
-                    u8::from_str(value).map_err(|_| CustomError::ConfigErrorReadingValue)?
+            "LOG_LEVEL" => {
+                let Some(level) = LogLevel::parse(value) else { return Err(format!("{name} must be error, warn or info, found '{value}'")) };
+                self.log_level = level
             }
+            "NPEERS" => self.npeers = parse_in_range(name, value, 1, MAX_NPEERS)?,
             "STORE_PATH" => self.store_path = String::from(value),
-            "CLIENT_ONLY" => self.client_only = value == "true",
+            "CLIENT_ONLY" => self.client_only = parse_bool(name, value)?,
+            "DUST_THRESHOLD" => self.dust_threshold = parse_value(name, value)?,
+            "MIN_SPEND_CONFIRMATIONS" => {
+                self.min_spend_confirmations =
+                    parse_in_range(name, value, 1, MAX_MIN_SPEND_CONFIRMATIONS)?
+            }
+            "ALERT_THRESHOLD_SATS" => {
+                self.alert_threshold_sats =
+                    parse_in_range(name, value, 0, MAX_ALERT_THRESHOLD_SATS)?
+            }
+            "PING_INTERVAL" | "PING_INTERVAL_SECS" => {
+                self.ping_interval = parse_in_range(name, value, 1, MAX_TIMEOUT_SECS)?
+            }
+            "PING_TIMEOUT" => self.ping_timeout = parse_in_range(name, value, 1, MAX_TIMEOUT_SECS)?,
+            "STALE_BLOCK_TIMEOUT_SECS" => {
+                self.stale_block_timeout_secs = parse_in_range(name, value, 1, MAX_TIMEOUT_SECS)?
+            }
+            "MAX_NOTFOUND_RETRIES" => {
+                self.max_notfound_retries =
+                    parse_in_range(name, value, 1, MAX_NOTFOUND_RETRIES_LIMIT)?
+            }
+            "PENDING_BLOCKS_POLL_SECS" => {
+                self.pending_blocks_poll_secs = parse_in_range(name, value, 1, MAX_TIMEOUT_SECS)?
+            }
+            "BROADCAST_PEERS" => self.broadcast_peers = parse_value(name, value)?,
+            "TX_ECHO_TIMEOUT" => {
+                self.tx_echo_timeout = parse_in_range(name, value, 1, MAX_TIMEOUT_SECS)?
+            }
+            "MAX_RELAY_TX_IO" => {
+                self.max_relay_tx_io = parse_in_range(name, value, 1, MAX_RELAY_TX_IO_LIMIT)?
+            }
+            "REBROADCAST_BLOCKS" => {
+                self.rebroadcast_blocks = parse_in_range(name, value, 1, MAX_REBROADCAST_BLOCKS)?
+            }
+            "LISTEN" => self.listen = Some(parse_bool(name, value)?),
+            "SPV_MODE" => self.spv_mode = parse_bool(name, value)?,
+            "REGTEST" => self.regtest = parse_bool(name, value)?,
+            "PRICE_SOURCE" => self.price_source = Some(PriceSource::parse(value)?),
+            "UTXO_SNAPSHOT_INTERVAL" => self.utxo_snapshot_interval = parse_value(name, value)?,
+            "PRIVATE_NETWORK_KEY" => {
+                let Some(key) = parse_private_network_key(value) else {
+                    return Err(format!(
+                        "{name} must be {PRIVATE_NETWORK_KEY_LEN} bytes in hexadecimal, found '{value}'"
+                    ));
+                };
+                self.private_network_key = Some(key)
+            }
+            "PRIVATE_PEERS" => {
+                self.private_peers = value
+                    .split(',')
+                    .map(|address| parse_value(name, address.trim()))
+                    .collect::<Result<_, _>>()?
+            }
+            "RPC_AUTH" => {
+                let Some(credentials) = RpcCredentials::parse(value) else { return Err(format!("{name} must be user:password")) };
+                self.rpc_auth = Some(credentials)
+            }
+            "RPC_WHITELIST" => {
+                self.rpc_whitelist = Some(
+                    value
+                        .split(',')
+                        .map(|method| method.trim().to_string())
+                        .filter(|method| !method.is_empty())
+                        .collect(),
+                )
+            }
+            "RPC_BIND" => self.rpc_bind = parse_value(name, value)?,
+            "RPC_ALLOW_EXTERNAL" => self.rpc_allow_external = parse_bool(name, value)?,
+            "RPC_PORT" => self.rpc_port = parse_value(name, value)?,
+            "NOTIFY_TOKEN" => self.notify_token = Some(String::from(value)),
+            "WEBHOOK_URL" => self.webhook_url = Some(WebhookUrl::parse(value)?),
+            "WEBHOOK_SECRET" => self.webhook_secret = Some(String::from(value)),
+            "USER_AGENT" => {
+                if value.len() > MAX_USER_AGENT_LENGTH {
+                    return Err(format!(
+                        "{name} must be at most {MAX_USER_AGENT_LENGTH} bytes long"
+                    ));
+                }
+                self.user_agent = String::from(value)
+            }
+            "XPUB_GAP_LIMIT" => {
+                self.xpub_gap_limit = parse_in_range(name, value, 1, MAX_XPUB_GAP_LIMIT)?
+            }
             _ => (),
         }
         Ok(())
     }
+
+    /// Devuelve true si el nodo acepta conexiones entrantes.
+    /// Un nodo client_only o en spv_mode nunca las acepta, aunque el config tenga LISTEN=true.
+    pub fn listens(&self) -> bool {
+        !self.client_only && !self.spv_mode && self.listen.unwrap_or(true)
+    }
+
+    /// Devuelve los servicios que anuncia el nodo, derivados de sus capacidades (ver ServiceCapabilities).
+    pub fn services(&self) -> u64 {
+        ServiceCapabilities::from_config(self).services()
+    }
+
+    /// Devuelve advertencias sobre valores del config que se contradicen, para loguearlas al iniciar el nodo.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = vec![];
+        if self.client_only && self.listen == Some(true) {
+            warnings.push(String::from(
+                "LISTEN=true is ignored because CLIENT_ONLY=true, the node will not accept incoming connections",
+            ));
+        }
+        if self.spv_mode && !self.client_only && self.listen == Some(true) {
+            warnings.push(String::from(
+                "LISTEN=true is ignored because SPV_MODE=true, the node only stores the transactions of its wallets",
+            ));
+        }
+        if self.private_network_key.is_none() && !self.private_peers.is_empty() {
+            warnings.push(String::from(
+                "PRIVATE_PEERS is ignored because PRIVATE_NETWORK_KEY is not set",
+            ));
+        }
+        if self.webhook_url.is_none() && self.webhook_secret.is_some() {
+            warnings.push(String::from(
+                "WEBHOOK_SECRET is ignored because WEBHOOK_URL is not set",
+            ));
+        }
+        warnings
+    }
+
+    /// Devuelve la red privada del config (ver PrivateNetwork), publica si no tiene PRIVATE_NETWORK_KEY.
+    pub fn private_network(&self) -> PrivateNetwork {
+        PrivateNetwork::new(self.private_network_key.clone(), self.private_peers.clone())
+    }
+
+    /// Devuelve el control de acceso del RPC (ver RpcAccess) con las credenciales de RPC_AUTH.
+    /// Sin RPC_AUTH genera las credenciales de la sesion en la cookie del store (ver RpcCredentials::create_cookie).
+    pub fn rpc_access(&self) -> Result<RpcAccess, CustomError> {
+        let credentials = match &self.rpc_auth {
+            Some(credentials) => credentials.clone(),
+            None => RpcCredentials::create_cookie(&self.store_path)?,
+        };
+        Ok(RpcAccess::new(credentials, self.rpc_whitelist.clone()))
+    }
+
+    /// Compara el config con el que se acaba de leer del archivo y devuelve los valores que cambiaron (ver ConfigDelta).
+    pub fn diff(&self, new: &Config) -> ConfigDelta {
+        let restart_required = [
+            ("SEED", self.seed != new.seed),
+            (
+                "PROTOCOL_VERSION",
+                self.protocol_version != new.protocol_version,
+            ),
+            ("PORT", self.port != new.port),
+            ("LOG", self.log_file != new.log_file),
+            ("NPEERS", self.npeers != new.npeers),
+            ("STORE_PATH", self.store_path != new.store_path),
+            ("CLIENT_ONLY", self.client_only != new.client_only),
+            ("PING_INTERVAL", self.ping_interval != new.ping_interval),
+            ("PING_TIMEOUT", self.ping_timeout != new.ping_timeout),
+            (
+                "PENDING_BLOCKS_POLL_SECS",
+                self.pending_blocks_poll_secs != new.pending_blocks_poll_secs,
+            ),
+            (
+                "TX_ECHO_TIMEOUT",
+                self.tx_echo_timeout != new.tx_echo_timeout,
+            ),
+            ("LISTEN", self.listen != new.listen),
+            ("SPV_MODE", self.spv_mode != new.spv_mode),
+            (
+                "PRIVATE_NETWORK_KEY",
+                self.private_network_key != new.private_network_key,
+            ),
+            ("PRIVATE_PEERS", self.private_peers != new.private_peers),
+            ("RPC_AUTH", self.rpc_auth != new.rpc_auth),
+            ("RPC_WHITELIST", self.rpc_whitelist != new.rpc_whitelist),
+            ("RPC_BIND", self.rpc_bind != new.rpc_bind),
+            (
+                "RPC_ALLOW_EXTERNAL",
+                self.rpc_allow_external != new.rpc_allow_external,
+            ),
+            ("RPC_PORT", self.rpc_port != new.rpc_port),
+            ("NOTIFY_TOKEN", self.notify_token != new.notify_token),
+            ("WEBHOOK_URL", self.webhook_url != new.webhook_url),
+            ("WEBHOOK_SECRET", self.webhook_secret != new.webhook_secret),
+            ("USER_AGENT", self.user_agent != new.user_agent),
+            ("XPUB_GAP_LIMIT", self.xpub_gap_limit != new.xpub_gap_limit),
+            ("REGTEST", self.regtest != new.regtest),
+        ]
+        .into_iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(name, _)| name.to_string())
+        .collect();
+
+        ConfigDelta {
+            log_level: changed(&self.log_level, &new.log_level),
+            dust_threshold: changed(&self.dust_threshold, &new.dust_threshold),
+            min_spend_confirmations: changed(
+                &self.min_spend_confirmations,
+                &new.min_spend_confirmations,
+            ),
+            alert_threshold_sats: changed(&self.alert_threshold_sats, &new.alert_threshold_sats),
+            stale_block_timeout_secs: changed(
+                &self.stale_block_timeout_secs,
+                &new.stale_block_timeout_secs,
+            ),
+            max_notfound_retries: changed(&self.max_notfound_retries, &new.max_notfound_retries),
+            broadcast_peers: changed(&self.broadcast_peers, &new.broadcast_peers),
+            utxo_snapshot_interval: changed(
+                &self.utxo_snapshot_interval,
+                &new.utxo_snapshot_interval,
+            ),
+            max_relay_tx_io: changed(&self.max_relay_tx_io, &new.max_relay_tx_io),
+            rebroadcast_blocks: changed(&self.rebroadcast_blocks, &new.rebroadcast_blocks),
+            price_source: changed(&self.price_source, &new.price_source),
+            restart_required,
+        }
+    }
+
+    /// Aplica al config los valores de delta que se pueden cambiar sin reiniciar el nodo.
+    /// Los de restart_required no se aplican, para que se sigan informando en las proximas recargas.
+    pub fn apply(&mut self, delta: &ConfigDelta) {
+        if let Some(log_level) = delta.log_level {
+            self.log_level = log_level;
+        }
+        if let Some(dust_threshold) = delta.dust_threshold {
+            self.dust_threshold = dust_threshold;
+        }
+        if let Some(min_spend_confirmations) = delta.min_spend_confirmations {
+            self.min_spend_confirmations = min_spend_confirmations;
+        }
+        if let Some(alert_threshold_sats) = delta.alert_threshold_sats {
+            self.alert_threshold_sats = alert_threshold_sats;
+        }
+        if let Some(stale_block_timeout_secs) = delta.stale_block_timeout_secs {
+            self.stale_block_timeout_secs = stale_block_timeout_secs;
+        }
+        if let Some(max_notfound_retries) = delta.max_notfound_retries {
+            self.max_notfound_retries = max_notfound_retries;
+        }
+        if let Some(broadcast_peers) = delta.broadcast_peers {
+            self.broadcast_peers = broadcast_peers;
+        }
+        if let Some(utxo_snapshot_interval) = delta.utxo_snapshot_interval {
+            self.utxo_snapshot_interval = utxo_snapshot_interval;
+        }
+        if let Some(max_relay_tx_io) = delta.max_relay_tx_io {
+            self.max_relay_tx_io = max_relay_tx_io;
+        }
+        if let Some(rebroadcast_blocks) = delta.rebroadcast_blocks {
+            self.rebroadcast_blocks = rebroadcast_blocks;
+        }
+        if let Some(price_source) = &delta.price_source {
+            self.price_source = price_source.clone();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// ConfigDelta contiene los cambios entre el config con el que esta corriendo el nodo y el que se volvio a leer del archivo.
+/// Los valores que se pueden cambiar sin reiniciar tienen Some con su valor nuevo si cambiaron,
+/// y los nombres del resto de los valores que cambiaron quedan en restart_required.
+/// Los elementos son:
+/// - log_level: LOG_LEVEL, lo aplica el logger.
+/// - dust_threshold: DUST_THRESHOLD, lo aplica el NodeState.
+/// - min_spend_confirmations: MIN_SPEND_CONFIRMATIONS, lo aplica el NodeState.
+/// - alert_threshold_sats: ALERT_THRESHOLD_SATS, lo aplica el NodeState.
+/// - stale_block_timeout_secs: STALE_BLOCK_TIMEOUT_SECS, lo aplican los bloques pendientes.
+/// - max_notfound_retries: MAX_NOTFOUND_RETRIES, lo aplican los bloques pendientes.
+/// - broadcast_peers: BROADCAST_PEERS, lo aplica el NodeState.
+/// - utxo_snapshot_interval: UTXO_SNAPSHOT_INTERVAL, lo aplican las UTXO.
+/// - max_relay_tx_io: MAX_RELAY_TX_IO, lo aplica el NodeState.
+/// - rebroadcast_blocks: REBROADCAST_BLOCKS, lo aplica el NodeState.
+/// - price_source: PRICE_SOURCE, None dentro del Some si se quito del archivo.
+/// - restart_required: Nombres de los valores que cambiaron pero solo se aplican al reiniciar el nodo.
+pub struct ConfigDelta {
+    pub log_level: Option<LogLevel>,
+    pub dust_threshold: Option<u64>,
+    pub min_spend_confirmations: Option<u32>,
+    pub alert_threshold_sats: Option<u64>,
+    pub stale_block_timeout_secs: Option<u64>,
+    pub max_notfound_retries: Option<u32>,
+    pub broadcast_peers: Option<usize>,
+    pub utxo_snapshot_interval: Option<u32>,
+    pub max_relay_tx_io: Option<usize>,
+    pub rebroadcast_blocks: Option<usize>,
+    pub price_source: Option<Option<PriceSource>>,
+    pub restart_required: Vec<String>,
+}
+
+impl ConfigDelta {
+    /// Devuelve los nombres de los valores que cambiaron y se aplican sin reiniciar.
+    pub fn applied(&self) -> Vec<&str> {
+        [
+            ("LOG_LEVEL", self.log_level.is_some()),
+            ("DUST_THRESHOLD", self.dust_threshold.is_some()),
+            (
+                "MIN_SPEND_CONFIRMATIONS",
+                self.min_spend_confirmations.is_some(),
+            ),
+            ("ALERT_THRESHOLD_SATS", self.alert_threshold_sats.is_some()),
+            (
+                "STALE_BLOCK_TIMEOUT_SECS",
+                self.stale_block_timeout_secs.is_some(),
+            ),
+            ("MAX_NOTFOUND_RETRIES", self.max_notfound_retries.is_some()),
+            ("BROADCAST_PEERS", self.broadcast_peers.is_some()),
+            (
+                "UTXO_SNAPSHOT_INTERVAL",
+                self.utxo_snapshot_interval.is_some(),
+            ),
+            ("MAX_RELAY_TX_IO", self.max_relay_tx_io.is_some()),
+            ("REBROADCAST_BLOCKS", self.rebroadcast_blocks.is_some()),
+            ("PRICE_SOURCE", self.price_source.is_some()),
+        ]
+        .into_iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(name, _)| name)
+        .collect()
+    }
+}
+
+/// Devuelve el valor nuevo si es distinto del actual.
+fn changed<T: PartialEq + Clone>(current: &T, new: &T) -> Option<T> {
+    (current != new).then(|| new.clone())
+}
+
+/// Lee un valor del tipo esperado. Devuelve la descripcion del problema si no se puede convertir.
+fn parse_value<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    T::from_str(value).map_err(|_| format!("invalid value for {name}: '{value}'"))
+}
+
+/// Lee un numero entre min y max inclusive (por ejemplo una cantidad de segundos o un puerto).
+/// Un numero que no entra en el tipo esperado tambien se informa como fuera de rango.
+fn parse_in_range<T: FromStr + PartialOrd + Display>(
+    name: &str,
+    value: &str,
+    min: T,
+    max: T,
+) -> Result<T, String> {
+    let out_of_range = || format!("{name} must be between {min} and {max}, found '{value}'");
+    match T::from_str(value) {
+        Ok(number) if number >= min && number <= max => Ok(number),
+        Ok(_) => Err(out_of_range()),
+        Err(_) if value.parse::<i128>().is_ok() => Err(out_of_range()),
+        Err(_) => Err(format!("invalid value for {name}: '{value}'")),
+    }
+}
+
+/// Lee un valor booleano, que debe ser true o false.
+fn parse_bool(name: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("{name} must be true or false, found '{value}'")),
+    }
+}
+
+/// Devuelve true si el nombre corresponde a un valor del config.
+fn is_known_value(name: &str) -> bool {
+    KNOWN_VALUES.contains(&name)
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::services::NODE_NETWORK_LIMITED;
+
     use super::*;
 
     #[test]
@@ -131,7 +701,7 @@ mod tests {
         let content = "KEY".as_bytes();
         let config = Config::from_reader(content);
         assert!(config.is_err());
-        assert!(matches!(config, Err(CustomError::ConfigInvalid)));
+        assert!(matches!(config, Err(CustomError::ConfigInvalid(_))));
     }
 
     #[test]
@@ -139,13 +709,13 @@ mod tests {
         let content = "SEED=seed.test\n".as_bytes();
         let config = Config::from_reader(content);
         assert!(config.is_err());
-        assert!(matches!(config, Err(CustomError::ConfigMissingValue)));
+        assert!(matches!(config, Err(CustomError::ConfigInvalid(_))));
     }
 
     #[test]
     fn config_con_valor_vacio() {
         let content = "SEED=\n\
-        PROTOCOL_VERSION=1234\n\
+        PROTOCOL_VERSION=70015\n\
         LOG=log.txt\n\
         NPEERS=5\n\
         PORT=4321\n\
@@ -154,51 +724,108 @@ mod tests {
             .as_bytes();
         let config = Config::from_reader(content);
         assert!(config.is_err());
-        assert!(matches!(config, Err(CustomError::ConfigMissingValue)));
+        assert!(matches!(config, Err(CustomError::ConfigInvalid(_))));
     }
 
     #[test]
     fn config_con_valores_requeridos() -> Result<(), CustomError> {
         let content = "SEED=seed.test\n\
-        PROTOCOL_VERSION=7000\n\
+        PROTOCOL_VERSION=70015\n\
         LOG=log.txt\n\
         NPEERS=5\n\
         PORT=4321\n\
         CLIENT_ONLY=true\n\
-        STORE_PATH=custom"
+        STORE_PATH=custom\n\
+        DUST_THRESHOLD=1000\n\
+        MIN_SPEND_CONFIRMATIONS=3\n\
+        PING_INTERVAL=30\n\
+        PING_TIMEOUT=10\n\
+        STALE_BLOCK_TIMEOUT_SECS=60\n\
+        MAX_NOTFOUND_RETRIES=3\n\
+        PENDING_BLOCKS_POLL_SECS=2\n\
+        BROADCAST_PEERS=2\n\
+        TX_ECHO_TIMEOUT=90\n\
+        MAX_RELAY_TX_IO=100\n\
+        REBROADCAST_BLOCKS=6\n\
+        XPUB_GAP_LIMIT=50"
             .as_bytes();
         let config = Config::from_reader(content)?;
-        assert_eq!(7000, config.protocol_version);
+        assert_eq!(70015, config.protocol_version);
         assert_eq!("seed.test", config.seed);
         assert_eq!(5, config.npeers);
         assert_eq!("log.txt", config.log_file);
         assert_eq!(4321, config.port);
         assert_eq!(true, config.client_only);
         assert_eq!("custom", config.store_path);
+        assert_eq!(1000, config.dust_threshold);
+        assert_eq!(3, config.min_spend_confirmations);
+        assert_eq!(30, config.ping_interval);
+        assert_eq!(10, config.ping_timeout);
+        assert_eq!(60, config.stale_block_timeout_secs);
+        assert_eq!(3, config.max_notfound_retries);
+        assert_eq!(2, config.pending_blocks_poll_secs);
+        assert_eq!(2, config.broadcast_peers);
+        assert_eq!(90, config.tx_echo_timeout);
+        assert_eq!(100, config.max_relay_tx_io);
+        assert_eq!(6, config.rebroadcast_blocks);
+        assert_eq!(50, config.xpub_gap_limit);
 
         let content = "SEED=seed.test\n\
-        PROTOCOL_VERSION=7000\n\
+        PROTOCOL_VERSION=70015\n\
         LOG=log.txt\n\
         NPEERS=5\n\
         PORT=4321"
             .as_bytes();
         let config = Config::from_reader(content)?;
-        assert_eq!(7000, config.protocol_version);
+        assert_eq!(70015, config.protocol_version);
         assert_eq!("seed.test", config.seed);
         assert_eq!(5, config.npeers);
         assert_eq!("log.txt", config.log_file);
         assert_eq!(4321, config.port);
         assert_eq!(false, config.client_only);
         assert_eq!("store", config.store_path);
+        assert_eq!(DEFAULT_DUST_THRESHOLD, config.dust_threshold);
+        assert_eq!(
+            DEFAULT_MIN_SPEND_CONFIRMATIONS,
+            config.min_spend_confirmations
+        );
+        assert_eq!(DEFAULT_PING_INTERVAL, config.ping_interval);
+        assert_eq!(DEFAULT_PING_TIMEOUT, config.ping_timeout);
+        assert_eq!(DEFAULT_STALE_BLOCK_TIMEOUT, config.stale_block_timeout_secs);
+        assert_eq!(DEFAULT_MAX_NOTFOUND_RETRIES, config.max_notfound_retries);
+        assert_eq!(DEFAULT_PENDING_BLOCKS_POLL, config.pending_blocks_poll_secs);
+        assert_eq!(DEFAULT_BROADCAST_PEERS, config.broadcast_peers);
+        assert_eq!(
+            DEFAULT_UTXO_SNAPSHOT_INTERVAL,
+            config.utxo_snapshot_interval
+        );
+        assert_eq!(DEFAULT_TX_ECHO_TIMEOUT, config.tx_echo_timeout);
+        assert_eq!(DEFAULT_MAX_RELAY_TX_IO, config.max_relay_tx_io);
+        assert_eq!(DEFAULT_REBROADCAST_BLOCKS, config.rebroadcast_blocks);
+        assert_eq!(DEFAULT_USER_AGENT, config.user_agent);
+        assert_eq!(DEFAULT_XPUB_GAP_LIMIT, config.xpub_gap_limit);
 
         Ok(())
     }
 
+    #[test]
+    fn config_con_confirmaciones_fuera_de_rango() {
+        let content = "SEED=seed.test\n\
+        PROTOCOL_VERSION=70015\n\
+        LOG=log.txt\n\
+        NPEERS=5\n\
+        PORT=4321\n\
+        MIN_SPEND_CONFIRMATIONS=7"
+            .as_bytes();
+        let config = Config::from_reader(content);
+        assert!(matches!(config, Err(CustomError::ConfigInvalid(_))));
+    }
+
     #[test]
     fn config_con_valores_de_mas() -> Result<(), CustomError> {
         let content = "SEED=seed.test\n\
         VALOR_NO_REQUERIDO=1234\n\
-        PROTOCOL_VERSION=7000\n\
+        PROTOCOL_VERSION=70015\n\
         LOG=log.txt\n\
         NPEERS=5\n\
         PORT=4321\n\
@@ -206,7 +833,7 @@ mod tests {
         STORE_PATH=custom"
             .as_bytes();
         let config = Config::from_reader(content)?;
-        assert_eq!(7000, config.protocol_version);
+        assert_eq!(70015, config.protocol_version);
         assert_eq!("seed.test", config.seed);
         assert_eq!(5, config.npeers);
         assert_eq!("log.txt", config.log_file);
@@ -216,7 +843,7 @@ mod tests {
 
         let content = "SEED=seed.test\n\
         VALOR_NO_REQUERIDO=\n\
-        PROTOCOL_VERSION=7000\n\
+        PROTOCOL_VERSION=70015\n\
         LOG=log.txt\n\
         NPEERS=5\n\
         PORT=4321\n\
@@ -224,7 +851,7 @@ mod tests {
         STORE_PATH=custom"
             .as_bytes();
         let config = Config::from_reader(content)?;
-        assert_eq!(7000, config.protocol_version);
+        assert_eq!(70015, config.protocol_version);
         assert_eq!("seed.test", config.seed);
         assert_eq!(5, config.npeers);
         assert_eq!("log.txt", config.log_file);
@@ -233,4 +860,371 @@ mod tests {
         assert_eq!("custom", config.store_path);
         Ok(())
     }
+
+    #[test]
+    fn config_con_ping_en_cero() {
+        let content = "SEED=seed.test\n\
+        PROTOCOL_VERSION=70015\n\
+        LOG=log.txt\n\
+        NPEERS=5\n\
+        PORT=4321\n\
+        PING_TIMEOUT=0"
+            .as_bytes();
+        let config = Config::from_reader(content);
+        assert!(matches!(config, Err(CustomError::ConfigInvalid(_))));
+    }
+
+    #[test]
+    fn config_con_stale_block_timeout_en_cero() {
+        let content = "SEED=seed.test\n\
+        PROTOCOL_VERSION=70015\n\
+        LOG=log.txt\n\
+        NPEERS=5\n\
+        PORT=4321\n\
+        STALE_BLOCK_TIMEOUT_SECS=0"
+            .as_bytes();
+        let config = Config::from_reader(content);
+        assert!(matches!(config, Err(CustomError::ConfigInvalid(_))));
+    }
+
+    #[test]
+    fn config_deriva_los_servicios_y_el_listener() -> Result<(), CustomError> {
+        let base = "SEED=seed.test\n\
+        PROTOCOL_VERSION=70015\n\
+        LOG=log.txt\n\
+        NPEERS=5\n\
+        PORT=4321\n";
+
+        let config = Config::from_reader(base.as_bytes())?;
+        assert!(config.listens());
+        assert_eq!(config.services(), NODE_NETWORK_LIMITED);
+        assert!(config.warnings().is_empty());
+
+        let config = Config::from_reader(format!("{base}LISTEN=false").as_bytes())?;
+        assert!(!config.listens());
+        assert_eq!(config.services(), NODE_NETWORK_LIMITED);
+
+        let config = Config::from_reader(format!("{base}CLIENT_ONLY=true").as_bytes())?;
+        assert!(!config.listens());
+        assert_eq!(config.services(), 0);
+        assert!(config.warnings().is_empty());
+
+        let config =
+            Config::from_reader(format!("{base}CLIENT_ONLY=true\nLISTEN=true").as_bytes())?;
+        assert!(!config.listens());
+        assert_eq!(config.services(), 0);
+        assert_eq!(config.warnings().len(), 1);
+
+        let config = Config::from_reader(format!("{base}SPV_MODE=true").as_bytes())?;
+        assert!(config.spv_mode);
+        assert!(!config.listens());
+        assert_eq!(config.services(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn config_solo_habilita_regtest_explicitamente() -> Result<(), CustomError> {
+        let base = "SEED=seed.test\n\
+        PROTOCOL_VERSION=70015\n\
+        PORT=4321\n";
+
+        assert!(!Config::from_reader(base.as_bytes())?.regtest);
+        assert!(Config::from_reader(format!("{base}REGTEST=true").as_bytes())?.regtest);
+        assert!(Config::from_reader(format!("{base}REGTEST=yes").as_bytes()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn config_con_comentarios_y_valores_por_defecto() -> Result<(), CustomError> {
+        let content = "# nodo de prueba\n\
+        \n\
+        SEED = seed.test\n\
+          # la version del protocolo\n\
+        PROTOCOL_VERSION=70015\n\
+        PORT=4321\n\
+        PING_INTERVAL_SECS=30\n"
+            .as_bytes();
+        let config = Config::from_reader(content)?;
+        assert_eq!("seed.test", config.seed);
+        assert_eq!(70015, config.protocol_version);
+        assert_eq!(4321, config.port);
+        assert_eq!(DEFAULT_NPEERS, config.npeers);
+        assert_eq!(DEFAULT_LOG_FILE, config.log_file);
+        assert_eq!(false, config.client_only);
+        assert_eq!(30, config.ping_interval);
+        assert_eq!(None, config.price_source);
+        Ok(())
+    }
+
+    #[test]
+    fn config_con_puerto_invalido() {
+        let content = "SEED=seed.test\n\
+        PROTOCOL_VERSION=70015\n\
+        PORT=43a1\n\
+        NPEERS=0\n\
+        CLIENT_ONLY=yes"
+            .as_bytes();
+        let Err(CustomError::ConfigInvalid(problems)) = Config::from_reader(content) else { panic!("expected ConfigInvalid") };
+        let problems: Vec<&str> = problems.lines().collect();
+        assert_eq!(
+            problems,
+            vec![
+                "line 3: invalid value for PORT: '43a1'",
+                "line 4: NPEERS must be between 1 and 64, found '0'",
+                "line 5: CLIENT_ONLY must be true or false, found 'yes'",
+            ]
+        );
+    }
+
+    #[test]
+    fn config_sin_seed() {
+        let content = "# sin seed\n\
+        PROTOCOL_VERSION=70015\n\
+        PORT=4321\n\
+        LOG"
+            .as_bytes();
+        let error = Config::from_reader(content).unwrap_err();
+        let CustomError::ConfigInvalid(problems) = &error else { panic!("expected ConfigInvalid") };
+        assert_eq!(
+            problems,
+            "line 4: expected NAME=VALUE, found 'LOG'\nmissing required value SEED"
+        );
+        assert!(error.to_string().contains("missing required value SEED"));
+    }
+
+    #[test]
+    fn config_con_red_privada() -> Result<(), CustomError> {
+        let key = "0f".repeat(PRIVATE_NETWORK_KEY_LEN);
+        let content = format!(
+            "SEED=seed.test\n\
+            PROTOCOL_VERSION=70015\n\
+            PORT=4321\n\
+            PRIVATE_NETWORK_KEY={key}\n\
+            PRIVATE_PEERS=10.0.0.1:18333, [::1]:18334"
+        );
+        let config = Config::from_reader(content.as_bytes())?;
+        assert_eq!(
+            Some(vec![0x0f; PRIVATE_NETWORK_KEY_LEN]),
+            config.private_network_key
+        );
+        assert_eq!(
+            vec![
+                SocketAddr::from(([10, 0, 0, 1], 18333)),
+                SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 18334)),
+            ],
+            config.private_network().peers()
+        );
+
+        let content = "SEED=seed.test\n\
+        PROTOCOL_VERSION=70015\n\
+        PORT=4321\n\
+        PRIVATE_NETWORK_KEY=0f0f\n\
+        PRIVATE_PEERS=10.0.0.1"
+            .as_bytes();
+        let Err(CustomError::ConfigInvalid(problems)) = Config::from_reader(content) else { panic!("expected ConfigInvalid") };
+        assert_eq!(problems.lines().count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn config_con_rpc() -> Result<(), CustomError> {
+        let content = "SEED=seed.test\n\
+        PROTOCOL_VERSION=70015\n\
+        PORT=4321\n\
+        RPC_AUTH=alice:secret\n\
+        RPC_WHITELIST=getblockcount, getbalance"
+            .as_bytes();
+        let config = Config::from_reader(content)?;
+        assert_eq!(config.rpc_auth, RpcCredentials::parse("alice:secret"));
+        assert_eq!(
+            Some(vec!["getblockcount".to_string(), "getbalance".to_string()]),
+            config.rpc_whitelist
+        );
+        assert_eq!(DEFAULT_RPC_BIND, config.rpc_bind);
+        assert_eq!(DEFAULT_RPC_PORT, config.rpc_port);
+
+        let content = "SEED=seed.test\n\
+        PROTOCOL_VERSION=70015\n\
+        PORT=4321\n\
+        RPC_AUTH=alice\n\
+        RPC_BIND=0.0.0.0"
+            .as_bytes();
+        let Err(CustomError::ConfigInvalid(problems)) = Config::from_reader(content) else { panic!("expected ConfigInvalid") };
+        assert_eq!(problems.lines().count(), 2);
+
+        let content = "SEED=seed.test\n\
+        PROTOCOL_VERSION=70015\n\
+        PORT=4321\n\
+        RPC_BIND=0.0.0.0\n\
+        RPC_ALLOW_EXTERNAL=true\n\
+        RPC_PORT=28332"
+            .as_bytes();
+        let config = Config::from_reader(content)?;
+        assert!(config.rpc_bind.is_unspecified());
+        assert_eq!(28332, config.rpc_port);
+        Ok(())
+    }
+
+    #[test]
+    fn config_con_webhook() -> Result<(), CustomError> {
+        let content = "SEED=seed.test\n\
+        PROTOCOL_VERSION=70015\n\
+        PORT=4321\n\
+        WEBHOOK_URL=http://127.0.0.1:9000/wallet\n\
+        WEBHOOK_SECRET=secret"
+            .as_bytes();
+        let config = Config::from_reader(content)?;
+        assert_eq!(
+            config.webhook_url,
+            WebhookUrl::parse("http://127.0.0.1:9000/wallet").ok()
+        );
+        assert_eq!(config.webhook_secret.as_deref(), Some("secret"));
+        assert!(config.warnings().is_empty());
+
+        let content = "SEED=seed.test\n\
+        PROTOCOL_VERSION=70015\n\
+        PORT=4321\n\
+        WEBHOOK_SECRET=secret"
+            .as_bytes();
+        assert_eq!(Config::from_reader(content)?.warnings().len(), 1);
+
+        let content = "SEED=seed.test\n\
+        PROTOCOL_VERSION=70015\n\
+        PORT=4321\n\
+        WEBHOOK_URL=https://example.com/wallet"
+            .as_bytes();
+        assert!(matches!(
+            Config::from_reader(content),
+            Err(CustomError::ConfigInvalid(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn config_con_user_agent() -> Result<(), CustomError> {
+        let content = "SEED=seed.test\n\
+        PROTOCOL_VERSION=70015\n\
+        PORT=4321\n\
+        USER_AGENT=/custom-node:2.0/"
+            .as_bytes();
+        assert_eq!(
+            Config::from_reader(content)?.user_agent,
+            "/custom-node:2.0/"
+        );
+
+        let content = format!(
+            "SEED=seed.test\nPROTOCOL_VERSION=70015\nPORT=4321\nUSER_AGENT={}",
+            "a".repeat(MAX_USER_AGENT_LENGTH + 1)
+        );
+        assert!(matches!(
+            Config::from_reader(content.as_bytes()),
+            Err(CustomError::ConfigInvalid(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn config_con_valores_fuera_de_rango() {
+        let content = "SEED=seed.test\n\
+        PROTOCOL_VERSION=7000\n\
+        PORT=0\n\
+        NPEERS=65\n\
+        PING_TIMEOUT=3601\n\
+        TX_ECHO_TIMEOUT=0\n\
+        MIN_SPEND_CONFIRMATIONS=7\n\
+        LOG_LEVEL=debug\n\
+        MAX_RELAY_TX_IO=0\n\
+        ALERT_THRESHOLD_SATS=2100000000000001"
+            .as_bytes();
+        let Err(CustomError::ConfigInvalid(problems)) = Config::from_reader(content) else { panic!("expected ConfigInvalid") };
+        let problems: Vec<&str> = problems.lines().collect();
+        assert_eq!(
+            problems,
+            vec![
+                "line 2: PROTOCOL_VERSION must be at least 70012, found '7000'",
+                "line 3: PORT must be between 1 and 65535, found '0'",
+                "line 4: NPEERS must be between 1 and 64, found '65'",
+                "line 5: PING_TIMEOUT must be between 1 and 3600, found '3601'",
+                "line 6: TX_ECHO_TIMEOUT must be between 1 and 3600, found '0'",
+                "line 7: MIN_SPEND_CONFIRMATIONS must be between 1 and 6, found '7'",
+                "line 8: LOG_LEVEL must be error, warn or info, found 'debug'",
+                "line 9: MAX_RELAY_TX_IO must be between 1 and 44444, found '0'",
+                "line 10: ALERT_THRESHOLD_SATS must be between 0 and 2100000000000000, found '2100000000000001'",
+            ]
+        );
+
+        // un numero que no entra en el tipo tambien esta fuera de rango
+        let content = "SEED=seed.test\n\
+        PROTOCOL_VERSION=70012\n\
+        PORT=70000\n\
+        NPEERS=-1"
+            .as_bytes();
+        let Err(CustomError::ConfigInvalid(problems)) = Config::from_reader(content) else { panic!("expected ConfigInvalid") };
+        let problems: Vec<&str> = problems.lines().collect();
+        assert_eq!(
+            problems,
+            vec![
+                "line 3: PORT must be between 1 and 65535, found '70000'",
+                "line 4: NPEERS must be between 1 and 64, found '-1'",
+            ]
+        );
+    }
+
+    #[test]
+    fn config_diff_separa_los_valores_que_requieren_reiniciar() -> Result<(), CustomError> {
+        let base = "SEED=seed.test\n\
+        PROTOCOL_VERSION=70015\n\
+        PORT=4321\n";
+        let active = Config::from_reader(base.as_bytes())?;
+        assert_eq!(active.log_level, LogLevel::Info);
+        assert_eq!(active.alert_threshold_sats, 0);
+        assert_eq!(
+            active.diff(&Config::from_reader(base.as_bytes())?),
+            ConfigDelta::default()
+        );
+
+        let reloaded = Config::from_reader(
+            format!(
+                "{base}LOG_LEVEL=error\n\
+                DUST_THRESHOLD=1000\n\
+                ALERT_THRESHOLD_SATS=100000000\n\
+                MAX_NOTFOUND_RETRIES=2\n\
+                PRICE_SOURCE=manual:30000\n\
+                NPEERS=4\n\
+                PING_TIMEOUT=10"
+            )
+            .as_bytes(),
+        )?;
+        let delta = active.diff(&reloaded);
+        assert_eq!(delta.log_level, Some(LogLevel::Error));
+        assert_eq!(delta.dust_threshold, Some(1000));
+        assert_eq!(delta.min_spend_confirmations, None);
+        assert_eq!(delta.alert_threshold_sats, Some(100_000_000));
+        assert_eq!(delta.max_notfound_retries, Some(2));
+        assert_eq!(delta.price_source, Some(reloaded.price_source.clone()));
+        assert_eq!(
+            delta.applied(),
+            vec![
+                "LOG_LEVEL",
+                "DUST_THRESHOLD",
+                "ALERT_THRESHOLD_SATS",
+                "MAX_NOTFOUND_RETRIES",
+                "PRICE_SOURCE"
+            ]
+        );
+        assert_eq!(delta.restart_required, vec!["NPEERS", "PING_TIMEOUT"]);
+
+        // aplicado el delta, los valores que requieren reiniciar se siguen informando
+        let mut active = active;
+        active.apply(&delta);
+        let delta = active.diff(&reloaded);
+        assert!(delta.applied().is_empty());
+        assert_eq!(delta.restart_required, vec!["NPEERS", "PING_TIMEOUT"]);
+
+        // quitar un valor del archivo vuelve a su valor por defecto
+        let delta = active.diff(&Config::from_reader(base.as_bytes())?);
+        assert_eq!(delta.price_source, Some(None));
+        assert_eq!(delta.log_level, Some(LogLevel::Info));
+        Ok(())
+    }
 }
@@ -0,0 +1,146 @@
+use std::sync::{mpsc, Arc, Mutex};
+
+use crate::{
+    config::{Config, ConfigDelta},
+    error::CustomError,
+    logger::{send_log, Log},
+    loops::node_action_loop::NodeAction,
+};
+
+/// Numero de la señal SIGHUP, con la que se pide recargar el config.
+#[cfg(unix)]
+const SIGHUP: i32 = 1;
+
+#[derive(Clone)]
+/// ConfigReloader vuelve a leer el archivo de configuracion mientras el nodo esta corriendo y avisa al nodo
+/// los valores que se pueden cambiar sin reiniciar (ver ConfigDelta) con NodeAction::ConfigChanged.
+/// Los elementos son:
+/// - path: Path del archivo de configuracion.
+/// - active: Config con el que esta corriendo el nodo, con los valores recargados ya aplicados.
+/// - node_action_sender: Sender para enviar acciones al nodo.
+/// - logger_sender: Sender para enviar logs al logger.
+pub struct ConfigReloader {
+    path: String,
+    active: Arc<Mutex<Config>>,
+    node_action_sender: mpsc::Sender<NodeAction>,
+    logger_sender: mpsc::Sender<Log>,
+}
+
+impl ConfigReloader {
+    /// Inicializa el ConfigReloader con el config con el que arranco el nodo.
+    pub fn new(
+        path: &str,
+        config: Config,
+        node_action_sender: mpsc::Sender<NodeAction>,
+        logger_sender: mpsc::Sender<Log>,
+    ) -> Self {
+        Self {
+            path: path.to_string(),
+            active: Arc::new(Mutex::new(config)),
+            node_action_sender,
+            logger_sender,
+        }
+    }
+
+    /// Vuelve a leer el archivo de configuracion y lo compara con el config activo.
+    /// Si cambiaron valores que se aplican sin reiniciar, los envia al nodo con NodeAction::ConfigChanged,
+    /// y loguea los valores que cambiaron pero necesitan reiniciar el nodo.
+    /// Si el archivo tiene problemas devuelve el error de Config::from_file y el config activo no cambia.
+    pub fn reload(&self) -> Result<ConfigDelta, CustomError> {
+        let reloaded = Config::from_file(&self.path)?;
+        let mut active = self.active.lock()?;
+        let delta = active.diff(&reloaded);
+        active.apply(&delta);
+        drop(active);
+
+        // se loguea antes de aplicar los cambios, que pueden subir el LOG_LEVEL y descartar el mensaje
+        if !delta.restart_required.is_empty() {
+            send_log(
+                &self.logger_sender,
+                Log::Message(format!(
+                    "Config reloaded, changes to {} need a restart to take effect",
+                    delta.restart_required.join(", ")
+                )),
+            );
+        }
+        if !delta.applied().is_empty() {
+            self.node_action_sender
+                .send(NodeAction::ConfigChanged(delta.clone()))?;
+        }
+        if delta == ConfigDelta::default() {
+            send_log(
+                &self.logger_sender,
+                Log::Message("Config reloaded, nothing changed".to_string()),
+            );
+        }
+        Ok(delta)
+    }
+
+    /// Recarga el config como reload, logueando el error si el archivo tiene problemas.
+    pub fn reload_and_log(&self) {
+        if let Err(error) = self.reload() {
+            send_log(&self.logger_sender, Log::Error(error));
+        }
+    }
+}
+
+/// Recarga el config con reloader cada vez que el proceso recibe SIGHUP.
+/// La señal se atiende en el main loop de glib, que corre tanto con la interfaz grafica como con la linea de estado.
+#[cfg(unix)]
+pub fn reload_on_sighup(reloader: ConfigReloader) {
+    gtk::glib::unix_signal_add(SIGHUP, move || {
+        reloader.reload_and_log();
+        gtk::glib::Continue(true)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::logger::LogLevel;
+
+    use super::*;
+
+    const BASE_CONFIG: &str = "SEED=seed.test\nPROTOCOL_VERSION=70015\nPORT=4321\n";
+
+    #[test]
+    fn reload_sends_the_safe_changes_to_the_node() {
+        let path = "tests/config_reload.txt";
+        fs::write(path, BASE_CONFIG).unwrap();
+        let (node_action_sender, node_action_receiver) = mpsc::channel();
+        let (logger_sender, logger_receiver) = mpsc::channel();
+        let reloader = ConfigReloader::new(
+            path,
+            Config::from_file(path).unwrap(),
+            node_action_sender,
+            logger_sender,
+        );
+
+        assert_eq!(reloader.reload().unwrap(), ConfigDelta::default());
+        assert!(node_action_receiver.try_recv().is_err());
+        assert!(matches!(logger_receiver.try_recv(), Ok(Log::Message(_))));
+
+        fs::write(path, format!("{BASE_CONFIG}LOG_LEVEL=error\nNPEERS=4\n")).unwrap();
+        let delta = reloader.reload().unwrap();
+        assert_eq!(delta.log_level, Some(LogLevel::Error));
+        assert_eq!(delta.restart_required, vec!["NPEERS"]);
+        let Ok(NodeAction::ConfigChanged(sent)) = node_action_receiver.try_recv() else { panic!("expected ConfigChanged") };
+        assert_eq!(sent, delta);
+        let Ok(Log::Message(message)) = logger_receiver.try_recv() else { panic!("expected a log message") };
+        assert!(message.contains("NPEERS"));
+
+        // un archivo invalido no cambia el config activo
+        fs::write(path, "SEED=seed.test\nPORT=0\n").unwrap();
+        assert!(matches!(
+            reloader.reload(),
+            Err(CustomError::ConfigInvalid(_))
+        ));
+        fs::write(path, format!("{BASE_CONFIG}LOG_LEVEL=error\nNPEERS=4\n")).unwrap();
+        let delta = reloader.reload().unwrap();
+        assert!(delta.applied().is_empty());
+        assert!(node_action_receiver.try_recv().is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+}
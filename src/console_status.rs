@@ -0,0 +1,265 @@
+use std::{
+    cell::RefCell,
+    env,
+    io::{self, Write},
+    rc::Rc,
+    sync::{mpsc, Arc, Mutex},
+};
+
+use gtk::glib::{self, Receiver};
+
+use crate::{
+    gui::init::GUIEvents,
+    logger::{send_log, Log},
+    loops::maintenance_loop::TaskStatus,
+    node_state::NodeState,
+    peer::DisconnectEvent,
+};
+
+const DEFAULT_TERMINAL_WIDTH: usize = 80;
+const REFRESH_SECONDS: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+/// SyncPhase representa la etapa de sincronizacion en la que se encuentra el nodo.
+pub enum SyncPhase {
+    #[default]
+    Headers,
+    Blocks,
+    Utxo,
+    Synced,
+}
+
+impl SyncPhase {
+    fn name(&self) -> &str {
+        match self {
+            SyncPhase::Headers => "headers",
+            SyncPhase::Blocks => "blocks",
+            SyncPhase::Utxo => "utxo",
+            SyncPhase::Synced => "synced",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+/// StatusSnapshot es una foto del estado del nodo que se muestra en la linea de estado.
+/// Los elementos son:
+/// - phase: Etapa de sincronizacion.
+/// - progress: Porcentaje de avance de la etapa.
+/// - rate: Elementos (headers o bloques) procesados por segundo.
+/// - peers: Cantidad de peers conectados.
+/// - recent_disconnects: Ultimas desconexiones de peers con sus motivos, de la mas antigua a la mas reciente.
+/// - tip_height: Altura del ultimo header.
+/// - mempool_size: Cantidad de transacciones pendientes.
+/// - utxo_total: Suma en satoshis de los valores de las UTXO (solo las desde START_DATE_IBD), ver UTXO::total_value.
+/// - maintenance_tasks: Tareas periodicas del nodo con su ultima y proxima ejecucion.
+pub struct StatusSnapshot {
+    pub phase: SyncPhase,
+    pub progress: u64,
+    pub rate: usize,
+    pub peers: usize,
+    pub recent_disconnects: Vec<DisconnectEvent>,
+    pub tip_height: usize,
+    pub mempool_size: usize,
+    pub utxo_total: u64,
+    pub maintenance_tasks: Vec<TaskStatus>,
+}
+
+impl StatusSnapshot {
+    /// Actualiza el estado con un GUIEvent del nodo, los mismos que recibe la interfaz grafica.
+    /// Devuelve true si cambio algo de lo que muestra la linea de estado.
+    /// - HeadersProgress y BlocksProgress: Etapa de headers o de bloques, con su porcentaje y velocidad.
+    ///   Con todos los bloques descargados pasa a la etapa utxo, mientras se generan las UTXO.
+    /// - NodeStateReady: El nodo esta sincronizado.
+    /// - NewHeaders: Altura del ultimo header.
+    /// - PeersUpdated: Cantidad de peers conectados.
+    /// - MempoolChanged: Cantidad de transacciones pendientes.
+    pub fn apply(&mut self, event: &GUIEvents) -> bool {
+        match event {
+            GUIEvents::HeadersProgress { percentage, rate } => {
+                self.phase = SyncPhase::Headers;
+                self.progress = *percentage;
+                self.rate = *rate;
+            }
+            GUIEvents::BlocksProgress { percentage, rate } if *percentage >= 100 => {
+                self.phase = SyncPhase::Utxo;
+                self.progress = 0;
+                self.rate = *rate;
+            }
+            GUIEvents::BlocksProgress { percentage, rate } => {
+                self.phase = SyncPhase::Blocks;
+                self.progress = *percentage as u64;
+                self.rate = *rate;
+            }
+            GUIEvents::NodeStateReady => {
+                self.phase = SyncPhase::Synced;
+                self.progress = 100;
+            }
+            GUIEvents::NewHeaders { tip_height } => self.tip_height = *tip_height,
+            GUIEvents::PeersUpdated { peers } => self.peers = *peers,
+            GUIEvents::MempoolChanged { size } => self.mempool_size = *size,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Arma la linea de estado a partir de un StatusSnapshot, recortandola al ancho de la terminal.
+pub fn format_status_line(snapshot: &StatusSnapshot, width: usize) -> String {
+    let progress = match snapshot.phase {
+        SyncPhase::Synced => String::new(),
+        SyncPhase::Utxo => format!(" {}%", snapshot.progress),
+        _ => format!(" {}% ({}/s)", snapshot.progress, snapshot.rate),
+    };
+
+    let line = format!(
+        "[{}]{} | peers {} | height {} | mempool {}",
+        snapshot.phase.name(),
+        progress,
+        snapshot.peers,
+        snapshot.tip_height,
+        snapshot.mempool_size
+    );
+
+    line.chars().take(width).collect()
+}
+
+/// Devuelve el ancho de la terminal a partir de la variable de entorno COLUMNS.
+/// Si no esta definida, devuelve un ancho por defecto.
+fn terminal_width() -> usize {
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse::<usize>().ok())
+        .filter(|columns| *columns > 0)
+        .unwrap_or(DEFAULT_TERMINAL_WIDTH)
+}
+
+/// Imprime la linea de estado sobre la anterior (sin salto de linea).
+fn render(line: &str) {
+    print!("\r\x1b[2K{}", line);
+    let _ = io::stdout().flush();
+}
+
+/// Inicia el modo de linea de estado, usado en lugar de la interfaz grafica.
+/// Arranca con un StatusSnapshot del nodo y despues lo actualiza con los GUIEvents que recibe, como la interfaz
+/// grafica (ver StatusSnapshot::apply), sin volver a tomar el lock del NodeState.
+/// Cada segundo vuelve a dibujar la linea, para ajustarla al ancho de la terminal.
+pub fn start(
+    gui_receiver: Receiver<GUIEvents>,
+    node_state_ref: Arc<Mutex<NodeState>>,
+    logger_sender: mpsc::Sender<Log>,
+) {
+    let snapshot = match node_state_ref.lock() {
+        Ok(node_state) => node_state.get_status_snapshot(),
+        Err(error) => Err(error.into()),
+    };
+    let snapshot = snapshot.unwrap_or_else(|error| {
+        send_log(&logger_sender, Log::Error(error));
+        StatusSnapshot::default()
+    });
+    render(&format_status_line(&snapshot, terminal_width()));
+    let snapshot = Rc::new(RefCell::new(snapshot));
+
+    let events_snapshot = snapshot.clone();
+    gui_receiver.attach(None, move |event| {
+        let mut snapshot = events_snapshot.borrow_mut();
+        if snapshot.apply(&event) {
+            render(&format_status_line(&snapshot, terminal_width()));
+        }
+        glib::Continue(true)
+    });
+
+    glib::timeout_add_seconds_local(REFRESH_SECONDS, move || {
+        render(&format_status_line(&snapshot.borrow(), terminal_width()));
+        glib::Continue(true)
+    });
+
+    glib::MainLoop::new(None, false).run();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(phase: SyncPhase) -> StatusSnapshot {
+        StatusSnapshot {
+            phase,
+            progress: 45,
+            rate: 120,
+            peers: 8,
+            recent_disconnects: vec![],
+            tip_height: 2450000,
+            mempool_size: 35,
+            utxo_total: 0,
+            maintenance_tasks: vec![],
+        }
+    }
+
+    #[test]
+    fn status_line_headers_and_blocks_phases() {
+        assert_eq!(
+            format_status_line(&snapshot(SyncPhase::Headers), 80),
+            "[headers] 45% (120/s) | peers 8 | height 2450000 | mempool 35"
+        );
+        assert_eq!(
+            format_status_line(&snapshot(SyncPhase::Blocks), 80),
+            "[blocks] 45% (120/s) | peers 8 | height 2450000 | mempool 35"
+        );
+    }
+
+    #[test]
+    fn status_line_utxo_and_synced_phases() {
+        assert_eq!(
+            format_status_line(&snapshot(SyncPhase::Utxo), 80),
+            "[utxo] 45% | peers 8 | height 2450000 | mempool 35"
+        );
+        assert_eq!(
+            format_status_line(&snapshot(SyncPhase::Synced), 80),
+            "[synced] | peers 8 | height 2450000 | mempool 35"
+        );
+    }
+
+    #[test]
+    fn status_line_follows_the_gui_events() {
+        let mut status = StatusSnapshot::default();
+        assert!(status.apply(&GUIEvents::HeadersProgress {
+            percentage: 30,
+            rate: 2000
+        }));
+        assert!(status.apply(&GUIEvents::NewHeaders { tip_height: 150000 }));
+        assert!(status.apply(&GUIEvents::PeersUpdated { peers: 8 }));
+        assert_eq!(
+            format_status_line(&status, 80),
+            "[headers] 30% (2000/s) | peers 8 | height 150000 | mempool 0"
+        );
+
+        assert!(status.apply(&GUIEvents::BlocksProgress {
+            percentage: 45,
+            rate: 12
+        }));
+        assert!(!status.apply(&GUIEvents::NewPendingTx));
+        assert!(status.apply(&GUIEvents::MempoolChanged { size: 35 }));
+        assert_eq!(
+            format_status_line(&status, 80),
+            "[blocks] 45% (12/s) | peers 8 | height 150000 | mempool 35"
+        );
+
+        // con todos los bloques descargados se generan las UTXO hasta que el nodo esta listo
+        assert!(status.apply(&GUIEvents::BlocksProgress {
+            percentage: 100,
+            rate: 12
+        }));
+        assert_eq!(status.phase, SyncPhase::Utxo);
+        assert!(status.apply(&GUIEvents::NodeStateReady));
+        assert_eq!(
+            format_status_line(&status, 80),
+            "[synced] | peers 8 | height 150000 | mempool 35"
+        );
+    }
+
+    #[test]
+    fn status_line_gets_truncated_to_terminal_width() {
+        let line = format_status_line(&snapshot(SyncPhase::Blocks), 20);
+        assert_eq!(line, "[blocks] 45% (120/s)");
+        assert_eq!(format_status_line(&snapshot(SyncPhase::Blocks), 0), "");
+    }
+}
@@ -0,0 +1,464 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::Read,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    error::CustomError,
+    message::Message,
+    messages::{block::Block, transaction::Transaction},
+    node_state::NodeState,
+    structs::{movement::Movement, tx_output::TransactionOutput},
+    wallet::address_from_pubkey_hash,
+};
+
+/// Cantidad maxima de bloques guardados, empezando por los mas recientes, en los que se buscan las transacciones
+/// que fondean los inputs de los pagos recibidos.
+pub const FUNDING_LOOKUP_BLOCKS: usize = 144;
+
+#[derive(Debug, Clone, PartialEq)]
+/// Counterparty es una direccion con la que opero la wallet activa.
+/// Los elementos son:
+/// - address: Direccion P2PKH.
+/// - label: Nombre asignado a la direccion, si tiene.
+/// - count: Cantidad de movimientos de la wallet en los que participa.
+/// - total_value: Suma en satoshis de lo que se le envio y de lo que se recibio de ella.
+pub struct Counterparty {
+    pub address: String,
+    pub label: Option<String>,
+    pub count: usize,
+    pub total_value: u64,
+}
+
+#[derive(Debug, Default)]
+/// CounterpartyIndex guarda las contrapartes de cada movimiento de la wallet activa, que se obtienen leyendo sus
+/// transacciones de los bloques guardados:
+/// - En los envios, las direcciones de los outputs que no son el cambio, con el valor de cada output.
+/// - En los pagos recibidos, las direcciones de los outputs que gastan sus inputs, que se buscan en las transacciones
+///   que los fondean. Cada direccion cuenta con el valor completo del pago.
+/// Los elementos son:
+/// - pubkey: Public key de la wallet a la que pertenecen los movimientos.
+/// - movements: Contrapartes (direccion y valor) por hash de transaccion.
+/// - funding_outputs: Outputs de las transacciones que fondean los pagos recibidos, por hash de transaccion.
+/// - missing_funding: Transacciones que fondean pagos recibidos que no estan en los ultimos FUNDING_LOOKUP_BLOCKS bloques,
+///   para no volver a buscarlas.
+pub struct CounterpartyIndex {
+    pubkey: Option<String>,
+    movements: HashMap<Vec<u8>, Vec<(String, u64)>>,
+    funding_outputs: HashMap<Vec<u8>, Vec<TransactionOutput>>,
+    missing_funding: HashSet<Vec<u8>>,
+}
+
+impl CounterpartyIndex {
+    /// Vacia las contrapartes de los movimientos si son de otra wallet. Las transacciones que fondean se conservan.
+    pub fn use_wallet(&mut self, pubkey: &str) {
+        if self.pubkey.as_deref() != Some(pubkey) {
+            self.movements.clear();
+            self.pubkey = Some(pubkey.to_string());
+        }
+    }
+
+    /// Devuelve true si ya se registraron las contrapartes del movimiento.
+    pub fn is_indexed(&self, tx_hash: &[u8]) -> bool {
+        self.movements.contains_key(tx_hash)
+    }
+
+    /// Devuelve los hashes de las transacciones que fondean los inputs de transaction que todavia no se buscaron.
+    pub fn missing_funding_txids(&self, transaction: &Transaction) -> Vec<Vec<u8>> {
+        if transaction.is_coinbase() {
+            return vec![];
+        }
+        transaction
+            .inputs
+            .iter()
+            .map(|input| input.previous_output.hash.clone())
+            .filter(|txid| {
+                !self.funding_outputs.contains_key(txid) && !self.missing_funding.contains(txid)
+            })
+            .collect()
+    }
+
+    /// Guarda los outputs de una transaccion que fondea un pago recibido.
+    pub fn add_funding_transaction(&mut self, transaction: &Transaction) {
+        self.funding_outputs
+            .insert(transaction.hash(), transaction.outputs.clone());
+    }
+
+    /// Registra que una transaccion que fondea un pago recibido no esta en los bloques en los que se busco.
+    pub fn mark_funding_not_found(&mut self, txid: Vec<u8>) {
+        self.missing_funding.insert(txid);
+    }
+
+    /// Registra las contrapartes del movimiento de la wallet (con public key hashes own_pubkey_hashes) en transaction.
+    /// Los inputs cuya transaccion fondeadora no se encontro no tienen contraparte.
+    pub fn index_movement(
+        &mut self,
+        movement: &Movement,
+        transaction: &Transaction,
+        own_pubkey_hashes: &[Vec<u8>],
+    ) {
+        let mut counterparties: Vec<(String, u64)> = vec![];
+        if movement.value < 0 {
+            for output in &transaction.outputs {
+                let Some(pubkey_hash) = output.get_p2pkh_hash() else { continue };
+                if own_pubkey_hashes.contains(&pubkey_hash) {
+                    continue;
+                }
+                let address = address_from_pubkey_hash(&pubkey_hash);
+                match counterparties
+                    .iter_mut()
+                    .find(|(known, _)| *known == address)
+                {
+                    Some((_, value)) => *value += output.value,
+                    None => counterparties.push((address, output.value)),
+                }
+            }
+        } else {
+            for input in &transaction.inputs {
+                let previous_output = &input.previous_output;
+                let Some(output) = self
+                    .funding_outputs
+                    .get(&previous_output.hash)
+                    .and_then(|outputs| outputs.get(previous_output.index as usize))
+                else {
+                    continue;
+                };
+                let Some(pubkey_hash) = output.get_p2pkh_hash() else { continue };
+                if own_pubkey_hashes.contains(&pubkey_hash) {
+                    continue;
+                }
+                let address = address_from_pubkey_hash(&pubkey_hash);
+                if !counterparties.iter().any(|(known, _)| *known == address) {
+                    counterparties.push((address, movement.value as u64));
+                }
+            }
+        }
+        self.movements
+            .insert(movement.tx_hash.clone(), counterparties);
+    }
+
+    /// Agrupa por direccion las contrapartes de los movimientos del historial (sin los abandonados, que no tienen bloque), con el nombre
+    /// de cada direccion si tiene. Se ordenan por cantidad de movimientos y luego por valor total, de mayor a menor.
+    pub fn counterparties(
+        &self,
+        history: &[Movement],
+        labels: &HashMap<String, String>,
+    ) -> Vec<Counterparty> {
+        let mut by_address: HashMap<&String, Counterparty> = HashMap::new();
+        for movement in history
+            .iter()
+            .filter(|movement| movement.block_hash.is_some())
+        {
+            let Some(entries) = self.movements.get(&movement.tx_hash) else { continue };
+            for (address, value) in entries {
+                let counterparty = by_address.entry(address).or_insert_with(|| Counterparty {
+                    address: address.clone(),
+                    label: labels.get(address).cloned(),
+                    count: 0,
+                    total_value: 0,
+                });
+                counterparty.count += 1;
+                counterparty.total_value += value;
+            }
+        }
+
+        let mut counterparties: Vec<Counterparty> = by_address.into_values().collect();
+        counterparties.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then(b.total_value.cmp(&a.total_value))
+                .then(a.address.cmp(&b.address))
+        });
+        counterparties
+    }
+
+    /// Devuelve como se muestra la contraparte de un movimiento en el historial: el nombre de su direccion o la
+    /// direccion si no tiene, seguida de cuantas contrapartes mas tiene el movimiento.
+    /// Devuelve None si el movimiento todavia no se registro o no tiene contrapartes conocidas.
+    pub fn history_counterparty(
+        &self,
+        tx_hash: &[u8],
+        labels: &HashMap<String, String>,
+    ) -> Option<String> {
+        let entries = self.movements.get(tx_hash)?;
+        let (address, _) = entries.first()?;
+        let name = labels.get(address).unwrap_or(address);
+        match entries.len() {
+            1 => Some(name.clone()),
+            len => Some(format!("{name} (+{})", len - 1)),
+        }
+    }
+}
+
+/// Registra en el indice las contrapartes de los movimientos confirmados de la wallet activa que todavia no tiene.
+/// Los bloques se leen sin tener el lock del NodeState, que solo se toma para abrir sus archivos, y las transacciones
+/// que fondean los pagos recibidos se buscan en los ultimos FUNDING_LOOKUP_BLOCKS bloques guardados.
+/// Devuelve true si se registro algun movimiento.
+pub fn resolve_counterparties(
+    node_state_ref: &Arc<Mutex<NodeState>>,
+    index_ref: &Arc<Mutex<CounterpartyIndex>>,
+) -> Result<bool, CustomError> {
+    let node_state = node_state_ref.lock()?;
+    let Some(wallet) = node_state.get_active_wallet() else { return Ok(false) };
+    let pubkey = wallet.pubkey.clone();
+    let own_pubkey_hashes = wallet.get_pubkey_hashes()?;
+    let history = wallet.get_history();
+    drop(node_state);
+
+    let mut index = index_ref.lock()?;
+    index.use_wallet(&pubkey);
+    let pending: Vec<Movement> = history
+        .into_iter()
+        .filter(|movement| {
+            movement.block_hash.is_some()
+                && !movement.provisional
+                && !index.is_indexed(&movement.tx_hash)
+        })
+        .collect();
+    drop(index);
+    if pending.is_empty() {
+        return Ok(false);
+    }
+
+    let mut transactions: HashMap<Vec<u8>, Transaction> = HashMap::new();
+    let block_hashes: HashSet<&Vec<u8>> = pending
+        .iter()
+        .filter_map(|movement| movement.block_hash.as_ref())
+        .collect();
+    for block_hash in block_hashes {
+        let Some(block) = read_stored_block(node_state_ref, block_hash)? else { continue };
+        for transaction in block.transactions {
+            let tx_hash = transaction.hash();
+            if pending.iter().any(|movement| movement.tx_hash == tx_hash) {
+                transactions.insert(tx_hash, transaction);
+            }
+        }
+    }
+
+    let index = index_ref.lock()?;
+    let mut needed: HashSet<Vec<u8>> = pending
+        .iter()
+        .filter(|movement| movement.value > 0)
+        .filter_map(|movement| transactions.get(&movement.tx_hash))
+        .flat_map(|transaction| index.missing_funding_txids(transaction))
+        .collect();
+    drop(index);
+
+    let mut funding = vec![];
+    for transaction in transactions.values() {
+        if needed.remove(&transaction.hash()) {
+            funding.push(transaction.clone());
+        }
+    }
+    if !needed.is_empty() {
+        let recent_blocks = node_state_ref
+            .lock()?
+            .recent_stored_blocks(FUNDING_LOOKUP_BLOCKS);
+        for block_hash in recent_blocks {
+            if needed.is_empty() {
+                break;
+            }
+            let Some(block) = read_stored_block(node_state_ref, &block_hash)? else { continue };
+            for transaction in block.transactions {
+                if needed.remove(&transaction.hash()) {
+                    funding.push(transaction);
+                }
+            }
+        }
+    }
+
+    let mut index = index_ref.lock()?;
+    // la wallet activa cambio mientras se leian los bloques
+    if index.pubkey.as_deref() != Some(pubkey.as_str()) {
+        return Ok(false);
+    }
+    for transaction in &funding {
+        index.add_funding_transaction(transaction);
+    }
+    for txid in needed {
+        index.mark_funding_not_found(txid);
+    }
+    for movement in &pending {
+        if let Some(transaction) = transactions.get(&movement.tx_hash) {
+            index.index_movement(movement, transaction, &own_pubkey_hashes);
+        }
+    }
+    Ok(true)
+}
+
+/// Lee un bloque guardado. Devuelve None si no esta en disco o no se puede parsear (por ejemplo un bloque filtrado).
+fn read_stored_block(
+    node_state_ref: &Arc<Mutex<NodeState>>,
+    block_hash: &[u8],
+) -> Result<Option<Block>, CustomError> {
+    let Ok(mut file) = node_state_ref.lock()?.get_block_file(block_hash) else { return Ok(None) };
+    let mut buffer = vec![];
+    file.read_to_end(&mut buffer)?;
+    Ok(Block::parse(buffer).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::structs::{outpoint::OutPoint, tx_input::TransactionInput};
+
+    use super::*;
+
+    fn p2pkh_output(pubkey_hash: &[u8], value: u64) -> TransactionOutput {
+        let mut script_pubkey = vec![0x76, 0xa9, 0x14];
+        script_pubkey.extend(pubkey_hash);
+        script_pubkey.extend([0x88, 0xac]);
+        TransactionOutput {
+            value,
+            script_pubkey,
+        }
+    }
+
+    fn transaction(inputs: Vec<OutPoint>, outputs: Vec<TransactionOutput>) -> Transaction {
+        Transaction {
+            version: 1,
+            inputs: inputs
+                .into_iter()
+                .map(|previous_output| TransactionInput {
+                    previous_output,
+                    script_sig: vec![],
+                    sequence: 0xffffffff,
+                })
+                .collect(),
+            outputs,
+            lock_time: 0,
+        }
+    }
+
+    fn movement(transaction: &Transaction, value: i64) -> Movement {
+        Movement {
+            tx_hash: transaction.hash(),
+            value,
+            block_hash: Some(vec![1; 32]),
+            provisional: false,
+        }
+    }
+
+    #[test]
+    fn counterparties_group_sends_and_receipts_by_address() {
+        let own = [0xaa; 20];
+        let bob = [0xbb; 20];
+        let carol = [0xcc; 20];
+        let (bob_address, carol_address) = (
+            address_from_pubkey_hash(&bob),
+            address_from_pubkey_hash(&carol),
+        );
+
+        // Bob fondea un pago que recibimos
+        let funding = transaction(vec![], vec![p2pkh_output(&bob, 9000)]);
+        let received = transaction(
+            vec![OutPoint {
+                hash: funding.hash(),
+                index: 0,
+            }],
+            vec![p2pkh_output(&own, 5000), p2pkh_output(&bob, 3900)],
+        );
+        // dos envios a Carol, el primero con cambio
+        let sent_with_change = transaction(
+            vec![OutPoint {
+                hash: received.hash(),
+                index: 0,
+            }],
+            vec![p2pkh_output(&carol, 3000), p2pkh_output(&own, 1900)],
+        );
+        let sent = transaction(
+            vec![OutPoint {
+                hash: sent_with_change.hash(),
+                index: 1,
+            }],
+            vec![p2pkh_output(&carol, 1800)],
+        );
+        // un pago cuyo fondeo no se encontro no tiene contraparte
+        let unresolved = transaction(
+            vec![OutPoint {
+                hash: vec![7; 32],
+                index: 0,
+            }],
+            vec![p2pkh_output(&own, 700)],
+        );
+
+        let mut index = CounterpartyIndex::default();
+        assert_eq!(index.missing_funding_txids(&received), vec![funding.hash()]);
+        index.add_funding_transaction(&funding);
+        index.mark_funding_not_found(vec![7; 32]);
+        assert!(index.missing_funding_txids(&received).is_empty());
+        assert!(index.missing_funding_txids(&unresolved).is_empty());
+
+        let history = vec![
+            movement(&received, 5000),
+            movement(&sent_with_change, -3100),
+            movement(&sent, -1900),
+            movement(&unresolved, 700),
+        ];
+        let transactions = [&received, &sent_with_change, &sent, &unresolved];
+        for (movement, transaction) in history.iter().zip(transactions) {
+            index.index_movement(movement, transaction, &[own.to_vec()]);
+        }
+
+        let counterparties = index.counterparties(&history, &HashMap::new());
+        assert_eq!(
+            counterparties,
+            vec![
+                Counterparty {
+                    address: carol_address.clone(),
+                    label: None,
+                    count: 2,
+                    total_value: 4800,
+                },
+                Counterparty {
+                    address: bob_address.clone(),
+                    label: None,
+                    count: 1,
+                    total_value: 5000,
+                },
+            ]
+        );
+        assert_eq!(
+            index.history_counterparty(&unresolved.hash(), &HashMap::new()),
+            None
+        );
+    }
+
+    #[test]
+    fn labels_reach_counterparties_and_history() {
+        let own = [0xaa; 20];
+        let (bob, carol) = ([0xbb; 20], [0xcc; 20]);
+        let carol_address = address_from_pubkey_hash(&carol);
+        let split = transaction(
+            vec![OutPoint {
+                hash: vec![3; 32],
+                index: 0,
+            }],
+            vec![p2pkh_output(&carol, 1000), p2pkh_output(&bob, 2000)],
+        );
+        let history = vec![movement(&split, -3100)];
+
+        let mut index = CounterpartyIndex::default();
+        index.index_movement(&history[0], &split, &[own.to_vec()]);
+
+        let labels = HashMap::from([(carol_address.clone(), String::from("Carol"))]);
+        let counterparties = index.counterparties(&history, &labels);
+        let carol_row = counterparties
+            .iter()
+            .find(|counterparty| counterparty.address == carol_address)
+            .unwrap();
+        assert_eq!(carol_row.label.as_deref(), Some("Carol"));
+        assert_eq!(
+            index.history_counterparty(&split.hash(), &labels),
+            Some(String::from("Carol (+1)"))
+        );
+        assert_eq!(
+            index.history_counterparty(&split.hash(), &HashMap::new()),
+            Some(format!("{carol_address} (+1)"))
+        );
+
+        // al cambiar de wallet se descartan sus movimientos
+        index.use_wallet("other wallet");
+        assert!(!index.is_indexed(&split.hash()));
+    }
+}
@@ -0,0 +1,231 @@
+use std::{
+    cell::Cell,
+    fs,
+    panic::{self, PanicHookInfo, UnwindSafe},
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, Mutex, OnceLock},
+    thread,
+};
+
+use chrono::{DateTime, Local};
+
+use crate::{
+    console_status::format_status_line, error::CustomError, logger::LogHistory,
+    node_state::NodeState,
+};
+
+/// El nodo se cerro normalmente.
+pub const EXIT_CLEAN: i32 = 0;
+/// El nodo no pudo iniciar por un error sin un codigo propio.
+pub const EXIT_FAILURE: i32 = 1;
+/// Falta el archivo de configuracion o tiene valores invalidos.
+pub const EXIT_CONFIG_ERROR: i32 = 2;
+/// Otro proceso esta usando el store (ver StoreLock).
+pub const EXIT_STORE_LOCKED: i32 = 3;
+/// El store esta corrupto y no se pudo recuperar.
+pub const EXIT_STORE_CORRUPT: i32 = 4;
+/// Un thread del nodo entro en panico (ver install_panic_hook).
+pub const EXIT_PANIC: i32 = 5;
+
+thread_local! {
+    /// Es true mientras el thread ejecuta una tarea con catch_panics.
+    static CATCHING_PANICS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Devuelve el codigo de salida con el que termina el nodo si falla al iniciar con error,
+/// para que los scripts y los servicios (por ejemplo la politica de reinicio de systemd) sepan por que termino.
+pub fn exit_code(error: &CustomError) -> i32 {
+    match error {
+        CustomError::ConfigInvalid(_) | CustomError::ConfigMissingFile => EXIT_CONFIG_ERROR,
+        CustomError::StoreLocked => EXIT_STORE_LOCKED,
+        CustomError::SerializedBufferIsInvalid
+        | CustomError::BlockChainBroken
+        | CustomError::UtxoTotalMismatch { .. } => EXIT_STORE_CORRUPT,
+        _ => EXIT_FAILURE,
+    }
+}
+
+/// Ejecuta task atrapando sus panics como panic::catch_unwind, pero sin que el panic hook del nodo
+/// lo termine. Lo usan las tareas cuyo panic no debe afectar al resto (ver Scheduler::run_due).
+pub fn catch_panics<R>(task: impl FnOnce() -> R + UnwindSafe) -> thread::Result<R> {
+    let previous = CATCHING_PANICS.with(|catching| catching.replace(true));
+    let result = panic::catch_unwind(task);
+    CATCHING_PANICS.with(|catching| catching.set(previous));
+    result
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// CrashReport es el reporte que se escribe en el store cuando un thread del nodo entra en panico.
+/// Los elementos son:
+/// - timestamp: Fecha y hora del panic.
+/// - thread: Nombre del thread que entro en panico.
+/// - message: Mensaje del panic, con su ubicacion en el codigo.
+/// - log_lines: Ultimas lineas del log (ver LogHistory).
+/// - status: Linea de estado del nodo (ver format_status_line), o None si no se pudo obtener.
+pub struct CrashReport {
+    pub timestamp: DateTime<Local>,
+    pub thread: String,
+    pub message: String,
+    pub log_lines: Vec<String>,
+    pub status: Option<String>,
+}
+
+impl CrashReport {
+    /// Devuelve el contenido del archivo del reporte.
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "Crash report\nTimestamp: {}\nThread: {}\nPanic: {}\nStatus: {}\n\nLast {} log lines:\n",
+            self.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            self.thread,
+            self.message,
+            self.status.as_deref().unwrap_or("not available"),
+            self.log_lines.len()
+        );
+        for line in &self.log_lines {
+            text.push_str(line);
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Escribe el reporte en crash-<fecha y hora>.txt dentro del store y devuelve la ubicacion del archivo.
+    pub fn write(&self, store_path: &str) -> Result<PathBuf, CustomError> {
+        let path = Path::new(store_path).join(format!(
+            "crash-{}.txt",
+            self.timestamp.format("%Y%m%d-%H%M%S")
+        ));
+        fs::write(&path, self.to_text())?;
+        Ok(path)
+    }
+}
+
+/// Instala el panic hook del nodo. Ademas de imprimir el panic como el hook por defecto, si no se
+/// atrapa con catch_panics escribe un CrashReport en el store y termina el proceso con EXIT_PANIC:
+/// un thread muerto deja al nodo a medias, por ejemplo con el lock del NodeState envenenado.
+/// Se instala antes de cargar el NodeState, para cubrir tambien los panics de la carga: devuelve el lugar
+/// donde guardar el NodeState una vez creado, hasta entonces el reporte no tiene la linea de estado.
+pub fn install_panic_hook(
+    store_path: String,
+    log_history: LogHistory,
+) -> Arc<OnceLock<Arc<Mutex<NodeState>>>> {
+    let node_state_slot = Arc::new(OnceLock::new());
+    let hook_node_state_slot = node_state_slot.clone();
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        if CATCHING_PANICS.with(Cell::get) {
+            return;
+        }
+
+        let report = CrashReport {
+            timestamp: Local::now(),
+            thread: thread::current().name().unwrap_or("unnamed").to_string(),
+            message: panic_message(info),
+            log_lines: log_history.lines(),
+            status: status_line(&hook_node_state_slot),
+        };
+        match report.write(&store_path) {
+            Ok(path) => eprintln!("Crash report written to {}", path.display()),
+            Err(error) => eprintln!("Cannot write crash report: {error}"),
+        }
+        process::exit(EXIT_PANIC);
+    }));
+    node_state_slot
+}
+
+fn panic_message(info: &PanicHookInfo) -> String {
+    let payload = info.payload();
+    let message = match (
+        payload.downcast_ref::<&str>(),
+        payload.downcast_ref::<String>(),
+    ) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => String::from("unknown panic payload"),
+    };
+    match info.location() {
+        Some(location) => format!("{message} at {location}"),
+        None => message,
+    }
+}
+
+/// Devuelve la linea de estado del nodo, o None si todavia no se cargo el NodeState.
+/// El lock se intenta tomar sin esperar, ya que puede tenerlo el thread que entro en panico o haber quedado envenenado.
+fn status_line(node_state_slot: &OnceLock<Arc<Mutex<NodeState>>>) -> Option<String> {
+    let node_state = node_state_slot.get()?.try_lock().ok()?;
+    let snapshot = node_state.get_status_snapshot().ok()?;
+    Some(format_status_line(&snapshot, usize::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn startup_errors_map_to_exit_codes() {
+        assert_eq!(
+            exit_code(&CustomError::ConfigInvalid("line 1".to_string())),
+            EXIT_CONFIG_ERROR
+        );
+        assert_eq!(
+            exit_code(&CustomError::ConfigMissingFile),
+            EXIT_CONFIG_ERROR
+        );
+        assert_eq!(exit_code(&CustomError::StoreLocked), EXIT_STORE_LOCKED);
+        assert_eq!(
+            exit_code(&CustomError::SerializedBufferIsInvalid),
+            EXIT_STORE_CORRUPT
+        );
+        assert_eq!(
+            exit_code(&CustomError::UtxoTotalMismatch {
+                saved: 1,
+                computed: 2
+            }),
+            EXIT_STORE_CORRUPT
+        );
+        assert_eq!(exit_code(&CustomError::CannotInitGUI), EXIT_FAILURE);
+    }
+
+    #[test]
+    fn caught_panics_do_not_end_the_process() {
+        let result = catch_panics(|| -> u32 { panic!("task") });
+        assert!(result.is_err());
+        assert!(!CATCHING_PANICS.with(Cell::get));
+        assert_eq!(catch_panics(|| 7).unwrap(), 7);
+    }
+
+    #[test]
+    fn crash_report_is_written_in_the_store() {
+        let store_path = "tests/store_crash_report_unit";
+        let _ = fs::remove_dir_all(store_path);
+        fs::create_dir_all(store_path).unwrap();
+
+        let report = CrashReport {
+            timestamp: Local.with_ymd_and_hms(2024, 3, 9, 14, 5, 6).unwrap(),
+            thread: "node".to_string(),
+            message: "boom at src/main.rs:1:1".to_string(),
+            log_lines: vec!["[2024-03-09 14:05:05] first".to_string()],
+            status: None,
+        };
+        let path = report.write(store_path).unwrap();
+        assert_eq!(
+            path,
+            Path::new(store_path).join("crash-20240309-140506.txt")
+        );
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "Crash report\nTimestamp: 2024-03-09 14:05:06\nThread: node\nPanic: boom at src/main.rs:1:1\n\
+            Status: not available\n\nLast 1 log lines:\n[2024-03-09 14:05:05] first\n"
+        );
+
+        fs::remove_dir_all(store_path).unwrap();
+    }
+
+    #[test]
+    fn status_line_is_not_available_before_the_node_state_loads() {
+        assert_eq!(status_line(&OnceLock::new()), None);
+    }
+}
@@ -1,13 +1,16 @@
 use core::fmt;
 use std::{
-    io::{Error, ErrorKind},
+    error::Error,
+    io::{self, ErrorKind},
     sync::{
-        mpsc::{RecvError, SendError},
-        PoisonError,
+        mpsc::{RecvError, RecvTimeoutError, SendError},
+        Arc, PoisonError,
     },
     time::SystemTimeError,
 };
 
+use crate::structs::outpoint::OutPoint;
+
 #[derive(Debug, Clone)]
 
 /// Custom error es un enum con los posibles errores que pueden ocurrir en el programa.
@@ -15,10 +18,8 @@ use std::{
 /// Cada variante debe tener un metodo description que devuelve un string con la descripcion del error.
 pub enum CustomError {
     TransactionNotFound,
-    ConfigInvalid,
-    ConfigMissingValue,
+    ConfigInvalid(String),
     ConfigMissingFile,
-    ConfigErrorReadingValue,
     CannotResolveSeedAddress,
     CannotConnectToNode,
     CannotHandshakeNode,
@@ -28,15 +29,14 @@ pub enum CustomError {
     Logging,
     CannotReadMessageHeader,
     CannotOpenFile,
-    CannotSendMessageToChannel,
+    Io(ErrorSource),
+    CannotSendMessageToChannel(Option<ErrorSource>),
     CloneFailed,
     CannotLockGuard,
-    CannotReceiveMessageFromChannel,
+    CannotReceiveMessageFromChannel(Option<ErrorSource>),
     CannotRemoveFile,
-    FileOperationInterrupted,
     HeaderInvalidPoW,
     InvalidMerkleRoot,
-    UnknownError,
     CannotInitGUI,
     CannotGetTimestamp,
     WalletNotFound,
@@ -51,6 +51,64 @@ pub enum CustomError {
     InvalidFee,
     InvalidTransferFields,
     PeerNotSynced,
+    NodeNotSynced,
+    CannotReconstructBlock,
+    MissingInput {
+        outpoint: OutPoint,
+    },
+    InvalidTransactionScript,
+    InvalidCoinbaseValue,
+    MaintenanceTaskPanicked,
+    InvalidProofOfWork,
+    UnexpectedDifficulty,
+    StreamReadTimeout,
+    StreamWriteTimeout,
+    PeerClosedConnection,
+    InvalidChecksum,
+    InvalidMagic,
+    MessageTooLarge(u32),
+    InvalidBenchArguments,
+    PeerActionsStalled,
+    InvalidPassphrase,
+    WalletsLocked,
+    InvalidUtxoSnapshot,
+    UtxoSnapshotNotOnChain,
+    ConnectedToSelf,
+    RpcUnauthorized,
+    RpcAuthCooldown,
+    RpcMethodNotAllowed(String),
+    RpcInvalidRequest,
+    RpcUnknownMethod(String),
+    RpcInvalidParams(String),
+    BlockAlreadyStored,
+    WebhookRejected(u16),
+    WebhookUnreachable,
+    WebhookInvalidResponse,
+    WalletIsWatchOnly,
+    InsufficientConfirmedFunds {
+        min_confirmations: u32,
+        spendable: u64,
+        balance: u64,
+    },
+    TransactionTooLarge {
+        estimated_size: u64,
+        max_size: u64,
+        inputs: usize,
+        consolidation_txs: usize,
+    },
+    TransactionOverRelayLimit(String),
+    UtxoTotalMismatch {
+        saved: u64,
+        computed: u64,
+    },
+    StoreLocked,
+    InvalidBlock(String),
+    SegwitSpendUnsupported {
+        segwit_value: u64,
+    },
+    RegtestOnly,
+    TransactionNotSent,
+    TransactionValueOverflow,
 }
 
 impl CustomError {
@@ -58,10 +116,8 @@ impl CustomError {
     pub fn description(&self) -> &str {
         match self {
             Self::TransactionNotFound => "transaction not found",
-            Self::ConfigInvalid => "invalid config file",
-            Self::ConfigMissingValue => "missing config values",
+            Self::ConfigInvalid(_) => "invalid config file",
             Self::ConfigMissingFile => "missing config file",
-            Self::ConfigErrorReadingValue => "error reading config value",
             Self::CannotResolveSeedAddress => "cannot resolve seed address",
             Self::CannotConnectToNode => "cannot connect to node",
             Self::CannotHandshakeNode => "cannot handshake with node",
@@ -71,17 +127,16 @@ impl CustomError {
             Self::Logging => "couldn't send log",
             Self::CannotReadMessageHeader => "cannot read message header",
             Self::CannotOpenFile => "cannot open file",
-            Self::CannotSendMessageToChannel => "receiving end of a channel is disconected",
+            Self::Io(_) => "input/output operation failed",
+            Self::CannotSendMessageToChannel(_) => "receiving end of a channel is disconected",
             Self::CloneFailed => "couldn't clone endpoint",
             Self::CannotLockGuard => "another user of mutex panicked while holding the mutex,",
-            Self::CannotReceiveMessageFromChannel => {
+            Self::CannotReceiveMessageFromChannel(_) => {
                 "cannot receive message from channel because sender has disconnected"
             }
             Self::CannotRemoveFile => "cannot remove file",
-            Self::FileOperationInterrupted => "file operation interrupted",
             Self::HeaderInvalidPoW => "header hash does not satisfy the proof of work dificulty",
             Self::InvalidMerkleRoot => "invalid merkle root",
-            Self::UnknownError => "unknown error",
             Self::CannotInitGUI => "cannot init GUI",
             Self::CannotGetTimestamp => "cannot get timestamp",
             Self::WalletNotFound => "wallet not found",
@@ -96,30 +151,157 @@ impl CustomError {
             Self::InvalidFee => "invalid fee",
             Self::InvalidTransferFields => "invalid transfer fields",
             Self::PeerNotSynced => "peer not synced",
+            Self::NodeNotSynced => {
+                "node is not synced yet, transactions can be sent once the block download finishes"
+            }
+            Self::CannotReconstructBlock => "cannot reconstruct block from compact block",
+            Self::MissingInput { .. } => "transaction input spends a missing output",
+            Self::InvalidTransactionScript => {
+                "transaction input script does not satisfy the output it spends"
+            }
+            Self::InvalidCoinbaseValue => "block creates more value than its subsidy plus fees",
+            Self::MaintenanceTaskPanicked => "maintenance task panicked",
+            Self::InvalidProofOfWork => "header hash does not meet its difficulty target",
+            Self::UnexpectedDifficulty => "header bits do not match the expected difficulty",
+            Self::StreamReadTimeout => "timed out reading from peer stream",
+            Self::StreamWriteTimeout => "timed out writing to peer stream",
+            Self::PeerClosedConnection => "peer closed the connection",
+            Self::InvalidChecksum => "message checksum does not match its payload",
+            Self::InvalidMagic => "message does not start with the network magic bytes",
+            Self::MessageTooLarge(_) => "message payload is over the maximum message size",
+            Self::InvalidBenchArguments => "invalid IBD benchmark arguments",
+            Self::PeerActionsStalled => "peers stopped taking the queued peer actions",
+            Self::InvalidPassphrase => "invalid wallets passphrase",
+            Self::WalletsLocked => "wallets are locked, unlock them with their passphrase first",
+            Self::InvalidUtxoSnapshot => "file is not a UTXO snapshot or its contents are corrupted",
+            Self::UtxoSnapshotNotOnChain => {
+                "UTXO snapshot block is not on the best header chain, sync the headers first"
+            }
+            Self::ConnectedToSelf => "peer sent our own version nonce, the node connected to itself",
+            Self::RpcUnauthorized => "missing or invalid credentials",
+            Self::RpcAuthCooldown => "too many failed authentication attempts, try again later",
+            Self::RpcMethodNotAllowed(_) => "method not allowed",
+            Self::RpcInvalidRequest => "request is not a valid JSON-RPC object",
+            Self::RpcUnknownMethod(_) => "method not found",
+            Self::RpcInvalidParams(_) => "invalid params",
+            Self::BlockAlreadyStored => "block is already stored",
+            Self::WebhookRejected(_) => "webhook rejected the event with status",
+            Self::WebhookUnreachable => "cannot resolve webhook address",
+            Self::WebhookInvalidResponse => "webhook response is not valid HTTP",
+            Self::WalletIsWatchOnly => "wallet is watch-only, it has no private key to sign transactions",
+            Self::InsufficientConfirmedFunds { .. } => {
+                "Insufficient funds with enough confirmations to spend, lower the minimum confirmations or wait for new blocks"
+            }
+            Self::TransactionTooLarge { .. } => {
+                "transaction is over the standard size limit, consolidate the wallet's UTXOs first"
+            }
+            Self::TransactionOverRelayLimit(_) => "transaction is over the relay limits",
+            Self::UtxoTotalMismatch { .. } => {
+                "total value of the saved UTXO set does not match its entries, the UTXO file is corrupted"
+            }
+            Self::StoreLocked => "store is being used by another node process",
+            Self::InvalidBlock(_) => "block rejected",
+            Self::SegwitSpendUnsupported { .. } => "segwit (P2WPKH) outputs cannot be spent yet",
+            Self::RegtestOnly => "only available when the node runs on regtest (REGTEST=true)",
+            Self::TransactionNotSent => {
+                "transaction could not be sent to any peer, it was discarded and its inputs released"
+            }
+            Self::TransactionValueOverflow => "transaction input or output values overflow",
+        }
+    }
+
+    /// Devuelve true si el error es transitorio y la operacion que lo causo puede volver a intentarse:
+    /// operaciones de entrada/salida interrumpidas o que superaron su timeout.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Io(source) => source.downcast_ref::<io::Error>().is_some_and(|error| {
+                matches!(
+                    error.kind(),
+                    ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut
+                )
+            }),
+            Self::StreamReadTimeout | Self::StreamWriteTimeout => true,
+            _ => false,
         }
     }
+
+    /// Devuelve el error seguido de sus causas (source), separadas por ": caused by: ".
+    pub fn with_sources(&self) -> String {
+        let mut chain = self.to_string();
+        let mut source = Error::source(self);
+        while let Some(error) = source {
+            chain.push_str(&format!(": caused by: {error}"));
+            source = error.source();
+        }
+        chain
+    }
+}
+
+#[derive(Debug, Clone)]
+/// ErrorSource es el error original que causo un CustomError, que se conserva para poder recorrer sus causas.
+/// Se guarda en un Arc para que CustomError siga siendo Clone.
+pub struct ErrorSource(Arc<dyn Error + Send + Sync>);
+
+impl ErrorSource {
+    /// Conserva el error recibido como causa.
+    pub fn new(error: impl Error + Send + Sync + 'static) -> Self {
+        Self(Arc::new(error))
+    }
+
+    /// Conserva solo la descripcion del error, para los errores que no se pueden guardar
+    /// (por ejemplo SendError, que contiene el mensaje que no se pudo enviar).
+    pub fn from_message(message: String) -> Self {
+        Self(Arc::new(SourceMessage(message)))
+    }
+
+    /// Devuelve el error original si es del tipo E.
+    pub fn downcast_ref<E: Error + 'static>(&self) -> Option<&E> {
+        self.0.downcast_ref::<E>()
+    }
 }
 
-impl From<Error> for CustomError {
-    fn from(error: Error) -> Self {
-        match error.kind() {
-            ErrorKind::NotFound => CustomError::CannotOpenFile,
-            ErrorKind::PermissionDenied => CustomError::CannotOpenFile,
-            ErrorKind::AlreadyExists => CustomError::CannotOpenFile,
-            ErrorKind::InvalidInput => CustomError::CannotOpenFile,
-            ErrorKind::Interrupted => CustomError::FileOperationInterrupted,
-            _ => CustomError::UnknownError,
+#[derive(Debug)]
+/// Descripcion de un error que no se pudo conservar (ver ErrorSource::from_message).
+struct SourceMessage(String);
+
+impl fmt::Display for SourceMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for SourceMessage {}
+
+impl Error for CustomError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(source)
+            | Self::CannotSendMessageToChannel(Some(source))
+            | Self::CannotReceiveMessageFromChannel(Some(source)) => Some(source.0.as_ref()),
+            _ => None,
         }
     }
 }
+
+impl From<io::Error> for CustomError {
+    fn from(error: io::Error) -> Self {
+        CustomError::Io(ErrorSource::new(error))
+    }
+}
 impl<T> From<SendError<T>> for CustomError {
-    fn from(_error: SendError<T>) -> Self {
-        CustomError::CannotSendMessageToChannel
+    fn from(error: SendError<T>) -> Self {
+        CustomError::CannotSendMessageToChannel(Some(ErrorSource::from_message(error.to_string())))
     }
 }
 impl From<RecvError> for CustomError {
-    fn from(_error: RecvError) -> Self {
-        CustomError::CannotReceiveMessageFromChannel
+    fn from(error: RecvError) -> Self {
+        CustomError::CannotReceiveMessageFromChannel(Some(ErrorSource::new(error)))
+    }
+}
+
+impl From<RecvTimeoutError> for CustomError {
+    fn from(error: RecvTimeoutError) -> Self {
+        CustomError::CannotReceiveMessageFromChannel(Some(ErrorSource::new(error)))
     }
 }
 
@@ -137,6 +319,121 @@ impl From<SystemTimeError> for CustomError {
 
 impl fmt::Display for CustomError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Error: {}", self.description())
+        match self {
+            Self::ConfigInvalid(problems) => {
+                write!(f, "Error: {}:\n{}", self.description(), problems)
+            }
+            Self::RpcMethodNotAllowed(detail)
+            | Self::RpcUnknownMethod(detail)
+            | Self::RpcInvalidParams(detail) => {
+                write!(f, "Error: {}: {}", self.description(), detail)
+            }
+            Self::WebhookRejected(status) => {
+                write!(f, "Error: {} {}", self.description(), status)
+            }
+            Self::TransactionTooLarge {
+                estimated_size,
+                max_size,
+                inputs,
+                consolidation_txs,
+            } => write!(
+                f,
+                "Error: {}: it needs {} inputs and would take {} bytes (limit {} bytes), send them to your own address choosing the inputs (coin control) in {} transactions",
+                self.description(),
+                inputs,
+                estimated_size,
+                max_size,
+                consolidation_txs
+            ),
+            Self::TransactionOverRelayLimit(limit) => {
+                write!(f, "Error: {}: {}", self.description(), limit)
+            }
+            Self::InvalidBlock(reason) => {
+                write!(f, "Error: {}: {}", self.description(), reason)
+            }
+            Self::SegwitSpendUnsupported { segwit_value } => write!(
+                f,
+                "Error: {}: {} satoshis of the wallet are in segwit outputs",
+                self.description(),
+                segwit_value
+            ),
+            Self::UtxoTotalMismatch { saved, computed } => write!(
+                f,
+                "Error: {} (saved {} satoshis, entries add up to {})",
+                self.description(),
+                saved,
+                computed
+            ),
+            _ => write!(f, "Error: {}", self.description()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, sync::mpsc, time::Duration};
+
+    use super::*;
+
+    fn open_missing_file() -> Result<File, CustomError> {
+        Ok(File::open("tests/missing_file_for_error_source.bin")?)
+    }
+
+    #[test]
+    fn io_error_keeps_its_source() {
+        let error = open_missing_file().unwrap_err();
+
+        let source = error.source().unwrap();
+        let io_error = source.downcast_ref::<io::Error>().unwrap();
+        assert_eq!(io_error.kind(), ErrorKind::NotFound);
+        assert!(!error.is_retryable());
+        assert_eq!(
+            error.with_sources(),
+            format!("Error: input/output operation failed: caused by: {io_error}")
+        );
+    }
+
+    #[test]
+    fn send_error_keeps_its_source() {
+        let (sender, receiver) = mpsc::channel();
+        drop(receiver);
+
+        let error = CustomError::from(sender.send(1).unwrap_err());
+        assert!(matches!(
+            error,
+            CustomError::CannotSendMessageToChannel(Some(_))
+        ));
+        assert_eq!(
+            error.source().unwrap().to_string(),
+            "sending on a closed channel"
+        );
+    }
+
+    #[test]
+    fn recv_timeout_error_keeps_its_source() {
+        let (sender, receiver) = mpsc::channel::<u8>();
+        drop(sender);
+
+        let error = CustomError::from(
+            receiver
+                .recv_timeout(Duration::from_millis(10))
+                .unwrap_err(),
+        );
+        assert!(matches!(
+            error,
+            CustomError::CannotReceiveMessageFromChannel(Some(_))
+        ));
+        assert_eq!(
+            error.source().unwrap().to_string(),
+            "channel is empty and sending half is closed"
+        );
+    }
+
+    #[test]
+    fn interrupted_io_error_is_retryable() {
+        let error = CustomError::from(io::Error::from(ErrorKind::Interrupted));
+        assert!(error.is_retryable());
+        assert!(CustomError::StreamReadTimeout.is_retryable());
+        assert!(!CustomError::CannotOpenFile.is_retryable());
     }
 }
@@ -1,19 +1,24 @@
 use std::sync::{mpsc, Arc, Mutex};
 
 use gtk::{
-    traits::{BoxExt, ContainerExt, LabelExt, WidgetExt},
+    glib,
+    traits::{BoxExt, ButtonExt, ContainerExt, EntryExt, LabelExt, WidgetExt},
     ListBox,
 };
 
 use crate::{
     error::CustomError,
     logger::{send_log, Log},
+    loops::node_action_loop::NodeAction,
     node_state::NodeState,
+    pricing::{format_fiat, format_rate, FiatRate},
+    utils::get_current_timestamp,
 };
 
 use super::{
     init::{get_gui_element, GUIEvents},
-    table_cells::{side_label, value_label},
+    tab::{GUIContext, GUITab},
+    table_cells::{abandon_button, attach_pending_tx_menu, side_label, value_with_fiat_label},
 };
 
 #[derive(Clone)]
@@ -24,27 +29,37 @@ use super::{
 /// - builder: Builder de gtk.
 /// - node_state_ref: Referencia al estado del nodo.
 /// - logger_sender: Sender para enviar logs al logger.
+/// - gui_sender: Sender para avisar a la interfaz grafica que cambio la cotizacion manual.
+/// - node_action_sender: Sender para pedirle al nodo que reemplace una transaccion pendiente.
 /// - available_balance: Balance disponible de la billetera.
 /// - pending_balance: Balance pendiente de la billetera.
 pub struct GUIBalance {
     pub builder: gtk::Builder,
     pub node_state_ref: Arc<Mutex<NodeState>>,
     pub logger_sender: mpsc::Sender<Log>,
+    pub gui_sender: glib::Sender<GUIEvents>,
+    pub node_action_sender: mpsc::Sender<NodeAction>,
     pub available_balance: f64,
     pub pending_balance: f64,
 }
 
-impl GUIBalance {
+impl GUITab for GUIBalance {
     /// Maneja los GUIEvents recibidos y hace las acciones acorde a cada envento.
     ///
     /// Para WalletChanged: Actualiza el balance pendiente y disponible y las transacciones pendientes.
     /// Para WalletsUpdated: Actualiza el balance pendiente y disponible y las transacciones pendientes.
     /// Para NewPendingTx: Actualiza las transacciones pendientes y el balance pendinente.
-    pub fn handle_events(&mut self, message: &GUIEvents) {
+    /// Para TransactionAbandoned y TransactionRejected: Actualiza el balance pendiente y disponible y las transacciones pendientes.
+    /// Para FiatRateChanged: Actualiza los montos en moneda fiat de los balances y las transacciones pendientes.
+    fn handle_events(&mut self, message: &GUIEvents) {
         let result = match message {
             GUIEvents::WalletChanged => self.handle_wallet_changed(),
             GUIEvents::NewPendingTx => self.handle_new_pending_tx(),
-            GUIEvents::WalletsUpdated => self.handle_wallet_updated(),
+            GUIEvents::WalletsUpdated { .. } => self.handle_wallet_updated(),
+            GUIEvents::TransactionAbandoned | GUIEvents::TransactionRejected(_) => {
+                self.handle_wallet_updated()
+            }
+            GUIEvents::FiatRateChanged => self.update_pending_txs(),
             _ => Ok(()),
         };
 
@@ -53,6 +68,50 @@ impl GUIBalance {
         }
     }
 
+    /// Establece los callbacks de los elementos de la interfaz grafica.
+    /// Si la cotizacion es manual, muestra el campo para cambiarla: al confirmarla se actualizan los montos en fiat.
+    fn handle_interactivity(&self) -> Result<(), CustomError> {
+        if !self.node_state_ref.lock()?.is_manual_rate() {
+            return Ok(());
+        }
+        let settings: gtk::Box = get_gui_element(&self.builder, "fiat-rate-settings")?;
+        let rate_entry: gtk::Entry = get_gui_element(&self.builder, "fiat-rate-entry")?;
+        let rate_button: gtk::Button = get_gui_element(&self.builder, "fiat-rate-button")?;
+        settings.show_all();
+
+        let gui_balance = self.clone();
+        rate_button.connect_clicked(move |_| {
+            if let Err(error) = gui_balance.change_manual_rate(rate_entry.text().as_str()) {
+                send_log(&gui_balance.logger_sender, Log::Error(error));
+            }
+        });
+        Ok(())
+    }
+}
+
+impl GUIBalance {
+    /// Crea el balance con el contexto de la interfaz grafica.
+    pub fn new(context: &GUIContext) -> Self {
+        Self {
+            builder: context.builder.clone(),
+            node_state_ref: context.node_state_ref.clone(),
+            logger_sender: context.logger_sender.clone(),
+            gui_sender: context.gui_sender.clone(),
+            node_action_sender: context.node_action_sender.clone(),
+            available_balance: 0.0,
+            pending_balance: 0.0,
+        }
+    }
+
+    fn change_manual_rate(&self, rate: &str) -> Result<(), CustomError> {
+        let rate: f64 = rate.trim().parse().map_err(|_| {
+            CustomError::Validation(String::from("The rate must be a positive number"))
+        })?;
+        self.node_state_ref.lock()?.set_manual_rate(rate)?;
+        self.gui_sender.send(GUIEvents::FiatRateChanged)?;
+        Ok(())
+    }
+
     fn handle_wallet_changed(&mut self) -> Result<(), CustomError> {
         self.update_available_balance()?;
         self.update_pending_txs()
@@ -74,6 +133,9 @@ impl GUIBalance {
             Ok(balance) => {
                 self.available_balance = balance as f64;
             }
+            Err(CustomError::WalletNotFound) => {
+                self.available_balance = 0.0;
+            }
             Err(error) => {
                 send_log(&self.logger_sender, Log::Error(error));
             }
@@ -89,10 +151,14 @@ impl GUIBalance {
         let pending_tx_list_box: gtk::ListBox =
             get_gui_element(&self.builder, "pending-transactions-list")?;
         let node_state_ref_clone = self.node_state_ref.clone();
-        let node_state = node_state_ref_clone.lock()?;
+        let mut node_state = node_state_ref_clone.lock()?;
         if node_state.get_active_wallet().is_none() {
-            return Ok(());
+            drop(node_state);
+            self.pending_balance = 0.0;
+            reset_table(&pending_tx_list_box);
+            return self.update_balances();
         }
+        let rate = node_state.current_rate();
         let pending_transactions = node_state.get_active_wallet_pending_txs()?;
 
         self.pending_balance = 0.0;
@@ -105,10 +171,36 @@ impl GUIBalance {
 
             let side_label = side_label(movement.value);
             pending_box.add(&side_label);
-            let value_label = value_label(movement.value);
+            let value_label = value_with_fiat_label(movement.value, rate.as_ref());
             pending_box.add(&value_label);
+            pending_box.add(&abandon_button(
+                movement.value,
+                movement.tx_hash.clone(),
+                self.builder.clone(),
+                self.logger_sender.clone(),
+                self.node_state_ref.clone(),
+            ));
+
+            // las transacciones enviadas por la wallet se pueden volver a enviar desde el menu contextual,
+            // y si son reemplazables tambien aumentarles el fee
+            let pending_event_box = gtk::EventBox::new();
+            pending_event_box.add(&pending_box);
+            if movement.value < 0 {
+                let replaceable = node_state
+                    .get_pending_tx(&movement.tx_hash)
+                    .is_some_and(|transaction| transaction.is_replaceable());
+                attach_pending_tx_menu(
+                    &pending_event_box,
+                    movement.tx_hash.clone(),
+                    replaceable,
+                    self.builder.clone(),
+                    self.logger_sender.clone(),
+                    self.node_state_ref.clone(),
+                    self.node_action_sender.clone(),
+                );
+            }
 
-            pending_tx_row.add(&pending_box);
+            pending_tx_row.add(&pending_event_box);
             pending_tx_row.show_all();
             pending_tx_list_box.add(&pending_tx_row);
         }
@@ -119,6 +211,7 @@ impl GUIBalance {
         Ok(())
     }
 
+    /// Muestra los balances en BTC y, si hay cotizacion, su equivalente en moneda fiat y la fecha de la cotizacion.
     fn update_balances(&self) -> Result<(), CustomError> {
         let available_balance: gtk::Label =
             get_gui_element(&self.builder, "label-available-balance")?;
@@ -126,25 +219,49 @@ impl GUIBalance {
         let total_balance: gtk::Label = get_gui_element(&self.builder, "label-total-balance")?;
         let transfer_balance: gtk::Label =
             get_gui_element(&self.builder, "label-transfer-balance")?;
+        let fiat_rate: gtk::Label = get_gui_element(&self.builder, "label-fiat-rate")?;
+        let rate = self.node_state_ref.lock()?.current_rate();
 
         let available_btc = self.available_balance / 100_000_000.0;
-        available_balance.set_text(format!("Balance:    {:.8} BTC", available_btc).as_str());
+        let available_fiat = fiat_suffix(self.available_balance, rate.as_ref());
+        available_balance
+            .set_text(format!("Balance:    {:.8} BTC{}", available_btc, available_fiat).as_str());
 
         let pending_btc = self.pending_balance / 100_000_000.0;
-        pending_balance.set_text(format!("Pending:    {:.8} BTC", pending_btc).as_str());
+        let pending_fiat = fiat_suffix(self.pending_balance, rate.as_ref());
+        pending_balance
+            .set_text(format!("Pending:    {:.8} BTC{}", pending_btc, pending_fiat).as_str());
 
         let total_satoshi = self.available_balance + self.pending_balance;
         let total_btc = total_satoshi / 100_000_000.0;
-        let total_balance_string = format!("Total:	     {:.8} BTC", total_btc);
-        let total_balance_string_satoshi = format!("Total:  {:.0} Sat", total_satoshi);
+        let total_fiat = fiat_suffix(total_satoshi, rate.as_ref());
+        let total_balance_string = format!("Total:	     {:.8} BTC{}", total_btc, total_fiat);
+        let total_balance_string_satoshi =
+            format!("Total:  {:.0} Sat{}", total_satoshi, total_fiat);
 
         total_balance.set_text(total_balance_string.as_str());
         transfer_balance.set_text(total_balance_string_satoshi.as_str());
 
+        match rate {
+            Some(rate) => {
+                fiat_rate.set_text(&format_rate(&rate, get_current_timestamp()?));
+                fiat_rate.show();
+            }
+            None => fiat_rate.hide(),
+        }
+
         Ok(())
     }
 }
 
+/// Devuelve el equivalente en fiat de un balance para agregar despues del monto en BTC, o un string vacio si no hay cotizacion.
+fn fiat_suffix(satoshis: f64, rate: Option<&FiatRate>) -> String {
+    match rate {
+        Some(rate) => format!("  ({})", format_fiat(satoshis as i64, rate)),
+        None => String::new(),
+    }
+}
+
 fn reset_table(list_box: &ListBox) {
     list_box.foreach(|child| {
         list_box.remove(child);
@@ -153,13 +270,16 @@ fn reset_table(list_box: &ListBox) {
     let utxo_box = gtk::Box::new(gtk::Orientation::Horizontal, 8);
     let side_label = gtk::Label::new(None);
     let value_label = gtk::Label::new(None);
+    let action_label = gtk::Label::new(None);
 
     utxo_box.set_homogeneous(true);
     side_label.set_markup("<b>Side</b>");
     value_label.set_markup("<b>Value</b>");
+    action_label.set_markup("<b>Action</b>");
 
     utxo_box.add(&side_label);
     utxo_box.add(&value_label);
+    utxo_box.add(&action_label);
 
     utxo_row.add(&utxo_box);
     utxo_row.show_all();
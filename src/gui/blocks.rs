@@ -1,42 +1,81 @@
-use std::sync::{mpsc::Sender, Arc, Mutex};
+use std::{
+    cell::{Cell, RefCell},
+    io::Read,
+    rc::Rc,
+    sync::{mpsc::Sender, Arc, Mutex},
+    thread,
+};
 
+use chrono::{Local, TimeZone};
 use gtk::{
-    traits::{ContainerExt, LabelExt, WidgetExt},
-    ListBox,
+    glib,
+    traits::{
+        ContainerExt, DialogExt, EntryExt, LabelExt, ListBoxExt, ScrolledWindowExt, WidgetExt,
+    },
+    ListBox, PositionType,
 };
 
 use crate::{
     error::CustomError,
     logger::{send_log, Log},
+    messages::{block::Block, transaction::Transaction},
     node_state::NodeState,
+    peer::GENESIS,
+    states::{block_metadata_state::BlockMetadata, headers_state::DifficultyInfo},
+    structs::block_header::{display_hash, parse_display_hash},
 };
 
 use super::{
     init::{get_gui_element, GUIEvents},
-    table_cells::{number_label, time_label, tx_hash_label},
+    tab::{GUIContext, GUITab},
+    table_cells::{number_label, pending_label, time_label, tx_hash_label},
+    table_rows::TableRows,
 };
 
+/// Cantidad de bloques que se agregan a la lista cada vez que se llega a su final.
+pub const BLOCKS_PAGE: usize = 1000;
+
 #[derive(Clone)]
 /// GUIBlocks es una estructura que contiene los elementos de la interfaz grafica
-/// relacionados con los bloques. Muestra un listado de los ultimos 100 bloques (fecha de creacion, tx hash, version y nbits).
+/// relacionados con los bloques. Muestra un listado de los ultimos BLOCKS_PAGE bloques (fecha de creacion, hash, height, nbits,
+/// cantidad de transacciones y tamaño), y BLOCKS_PAGE mas anteriores cada vez que se llega al final de la lista. Permite buscar un bloque por hash o por height (con el formato "height:12345")
+/// y muestra los campos de su header y su resumen.
+/// La cantidad de transacciones y el tamaño salen de los resumenes de los bloques (nunca se abren sus archivos),
+/// si el resumen todavia no esta disponible se muestran como pendientes.
+/// Con doble click en un bloque de la lista se abre su detalle (campos del header y sus transacciones), que se lee
+/// del disco en otro thread para no bloquear la interfaz grafica.
 /// Los elementos son:
 /// - builder: Builder de gtk.
 /// - node_state_ref: Referencia al estado del nodo.
 /// - logger_sender: Sender para enviar logs al logger.
+/// - gui_sender: Sender para enviar GUIEvents desde el thread que lee el bloque elegido.
+/// - node_state_ready: Indica si el node state ya se sincronizo y se puede mostrar la lista.
+/// - rows: Filas de la lista de bloques por hash, que se actualizan solo si cambian (ver TableRows).
+/// - shown: Cantidad de bloques que muestra la lista.
 pub struct GUIBlocks {
     pub logger_sender: Sender<Log>,
     pub builder: gtk::Builder,
     pub node_state_ref: Arc<Mutex<NodeState>>,
+    pub gui_sender: glib::Sender<GUIEvents>,
     pub node_state_ready: bool,
+    pub rows: Rc<RefCell<TableRows<Vec<u8>, BlockRow>>>,
+    pub shown: Rc<Cell<usize>>,
 }
 
-impl GUIBlocks {
+impl GUITab for GUIBlocks {
     /// Maneja los GUIEvents recibidos y hace las acciones acorde a cada envento.
     /// Para NewHeaders: Actualiza la lista de bloques.
-    pub fn handle_events(&mut self, message: &GUIEvents) {
+    /// Para DifficultyUpdated: Muestra la dificultad actual y el proximo ajuste arriba de la lista.
+    /// Para BlockDetailsLoaded: Muestra el detalle del bloque elegido.
+    fn handle_events(&mut self, message: &GUIEvents) {
         let result = match message {
-            GUIEvents::NodeStateReady => self.initialize(),
-            GUIEvents::NewHeaders => self.update_blocks(),
+            GUIEvents::NodeStateReady => self.load_blocks(),
+            GUIEvents::NewHeaders { .. } => self.update_blocks(),
+            GUIEvents::DifficultyUpdated(info) => self.show_difficulty(Some(info)),
+            GUIEvents::BlockDetailsLoaded {
+                block_hash,
+                details,
+            } => self.show_block_details(block_hash, details.as_ref()),
             _ => Ok(()),
         };
 
@@ -45,41 +84,417 @@ impl GUIBlocks {
         }
     }
 
-    /// Inicializa la lista de bloques.
-    fn initialize(&mut self) -> Result<(), CustomError> {
+    /// Establece los callbacks de los elementos de la interfaz grafica.
+    /// Para el buscador: Muestra los campos del header del bloque buscado.
+    /// Para la lista de bloques: Lee en otro thread el bloque activado con doble click.
+    /// Al llegar al final de la lista de bloques: Agrega los BLOCKS_PAGE bloques anteriores.
+    fn handle_interactivity(&self) -> Result<(), CustomError> {
+        self.handle_block_activated()?;
+        self.handle_blocks_scrolled()?;
+
+        let search_entry: gtk::SearchEntry = get_gui_element(&self.builder, "blocks-search")?;
+        let result_label: gtk::Label = get_gui_element(&self.builder, "blocks-search-result")?;
+        let node_state_ref = self.node_state_ref.clone();
+        let logger_sender = self.logger_sender.clone();
+
+        search_entry.connect_activate(move |entry| {
+            match search_block(&node_state_ref, entry.text().trim()) {
+                Ok(result) => result_label.set_text(&result),
+                Err(error) => send_log(&logger_sender, Log::Error(error)),
+            }
+        });
+        Ok(())
+    }
+}
+
+impl GUIBlocks {
+    /// Crea la lista de bloques con el contexto de la interfaz grafica.
+    pub fn new(context: &GUIContext) -> Self {
+        Self {
+            builder: context.builder.clone(),
+            node_state_ref: context.node_state_ref.clone(),
+            logger_sender: context.logger_sender.clone(),
+            gui_sender: context.gui_sender.clone(),
+            node_state_ready: false,
+            rows: Rc::new(RefCell::new(TableRows::new(1))),
+            shown: Rc::new(Cell::new(BLOCKS_PAGE)),
+        }
+    }
+
+    fn handle_block_activated(&self) -> Result<(), CustomError> {
+        let blocks_list_box: gtk::ListBox = get_gui_element(&self.builder, "blocks-list")?;
+        let node_state_ref = self.node_state_ref.clone();
+        let logger_sender = self.logger_sender.clone();
+        let gui_sender = self.gui_sender.clone();
+
+        blocks_list_box.connect_row_activated(move |_, row| {
+            // la fila de titulos no tiene hash
+            let Some(block_hash) = parse_display_hash(&row.widget_name()) else { return };
+
+            let node_state_ref = node_state_ref.clone();
+            let logger_sender = logger_sender.clone();
+            let gui_sender = gui_sender.clone();
+            thread::spawn(move || {
+                let details = match load_block_details(&node_state_ref, &block_hash) {
+                    Ok(details) => details,
+                    Err(error) => {
+                        send_log(&logger_sender, Log::Error(error));
+                        return;
+                    }
+                };
+                if gui_sender
+                    .send(GUIEvents::BlockDetailsLoaded {
+                        block_hash,
+                        details,
+                    })
+                    .is_err()
+                {
+                    send_log(
+                        &logger_sender,
+                        Log::Error(CustomError::CannotSendMessageToChannel(None)),
+                    );
+                }
+            });
+        });
+        Ok(())
+    }
+
+    fn handle_blocks_scrolled(&self) -> Result<(), CustomError> {
+        let blocks_scroll: gtk::ScrolledWindow = get_gui_element(&self.builder, "blocks-scroll")?;
+        let blocks = self.clone();
+
+        blocks_scroll.connect_edge_reached(move |_, position| {
+            if position != PositionType::Bottom {
+                return;
+            }
+            if let Err(error) = blocks.show_older_blocks() {
+                send_log(&blocks.logger_sender, Log::Error(error));
+            }
+        });
+        Ok(())
+    }
+
+    /// Muestra el dialogo con los campos del header y las transacciones del bloque.
+    /// Si el bloque no esta guardado (por ejemplo si es anterior a START_DATE_IBD) lo indica en lugar de mostrar un error.
+    fn show_block_details(
+        &self,
+        block_hash: &[u8],
+        details: Option<&BlockDetails>,
+    ) -> Result<(), CustomError> {
+        let dialog: gtk::Dialog = get_gui_element(&self.builder, "block-details-dialog")?;
+        let header_label: gtk::Label = get_gui_element(&self.builder, "block-details-header")?;
+        let transactions_list_box: gtk::ListBox =
+            get_gui_element(&self.builder, "block-details-transactions")?;
+
+        transactions_list_box.foreach(|child| {
+            transactions_list_box.remove(child);
+        });
+        match details {
+            Some(details) => {
+                header_label.set_text(&details.header_text());
+                transactions_list_box.add(&transaction_row(
+                    "<b>Transaction Hash</b>",
+                    "<b>Output Value</b>",
+                    "<b>Inputs</b>",
+                    "<b>Outputs</b>",
+                ));
+                for transaction in &details.transactions {
+                    transactions_list_box.add(&transaction_row(
+                        &transaction.hash,
+                        &format_btc(transaction.total_output),
+                        &transaction.inputs.to_string(),
+                        &transaction.outputs.to_string(),
+                    ));
+                }
+            }
+            None => header_label.set_text(&format!(
+                "Block {} is not stored.\nOnly the blocks after the IBD start date are downloaded.",
+                display_hash(block_hash)
+            )),
+        }
+
+        dialog.run();
+        dialog.hide();
+        Ok(())
+    }
+
+    /// Inicializa la lista de bloques y la dificultad.
+    fn load_blocks(&mut self) -> Result<(), CustomError> {
         self.node_state_ready = true;
+        let info = self.node_state_ref.lock()?.get_difficulty_info();
+        self.show_difficulty(info.as_ref())?;
         self.update_blocks()
     }
 
+    /// Muestra la dificultad actual y el proximo ajuste arriba de la lista, o nada si el nodo no tiene headers.
+    fn show_difficulty(&self, info: Option<&DifficultyInfo>) -> Result<(), CustomError> {
+        let difficulty_label: gtk::Label = get_gui_element(&self.builder, "blocks-difficulty")?;
+        difficulty_label.set_text(&info.map(difficulty_text).unwrap_or_default());
+        Ok(())
+    }
+
     fn update_blocks(&self) -> Result<(), CustomError> {
         if !self.node_state_ready {
             return Ok(());
         }
+        self.refresh_blocks()
+    }
+
+    /// Agrega a la lista los BLOCKS_PAGE bloques anteriores al ultimo que muestra, si los hay.
+    fn show_older_blocks(&self) -> Result<(), CustomError> {
+        if self.rows.borrow().len() < self.shown.get() {
+            return Ok(());
+        }
+        self.shown.set(self.shown.get() + BLOCKS_PAGE);
+        self.refresh_blocks()
+    }
+
+    /// Actualiza la lista con los ultimos shown bloques, del mas reciente al mas antiguo.
+    /// Solo se agregan los bloques nuevos y se quitan los desconectados por un reorg, las demas filas
+    /// se completan de vuelta solo si cambiaron (por ejemplo al registrarse el resumen del bloque).
+    fn refresh_blocks(&self) -> Result<(), CustomError> {
         let blocks_list_box: gtk::ListBox = get_gui_element(&self.builder, "blocks-list")?;
-        let node_state_ref_clone = self.node_state_ref.clone();
-        let node_state = node_state_ref_clone.lock()?;
-        let headers = node_state.get_last_headers(100);
-
-        reset_table(&blocks_list_box);
-        for (height, header) in headers.into_iter() {
-            let utxo_row = gtk::ListBoxRow::new();
-            let utxo_box = gtk::Box::new(gtk::Orientation::Horizontal, 8);
-            utxo_box.set_margin_top(8);
-            utxo_box.set_margin_bottom(8);
-
-            utxo_box.add(&time_label(header.timestamp));
-            utxo_box.add(&tx_hash_label(header.hash().clone()));
-            utxo_box.add(&number_label(height as i64));
-            utxo_box.add(&number_label(header.bits as i64));
-
-            utxo_row.add(&utxo_box);
-            utxo_row.show_all();
-            blocks_list_box.add(&utxo_row);
+        let node_state = self.node_state_ref.lock()?;
+        let blocks: Vec<(Vec<u8>, BlockRow)> = node_state
+            .get_last_headers(self.shown.get())
+            .into_iter()
+            .map(|(height, header, metadata)| {
+                let row = BlockRow {
+                    height,
+                    timestamp: header.timestamp,
+                    bits: header.bits,
+                    metadata,
+                };
+                (header.hash().clone(), row)
+            })
+            .collect();
+        drop(node_state);
+
+        if blocks_list_box.children().is_empty() {
+            reset_table(&blocks_list_box);
         }
+        self.rows
+            .borrow_mut()
+            .update(&blocks_list_box, blocks, |row, block_hash, block| {
+                fill_block_row(row, block_hash, block)
+            });
         Ok(())
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+/// BlockRow son los datos de un bloque que se muestran en su fila de la lista de bloques.
+/// Los elementos son:
+/// - height: Altura del bloque.
+/// - timestamp: Fecha de creacion del bloque.
+/// - bits: Bits de dificultad del bloque.
+/// - metadata: Resumen del bloque, o None si todavia no esta disponible.
+pub struct BlockRow {
+    pub height: usize,
+    pub timestamp: u32,
+    pub bits: u32,
+    pub metadata: Option<BlockMetadata>,
+}
+
+/// Completa la fila de un bloque de la lista. El nombre de la fila es el hash del bloque, para abrir su detalle.
+fn fill_block_row(row: &gtk::ListBoxRow, block_hash: &[u8], block: &BlockRow) {
+    row.set_widget_name(&display_hash(block_hash));
+    let block_box = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    block_box.set_margin_top(8);
+    block_box.set_margin_bottom(8);
+
+    block_box.add(&time_label(block.timestamp));
+    block_box.add(&tx_hash_label(block_hash.to_vec()));
+    block_box.add(&number_label(block.height as i64));
+    block_box.add(&number_label(block.bits as i64));
+    match block.metadata {
+        Some(metadata) => {
+            block_box.add(&number_label(metadata.tx_count as i64));
+            block_box.add(&number_label(metadata.size as i64));
+        }
+        None => {
+            block_box.add(&pending_label());
+            block_box.add(&pending_label());
+        }
+    }
+    row.add(&block_box);
+}
+
+/// Busca un bloque por hash o por height ("height:12345") y devuelve los campos de su header, uno por linea.
+fn search_block(
+    node_state_ref: &Arc<Mutex<NodeState>>,
+    query: &str,
+) -> Result<String, CustomError> {
+    let node_state = node_state_ref.lock()?;
+    let height = match query.strip_prefix("height:") {
+        Some(height) => height
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| CustomError::Validation("The height must be a number".to_string()))?,
+        None => {
+            let Some(block_hash) = parse_display_hash(query) else {
+                return Err(CustomError::Validation(
+                    "Search a block hash or height:<number>".to_string(),
+                ));
+            };
+            match node_state.height_of_block(&block_hash) {
+                Some(height) => height,
+                None => return Ok("Block not found".to_string()),
+            }
+        }
+    };
+
+    if height == 0 {
+        return Ok(format!("Genesis block {}", display_hash(&GENESIS)));
+    }
+    let Some((header, confirmations)) = node_state.get_header_at_height(height) else { return Ok("Block not found".to_string()) };
+
+    let metadata = node_state.get_block_metadata(header.hash());
+    let lines: Vec<String> = header
+        .fields(height, confirmations)
+        .into_iter()
+        .chain(BlockMetadata::fields(metadata.as_ref()))
+        .map(|(name, value)| format!("{name}: {value}"))
+        .collect();
+    Ok(lines.join("\n"))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// BlockDetails es el detalle de un bloque guardado que se muestra al elegirlo en la lista de bloques.
+/// Los elementos son:
+/// - header_fields: Campos del header como pares (nombre, valor), con la fecha en hora local.
+/// - transactions: Resumen de cada transaccion del bloque.
+pub struct BlockDetails {
+    pub header_fields: Vec<(&'static str, String)>,
+    pub transactions: Vec<TransactionSummary>,
+}
+
+impl BlockDetails {
+    /// Genera el detalle de un bloque.
+    pub fn from_block(block: &Block) -> Self {
+        let header = &block.header;
+        Self {
+            header_fields: vec![
+                ("Hash", display_hash(header.hash())),
+                ("Version", header.version.to_string()),
+                ("Time", format_local_time(header.timestamp)),
+                ("Bits", format!("{:08x}", header.bits)),
+                ("Nonce", header.nonce.to_string()),
+                ("Merkle root", display_hash(&header.merkle_root)),
+                ("Transactions", block.transactions.len().to_string()),
+            ],
+            transactions: block
+                .transactions
+                .iter()
+                .map(TransactionSummary::from_transaction)
+                .collect(),
+        }
+    }
+
+    /// Devuelve los campos del header, uno por linea.
+    pub fn header_text(&self) -> String {
+        self.header_fields
+            .iter()
+            .map(|(name, value)| format!("{name}: {value}"))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// TransactionSummary es el resumen de una transaccion en el detalle de un bloque.
+/// Los elementos son:
+/// - hash: Hash de la transaccion como lo muestran los exploradores.
+/// - total_output: Suma de los valores de sus outputs, en satoshis.
+/// - inputs: Cantidad de inputs.
+/// - outputs: Cantidad de outputs.
+pub struct TransactionSummary {
+    pub hash: String,
+    pub total_output: u64,
+    pub inputs: usize,
+    pub outputs: usize,
+}
+
+impl TransactionSummary {
+    /// Genera el resumen de una transaccion.
+    pub fn from_transaction(transaction: &Transaction) -> Self {
+        Self {
+            hash: display_hash(&transaction.hash()),
+            total_output: transaction.outputs.iter().map(|output| output.value).sum(),
+            inputs: transaction.inputs.len(),
+            outputs: transaction.outputs.len(),
+        }
+    }
+}
+
+/// Devuelve la fecha de un timestamp en hora local (año-mes-dia hora:minutos:segundos).
+pub fn format_local_time(timestamp: u32) -> String {
+    match Local.timestamp_opt(timestamp as i64, 0).single() {
+        Some(time) => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => timestamp.to_string(),
+    }
+}
+
+/// Devuelve el texto con la dificultad actual y el proximo ajuste que se muestra arriba de la lista de bloques.
+pub fn difficulty_text(info: &DifficultyInfo) -> String {
+    format!(
+        "Difficulty: {:.2} at height {}. Next retarget in {} blocks (~{})",
+        info.difficulty,
+        info.height,
+        info.blocks_until_retarget,
+        format_local_time(info.estimated_retarget)
+    )
+}
+
+/// Devuelve un valor en satoshis expresado en BTC con sus 8 decimales, sin pasar por punto flotante.
+pub fn format_btc(value: u64) -> String {
+    format!("{}.{:08} BTC", value / 100_000_000, value % 100_000_000)
+}
+
+/// Lee el bloque del disco. Devuelve None si no esta guardado.
+/// El NodeState solo se bloquea para abrir el archivo, la lectura se hace sin retenerlo.
+fn load_block_details(
+    node_state_ref: &Arc<Mutex<NodeState>>,
+    block_hash: &[u8],
+) -> Result<Option<BlockDetails>, CustomError> {
+    let node_state = node_state_ref.lock()?;
+    if !node_state.is_block_stored(block_hash) {
+        return Ok(None);
+    }
+    let mut file = node_state.get_block_file(block_hash)?;
+    drop(node_state);
+
+    let mut buffer = vec![];
+    file.read_to_end(&mut buffer)?;
+    let block = Block::parse(buffer)?;
+    Ok(Some(BlockDetails::from_block(&block)))
+}
+
+/// Genera una fila de la lista de transacciones del detalle de un bloque, los textos pueden tener markup.
+fn transaction_row(hash: &str, value: &str, inputs: &str, outputs: &str) -> gtk::ListBoxRow {
+    let row = gtk::ListBoxRow::new();
+    let row_box = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    row_box.set_margin_top(4);
+    row_box.set_margin_bottom(4);
+
+    let hash_label = gtk::Label::new(None);
+    hash_label.set_markup(hash);
+    hash_label.set_expand(true);
+    hash_label.set_selectable(true);
+    row_box.add(&hash_label);
+
+    for (text, width) in [(value, 160), (inputs, 80), (outputs, 80)] {
+        let label = gtk::Label::new(None);
+        label.set_markup(text);
+        label.set_width_request(width);
+        row_box.add(&label);
+    }
+
+    row.add(&row_box);
+    row.show_all();
+    row
+}
+
 fn reset_table(list_box: &ListBox) {
     list_box.foreach(|child| {
         list_box.remove(child);
@@ -90,6 +505,8 @@ fn reset_table(list_box: &ListBox) {
     let time_label = gtk::Label::new(None);
     let nbits_label = gtk::Label::new(None);
     let height_label = gtk::Label::new(None);
+    let tx_count_label = gtk::Label::new(None);
+    let size_label = gtk::Label::new(None);
 
     time_label.set_width_request(92);
     time_label.set_markup("<b>Time</b>");
@@ -103,12 +520,93 @@ fn reset_table(list_box: &ListBox) {
     height_label.set_width_request(100);
     height_label.set_markup("<b>Height</b>");
 
+    tx_count_label.set_width_request(100);
+    tx_count_label.set_markup("<b>Txs</b>");
+
+    size_label.set_width_request(100);
+    size_label.set_markup("<b>Size</b>");
+
     utxo_box.add(&time_label);
     utxo_box.add(&tx_hash_label);
     utxo_box.add(&height_label);
     utxo_box.add(&nbits_label);
+    utxo_box.add(&tx_count_label);
+    utxo_box.add(&size_label);
 
     utxo_row.add(&utxo_box);
     utxo_row.show_all();
     list_box.add(&utxo_row);
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+
+    use crate::synthetic_chain::{SyntheticChain, SYNTHETIC_MIN_DIFFICULTY_BITS};
+
+    use super::*;
+
+    #[test]
+    fn btc_values_keep_all_their_decimals() {
+        assert_eq!(format_btc(0), "0.00000000 BTC");
+        assert_eq!(format_btc(150_000_000), "1.50000000 BTC");
+        assert_eq!(format_btc(2_100_000_000_000_001), "21000000.00000001 BTC");
+    }
+
+    #[test]
+    fn block_time_is_shown_in_local_time() {
+        let timestamp = 1_700_000_000;
+        let utc = NaiveDateTime::from_timestamp_opt(timestamp as i64, 0).unwrap();
+        let expected = Local.from_utc_datetime(&utc).format("%Y-%m-%d %H:%M:%S");
+        assert_eq!(format_local_time(timestamp), expected.to_string());
+    }
+
+    #[test]
+    fn difficulty_text_shows_the_next_retarget() {
+        let info = DifficultyInfo {
+            height: 800000,
+            difficulty: 53911173001054.586,
+            blocks_since_retarget: 1664,
+            blocks_until_retarget: 352,
+            estimated_retarget: 1_700_000_000,
+        };
+        assert_eq!(
+            difficulty_text(&info),
+            format!(
+                "Difficulty: 53911173001054.59 at height 800000. Next retarget in 352 blocks (~{})",
+                format_local_time(1_700_000_000)
+            )
+        );
+    }
+
+    #[test]
+    fn block_details_summarize_header_and_transactions() {
+        let chain = SyntheticChain::generate(3, 1, 1, SYNTHETIC_MIN_DIFFICULTY_BITS).unwrap();
+        let block = &chain.blocks[0];
+        let details = BlockDetails::from_block(block);
+
+        let header_text = details.header_text();
+        assert!(header_text.contains(&format!("Hash: {}", display_hash(block.header.hash()))));
+        assert!(header_text.contains(&format!("Bits: {:08x}", block.header.bits)));
+        assert!(header_text.contains(&format!("Nonce: {}", block.header.nonce)));
+        assert!(header_text.contains(&format!(
+            "Merkle root: {}",
+            display_hash(&block.header.merkle_root)
+        )));
+
+        assert_eq!(details.transactions.len(), block.transactions.len());
+        for (summary, transaction) in details.transactions.iter().zip(&block.transactions) {
+            assert_eq!(summary.hash, display_hash(&transaction.hash()));
+            assert_eq!(summary.inputs, transaction.inputs.len());
+            assert_eq!(summary.outputs, transaction.outputs.len());
+            assert_eq!(
+                summary.total_output,
+                transaction
+                    .outputs
+                    .iter()
+                    .map(|output| output.value)
+                    .sum::<u64>()
+            );
+        }
+    }
+}
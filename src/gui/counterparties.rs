@@ -0,0 +1,215 @@
+use std::{
+    sync::{mpsc::Sender, Arc, Mutex},
+    thread,
+};
+
+use gtk::{
+    glib,
+    traits::{ContainerExt, EntryExt, LabelExt, WidgetExt},
+    ListBox,
+};
+
+use crate::{
+    counterparties::{resolve_counterparties, Counterparty, CounterpartyIndex},
+    error::CustomError,
+    logger::{send_log, Log},
+    node_state::NodeState,
+};
+
+use super::{
+    init::{get_gui_element, GUIEvents},
+    tab::{GUIContext, GUITab},
+    table_cells::{number_label, value_label},
+};
+
+#[derive(Clone)]
+/// GUICounterparties es una estructura que contiene los elementos de la interfaz grafica
+/// relacionados con las direcciones con las que opera la wallet activa (ver CounterpartyIndex).
+/// Las lista ordenadas por cantidad de movimientos (direccion, nombre, movimientos y valor total) y permite asignarle
+/// un nombre a cada direccion, que tambien se muestra en el historial.
+/// Las contrapartes se obtienen leyendo bloques guardados, por lo que se resuelven en otro thread.
+/// Los elementos son:
+/// - builder: Builder de gtk.
+/// - node_state_ref: Referencia al estado del nodo.
+/// - logger_sender: Sender para enviar logs al logger.
+/// - gui_sender: Sender para avisar que cambiaron las contrapartes o sus nombres.
+/// - counterparty_index_ref: Contrapartes de los movimientos de la wallet activa, compartidas con el historial.
+pub struct GUICounterparties {
+    pub logger_sender: Sender<Log>,
+    pub builder: gtk::Builder,
+    pub node_state_ref: Arc<Mutex<NodeState>>,
+    pub gui_sender: glib::Sender<GUIEvents>,
+    pub counterparty_index_ref: Arc<Mutex<CounterpartyIndex>>,
+}
+
+impl GUITab for GUICounterparties {
+    /// Maneja los GUIEvents recibidos y hace las acciones acorde a cada envento.
+    /// Para NodeStateReady y WalletChanged: Resuelve las contrapartes de la wallet activa.
+    /// Para WalletsUpdated: Resuelve las contrapartes de los movimientos nuevos si la wallet activa fue afectada.
+    /// Para CounterpartiesUpdated: Actualiza la lista de contrapartes.
+    fn handle_events(&mut self, message: &GUIEvents) {
+        let result = match message {
+            GUIEvents::NodeStateReady => self.resolve(),
+            GUIEvents::WalletChanged => self.update_counterparties().and_then(|_| self.resolve()),
+            GUIEvents::WalletsUpdated { affected } => self.handle_wallets_updated(affected),
+            GUIEvents::CounterpartiesUpdated => self.update_counterparties(),
+            _ => Ok(()),
+        };
+
+        if let Err(error) = result {
+            send_log(&self.logger_sender, Log::Error(error));
+        }
+    }
+}
+
+impl GUICounterparties {
+    /// Crea las contrapartes con el contexto de la interfaz grafica.
+    pub fn new(context: &GUIContext) -> Self {
+        Self {
+            builder: context.builder.clone(),
+            node_state_ref: context.node_state_ref.clone(),
+            logger_sender: context.logger_sender.clone(),
+            gui_sender: context.gui_sender.clone(),
+            counterparty_index_ref: context.counterparty_index_ref.clone(),
+        }
+    }
+
+    fn handle_wallets_updated(&self, affected: &[String]) -> Result<(), CustomError> {
+        let node_state = self.node_state_ref.lock()?;
+        let active_affected = node_state
+            .get_active_wallet()
+            .map_or(false, |wallet| affected.contains(&wallet.pubkey));
+        drop(node_state);
+
+        if active_affected {
+            self.resolve()?;
+        }
+        Ok(())
+    }
+
+    /// Resuelve en otro thread las contrapartes de los movimientos que todavia no estan en el indice.
+    fn resolve(&self) -> Result<(), CustomError> {
+        let node_state_ref = self.node_state_ref.clone();
+        let counterparty_index_ref = self.counterparty_index_ref.clone();
+        let logger_sender = self.logger_sender.clone();
+        let gui_sender = self.gui_sender.clone();
+
+        thread::spawn(move || {
+            match resolve_counterparties(&node_state_ref, &counterparty_index_ref) {
+                Ok(true) => {
+                    if gui_sender.send(GUIEvents::CounterpartiesUpdated).is_err() {
+                        send_log(
+                            &logger_sender,
+                            Log::Error(CustomError::CannotSendMessageToChannel(None)),
+                        );
+                    }
+                }
+                Ok(false) => (),
+                Err(error) => send_log(&logger_sender, Log::Error(error)),
+            }
+        });
+        Ok(())
+    }
+
+    fn update_counterparties(&self) -> Result<(), CustomError> {
+        let counterparties_list_box: gtk::ListBox =
+            get_gui_element(&self.builder, "counterparties-list")?;
+        let node_state = self.node_state_ref.lock()?;
+        let history = node_state
+            .get_active_wallet()
+            .map(|wallet| wallet.get_history())
+            .unwrap_or_default();
+        let labels = node_state.get_address_labels();
+        drop(node_state);
+        let counterparties = self
+            .counterparty_index_ref
+            .lock()?
+            .counterparties(&history, &labels);
+
+        reset_table(&counterparties_list_box);
+        for counterparty in counterparties {
+            counterparties_list_box.add(&self.counterparty_row(counterparty));
+        }
+        Ok(())
+    }
+
+    /// Fila de una contraparte. Al confirmar el nombre (enter) se guarda y se actualizan la lista y el historial.
+    fn counterparty_row(&self, counterparty: Counterparty) -> gtk::ListBoxRow {
+        let counterparty_row = gtk::ListBoxRow::new();
+        let counterparty_box = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+        counterparty_box.set_margin_top(4);
+        counterparty_box.set_margin_bottom(4);
+
+        let address_label = gtk::Label::new(Some(counterparty.address.as_str()));
+        address_label.set_expand(true);
+        address_label.set_selectable(true);
+
+        let label_entry = gtk::Entry::new();
+        label_entry.set_width_request(200);
+        label_entry.set_placeholder_text(Some("Name"));
+        label_entry.set_text(counterparty.label.as_deref().unwrap_or_default());
+        let node_state_ref = self.node_state_ref.clone();
+        let logger_sender = self.logger_sender.clone();
+        let gui_sender = self.gui_sender.clone();
+        let address = counterparty.address.clone();
+        label_entry.connect_activate(move |entry| {
+            if let Err(error) = save_label(&node_state_ref, &gui_sender, &address, &entry.text()) {
+                send_log(&logger_sender, Log::Error(error));
+            }
+        });
+
+        counterparty_box.add(&address_label);
+        counterparty_box.add(&label_entry);
+        counterparty_box.add(&number_label(counterparty.count as i64));
+        counterparty_box.add(&value_label(counterparty.total_value as i64));
+
+        counterparty_row.add(&counterparty_box);
+        counterparty_row.show_all();
+        counterparty_row
+    }
+}
+
+/// Guarda el nombre de una direccion y avisa que cambio, para actualizar la lista y el historial.
+fn save_label(
+    node_state_ref: &Arc<Mutex<NodeState>>,
+    gui_sender: &glib::Sender<GUIEvents>,
+    address: &str,
+    label: &str,
+) -> Result<(), CustomError> {
+    node_state_ref.lock()?.set_address_label(address, label)?;
+    gui_sender.send(GUIEvents::CounterpartiesUpdated)?;
+    Ok(())
+}
+
+fn reset_table(list_box: &ListBox) {
+    list_box.foreach(|child| {
+        list_box.remove(child);
+    });
+    let header_row = gtk::ListBoxRow::new();
+    let header_box = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let address_label = gtk::Label::new(None);
+    let name_label = gtk::Label::new(None);
+    let count_label = gtk::Label::new(None);
+    let value_label = gtk::Label::new(None);
+
+    address_label.set_expand(true);
+    address_label.set_markup("<b>Address</b>");
+
+    name_label.set_width_request(200);
+    name_label.set_markup("<b>Name</b>");
+
+    count_label.set_width_request(100);
+    count_label.set_markup("<b>Movements</b>");
+
+    value_label.set_width_request(128);
+    value_label.set_markup("<b>Total</b>");
+
+    header_box.add(&address_label);
+    header_box.add(&name_label);
+    header_box.add(&count_label);
+    header_box.add(&value_label);
+
+    header_row.add(&header_box);
+    header_row.show_all();
+    list_box.add(&header_row);
+}
@@ -1,42 +1,71 @@
-use std::sync::{mpsc::Sender, Arc, Mutex};
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{mpsc::Sender, Arc, Mutex},
+};
 
 use gtk::{
-    traits::{ContainerExt, LabelExt, WidgetExt},
+    traits::{ContainerExt, LabelExt, ListBoxExt, WidgetExt},
     ListBox,
 };
 
 use crate::{
+    counterparties::CounterpartyIndex,
     error::CustomError,
     logger::{send_log, Log},
     node_state::NodeState,
+    pricing::FiatRate,
 };
 
 use super::{
     init::{get_gui_element, GUIEvents},
-    table_cells::{merkle_proof_button, side_label, tx_hash_label, value_label},
+    tab::{GUIContext, GUITab},
+    table_cells::{
+        abandoned_label, counterparty_label, merkle_proof_button, side_label, tx_hash_label,
+        unverified_label, value_with_fiat_label,
+    },
+    table_rows::TableRows,
 };
 
 #[derive(Clone)]
 /// GUIHistory es una estructura que contiene los elementos de la interfaz grafica
-/// relacionados con el historial de movimientos de una wallet y los lista (tx hash, enviado o recibido, valor y pedir el merkle proof de esa tx).
+/// relacionados con el historial de movimientos de una wallet y los lista (tx hash, contraparte, enviado o recibido, valor y pedir el merkle proof de esa tx).
+/// La contraparte se muestra con el nombre asignado a su direccion si tiene (ver GUICounterparties).
 /// Los elementos son:
 /// - builder: Builder de gtk.
 /// - node_state_ref: Referencia al estado del nodo.
 /// - logger_sender: Sender para enviar logs al logger.
+/// - counterparty_index_ref: Contrapartes de los movimientos de la wallet activa.
+/// - rows: Filas de la lista de movimientos por tx hash, que se actualizan solo si cambian (ver TableRows).
+/// - progress_row: Fila con el progreso del historial, si todavia no esta completo.
 pub struct GUIHistory {
     pub logger_sender: Sender<Log>,
     pub builder: gtk::Builder,
     pub node_state_ref: Arc<Mutex<NodeState>>,
+    pub counterparty_index_ref: Arc<Mutex<CounterpartyIndex>>,
+    pub rows: Rc<RefCell<TableRows<Vec<u8>, HistoryRow>>>,
+    pub progress_row: Rc<RefCell<Option<gtk::ListBoxRow>>>,
 }
 
-impl GUIHistory {
+impl GUITab for GUIHistory {
     /// Maneja los GUIEvents recibidos y hace las acciones acorde a cada envento.
     /// Para WalletChanged: Actualiza la lista de movimientos.
-    /// Para WalletsUpdated: Actualiza la lista de movimientos.
-    pub fn handle_events(&mut self, message: &GUIEvents) {
+    /// Para WalletsUpdated: Actualiza la lista de movimientos si la wallet activa fue afectada.
+    /// Para TransactionAbandoned y TransactionRejected: Actualiza la lista de movimientos.
+    /// Para WalletHistoryProgress y NodeStateReady: Actualiza el progreso del historial (o lo saca si ya esta completo).
+    /// Para FiatRateChanged: Actualiza los montos en moneda fiat de la lista de movimientos.
+    /// Para CounterpartiesUpdated: Actualiza las contrapartes de la lista de movimientos.
+    fn handle_events(&mut self, message: &GUIEvents) {
         let result = match message {
+            GUIEvents::CounterpartiesUpdated => self.update_txs(),
             GUIEvents::WalletChanged => self.update_txs(),
-            GUIEvents::WalletsUpdated => self.update_txs(),
+            GUIEvents::TransactionAbandoned | GUIEvents::TransactionRejected(_) => {
+                self.update_txs()
+            }
+            GUIEvents::WalletHistoryProgress { .. } => self.update_txs(),
+            GUIEvents::NodeStateReady => self.update_txs(),
+            GUIEvents::FiatRateChanged => self.update_txs(),
+            GUIEvents::WalletsUpdated { affected } => self.handle_wallets_updated(affected),
             _ => Ok(()),
         };
 
@@ -44,38 +73,151 @@ impl GUIHistory {
             send_log(&self.logger_sender, Log::Error(error));
         }
     }
+}
+
+impl GUIHistory {
+    /// Crea el historial con el contexto de la interfaz grafica.
+    pub fn new(context: &GUIContext) -> Self {
+        Self {
+            builder: context.builder.clone(),
+            node_state_ref: context.node_state_ref.clone(),
+            logger_sender: context.logger_sender.clone(),
+            counterparty_index_ref: context.counterparty_index_ref.clone(),
+            rows: Rc::new(RefCell::new(TableRows::new(1))),
+            progress_row: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    fn handle_wallets_updated(&self, affected: &[String]) -> Result<(), CustomError> {
+        let node_state = self.node_state_ref.lock()?;
+        let active_affected = node_state
+            .get_active_wallet()
+            .map_or(false, |wallet| affected.contains(&wallet.pubkey));
+        drop(node_state);
+
+        if active_affected {
+            self.update_txs()?;
+        }
+        Ok(())
+    }
 
+    /// Actualiza la lista de movimientos de la wallet activa, del mas reciente al mas antiguo.
+    /// Solo se agregan los movimientos nuevos y se quitan los que ya no estan, las demas filas se completan
+    /// de vuelta solo si cambiaron (por ejemplo al confirmarse el movimiento o cambiar la cotizacion).
     fn update_txs(&self) -> Result<(), CustomError> {
         let history_list_box: gtk::ListBox = get_gui_element(&self.builder, "history-list")?;
-        let node_state_ref_clone = self.node_state_ref.clone();
-        let node_state = node_state_ref_clone.lock()?;
-        let Some(active_wallet) = node_state.get_active_wallet() else { return Ok(()) };
-        let history = active_wallet.get_history();
-        reset_table(&history_list_box);
-
-        for movement in history.iter().rev() {
-            let history_row = gtk::ListBoxRow::new();
-            let history_box = gtk::Box::new(gtk::Orientation::Horizontal, 8);
-
-            history_box.add(&tx_hash_label(movement.tx_hash.clone()));
-            history_box.add(&side_label(movement.value));
-            history_box.add(&value_label(movement.value));
+        let mut node_state = self.node_state_ref.lock()?;
+        let rate = node_state.current_rate();
+        let labels = node_state.get_address_labels();
+        // sin wallet activa (por ejemplo al quitarla) la lista queda vacia
+        let history = node_state.get_active_wallet_history()?.unwrap_or_default();
+        let progress = node_state.wallet_history_progress()?;
+        drop(node_state);
+
+        let counterparty_index = self.counterparty_index_ref.lock()?;
+        let movements: Vec<(Vec<u8>, HistoryRow)> = history
+            .iter()
+            .rev()
+            .map(|view| {
+                let movement = &view.movement;
+                let row = HistoryRow {
+                    counterparty: counterparty_index
+                        .history_counterparty(&movement.tx_hash, &labels),
+                    value: movement.value,
+                    rate: rate.clone(),
+                    block_hash: movement.block_hash.clone(),
+                    provisional: movement.provisional,
+                    abandoned: view.is_abandoned(),
+                };
+                (movement.tx_hash.clone(), row)
+            })
+            .collect();
+        drop(counterparty_index);
+
+        if history_list_box.children().is_empty() {
+            reset_table(&history_list_box);
+        }
+        let offset = self.update_progress_row(&history_list_box, progress);
+        let mut rows = self.rows.borrow_mut();
+        rows.set_offset(offset);
+        rows.update(&history_list_box, movements, |row, tx_hash, movement| {
+            self.fill_history_row(row, tx_hash, movement)
+        });
+        Ok(())
+    }
+
+    /// Muestra, actualiza o quita la fila que indica que el historial va a estar completo cuando termine
+    /// la descarga de bloques, debajo de la de titulos. Devuelve la cantidad de filas fijas al principio de la lista.
+    fn update_progress_row(&self, list_box: &ListBox, progress: Option<u64>) -> usize {
+        let mut progress_row = self.progress_row.borrow_mut();
+        let Some(progress) = progress else {
+            if let Some(row) = progress_row.take() {
+                list_box.remove(&row);
+            }
+            return 1;
+        };
+
+        let row = progress_row.get_or_insert_with(|| {
+            let row = gtk::ListBoxRow::new();
+            list_box.insert(&row, 1);
+            row
+        });
+        row.foreach(|child| row.remove(child));
+        let progress_label = gtk::Label::new(Some(&format!(
+            "History available after block sync: {}%",
+            progress
+        )));
+        progress_label.set_expand(true);
+        row.add(&progress_label);
+        row.show_all();
+        2
+    }
+
+    /// Completa la fila de un movimiento de la lista.
+    fn fill_history_row(&self, row: &gtk::ListBoxRow, tx_hash: &[u8], movement: &HistoryRow) {
+        let history_box = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+
+        history_box.add(&tx_hash_label(tx_hash.to_vec()));
+        history_box.add(&counterparty_label(movement.counterparty.clone()));
+        history_box.add(&side_label(movement.value));
+        history_box.add(&value_with_fiat_label(
+            movement.value,
+            movement.rate.as_ref(),
+        ));
+        if movement.abandoned {
+            history_box.add(&abandoned_label());
+        } else if movement.provisional {
+            history_box.add(&unverified_label());
+        } else {
             history_box.add(&merkle_proof_button(
                 movement.block_hash.clone(),
-                movement.tx_hash.clone(),
+                tx_hash.to_vec(),
                 self.logger_sender.clone(),
                 self.node_state_ref.clone(),
             ));
-
-            history_row.add(&history_box);
-            history_row.show_all();
-            history_list_box.add(&history_row);
         }
-        drop(node_state);
-        Ok(())
+        row.add(&history_box);
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+/// HistoryRow son los datos de un movimiento que se muestran en su fila del historial.
+/// Los elementos son:
+/// - counterparty: Contraparte del movimiento (ver CounterpartyIndex::history_counterparty).
+/// - value: Valor del movimiento en satoshis, negativo si es un envio.
+/// - rate: Cotizacion con la que se muestra el valor en moneda fiat.
+/// - block_hash: Bloque en el que se confirmo el movimiento.
+/// - provisional: Indica si el movimiento salio de un merkleblock y todavia no se verifico.
+/// - abandoned: Indica si la transaccion se abandono y no se confirmo despues.
+pub struct HistoryRow {
+    pub counterparty: Option<String>,
+    pub value: i64,
+    pub rate: Option<FiatRate>,
+    pub block_hash: Option<Vec<u8>>,
+    pub provisional: bool,
+    pub abandoned: bool,
+}
+
 fn reset_table(list_box: &ListBox) {
     list_box.foreach(|child| {
         list_box.remove(child);
@@ -83,6 +225,7 @@ fn reset_table(list_box: &ListBox) {
     let utxo_row = gtk::ListBoxRow::new();
     let utxo_box = gtk::Box::new(gtk::Orientation::Horizontal, 8);
     let tx_hash_label = gtk::Label::new(None);
+    let counterparty_label = gtk::Label::new(None);
     let side_label = gtk::Label::new(None);
     let value_label = gtk::Label::new(None);
     let action_label = gtk::Label::new(None);
@@ -90,6 +233,9 @@ fn reset_table(list_box: &ListBox) {
     tx_hash_label.set_expand(true);
     tx_hash_label.set_markup("<b>Tx Hash</b>");
 
+    counterparty_label.set_width_request(200);
+    counterparty_label.set_markup("<b>Counterparty</b>");
+
     side_label.set_width_request(92);
     side_label.set_markup("<b>Side</b>");
 
@@ -100,6 +246,7 @@ fn reset_table(list_box: &ListBox) {
     action_label.set_markup("<b>Action</b>");
 
     utxo_box.add(&tx_hash_label);
+    utxo_box.add(&counterparty_label);
     utxo_box.add(&side_label);
     utxo_box.add(&value_label);
     utxo_box.add(&action_label);
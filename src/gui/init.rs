@@ -1,62 +1,134 @@
 use std::sync::{mpsc, Arc, Mutex};
 
 use gtk::{
-    glib::{self, Object, Receiver},
+    glib::{self, Object, Receiver, Sender},
     prelude::{BuilderExtManual, IsA},
 };
 
 use crate::{
-    error::CustomError, logger::Log, loops::node_action_loop::NodeAction, node_state::NodeState,
+    config_reload::ConfigReloader, counterparties::CounterpartyIndex, error::CustomError,
+    logger::Log, loops::node_action_loop::NodeAction, node_state::NodeState, peer::DisconnectEvent,
+    states::headers_state::DifficultyInfo, wallet_import::ImportSummary,
 };
 
 use super::{
-    balance::GUIBalance, blocks::GUIBlocks, history::GUIHistory, logs::GUILogs,
-    transfer::GUITransfer, utxo::GUIUtxo, wallet::GUIWallet, window::GUIWindow,
+    balance::GUIBalance,
+    blocks::{BlockDetails, GUIBlocks},
+    counterparties::GUICounterparties,
+    history::GUIHistory,
+    logs::GUILogs,
+    peers::GUIPeers,
+    tab::{GUIContext, GUITabs},
+    transfer::GUITransfer,
+    utxo::GUIUtxo,
+    wallet::GUIWallet,
+    window::GUIWindow,
 };
 
 /// GUIEvents es un enum que contiene los eventos que se pueden recibir en el canal de eventos de la interfaz grafica.
 /// Los eventos son:
 /// - Log: Recibe un Log y lo muestra en la lista de logs.
 /// - WalletChanged: Se cambio la wallet activa.
-/// - WalletsUpdated: Se Actualizo alguna de las wallets cargadas.
+/// - WalletsUpdated: Se Actualizo alguna de las wallets cargadas, affected contiene sus public keys.
+/// - WalletListChanged: Se renombro o se quito alguna de las wallets cargadas.
 /// - NewPendingTx: Alguna de las wallets cargadas recibio una pending transaction.
 /// - NodeStateReady: El node state ya se sincronizo y se puede mostrar la informacion.
 /// - NewBlock: Llego un nuevo bloque.
 /// - TransactionSent: Se envio una transaccion del usuario.
-/// - NewHeaders: Hay nuevos Headers.
+/// - TransactionSeenOnNetwork: Un peer al que no se la enviamos anuncio una transaccion del usuario, txid es su hash.
+/// - TransactionMayNotHavePropagated: Ningun peer anuncio una transaccion del usuario a pesar de reenviarla, txid es su hash.
+/// - NewHeaders: Hay nuevos Headers, tip_height es la altura del ultimo.
+/// - DifficultyUpdated: Llegaron headers nuevos con los headers ya sincronizados, contiene la dificultad actual y la estimacion del proximo ajuste.
+/// - TransactionAbandoned: Se abandono una transaccion pendiente de alguna wallet.
+/// - TransactionRejected: Un peer rechazo una transaccion del usuario, que deja de estar pendiente. Contiene el motivo.
+/// - BehindNetwork: El nodo quedo atrasado respecto de la red, blocks_behind es la cantidad de bloques que le faltaban.
+/// - CaughtUpWithNetwork: El nodo alcanzo la altura de la red.
+/// - AddressImportProgress: Progreso de la importacion de direcciones watch-only (direcciones validadas y total).
+/// - AddressesImported: Termino la importacion de direcciones watch-only, summary contiene el resultado.
+/// - PeerDisconnected: Se desconecto un peer, contiene el motivo y los datos de la conexion.
+/// - PeersUpdated: Se conecto o desconecto un peer o cambio su latencia, peers es la cantidad de peers conectados.
+/// - MempoolChanged: Cambiaron las transacciones pendientes del nodo, size es su cantidad.
+/// - WalletHistoryProgress: Cambio el porcentaje de bloques descargados del que depende el historial de las wallets.
+/// - HeadersProgress: Cambio el porcentaje de headers descargados durante el IBD, rate son los headers por segundo.
+/// - BlocksProgress: Cambio el porcentaje de bloques descargados durante el IBD, rate son los bloques por segundo.
+/// - FiatRateChanged: Se cambio la cotizacion manual de BTC en moneda fiat.
+/// - BlockDetailsLoaded: Se leyo del disco el bloque elegido en la lista de bloques, details es None si no esta guardado.
+/// - CounterpartiesUpdated: Se resolvieron contrapartes de la wallet activa o cambio el nombre de una direccion.
+/// - LargePaymentAlert: Una wallet, activa o no, recibio un pago de al menos ALERT_THRESHOLD_SATS. Contiene el nombre
+///   de la wallet, el monto, el hash de la transaccion y si ya estaba confirmada al alertar.
 pub enum GUIEvents {
     Log(Log),
     WalletChanged,
-    WalletsUpdated,
+    WalletsUpdated {
+        affected: Vec<String>,
+    },
+    WalletListChanged,
     NewPendingTx,
     NodeStateReady,
     NewBlock,
     TransactionSent,
-    NewHeaders,
+    TransactionSeenOnNetwork {
+        txid: String,
+    },
+    TransactionMayNotHavePropagated {
+        txid: String,
+    },
+    NewHeaders {
+        tip_height: usize,
+    },
+    DifficultyUpdated(DifficultyInfo),
+    TransactionAbandoned,
+    TransactionRejected(String),
+    BehindNetwork {
+        blocks_behind: usize,
+    },
+    CaughtUpWithNetwork,
+    AddressImportProgress {
+        validated: usize,
+        total: usize,
+    },
+    AddressesImported {
+        summary: ImportSummary,
+    },
+    PeerDisconnected(DisconnectEvent),
+    PeersUpdated {
+        peers: usize,
+    },
+    MempoolChanged {
+        size: usize,
+    },
+    WalletHistoryProgress {
+        progress: u64,
+    },
+    HeadersProgress {
+        percentage: u64,
+        rate: usize,
+    },
+    BlocksProgress {
+        percentage: usize,
+        rate: usize,
+    },
+    FiatRateChanged,
+    BlockDetailsLoaded {
+        block_hash: Vec<u8>,
+        details: Option<BlockDetails>,
+    },
+    CounterpartiesUpdated,
+    LargePaymentAlert {
+        wallet: String,
+        value: u64,
+        tx_hash: String,
+        confirmed: bool,
+    },
 }
 
-/// GUI es una estructura que contiene los elementos que manejan la interfaz grafica
-/// Contiene y les maneja el ciclo de vida a cada uno de los elementos de la interfaz grafica.
+/// GUI es una estructura que contiene las pestañas de la interfaz grafica (ver GUITab).
+/// Les maneja el ciclo de vida: las crea con el GUIContext, las inicia y les pasa los GUIEvents recibidos.
+/// Para agregar una pestaña alcanza con registrarla en register_tabs.
 /// Los elementos son:
-/// - node_action_sender: Sender para enviar acciones al nodo.
-/// - wallet: GUIWallet.
-/// - balance: GUIBalance.
-/// - logs: GUILogs.
-/// - history: GUIHistory.
-/// - utxo: GUIUtxo.
-/// - blocks: GUIBlocks.
-/// - transfer: GUITransfer.
-/// - window: GUIWindow.
+/// - tabs: Pestañas registradas.
 pub struct GUI {
-    node_action_sender: mpsc::Sender<NodeAction>,
-    wallet: GUIWallet,
-    balance: GUIBalance,
-    logs: GUILogs,
-    history: GUIHistory,
-    utxo: GUIUtxo,
-    blocks: GUIBlocks,
-    transfer: GUITransfer,
-    window: GUIWindow,
+    tabs: GUITabs,
 }
 
 impl GUI {
@@ -65,124 +137,88 @@ impl GUI {
     /// Inicializa el ciclo de vida de la interfaz grafica (escuchar los GUIEvents).
     pub fn start(
         gui_receiver: Receiver<GUIEvents>,
+        gui_sender: Sender<GUIEvents>,
         node_state_ref: Arc<Mutex<NodeState>>,
         logger_sender: mpsc::Sender<Log>,
         node_action_sender: mpsc::Sender<NodeAction>,
+        config_reloader: ConfigReloader,
     ) -> Result<(), CustomError> {
         if gtk::init().is_err() {
             return Err(CustomError::CannotInitGUI);
         }
 
         let glade_src = include_str!("gui.glade");
-        let builder = gtk::Builder::from_string(glade_src);
-
-        let wallet = GUIWallet {
-            builder: builder.clone(),
-            node_state_ref: node_state_ref.clone(),
-            logger_sender: logger_sender.clone(),
-        };
-
-        let balance = GUIBalance {
-            builder: builder.clone(),
-            node_state_ref: node_state_ref.clone(),
-            logger_sender: logger_sender.clone(),
-            available_balance: 0.0,
-            pending_balance: 0.0,
-        };
-
-        let logs = GUILogs {
-            builder: builder.clone(),
-            logger_sender: logger_sender.clone(),
-        };
-
-        let history = GUIHistory {
-            builder: builder.clone(),
-            logger_sender: logger_sender.clone(),
-            node_state_ref: node_state_ref.clone(),
-        };
-
-        let utxo = GUIUtxo {
-            builder: builder.clone(),
-            logger_sender: logger_sender.clone(),
-            node_state_ref: node_state_ref.clone(),
-        };
-
-        let blocks = GUIBlocks {
-            builder: builder.clone(),
-            logger_sender: logger_sender.clone(),
-            node_state_ref: node_state_ref.clone(),
-            node_state_ready: false,
-        };
-
-        let transfer = GUITransfer {
-            builder: builder.clone(),
-            logger_sender: logger_sender.clone(),
+        let context = GUIContext {
+            builder: gtk::Builder::from_string(glade_src),
             node_state_ref,
-        };
-
-        let window = GUIWindow {
-            builder,
             logger_sender,
+            node_action_sender,
+            gui_sender,
+            config_reloader,
+            counterparty_index_ref: Arc::new(Mutex::new(CounterpartyIndex::default())),
         };
 
         let gui = Self {
-            node_action_sender,
-            wallet,
-            balance,
-            logs,
-            history,
-            utxo,
-            blocks,
-            transfer,
-            window,
+            tabs: register_tabs(&context),
         };
 
-        gui.handle_interactivity()?;
-        gui.gui_actions_loop(gui_receiver)?;
+        gui.tabs.start()?;
+        gui.gui_actions_loop(gui_receiver);
 
         gtk::main();
 
         Ok(())
     }
 
-    /// Inicializa los elementos de la interfaz grafica.
-    /// Establece la interactividad de los elementos que la necesitan.
-    pub fn handle_interactivity(&self) -> Result<(), CustomError> {
-        // initialize
-        self.wallet.initialize()?;
-        self.window.initialize()?;
-
-        // interactivity
-        self.wallet.handle_interactivity()?;
-        self.transfer
-            .handle_interactivity(&self.node_action_sender)?;
-
-        Ok(())
-    }
-
-    fn gui_actions_loop(&self, gui_receiver: Receiver<GUIEvents>) -> Result<(), CustomError> {
-        let mut balance = self.balance.clone();
-        let logs = self.logs.clone();
-        let mut transactions = self.history.clone();
-        let window = self.window.clone();
-        let mut transfer = self.transfer.clone();
-        let mut utxo = self.utxo.clone();
-        let mut blocks = self.blocks.clone();
-
+    /// Pasa cada GUIEvent recibido a las pestañas registradas.
+    fn gui_actions_loop(mut self, gui_receiver: Receiver<GUIEvents>) {
         gui_receiver.attach(None, move |message| {
-            balance.handle_events(&message);
-            logs.handle_events(&message);
-            transactions.handle_events(&message);
-            window.handle_events(&message);
-            transfer.handle_events(&message);
-            utxo.handle_events(&message);
-            blocks.handle_events(&message);
+            self.tabs.dispatch(&message);
 
             glib::Continue(true)
         });
+    }
+}
 
-        Ok(())
+/// Pestañas de la interfaz grafica, con su nombre y la funcion que la crea con el contexto y la registra.
+/// Estan en el orden en que se registran y por lo tanto en el que se inician y reciben los GUIEvents:
+/// GUIWallet va primero, asi el selector muestra la wallet activa antes de que las demas pestañas carguen sus datos.
+const TABS: [(&str, fn(&mut GUITabs, &GUIContext)); 10] = [
+    ("wallet", |tabs, context| {
+        tabs.register(GUIWallet::new(context))
+    }),
+    ("balance", |tabs, context| {
+        tabs.register(GUIBalance::new(context))
+    }),
+    ("logs", |tabs, context| tabs.register(GUILogs::new(context))),
+    ("history", |tabs, context| {
+        tabs.register(GUIHistory::new(context))
+    }),
+    ("window", |tabs, context| {
+        tabs.register(GUIWindow::new(context))
+    }),
+    ("transfer", |tabs, context| {
+        tabs.register(GUITransfer::new(context))
+    }),
+    ("utxo", |tabs, context| tabs.register(GUIUtxo::new(context))),
+    ("blocks", |tabs, context| {
+        tabs.register(GUIBlocks::new(context))
+    }),
+    ("peers", |tabs, context| {
+        tabs.register(GUIPeers::new(context))
+    }),
+    ("counterparties", |tabs, context| {
+        tabs.register(GUICounterparties::new(context))
+    }),
+];
+
+/// Crea las pestañas de la interfaz grafica con el contexto y las registra en el orden de TABS.
+fn register_tabs(context: &GUIContext) -> GUITabs {
+    let mut tabs = GUITabs::default();
+    for (_, register) in TABS {
+        register(&mut tabs, context);
     }
+    tabs
 }
 
 /// Devuelve un elemento de la interfaz grafica.
@@ -194,3 +230,28 @@ pub fn get_gui_element<T: IsA<Object>>(
     let element: T = builder.object(name).ok_or(CustomError::MissingGUIElement)?;
     Ok(element)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tabs_are_registered_in_the_dispatch_order() {
+        let names: Vec<&str> = TABS.iter().map(|(name, _)| *name).collect();
+        assert_eq!(
+            names,
+            vec![
+                "wallet",
+                "balance",
+                "logs",
+                "history",
+                "window",
+                "transfer",
+                "utxo",
+                "blocks",
+                "peers",
+                "counterparties",
+            ]
+        );
+    }
+}
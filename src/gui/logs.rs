@@ -8,7 +8,10 @@ use crate::{
     logger::{send_log, Log},
 };
 
-use super::init::{get_gui_element, GUIEvents};
+use super::{
+    init::{get_gui_element, GUIEvents},
+    tab::{GUIContext, GUITab},
+};
 
 #[derive(Clone)]
 /// GUILogs es una estructura que contiene los elementos de la interfaz grafica
@@ -21,10 +24,10 @@ pub struct GUILogs {
     pub logger_sender: mpsc::Sender<Log>,
 }
 
-impl GUILogs {
+impl GUITab for GUILogs {
     /// Maneja los GUIEvents recibidos y hace las acciones acorde a cada envento.
     /// Para Log: Actualiza los logs en la interfaz.
-    pub fn handle_events(&self, message: &GUIEvents) {
+    fn handle_events(&mut self, message: &GUIEvents) {
         let result = match message {
             GUIEvents::Log(log) => self.handle_log(log),
             _ => Ok(()),
@@ -34,6 +37,16 @@ impl GUILogs {
             send_log(&self.logger_sender, Log::Error(error));
         }
     }
+}
+
+impl GUILogs {
+    /// Crea los logs con el contexto de la interfaz grafica.
+    pub fn new(context: &GUIContext) -> Self {
+        Self {
+            builder: context.builder.clone(),
+            logger_sender: context.logger_sender.clone(),
+        }
+    }
 
     fn handle_log(&self, log: &Log) -> Result<(), CustomError> {
         let logs: gtk::Label = get_gui_element(&self.builder, "logs")?;
@@ -48,6 +61,13 @@ impl GUILogs {
                 logs.set_text(formatted_string.as_str());
                 load_screen_logs.set_text(formatted_string.as_str());
             }
+            Log::Warning(string) => {
+                let current_time = Local::now();
+                let formatted_time = current_time.format("%Y-%m-%d %H:%M:%S");
+                let formatted_string = format!("[{}] [WARN] {}", formatted_time, string);
+                logs.set_text(formatted_string.as_str());
+                load_screen_logs.set_text(formatted_string.as_str());
+            }
             Log::Error(error) => {
                 dialog_error.set_text(Some("Error"));
                 match error {
@@ -62,7 +82,7 @@ impl GUILogs {
                 dialog_error.set_text(Some(""));
                 dialog_error.set_secondary_text(Some(""));
             }
-            Log::Terminate => {}
+            Log::SetLevel(_) | Log::Terminate => {}
         }
 
         Ok(())
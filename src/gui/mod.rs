@@ -1,9 +1,14 @@
 pub mod balance;
 pub mod blocks;
+pub mod counterparties;
 pub mod history;
 pub mod init;
 pub mod logs;
+pub mod peers;
+pub mod tab;
 pub mod table_cells;
+pub mod table_diff;
+pub mod table_rows;
 pub mod transfer;
 pub mod utxo;
 pub mod wallet;
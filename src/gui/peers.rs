@@ -0,0 +1,186 @@
+use std::{
+    sync::{mpsc::Sender, Arc, Mutex},
+    time::Duration,
+};
+
+use gtk::{
+    prelude::{GtkListStoreExt, GtkListStoreExtManual},
+    traits::{ContainerExt, ExpanderExt, LabelExt, WidgetExt},
+    ListBox,
+};
+
+use crate::{
+    error::CustomError,
+    logger::{send_log, Log},
+    node_state::NodeState,
+};
+
+use super::{
+    init::{get_gui_element, GUIEvents},
+    tab::{GUIContext, GUITab},
+    table_cells::{number_label, time_label},
+};
+
+#[derive(Clone)]
+/// GUIPeers es una estructura que contiene los elementos de la interfaz grafica
+/// relacionados con los peers. Muestra una tabla con los peers conectados (direccion, version, servicios,
+/// direccion de la conexion, tiempo conectado y latencia promedio de los pings) y, en una seccion desplegable, las ultimas desconexiones de peers
+/// (fecha, direccion, motivo, segundos conectado y bytes transferidos).
+/// Los elementos son:
+/// - builder: Builder de gtk.
+/// - node_state_ref: Referencia al estado del nodo.
+/// - logger_sender: Sender para enviar logs al logger.
+pub struct GUIPeers {
+    pub logger_sender: Sender<Log>,
+    pub builder: gtk::Builder,
+    pub node_state_ref: Arc<Mutex<NodeState>>,
+}
+
+impl GUITab for GUIPeers {
+    /// Maneja los GUIEvents recibidos y hace las acciones acorde a cada envento.
+    /// Para PeerDisconnected: Actualiza la lista de desconexiones.
+    /// Para PeersUpdated y NodeStateReady: Actualiza la tabla de peers conectados.
+    fn handle_events(&mut self, message: &GUIEvents) {
+        let result = match message {
+            GUIEvents::PeerDisconnected(_) => self.update_disconnects(),
+            GUIEvents::PeersUpdated { .. } => self.update_peers(),
+            GUIEvents::NodeStateReady => self.update_peers(),
+            _ => Ok(()),
+        };
+
+        if let Err(error) = result {
+            send_log(&self.logger_sender, Log::Error(error));
+        }
+    }
+}
+
+impl GUIPeers {
+    /// Crea la tabla de peers con el contexto de la interfaz grafica.
+    pub fn new(context: &GUIContext) -> Self {
+        Self {
+            builder: context.builder.clone(),
+            node_state_ref: context.node_state_ref.clone(),
+            logger_sender: context.logger_sender.clone(),
+        }
+    }
+
+    fn update_peers(&self) -> Result<(), CustomError> {
+        let peers_store: gtk::ListStore = get_gui_element(&self.builder, "peers-store")?;
+        let peers_label: gtk::Label = get_gui_element(&self.builder, "peers-label")?;
+        let node_state = self.node_state_ref.lock()?;
+        let summaries = node_state.get_peer_summaries();
+        drop(node_state);
+
+        peers_label.set_label(&format!("Connected peers ({})", summaries.len()));
+
+        peers_store.clear();
+        for summary in summaries {
+            peers_store.insert_with_values(
+                None,
+                &[
+                    (0, &summary.address.to_string()),
+                    (1, &summary.version),
+                    (2, &summary.services.join(", ")),
+                    (3, &summary.direction()),
+                    (4, &format_uptime(summary.uptime)),
+                    (5, &format_latency(summary.avg_latency_ms)),
+                ],
+            );
+        }
+        Ok(())
+    }
+
+    fn update_disconnects(&self) -> Result<(), CustomError> {
+        let disconnects_list_box: gtk::ListBox =
+            get_gui_element(&self.builder, "disconnects-list")?;
+        let disconnects_expander: gtk::Expander =
+            get_gui_element(&self.builder, "disconnects-expander")?;
+        let node_state = self.node_state_ref.lock()?;
+        let disconnects = node_state.recent_disconnects();
+        drop(node_state);
+
+        disconnects_expander.set_label(Some(&format!(
+            "Recent disconnections ({})",
+            disconnects.len()
+        )));
+
+        reset_table(&disconnects_list_box);
+        for event in disconnects.iter().rev() {
+            let disconnect_row = gtk::ListBoxRow::new();
+            let disconnect_box = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+
+            let address_label = gtk::Label::new(Some(event.address.to_string().as_str()));
+            address_label.set_expand(true);
+            let reason_label = gtk::Label::new(Some(event.reason.to_string().as_str()));
+            reason_label.set_width_request(220);
+
+            disconnect_box.add(&time_label(event.timestamp));
+            disconnect_box.add(&address_label);
+            disconnect_box.add(&reason_label);
+            disconnect_box.add(&number_label(event.connected_for.as_secs() as i64));
+            disconnect_box.add(&number_label(event.bytes_transferred as i64));
+
+            disconnect_row.add(&disconnect_box);
+            disconnect_row.show_all();
+            disconnects_list_box.add(&disconnect_row);
+        }
+        Ok(())
+    }
+}
+
+/// Devuelve el tiempo conectado como horas, minutos y segundos (por ejemplo 2h 05m 09s).
+fn format_uptime(uptime: Duration) -> String {
+    let seconds = uptime.as_secs();
+    format!(
+        "{}h {:02}m {:02}s",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// Devuelve la latencia de los pings de un peer en milisegundos, o "-" si todavia no respondio ninguno.
+fn format_latency(avg_latency_ms: Option<u64>) -> String {
+    match avg_latency_ms {
+        Some(latency) => format!("{} ms", latency),
+        None => "-".to_string(),
+    }
+}
+
+fn reset_table(list_box: &ListBox) {
+    list_box.foreach(|child| {
+        list_box.remove(child);
+    });
+    let disconnect_row = gtk::ListBoxRow::new();
+    let disconnect_box = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let time_label = gtk::Label::new(None);
+    let address_label = gtk::Label::new(None);
+    let reason_label = gtk::Label::new(None);
+    let connected_label = gtk::Label::new(None);
+    let bytes_label = gtk::Label::new(None);
+
+    time_label.set_width_request(92);
+    time_label.set_markup("<b>Time</b>");
+
+    address_label.set_expand(true);
+    address_label.set_markup("<b>Address</b>");
+
+    reason_label.set_width_request(220);
+    reason_label.set_markup("<b>Reason</b>");
+
+    connected_label.set_width_request(100);
+    connected_label.set_markup("<b>Connected (s)</b>");
+
+    bytes_label.set_width_request(100);
+    bytes_label.set_markup("<b>Bytes</b>");
+
+    disconnect_box.add(&time_label);
+    disconnect_box.add(&address_label);
+    disconnect_box.add(&reason_label);
+    disconnect_box.add(&connected_label);
+    disconnect_box.add(&bytes_label);
+
+    disconnect_row.add(&disconnect_box);
+    disconnect_row.show_all();
+    list_box.add(&disconnect_row);
+}
@@ -0,0 +1,221 @@
+use std::sync::{mpsc, Arc, Mutex};
+
+use gtk::glib;
+
+use crate::{
+    config_reload::ConfigReloader, counterparties::CounterpartyIndex, error::CustomError,
+    logger::Log, loops::node_action_loop::NodeAction, node_state::NodeState,
+};
+
+use super::init::GUIEvents;
+
+#[derive(Clone)]
+/// GUIContext contiene lo que comparten las pestañas de la interfaz grafica, con lo que cada una se crea (ver GUITab).
+/// Los elementos son:
+/// - builder: Builder de gtk, cada pestaña busca en el sus propios elementos.
+/// - node_state_ref: Referencia al estado del nodo.
+/// - logger_sender: Sender para enviar logs al logger.
+/// - node_action_sender: Sender para enviar acciones al nodo.
+/// - gui_sender: Sender para enviar GUIEvents desde otros threads.
+/// - config_reloader: ConfigReloader con el que se recarga el archivo de configuracion.
+/// - counterparty_index_ref: Contrapartes de los movimientos de la wallet activa, compartidas entre pestañas.
+pub struct GUIContext {
+    pub builder: gtk::Builder,
+    pub node_state_ref: Arc<Mutex<NodeState>>,
+    pub logger_sender: mpsc::Sender<Log>,
+    pub node_action_sender: mpsc::Sender<NodeAction>,
+    pub gui_sender: glib::Sender<GUIEvents>,
+    pub config_reloader: ConfigReloader,
+    pub counterparty_index_ref: Arc<Mutex<CounterpartyIndex>>,
+}
+
+/// GUITab es una parte de la interfaz grafica (una pestaña o un elemento de la ventana) que se registra en GUITabs.
+/// Al iniciar la interfaz grafica se llama initialize en cada pestaña, despues handle_interactivity en cada una
+/// y por ultimo on_ready. Cada GUIEvent recibido se pasa a todas las pestañas con handle_events.
+pub trait GUITab {
+    /// Carga los datos que la pestaña muestra al iniciar.
+    fn initialize(&self) -> Result<(), CustomError> {
+        Ok(())
+    }
+
+    /// Establece los callbacks de los elementos de la pestaña.
+    fn handle_interactivity(&self) -> Result<(), CustomError> {
+        Ok(())
+    }
+
+    /// Se llama una vez que todas las pestañas establecieron sus callbacks.
+    fn on_ready(&self) -> Result<(), CustomError> {
+        Ok(())
+    }
+
+    /// Maneja un GUIEvent recibido, ignorando los que no le corresponden.
+    fn handle_events(&mut self, message: &GUIEvents);
+}
+
+#[derive(Default)]
+/// GUITabs son las pestañas registradas de la interfaz grafica, que reciben los GUIEvents en el orden en que se registraron.
+/// Para agregar una pestaña alcanza con registrarla, sin cambiar el resto.
+pub struct GUITabs {
+    tabs: Vec<Box<dyn GUITab>>,
+}
+
+impl GUITabs {
+    /// Registra una pestaña, despues de las ya registradas.
+    pub fn register(&mut self, tab: impl GUITab + 'static) {
+        self.tabs.push(Box::new(tab));
+    }
+
+    /// Inicia las pestañas: las inicializa a todas, despues establece sus callbacks y por ultimo llama on_ready.
+    /// Si alguna devuelve un error no se continua con las demas.
+    pub fn start(&self) -> Result<(), CustomError> {
+        for tab in &self.tabs {
+            tab.initialize()?;
+        }
+        for tab in &self.tabs {
+            tab.handle_interactivity()?;
+        }
+        for tab in &self.tabs {
+            tab.on_ready()?;
+        }
+        Ok(())
+    }
+
+    /// Pasa un GUIEvent a todas las pestañas.
+    pub fn dispatch(&mut self, message: &GUIEvents) {
+        for tab in &mut self.tabs {
+            tab.handle_events(message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    /// Pestaña que registra las llamadas que recibe, con su nombre, en calls.
+    struct RecordingTab {
+        name: &'static str,
+        calls: Rc<RefCell<Vec<String>>>,
+        fail_initialize: bool,
+    }
+
+    impl RecordingTab {
+        fn new(name: &'static str, calls: &Rc<RefCell<Vec<String>>>) -> Self {
+            Self {
+                name,
+                calls: calls.clone(),
+                fail_initialize: false,
+            }
+        }
+
+        fn record(&self, call: &str) {
+            self.calls
+                .borrow_mut()
+                .push(format!("{} {call}", self.name));
+        }
+    }
+
+    impl GUITab for RecordingTab {
+        fn initialize(&self) -> Result<(), CustomError> {
+            self.record("initialize");
+            match self.fail_initialize {
+                true => Err(CustomError::MissingGUIElement),
+                false => Ok(()),
+            }
+        }
+
+        fn handle_interactivity(&self) -> Result<(), CustomError> {
+            self.record("interactivity");
+            Ok(())
+        }
+
+        fn on_ready(&self) -> Result<(), CustomError> {
+            self.record("ready");
+            Ok(())
+        }
+
+        fn handle_events(&mut self, message: &GUIEvents) {
+            match message {
+                GUIEvents::NewBlock => self.record("NewBlock"),
+                GUIEvents::WalletChanged => self.record("WalletChanged"),
+                _ => self.record("other"),
+            }
+        }
+    }
+
+    /// Pestaña que solo maneja eventos, con el resto de los metodos por defecto.
+    struct EventsOnlyTab {
+        events: Rc<RefCell<usize>>,
+    }
+
+    impl GUITab for EventsOnlyTab {
+        fn handle_events(&mut self, _message: &GUIEvents) {
+            *self.events.borrow_mut() += 1;
+        }
+    }
+
+    #[test]
+    fn tabs_start_in_phases_and_in_registration_order() {
+        let calls = Rc::new(RefCell::new(vec![]));
+        let mut tabs = GUITabs::default();
+        tabs.register(RecordingTab::new("wallet", &calls));
+        tabs.register(RecordingTab::new("window", &calls));
+
+        tabs.start().unwrap();
+        assert_eq!(
+            *calls.borrow(),
+            vec![
+                "wallet initialize",
+                "window initialize",
+                "wallet interactivity",
+                "window interactivity",
+                "wallet ready",
+                "window ready",
+            ]
+        );
+    }
+
+    #[test]
+    fn start_stops_at_the_first_error() {
+        let calls = Rc::new(RefCell::new(vec![]));
+        let mut tabs = GUITabs::default();
+        tabs.register(RecordingTab {
+            fail_initialize: true,
+            ..RecordingTab::new("wallet", &calls)
+        });
+        tabs.register(RecordingTab::new("window", &calls));
+
+        assert!(matches!(tabs.start(), Err(CustomError::MissingGUIElement)));
+        assert_eq!(*calls.borrow(), vec!["wallet initialize"]);
+    }
+
+    #[test]
+    fn every_event_is_routed_to_every_tab_in_registration_order() {
+        let calls = Rc::new(RefCell::new(vec![]));
+        let events = Rc::new(RefCell::new(0));
+        let mut tabs = GUITabs::default();
+        tabs.register(RecordingTab::new("balance", &calls));
+        tabs.register(EventsOnlyTab {
+            events: events.clone(),
+        });
+        tabs.register(RecordingTab::new("blocks", &calls));
+
+        tabs.start().unwrap();
+        calls.borrow_mut().clear();
+        tabs.dispatch(&GUIEvents::WalletChanged);
+        tabs.dispatch(&GUIEvents::NewBlock);
+
+        assert_eq!(
+            *calls.borrow(),
+            vec![
+                "balance WalletChanged",
+                "blocks WalletChanged",
+                "balance NewBlock",
+                "blocks NewBlock",
+            ]
+        );
+        assert_eq!(*events.borrow(), 2);
+    }
+}
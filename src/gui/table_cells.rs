@@ -1,14 +1,24 @@
 use std::sync::{mpsc::Sender, Arc, Mutex};
 
 use chrono::{DateTime, Local, NaiveDateTime};
-use gtk::traits::{ButtonExt, ContainerExt, LabelExt, WidgetExt};
+use gtk::traits::{
+    ButtonExt, ContainerExt, DialogExt, EntryExt, GtkMenuExt, GtkMenuItemExt, LabelExt,
+    MenuShellExt, WidgetExt,
+};
 
 use crate::{
+    error::CustomError,
     logger::{send_log, Log},
+    loops::node_action_loop::NodeAction,
     node_state::NodeState,
+    pricing::{format_fiat, format_rate, FiatRate},
+    send_many::CoinMaturity,
     structs::block_header::hash_as_string,
+    utils::get_current_timestamp,
 };
 
+use super::init::get_gui_element;
+
 /// Genera un label formateado para un hash en formato hexadecimal y lo devuelve.
 pub fn tx_hash_label(mut tx_hash: Vec<u8>) -> gtk::Label {
     let tx_hash_label = gtk::Label::new(None);
@@ -55,6 +65,21 @@ pub fn value_label(value: i64) -> gtk::Label {
     value_label
 }
 
+/// Genera un label con el valor de una transaccion en BTC y, si hay cotizacion, debajo su equivalente en moneda fiat.
+/// El tooltip indica la cotizacion usada y de cuando es.
+pub fn value_with_fiat_label(value: i64, rate: Option<&FiatRate>) -> gtk::Label {
+    let value_label = value_label(value);
+    let Some(rate) = rate else { return value_label };
+
+    let btc_string = format!("{:.8} BTC", (value as f64) / 100_000_000.0);
+    value_label.set_text(format!("{}\n{}", btc_string, format_fiat(value, rate)).as_str());
+    if let Ok(now) = get_current_timestamp() {
+        value_label.set_tooltip_text(Some(format_rate(rate, now).as_str()));
+    }
+
+    value_label
+}
+
 /// Genera un boton para pedir el merkle proof de una transaccion y lo devuelve.
 /// Si el bloque no esta en la base de datos, no se muestra el boton.
 pub fn merkle_proof_button(
@@ -69,10 +94,9 @@ pub fn merkle_proof_button(
         let button = gtk::Button::new();
 
         button.set_label("Merkle Proof");
-        let block_hash_string = hash_as_string(block_hash);
         button.connect_clicked(move |_| {
             let node_state = node_state_ref.lock().unwrap();
-            let block = match node_state.get_block(block_hash_string.clone()) {
+            let block = match node_state.get_block(&block_hash) {
                 Ok(block) => block,
                 Err(error) => {
                     send_log(&logger_sender, Log::Error(error));
@@ -103,6 +127,157 @@ pub fn merkle_proof_button(
     button_box
 }
 
+/// Genera un boton para abandonar una transaccion pendiente enviada por la wallet y lo devuelve.
+/// Antes de abandonarla pide confirmacion, ya que la transaccion todavia puede confirmarse.
+/// Si la transaccion no es un envio de la wallet, no se muestra el boton.
+pub fn abandon_button(
+    value: i64,
+    tx_hash: Vec<u8>,
+    builder: gtk::Builder,
+    logger_sender: Sender<Log>,
+    node_state_ref: Arc<Mutex<NodeState>>,
+) -> gtk::Box {
+    let button_box = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+
+    if value < 0 {
+        let button = gtk::Button::new();
+
+        button.set_label("Abandon");
+        button.connect_clicked(move |_| {
+            let dialog: gtk::MessageDialog = match get_gui_element(&builder, "abandon-tx-dialog") {
+                Ok(dialog) => dialog,
+                Err(error) => {
+                    send_log(&logger_sender, Log::Error(error));
+                    return;
+                }
+            };
+            let response = dialog.run();
+            dialog.hide();
+            if response != gtk::ResponseType::Yes {
+                return;
+            }
+
+            let mut node_state = node_state_ref.lock().unwrap();
+            if let Err(error) = node_state.abandon_transaction(&tx_hash) {
+                send_log(&logger_sender, Log::Error(error));
+            }
+        });
+
+        button_box.add(&button);
+    }
+    button_box.set_width_request(128);
+
+    button_box
+}
+
+/// Boton del mouse con el que se abren los menus contextuales (click derecho).
+const CONTEXT_MENU_BUTTON: u32 = 3;
+
+/// Agrega a la fila de una transaccion pendiente enviada por la wallet un menu contextual (click derecho)
+/// con la opcion de volver a enviarla a los peers en ese momento, sin esperar a su proximo reenvio.
+/// Si la transaccion es reemplazable tambien tiene la opcion de aumentarle el fee: pide el nuevo fee
+/// y le pide al nodo que la reemplace (ver NodeState::replace_transaction).
+pub fn attach_pending_tx_menu(
+    event_box: &gtk::EventBox,
+    tx_hash: Vec<u8>,
+    replaceable: bool,
+    builder: gtk::Builder,
+    logger_sender: Sender<Log>,
+    node_state_ref: Arc<Mutex<NodeState>>,
+    node_action_sender: Sender<NodeAction>,
+) {
+    let menu = gtk::Menu::new();
+    let rebroadcast_item = gtk::MenuItem::with_label("Rebroadcast now");
+    let rebroadcast_tx_hash = tx_hash.clone();
+    let rebroadcast_logger_sender = logger_sender.clone();
+    rebroadcast_item.connect_activate(move |_| {
+        let mut node_state = node_state_ref.lock().unwrap();
+        if let Err(error) = node_state.rebroadcast_transaction(&rebroadcast_tx_hash) {
+            send_log(&rebroadcast_logger_sender, Log::Error(error));
+        }
+    });
+    menu.append(&rebroadcast_item);
+
+    if replaceable {
+        let bump_fee_item = gtk::MenuItem::with_label("Bump fee");
+        bump_fee_item.connect_activate(move |_| {
+            let new_fee = match ask_new_fee(&builder) {
+                Ok(Some(new_fee)) => new_fee,
+                Ok(None) => return,
+                Err(error) => {
+                    send_log(&logger_sender, Log::Error(error));
+                    return;
+                }
+            };
+            if node_action_sender
+                .send(NodeAction::ReplaceTransaction((tx_hash.clone(), new_fee)))
+                .is_err()
+            {
+                send_log(
+                    &logger_sender,
+                    Log::Error(CustomError::CannotSendMessageToChannel(None)),
+                );
+            }
+        });
+        menu.append(&bump_fee_item);
+    }
+    menu.show_all();
+
+    event_box.connect_button_press_event(move |_, event| {
+        if event.button() != CONTEXT_MENU_BUTTON {
+            return gtk::Inhibit(false);
+        }
+        let trigger_event: &gtk::gdk::Event = event;
+        menu.popup_at_pointer(Some(trigger_event));
+        gtk::Inhibit(true)
+    });
+}
+
+/// Muestra el dialogo para ingresar el nuevo fee de una transaccion reemplazable y lo devuelve.
+/// Devuelve None si se cancelo, y CustomError::InvalidFee si el fee no es un numero de satoshis mayor a 0.
+fn ask_new_fee(builder: &gtk::Builder) -> Result<Option<u64>, CustomError> {
+    let dialog: gtk::MessageDialog = get_gui_element(builder, "bump-fee-dialog")?;
+    let fee_entry: gtk::Entry = get_gui_element(builder, "bump-fee-entry")?;
+    fee_entry.set_text("");
+    let response = dialog.run();
+    dialog.hide();
+    if response != gtk::ResponseType::Ok {
+        return Ok(None);
+    }
+
+    match fee_entry.text().trim().parse::<u64>() {
+        Ok(fee) if fee > 0 => Ok(Some(fee)),
+        _ => Err(CustomError::InvalidFee),
+    }
+}
+
+/// Genera un label que indica que una transaccion fue abandonada y lo devuelve.
+pub fn abandoned_label() -> gtk::Label {
+    let abandoned_label = gtk::Label::new(Some("Abandoned"));
+
+    abandoned_label.set_width_request(128);
+
+    abandoned_label
+}
+
+/// Genera un label que indica que el movimiento es provisional, todavia no se verifico con el bloque completo.
+pub fn unverified_label() -> gtk::Label {
+    let unverified_label = gtk::Label::new(Some("Unverified"));
+
+    unverified_label.set_width_request(128);
+
+    unverified_label
+}
+
+/// Genera un label que indica que un dato todavia no esta disponible y lo devuelve.
+pub fn pending_label() -> gtk::Label {
+    let pending_label = gtk::Label::new(Some("Pending"));
+
+    pending_label.set_width_request(100);
+
+    pending_label
+}
+
 /// Genera un label formateado que indica si se recibe o se envia en la transaccion.
 /// Si el valor es positivo, se recibe, sino se envia
 pub fn side_label(value: i64) -> gtk::Label {
@@ -117,6 +292,17 @@ pub fn side_label(value: i64) -> gtk::Label {
     side_label
 }
 
+/// Genera un label con la contraparte de un movimiento (nombre o direccion) y lo devuelve.
+/// Si todavia no se conoce, queda vacio.
+pub fn counterparty_label(counterparty: Option<String>) -> gtk::Label {
+    let counterparty_label = gtk::Label::new(counterparty.as_deref());
+
+    counterparty_label.set_width_request(200);
+    counterparty_label.set_ellipsize(gtk::pango::EllipsizeMode::Middle);
+
+    counterparty_label
+}
+
 /// Genera un label formateado para un numero y lo devuelve.
 pub fn number_label(value: i64) -> gtk::Label {
     let number_label = gtk::Label::new(Some(value.to_string().as_str()));
@@ -125,3 +311,18 @@ pub fn number_label(value: i64) -> gtk::Label {
 
     number_label
 }
+
+/// Genera un label con las confirmaciones de una UTXO y lo devuelve.
+/// Si la UTXO todavia no se puede gastar, indica el motivo.
+pub fn confirmations_label(confirmations: u32, maturity: CoinMaturity) -> gtk::Label {
+    let text = match maturity {
+        CoinMaturity::Spendable => confirmations.to_string(),
+        CoinMaturity::BelowThreshold => format!("{confirmations} (below minimum)"),
+        CoinMaturity::ImmatureCoinbase => format!("{confirmations} (immature coinbase)"),
+    };
+    let confirmations_label = gtk::Label::new(Some(text.as_str()));
+
+    confirmations_label.set_width_request(160);
+
+    confirmations_label
+}
@@ -0,0 +1,190 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+/// TableDiff son los cambios para pasar de las filas que muestra una tabla a las nuevas, sin volver a generarlas todas.
+/// Se aplican en orden: primero las filas quitadas, despues las actualizadas y por ultimo las insertadas,
+/// en orden creciente de posicion.
+/// Los elementos son:
+/// - removals: Claves de las filas a quitar.
+/// - updates: Filas que siguen en la tabla pero cambiaron sus valores, con los valores nuevos.
+/// - insertions: Filas nuevas, con su posicion en la tabla final.
+pub struct TableDiff<K, V> {
+    pub removals: Vec<K>,
+    pub updates: Vec<(K, V)>,
+    pub insertions: Vec<(usize, K, V)>,
+}
+
+impl<K, V> TableDiff<K, V> {
+    /// Devuelve true si la tabla no cambia.
+    pub fn is_empty(&self) -> bool {
+        self.removals.is_empty() && self.updates.is_empty() && self.insertions.is_empty()
+    }
+}
+
+/// Devuelve los cambios para pasar de las filas previous a las filas next, ambas como pares (clave, valores) en el
+/// orden en el que se muestran. Si una clave se repite en next solo se tiene en cuenta su primera fila.
+/// Las filas que siguen en la tabla se actualizan en su lugar si cambiaron sus valores. Las que quedaron en otro
+/// orden respecto de las demas (lo que no pasa al agregar o quitar filas) se quitan y se vuelven a insertar.
+pub fn diff_rows<K, V>(previous: &[(K, V)], next: &[(K, V)]) -> TableDiff<K, V>
+where
+    K: Clone + Eq + Hash,
+    V: Clone + PartialEq,
+{
+    let previous_index: HashMap<&K, (usize, &V)> = previous
+        .iter()
+        .enumerate()
+        .map(|(index, (key, value))| (key, (index, value)))
+        .collect();
+
+    let mut seen: HashSet<&K> = HashSet::new();
+    let mut kept: HashSet<&K> = HashSet::new();
+    let mut updates = vec![];
+    let mut insertions = vec![];
+    let mut last_kept_index = None;
+    for (key, value) in next {
+        if !seen.insert(key) {
+            continue;
+        }
+        let position = seen.len() - 1;
+        match previous_index.get(key) {
+            Some((index, previous_value)) if last_kept_index < Some(*index) => {
+                last_kept_index = Some(*index);
+                kept.insert(key);
+                if *previous_value != value {
+                    updates.push((key.clone(), value.clone()));
+                }
+            }
+            _ => insertions.push((position, key.clone(), value.clone())),
+        }
+    }
+
+    let removals = previous
+        .iter()
+        .filter(|(key, _)| !kept.contains(key))
+        .map(|(key, _)| key.clone())
+        .collect();
+
+    TableDiff {
+        removals,
+        updates,
+        insertions,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Aplica los cambios a las filas, como lo hace TableRows con las filas de la interfaz grafica.
+    fn apply(rows: &mut Vec<(u32, &'static str)>, diff: TableDiff<u32, &'static str>) {
+        rows.retain(|(key, _)| !diff.removals.contains(key));
+        for (key, value) in diff.updates {
+            if let Some(row) = rows.iter_mut().find(|(row_key, _)| *row_key == key) {
+                row.1 = value;
+            }
+        }
+        for (position, key, value) in diff.insertions {
+            rows.insert(position, (key, value));
+        }
+    }
+
+    #[test]
+    fn new_rows_are_inserted_on_top() {
+        let previous = vec![(3, "c"), (2, "b"), (1, "a")];
+        let next = vec![(5, "e"), (4, "d"), (3, "c"), (2, "b"), (1, "a")];
+
+        let diff = diff_rows(&previous, &next);
+        assert_eq!(diff.removals, vec![]);
+        assert_eq!(diff.updates, vec![]);
+        assert_eq!(diff.insertions, vec![(0, 5, "e"), (1, 4, "d")]);
+
+        let mut rows = previous;
+        apply(&mut rows, diff);
+        assert_eq!(rows, next);
+    }
+
+    #[test]
+    fn rows_beyond_the_cap_are_removed() {
+        let previous = vec![(3, "c"), (2, "b"), (1, "a")];
+        let next = vec![(4, "d"), (3, "c"), (2, "b")];
+
+        let diff = diff_rows(&previous, &next);
+        assert_eq!(diff.removals, vec![1]);
+        assert_eq!(diff.insertions, vec![(0, 4, "d")]);
+
+        let mut rows = previous;
+        apply(&mut rows, diff);
+        assert_eq!(rows, next);
+    }
+
+    #[test]
+    fn reorg_removes_disconnected_rows_and_inserts_the_new_branch() {
+        // los bloques 3 y 4 se reemplazan por 3' y 4' y 5'
+        let previous = vec![(4, "d"), (3, "c"), (2, "b"), (1, "a")];
+        let next = vec![(53, "e'"), (43, "d'"), (33, "c'"), (2, "b"), (1, "a")];
+
+        let diff = diff_rows(&previous, &next);
+        assert_eq!(diff.removals, vec![4, 3]);
+        assert_eq!(diff.updates, vec![]);
+        assert_eq!(
+            diff.insertions,
+            vec![(0, 53, "e'"), (1, 43, "d'"), (2, 33, "c'")]
+        );
+
+        let mut rows = previous;
+        apply(&mut rows, diff);
+        assert_eq!(rows, next);
+    }
+
+    #[test]
+    fn changed_rows_are_updated_in_place() {
+        let previous = vec![(3, "pending"), (2, "b"), (1, "a")];
+        let next = vec![(3, "c"), (2, "b"), (1, "a*")];
+
+        let diff = diff_rows(&previous, &next);
+        assert_eq!(diff.removals, vec![]);
+        assert_eq!(diff.updates, vec![(3, "c"), (1, "a*")]);
+        assert_eq!(diff.insertions, vec![]);
+
+        let mut rows = previous;
+        apply(&mut rows, diff);
+        assert_eq!(rows, next);
+    }
+
+    #[test]
+    fn unchanged_rows_produce_no_changes() {
+        let rows = vec![(2, "b"), (1, "a")];
+        assert!(diff_rows(&rows, &rows).is_empty());
+        assert!(diff_rows::<u32, &str>(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn reordered_rows_are_moved() {
+        let previous = vec![(3, "c"), (2, "b"), (1, "a")];
+        let next = vec![(1, "a"), (3, "c"), (2, "b")];
+
+        let diff = diff_rows(&previous, &next);
+        assert_eq!(diff.removals, vec![3, 2]);
+        assert_eq!(diff.insertions, vec![(1, 3, "c"), (2, 2, "b")]);
+
+        let mut rows = previous;
+        apply(&mut rows, diff);
+        assert_eq!(rows, next);
+    }
+
+    #[test]
+    fn repeated_keys_keep_their_first_row() {
+        let previous = vec![(1, "a")];
+        let next = vec![(2, "b"), (1, "a"), (2, "b*")];
+
+        let diff = diff_rows(&previous, &next);
+        assert_eq!(diff.insertions, vec![(0, 2, "b")]);
+
+        let mut rows = previous;
+        apply(&mut rows, diff);
+        assert_eq!(rows, vec![(2, "b"), (1, "a")]);
+    }
+}
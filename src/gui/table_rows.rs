@@ -0,0 +1,90 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+use gtk::{
+    traits::{ContainerExt, ListBoxExt, WidgetExt},
+    ListBox,
+};
+
+use super::table_diff::diff_rows;
+
+/// TableRows es el modelo de las filas de una tabla de la interfaz grafica (un ListBox), que se actualiza
+/// con los cambios respecto de la ultima vez (ver diff_rows) en lugar de volver a generar todas sus filas.
+/// Los elementos son:
+/// - offset: Cantidad de filas fijas al principio de la tabla (por ejemplo la de titulos), que no maneja el modelo.
+/// - rows: Filas que muestra la tabla como pares (clave, valores), en orden.
+/// - widgets: Fila de la tabla de cada clave.
+pub struct TableRows<K, V> {
+    offset: usize,
+    rows: Vec<(K, V)>,
+    widgets: HashMap<K, gtk::ListBoxRow>,
+}
+
+impl<K, V> TableRows<K, V>
+where
+    K: Clone + Eq + Hash,
+    V: Clone + PartialEq,
+{
+    /// Crea un modelo sin filas, para una tabla con offset filas fijas al principio.
+    pub fn new(offset: usize) -> Self {
+        Self {
+            offset,
+            rows: vec![],
+            widgets: HashMap::new(),
+        }
+    }
+
+    /// Cambia la cantidad de filas fijas al principio de la tabla.
+    pub fn set_offset(&mut self, offset: usize) {
+        self.offset = offset;
+    }
+
+    /// Devuelve la cantidad de filas del modelo.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Devuelve true si el modelo no tiene filas.
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Actualiza la tabla para que muestre las filas next: quita las que ya no estan, vuelve a completar solo
+    /// las que cambiaron y agrega las nuevas en su posicion. fill completa una fila vacia con sus celdas.
+    pub fn update(
+        &mut self,
+        list_box: &ListBox,
+        next: Vec<(K, V)>,
+        fill: impl Fn(&gtk::ListBoxRow, &K, &V),
+    ) {
+        let mut seen = HashSet::new();
+        let next: Vec<(K, V)> = next
+            .into_iter()
+            .filter(|(key, _)| seen.insert(key.clone()))
+            .collect();
+        let diff = diff_rows(&self.rows, &next);
+
+        for key in diff.removals {
+            if let Some(row) = self.widgets.remove(&key) {
+                list_box.remove(&row);
+            }
+        }
+        for (key, value) in diff.updates {
+            if let Some(row) = self.widgets.get(&key) {
+                row.foreach(|child| row.remove(child));
+                fill(row, &key, &value);
+                row.show_all();
+            }
+        }
+        for (position, key, value) in diff.insertions {
+            let row = gtk::ListBoxRow::new();
+            fill(&row, &key, &value);
+            row.show_all();
+            list_box.insert(&row, (self.offset + position) as i32);
+            self.widgets.insert(key, row);
+        }
+        self.rows = next;
+    }
+}
@@ -1,42 +1,63 @@
-use std::{
-    collections::HashMap,
-    sync::{mpsc::Sender, Arc, Mutex},
-};
+use std::sync::{mpsc::Sender, Arc, Mutex};
 
-use gtk::traits::{ButtonExt, DialogExt, EntryExt, LabelExt, WidgetExt};
+use gtk::traits::{
+    ButtonExt, DialogExt, EditableSignals, EntryExt, GridExt, LabelExt, MessageDialogExt,
+    ToggleButtonExt, WidgetExt,
+};
 
 use crate::{
+    bech32::is_segwit_address,
     error::CustomError,
     logger::{send_log, Log},
     loops::node_action_loop::NodeAction,
+    messages::transaction::TransactionOptions,
     node_state::NodeState,
+    pricing::{format_fiat, format_rate},
+    send_many::{estimate_size, p2pkh_input_size, FeeSpec, SendManyRequest},
+    states::fee_state::{FAST_TARGET_BLOCKS, NORMAL_TARGET_BLOCKS, SLOW_TARGET_BLOCKS},
+    utils::get_current_timestamp,
 };
 
-use super::init::{get_gui_element, GUIEvents};
-
-const TRANSFER_OUTPUTS: u8 = 3;
+use super::{
+    init::{get_gui_element, GUIEvents},
+    tab::{GUIContext, GUITab},
+};
 
 #[derive(Clone)]
 /// GUITransfer es una estructura que contiene los elementos de la interfaz grafica
 /// relacionados con el envio de transacciones. Permite enviar transacciones a una o mas direcciones ingresando para cada una la pubkey y el monto, ademas del fee.
+/// Las filas de receivers se pueden agregar desde la interfaz, y cada una se registra en el builder como output-{i}-pubkey, output-{i}-value
+/// y output-{i}-fiat, el label con el equivalente del monto en moneda fiat.
 /// Los elementos son:
 /// - builder: Builder de gtk.
 /// - node_state_ref: Referencia al estado del nodo.
 /// - logger_sender: Sender para enviar logs al logger.
+/// - node_action_sender: Sender para enviar al nodo las transacciones a crear.
 pub struct GUITransfer {
     pub logger_sender: Sender<Log>,
     pub builder: gtk::Builder,
     pub node_state_ref: Arc<Mutex<NodeState>>,
+    pub node_action_sender: Sender<NodeAction>,
 }
 
-impl GUITransfer {
+impl GUITab for GUITransfer {
     /// Maneja los GUIEvents recibidos y hace las acciones acorde a cada envento.
-    /// Para WalletChanged: Resetea los campos de la transaccion.
+    /// Para WalletChanged: Resetea los campos de la transaccion y deshabilita el boton de enviar si la wallet activa es watch-only.
     /// Para TransactionSent: Muestra un dialogo de transaccion enviada y resetea los campos.
-    pub fn handle_events(&mut self, message: &GUIEvents) {
+    /// Para TransactionMayNotHavePropagated: Muestra un dialogo avisando que la transaccion puede no haberse propagado.
+    /// Para TransactionRejected: Muestra un dialogo con el motivo por el que un peer rechazo la transaccion.
+    /// Para FiatRateChanged: Actualiza los equivalentes en moneda fiat de los montos y el fee.
+    fn handle_events(&mut self, message: &GUIEvents) {
         let result = match message {
-            GUIEvents::WalletChanged => self.reset_tx_fields(),
+            GUIEvents::WalletChanged => self
+                .reset_tx_fields()
+                .and_then(|_| self.update_send_button()),
             GUIEvents::TransactionSent => self.handle_sent_transaction(),
+            GUIEvents::TransactionMayNotHavePropagated { txid } => {
+                self.handle_stuck_transaction(txid)
+            }
+            GUIEvents::TransactionRejected(reason) => self.handle_rejected_transaction(reason),
+            GUIEvents::FiatRateChanged => update_fiat_labels(&self.builder, &self.node_state_ref),
             _ => Ok(()),
         };
 
@@ -44,23 +65,79 @@ impl GUITransfer {
             send_log(&self.logger_sender, Log::Error(error));
         }
     }
+
     /// Establece los callbacks de los elementos de la interfaz grafica.
     /// Para el boton de enviar transaccion: Envia la transaccion al nodo (o abre una ventana de error en caso de estar mal ingresada) con los valores leidos de la interfaz.
-    pub fn handle_interactivity(
-        &self,
-        node_action_sender: &Sender<NodeAction>,
-    ) -> Result<(), CustomError> {
+    /// Los outputs se envian en el orden de las filas, incluso si una direccion se repite.
+    /// Para el boton de agregar receiver: Agrega una nueva fila de direccion y monto.
+    /// Para los montos: Muestran al lado su equivalente en moneda fiat.
+    /// Para el fee: Muestra el fee por byte que pagaria la transaccion segun su tamaño estimado y su equivalente en moneda fiat.
+    /// Para los botones de fee lento, normal y rapido: Completan el fee con el estimado para confirmarse en esa cantidad de bloques.
+    fn handle_interactivity(&self) -> Result<(), CustomError> {
+        let add_recipient_button: gtk::Button =
+            get_gui_element(&self.builder, "add-recipient-button")?;
+        let builder = self.builder.clone();
+        let logger_sender = self.logger_sender.clone();
+        let node_state_ref = self.node_state_ref.clone();
+        add_recipient_button.connect_clicked(move |_| {
+            if let Err(error) = add_output_row(&builder, &node_state_ref, &logger_sender) {
+                send_log(&logger_sender, Log::Error(error));
+            }
+        });
+
+        for i in 0..count_outputs(&self.builder) {
+            let value: gtk::Entry = get_gui_element(&self.builder, &format!("output-{}-value", i))?;
+            connect_fiat_update(
+                &value,
+                &self.builder,
+                &self.node_state_ref,
+                &self.logger_sender,
+            );
+        }
+
+        let fee_entry: gtk::Entry = get_gui_element(&self.builder, "tx-fee")?;
+        let builder = self.builder.clone();
+        let logger_sender = self.logger_sender.clone();
+        let node_state_ref = self.node_state_ref.clone();
+        fee_entry.connect_changed(move |_| {
+            if let Err(error) = update_fee_rate(&builder, &node_state_ref) {
+                send_log(&logger_sender, Log::Error(error));
+            }
+        });
+        connect_fiat_update(
+            &fee_entry,
+            &self.builder,
+            &self.node_state_ref,
+            &self.logger_sender,
+        );
+
+        for (button_id, target_blocks) in [
+            ("tx-fee-slow", SLOW_TARGET_BLOCKS),
+            ("tx-fee-normal", NORMAL_TARGET_BLOCKS),
+            ("tx-fee-fast", FAST_TARGET_BLOCKS),
+        ] {
+            let button: gtk::Button = get_gui_element(&self.builder, button_id)?;
+            let builder = self.builder.clone();
+            let logger_sender = self.logger_sender.clone();
+            let node_state_ref = self.node_state_ref.clone();
+            button.connect_clicked(move |_| {
+                if let Err(error) = fill_estimated_fee(&builder, &node_state_ref, target_blocks) {
+                    send_log(&logger_sender, Log::Error(error));
+                }
+            });
+        }
+
         let send_button: gtk::Button = get_gui_element(&self.builder, "send-tx")?;
 
-        let node_action_sender_clone = node_action_sender.clone();
+        let node_action_sender_clone = self.node_action_sender.clone();
         let builder = self.builder.clone();
         let logger_sender = self.logger_sender.clone();
 
         send_button.connect_clicked(move |_| {
-            let mut outputs = HashMap::new();
-            for i in 0..TRANSFER_OUTPUTS {
+            let mut outputs = vec![];
+            for i in 0..count_outputs(&builder) {
                 match get_output(&builder, i) {
-                    Ok(Some((pubkey, value))) => outputs.insert(pubkey, value),
+                    Ok(Some(output)) => outputs.push(output),
                     Ok(None) => continue,
                     Err(error) => {
                         send_log(&logger_sender, Log::Error(error));
@@ -69,6 +146,14 @@ impl GUITransfer {
                 };
             }
 
+            let options = match get_tx_options(&builder) {
+                Ok(options) => options,
+                Err(error) => {
+                    send_log(&logger_sender, Log::Error(error));
+                    return;
+                }
+            };
+
             let fee_entry: gtk::Entry = match get_gui_element(&builder, "tx-fee") {
                 Ok(fee_entry) => fee_entry,
                 Err(error) => {
@@ -84,12 +169,12 @@ impl GUITransfer {
                         return;
                     }
                     if node_action_sender_clone
-                        .send(NodeAction::MakeTransaction((outputs, fee)))
+                        .send(NodeAction::MakeTransaction((outputs, fee, options)))
                         .is_err()
                     {
                         send_log(
                             &logger_sender,
-                            Log::Error(CustomError::CannotSendMessageToChannel),
+                            Log::Error(CustomError::CannotSendMessageToChannel(None)),
                         );
                     };
                 }
@@ -100,21 +185,54 @@ impl GUITransfer {
         });
         Ok(())
     }
+}
+
+impl GUITransfer {
+    /// Crea el envio de transacciones con el contexto de la interfaz grafica.
+    pub fn new(context: &GUIContext) -> Self {
+        Self {
+            builder: context.builder.clone(),
+            node_state_ref: context.node_state_ref.clone(),
+            logger_sender: context.logger_sender.clone(),
+            node_action_sender: context.node_action_sender.clone(),
+        }
+    }
 
     fn reset_tx_fields(&self) -> Result<(), CustomError> {
         let fee_entry: gtk::Entry = get_gui_element(&self.builder, "tx-fee")?;
         fee_entry.set_text("0");
+        let lock_time_entry: gtk::Entry = get_gui_element(&self.builder, "tx-lock-time")?;
+        lock_time_entry.set_text("");
+        let replaceable_check: gtk::CheckButton = get_gui_element(&self.builder, "tx-replaceable")?;
+        replaceable_check.set_active(false);
 
-        for i in 0..TRANSFER_OUTPUTS {
+        for i in 0..count_outputs(&self.builder) {
             let receiver_pubkey: gtk::Entry =
                 get_gui_element(&self.builder, &format!("output-{}-pubkey", i))?;
             receiver_pubkey.set_text("");
             let receiver_value: gtk::Entry =
                 get_gui_element(&self.builder, &format!("output-{}-value", i))?;
             receiver_value.set_text("");
-            let label: gtk::Label =
-                get_gui_element(&self.builder, &format!("tx-information-label{}", i))?;
-            label.set_text("");
+        }
+        let label: gtk::Label = get_gui_element(&self.builder, "tx-information-label")?;
+        label.set_text("");
+        Ok(())
+    }
+
+    /// Deshabilita el boton de enviar mientras la wallet activa sea watch-only, ya que no puede firmar transacciones.
+    fn update_send_button(&self) -> Result<(), CustomError> {
+        let send_button: gtk::Button = get_gui_element(&self.builder, "send-tx")?;
+        let watch_only = self
+            .node_state_ref
+            .lock()?
+            .get_active_wallet()
+            .is_some_and(|wallet| wallet.is_watch_only());
+        send_button.set_sensitive(!watch_only);
+        match watch_only {
+            true => send_button.set_tooltip_text(Some(
+                "Watch-only wallets have no private key to sign transactions",
+            )),
+            false => send_button.set_tooltip_text(None),
         }
         Ok(())
     }
@@ -122,29 +240,221 @@ impl GUITransfer {
     fn handle_sent_transaction(&self) -> Result<(), CustomError> {
         let dialog: gtk::MessageDialog = get_gui_element(&self.builder, "successful-tx-dialog")?;
 
-        for i in 0..TRANSFER_OUTPUTS {
-            let label: gtk::Label =
-                get_gui_element(&self.builder, &format!("tx-information-label{}", i))?;
+        let mut information = vec![];
+        for i in 0..count_outputs(&self.builder) {
             if let Ok(Some((pubkey, value))) = get_output(&self.builder, i) {
-                label.set_text(&format!("Transaction of {} sent to: {}", value, pubkey));
+                information.push(format!("Transaction of {} sent to: {}", value, pubkey));
             };
         }
+        let label: gtk::Label = get_gui_element(&self.builder, "tx-information-label")?;
+        label.set_text(&information.join("\n"));
         dialog.run();
         dialog.hide();
 
         self.reset_tx_fields()?;
         Ok(())
     }
+
+    fn handle_stuck_transaction(&self, txid: &str) -> Result<(), CustomError> {
+        let dialog: gtk::MessageDialog = get_gui_element(&self.builder, "error-dialog")?;
+        dialog.set_text(Some("Transaction may not have propagated"));
+        dialog.set_secondary_text(Some(&format!(
+            "No peer announced transaction {txid} after sending it to several peers. If it does not confirm, consider abandoning it and sending it again."
+        )));
+        dialog.run();
+        dialog.hide();
+        dialog.set_text(Some(""));
+        dialog.set_secondary_text(Some(""));
+        Ok(())
+    }
+
+    fn handle_rejected_transaction(&self, reason: &str) -> Result<(), CustomError> {
+        let dialog: gtk::MessageDialog = get_gui_element(&self.builder, "error-dialog")?;
+        dialog.set_text(Some("Transaction rejected"));
+        dialog.set_secondary_text(Some(&format!(
+            "A peer rejected the transaction, so it is no longer pending and its funds are available again.\n{reason}"
+        )));
+        dialog.run();
+        dialog.hide();
+        dialog.set_text(Some(""));
+        dialog.set_secondary_text(Some(""));
+        Ok(())
+    }
+}
+
+/// Devuelve la cantidad de filas de receivers que tiene la interfaz grafica.
+fn count_outputs(builder: &gtk::Builder) -> usize {
+    let mut count = 0;
+    while builder
+        .object::<gtk::Entry>(&format!("output-{}-pubkey", count))
+        .is_some()
+    {
+        count += 1;
+    }
+    count
+}
+
+/// Muestra el fee por byte y el tamaño estimado de la transaccion ingresada, planificandola sin enviarla.
+/// Si la transaccion todavia no es valida, limpia el label.
+fn update_fee_rate(
+    builder: &gtk::Builder,
+    node_state_ref: &Arc<Mutex<NodeState>>,
+) -> Result<(), CustomError> {
+    let label: gtk::Label = get_gui_element(builder, "tx-fee-rate")?;
+    let fee_entry: gtk::Entry = get_gui_element(builder, "tx-fee")?;
+
+    let mut outputs = vec![];
+    for i in 0..count_outputs(builder) {
+        if let Ok(Some(output)) = get_output(builder, i) {
+            outputs.push(output);
+        }
+    }
+
+    let plan = match fee_entry.text().to_string().parse::<u64>() {
+        Ok(fee) if !outputs.is_empty() => {
+            let request = SendManyRequest::new(outputs, fee);
+            node_state_ref.lock()?.plan_transaction(&request).ok()
+        }
+        _ => None,
+    };
+
+    match plan {
+        Some(plan) => label.set_text(&format!(
+            "{:.1} sat/vB ({} vB)",
+            plan.fee_rate(),
+            plan.estimated_size()
+        )),
+        None => label.set_text(""),
+    }
+    Ok(())
+}
+
+/// Completa el fee con el estimado para que la transaccion ingresada se confirme en target_blocks bloques.
+/// El fee por byte estimado se multiplica por el tamaño de la transaccion planificada con ese fee por byte,
+/// o, si todavia no se puede planificar, por el de una transaccion de un input con cambio.
+fn fill_estimated_fee(
+    builder: &gtk::Builder,
+    node_state_ref: &Arc<Mutex<NodeState>>,
+    target_blocks: u16,
+) -> Result<(), CustomError> {
+    let fee_entry: gtk::Entry = get_gui_element(builder, "tx-fee")?;
+
+    let mut outputs = vec![];
+    for i in 0..count_outputs(builder) {
+        if let Ok(Some(output)) = get_output(builder, i) {
+            outputs.push(output);
+        }
+    }
+
+    let node_state = node_state_ref.lock()?;
+    let fee_rate = node_state.estimate_fee_rate(target_blocks);
+    let input_size = p2pkh_input_size(
+        node_state
+            .get_active_wallet()
+            .map_or(true, |wallet| wallet.compressed_pubkey),
+    );
+    let fallback_fee = fee_rate * estimate_size(1, outputs.len().max(1) + 1, input_size);
+    let mut request = SendManyRequest::new(outputs, 0);
+    request.fee = FeeSpec::Rate(fee_rate);
+    let fee = match node_state.plan_transaction(&request) {
+        Ok(plan) => plan.fee,
+        Err(_) => fallback_fee,
+    };
+    drop(node_state);
+
+    // el cambio del entry vuelve a bloquear el node state para mostrar el fee por byte
+    fee_entry.set_text(&fee.to_string());
+    Ok(())
+}
+
+/// Muestra al lado de cada monto y del fee su equivalente en moneda fiat, con la fecha de la cotizacion en el tooltip.
+/// Si no hay cotizacion o el monto no es valido, limpia el label. Es solo informativo: los montos se envian en satoshis.
+fn update_fiat_labels(
+    builder: &gtk::Builder,
+    node_state_ref: &Arc<Mutex<NodeState>>,
+) -> Result<(), CustomError> {
+    let rate = node_state_ref.lock()?.current_rate();
+    let now = get_current_timestamp()?;
+
+    let mut fields: Vec<(String, String)> = (0..count_outputs(builder))
+        .map(|i| (format!("output-{}-value", i), format!("output-{}-fiat", i)))
+        .collect();
+    fields.push((String::from("tx-fee"), String::from("tx-fee-fiat")));
+
+    for (entry_id, label_id) in fields {
+        let entry: gtk::Entry = get_gui_element(builder, &entry_id)?;
+        let label: gtk::Label = get_gui_element(builder, &label_id)?;
+        match (entry.text().to_string().parse::<u64>(), &rate) {
+            (Ok(satoshis), Some(rate)) => {
+                label.set_text(&format_fiat(satoshis as i64, rate));
+                label.set_tooltip_text(Some(&format_rate(rate, now)));
+            }
+            _ => {
+                label.set_text("");
+                label.set_tooltip_text(None);
+            }
+        }
+    }
+    Ok(())
 }
 
-fn get_output(builder: &gtk::Builder, i: u8) -> Result<Option<(String, u64)>, CustomError> {
+/// Actualiza los equivalentes en moneda fiat cada vez que cambia el monto del entry.
+fn connect_fiat_update(
+    entry: &gtk::Entry,
+    builder: &gtk::Builder,
+    node_state_ref: &Arc<Mutex<NodeState>>,
+    logger_sender: &Sender<Log>,
+) {
+    let builder = builder.clone();
+    let node_state_ref = node_state_ref.clone();
+    let logger_sender = logger_sender.clone();
+    entry.connect_changed(move |_| {
+        if let Err(error) = update_fiat_labels(&builder, &node_state_ref) {
+            send_log(&logger_sender, Log::Error(error));
+        }
+    });
+}
+
+/// Agrega una fila de receiver debajo de la ultima y registra sus entries y el label del monto en fiat en el builder.
+fn add_output_row(
+    builder: &gtk::Builder,
+    node_state_ref: &Arc<Mutex<NodeState>>,
+    logger_sender: &Sender<Log>,
+) -> Result<(), CustomError> {
+    let grid: gtk::Grid = get_gui_element(builder, "transfer")?;
+    let row = count_outputs(builder);
+    grid.insert_row(row as i32);
+
+    let label = gtk::Label::new(Some(&format!("Receiver {}", row + 1)));
+    let pubkey = gtk::Entry::new();
+    pubkey.set_placeholder_text(Some("PubKey"));
+    let value = gtk::Entry::new();
+    value.set_placeholder_text(Some("Value (Sat)"));
+    let fiat = gtk::Label::new(None);
+    fiat.set_halign(gtk::Align::Start);
+
+    grid.attach(&label, 0, row as i32, 1, 1);
+    grid.attach(&pubkey, 1, row as i32, 2, 1);
+    grid.attach(&value, 3, row as i32, 2, 1);
+    grid.attach(&fiat, 5, row as i32, 1, 1);
+    builder.expose_object(&format!("output-{}-pubkey", row), &pubkey);
+    builder.expose_object(&format!("output-{}-value", row), &value);
+    builder.expose_object(&format!("output-{}-fiat", row), &fiat);
+    connect_fiat_update(&value, builder, node_state_ref, logger_sender);
+    grid.show_all();
+    Ok(())
+}
+
+fn get_output(builder: &gtk::Builder, i: usize) -> Result<Option<(String, u64)>, CustomError> {
     let pubkey: gtk::Entry = get_gui_element(builder, &format!("output-{}-pubkey", i))?;
     let value: gtk::Entry = get_gui_element(builder, &format!("output-{}-value", i))?;
 
     if pubkey.text().to_string().is_empty() && value.text().to_string().is_empty() {
         return Ok(None);
     }
-    if pubkey.text().to_string().len() != 34 || value.text().to_string().is_empty() {
+    let address = pubkey.text().to_string();
+    if (address.len() != 34 && !is_segwit_address(&address)) || value.text().to_string().is_empty()
+    {
         return Err(CustomError::InvalidTransferFields);
     }
 
@@ -156,3 +466,20 @@ fn get_output(builder: &gtk::Builder, i: u8) -> Result<Option<(String, u64)>, Cu
 
     Ok(Some((pubkey.text().to_string(), value)))
 }
+
+/// Lee las opciones avanzadas de la transaccion: el lock time (altura o timestamp, vacio si no tiene)
+/// y si se puede reemplazar por otra con mayor fee (RBF).
+fn get_tx_options(builder: &gtk::Builder) -> Result<TransactionOptions, CustomError> {
+    let lock_time_entry: gtk::Entry = get_gui_element(builder, "tx-lock-time")?;
+    let replaceable_check: gtk::CheckButton = get_gui_element(builder, "tx-replaceable")?;
+
+    let lock_time = match lock_time_entry.text().trim() {
+        "" => None,
+        text => Some(text.parse::<u32>().map_err(|_| CustomError::InvalidValue)?),
+    };
+
+    Ok(TransactionOptions {
+        lock_time,
+        replaceable: replaceable_check.is_active(),
+    })
+}
@@ -1,7 +1,7 @@
 use std::sync::{mpsc::Sender, Arc, Mutex, MutexGuard};
 
 use gtk::{
-    traits::{ContainerExt, LabelExt, WidgetExt},
+    traits::{ContainerExt, LabelExt, SpinButtonExt, WidgetExt},
     ListBox,
 };
 
@@ -9,18 +9,23 @@ use crate::{
     error::CustomError,
     logger::{send_log, Log},
     node_state::NodeState,
+    send_many::CoinMaturity,
     states::utxo_state::UTXOValue,
     structs::outpoint::OutPoint,
 };
 
 use super::{
     init::{get_gui_element, GUIEvents},
-    table_cells::{merkle_proof_button, time_label, tx_hash_label, value_label},
+    tab::{GUIContext, GUITab},
+    table_cells::{
+        confirmations_label, merkle_proof_button, time_label, tx_hash_label, value_label,
+    },
 };
 
 #[derive(Clone)]
 /// GUIUtxo es una estructura que contiene los elementos de la interfaz grafica
-/// relacionados con los UTXO de una wallet y los lista (tx hash, fecha de creacion, valor y pedir el merkle proof de esa tx).
+/// relacionados con los UTXO de una wallet y los lista (tx hash, fecha de creacion, valor, confirmaciones y pedir el merkle proof de esa tx).
+/// Las UTXO que todavia no se pueden gastar (por tener menos confirmaciones que el minimo elegido o ser una coinbase inmadura) se marcan en la columna de confirmaciones.
 /// Los elementos son:
 /// - builder: Builder de gtk.
 /// - node_state_ref: Referencia al estado del nodo.
@@ -31,14 +36,16 @@ pub struct GUIUtxo {
     pub node_state_ref: Arc<Mutex<NodeState>>,
 }
 
-impl GUIUtxo {
+impl GUITab for GUIUtxo {
     /// Maneja los GUIEvents recibidos y hace las acciones acorde a cada envento.
     /// Para WalletChanged: Actualiza la lista de UTXO.
-    /// Para WalletsUpdated: Actualiza la lista de UTXO.
-    pub fn handle_events(&mut self, message: &GUIEvents) {
+    /// Para WalletsUpdated: Actualiza la lista de UTXO si la wallet activa fue afectada.
+    /// Para NewHeaders: Actualiza las confirmaciones de la lista de UTXO si hay una wallet activa.
+    fn handle_events(&mut self, message: &GUIEvents) {
         let result = match message {
             GUIEvents::WalletChanged => self.update_utxo(),
-            GUIEvents::WalletsUpdated => self.update_utxo(),
+            GUIEvents::WalletsUpdated { affected } => self.handle_wallets_updated(affected),
+            GUIEvents::NewHeaders { .. } => self.handle_new_headers(),
             _ => Ok(()),
         };
 
@@ -47,20 +54,85 @@ impl GUIUtxo {
         }
     }
 
+    /// Establece los callbacks de los elementos de la interfaz grafica.
+    /// Para el minimo de confirmaciones: Lo cambia en el nodo y actualiza la lista de UTXO.
+    fn handle_interactivity(&self) -> Result<(), CustomError> {
+        let min_confirmations: gtk::SpinButton =
+            get_gui_element(&self.builder, "min-confirmations")?;
+        let current = self.node_state_ref.lock()?.get_min_spend_confirmations();
+        min_confirmations.set_value(current as f64);
+
+        let gui_utxo = self.clone();
+        min_confirmations.connect_value_changed(move |spin_button| {
+            if let Err(error) = gui_utxo.change_min_confirmations(spin_button.value_as_int() as u32)
+            {
+                send_log(&gui_utxo.logger_sender, Log::Error(error));
+            }
+        });
+        Ok(())
+    }
+}
+
+impl GUIUtxo {
+    /// Crea la lista de UTXO con el contexto de la interfaz grafica.
+    pub fn new(context: &GUIContext) -> Self {
+        Self {
+            builder: context.builder.clone(),
+            node_state_ref: context.node_state_ref.clone(),
+            logger_sender: context.logger_sender.clone(),
+        }
+    }
+
+    fn change_min_confirmations(&self, min_confirmations: u32) -> Result<(), CustomError> {
+        let mut node_state = self.node_state_ref.lock()?;
+        node_state.set_min_spend_confirmations(min_confirmations)?;
+        let has_active_wallet = node_state.get_active_wallet().is_some();
+        drop(node_state);
+
+        if has_active_wallet {
+            self.update_utxo()?;
+        }
+        Ok(())
+    }
+
+    fn handle_new_headers(&self) -> Result<(), CustomError> {
+        let has_active_wallet = self.node_state_ref.lock()?.get_active_wallet().is_some();
+        if has_active_wallet {
+            self.update_utxo()?;
+        }
+        Ok(())
+    }
+
+    fn handle_wallets_updated(&self, affected: &[String]) -> Result<(), CustomError> {
+        let node_state = self.node_state_ref.lock()?;
+        let active_affected = node_state
+            .get_active_wallet()
+            .map_or(false, |wallet| affected.contains(&wallet.pubkey));
+        drop(node_state);
+
+        if active_affected {
+            self.update_utxo()?;
+        }
+        Ok(())
+    }
+
     fn update_utxo(&self) -> Result<(), CustomError> {
         let utxo_list_box: gtk::ListBox = get_gui_element(&self.builder, "utxo-list")?;
         let node_state_ref_clone = self.node_state_ref.clone();
         let node_state = node_state_ref_clone.lock()?;
+        let min_confirmations = node_state.get_min_spend_confirmations();
         let wallet_utxo = get_wallet_sorted_utxo(node_state)?;
 
         reset_table(&utxo_list_box);
-        for (out_point, utxo_value) in wallet_utxo.iter() {
+        for (out_point, utxo_value, confirmations) in wallet_utxo.iter() {
             let utxo_row = gtk::ListBoxRow::new();
             let utxo_box = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+            let maturity = CoinMaturity::new(utxo_value, *confirmations, min_confirmations);
 
             utxo_box.add(&tx_hash_label(out_point.hash.clone()));
             utxo_box.add(&time_label(utxo_value.block_timestamp));
             utxo_box.add(&value_label(utxo_value.tx_out.value as i64));
+            utxo_box.add(&confirmations_label(*confirmations, maturity));
             utxo_box.add(&merkle_proof_button(
                 Some(utxo_value.block_hash.clone()),
                 out_point.hash.clone(),
@@ -78,8 +150,11 @@ impl GUIUtxo {
 
 fn get_wallet_sorted_utxo(
     node_state: MutexGuard<'_, NodeState>,
-) -> Result<Vec<(OutPoint, UTXOValue)>, CustomError> {
-    let mut wallet_utxo = node_state.get_active_wallet_utxo()?;
+) -> Result<Vec<(OutPoint, UTXOValue, u32)>, CustomError> {
+    if node_state.get_active_wallet().is_none() {
+        return Ok(vec![]);
+    }
+    let mut wallet_utxo = node_state.get_active_wallet_utxo_with_confirmations()?;
     wallet_utxo.sort_by(|a, b| {
         if a.1.block_timestamp > b.1.block_timestamp {
             std::cmp::Ordering::Less
@@ -99,6 +174,7 @@ fn reset_table(list_box: &ListBox) {
     let tx_hash_label = gtk::Label::new(None);
     let time_label = gtk::Label::new(None);
     let value_label = gtk::Label::new(None);
+    let confirmations_label = gtk::Label::new(None);
     let action_label = gtk::Label::new(None);
 
     tx_hash_label.set_expand(true);
@@ -110,12 +186,16 @@ fn reset_table(list_box: &ListBox) {
     value_label.set_width_request(128);
     value_label.set_markup("<b>Value</b>");
 
+    confirmations_label.set_width_request(160);
+    confirmations_label.set_markup("<b>Confirmations</b>");
+
     action_label.set_width_request(128);
     action_label.set_markup("<b>Action</b>");
 
     utxo_box.add(&tx_hash_label);
     utxo_box.add(&time_label);
     utxo_box.add(&value_label);
+    utxo_box.add(&confirmations_label);
     utxo_box.add(&action_label);
 
     utxo_row.add(&utxo_box);
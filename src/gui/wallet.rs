@@ -1,33 +1,74 @@
-use std::sync::{mpsc, Arc, Mutex};
+use std::{
+    collections::HashSet,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
 
-use gtk::traits::{ButtonExt, ComboBoxExt, ComboBoxTextExt, DialogExt, EntryExt, WidgetExt};
+use gtk::{
+    glib::Sender,
+    traits::{
+        ButtonExt, ComboBoxExt, ComboBoxTextExt, DialogExt, EntryExt, FileChooserExt, LabelExt,
+        MessageDialogExt, ToggleButtonExt, WidgetExt,
+    },
+};
 
 use crate::{
     error::CustomError,
     logger::{send_log, Log},
     node_state::NodeState,
+    wallet_import::{import_address_file, ImportSummary},
 };
 
-use super::init::get_gui_element;
+use super::{
+    init::{get_gui_element, GUIEvents},
+    tab::{GUIContext, GUITab},
+};
 
 #[derive(Clone)]
 /// GUIWallet es una estructura que contiene los elementos de la interfaz grafica
-/// relacionados con la billetera. Permite agregar y cambiar de wallet y muestra la wallet activa.
+/// relacionados con la billetera. Permite agregar, cambiar, renombrar y quitar wallets, importar direcciones watch-only y muestra la wallet activa.
+/// Los elementos son:
+/// - builder: Builder de gtk.
+/// - node_state_ref: Referencia al estado del nodo.
+/// - logger_sender: Sender para enviar logs al logger.
+/// - gui_sender: Sender para enviar GUIEvents desde el thread que importa direcciones.
+/// - wallets_with_activity: Public keys de las wallets no activas con movimientos que el usuario todavia no vio.
 pub struct GUIWallet {
     pub builder: gtk::Builder,
     pub node_state_ref: Arc<Mutex<NodeState>>,
     pub logger_sender: mpsc::Sender<Log>,
+    pub gui_sender: Sender<GUIEvents>,
+    pub wallets_with_activity: HashSet<String>,
 }
 
-impl GUIWallet {
-    /// Inicializa la los datos del combobox para seleccionar wallet.
-    pub fn initialize(&self) -> Result<(), CustomError> {
-        let select_wallet_cb: gtk::ComboBoxText =
-            get_gui_element(&self.builder, "select-wallet-combo-box")?;
+impl GUITab for GUIWallet {
+    /// Carga el combobox para seleccionar wallet y avisa si se restauro la wallet activa (ver notify_restored_wallet).
+    fn initialize(&self) -> Result<(), CustomError> {
+        self.update_combo_box()?;
+        self.notify_restored_wallet()
+    }
 
-        update_wallet_combo_box(self.node_state_ref.clone(), select_wallet_cb)?;
+    /// Maneja los GUIEvents recibidos y hace las acciones acorde a cada envento.
+    /// Para WalletChanged: Quita la marca de actividad de la wallet activa.
+    /// Para WalletsUpdated: Marca en el combobox las wallets afectadas que no son la activa.
+    /// Para WalletListChanged: Recarga el combobox con los nombres nuevos y sin las wallets quitadas.
+    /// Para AddressImportProgress: Muestra el progreso de la importacion de direcciones.
+    /// Para AddressesImported: Recarga el combobox y muestra el resumen de la importacion.
+    fn handle_events(&mut self, message: &GUIEvents) {
+        let result = match message {
+            GUIEvents::WalletChanged => self.handle_wallet_changed(),
+            GUIEvents::WalletsUpdated { affected } => self.handle_wallets_updated(affected),
+            GUIEvents::WalletListChanged => self.handle_wallet_list_changed(),
+            GUIEvents::AddressImportProgress { validated, total } => {
+                self.handle_import_progress(*validated, *total)
+            }
+            GUIEvents::AddressesImported { summary } => self.handle_addresses_imported(summary),
+            _ => Ok(()),
+        };
 
-        Ok(())
+        if let Err(error) = result {
+            send_log(&self.logger_sender, Log::Error(error));
+        }
     }
 
     /// Agrega los callbacks a los elementos de la interfaz grafica.
@@ -36,16 +77,149 @@ impl GUIWallet {
     /// - handle_add_wallet_submit: Agrega la wallet ingresada a la lista de wallets.
     /// - cancel_add_wallet: Cancela el agregado de una wallet.
     /// - handle_change_wallet: Cambia la wallet activa.
+    /// - handle_rename_wallet: Cambia el nombre de la wallet activa.
+    /// - handle_delete_wallet: Quita la wallet activa, previa confirmacion.
+    /// - handle_import_addresses: Importa como watch-only las direcciones de un archivo elegido por el usuario.
+    /// - handle_unlock_wallets: Desbloquea las wallets con el passphrase ingresado.
     ///
-    pub fn handle_interactivity(&self) -> Result<(), CustomError> {
+    fn handle_interactivity(&self) -> Result<(), CustomError> {
         self.handle_add_wallet_trigger()?;
         self.handle_add_wallet_submit()?;
         self.cancel_add_wallet()?;
         self.handle_change_wallet()?;
+        self.handle_rename_wallet()?;
+        self.handle_delete_wallet()?;
+        self.handle_import_addresses()?;
+        self.handle_unlock_wallets()?;
+
+        Ok(())
+    }
+
+    /// Pide el passphrase de las wallets bloqueadas, una vez que el dialogo ya tiene sus callbacks.
+    fn on_ready(&self) -> Result<(), CustomError> {
+        self.prompt_unlock()
+    }
+}
+
+impl GUIWallet {
+    /// Crea el selector de wallets con el contexto de la interfaz grafica.
+    pub fn new(context: &GUIContext) -> Self {
+        Self {
+            builder: context.builder.clone(),
+            node_state_ref: context.node_state_ref.clone(),
+            logger_sender: context.logger_sender.clone(),
+            gui_sender: context.gui_sender.clone(),
+            wallets_with_activity: HashSet::new(),
+        }
+    }
+
+    /// Carga los datos del combobox para seleccionar wallet y vuelve a seleccionar la wallet activa.
+    fn update_combo_box(&self) -> Result<(), CustomError> {
+        let select_wallet_cb: gtk::ComboBoxText =
+            get_gui_element(&self.builder, "select-wallet-combo-box")?;
+
+        update_wallet_combo_box(
+            self.node_state_ref.clone(),
+            select_wallet_cb.clone(),
+            &self.wallets_with_activity,
+        )?;
+        // seleccionar la wallet dispara switch_active_wallet, que bloquea el node state
+        select_wallet_cb.set_active_id(self.active_pubkey()?.as_deref());
+
+        Ok(())
+    }
+
+    /// Si el nodo restauro la wallet activa de la ultima ejecucion, envia WalletChanged una unica vez
+    /// para que las vistas que dependen de la wallet activa se carguen al iniciar.
+    fn notify_restored_wallet(&self) -> Result<(), CustomError> {
+        if self.active_pubkey()?.is_some() {
+            self.gui_sender.send(GUIEvents::WalletChanged)?;
+        }
+        Ok(())
+    }
+
+    /// Si las wallets tienen passphrase, muestra el boton para desbloquearlas y pide el passphrase una unica vez al iniciar.
+    fn prompt_unlock(&self) -> Result<(), CustomError> {
+        if !self.node_state_ref.lock()?.are_wallets_locked() {
+            return Ok(());
+        }
+        let trigger: gtk::Button = get_gui_element(&self.builder, "unlock-wallets-button")?;
+        let dialog: gtk::Dialog = get_gui_element(&self.builder, "unlock-wallets-dialog")?;
+        trigger.show();
+        dialog.run();
+        dialog.hide();
+        Ok(())
+    }
+
+    fn handle_wallet_changed(&mut self) -> Result<(), CustomError> {
+        let Some(active_pubkey) = self.active_pubkey()? else { return Ok(()) };
+        if self.wallets_with_activity.remove(&active_pubkey) {
+            self.update_combo_box()?;
+        }
+        Ok(())
+    }
+
+    fn handle_wallets_updated(&mut self, affected: &[String]) -> Result<(), CustomError> {
+        let active_pubkey = self.active_pubkey()?;
+
+        let mut badges_changed = false;
+        for pubkey in affected {
+            if Some(pubkey) != active_pubkey.as_ref() {
+                badges_changed |= self.wallets_with_activity.insert(pubkey.clone());
+            }
+        }
 
+        if badges_changed {
+            self.update_combo_box()?;
+        }
         Ok(())
     }
 
+    fn handle_wallet_list_changed(&mut self) -> Result<(), CustomError> {
+        let node_state = self.node_state_ref.lock()?;
+        self.wallets_with_activity.retain(|pubkey| {
+            node_state
+                .get_wallets()
+                .iter()
+                .any(|wallet| wallet.pubkey == *pubkey)
+        });
+        drop(node_state);
+        self.update_combo_box()
+    }
+
+    fn handle_import_progress(&self, validated: usize, total: usize) -> Result<(), CustomError> {
+        let label: gtk::Label = get_gui_element(&self.builder, "import-progress-label")?;
+        label.set_text(&format!("Importing addresses: {}/{}", validated, total));
+        Ok(())
+    }
+
+    fn handle_addresses_imported(&self, summary: &ImportSummary) -> Result<(), CustomError> {
+        let label: gtk::Label = get_gui_element(&self.builder, "import-progress-label")?;
+        let trigger: gtk::Button = get_gui_element(&self.builder, "import-addresses-button")?;
+        let dialog: gtk::MessageDialog = get_gui_element(&self.builder, "import-summary-dialog")?;
+        label.set_text("");
+        trigger.set_sensitive(true);
+
+        self.update_combo_box()?;
+        if summary.imported.is_empty() && summary.skipped.is_empty() {
+            return Ok(());
+        }
+
+        dialog.set_secondary_text(Some(summary.describe().as_str()));
+        dialog.run();
+        dialog.hide();
+        Ok(())
+    }
+
+    fn active_pubkey(&self) -> Result<Option<String>, CustomError> {
+        let node_state = self.node_state_ref.lock()?;
+        let active_pubkey = node_state
+            .get_active_wallet()
+            .map(|wallet| wallet.pubkey.clone());
+        drop(node_state);
+        Ok(active_pubkey)
+    }
+
     fn handle_change_wallet(&self) -> Result<(), CustomError> {
         let select_wallet_cb: gtk::ComboBoxText =
             get_gui_element(&self.builder, "select-wallet-combo-box")?;
@@ -63,11 +237,165 @@ impl GUIWallet {
         Ok(())
     }
 
+    /// Cambia el nombre de la wallet activa por el ingresado en el dialogo.
+    /// Si el nombre no es valido lo indica en el dialogo, que sigue abierto para corregirlo.
+    fn handle_rename_wallet(&self) -> Result<(), CustomError> {
+        let trigger: gtk::Button = get_gui_element(&self.builder, "rename-wallet-button")?;
+        let dialog: gtk::Dialog = get_gui_element(&self.builder, "rename-wallet-dialog")?;
+        let action: gtk::Button = get_gui_element(&self.builder, "rename-wallet-action")?;
+        let cancel: gtk::Button = get_gui_element(&self.builder, "rename-wallet-cancel")?;
+        let name: gtk::Entry = get_gui_element(&self.builder, "rename-wallet-name")?;
+        let error_label: gtk::Label = get_gui_element(&self.builder, "rename-wallet-error")?;
+        let node_state_ref = self.node_state_ref.clone();
+        let logger_sender = self.logger_sender.clone();
+
+        let trigger_dialog = dialog.clone();
+        let trigger_name = name.clone();
+        let trigger_node_state_ref = node_state_ref.clone();
+        trigger.connect_clicked(move |_| {
+            let active_name = match trigger_node_state_ref.lock() {
+                Ok(node_state) => node_state
+                    .get_active_wallet()
+                    .map(|wallet| wallet.name.clone()),
+                Err(_) => None,
+            };
+            let Some(active_name) = active_name else { return };
+            trigger_name.set_text(&active_name);
+            trigger_dialog.run();
+            trigger_dialog.hide();
+        });
+
+        let action_dialog = dialog.clone();
+        let action_error_label = error_label.clone();
+        action.connect_clicked(move |_| {
+            let result = match node_state_ref.lock() {
+                Ok(mut node_state) => match node_state
+                    .get_active_wallet()
+                    .map(|wallet| wallet.pubkey.clone())
+                {
+                    Some(pubkey) => node_state.rename_wallet(&pubkey, name.text().as_str()),
+                    None => Err(CustomError::WalletNotFound),
+                },
+                Err(_) => Err(CustomError::CannotLockGuard),
+            };
+            match result {
+                Ok(()) => {
+                    action_error_label.set_text("");
+                    action_dialog.hide();
+                }
+                Err(CustomError::Validation(message)) => action_error_label.set_text(&message),
+                Err(error) => send_log(&logger_sender, Log::Error(error)),
+            }
+        });
+
+        cancel.connect_clicked(move |_| {
+            error_label.set_text("");
+            dialog.hide();
+        });
+
+        Ok(())
+    }
+
+    /// Quita la wallet activa despues de confirmarlo en un dialogo. El historial de las demas wallets no cambia.
+    fn handle_delete_wallet(&self) -> Result<(), CustomError> {
+        let trigger: gtk::Button = get_gui_element(&self.builder, "delete-wallet-button")?;
+        let dialog: gtk::MessageDialog = get_gui_element(&self.builder, "delete-wallet-dialog")?;
+        let node_state_ref = self.node_state_ref.clone();
+        let logger_sender = self.logger_sender.clone();
+
+        trigger.connect_clicked(move |_| {
+            let active_wallet = match node_state_ref.lock() {
+                Ok(node_state) => node_state
+                    .get_active_wallet()
+                    .map(|wallet| (wallet.pubkey.clone(), wallet.name.clone())),
+                Err(_) => None,
+            };
+            let Some((pubkey, name)) = active_wallet else { return };
+
+            dialog.set_text(Some(format!("Delete the wallet {name}?").as_str()));
+            let response = dialog.run();
+            dialog.hide();
+            if response != gtk::ResponseType::Yes {
+                return;
+            }
+
+            let result = match node_state_ref.lock() {
+                Ok(mut node_state) => node_state.remove_wallet(&pubkey),
+                Err(_) => Err(CustomError::CannotLockGuard),
+            };
+            if let Err(error) = result {
+                send_log(&logger_sender, Log::Error(error));
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Al elegir un archivo, la validacion y el registro de las direcciones se hacen en otro thread
+    /// para no congelar la interfaz, que recibe el progreso y el resumen como GUIEvents.
+    fn handle_import_addresses(&self) -> Result<(), CustomError> {
+        let trigger: gtk::Button = get_gui_element(&self.builder, "import-addresses-button")?;
+        let dialog: gtk::FileChooserDialog =
+            get_gui_element(&self.builder, "import-addresses-dialog")?;
+        let node_state_ref = self.node_state_ref.clone();
+        let logger_sender = self.logger_sender.clone();
+        let gui_sender = self.gui_sender.clone();
+
+        trigger.connect_clicked(move |trigger| {
+            let response = dialog.run();
+            dialog.hide();
+            if response != gtk::ResponseType::Accept {
+                return;
+            }
+            let Some(path) = dialog.filename() else { return };
+            trigger.set_sensitive(false);
+
+            let node_state_ref = node_state_ref.clone();
+            let logger_sender = logger_sender.clone();
+            let gui_sender = gui_sender.clone();
+            thread::spawn(move || {
+                let path = path.to_string_lossy().to_string();
+                let summary = import_address_file(&path, &node_state_ref, |validated, total| {
+                    let _ = gui_sender.send(GUIEvents::AddressImportProgress { validated, total });
+                })
+                .unwrap_or_else(|error| {
+                    send_log(&logger_sender, Log::Error(error));
+                    ImportSummary::default()
+                });
+
+                if gui_sender
+                    .send(GUIEvents::AddressesImported { summary })
+                    .is_err()
+                {
+                    send_log(
+                        &logger_sender,
+                        Log::Error(CustomError::CannotSendMessageToChannel(None)),
+                    );
+                }
+            });
+        });
+
+        Ok(())
+    }
+
     fn handle_add_wallet_trigger(&self) -> Result<(), CustomError> {
         let trigger: gtk::Button = get_gui_element(&self.builder, "add-wallet-button")?;
         let dialog: gtk::Dialog = get_gui_element(&self.builder, "add-wallet-dialog")?;
+        let passphrase_label: gtk::Label =
+            get_gui_element(&self.builder, "add-wallet-passphrase-label")?;
+        let passphrase: gtk::Entry = get_gui_element(&self.builder, "add-wallet-passphrase")?;
+        let node_state_ref = self.node_state_ref.clone();
 
         trigger.connect_clicked(move |_| {
+            // el passphrase se pide solo al crear la primera wallet
+            let first_wallet = match node_state_ref.lock() {
+                Ok(node_state) => {
+                    node_state.get_wallets().is_empty() && !node_state.are_wallets_encrypted()
+                }
+                Err(_) => false,
+            };
+            passphrase_label.set_visible(first_wallet);
+            passphrase.set_visible(first_wallet);
             dialog.run();
             dialog.hide();
         });
@@ -81,6 +409,8 @@ impl GUIWallet {
         let name: gtk::Entry = get_gui_element(&self.builder, "add-wallet-name")?;
         let pubkey: gtk::Entry = get_gui_element(&self.builder, "add-wallet-pubkey")?;
         let privkey: gtk::Entry = get_gui_element(&self.builder, "add-wallet-privkey")?;
+        let xpub: gtk::CheckButton = get_gui_element(&self.builder, "add-wallet-xpub")?;
+        let passphrase: gtk::Entry = get_gui_element(&self.builder, "add-wallet-passphrase")?;
         let wallet_combobox: gtk::ComboBoxText =
             get_gui_element(&self.builder, "select-wallet-combo-box")?;
         let node_state_ref = self.node_state_ref.clone();
@@ -97,27 +427,45 @@ impl GUIWallet {
                     return;
                 }
             };
-            if let Err(error) = node_state.append_wallet(
-                name.text().to_string(),
-                pubkey.text().to_string(),
-                privkey.text().to_string(),
-            ) {
-                send_log(&logger_sender, Log::Error(error));
-                drop(node_state);
-                return;
+            if passphrase.is_visible() && !passphrase.text().is_empty() {
+                if let Err(error) = node_state.set_wallets_passphrase(passphrase.text().as_str()) {
+                    send_log(&logger_sender, Log::Error(error));
+                    drop(node_state);
+                    return;
+                }
             }
+            // con Import xpub la public key es un xpub, y sin private key la wallet se agrega como watch-only
+            let result = match (xpub.is_active(), privkey.text().is_empty()) {
+                (true, _) => {
+                    node_state.append_xpub_wallet(name.text().to_string(), pubkey.text().as_str())
+                }
+                (false, true) => node_state
+                    .append_watch_only_wallet(name.text().to_string(), pubkey.text().to_string())
+                    .map(|_| pubkey.text().to_string()),
+                (false, false) => node_state
+                    .append_wallet(
+                        name.text().to_string(),
+                        pubkey.text().to_string(),
+                        privkey.text().to_string(),
+                    )
+                    .map(|_| pubkey.text().to_string()),
+            };
+            let wallet_pubkey = match result {
+                Ok(wallet_pubkey) => wallet_pubkey,
+                Err(error) => {
+                    send_log(&logger_sender, Log::Error(error));
+                    drop(node_state);
+                    return;
+                }
+            };
             drop(node_state);
 
-            update_wallet_combo_box(node_state_ref.clone(), wallet_combobox.clone())
-                .unwrap_or_else(|_| {
-                    send_log(
-                        &logger_sender,
-                        Log::Message("Error updating combo box".to_string()),
-                    )
-                });
+            wallet_combobox.append(Some(wallet_pubkey.as_str()), name.text().as_str());
             name.set_text("");
             pubkey.set_text("");
             privkey.set_text("");
+            xpub.set_active(false);
+            passphrase.set_text("");
             dialog.hide();
         });
 
@@ -130,11 +478,64 @@ impl GUIWallet {
         let name: gtk::Entry = get_gui_element(&self.builder, "add-wallet-name")?;
         let pubkey: gtk::Entry = get_gui_element(&self.builder, "add-wallet-pubkey")?;
         let privkey: gtk::Entry = get_gui_element(&self.builder, "add-wallet-privkey")?;
+        let xpub: gtk::CheckButton = get_gui_element(&self.builder, "add-wallet-xpub")?;
+        let passphrase: gtk::Entry = get_gui_element(&self.builder, "add-wallet-passphrase")?;
 
         cancel.connect_clicked(move |_| {
             name.set_text("");
             pubkey.set_text("");
             privkey.set_text("");
+            xpub.set_active(false);
+            passphrase.set_text("");
+            dialog.hide();
+        });
+
+        Ok(())
+    }
+
+    /// Desbloquea las wallets con el passphrase del dialogo.
+    /// Si el passphrase es incorrecto lo indica en el dialogo, que sigue abierto para volver a intentarlo.
+    fn handle_unlock_wallets(&self) -> Result<(), CustomError> {
+        let trigger: gtk::Button = get_gui_element(&self.builder, "unlock-wallets-button")?;
+        let dialog: gtk::Dialog = get_gui_element(&self.builder, "unlock-wallets-dialog")?;
+        let action: gtk::Button = get_gui_element(&self.builder, "unlock-wallets-action")?;
+        let cancel: gtk::Button = get_gui_element(&self.builder, "unlock-wallets-cancel")?;
+        let passphrase: gtk::Entry = get_gui_element(&self.builder, "unlock-wallets-passphrase")?;
+        let error_label: gtk::Label = get_gui_element(&self.builder, "unlock-wallets-error")?;
+        let node_state_ref = self.node_state_ref.clone();
+        let logger_sender = self.logger_sender.clone();
+
+        let trigger_dialog = dialog.clone();
+        trigger.connect_clicked(move |_| {
+            trigger_dialog.run();
+            trigger_dialog.hide();
+        });
+
+        let action_dialog = dialog.clone();
+        let action_passphrase = passphrase.clone();
+        let action_error_label = error_label.clone();
+        action.connect_clicked(move |_| {
+            let result = match node_state_ref.lock() {
+                Ok(mut node_state) => node_state.unlock_wallets(action_passphrase.text().as_str()),
+                Err(_) => Err(CustomError::CannotLockGuard),
+            };
+            match result {
+                Ok(()) => {
+                    action_passphrase.set_text("");
+                    action_error_label.set_text("");
+                    trigger.hide();
+                    action_dialog.hide();
+                }
+                Err(CustomError::InvalidPassphrase) => {
+                    action_error_label.set_text("Invalid passphrase, try again");
+                }
+                Err(error) => send_log(&logger_sender, Log::Error(error)),
+            }
+        });
+
+        cancel.connect_clicked(move |_| {
+            passphrase.set_text("");
+            error_label.set_text("");
             dialog.hide();
         });
 
@@ -142,34 +543,50 @@ impl GUIWallet {
     }
 }
 
+/// Cambia la wallet activa por la seleccionada en el combobox.
+/// Si el cambio falla, vuelve a sincronizar la seleccion del combobox con la wallet activa del nodo.
+/// El lock del node state se libera antes de modificar el combobox, ya que eso vuelve a disparar este handler.
 fn switch_active_wallet(
     node_state_ref: &Arc<Mutex<NodeState>>,
     builder: &gtk::Builder,
 ) -> Result<(), CustomError> {
     let select_wallet_cb: gtk::ComboBoxText = get_gui_element(builder, "select-wallet-combo-box")?;
 
-    if let Some(active_pubkey) = select_wallet_cb.active_id() {
-        let mut node_state = node_state_ref.lock()?;
-        node_state.change_wallet(active_pubkey.to_string())?;
-        if let Some(active_wallet) = node_state.get_active_wallet() {
-            select_wallet_cb.set_active_id(Some(active_wallet.pubkey.as_str()));
-        } else {
-            select_wallet_cb.set_active_id(None);
+    let Some(selected_pubkey) = select_wallet_cb.active_id() else { return Ok(()) };
+
+    let mut node_state = node_state_ref.lock()?;
+    if let Some(active_wallet) = node_state.get_active_wallet() {
+        if active_wallet.pubkey == selected_pubkey.as_str() {
+            return Ok(());
         }
-        drop(node_state);
     }
+    let result = node_state.change_wallet(selected_pubkey.to_string());
+    let active_pubkey = node_state
+        .get_active_wallet()
+        .map(|wallet| wallet.pubkey.clone());
+    drop(node_state);
 
-    Ok(())
+    if active_pubkey.as_deref() != Some(selected_pubkey.as_str()) {
+        select_wallet_cb.set_active_id(active_pubkey.as_deref());
+    }
+
+    result
 }
 
+/// Carga las wallets del nodo en el combobox, marcando con un punto las que tienen actividad sin ver.
 fn update_wallet_combo_box(
     node_state_ref: Arc<Mutex<NodeState>>,
     select_wallet_cb: gtk::ComboBoxText,
+    wallets_with_activity: &HashSet<String>,
 ) -> Result<(), CustomError> {
     let node_state = node_state_ref.lock()?;
     select_wallet_cb.remove_all();
     for wallet in node_state.get_wallets() {
-        select_wallet_cb.append(Some(wallet.pubkey.as_str()), wallet.name.as_str());
+        let name = match wallets_with_activity.contains(&wallet.pubkey) {
+            true => format!("{} •", wallet.name),
+            false => wallet.name.clone(),
+        };
+        select_wallet_cb.append(Some(wallet.pubkey.as_str()), name.as_str());
     }
     drop(node_state);
     Ok(())
@@ -1,43 +1,62 @@
-use gtk::traits::{GtkWindowExt, WidgetExt};
+use gtk::traits::{ButtonExt, GtkWindowExt, LabelExt, ProgressBarExt, WidgetExt};
 use std::sync::mpsc;
 
-use super::init::{get_gui_element, GUIEvents};
+use super::{
+    blocks::format_btc,
+    init::{get_gui_element, GUIEvents},
+    tab::{GUIContext, GUITab},
+};
 use crate::{
+    config_reload::ConfigReloader,
     error::CustomError,
     logger::{send_log, Log},
 };
 
 #[derive(Clone)]
 /// GUIWindow es una estructura que contiene los elementos de la interfaz grafica
-/// relacionados con la ventana principal. Muestra la ventana principal y la ventana de carga.
+/// relacionados con la ventana principal. Muestra la ventana principal y la ventana de carga, y permite recargar el config.
 /// Los elementos son:
 /// - builder: Builder de gtk.
 /// - logger_sender: Sender para enviar logs al logger.
+/// - config_reloader: ConfigReloader con el que se recarga el archivo de configuracion.
 pub struct GUIWindow {
     pub builder: gtk::Builder,
     pub logger_sender: mpsc::Sender<Log>,
+    pub config_reloader: ConfigReloader,
 }
 
-impl GUIWindow {
+impl GUITab for GUIWindow {
     /// Inicializa la ventana de carga.
-    pub fn initialize(&self) -> Result<(), CustomError> {
+    fn initialize(&self) -> Result<(), CustomError> {
         self.show_loading_window()?;
         Ok(())
     }
 
-    fn show_loading_window(&self) -> Result<(), CustomError> {
-        let load_window: gtk::Window = get_gui_element(&self.builder, "load-window")?;
-        load_window.set_default_size(600, 400);
-        load_window.set_resizable(false);
-        load_window.show_all();
-        Ok(())
-    }
-
     /// Maneja los GUIEvents recibidos y hace las acciones acorde a cada envento.
-    /// Para NodeStateReady: Muestra la ventana principal y oculta la de carga.
-    pub fn handle_events(&self, message: &GUIEvents) {
+    /// Para NodeStateReady: Muestra la ventana principal y oculta la de carga y las barras de progreso del IBD.
+    /// Para HeadersProgress y BlocksProgress: Actualiza la barra de progreso correspondiente de la ventana de carga.
+    /// Para BehindNetwork: Muestra el aviso de que el nodo esta atrasado respecto de la red.
+    /// Para CaughtUpWithNetwork: Oculta el aviso de que el nodo esta atrasado respecto de la red.
+    /// Para LargePaymentAlert: Agrega el pago al aviso de pagos grandes, que queda visible hasta que el usuario lo descarta.
+    fn handle_events(&mut self, message: &GUIEvents) {
         let result = match message {
             GUIEvents::NodeStateReady => self.handle_node_state_ready(),
+            GUIEvents::BehindNetwork { blocks_behind } => {
+                self.handle_behind_network(*blocks_behind)
+            }
+            GUIEvents::CaughtUpWithNetwork => self.handle_caught_up_with_network(),
+            GUIEvents::LargePaymentAlert {
+                wallet,
+                value,
+                tx_hash,
+                confirmed,
+            } => self.handle_large_payment_alert(wallet, *value, tx_hash, *confirmed),
+            GUIEvents::HeadersProgress { percentage, .. } => {
+                self.update_progress("headers-progress", "Headers", *percentage as usize)
+            }
+            GUIEvents::BlocksProgress { percentage, .. } => {
+                self.update_progress("blocks-progress", "Blocks", *percentage)
+            }
             _ => Ok(()),
         };
 
@@ -46,11 +65,112 @@ impl GUIWindow {
         }
     }
 
+    /// Establece los callbacks de los elementos de la interfaz grafica.
+    /// Para el boton de recargar el config: Vuelve a leer el archivo de configuracion (ver ConfigReloader::reload).
+    /// Para el boton de descartar el aviso de pagos grandes: Oculta el aviso y borra los pagos que mostraba.
+    fn handle_interactivity(&self) -> Result<(), CustomError> {
+        let reload_button: gtk::Button = get_gui_element(&self.builder, "reload-config-button")?;
+        let config_reloader = self.config_reloader.clone();
+        reload_button.connect_clicked(move |_| config_reloader.reload_and_log());
+
+        let dismiss_button: gtk::Button =
+            get_gui_element(&self.builder, "dismiss-large-payment-button")?;
+        let banner: gtk::Box = get_gui_element(&self.builder, "large-payment-banner")?;
+        let label: gtk::Label = get_gui_element(&self.builder, "large-payment-label")?;
+        dismiss_button.connect_clicked(move |_| {
+            label.set_text("");
+            banner.hide();
+        });
+        Ok(())
+    }
+}
+
+impl GUIWindow {
+    /// Crea la ventana principal con el contexto de la interfaz grafica.
+    pub fn new(context: &GUIContext) -> Self {
+        Self {
+            builder: context.builder.clone(),
+            logger_sender: context.logger_sender.clone(),
+            config_reloader: context.config_reloader.clone(),
+        }
+    }
+
+    fn show_loading_window(&self) -> Result<(), CustomError> {
+        let load_window: gtk::Window = get_gui_element(&self.builder, "load-window")?;
+        load_window.set_default_size(600, 400);
+        load_window.set_resizable(false);
+        load_window.show_all();
+        Ok(())
+    }
+
     fn handle_node_state_ready(&self) -> Result<(), CustomError> {
+        for name in ["headers-progress", "blocks-progress"] {
+            let progress_bar: gtk::ProgressBar = get_gui_element(&self.builder, name)?;
+            progress_bar.hide();
+        }
         self.show_main_window()?;
         Ok(())
     }
 
+    fn update_progress(
+        &self,
+        name: &str,
+        label: &str,
+        percentage: usize,
+    ) -> Result<(), CustomError> {
+        let progress_bar: gtk::ProgressBar = get_gui_element(&self.builder, name)?;
+        progress_bar.set_fraction(percentage.min(100) as f64 / 100.0);
+        progress_bar.set_text(Some(&format!("{} {}%", label, percentage.min(100))));
+        progress_bar.show();
+        Ok(())
+    }
+
+    fn handle_behind_network(&self, blocks_behind: usize) -> Result<(), CustomError> {
+        let banner: gtk::Label = get_gui_element(&self.builder, "behind-network-banner")?;
+        banner.set_text(&format!(
+            "Behind the network: {} blocks to catch up",
+            blocks_behind
+        ));
+        banner.show();
+        Ok(())
+    }
+
+    fn handle_caught_up_with_network(&self) -> Result<(), CustomError> {
+        let banner: gtk::Label = get_gui_element(&self.builder, "behind-network-banner")?;
+        banner.hide();
+        Ok(())
+    }
+
+    /// Agrega una linea con el pago al aviso de pagos grandes y lo muestra. Los pagos se acumulan hasta que se descarta.
+    fn handle_large_payment_alert(
+        &self,
+        wallet: &str,
+        value: u64,
+        tx_hash: &str,
+        confirmed: bool,
+    ) -> Result<(), CustomError> {
+        let banner: gtk::Box = get_gui_element(&self.builder, "large-payment-banner")?;
+        let label: gtk::Label = get_gui_element(&self.builder, "large-payment-label")?;
+        let state = match confirmed {
+            true => "confirmed",
+            false => "pending",
+        };
+        let line = format!(
+            "Large payment: wallet {} received {} ({}) in transaction {}",
+            wallet,
+            format_btc(value),
+            state,
+            tx_hash
+        );
+        let text = label.text().to_string();
+        match text.is_empty() {
+            true => label.set_text(&line),
+            false => label.set_text(&format!("{}\n{}", text, line)),
+        }
+        banner.show();
+        Ok(())
+    }
+
     fn show_main_window(&self) -> Result<(), CustomError> {
         let load_window: gtk::Window = get_gui_element(&self.builder, "load-window")?;
         load_window.hide();
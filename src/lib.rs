@@ -1,15 +1,37 @@
+pub mod bech32;
+pub mod bench_ibd;
+pub mod bip32;
 pub mod config;
+pub mod config_reload;
+pub mod console_status;
+pub mod counterparties;
+pub mod crash_report;
 pub mod error;
 pub mod gui;
 pub mod logger;
 pub mod loops;
 pub mod message;
 pub mod messages;
+pub mod mining;
 pub mod node;
+pub mod node_handle;
 pub mod node_state;
 pub mod parser;
 pub mod peer;
+pub mod pricing;
+pub mod private_network;
+pub mod rng;
+pub mod rpc;
+pub mod rpc_auth;
+pub mod send_many;
+pub mod services;
 pub mod states;
+pub mod store_lock;
 pub mod structs;
+pub mod synthetic_chain;
 pub mod utils;
+pub mod utxo_snapshot;
 pub mod wallet;
+pub mod wallet_crypto;
+pub mod wallet_import;
+pub mod webhooks;
@@ -1,9 +1,13 @@
+use std::collections::VecDeque;
 use std::fs;
 use std::io::Write;
 use std::path::Path;
 use std::{
     fs::OpenOptions,
-    sync::mpsc::{self, Sender},
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
     thread,
 };
 
@@ -15,25 +19,85 @@ use crate::gui::init::GUIEvents;
 
 #[derive(Debug, Clone)]
 /// Log es el tipo de dato que se envia al logger.
-/// Puede ser un Message o un Error.
+/// Puede ser un Message, un Warning o un Error.
 /// Los Message reciben un String y esos seran los logs que se guarden en el archivo, impriman en consola y se muestren en la interfaz.
-/// Los Error reciben un CustomError y haran los mismo que los Message, y ademas muestran una ventana popup con el error.
+/// Los Warning hacen lo mismo que los Message marcando la linea con [WARN], para avisos que el usuario no deberia pasar por alto.
+/// Los Error reciben un CustomError y haran los mismo que los Message (imprimiendo el error junto con sus causas), y ademas muestran una ventana popup con el error.
+/// SetLevel cambia el LogLevel del logger mientras el nodo esta corriendo (por ejemplo al recargar el config).
 pub enum Log {
     Message(String),
+    Warning(String),
     Error(CustomError),
+    SetLevel(LogLevel),
     Terminate,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// LogLevel indica que logs se registran: con Info todos, con Warn los Log::Warning y los Log::Error,
+/// y con Error solo los Log::Error (los demas se descartan).
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+}
+
+impl LogLevel {
+    /// Lee un LogLevel de su nombre en minuscula (error, warn o info), o devuelve None si no es ninguno.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "error" => Some(Self::Error),
+            "warn" => Some(Self::Warn),
+            "info" => Some(Self::Info),
+            _ => None,
+        }
+    }
+}
+
+/// Cantidad de lineas que guarda LogHistory.
+pub const LOG_HISTORY_LINES: usize = 200;
+
+#[derive(Debug, Clone, Default)]
+/// LogHistory guarda en memoria las ultimas LOG_HISTORY_LINES lineas escritas en el archivo de log,
+/// para incluirlas en el reporte de un panic (ver crash_report) sin tener que leer el archivo.
+pub struct LogHistory(Arc<Mutex<VecDeque<String>>>);
+
+impl LogHistory {
+    /// Agrega una linea, descartando la mas antigua si ya hay LOG_HISTORY_LINES.
+    pub fn push(&self, line: String) {
+        let mut lines = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if lines.len() == LOG_HISTORY_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    /// Devuelve las lineas guardadas, de la mas antigua a la mas reciente.
+    /// Se puede llamar durante un panic: si el lock quedo envenenado igual devuelve las lineas.
+    pub fn lines(&self) -> Vec<String> {
+        let lines = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        lines.iter().cloned().collect()
+    }
+}
+
 /// Logger es una estructura que contiene los elementos necesarios para manejar los logs.
 /// Los elementos son:
 /// - tx: Sender para enviar logs al logger.
+/// - thread: Thread que escribe los logs.
+/// - history: Ultimas lineas escritas en el archivo de log.
 pub struct Logger {
     pub tx: Sender<Log>,
     pub thread: thread::JoinHandle<Result<(), CustomError>>,
+    pub history: LogHistory,
 }
 
 impl Logger {
-    /// Inicializa el logger.
+    /// Inicializa el logger con LogLevel::Info.
     /// Si el archivo donde se guardan los logs existe, lo borra.
     /// Crea el archivo de logs en la ubicacion recibida
     /// Inicializa el thread que escucha los Logs recibidos por un channel que crea.
@@ -41,6 +105,17 @@ impl Logger {
     pub fn new(
         filename: &String,
         gui_sender: glib::Sender<GUIEvents>,
+    ) -> Result<Self, CustomError> {
+        Self::with_status_line(filename, gui_sender, false)
+    }
+
+    /// Inicializa el logger igual que new.
+    /// Si status_line es true, los Message solo se guardan en el archivo (la consola la usa la linea de estado)
+    /// y los Warning y los Error se imprimen en una linea completa por encima de la linea de estado.
+    pub fn with_status_line(
+        filename: &String,
+        gui_sender: glib::Sender<GUIEvents>,
+        status_line: bool,
     ) -> Result<Self, CustomError> {
         let (tx, rx) = mpsc::channel::<Log>();
 
@@ -54,34 +129,69 @@ impl Logger {
             .append(true)
             .open(filename)?;
 
+        let history = LogHistory::default();
+        let thread_history = history.clone();
         let thread = thread::spawn(move || -> Result<(), CustomError> {
+            let mut level = LogLevel::Info;
             while let Ok(message) = rx.recv() {
                 match message {
+                    Log::Message(_) if level != LogLevel::Info => continue,
+                    Log::Warning(_) if level == LogLevel::Error => continue,
                     Log::Message(ref string) => {
                         let current_time = Local::now();
                         let formatted_time = current_time.format("%Y-%m-%d %H:%M:%S");
-                        println!("[{}] {}", formatted_time, string);
-                        writeln!(file, "[{}] {}", formatted_time, string)?;
+                        if !status_line {
+                            println!("[{}] {}", formatted_time, string);
+                        }
+                        let line = format!("[{}] {}", formatted_time, string);
+                        writeln!(file, "{}", line)?;
+                        thread_history.push(line);
                         if let Err(error) = gui_sender.send(GUIEvents::Log(message)) {
                             println!("Error sending log message to gui: {}", error);
                         }
                     }
+                    Log::Warning(ref string) => {
+                        let current_time = Local::now();
+                        let formatted_time = current_time.format("%Y-%m-%d %H:%M:%S");
+                        let line = format!("[{}] [WARN] {}", formatted_time, string);
+                        if status_line {
+                            println!("\r\x1b[2K{}", line);
+                        } else {
+                            println!("{}", line);
+                        }
+                        writeln!(file, "{}", line)?;
+                        thread_history.push(line);
+                        if let Err(error) = gui_sender.send(GUIEvents::Log(message)) {
+                            println!("Error sending log warning to gui: {}", error);
+                        }
+                    }
                     Log::Error(ref error) => {
                         let current_time = Local::now();
                         let formatted_time = current_time.format("%Y-%m-%d %H:%M:%S");
-                        println!("[{}] [ERROR] {}", formatted_time, error);
-                        writeln!(file, "[{}] [ERROR] {}", formatted_time, error)?;
+                        let line = format!("[{}] [ERROR] {}", formatted_time, error.with_sources());
+                        if status_line {
+                            println!("\r\x1b[2K{}", line);
+                        } else {
+                            println!("{}", line);
+                        }
+                        writeln!(file, "{}", line)?;
+                        thread_history.push(line);
                         if let Err(error) = gui_sender.send(GUIEvents::Log(message)) {
                             println!("Error sending log error to gui: {}", error);
                         }
                     }
+                    Log::SetLevel(new_level) => level = new_level,
                     Log::Terminate => break,
                 }
             }
             Ok(())
         });
 
-        Ok(Self { tx, thread })
+        Ok(Self {
+            tx,
+            thread,
+            history,
+        })
     }
 
     /// Devuelve el sender para enviar logs al logger.
@@ -212,4 +322,123 @@ mod tests {
         assert_eq!(fs::read_to_string("test4.txt").unwrap(), result);
         fs::remove_file("test4.txt").unwrap();
     }
+
+    #[test]
+    fn log_level_error_skips_messages() {
+        let (tx, _rx) = glib::MainContext::channel(Priority::default());
+
+        let logger = Logger::new(&String::from("test5.txt"), tx).unwrap();
+        let sender = logger.get_sender();
+        sender.send(Log::SetLevel(LogLevel::Error)).unwrap();
+        sender
+            .send(Log::Message(String::from("Skipped message")))
+            .unwrap();
+        sender
+            .send(Log::Error(CustomError::CannotRemoveFile))
+            .unwrap();
+        sender.send(Log::SetLevel(LogLevel::Info)).unwrap();
+        sender
+            .send(Log::Message(String::from("Written message")))
+            .unwrap();
+        sender.send(Log::Terminate).unwrap();
+        logger.thread.join().unwrap().unwrap();
+
+        let content = fs::read_to_string("test5.txt").unwrap();
+        assert!(!content.contains("Skipped message"));
+        assert!(content.contains("[ERROR] Error: cannot remove file"));
+        assert!(content.contains("Written message"));
+        fs::remove_file("test5.txt").unwrap();
+    }
+
+    #[test]
+    fn log_level_warn_keeps_warnings_and_errors() {
+        let (tx, _rx) = glib::MainContext::channel(Priority::default());
+
+        let logger = Logger::new(&String::from("test7.txt"), tx).unwrap();
+        let sender = logger.get_sender();
+        sender.send(Log::SetLevel(LogLevel::Warn)).unwrap();
+        sender
+            .send(Log::Message(String::from("Skipped message")))
+            .unwrap();
+        sender
+            .send(Log::Warning(String::from("Written warning")))
+            .unwrap();
+        sender
+            .send(Log::Error(CustomError::CannotRemoveFile))
+            .unwrap();
+        sender.send(Log::SetLevel(LogLevel::Error)).unwrap();
+        sender
+            .send(Log::Warning(String::from("Skipped warning")))
+            .unwrap();
+        sender.send(Log::Terminate).unwrap();
+        logger.thread.join().unwrap().unwrap();
+
+        let content = fs::read_to_string("test7.txt").unwrap();
+        assert!(!content.contains("Skipped"));
+        assert!(content.contains("[WARN] Written warning"));
+        assert!(content.contains("[ERROR] Error: cannot remove file"));
+        fs::remove_file("test7.txt").unwrap();
+    }
+
+    #[test]
+    fn status_line_mode_keeps_warnings_and_errors() {
+        let (tx, _rx) = glib::MainContext::channel(Priority::default());
+
+        let logger = Logger::with_status_line(&String::from("test8.txt"), tx, true).unwrap();
+        let sender = logger.get_sender();
+        sender
+            .send(Log::Message(String::from("Status message")))
+            .unwrap();
+        sender
+            .send(Log::Warning(String::from("Status warning")))
+            .unwrap();
+        sender
+            .send(Log::Error(CustomError::CannotRemoveFile))
+            .unwrap();
+        sender.send(Log::Terminate).unwrap();
+        logger.thread.join().unwrap().unwrap();
+
+        let lines = logger.history.lines();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].ends_with("] [WARN] Status warning"));
+        assert!(lines[2].ends_with("] [ERROR] Error: cannot remove file"));
+        let content = fs::read_to_string("test8.txt").unwrap();
+        assert!(!content.contains('\x1b'));
+        assert_eq!(content, format!("{}\n", lines.join("\n")));
+        fs::remove_file("test8.txt").unwrap();
+    }
+
+    #[test]
+    fn log_level_parse() {
+        assert_eq!(LogLevel::parse("error"), Some(LogLevel::Error));
+        assert_eq!(LogLevel::parse("warn"), Some(LogLevel::Warn));
+        assert_eq!(LogLevel::parse("info"), Some(LogLevel::Info));
+        assert_eq!(LogLevel::parse("INFO"), None);
+    }
+
+    #[test]
+    fn log_history_keeps_the_last_lines() {
+        let (tx, _rx) = glib::MainContext::channel(Priority::default());
+
+        let logger = Logger::new(&String::from("test6.txt"), tx).unwrap();
+        let sender = logger.get_sender();
+        for index in 0..LOG_HISTORY_LINES + 5 {
+            sender
+                .send(Log::Message(format!("Message {index}")))
+                .unwrap();
+        }
+        sender
+            .send(Log::Error(CustomError::CannotRemoveFile))
+            .unwrap();
+        sender.send(Log::Terminate).unwrap();
+        logger.thread.join().unwrap().unwrap();
+
+        let lines = logger.history.lines();
+        assert_eq!(lines.len(), LOG_HISTORY_LINES);
+        assert!(lines[0].ends_with("] Message 6"));
+        assert!(lines[LOG_HISTORY_LINES - 1].ends_with("[ERROR] Error: cannot remove file"));
+        let content = fs::read_to_string("test6.txt").unwrap();
+        assert!(content.ends_with(&format!("{}\n", lines.join("\n"))));
+        fs::remove_file("test6.txt").unwrap();
+    }
 }
@@ -0,0 +1,100 @@
+use std::{
+    collections::HashSet,
+    io::Read,
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    error::CustomError,
+    logger::{send_log, Log},
+    message::Message,
+    messages::block::Block,
+    node_state::NodeState,
+    states::block_metadata_state::BlockMetadata,
+    structs::block_header::hash_as_string,
+};
+
+use super::maintenance_loop::MaintenanceTask;
+
+/// Nombre con el que se registra la tarea de los resumenes de bloques en el Scheduler del maintenance_loop.
+pub const BLOCK_METADATA_TASK: &str = "block_metadata";
+
+/// Tiempo entre ejecuciones de la tarea que completa los resumenes de los bloques.
+pub const BLOCK_METADATA_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Cantidad maxima de bloques que se leen en cada ejecucion, para completar los resumenes de a poco sin ocupar el disco.
+pub const BLOCK_METADATA_BATCH: usize = 20;
+
+/// block_metadata_task devuelve la tarea del maintenance_loop que completa los resumenes (BlockMetadata) de los bloques
+/// guardados antes de que se registraran al recibirlos, por ejemplo en un store creado con una version anterior.
+/// Los bloques que no se pueden leer se loguean y no se vuelven a intentar hasta reiniciar el nodo.
+/// Los elementos son:
+/// - node_state_ref: Referencia al estado del nodo.
+/// - logger_sender: Sender para enviar logs al logger.
+pub fn block_metadata_task(
+    node_state_ref: Arc<Mutex<NodeState>>,
+    logger_sender: mpsc::Sender<Log>,
+) -> MaintenanceTask {
+    let mut unreadable = HashSet::new();
+    Box::new(move || {
+        let completed = backfill_block_metadata(
+            &node_state_ref,
+            BLOCK_METADATA_BATCH,
+            &mut unreadable,
+            &logger_sender,
+        )?;
+        if completed > 0 {
+            send_log(
+                &logger_sender,
+                Log::Message(format!(
+                    "Completed the summary of {completed} stored blocks"
+                )),
+            );
+        }
+        Ok(())
+    })
+}
+
+/// Completa el resumen de hasta batch bloques guardados que no lo tienen y devuelve cuantos completo.
+/// Los archivos se leen y parsean sin tener el lock del NodeState, que solo se toma para buscarlos y para registrar los resumenes.
+/// Un bloque que no se puede leer o parsear se loguea, se agrega a unreadable para no volver a buscarlo y se continua con el resto.
+pub fn backfill_block_metadata(
+    node_state_ref: &Arc<Mutex<NodeState>>,
+    batch: usize,
+    unreadable: &mut HashSet<Vec<u8>>,
+    logger_sender: &mpsc::Sender<Log>,
+) -> Result<usize, CustomError> {
+    let missing = node_state_ref
+        .lock()?
+        .blocks_missing_metadata(batch, unreadable);
+
+    let mut completed = vec![];
+    for (block_hash, mut file) in missing {
+        let mut buffer = vec![];
+        let block = file
+            .read_to_end(&mut buffer)
+            .map_err(CustomError::from)
+            .and_then(|_| Block::parse(buffer));
+        match block {
+            Ok(block) => completed.push((block_hash, BlockMetadata::from_block(&block))),
+            Err(error) => {
+                send_log(
+                    logger_sender,
+                    Log::Warning(format!(
+                        "cannot read stored block {} to complete its summary: {}",
+                        hash_as_string(block_hash.clone()),
+                        error
+                    )),
+                );
+                unreadable.insert(block_hash);
+            }
+        }
+    }
+
+    let mut node_state = node_state_ref.lock()?;
+    for (block_hash, metadata) in &completed {
+        node_state.append_block_metadata(block_hash, *metadata)?;
+    }
+    Ok(completed.len())
+}
@@ -0,0 +1,471 @@
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::DefaultHasher, BinaryHeap},
+    hash::{Hash, Hasher},
+    panic::AssertUnwindSafe,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    crash_report::catch_panics,
+    error::CustomError,
+    logger::{send_log, Log},
+    node_state::NodeState,
+    rng::random_nonce,
+};
+
+/// Intervalo minimo de una tarea, para que una tarea con intervalo 0 no ocupe todo el loop.
+pub const MIN_TASK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// El jitter de cada ejecucion es como maximo el intervalo de la tarea dividido JITTER_DIVISOR.
+const JITTER_DIVISOR: u32 = 10;
+
+/// Tiempo que espera el loop si no hay ninguna tarea registrada.
+const IDLE_WAIT: Duration = Duration::from_secs(60);
+
+/// Tarea periodica del MaintenanceLoop. Captura los channels y referencias que necesita.
+/// Si devuelve un error se loguea y se vuelve a programar igual.
+pub type MaintenanceTask = Box<dyn FnMut() -> Result<(), CustomError> + Send>;
+
+/// MaintenanceAction representa las acciones que se le pueden pedir al MaintenanceLoop:
+/// - RunNow: Ejecuta la tarea con ese nombre lo antes posible, sin cambiar sus proximas ejecuciones (por ejemplo desde la GUI).
+/// - RunAt: Adelanta la proxima ejecucion de la tarea con ese nombre a ese momento, si es anterior (ver Scheduler::run_at).
+/// - Terminate: Termina el loop.
+pub enum MaintenanceAction {
+    RunNow(String),
+    RunAt(String, Instant),
+    Terminate,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// TaskStatus es el estado de una tarea del Scheduler, que se muestra en el StatusSnapshot.
+/// Los elementos son:
+/// - name: Nombre de la tarea.
+/// - interval: Tiempo entre ejecuciones.
+/// - last_run: Momento de la ultima ejecucion, None si todavia no se ejecuto.
+/// - next_run: Momento de la proxima ejecucion.
+/// - failures: Cantidad de ejecuciones que devolvieron error.
+pub struct TaskStatus {
+    pub name: String,
+    pub interval: Duration,
+    pub last_run: Option<Instant>,
+    pub next_run: Instant,
+    pub failures: usize,
+}
+
+/// Tarea registrada en el Scheduler.
+/// base_run es la proxima ejecucion sin jitter: avanza de a un intervalo desde el registro, por lo que
+/// ni el jitter ni las demoras en ejecutar la tarea se acumulan.
+struct ScheduledTask {
+    status: TaskStatus,
+    base_run: Instant,
+    runs: u64,
+    task: MaintenanceTask,
+}
+
+/// Scheduler es una cola de prioridad de tareas periodicas, ordenadas por su proxima ejecucion.
+/// No depende del reloj: todas las operaciones reciben el momento actual, lo que permite probarlo con un reloj falso.
+/// Los elementos son:
+/// - tasks: Tareas registradas, en el orden de registro.
+/// - queue: Proxima ejecucion e indice de cada tarea. Las entradas que no coinciden con el next_run de su tarea
+///   (porque se adelanto con run_now) se descartan al sacarlas.
+/// - jitter: Indica si se suma jitter a cada ejecucion, para que las tareas con el mismo intervalo no se ejecuten juntas.
+/// - seed: Semilla aleatoria del jitter, para que las tareas de distintos nodos tampoco se ejecuten juntas.
+pub struct Scheduler {
+    tasks: Vec<ScheduledTask>,
+    queue: BinaryHeap<Reverse<(Instant, usize)>>,
+    jitter: bool,
+    seed: u64,
+}
+
+impl Scheduler {
+    #[must_use]
+    /// Crea un Scheduler sin tareas.
+    pub fn new(jitter: bool) -> Self {
+        Self {
+            tasks: vec![],
+            queue: BinaryHeap::new(),
+            jitter,
+            seed: random_nonce(),
+        }
+    }
+
+    /// Registra una tarea que se ejecuta cada interval (como minimo MIN_TASK_INTERVAL), la primera vez un intervalo despues de now.
+    pub fn register(
+        &mut self,
+        name: &str,
+        interval: Duration,
+        now: Instant,
+        task: MaintenanceTask,
+    ) {
+        let interval = interval.max(MIN_TASK_INTERVAL);
+        let base_run = now + interval;
+        let next_run = base_run + jitter_for(self.jitter, self.seed, name, 0, interval);
+
+        self.queue.push(Reverse((next_run, self.tasks.len())));
+        self.tasks.push(ScheduledTask {
+            status: TaskStatus {
+                name: name.to_string(),
+                interval,
+                last_run: None,
+                next_run,
+                failures: 0,
+            },
+            base_run,
+            runs: 0,
+            task,
+        });
+    }
+
+    /// Programa la tarea con ese nombre para ejecutarse en now. Sus ejecuciones siguientes no cambian.
+    /// Devuelve false si no hay ninguna tarea con ese nombre.
+    pub fn run_now(&mut self, name: &str, now: Instant) -> bool {
+        self.run_at(name, now)
+    }
+
+    /// Adelanta la proxima ejecucion de la tarea con ese nombre a when, si es anterior a la que tenia programada,
+    /// lo que permite a una tarea revisar con mas frecuencia mientras tiene trabajo pendiente. Sus ejecuciones siguientes no cambian.
+    /// Devuelve false si no hay ninguna tarea con ese nombre.
+    pub fn run_at(&mut self, name: &str, when: Instant) -> bool {
+        let index = match self.tasks.iter().position(|task| task.status.name == name) {
+            Some(index) => index,
+            None => return false,
+        };
+        if when < self.tasks[index].status.next_run {
+            self.tasks[index].status.next_run = when;
+            self.queue.push(Reverse((when, index)));
+        }
+        true
+    }
+
+    /// Devuelve cuanto falta para la proxima ejecucion (cero si ya hay alguna vencida), o None si no hay tareas.
+    pub fn next_wakeup(&mut self, now: Instant) -> Option<Duration> {
+        self.discard_stale_entries();
+        let Reverse((next_run, _)) = self.queue.peek()?;
+        Some(next_run.saturating_duration_since(now))
+    }
+
+    /// Ejecuta, en orden, las tareas cuya proxima ejecucion ya paso y las vuelve a programar.
+    /// Un error (o un panic) en una tarea no afecta a las demas: se devuelve junto al nombre de la tarea.
+    pub fn run_due(&mut self, now: Instant) -> Vec<(String, CustomError)> {
+        let mut failures = vec![];
+        loop {
+            self.discard_stale_entries();
+            let index = match self.queue.peek() {
+                Some(Reverse((next_run, index))) if *next_run <= now => *index,
+                _ => break,
+            };
+            self.queue.pop();
+
+            let scheduled = &mut self.tasks[index];
+            let result = catch_panics(AssertUnwindSafe(&mut scheduled.task))
+                .unwrap_or(Err(CustomError::MaintenanceTaskPanicked));
+            scheduled.status.last_run = Some(now);
+            scheduled.runs += 1;
+            if let Err(error) = result {
+                scheduled.status.failures += 1;
+                failures.push((scheduled.status.name.clone(), error));
+            }
+            self.reschedule(index, now);
+        }
+        failures
+    }
+
+    /// Devuelve el estado de las tareas, en el orden en que se registraron.
+    pub fn statuses(&self) -> Vec<TaskStatus> {
+        self.tasks.iter().map(|task| task.status.clone()).collect()
+    }
+
+    /// Programa la proxima ejecucion de la tarea: el siguiente base_run posterior a now, mas el jitter.
+    /// Si la tarea se atraso mas de un intervalo, las ejecuciones perdidas no se recuperan.
+    fn reschedule(&mut self, index: usize, now: Instant) {
+        let scheduled = &mut self.tasks[index];
+        let interval = scheduled.status.interval;
+        while scheduled.base_run <= now {
+            scheduled.base_run += interval;
+        }
+        let jitter = jitter_for(
+            self.jitter,
+            self.seed,
+            &scheduled.status.name,
+            scheduled.runs,
+            interval,
+        );
+        scheduled.status.next_run = scheduled.base_run + jitter;
+        self.queue.push(Reverse((scheduled.status.next_run, index)));
+    }
+
+    /// Saca de la cola las entradas que ya no corresponden a la proxima ejecucion de su tarea.
+    fn discard_stale_entries(&mut self) {
+        while let Some(Reverse((next_run, index))) = self.queue.peek() {
+            if self.tasks[*index].status.next_run == *next_run {
+                break;
+            }
+            self.queue.pop();
+        }
+    }
+}
+
+/// Calcula el jitter de una ejecucion a partir de la semilla del Scheduler, el nombre de la tarea y la cantidad de ejecuciones,
+/// por lo que es distinto para cada tarea y cada ejecucion pero reproducible con la misma semilla. Es cero si enabled es false.
+fn jitter_for(enabled: bool, seed: u64, name: &str, runs: u64, interval: Duration) -> Duration {
+    let max_jitter = (interval / JITTER_DIVISOR).as_nanos() as u64;
+    if !enabled || max_jitter == 0 {
+        return Duration::ZERO;
+    }
+    let mut hasher = DefaultHasher::new();
+    (seed, name, runs).hash(&mut hasher);
+    Duration::from_nanos(hasher.finish() % max_jitter)
+}
+
+/// maintenance_loop es una funcion que genera el loop que ejecuta las tareas periodicas del nodo con un Scheduler,
+/// en un unico thread en lugar de uno por tarea.
+/// Despues de cada ejecucion publica el estado de las tareas en el NodeState, para el StatusSnapshot.
+/// Las tareas que fallan se loguean y se vuelven a programar.
+/// Los elementos son:
+/// - scheduler: Scheduler con las tareas ya registradas.
+/// - maintenance_receiver: Receiver de las MaintenanceAction (ver NodeState::run_maintenance_task).
+/// - node_state_ref: Referencia al estado del nodo.
+/// - logger_sender: Sender para enviar logs al logger.
+pub fn maintenance_loop(
+    mut scheduler: Scheduler,
+    maintenance_receiver: mpsc::Receiver<MaintenanceAction>,
+    node_state_ref: Arc<Mutex<NodeState>>,
+    logger_sender: mpsc::Sender<Log>,
+) -> thread::JoinHandle<Result<(), CustomError>> {
+    thread::spawn(move || -> Result<(), CustomError> {
+        loop {
+            for (name, error) in scheduler.run_due(Instant::now()) {
+                send_log(
+                    &logger_sender,
+                    Log::Message(format!(
+                        "Maintenance task {} failed: {}",
+                        name,
+                        error.description()
+                    )),
+                );
+            }
+            node_state_ref
+                .lock()?
+                .set_maintenance_tasks(scheduler.statuses());
+
+            let wait = scheduler.next_wakeup(Instant::now()).unwrap_or(IDLE_WAIT);
+            match maintenance_receiver.recv_timeout(wait) {
+                Ok(MaintenanceAction::RunNow(name)) => {
+                    if !scheduler.run_now(&name, Instant::now()) {
+                        send_log(
+                            &logger_sender,
+                            Log::Message(format!("Unknown maintenance task {}", name)),
+                        );
+                    }
+                }
+                Ok(MaintenanceAction::RunAt(name, when)) => {
+                    if !scheduler.run_at(&name, when) {
+                        send_log(
+                            &logger_sender,
+                            Log::Message(format!("Unknown maintenance task {}", name)),
+                        );
+                    }
+                }
+                Ok(MaintenanceAction::Terminate) | Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Reloj falso: el momento actual es el inicio mas el tiempo que se avanzo.
+    struct FakeClock {
+        start: Instant,
+        elapsed: Duration,
+    }
+
+    impl FakeClock {
+        fn new() -> Self {
+            Self {
+                start: Instant::now(),
+                elapsed: Duration::ZERO,
+            }
+        }
+
+        fn now(&self) -> Instant {
+            self.start + self.elapsed
+        }
+
+        fn advance(&mut self, duration: Duration) -> Instant {
+            self.elapsed += duration;
+            self.now()
+        }
+    }
+
+    /// Tarea que registra su nombre en runs cada vez que se ejecuta.
+    fn recording_task(name: &'static str, runs: &Arc<Mutex<Vec<&'static str>>>) -> MaintenanceTask {
+        let runs = runs.clone();
+        Box::new(move || {
+            runs.lock()?.push(name);
+            Ok(())
+        })
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn tasks_run_in_order_of_next_run() {
+        let mut clock = FakeClock::new();
+        let runs = Arc::new(Mutex::new(vec![]));
+        let mut scheduler = Scheduler::new(false);
+        scheduler.register("slow", secs(3), clock.now(), recording_task("slow", &runs));
+        scheduler.register("fast", secs(2), clock.now(), recording_task("fast", &runs));
+
+        assert_eq!(scheduler.next_wakeup(clock.now()), Some(secs(2)));
+        assert!(scheduler.run_due(clock.advance(secs(1))).is_empty());
+        assert!(runs.lock().unwrap().is_empty());
+
+        for _ in 0..5 {
+            scheduler.run_due(clock.advance(secs(1)));
+        }
+        // en t=6 las dos vencen juntas y se ejecutan en el orden en que se registraron
+        assert_eq!(
+            *runs.lock().unwrap(),
+            vec!["fast", "slow", "fast", "slow", "fast"]
+        );
+        assert_eq!(scheduler.next_wakeup(clock.now()), Some(secs(2)));
+    }
+
+    #[test]
+    fn late_runs_do_not_drift_the_schedule() {
+        let mut clock = FakeClock::new();
+        let runs = Arc::new(Mutex::new(vec![]));
+        let mut scheduler = Scheduler::new(false);
+        scheduler.register("task", secs(10), clock.now(), recording_task("task", &runs));
+
+        // se ejecuta 3 segundos tarde, pero la siguiente sigue siendo en t=20
+        scheduler.run_due(clock.advance(secs(13)));
+        assert_eq!(scheduler.next_wakeup(clock.now()), Some(secs(7)));
+        assert_eq!(scheduler.statuses()[0].next_run, clock.start + secs(20));
+
+        // si se atrasa mas de un intervalo, las ejecuciones perdidas no se recuperan
+        scheduler.run_due(clock.advance(secs(25)));
+        assert_eq!(runs.lock().unwrap().len(), 2);
+        assert_eq!(scheduler.statuses()[0].next_run, clock.start + secs(40));
+        assert_eq!(
+            scheduler.statuses()[0].last_run,
+            Some(clock.start + secs(38))
+        );
+    }
+
+    #[test]
+    fn failing_task_is_rescheduled_without_affecting_others() {
+        let mut clock = FakeClock::new();
+        let runs = Arc::new(Mutex::new(vec![]));
+        let mut scheduler = Scheduler::new(false);
+        scheduler.register(
+            "failing",
+            secs(1),
+            clock.now(),
+            Box::new(|| Err(CustomError::CannotLockGuard)),
+        );
+        scheduler.register(
+            "panicking",
+            secs(1),
+            clock.now(),
+            Box::new(|| panic!("task")),
+        );
+        scheduler.register("ok", secs(1), clock.now(), recording_task("ok", &runs));
+
+        let failures = scheduler.run_due(clock.advance(secs(1)));
+        let names: Vec<&str> = failures.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["failing", "panicking"]);
+        assert!(matches!(
+            failures[1].1,
+            CustomError::MaintenanceTaskPanicked
+        ));
+
+        scheduler.run_due(clock.advance(secs(1)));
+        assert_eq!(*runs.lock().unwrap(), vec!["ok", "ok"]);
+        let statuses = scheduler.statuses();
+        assert_eq!(statuses[0].failures, 2);
+        assert_eq!(statuses[1].failures, 2);
+        assert_eq!(statuses[2].failures, 0);
+        assert_eq!(statuses[0].next_run, clock.start + secs(3));
+    }
+
+    #[test]
+    fn run_now_runs_task_without_changing_its_schedule() {
+        let mut clock = FakeClock::new();
+        let runs = Arc::new(Mutex::new(vec![]));
+        let mut scheduler = Scheduler::new(false);
+        scheduler.register("task", secs(10), clock.now(), recording_task("task", &runs));
+
+        let now = clock.advance(secs(4));
+        assert!(scheduler.run_now("task", now));
+        assert!(!scheduler.run_now("unknown", now));
+        assert_eq!(scheduler.next_wakeup(now), Some(Duration::ZERO));
+
+        scheduler.run_due(now);
+        assert_eq!(runs.lock().unwrap().len(), 1);
+        assert_eq!(scheduler.next_wakeup(now), Some(secs(6)));
+    }
+
+    #[test]
+    fn run_at_only_brings_the_next_run_forward() {
+        let mut clock = FakeClock::new();
+        let runs = Arc::new(Mutex::new(vec![]));
+        let mut scheduler = Scheduler::new(false);
+        scheduler.register("task", secs(10), clock.now(), recording_task("task", &runs));
+
+        // una ejecucion posterior a la programada no la atrasa
+        assert!(scheduler.run_at("task", clock.start + secs(15)));
+        assert_eq!(scheduler.next_wakeup(clock.now()), Some(secs(10)));
+
+        assert!(scheduler.run_at("task", clock.start + secs(3)));
+        assert!(!scheduler.run_at("unknown", clock.start + secs(3)));
+        assert_eq!(scheduler.next_wakeup(clock.now()), Some(secs(3)));
+
+        scheduler.run_due(clock.advance(secs(3)));
+        assert_eq!(runs.lock().unwrap().len(), 1);
+        assert_eq!(scheduler.next_wakeup(clock.now()), Some(secs(7)));
+    }
+
+    #[test]
+    fn jitter_is_bounded_and_spreads_tasks() {
+        let clock = FakeClock::new();
+        let mut scheduler = Scheduler::new(true);
+        for name in ["a", "b", "c", "d"] {
+            scheduler.register(name, secs(10), clock.now(), Box::new(|| Ok(())));
+        }
+
+        let next_runs: Vec<Instant> = scheduler
+            .statuses()
+            .iter()
+            .map(|status| status.next_run)
+            .collect();
+        for next_run in &next_runs {
+            assert!(*next_run >= clock.start + secs(10));
+            assert!(*next_run < clock.start + secs(11));
+        }
+        assert!(next_runs.iter().any(|next_run| *next_run != next_runs[0]));
+    }
+
+    #[test]
+    fn zero_interval_is_raised_to_minimum() {
+        let clock = FakeClock::new();
+        let mut scheduler = Scheduler::new(false);
+        scheduler.register("task", Duration::ZERO, clock.now(), Box::new(|| Ok(())));
+        assert_eq!(scheduler.statuses()[0].interval, MIN_TASK_INTERVAL);
+    }
+}
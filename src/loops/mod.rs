@@ -1,5 +1,12 @@
+pub mod block_metadata_task;
+pub mod maintenance_loop;
 pub mod node_action_loop;
 pub mod peer_action_loop;
+pub mod peer_actions_watchdog_task;
 pub mod peer_stream_loop;
-pub mod pending_blocks_loop;
+pub mod ping_loop;
+pub mod pending_blocks_task;
+pub mod reconnect_loop;
 pub mod tcp_listener_loop;
+pub mod tx_propagation_loop;
+pub mod webhook_loop;
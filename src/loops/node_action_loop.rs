@@ -7,47 +7,103 @@ use std::{
 use gtk::glib;
 
 use crate::{
+    config::ConfigDelta,
     error::CustomError,
     gui::init::GUIEvents,
     logger::{send_log, Log},
     message::Message,
     messages::{
-        block::Block, get_data::GetData, get_headers::GetHeaders, headers::Headers, inv::Inv,
-        not_found::NotFound, transaction::Transaction,
+        block::Block,
+        block_txn::{BlockTxn, GetBlockTxn},
+        compact_block::{CompactBlock, PartialBlock},
+        get_blocks::GetBlocks,
+        get_data::GetData,
+        get_headers::GetHeaders,
+        headers::{Headers, RawHeaders},
+        inv::{Inv, MAX_INV_ENTRIES},
+        not_found::NotFound,
+        reject::Reject,
+        transaction::{Transaction, TransactionOptions},
     },
     node_state::NodeState,
+    peer::DisconnectReason,
+    rpc::{RpcCall, RpcResponse},
+    states::block_store_state::BlockReader,
     structs::{
         block_header::{hash_as_string, BlockHeader},
+        bloom_filter::BloomFilter,
         inventory::{Inventory, InventoryType},
     },
 };
 
-use super::peer_action_loop::PeerAction;
+use super::peer_action_loop::{PeerAction, PeerActionSender};
 
 /// NodeAction es una enumeracion de las acciones que puede realizar el nodo.
+/// Los payloads grandes (bloques, transacciones e inventories a reintentar) viajan en un Arc,
+/// para que pasarlos entre loops no copie los datos y nadie pueda modificarlos mientras se comparten.
 /// Las acciones son:
-/// - PeerError: Peer tiene comportamiento no esperado.
-/// - NewHeaders: Recibe nuevos headers.
+/// - PeerError: Peer tiene comportamiento no esperado o se cerro su conexion, contiene el motivo de la desconexion.
+/// - NewHeaders: Recibe nuevos headers de un peer.
 /// - GetHeadersError: Error al solicitar headers.
 /// - Block: Recibe un bloque.
+/// - FilteredBlock: Recibe un bloque filtrado (merkleblock) con solo las transacciones de las wallets.
 /// - GetDataError: Error al solicitar data.
+/// - NotFound: Un peer respondio notfound a inventarios que le pedimos.
+/// - Rejected: Un peer rechazo un mensaje nuestro (por ejemplo una transaccion) con un mensaje reject.
 /// - PendingTransaction: Recibe una transaccion.
-/// - MakeTransaction: Solicitar una transaccion.
+/// - MakeTransaction: Solicitar una transaccion, con sus outputs, fee y opciones (lock time y si es reemplazable).
+/// - ReplaceTransaction: Reemplazar una pending tx reemplazable nuestra por otra con mayor fee, contiene su hash y el nuevo fee.
 /// - SendHeaders: Habilita el envio directo de headers a un peer.
 /// - GetHeaders: Solicitud de headers de parte de un peer.
+/// - GetBlocks: Solicitud del inventario de bloques de parte de un peer.
 /// - GetData: Solicitud de data de parte de un peer.
+/// - CompactBlock: Recibe un compact block de un peer.
+/// - BlockTxn: Recibe las transacciones faltantes de un compact block.
+/// - Mempool: Solicitud de las transacciones pendientes de parte de un peer.
+/// - Pong: Recibe la respuesta de un peer a un ping, contiene su nonce.
+/// - TransactionsAnnounced: Un peer anuncio transacciones en un inv, contiene sus hashes.
+/// - InventoryAnnounced: Un peer anuncio inventarios en un inv que se le pueden pedir, el nodo decide cuales pedirle.
+/// - PeerMessageReceived: Se recibio un mensaje de un peer, contiene su tamaño en bytes.
+/// - NewAddresses: Un peer anuncio direcciones de otros nodos en un mensaje addr.
+/// - FilterLoad: Un peer cargo un filtro bloom con filterload, o lo quito con filterclear (None).
+/// - FilterAdd: Un peer agrego un elemento a su filtro bloom con filteradd.
+/// - BehindNetwork: El nodo quedo atrasado respecto de la altura de los peers.
+/// - RunMaintenanceTask: Ejecutar ahora una tarea del maintenance_loop (por ejemplo desde la GUI), contiene su nombre.
+/// - RebroadcastTransactions: Volver a enviar a los peers transacciones que creamos que siguen pendientes, contiene sus hashes.
+/// - Rpc: Pedido del RPC (ver RpcServer), contiene la llamada y el sender por el que se devuelve su resultado en JSON.
+/// - ConfigChanged: Se recargo el archivo de configuracion, contiene los valores que cambiaron y se aplican sin reiniciar (ver ConfigReloader).
 /// - Terminate: Termina el nodo.
 pub enum NodeAction {
-    PeerError(SocketAddrV6),
-    NewHeaders(Headers),
+    PeerError(SocketAddrV6, DisconnectReason),
+    NewHeaders(SocketAddrV6, Headers),
     GetHeadersError,
-    Block((Vec<u8>, Block)),
-    GetDataError(Vec<Inventory>),
-    PendingTransaction(Transaction),
-    MakeTransaction((HashMap<String, u64>, u64)),
+    Block((Vec<u8>, Arc<Block>)),
+    FilteredBlock((Vec<u8>, Arc<Block>)),
+    GetDataError(Arc<[Inventory]>),
+    NotFound(Arc<[Inventory]>),
+    Rejected(SocketAddrV6, Reject),
+    PendingTransaction(Arc<Transaction>),
+    MakeTransaction((Vec<(String, u64)>, u64, TransactionOptions)),
+    ReplaceTransaction((Vec<u8>, u64)),
     SendHeaders(SocketAddrV6),
     GetHeaders(SocketAddrV6, GetHeaders),
+    GetBlocks(SocketAddrV6, GetBlocks),
     GetData(SocketAddrV6, GetData),
+    CompactBlock(SocketAddrV6, CompactBlock),
+    BlockTxn(SocketAddrV6, BlockTxn),
+    Mempool(SocketAddrV6),
+    Pong(SocketAddrV6, u64),
+    TransactionsAnnounced(SocketAddrV6, Vec<Vec<u8>>),
+    InventoryAnnounced(SocketAddrV6, Vec<Inventory>),
+    PeerMessageReceived(SocketAddrV6, usize),
+    NewAddresses(Vec<SocketAddrV6>),
+    FilterLoad(SocketAddrV6, Option<BloomFilter>),
+    FilterAdd(SocketAddrV6, Vec<u8>),
+    BehindNetwork,
+    RunMaintenanceTask(String),
+    RebroadcastTransactions(Vec<Vec<u8>>),
+    Rpc((RpcCall, mpsc::Sender<Result<String, CustomError>>)),
+    ConfigChanged(ConfigDelta),
     Terminate,
 }
 
@@ -61,12 +117,14 @@ const START_DATE_IBD: u32 = 1681095630;
 /// - peer_action_sender: Sender para enviar acciones al los peers.
 /// - logger_sender: Sender para enviar logs al logger.
 /// - node_state_ref: Referencia al estado del nodo.
+/// - partial_blocks: Compact blocks a los que les faltan transacciones, esperando la respuesta al getblocktxn.
 pub struct NodeActionLoop {
     gui_sender: glib::Sender<GUIEvents>,
     node_action_receiver: mpsc::Receiver<NodeAction>,
-    peer_action_sender: mpsc::Sender<PeerAction>,
+    peer_action_sender: PeerActionSender,
     logger_sender: mpsc::Sender<Log>,
     node_state_ref: Arc<Mutex<NodeState>>,
+    partial_blocks: HashMap<Vec<u8>, PartialBlock>,
 }
 
 impl NodeActionLoop {
@@ -74,7 +132,7 @@ impl NodeActionLoop {
     pub fn start(
         gui_sender: glib::Sender<GUIEvents>,
         node_action_receiver: mpsc::Receiver<NodeAction>,
-        peer_action_sender: mpsc::Sender<PeerAction>,
+        peer_action_sender: PeerActionSender,
         logger_sender: mpsc::Sender<Log>,
         node_state_ref: Arc<Mutex<NodeState>>,
     ) {
@@ -84,6 +142,7 @@ impl NodeActionLoop {
             peer_action_sender,
             logger_sender,
             node_state_ref,
+            partial_blocks: HashMap::new(),
         };
         node_thread.event_loop();
     }
@@ -91,13 +150,23 @@ impl NodeActionLoop {
     fn event_loop(&mut self) {
         while let Ok(message) = self.node_action_receiver.recv() {
             let response = match message {
-                NodeAction::PeerError(address) => self.handle_peer_error(address),
+                NodeAction::PeerError(address, reason) => self.handle_peer_error(address, reason),
                 NodeAction::Block((block_hash, block)) => self.handle_block(block_hash, block),
-                NodeAction::NewHeaders(new_headers) => self.handle_new_headers(new_headers),
+                NodeAction::FilteredBlock((block_hash, block)) => {
+                    self.handle_filtered_block(block_hash, block)
+                }
+                NodeAction::NewHeaders(address, new_headers) => {
+                    self.handle_new_headers(address, new_headers)
+                }
                 NodeAction::GetHeadersError => self.handle_get_headers_error(),
                 NodeAction::GetDataError(inventory) => self.handle_get_data_error(inventory),
-                NodeAction::MakeTransaction((outputs, fee)) => {
-                    self.handle_make_transaction(outputs, fee)
+                NodeAction::NotFound(inventories) => self.handle_not_found(inventories),
+                NodeAction::Rejected(address, reject) => self.handle_rejected(address, reject),
+                NodeAction::MakeTransaction((outputs, fee, options)) => {
+                    self.handle_make_transaction(outputs, fee, options)
+                }
+                NodeAction::ReplaceTransaction((tx_hash, new_fee)) => {
+                    self.handle_replace_transaction(tx_hash, new_fee)
                 }
                 NodeAction::PendingTransaction(transaction) => {
                     self.handle_pending_transaction(transaction)
@@ -106,7 +175,35 @@ impl NodeActionLoop {
                 NodeAction::GetHeaders(address, getheaders) => {
                     self.handle_get_headers(address, getheaders)
                 }
+                NodeAction::GetBlocks(address, getblocks) => {
+                    self.handle_get_blocks(address, getblocks)
+                }
                 NodeAction::GetData(address, getdata) => self.handle_get_data(address, getdata),
+                NodeAction::CompactBlock(address, compact_block) => {
+                    self.handle_compact_block(address, compact_block)
+                }
+                NodeAction::BlockTxn(_, block_txn) => self.handle_block_txn(block_txn),
+                NodeAction::Mempool(address) => self.handle_mempool(address),
+                NodeAction::Pong(address, nonce) => self.handle_pong(address, nonce),
+                NodeAction::TransactionsAnnounced(address, tx_hashes) => {
+                    self.handle_transactions_announced(address, tx_hashes)
+                }
+                NodeAction::InventoryAnnounced(address, inventories) => {
+                    self.handle_inventory_announced(address, inventories)
+                }
+                NodeAction::PeerMessageReceived(address, bytes) => {
+                    self.handle_peer_message_received(address, bytes)
+                }
+                NodeAction::NewAddresses(addresses) => self.handle_new_addresses(addresses),
+                NodeAction::FilterLoad(address, filter) => self.handle_filter_load(address, filter),
+                NodeAction::FilterAdd(address, data) => self.handle_filter_add(address, data),
+                NodeAction::BehindNetwork => self.handle_behind_network(),
+                NodeAction::RunMaintenanceTask(name) => self.handle_run_maintenance_task(name),
+                NodeAction::RebroadcastTransactions(tx_hashes) => {
+                    self.handle_rebroadcast_transactions(tx_hashes)
+                }
+                NodeAction::Rpc((call, response_sender)) => self.handle_rpc(call, response_sender),
+                NodeAction::ConfigChanged(delta) => self.handle_config_changed(delta),
                 NodeAction::Terminate => break,
             };
 
@@ -117,48 +214,194 @@ impl NodeActionLoop {
                 );
             }
         }
+
+        if let Err(error) = self.handle_terminate() {
+            send_log(
+                &self.logger_sender,
+                Log::Message(format!("Error saving download session: {error}")),
+            );
+        }
+    }
+
+    /// Guarda la sesion de descarga de los bloques pendientes antes de cerrar el nodo.
+    fn handle_terminate(&mut self) -> Result<(), CustomError> {
+        let node_state = self.node_state_ref.lock()?;
+        node_state.save_download_session()
+    }
+
+    fn handle_peer_error(
+        &mut self,
+        address: SocketAddrV6,
+        reason: DisconnectReason,
+    ) -> Result<(), CustomError> {
+        let mut node_state = self.node_state_ref.lock()?;
+        node_state.remove_peer(address, reason)
     }
 
-    fn handle_peer_error(&mut self, address: SocketAddrV6) -> Result<(), CustomError> {
+    fn handle_pong(&mut self, address: SocketAddrV6, nonce: u64) -> Result<(), CustomError> {
         let mut node_state = self.node_state_ref.lock()?;
-        send_log(
-            &self.logger_sender,
-            Log::Message(format!("Deleting Peer {} from list...", address)),
-        );
-        node_state.remove_peer(address);
+        node_state.peer_pong(address, nonce)
+    }
+
+    fn handle_transactions_announced(
+        &mut self,
+        address: SocketAddrV6,
+        tx_hashes: Vec<Vec<u8>>,
+    ) -> Result<(), CustomError> {
+        let mut node_state = self.node_state_ref.lock()?;
+        node_state.transactions_announced(address, &tx_hashes)
+    }
+
+    /// Le pide al peer con getdata los inventarios anunciados que todavia no se le pidieron a ningun otro peer.
+    fn handle_inventory_announced(
+        &mut self,
+        address: SocketAddrV6,
+        inventories: Vec<Inventory>,
+    ) -> Result<(), CustomError> {
+        let mut node_state = self.node_state_ref.lock()?;
+        let inventories = node_state.inventories_to_request(address, inventories);
+        drop(node_state);
+        if inventories.is_empty() {
+            return Ok(());
+        }
+        self.send_message(address, GetData::new(inventories))
+    }
+
+    fn handle_peer_message_received(
+        &mut self,
+        address: SocketAddrV6,
+        bytes: usize,
+    ) -> Result<(), CustomError> {
+        let mut node_state = self.node_state_ref.lock()?;
+        node_state.peer_message_received(address, bytes);
         Ok(())
     }
 
     fn handle_make_transaction(
         &mut self,
-        outputs: HashMap<String, u64>,
+        outputs: Vec<(String, u64)>,
         fee: u64,
+        options: TransactionOptions,
     ) -> Result<(), CustomError> {
         let mut node_state = self.node_state_ref.lock()?;
-        let transaction = match node_state.make_transaction(outputs, fee) {
+        let transaction = match node_state.make_transaction(outputs, fee, options) {
             Ok(transaction) => transaction,
             Err(error) => {
                 send_log(&self.logger_sender, Log::Error(error));
                 return Ok(());
             }
         };
-        drop(node_state);
+        if !self.broadcast_own_transaction(node_state, &transaction)? {
+            self.discard_unsent_transaction(&transaction)?;
+        }
+        Ok(())
+    }
 
-        self.broadcast(transaction.clone())?;
+    fn handle_replace_transaction(
+        &mut self,
+        tx_hash: Vec<u8>,
+        new_fee: u64,
+    ) -> Result<(), CustomError> {
+        let mut node_state = self.node_state_ref.lock()?;
+        let transaction = match node_state.replace_transaction(&tx_hash, new_fee) {
+            Ok(transaction) => transaction,
+            Err(error) => {
+                send_log(&self.logger_sender, Log::Error(error));
+                return Ok(());
+            }
+        };
+        self.broadcast_own_transaction(node_state, &transaction)?;
+        Ok(())
+    }
+
+    /// Ejecuta un pedido del RPC y le devuelve el resultado al RpcServer.
+    /// Si el pedido creo una transaccion la envia a los peers antes de responder, como las que se crean desde la GUI,
+    /// y si no se le pudo enviar a ningun peer la deshace y responde con el error.
+    /// Si agrego un bloque se lo anuncia a los peers como a los bloques que llegan de la red.
+    fn handle_rpc(
+        &mut self,
+        call: RpcCall,
+        response_sender: mpsc::Sender<Result<String, CustomError>>,
+    ) -> Result<(), CustomError> {
+        let mut node_state = self.node_state_ref.lock()?;
+        let response = match call.execute(&mut node_state) {
+            Ok(RpcResponse {
+                result,
+                transaction: Some(transaction),
+                ..
+            }) => match self.broadcast_own_transaction(node_state, &transaction)? {
+                true => Ok(result),
+                false => Err(self.discard_unsent_transaction(&transaction)?),
+            },
+            Ok(RpcResponse {
+                result,
+                block_header: Some(block_header),
+                ..
+            }) => {
+                drop(node_state);
+                self.broadcast_new_header(block_header)?;
+                Ok(result)
+            }
+            Ok(response) => Ok(response.result),
+            Err(error) => Err(error),
+        };
+        // si el cliente cerro la conexion nadie espera la respuesta
+        let _ = response_sender.send(response);
+        Ok(())
+    }
+
+    /// Envia a los peers una transaccion que acabamos de crear y avisa a la GUI.
+    /// Devuelve false, sin avisar a la GUI, si habia peers a los que enviarla y no se le pudo enviar a ninguno.
+    fn broadcast_own_transaction(
+        &self,
+        mut node_state: std::sync::MutexGuard<'_, NodeState>,
+        transaction: &Transaction,
+    ) -> Result<bool, CustomError> {
+        let targets = node_state.broadcast_targets().len();
+        let failed_peers = node_state.push_transaction(transaction);
+        let sent = targets == 0 || failed_peers.len() < targets;
+        for (address, reason) in failed_peers {
+            node_state.remove_peer(address, reason)?;
+            send_log(
+                &self.logger_sender,
+                Log::Message(format!("Error sending message tx to peer {}", address)),
+            );
+        }
+        drop(node_state);
+        if !sent {
+            return Ok(false);
+        }
 
         send_log(
             &self.logger_sender,
             Log::Message("Transaction broadcasted!".to_string()),
         );
 
-        let mut node_state = self.node_state_ref.lock()?;
-        node_state.append_pending_tx(transaction)?;
         self.gui_sender.send(GUIEvents::TransactionSent)?;
 
-        Ok(())
+        Ok(true)
+    }
+
+    /// Deshace una transaccion que creamos y que no se le pudo enviar a ningun peer (ver NodeState::discard_unsent_transaction),
+    /// registrando el error. Devuelve CustomError::TransactionNotSent para informarlo a quien la pidio.
+    fn discard_unsent_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<CustomError, CustomError> {
+        self.node_state_ref
+            .lock()?
+            .discard_unsent_transaction(&transaction.hash())?;
+        send_log(
+            &self.logger_sender,
+            Log::Error(CustomError::TransactionNotSent),
+        );
+        Ok(CustomError::TransactionNotSent)
     }
 
-    fn handle_get_data_error(&mut self, inventory: Vec<Inventory>) -> Result<(), CustomError> {
+    fn handle_get_data_error(&mut self, inventory: Arc<[Inventory]>) -> Result<(), CustomError> {
+        self.node_state_ref
+            .lock()?
+            .inventories_not_found(&inventory);
         send_log(
             &self.logger_sender,
             Log::Message("Error requesting data,trying with another peer...".to_string()),
@@ -169,6 +412,44 @@ impl NodeActionLoop {
         Ok(())
     }
 
+    /// Vuelve a pedir a los peers los inventarios a los que un peer respondio notfound, salvo los que ya no se
+    /// vuelven a pedir (ver NodeState::data_not_found).
+    fn handle_not_found(&mut self, inventories: Arc<[Inventory]>) -> Result<(), CustomError> {
+        let to_retry = self.node_state_ref.lock()?.data_not_found(&inventories)?;
+        if to_retry.is_empty() {
+            return Ok(());
+        }
+        send_log(
+            &self.logger_sender,
+            Log::Message(format!(
+                "Peer sent notfound for {} inventories, trying with another peer...",
+                to_retry.len()
+            )),
+        );
+        self.peer_action_sender
+            .send(PeerAction::GetData(to_retry.into()))?;
+        Ok(())
+    }
+
+    /// Registra en el log los mensajes que rechazo un peer, tanto transacciones como bloques.
+    /// Si rechazo una transaccion que creamos y que le enviamos, deja de estar pendiente (ver NodeState::transaction_rejected).
+    fn handle_rejected(
+        &mut self,
+        address: SocketAddrV6,
+        reject: Reject,
+    ) -> Result<(), CustomError> {
+        send_log(
+            &self.logger_sender,
+            Log::Message(format!("Peer {address} sent reject: {}", reject.describe())),
+        );
+        if reject.message == "tx" {
+            self.node_state_ref
+                .lock()?
+                .transaction_rejected(address, &reject)?;
+        }
+        Ok(())
+    }
+
     fn handle_get_headers_error(&mut self) -> Result<(), CustomError> {
         let node_state = self.node_state_ref.lock()?;
         let last_header = node_state.get_last_header_hash();
@@ -184,15 +465,89 @@ impl NodeActionLoop {
         Ok(())
     }
 
-    fn handle_new_headers(&mut self, new_headers: Headers) -> Result<(), CustomError> {
+    /// Prioriza la descarga de headers pidiendole a los peers los siguientes a nuestro ultimo header.
+    fn handle_behind_network(&mut self) -> Result<(), CustomError> {
+        let node_state = self.node_state_ref.lock()?;
+        let last_header = node_state.get_last_header_hash();
+        let blocks_behind = node_state.get_blocks_behind();
+        drop(node_state);
+
+        send_log(
+            &self.logger_sender,
+            Log::Message(format!(
+                "Node is {} blocks behind the network, requesting headers...",
+                blocks_behind
+            )),
+        );
+
+        self.peer_action_sender
+            .send(PeerAction::GetHeaders(last_header))?;
+        Ok(())
+    }
+
+    /// Pide al maintenance_loop que ejecute la tarea ahora, sin esperar a su proxima ejecucion.
+    fn handle_new_addresses(&mut self, addresses: Vec<SocketAddrV6>) -> Result<(), CustomError> {
         let mut node_state = self.node_state_ref.lock()?;
-        node_state.append_headers(&new_headers)?;
+        let added = node_state.add_announced_addresses(&addresses)?;
+        drop(node_state);
+
+        if added > 0 {
+            send_log(
+                &self.logger_sender,
+                Log::Message(format!(
+                    "Learned {} new peer addresses ({} announced)",
+                    added,
+                    addresses.len()
+                )),
+            );
+        }
+        Ok(())
+    }
+
+    fn handle_run_maintenance_task(&mut self, name: String) -> Result<(), CustomError> {
+        let node_state = self.node_state_ref.lock()?;
+        node_state.run_maintenance_task(&name)?;
+        drop(node_state);
+
+        send_log(
+            &self.logger_sender,
+            Log::Message(format!("Running maintenance task {} now", name)),
+        );
+        Ok(())
+    }
+
+    /// Aplica los valores recargados del config: el LOG_LEVEL lo aplica el logger y el resto el NodeState.
+    fn handle_config_changed(&mut self, delta: ConfigDelta) -> Result<(), CustomError> {
+        self.node_state_ref.lock()?.apply_config_delta(&delta)?;
+
+        send_log(
+            &self.logger_sender,
+            Log::Message(format!(
+                "Config reloaded, applied changes to {}",
+                delta.applied().join(", ")
+            )),
+        );
+        if let Some(level) = delta.log_level {
+            send_log(&self.logger_sender, Log::SetLevel(level));
+        }
+        Ok(())
+    }
+
+    fn handle_new_headers(
+        &mut self,
+        address: SocketAddrV6,
+        new_headers: Headers,
+    ) -> Result<(), CustomError> {
+        let mut node_state = self.node_state_ref.lock()?;
+        let appended_headers = node_state.append_headers(&new_headers)?;
+        if !new_headers.headers.is_empty() {
+            node_state.peer_announced_tip(address);
+        }
         drop(node_state);
 
-        let headers_after_timestamp = &new_headers
-            .headers
+        let headers_after_timestamp = &appended_headers
             .iter()
-            .filter(|header| header.timestamp > START_DATE_IBD)
+            .filter(|header| header.timestamp > START_DATE_IBD && !header.block_downloaded)
             .collect::<Vec<_>>();
         let chunks: Vec<&[&BlockHeader]> = headers_after_timestamp.chunks(5).collect();
         for chunk in chunks {
@@ -205,25 +560,58 @@ impl NodeActionLoop {
         Ok(())
     }
 
+    /// Pide los bloques de los headers recibidos.
+    /// Si el nodo ya esta sincronizado los pide como compact blocks, ya que tiene la mayoria de sus transacciones pendientes.
     fn request_block(&mut self, headers: &[&BlockHeader]) -> Result<(), CustomError> {
         let mut node_state = self.node_state_ref.lock()?;
+        let inventory_type = match node_state.is_synced() {
+            true => InventoryType::CompactBlock,
+            false => InventoryType::Block,
+        };
 
-        let mut inventories = vec![];
-        for header in headers {
-            node_state.append_pending_block(header.hash().clone())?;
-            inventories.push(Inventory::new(InventoryType::Block, header.hash().clone()));
+        let requests = headers
+            .iter()
+            .map(|header| {
+                (
+                    Inventory::new(inventory_type.clone(), header.hash().clone()),
+                    None,
+                )
+            })
+            .collect();
+
+        let failed_peers = node_state.request_blocks(requests)?;
+        for (address, reason) in failed_peers {
+            node_state.remove_peer(address, reason)?;
+            send_log(
+                &self.logger_sender,
+                Log::Message(format!("Error sending message getdata to peer {}", address)),
+            );
         }
-
-        drop(node_state);
-
-        self.peer_action_sender
-            .send(PeerAction::GetData(inventories))?;
         Ok(())
     }
 
-    fn handle_block(&mut self, block_hash: Vec<u8>, block: Block) -> Result<(), CustomError> {
+    /// En spv_mode el bloque filtrado se procesa como cualquier bloque.
+    /// Si no, es parte del historial provisional de las wallets y se reconcilia cuando llegue el bloque completo.
+    fn handle_filtered_block(
+        &mut self,
+        block_hash: Vec<u8>,
+        block: Arc<Block>,
+    ) -> Result<(), CustomError> {
+        let mut node_state = self.node_state_ref.lock()?;
+        if node_state.is_spv_mode() {
+            drop(node_state);
+            return self.handle_block(block_hash, block);
+        }
+        node_state.append_provisional_block(&block)
+    }
+
+    /// Agrega el bloque si estaba pendiente. Si no, lo guarda como huerfano si todavia no se conoce su header.
+    fn handle_block(&mut self, block_hash: Vec<u8>, block: Arc<Block>) -> Result<(), CustomError> {
+        self.partial_blocks.remove(&block_hash);
+
         let mut node_state = self.node_state_ref.lock()?;
         if !node_state.is_block_pending(&block_hash)? {
+            node_state.append_orphan_block(block);
             drop(node_state);
             return Ok(());
         }
@@ -235,23 +623,138 @@ impl NodeActionLoop {
         drop(node_state);
 
         if is_synced {
-            self.broadcast_new_header(block.header)?;
+            self.broadcast_new_header(block.header.clone())?;
         }
         Ok(())
     }
 
-    fn handle_pending_transaction(&mut self, transaction: Transaction) -> Result<(), CustomError> {
+    /// Intenta reconstruir el bloque con las pending txs.
+    /// Si faltan transacciones se las pide al peer con getblocktxn, si el compact block es invalido se pide el bloque completo.
+    fn handle_compact_block(
+        &mut self,
+        address: SocketAddrV6,
+        compact_block: CompactBlock,
+    ) -> Result<(), CustomError> {
+        let block_hash = compact_block.header.hash().clone();
+
+        let node_state = self.node_state_ref.lock()?;
+        if !node_state.is_block_pending(&block_hash)? {
+            drop(node_state);
+            return Ok(());
+        }
+
+        let partial_block = match node_state.reconstruct_compact_block(&compact_block) {
+            Ok(partial_block) => partial_block,
+            Err(_) => {
+                drop(node_state);
+                return self.request_full_block(block_hash);
+            }
+        };
+
+        let missing_indexes = partial_block.missing_indexes();
+        if missing_indexes.is_empty() {
+            drop(node_state);
+            return self.handle_reconstructed_block(block_hash, partial_block, vec![]);
+        }
+
+        drop(node_state);
+        send_log(
+            &self.logger_sender,
+            Log::Message(format!(
+                "Requesting {} missing transactions of compact block",
+                missing_indexes.len()
+            )),
+        );
+        let message = GetBlockTxn::new(block_hash.clone(), missing_indexes);
+        self.send_message(address, message)?;
+
+        self.partial_blocks.insert(block_hash, partial_block);
+        Ok(())
+    }
+
+    fn handle_block_txn(&mut self, block_txn: BlockTxn) -> Result<(), CustomError> {
+        let Some(partial_block) = self.partial_blocks.remove(&block_txn.block_hash) else { return Ok(()) };
+        self.handle_reconstructed_block(block_txn.block_hash, partial_block, block_txn.transactions)
+    }
+
+    fn handle_reconstructed_block(
+        &mut self,
+        block_hash: Vec<u8>,
+        partial_block: PartialBlock,
+        missing_txs: Vec<Transaction>,
+    ) -> Result<(), CustomError> {
+        match partial_block.fill(missing_txs) {
+            Ok(block) if block.create_merkle_root().is_ok() => {
+                self.handle_block(block_hash, Arc::new(block))
+            }
+            _ => self.request_full_block(block_hash),
+        }
+    }
+
+    fn request_full_block(&mut self, block_hash: Vec<u8>) -> Result<(), CustomError> {
+        send_log(
+            &self.logger_sender,
+            Log::Message("Cannot reconstruct compact block, requesting full block...".to_string()),
+        );
+
+        self.peer_action_sender
+            .send(PeerAction::GetData(Arc::new([Inventory::new(
+                InventoryType::Block,
+                block_hash,
+            )])))?;
+        Ok(())
+    }
+
+    fn handle_pending_transaction(
+        &mut self,
+        transaction: Arc<Transaction>,
+    ) -> Result<(), CustomError> {
         let mut node_state = self.node_state_ref.lock()?;
-        if !node_state.is_synced() {
+        if !node_state.is_synced() || node_state.is_abandoned(&transaction.hash()) {
             drop(node_state);
             return Ok(());
         }
+        if let Err(error) = transaction.check_relay_limits(node_state.get_max_relay_tx_io()) {
+            drop(node_state);
+            send_log(
+                &self.logger_sender,
+                Log::Message(format!("Rejected relayed transaction: {error}")),
+            );
+            return Ok(());
+        }
 
-        let is_pending_new = node_state.append_pending_tx(transaction.clone())?;
+        let is_pending_new = node_state.append_pending_tx(Transaction::clone(&transaction))?;
         drop(node_state);
 
         if is_pending_new {
-            self.broadcast(transaction)?;
+            self.broadcast(transaction.as_ref())?;
+        }
+        Ok(())
+    }
+
+    /// Vuelve a enviar a todos los peers las transacciones que creamos, salvo las que ya no estan pendientes.
+    fn handle_rebroadcast_transactions(
+        &mut self,
+        tx_hashes: Vec<Vec<u8>>,
+    ) -> Result<(), CustomError> {
+        for tx_hash in tx_hashes {
+            let mut node_state = self.node_state_ref.lock()?;
+            let Some(transaction) = node_state.get_pending_tx(&tx_hash) else { continue };
+            let addresses: Vec<SocketAddrV6> = node_state
+                .get_peer_senders()
+                .iter()
+                .map(|peer| peer.address)
+                .collect();
+            node_state.mark_transaction_sent(&tx_hash, &addresses);
+            drop(node_state);
+            self.broadcast(&transaction)?;
+            send_log(
+                &self.logger_sender,
+                Log::Message(format!(
+                    "Transaction {} not confirmed yet, broadcasted again",
+                    hash_as_string(tx_hash)
+                )),
+            );
         }
         Ok(())
     }
@@ -262,6 +765,26 @@ impl NodeActionLoop {
         Ok(())
     }
 
+    fn handle_filter_load(
+        &mut self,
+        address: SocketAddrV6,
+        filter: Option<BloomFilter>,
+    ) -> Result<(), CustomError> {
+        let mut node_state = self.node_state_ref.lock()?;
+        node_state.peer_filter_load(address, filter);
+        Ok(())
+    }
+
+    fn handle_filter_add(
+        &mut self,
+        address: SocketAddrV6,
+        data: Vec<u8>,
+    ) -> Result<(), CustomError> {
+        let mut node_state = self.node_state_ref.lock()?;
+        node_state.peer_filter_add(address, &data);
+        Ok(())
+    }
+
     fn handle_get_headers(
         &mut self,
         address: SocketAddrV6,
@@ -269,10 +792,52 @@ impl NodeActionLoop {
     ) -> Result<(), CustomError> {
         let mut node_state = self.node_state_ref.lock()?;
         node_state.peer_requested_headers(address);
-        let headers = node_state.get_headers(getheaders);
+        let entries = node_state.get_serialized_headers(getheaders);
+        drop(node_state);
 
-        let message = Headers { headers };
-        send_message(&mut node_state, address, message)
+        let message = RawHeaders::new(entries)?;
+        self.send_message(address, message)
+    }
+
+    /// Responde un mensaje getblocks con un inv de los bloques siguientes al block locator.
+    fn handle_get_blocks(
+        &mut self,
+        address: SocketAddrV6,
+        getblocks: GetBlocks,
+    ) -> Result<(), CustomError> {
+        let inventories: Vec<Inventory> = self
+            .node_state_ref
+            .lock()?
+            .get_block_hashes(&getblocks)
+            .into_iter()
+            .map(|block_hash| Inventory::new(InventoryType::Block, block_hash))
+            .collect();
+
+        if inventories.is_empty() {
+            return Ok(());
+        }
+        self.send_message(address, Inv::new(inventories))
+    }
+
+    /// Responde un mensaje mempool enviando al peer los hashes de las pending txs,
+    /// en mensajes inv de a lo sumo MAX_INV_ENTRIES inventories.
+    /// Durante el IBD no se responde, ya que las transacciones pendientes no se pueden validar.
+    fn handle_mempool(&mut self, address: SocketAddrV6) -> Result<(), CustomError> {
+        let node_state = self.node_state_ref.lock()?;
+        if !node_state.is_synced() {
+            return Ok(());
+        }
+        let tx_hashes = node_state.get_pending_tx_hashes();
+        drop(node_state);
+
+        for chunk in tx_hashes.chunks(MAX_INV_ENTRIES) {
+            let inventories = chunk
+                .iter()
+                .map(|tx_hash| Inventory::new(InventoryType::Tx, tx_hash.clone()))
+                .collect();
+            self.send_message(address, Inv::new(inventories))?;
+        }
+        Ok(())
     }
 
     fn handle_get_data(
@@ -280,70 +845,86 @@ impl NodeActionLoop {
         address: SocketAddrV6,
         getdata: GetData,
     ) -> Result<(), CustomError> {
-        let mut node_state = self.node_state_ref.lock()?;
         for inventory in getdata.get_inventories() {
+            // el lock se toma para buscar cada inventory y se suelta antes de enviarla
+            let mut node_state = self.node_state_ref.lock()?;
             match inventory.inventory_type {
-                InventoryType::Block => {
-                    match node_state.get_block(hash_as_string(inventory.hash.clone())) {
-                        Ok(block) => send_message(&mut node_state, address, block)?,
-                        Err(_) => {
-                            let not_found = NotFound::new(vec![inventory.clone()]);
-                            send_message(&mut node_state, address, not_found)?;
-                        }
+                // en spv_mode los bloques guardados solo tienen nuestras transacciones, no se envian
+                InventoryType::Block if !node_state.is_spv_mode() => {
+                    let block_file = node_state.get_block_file(&inventory.hash);
+                    drop(node_state);
+                    match block_file {
+                        Ok(mut block_file) => self.send_block_file(address, &mut block_file)?,
+                        Err(_) => self.send_not_found(address, inventory)?,
                     }
                 }
-                InventoryType::Tx => {
-                    match node_state.get_pending_tx(&inventory.hash) {
-                        Some(tx) => send_message(&mut node_state, address, tx)?,
-                        None => {
-                            let not_found = NotFound::new(vec![inventory.clone()]);
-                            send_message(&mut node_state, address, not_found)?;
+                // se responde con un merkleblock seguido de las transacciones que coinciden con el filtro del peer
+                InventoryType::FilteredBlock if !node_state.is_spv_mode() => {
+                    let filtered_block = node_state.get_filtered_block(address, &inventory.hash);
+                    drop(node_state);
+                    match filtered_block {
+                        Ok((merkle_block, transactions)) => {
+                            self.send_message(address, merkle_block)?;
+                            for transaction in transactions {
+                                self.send_message(address, transaction)?;
+                            }
                         }
+                        Err(_) => self.send_not_found(address, inventory)?,
+                    }
+                }
+                // durante el IBD los bloques se siguen enviando, pero las transacciones no
+                InventoryType::Tx if node_state.is_synced() => {
+                    let tx = node_state.get_pending_tx(&inventory.hash);
+                    drop(node_state);
+                    match tx {
+                        Some(tx) => self.send_message(address, tx)?,
+                        None => self.send_not_found(address, inventory)?,
                     };
                 }
                 _ => {
-                    let not_found = NotFound::new(vec![inventory.clone()]);
-                    send_message(&mut node_state, address, not_found)?;
+                    drop(node_state);
+                    self.send_not_found(address, inventory)?;
                 }
             }
         }
         Ok(())
     }
 
-    fn broadcast(&mut self, message: impl Message) -> Result<(), CustomError> {
-        let mut node_state = self.node_state_ref.lock()?;
+    /// Envia el mensaje a todos los peers. Los envios se hacen despues de soltar el lock del NodeState (ver PeerSender),
+    /// para que un peer que no lee su socket no bloquee al resto del nodo mientras dura el envio.
+    fn broadcast(&mut self, message: &impl Message) -> Result<(), CustomError> {
+        let peers = self.node_state_ref.lock()?.get_peer_senders();
 
-        let peers = node_state.get_peers();
-        let mut peers_to_remove = vec![];
+        let mut failed_peers = vec![];
         for peer in peers {
-            if message.send(&mut peer.stream).is_err() {
-                peers_to_remove.push(peer.address);
+            if let Err(error) = peer.send(message) {
+                failed_peers.push((peer.address, DisconnectReason::from_error(&error)));
+                send_log(
+                    &self.logger_sender,
+                    Log::Message(format!(
+                        "Error sending message {} to peer {}",
+                        message.get_command(),
+                        peer.address,
+                    )),
+                );
             }
         }
-
-        for address in peers_to_remove {
-            node_state.remove_peer(address);
-            send_log(
-                &self.logger_sender,
-                Log::Message(format!(
-                    "Error sending message {} to peer {}",
-                    message.get_command(),
-                    address,
-                )),
-            );
-        }
-
-        Ok(())
+        self.remove_failed_peers(failed_peers)
     }
 
+    /// Anuncia el nuevo header a los peers que ya nos pidieron headers, con un mensaje headers o con un inv
+    /// segun lo que soporte cada uno. Como en broadcast, los envios se hacen sin el lock del NodeState.
     fn broadcast_new_header(&self, header: BlockHeader) -> Result<(), CustomError> {
         let mut node_state = self.node_state_ref.lock()?;
         let headers_to_send = node_state.get_headers_to_send(header.hash());
         if headers_to_send.is_empty() {
             return Ok(());
         }
-        let mut peers_to_remove = vec![];
-        for peer in node_state.get_peers() {
+        let peers = node_state.get_peer_senders();
+        drop(node_state);
+
+        let mut failed_peers = vec![];
+        for peer in peers {
             if !peer.requested_headers {
                 continue;
             }
@@ -351,37 +932,71 @@ impl NodeActionLoop {
                 let headers_msg = Headers {
                     headers: headers_to_send.clone(),
                 };
-                headers_msg.send(&mut peer.stream)
+                peer.send(&headers_msg)
             } else {
                 let mut inventories = vec![];
                 for header in &headers_to_send {
                     inventories.push(Inventory::new(InventoryType::Block, header.hash().clone()));
                 }
                 let inv_msg = Inv::new(inventories);
-                inv_msg.send(&mut peer.stream)
+                peer.send(&inv_msg)
             };
-            if sent.is_err() {
-                peers_to_remove.push(peer.address);
+            if let Err(error) = sent {
+                failed_peers.push((peer.address, DisconnectReason::from_error(&error)));
             }
         }
+        self.remove_failed_peers(failed_peers)
+    }
 
-        for address in peers_to_remove {
-            node_state.remove_peer(address);
+    /// Envia un mensaje a un peer despues de soltar el lock del NodeState. Si no se le puede enviar, se desconecta al peer.
+    fn send_message(
+        &self,
+        address: SocketAddrV6,
+        message: impl Message,
+    ) -> Result<(), CustomError> {
+        let Some(peer) = self.node_state_ref.lock()?.get_peer_sender(&address) else { return Ok(()) };
+        if let Err(error) = peer.send(&message) {
+            self.remove_failed_peers(vec![(address, DisconnectReason::from_error(&error))])?;
+        }
+        Ok(())
+    }
+
+    /// Envia al peer un bloque guardado en disco por fragmentos, sin cargarlo completo en memoria.
+    /// Como send_message, lo envia sin el lock del NodeState y si no se le puede enviar se desconecta al peer.
+    fn send_block_file(
+        &self,
+        address: SocketAddrV6,
+        block_file: &mut BlockReader,
+    ) -> Result<(), CustomError> {
+        let Some(peer) = self.node_state_ref.lock()?.get_peer_sender(&address) else { return Ok(()) };
+        if let Err(error) = peer.send_streamed("block", block_file) {
+            self.remove_failed_peers(vec![(address, DisconnectReason::from_error(&error))])?;
         }
         Ok(())
     }
-}
 
-fn send_message(
-    node_state: &mut std::sync::MutexGuard<'_, NodeState>,
-    address: SocketAddrV6,
-    message: impl Message,
-) -> Result<(), CustomError> {
-    let peer = node_state.get_peer(&address);
-    if let Some(peer) = peer {
-        if peer.send(message).is_err() {
-            node_state.remove_peer(address);
+    /// Le responde al peer con un notfound de la inventory que pidio.
+    fn send_not_found(
+        &self,
+        address: SocketAddrV6,
+        inventory: &Inventory,
+    ) -> Result<(), CustomError> {
+        self.send_message(address, NotFound::new(vec![inventory.clone()]))
+    }
+
+    /// Desconecta a los peers a los que fallo un envio, como ante un NodeAction::PeerError (ver handle_peer_error).
+    /// El lock del NodeState se vuelve a tomar solo si hay peers para desconectar.
+    fn remove_failed_peers(
+        &self,
+        failed_peers: Vec<(SocketAddrV6, DisconnectReason)>,
+    ) -> Result<(), CustomError> {
+        if failed_peers.is_empty() {
+            return Ok(());
+        }
+        let mut node_state = self.node_state_ref.lock()?;
+        for (address, reason) in failed_peers {
+            node_state.remove_peer(address, reason)?;
         }
+        Ok(())
     }
-    Ok(())
 }
@@ -1,31 +1,131 @@
 use std::{
-    net::{SocketAddrV6, TcpStream},
-    sync::{mpsc, Arc, Mutex},
+    net::SocketAddrV6,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex, TryLockError,
+    },
     thread::{self, JoinHandle},
+    time::Duration,
 };
 
 use crate::{
     error::CustomError,
     logger::{send_log, Log},
-    message::Message,
     messages::{get_data::GetData, transaction::Transaction},
-    peer::request_headers,
+    peer::{request_headers, DisconnectReason, PeerConnection, PeerSender},
     structs::inventory::Inventory,
 };
 
 use super::node_action_loop::NodeAction;
 
+/// Cada cuanto el loop deja de esperar acciones para revisar si el nodo cerro la conexion con el peer.
+const STOPPED_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
 /// PeerAction es una enumeracion de las acciones que puede realizar un peer.
+/// Todas las acciones son trabajo para un unico peer: la realiza el primero que la toma de la cola compartida
+/// (ver PeerActionReceiver), por lo que no sirven para avisarle algo a todos los peers.
+/// Para terminar los PeerActionLoop no se encola nada: cada peer se detiene con Peer::stop.
 /// Las acciones son:
 /// - GetHeaders: Solicita headers al peer.
-/// - GetData: Solicita data al peer.
+/// - GetData: Solicita data al peer. Los inventories se comparten en un Arc para poder reintentarlos sin copiarlos.
 /// - SendTransaction: Envia una transaccion al peer.
-/// - Terminate: Termina la conexion con el peer.
 pub enum PeerAction {
     GetHeaders(Option<Vec<u8>>),
-    GetData(Vec<Inventory>),
+    GetData(Arc<[Inventory]>),
     SendTransaction(Transaction),
-    Terminate,
+}
+
+#[derive(Default)]
+/// Cantidad de PeerAction encoladas y consumidas, para saber cuantas esperan un peer (ver PeerActionReceiver::pending).
+struct PeerActionCounters {
+    sent: AtomicUsize,
+    consumed: AtomicUsize,
+}
+
+/// Crea la cola de PeerAction compartida por todos los peers y devuelve sus dos extremos.
+pub fn peer_action_channel() -> (PeerActionSender, PeerActionReceiver) {
+    let (sender, receiver) = mpsc::channel();
+    let counters = Arc::new(PeerActionCounters::default());
+    (
+        PeerActionSender {
+            sender,
+            counters: counters.clone(),
+        },
+        PeerActionReceiver {
+            receiver: Arc::new(Mutex::new(receiver)),
+            counters,
+        },
+    )
+}
+
+#[derive(Clone)]
+/// PeerActionSender encola PeerAction para que las realice alguno de los peers.
+pub struct PeerActionSender {
+    sender: mpsc::Sender<PeerAction>,
+    counters: Arc<PeerActionCounters>,
+}
+
+impl PeerActionSender {
+    /// Encola una accion, que realizara el primer peer que la tome.
+    pub fn send(&self, action: PeerAction) -> Result<(), CustomError> {
+        self.counters.sent.fetch_add(1, Ordering::Relaxed);
+        if let Err(error) = self.sender.send(action) {
+            self.counters.sent.fetch_sub(1, Ordering::Relaxed);
+            return Err(error.into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+/// PeerActionReceiver es el extremo de la cola de PeerAction que comparten los PeerActionLoop de todos los peers.
+/// Cada accion la consume un unico peer.
+pub struct PeerActionReceiver {
+    receiver: Arc<Mutex<mpsc::Receiver<PeerAction>>>,
+    counters: Arc<PeerActionCounters>,
+}
+
+impl PeerActionReceiver {
+    /// Espera hasta timeout la proxima accion de la cola. Devuelve None si no llego ninguna
+    /// o si otro peer esta esperando en la cola (en ese caso solo espera timeout).
+    /// Si un peer entro en panico mientras esperaba, el lock queda envenenado: como el receiver no queda
+    /// en un estado invalido, se recupera el lock para que el resto de los peers sigan consumiendo acciones.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<PeerAction>, CustomError> {
+        let receiver = match self.receiver.try_lock() {
+            Ok(receiver) => receiver,
+            Err(TryLockError::WouldBlock) => {
+                thread::sleep(timeout);
+                return Ok(None);
+            }
+            Err(TryLockError::Poisoned(poisoned)) => {
+                self.receiver.clear_poison();
+                poisoned.into_inner()
+            }
+        };
+        match receiver.recv_timeout(timeout) {
+            Ok(action) => {
+                self.counters.consumed.fetch_add(1, Ordering::Relaxed);
+                Ok(Some(action))
+            }
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(CustomError::CannotReceiveMessageFromChannel(None))
+            }
+        }
+    }
+
+    /// Devuelve la cantidad de acciones encoladas que todavia no tomo ningun peer.
+    pub fn pending(&self) -> usize {
+        let consumed = self.counters.consumed.load(Ordering::Relaxed);
+        let sent = self.counters.sent.load(Ordering::Relaxed);
+        sent.saturating_sub(consumed)
+    }
+
+    /// Devuelve la cantidad de acciones que tomaron los peers desde que se creo la cola.
+    pub fn consumed(&self) -> usize {
+        self.counters.consumed.load(Ordering::Relaxed)
+    }
 }
 
 /// PeerActionLoop es una estructura que contiene los elementos necesarios para manejar los las acciones a enviar al peer asociado.
@@ -33,16 +133,18 @@ pub enum PeerAction {
 /// Los elementos son:
 /// - address: Direccion del peer.
 /// - version: Version del nodo.
-/// - stream: Stream del peer.
+/// - sender: PeerSender por el que se le escribe al peer, compartiendo el lock de escritura con el resto de los envios al peer.
+/// - connection: Estado de la conexion con el peer.
 /// - logger_sender: Sender para enviar logs al logger.
-/// - peer_action_receiver: Receiver para recibir acciones del peer.
+/// - peer_action_receiver: Cola compartida de la que se toman las acciones a realizar.
 /// - node_action_sender: Sender para enviar acciones al nodo.
 pub struct PeerActionLoop {
     pub address: SocketAddrV6,
     pub version: i32,
-    pub stream: TcpStream,
+    pub sender: PeerSender,
+    pub connection: Arc<PeerConnection>,
     pub logger_sender: mpsc::Sender<Log>,
-    pub peer_action_receiver: Arc<Mutex<mpsc::Receiver<PeerAction>>>,
+    pub peer_action_receiver: PeerActionReceiver,
     pub node_action_sender: mpsc::Sender<NodeAction>,
 }
 
@@ -51,9 +153,10 @@ impl PeerActionLoop {
     pub fn spawn(
         address: SocketAddrV6,
         version: i32,
-        stream: TcpStream,
+        sender: PeerSender,
+        connection: Arc<PeerConnection>,
         logger_sender: mpsc::Sender<Log>,
-        peer_action_receiver: Arc<Mutex<mpsc::Receiver<PeerAction>>>,
+        peer_action_receiver: PeerActionReceiver,
         node_action_sender: mpsc::Sender<NodeAction>,
     ) -> JoinHandle<Result<(), CustomError>> {
         thread::spawn(move || -> Result<(), CustomError> {
@@ -61,7 +164,8 @@ impl PeerActionLoop {
                 address,
                 peer_action_receiver,
                 version,
-                stream,
+                sender,
+                connection,
                 logger_sender,
                 node_action_sender,
             };
@@ -69,20 +173,26 @@ impl PeerActionLoop {
         })
     }
 
+    /// El receiver es compartido por todos los peers, por lo que cada STOPPED_CHECK_INTERVAL se revisa
+    /// que el nodo no haya cerrado la conexion, para no consumir acciones que deberia realizar otro peer
+    /// y para que el nodo pueda esperar a que el thread termine.
     fn event_loop(&mut self) -> Result<(), CustomError> {
         loop {
-            let peer_message = self
+            if self.connection.is_stopped() {
+                break;
+            }
+            let Some(peer_message) = self
                 .peer_action_receiver
-                .lock()
-                .map_err(|_| CustomError::CannotLockGuard)?
-                .recv()?;
+                .recv_timeout(STOPPED_CHECK_INTERVAL)?
+            else {
+                continue;
+            };
             let response = match peer_message {
                 PeerAction::GetHeaders(last_header) => self.handle_getheaders(last_header),
                 PeerAction::GetData(inventories) => self.handle_getdata(inventories),
                 PeerAction::SendTransaction(transaction) => {
                     self.handle_send_transaction(&transaction)
                 }
-                PeerAction::Terminate => break,
             };
 
             if let Err(error) = response {
@@ -90,8 +200,12 @@ impl PeerActionLoop {
                     &self.logger_sender,
                     Log::Message(format!("Error on PeerActionLoop: {error}")),
                 );
-                self.node_action_sender
-                    .send(NodeAction::PeerError(self.address))?;
+                if !self.connection.is_stopped() {
+                    self.node_action_sender.send(NodeAction::PeerError(
+                        self.address,
+                        DisconnectReason::from_error(&error),
+                    ))?;
+                }
                 break;
             }
         }
@@ -99,31 +213,83 @@ impl PeerActionLoop {
     }
 
     fn handle_send_transaction(&mut self, transaction: &Transaction) -> Result<(), CustomError> {
-        transaction.send(&mut self.stream)?;
+        self.sender.send(transaction)?;
         send_log(
             &self.logger_sender,
             Log::Message("Sending transaction".to_string()),
         );
         Ok(())
     }
-    fn handle_getdata(&mut self, inventories: Vec<Inventory>) -> Result<(), CustomError> {
-        let inventories_clone = inventories.clone();
-        let request = GetData::new(inventories).send(&mut self.stream);
-        if let Err(error) = request {
-            self.node_action_sender
-                .send(NodeAction::GetDataError(inventories_clone))?;
-            return Err(error);
-        };
-        Ok(())
+    fn handle_getdata(&mut self, inventories: Arc<[Inventory]>) -> Result<(), CustomError> {
+        let request = self.sender.send(&GetData::new(inventories.to_vec()));
+        match request {
+            Ok(()) => Ok(()),
+            Err(error) => {
+                self.node_action_sender
+                    .send(NodeAction::GetDataError(inventories))?;
+                Err(error)
+            }
+        }
     }
 
     fn handle_getheaders(&mut self, last_header: Option<Vec<u8>>) -> Result<(), CustomError> {
         request_headers(
             last_header,
             self.version,
-            &mut self.stream,
+            &self.sender,
             &self.logger_sender,
             &self.node_action_sender,
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peers_keep_consuming_after_one_panics_holding_the_queue() {
+        let (sender, receiver) = peer_action_channel();
+
+        // un peer entra en panico mientras espera acciones, envenenando el lock de la cola
+        let panicking_receiver = receiver.clone();
+        let panicked = thread::spawn(move || {
+            let _receiver = panicking_receiver.receiver.lock();
+            panic!("peer action loop panicked");
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert!(receiver.receiver.is_poisoned());
+
+        for _ in 0..10 {
+            sender.send(PeerAction::GetHeaders(None)).unwrap();
+        }
+        assert_eq!(receiver.pending(), 10);
+
+        let consumers: Vec<_> = (0..2)
+            .map(|_| {
+                let receiver = receiver.clone();
+                thread::spawn(move || {
+                    let mut consumed = 0;
+                    while receiver.pending() > 0 {
+                        if let Some(PeerAction::GetHeaders(_)) =
+                            receiver.recv_timeout(Duration::from_millis(10)).unwrap()
+                        {
+                            consumed += 1;
+                        }
+                    }
+                    consumed
+                })
+            })
+            .collect();
+        let consumed: usize = consumers
+            .into_iter()
+            .map(|consumer| consumer.join().unwrap())
+            .sum();
+
+        assert_eq!(consumed, 10);
+        assert_eq!(receiver.pending(), 0);
+        assert_eq!(receiver.consumed(), 10);
+        assert!(!receiver.receiver.is_poisoned());
+    }
+}
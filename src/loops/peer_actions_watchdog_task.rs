@@ -0,0 +1,140 @@
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::CustomError,
+    logger::{send_log, Log},
+    node_state::NodeState,
+};
+
+use super::{
+    maintenance_loop::MaintenanceTask, node_action_loop::NodeAction,
+    peer_action_loop::PeerActionReceiver,
+};
+
+/// Nombre con el que se registra la tarea que vigila la cola de PeerActions en el Scheduler del maintenance_loop.
+pub const PEER_ACTIONS_WATCHDOG_TASK: &str = "peer_actions_watchdog";
+
+/// Tiempo entre revisiones de la cola de PeerActions.
+pub const PEER_ACTIONS_WATCHDOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Tiempo sin que ningun peer tome una PeerAction, habiendo acciones encoladas, tras el cual se considera que la cola se trabo.
+pub const PEER_ACTIONS_STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// PeerActionsWatchdog detecta que los peers dejaron de consumir la cola de PeerActions a pesar de que tiene acciones.
+/// Los elementos son:
+/// - last_consumed: Cantidad de acciones consumidas en la ultima revision.
+/// - last_progress: Ultima revision en la que se consumio alguna accion o la cola estaba vacia.
+pub struct PeerActionsWatchdog {
+    last_consumed: usize,
+    last_progress: Instant,
+}
+
+impl PeerActionsWatchdog {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            last_consumed: 0,
+            last_progress: now,
+        }
+    }
+
+    /// Devuelve true si hay acciones pendientes y no se consumio ninguna durante PEER_ACTIONS_STALL_TIMEOUT.
+    /// Tras detectarlo vuelve a esperar PEER_ACTIONS_STALL_TIMEOUT antes de volver a avisar.
+    pub fn is_stalled(&mut self, pending: usize, consumed: usize, now: Instant) -> bool {
+        if pending == 0 || consumed != self.last_consumed {
+            self.last_consumed = consumed;
+            self.last_progress = now;
+            return false;
+        }
+        if now.duration_since(self.last_progress) < PEER_ACTIONS_STALL_TIMEOUT {
+            return false;
+        }
+        self.last_progress = now;
+        true
+    }
+}
+
+/// peer_actions_watchdog_task devuelve la tarea del maintenance_loop que vigila que los peers consuman las PeerActions.
+/// Si la cola se trabo lo loguea como error y vuelve a crear los threads de PeerActions de los peers conectados que terminaron.
+/// Los elementos son:
+/// - node_state_ref: Referencia al estado del nodo.
+/// - peer_action_receiver: Cola compartida de acciones de los peers.
+/// - node_action_sender: Sender para enviar acciones al nodo, lo necesitan los threads que se vuelven a crear.
+/// - logger_sender: Sender para enviar logs al logger.
+pub fn peer_actions_watchdog_task(
+    node_state_ref: Arc<Mutex<NodeState>>,
+    peer_action_receiver: PeerActionReceiver,
+    node_action_sender: mpsc::Sender<NodeAction>,
+    logger_sender: mpsc::Sender<Log>,
+) -> MaintenanceTask {
+    let mut watchdog = PeerActionsWatchdog::new(Instant::now());
+    Box::new(move || {
+        let pending = peer_action_receiver.pending();
+        if !watchdog.is_stalled(pending, peer_action_receiver.consumed(), Instant::now()) {
+            return Ok(());
+        }
+        let respawned = respawn_peer_action_threads(
+            &node_state_ref,
+            &peer_action_receiver,
+            &node_action_sender,
+            &logger_sender,
+        )?;
+
+        send_log(&logger_sender, Log::Error(CustomError::PeerActionsStalled));
+        send_log(
+            &logger_sender,
+            Log::Message(format!(
+                "No peer took any of the {pending} queued actions in {} seconds, restarted {respawned} peer action threads",
+                PEER_ACTIONS_STALL_TIMEOUT.as_secs()
+            )),
+        );
+        Ok(())
+    })
+}
+
+/// Vuelve a crear los threads de PeerActions que terminaron de los peers conectados y devuelve cuantos se crearon.
+fn respawn_peer_action_threads(
+    node_state_ref: &Arc<Mutex<NodeState>>,
+    peer_action_receiver: &PeerActionReceiver,
+    node_action_sender: &mpsc::Sender<NodeAction>,
+    logger_sender: &mpsc::Sender<Log>,
+) -> Result<usize, CustomError> {
+    let mut node_state = node_state_ref.lock()?;
+    let mut respawned = 0;
+    for peer in node_state.get_peers().iter_mut() {
+        if peer.respawn_action_thread(
+            peer_action_receiver.clone(),
+            node_action_sender.clone(),
+            logger_sender.clone(),
+        )? {
+            respawned += 1;
+        }
+    }
+    Ok(respawned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchdog_detects_stalled_queue() {
+        let start = Instant::now();
+        let mut watchdog = PeerActionsWatchdog::new(start);
+
+        // con la cola vacia nunca se considera trabada
+        assert!(!watchdog.is_stalled(0, 0, start + PEER_ACTIONS_STALL_TIMEOUT * 2));
+
+        let later = start + PEER_ACTIONS_STALL_TIMEOUT * 2;
+        assert!(!watchdog.is_stalled(3, 5, later));
+        assert!(!watchdog.is_stalled(3, 5, later + PEER_ACTIONS_STALL_TIMEOUT / 2));
+        assert!(watchdog.is_stalled(3, 5, later + PEER_ACTIONS_STALL_TIMEOUT));
+
+        // si se consume alguna accion vuelve a esperar el timeout completo
+        let resumed = later + PEER_ACTIONS_STALL_TIMEOUT * 2;
+        assert!(!watchdog.is_stalled(2, 6, resumed));
+        assert!(!watchdog.is_stalled(2, 6, resumed + PEER_ACTIONS_STALL_TIMEOUT / 2));
+    }
+}
@@ -1,25 +1,33 @@
 use std::{
-    io::Read,
     net::{SocketAddrV6, TcpStream},
-    sync::mpsc,
+    sync::{mpsc, Arc},
     thread::{self, JoinHandle},
 };
 
 use crate::{
     error::CustomError,
     logger::{send_log, Log},
-    message::{Message, MessageHeader},
+    message::{Message, MessageHeader, MESSAGE_HEADER_SIZE},
     messages::{
+        addr::Addr,
         block::Block,
+        block_txn::BlockTxn,
+        compact_block::CompactBlock,
+        filter_load::{FilterAdd, FilterClear, FilterLoad},
+        get_blocks::GetBlocks,
         get_data::GetData,
         get_headers::GetHeaders,
         headers::Headers,
         inv::Inv,
+        mempool::Mempool,
+        merkle_block::{FilteredBlock, MerkleBlock},
         ping_pong::{Ping, Pong},
+        reject::Reject,
+        send_compact::{SendCompact, COMPACT_BLOCKS_PROTOCOL_VERSION},
         send_headers::SendHeaders,
         transaction::Transaction,
     },
-    peer::request_headers,
+    peer::{request_headers, DisconnectReason, PeerConnection, PeerSender, INVALID_MESSAGE_SCORE},
     structs::{
         block_header::BlockHeader,
         inventory::{Inventory, InventoryType},
@@ -28,20 +36,86 @@ use crate::{
 
 use super::node_action_loop::NodeAction;
 
+/// Puntaje de mal comportamiento que suma cada mensaje de una capacidad que no se negocio con el peer.
+pub const UNNEGOTIATED_MESSAGE_SCORE: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// ProtocolCheck es lo que se hace con un mensaje recibido de un peer segun el estado del protocolo (ver PeerProtocolState).
+/// Los valores son:
+/// - Process: Se procesa el mensaje.
+/// - Ignore: Se descarta el mensaje, contiene el puntaje de mal comportamiento acumulado por el peer.
+/// - Disconnect: Se desconecta al peer con el motivo.
+pub enum ProtocolCheck {
+    Process,
+    Ignore(u32),
+    Disconnect(DisconnectReason),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// PeerProtocolState es el estado del protocolo con un peer despues del handshake, que lleva su PeerStreamLoop
+/// para detectar los mensajes que el peer no deberia enviar.
+/// Los elementos son:
+/// - compact_blocks: Indica si se negociaron compact blocks con el peer (si le enviamos sendcmpct).
+/// - misbehavior: Puntaje de mal comportamiento acumulado por los mensajes descartados.
+pub struct PeerProtocolState {
+    compact_blocks: bool,
+    misbehavior: u32,
+}
+
+impl PeerProtocolState {
+    /// Crea el estado del protocolo con un peer de esa version del protocolo, tras el handshake.
+    pub fn new(version: i32) -> Self {
+        Self {
+            compact_blocks: version >= COMPACT_BLOCKS_PROTOCOL_VERSION,
+            misbehavior: 0,
+        }
+    }
+
+    /// Revisa un mensaje recibido del peer.
+    /// Un version o verack despues del handshake es una violacion del protocolo y se desconecta al peer.
+    /// Los mensajes de compact blocks (cmpctblock y blocktxn) sin haberlos negociado se descartan y suman
+    /// UNNEGOTIATED_MESSAGE_SCORE al puntaje del peer, que se desconecta al llegar a INVALID_MESSAGE_SCORE.
+    pub fn check(&mut self, command: &str) -> ProtocolCheck {
+        match command {
+            "version" | "verack" => ProtocolCheck::Disconnect(DisconnectReason::ProtocolViolation),
+            "cmpctblock" | "blocktxn" if !self.compact_blocks => {
+                self.misbehavior += UNNEGOTIATED_MESSAGE_SCORE;
+                match self.misbehavior >= INVALID_MESSAGE_SCORE {
+                    true => {
+                        ProtocolCheck::Disconnect(DisconnectReason::Misbehavior(self.misbehavior))
+                    }
+                    false => ProtocolCheck::Ignore(self.misbehavior),
+                }
+            }
+            _ => ProtocolCheck::Process,
+        }
+    }
+}
+
 /// PeerStreamLoop es una estructura que contiene los elementos necesarios para manejar los mensajes recibidos del peer asociado.
 /// Genera el loop de eventos alrededor de los mensajes recibidos por el TcpStream.
+/// Cada mensaje recibido se informa al nodo con NodeAction::PeerMessageReceived para las estadisticas del peer.
+/// Antes de procesar cada mensaje se revisa con el estado del protocolo con el peer (ver PeerProtocolState::check).
 /// Los elementos son:
 /// - address: Direccion del peer.
-/// - stream: Stream del peer.
+/// - stream: Stream del peer, del que se leen sus mensajes.
+/// - sender: PeerSender por el que se le responde al peer, compartiendo el lock de escritura con el resto de los envios al peer.
+/// - connection: Estado de la conexion con el peer, se le suman los bytes de los mensajes recibidos.
 /// - node_action_sender: Sender para enviar acciones al nodo.
 /// - version: Version del nodo.
 /// - logger_sender: Sender para enviar logs al logger.
+/// - filtered_block: Bloque filtrado (merkleblock) que espera las transacciones que coinciden, que el peer envia a continuacion.
+/// - protocol: Estado del protocolo con el peer, para descartar los mensajes que no deberia enviar (ver PeerProtocolState).
 pub struct PeerStreamLoop {
     pub address: SocketAddrV6,
     pub stream: TcpStream,
+    pub sender: PeerSender,
+    pub connection: Arc<PeerConnection>,
     pub node_action_sender: mpsc::Sender<NodeAction>,
     pub version: i32,
     pub logger_sender: mpsc::Sender<Log>,
+    filtered_block: Option<FilteredBlock>,
+    protocol: PeerProtocolState,
 }
 
 impl PeerStreamLoop {
@@ -51,6 +125,8 @@ impl PeerStreamLoop {
         version: i32,
         address: SocketAddrV6,
         stream: TcpStream,
+        sender: PeerSender,
+        connection: Arc<PeerConnection>,
         logger_sender: mpsc::Sender<Log>,
         node_action_sender: mpsc::Sender<NodeAction>,
     ) -> JoinHandle<Result<(), CustomError>> {
@@ -58,9 +134,13 @@ impl PeerStreamLoop {
             let mut peer_action_thread = Self {
                 address,
                 stream,
+                sender,
+                connection,
                 node_action_sender,
                 version,
                 logger_sender,
+                filtered_block: None,
+                protocol: PeerProtocolState::new(version),
             };
             peer_action_thread.event_loop()
         })
@@ -68,19 +148,33 @@ impl PeerStreamLoop {
 
     fn event_loop(&mut self) -> Result<(), CustomError> {
         loop {
-            let response_header = MessageHeader::read(&mut self.stream)?;
-
-            let response = match response_header.command.as_str() {
-                "headers" => self.handle_headers(&response_header),
-                "block" => self.handle_block(&response_header),
-                "ping" => self.handle_ping(&response_header),
-                "inv" => self.handle_inv(&response_header),
-                "tx" => self.handle_tx(&response_header),
-                "notfound" => self.handle_notfound(&response_header),
-                "sendheaders" => self.handle_sendheaders(&response_header),
-                "getheaders" => self.handle_getheaders(&response_header),
-                "getdata" => self.handle_getdata(&response_header),
-                _ => self.ignore_message(&response_header),
+            let response_header = match MessageHeader::read_resync(&mut self.stream) {
+                Ok((response_header, skipped)) => {
+                    self.log_skipped_bytes(skipped);
+                    response_header
+                }
+                Err(error) => {
+                    self.report_error(&error)?;
+                    break;
+                }
+            };
+            let message_size = MESSAGE_HEADER_SIZE + response_header.payload_size as usize;
+            self.connection.add_bytes(message_size as u64);
+
+            let response = match self.protocol.check(&response_header.command) {
+                ProtocolCheck::Process => self.handle_message(&response_header),
+                ProtocolCheck::Ignore(score) => self.ignore_unnegotiated(&response_header, score),
+                ProtocolCheck::Disconnect(reason) => {
+                    send_log(
+                        &self.logger_sender,
+                        Log::Message(format!(
+                            "Peer {} sent {} after the handshake: {reason}",
+                            self.address, response_header.command
+                        )),
+                    );
+                    self.disconnect(reason)?;
+                    break;
+                }
             };
 
             if let Err(error) = response {
@@ -88,16 +182,91 @@ impl PeerStreamLoop {
                     &self.logger_sender,
                     Log::Message(format!("Error on PeerStreamLoop: {error}")),
                 );
-                self.node_action_sender
-                    .send(NodeAction::PeerError(self.address))?;
+                self.report_error(&error)?;
                 break;
             }
+            self.node_action_sender
+                .send(NodeAction::PeerMessageReceived(self.address, message_size))?;
+        }
+        Ok(())
+    }
+
+    /// Procesa un mensaje del peer segun su comando.
+    fn handle_message(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
+        match response_header.command.as_str() {
+            "headers" => self.handle_headers(response_header),
+            "block" => self.handle_block(response_header),
+            "merkleblock" => self.handle_merkleblock(response_header),
+            "ping" => self.handle_ping(response_header),
+            "pong" => self.handle_pong(response_header),
+            "inv" => self.handle_inv(response_header),
+            "tx" => self.handle_tx(response_header),
+            "notfound" => self.handle_notfound(response_header),
+            "reject" => self.handle_reject(response_header),
+            "sendheaders" => self.handle_sendheaders(response_header),
+            "getheaders" => self.handle_getheaders(response_header),
+            "getblocks" => self.handle_getblocks(response_header),
+            "getdata" => self.handle_getdata(response_header),
+            "sendcmpct" => self.handle_sendcmpct(response_header),
+            "cmpctblock" => self.handle_cmpctblock(response_header),
+            "blocktxn" => self.handle_blocktxn(response_header),
+            "mempool" => self.handle_mempool(response_header),
+            "addr" => self.handle_addr(response_header),
+            "filterload" => self.handle_filterload(response_header),
+            "filteradd" => self.handle_filteradd(response_header),
+            "filterclear" => self.handle_filterclear(response_header),
+            _ => self.ignore_message(response_header),
+        }
+    }
+
+    /// Descarta un mensaje de una capacidad que no se negocio con el peer, registrando su puntaje de mal comportamiento.
+    fn ignore_unnegotiated(
+        &mut self,
+        response_header: &MessageHeader,
+        score: u32,
+    ) -> Result<(), CustomError> {
+        send_log(
+            &self.logger_sender,
+            Log::Message(format!(
+                "Ignored {} from {} without negotiating it, misbehavior score {score}",
+                response_header.command, self.address
+            )),
+        );
+        response_header.read_payload(&mut self.stream)?;
+        Ok(())
+    }
+
+    /// Registra los bytes descartados antes de un magic number valido y los suma a los recibidos del peer.
+    fn log_skipped_bytes(&self, skipped: usize) {
+        if skipped == 0 {
+            return;
+        }
+        self.connection.add_bytes(skipped as u64);
+        send_log(
+            &self.logger_sender,
+            Log::Message(format!(
+                "Skipped {skipped} bytes without a valid magic from {}",
+                self.address
+            )),
+        );
+    }
+
+    /// Informa al nodo que hay que desconectar al peer por el error, salvo que el nodo ya haya cerrado la conexion.
+    fn report_error(&self, error: &CustomError) -> Result<(), CustomError> {
+        self.disconnect(DisconnectReason::from_error(error))
+    }
+
+    /// Informa al nodo que hay que desconectar al peer por el motivo, salvo que el nodo ya haya cerrado la conexion.
+    fn disconnect(&self, reason: DisconnectReason) -> Result<(), CustomError> {
+        if !self.connection.is_stopped() {
+            self.node_action_sender
+                .send(NodeAction::PeerError(self.address, reason))?;
         }
         Ok(())
     }
 
     fn handle_headers(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
-        let response = match Headers::read(&mut self.stream, response_header.payload_size) {
+        let response = match Headers::read_with_header(&mut self.stream, response_header) {
             Ok(response) => response,
             Err(error) => {
                 self.node_action_sender.send(NodeAction::GetHeadersError)?;
@@ -110,23 +279,23 @@ impl PeerStreamLoop {
             request_headers(
                 last_header,
                 self.version,
-                &mut self.stream,
+                &self.sender,
                 &self.logger_sender,
                 &self.node_action_sender,
             )?;
         }
         self.node_action_sender
-            .send(NodeAction::NewHeaders(response))?;
+            .send(NodeAction::NewHeaders(self.address, response))?;
         Ok(())
     }
 
     fn handle_block(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
-        let block = Block::read(&mut self.stream, response_header.payload_size)?;
+        let block = Block::read_with_header(&mut self.stream, response_header)?;
         if let Err(error) = block.create_merkle_root() {
             let inventory = Inventory::new(InventoryType::Block, block.header.hash().clone());
 
             self.node_action_sender
-                .send(NodeAction::GetDataError(vec![inventory]))?;
+                .send(NodeAction::GetDataError(Arc::new([inventory])))?;
 
             send_log(
                 &self.logger_sender,
@@ -138,71 +307,244 @@ impl PeerStreamLoop {
             return Err(error);
         };
 
-        self.node_action_sender
-            .send(NodeAction::Block((block.header.hash().clone(), block)))?;
+        self.node_action_sender.send(NodeAction::Block((
+            block.header.hash().clone(),
+            Arc::new(block),
+        )))?;
+
+        Ok(())
+    }
+
+    /// Verifica el arbol parcial del merkleblock y espera las transacciones que coinciden.
+    /// Si no coincide ninguna el bloque filtrado (sin transacciones) se envia al nodo directamente.
+    fn handle_merkleblock(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
+        let merkle_block = MerkleBlock::read_with_header(&mut self.stream, response_header)?;
+        let block_hash = merkle_block.header.hash().clone();
+        let filtered_block = match FilteredBlock::new(merkle_block) {
+            Ok(filtered_block) => filtered_block,
+            Err(error) => {
+                let inventory = Inventory::new(InventoryType::FilteredBlock, block_hash);
+                self.node_action_sender
+                    .send(NodeAction::GetDataError(Arc::new([inventory])))?;
+                return Err(error);
+            }
+        };
+
+        if let Some(previous) = self.filtered_block.take() {
+            send_log(
+                &self.logger_sender,
+                Log::Message(format!(
+                    "Filtered block dropped with {} missing transactions",
+                    previous.missing()
+                )),
+            );
+        }
+        self.filtered_block = Some(filtered_block);
+        self.send_filtered_block_if_complete()
+    }
 
+    /// Si ya llegaron todas las transacciones del bloque filtrado, lo envia al nodo.
+    fn send_filtered_block_if_complete(&mut self) -> Result<(), CustomError> {
+        let complete =
+            matches!(&self.filtered_block, Some(filtered_block) if filtered_block.is_complete());
+        if !complete {
+            return Ok(());
+        }
+        let Some(filtered_block) = self.filtered_block.take() else { return Ok(()) };
+        let Some(block) = filtered_block.into_block() else { return Ok(()) };
+        self.node_action_sender.send(NodeAction::FilteredBlock((
+            block.header.hash().clone(),
+            Arc::new(block),
+        )))?;
         Ok(())
     }
 
     fn handle_ping(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
-        let ping = Ping::read(&mut self.stream, response_header.payload_size)?;
-        let pong = Pong { nonce: ping.nonce };
-        pong.send(&mut self.stream)?;
+        let ping = Ping::read_with_header(&mut self.stream, response_header)?;
+        self.sender.send(&Pong { nonce: ping.nonce })
+    }
+
+    fn handle_pong(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
+        let pong = Pong::read_with_header(&mut self.stream, response_header)?;
+        self.node_action_sender
+            .send(NodeAction::Pong(self.address, pong.nonce))?;
         Ok(())
     }
 
+    /// Informa al nodo los inventarios anunciados para que decida cuales pedirle al peer (ver NodeState::inventories_to_request),
+    /// de forma que cada hash se pida a un unico peer aunque lo anuncien varios.
+    /// Las transacciones no se informan durante el IBD, donde no se pueden validar y no se descargan.
+    /// Los anuncios de transacciones se informan al nodo igual, para verificar la propagacion de nuestras transacciones.
     fn handle_inv(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
-        let inv = Inv::read(&mut self.stream, response_header.payload_size)?;
+        let inv = Inv::read_with_header(&mut self.stream, response_header)?;
+        let relay_txs = self.connection.relays_txs();
 
+        let mut announced_txs = vec![];
+        let mut requestable = vec![];
         for inventory in inv.inventories {
-            if inventory.inventory_type == InventoryType::Tx {
-                let message = GetData::new(vec![inventory]);
-                message.send(&mut self.stream)?;
+            match inventory.inventory_type {
+                InventoryType::Tx => {
+                    announced_txs.push(inventory.hash.clone());
+                    if relay_txs {
+                        requestable.push(inventory);
+                    }
+                }
+                InventoryType::Block => requestable.push(inventory),
+                _ => (),
             }
         }
+
+        if !requestable.is_empty() {
+            self.node_action_sender
+                .send(NodeAction::InventoryAnnounced(self.address, requestable))?;
+        }
+
+        if !announced_txs.is_empty() {
+            self.node_action_sender
+                .send(NodeAction::TransactionsAnnounced(
+                    self.address,
+                    announced_txs,
+                ))?;
+        }
         Ok(())
     }
 
+    /// Las transacciones de un bloque filtrado completan el bloque.
+    /// Las demas se descartan durante el IBD, ya que no se pidieron.
+    /// Las que superan MAX_RELAY_TX_SIZE se descartan sin leerlas completas (ver Transaction::read_relayed).
     fn handle_tx(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
-        let tx = Transaction::read(&mut self.stream, response_header.payload_size)?;
+        let tx = match Transaction::read_relayed(&mut self.stream, response_header) {
+            Ok(tx) => tx,
+            Err(error @ CustomError::TransactionOverRelayLimit(_)) => {
+                send_log(
+                    &self.logger_sender,
+                    Log::Message(format!(
+                        "Rejected transaction from {}: {error}",
+                        self.address
+                    )),
+                );
+                return Ok(());
+            }
+            Err(error) => return Err(error),
+        };
+        if let Some(filtered_block) = &mut self.filtered_block {
+            if filtered_block.add_transaction(tx.clone()) {
+                return self.send_filtered_block_if_complete();
+            }
+        }
+        if !self.connection.relays_txs() {
+            return Ok(());
+        }
         self.node_action_sender
-            .send(NodeAction::PendingTransaction(tx))?;
+            .send(NodeAction::PendingTransaction(Arc::new(tx)))?;
         Ok(())
     }
 
     fn handle_notfound(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
-        let notfound = GetData::read(&mut self.stream, response_header.payload_size)?;
-        let inventories = notfound.get_inventories().clone();
+        let notfound = GetData::read_with_header(&mut self.stream, response_header)?;
+        let inventories: Arc<[Inventory]> = notfound.get_inventories().as_slice().into();
         self.node_action_sender
-            .send(NodeAction::GetDataError(inventories))?;
+            .send(NodeAction::NotFound(inventories))?;
 
         Ok(())
     }
 
+    fn handle_reject(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
+        let reject = Reject::read_with_header(&mut self.stream, response_header)?;
+        self.node_action_sender
+            .send(NodeAction::Rejected(self.address, reject))?;
+        Ok(())
+    }
+
     fn handle_sendheaders(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
-        let _ = SendHeaders::read(&mut self.stream, response_header.payload_size)?;
+        let _ = SendHeaders::read_with_header(&mut self.stream, response_header)?;
         self.node_action_sender
             .send(NodeAction::SendHeaders(self.address))?;
         Ok(())
     }
 
     fn handle_getheaders(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
-        let getheaders = GetHeaders::read(&mut self.stream, response_header.payload_size)?;
+        let getheaders = GetHeaders::read_with_header(&mut self.stream, response_header)?;
         self.node_action_sender
             .send(NodeAction::GetHeaders(self.address, getheaders))?;
         Ok(())
     }
 
+    fn handle_getblocks(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
+        let getblocks = GetBlocks::read_with_header(&mut self.stream, response_header)?;
+        self.node_action_sender
+            .send(NodeAction::GetBlocks(self.address, getblocks))?;
+        Ok(())
+    }
+
     fn handle_getdata(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
-        let getdata = GetData::read(&mut self.stream, response_header.payload_size)?;
+        let getdata = GetData::read_with_header(&mut self.stream, response_header)?;
         self.node_action_sender
             .send(NodeAction::GetData(self.address, getdata))?;
         Ok(())
     }
 
+    fn handle_sendcmpct(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
+        let _ = SendCompact::read_with_header(&mut self.stream, response_header)?;
+        Ok(())
+    }
+
+    fn handle_cmpctblock(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
+        let compact_block = CompactBlock::read_with_header(&mut self.stream, response_header)?;
+        self.node_action_sender
+            .send(NodeAction::CompactBlock(self.address, compact_block))?;
+        Ok(())
+    }
+
+    fn handle_blocktxn(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
+        let block_txn = BlockTxn::read_with_header(&mut self.stream, response_header)?;
+        self.node_action_sender
+            .send(NodeAction::BlockTxn(self.address, block_txn))?;
+        Ok(())
+    }
+
+    fn handle_mempool(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
+        let _ = Mempool::read_with_header(&mut self.stream, response_header)?;
+        self.node_action_sender
+            .send(NodeAction::Mempool(self.address))?;
+        Ok(())
+    }
+
+    fn handle_addr(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
+        let addr = Addr::read_with_header(&mut self.stream, response_header)?;
+        if !addr.addresses.is_empty() {
+            self.node_action_sender
+                .send(NodeAction::NewAddresses(addr.sockets()))?;
+        }
+        Ok(())
+    }
+
+    fn handle_filterload(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
+        let filter_load = FilterLoad::read_with_header(&mut self.stream, response_header)?;
+        self.node_action_sender.send(NodeAction::FilterLoad(
+            self.address,
+            Some(filter_load.filter),
+        ))?;
+        Ok(())
+    }
+
+    fn handle_filteradd(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
+        let filter_add = FilterAdd::read_with_header(&mut self.stream, response_header)?;
+        self.node_action_sender
+            .send(NodeAction::FilterAdd(self.address, filter_add.data))?;
+        Ok(())
+    }
+
+    fn handle_filterclear(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
+        let _ = FilterClear::read_with_header(&mut self.stream, response_header)?;
+        self.node_action_sender
+            .send(NodeAction::FilterLoad(self.address, None))?;
+        Ok(())
+    }
+
     fn ignore_message(&mut self, response_header: &MessageHeader) -> Result<(), CustomError> {
         let cmd = response_header.command.as_str();
-        if cmd != "alert" && cmd != "addr" {
+        if cmd != "alert" {
             send_log(
                 &self.logger_sender,
                 Log::Message(format!(
@@ -211,8 +553,57 @@ impl PeerStreamLoop {
                 )),
             );
         }
-        let mut buffer: Vec<u8> = vec![0; response_header.payload_size as usize];
-        self.stream.read_exact(&mut buffer)?;
+        response_header.read_payload(&mut self.stream)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_and_verack_after_the_handshake_are_violations() {
+        let mut protocol = PeerProtocolState::new(70015);
+        for command in ["version", "verack"] {
+            assert_eq!(
+                protocol.check(command),
+                ProtocolCheck::Disconnect(DisconnectReason::ProtocolViolation)
+            );
+        }
+    }
+
+    #[test]
+    fn compact_blocks_are_processed_only_if_negotiated() {
+        let mut protocol = PeerProtocolState::new(COMPACT_BLOCKS_PROTOCOL_VERSION);
+        assert_eq!(protocol.check("cmpctblock"), ProtocolCheck::Process);
+        assert_eq!(protocol.check("blocktxn"), ProtocolCheck::Process);
+
+        let mut protocol = PeerProtocolState::new(COMPACT_BLOCKS_PROTOCOL_VERSION - 1);
+        assert_eq!(
+            protocol.check("cmpctblock"),
+            ProtocolCheck::Ignore(UNNEGOTIATED_MESSAGE_SCORE)
+        );
+        assert_eq!(
+            protocol.check("blocktxn"),
+            ProtocolCheck::Ignore(2 * UNNEGOTIATED_MESSAGE_SCORE)
+        );
+        assert_eq!(protocol.check("headers"), ProtocolCheck::Process);
+    }
+
+    #[test]
+    fn unnegotiated_messages_disconnect_at_the_misbehavior_limit() {
+        let mut protocol = PeerProtocolState::new(70012);
+        let ignored = INVALID_MESSAGE_SCORE / UNNEGOTIATED_MESSAGE_SCORE - 1;
+        for _ in 0..ignored {
+            assert!(matches!(
+                protocol.check("cmpctblock"),
+                ProtocolCheck::Ignore(_)
+            ));
+        }
+        assert_eq!(
+            protocol.check("cmpctblock"),
+            ProtocolCheck::Disconnect(DisconnectReason::Misbehavior(INVALID_MESSAGE_SCORE))
+        );
+    }
+}
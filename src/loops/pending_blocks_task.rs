@@ -0,0 +1,79 @@
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::CustomError,
+    logger::{send_log, Log},
+    node_state::NodeState,
+    structs::inventory::{Inventory, InventoryType},
+};
+
+use super::maintenance_loop::MaintenanceTask;
+
+/// Segundos entre revisiones de los bloques pendientes si el config no indica PENDING_BLOCKS_POLL_SECS.
+pub const DEFAULT_PENDING_BLOCKS_POLL: u64 = 1;
+
+/// Nombre con el que se registra la tarea de los bloques pendientes en el Scheduler del maintenance_loop.
+pub const PENDING_BLOCKS_TASK: &str = "pending_blocks";
+
+/// pending_blocks_task devuelve la tarea del maintenance_loop que se encarga de reenviar los bloques que no fueron recibidos por los peers.
+/// Los bloques vencidos se vuelven a pedir a un peer distinto del que no los envio, si hay otro conectado.
+/// Cada vez que vencen bloques se guarda la sesion de descarga, para conservar sus reintentos si se cierra el nodo.
+/// Se registra con el intervalo IDLE_PENDING_BLOCKS_POLL, y mientras hay bloques pendientes adelanta su proxima ejecucion
+/// hasta que venza el primero, como maximo poll_interval. PendingBlocks la despierta cuando se agrega un bloque y no habia
+/// ninguno pendiente (ver NodeState::watch_pending_blocks).
+/// Los elementos son:
+/// - node_state_ref: Referencia al estado del nodo.
+/// - logger_sender: Sender para enviar logs al logger.
+/// - poll_interval: Tiempo maximo entre revisiones mientras hay bloques pendientes.
+pub fn pending_blocks_task(
+    node_state_ref: Arc<Mutex<NodeState>>,
+    logger_sender: mpsc::Sender<Log>,
+    poll_interval: Duration,
+) -> MaintenanceTask {
+    Box::new(move || {
+        refetch_stale_blocks(&node_state_ref, &logger_sender)?;
+
+        let node_state = node_state_ref.lock()?;
+        let wait = node_state.next_pending_blocks_check(poll_interval)?;
+        node_state.schedule_maintenance_task(PENDING_BLOCKS_TASK, Instant::now() + wait)
+    })
+}
+
+fn refetch_stale_blocks(
+    node_state_ref: &Arc<Mutex<NodeState>>,
+    logger_sender: &mpsc::Sender<Log>,
+) -> Result<(), CustomError> {
+    let mut node_state = node_state_ref.lock()?;
+
+    let blocks_to_refetch = node_state.get_stale_requests()?;
+    if blocks_to_refetch.is_empty() {
+        return Ok(());
+    }
+    node_state.save_download_session()?;
+
+    send_log(
+        logger_sender,
+        Log::Message(format!(
+            "Refetching {} pending blocks...",
+            blocks_to_refetch.len()
+        )),
+    );
+
+    let requests = blocks_to_refetch
+        .into_iter()
+        .map(|(block_hash, peer)| (Inventory::new(InventoryType::Block, block_hash), peer))
+        .collect();
+
+    let failed_peers = node_state.request_blocks(requests)?;
+    for (address, reason) in failed_peers {
+        node_state.remove_peer(address, reason)?;
+        send_log(
+            logger_sender,
+            Log::Message(format!("Error sending message getdata to peer {}", address)),
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    error::CustomError,
+    logger::{send_log, Log},
+    node_state::NodeState,
+};
+
+use super::node_action_loop::NodeAction;
+
+/// Segundos entre los pings enviados a cada peer si el config no indica PING_INTERVAL.
+pub const DEFAULT_PING_INTERVAL: u64 = 120;
+
+/// Segundos que tiene un peer para responder un ping si el config no indica PING_TIMEOUT.
+pub const DEFAULT_PING_TIMEOUT: u64 = 60;
+
+/// Tiempo maximo entre cada revision de los pings de los peers.
+const PING_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// ping_loop es una funcion que genera un loop que envia pings a los peers cada interval
+/// y desconecta, mediante NodeAction::PeerError, a los que no responden con un pong dentro del timeout.
+/// Los elementos son:
+/// - node_state_ref: Referencia al estado del nodo.
+/// - node_action_sender: Sender para enviar acciones al nodo.
+/// - logger_sender: Sender para enviar logs al logger.
+/// - interval: Tiempo entre los pings enviados a cada peer.
+/// - timeout: Tiempo que tiene un peer para responder un ping.
+pub fn ping_loop(
+    node_state_ref: Arc<Mutex<NodeState>>,
+    node_action_sender: mpsc::Sender<NodeAction>,
+    logger_sender: mpsc::Sender<Log>,
+    interval: Duration,
+    timeout: Duration,
+) -> thread::JoinHandle<Result<(), CustomError>> {
+    let check_interval = PING_CHECK_INTERVAL.min(interval).min(timeout);
+    thread::spawn(move || -> Result<(), CustomError> {
+        loop {
+            thread::sleep(check_interval);
+            let mut node_state = node_state_ref.lock()?;
+            let dead_peers = node_state.ping_peers(interval, timeout);
+            drop(node_state);
+
+            for (address, reason) in dead_peers {
+                send_log(
+                    &logger_sender,
+                    Log::Message(format!("Peer {address} did not answer our ping: {reason}")),
+                );
+                node_action_sender.send(NodeAction::PeerError(address, reason))?;
+            }
+        }
+    })
+}
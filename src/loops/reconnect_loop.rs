@@ -0,0 +1,121 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    error::CustomError,
+    logger::{send_log, Log},
+    node_state::NodeState,
+    peer::{DisconnectEvent, Peer},
+    utils::get_address_v6,
+};
+
+use super::node_action_loop::NodeAction;
+
+/// Tiempo tras el cual, si siguen faltando peers, se vuelve a intentar aunque no se haya desconectado ninguno.
+const RECONNECT_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// ReconnectLoop es el loop de eventos que reemplaza a los peers que se desconectan.
+/// Cada vez que el nodo informa que faltan peers (o cada RECONNECT_RETRY_INTERVAL, si siguen faltando), llama a
+/// las direcciones del seed DNS que no estan conectadas ni se intentaron recientemente y agrega los nuevos peers al nodo.
+/// Los elementos son:
+/// - logger_sender: Sender para enviar logs al logger
+/// - node_state_ref: Referencia al estado del nodo
+/// - node_action_sender: Sender para enviar acciones al nodo
+/// - reconnect_receiver: Receiver por el que el nodo informa cuantos peers faltan
+/// - call_peer: Funcion que llama a una direccion y realiza el handshake (Peer::call con los datos del nodo)
+pub struct ReconnectLoop<F> {
+    logger_sender: mpsc::Sender<Log>,
+    node_state_ref: Arc<Mutex<NodeState>>,
+    node_action_sender: mpsc::Sender<NodeAction>,
+    reconnect_receiver: mpsc::Receiver<usize>,
+    call_peer: F,
+}
+
+impl<F> ReconnectLoop<F>
+where
+    F: Fn(SocketAddr) -> Result<Peer, CustomError> + Send + 'static,
+{
+    /// Inicializa el loop de eventos en un thread.
+    pub fn spawn(
+        logger_sender: mpsc::Sender<Log>,
+        node_state_ref: Arc<Mutex<NodeState>>,
+        node_action_sender: mpsc::Sender<NodeAction>,
+        reconnect_receiver: mpsc::Receiver<usize>,
+        call_peer: F,
+    ) -> JoinHandle<Result<(), CustomError>> {
+        thread::spawn(move || -> Result<(), CustomError> {
+            let thread = Self {
+                logger_sender,
+                node_state_ref,
+                node_action_sender,
+                reconnect_receiver,
+                call_peer,
+            };
+            thread.event_loop()
+        })
+    }
+
+    fn event_loop(&self) -> Result<(), CustomError> {
+        loop {
+            match self
+                .reconnect_receiver
+                .recv_timeout(RECONNECT_RETRY_INTERVAL)
+            {
+                Ok(_) | Err(RecvTimeoutError::Timeout) => self.reconnect()?,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn reconnect(&self) -> Result<(), CustomError> {
+        let node_state = self.node_state_ref.lock()?;
+        let candidates = node_state.reconnect_candidates(node_state.missing_peers());
+        drop(node_state);
+
+        for candidate in candidates {
+            self.connect(candidate)?;
+        }
+        Ok(())
+    }
+
+    fn connect(&self, candidate: SocketAddr) -> Result<(), CustomError> {
+        send_log(
+            &self.logger_sender,
+            Log::Message(format!("Reconnecting: calling peer {candidate}")),
+        );
+        let new_peer = (self.call_peer)(candidate);
+
+        let mut node_state = self.node_state_ref.lock()?;
+        node_state.record_connection_attempt(candidate, new_peer.is_ok());
+        let new_peer = match new_peer {
+            Ok(new_peer) => new_peer,
+            Err(CustomError::CannotConnectToNode) => {
+                send_log(
+                    &self.logger_sender,
+                    Log::Message(format!("Reconnecting: cannot connect to peer {candidate}")),
+                );
+                return Ok(());
+            }
+            Err(_) => {
+                return node_state.record_disconnect(DisconnectEvent::handshake_failed(
+                    get_address_v6(candidate),
+                ))
+            }
+        };
+        let behind_network = node_state.append_peers(vec![new_peer])?;
+        drop(node_state);
+
+        if behind_network {
+            self.node_action_sender.send(NodeAction::BehindNetwork)?;
+        }
+        Ok(())
+    }
+}
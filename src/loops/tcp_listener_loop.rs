@@ -1,5 +1,5 @@
 use std::{
-    net::{SocketAddrV6, TcpListener},
+    net::{SocketAddr, SocketAddrV6, TcpListener, TcpStream},
     sync::{mpsc, Arc, Mutex},
     thread::{self, JoinHandle},
 };
@@ -8,34 +8,47 @@ use crate::{
     error::CustomError,
     logger::{send_log, Log},
     node_state::NodeState,
-    peer::Peer,
+    peer::{DisconnectEvent, Peer},
+    private_network::PrivateNetwork,
+    utils::get_address_v6,
 };
 
-use super::{node_action_loop::NodeAction, peer_action_loop::PeerAction};
+use super::{node_action_loop::NodeAction, peer_action_loop::PeerActionReceiver};
 
 /// TcpListenerLoop es el loop de eventos que se encarga de escuchar conexiones entrantes.
-/// Cada vez que se recibe una conexión, inicializa un nuevo Peer y contesta el handshake.
-/// Luego, agrega el nuevo Peer a la lista de peers del nodo
+/// Cada vez que se recibe una conexión, la atiende en su propio thread para que un cliente que no responde no frene al resto:
+/// la autentica si el nodo es de una red privada (ver PrivateNetwork::accept),
+/// inicializa un nuevo Peer y contesta el handshake (si falla, registra la desconexion).
+/// Luego, agrega el nuevo Peer a la lista de peers del nodo, y si por su altura el nodo quedo atrasado respecto de la red
+/// se lo informa al nodo para que priorice la descarga de headers.
 /// Los elementos son:
 /// - logger_sender: Sender para enviar logs al logger
 /// - node_state_ref: Referencia al estado del nodo
 /// - address: Dirección del nodo
 /// - services: Servicios que ofrece el nodo
 /// - version: Versión del protocolo que maneja el nodo
-/// - peer_action_receiver: Receiver para recibir acciones de los peers
+/// - nonce: Nonce del nodo, para rechazar las conexiones del propio nodo
+/// - user_agent: User agent con el que el nodo se presenta en el mensaje version
+/// - peer_action_receiver: Cola compartida de acciones de los peers
 /// - node_action_sender: Sender para enviar acciones al nodo
+/// - private_network: Red privada con la que se autentican las conexiones entrantes
+#[derive(Clone)]
 pub struct TcpListenerLoop {
     logger_sender: mpsc::Sender<Log>,
     node_state_ref: Arc<Mutex<NodeState>>,
     address: SocketAddrV6,
     services: u64,
     version: i32,
-    peer_action_receiver: Arc<Mutex<mpsc::Receiver<PeerAction>>>,
+    nonce: u64,
+    user_agent: String,
+    peer_action_receiver: PeerActionReceiver,
     node_action_sender: mpsc::Sender<NodeAction>,
+    private_network: PrivateNetwork,
 }
 
 impl TcpListenerLoop {
     #[must_use]
+    #[allow(clippy::too_many_arguments)]
     /// Inicializa el loop de eventos en un thread.
     pub fn spawn(
         logger_sender: mpsc::Sender<Log>,
@@ -43,8 +56,11 @@ impl TcpListenerLoop {
         address: SocketAddrV6,
         services: u64,
         version: i32,
-        peer_action_receiver: Arc<Mutex<mpsc::Receiver<PeerAction>>>,
+        nonce: u64,
+        user_agent: String,
+        peer_action_receiver: PeerActionReceiver,
         node_action_sender: mpsc::Sender<NodeAction>,
+        private_network: PrivateNetwork,
     ) -> JoinHandle<Result<(), CustomError>> {
         thread::spawn(move || -> Result<(), CustomError> {
             let mut thread = Self {
@@ -53,8 +69,11 @@ impl TcpListenerLoop {
                 address,
                 services,
                 version,
+                nonce,
+                user_agent,
                 peer_action_receiver,
                 node_action_sender,
+                private_network,
             };
             thread.event_loop()
         })
@@ -75,19 +94,69 @@ impl TcpListenerLoop {
                 Log::Message(format!("New connection: {:?}", peer_address)),
             );
 
-            let new_peer = Peer::answer(
-                stream,
-                self.address,
-                self.services,
-                self.version,
-                self.peer_action_receiver.clone(),
-                self.logger_sender.clone(),
-                self.node_action_sender.clone(),
-            )?;
+            let connection = self.clone();
+            thread::spawn(move || {
+                if let Err(error) = connection.handle_connection(stream, peer_address) {
+                    send_log(
+                        &connection.logger_sender,
+                        Log::Message(format!(
+                            "Error on connection from {:?}: {}",
+                            peer_address, error
+                        )),
+                    );
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Autentica la conexion entrante, contesta el handshake y agrega el nuevo Peer al nodo.
+    fn handle_connection(
+        &self,
+        mut stream: TcpStream,
+        peer_address: SocketAddr,
+    ) -> Result<(), CustomError> {
+        if self.private_network.accept(&mut stream).is_err() {
+            send_log(
+                &self.logger_sender,
+                Log::Message(format!(
+                    "Rejected connection from {:?}: private network authentication failed",
+                    peer_address
+                )),
+            );
+            self.node_state_ref
+                .lock()?
+                .record_disconnect(DisconnectEvent::handshake_failed(get_address_v6(
+                    peer_address,
+                )))?;
+            return Ok(());
+        }
+
+        let new_peer = Peer::answer(
+            stream,
+            self.address,
+            self.services,
+            self.version,
+            self.nonce,
+            &self.user_agent,
+            self.peer_action_receiver.clone(),
+            self.logger_sender.clone(),
+            self.node_action_sender.clone(),
+        );
+
+        let mut node_state = self.node_state_ref.lock()?;
+        let Ok(new_peer) = new_peer else {
+            node_state.record_disconnect(DisconnectEvent::handshake_failed(get_address_v6(
+                peer_address,
+            )))?;
+            return Ok(());
+        };
+        let behind_network = node_state.append_peers(vec![new_peer])?;
+        drop(node_state);
 
-            let mut node_state = self.node_state_ref.lock()?;
-            node_state.append_peers(vec![new_peer]);
-            drop(node_state);
+        if behind_network {
+            self.node_action_sender.send(NodeAction::BehindNetwork)?;
         }
 
         Ok(())
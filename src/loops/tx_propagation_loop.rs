@@ -0,0 +1,58 @@
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    error::CustomError,
+    logger::{send_log, Log},
+    node_state::NodeState,
+};
+
+use super::node_action_loop::NodeAction;
+
+/// Cantidad de peers a los que se envian las transacciones que creamos si el config no indica BROADCAST_PEERS (0 las envia a todos sin verificar que se propaguen).
+pub const DEFAULT_BROADCAST_PEERS: usize = 0;
+
+/// Segundos que se espera a que otro peer anuncie una transaccion que creamos si el config no indica TX_ECHO_TIMEOUT.
+pub const DEFAULT_TX_ECHO_TIMEOUT: u64 = 60;
+
+/// Tiempo maximo entre cada revision de la propagacion de las transacciones.
+const PROPAGATION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// tx_propagation_loop es una funcion que genera un loop que verifica que las transacciones que creamos se propaguen.
+/// Si ningun peer al que no se la enviamos anuncia una transaccion dentro del timeout, se reenvia a otros peers
+/// y, si se agotan los reenvios, se avisa a la interfaz (ver NodeState::check_tx_propagation).
+/// Los peers a los que no se pudo reenviar se desconectan mediante NodeAction::PeerError.
+/// Los elementos son:
+/// - node_state_ref: Referencia al estado del nodo.
+/// - node_action_sender: Sender para enviar acciones al nodo.
+/// - logger_sender: Sender para enviar logs al logger.
+/// - timeout: Tiempo que se espera a que otro peer anuncie la transaccion desde su ultimo envio.
+pub fn tx_propagation_loop(
+    node_state_ref: Arc<Mutex<NodeState>>,
+    node_action_sender: mpsc::Sender<NodeAction>,
+    logger_sender: mpsc::Sender<Log>,
+    timeout: Duration,
+) -> thread::JoinHandle<Result<(), CustomError>> {
+    let check_interval = PROPAGATION_CHECK_INTERVAL.min(timeout);
+    thread::spawn(move || -> Result<(), CustomError> {
+        loop {
+            thread::sleep(check_interval);
+            let mut node_state = node_state_ref.lock()?;
+            let failed_peers = node_state.check_tx_propagation(timeout)?;
+            drop(node_state);
+
+            for (address, reason) in failed_peers {
+                send_log(
+                    &logger_sender,
+                    Log::Message(format!(
+                        "Error sending transaction to peer {address}: {reason}"
+                    )),
+                );
+                node_action_sender.send(NodeAction::PeerError(address, reason))?;
+            }
+        }
+    })
+}
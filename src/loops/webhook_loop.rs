@@ -0,0 +1,74 @@
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::CustomError,
+    logger::{send_log, Log},
+    node_state::NodeState,
+    webhooks::Webhook,
+};
+
+/// Tiempo entre las revisiones de la cola de eventos que se envian al webhook.
+pub const WEBHOOK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Cantidad maxima de eventos que se sacan de la cola en cada revision, para no retener los eventos que se
+/// encolan mientras se envian si el webhook responde lento.
+pub const WEBHOOK_BATCH: usize = 5;
+
+/// webhook_loop es una funcion que genera un loop que envia al webhook los eventos de las wallets encolados en el NodeState.
+/// Corre en su propio thread porque cada envio puede demorar hasta WEBHOOK_DEADLINE, y no debe frenar al resto de las tareas.
+/// Los eventos se sacan de la cola con el lock del NodeState, pero se envian sin tenerlo.
+/// Los que fallan se reintentan (ver WebhookQueue::failed) y los que agotan sus intentos se guardan en el dead letter log.
+/// Tambien loguea cuantos eventos se descartaron por encontrar la cola llena desde la revision anterior.
+/// Los elementos son:
+/// - node_state_ref: Referencia al estado del nodo.
+/// - webhook: Webhook al que se envian los eventos.
+/// - logger_sender: Sender para enviar logs al logger.
+pub fn webhook_loop(
+    node_state_ref: Arc<Mutex<NodeState>>,
+    webhook: Webhook,
+    logger_sender: mpsc::Sender<Log>,
+) -> thread::JoinHandle<Result<(), CustomError>> {
+    thread::spawn(move || -> Result<(), CustomError> {
+        let mut logged_dropped = 0;
+        loop {
+            thread::sleep(WEBHOOK_INTERVAL);
+            let mut node_state = node_state_ref.lock()?;
+            let due = node_state.take_due_webhook_events(Instant::now(), WEBHOOK_BATCH);
+            let dropped = node_state.webhook_events_dropped();
+            drop(node_state);
+
+            if dropped > logged_dropped {
+                send_log(
+                    &logger_sender,
+                    Log::Message(format!(
+                        "Webhook queue is full, {} events dropped",
+                        dropped - logged_dropped
+                    )),
+                );
+                logged_dropped = dropped;
+            }
+
+            for queued in due {
+                let Err(error) = webhook.deliver(&queued.event) else { continue };
+                let exhausted = node_state_ref
+                    .lock()?
+                    .webhook_event_failed(queued, Instant::now());
+                let Some(exhausted) = exhausted else { continue };
+                if let Err(error) = webhook.dead_letter(&exhausted.event, &error) {
+                    send_log(&logger_sender, Log::Error(error));
+                }
+                send_log(
+                    &logger_sender,
+                    Log::Message(format!(
+                        "Webhook event for transaction {} could not be delivered: {}",
+                        exhausted.event.txid, error
+                    )),
+                );
+            }
+        }
+    })
+}
@@ -1,111 +1,136 @@
 use bitcoin::{
+    bench_ibd::{self, BENCH_IBD_FLAG},
     config::Config,
+    config_reload::ConfigReloader,
+    console_status,
+    crash_report::{exit_code, EXIT_CLEAN, EXIT_CONFIG_ERROR, EXIT_FAILURE},
     gui::init::GUI,
     logger::{send_log, Log, Logger},
-    loops::node_action_loop::NodeAction,
-    node::Node,
-    node_state::NodeState,
-    utils::get_addresses,
+    node_handle::NodeHandle,
+    rpc::HEADLESS_FLAG,
+    store_lock::StoreLock,
+    utxo_snapshot::{self, EXPORT_UTXO_FLAG, IMPORT_UTXO_FLAG},
 };
 use gtk::glib::{self, Priority};
-use std::{env, path::Path};
+use std::{env, path::Path, process};
 
 const CANT_ARGS: usize = 2;
+const STATUS_LINE_FLAG: &str = "--status-line";
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    if args.iter().any(|arg| arg == BENCH_IBD_FLAG) {
+        process::exit(bench_ibd::run_from_args(&args));
+    }
     if args.len() < CANT_ARGS {
         println!("ERROR: config file path missing");
-        return;
+        process::exit(EXIT_CONFIG_ERROR);
     }
+    let status_line = args.iter().skip(2).any(|arg| arg == STATUS_LINE_FLAG);
+    let headless = args.iter().skip(2).any(|arg| arg == HEADLESS_FLAG);
     let path = Path::new(&args[1]);
     if !path.exists() {
         println!("ERROR: config file not found at {}", path.display());
-        return;
+        process::exit(EXIT_CONFIG_ERROR);
     }
 
     let config = match Config::from_file(args[1].as_str()) {
         Ok(config) => config,
         Err(error) => {
             println!("ERROR: {error}");
-            return;
+            process::exit(exit_code(&error));
         }
     };
 
+    if args
+        .iter()
+        .any(|arg| arg == EXPORT_UTXO_FLAG || arg == IMPORT_UTXO_FLAG)
+    {
+        let _store_lock = acquire_store_lock(&config);
+        process::exit(utxo_snapshot::run_from_args(&config, &args));
+    }
+
+    // se libera al terminar el proceso, aunque sea por un panic
+    let store_lock = acquire_store_lock(&config);
+
     let (gui_sender, gui_receiver) = glib::MainContext::channel(Priority::default());
 
-    let logger = match Logger::new(&config.log_file, gui_sender.clone()) {
+    let logger = match Logger::with_status_line(&config.log_file, gui_sender.clone(), status_line) {
         Ok(logger) => logger,
         Err(error) => {
             println!("ERROR: {error}");
-            return;
+            process::exit(exit_code(&error));
         }
     };
 
-    let logger_sender = logger.get_sender();
-
-    let node_state_ref = match NodeState::new(
-        logger_sender.clone(),
-        gui_sender.clone(),
-        &config.store_path,
-    ) {
-        Ok(node_state) => node_state,
-        Err(error) => {
-            send_log(&logger_sender, Log::Error(error));
-            return;
-        }
-    };
-
-    let node = match Node::new(&config, &logger, node_state_ref.clone()) {
+    let node = match NodeHandle::start_with(&config, store_lock, logger, gui_sender.clone(), true) {
         Ok(node) => node,
-        Err(error) => {
-            send_log(&logger_sender, Log::Error(error));
-            return;
-        }
+        Err(error) => process::exit(exit_code(&error)),
     };
 
-    let node_action_sender = node.node_action_sender.clone();
-
-    let addresses = get_addresses(config.seed.clone(), config.port);
-    let addresses = match addresses {
-        Ok(addresses) => addresses,
-        Err(error) => {
-            send_log(&logger_sender, Log::Error(error));
-            return;
+    if headless {
+        if let Err(error) = node.start_rpc_server(&config) {
+            let code = exit_code(&error);
+            send_log(&node.logger_sender, Log::Error(error));
+            let _ = node.stop();
+            process::exit(code);
         }
-    };
+    }
+
+    let config_reloader = ConfigReloader::new(
+        &args[1],
+        config,
+        node.node_action_sender.clone(),
+        node.logger_sender.clone(),
+    );
+    #[cfg(unix)]
+    bitcoin::config_reload::reload_on_sighup(config_reloader.clone());
 
-    let node_thread = node.spawn(addresses, gui_sender);
+    if status_line {
+        console_status::start(
+            gui_receiver,
+            node.node_state_ref.clone(),
+            node.logger_sender.clone(),
+        );
+        return;
+    }
+    if headless {
+        // sin interfaz grafica solo se consumen los GUIEvents, el nodo se maneja por el RPC
+        gui_receiver.attach(None, |_| glib::Continue(true));
+        glib::MainLoop::new(None, false).run();
+        return;
+    }
 
     let gui = GUI::start(
         gui_receiver,
-        node_state_ref,
-        logger_sender.clone(),
-        node_action_sender.clone(),
+        gui_sender,
+        node.node_state_ref.clone(),
+        node.logger_sender.clone(),
+        node.node_action_sender.clone(),
+        config_reloader,
     );
 
+    let mut code = EXIT_CLEAN;
     if let Err(error) = gui {
         send_log(
-            &logger_sender,
+            &node.logger_sender,
             Log::Message(format!("Error starting GUI: {}", error)),
         );
+        code = EXIT_FAILURE;
     };
 
-    if node_action_sender.send(NodeAction::Terminate).is_ok() {
-        if let Err(error) = node_thread.join() {
-            send_log(
-                &logger_sender,
-                Log::Message(format!("Error closing node thread: {:?}", error)),
-            );
-        };
-    }
+    // los errores del thread del nodo ya quedan logueados
+    let _ = node.stop();
+    process::exit(code);
+}
 
-    if logger.tx.send(Log::Terminate).is_ok() {
-        if let Err(error) = logger.thread.join() {
-            send_log(
-                &logger_sender,
-                Log::Message(format!("Error closing logger thread: {:?}", error)),
-            );
-        };
+/// Toma el lock del store, terminando el proceso si otro nodo lo esta usando.
+fn acquire_store_lock(config: &Config) -> StoreLock {
+    match StoreLock::acquire(&config.store_path) {
+        Ok(store_lock) => store_lock,
+        Err(error) => {
+            println!("ERROR: {error}");
+            process::exit(exit_code(&error));
+        }
     }
 }
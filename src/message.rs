@@ -1,8 +1,12 @@
-use crate::error::CustomError;
+use crate::error::{CustomError, ErrorSource};
 use bitcoin_hashes::sha256;
 use bitcoin_hashes::Hash;
+use bitcoin_hashes::HashEngine;
 
+use std::io::ErrorKind;
 use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
 use std::io::Write;
 use std::net::TcpStream;
 
@@ -30,30 +34,39 @@ pub trait Message {
     where
         Self: Sized,
     {
-        let header = MessageHeader::new(self);
+        self.send_sized(stream)?;
+        Ok(())
+    }
 
-        stream
-            .write(&header.serialize())
-            .map_err(|_| CustomError::CannotSendMessageToChannel)?;
+    /// Envía el mensaje a un stream y devuelve la cantidad de bytes enviados (header y payload).
+    /// Si la escritura supera el timeout del stream devuelve StreamWriteTimeout, en el resto de los casos igual que send.
+    fn send_sized(&self, stream: &mut TcpStream) -> Result<u64, CustomError>
+    where
+        Self: Sized,
+    {
+        let payload = self.serialize();
+        let header = MessageHeader::from_payload(self.get_command(), &payload);
 
-        stream
-            .write(&self.serialize())
-            .map_err(|_| CustomError::CannotSendMessageToChannel)?;
+        stream.write_all(&header.serialize()).map_err(write_error)?;
 
-        stream
-            .flush()
-            .map_err(|_| CustomError::CannotSendMessageToChannel)?;
+        stream.write_all(&payload).map_err(write_error)?;
 
-        Ok(())
+        stream.flush().map_err(write_error)?;
+
+        Ok((MESSAGE_HEADER_SIZE + payload.len()) as u64)
     }
 
     /// Lee un mensaje de un stream y lo parsea.
     /// Devuelve CustomError si:
+    /// - El tamaño del mensaje supera MAX_MESSAGE_SIZE (MessageTooLarge).
     /// - No se puede leer del stream
     fn read(stream: &mut TcpStream, message_size: u32) -> Result<Self, CustomError>
     where
         Self: Sized,
     {
+        if message_size > MAX_MESSAGE_SIZE {
+            return Err(CustomError::MessageTooLarge(message_size));
+        }
         let mut payload_buffer = vec![0; message_size as usize];
 
         stream
@@ -62,6 +75,17 @@ pub trait Message {
 
         Self::parse(payload_buffer)
     }
+
+    /// Lee el payload del mensaje descripto por el header y lo parsea, verificando antes que coincida con el checksum del header.
+    /// Devuelve CustomError si:
+    /// - No se puede leer del stream.
+    /// - El checksum del payload no coincide con el del header (InvalidChecksum).
+    fn read_with_header(stream: &mut impl Read, header: &MessageHeader) -> Result<Self, CustomError>
+    where
+        Self: Sized,
+    {
+        Self::parse(header.read_payload(stream)?)
+    }
 }
 
 /// Calcula el checksum de un payload.
@@ -72,8 +96,107 @@ fn get_checksum(payload: &[u8]) -> [u8; 4] {
     [hash[0], hash[1], hash[2], hash[3]]
 }
 
+/// Tamaño en bytes de los fragmentos en los que se lee y envia un payload guardado en disco.
+pub const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Envía a un stream un mensaje cuyo payload ya esta serializado en un archivo (por ejemplo un bloque guardado en disco),
+/// sin cargarlo completo en memoria: el tamaño del payload es el largo del archivo y el checksum se calcula leyendolo de a
+/// STREAM_CHUNK_SIZE bytes, luego se escribe el header y se copia el archivo al stream en fragmentos del mismo tamaño.
+/// Devuelve la cantidad de bytes enviados (header y payload).
+/// Devuelve CustomError si:
+/// - No se puede leer el archivo o es mas grande que el maximo tamaño de payload.
+/// - No se puede escribir en el stream (StreamWriteTimeout si se supera el timeout de escritura).
+pub fn send_streamed(
+    stream: &mut impl Write,
+    command: &str,
+    payload: &mut (impl Read + Seek),
+) -> Result<u64, CustomError> {
+    write_streamed(stream, command, payload, STREAM_CHUNK_SIZE)
+}
+
+fn write_streamed(
+    stream: &mut impl Write,
+    command: &str,
+    payload: &mut (impl Read + Seek),
+    chunk_size: usize,
+) -> Result<u64, CustomError> {
+    let payload_size = payload.seek(SeekFrom::End(0))?;
+    let payload_size =
+        u32::try_from(payload_size).map_err(|_| CustomError::SerializedBufferIsInvalid)?;
+
+    payload.seek(SeekFrom::Start(0))?;
+    let checksum = get_streamed_checksum(payload, chunk_size)?;
+    let header = MessageHeader {
+        magic: MAGIC,
+        command: command.to_string(),
+        payload_size,
+        checksum,
+    };
+    stream.write_all(&header.serialize()).map_err(write_error)?;
+
+    payload.seek(SeekFrom::Start(0))?;
+    let mut buffer = vec![0; chunk_size];
+    let mut remaining = payload_size as usize;
+    while remaining > 0 {
+        let chunk = &mut buffer[..remaining.min(chunk_size)];
+        payload.read_exact(chunk)?;
+        stream.write_all(chunk).map_err(write_error)?;
+        remaining -= chunk.len();
+    }
+    stream.flush().map_err(write_error)?;
+
+    Ok((MESSAGE_HEADER_SIZE + payload_size as usize) as u64)
+}
+
+/// Calcula el checksum de un payload leyendolo de a chunk_size bytes, igual que get_checksum pero sin tenerlo completo en memoria.
+fn get_streamed_checksum(
+    payload: &mut impl Read,
+    chunk_size: usize,
+) -> Result<[u8; 4], CustomError> {
+    let mut engine = sha256::Hash::engine();
+    let mut buffer = vec![0; chunk_size];
+    loop {
+        let read = payload.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        engine.input(&buffer[..read]);
+    }
+    let first_hash = sha256::Hash::from_engine(engine);
+    let hash = sha256::Hash::hash(first_hash.as_byte_array());
+    Ok([hash[0], hash[1], hash[2], hash[3]])
+}
+
+/// Convierte un error de lectura del stream, distinguiendo si se supero el timeout de lectura o si el peer cerro la conexion.
+fn read_error(error: std::io::Error) -> CustomError {
+    match error.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => CustomError::StreamReadTimeout,
+        ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted => {
+            CustomError::PeerClosedConnection
+        }
+        _ => CustomError::CannotReadMessageHeader,
+    }
+}
+
+/// Convierte un error de escritura en el stream, distinguiendo si se supero el timeout de escritura.
+/// En el resto de los casos conserva el error original como causa.
+fn write_error(error: std::io::Error) -> CustomError {
+    match error.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => CustomError::StreamWriteTimeout,
+        _ => CustomError::CannotSendMessageToChannel(Some(ErrorSource::new(error))),
+    }
+}
+
 /// El magic number es un número que se usa para identificar la red, en nuestro caso, la testnet.
 const MAGIC: u32 = 0x0b110907;
+
+/// Tamaño en bytes del header de todos los mensajes.
+pub const MESSAGE_HEADER_SIZE: usize = 24;
+
+/// Tamaño maximo en bytes del payload de un mensaje (el mismo limite que usa Bitcoin Core).
+/// Un header que anuncia un payload mas grande se rechaza antes de reservar memoria para leerlo.
+pub const MAX_MESSAGE_SIZE: u32 = 32 * 1024 * 1024;
+
 #[derive(Debug)]
 /// Representa el header de un mensaje.
 /// El header contiene:
@@ -91,15 +214,16 @@ pub struct MessageHeader {
 impl MessageHeader {
     /// Crea un nuevo header a partir de un mensaje.
     pub fn new(message: &dyn Message) -> Self {
-        let payload = message.serialize();
-        let payload_size = payload.len() as u32;
-        let checksum = get_checksum(&payload);
+        Self::from_payload(message.get_command(), &message.serialize())
+    }
 
+    /// Crea un nuevo header a partir del comando y el payload ya serializado de un mensaje.
+    pub fn from_payload(command: String, payload: &[u8]) -> Self {
         MessageHeader {
             magic: MAGIC,
-            command: message.get_command(),
-            payload_size,
-            checksum,
+            command,
+            payload_size: payload.len() as u32,
+            checksum: get_checksum(payload),
         }
     }
 
@@ -151,25 +275,90 @@ impl MessageHeader {
 
     /// Lee un header de un stream y lo parsea.
     /// Devuelve CustomError si:
+    /// - Se supera el timeout de lectura del stream (StreamReadTimeout).
+    /// - El peer cerro la conexion o la reinicio (PeerClosedConnection).
     /// - No se puede leer del stream.
-    pub fn read(stream: &mut TcpStream) -> Result<Self, CustomError> {
+    /// - El header no empieza con el magic number de la red (InvalidMagic).
+    pub fn read(stream: &mut impl Read) -> Result<Self, CustomError> {
         let mut header_buffer = [0; 24];
 
-        stream
-            .read_exact(&mut header_buffer)
-            .map_err(|_| CustomError::CannotReadMessageHeader)?;
+        stream.read_exact(&mut header_buffer).map_err(read_error)?;
 
         let header = Self::parse(header_buffer)?;
+        if header.magic != MAGIC {
+            return Err(CustomError::InvalidMagic);
+        }
 
         Ok(header)
     }
+
+    /// Lee un header de un stream, descartando de a un byte lo que haya antes del magic number de la red
+    /// para volver a sincronizarse con el inicio de un mensaje en lugar de cortar la conexion.
+    /// Devuelve el header y la cantidad de bytes descartados.
+    /// Devuelve CustomError en los mismos casos que read, salvo InvalidMagic.
+    pub fn read_resync(stream: &mut impl Read) -> Result<(Self, usize), CustomError> {
+        let mut header_buffer = [0; 24];
+        stream
+            .read_exact(&mut header_buffer[..4])
+            .map_err(read_error)?;
+
+        let mut skipped = 0;
+        while header_buffer[..4] != MAGIC.to_be_bytes() {
+            header_buffer.copy_within(1..4, 0);
+            stream
+                .read_exact(&mut header_buffer[3..4])
+                .map_err(read_error)?;
+            skipped += 1;
+        }
+        stream
+            .read_exact(&mut header_buffer[4..])
+            .map_err(read_error)?;
+
+        Ok((Self::parse(header_buffer)?, skipped))
+    }
+
+    /// Lee de un stream el payload del mensaje descripto por el header.
+    /// Devuelve CustomError si:
+    /// - El header anuncia un payload de mas de MAX_MESSAGE_SIZE bytes (MessageTooLarge), sin leerlo.
+    /// - No se puede leer del stream.
+    /// - El checksum del payload no coincide con el del header (InvalidChecksum).
+    pub fn read_payload(&self, stream: &mut impl Read) -> Result<Vec<u8>, CustomError> {
+        if self.payload_size > MAX_MESSAGE_SIZE {
+            return Err(CustomError::MessageTooLarge(self.payload_size));
+        }
+        let mut payload = vec![0; self.payload_size as usize];
+        stream
+            .read_exact(&mut payload)
+            .map_err(|_| CustomError::CannotReadStream)?;
+
+        if get_checksum(&payload) != self.checksum {
+            return Err(CustomError::InvalidChecksum);
+        }
+        Ok(payload)
+    }
+
+    /// Descarta de un stream el payload del mensaje descripto por el header, leyendolo de a fragmentos
+    /// para no cargar en memoria un payload que no se va a usar (por ejemplo uno que supera un limite de tamaño).
+    /// Devuelve CustomError si no se puede leer el payload completo del stream.
+    pub fn skip_payload(&self, stream: &mut impl Read) -> Result<(), CustomError> {
+        let payload_size = u64::from(self.payload_size);
+        let skipped = std::io::copy(
+            &mut stream.by_ref().take(payload_size),
+            &mut std::io::sink(),
+        )
+        .map_err(|_| CustomError::CannotReadStream)?;
+        if skipped != payload_size {
+            return Err(CustomError::CannotReadStream);
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::net::{Ipv6Addr, SocketAddrV6};
 
-    use crate::messages::version::Version;
+    use crate::messages::{block::Block, ping_pong::Ping, version::Version};
 
     use super::*;
 
@@ -184,7 +373,7 @@ mod tests {
     fn test_message_header_length() {
         let sender_address = SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), 4321, 0, 0);
         let receiver_address = SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), 8080, 0, 0);
-        let version = Version::new(receiver_address, sender_address, 70000, 0x00);
+        let version = Version::new(receiver_address, sender_address, 70000, 0x00, 0);
 
         let header = MessageHeader::new(&version).serialize();
         assert_eq!(header.len(), 24);
@@ -205,4 +394,111 @@ mod tests {
         assert_eq!(header.checksum.len(), 4);
         assert_eq!(header.checksum, [75, 114, 249, 186]);
     }
+
+    #[test]
+    fn message_header_read_detects_closed_connection() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (remote, _) = listener.accept().unwrap();
+        drop(remote);
+
+        let error = MessageHeader::read(&mut stream).unwrap_err();
+        assert!(matches!(error, CustomError::PeerClosedConnection));
+    }
+
+    fn in_memory_message(command: &str, payload: &[u8]) -> Vec<u8> {
+        let mut message = MessageHeader::from_payload(command.to_string(), payload).serialize();
+        message.extend(payload);
+        message
+    }
+
+    #[test]
+    fn streamed_message_matches_in_memory_message() {
+        let sizes = [0, 1, 7, 4095, 4096, 4097, 3 * 4096 + 5];
+        for size in sizes {
+            let payload: Vec<u8> = (0..size).map(|i| (i * 31 % 251) as u8).collect();
+            for chunk_size in [1, 7, 4096, STREAM_CHUNK_SIZE] {
+                let mut streamed = vec![];
+                let sent = write_streamed(
+                    &mut streamed,
+                    "block",
+                    &mut std::io::Cursor::new(&payload),
+                    chunk_size,
+                )
+                .unwrap();
+
+                assert_eq!(streamed, in_memory_message("block", &payload));
+                assert_eq!(sent, (MESSAGE_HEADER_SIZE + size) as u64);
+            }
+        }
+    }
+
+    #[test]
+    fn streamed_block_file_matches_block_message() {
+        let mut file = std::fs::File::open("tests/blocks/test_block.bin").unwrap();
+        let mut payload = vec![];
+        file.read_to_end(&mut payload).unwrap();
+        let block = Block::parse(payload.clone()).unwrap();
+
+        for chunk_size in [1000, STREAM_CHUNK_SIZE] {
+            let mut streamed = vec![];
+            write_streamed(&mut streamed, "block", &mut file, chunk_size).unwrap();
+
+            let header = MessageHeader::parse(streamed[..24].try_into().unwrap()).unwrap();
+            assert_eq!(header.payload_size as usize, payload.len());
+            assert_eq!(header.checksum, get_checksum(&payload));
+            assert_eq!(streamed, in_memory_message("block", &block.serialize()));
+        }
+    }
+
+    #[test]
+    fn read_with_header_rejects_flipped_payload_byte() {
+        let mut message = in_memory_message("ping", &Ping { nonce: 42 }.serialize());
+        let mut stream = std::io::Cursor::new(message.clone());
+        let header = MessageHeader::read(&mut stream).unwrap();
+        assert_eq!(
+            Ping::read_with_header(&mut stream, &header).unwrap().nonce,
+            42
+        );
+
+        message[MESSAGE_HEADER_SIZE] ^= 0x01;
+        let mut stream = std::io::Cursor::new(message);
+        let header = MessageHeader::read(&mut stream).unwrap();
+        let result = Ping::read_with_header(&mut stream, &header);
+        assert!(matches!(result, Err(CustomError::InvalidChecksum)));
+    }
+
+    #[test]
+    fn read_payload_rejects_headers_over_the_max_message_size() {
+        let mut message = in_memory_message("ping", &Ping { nonce: 42 }.serialize());
+        message[16..20].copy_from_slice(&(MAX_MESSAGE_SIZE + 1).to_le_bytes());
+        let mut stream = std::io::Cursor::new(message);
+        let header = MessageHeader::read(&mut stream).unwrap();
+
+        let result = Ping::read_with_header(&mut stream, &header);
+        assert!(matches!(
+            result,
+            Err(CustomError::MessageTooLarge(size)) if size == MAX_MESSAGE_SIZE + 1
+        ));
+        // el payload no se leyo del stream
+        assert_eq!(stream.position(), MESSAGE_HEADER_SIZE as u64);
+    }
+
+    #[test]
+    fn read_resync_skips_leading_junk() {
+        let mut stream = vec![0x0b, 0x11, 0xff, 0x00, 0x0b, 0x11, 0x09];
+        stream.extend(in_memory_message("ping", &Ping { nonce: 7 }.serialize()));
+
+        let error = MessageHeader::read(&mut std::io::Cursor::new(stream.clone())).unwrap_err();
+        assert!(matches!(error, CustomError::InvalidMagic));
+
+        let mut stream = std::io::Cursor::new(stream);
+        let (header, skipped) = MessageHeader::read_resync(&mut stream).unwrap();
+        assert_eq!(skipped, 7);
+        assert_eq!(header.command, "ping");
+        assert_eq!(
+            Ping::read_with_header(&mut stream, &header).unwrap().nonce,
+            7
+        );
+    }
 }
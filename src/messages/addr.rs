@@ -0,0 +1,163 @@
+use std::net::SocketAddrV6;
+
+use crate::{
+    error::CustomError,
+    message::Message,
+    parser::{BufferParser, VarIntSerialize},
+};
+
+/// Cantidad maxima de direcciones que puede tener un mensaje addr.
+pub const MAX_ADDR_ENTRIES: usize = 1000;
+
+/// Tamaño de cada direccion serializada: timestamp (4), services (8), ip (16) y puerto (2).
+const NETWORK_ADDRESS_SIZE: usize = 30;
+
+#[derive(Debug, Clone, PartialEq)]
+/// NetworkAddress es una direccion anunciada en un mensaje addr.
+/// Los elementos son:
+/// - timestamp: Ultima vez que el peer que la anuncia vio activa la direccion.
+/// - services: Servicios que ofrece el nodo de la direccion.
+/// - address: Socket v6 del nodo (las direcciones ipv4 vienen mapeadas a ipv6).
+pub struct NetworkAddress {
+    pub timestamp: u32,
+    pub services: u64,
+    pub address: SocketAddrV6,
+}
+
+impl NetworkAddress {
+    /// Crea una direccion a partir de sus elementos.
+    pub fn new(timestamp: u32, services: u64, address: SocketAddrV6) -> Self {
+        Self {
+            timestamp,
+            services,
+            address,
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut buffer = vec![];
+        buffer.extend(self.timestamp.to_le_bytes());
+        buffer.extend(self.services.to_le_bytes());
+        buffer.extend(self.address.ip().octets());
+        buffer.extend(self.address.port().to_be_bytes());
+        buffer
+    }
+
+    fn parse(parser: &mut BufferParser) -> Result<Self, CustomError> {
+        let timestamp = parser.extract_u32()?;
+        let services = parser.extract_u64()?;
+        let address = parser.extract_address()?;
+        Ok(Self {
+            timestamp,
+            services,
+            address,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Addr es el mensaje 'addr', con direcciones de otros nodos de la red que conoce el peer.
+/// Lo envian los peers como respuesta a un mensaje getaddr o para anunciar direcciones nuevas.
+pub struct Addr {
+    pub addresses: Vec<NetworkAddress>,
+}
+
+impl Addr {
+    /// Crea un nuevo mensaje addr con las direcciones recibidas.
+    pub fn new(addresses: Vec<NetworkAddress>) -> Self {
+        Self { addresses }
+    }
+
+    /// Devuelve los sockets de las direcciones del mensaje.
+    pub fn sockets(&self) -> Vec<SocketAddrV6> {
+        self.addresses
+            .iter()
+            .map(|network_address| network_address.address)
+            .collect()
+    }
+}
+
+/// Implementa el trait Message para el mensaje addr.
+/// Permite serializar, parsear y obtener el comando
+impl Message for Addr {
+    fn get_command(&self) -> String {
+        String::from("addr")
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut buffer = vec![];
+        buffer.extend(self.addresses.len().to_varint_bytes());
+        for network_address in &self.addresses {
+            buffer.extend(network_address.serialize());
+        }
+        buffer
+    }
+
+    fn parse(buffer: Vec<u8>) -> Result<Self, CustomError> {
+        let mut parser = BufferParser::new(buffer);
+        let count = parser.extract_varint()? as usize;
+
+        if count > MAX_ADDR_ENTRIES || parser.len() != count * NETWORK_ADDRESS_SIZE {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+
+        let mut addresses = vec![];
+        for _ in 0..count {
+            addresses.push(NetworkAddress::parse(&mut parser)?);
+        }
+        Ok(Self { addresses })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    fn test_addr() -> Addr {
+        Addr::new(vec![
+            NetworkAddress::new(
+                1_700_000_000,
+                1,
+                SocketAddrV6::new(Ipv4Addr::new(10, 0, 0, 1).to_ipv6_mapped(), 18333, 0, 0),
+            ),
+            NetworkAddress::new(
+                1_700_000_100,
+                0x409,
+                SocketAddrV6::new(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), 8333, 0, 0),
+            ),
+        ])
+    }
+
+    #[test]
+    fn addr_round_trip() {
+        let addr = test_addr();
+        let buffer = addr.serialize();
+        assert_eq!(buffer.len(), 1 + 2 * NETWORK_ADDRESS_SIZE);
+        assert_eq!(Addr::parse(buffer).unwrap(), addr);
+    }
+
+    #[test]
+    fn addr_serializes_port_big_endian() {
+        let buffer = test_addr().serialize();
+        assert_eq!(&buffer[29..31], &18333_u16.to_be_bytes());
+    }
+
+    #[test]
+    fn addr_rejects_truncated_and_trailing_bytes() {
+        let mut buffer = test_addr().serialize();
+        buffer.push(0);
+        assert!(Addr::parse(buffer.clone()).is_err());
+
+        buffer.truncate(buffer.len() - 2);
+        assert!(Addr::parse(buffer).is_err());
+    }
+
+    #[test]
+    fn addr_rejects_too_many_entries() {
+        let entry = test_addr().addresses[0].clone();
+        let addr = Addr::new(vec![entry; MAX_ADDR_ENTRIES + 1]);
+        assert!(Addr::parse(addr.serialize()).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     fs::remove_file,
     io::{Read, Write},
     vec,
@@ -12,10 +13,16 @@ use crate::{
     error::CustomError,
     message::Message,
     parser::{BufferParser, VarIntSerialize},
-    structs::block_header::BlockHeader,
-    utils::open_new_file,
+    states::utxo_state::UTXO,
+    structs::{block_header::BlockHeader, tx_output::TransactionOutput},
+    utils::{open_file, OpenMode},
 };
 
+/// Subsidio en satoshis de la coinbase de los primeros bloques.
+const INITIAL_SUBSIDY: u64 = 50 * 100_000_000;
+/// Cantidad de bloques cada la cual el subsidio se divide a la mitad.
+const HALVING_INTERVAL: usize = 210_000;
+
 #[derive(Debug)]
 
 /// Esta estructura es la que se encarga de almacenar los bloques, esto lo hace con un BlockHeader y en un vector de 'transactions' por cada uno
@@ -34,9 +41,9 @@ impl Block {
     }
 
     /// Esta funcion se encargar de restaurar un bloque, recibe un path al archivo que contiene al bloque, lo lee y lo parsea
-    /// Devuelve CustomError si no puede abrir o leer el archivo
+    /// Devuelve CustomError si no puede abrir o leer el archivo (si no existe no se crea).
     pub fn restore(path: String) -> Result<Self, CustomError> {
-        let mut block_file = open_new_file(path.clone(), true)?;
+        let mut block_file = open_file(&path, OpenMode::Read)?;
         let mut block_buffer = Vec::new();
         block_file.read_to_end(&mut block_buffer)?;
         let block = match Self::parse(block_buffer) {
@@ -51,9 +58,15 @@ impl Block {
     }
 
     /// Esta funcion se encarga de guardar un bloque, recibe un path al archivo donde se va a guardar el bloque serializado en bytes
+    /// Un bloque se guarda una unica vez: si el archivo ya existe devuelve un error sin modificarlo.
+    /// Si la escritura falla se borra el archivo, para que no quede un bloque incompleto que parezca guardado.
     pub fn save(&self, path: String) -> Result<(), CustomError> {
-        let mut block_file = open_new_file(path, true)?;
-        block_file.write_all(&self.serialize())?;
+        let mut block_file = open_file(&path, OpenMode::CreateNew)?;
+        if let Err(error) = block_file.write_all(&self.serialize()) {
+            drop(block_file);
+            remove_file(&path)?;
+            return Err(error.into());
+        }
         Ok(())
     }
 
@@ -72,12 +85,8 @@ impl Block {
     /// Esta funcion se encarga de validar la proof of inclusion del bloque, creando el merkle tree y comparando el merkle root del BlockHeader con el merkle root calculado
     /// Devuelve CustomError si el merkle root del BlockHeader no coincide con el merkle root calculado, significando que el bloque no es valido
     pub fn create_merkle_root(&self) -> Result<(), CustomError> {
-        let merkle_tree = self.create_merkle_tree();
-
-        let merkle_root = match merkle_tree.last() {
-            Some(root_level) => root_level[0].to_vec(),
-            None => return Err(CustomError::InvalidMerkleRoot),
-        };
+        let merkle_root =
+            calculate_merkle_root(&self.transactions).ok_or(CustomError::InvalidMerkleRoot)?;
 
         if merkle_root != self.header.merkle_root {
             return Err(CustomError::InvalidMerkleRoot);
@@ -85,6 +94,55 @@ impl Block {
         Ok(())
     }
 
+    /// Verifica que la coinbase del bloque no cree mas que el subsidio del height mas las fees del resto de las transacciones.
+    /// Las fees se calculan con los outputs que gasta cada transaccion, que se buscan en las transacciones anteriores del bloque
+    /// o en las UTXO, por lo que las UTXO tienen que estar actualizadas hasta el bloque anterior.
+    /// Devuelve Ok(false) si alguno de esos outputs no se conoce (por ejemplo, es anterior a START_DATE_IBD) y no se puede verificar.
+    /// Devuelve CustomError::InvalidCoinbaseValue si la coinbase supera el subsidio mas las fees
+    /// o si una transaccion gasta menos de lo que crea.
+    pub fn validate_coinbase(&self, utxo: &UTXO, height: usize) -> Result<bool, CustomError> {
+        let mut created_in_block: HashMap<Vec<u8>, &Transaction> = HashMap::new();
+        let mut coinbase_value: u64 = 0;
+        let mut fees: u64 = 0;
+
+        for tx in &self.transactions {
+            let output_value = outputs_value(&tx.outputs)?;
+            if tx.is_coinbase() {
+                coinbase_value = coinbase_value
+                    .checked_add(output_value)
+                    .ok_or(CustomError::InvalidCoinbaseValue)?;
+            } else {
+                let mut input_value: u64 = 0;
+                for tx_in in &tx.inputs {
+                    let out_point = &tx_in.previous_output;
+                    let prev_output = match created_in_block.get(&out_point.hash) {
+                        Some(prev_tx) => prev_tx.outputs.get(out_point.index as usize),
+                        None => utxo.tx_set.get(out_point).map(|value| &value.tx_out),
+                    };
+                    let prev_output = match prev_output {
+                        Some(prev_output) => prev_output,
+                        None => return Ok(false),
+                    };
+                    input_value = input_value
+                        .checked_add(prev_output.value)
+                        .ok_or(CustomError::InvalidCoinbaseValue)?;
+                }
+                let fee = input_value
+                    .checked_sub(output_value)
+                    .ok_or(CustomError::InvalidCoinbaseValue)?;
+                fees = fees
+                    .checked_add(fee)
+                    .ok_or(CustomError::InvalidCoinbaseValue)?;
+            }
+            created_in_block.insert(tx.hash(), tx);
+        }
+
+        if coinbase_value > block_subsidy(height).saturating_add(fees) {
+            return Err(CustomError::InvalidCoinbaseValue);
+        }
+        Ok(true)
+    }
+
     /// Esta funcion se encarga de encontrar el indice de una transaccion dado un bloque y el hash de la transaccion
     /// Devuelve CustomError si no puede encontrar la transaccion en el bloque
     fn find_transaction_index(&self, transaction_hash: &Vec<u8>) -> Result<usize, CustomError> {
@@ -132,8 +190,42 @@ impl Block {
     }
 }
 
+/// Devuelve el subsidio en satoshis que puede crear la coinbase del bloque con ese height.
+/// Empieza en INITIAL_SUBSIDY y se divide a la mitad cada HALVING_INTERVAL bloques.
+pub fn block_subsidy(height: usize) -> u64 {
+    let halvings = height / HALVING_INTERVAL;
+    if halvings >= 64 {
+        return 0;
+    }
+    INITIAL_SUBSIDY >> halvings
+}
+
+/// Suma el valor de los outputs, devuelve CustomError::InvalidCoinbaseValue si la suma no entra en un u64.
+fn outputs_value(outputs: &[TransactionOutput]) -> Result<u64, CustomError> {
+    outputs.iter().try_fold(0_u64, |total, output| {
+        total
+            .checked_add(output.value)
+            .ok_or(CustomError::InvalidCoinbaseValue)
+    })
+}
+
+/// Calcula el merkle root de las transacciones recibidas, por ejemplo para armar el header de un bloque nuevo.
+/// Devuelve None si no hay transacciones.
+pub fn calculate_merkle_root(transactions: &[Transaction]) -> Option<Vec<u8>> {
+    let hashes: Vec<Vec<u8>> = transactions.iter().map(|tx| tx.hash()).collect();
+    if hashes.is_empty() {
+        return None;
+    }
+    let mut merkle_tree = vec![hashes.clone()];
+    generate_merkle_tree(hashes, &mut merkle_tree);
+    merkle_tree
+        .last()
+        .and_then(|root_level| root_level.first().cloned())
+}
+
 /// Esta funcion se encarga de mergear dos hashes, recibe dos hashes y los mergea en un solo hash
-fn merge_hashes(mut left: Vec<u8>, mut right: Vec<u8>) -> Vec<u8> {
+/// Tambien se usa para verificar los arboles parciales de los mensajes merkleblock.
+pub fn merge_hashes(mut left: Vec<u8>, mut right: Vec<u8>) -> Vec<u8> {
     left.append(&mut right);
     let hash = sha256::Hash::hash(sha256::Hash::hash(left.as_slice()).as_byte_array())
         .as_byte_array()
@@ -188,6 +280,9 @@ impl Message for Block {
             let transaction = Transaction::parse_from_parser(&mut parser)?;
             transactions.push(transaction);
         }
+        if !parser.is_empty() {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
 
         Ok(Self {
             header,
@@ -199,12 +294,134 @@ impl Message for Block {
 #[cfg(test)]
 
 mod tests {
-    use std::io::Read;
+    use std::{fs, path::Path};
 
-    use crate::utils::open_new_file;
+    use crate::{
+        states::utxo_state::UTXOValue,
+        structs::{outpoint::OutPoint, tx_input::TransactionInput},
+    };
 
     use super::*;
 
+    fn test_header() -> BlockHeader {
+        let buffer = vec![
+            1, 0, 0, 0, 5, 159, 141, 74, 195, 4, 19, 253, 127, 1, 148, 149, 222, 143, 237, 24, 27,
+            124, 186, 34, 123, 241, 216, 166, 203, 239, 86, 108, 0, 0, 0, 0, 233, 233, 109, 115,
+            249, 241, 6, 200, 176, 73, 10, 24, 28, 209, 102, 159, 255, 179, 239, 72, 185, 225, 10,
+            14, 219, 74, 174, 208, 207, 59, 18, 12, 170, 7, 195, 79, 255, 255, 0, 29, 14, 171, 58,
+            61,
+        ];
+        BlockHeader::parse(buffer).unwrap()
+    }
+
+    fn test_tx(previous_output: OutPoint, value: u64) -> Transaction {
+        Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output,
+                script_sig: vec![],
+                sequence: 0xffffffff,
+            }],
+            outputs: vec![TransactionOutput {
+                value,
+                script_pubkey: vec![],
+            }],
+            lock_time: 0,
+        }
+    }
+
+    fn coinbase_tx(value: u64) -> Transaction {
+        let previous_output = OutPoint {
+            hash: vec![0; 32],
+            index: u32::MAX,
+        };
+        test_tx(previous_output, value)
+    }
+
+    /// UTXO con un unico output de 10000 satoshis, en el outpoint [1; 32]:0
+    fn test_utxo() -> UTXO {
+        let mut utxo = UTXO::new(String::from("tests"), String::from("unused")).unwrap();
+        utxo.tx_set.insert(
+            OutPoint {
+                hash: vec![1; 32],
+                index: 0,
+            },
+            UTXOValue {
+                tx_out: TransactionOutput {
+                    value: 10_000,
+                    script_pubkey: vec![],
+                },
+                block_hash: vec![2; 32],
+                block_timestamp: 0,
+                is_coinbase: false,
+            },
+        );
+        utxo
+    }
+
+    fn funding_outpoint() -> OutPoint {
+        OutPoint {
+            hash: vec![1; 32],
+            index: 0,
+        }
+    }
+
+    #[test]
+    fn block_subsidy_halves_every_interval() {
+        assert_eq!(block_subsidy(0), 5_000_000_000);
+        assert_eq!(block_subsidy(209_999), 5_000_000_000);
+        assert_eq!(block_subsidy(210_000), 2_500_000_000);
+        assert_eq!(block_subsidy(420_000), 1_250_000_000);
+        assert_eq!(block_subsidy(64 * 210_000), 0);
+    }
+
+    #[test]
+    fn validate_coinbase_accepts_subsidy_plus_fees() {
+        let spend = test_tx(funding_outpoint(), 9_000);
+        let chained = test_tx(
+            OutPoint {
+                hash: spend.hash(),
+                index: 0,
+            },
+            8_500,
+        );
+        let coinbase = coinbase_tx(block_subsidy(100) + 1_500);
+        let block = Block::new(test_header(), vec![coinbase, spend, chained]);
+
+        assert!(block.validate_coinbase(&test_utxo(), 100).unwrap());
+    }
+
+    #[test]
+    fn validate_coinbase_rejects_value_over_subsidy_plus_fees() {
+        let spend = test_tx(funding_outpoint(), 9_000);
+        let coinbase = coinbase_tx(block_subsidy(300_000) + 1_001);
+        let block = Block::new(test_header(), vec![coinbase, spend]);
+
+        let result = block.validate_coinbase(&test_utxo(), 300_000);
+        assert!(matches!(result, Err(CustomError::InvalidCoinbaseValue)));
+    }
+
+    #[test]
+    fn validate_coinbase_rejects_transaction_creating_value() {
+        let spend = test_tx(funding_outpoint(), 10_001);
+        let block = Block::new(test_header(), vec![coinbase_tx(0), spend]);
+
+        let result = block.validate_coinbase(&test_utxo(), 100);
+        assert!(matches!(result, Err(CustomError::InvalidCoinbaseValue)));
+    }
+
+    #[test]
+    fn validate_coinbase_with_unknown_outputs_is_not_verified() {
+        let unknown = OutPoint {
+            hash: vec![3; 32],
+            index: 0,
+        };
+        let coinbase = coinbase_tx(u64::MAX);
+        let block = Block::new(test_header(), vec![coinbase, test_tx(unknown, 9_000)]);
+
+        assert!(!block.validate_coinbase(&test_utxo(), 100).unwrap());
+    }
+
     #[test]
     fn merge_hashes_test() {
         let left_hash: Vec<u8> = vec![1, 2, 3];
@@ -220,7 +437,7 @@ mod tests {
 
     #[test]
     fn test_merkle_tree() {
-        let mut file = open_new_file("tests/blocks/test_block.bin".to_string(), true).unwrap();
+        let mut file = open_file("tests/blocks/test_block.bin", OpenMode::Read).unwrap();
         let mut buffer = vec![];
         file.read_to_end(&mut buffer).unwrap();
         let block = Block::parse(buffer).unwrap();
@@ -240,7 +457,7 @@ mod tests {
 
     #[test]
     fn test_merkle_path() {
-        let mut file = open_new_file("tests/blocks/test_block.bin".to_string(), true).unwrap();
+        let mut file = open_file("tests/blocks/test_block.bin", OpenMode::Read).unwrap();
         let mut buffer = vec![];
         file.read_to_end(&mut buffer).unwrap();
         let block = Block::parse(buffer).unwrap();
@@ -264,6 +481,37 @@ mod tests {
         assert_eq!(merging, block.header.merkle_root);
     }
 
+    #[test]
+    fn block_with_trailing_bytes_is_invalid() {
+        let mut buffer = fs::read("tests/blocks/test_block.bin").unwrap();
+        assert!(Block::parse(buffer.clone()).is_ok());
+
+        buffer.push(0);
+        assert!(Block::parse(buffer).is_err());
+    }
+
+    #[test]
+    fn save_fails_if_block_file_exists() {
+        let path = "tests/test_block_save.bin".to_string();
+        let _ = remove_file(&path);
+        let block = Block::restore("tests/blocks/test_block.bin".to_string()).unwrap();
+
+        block.save(path.clone()).unwrap();
+        assert!(block.save(path.clone()).is_err());
+
+        let saved = Block::restore(path.clone()).unwrap();
+        assert_eq!(saved.serialize(), block.serialize());
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn restore_does_not_create_missing_block_file() {
+        let path = "tests/test_block_missing.bin";
+
+        assert!(Block::restore(path.to_string()).is_err());
+        assert!(!Path::new(path).exists());
+    }
+
     #[test]
     fn get_command_block_test() {
         let buffer = vec![
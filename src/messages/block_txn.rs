@@ -0,0 +1,194 @@
+use super::transaction::Transaction;
+
+use crate::{
+    error::CustomError,
+    message::Message,
+    parser::{BufferParser, VarIntSerialize},
+};
+
+/// Esta estructura representa al mensaje 'getblocktxn' de Bitcoin (BIP 152), el cual se utiliza para pedirle a un peer
+/// las transacciones de un compact block que no se pudieron reconstruir.
+/// Contiene los siguientes campos:
+/// - block_hash: Hash del bloque.
+/// - indexes: Indices de las transacciones pedidas, ordenados de menor a mayor.
+pub struct GetBlockTxn {
+    pub block_hash: Vec<u8>,
+    pub indexes: Vec<usize>,
+}
+
+impl GetBlockTxn {
+    /// Esta funcion se encarga de crear un nuevo mensaje 'getblocktxn'.
+    pub fn new(block_hash: Vec<u8>, indexes: Vec<usize>) -> Self {
+        Self {
+            block_hash,
+            indexes,
+        }
+    }
+}
+
+/// Implementa el trait Message para el mensaje 'getblocktxn'
+/// Permite serializar, parsear y obtener el comando
+/// Los indices se serializan de forma diferencial, cada uno como la distancia al indice anterior.
+impl Message for GetBlockTxn {
+    fn serialize(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = vec![];
+        buffer.extend(&self.block_hash);
+        buffer.extend(self.indexes.len().to_varint_bytes());
+        let mut next_index = 0;
+        for index in &self.indexes {
+            buffer.extend((index - next_index).to_varint_bytes());
+            next_index = index + 1;
+        }
+        buffer
+    }
+
+    fn get_command(&self) -> String {
+        String::from("getblocktxn")
+    }
+
+    fn parse(buffer: Vec<u8>) -> Result<Self, CustomError> {
+        let mut parser = BufferParser::new(buffer);
+        let block_hash = parser.extract_buffer(32)?.to_vec();
+        let indexes_count = parser.extract_varint()? as usize;
+        let mut indexes = vec![];
+        let mut next_index: usize = 0;
+        for _ in 0..indexes_count {
+            let index = next_index
+                .checked_add(parser.extract_varint()? as usize)
+                .ok_or(CustomError::SerializedBufferIsInvalid)?;
+            indexes.push(index);
+            next_index = index
+                .checked_add(1)
+                .ok_or(CustomError::SerializedBufferIsInvalid)?;
+        }
+        if !parser.is_empty() {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+
+        Ok(Self {
+            block_hash,
+            indexes,
+        })
+    }
+}
+
+/// Esta estructura representa al mensaje 'blocktxn' de Bitcoin (BIP 152), la respuesta a 'getblocktxn'.
+/// Contiene los siguientes campos:
+/// - block_hash: Hash del bloque.
+/// - transactions: Transacciones pedidas, en el mismo orden que los indices del 'getblocktxn'.
+pub struct BlockTxn {
+    pub block_hash: Vec<u8>,
+    pub transactions: Vec<Transaction>,
+}
+
+impl BlockTxn {
+    /// Esta funcion se encarga de crear un nuevo mensaje 'blocktxn'.
+    pub fn new(block_hash: Vec<u8>, transactions: Vec<Transaction>) -> Self {
+        Self {
+            block_hash,
+            transactions,
+        }
+    }
+}
+
+/// Implementa el trait Message para el mensaje 'blocktxn'
+/// Permite serializar, parsear y obtener el comando
+impl Message for BlockTxn {
+    fn serialize(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = vec![];
+        buffer.extend(&self.block_hash);
+        buffer.extend(self.transactions.len().to_varint_bytes());
+        for transaction in &self.transactions {
+            buffer.extend(transaction.serialize());
+        }
+        buffer
+    }
+
+    fn get_command(&self) -> String {
+        String::from("blocktxn")
+    }
+
+    fn parse(buffer: Vec<u8>) -> Result<Self, CustomError> {
+        let mut parser = BufferParser::new(buffer);
+        let block_hash = parser.extract_buffer(32)?.to_vec();
+        let tx_count = parser.extract_varint()? as usize;
+        let mut transactions = vec![];
+        for _ in 0..tx_count {
+            transactions.push(Transaction::parse_from_parser(&mut parser)?);
+        }
+        if !parser.is_empty() {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+
+        Ok(Self {
+            block_hash,
+            transactions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_get_block_txn() {
+        let get_block_txn = GetBlockTxn::new(vec![1; 32], vec![0, 1, 5]);
+        let serialized = get_block_txn.serialize();
+        assert_eq!(serialized[0..32].to_vec(), vec![1; 32]);
+        assert_eq!(serialized[32..].to_vec(), vec![3, 0, 0, 3]);
+    }
+
+    #[test]
+    fn parse_get_block_txn() {
+        let get_block_txn = GetBlockTxn::new(vec![1; 32], vec![2, 3, 10]);
+        let parsed = GetBlockTxn::parse(get_block_txn.serialize()).unwrap();
+        assert_eq!(parsed.block_hash, vec![1; 32]);
+        assert_eq!(parsed.indexes, vec![2, 3, 10]);
+    }
+
+    #[test]
+    fn parse_invalid_get_block_txn() {
+        let parsed = GetBlockTxn::parse(vec![1; 10]);
+        assert_eq!(parsed.is_err(), true);
+    }
+
+    #[test]
+    fn parse_get_block_txn_with_overflowing_index() {
+        let mut buffer = vec![1; 32];
+        buffer.push(2);
+        buffer.extend(usize::MAX.to_varint_bytes());
+        buffer.push(0);
+        assert!(GetBlockTxn::parse(buffer).is_err());
+    }
+
+    #[test]
+    fn parse_get_block_txn_with_trailing_bytes() {
+        let mut buffer = GetBlockTxn::new(vec![1; 32], vec![2, 3]).serialize();
+        buffer.push(0);
+        assert!(GetBlockTxn::parse(buffer).is_err());
+    }
+
+    #[test]
+    fn parse_block_txn() {
+        let tx = Transaction {
+            version: 1,
+            inputs: vec![],
+            outputs: vec![],
+            lock_time: 7,
+        };
+        let block_txn = BlockTxn::new(vec![2; 32], vec![tx.clone()]);
+        let parsed = BlockTxn::parse(block_txn.serialize()).unwrap();
+        assert_eq!(parsed.block_hash, vec![2; 32]);
+        assert_eq!(parsed.transactions.len(), 1);
+        assert_eq!(parsed.transactions[0].hash(), tx.hash());
+    }
+
+    #[test]
+    fn get_command_block_txn() {
+        let get_block_txn = GetBlockTxn::new(vec![1; 32], vec![]);
+        assert_eq!(get_block_txn.get_command(), "getblocktxn");
+        let block_txn = BlockTxn::new(vec![1; 32], vec![]);
+        assert_eq!(block_txn.get_command(), "blocktxn");
+    }
+}
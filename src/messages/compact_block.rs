@@ -0,0 +1,369 @@
+use std::collections::{HashMap, HashSet};
+
+use bitcoin_hashes::{sha256, siphash24, Hash};
+
+use super::{block::Block, transaction::Transaction};
+
+use crate::{
+    error::CustomError,
+    message::Message,
+    parser::{BufferParser, VarIntSerialize},
+    structs::block_header::BlockHeader,
+};
+
+const SHORT_ID_BYTES: usize = 6;
+
+#[derive(Debug, Clone)]
+/// Esta estructura representa una transaccion que el peer envia completa dentro del compact block (por ejemplo la coinbase).
+/// Contiene los siguientes campos:
+/// - index: Posicion de la transaccion dentro del bloque.
+/// - tx: Transaccion.
+pub struct PrefilledTransaction {
+    pub index: usize,
+    pub tx: Transaction,
+}
+
+#[derive(Debug)]
+/// Esta estructura representa al mensaje 'cmpctblock' de Bitcoin (BIP 152), el cual anuncia un bloque enviando su header
+/// y un short id de 6 bytes por cada transaccion, para que el nodo lo reconstruya con las transacciones pendientes que ya conoce.
+/// Contiene los siguientes campos:
+/// - header: Header del bloque.
+/// - nonce: Nonce utilizado para calcular las claves de los short ids.
+/// - short_ids: Short ids de las transacciones que no fueron enviadas completas, en el orden del bloque.
+/// - prefilled_txs: Transacciones enviadas completas, ordenadas por su indice en el bloque.
+pub struct CompactBlock {
+    pub header: BlockHeader,
+    pub nonce: u64,
+    pub short_ids: Vec<u64>,
+    pub prefilled_txs: Vec<PrefilledTransaction>,
+}
+
+impl CompactBlock {
+    /// Esta funcion se encarga de crear un compact block a partir de un bloque completo.
+    /// Las transacciones cuyos indices estan en prefilled_indexes se envian completas, el resto como short ids.
+    pub fn from_block(block: &Block, nonce: u64, prefilled_indexes: &[usize]) -> Self {
+        let mut compact_block = Self {
+            header: block.header.clone(),
+            nonce,
+            short_ids: vec![],
+            prefilled_txs: vec![],
+        };
+
+        let (k0, k1) = compact_block.short_id_keys();
+        for (index, tx) in block.transactions.iter().enumerate() {
+            if prefilled_indexes.contains(&index) {
+                compact_block.prefilled_txs.push(PrefilledTransaction {
+                    index,
+                    tx: tx.clone(),
+                });
+            } else {
+                compact_block.short_ids.push(short_id(k0, k1, &tx.hash()));
+            }
+        }
+        compact_block
+    }
+
+    /// Devuelve la cantidad total de transacciones del bloque.
+    pub fn tx_count(&self) -> usize {
+        self.short_ids.len() + self.prefilled_txs.len()
+    }
+
+    /// Esta funcion se encarga de calcular las claves de siphash para los short ids.
+    /// Las claves son los primeros 16 bytes del sha256 del header serializado seguido del nonce.
+    fn short_id_keys(&self) -> (u64, u64) {
+        let mut buffer = self.header.serialize();
+        buffer.extend(self.nonce.to_le_bytes());
+        let hash = sha256::Hash::hash(&buffer).to_byte_array();
+
+        let mut k0 = [0; 8];
+        let mut k1 = [0; 8];
+        k0.copy_from_slice(&hash[0..8]);
+        k1.copy_from_slice(&hash[8..16]);
+        (u64::from_le_bytes(k0), u64::from_le_bytes(k1))
+    }
+
+    /// Esta funcion se encarga de reconstruir el bloque con las transacciones prefilled y las transacciones pendientes recibidas.
+    /// Las transacciones que no se encuentran, o cuyo short id coincide con mas de una transaccion, quedan vacias en el PartialBlock
+    /// para pedirlas con 'getblocktxn'.
+    /// Devuelve CustomError si los indices de las transacciones prefilled son invalidos.
+    pub fn reconstruct<'a>(
+        &self,
+        pending_txs: impl Iterator<Item = (&'a Vec<u8>, &'a Transaction)>,
+    ) -> Result<PartialBlock, CustomError> {
+        let tx_count = self.tx_count();
+        let mut transactions: Vec<Option<Transaction>> = vec![None; tx_count];
+        for prefilled in &self.prefilled_txs {
+            if prefilled.index >= tx_count || transactions[prefilled.index].is_some() {
+                return Err(CustomError::SerializedBufferIsInvalid);
+            }
+            transactions[prefilled.index] = Some(prefilled.tx.clone());
+        }
+
+        let empty_indexes = transactions
+            .iter()
+            .enumerate()
+            .filter(|(_, tx)| tx.is_none())
+            .map(|(index, _)| index);
+
+        let mut indexes_by_short_id: HashMap<u64, usize> = HashMap::new();
+        let mut repeated_short_ids = HashSet::new();
+        for (short_id, index) in self.short_ids.iter().zip(empty_indexes) {
+            if indexes_by_short_id.insert(*short_id, index).is_some() {
+                repeated_short_ids.insert(*short_id);
+            }
+        }
+        for short_id in repeated_short_ids {
+            indexes_by_short_id.remove(&short_id);
+        }
+
+        let (k0, k1) = self.short_id_keys();
+        let mut collided_indexes = vec![];
+        for (tx_hash, tx) in pending_txs {
+            let Some(index) = indexes_by_short_id.get(&short_id(k0, k1, tx_hash)) else { continue };
+            if transactions[*index].is_some() {
+                collided_indexes.push(*index);
+            }
+            transactions[*index] = Some(tx.clone());
+        }
+        for index in collided_indexes {
+            transactions[index] = None;
+        }
+
+        Ok(PartialBlock {
+            header: self.header.clone(),
+            transactions,
+        })
+    }
+}
+
+/// Esta funcion se encarga de calcular el short id de una transaccion: los 6 bytes menos significativos del siphash del txid.
+fn short_id(k0: u64, k1: u64, tx_hash: &[u8]) -> u64 {
+    siphash24::Hash::hash_with_keys(k0, k1, tx_hash).as_u64() & 0x0000_ffff_ffff_ffff
+}
+
+/// Implementa el trait Message para el mensaje 'cmpctblock'
+/// Permite serializar, parsear y obtener el comando
+impl Message for CompactBlock {
+    fn serialize(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = vec![];
+        buffer.extend(self.header.serialize());
+        buffer.extend(self.nonce.to_le_bytes());
+
+        buffer.extend(self.short_ids.len().to_varint_bytes());
+        for short_id in &self.short_ids {
+            buffer.extend(&short_id.to_le_bytes()[0..SHORT_ID_BYTES]);
+        }
+
+        buffer.extend(self.prefilled_txs.len().to_varint_bytes());
+        let mut next_index = 0;
+        for prefilled in &self.prefilled_txs {
+            buffer.extend((prefilled.index - next_index).to_varint_bytes());
+            buffer.extend(prefilled.tx.serialize());
+            next_index = prefilled.index + 1;
+        }
+        buffer
+    }
+
+    fn get_command(&self) -> String {
+        String::from("cmpctblock")
+    }
+
+    fn parse(buffer: Vec<u8>) -> Result<Self, CustomError> {
+        let mut parser = BufferParser::new(buffer);
+        let header = BlockHeader::parse(parser.extract_buffer(80)?.to_vec())?;
+        let nonce = parser.extract_u64()?;
+
+        let short_ids_count = parser.extract_varint()? as usize;
+        let mut short_ids = vec![];
+        for _ in 0..short_ids_count {
+            let mut short_id = [0; 8];
+            short_id[0..SHORT_ID_BYTES].copy_from_slice(parser.extract_buffer(SHORT_ID_BYTES)?);
+            short_ids.push(u64::from_le_bytes(short_id));
+        }
+
+        let prefilled_count = parser.extract_varint()? as usize;
+        let mut prefilled_txs = vec![];
+        let mut next_index: usize = 0;
+        for _ in 0..prefilled_count {
+            let index = next_index
+                .checked_add(parser.extract_varint()? as usize)
+                .ok_or(CustomError::SerializedBufferIsInvalid)?;
+            let tx = Transaction::parse_from_parser(&mut parser)?;
+            prefilled_txs.push(PrefilledTransaction { index, tx });
+            next_index = index
+                .checked_add(1)
+                .ok_or(CustomError::SerializedBufferIsInvalid)?;
+        }
+        if !parser.is_empty() {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+
+        Ok(Self {
+            header,
+            nonce,
+            short_ids,
+            prefilled_txs,
+        })
+    }
+}
+
+#[derive(Debug)]
+/// Esta estructura representa un bloque reconstruido a partir de un compact block al que le pueden faltar transacciones.
+/// Contiene los siguientes campos:
+/// - header: Header del bloque.
+/// - transactions: Transacciones del bloque, None para las que todavia no se tienen.
+pub struct PartialBlock {
+    pub header: BlockHeader,
+    pub transactions: Vec<Option<Transaction>>,
+}
+
+impl PartialBlock {
+    /// Devuelve los indices de las transacciones que faltan en el bloque.
+    pub fn missing_indexes(&self) -> Vec<usize> {
+        self.transactions
+            .iter()
+            .enumerate()
+            .filter(|(_, tx)| tx.is_none())
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Esta funcion se encarga de completar el bloque con las transacciones faltantes, recibidas en el orden de missing_indexes.
+    /// Devuelve CustomError si la cantidad de transacciones recibidas no coincide con la cantidad de transacciones faltantes.
+    pub fn fill(self, missing_txs: Vec<Transaction>) -> Result<Block, CustomError> {
+        let mut missing_txs = missing_txs.into_iter();
+        let mut transactions = vec![];
+        for tx in self.transactions {
+            match tx.or_else(|| missing_txs.next()) {
+                Some(tx) => transactions.push(tx),
+                None => return Err(CustomError::CannotReconstructBlock),
+            }
+        }
+        if missing_txs.next().is_some() {
+            return Err(CustomError::CannotReconstructBlock);
+        }
+        Ok(Block::new(self.header, transactions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::utils::{open_file, OpenMode};
+
+    use super::*;
+
+    fn test_block() -> Block {
+        let mut file = open_file("tests/blocks/test_block.bin", OpenMode::Read).unwrap();
+        let mut buffer = vec![];
+        file.read_to_end(&mut buffer).unwrap();
+        Block::parse(buffer).unwrap()
+    }
+
+    fn pending_txs(transactions: &[Transaction]) -> HashMap<Vec<u8>, Transaction> {
+        transactions
+            .iter()
+            .map(|tx| (tx.hash(), tx.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn serialize_and_parse_compact_block() {
+        let block = test_block();
+        let compact_block = CompactBlock::from_block(&block, 42, &[0, 3]);
+        assert_eq!(compact_block.short_ids.len(), block.transactions.len() - 2);
+
+        let parsed = CompactBlock::parse(compact_block.serialize()).unwrap();
+        assert_eq!(parsed.header.hash(), block.header.hash());
+        assert_eq!(parsed.nonce, 42);
+        assert_eq!(parsed.short_ids, compact_block.short_ids);
+        assert_eq!(parsed.prefilled_txs.len(), 2);
+        assert_eq!(parsed.prefilled_txs[0].index, 0);
+        assert_eq!(parsed.prefilled_txs[1].index, 3);
+        assert_eq!(
+            parsed.prefilled_txs[1].tx.hash(),
+            block.transactions[3].hash()
+        );
+    }
+
+    #[test]
+    fn parse_compact_block_with_trailing_bytes() {
+        let block = test_block();
+        let mut buffer = CompactBlock::from_block(&block, 42, &[0]).serialize();
+        buffer.push(0);
+        assert!(CompactBlock::parse(buffer).is_err());
+    }
+
+    #[test]
+    fn short_ids_have_six_bytes() {
+        let block = test_block();
+        let compact_block = CompactBlock::from_block(&block, 7, &[0]);
+        assert!(compact_block
+            .short_ids
+            .iter()
+            .all(|short_id| *short_id <= 0x0000_ffff_ffff_ffff));
+    }
+
+    #[test]
+    fn reconstruct_block_with_all_pending_txs() {
+        let block = test_block();
+        let compact_block = CompactBlock::from_block(&block, 1, &[0]);
+        let pending = pending_txs(&block.transactions[1..]);
+
+        let partial_block = compact_block.reconstruct(pending.iter()).unwrap();
+        assert!(partial_block.missing_indexes().is_empty());
+
+        let reconstructed = partial_block.fill(vec![]).unwrap();
+        assert!(reconstructed.create_merkle_root().is_ok());
+    }
+
+    #[test]
+    fn reconstruct_block_with_missing_txs() {
+        let block = test_block();
+        let compact_block = CompactBlock::from_block(&block, 1, &[0]);
+        let mut mempool_txs = block.transactions[1..].to_vec();
+        mempool_txs.remove(4);
+        mempool_txs.remove(8);
+        let pending = pending_txs(&mempool_txs);
+
+        let partial_block = compact_block.reconstruct(pending.iter()).unwrap();
+        assert_eq!(partial_block.missing_indexes(), vec![5, 10]);
+
+        let missing = vec![
+            block.transactions[5].clone(),
+            block.transactions[10].clone(),
+        ];
+        let reconstructed = partial_block.fill(missing).unwrap();
+        assert!(reconstructed.create_merkle_root().is_ok());
+    }
+
+    #[test]
+    fn fill_block_with_wrong_amount_of_txs() {
+        let block = test_block();
+        let compact_block = CompactBlock::from_block(&block, 1, &[0]);
+
+        let partial_block = compact_block.reconstruct(HashMap::new().iter()).unwrap();
+        assert_eq!(
+            partial_block.missing_indexes().len(),
+            block.transactions.len() - 1
+        );
+        assert!(partial_block.fill(vec![]).is_err());
+    }
+
+    #[test]
+    fn reconstruct_block_with_invalid_prefilled_index() {
+        let block = test_block();
+        let mut compact_block = CompactBlock::from_block(&block, 1, &[0]);
+        compact_block.prefilled_txs[0].index = block.transactions.len();
+
+        assert!(compact_block.reconstruct(HashMap::new().iter()).is_err());
+    }
+
+    #[test]
+    fn get_command_compact_block() {
+        let block = test_block();
+        let compact_block = CompactBlock::from_block(&block, 1, &[0]);
+        assert_eq!(compact_block.get_command(), "cmpctblock");
+    }
+}
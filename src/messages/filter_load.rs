@@ -0,0 +1,151 @@
+use crate::{
+    error::CustomError,
+    message::Message,
+    parser::{BufferParser, VarIntSerialize},
+    structs::bloom_filter::BloomFilter,
+};
+
+/// Tamaño maximo de un elemento agregado con filteradd (el de un elemento de un script).
+pub const MAX_FILTER_ADD_SIZE: usize = 520;
+
+#[derive(Debug, Clone, PartialEq)]
+/// FilterLoad es el mensaje 'filterload' de BIP 37.
+/// Le carga al peer un filtro bloom: desde entonces solo nos anuncia las transacciones que coinciden con el filtro
+/// y responde los pedidos de bloques filtrados con mensajes merkleblock.
+pub struct FilterLoad {
+    pub filter: BloomFilter,
+}
+
+impl FilterLoad {
+    /// Crea un nuevo mensaje 'filterload' con el filtro recibido.
+    pub fn new(filter: BloomFilter) -> Self {
+        Self { filter }
+    }
+}
+
+/// Implementa el trait Message para el mensaje 'filterload'.
+/// Permite serializar, parsear y obtener el comando
+impl Message for FilterLoad {
+    fn get_command(&self) -> String {
+        String::from("filterload")
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        self.filter.serialize()
+    }
+
+    fn parse(buffer: Vec<u8>) -> Result<Self, CustomError> {
+        let mut parser = BufferParser::new(buffer);
+        let filter = BloomFilter::parse(&mut parser)?;
+        if !parser.is_empty() {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+        Ok(Self { filter })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// FilterAdd es el mensaje 'filteradd' de BIP 37, agrega un elemento al filtro cargado en el peer.
+pub struct FilterAdd {
+    pub data: Vec<u8>,
+}
+
+impl FilterAdd {
+    /// Crea un nuevo mensaje 'filteradd' con el elemento recibido.
+    pub fn new(data: Vec<u8>) -> Self {
+        Self { data }
+    }
+}
+
+/// Implementa el trait Message para el mensaje 'filteradd'.
+/// Permite serializar, parsear y obtener el comando
+impl Message for FilterAdd {
+    fn get_command(&self) -> String {
+        String::from("filteradd")
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut buffer = self.data.len().to_varint_bytes();
+        buffer.extend(&self.data);
+        buffer
+    }
+
+    fn parse(buffer: Vec<u8>) -> Result<Self, CustomError> {
+        let mut parser = BufferParser::new(buffer);
+        let size = parser.extract_varint()? as usize;
+        if size > MAX_FILTER_ADD_SIZE || parser.len() != size {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+        let data = parser.extract_buffer(size)?.to_vec();
+        Ok(Self { data })
+    }
+}
+
+#[derive(Debug, Default)]
+/// FilterClear es el mensaje 'filterclear' de BIP 37, no tiene payload.
+/// Le saca el filtro al peer, que vuelve a anunciarnos todas las transacciones.
+pub struct FilterClear {}
+
+impl FilterClear {
+    /// Crea un nuevo mensaje 'filterclear'.
+    pub fn new() -> Self {
+        FilterClear {}
+    }
+}
+
+/// Implementa el trait Message para el mensaje 'filterclear'.
+/// Permite serializar, parsear y obtener el comando
+impl Message for FilterClear {
+    fn get_command(&self) -> String {
+        String::from("filterclear")
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    fn parse(buffer: Vec<u8>) -> Result<Self, CustomError> {
+        if !buffer.is_empty() {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+        Ok(FilterClear {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::structs::bloom_filter::BLOOM_UPDATE_ALL;
+
+    use super::*;
+
+    #[test]
+    fn filter_load_round_trip() {
+        let mut filter = BloomFilter::new(2, 0.001, 5, BLOOM_UPDATE_ALL);
+        filter.insert(&[1; 20]);
+        let filter_load = FilterLoad::new(filter);
+
+        let mut buffer = filter_load.serialize();
+        assert_eq!(FilterLoad::parse(buffer.clone()).unwrap(), filter_load);
+        buffer.push(0);
+        assert!(FilterLoad::parse(buffer).is_err());
+    }
+
+    #[test]
+    fn filter_add_round_trip_and_size_limit() {
+        let filter_add = FilterAdd::new(vec![7; 36]);
+        assert_eq!(
+            FilterAdd::parse(filter_add.serialize()).unwrap(),
+            filter_add
+        );
+
+        let oversized = FilterAdd::new(vec![7; MAX_FILTER_ADD_SIZE + 1]);
+        assert!(FilterAdd::parse(oversized.serialize()).is_err());
+    }
+
+    #[test]
+    fn filter_clear_has_no_payload() {
+        assert!(FilterClear::new().serialize().is_empty());
+        assert!(FilterClear::parse(vec![]).is_ok());
+        assert!(FilterClear::parse(vec![0]).is_err());
+    }
+}
@@ -0,0 +1,61 @@
+use crate::{error::CustomError, message::Message};
+
+#[derive(Debug)]
+/// GetAddr es el mensaje 'getaddr', no tiene payload.
+/// Se envia tras el handshake para pedirle al peer direcciones de otros nodos, que responde con mensajes addr.
+pub struct GetAddr {}
+
+impl GetAddr {
+    /// Crea un nuevo mensaje 'getaddr'.
+    pub fn new() -> Self {
+        GetAddr {}
+    }
+}
+
+impl Default for GetAddr {
+    fn default() -> Self {
+        GetAddr::new()
+    }
+}
+
+/// Implementa el trait Message para el mensaje 'getaddr'.
+/// Permite serializar, parsear y obtener el comando
+impl Message for GetAddr {
+    fn get_command(&self) -> String {
+        String::from("getaddr")
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    fn parse(buffer: Vec<u8>) -> Result<Self, CustomError> {
+        if !buffer.is_empty() {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+        Ok(GetAddr {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_getaddr() {
+        let getaddr = GetAddr::new();
+        assert!(getaddr.serialize().is_empty());
+    }
+
+    #[test]
+    fn parse_getaddr() {
+        assert!(GetAddr::parse(vec![]).is_ok());
+        assert!(GetAddr::parse(vec![0x00]).is_err());
+    }
+
+    #[test]
+    fn get_command_getaddr() {
+        let getaddr = GetAddr::new();
+        assert_eq!(getaddr.get_command(), String::from("getaddr"));
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{
+    error::CustomError,
+    message::Message,
+    parser::{BufferParser, VarIntSerialize},
+};
+
+#[derive(PartialEq, Debug)]
+
+/// Esta estructura representa el mensaje 'getblocks' de Bitcoin, el cual se utiliza para pedirle a un nodo que nos envie un inv
+/// con los hashes de los bloques que tiene a partir del block locator.
+pub struct GetBlocks {
+    pub version: i32,
+    pub block_locator_hashes: Vec<Vec<u8>>,
+    pub hash_stop: Vec<u8>,
+}
+
+impl GetBlocks {
+    /// Esta funcion se encarga de crear un nuevo mensaje 'getblocks' con la version, el block locator hashes y el hash stop que se reciben por parametro
+    pub fn new(version: i32, block_locator_hashes: Vec<Vec<u8>>, hash_stop: Vec<u8>) -> Self {
+        GetBlocks {
+            version,
+            block_locator_hashes,
+            hash_stop,
+        }
+    }
+}
+
+/// Implementa el trait Message para el mensaje 'getblocks'.
+/// Permite serializar, parsear y obtener el comando
+impl Message for GetBlocks {
+    fn get_command(&self) -> String {
+        String::from("getblocks")
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = vec![];
+        buffer.extend(&self.version.to_le_bytes());
+        buffer.extend(self.block_locator_hashes.len().to_varint_bytes());
+        for hash in &self.block_locator_hashes {
+            buffer.extend(hash);
+        }
+        buffer.extend(&self.hash_stop);
+        buffer
+    }
+
+    fn parse(buffer: Vec<u8>) -> Result<Self, CustomError> {
+        let mut parser = BufferParser::new(buffer);
+
+        if parser.len() < 37 {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+        let version = parser.extract_i32()?;
+        let hash_count = parser.extract_varint()?;
+
+        let mut block_locator_hashes: Vec<Vec<u8>> = vec![];
+
+        while parser.len() > 32 {
+            let hash = parser.extract_buffer(32)?.to_vec();
+            block_locator_hashes.push(hash);
+        }
+
+        let hash_stop = parser.extract_buffer(32)?.to_vec();
+
+        if !parser.is_empty() || block_locator_hashes.len() != hash_count as usize {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+
+        Ok(GetBlocks {
+            version,
+            block_locator_hashes,
+            hash_stop,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::peer::GENESIS;
+
+    use super::*;
+
+    #[test]
+    fn get_blocks_serialize() {
+        let get_blocks = GetBlocks::new(70015, vec![GENESIS.to_vec()], vec![0; 32]);
+        let serialized_getblocks = get_blocks.serialize();
+        let parsed_getblocks = GetBlocks::parse(serialized_getblocks).unwrap();
+        assert_eq!(get_blocks, parsed_getblocks);
+    }
+
+    #[test]
+    fn no_hashes_get_blocks_parses_correctly() {
+        let mut serialized_getblocks = vec![127, 17, 1, 0, 0];
+        serialized_getblocks.extend([0; 32]);
+
+        let parsed_getblocks = GetBlocks::parse(serialized_getblocks).unwrap();
+        assert_eq!(parsed_getblocks.version, 70015);
+        assert_eq!(parsed_getblocks.block_locator_hashes.len(), 0);
+        assert_eq!(parsed_getblocks.hash_stop, vec![0; 32]);
+    }
+
+    #[test]
+    fn parse_invalid_getblocks_with_wrong_hash_count() {
+        let mut invalid_getblocks = vec![127, 17, 1, 0, 2];
+        invalid_getblocks.extend([0; 64]);
+        let parsed_getblocks = GetBlocks::parse(invalid_getblocks);
+        assert!(parsed_getblocks.is_err());
+    }
+
+    #[test]
+    fn get_command_getblocks() {
+        let getblocks = GetBlocks::new(70015, vec![], vec![]);
+        assert_eq!(getblocks.get_command(), "getblocks");
+    }
+}
@@ -5,6 +5,9 @@ use crate::{
     structs::block_header::BlockHeader,
 };
 
+/// Cantidad de bytes que ocupa cada header en el mensaje 'headers': 80 del header mas el tx count (siempre 0).
+pub const HEADER_ENTRY_BYTES: usize = 81;
+
 #[derive(Debug, Clone)]
 
 ///Esta estructura es la que se encarga de almacenar los headers de los bloques, esto lo hace en un vector de 'BlockHeaders'
@@ -12,6 +15,17 @@ pub struct Headers {
     pub headers: Vec<BlockHeader>,
 }
 
+#[derive(Debug, Clone)]
+/// RawHeaders es el mensaje 'headers' armado a partir de los headers ya serializados en formato de red.
+/// Permite responder un getheaders copiando bytes, sin clonar ni volver a serializar cada BlockHeader.
+/// Los elementos son:
+/// - count: Cantidad de headers del mensaje.
+/// - entries: Los headers serializados, HEADER_ENTRY_BYTES bytes cada uno.
+pub struct RawHeaders {
+    count: usize,
+    entries: Vec<u8>,
+}
+
 impl Headers {
     /// Esta funcion se encarga de crear un nuevo Headers con un vector vacio de 'BlockHeaders'
     pub fn new() -> Self {
@@ -19,6 +33,27 @@ impl Headers {
     }
 }
 
+impl RawHeaders {
+    /// Crea el mensaje a partir de los headers ya serializados con serialize_header_entry.
+    /// Devuelve CustomError si el largo no es multiplo de HEADER_ENTRY_BYTES.
+    pub fn new(entries: Vec<u8>) -> Result<Self, CustomError> {
+        if entries.len() % HEADER_ENTRY_BYTES != 0 {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+        Ok(RawHeaders {
+            count: entries.len() / HEADER_ENTRY_BYTES,
+            entries,
+        })
+    }
+}
+
+/// Serializa un header tal como se envia en el mensaje 'headers'.
+pub fn serialize_header_entry(header: &BlockHeader) -> Vec<u8> {
+    let mut buffer = header.serialize();
+    buffer.extend(0_u8.to_le_bytes());
+    buffer
+}
+
 impl Default for Headers {
     fn default() -> Self {
         Headers::new()
@@ -36,8 +71,7 @@ impl Message for Headers {
         let mut buffer: Vec<u8> = vec![];
         buffer.extend(self.headers.len().to_varint_bytes());
         for header in &self.headers {
-            buffer.extend(&header.serialize());
-            buffer.extend(0_u8.to_le_bytes());
+            buffer.extend(serialize_header_entry(header));
         }
         buffer
     }
@@ -46,12 +80,12 @@ impl Message for Headers {
         let mut parser = BufferParser::new(buffer);
 
         let header_count = parser.extract_varint()?;
-        if parser.len() % 81 != 0 {
+        if parser.len() % HEADER_ENTRY_BYTES != 0 {
             return Err(CustomError::SerializedBufferIsInvalid);
         }
 
         let mut headers = vec![];
-        while parser.len() >= 81 {
+        while parser.len() >= HEADER_ENTRY_BYTES {
             headers.push(BlockHeader::parse(parser.extract_buffer(80)?.to_vec())?);
             parser.extract_buffer(1)?;
         }
@@ -64,6 +98,28 @@ impl Message for Headers {
     }
 }
 
+/// Implementa el trait Message para RawHeaders, usa el mismo comando que Headers.
+impl Message for RawHeaders {
+    fn get_command(&self) -> String {
+        String::from("headers")
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = self.count.to_varint_bytes();
+        buffer.extend(&self.entries);
+        buffer
+    }
+
+    fn parse(buffer: Vec<u8>) -> Result<Self, CustomError> {
+        let headers = Headers::parse(buffer)?;
+        let mut entries = vec![];
+        for header in &headers.headers {
+            entries.extend(serialize_header_entry(header));
+        }
+        RawHeaders::new(entries)
+    }
+}
+
 #[cfg(test)]
 
 mod tests {
@@ -100,4 +156,25 @@ mod tests {
 
         assert!(headers.is_err());
     }
+
+    #[test]
+    fn raw_headers_serialize_like_headers() {
+        let buffer = vec![
+            1, 0, 0, 128, 32, 169, 255, 173, 21, 40, 44, 123, 115, 129, 193, 143, 57, 71, 116, 199,
+            75, 244, 113, 169, 45, 227, 42, 180, 111, 0, 0, 0, 0, 0, 0, 0, 0, 109, 105, 250, 106,
+            92, 126, 17, 171, 97, 243, 124, 194, 172, 252, 249, 166, 202, 8, 231, 136, 21, 107,
+            106, 136, 64, 241, 195, 82, 179, 236, 159, 63, 155, 22, 96, 100, 105, 90, 32, 25, 11,
+            42, 241, 166, 0,
+        ];
+
+        let raw_headers = RawHeaders::new(buffer[1..].to_vec()).unwrap();
+        assert_eq!(raw_headers.serialize(), buffer);
+        assert_eq!(
+            RawHeaders::parse(buffer.clone()).unwrap().serialize(),
+            buffer
+        );
+        assert_eq!(raw_headers.get_command(), String::from("headers"));
+
+        assert!(RawHeaders::new(buffer[2..].to_vec()).is_err());
+    }
 }
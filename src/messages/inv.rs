@@ -3,6 +3,9 @@ use crate::{
     structs::inventory::Inventory,
 };
 
+/// Cantidad maxima de inventories que puede tener un mensaje inv.
+pub const MAX_INV_ENTRIES: usize = 50000;
+
 #[derive(Debug, Clone, PartialEq)]
 /// Esta es la estructura de un mensaje inv, la cual contiene un vector de inventories
 pub struct Inv {
@@ -35,9 +38,9 @@ impl Message for Inv {
     fn parse(buffer: Vec<u8>) -> Result<Self, crate::error::CustomError> {
         let mut parser = BufferParser::new(buffer);
 
-        let count = parser.extract_varint()? as usize;
+        let count = parser.extract_varint()?;
 
-        if parser.len() % 36 != 0 {
+        if parser.len() % 36 != 0 || (parser.len() / 36) as u64 != count {
             return Err(CustomError::SerializedBufferIsInvalid);
         }
 
@@ -90,6 +93,17 @@ mod tests {
         assert!(parsed_inv.is_err());
     }
 
+    #[test]
+    fn inv_with_wrong_count_is_invalid() {
+        let inventory = Inventory::new(InventoryType::Block, vec![1; 32]);
+        let mut buffer = Inv::new(vec![inventory.clone(), inventory]).serialize();
+        buffer[0] = 1;
+        assert!(Inv::parse(buffer.clone()).is_err());
+
+        buffer[0] = 3;
+        assert!(Inv::parse(buffer).is_err());
+    }
+
     #[test]
     fn get_command_inv() {
         let inv = Inv::new(vec![]);
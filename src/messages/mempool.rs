@@ -0,0 +1,62 @@
+use crate::{error::CustomError, message::Message};
+
+#[derive(Debug)]
+/// Mempool es el mensaje 'mempool', no tiene payload.
+/// Lo envia un peer para pedir los hashes de las transacciones pendientes que conoce el nodo,
+/// que se le responden con mensajes inv.
+pub struct Mempool {}
+
+impl Mempool {
+    /// Crea un nuevo mensaje 'mempool'.
+    pub fn new() -> Self {
+        Mempool {}
+    }
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Mempool::new()
+    }
+}
+
+/// Implementa el trait Message para el mensaje 'mempool'.
+/// Permite serializar, parsear y obtener el comando
+impl Message for Mempool {
+    fn get_command(&self) -> String {
+        String::from("mempool")
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    fn parse(buffer: Vec<u8>) -> Result<Self, CustomError> {
+        if !buffer.is_empty() {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+        Ok(Mempool {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_mempool() {
+        let mempool = Mempool::new();
+        assert!(mempool.serialize().is_empty());
+    }
+
+    #[test]
+    fn parse_mempool() {
+        assert!(Mempool::parse(vec![]).is_ok());
+        assert!(Mempool::parse(vec![0x00]).is_err());
+    }
+
+    #[test]
+    fn get_command_mempool() {
+        let mempool = Mempool::new();
+        assert_eq!(mempool.get_command(), String::from("mempool"));
+    }
+}
@@ -0,0 +1,478 @@
+use std::collections::HashMap;
+
+use crate::{
+    error::CustomError,
+    message::Message,
+    messages::{
+        block::{merge_hashes, Block},
+        transaction::Transaction,
+    },
+    parser::{BufferParser, VarIntSerialize},
+    structs::block_header::BlockHeader,
+};
+
+/// Cantidad maxima de transacciones de un bloque (peso maximo del bloque dividido el peso minimo de una transaccion).
+const MAX_BLOCK_TRANSACTIONS: u32 = 4_000_000 / 240;
+
+#[derive(Debug, Clone)]
+/// MerkleBlock es el mensaje 'merkleblock' de BIP 37, la respuesta a un pedido de bloque filtrado.
+/// Contiene el header del bloque y un arbol de merkle parcial con las transacciones que coinciden con nuestro filtro bloom.
+/// Las transacciones que coinciden se envian a continuacion en mensajes tx.
+/// Los elementos son:
+/// - header: Header del bloque.
+/// - total_transactions: Cantidad de transacciones del bloque.
+/// - hashes: Hashes del arbol parcial, en el orden en que se recorre el arbol en profundidad.
+/// - flags: Bits que indican, en el mismo recorrido, si cada nodo es ancestro de una transaccion que coincide.
+pub struct MerkleBlock {
+    pub header: BlockHeader,
+    pub total_transactions: u32,
+    pub hashes: Vec<Vec<u8>>,
+    pub flags: Vec<u8>,
+}
+
+/// Recorrido del arbol parcial al verificarlo, con los hashes y bits usados hasta el momento.
+struct PartialTreeTraversal<'a> {
+    merkle_block: &'a MerkleBlock,
+    bits_used: usize,
+    hashes_used: usize,
+    matches: Vec<Vec<u8>>,
+}
+
+impl MerkleBlock {
+    /// Crea un merkleblock de un bloque, con el arbol parcial de las transacciones que indica matches (una por transaccion).
+    pub fn new(block: &Block, matches: &[bool]) -> Self {
+        let tx_hashes: Vec<Vec<u8>> = block.transactions.iter().map(Transaction::hash).collect();
+        let total_transactions = tx_hashes.len() as u32;
+
+        let mut bits = vec![];
+        let mut hashes = vec![];
+        let height = tree_height(total_transactions);
+        build_partial_tree(&tx_hashes, matches, height, 0, &mut bits, &mut hashes);
+
+        let mut flags = vec![0; (bits.len() + 7) / 8];
+        for (index, bit) in bits.iter().enumerate() {
+            flags[index / 8] |= (*bit as u8) << (index % 8);
+        }
+
+        Self {
+            header: block.header.clone(),
+            total_transactions,
+            hashes,
+            flags,
+        }
+    }
+
+    /// Verifica el arbol parcial contra el merkle root del header y devuelve los hashes de las transacciones que coinciden,
+    /// en el orden en que aparecen en el bloque.
+    /// Devuelve CustomError::InvalidMerkleRoot si el arbol esta mal formado (sobran o faltan hashes o bits,
+    /// tiene ramas duplicadas) o si su raiz no coincide con el merkle root del header.
+    pub fn extract_matches(&self) -> Result<Vec<Vec<u8>>, CustomError> {
+        let total = self.total_transactions;
+        if total == 0
+            || total > MAX_BLOCK_TRANSACTIONS
+            || self.hashes.len() > total as usize
+            || self.flags.len() * 8 < self.hashes.len()
+        {
+            return Err(CustomError::InvalidMerkleRoot);
+        }
+
+        let mut traversal = PartialTreeTraversal {
+            merkle_block: self,
+            bits_used: 0,
+            hashes_used: 0,
+            matches: vec![],
+        };
+        let root = traversal.traverse(tree_height(total), 0)?;
+
+        if (traversal.bits_used + 7) / 8 != self.flags.len()
+            || traversal.hashes_used != self.hashes.len()
+            || root != self.header.merkle_root
+        {
+            return Err(CustomError::InvalidMerkleRoot);
+        }
+        Ok(traversal.matches)
+    }
+
+    fn bit(&self, index: usize) -> Option<bool> {
+        let byte = self.flags.get(index / 8)?;
+        Some(byte >> (index % 8) & 1 == 1)
+    }
+}
+
+impl PartialTreeTraversal<'_> {
+    fn traverse(&mut self, height: u32, position: u32) -> Result<Vec<u8>, CustomError> {
+        let Some(parent_of_match) = self.merkle_block.bit(self.bits_used) else { return Err(CustomError::InvalidMerkleRoot) };
+        self.bits_used += 1;
+
+        if height == 0 || !parent_of_match {
+            let Some(hash) = self.merkle_block.hashes.get(self.hashes_used) else { return Err(CustomError::InvalidMerkleRoot) };
+            self.hashes_used += 1;
+            if height == 0 && parent_of_match {
+                self.matches.push(hash.clone());
+            }
+            return Ok(hash.clone());
+        }
+
+        let total = self.merkle_block.total_transactions;
+        let left = self.traverse(height - 1, position * 2)?;
+        let right = match position * 2 + 1 < tree_width(total, height - 1) {
+            true => {
+                let right = self.traverse(height - 1, position * 2 + 1)?;
+                // dos ramas iguales permitirian agregar transacciones duplicadas al arbol (CVE-2012-2459)
+                if right == left {
+                    return Err(CustomError::InvalidMerkleRoot);
+                }
+                right
+            }
+            false => left.clone(),
+        };
+        Ok(merge_hashes(left, right))
+    }
+}
+
+/// Devuelve la cantidad de nodos del nivel height del arbol de merkle de total transacciones (0 son las hojas).
+fn tree_width(total: u32, height: u32) -> u32 {
+    ((total as u64 + (1 << height) - 1) >> height) as u32
+}
+
+/// Devuelve la altura del arbol de merkle de total transacciones.
+fn tree_height(total: u32) -> u32 {
+    let mut height = 0;
+    while tree_width(total, height) > 1 {
+        height += 1;
+    }
+    height
+}
+
+/// Calcula el hash del nodo position del nivel height del arbol completo.
+fn tree_hash(tx_hashes: &[Vec<u8>], height: u32, position: u32) -> Vec<u8> {
+    if height == 0 {
+        return tx_hashes[position as usize].clone();
+    }
+    let left = tree_hash(tx_hashes, height - 1, position * 2);
+    let right = match position * 2 + 1 < tree_width(tx_hashes.len() as u32, height - 1) {
+        true => tree_hash(tx_hashes, height - 1, position * 2 + 1),
+        false => left.clone(),
+    };
+    merge_hashes(left, right)
+}
+
+/// Agrega los bits y hashes del subarbol del nodo position del nivel height al arbol parcial.
+/// Solo se desciende por los nodos que son ancestros de una transaccion que coincide.
+fn build_partial_tree(
+    tx_hashes: &[Vec<u8>],
+    matches: &[bool],
+    height: u32,
+    position: u32,
+    bits: &mut Vec<bool>,
+    hashes: &mut Vec<Vec<u8>>,
+) {
+    let start = (position as usize) << height;
+    let end = ((position as usize + 1) << height).min(tx_hashes.len());
+    let parent_of_match = (start..end).any(|index| matches.get(index) == Some(&true));
+    bits.push(parent_of_match);
+
+    if height == 0 || !parent_of_match {
+        hashes.push(tree_hash(tx_hashes, height, position));
+        return;
+    }
+    build_partial_tree(tx_hashes, matches, height - 1, position * 2, bits, hashes);
+    if position * 2 + 1 < tree_width(tx_hashes.len() as u32, height - 1) {
+        build_partial_tree(
+            tx_hashes,
+            matches,
+            height - 1,
+            position * 2 + 1,
+            bits,
+            hashes,
+        );
+    }
+}
+
+/// Implementa el trait Message para el mensaje merkleblock.
+/// Permite serializar, parsear y obtener el comando
+impl Message for MerkleBlock {
+    fn get_command(&self) -> String {
+        String::from("merkleblock")
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut buffer = self.header.serialize();
+        buffer.extend(self.total_transactions.to_le_bytes());
+        buffer.extend(self.hashes.len().to_varint_bytes());
+        for hash in &self.hashes {
+            buffer.extend(hash);
+        }
+        buffer.extend(self.flags.len().to_varint_bytes());
+        buffer.extend(&self.flags);
+        buffer
+    }
+
+    fn parse(buffer: Vec<u8>) -> Result<Self, CustomError> {
+        let mut parser = BufferParser::new(buffer);
+        let header = BlockHeader::parse(parser.extract_buffer(80)?.to_vec())?;
+        let total_transactions = parser.extract_u32()?;
+
+        let hash_count = parser.extract_varint()? as usize;
+        match hash_count.checked_mul(32) {
+            Some(size) if size <= parser.len() => {}
+            _ => return Err(CustomError::SerializedBufferIsInvalid),
+        }
+        let mut hashes = vec![];
+        for _ in 0..hash_count {
+            hashes.push(parser.extract_buffer(32)?.to_vec());
+        }
+
+        let flags_count = parser.extract_varint()? as usize;
+        if flags_count != parser.len() {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+        let flags = parser.extract_buffer(flags_count)?.to_vec();
+
+        Ok(Self {
+            header,
+            total_transactions,
+            hashes,
+            flags,
+        })
+    }
+}
+
+/// FilteredBlock junta un merkleblock verificado con las transacciones que coinciden, que el peer envia a continuacion.
+/// Cuando llegaron todas se convierte en un Block con solo esas transacciones, que se procesa como cualquier bloque.
+/// Los elementos son:
+/// - header: Header del bloque.
+/// - matches: Hashes de las transacciones que coinciden, en el orden del bloque.
+/// - received: Transacciones que coinciden ya recibidas, por hash.
+pub struct FilteredBlock {
+    header: BlockHeader,
+    matches: Vec<Vec<u8>>,
+    received: HashMap<Vec<u8>, Transaction>,
+}
+
+impl FilteredBlock {
+    /// Verifica el merkleblock (ver MerkleBlock::extract_matches) y crea el bloque filtrado sin transacciones recibidas.
+    pub fn new(merkle_block: MerkleBlock) -> Result<Self, CustomError> {
+        let matches = merkle_block.extract_matches()?;
+        Ok(Self {
+            header: merkle_block.header,
+            matches,
+            received: HashMap::new(),
+        })
+    }
+
+    /// Devuelve el hash del bloque.
+    pub fn hash(&self) -> &Vec<u8> {
+        self.header.hash()
+    }
+
+    /// Agrega una transaccion recibida. Devuelve false si no es una de las que coinciden, y entonces no se agrega.
+    pub fn add_transaction(&mut self, transaction: Transaction) -> bool {
+        let tx_hash = transaction.hash();
+        if !self.matches.contains(&tx_hash) {
+            return false;
+        }
+        self.received.insert(tx_hash, transaction);
+        true
+    }
+
+    /// Devuelve true si ya se recibieron todas las transacciones que coinciden.
+    pub fn is_complete(&self) -> bool {
+        self.matches
+            .iter()
+            .all(|tx_hash| self.received.contains_key(tx_hash))
+    }
+
+    /// Devuelve la cantidad de transacciones que coinciden que todavia no se recibieron.
+    pub fn missing(&self) -> usize {
+        self.matches
+            .iter()
+            .filter(|tx_hash| !self.received.contains_key(*tx_hash))
+            .count()
+    }
+
+    /// Convierte el bloque filtrado en un Block con las transacciones que coinciden, en el orden del bloque.
+    /// Devuelve None si falta recibir alguna.
+    pub fn into_block(mut self) -> Option<Block> {
+        let mut transactions = vec![];
+        for tx_hash in &self.matches {
+            transactions.push(self.received.remove(tx_hash)?);
+        }
+        Some(Block::new(self.header, transactions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Read};
+
+    use super::*;
+    use crate::structs::{
+        outpoint::OutPoint, tx_input::TransactionInput, tx_output::TransactionOutput,
+    };
+
+    fn test_block() -> Block {
+        let mut buffer = vec![];
+        File::open("tests/blocks/test_block.bin")
+            .unwrap()
+            .read_to_end(&mut buffer)
+            .unwrap();
+        Block::parse(buffer).unwrap()
+    }
+
+    fn matches_at(block: &Block, indexes: &[usize]) -> Vec<bool> {
+        (0..block.transactions.len())
+            .map(|index| indexes.contains(&index))
+            .collect()
+    }
+
+    #[test]
+    fn merkle_block_extracts_matches_of_a_real_block() {
+        let block = test_block();
+        assert!(block.transactions.len() > 2);
+        let last = block.transactions.len() - 1;
+
+        for indexes in [vec![], vec![0], vec![1], vec![last], vec![0, 1, last]] {
+            let merkle_block = MerkleBlock::new(&block, &matches_at(&block, &indexes));
+            let expected: Vec<Vec<u8>> = indexes
+                .iter()
+                .map(|index| block.transactions[*index].hash())
+                .collect();
+            assert_eq!(merkle_block.extract_matches().unwrap(), expected);
+        }
+
+        let all: Vec<usize> = (0..block.transactions.len()).collect();
+        let merkle_block = MerkleBlock::new(&block, &matches_at(&block, &all));
+        assert_eq!(merkle_block.hashes.len(), block.transactions.len());
+    }
+
+    #[test]
+    fn merkle_block_round_trip() {
+        let block = test_block();
+        let merkle_block = MerkleBlock::new(&block, &matches_at(&block, &[1]));
+        let parsed = MerkleBlock::parse(merkle_block.serialize()).unwrap();
+        assert_eq!(parsed.serialize(), merkle_block.serialize());
+        assert_eq!(parsed.header.hash(), block.header.hash());
+
+        let mut buffer = merkle_block.serialize();
+        buffer.push(0);
+        assert!(MerkleBlock::parse(buffer).is_err());
+    }
+
+    #[test]
+    fn merkle_block_without_matches_only_has_the_root() {
+        let block = test_block();
+        let merkle_block = MerkleBlock::new(&block, &matches_at(&block, &[]));
+        assert_eq!(merkle_block.hashes, vec![block.header.merkle_root.clone()]);
+        assert_eq!(merkle_block.flags, vec![0]);
+
+        let parsed = MerkleBlock::parse(merkle_block.serialize()).unwrap();
+        assert_eq!(parsed.total_transactions as usize, block.transactions.len());
+        assert!(parsed.extract_matches().unwrap().is_empty());
+    }
+
+    #[test]
+    fn merkle_block_rejects_tampered_trees() {
+        let block = test_block();
+        let merkle_block = MerkleBlock::new(&block, &matches_at(&block, &[1]));
+
+        let mut wrong_hash = merkle_block.clone();
+        wrong_hash.hashes[0][0] ^= 1;
+        assert!(wrong_hash.extract_matches().is_err());
+
+        let mut extra_hash = merkle_block.clone();
+        extra_hash.hashes.push(vec![0; 32]);
+        assert!(extra_hash.extract_matches().is_err());
+
+        let mut missing_hash = merkle_block.clone();
+        missing_hash.hashes.pop();
+        assert!(missing_hash.extract_matches().is_err());
+
+        let mut extra_flags = merkle_block.clone();
+        extra_flags.flags.push(0);
+        assert!(extra_flags.extract_matches().is_err());
+
+        // un total con otra altura del arbol; uno con la misma altura puede dar la misma raiz,
+        // porque las ramas sin coincidencias se envian como un solo hash
+        let mut wrong_total = merkle_block.clone();
+        wrong_total.total_transactions *= 2;
+        assert!(wrong_total.extract_matches().is_err());
+
+        let mut empty = merkle_block;
+        empty.total_transactions = 0;
+        assert!(empty.extract_matches().is_err());
+    }
+
+    fn test_transaction(value: u64) -> Transaction {
+        Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output: OutPoint {
+                    hash: vec![value as u8; 32],
+                    index: 0,
+                },
+                script_sig: vec![],
+                sequence: 0xffffffff,
+            }],
+            outputs: vec![TransactionOutput {
+                value,
+                script_pubkey: vec![],
+            }],
+            lock_time: 0,
+        }
+    }
+
+    #[test]
+    fn merkle_block_rejects_duplicated_branches() {
+        // con tres transacciones la ultima se duplica en el arbol, un arbol con una cuarta igual a la tercera
+        // tiene el mismo merkle root y no debe aceptarse
+        let transactions = vec![
+            test_transaction(1),
+            test_transaction(2),
+            test_transaction(3),
+        ];
+        let mut block = test_block();
+        block.transactions = transactions.clone();
+        let tx_hashes: Vec<Vec<u8>> = transactions.iter().map(Transaction::hash).collect();
+        block.header.merkle_root = tree_hash(&tx_hashes, tree_height(3), 0);
+
+        let merkle_block = MerkleBlock::new(&block, &[false, false, true]);
+        assert_eq!(
+            merkle_block.extract_matches().unwrap(),
+            vec![tx_hashes[2].clone()]
+        );
+
+        let mut duplicated = transactions;
+        duplicated.push(test_transaction(3));
+        block.transactions = duplicated;
+        let forged = MerkleBlock::new(&block, &[false, false, false, true]);
+        assert_eq!(forged.header.merkle_root, merkle_block.header.merkle_root);
+        assert!(forged.extract_matches().is_err());
+    }
+
+    #[test]
+    fn filtered_block_waits_for_matched_transactions() {
+        let block = test_block();
+        let merkle_block = MerkleBlock::new(&block, &matches_at(&block, &[0, 2]));
+        let mut filtered_block = FilteredBlock::new(merkle_block).unwrap();
+        assert_eq!(filtered_block.missing(), 2);
+
+        assert!(!filtered_block.add_transaction(block.transactions[1].clone()));
+        assert!(filtered_block.add_transaction(block.transactions[2].clone()));
+        assert!(!filtered_block.is_complete());
+        assert!(filtered_block.add_transaction(block.transactions[0].clone()));
+        assert!(filtered_block.is_complete());
+
+        let filtered = filtered_block.into_block().unwrap();
+        assert_eq!(filtered.header.hash(), block.header.hash());
+        let tx_hashes: Vec<Vec<u8>> = filtered
+            .transactions
+            .iter()
+            .map(Transaction::hash)
+            .collect();
+        assert_eq!(
+            tx_hashes,
+            vec![block.transactions[0].hash(), block.transactions[2].hash()]
+        );
+    }
+}
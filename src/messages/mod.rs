@@ -1,10 +1,20 @@
+pub mod addr;
 pub mod block;
+pub mod block_txn;
+pub mod compact_block;
+pub mod filter_load;
+pub mod get_addr;
+pub mod get_blocks;
 pub mod get_data;
 pub mod get_headers;
 pub mod headers;
 pub mod inv;
+pub mod mempool;
+pub mod merkle_block;
 pub mod not_found;
 pub mod ping_pong;
+pub mod reject;
+pub mod send_compact;
 pub mod send_headers;
 pub mod transaction;
 pub mod ver_ack;
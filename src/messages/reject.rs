@@ -0,0 +1,174 @@
+use crate::{
+    error::CustomError,
+    message::Message,
+    parser::{BufferParser, VarIntSerialize},
+    structs::block_header::display_hash,
+};
+
+/// El mensaje rechazado tenia un formato invalido.
+pub const REJECT_MALFORMED: u8 = 0x01;
+/// El bloque o la transaccion no cumple las reglas de consenso.
+pub const REJECT_INVALID: u8 = 0x10;
+/// La version del mensaje ya no se acepta.
+pub const REJECT_OBSOLETE: u8 = 0x11;
+/// El peer ya tenia el bloque o la transaccion, o gasta outputs ya gastados.
+pub const REJECT_DUPLICATE: u8 = 0x12;
+/// La transaccion no es estandar y el peer no la retransmite.
+pub const REJECT_NONSTANDARD: u8 = 0x40;
+/// Algun output de la transaccion es dust.
+pub const REJECT_DUST: u8 = 0x41;
+/// El fee de la transaccion no alcanza el minimo del peer.
+pub const REJECT_INSUFFICIENT_FEE: u8 = 0x42;
+/// El bloque no coincide con un checkpoint del peer.
+pub const REJECT_CHECKPOINT: u8 = 0x43;
+
+const DATA_SIZE: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+/// Esta estructura representa al mensaje 'reject' de Bitcoin, con el que un peer avisa que rechazo un mensaje nuestro.
+/// Los elementos son:
+/// - message: Comando del mensaje rechazado (por ejemplo "tx" o "block").
+/// - ccode: Codigo del motivo del rechazo (ver REJECT_*).
+/// - reason: Motivo del rechazo en texto, tal como lo envia el peer.
+/// - data: Hash del bloque o la transaccion rechazada, si el mensaje rechazado es uno de ellos.
+pub struct Reject {
+    pub message: String,
+    pub ccode: u8,
+    pub reason: String,
+    pub data: Option<Vec<u8>>,
+}
+
+impl Reject {
+    /// Devuelve el nombre del codigo del motivo del rechazo.
+    pub fn ccode_name(&self) -> &'static str {
+        match self.ccode {
+            REJECT_MALFORMED => "malformed",
+            REJECT_INVALID => "invalid",
+            REJECT_OBSOLETE => "obsolete",
+            REJECT_DUPLICATE => "duplicate",
+            REJECT_NONSTANDARD => "nonstandard",
+            REJECT_DUST => "dust",
+            REJECT_INSUFFICIENT_FEE => "insufficient fee",
+            REJECT_CHECKPOINT => "checkpoint",
+            _ => "unknown",
+        }
+    }
+
+    /// Devuelve el motivo del rechazo para mostrarle al usuario, con el nombre del codigo y el texto del peer.
+    pub fn describe(&self) -> String {
+        let subject = match &self.data {
+            Some(hash) => format!("{} {}", self.message, display_hash(hash)),
+            None => self.message.clone(),
+        };
+        format!(
+            "{subject} rejected ({}): {}",
+            self.ccode_name(),
+            self.reason
+        )
+    }
+}
+
+/// Implementa el trait Message para el mensaje 'reject'
+/// Permite serializar, parsear y obtener el comando
+impl Message for Reject {
+    fn serialize(&self) -> Vec<u8> {
+        let mut buffer = self.message.len().to_varint_bytes();
+        buffer.extend(self.message.as_bytes());
+        buffer.push(self.ccode);
+        buffer.extend(self.reason.len().to_varint_bytes());
+        buffer.extend(self.reason.as_bytes());
+        if let Some(data) = &self.data {
+            buffer.extend(data);
+        }
+        buffer
+    }
+
+    fn get_command(&self) -> String {
+        String::from("reject")
+    }
+
+    /// El hash de data es opcional: solo se lee si quedan sus 32 bytes despues del motivo.
+    fn parse(buffer: Vec<u8>) -> Result<Self, CustomError> {
+        let mut parser = BufferParser::new(buffer);
+        let message_size = parser.extract_varint()? as usize;
+        let message = parser.extract_string(message_size)?;
+        let ccode = parser.extract_u8()?;
+        let reason_size = parser.extract_varint()? as usize;
+        let reason = parser.extract_string(reason_size)?;
+        let data = match parser.len() >= DATA_SIZE {
+            true => Some(parser.extract_buffer(DATA_SIZE)?.to_vec()),
+            false => None,
+        };
+        Ok(Self {
+            message,
+            ccode,
+            reason,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // reject de bitcoind a una transaccion con fee insuficiente
+    const TX_REJECT: [u8; 58] = [
+        0x02, 0x74, 0x78, 0x42, 0x15, 0x6d, 0x69, 0x6e, 0x20, 0x72, 0x65, 0x6c, 0x61, 0x79, 0x20,
+        0x66, 0x65, 0x65, 0x20, 0x6e, 0x6f, 0x74, 0x20, 0x6d, 0x65, 0x74, 0x3d, 0x2f, 0x8c, 0x0a,
+        0xb8, 0xa3, 0xab, 0x7e, 0x5f, 0x6c, 0x4d, 0x8e, 0x6f, 0x0a, 0x1b, 0x2c, 0x3d, 0x4e, 0x5f,
+        0x60, 0x71, 0x82, 0x93, 0xa4, 0xb5, 0xc6, 0xd7, 0xe8, 0xf9, 0x01, 0x12, 0x23,
+    ];
+
+    // reject de bitcoind a un version viejo, sin hash
+    const VERSION_REJECT: [u8; 42] = [
+        0x07, 0x76, 0x65, 0x72, 0x73, 0x69, 0x6f, 0x6e, 0x11, 0x20, 0x56, 0x65, 0x72, 0x73, 0x69,
+        0x6f, 0x6e, 0x20, 0x6d, 0x75, 0x73, 0x74, 0x20, 0x62, 0x65, 0x20, 0x33, 0x31, 0x38, 0x30,
+        0x30, 0x20, 0x6f, 0x72, 0x20, 0x67, 0x72, 0x65, 0x61, 0x74, 0x65, 0x72,
+    ];
+
+    #[test]
+    fn parse_transaction_reject() {
+        let reject = Reject::parse(TX_REJECT.to_vec()).unwrap();
+        assert_eq!(reject.message, "tx");
+        assert_eq!(reject.ccode, REJECT_INSUFFICIENT_FEE);
+        assert_eq!(reject.reason, "min relay fee not met");
+        assert_eq!(reject.data, Some(TX_REJECT[26..].to_vec()));
+        assert_eq!(
+            reject.describe(),
+            "tx 231201f9e8d7c6b5a4938271605f4e3d2c1b0a6f8e4d6c5f7eaba3b80a8c2f3d rejected (insufficient fee): min relay fee not met"
+        );
+    }
+
+    #[test]
+    fn parse_reject_without_data() {
+        let reject = Reject::parse(VERSION_REJECT.to_vec()).unwrap();
+        assert_eq!(reject.message, "version");
+        assert_eq!(reject.ccode, REJECT_OBSOLETE);
+        assert_eq!(reject.reason, "Version must be 31800 or greater");
+        assert_eq!(reject.data, None);
+        assert_eq!(
+            reject.describe(),
+            "version rejected (obsolete): Version must be 31800 or greater"
+        );
+    }
+
+    #[test]
+    fn serialize_reject() {
+        let reject = Reject::parse(TX_REJECT.to_vec()).unwrap();
+        assert_eq!(reject.serialize(), TX_REJECT.to_vec());
+        let reject = Reject::parse(VERSION_REJECT.to_vec()).unwrap();
+        assert_eq!(reject.serialize(), VERSION_REJECT.to_vec());
+    }
+
+    #[test]
+    fn parse_truncated_reject() {
+        assert!(Reject::parse(TX_REJECT[..10].to_vec()).is_err());
+    }
+
+    #[test]
+    fn get_command_reject() {
+        let reject = Reject::parse(VERSION_REJECT.to_vec()).unwrap();
+        assert_eq!(reject.get_command(), "reject");
+    }
+}
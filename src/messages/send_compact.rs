@@ -0,0 +1,81 @@
+use crate::{error::CustomError, message::Message, parser::BufferParser};
+
+const SEND_COMPACT_BYTES: usize = 9;
+
+/// Version de compact blocks (BIP 152) que soporta el nodo, los short ids se calculan con el txid de las transacciones.
+pub const COMPACT_BLOCKS_VERSION: u64 = 1;
+
+/// Version del protocolo a partir de la cual los peers soportan compact blocks, solo a ellos se les envia sendcmpct.
+pub const COMPACT_BLOCKS_PROTOCOL_VERSION: i32 = 70014;
+
+#[derive(Debug)]
+/// SendCompact es el mensaje 'sendcmpct' que se envia tras el handshake para negociar el uso de compact blocks (BIP 152).
+/// Los elementos son:
+/// - announce: Si es true, el peer puede anunciar los bloques nuevos enviando directamente el cmpctblock.
+/// - version: Version de compact blocks soportada.
+pub struct SendCompact {
+    pub announce: bool,
+    pub version: u64,
+}
+
+impl SendCompact {
+    /// Crea un nuevo mensaje 'sendcmpct'.
+    pub fn new(announce: bool, version: u64) -> Self {
+        SendCompact { announce, version }
+    }
+}
+
+/// Implementa el trait Message para el mensaje 'sendcmpct'.
+/// Permite serializar, parsear y obtener el comando
+impl Message for SendCompact {
+    fn get_command(&self) -> String {
+        String::from("sendcmpct")
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = vec![];
+        buffer.push(self.announce as u8);
+        buffer.extend(self.version.to_le_bytes());
+        buffer
+    }
+
+    fn parse(buffer: Vec<u8>) -> Result<Self, CustomError> {
+        if buffer.len() != SEND_COMPACT_BYTES {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+        let mut parser = BufferParser::new(buffer);
+        let announce = parser.extract_u8()? != 0;
+        let version = parser.extract_u64()?;
+        Ok(SendCompact { announce, version })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_send_compact() {
+        let send_compact = SendCompact::new(false, COMPACT_BLOCKS_VERSION);
+        assert_eq!(send_compact.serialize(), vec![0, 1, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn parse_send_compact() {
+        let send_compact = SendCompact::parse(vec![1, 2, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert!(send_compact.announce);
+        assert_eq!(send_compact.version, 2);
+    }
+
+    #[test]
+    fn parse_invalid_send_compact() {
+        let send_compact = SendCompact::parse(vec![1, 2, 0]);
+        assert!(send_compact.is_err());
+    }
+
+    #[test]
+    fn get_command_send_compact() {
+        let send_compact = SendCompact::new(false, COMPACT_BLOCKS_VERSION);
+        assert_eq!(send_compact.get_command(), String::from("sendcmpct"));
+    }
+}
@@ -1,11 +1,11 @@
-use std::collections::HashMap;
-
-use bitcoin_hashes::{sha256, sha256d, Hash};
+use bitcoin_hashes::{hash160, sha256, sha256d, Hash};
 use secp256k1::Secp256k1;
+use std::{collections::HashSet, io::Read};
 
 use crate::{
+    bech32::is_segwit_address,
     error::CustomError,
-    message::Message,
+    message::{Message, MessageHeader},
     parser::{BufferParser, VarIntSerialize},
     states::utxo_state::UTXO,
     structs::{
@@ -16,6 +16,54 @@ use crate::{
 };
 
 const SIGHASH_ALL: u32 = 1;
+const SIGHASH_NONE: u32 = 2;
+const SIGHASH_SINGLE: u32 = 3;
+const SIGHASH_ANYONECANPAY: u32 = 0x80;
+
+/// Mensaje que se firma con SIGHASH_SINGLE cuando no hay un output con el indice del input:
+/// bitcoin firma en ese caso el uint256 1 (en little endian), en lugar del hash de la transaccion.
+const SIGHASH_SINGLE_BUG_MESSAGE: [u8; 32] = {
+    let mut message = [0; 32];
+    message[0] = 1;
+    message
+};
+
+/// Tamaño maximo en bytes de una transaccion serializada que se acepta de un peer.
+pub const MAX_RELAY_TX_SIZE: usize = 400_000;
+
+/// Cantidad maxima por defecto de inputs, y de outputs, de una transaccion que se acepta de un peer (ver MAX_RELAY_TX_IO en el config).
+pub const DEFAULT_MAX_RELAY_TX_IO: usize = 2_500;
+
+/// Sequence maximo de un input, con el que la transaccion es final y se ignora su lock time.
+pub const FINAL_SEQUENCE: u32 = 0xffffffff;
+
+/// Sequence de los inputs de una transaccion con lock time que no se puede reemplazar.
+pub const LOCK_TIME_SEQUENCE: u32 = 0xfffffffe;
+
+/// Sequence de los inputs de una transaccion que señaliza que se puede reemplazar por otra con mas fee (BIP 125).
+pub const REPLACEABLE_SEQUENCE: u32 = 0xfffffffd;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+/// TransactionOptions son las opciones avanzadas de una transaccion que creamos.
+/// Los elementos son:
+/// - lock_time: Height o timestamp a partir del cual la transaccion se puede incluir en un bloque, o None si no tiene.
+/// - replaceable: Si es true la transaccion se puede reemplazar por otra que gaste los mismos inputs con mas fee (opt-in RBF).
+pub struct TransactionOptions {
+    pub lock_time: Option<u32>,
+    pub replaceable: bool,
+}
+
+impl TransactionOptions {
+    /// Devuelve el sequence de los inputs: REPLACEABLE_SEQUENCE si es reemplazable, LOCK_TIME_SEQUENCE si tiene lock time
+    /// (para que el lock time tenga efecto) y FINAL_SEQUENCE si no.
+    pub fn sequence(&self) -> u32 {
+        match (self.replaceable, self.lock_time) {
+            (true, _) => REPLACEABLE_SEQUENCE,
+            (false, Some(_)) => LOCK_TIME_SEQUENCE,
+            (false, None) => FINAL_SEQUENCE,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 
@@ -40,10 +88,73 @@ impl Transaction {
             .to_vec()
     }
 
+    /// Devuelve true si algun input señaliza que la transaccion se puede reemplazar (sequence menor a LOCK_TIME_SEQUENCE).
+    pub fn is_replaceable(&self) -> bool {
+        self.inputs
+            .iter()
+            .any(|input| input.sequence < LOCK_TIME_SEQUENCE)
+    }
+
+    /// Devuelve true si la transaccion es una coinbase: tiene un unico input que no gasta ningun output.
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.len() == 1
+            && self.inputs[0].previous_output.index == u32::MAX
+            && self.inputs[0]
+                .previous_output
+                .hash
+                .iter()
+                .all(|byte| *byte == 0)
+    }
+
+    /// Lee una transaccion enviada por un peer a partir del header de su mensaje.
+    /// Si el payload supera MAX_RELAY_TX_SIZE bytes lo descarta del stream sin cargarlo en memoria y devuelve
+    /// TransactionOverRelayLimit, por lo que el stream queda listo para leer el mensaje siguiente.
+    pub fn read_relayed(
+        stream: &mut impl Read,
+        header: &MessageHeader,
+    ) -> Result<Self, CustomError> {
+        let size = header.payload_size as usize;
+        if size > MAX_RELAY_TX_SIZE {
+            header.skip_payload(stream)?;
+            return Err(over_relay_limit(size, "bytes", MAX_RELAY_TX_SIZE));
+        }
+        Self::read_with_header(stream, header)
+    }
+
+    /// Verifica los limites con los que se aceptan las transacciones de los peers: como maximo MAX_RELAY_TX_SIZE bytes
+    /// serializada, max_inputs_outputs inputs y max_inputs_outputs outputs.
+    /// Devuelve TransactionOverRelayLimit indicando el primer limite que supera.
+    pub fn check_relay_limits(&self, max_inputs_outputs: usize) -> Result<(), CustomError> {
+        let size = self.serialize().len();
+        if size > MAX_RELAY_TX_SIZE {
+            return Err(over_relay_limit(size, "bytes", MAX_RELAY_TX_SIZE));
+        }
+        if self.inputs.len() > max_inputs_outputs {
+            return Err(over_relay_limit(
+                self.inputs.len(),
+                "inputs",
+                max_inputs_outputs,
+            ));
+        }
+        if self.outputs.len() > max_inputs_outputs {
+            return Err(over_relay_limit(
+                self.outputs.len(),
+                "outputs",
+                max_inputs_outputs,
+            ));
+        }
+        Ok(())
+    }
+
     /// Esta funcion se encarga de parsear una transacción a partir de un parser.
+    /// Devuelve CustomError si la transaccion usa el formato segwit (BIP 144), que no esta soportado:
+    /// el marker 0x00 se leeria como una transaccion sin inputs seguida del flag.
     pub fn parse_from_parser(parser: &mut BufferParser) -> Result<Self, CustomError> {
         let version = parser.extract_u32()?;
         let tx_in_count = parser.extract_varint()? as usize;
+        if tx_in_count == 0 && matches!(parser.peek_u8(), Some(flag) if flag != 0) {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
         let mut inputs = vec![];
         for _ in 0..tx_in_count {
             inputs.push(TransactionInput::parse(parser)?);
@@ -64,23 +175,23 @@ impl Transaction {
     }
 
     /// Esta funcion se encarga de obtener un movement de una transacción.(ver structs/movement.rs)
-    /// Recibe por parametro el hash del public key de la wallet en la cual se quiere ver si se realizo un movimiento, el estado de UTXO, y la transaccion en la que se realizo el movimiento.
-    /// Devuelve un Option<Movement> que puede ser None si no se realizo ningun movimiento para la wallet indicada por el public key hash en la transacción, o Some(Movement) si se realizo un movimiento para la wallet determinada por la public key hash.
+    /// Recibe por parametro los hashes de las public keys de la wallet en la cual se quiere ver si se realizo un movimiento (ver Wallet::get_pubkey_hash_set), el estado de UTXO, y la transaccion en la que se realizo el movimiento.
+    /// Devuelve un Option<Movement> que puede ser None si no se realizo ningun movimiento para la wallet indicada por los public key hashes en la transacción, o Some(Movement) si se realizo un movimiento para la wallet determinada por los public key hashes.
     pub fn get_movement(
         &self,
-        public_key_hash: &Vec<u8>,
+        public_key_hashes: &HashSet<Vec<u8>>,
         utxo: &UTXO,
     ) -> Result<Option<Movement>, CustomError> {
         let mut value: i64 = 0;
         for input in &self.inputs {
             if let Some(utxo_value) = utxo.tx_set.get(&input.previous_output) {
-                if utxo_value.tx_out.is_sent_to_key(public_key_hash)? {
+                if utxo_value.tx_out.is_sent_to_any_key(public_key_hashes) {
                     value -= utxo_value.tx_out.value as i64;
                 }
             }
         }
         for output in &self.outputs {
-            if output.is_sent_to_key(public_key_hash)? {
+            if output.is_sent_to_any_key(public_key_hashes) {
                 value += output.value as i64;
             }
         }
@@ -89,6 +200,7 @@ impl Transaction {
                 tx_hash: self.hash(),
                 value,
                 block_hash: None,
+                provisional: false,
             }))
         } else {
             Ok(None)
@@ -96,7 +208,9 @@ impl Transaction {
     }
 
     /// Esta funcion se encarga de crear una transacción.
-    /// Recibe por parametro la wallet de la cual se quiere enviar la transacción, un vector de OutPoint que contiene los outpoints de las transacciones que se quieren gastar, y un HashMap que contiene los public key hash de las wallets a las cuales se quiere enviar dinero y la cantidad de dinero que se quiere enviar a cada una.
+    /// Recibe por parametro la wallet de la cual se quiere enviar la transacción, un vector con los outpoints que se quieren gastar junto al script pubkey del output que gasta cada uno, y un vector con las public keys a las cuales se quiere enviar dinero y la cantidad de dinero que se quiere enviar a cada una.
+    /// Los outputs se crean en el mismo orden del vector, y una misma public key puede recibir mas de un output.
+    /// Las opciones indican el lock time de la transaccion y si se puede reemplazar, que definen el sequence de los inputs (ver TransactionOptions::sequence).
     /// Crea la transacción y la manda a firmar con la wallet de la cual se quiere enviar la transacción.
     /// Finalmente devuelve la transacción firmada.
     /// Devuelve CustomError si:
@@ -104,22 +218,26 @@ impl Transaction {
     /// - No se pudo firmar la transacción.
     pub fn create(
         sender_wallet: &Wallet,
-        inputs_outpoints: Vec<OutPoint>,
-        outputs: HashMap<String, u64>,
+        inputs: Vec<(OutPoint, Vec<u8>)>,
+        outputs: Vec<(String, u64)>,
+        options: TransactionOptions,
     ) -> Result<Self, CustomError> {
         let mut transaction = Transaction {
             version: 1,
             inputs: vec![],
             outputs: vec![],
-            lock_time: 0,
+            lock_time: options.lock_time.unwrap_or(0),
         };
-        for outpoint in inputs_outpoints {
+        let sequence = options.sequence();
+        let mut prev_script_pubkeys = vec![];
+        for (outpoint, prev_script_pubkey) in inputs {
             let input = TransactionInput {
                 previous_output: outpoint,
                 script_sig: vec![],
-                sequence: 0xffffffff,
+                sequence,
             };
             transaction.inputs.push(input);
+            prev_script_pubkeys.push(prev_script_pubkey);
         }
         for (pubkey, value) in outputs {
             let script_pubkey = get_script_pubkey(pubkey)?;
@@ -130,36 +248,229 @@ impl Transaction {
             transaction.outputs.push(output);
         }
 
-        transaction.get_script_sigs(sender_wallet)?;
+        transaction.sign(sender_wallet, &prev_script_pubkeys)?;
 
         Ok(transaction)
     }
 
-    /// Esta funcion se encarga de mandar a firmar una transacción.
-    /// Recibe por parametro la wallet con la cual se quiere firmar.
+    /// Esta funcion se encarga de firmar todos los inputs de una transacción con SIGHASH_ALL.
+    /// Recibe por parametro la wallet con la cual se quiere firmar y el script pubkey del output que gasta cada input, en el mismo orden que los inputs.
+    /// Cada input se firma con su propio sighash y su firma se coloca en su script sig.
     /// Devuelve CustomError si:
-    /// - No se puede obtener la script pub key de la wallet.
-    /// - No se puede obtener el hash del private key de la wallet.
+    /// - La cantidad de scripts no coincide con la cantidad de inputs.
+    /// - Algun input gasta un output que no es de la wallet.
+    /// - La wallet es P2WPKH: sus outputs necesitan una firma segwit, que todavia no se soporta.
+    /// - No se puede obtener la script pub key o el hash del private key de la wallet.
     /// - No se pudo firmar la transacción.
-    fn get_script_sigs(&mut self, wallet: &Wallet) -> Result<(), CustomError> {
-        let mut script_sigs = vec![];
-        let script_pubkey = wallet.get_script_pubkey()?;
+    pub fn sign(
+        &mut self,
+        wallet: &Wallet,
+        prev_script_pubkeys: &[Vec<u8>],
+    ) -> Result<(), CustomError> {
+        if prev_script_pubkeys.len() != self.inputs.len() {
+            return Err(CustomError::CannotSignTx);
+        }
+        if is_segwit_address(&wallet.pubkey) {
+            return Err(CustomError::CannotSignTx);
+        }
+        let wallet_script_pubkey = wallet.get_script_pubkey()?;
         let privkey_hash = wallet.get_privkey_hash()?;
 
-        for i in 0..self.inputs.len() {
-            self.inputs[i].script_sig = script_pubkey.clone();
-            let serialized_unsigned_tx = self.serialize();
-            let script_sig = sign(serialized_unsigned_tx, &privkey_hash)?;
-            script_sigs.push(script_sig);
-            self.inputs[i].script_sig = vec![];
+        let mut script_sigs = vec![];
+        for (index, prev_script_pubkey) in prev_script_pubkeys.iter().enumerate() {
+            if prev_script_pubkey != &wallet_script_pubkey {
+                return Err(CustomError::CannotSignTx);
+            }
+            let preimage = self.sighash_preimage(index, prev_script_pubkey);
+            script_sigs.push(sign(
+                preimage,
+                &privkey_hash,
+                wallet.compressed_pubkey,
+                SIGHASH_ALL,
+            )?);
         }
 
-        for (index, script_sig) in script_sigs.iter().enumerate() {
-            self.inputs[index].script_sig = script_sig.clone();
+        for (input, script_sig) in self.inputs.iter_mut().zip(script_sigs) {
+            input.script_sig = script_sig;
         }
 
         Ok(())
     }
+
+    /// Devuelve el preimage del sighash (SIGHASH_ALL) del input input_index: la transaccion serializada
+    /// con el script sig de ese input reemplazado por el script pubkey del output que gasta,
+    /// el resto de los script sigs vacios, y el tipo de sighash al final.
+    pub fn sighash_preimage(&self, input_index: usize, prev_script_pubkey: &[u8]) -> Vec<u8> {
+        self.sighash_preimage_with_type(input_index, prev_script_pubkey, SIGHASH_ALL)
+            .unwrap_or_default()
+    }
+
+    /// Devuelve el preimage del sighash legacy del input input_index para el tipo de sighash recibido:
+    /// - SIGHASH_NONE no incluye los outputs y pone en 0 el sequence del resto de los inputs.
+    /// - SIGHASH_SINGLE solo incluye los outputs hasta el del mismo indice que el input, con los anteriores vacios
+    ///   (valor -1 y script vacio), y pone en 0 el sequence del resto de los inputs.
+    /// - Con SIGHASH_ANYONECANPAY solo se incluye el input que se firma.
+    ///
+    /// Devuelve None si el input no existe o si es SIGHASH_SINGLE y no hay un output con el indice del input:
+    /// bitcoin firma en ese caso un mensaje fijo en lugar de un preimage (ver sighash_message).
+    pub fn sighash_preimage_with_type(
+        &self,
+        input_index: usize,
+        prev_script_pubkey: &[u8],
+        sighash_type: u32,
+    ) -> Option<Vec<u8>> {
+        if input_index >= self.inputs.len() {
+            return None;
+        }
+        let mut unsigned = self.clone();
+        for (index, input) in unsigned.inputs.iter_mut().enumerate() {
+            input.script_sig = if index == input_index {
+                prev_script_pubkey.to_vec()
+            } else {
+                vec![]
+            };
+        }
+
+        let base_type = sighash_type & 0x1f;
+        if base_type == SIGHASH_NONE || base_type == SIGHASH_SINGLE {
+            for (index, input) in unsigned.inputs.iter_mut().enumerate() {
+                if index != input_index {
+                    input.sequence = 0;
+                }
+            }
+        }
+        if base_type == SIGHASH_NONE {
+            unsigned.outputs.clear();
+        } else if base_type == SIGHASH_SINGLE {
+            if input_index >= unsigned.outputs.len() {
+                return None;
+            }
+            unsigned.outputs.truncate(input_index + 1);
+            for output in unsigned.outputs.iter_mut().take(input_index) {
+                output.value = u64::MAX;
+                output.script_pubkey = vec![];
+            }
+        }
+        if sighash_type & SIGHASH_ANYONECANPAY != 0 {
+            unsigned.inputs = vec![unsigned.inputs.swap_remove(input_index)];
+        }
+
+        let mut preimage = unsigned.serialize();
+        preimage.extend(sighash_type.to_le_bytes());
+        Some(preimage)
+    }
+
+    /// Devuelve el mensaje que firma el input input_index con el tipo de sighash recibido: el sha256d de su preimage
+    /// (ver sighash_preimage_with_type), o SIGHASH_SINGLE_BUG_MESSAGE si es SIGHASH_SINGLE y no hay un output
+    /// con el indice del input. Devuelve None si el input no existe.
+    pub fn sighash_message(
+        &self,
+        input_index: usize,
+        prev_script_pubkey: &[u8],
+        sighash_type: u32,
+    ) -> Option<[u8; 32]> {
+        if input_index >= self.inputs.len() {
+            return None;
+        }
+        match self.sighash_preimage_with_type(input_index, prev_script_pubkey, sighash_type) {
+            Some(preimage) => Some(sha256d::Hash::hash(&preimage).to_byte_array()),
+            None => Some(SIGHASH_SINGLE_BUG_MESSAGE),
+        }
+    }
+
+    /// Verifica que el script sig del input input_index cumpla el script pubkey P2PKH del output que gasta:
+    /// los dos ultimos datos que agrega al stack deben ser una firma y una public key, el hash160 de la public key
+    /// debe ser el del script pubkey y la firma debe ser valida para el mensaje que firma el input,
+    /// segun el tipo de sighash que indica (ver sighash_message).
+    /// Los outputs que no son P2PKH, y los script sigs con operaciones que no son pushes, no se pueden verificar y se aceptan.
+    /// Devuelve CustomError::InvalidTransactionScript si el script sig no cumple el script pubkey.
+    pub fn verify_input_script(
+        &self,
+        input_index: usize,
+        prev_script_pubkey: &[u8],
+    ) -> Result<(), CustomError> {
+        let Some(pubkey_hash) = p2pkh_hash(prev_script_pubkey) else { return Ok(()) };
+        let input = self
+            .inputs
+            .get(input_index)
+            .ok_or(CustomError::InvalidTransactionScript)?;
+
+        let Some(pushes) = script_pushes(&input.script_sig) else { return Ok(()) };
+        let [.., signature, publickey] = pushes.as_slice() else { return Err(CustomError::InvalidTransactionScript) };
+        if hash160::Hash::hash(publickey).as_byte_array() != pubkey_hash {
+            return Err(CustomError::InvalidTransactionScript);
+        }
+        let (sighash_type, signature) = signature
+            .split_last()
+            .ok_or(CustomError::InvalidTransactionScript)?;
+        let sighash = self
+            .sighash_message(input_index, prev_script_pubkey, *sighash_type as u32)
+            .ok_or(CustomError::InvalidTransactionScript)?;
+
+        let mut signature = secp256k1::ecdsa::Signature::from_der(signature)
+            .map_err(|_| CustomError::InvalidTransactionScript)?;
+        // las firmas con S alto son validas por consenso, pero secp256k1 solo verifica las normalizadas
+        signature.normalize_s();
+        let publickey = secp256k1::PublicKey::from_slice(publickey)
+            .map_err(|_| CustomError::InvalidTransactionScript)?;
+        let message = secp256k1::Message::from_slice(&sighash)
+            .map_err(|_| CustomError::InvalidTransactionScript)?;
+
+        Secp256k1::verification_only()
+            .verify_ecdsa(&message, &signature, &publickey)
+            .map_err(|_| CustomError::InvalidTransactionScript)
+    }
+}
+
+/// Devuelve el hash de la public key de un script pubkey P2PKH
+/// (OP_DUP OP_HASH160 <20 bytes> OP_EQUALVERIFY OP_CHECKSIG), o None si el script es de otro tipo.
+fn p2pkh_hash(script_pubkey: &[u8]) -> Option<&[u8]> {
+    match script_pubkey {
+        [0x76, 0xa9, 0x14, hash @ .., 0x88, 0xac] if hash.len() == 20 => Some(hash),
+        _ => None,
+    }
+}
+
+/// Devuelve los datos que agrega al stack un script formado solo por pushes (como el script sig de un input P2PKH),
+/// o None si contiene otras operaciones o esta truncado.
+/// Los pushes son OP_0, los de 1 a 75 bytes, OP_PUSHDATA1, OP_PUSHDATA2, OP_PUSHDATA4, OP_1NEGATE y OP_1 a OP_16.
+fn script_pushes(script: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut pushes = vec![];
+    let mut position = 0;
+    while position < script.len() {
+        let opcode = script[position];
+        let (length, data_start) = match opcode {
+            0x00..=0x4b => (opcode as usize, position + 1),
+            0x4c => (*script.get(position + 1)? as usize, position + 2),
+            0x4d => {
+                let length = script.get(position + 1..position + 3)?;
+                (
+                    u16::from_le_bytes([length[0], length[1]]) as usize,
+                    position + 3,
+                )
+            }
+            0x4e => {
+                let length = script.get(position + 1..position + 5)?;
+                let length = u32::from_le_bytes([length[0], length[1], length[2], length[3]]);
+                (usize::try_from(length).ok()?, position + 5)
+            }
+            0x4f => {
+                pushes.push(vec![0x81]);
+                position += 1;
+                continue;
+            }
+            0x51..=0x60 => {
+                pushes.push(vec![opcode - 0x50]);
+                position += 1;
+                continue;
+            }
+            _ => return None,
+        };
+        let data_end = data_start.checked_add(length)?;
+        pushes.push(script.get(data_start..data_end)?.to_vec());
+        position = data_end;
+    }
+    Some(pushes)
 }
 
 /// Implementa el trait Message para la estructura Transaction.
@@ -186,23 +497,42 @@ impl Message for Transaction {
 
     fn parse(buffer: Vec<u8>) -> Result<Self, crate::error::CustomError> {
         let mut parser = BufferParser::new(buffer);
-        Transaction::parse_from_parser(&mut parser)
+        let transaction = Transaction::parse_from_parser(&mut parser)?;
+        if !parser.is_empty() {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+        Ok(transaction)
     }
 }
 
-/// Esta funcion se encarga de firmar una transacción.
-/// Recibe un buffer que contiene la transacción a firmar y el hash del private key de la wallet con la cual se quiere firmar la transacción.
-fn sign(mut buffer: Vec<u8>, privkey: &[u8]) -> Result<Vec<u8>, CustomError> {
-    buffer.extend(SIGHASH_ALL.to_le_bytes());
+/// Devuelve el error de una transaccion de un peer que supera un limite, con lo que tiene y el limite (por ejemplo "3000 inputs, limit 2500").
+fn over_relay_limit(found: usize, unit: &str, limit: usize) -> CustomError {
+    CustomError::TransactionOverRelayLimit(format!("{found} {unit}, limit {limit}"))
+}
 
-    let z = sha256d::Hash::hash(&buffer);
+/// Esta funcion se encarga de firmar un input de una transacción.
+/// Recibe el preimage del sighash del input (ver Transaction::sighash_preimage), el hash del private key de la wallet con la cual se quiere firmar,
+/// si su public key va comprimida (el script pubkey que se gasta tiene el hash de una de las dos formas, ver Wallet::compressed_pubkey)
+/// y el tipo de sighash del preimage, que se agrega al final de la firma.
+/// Devuelve el script sig del input.
+fn sign(
+    preimage: Vec<u8>,
+    privkey: &[u8],
+    compressed_pubkey: bool,
+    sighash_type: u32,
+) -> Result<Vec<u8>, CustomError> {
+    let z = sha256d::Hash::hash(&preimage);
 
     let secp = Secp256k1::new();
     let msg = secp256k1::Message::from_slice(&z.to_byte_array())
         .map_err(|_| CustomError::CannotSignTx)?;
 
     let key = secp256k1::SecretKey::from_slice(privkey).map_err(|_| CustomError::CannotSignTx)?;
-    let publickey = secp256k1::PublicKey::from_secret_key(&secp, &key).serialize();
+    let publickey = secp256k1::PublicKey::from_secret_key(&secp, &key);
+    let publickey = match compressed_pubkey {
+        true => publickey.serialize().to_vec(),
+        false => publickey.serialize_uncompressed().to_vec(),
+    };
 
     let signature = secp.sign_ecdsa(&msg, &key).serialize_der();
 
@@ -210,7 +540,7 @@ fn sign(mut buffer: Vec<u8>, privkey: &[u8]) -> Result<Vec<u8>, CustomError> {
 
     script_sig.extend((signature.len() + 1).to_varint_bytes());
     script_sig.extend(signature.to_vec());
-    script_sig.extend((0x1_u8).to_le_bytes());
+    script_sig.push(sighash_type as u8);
     script_sig.extend(publickey.len().to_varint_bytes());
     script_sig.extend(publickey);
 
@@ -308,6 +638,518 @@ mod tests {
         ];
         let mut parser = BufferParser::new(buffer);
         let mut tx = Transaction::parse_from_parser(&mut parser).unwrap();
-        assert!(tx.get_script_sigs(&wallet).is_ok());
+        let script_pubkey = wallet.get_script_pubkey().unwrap();
+        assert!(tx.sign(&wallet, &[script_pubkey]).is_ok());
+    }
+
+    #[test]
+    fn create_keeps_outputs_order_and_duplicated_addresses() {
+        let wallet = Wallet::new(
+            String::from("test"),
+            String::from("mscatccDgq7azndWHFTzvEuZuywCsUvTRu"),
+            String::from("cNpwEsaVLhju18SJowLtdCNaJtvMvqL4jtFLm2FXw7vZjg4sRWvH"),
+            &UTXO::new(String::from("tests"), String::from("test_utxo.bin")).unwrap(),
+        )
+        .unwrap();
+        let outpoint = OutPoint {
+            hash: vec![1; 32],
+            index: 0,
+        };
+        let outputs = vec![
+            (String::from("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm"), 1000),
+            (String::from("mscatccDgq7azndWHFTzvEuZuywCsUvTRu"), 500),
+            (String::from("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm"), 200),
+        ];
+
+        let inputs = vec![(outpoint, wallet.get_script_pubkey().unwrap())];
+        let tx = Transaction::create(
+            &wallet,
+            inputs,
+            outputs.clone(),
+            TransactionOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(tx.outputs.len(), 3);
+        for (output, (pubkey, value)) in tx.outputs.iter().zip(outputs) {
+            assert_eq!(output.value, value);
+            assert_eq!(output.script_pubkey, get_script_pubkey(pubkey).unwrap());
+        }
+    }
+
+    /// Crea una transaccion de un input con las opciones recibidas y devuelve su serializacion.
+    fn serialize_with_options(options: TransactionOptions) -> Vec<u8> {
+        let wallet = Wallet::new(
+            String::from("test"),
+            String::from("mscatccDgq7azndWHFTzvEuZuywCsUvTRu"),
+            String::from("cNpwEsaVLhju18SJowLtdCNaJtvMvqL4jtFLm2FXw7vZjg4sRWvH"),
+            &UTXO::new(String::from("tests"), String::from("test_utxo.bin")).unwrap(),
+        )
+        .unwrap();
+        let outpoint = OutPoint {
+            hash: vec![1; 32],
+            index: 0,
+        };
+        let inputs = vec![(outpoint, wallet.get_script_pubkey().unwrap())];
+        let outputs = vec![(String::from("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm"), 1000)];
+        Transaction::create(&wallet, inputs, outputs, options)
+            .unwrap()
+            .serialize()
+    }
+
+    /// Devuelve el sequence del unico input y el lock time de una transaccion serializada,
+    /// leidos de los bytes antes del output (1 output P2PKH de 34 bytes y su cantidad) y de los ultimos 4 bytes.
+    fn sequence_and_lock_time(serialized: &[u8]) -> ([u8; 4], [u8; 4]) {
+        let len = serialized.len();
+        let sequence_end = len - 4 - 34 - 1;
+        let sequence = serialized[sequence_end - 4..sequence_end]
+            .try_into()
+            .unwrap();
+        let lock_time = serialized[len - 4..].try_into().unwrap();
+        (sequence, lock_time)
+    }
+
+    #[test]
+    fn create_sets_sequence_and_lock_time_from_the_options() {
+        let final_tx = serialize_with_options(TransactionOptions::default());
+        assert_eq!(
+            sequence_and_lock_time(&final_tx),
+            ([0xff, 0xff, 0xff, 0xff], [0, 0, 0, 0])
+        );
+
+        let locked = serialize_with_options(TransactionOptions {
+            lock_time: Some(2_500_000),
+            replaceable: false,
+        });
+        assert_eq!(
+            sequence_and_lock_time(&locked),
+            ([0xfe, 0xff, 0xff, 0xff], [0xa0, 0x25, 0x26, 0x00])
+        );
+
+        let replaceable = serialize_with_options(TransactionOptions {
+            lock_time: None,
+            replaceable: true,
+        });
+        assert_eq!(
+            sequence_and_lock_time(&replaceable),
+            ([0xfd, 0xff, 0xff, 0xff], [0, 0, 0, 0])
+        );
+        let replaceable = Transaction::parse(replaceable).unwrap();
+        assert!(replaceable.is_replaceable());
+
+        let both = serialize_with_options(TransactionOptions {
+            lock_time: Some(1_700_000_000),
+            replaceable: true,
+        });
+        assert_eq!(
+            sequence_and_lock_time(&both),
+            ([0xfd, 0xff, 0xff, 0xff], 1_700_000_000_u32.to_le_bytes())
+        );
+        assert!(!Transaction::parse(locked).unwrap().is_replaceable());
+    }
+
+    fn two_inputs_transaction() -> Transaction {
+        let inputs = [(vec![1; 32], 0), (vec![2; 32], 1)].map(|(hash, index)| TransactionInput {
+            previous_output: OutPoint { hash, index },
+            script_sig: vec![],
+            sequence: 0xffffffff,
+        });
+        Transaction {
+            version: 1,
+            inputs: inputs.to_vec(),
+            outputs: vec![TransactionOutput {
+                value: 1000,
+                script_pubkey: get_script_pubkey(String::from(
+                    "mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm",
+                ))
+                .unwrap(),
+            }],
+            lock_time: 0,
+        }
+    }
+
+    #[test]
+    fn tx_with_trailing_bytes_is_invalid() {
+        let mut buffer = two_inputs_transaction().serialize();
+        assert!(Transaction::parse(buffer.clone()).is_ok());
+
+        buffer.push(0);
+        assert!(Transaction::parse(buffer).is_err());
+    }
+
+    #[test]
+    fn segwit_tx_is_invalid() {
+        let legacy = two_inputs_transaction().serialize();
+        let mut segwit = legacy[0..4].to_vec();
+        segwit.extend([0x00, 0x01]);
+        segwit.extend(&legacy[4..]);
+
+        let mut parser = BufferParser::new(segwit);
+        assert!(Transaction::parse_from_parser(&mut parser).is_err());
+    }
+
+    #[test]
+    fn sighash_preimages_only_include_the_signed_input_script() {
+        let tx = two_inputs_transaction();
+        let first_script =
+            get_script_pubkey(String::from("mscatccDgq7azndWHFTzvEuZuywCsUvTRu")).unwrap();
+        let second_script =
+            get_script_pubkey(String::from("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm")).unwrap();
+        let first_preimage = vec![
+            0x01, 0x00, 0x00, 0x00, 0x02, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+            0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+            0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x19,
+            0x76, 0xA9, 0x14, 0x84, 0xB2, 0x23, 0x4E, 0x2F, 0xAA, 0x6E, 0x1A, 0x75, 0x1D, 0x7E,
+            0x52, 0x84, 0xEB, 0x10, 0xCC, 0xE6, 0xF7, 0x51, 0xF6, 0x88, 0xAC, 0xFF, 0xFF, 0xFF,
+            0xFF, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+            0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+            0x02, 0x02, 0x02, 0x02, 0x02, 0x01, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF,
+            0x01, 0xE8, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x19, 0x76, 0xA9, 0x14, 0x1B,
+            0x28, 0xDB, 0x21, 0x45, 0x14, 0x04, 0x6C, 0x69, 0xEA, 0x57, 0x47, 0x32, 0x32, 0x9A,
+            0x16, 0x10, 0xDC, 0x40, 0x55, 0x88, 0xAC, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+            0x00,
+        ];
+        let second_preimage = vec![
+            0x01, 0x00, 0x00, 0x00, 0x02, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+            0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+            0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0xFF, 0xFF, 0xFF, 0xFF, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+            0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+            0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x01, 0x00, 0x00, 0x00, 0x19, 0x76,
+            0xA9, 0x14, 0x1B, 0x28, 0xDB, 0x21, 0x45, 0x14, 0x04, 0x6C, 0x69, 0xEA, 0x57, 0x47,
+            0x32, 0x32, 0x9A, 0x16, 0x10, 0xDC, 0x40, 0x55, 0x88, 0xAC, 0xFF, 0xFF, 0xFF, 0xFF,
+            0x01, 0xE8, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x19, 0x76, 0xA9, 0x14, 0x1B,
+            0x28, 0xDB, 0x21, 0x45, 0x14, 0x04, 0x6C, 0x69, 0xEA, 0x57, 0x47, 0x32, 0x32, 0x9A,
+            0x16, 0x10, 0xDC, 0x40, 0x55, 0x88, 0xAC, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+            0x00,
+        ];
+
+        assert_eq!(tx.sighash_preimage(0, &first_script), first_preimage);
+        assert_eq!(tx.sighash_preimage(1, &second_script), second_preimage);
+    }
+
+    #[test]
+    fn sign_places_each_input_signature_over_its_own_sighash() {
+        let wallet = Wallet::new(
+            String::from("test"),
+            String::from("mscatccDgq7azndWHFTzvEuZuywCsUvTRu"),
+            String::from("cNpwEsaVLhju18SJowLtdCNaJtvMvqL4jtFLm2FXw7vZjg4sRWvH"),
+            &UTXO::new(String::from("tests"), String::from("test_utxo.bin")).unwrap(),
+        )
+        .unwrap();
+        let script_pubkey = wallet.get_script_pubkey().unwrap();
+        let unsigned = two_inputs_transaction();
+        let mut tx = unsigned.clone();
+        tx.sign(&wallet, &[script_pubkey.clone(), script_pubkey.clone()])
+            .unwrap();
+
+        // script sig: largo de la firma, firma DER, tipo de sighash, largo de la public key y public key
+        let secp = Secp256k1::new();
+        for (index, input) in tx.inputs.iter().enumerate() {
+            let signature_end = input.script_sig[0] as usize;
+            assert_eq!(input.script_sig[signature_end], 0x01);
+            let signature =
+                secp256k1::ecdsa::Signature::from_der(&input.script_sig[1..signature_end]).unwrap();
+            let publickey =
+                secp256k1::PublicKey::from_slice(&input.script_sig[signature_end + 2..]).unwrap();
+            let sighash = sha256d::Hash::hash(&unsigned.sighash_preimage(index, &script_pubkey));
+            let message = secp256k1::Message::from_slice(&sighash.to_byte_array()).unwrap();
+            assert!(secp.verify_ecdsa(&message, &signature, &publickey).is_ok());
+        }
+        assert_ne!(tx.inputs[0].script_sig, tx.inputs[1].script_sig);
+
+        let foreign_script =
+            get_script_pubkey(String::from("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm")).unwrap();
+        assert!(unsigned
+            .clone()
+            .sign(&wallet, &[script_pubkey, foreign_script])
+            .is_err());
+        assert!(unsigned.clone().sign(&wallet, &[]).is_err());
+    }
+
+    #[test]
+    fn sign_serializes_the_pubkey_in_the_form_of_the_wallet_address() {
+        // la misma private key, con la direccion de su public key sin comprimir
+        let mut wallet = Wallet::new(
+            String::from("test"),
+            String::from("mhh2JbY3QmNaX9a1faSdk5xBpbLTLd12jK"),
+            String::from("cNpwEsaVLhju18SJowLtdCNaJtvMvqL4jtFLm2FXw7vZjg4sRWvH"),
+            &UTXO::new(String::from("tests"), String::from("test_utxo.bin")).unwrap(),
+        )
+        .unwrap();
+        wallet.compressed_pubkey = false;
+        let script_pubkey = wallet.get_script_pubkey().unwrap();
+        let mut tx = two_inputs_transaction();
+        tx.sign(&wallet, &[script_pubkey.clone(), script_pubkey.clone()])
+            .unwrap();
+
+        for index in 0..2 {
+            let script_sig = &tx.inputs[index].script_sig;
+            let signature_end = script_sig[0] as usize;
+            assert_eq!(script_sig[signature_end + 1], 65);
+            assert!(tx.verify_input_script(index, &script_pubkey).is_ok());
+        }
+
+        // firmando con la public key comprimida el hash no coincide con el del script pubkey
+        wallet.compressed_pubkey = true;
+        tx.sign(&wallet, &[script_pubkey.clone(), script_pubkey.clone()])
+            .unwrap();
+        assert!(matches!(
+            tx.verify_input_script(0, &script_pubkey),
+            Err(CustomError::InvalidTransactionScript)
+        ));
+    }
+
+    fn p2pkh_test_wallet() -> Wallet {
+        Wallet::new(
+            String::from("test"),
+            String::from("mscatccDgq7azndWHFTzvEuZuywCsUvTRu"),
+            String::from("cNpwEsaVLhju18SJowLtdCNaJtvMvqL4jtFLm2FXw7vZjg4sRWvH"),
+            &UTXO::new(String::from("tests"), String::from("test_utxo.bin")).unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn verify_checks_signatures_of_every_sighash_type() {
+        let wallet = p2pkh_test_wallet();
+        let script_pubkey = wallet.get_script_pubkey().unwrap();
+        let privkey = wallet.get_privkey_hash().unwrap();
+        let mut tx = two_inputs_transaction();
+        let sign_first_input = |tx: &mut Transaction, sighash_type: u32| {
+            let preimage = tx
+                .sighash_preimage_with_type(0, &script_pubkey, sighash_type)
+                .unwrap();
+            tx.inputs[0].script_sig =
+                sign(preimage, &privkey, wallet.compressed_pubkey, sighash_type).unwrap();
+        };
+
+        for sighash_type in [
+            SIGHASH_ALL,
+            SIGHASH_NONE,
+            SIGHASH_SINGLE,
+            SIGHASH_ALL | SIGHASH_ANYONECANPAY,
+            SIGHASH_NONE | SIGHASH_ANYONECANPAY,
+            SIGHASH_SINGLE | SIGHASH_ANYONECANPAY,
+        ] {
+            sign_first_input(&mut tx, sighash_type);
+            assert!(tx.verify_input_script(0, &script_pubkey).is_ok());
+        }
+
+        // SIGHASH_NONE no firma los outputs, SIGHASH_ALL si
+        sign_first_input(&mut tx, SIGHASH_NONE);
+        tx.outputs[0].value = 1;
+        assert!(tx.verify_input_script(0, &script_pubkey).is_ok());
+        sign_first_input(&mut tx, SIGHASH_ALL);
+        tx.outputs[0].value = 2;
+        assert!(tx.verify_input_script(0, &script_pubkey).is_err());
+
+        // SIGHASH_SINGLE del segundo input, sin un segundo output: firma un mensaje fijo
+        // y la firma del primer input no es valida para el
+        assert_eq!(
+            tx.sighash_preimage_with_type(1, &script_pubkey, SIGHASH_SINGLE),
+            None
+        );
+        let mut single = tx.inputs[0].script_sig.clone();
+        let signature_end = single[0] as usize;
+        single[signature_end] = SIGHASH_SINGLE as u8;
+        tx.inputs[1].script_sig = single;
+        assert!(matches!(
+            tx.verify_input_script(1, &script_pubkey),
+            Err(CustomError::InvalidTransactionScript)
+        ));
+    }
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn sighash_single_without_matching_output_signs_the_constant_one() {
+        // vector de tx_valid.json de Bitcoin Core: el segundo input firma con SIGHASH_SINGLE y la transaccion tiene un solo output
+        let tx = Transaction::parse(from_hex(
+            "01000000020002000000000000000000000000000000000000000000000000000000000000000000000151ffffffff\
+            0001000000000000000000000000000000000000000000000000000000000000000000006b483045022100c9cdd08798a28af9\
+            d1baf44a6c77bcc7e279f47dc487c8c899911bc48feaffcc0220503c5c50ae3998a733263c5c0f7061b483e2b56c4c41b456e7\
+            d2f5a78a74c077032102d5c25adb51b61339d2b05315791e21bbe80ea470a49db0135720983c905aace0ffffffff01000000000\
+            0000000015100000000",
+        ))
+        .unwrap();
+        let script_pubkey = from_hex("76a914e52b482f2faa8ecbf0db344f93c84ac908557f3388ac");
+
+        assert_eq!(
+            tx.sighash_message(1, &script_pubkey, SIGHASH_SINGLE),
+            Some(SIGHASH_SINGLE_BUG_MESSAGE)
+        );
+        assert!(tx.verify_input_script(1, &script_pubkey).is_ok());
+
+        // indicando SIGHASH_ALL la misma firma no es valida, ya que no firma la transaccion
+        let mut other = tx.clone();
+        other.inputs[1].script_sig[0x48] = SIGHASH_ALL as u8;
+        assert!(other.verify_input_script(1, &script_pubkey).is_err());
+    }
+
+    #[test]
+    fn verify_accepts_every_push_form_of_the_script_sig() {
+        let wallet = p2pkh_test_wallet();
+        let script_pubkey = wallet.get_script_pubkey().unwrap();
+        let mut tx = two_inputs_transaction();
+        tx.sign(&wallet, &[script_pubkey.clone(), script_pubkey.clone()])
+            .unwrap();
+        let script_sig = tx.inputs[0].script_sig.clone();
+        let signature_end = script_sig[0] as usize + 1;
+        let signature = &script_sig[1..signature_end];
+        let publickey = &script_sig[signature_end + 1..];
+
+        // OP_0 y OP_1 antes de la firma quedan debajo en el stack y no cambian el resultado
+        let mut with_small_pushes = vec![0x00, 0x51];
+        with_small_pushes.extend(&script_sig);
+        tx.inputs[0].script_sig = with_small_pushes;
+        assert!(tx.verify_input_script(0, &script_pubkey).is_ok());
+
+        // la firma con OP_PUSHDATA2 y la public key con OP_PUSHDATA4
+        let mut pushdata = vec![0x4d];
+        pushdata.extend((signature.len() as u16).to_le_bytes());
+        pushdata.extend(signature);
+        pushdata.push(0x4e);
+        pushdata.extend((publickey.len() as u32).to_le_bytes());
+        pushdata.extend(publickey);
+        tx.inputs[0].script_sig = pushdata;
+        assert!(tx.verify_input_script(0, &script_pubkey).is_ok());
+
+        // una firma vacia (OP_0) hace fallar el OP_CHECKSIG
+        let mut empty_signature = vec![0x00];
+        empty_signature.extend(&script_sig[signature_end..]);
+        tx.inputs[0].script_sig = empty_signature;
+        assert!(matches!(
+            tx.verify_input_script(0, &script_pubkey),
+            Err(CustomError::InvalidTransactionScript)
+        ));
+
+        // un OP_PUSHDATA2 truncado no es un script de pushes valido
+        assert_eq!(script_pushes(&[0x4d, 0x05]), None);
+        assert_eq!(
+            script_pushes(&[0x00, 0x4f, 0x60, 0x4c, 0x01, 0xaa]),
+            Some(vec![vec![], vec![0x81], vec![16], vec![0xaa]])
+        );
+    }
+
+    #[test]
+    fn script_sigs_with_other_operations_are_not_verified() {
+        let wallet = p2pkh_test_wallet();
+        let script_pubkey = wallet.get_script_pubkey().unwrap();
+        let mut tx = two_inputs_transaction();
+
+        // un OP_NOP entre los pushes: el script sig no es solo de pushes y no se puede interpretar
+        let mut script_sig = vec![0x61];
+        script_sig.extend([0x01, 0xaa, 0x01, 0xbb]);
+        tx.inputs[0].script_sig = script_sig;
+        assert!(tx.verify_input_script(0, &script_pubkey).is_ok());
+
+        // el script sig de un output P2SH no se verifica
+        let p2sh_script_pubkey = from_hex("a914e52b482f2faa8ecbf0db344f93c84ac908557f3387");
+        tx.inputs[0].script_sig = vec![0x00, 0x01, 0xaa];
+        assert!(tx.verify_input_script(0, &p2sh_script_pubkey).is_ok());
+    }
+
+    #[test]
+    fn forged_signature_with_other_sighash_type_is_rejected() {
+        let wallet = p2pkh_test_wallet();
+        let script_pubkey = wallet.get_script_pubkey().unwrap();
+        let mut tx = two_inputs_transaction();
+        tx.sign(&wallet, &[script_pubkey.clone(), script_pubkey.clone()])
+            .unwrap();
+        let signature_end = tx.inputs[0].script_sig[0] as usize;
+        let publickey = tx.inputs[0].script_sig[signature_end + 2..].to_vec();
+
+        for sighash_type in [0x00, 0x02, 0x03, 0x81, 0x82, 0x83, 0xff] {
+            // firma DER basura con la public key de la wallet
+            let junk_signature = [0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x01];
+            let mut script_sig = vec![junk_signature.len() as u8 + 1];
+            script_sig.extend(junk_signature);
+            script_sig.push(sighash_type);
+            script_sig.push(publickey.len() as u8);
+            script_sig.extend(&publickey);
+            tx.inputs[0].script_sig = script_sig;
+
+            assert!(
+                matches!(
+                    tx.verify_input_script(0, &script_pubkey),
+                    Err(CustomError::InvalidTransactionScript)
+                ),
+                "{sighash_type:#x}"
+            );
+        }
+    }
+
+    #[test]
+    fn sign_rejects_p2wpkh_wallets() {
+        let wallet = Wallet::new(
+            String::from("test"),
+            String::from("tb1qsjezxn304fhp5aga0efgf6csenn0w50khsvwxp"),
+            String::from("cNpwEsaVLhju18SJowLtdCNaJtvMvqL4jtFLm2FXw7vZjg4sRWvH"),
+            &UTXO::new(String::from("tests"), String::from("test_utxo.bin")).unwrap(),
+        )
+        .unwrap();
+        let script_pubkey = wallet.get_script_pubkey().unwrap();
+
+        let mut tx = two_inputs_transaction();
+        assert!(matches!(
+            tx.sign(&wallet, &[script_pubkey.clone(), script_pubkey]),
+            Err(CustomError::CannotSignTx)
+        ));
+    }
+
+    #[test]
+    fn relayed_tx_over_the_input_and_output_limits_is_rejected() {
+        let mut tx = two_inputs_transaction();
+        assert!(tx.check_relay_limits(2).is_ok());
+
+        let Err(CustomError::TransactionOverRelayLimit(limit)) = tx.check_relay_limits(1) else { panic!("expected TransactionOverRelayLimit") };
+        assert_eq!(limit, "2 inputs, limit 1");
+
+        tx.inputs.truncate(1);
+        tx.outputs = vec![tx.outputs[0].clone(); 3];
+        let Err(CustomError::TransactionOverRelayLimit(limit)) = tx.check_relay_limits(2) else { panic!("expected TransactionOverRelayLimit") };
+        assert_eq!(limit, "3 outputs, limit 2");
+
+        tx.outputs[0].script_pubkey = vec![0; MAX_RELAY_TX_SIZE];
+        let Err(CustomError::TransactionOverRelayLimit(limit)) =
+            tx.check_relay_limits(DEFAULT_MAX_RELAY_TX_IO)
+        else {
+            panic!("expected TransactionOverRelayLimit")
+        };
+        assert!(limit.ends_with("bytes, limit 400000"));
+    }
+
+    #[test]
+    fn oversized_relayed_tx_is_skipped_without_loading_it() {
+        // header que anuncia un payload de 256 MB, que no se llega a reservar en memoria
+        let payload_size = 256 * 1024 * 1024_u32;
+        let mut header = MessageHeader::from_payload("tx".to_string(), &[]).serialize();
+        header[16..20].copy_from_slice(&payload_size.to_le_bytes());
+        let next_tx = two_inputs_transaction();
+        let mut stream = std::io::repeat(0)
+            .take(u64::from(payload_size))
+            .chain(std::io::Cursor::new(next_tx.serialize()));
+
+        let oversized = MessageHeader::parse(header.try_into().unwrap()).unwrap();
+        let Err(CustomError::TransactionOverRelayLimit(limit)) =
+            Transaction::read_relayed(&mut stream, &oversized)
+        else {
+            panic!("expected TransactionOverRelayLimit")
+        };
+        assert_eq!(
+            limit,
+            format!("{payload_size} bytes, limit {MAX_RELAY_TX_SIZE}")
+        );
+
+        // el stream queda al comienzo de la transaccion siguiente
+        let header = MessageHeader::new(&next_tx);
+        let relayed = Transaction::read_relayed(&mut stream, &header).unwrap();
+        assert_eq!(relayed.hash(), next_tx.hash());
     }
 }
@@ -4,6 +4,13 @@ use crate::error::CustomError;
 use crate::message::Message;
 use crate::parser::BufferParser;
 
+/// User agent con el que el nodo se presenta si el config no indica USER_AGENT.
+pub const DEFAULT_USER_AGENT: &str = "/btc-node-rs:0.1/";
+
+/// Largo maximo en bytes del user agent, para que su largo entre en el unico byte que se serializa
+/// (un compact size de un byte llega hasta 252).
+pub const MAX_USER_AGENT_LENGTH: usize = 252;
+
 #[derive(PartialEq, Debug)]
 /// Crea una estructura para el mensaje de versión con los campos necesarios de acuerdo con el protocolo de Bitcoin.
 /// Los campos son:
@@ -16,7 +23,7 @@ use crate::parser::BufferParser;
 /// - sender_address: que indica la socket v6 del nodo que envía el mensaje.
 /// - nonce: que indica un número aleatorio que se utiliza para detectar conexiones a sí mismo.
 /// - user_agent: que indica el software que utiliza el nodo que envía el mensaje, puede ser vacío.
-/// - user_agent_length: que indica la longitud del campo user_agent. Si es 0, el campo user_agent no se incluye.
+/// - user_agent_length: que indica la longitud del campo user_agent, como maximo MAX_USER_AGENT_LENGTH. Si es 0, el campo user_agent no se incluye.
 /// - start_height: que indica el tamaño de la blockchain del nodo que envía el mensaje.
 pub struct Version {
     pub version: i32,
@@ -35,13 +42,14 @@ pub struct Version {
 impl Version {
     /// Crea un nuevo mensaje de versión a partir de un nodo transmisor y una dirección IPv6 del nodo que recibe el mensaje.
     /// El campo user_agent se inicializa con un string vacío y el campo user_agent_length con 0.
-    /// El campo nonce se inicializa con 0.
+    /// El campo nonce identifica al nodo transmisor, para que detecte si se conecto a si mismo.
     /// El campo start_height se inicializa con 0.
     pub fn new(
         receiver_address: SocketAddrV6,
         sender_address: SocketAddrV6,
         version: i32,
         services: u64,
+        nonce: u64,
     ) -> Self {
         Version {
             version,
@@ -51,12 +59,25 @@ impl Version {
             receiver_address,
             sender_services: services,
             sender_address,
-            nonce: 0x00,
+            nonce,
             user_agent: String::from(""),
             user_agent_length: 0x00,
             start_height: 0x00,
         }
     }
+
+    /// Devuelve el mensaje con el user agent recibido, recortado a MAX_USER_AGENT_LENGTH bytes
+    /// sin partir ningun caracter.
+    #[must_use]
+    pub fn with_user_agent(mut self, user_agent: &str) -> Self {
+        let mut length = user_agent.len().min(MAX_USER_AGENT_LENGTH);
+        while !user_agent.is_char_boundary(length) {
+            length -= 1;
+        }
+        self.user_agent = user_agent[..length].to_string();
+        self.user_agent_length = length as u8;
+        self
+    }
 }
 
 /// Implementa el trait Message para el mensaje de versión.
@@ -109,6 +130,9 @@ impl Message for Version {
         let sender_address = parser.extract_address()?;
         let nonce = parser.extract_u64()?;
         let user_agent_length = parser.extract_u8()?;
+        if user_agent_length as usize > MAX_USER_AGENT_LENGTH {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
         let user_agent = parser.extract_string(user_agent_length as usize)?;
         let start_height = parser.extract_i32()?;
 
@@ -139,13 +163,53 @@ mod tests {
     fn create_version_message() -> Result<(), CustomError> {
         let sender_address = SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), 4321, 0, 0);
         let receiver_address = SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), 1234, 0, 0);
-        let version: Version = Version::new(receiver_address, sender_address, 7000, 0x00);
+        let version: Version = Version::new(receiver_address, sender_address, 7000, 0x00, 0);
         let buffer = version.serialize();
         let parsed_version = Version::parse(buffer)?;
         assert_eq!(version, parsed_version);
         Ok(())
     }
 
+    #[test]
+    fn user_agent_round_trips_through_version() -> Result<(), CustomError> {
+        let address = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 18333, 0, 0);
+        let version =
+            Version::new(address, address, 70015, 0x00, 1).with_user_agent(DEFAULT_USER_AGENT);
+        assert_eq!(version.user_agent_length as usize, DEFAULT_USER_AGENT.len());
+
+        let parsed_version = Version::parse(version.serialize())?;
+        assert_eq!(parsed_version.user_agent, DEFAULT_USER_AGENT);
+        assert_eq!(parsed_version, version);
+        Ok(())
+    }
+
+    #[test]
+    fn user_agent_is_truncated_to_the_length_limit() -> Result<(), CustomError> {
+        let address = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 18333, 0, 0);
+        // con la 'a' inicial el byte MAX_USER_AGENT_LENGTH cae en medio de una 'ñ', que se descarta entera
+        let long_agent = format!("a{}", "ñ".repeat(MAX_USER_AGENT_LENGTH));
+        let version = Version::new(address, address, 70015, 0x00, 1).with_user_agent(&long_agent);
+        assert_eq!(version.user_agent.len(), MAX_USER_AGENT_LENGTH - 1);
+        assert_eq!(
+            version.user_agent_length as usize,
+            MAX_USER_AGENT_LENGTH - 1
+        );
+
+        let parsed_version = Version::parse(version.serialize())?;
+        assert_eq!(parsed_version.user_agent, version.user_agent);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_rejects_user_agent_longer_than_the_limit() {
+        let address = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 18333, 0, 0);
+        let mut buffer = Version::new(address, address, 70015, 0x00, 1).serialize();
+        // el largo del user agent esta despues de version, servicios, timestamp, direcciones y nonce
+        buffer[80] = 0xfd;
+        buffer.extend(vec![b'a'; 0xfd]);
+        assert!(Version::parse(buffer).is_err());
+    }
+
     #[test]
     fn parse_invalid_version() {
         let buffer_too_short = vec![
@@ -0,0 +1,500 @@
+use std::{cmp::Ordering, collections::HashSet};
+
+use crate::{
+    error::CustomError,
+    message::Message,
+    messages::{
+        block::{block_subsidy, calculate_merkle_root, Block},
+        transaction::Transaction,
+    },
+    structs::{
+        block_header::BlockHeader, outpoint::OutPoint, tx_input::TransactionInput,
+        tx_output::TransactionOutput,
+    },
+};
+
+/// Bits de la dificultad de regtest, la minima: aproximadamente la mitad de los nonces cumplen la proof of work.
+/// Es la dificultad de los bloques que arma el nodo y la unica que acepta submit_block.
+pub const REGTEST_BITS: u32 = 0x207fffff;
+
+/// Version de los bloques que arma el nodo (BIP 9, sin señalizar ningun soft fork).
+pub const TEMPLATE_BLOCK_VERSION: i32 = 0x20000000;
+
+/// Tamaño maximo de un bloque serializado, en bytes.
+pub const MAX_BLOCK_SIZE: usize = 1_000_000;
+
+/// Cantidad de bloques anteriores con cuyos timestamps se calcula el median time past.
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Segundos que el timestamp de un bloque puede adelantarse al reloj del nodo.
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+
+/// Maximo ajuste, en segundos, que los relojes de los peers pueden aplicarle al tiempo de red.
+pub const MAX_TIME_ADJUSTMENT: i64 = 70 * 60;
+
+/// Cantidad minima de peers con los que se ajusta el tiempo de red, con menos se usa el reloj del nodo.
+pub const MIN_TIME_SAMPLES: usize = 5;
+
+/// Bytes del bloque que se reservan para el header, el contador de transacciones y la coinbase.
+const COINBASE_RESERVED_SIZE: usize = 1000;
+
+#[derive(Debug, Clone)]
+/// TemplateCandidate es una pending tx que puede incluirse en un BlockTemplate.
+/// Los elementos son:
+/// - transaction: La transaccion.
+/// - fee: Fee que paga la transaccion, en satoshis.
+/// - size: Tamaño de la transaccion serializada, en bytes.
+pub struct TemplateCandidate {
+    pub transaction: Transaction,
+    pub fee: u64,
+    pub size: usize,
+}
+
+impl TemplateCandidate {
+    /// Crea el candidato de una transaccion que paga fee.
+    pub fn new(transaction: Transaction, fee: u64) -> Self {
+        let size = transaction.serialize().len();
+        Self {
+            transaction,
+            fee,
+            size,
+        }
+    }
+
+    /// Compara el fee por byte de dos candidatos sin redondear, devuelve Greater si self paga mas por byte.
+    fn cmp_fee_rate(&self, other: &Self) -> Ordering {
+        (self.fee as u128 * other.size as u128).cmp(&(other.fee as u128 * self.size as u128))
+    }
+}
+
+#[derive(Debug, Clone)]
+/// BlockTemplate es un bloque candidato sobre la punta de la cadena, al que solo le falta encontrar el nonce (ver mine_block).
+/// Los elementos son:
+/// - version: Version del bloque.
+/// - prev_block_hash: Hash del ultimo header de la cadena.
+/// - height: Altura del bloque.
+/// - timestamp: Timestamp del bloque, posterior al median time past de los bloques anteriores.
+/// - bits: Dificultad del bloque (REGTEST_BITS).
+/// - coinbase: Coinbase del bloque, paga el subsidio de height mas las fees.
+/// - transactions: Pending txs incluidas, sin la coinbase, ordenadas para que cada una vaya despues de las que gasta.
+/// - fees: Suma de las fees de las transacciones incluidas.
+pub struct BlockTemplate {
+    pub version: i32,
+    pub prev_block_hash: Vec<u8>,
+    pub height: usize,
+    pub timestamp: u32,
+    pub bits: u32,
+    pub coinbase: Transaction,
+    pub transactions: Vec<Transaction>,
+    pub fees: u64,
+}
+
+impl BlockTemplate {
+    /// Arma el template del bloque de altura height sobre prev_block_hash, con una coinbase que le paga a script_pubkey
+    /// y las transacciones de candidates que entran en el bloque (ver select_transactions).
+    pub fn new(
+        prev_block_hash: Vec<u8>,
+        height: usize,
+        timestamp: u32,
+        script_pubkey: Vec<u8>,
+        candidates: Vec<TemplateCandidate>,
+    ) -> Self {
+        let (transactions, fees) =
+            select_transactions(candidates, MAX_BLOCK_SIZE - COINBASE_RESERVED_SIZE);
+        let coinbase = coinbase_transaction(
+            height,
+            block_subsidy(height).saturating_add(fees),
+            script_pubkey,
+        );
+        Self {
+            version: TEMPLATE_BLOCK_VERSION,
+            prev_block_hash,
+            height,
+            timestamp,
+            bits: REGTEST_BITS,
+            coinbase,
+            transactions,
+            fees,
+        }
+    }
+
+    /// Devuelve las transacciones del bloque, empezando por la coinbase.
+    pub fn block_transactions(&self) -> Vec<Transaction> {
+        let mut transactions = vec![self.coinbase.clone()];
+        transactions.extend(self.transactions.iter().cloned());
+        transactions
+    }
+}
+
+/// Crea la coinbase del bloque de altura height, que le paga value a script_pubkey.
+/// Su script_sig empieza con la altura (BIP 34), por lo que las coinbases de distintos bloques nunca tienen el mismo hash.
+pub fn coinbase_transaction(height: usize, value: u64, script_pubkey: Vec<u8>) -> Transaction {
+    Transaction {
+        version: 1,
+        inputs: vec![TransactionInput {
+            previous_output: OutPoint {
+                hash: vec![0; 32],
+                index: u32::MAX,
+            },
+            script_sig: height_script(height),
+            sequence: u32::MAX,
+        }],
+        outputs: vec![TransactionOutput {
+            value,
+            script_pubkey,
+        }],
+        lock_time: 0,
+    }
+}
+
+/// Devuelve el script que pushea la altura como un numero de script: little endian, con los bytes minimos
+/// y un byte 0 extra si el ultimo tiene el bit de signo.
+fn height_script(height: usize) -> Vec<u8> {
+    let mut number = height.to_le_bytes().to_vec();
+    while number.len() > 1 && number[number.len() - 1] == 0 {
+        number.pop();
+    }
+    if number[number.len() - 1] & 0x80 != 0 {
+        number.push(0);
+    }
+    let mut script = vec![number.len() as u8];
+    script.extend(number);
+    script
+}
+
+/// Elige las transacciones de un bloque de hasta max_size bytes, de mayor a menor fee por byte.
+/// Una transaccion que gasta outputs de otro candidato solo se incluye despues de el, y las que gastan
+/// un output que ya gasto otra transaccion incluida se descartan. Devuelve las transacciones y la suma de sus fees.
+pub fn select_transactions(
+    mut candidates: Vec<TemplateCandidate>,
+    max_size: usize,
+) -> (Vec<Transaction>, u64) {
+    candidates.sort_by(|a, b| b.cmp_fee_rate(a));
+    let candidate_hashes: HashSet<Vec<u8>> = candidates
+        .iter()
+        .map(|candidate| candidate.transaction.hash())
+        .collect();
+
+    let mut selected = vec![];
+    let mut included: HashSet<Vec<u8>> = HashSet::new();
+    let mut spent: HashSet<OutPoint> = HashSet::new();
+    let mut size = 0;
+    let mut fees: u64 = 0;
+    // cada pasada puede habilitar a los hijos de las transacciones que agrego
+    loop {
+        let mut waiting = vec![];
+        let mut added = false;
+        for candidate in candidates {
+            let inputs = &candidate.transaction.inputs;
+            let conflicts = inputs
+                .iter()
+                .any(|input| spent.contains(&input.previous_output));
+            if conflicts || size + candidate.size > max_size {
+                continue;
+            }
+            let parents_included = inputs.iter().all(|input| {
+                let parent = &input.previous_output.hash;
+                !candidate_hashes.contains(parent) || included.contains(parent)
+            });
+            if !parents_included {
+                waiting.push(candidate);
+                continue;
+            }
+
+            spent.extend(inputs.iter().map(|input| input.previous_output.clone()));
+            included.insert(candidate.transaction.hash());
+            size += candidate.size;
+            fees = fees.saturating_add(candidate.fee);
+            selected.push(candidate.transaction);
+            added = true;
+        }
+        if !added || waiting.is_empty() {
+            break;
+        }
+        candidates = waiting;
+    }
+    (selected, fees)
+}
+
+/// Busca un nonce con el que el header del template cumpla la proof of work, probando hasta max_iterations nonces.
+/// Devuelve el bloque minado, o None si ninguno de los nonces probados sirve.
+/// Solo es practico con la dificultad de regtest, por ejemplo para minar bloques en los tests.
+pub fn mine_block(template: &BlockTemplate, max_iterations: u32) -> Option<Block> {
+    let transactions = template.block_transactions();
+    let merkle_root = calculate_merkle_root(&transactions)?;
+    let header = mine_header(
+        template.version,
+        &template.prev_block_hash,
+        &merkle_root,
+        template.timestamp,
+        template.bits,
+        max_iterations,
+    )?;
+    Some(Block::new(header, transactions))
+}
+
+/// Busca, desde el nonce 0 y probando hasta max_iterations nonces, uno con el que el header cumpla la proof of work de bits.
+/// Devuelve el header minado, o None si ninguno de los nonces probados sirve.
+pub fn mine_header(
+    version: i32,
+    prev_block_hash: &[u8],
+    merkle_root: &[u8],
+    timestamp: u32,
+    bits: u32,
+    max_iterations: u32,
+) -> Option<BlockHeader> {
+    (0..max_iterations).find_map(|nonce| {
+        let mut buffer = vec![];
+        buffer.extend(version.to_le_bytes());
+        buffer.extend(prev_block_hash);
+        buffer.extend(merkle_root);
+        buffer.extend(timestamp.to_le_bytes());
+        buffer.extend(bits.to_le_bytes());
+        buffer.extend(nonce.to_le_bytes());
+        BlockHeader::parse(buffer).ok()
+    })
+}
+
+/// Devuelve cuantos segundos ajustar el reloj del nodo para obtener el tiempo de red, a partir de la diferencia con el
+/// reloj de cada peer: la mediana de las diferencias, o 0 si hay menos de MIN_TIME_SAMPLES o la mediana supera
+/// MAX_TIME_ADJUSTMENT (en ese caso es mas probable que esten mal los relojes de los peers que el nuestro).
+pub fn network_time_offset(offsets: &[i64]) -> i64 {
+    if offsets.len() < MIN_TIME_SAMPLES {
+        return 0;
+    }
+    let mut offsets = offsets.to_vec();
+    offsets.sort_unstable();
+    let median = offsets[offsets.len() / 2];
+    match median.abs() <= MAX_TIME_ADJUSTMENT {
+        true => median,
+        false => 0,
+    }
+}
+
+/// Devuelve la mediana de los timestamps de los ultimos bloques (median time past), o 0 si no hay ninguno.
+/// El timestamp de un bloque nuevo tiene que ser mayor a la mediana de los MEDIAN_TIME_SPAN anteriores.
+pub fn median_time_past(timestamps: &[u32]) -> u32 {
+    let mut timestamps = timestamps.to_vec();
+    timestamps.sort_unstable();
+    timestamps.get(timestamps.len() / 2).copied().unwrap_or(0)
+}
+
+/// Verifica la estructura de un bloque, sin mirar las UTXO: que la primera transaccion, y solo esa, sea una coinbase,
+/// que no repita transacciones ni gaste dos veces el mismo output, que no supere MAX_BLOCK_SIZE y que su merkle root
+/// corresponda a sus transacciones.
+/// Devuelve CustomError::InvalidBlock con el motivo, o CustomError::InvalidMerkleRoot.
+pub fn check_block(block: &Block) -> Result<(), CustomError> {
+    let Some(coinbase) = block.transactions.first() else {
+        return Err(CustomError::InvalidBlock(
+            "block has no transactions".to_string(),
+        ));
+    };
+    if !coinbase.is_coinbase() {
+        return Err(CustomError::InvalidBlock(
+            "first transaction is not a coinbase".to_string(),
+        ));
+    }
+
+    let mut tx_hashes = HashSet::new();
+    let mut spent = HashSet::new();
+    for (index, transaction) in block.transactions.iter().enumerate() {
+        if index > 0 && transaction.is_coinbase() {
+            return Err(CustomError::InvalidBlock(
+                "more than one coinbase".to_string(),
+            ));
+        }
+        if transaction.inputs.is_empty() || transaction.outputs.is_empty() {
+            return Err(CustomError::InvalidBlock(
+                "transaction without inputs or outputs".to_string(),
+            ));
+        }
+        if !tx_hashes.insert(transaction.hash()) {
+            return Err(CustomError::InvalidBlock(
+                "duplicate transaction".to_string(),
+            ));
+        }
+        if index > 0
+            && !transaction
+                .inputs
+                .iter()
+                .all(|input| spent.insert(input.previous_output.clone()))
+        {
+            return Err(CustomError::InvalidBlock(
+                "output spent twice in the block".to_string(),
+            ));
+        }
+    }
+
+    if block.serialize().len() > MAX_BLOCK_SIZE {
+        return Err(CustomError::InvalidBlock("block is too large".to_string()));
+    }
+    block.create_merkle_root()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spending(outpoint: OutPoint, value: u64) -> Transaction {
+        Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output: outpoint,
+                script_sig: vec![],
+                sequence: u32::MAX,
+            }],
+            outputs: vec![TransactionOutput {
+                value,
+                script_pubkey: vec![0x51],
+            }],
+            lock_time: 0,
+        }
+    }
+
+    fn hashes(transactions: &[Transaction]) -> Vec<Vec<u8>> {
+        transactions.iter().map(Transaction::hash).collect()
+    }
+
+    fn outpoint(byte: u8) -> OutPoint {
+        OutPoint {
+            hash: vec![byte; 32],
+            index: 0,
+        }
+    }
+
+    fn child_of(parent: &Transaction, value: u64) -> Transaction {
+        spending(
+            OutPoint {
+                hash: parent.hash(),
+                index: 0,
+            },
+            value,
+        )
+    }
+
+    #[test]
+    fn coinbase_script_starts_with_the_height() {
+        assert_eq!(height_script(1), vec![1, 1]);
+        assert_eq!(height_script(128), vec![2, 128, 0]);
+        assert_eq!(height_script(500_000), vec![3, 0x20, 0xa1, 0x07]);
+
+        let coinbase = coinbase_transaction(5, 1000, vec![0x51]);
+        assert!(coinbase.is_coinbase());
+        assert_ne!(
+            coinbase.hash(),
+            coinbase_transaction(6, 1000, vec![0x51]).hash()
+        );
+    }
+
+    #[test]
+    fn transactions_are_selected_by_fee_rate_after_their_parents() {
+        let low = spending(outpoint(1), 1000);
+        let high = spending(outpoint(2), 1000);
+        let parent = spending(outpoint(3), 1000);
+        let child = child_of(&parent, 500);
+        let conflict = spending(outpoint(2), 900);
+
+        let candidates = vec![
+            TemplateCandidate::new(low.clone(), 10),
+            TemplateCandidate::new(child.clone(), 5000),
+            TemplateCandidate::new(high.clone(), 3000),
+            TemplateCandidate::new(conflict, 100),
+            TemplateCandidate::new(parent.clone(), 20),
+        ];
+        let (selected, fees) = select_transactions(candidates.clone(), usize::MAX);
+        assert_eq!(
+            hashes(&selected),
+            hashes(&[high.clone(), parent, low, child])
+        );
+        assert_eq!(fees, 3000 + 20 + 10 + 5000);
+
+        // solo entra la de mayor fee por byte
+        let size = candidates[0].size;
+        let (selected, fees) = select_transactions(candidates, size);
+        assert_eq!(hashes(&selected), hashes(&[high]));
+        assert_eq!(fees, 3000);
+    }
+
+    #[test]
+    fn template_pays_subsidy_and_fees_and_can_be_mined() {
+        let transaction = spending(outpoint(1), 1000);
+        let template = BlockTemplate::new(
+            vec![7; 32],
+            3,
+            1_700_000_000,
+            vec![0x51],
+            vec![TemplateCandidate::new(transaction.clone(), 250)],
+        );
+        assert_eq!(template.fees, 250);
+        assert_eq!(template.coinbase.outputs[0].value, block_subsidy(3) + 250);
+        assert_eq!(hashes(&template.transactions), hashes(&[transaction]));
+
+        let block = mine_block(&template, 1000).unwrap();
+        assert_eq!(block.header.prev_block_hash, vec![7; 32]);
+        assert_eq!(block.header.bits, REGTEST_BITS);
+        block.header.verify_proof_of_work().unwrap();
+        check_block(&block).unwrap();
+    }
+
+    #[test]
+    fn median_time_past_is_the_middle_timestamp() {
+        assert_eq!(median_time_past(&[]), 0);
+        assert_eq!(median_time_past(&[5, 1, 3]), 3);
+        assert_eq!(median_time_past(&[10, 40, 20, 30]), 30);
+    }
+
+    #[test]
+    fn network_time_offset_is_the_bounded_median_of_the_peers() {
+        assert_eq!(network_time_offset(&[]), 0);
+        assert_eq!(network_time_offset(&[60, 60, 60, 60]), 0);
+        assert_eq!(network_time_offset(&[-30, 5, 90, 60, 10]), 10);
+        assert_eq!(network_time_offset(&[-5, 0, 3000, 4000, 4500, 5000]), 4000);
+        let far_ahead = [MAX_TIME_ADJUSTMENT + 1; MIN_TIME_SAMPLES];
+        assert_eq!(network_time_offset(&far_ahead), 0);
+    }
+
+    #[test]
+    fn invalid_block_structures_are_rejected() {
+        let template = BlockTemplate::new(vec![7; 32], 1, 1_700_000_000, vec![0x51], vec![]);
+        let block = mine_block(&template, 1000).unwrap();
+
+        let without_coinbase = Block::new(block.header.clone(), vec![spending(outpoint(1), 5)]);
+        let transaction = spending(outpoint(1), 5);
+        let duplicated = Block::new(
+            block.header.clone(),
+            vec![
+                template.coinbase.clone(),
+                transaction.clone(),
+                transaction.clone(),
+            ],
+        );
+        let double_spend = Block::new(
+            block.header.clone(),
+            vec![
+                template.coinbase.clone(),
+                transaction,
+                spending(outpoint(1), 4),
+            ],
+        );
+        for invalid in [
+            Block::new(block.header.clone(), vec![]),
+            without_coinbase,
+            duplicated,
+            double_spend,
+        ] {
+            assert!(matches!(
+                check_block(&invalid),
+                Err(CustomError::InvalidBlock(_))
+            ));
+        }
+
+        let wrong_merkle_root = Block::new(
+            block.header.clone(),
+            vec![coinbase_transaction(2, 5, vec![0x51])],
+        );
+        assert!(matches!(
+            check_block(&wrong_merkle_root),
+            Err(CustomError::InvalidMerkleRoot)
+        ));
+    }
+}
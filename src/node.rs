@@ -2,6 +2,7 @@ use std::{
     net::{Ipv6Addr, SocketAddr, SocketAddrV6},
     sync::{mpsc, Arc, Mutex},
     thread::{self, JoinHandle},
+    time::{Duration, Instant},
     vec::IntoIter,
 };
 
@@ -13,62 +14,125 @@ use crate::{
     gui::init::GUIEvents,
     logger::{send_log, Log, Logger},
     loops::{
+        block_metadata_task::{block_metadata_task, BLOCK_METADATA_INTERVAL, BLOCK_METADATA_TASK},
+        maintenance_loop::{maintenance_loop, Scheduler},
         node_action_loop::{NodeAction, NodeActionLoop},
-        peer_action_loop::PeerAction,
-        pending_blocks_loop::pending_blocks_loop,
+        peer_action_loop::{peer_action_channel, PeerAction, PeerActionReceiver, PeerActionSender},
+        peer_actions_watchdog_task::{
+            peer_actions_watchdog_task, PEER_ACTIONS_WATCHDOG_INTERVAL, PEER_ACTIONS_WATCHDOG_TASK,
+        },
+        pending_blocks_task::{pending_blocks_task, PENDING_BLOCKS_TASK},
+        ping_loop::ping_loop,
+        reconnect_loop::ReconnectLoop,
         tcp_listener_loop::TcpListenerLoop,
+        tx_propagation_loop::tx_propagation_loop,
+        webhook_loop::webhook_loop,
     },
     node_state::NodeState,
-    peer::{request_headers, Peer},
+    peer::{request_headers, DisconnectEvent, DisconnectReason, Peer},
+    private_network::PrivateNetwork,
+    rng::random_nonce,
+    states::pending_blocks_state::IDLE_PENDING_BLOCKS_POLL,
+    utils::get_address_v6,
+    webhooks::{Webhook, MAX_WEBHOOK_QUEUE},
 };
 
 /// Node es la estructura que representa nuestro nodo.
 /// Los elementos son:
-/// - address: Direccion del nodo.
-/// - services: Servicios que ofrece el nodo.
+/// - address: Direccion del nodo, con puerto 0 si no acepta conexiones entrantes.
+/// - services: Servicios que ofrece el nodo, derivados del config con Config::services.
 /// - version: Version del nodo.
-/// - client_only: Indica si el nodo es completo o solo cliente.
+/// - nonce: Nonce aleatorio con el que el nodo se presenta en el mensaje version, para detectar conexiones a si mismo.
+///   Se genera una vez por sesion y es el mismo para todos los peers.
+/// - user_agent: User agent con el que el nodo se presenta en el mensaje version (ver Config::user_agent).
+/// - listen: Indica si el nodo acepta conexiones entrantes (ver Config::listens).
 /// - logger_sender: Sender para enviar logs al logger.
 /// - peer_action_sender: Sender para enviar acciones al los peers.
-/// - peer_action_receiver: Receiver para recibir acciones del peer.
+/// - peer_action_receiver: Cola compartida de la que los peers toman sus acciones.
 /// - node_action_sender: Sender para enviar acciones al nodo.
 /// - node_action_receiver: Receiver para recibir acciones del nodo.
 /// - tcp_listener_thread: Thread del loop para atender conexiones entrantes a este nodo.
 /// - node_state_ref: Referencia al estado del nodo.
 /// - npeers: Cantidad de peers.
+/// - ping_interval: Tiempo entre los pings enviados a cada peer.
+/// - ping_timeout: Tiempo que tiene un peer para responder un ping.
+/// - pending_blocks_poll: Tiempo maximo entre revisiones de los bloques pendientes mientras hay alguno.
+/// - tx_echo_timeout: Tiempo que se espera a que otro peer anuncie una transaccion que creamos antes de reenviarla.
+/// - private_network: Red privada con la que se autentican las conexiones (ver PrivateNetwork).
+/// - webhook: Webhook al que se envian los eventos de las wallets, si el config indica WEBHOOK_URL.
 pub struct Node {
     pub address: SocketAddrV6,
     pub services: u64,
     pub version: i32,
-    client_only: bool,
+    pub nonce: u64,
+    pub user_agent: String,
+    listen: bool,
     logger_sender: mpsc::Sender<Log>,
-    peer_action_sender: mpsc::Sender<PeerAction>,
-    peer_action_receiver: Arc<Mutex<mpsc::Receiver<PeerAction>>>,
+    peer_action_sender: PeerActionSender,
+    peer_action_receiver: PeerActionReceiver,
     pub node_action_sender: mpsc::Sender<NodeAction>,
     node_action_receiver: Option<mpsc::Receiver<NodeAction>>,
     tcp_listener_thread: Option<thread::JoinHandle<Result<(), CustomError>>>,
     node_state_ref: Arc<Mutex<NodeState>>,
     npeers: u8,
+    ping_interval: Duration,
+    ping_timeout: Duration,
+    pending_blocks_poll: Duration,
+    tx_echo_timeout: Duration,
+    private_network: PrivateNetwork,
+    webhook: Option<Webhook>,
 }
 
 impl Node {
     /// Inicializa el nodo.
     /// Crea los channels necesarios para la comunicacion con los peers y el logger.
+    /// Loguea las advertencias del config, como los valores que se contradicen.
     pub fn new(
         config: &Config,
         logger: &Logger,
         node_state_ref: Arc<Mutex<NodeState>>,
     ) -> Result<Self, CustomError> {
         let logger_sender = logger.get_sender();
-        let (peer_action_sender, receiver) = mpsc::channel();
-        let peer_action_receiver = Arc::new(Mutex::new(receiver));
+        for warning in config.warnings() {
+            send_log(&logger_sender, Log::Warning(warning));
+        }
+
+        let (peer_action_sender, peer_action_receiver) = peer_action_channel();
         let (node_action_sender, node_action_receiver) = mpsc::channel();
 
+        let mut node_state = node_state_ref.lock()?;
+        node_state.set_dust_threshold(config.dust_threshold);
+        node_state.set_min_spend_confirmations(config.min_spend_confirmations)?;
+        node_state.set_alert_threshold(config.alert_threshold_sats);
+        node_state.set_stale_block_timeout(Duration::from_secs(config.stale_block_timeout_secs))?;
+        node_state.set_max_not_found_retries(config.max_notfound_retries)?;
+        node_state.set_broadcast_peers(config.broadcast_peers);
+        node_state.set_max_relay_tx_io(config.max_relay_tx_io);
+        node_state.set_rebroadcast_blocks(config.rebroadcast_blocks);
+        node_state.set_xpub_gap_limit(config.xpub_gap_limit);
+        node_state.enable_rebroadcasts(node_action_sender.clone());
+        node_state.set_utxo_snapshot_interval(config.utxo_snapshot_interval);
+        node_state.set_spv_mode(config.spv_mode);
+        node_state.set_regtest(config.regtest);
+        node_state.set_price_source(config.price_source.clone());
+        if config.webhook_url.is_some() {
+            node_state.enable_webhooks(MAX_WEBHOOK_QUEUE);
+        }
+        drop(node_state);
+
+        // el puerto 0 en el mensaje version indica que no aceptamos conexiones entrantes
+        let listen_port = match config.listens() {
+            true => config.port,
+            false => 0,
+        };
+
         let node = Self {
-            address: SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), config.port, 0, 0),
-            services: 0x00,
+            address: SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), listen_port, 0, 0),
+            services: config.services(),
             version: config.protocol_version,
-            client_only: config.client_only,
+            nonce: random_nonce(),
+            user_agent: config.user_agent.clone(),
+            listen: config.listens(),
             logger_sender,
             peer_action_sender,
             peer_action_receiver,
@@ -76,6 +140,15 @@ impl Node {
             node_action_receiver: Some(node_action_receiver),
             tcp_listener_thread: None,
             npeers: config.npeers,
+            ping_interval: Duration::from_secs(config.ping_interval),
+            ping_timeout: Duration::from_secs(config.ping_timeout),
+            pending_blocks_poll: Duration::from_secs(config.pending_blocks_poll_secs),
+            tx_echo_timeout: Duration::from_secs(config.tx_echo_timeout),
+            private_network: config.private_network(),
+            webhook: config
+                .webhook_url
+                .clone()
+                .map(|url| Webhook::new(url, config.webhook_secret.clone(), &config.store_path)),
             node_state_ref,
         };
 
@@ -83,8 +156,12 @@ impl Node {
     }
 
     /// Inicializa el nodo en un thread.
-    /// Comienza el thread de pending_blocks_loop.
+    /// Comienza el thread de maintenance_loop, con las tareas periodicas del nodo (ver initialize_maintenance_loop).
+    /// Comienza el thread de ping_loop.
+    /// Comienza el thread de webhook_loop, si el config indica WEBHOOK_URL.
+    /// Comienza el thread de tx_propagation_loop.
     /// Comienza el thread de tcp_listener_loop.
+    /// Se conecta a los peers y comienza el thread de reconnect_loop, que reemplaza a los que se desconecten.
     /// Comienza la descarga de headers.
     /// Comienza el thread de node_action_loop.
     pub fn spawn(
@@ -92,13 +169,21 @@ impl Node {
         addresses: IntoIter<SocketAddr>,
         gui_sender: glib::Sender<GUIEvents>,
     ) -> JoinHandle<Result<(), CustomError>> {
-        self.initialize_pending_blocks_loop();
+        if let Err(error) = self.initialize_maintenance_loop() {
+            send_log(&self.logger_sender, Log::Error(error));
+        }
+        self.initialize_ping_loop();
+        self.initialize_webhook_loop();
+        self.initialize_tx_propagation_loop();
         self.initialize_tcp_listener_loop();
 
         thread::spawn(move || -> Result<(), CustomError> {
             if let Err(error) = self.connect(addresses, self.npeers) {
                 send_log(&self.logger_sender, Log::Error(error));
             }
+            if let Err(error) = self.initialize_reconnect_loop() {
+                send_log(&self.logger_sender, Log::Error(error));
+            }
             if let Err(error) = self.initialize_ibd() {
                 send_log(&self.logger_sender, Log::Error(error));
             }
@@ -109,72 +194,232 @@ impl Node {
         })
     }
 
+    /// Se conecta a number_of_peers direcciones: primero las de la red privada, despues las del seed y por ultimo
+    /// las guardadas en ejecuciones anteriores, para poder conectarse aunque el seed no responda.
     fn connect(
         &mut self,
         addresses: IntoIter<SocketAddr>,
         mut number_of_peers: u8,
     ) -> Result<(), CustomError> {
+        let seed_addresses: Vec<SocketAddr> = addresses.collect();
+        let seed_count = seed_addresses.len();
+        let mut addresses = self.private_network.peers();
+        for address in seed_addresses {
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+        for known in self.node_state_ref.lock()?.known_peer_addresses() {
+            if !addresses.contains(&known) {
+                addresses.push(known);
+            }
+        }
+
         send_log(
             &self.logger_sender,
             Log::Message(format!(
-                "Handshaking with {} nodes ({} available)",
+                "Handshaking with {} nodes ({} available, {} from the seed)",
                 number_of_peers,
-                addresses.len()
+                addresses.len(),
+                seed_count
             )),
         );
 
         let mut peers = vec![];
+        let mut failed_handshakes = vec![];
+        let mut attempts = vec![];
 
-        for address in addresses {
+        for address in addresses.iter().copied() {
             if number_of_peers == 0 {
                 break;
             }
 
-            match Peer::call(
-                address,
-                self.address,
-                self.services,
-                self.version,
-                self.peer_action_receiver.clone(),
-                self.logger_sender.clone(),
-                self.node_action_sender.clone(),
-            ) {
+            let peer = self.private_network.connect(address).and_then(|stream| {
+                Peer::call(
+                    stream,
+                    self.address,
+                    self.services,
+                    self.version,
+                    self.nonce,
+                    &self.user_agent,
+                    self.peer_action_receiver.clone(),
+                    self.logger_sender.clone(),
+                    self.node_action_sender.clone(),
+                )
+            });
+            match peer {
                 Ok(peer) => {
                     peers.push(peer);
+                    attempts.push((address, true));
                     number_of_peers -= 1;
                 }
-                Err(error) => {
+                Err(CustomError::ConnectedToSelf) => {
+                    attempts.push((address, false));
                     send_log(
                         &self.logger_sender,
-                        Log::Message(format!("Error connecting to peer: {:?}", error)),
+                        Log::Message(format!("Ignoring connection to ourselves: {:?}", address)),
                     );
                 }
+                Err(CustomError::CannotConnectToNode) => {
+                    attempts.push((address, false));
+                    send_log(
+                        &self.logger_sender,
+                        Log::Message(format!("Error connecting to peer: {:?}", address)),
+                    );
+                }
+                Err(_) => {
+                    attempts.push((address, false));
+                    failed_handshakes
+                        .push(DisconnectEvent::handshake_failed(get_address_v6(address)));
+                }
             };
         }
 
+        // si el nodo quedo atrasado respecto de la red, initialize_ibd pide los headers a continuacion
+        let mut node_state = self.node_state_ref.lock()?;
+        node_state.add_peer_addresses(&addresses)?;
+        for (address, connected) in attempts {
+            node_state.record_connection_attempt(address, connected);
+        }
+        for event in failed_handshakes {
+            node_state.record_disconnect(event)?;
+        }
+        node_state.append_peers(peers)?;
+        Ok(())
+    }
+
+    /// Comienza el loop que reemplaza a los peers desconectados, llamando a las direcciones del seed con Peer::call.
+    fn initialize_reconnect_loop(&self) -> Result<(), CustomError> {
+        let (reconnect_sender, reconnect_receiver) = mpsc::channel();
+        let mut node_state = self.node_state_ref.lock()?;
+        node_state.enable_reconnects(reconnect_sender, self.npeers as usize);
+        drop(node_state);
+
+        let address = self.address;
+        let services = self.services;
+        let version = self.version;
+        let nonce = self.nonce;
+        let user_agent = self.user_agent.clone();
+        let peer_action_receiver = self.peer_action_receiver.clone();
+        let logger_sender = self.logger_sender.clone();
+        let node_action_sender = self.node_action_sender.clone();
+        let private_network = self.private_network.clone();
+        ReconnectLoop::spawn(
+            self.logger_sender.clone(),
+            self.node_state_ref.clone(),
+            self.node_action_sender.clone(),
+            reconnect_receiver,
+            move |candidate| {
+                Peer::call(
+                    private_network.connect(candidate)?,
+                    address,
+                    services,
+                    version,
+                    nonce,
+                    &user_agent,
+                    peer_action_receiver.clone(),
+                    logger_sender.clone(),
+                    node_action_sender.clone(),
+                )
+            },
+        );
+        Ok(())
+    }
+
+    /// Registra las tareas periodicas en un Scheduler y comienza el maintenance_loop que las ejecuta:
+    /// - PENDING_BLOCKS_TASK: Vuelve a pedir los bloques pendientes vencidos, cada pending_blocks_poll mientras hay alguno
+    ///   y cada IDLE_PENDING_BLOCKS_POLL si no.
+    /// - BLOCK_METADATA_TASK: Completa de a poco los resumenes de los bloques guardados que no los tienen, cada BLOCK_METADATA_INTERVAL.
+    /// - PEER_ACTIONS_WATCHDOG_TASK: Revisa que los peers sigan tomando las PeerActions, cada PEER_ACTIONS_WATCHDOG_INTERVAL.
+    fn initialize_maintenance_loop(&self) -> Result<(), CustomError> {
+        let mut scheduler = Scheduler::new(true);
+        scheduler.register(
+            PENDING_BLOCKS_TASK,
+            IDLE_PENDING_BLOCKS_POLL,
+            Instant::now(),
+            pending_blocks_task(
+                self.node_state_ref.clone(),
+                self.logger_sender.clone(),
+                self.pending_blocks_poll,
+            ),
+        );
+        // los bloques pendientes al iniciar nunca se pidieron: se revisan ya y la tarea se adapta desde ahi
+        scheduler.run_now(PENDING_BLOCKS_TASK, Instant::now());
+        scheduler.register(
+            BLOCK_METADATA_TASK,
+            BLOCK_METADATA_INTERVAL,
+            Instant::now(),
+            block_metadata_task(self.node_state_ref.clone(), self.logger_sender.clone()),
+        );
+        scheduler.register(
+            PEER_ACTIONS_WATCHDOG_TASK,
+            PEER_ACTIONS_WATCHDOG_INTERVAL,
+            Instant::now(),
+            peer_actions_watchdog_task(
+                self.node_state_ref.clone(),
+                self.peer_action_receiver.clone(),
+                self.node_action_sender.clone(),
+                self.logger_sender.clone(),
+            ),
+        );
+
+        let (maintenance_sender, maintenance_receiver) = mpsc::channel();
         let mut node_state = self.node_state_ref.lock()?;
-        node_state.append_peers(peers);
+        node_state.watch_pending_blocks(maintenance_sender.clone())?;
+        node_state.enable_maintenance(maintenance_sender);
+        drop(node_state);
+
+        maintenance_loop(
+            scheduler,
+            maintenance_receiver,
+            self.node_state_ref.clone(),
+            self.logger_sender.clone(),
+        );
         Ok(())
     }
 
-    fn initialize_pending_blocks_loop(&self) {
-        pending_blocks_loop(
+    fn initialize_ping_loop(&self) {
+        ping_loop(
             self.node_state_ref.clone(),
-            self.peer_action_sender.clone(),
+            self.node_action_sender.clone(),
             self.logger_sender.clone(),
+            self.ping_interval,
+            self.ping_timeout,
+        );
+    }
+
+    fn initialize_webhook_loop(&self) {
+        if let Some(webhook) = &self.webhook {
+            webhook_loop(
+                self.node_state_ref.clone(),
+                webhook.clone(),
+                self.logger_sender.clone(),
+            );
+        }
+    }
+
+    fn initialize_tx_propagation_loop(&self) {
+        tx_propagation_loop(
+            self.node_state_ref.clone(),
+            self.node_action_sender.clone(),
+            self.logger_sender.clone(),
+            self.tx_echo_timeout,
         );
     }
 
     fn initialize_tcp_listener_loop(&mut self) {
-        if !self.client_only {
+        if self.listen {
             self.tcp_listener_thread = Some(TcpListenerLoop::spawn(
                 self.logger_sender.clone(),
                 self.node_state_ref.clone(),
                 self.address,
                 self.services,
                 self.version,
+                self.nonce,
+                self.user_agent.clone(),
                 self.peer_action_receiver.clone(),
                 self.node_action_sender.clone(),
+                self.private_network.clone(),
             ));
         }
     }
@@ -188,7 +433,7 @@ impl Node {
             request_headers(
                 last_header,
                 self.version,
-                &mut fastest_peer.stream,
+                &fastest_peer.sender(),
                 &self.logger_sender,
                 &self.node_action_sender,
             )?;
@@ -229,21 +474,12 @@ impl Node {
 }
 
 impl Drop for Node {
-    /// Espera a que los threads de PeerActions terminen.
-    /// Estos junto al NodeActionLoop son los únicos threads que modifican el estado del nodo, por lo que hace falta esperarlos.
+    /// Cierra la conexion con todos los peers y espera a que terminen sus threads (ver Peer::stop).
+    /// Los threads de PeerActions junto al NodeActionLoop son los únicos threads que modifican el estado del nodo, por lo que hace falta esperarlos.
+    /// No se encola nada para terminarlos: la cola de PeerActions solo tiene acciones para un unico peer.
     fn drop(&mut self) {
         if let Ok(mut node_state) = self.node_state_ref.lock() {
-            let peers = node_state.get_peers();
-            for _ in peers.iter() {
-                self.peer_action_sender.send(PeerAction::Terminate).unwrap();
-            }
-            for peer in peers {
-                if let Some(thread) = peer.peer_action_thread.take() {
-                    if let Err(error) = thread.join() {
-                        println!("Error joining thread: {:?}", error);
-                    }
-                }
-            }
+            let _ = node_state.remove_all_peers(DisconnectReason::Terminated);
         }
     }
 }
@@ -0,0 +1,317 @@
+use std::{
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use gtk::glib::{self, Priority};
+
+use crate::{
+    config::Config,
+    crash_report::install_panic_hook,
+    error::CustomError,
+    gui::init::GUIEvents,
+    logger::{send_log, Log, Logger},
+    loops::node_action_loop::NodeAction,
+    node::Node,
+    node_state::NodeState,
+    rpc::{RpcCall, RpcServer},
+    store_lock::StoreLock,
+    utils::get_addresses,
+};
+
+const NOTIFIER_POLL: Duration = Duration::from_millis(20);
+const WAIT_POLL: Duration = Duration::from_millis(100);
+
+/// Cantidad maxima de GUIEvents que el HeadlessNotifier guarda sin que se lean con next_event. Los que llegan con
+/// la cola llena se descartan, para que un programa que nunca lee los eventos no acumule memoria sin limite.
+pub const MAX_QUEUED_EVENTS: usize = 1024;
+
+/// NodeHandle es un nodo corriendo, para usar el nodo como biblioteca desde otros programas (ver examples/).
+/// Con start arranca igual que el binario en modo headless y los GUIEvents se reciben con next_event;
+/// el binario lo arranca con start_with, enviando los GUIEvents a la interfaz grafica o a la linea de estado.
+/// Los elementos son:
+/// - node_state_ref: Estado del nodo, compartido con sus threads.
+/// - node_action_sender: Sender para enviarle acciones al nodo.
+/// - logger_sender: Sender para loguear en el log del nodo.
+/// - events: GUIEvents del nodo, reenviados por el HeadlessNotifier (None si se arranco con start_with).
+/// - notifier: HeadlessNotifier que recibe los GUIEvents del nodo (None si se arranco con start_with).
+/// - logger: Logger del nodo, se termina en stop.
+/// - node_thread: Thread del nodo.
+/// - _store_lock: Lock del store, se libera al terminar el NodeHandle.
+pub struct NodeHandle {
+    pub node_state_ref: Arc<Mutex<NodeState>>,
+    pub node_action_sender: mpsc::Sender<NodeAction>,
+    pub logger_sender: mpsc::Sender<Log>,
+    events: Option<mpsc::Receiver<GUIEvents>>,
+    notifier: Option<HeadlessNotifier>,
+    logger: Logger,
+    node_thread: JoinHandle<Result<(), CustomError>>,
+    _store_lock: StoreLock,
+}
+
+impl NodeHandle {
+    /// Lee el config de config_path y arranca el nodo (ver start).
+    pub fn from_config_file(config_path: &str) -> Result<Self, CustomError> {
+        let config = Config::from_file(config_path)?;
+        Self::start(&config)
+    }
+
+    /// Arranca el nodo con el config recibido: toma el lock del store, carga el NodeState y se conecta a los peers
+    /// del seed (o a los guardados si el seed no responde), como el binario.
+    /// Devuelve CustomError::StoreLocked si otro nodo esta usando el store.
+    pub fn start(config: &Config) -> Result<Self, CustomError> {
+        let store_lock = StoreLock::acquire(&config.store_path)?;
+        let (notifier, events) = HeadlessNotifier::spawn()?;
+        let gui_sender = notifier.gui_sender.clone();
+
+        let node = Logger::new(&config.log_file, gui_sender.clone())
+            .and_then(|logger| Self::start_with(config, store_lock, logger, gui_sender, false));
+        match node {
+            Ok(mut node) => {
+                node.events = Some(events);
+                node.notifier = Some(notifier);
+                Ok(node)
+            }
+            Err(error) => {
+                notifier.stop();
+                Err(error)
+            }
+        }
+    }
+
+    /// Arranca el nodo como start, pero con el lock del store y el logger ya creados, y enviando los GUIEvents
+    /// a gui_sender en lugar de a un HeadlessNotifier, por lo que next_event no devuelve ninguno.
+    /// Es como lo arranca el binario, que envia los GUIEvents a la interfaz grafica o a la linea de estado.
+    /// Con panic_hook instala el panic hook del nodo (ver install_panic_hook), que termina el proceso si un thread
+    /// entra en panico: solo debe usarlo el binario.
+    /// Si no puede arrancar loguea el error y termina el logger.
+    pub fn start_with(
+        config: &Config,
+        store_lock: StoreLock,
+        logger: Logger,
+        gui_sender: glib::Sender<GUIEvents>,
+        panic_hook: bool,
+    ) -> Result<Self, CustomError> {
+        let logger_sender = logger.get_sender();
+        send_log(&logger_sender, Log::SetLevel(config.log_level));
+        let node_state_slot = match panic_hook {
+            true => Some(install_panic_hook(
+                config.store_path.clone(),
+                logger.history.clone(),
+            )),
+            false => None,
+        };
+
+        let node = NodeState::new(
+            logger_sender.clone(),
+            gui_sender.clone(),
+            &config.store_path,
+        )
+        .and_then(|node_state_ref| {
+            if let Some(node_state_slot) = node_state_slot {
+                let _ = node_state_slot.set(node_state_ref.clone());
+            }
+            let node = Node::new(config, &logger, node_state_ref.clone())?;
+            Ok((node_state_ref, node))
+        });
+        let (node_state_ref, node) = match node {
+            Ok(node) => node,
+            Err(error) => {
+                send_log(&logger_sender, Log::Error(error.clone()));
+                stop_logger(logger);
+                return Err(error);
+            }
+        };
+        let node_action_sender = node.node_action_sender.clone();
+
+        let addresses = match get_addresses(config.seed.clone(), config.port) {
+            Ok(addresses) => addresses,
+            Err(error) => {
+                send_log(&logger_sender, Log::Error(error));
+                vec![].into_iter()
+            }
+        };
+        let node_thread = node.spawn(addresses, gui_sender);
+
+        Ok(Self {
+            node_state_ref,
+            node_action_sender,
+            logger_sender,
+            events: None,
+            notifier: None,
+            logger,
+            node_thread,
+            _store_lock: store_lock,
+        })
+    }
+
+    /// Inicia el RpcServer en RPC_BIND:RPC_PORT, con las credenciales del config, para manejar el nodo por el RPC
+    /// como en el modo headless del binario.
+    pub fn start_rpc_server(&self, config: &Config) -> Result<(), CustomError> {
+        let access = config.rpc_access()?;
+        let listener = TcpListener::bind((config.rpc_bind, config.rpc_port))?;
+        send_log(
+            &self.logger_sender,
+            Log::Message(format!("RPC listening on {}", listener.local_addr()?)),
+        );
+        let _rpc_thread = RpcServer::spawn(
+            listener,
+            access,
+            self.node_action_sender.clone(),
+            self.logger_sender.clone(),
+        );
+        Ok(())
+    }
+
+    /// Devuelve el proximo GUIEvent del nodo, o None si no llega ninguno en timeout.
+    /// Si el nodo se arranco con start_with los GUIEvents van a su gui_sender y siempre devuelve None.
+    pub fn next_event(&self, timeout: Duration) -> Result<Option<GUIEvents>, CustomError> {
+        let Some(events) = &self.events else {
+            thread::sleep(timeout);
+            return Ok(None);
+        };
+        match events.recv_timeout(timeout) {
+            Ok(event) => Ok(Some(event)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Espera hasta que el estado del nodo cumpla condition, revisandolo periodicamente.
+    /// Devuelve false si no la cumple en timeout.
+    pub fn wait_until(
+        &self,
+        timeout: Duration,
+        condition: impl Fn(&NodeState) -> bool,
+    ) -> Result<bool, CustomError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if condition(&*self.node_state_ref.lock()?) {
+                return Ok(true);
+            }
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            thread::sleep(WAIT_POLL);
+        }
+    }
+
+    /// Ejecuta un pedido del RPC en el NodeActionLoop, igual que el RpcServer, y devuelve su resultado en JSON.
+    /// Si el pedido crea una transaccion, el nodo la envia a los peers antes de responder.
+    pub fn rpc(&self, call: RpcCall) -> Result<String, CustomError> {
+        let (response_sender, response_receiver) = mpsc::channel();
+        self.node_action_sender
+            .send(NodeAction::Rpc((call, response_sender)))?;
+        response_receiver.recv()?
+    }
+
+    /// Termina el nodo: espera a que termine su thread, despues el del logger (escribiendo los logs pendientes)
+    /// y por ultimo el del HeadlessNotifier. Devuelve el error con el que termino el thread del nodo, si hubo uno.
+    pub fn stop(self) -> Result<(), CustomError> {
+        let mut result = Ok(());
+        if self.node_action_sender.send(NodeAction::Terminate).is_ok() {
+            result = match self.node_thread.join() {
+                Ok(result) => result,
+                Err(error) => {
+                    send_log(
+                        &self.logger_sender,
+                        Log::Message(format!("Error closing node thread: {:?}", error)),
+                    );
+                    Ok(())
+                }
+            };
+        }
+        stop_logger(self.logger);
+        if let Some(notifier) = self.notifier {
+            notifier.stop();
+        }
+        result
+    }
+}
+
+/// Termina el logger, esperando a que escriba los logs pendientes.
+fn stop_logger(logger: Logger) {
+    if logger.tx.send(Log::Terminate).is_ok() {
+        let _ = logger.thread.join();
+    }
+}
+
+/// HeadlessNotifier reemplaza a la interfaz grafica como destino de los GUIEvents: los recibe en un MainContext
+/// propio, en su thread, y los reenvia por un channel comun para poder leerlos sin correr un MainLoop.
+/// El channel guarda como maximo MAX_QUEUED_EVENTS eventos sin leer, los demas se descartan.
+/// Los elementos son:
+/// - gui_sender: Sender de los GUIEvents, el que reciben el nodo y el logger.
+/// - stop: Indica al thread que deje de reenviar los eventos.
+/// - thread: Thread que reenvia los eventos.
+struct HeadlessNotifier {
+    gui_sender: glib::Sender<GUIEvents>,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl HeadlessNotifier {
+    /// Comienza el thread que reenvia los eventos y devuelve el receiver por el que llegan.
+    fn spawn() -> Result<(Self, mpsc::Receiver<GUIEvents>), CustomError> {
+        let (events_sender, events_receiver) = mpsc::sync_channel(MAX_QUEUED_EVENTS);
+        let (ready_sender, ready_receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+
+        let thread = thread::spawn(move || {
+            // el receiver de glib se atiende en el thread en el que se crea su MainContext
+            let context = glib::MainContext::new();
+            let (gui_sender, gui_receiver) = glib::MainContext::channel(Priority::default());
+            gui_receiver.attach(Some(&context), move |event| {
+                // si la cola esta llena el evento se descarta, nadie esta leyendo los eventos
+                let _ = events_sender.try_send(event);
+                glib::Continue(true)
+            });
+            if ready_sender.send(gui_sender).is_err() {
+                return;
+            }
+            while !thread_stop.load(Ordering::Relaxed) {
+                while context.iteration(false) {}
+                thread::sleep(NOTIFIER_POLL);
+            }
+        });
+
+        let gui_sender = ready_receiver.recv()?;
+        let notifier = Self {
+            gui_sender,
+            stop,
+            thread,
+        };
+        Ok((notifier, events_receiver))
+    }
+
+    /// Deja de reenviar los eventos y espera a que termine el thread.
+    fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unread_events_are_bounded() -> Result<(), CustomError> {
+        let (notifier, events) = HeadlessNotifier::spawn()?;
+        for _ in 0..MAX_QUEUED_EVENTS + 10 {
+            notifier.gui_sender.send(GUIEvents::NewBlock)?;
+        }
+        // sin leer los eventos, el notifier los reenvia hasta llenar la cola
+        thread::sleep(Duration::from_millis(500));
+        notifier.stop();
+
+        assert_eq!(events.try_iter().count(), MAX_QUEUED_EVENTS);
+        Ok(())
+    }
+}
@@ -1,50 +1,178 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
     fs,
-    net::SocketAddrV6,
+    net::{SocketAddr, SocketAddrV6},
     path::Path,
     sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 use gtk::glib::Sender;
 
 use crate::{
+    bip32::DEFAULT_XPUB_GAP_LIMIT,
+    config::ConfigDelta,
+    console_status::{StatusSnapshot, SyncPhase},
     error::CustomError,
     gui::init::GUIEvents,
     logger::{send_log, Log},
-    messages::{block::Block, get_headers::GetHeaders, headers::Headers, transaction::Transaction},
-    peer::Peer,
+    loops::{
+        maintenance_loop::{MaintenanceAction, TaskStatus},
+        node_action_loop::NodeAction,
+        tx_propagation_loop::DEFAULT_BROADCAST_PEERS,
+    },
+    message::Message,
+    messages::{
+        block::Block,
+        compact_block::{CompactBlock, PartialBlock},
+        filter_load::{FilterClear, FilterLoad},
+        get_blocks::GetBlocks,
+        get_data::GetData,
+        get_headers::GetHeaders,
+        headers::Headers,
+        inv::MAX_INV_ENTRIES,
+        mempool::Mempool,
+        merkle_block::MerkleBlock,
+        reject::{Reject, REJECT_DUPLICATE},
+        transaction::{Transaction, TransactionOptions, DEFAULT_MAX_RELAY_TX_IO},
+    },
+    mining::{
+        check_block, median_time_past, network_time_offset, BlockTemplate, TemplateCandidate,
+        MAX_FUTURE_BLOCK_TIME, MEDIAN_TIME_SPAN, REGTEST_BITS,
+    },
+    peer::{DisconnectEvent, DisconnectReason, Peer, PeerSender, PeerStats, PeerSummary, GENESIS},
+    pricing::{FiatRate, PriceSource, Pricing},
+    rng::random_nonce,
+    send_many::{
+        p2pkh_input_size, plan_transaction, SendManyError, SendManyRequest, SendManyResult,
+        TransactionPlan, DEFAULT_DUST_THRESHOLD, DEFAULT_MIN_SPEND_CONFIRMATIONS,
+        MAX_MIN_SPEND_CONFIRMATIONS,
+    },
+    services::serves_bloom,
     states::{
-        blocks_state::BlocksState,
-        headers_state::HeadersState,
-        pending_blocks_state::PendingBlocks,
-        pending_txs_state::PendingTxs,
-        utxo_state::{UTXOValue, UTXO},
+        block_metadata_state::{BlockMetadata, BlockMetadataState},
+        block_store_state::{BlockReader, BlockStore},
+        blocks_state::{import_block_files, write_store_version, BlocksState, BLOCK_STORE_VERSION},
+        fee_state::{FeeEstimator, MAX_FEE_SAMPLES},
+        headers_state::{DifficultyInfo, HeadersState},
+        inventory_tracker_state::InventoryTracker,
+        orphans_state::{OrphanPool, MAX_ORPHANS},
+        payment_alerts_state::PaymentAlerts,
+        peer_addresses_state::PeerAddressesState,
+        pending_blocks_state::{NotFoundAction, PendingBlocks},
+        pending_txs_state::{BroadcastCheck, PendingTxs, DEFAULT_REBROADCAST_BLOCKS},
+        utxo_state::{UTXOValue, START_DATE_IBD, UTXO},
+        wallet_meta_state::{MovementMeta, MovementView, WalletMetaState},
         wallets_state::WalletsState,
     },
-    structs::{block_header::BlockHeader, movement::Movement, outpoint::OutPoint},
-    wallet::Wallet,
+    structs::{
+        block_header::{display_hash, hash_as_string, BlockHeader},
+        bloom_filter::{BloomFilter, BLOOM_UPDATE_ALL},
+        inventory::{Inventory, InventoryType},
+        movement::Movement,
+        outpoint::OutPoint,
+    },
+    utils::{get_current_timestamp, get_socket_address},
+    utxo_snapshot::UtxoSnapshot,
+    wallet::{get_script_pubkey, pubkey_is_compressed, Wallet},
+    webhooks::{QueuedWebhookEvent, WebhookEvent, WebhookEventKind, WebhookQueue},
 };
 
+/// Margen en segundos que puede tener el timestamp de un bloque respecto de la hora en la que se mino.
+const BLOCK_TIMESTAMP_TOLERANCE: u64 = 2 * 60 * 60;
+
+/// Cantidad de bloques que la altura de la red puede superar a la nuestra antes de considerar que el nodo esta atrasado.
+pub const BEHIND_NETWORK_THRESHOLD: usize = 3;
+
+/// Cantidad de desconexiones de peers que se recuerdan.
+pub const MAX_DISCONNECT_EVENTS: usize = 100;
+
+/// Cantidad maxima de bloques que se piden a un peer en un mismo getdata.
+pub const BLOCKS_PER_GETDATA: usize = 5;
+
+/// Probabilidad de falso positivo del filtro bloom que se carga en los peers en spv_mode.
+pub const BLOOM_FALSE_POSITIVE_RATE: f64 = 0.0005;
+
+/// Tiempo minimo entre los GUIEvents::PeersUpdated por las latencias de los pongs, que llegan de todos los peers a la vez.
+pub const PONG_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
 /// NodeState es una estructura que contiene el estado del nodo.
 /// Los elementos son (para mas informacion de cada una de estas estructuras ver su documentacion en la carpeta states):
 /// - logger_sender: Sender para enviar logs al logger.
 /// - gui_sender: Sender para enviar eventos a la interfaz grafica.
 /// - headers: HeadersState.
 /// - peers: Vector de peers conectados al nodo.
+/// - disconnects: Ultimas MAX_DISCONNECT_EVENTS desconexiones de peers, de la mas antigua a la mas reciente.
+/// - peer_addresses: PeerAddressesState, direcciones del seed DNS y de los mensajes addr para reemplazar a los peers que se desconectan.
+/// - target_peers: Cantidad de peers que el nodo intenta mantener conectados (npeers del config).
+/// - reconnect_sender: Sender para pedir al ReconnectLoop que reemplace a los peers que faltan, si esta activo.
+/// - next_block_peer: Indice del proximo peer al que pedirle bloques, para repartir la descarga entre los peers por turnos.
+/// - last_pong_update: Momento del ultimo GUIEvents::PeersUpdated por un pong (ver peer_pong).
+/// - maintenance_sender: Sender para pedirle acciones al maintenance_loop, si esta activo.
+/// - maintenance_tasks: Estado de las tareas del maintenance_loop en su ultima ejecucion.
 /// - wallets: WalletsState.
+/// - wallet_meta: WalletMetaState, metadatos de los movimientos de las wallets (nombre, fee, notas, si se abandono).
 /// - blocks: BlocksState.
+/// - block_metadata: BlockMetadataState, resumen de cada bloque descargado para mostrarlo sin abrir su archivo.
 /// - utxo: UTXO.
 /// - pending_txs: PendingTxs.
+/// - fee_estimator: FeeEstimator, estima el fee por byte a partir de cuanto tardan en confirmarse las pending txs.
+/// - orphan_txs: Transacciones que gastan un output desconocido mientras hay bloques pendientes, por el outpoint que esperan.
+/// - orphan_blocks: Bloques recibidos antes que su header, por el hash de su bloque anterior.
+/// - catching_up: Indica si el nodo esta atrasado respecto de la red y la esta alcanzando.
+/// - dust_threshold: Valor minimo del cambio de las transacciones que creamos, por debajo se suma al fee.
+/// - min_spend_confirmations: Confirmaciones minimas que necesita una UTXO para usarse como input de las transacciones que creamos.
+/// - broadcast_peers: Cantidad de peers a los que se envian las transacciones que creamos para verificar que se propaguen (0 las envia a todos sin verificar).
+/// - max_relay_tx_io: Cantidad maxima de inputs, y de outputs, de las transacciones que se aceptan de los peers.
+/// - rebroadcast_blocks: Cantidad de bloques sin confirmarse tras los cuales se vuelve a enviar una transaccion que creamos.
+/// - rebroadcast_sender: Sender para pedir al NodeActionLoop que reenvie las transacciones que creamos, si esta activo.
+/// - xpub_gap_limit: Cantidad de direcciones sin usar que se derivan en cada cadena de las cuentas xpub que se importan.
+/// - spv_mode: Indica si el nodo es un cliente SPV, que carga un filtro bloom en los peers y solo descarga bloques filtrados.
+/// - regtest: Indica si el nodo corre en una red regtest, la unica en la que arma templates y acepta bloques minados.
+/// - tx_relay: Indica si ya se habilito el relay de transacciones con los peers, lo que ocurre la primera vez que el nodo se sincroniza.
+/// - pricing: Pricing, cotizacion de BTC en moneda fiat que la interfaz grafica muestra junto a los montos.
+/// - inventory_tracker: InventoryTracker, inventarios anunciados por los peers que se pidieron hace poco.
+/// - webhooks: Cola de eventos de las wallets a enviar al webhook, si el config indica WEBHOOK_URL.
+/// - payment_alerts: PaymentAlerts, pagos recibidos por las wallets que superan ALERT_THRESHOLD_SATS y ya alertaron.
+/// - abandoned_txs: Hashes de las transacciones abandonadas desde que inicio el nodo, que no se aceptan de los peers.
 pub struct NodeState {
     logger_sender: mpsc::Sender<Log>,
     gui_sender: Sender<GUIEvents>,
     headers: HeadersState,
     peers: Vec<Peer>,
+    disconnects: VecDeque<DisconnectEvent>,
+    peer_addresses: PeerAddressesState,
+    target_peers: usize,
+    reconnect_sender: Option<mpsc::Sender<usize>>,
+    next_block_peer: usize,
+    last_pong_update: Option<Instant>,
+    maintenance_sender: Option<mpsc::Sender<MaintenanceAction>>,
+    maintenance_tasks: Vec<TaskStatus>,
     wallets: WalletsState,
+    wallet_meta: WalletMetaState,
     blocks: BlocksState,
+    block_metadata: BlockMetadataState,
     utxo: UTXO,
     pending_txs: PendingTxs,
+    fee_estimator: FeeEstimator,
+    orphan_txs: OrphanPool<OutPoint, Transaction>,
+    orphan_blocks: OrphanPool<Vec<u8>, Arc<Block>>,
+    catching_up: bool,
+    dust_threshold: u64,
+    min_spend_confirmations: u32,
+    broadcast_peers: usize,
+    max_relay_tx_io: usize,
+    rebroadcast_blocks: usize,
+    rebroadcast_sender: Option<mpsc::Sender<NodeAction>>,
+    xpub_gap_limit: u32,
+    spv_mode: bool,
+    regtest: bool,
+    tx_relay: bool,
+    pricing: Pricing,
+    inventory_tracker: InventoryTracker,
+    webhooks: Option<WebhookQueue>,
+    payment_alerts: PaymentAlerts,
+    abandoned_txs: HashSet<Vec<u8>>,
 }
 
 impl NodeState {
@@ -60,46 +188,259 @@ impl NodeState {
         );
         create_store_dir(store_path)?;
 
-        let headers =
-            HeadersState::new(format!("{}/headers.bin", store_path), logger_sender.clone())?;
-        let pending_blocks_ref = PendingBlocks::new(store_path, headers.get_all());
-
-        let node_state_ref = Arc::new(Mutex::new(Self {
+        let headers = HeadersState::new(
+            format!("{}/headers.bin", store_path),
+            logger_sender.clone(),
+            gui_sender.clone(),
+        )?;
+        let mut block_store = BlockStore::open(store_path)?;
+        import_block_files(store_path, &mut block_store, &logger_sender)?;
+        let pending_blocks_ref = PendingBlocks::new(store_path, headers.get_all(), |block_hash| {
+            block_store.contains(block_hash)
+        });
+
+        let mut node_state = Self {
             logger_sender: logger_sender.clone(),
-            gui_sender,
+            gui_sender: gui_sender.clone(),
             headers,
             peers: vec![],
+            disconnects: VecDeque::with_capacity(MAX_DISCONNECT_EVENTS),
+            peer_addresses: PeerAddressesState::restore(format!("{}/peers.bin", store_path))?,
+            target_peers: 0,
+            reconnect_sender: None,
+            next_block_peer: 0,
+            last_pong_update: None,
+            maintenance_sender: None,
+            maintenance_tasks: vec![],
             wallets: WalletsState::new(format!("{}/wallets.bin", store_path))?,
-            blocks: BlocksState::new(store_path.clone(), logger_sender, pending_blocks_ref),
+            wallet_meta: WalletMetaState::new(store_path),
+            blocks: BlocksState::new(block_store, logger_sender, gui_sender, pending_blocks_ref),
+            block_metadata: BlockMetadataState::new(format!("{}/block_metadata.bin", store_path))?,
             utxo: UTXO::new(store_path.clone(), "/utxo.bin".to_string())?,
-            pending_txs: PendingTxs::new(),
-        }));
+            pending_txs: PendingTxs::new(format!("{}/pending_txs.bin", store_path))?,
+            fee_estimator: FeeEstimator::new(MAX_FEE_SAMPLES),
+            orphan_txs: OrphanPool::new(MAX_ORPHANS),
+            orphan_blocks: OrphanPool::new(MAX_ORPHANS),
+            catching_up: false,
+            dust_threshold: DEFAULT_DUST_THRESHOLD,
+            min_spend_confirmations: DEFAULT_MIN_SPEND_CONFIRMATIONS,
+            broadcast_peers: DEFAULT_BROADCAST_PEERS,
+            max_relay_tx_io: DEFAULT_MAX_RELAY_TX_IO,
+            rebroadcast_blocks: DEFAULT_REBROADCAST_BLOCKS,
+            rebroadcast_sender: None,
+            xpub_gap_limit: DEFAULT_XPUB_GAP_LIMIT,
+            spv_mode: false,
+            regtest: false,
+            tx_relay: false,
+            pricing: Pricing::new(None),
+            inventory_tracker: InventoryTracker::default(),
+            webhooks: None,
+            payment_alerts: PaymentAlerts::default(),
+            abandoned_txs: HashSet::new(),
+        };
+        node_state.remove_confirmed_pending_txs()?;
+        let migrated = node_state
+            .wallets
+            .migrate_metadata(&mut node_state.wallet_meta)?;
+        if migrated > 0 {
+            send_log(
+                &node_state.logger_sender,
+                Log::Message(format!(
+                    "Moved the metadata of {migrated} wallet movements to the wallet metadata store"
+                )),
+            );
+        }
 
-        Ok(node_state_ref)
+        Ok(Arc::new(Mutex::new(node_state)))
     }
 
     /// Agrega un bloque nuevo, lo guarda en su archivo y actualiza los pending_blocks, wallets, pending_txs y utxo.
+    /// Durante el IBD avisa a la interfaz grafica cuando cambia el porcentaje del historial de las wallets.
+    /// Si el bloque no esta en la cadena (por ejemplo, quedo en una rama desconectada por un reorg) se descarta.
     /// Tambien verifica si ahora el nodo esta actualizado con la red
     pub fn append_block(&mut self, block_hash: Vec<u8>, block: &Block) -> Result<(), CustomError> {
+        if !self.headers.contains(&block_hash) {
+            let mut pending_blocks = self.blocks.pending_blocks_ref.lock()?;
+            pending_blocks.remove_block(&block_hash)?;
+            drop(pending_blocks);
+
+            send_log(
+                &self.logger_sender,
+                Log::Message(String::from("Block outside the current chain ignored")),
+            );
+            return Ok(());
+        }
+
+        // con las UTXO generadas se rechaza el bloque, antes de guardarlo, si sus scripts o su coinbase no son validos
+        // (en spv_mode los bloques solo tienen nuestras transacciones y no se puede verificar la coinbase)
+        if self.utxo.is_synced() && !self.spv_mode {
+            self.utxo.verify_block_scripts(block)?;
+            self.verify_coinbase(&block_hash, block)?;
+        }
+
+        let previous_progress = self.wallet_history_progress()?;
         self.blocks
             .append_block(&block_hash, block, self.headers.total_headers_to_download())?;
         self.headers.set_downloaded(&block_hash);
+        // en spv_mode el bloque solo tiene nuestras transacciones, su resumen no seria el del bloque completo
+        if !self.spv_mode {
+            self.block_metadata
+                .append(&block_hash, BlockMetadata::from_block(block))?;
+        }
 
         self.verify_sync()?;
 
         self.update_wallets(block)?;
         self.update_pending_tx(block)?;
 
+        let progress = self.wallet_history_progress()?;
+        if let Some(progress) = progress.filter(|progress| Some(*progress) != previous_progress) {
+            if !self.wallets.get_all().is_empty() {
+                self.gui_sender
+                    .send(GUIEvents::WalletHistoryProgress { progress })?;
+            }
+        }
+
         if self.is_synced() {
-            self.utxo.update_from_block(block, true)?;
+            if let Some(anomaly) = self.utxo.update_from_block(block, true)? {
+                send_log(&self.logger_sender, Log::Warning(anomaly.message()));
+            }
+        }
+
+        if !self.orphan_txs.is_empty() {
+            self.promote_orphan_txs_from_block(block)?;
+        }
+
+        Ok(())
+    }
+
+    /// Guarda un bloque que no estaba pendiente si todavia no se conoce su header ni el de su bloque anterior,
+    /// para agregarlo cuando lleguen sus headers (ver promote_orphan_blocks). Si no, lo descarta.
+    pub fn append_orphan_block(&mut self, block: Arc<Block>) {
+        let prev_block_hash = block.header.prev_block_hash.clone();
+        if self.headers.contains(block.header.hash()) || self.headers.contains(&prev_block_hash) {
+            return;
+        }
+        self.orphan_blocks.insert(prev_block_hash, block);
+        send_log(
+            &self.logger_sender,
+            Log::Message(String::from("Orphan block stored until its header arrives")),
+        );
+    }
+
+    /// Agrega los bloques huerfanos de los headers recibidos que estan pendientes de descargar.
+    /// Devuelve true si agrego alguno.
+    fn promote_orphan_blocks(&mut self, headers: &[BlockHeader]) -> Result<bool, CustomError> {
+        let mut promoted = false;
+        for header in headers {
+            let blocks = self.orphan_blocks.take(&header.prev_block_hash, |block| {
+                block.header.hash() == header.hash()
+            });
+            for block in blocks {
+                if self.is_block_pending(header.hash())? {
+                    self.append_block(header.hash().clone(), &block)?;
+                    promoted = true;
+                }
+            }
+        }
+        Ok(promoted)
+    }
+
+    /// Vuelve a evaluar las transacciones huerfanas que esperaban los outputs de las transacciones del bloque.
+    /// Si ya no quedan bloques pendientes, las vuelve a evaluar todas: las que siguen sin encontrar sus inputs
+    /// gastan outputs anteriores a START_DATE_IBD, que no estan en las UTXO.
+    fn promote_orphan_txs_from_block(&mut self, block: &Block) -> Result<(), CustomError> {
+        let mut orphans = vec![];
+        for tx in &block.transactions {
+            orphans.extend(self.take_orphan_txs(&tx.hash(), tx.outputs.len()));
         }
+        if self.is_pending_blocks_empty()? {
+            orphans.extend(self.orphan_txs.drain());
+        }
+        for orphan in orphans {
+            self.append_pending_tx(orphan)?;
+        }
+        Ok(())
+    }
 
+    /// Saca las transacciones huerfanas que esperaban alguno de los outputs de la transaccion tx_hash.
+    fn take_orphan_txs(&mut self, tx_hash: &[u8], outputs: usize) -> Vec<Transaction> {
+        (0..outputs)
+            .flat_map(|index| {
+                let outpoint = OutPoint {
+                    hash: tx_hash.to_vec(),
+                    index: index as u32,
+                };
+                self.orphan_txs.take(&outpoint, |_| true)
+            })
+            .collect()
+    }
+
+    /// Verifica el valor de la coinbase del bloque con su height (ver Block::validate_coinbase).
+    /// Si el bloque gasta outputs que no estan en las UTXO no se puede verificar, y solo se loguea una advertencia.
+    fn verify_coinbase(&self, block_hash: &[u8], block: &Block) -> Result<(), CustomError> {
+        let height = match self.headers.get_height(block_hash) {
+            Some(height) => height,
+            None => return Ok(()),
+        };
+        if !block.validate_coinbase(&self.utxo, height)? {
+            send_log(
+                &self.logger_sender,
+                Log::Warning(String::from(
+                    "coinbase value not verified, the block spends unknown outputs",
+                )),
+            );
+        }
         Ok(())
     }
 
     /// Obtiene un bloque a partir de su hash
-    pub fn get_block(&self, block_string_hash: String) -> Result<Block, CustomError> {
-        self.blocks.get_block(block_string_hash)
+    pub fn get_block(&self, block_hash: &[u8]) -> Result<Block, CustomError> {
+        self.blocks.get_block(block_hash)
+    }
+
+    /// Devuelve true si el bloque esta guardado en disco.
+    pub fn is_block_stored(&self, block_hash: &[u8]) -> bool {
+        self.blocks.is_stored(block_hash)
+    }
+
+    /// Arma el merkleblock de un bloque guardado para un peer, junto a las transacciones que coinciden con su filtro bloom
+    /// (ver BloomFilter::matches_transaction), que se le envian a continuacion.
+    /// Si el peer no cargo un filtro no coincide ninguna y el merkleblock solo tiene el header del bloque.
+    /// Devuelve CustomError::CannotOpenFile si el bloque no esta guardado.
+    pub fn get_filtered_block(
+        &mut self,
+        address: SocketAddrV6,
+        block_hash: &[u8],
+    ) -> Result<(MerkleBlock, Vec<Transaction>), CustomError> {
+        let block = self.blocks.get_block(block_hash)?;
+        let filter = self
+            .peers
+            .iter_mut()
+            .find(|p| p.address == address)
+            .and_then(|peer| peer.bloom_filter.as_mut());
+
+        let matches: Vec<bool> = match filter {
+            Some(filter) => block
+                .transactions
+                .iter()
+                .map(|transaction| filter.matches_transaction(transaction))
+                .collect(),
+            None => vec![false; block.transactions.len()],
+        };
+        let merkle_block = MerkleBlock::new(&block, &matches);
+        let transactions = block
+            .transactions
+            .into_iter()
+            .zip(matches)
+            .filter_map(|(transaction, matched)| matched.then_some(transaction))
+            .collect();
+        Ok((merkle_block, transactions))
+    }
+
+    /// Devuelve un lector del bloque guardado en disco
+    pub fn get_block_file(&self, block_hash: &[u8]) -> Result<BlockReader, CustomError> {
+        self.blocks.get_block_file(block_hash)
     }
 
     /********************     PEERS     ********************/
@@ -109,25 +450,211 @@ impl NodeState {
         &mut self.peers
     }
 
+    /// Devuelve el resumen de cada peer conectado (ver PeerSummary), en el orden en que se conectaron.
+    pub fn get_peer_summaries(&self) -> Vec<PeerSummary> {
+        self.peers.iter().map(Peer::summary).collect()
+    }
+
     /// Devuelve referencia a un peer en particular
     pub fn get_peer(&mut self, address: &SocketAddrV6) -> Option<&mut Peer> {
         self.peers.iter_mut().find(|p| &p.address == address)
     }
 
+    /// Devuelve un PeerSender por cada peer conectado, para enviarles mensajes despues de soltar el lock del NodeState.
+    pub fn get_peer_senders(&self) -> Vec<PeerSender> {
+        self.peers.iter().map(Peer::sender).collect()
+    }
+
+    /// Devuelve el PeerSender de un peer en particular, o None si no esta conectado.
+    pub fn get_peer_sender(&self, address: &SocketAddrV6) -> Option<PeerSender> {
+        self.peers
+            .iter()
+            .find(|p| &p.address == address)
+            .map(Peer::sender)
+    }
+
     /// Agrega varios peers nuevos al nodo
-    pub fn append_peers(&mut self, peers: Vec<Peer>) {
-        self.peers.extend(peers);
+    /// Si ya habia un peer con la misma direccion, se lo desconecta y reemplaza por el nuevo.
+    /// En spv_mode les carga el filtro bloom de las wallets, desconectando a los que no se les puede enviar.
+    /// Si el relay de transacciones ya esta habilitado, tambien se habilita con los nuevos peers.
+    /// Avisa a la GUI que cambiaron los peers con GUIEvents::PeersUpdated.
+    /// Devuelve true si, con las alturas de los nuevos peers, el nodo paso a estar atrasado respecto de la red.
+    pub fn append_peers(&mut self, peers: Vec<Peer>) -> Result<bool, CustomError> {
+        let addresses: Vec<SocketAddrV6> = peers.iter().map(|peer| peer.address).collect();
+        for peer in peers {
+            self.remove_peer(peer.address, DisconnectReason::Replaced)?;
+            peer.connection.set_relay_txs(self.tx_relay);
+            self.peers.push(peer);
+        }
+        if self.spv_mode {
+            self.load_bloom_filter(&addresses)?;
+        }
+        self.gui_sender.send(GUIEvents::PeersUpdated {
+            peers: self.peers.len(),
+        })?;
+        self.update_network_position()
     }
 
-    /// Elimina del nodo a un peer en particular
-    pub fn remove_peer(&mut self, address: SocketAddrV6) {
+    /// Elimina del nodo a un peer en particular, cerrando su conexion y registrando el motivo de la desconexion.
+    /// No espera a que terminen sus threads (ver Peer::close), para no retener el lock del NodeState mientras tanto.
+    /// Si el peer ya no estaba conectado no hace nada, si no avisa a la GUI con GUIEvents::PeersUpdated.
+    /// Si quedan menos peers que target_peers se pide reemplazarlos, salvo que el peer se reemplace por una nueva conexion.
+    /// Como cambia la altura de la red, se vuelve a comparar con la nuestra (ver update_network_position).
+    pub fn remove_peer(
+        &mut self,
+        address: SocketAddrV6,
+        reason: DisconnectReason,
+    ) -> Result<(), CustomError> {
         let index = self.peers.iter().position(|p| p.address == address);
 
         if let Some(index) = index {
-            self.peers.remove(index);
+            let mut peer = self.peers.remove(index);
+            // el reemplazo se pide antes que nada que pueda fallar, para no quedar con menos peers
+            if reason != DisconnectReason::Replaced {
+                self.request_reconnect();
+            }
+            self.record_disconnect(peer.close(reason))?;
+            self.gui_sender.send(GUIEvents::PeersUpdated {
+                peers: self.peers.len(),
+            })?;
+            self.update_network_position()?;
+        }
+        Ok(())
+    }
+
+    /// Activa el reemplazo de peers: cada vez que se desconecte un peer y queden menos de target_peers,
+    /// se envia por reconnect_sender la cantidad de peers que faltan.
+    pub fn enable_reconnects(
+        &mut self,
+        reconnect_sender: mpsc::Sender<usize>,
+        target_peers: usize,
+    ) {
+        self.reconnect_sender = Some(reconnect_sender);
+        self.target_peers = target_peers;
+    }
+
+    /// Establece el sender por el que se le piden acciones al maintenance_loop.
+    pub fn enable_maintenance(&mut self, maintenance_sender: mpsc::Sender<MaintenanceAction>) {
+        self.maintenance_sender = Some(maintenance_sender);
+    }
+
+    /// Pide al maintenance_loop que ejecute ahora la tarea con ese nombre (por ejemplo desde la GUI).
+    /// Si el maintenance_loop no esta activo no hace nada.
+    pub fn run_maintenance_task(&self, name: &str) -> Result<(), CustomError> {
+        if let Some(maintenance_sender) = &self.maintenance_sender {
+            maintenance_sender.send(MaintenanceAction::RunNow(name.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Pide al maintenance_loop que adelante la proxima ejecucion de la tarea con ese nombre a when, si es anterior.
+    /// Si el maintenance_loop no esta activo no hace nada.
+    pub fn schedule_maintenance_task(&self, name: &str, when: Instant) -> Result<(), CustomError> {
+        if let Some(maintenance_sender) = &self.maintenance_sender {
+            maintenance_sender.send(MaintenanceAction::RunAt(name.to_string(), when))?;
+        }
+        Ok(())
+    }
+
+    /// Guarda el estado de las tareas del maintenance_loop, para mostrarlo en el StatusSnapshot.
+    pub fn set_maintenance_tasks(&mut self, maintenance_tasks: Vec<TaskStatus>) {
+        self.maintenance_tasks = maintenance_tasks;
+    }
+
+    /// Devuelve la cantidad de peers que faltan para llegar a target_peers.
+    pub fn missing_peers(&self) -> usize {
+        self.target_peers.saturating_sub(self.peers.len())
+    }
+
+    fn request_reconnect(&self) {
+        let missing = self.missing_peers();
+        if missing == 0 {
+            return;
+        }
+        if let Some(reconnect_sender) = &self.reconnect_sender {
+            // si el ReconnectLoop termino no hay a quien pedirle los peers
+            let _ = reconnect_sender.send(missing);
         }
     }
 
+    /// Agrega las direcciones obtenidas del seed DNS a las direcciones conocidas y las guarda.
+    pub fn add_peer_addresses(&mut self, addresses: &[SocketAddr]) -> Result<(), CustomError> {
+        if self.peer_addresses.add_addresses(addresses) > 0 {
+            self.peer_addresses.save()?;
+        }
+        Ok(())
+    }
+
+    /// Agrega las direcciones que anuncio un peer en un mensaje addr, descartando las que no son conectables.
+    /// Si se agrego alguna la guarda y, si faltan peers, le pide al ReconnectLoop que intente conectarse.
+    /// Devuelve la cantidad de direcciones agregadas.
+    pub fn add_announced_addresses(
+        &mut self,
+        addresses: &[SocketAddrV6],
+    ) -> Result<usize, CustomError> {
+        let addresses: Vec<SocketAddr> = addresses
+            .iter()
+            .filter(|address| !address.ip().is_unspecified() && address.port() != 0)
+            .map(|address| get_socket_address(*address))
+            .collect();
+
+        let added = self.peer_addresses.add_addresses(&addresses);
+        if added > 0 {
+            self.peer_addresses.save()?;
+            self.request_reconnect();
+        }
+        Ok(added)
+    }
+
+    /// Devuelve las direcciones conocidas, incluidas las guardadas en ejecuciones anteriores.
+    pub fn known_peer_addresses(&self) -> Vec<SocketAddr> {
+        self.peer_addresses.addresses()
+    }
+
+    /// Registra un intento de conexion a una direccion conocida, para calcular cuando volver a intentar.
+    pub fn record_connection_attempt(&mut self, address: SocketAddr, connected: bool) {
+        self.peer_addresses
+            .record_attempt(address, connected, Instant::now());
+    }
+
+    /// Devuelve hasta count direcciones conocidas a las que no estamos conectados y que no se intentaron recientemente.
+    pub fn reconnect_candidates(&self, count: usize) -> Vec<SocketAddr> {
+        let connected: Vec<SocketAddrV6> = self.peers.iter().map(|peer| peer.address).collect();
+        self.peer_addresses
+            .candidates(&connected, count, Instant::now())
+    }
+
+    /// Desconecta a todos los peers del nodo por el mismo motivo.
+    pub fn remove_all_peers(&mut self, reason: DisconnectReason) -> Result<(), CustomError> {
+        for mut peer in std::mem::take(&mut self.peers) {
+            self.record_disconnect(peer.stop(reason))?;
+        }
+        self.gui_sender.send(GUIEvents::PeersUpdated {
+            peers: self.peers.len(),
+        })?;
+        Ok(())
+    }
+
+    /// Registra la desconexion de un peer, descartando la mas antigua si ya hay MAX_DISCONNECT_EVENTS.
+    /// Tambien se usa para los peers con los que fallo el handshake, que nunca llegaron a agregarse.
+    pub fn record_disconnect(&mut self, event: DisconnectEvent) -> Result<(), CustomError> {
+        send_log(&self.logger_sender, Log::Message(event.to_string()));
+
+        if self.disconnects.len() == MAX_DISCONNECT_EVENTS {
+            self.disconnects.pop_front();
+        }
+        self.disconnects.push_back(event.clone());
+
+        self.gui_sender
+            .send(GUIEvents::PeerDisconnected(event))
+            .map_err(CustomError::from)
+    }
+
+    /// Devuelve las ultimas desconexiones de peers, de la mas antigua a la mas reciente.
+    pub fn recent_disconnects(&self) -> Vec<DisconnectEvent> {
+        self.disconnects.iter().cloned().collect()
+    }
+
     /// Registra que un peer solicito el envio directo de headers
     pub fn peer_send_headers(&mut self, address: SocketAddrV6) {
         let peer = self.peers.iter_mut().find(|p| p.address == address);
@@ -136,6 +663,22 @@ impl NodeState {
         }
     }
 
+    /// Registra el filtro bloom que cargo un peer con filterload, o lo quita si recibe None (filterclear).
+    pub fn peer_filter_load(&mut self, address: SocketAddrV6, filter: Option<BloomFilter>) {
+        let peer = self.peers.iter_mut().find(|p| p.address == address);
+        if let Some(peer) = peer {
+            peer.bloom_filter = filter;
+        }
+    }
+
+    /// Agrega un elemento al filtro bloom de un peer (filteradd). Si el peer no cargo un filtro no hace nada.
+    pub fn peer_filter_add(&mut self, address: SocketAddrV6, element: &[u8]) {
+        let peer = self.peers.iter_mut().find(|p| p.address == address);
+        if let Some(filter) = peer.and_then(|peer| peer.bloom_filter.as_mut()) {
+            filter.insert(element);
+        }
+    }
+
     /// Registra que un peer solicito headers
     pub fn peer_requested_headers(&mut self, address: SocketAddrV6) {
         let peer = self.peers.iter_mut().find(|p| p.address == address);
@@ -144,11 +687,88 @@ impl NodeState {
         }
     }
 
-    /// Obtiene el peer con el que haya realizado el handshake mas rapido
+    /// Envia un ping a los peers que no tienen uno sin responder y a los que no se les envio uno en el ultimo interval.
+    /// Devuelve los peers a desconectar: los que no respondieron su ultimo ping dentro del timeout y a los que no se les pudo enviar el ping.
+    pub fn ping_peers(
+        &mut self,
+        interval: Duration,
+        timeout: Duration,
+    ) -> Vec<(SocketAddrV6, DisconnectReason)> {
+        let mut dead_peers = vec![];
+        for peer in self.peers.iter_mut() {
+            if peer.is_ping_expired(timeout) {
+                dead_peers.push((peer.address, DisconnectReason::PingTimeout));
+            } else if peer.needs_ping(interval) {
+                if let Err(error) = peer.ping(random_nonce()) {
+                    dead_peers.push((peer.address, DisconnectReason::from_error(&error)));
+                }
+            }
+        }
+        dead_peers
+    }
+
+    /// Registra el pong de un peer, respuesta a su ultimo ping, y avisa a la GUI con GUIEvents::PeersUpdated
+    /// para que muestre su nueva latencia (ver Peer::avg_latency_ms).
+    /// Como los pongs de todos los peers llegan casi juntos, se avisa a lo sumo una vez cada PONG_UPDATE_INTERVAL:
+    /// las latencias de los pongs que llegan entre medio se muestran con el proximo aviso.
+    /// Un pong que no responde al ultimo ping enviado se loguea y se ignora.
+    pub fn peer_pong(&mut self, address: SocketAddrV6, nonce: u64) -> Result<(), CustomError> {
+        let peer = self.peers.iter_mut().find(|p| p.address == address);
+        if let Some(peer) = peer {
+            if !peer.receive_pong(nonce) {
+                send_log(
+                    &self.logger_sender,
+                    Log::Message(format!("Unexpected pong nonce from peer {address}")),
+                );
+                return Ok(());
+            }
+            let now = Instant::now();
+            if self
+                .last_pong_update
+                .is_some_and(|last| now.duration_since(last) < PONG_UPDATE_INTERVAL)
+            {
+                return Ok(());
+            }
+            self.last_pong_update = Some(now);
+            self.gui_sender.send(GUIEvents::PeersUpdated {
+                peers: self.peers.len(),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Registra que un peer nos envio headers, por lo que su cadena llega al menos hasta nuestro ultimo header.
+    pub fn peer_announced_tip(&mut self, address: SocketAddrV6) {
+        let tip_height = self.headers.get_all().len();
+        let peer = self.peers.iter_mut().find(|p| p.address == address);
+        if let Some(peer) = peer {
+            peer.best_height = peer.best_height.max(tip_height);
+        }
+    }
+
+    /// Registra en las estadisticas de un peer un mensaje recibido de bytes de tamaño.
+    pub fn peer_message_received(&mut self, address: SocketAddrV6, bytes: usize) {
+        let peer = self.peers.iter_mut().find(|p| p.address == address);
+        if let Some(peer) = peer {
+            peer.stats.record_message(bytes, Instant::now());
+        }
+    }
+
+    /// Devuelve las estadisticas de descarga de los peers conectados.
+    pub fn get_peer_stats(&self) -> Vec<(SocketAddrV6, PeerStats)> {
+        self.peers
+            .iter()
+            .map(|peer| (peer.address, peer.stats))
+            .collect()
+    }
+
+    /// Obtiene el peer mas rapido: el de mayor throughput observado o,
+    /// si todavia no se recibieron datos de ningun peer, el de handshake mas rapido.
+    /// Los empates se deciden por la latencia de los pings (ver PeerStats::cmp_speed).
     pub fn get_fastest_peer(&mut self) -> Option<&mut Peer> {
         self.peers
             .iter_mut()
-            .min_by(|a, b| a.benchmark.cmp(&b.benchmark))
+            .max_by(|a, b| a.stats.cmp_speed(&b.stats))
     }
 
     /********************     HEADERS     ********************/
@@ -159,7 +779,12 @@ impl NodeState {
     }
 
     /// agrega un header nuevo en HeadersState
-    pub fn append_headers(&mut self, headers: &Headers) -> Result<(), CustomError> {
+    /// Los bloques de los headers posteriores a START_DATE_IBD se agregan a los bloques pendientes
+    /// en el mismo momento, para que verify_sync no pueda considerar sincronizados los bloques antes de pedirlos.
+    /// Si los headers producen un reorg, deshace los bloques desconectados en las UTXO y en las wallets.
+    /// Si los headers ya estaban sincronizados, avisa a la interfaz la dificultad actual (ver DifficultyInfo).
+    /// Devuelve los headers que efectivamente se agregaron a la cadena.
+    pub fn append_headers(&mut self, headers: &Headers) -> Result<Vec<BlockHeader>, CustomError> {
         let mut new_headers = vec![];
 
         for header in headers.headers.iter() {
@@ -170,20 +795,235 @@ impl NodeState {
             new_headers.push(header);
         }
 
-        self.headers.append_headers(new_headers)?;
-        self.gui_sender.send(GUIEvents::NewHeaders)?;
+        let previous_len = self.headers.get_all().len();
+        let headers_were_synced = self.headers.is_synced();
+        let disconnected = self.headers.append_headers(new_headers)?;
+        if !disconnected.is_empty() {
+            self.disconnect_blocks(&disconnected)?;
+        }
+
+        let kept_len = previous_len - disconnected.len();
+        self.mark_stored_blocks(kept_len);
+
+        let all_headers = self.headers.get_all();
+        let mut appended = all_headers[kept_len..].to_vec();
+        let blocks_to_download: Vec<Vec<u8>> = appended
+            .iter()
+            .filter(|header| header.timestamp > START_DATE_IBD && !header.block_downloaded)
+            .map(|header| header.hash().clone())
+            .collect();
+
+        if !blocks_to_download.is_empty() {
+            let mut pending_blocks = self.blocks.pending_blocks_ref.lock()?;
+            for block_hash in blocks_to_download {
+                pending_blocks.append_block(block_hash)?;
+            }
+            drop(pending_blocks);
+
+            if !self.utxo.is_synced() {
+                self.blocks.invalidate_sync(self.headers.get_all().len());
+            }
+        }
+
+        // los bloques huerfanos de los headers nuevos ya no se piden
+        if !self.orphan_blocks.is_empty() && self.promote_orphan_blocks(&appended)? {
+            appended = self.headers.get_all()[kept_len..].to_vec();
+        }
+
+        // apenas se sincronizan los headers se pide el historial provisional de las wallets
+        if !headers_were_synced && self.headers.is_synced() {
+            let pubkeys: Vec<String> = self
+                .wallets
+                .get_all()
+                .iter()
+                .map(|wallet| wallet.pubkey.clone())
+                .collect();
+            self.request_provisional_history(&pubkeys)?;
+        }
+
+        self.update_network_position()?;
+        self.gui_sender.send(GUIEvents::NewHeaders {
+            tip_height: self.headers.get_all().len(),
+        })?;
+        if headers_were_synced && !appended.is_empty() {
+            if let Some(info) = self.headers.get_difficulty_info() {
+                self.gui_sender.send(GUIEvents::DifficultyUpdated(info))?;
+            }
+        }
+
+        Ok(appended)
+    }
+
+    /// Durante el IBD marca como descargados los headers agregados a partir de from cuyo bloque ya esta guardado en disco
+    /// (por ejemplo al reiniciar un IBD interrumpido), para no volver a pedirlos: las UTXO se generan leyendolos del disco.
+    fn mark_stored_blocks(&mut self, from: usize) {
+        if self.utxo.is_synced() {
+            return;
+        }
+        let stored_blocks: Vec<Vec<u8>> = self.headers.get_all()[from..]
+            .iter()
+            .filter(|header| {
+                header.timestamp > START_DATE_IBD && self.blocks.is_stored(header.hash())
+            })
+            .map(|header| header.hash().clone())
+            .collect();
+
+        for block_hash in stored_blocks {
+            self.headers.set_downloaded(&block_hash);
+        }
+    }
+
+    /// Deshace en las UTXO y en el historial de las wallets los bloques que quedaron fuera de la cadena por un reorg,
+    /// y los saca de los bloques pendientes para no descargarlos.
+    /// Si las UTXO estan sincronizadas, las transacciones de esos bloques (salvo las coinbase) vuelven a las pending txs,
+    /// en el orden de la cadena, para que se puedan volver a minar.
+    fn disconnect_blocks(&mut self, block_hashes: &[Vec<u8>]) -> Result<(), CustomError> {
+        send_log(
+            &self.logger_sender,
+            Log::Message(format!(
+                "Chain reorg detected, {} blocks disconnected",
+                block_hashes.len()
+            )),
+        );
+
+        // se leen antes de deshacerlos, los hashes llegan empezando por el ultimo bloque
+        let mut disconnected_txs = vec![];
+        if self.utxo.is_synced() {
+            for block_hash in block_hashes.iter().rev() {
+                let Ok(block) = self.blocks.get_block(block_hash) else { continue };
+                disconnected_txs.extend(
+                    block
+                        .transactions
+                        .into_iter()
+                        .filter(|tx| !tx.is_coinbase()),
+                );
+            }
+        }
+
+        self.utxo.disconnect_blocks(block_hashes)?;
+
+        let mut pending_blocks = self.blocks.pending_blocks_ref.lock()?;
+        for block_hash in block_hashes {
+            pending_blocks.remove_block(block_hash)?;
+        }
+        drop(pending_blocks);
+
+        let affected = self.wallets.disconnect_blocks(block_hashes)?;
+        if !affected.is_empty() {
+            self.gui_sender
+                .send(GUIEvents::WalletsUpdated { affected })?;
+        }
 
+        for transaction in disconnected_txs {
+            self.append_pending_tx(transaction)?;
+        }
         Ok(())
     }
 
     /// Devuelve los ultimos count headers del HeaderState
-    pub fn get_last_headers(&self, count: usize) -> Vec<(usize, BlockHeader)> {
-        self.headers.get_last_headers(count)
+    pub fn get_last_headers(
+        &self,
+        count: usize,
+    ) -> Vec<(usize, BlockHeader, Option<BlockMetadata>)> {
+        self.headers
+            .get_last_headers(count)
+            .into_iter()
+            .map(|(height, header)| {
+                let metadata = self.block_metadata.get(header.hash());
+                (height, header, metadata)
+            })
+            .collect()
+    }
+
+    /// Devuelve el resumen de un bloque, o None si todavia no esta disponible (el bloque no se descargo o falta completarlo).
+    /// Nunca lee el archivo del bloque, por lo que se puede usar desde la interfaz grafica.
+    pub fn get_block_metadata(&self, block_hash: &[u8]) -> Option<BlockMetadata> {
+        self.block_metadata.get(block_hash)
+    }
+
+    /// Devuelve hasta limit bloques guardados en disco que todavia no tienen resumen, empezando por los mas recientes,
+    /// junto a un lector del bloque para leerlo sin tener el lock del NodeState. Saltea los bloques de skip.
+    /// En spv_mode no se completan ya que los bloques guardados son bloques filtrados.
+    pub fn blocks_missing_metadata(
+        &self,
+        limit: usize,
+        skip: &HashSet<Vec<u8>>,
+    ) -> Vec<(Vec<u8>, BlockReader)> {
+        if self.spv_mode {
+            return vec![];
+        }
+        self.headers
+            .get_all()
+            .iter()
+            .rev()
+            .take_while(|header| header.timestamp > START_DATE_IBD)
+            .filter(|header| {
+                header.block_downloaded
+                    && !self.block_metadata.contains(header.hash())
+                    && !skip.contains(header.hash())
+            })
+            .filter_map(|header| {
+                let file = self.blocks.get_block_file(header.hash()).ok()?;
+                Some((header.hash().clone(), file))
+            })
+            .take(limit)
+            .collect()
+    }
+
+    /// Devuelve los hashes de hasta limit bloques guardados en disco, empezando por los mas recientes.
+    pub fn recent_stored_blocks(&self, limit: usize) -> Vec<Vec<u8>> {
+        self.headers
+            .get_all()
+            .iter()
+            .rev()
+            .take_while(|header| header.timestamp > START_DATE_IBD)
+            .filter(|header| header.block_downloaded && self.blocks.is_stored(header.hash()))
+            .map(|header| header.hash().clone())
+            .take(limit)
+            .collect()
+    }
+
+    /// Registra el resumen de un bloque que no lo tenia (ver blocks_missing_metadata).
+    pub fn append_block_metadata(
+        &mut self,
+        block_hash: &[u8],
+        metadata: BlockMetadata,
+    ) -> Result<(), CustomError> {
+        self.block_metadata.append(block_hash, metadata)
+    }
+
+    /// Devuelve el hash del bloque con ese height (0 es genesis), o None si el nodo todavia no lo tiene.
+    pub fn block_hash_at_height(&self, height: usize) -> Option<Vec<u8>> {
+        self.headers.get_hash_at_height(height)
+    }
+
+    /// Devuelve la dificultad actual y la estimacion del proximo ajuste (ver HeadersState::get_difficulty_info).
+    pub fn get_difficulty_info(&self) -> Option<DifficultyInfo> {
+        self.headers.get_difficulty_info()
+    }
+
+    /// Devuelve el height del bloque con ese hash (0 para genesis), o None si no esta en la cadena del nodo.
+    pub fn height_of_block(&self, block_hash: &[u8]) -> Option<usize> {
+        self.headers.get_height(block_hash)
+    }
+
+    /// Devuelve el header del bloque con ese height junto a sus confirmaciones.
+    /// Devuelve None para genesis o si el nodo todavia no tiene ese header.
+    pub fn get_header_at_height(&self, height: usize) -> Option<(BlockHeader, u32)> {
+        let header = self.headers.get_header_at_height(height)?;
+        let confirmations = self.headers.get_confirmations(header.hash());
+        Some((header.clone(), confirmations))
     }
 
     /// Devuelve los headers que se le solicitan mediante el mensaje GetHeaders del protocolo btc
-    pub fn get_headers(&self, get_headers: GetHeaders) -> Vec<BlockHeader> {
-        self.headers.get_headers(get_headers)
+    /// ya serializados en formato de red, listos para armar un RawHeaders.
+    pub fn get_serialized_headers(&mut self, get_headers: GetHeaders) -> Vec<u8> {
+        self.headers.get_serialized_headers(get_headers)
+    }
+
+    /// Devuelve los hashes de los bloques pedidos en un mensaje getblocks.
+    pub fn get_block_hashes(&self, get_blocks: &GetBlocks) -> Vec<Vec<u8>> {
+        self.headers.get_block_hashes(get_blocks)
     }
 
     /// Devuelve los headers listos para enviar a medida que se descargan sus bloques, siguiendo el orden de la blockchain.
@@ -199,30 +1039,168 @@ impl NodeState {
     }
 
     /// Verifica si el nodo esta sincronizado con la red
-    /// Si el nodo esta sincronizado, envia un evento a la interfaz grafica para indicar que el nodo esta listo para usarse
+    /// Si el nodo esta sincronizado y no esta atrasado respecto de la red, envia un evento a la interfaz grafica para indicar que el nodo esta listo para usarse
     /// Si el nodo no esta sincronizado, verifica si los headers estan sincronizados
     /// Si los headers estan sincronizados, verifica si los bloques estan sincronizados
-    /// Si los bloques estan sincronizados, genera el UTXO
+    /// Si los bloques estan sincronizados, genera el UTXO y vuelve a registrar las pending txs propias (ver mark_own_pending_txs)
+    /// La primera vez que el nodo queda sincronizado habilita el relay de transacciones (ver start_tx_relay)
     ///
     pub fn verify_sync(&mut self) -> Result<(), CustomError> {
+        let headers_count = self.headers.get_all().len();
+
         if self.headers.is_synced() {
-            self.blocks.verify_sync()?;
+            self.blocks.verify_sync(headers_count)?;
         }
 
-        if self.blocks.is_synced() && !self.utxo.is_synced() {
-            self.utxo
-                .generate(self.headers.get_all(), &mut self.logger_sender)?;
+        if self.blocks.is_synced_at(headers_count) && !self.utxo.is_synced() {
+            self.utxo.generate(
+                self.headers.get_all(),
+                self.blocks.store(),
+                &mut self.logger_sender,
+            )?;
+            self.mark_own_pending_txs()?;
         }
 
-        if self.is_synced() {
-            self.gui_sender
-                .send(GUIEvents::NodeStateReady)
-                .map_err(|_| CustomError::CannotInitGUI)?;
+        if self.is_synced() && !self.tx_relay {
+            self.start_tx_relay()?;
         }
 
+        if self.is_synced() && !self.catching_up {
+            self.gui_sender.send(GUIEvents::NodeStateReady)?;
+        }
+
+        Ok(())
+    }
+
+    /// Habilita el relay de transacciones con los peers y les pide sus transacciones pendientes (mempool),
+    /// que durante el IBD se ignoraron. Los peers a los que no se les puede enviar se desconectan.
+    fn start_tx_relay(&mut self) -> Result<(), CustomError> {
+        self.tx_relay = true;
+        let mut failed_peers = vec![];
+        for peer in self.peers.iter_mut() {
+            peer.connection.set_relay_txs(true);
+            if let Err(error) = peer.send(&Mempool::new()) {
+                failed_peers.push((peer.address, DisconnectReason::from_error(&error)));
+            }
+        }
+        for (address, reason) in failed_peers {
+            self.remove_peer(address, reason)?;
+        }
+        send_log(
+            &self.logger_sender,
+            Log::Message(String::from(
+                "Node synced, requesting pending transactions from peers",
+            )),
+        );
         Ok(())
     }
 
+    /// Devuelve la altura de nuestra cadena de headers.
+    pub fn get_tip_height(&self) -> usize {
+        self.headers.get_all().len()
+    }
+
+    /// Devuelve la mediana de las alturas de los peers conectados, o None si no hay peers.
+    pub fn get_network_height(&self) -> Option<usize> {
+        let mut heights: Vec<usize> = self.peers.iter().map(|peer| peer.best_height).collect();
+        if heights.is_empty() {
+            return None;
+        }
+        heights.sort_unstable();
+        Some(heights[(heights.len() - 1) / 2])
+    }
+
+    /// Devuelve cuantos bloques le faltan a nuestra cadena de headers para alcanzar la altura de la red.
+    pub fn get_blocks_behind(&self) -> usize {
+        let tip_height = self.headers.get_all().len();
+        self.get_network_height().map_or(0, |network_height| {
+            network_height.saturating_sub(tip_height)
+        })
+    }
+
+    /// Devuelve true si el nodo esta atrasado respecto de la red y la esta alcanzando.
+    pub fn is_catching_up(&self) -> bool {
+        self.catching_up
+    }
+
+    /// Compara nuestra altura con la de la red (ver get_network_height).
+    /// Si la red nos supera por mas de BEHIND_NETWORK_THRESHOLD bloques, el nodo pasa a estar alcanzandola:
+    /// la interfaz grafica muestra el deficit de bloques y no se indica que el nodo esta listo hasta alcanzarla.
+    /// Se sale de ese estado cuando nuestro ultimo header llega a la altura de la red.
+    /// Solo se avisa a la GUI cuando se entra o se sale de ese estado. Sin peers no se conoce la altura de la red
+    /// y el estado no cambia.
+    /// Devuelve true si el nodo acaba de quedar atrasado.
+    fn update_network_position(&mut self) -> Result<bool, CustomError> {
+        if self.peers.is_empty() {
+            return Ok(false);
+        }
+        let blocks_behind = self.get_blocks_behind();
+        let was_catching_up = self.catching_up;
+
+        if !self.catching_up && blocks_behind > BEHIND_NETWORK_THRESHOLD {
+            self.catching_up = true;
+        } else if self.catching_up && blocks_behind == 0 {
+            self.catching_up = false;
+        }
+
+        if self.catching_up && !was_catching_up {
+            self.gui_sender
+                .send(GUIEvents::BehindNetwork { blocks_behind })?;
+        } else if !self.catching_up && was_catching_up {
+            send_log(
+                &self.logger_sender,
+                Log::Message(String::from("Node caught up with the network")),
+            );
+            self.gui_sender.send(GUIEvents::CaughtUpWithNetwork)?;
+            if self.is_synced() {
+                self.gui_sender.send(GUIEvents::NodeStateReady)?;
+            }
+        }
+
+        Ok(self.catching_up && !was_catching_up)
+    }
+
+    /// Devuelve la cantidad de bloques descargados de los posteriores a START_DATE_IBD y el porcentaje que representan.
+    fn blocks_download_progress(&self) -> Result<(usize, u64), CustomError> {
+        let total_blocks = self.headers.total_headers_to_download();
+        let pending_blocks = self.blocks.pending_blocks_ref.lock()?.len();
+        let downloaded_blocks = total_blocks.saturating_sub(pending_blocks);
+        let progress = match total_blocks {
+            0 => 0,
+            total => (downloaded_blocks * 100 / total) as u64,
+        };
+        Ok((downloaded_blocks, progress))
+    }
+
+    /// Devuelve un StatusSnapshot con la etapa de sincronizacion y el estado actual del nodo.
+    pub fn get_status_snapshot(&self) -> Result<StatusSnapshot, CustomError> {
+        let tip_height = self.headers.get_all().len();
+
+        let (phase, progress) = if !self.headers.is_synced() {
+            (SyncPhase::Headers, self.headers.get_progress()?)
+        } else if !self.blocks.is_synced() {
+            let (_, progress) = self.blocks_download_progress()?;
+            (SyncPhase::Blocks, progress)
+        } else if !self.utxo.is_synced() {
+            (SyncPhase::Utxo, 0)
+        } else {
+            (SyncPhase::Synced, 100)
+        };
+
+        // la velocidad se conoce recien con el proximo HeadersProgress o BlocksProgress
+        Ok(StatusSnapshot {
+            phase,
+            progress,
+            rate: 0,
+            peers: self.peers.len(),
+            recent_disconnects: self.recent_disconnects(),
+            tip_height,
+            mempool_size: self.pending_txs.len(),
+            utxo_total: self.utxo.total_value(),
+            maintenance_tasks: self.maintenance_tasks.clone(),
+        })
+    }
+
     /********************     WALLETS     ********************/
 
     /// Devuelve todas las wallets del nodo
@@ -231,22 +1209,317 @@ impl NodeState {
     }
 
     /// Agrega una wallet nueva a WalletState
+    /// La public key puede ser la direccion de la public key comprimida o sin comprimir de la private key,
+    /// y la wallet firma con la que corresponda (ver pubkey_is_compressed). Si no es ninguna devuelve un error de validacion.
     pub fn append_wallet(
         &mut self,
         name: String,
         public_key: String,
         private_key: String,
     ) -> Result<(), CustomError> {
-        let new_wallet = Wallet::new(name, public_key, private_key, &self.utxo)?;
-        self.wallets.append(new_wallet)
+        let mut new_wallet = Wallet::new(name, public_key.clone(), private_key, &self.utxo)?;
+        new_wallet.compressed_pubkey =
+            pubkey_is_compressed(&new_wallet.pubkey, &new_wallet.privkey)?;
+        self.wallets.append(new_wallet)?;
+        self.reload_bloom_filter()?;
+        self.request_provisional_history(&[public_key])
     }
 
-    /// Devuelve la wallet activa de WalletState
-    pub fn get_active_wallet(&self) -> Option<&Wallet> {
-        self.wallets.get_active()
-    }
+    /// Agrega a WalletState una wallet watch-only, sin private key: sigue el balance, las UTXO y el historial
+    /// de la direccion igual que cualquier otra wallet, pero no puede firmar transacciones (ver send_many).
+    /// Si ya existe una wallet con esa public key devuelve un error de validacion.
+    pub fn append_watch_only_wallet(
+        &mut self,
+        name: String,
+        public_key: String,
+    ) -> Result<(), CustomError> {
+        let new_wallet = Wallet::watch_only(name, public_key)?;
+        if self.wallets.contains(&new_wallet.pubkey) {
+            return Err(CustomError::Validation(
+                "Public key already exists".to_string(),
+            ));
+        }
+        self.import_watch_only_wallets(vec![new_wallet])?;
+        Ok(())
+    }
+
+    /// Agrega a WalletState una wallet watch-only que sigue las direcciones derivadas de una extended public key
+    /// (xpub o tpub), con xpub_gap_limit direcciones sin usar en cada cadena (ver Wallet::from_xpub).
+    /// Su historial se completa con el mismo rescan que las demas wallets watch-only.
+    /// Devuelve la public key de la wallet, su primera direccion de recepcion.
+    /// Si ya existe una wallet con esa public key devuelve un error de validacion.
+    pub fn append_xpub_wallet(&mut self, name: String, xpub: &str) -> Result<String, CustomError> {
+        let new_wallet = Wallet::from_xpub(name, xpub, self.xpub_gap_limit)?;
+        let public_key = new_wallet.pubkey.clone();
+        if self.wallets.contains(&public_key) {
+            return Err(CustomError::Validation(
+                "Public key already exists".to_string(),
+            ));
+        }
+        self.import_watch_only_wallets(vec![new_wallet])?;
+        Ok(public_key)
+    }
+
+    /// Cambia la cantidad de direcciones sin usar que se derivan en cada cadena de las cuentas xpub que se importan.
+    pub fn set_xpub_gap_limit(&mut self, xpub_gap_limit: u32) {
+        self.xpub_gap_limit = xpub_gap_limit;
+    }
+
+    /// Asigna un nombre a una direccion, que se muestra en Counterparties y en el historial (ver WalletsState::set_label)
+    pub fn set_address_label(&mut self, address: &str, label: &str) -> Result<(), CustomError> {
+        self.wallets.set_label(address, label)
+    }
+
+    /// Devuelve los nombres de las direcciones conocidas (ver WalletsState::get_labels)
+    pub fn get_address_labels(&self) -> HashMap<String, String> {
+        self.wallets.get_labels()
+    }
+
+    /// Devuelve true si las wallets tienen passphrase y todavia no se desbloquearon
+    pub fn are_wallets_locked(&self) -> bool {
+        self.wallets.is_locked()
+    }
+
+    /// Devuelve true si las private keys de las wallets se guardan cifradas con un passphrase
+    pub fn are_wallets_encrypted(&self) -> bool {
+        self.wallets.is_encrypted()
+    }
+
+    /// Establece el passphrase con el que se cifran las private keys de las wallets
+    pub fn set_wallets_passphrase(&mut self, passphrase: &str) -> Result<(), CustomError> {
+        self.wallets.set_passphrase(passphrase)
+    }
+
+    /// Desbloquea las wallets con su passphrase, lo que permite firmar transacciones
+    pub fn unlock_wallets(&mut self, passphrase: &str) -> Result<(), CustomError> {
+        self.wallets.unlock(passphrase)
+    }
+
+    /// Importa wallets watch-only a WalletState, con un unico rescan de las UTXO para todas ellas.
+    /// Notifica a la interfaz grafica las wallets importadas que ya tenian movimientos.
+    /// Devuelve las public keys de las wallets importadas (las que ya existian se ignoran).
+    pub fn import_watch_only_wallets(
+        &mut self,
+        wallets: Vec<Wallet>,
+    ) -> Result<Vec<String>, CustomError> {
+        let imported = self.wallets.import_watch_only(wallets, &self.utxo)?;
+        if !imported.is_empty() {
+            self.reload_bloom_filter()?;
+            self.request_provisional_history(&imported)?;
+        }
+
+        let affected: Vec<String> = self
+            .wallets
+            .get_all()
+            .iter()
+            .filter(|wallet| imported.contains(&wallet.pubkey) && !wallet.history.is_empty())
+            .map(|wallet| wallet.pubkey.clone())
+            .collect();
+        if !affected.is_empty() {
+            self.gui_sender
+                .send(GUIEvents::WalletsUpdated { affected })?;
+        }
+        Ok(imported)
+    }
+
+    /// Arma el filtro bloom de BIP 37 con lo que identifica a las transacciones de las wallets:
+    /// los hashes de las public keys de cada wallet (para los outputs que recibe) y sus UTXO (para los inputs que las gastan).
+    pub fn bloom_filter(&self, wallets: &[Wallet]) -> Result<BloomFilter, CustomError> {
+        let mut elements = vec![];
+        for wallet in wallets {
+            elements.extend(wallet.get_pubkey_hashes()?);
+            for (outpoint, _) in self.utxo.generate_wallet_utxo(wallet)? {
+                elements.push(outpoint.serialize());
+            }
+        }
+
+        let mut filter = BloomFilter::new(
+            elements.len(),
+            BLOOM_FALSE_POSITIVE_RATE,
+            random_nonce() as u32,
+            BLOOM_UPDATE_ALL,
+        );
+        for element in elements {
+            filter.insert(&element);
+        }
+        Ok(filter)
+    }
+
+    /// En spv_mode vuelve a cargar el filtro bloom en todos los peers, por ejemplo al agregar una wallet.
+    /// Los peers a los que no se les puede enviar se desconectan.
+    pub fn reload_bloom_filter(&mut self) -> Result<(), CustomError> {
+        if !self.spv_mode {
+            return Ok(());
+        }
+        let addresses: Vec<SocketAddrV6> = self.peers.iter().map(|peer| peer.address).collect();
+        self.load_bloom_filter(&addresses)
+    }
+
+    /// Envia el filtro bloom de las wallets a los peers indicados que anuncian NODE_BLOOM, desconectando a los que
+    /// no se les puede enviar. A los que no lo anuncian no se les envia, tampoco se les piden bloques filtrados.
+    fn load_bloom_filter(&mut self, addresses: &[SocketAddrV6]) -> Result<(), CustomError> {
+        let filter_load = FilterLoad::new(self.bloom_filter(self.wallets.get_all())?);
+        let mut failed_peers = vec![];
+        for peer in self.peers.iter_mut() {
+            if !addresses.contains(&peer.address) || !serves_bloom(peer.services) {
+                continue;
+            }
+            if let Err(error) = peer.send(&filter_load) {
+                failed_peers.push((peer.address, DisconnectReason::from_error(&error)));
+            }
+        }
+        for (address, reason) in failed_peers {
+            self.remove_peer(address, reason)?;
+        }
+        Ok(())
+    }
+
+    /// Mientras se descargan los bloques, pide al peer mas rapido de los que anuncian NODE_BLOOM los bloques filtrados
+    /// (merkleblock) que faltan descargar con las transacciones de las wallets indicadas, para mostrar un historial
+    /// provisional antes de terminar el IBD.
+    /// Le carga al peer un filtro bloom solo con esas wallets y se lo saca (filterclear) luego del getdata.
+    /// No hace nada en spv_mode (todos los bloques ya son filtrados), si los headers no estan sincronizados o si ya
+    /// se descargaron todos los bloques.
+    pub fn request_provisional_history(&mut self, pubkeys: &[String]) -> Result<(), CustomError> {
+        if self.spv_mode || !self.headers.is_synced() || self.blocks.is_synced() {
+            return Ok(());
+        }
+        let wallets: Vec<Wallet> = self
+            .wallets
+            .get_all()
+            .iter()
+            .filter(|wallet| pubkeys.contains(&wallet.pubkey))
+            .cloned()
+            .collect();
+        let block_hashes = self.blocks.pending_blocks_ref.lock()?.block_hashes();
+        if wallets.is_empty() || block_hashes.is_empty() {
+            return Ok(());
+        }
+
+        let filter_load = FilterLoad::new(self.bloom_filter(&wallets)?);
+        let peer = self
+            .peers
+            .iter_mut()
+            .filter(|peer| serves_bloom(peer.services))
+            .max_by(|a, b| a.stats.cmp_speed(&b.stats));
+        let Some(peer) = peer else { return Ok(()) };
+        let address = peer.address;
+        let mut result = peer.send(&filter_load);
+        for chunk in block_hashes.chunks(MAX_INV_ENTRIES) {
+            let inventories = chunk
+                .iter()
+                .map(|block_hash| Inventory::new(InventoryType::FilteredBlock, block_hash.clone()))
+                .collect();
+            result = result.and_then(|_| peer.send(&GetData::new(inventories)));
+        }
+        result = result.and_then(|_| peer.send(&FilterClear::new()));
+
+        if let Err(error) = result {
+            return self.remove_peer(address, DisconnectReason::from_error(&error));
+        }
+        send_log(
+            &self.logger_sender,
+            Log::Message(format!(
+                "Requesting provisional history of {} wallets in {} filtered blocks",
+                wallets.len(),
+                block_hashes.len()
+            )),
+        );
+        Ok(())
+    }
+
+    /// Agrega a las wallets los movimientos provisionales de un bloque filtrado (ver WalletsState::update_provisional).
+    /// Si el bloque ya se descargo o no esta en la cadena se ignora, el bloque completo es el que vale.
+    pub fn append_provisional_block(&mut self, block: &Block) -> Result<(), CustomError> {
+        let block_hash = block.header.hash();
+        if !self.headers.contains(block_hash) || !self.is_block_pending(block_hash)? {
+            return Ok(());
+        }
+        let affected = self.wallets.update_provisional(block, &self.utxo)?;
+        if !affected.is_empty() {
+            self.gui_sender
+                .send(GUIEvents::WalletsUpdated { affected })?;
+        }
+        Ok(())
+    }
+
+    /// Devuelve el porcentaje de bloques descargados del que depende el historial de las wallets, o None si ya se
+    /// descargaron todos y el historial esta completo.
+    /// Todas las wallets se sincronizan desde START_DATE_IBD, por lo que el progreso es el mismo para todas.
+    pub fn wallet_history_progress(&self) -> Result<Option<u64>, CustomError> {
+        if self.blocks.is_synced() {
+            return Ok(None);
+        }
+        let (_, progress) = self.blocks_download_progress()?;
+        Ok(Some(progress))
+    }
+
+    /// Devuelve la wallet activa de WalletState
+    pub fn get_active_wallet(&self) -> Option<&Wallet> {
+        self.wallets.get_active()
+    }
+
+    /// Devuelve el historial de la wallet activa con los metadatos de cada movimiento (ver WalletMetaState),
+    /// o None si no hay wallet activa.
+    pub fn get_active_wallet_history(&mut self) -> Result<Option<Vec<MovementView>>, CustomError> {
+        let Some(wallet) = self.wallets.get_active() else { return Ok(None) };
+        Ok(Some(self.wallet_meta.history_view(wallet)?))
+    }
+
+    /// Devuelve los metadatos de un movimiento de una wallet, vacios si no tiene.
+    /// Devuelve CustomError::WalletNotFound si no hay una wallet con esa public key.
+    pub fn get_movement_meta(
+        &mut self,
+        pubkey: &str,
+        tx_hash: &[u8],
+    ) -> Result<MovementMeta, CustomError> {
+        if !self.wallets.contains(pubkey) {
+            return Err(CustomError::WalletNotFound);
+        }
+        self.wallet_meta.get(pubkey, tx_hash)
+    }
+
+    /// Reemplaza los metadatos de un movimiento de una wallet, los guarda y avisa a la interfaz grafica.
+    /// Devuelve CustomError si no hay una wallet con esa public key o los metadatos no son validos.
+    pub fn set_movement_meta(
+        &mut self,
+        pubkey: &str,
+        tx_hash: &[u8],
+        meta: MovementMeta,
+    ) -> Result<(), CustomError> {
+        if !self.wallets.contains(pubkey) {
+            return Err(CustomError::WalletNotFound);
+        }
+        self.wallet_meta.set(pubkey, tx_hash, meta)?;
+        self.gui_sender.send(GUIEvents::WalletsUpdated {
+            affected: vec![pubkey.to_string()],
+        })?;
+        Ok(())
+    }
+
+    /// Cambia el nombre de una wallet de WalletState (ver WalletsState::rename)
+    /// y avisa a la interfaz grafica que cambio la lista de wallets.
+    pub fn rename_wallet(&mut self, public_key: &str, new_name: &str) -> Result<(), CustomError> {
+        self.wallets.rename(public_key, new_name)?;
+        self.gui_sender.send(GUIEvents::WalletListChanged)?;
+        Ok(())
+    }
+
+    /// Quita una wallet de WalletState junto a sus metadatos (ver WalletsState::remove), sin modificar el historial de las demas,
+    /// y avisa a la interfaz grafica que cambio la lista de wallets.
+    /// Si era la wallet activa el nodo queda sin wallet activa y tambien envia WalletChanged, para que las vistas se vacien.
+    pub fn remove_wallet(&mut self, public_key: &str) -> Result<(), CustomError> {
+        let was_active = self.wallets.remove(public_key, &mut self.wallet_meta)?;
+        self.reload_bloom_filter()?;
+        self.gui_sender.send(GUIEvents::WalletListChanged)?;
+        if was_active {
+            self.gui_sender.send(GUIEvents::WalletChanged)?;
+        }
+        Ok(())
+    }
 
     /// Cambia la wallet activa de WalletState
+    /// Solo notifica a la interfaz grafica si el cambio fue exitoso.
     pub fn change_wallet(&mut self, public_key: String) -> Result<(), CustomError> {
         self.wallets.set_active(&public_key)?;
         self.gui_sender.send(GUIEvents::WalletChanged)?;
@@ -254,12 +1527,30 @@ impl NodeState {
     }
 
     /// Actualiza las wallets de WalletState
+    /// Antes extiende el gap de las cuentas xpub con actividad en el bloque y, si derivaron direcciones nuevas,
+    /// vuelve a cargar el filtro bloom para recibir sus transacciones.
+    /// Notifica a la interfaz grafica cuales wallets tuvieron movimientos, aunque ninguna sea la wallet activa,
+    /// encola un evento de confirmacion para el webhook por cada movimiento del bloque y alerta los pagos grandes
+    /// que no alertaron como pendientes (ver alert_large_payment).
     pub fn update_wallets(&mut self, block: &Block) -> Result<(), CustomError> {
-        let wallets_updated = self.wallets.update(block, &self.utxo)?;
-        if wallets_updated {
+        let extended = self.wallets.extend_xpub_gaps(block, &self.utxo)?;
+        if !extended.is_empty() {
+            self.reload_bloom_filter()?;
+        }
+        let mut affected = self.wallets.update(block, &self.utxo)?;
+        for pubkey in extended {
+            if !affected.contains(&pubkey) {
+                affected.push(pubkey);
+            }
+        }
+        if !affected.is_empty() {
+            let movements = self.block_movements(block.header.hash(), &affected);
+            self.queue_confirmation_events(&movements)?;
+            for (wallet, movement) in movements {
+                self.alert_large_payment(wallet, &movement, true)?;
+            }
             self.gui_sender
-                .send(GUIEvents::WalletsUpdated)
-                .map_err(|_| CustomError::CannotInitGUI)?;
+                .send(GUIEvents::WalletsUpdated { affected })?;
         }
         Ok(())
     }
@@ -278,11 +1569,238 @@ impl NodeState {
         self.utxo.generate_wallet_utxo(active_wallet)
     }
 
+    /// Devuelve el UTXO de la wallet activa junto a las confirmaciones de cada UTXO
+    pub fn get_active_wallet_utxo_with_confirmations(
+        &self,
+    ) -> Result<Vec<(OutPoint, UTXOValue, u32)>, CustomError> {
+        Ok(self
+            .get_active_wallet_utxo()?
+            .into_iter()
+            .map(|(out_point, value)| {
+                let confirmations = self.headers.get_confirmations(&value.block_hash);
+                (out_point, value, confirmations)
+            })
+            .collect())
+    }
+
+    /// Exporta las UTXO guardadas en el store junto al bloque hasta el que estan procesadas.
+    /// Devuelve CustomError::NodeNotSynced si todavia no se generaron las UTXO,
+    /// o CustomError::UtxoSnapshotNotOnChain si su bloque no esta en la cadena de headers.
+    pub fn export_utxo_snapshot(&self) -> Result<UtxoSnapshot, CustomError> {
+        let Some((block_hash, tx_set)) = self.utxo.read_saved()? else { return Err(CustomError::NodeNotSynced) };
+        let height = self
+            .headers
+            .get_height(&block_hash)
+            .ok_or(CustomError::UtxoSnapshotNotOnChain)?;
+
+        Ok(UtxoSnapshot {
+            block_hash,
+            height,
+            tx_set,
+        })
+    }
+
+    /// Importa un snapshot de las UTXO en un nodo que todavia no las genero, para no descargar ni procesar
+    /// los bloques hasta el del snapshot. Solo se verifica que ese bloque este en la cadena de headers, con su height,
+    /// y que los headers hasta el se encadenen desde genesis cumpliendo la proof of work: las UTXO se confian.
+    /// Los bloques anteriores dejan de estar pendientes y al descargar los posteriores las UTXO continuan desde el snapshot.
+    /// Devuelve CustomError::UtxoSnapshotNotOnChain si el bloque no esta en la cadena de headers.
+    pub fn import_utxo_snapshot(&mut self, snapshot: UtxoSnapshot) -> Result<(), CustomError> {
+        if self.utxo.is_synced() || self.utxo.read_saved()?.is_some() {
+            return Err(CustomError::Validation(
+                "UTXO snapshots can only be imported into a node that has not generated its UTXOs yet"
+                    .to_string(),
+            ));
+        }
+        if snapshot.height == 0
+            || self.headers.get_height(&snapshot.block_hash) != Some(snapshot.height)
+        {
+            return Err(CustomError::UtxoSnapshotNotOnChain);
+        }
+
+        let chain = &self.headers.get_all()[..snapshot.height];
+        let linked = chain[0].prev_block_hash == GENESIS
+            && chain
+                .windows(2)
+                .all(|pair| pair[1].prev_block_hash == *pair[0].hash());
+        if !linked
+            || chain
+                .iter()
+                .any(|header| header.verify_proof_of_work().is_err())
+        {
+            return Err(CustomError::UtxoSnapshotNotOnChain);
+        }
+        let skipped_blocks: Vec<Vec<u8>> =
+            chain.iter().map(|header| header.hash().clone()).collect();
+
+        let utxo_count = snapshot.tx_set.len();
+        self.utxo
+            .install_snapshot(snapshot.block_hash, snapshot.tx_set)?;
+
+        let mut pending_blocks = self.blocks.pending_blocks_ref.lock()?;
+        for block_hash in &skipped_blocks {
+            pending_blocks.remove_block(block_hash)?;
+        }
+        drop(pending_blocks);
+
+        send_log(
+            &self.logger_sender,
+            Log::Message(format!(
+                "UTXO snapshot imported ({} UTXOs at height {})",
+                utxo_count, snapshot.height
+            )),
+        );
+        self.verify_sync()
+    }
+
     /********************     PENDING TXs     ********************/
 
-    /// Actualiza las pending txs de PendingTxs
+    /// Actualiza las pending txs de PendingTxs y registra en el FeeEstimator cuanto tardaron en confirmarse.
+    /// Luego pide reenviar las transacciones que creamos que siguen sin confirmarse (ver request_rebroadcasts).
     pub fn update_pending_tx(&mut self, block: &Block) -> Result<(), CustomError> {
-        self.pending_txs.update_pending_tx(block)
+        let height = self.headers.get_height(block.header.hash());
+        if let Some(height) = height {
+            self.fee_estimator.confirm_block(block, height);
+        }
+        self.pending_txs.update_pending_tx(block)?;
+        if let Some(height) = height {
+            self.request_rebroadcasts(height);
+        }
+        self.notify_mempool_changed()
+    }
+
+    /// Avisa a la GUI la cantidad de pending txs con GUIEvents::MempoolChanged.
+    fn notify_mempool_changed(&self) -> Result<(), CustomError> {
+        self.gui_sender.send(GUIEvents::MempoolChanged {
+            size: self.pending_txs.len(),
+        })?;
+        Ok(())
+    }
+
+    /// Activa el reenvio de las transacciones que creamos: se piden al NodeActionLoop por rebroadcast_sender
+    /// con NodeAction::RebroadcastTransactions.
+    pub fn enable_rebroadcasts(&mut self, rebroadcast_sender: mpsc::Sender<NodeAction>) {
+        self.rebroadcast_sender = Some(rebroadcast_sender);
+    }
+
+    /// Cambia la cantidad de bloques sin confirmarse tras los cuales se vuelve a enviar una transaccion que creamos.
+    pub fn set_rebroadcast_blocks(&mut self, rebroadcast_blocks: usize) {
+        self.rebroadcast_blocks = rebroadcast_blocks;
+    }
+
+    /// Pide reenviar las transacciones que creamos que siguen sin confirmarse rebroadcast_blocks bloques despues de su
+    /// ultimo envio, con la cadena en height. Si no se activaron los reenvios no hace nada.
+    fn request_rebroadcasts(&mut self, height: usize) {
+        let Some(rebroadcast_sender) = &self.rebroadcast_sender else { return };
+        let tx_hashes = self
+            .pending_txs
+            .due_rebroadcasts(height, self.rebroadcast_blocks);
+        if !tx_hashes.is_empty() {
+            // si el NodeActionLoop termino no hay quien las reenvie
+            let _ = rebroadcast_sender.send(NodeAction::RebroadcastTransactions(tx_hashes));
+        }
+    }
+
+    /// Pide reenviar ahora una pending tx enviada por alguna de nuestras wallets (por ejemplo desde la GUI),
+    /// y vuelve a contar desde ahora los bloques para su proximo reenvio.
+    /// Devuelve CustomError si:
+    /// - No es una pending tx (TransactionNotFound).
+    /// - No la envio ninguna de nuestras wallets.
+    pub fn rebroadcast_transaction(&mut self, tx_hash: &Vec<u8>) -> Result<(), CustomError> {
+        let Some(transaction) = self.pending_txs.get_pending_tx(tx_hash) else { return Err(CustomError::TransactionNotFound) };
+        if !self.wallets.is_authored(&transaction, &self.utxo)? {
+            return Err(CustomError::Validation(
+                "Only transactions sent by our wallets can be rebroadcast".to_string(),
+            ));
+        }
+
+        self.pending_txs
+            .mark_own(tx_hash, self.headers.get_all().len());
+        if let Some(rebroadcast_sender) = &self.rebroadcast_sender {
+            rebroadcast_sender.send(NodeAction::RebroadcastTransactions(vec![tx_hash.clone()]))?;
+        }
+        Ok(())
+    }
+
+    /// Estima el fee por byte (sat/vB) para que una transaccion se confirme en target_blocks bloques
+    pub fn estimate_fee_rate(&self, target_blocks: u16) -> u64 {
+        self.fee_estimator.estimate_fee_rate(target_blocks)
+    }
+
+    /// Devuelve el fee por byte de la transaccion, si se conocen los valores de todos sus inputs (ver fee_of)
+    fn fee_rate_of(&self, transaction: &Transaction) -> Result<Option<u64>, CustomError> {
+        Ok(self
+            .fee_of(transaction)?
+            .map(|fee| fee / transaction.serialize().len() as u64))
+    }
+
+    /// Devuelve el fee de la transaccion, si se conocen los valores de todos sus inputs
+    /// (estan en las UTXO o son outputs de otras pending txs).
+    /// Devuelve CustomError::TransactionValueOverflow si la suma de sus inputs o de sus outputs no entra en un u64,
+    /// por lo que la transaccion es invalida.
+    fn fee_of(&self, transaction: &Transaction) -> Result<Option<u64>, CustomError> {
+        let output_value = transaction
+            .outputs
+            .iter()
+            .try_fold(0_u64, |total, output| total.checked_add(output.value))
+            .ok_or(CustomError::TransactionValueOverflow)?;
+        let mut input_value: u64 = 0;
+        for input in &transaction.inputs {
+            let outpoint = &input.previous_output;
+            let value = match self.utxo.tx_set.get(outpoint) {
+                Some(value) => value.tx_out.value,
+                None => {
+                    let value =
+                        self.pending_txs
+                            .get_pending_tx(&outpoint.hash)
+                            .and_then(|prev_tx| {
+                                Some(prev_tx.outputs.get(outpoint.index as usize)?.value)
+                            });
+                    let Some(value) = value else { return Ok(None) };
+                    value
+                }
+            };
+            input_value = input_value
+                .checked_add(value)
+                .ok_or(CustomError::TransactionValueOverflow)?;
+        }
+        Ok(input_value.checked_sub(output_value))
+    }
+
+    /// Elimina de las pending txs restauradas las que ya estan incluidas en un bloque guardado.
+    /// Solo se revisan los bloques con timestamp posterior a la pending tx mas antigua,
+    /// con un margen de BLOCK_TIMESTAMP_TOLERANCE ya que el timestamp de un bloque puede estar atrasado.
+    fn remove_confirmed_pending_txs(&mut self) -> Result<(), CustomError> {
+        let Some(oldest_received_at) = self.pending_txs.oldest_received_at() else { return Ok(()) };
+        let since = oldest_received_at.saturating_sub(BLOCK_TIMESTAMP_TOLERANCE);
+
+        for header in self
+            .headers
+            .get_all()
+            .iter()
+            .rev()
+            .take_while(|header| header.timestamp as u64 >= since)
+        {
+            if let Ok(block) = self.blocks.get_block(header.hash()) {
+                self.pending_txs.update_pending_tx(&block)?;
+            }
+        }
+
+        self.notify_mempool_changed()
+    }
+
+    /// Vuelve a registrar como propias las pending txs restauradas que envio alguna de nuestras wallets (ver
+    /// WalletsState::is_authored), para seguir reenviandolas si no se confirman. Se cuentan los bloques desde la altura actual.
+    /// Se llama al generar las UTXO, que se necesitan para saber quien gasta los inputs.
+    fn mark_own_pending_txs(&mut self) -> Result<(), CustomError> {
+        let height = self.headers.get_all().len();
+        for tx_hash in self.pending_txs.get_all_hashes() {
+            let Some(transaction) = self.pending_txs.get_pending_tx(&tx_hash) else { continue };
+            if self.wallets.is_authored(&transaction, &self.utxo)? {
+                self.pending_txs.mark_own(&tx_hash, height);
+            }
+        }
+        Ok(())
     }
 
     /// Devuelve las pending txs de la wallet activa
@@ -292,28 +1810,612 @@ impl NodeState {
         self.pending_txs.from_wallet(active_wallet, &self.utxo)
     }
 
-    /// Agrega una pending tx nueva a PendingTxs
+    /// Agrega una pending tx nueva a PendingTxs, y luego las transacciones huerfanas que esperaban sus outputs.
+    /// Si le envia fondos a alguna wallet encola un evento para el webhook y, si es un pago grande, lo alerta
+    /// (ver alert_large_payment).
+    /// Si gasta un output que todavia no se conoce (ver missing_input) la guarda como huerfana y devuelve false,
+    /// para agregarla cuando llegue el bloque o la transaccion que lo crea.
+    /// Si la suma de sus inputs o de sus outputs no entra en un u64 (ver fee_of) la descarta y devuelve false.
     pub fn append_pending_tx(&mut self, transaction: Transaction) -> Result<bool, CustomError> {
-        let updated = self.pending_txs.append_pending_tx(transaction);
+        if let Some(outpoint) = self.missing_input(&transaction) {
+            self.orphan_txs.insert(outpoint, transaction);
+            send_log(
+                &self.logger_sender,
+                Log::Message("Orphan transaction stored until its inputs arrive".to_string()),
+            );
+            return Ok(false);
+        }
+
+        let tx_hash = transaction.hash();
+        let fee_rate = match self.fee_rate_of(&transaction) {
+            // el tiempo de confirmacion solo es representativo con el nodo sincronizado
+            Ok(fee_rate) => fee_rate.filter(|_| self.is_synced()),
+            Err(error) => {
+                send_log(
+                    &self.logger_sender,
+                    Log::Message(format!(
+                        "Rejected transaction {}: {error}",
+                        hash_as_string(tx_hash)
+                    )),
+                );
+                return Ok(false);
+            }
+        };
+        let outputs = transaction.outputs.len();
+        let incoming = self.wallet_movements(&transaction)?;
+        let updated = self.pending_txs.append_pending_tx(transaction)?;
+        if let (true, Some(fee_rate)) = (updated, fee_rate) {
+            self.fee_estimator
+                .track(tx_hash.clone(), fee_rate, self.headers.get_all().len());
+        }
 
         if updated {
-            self.gui_sender
-                .send(GUIEvents::NewPendingTx)
-                .map_err(|_| CustomError::CannotInitGUI)?;
+            for (wallet, movement) in incoming {
+                if movement.value > 0 {
+                    self.queue_webhook_event(
+                        WebhookEventKind::IncomingPending,
+                        wallet.clone(),
+                        &movement,
+                        0,
+                    )?;
+                    self.alert_large_payment(wallet, &movement, false)?;
+                }
+            }
+            self.gui_sender.send(GUIEvents::NewPendingTx)?;
+            self.notify_mempool_changed()?;
             send_log(
                 &self.logger_sender,
                 Log::Message("New pending transaction received".to_string()),
             );
+            for orphan in self.take_orphan_txs(&tx_hash, outputs) {
+                self.append_pending_tx(orphan)?;
+            }
         }
 
         Ok(updated)
     }
 
+    /// Devuelve el primer input de la transaccion que no esta en las UTXO ni es un output de una pending tx,
+    /// si todavia hay bloques pendientes de procesar que podrian crearlo.
+    /// Como las UTXO arrancan en START_DATE_IBD, sin bloques pendientes un input desconocido puede gastar un output
+    /// anterior y la transaccion se acepta.
+    fn missing_input(&self, transaction: &Transaction) -> Option<OutPoint> {
+        if !self.utxo.is_synced() || self.is_pending_blocks_empty().unwrap_or(true) {
+            return None;
+        }
+        transaction
+            .inputs
+            .iter()
+            .map(|input| &input.previous_output)
+            .find(|outpoint| {
+                !self.utxo.tx_set.contains_key(*outpoint)
+                    && !self.pending_txs.contains_output(outpoint)
+            })
+            .cloned()
+    }
+
     /// Devuelve una pending tx de PendingTxs en base a su hash
     pub fn get_pending_tx(&self, tx_hash: &Vec<u8>) -> Option<Transaction> {
         self.pending_txs.get_pending_tx(tx_hash)
     }
 
+    /// Registra los peers a los que se reenvio una transaccion que creamos (ver PendingTxs::mark_sent_to).
+    pub fn mark_transaction_sent(&mut self, tx_hash: &Vec<u8>, addresses: &[SocketAddrV6]) {
+        self.pending_txs.mark_sent_to(tx_hash, addresses);
+    }
+
+    /// Devuelve los hashes de todas las pending txs de PendingTxs
+    pub fn get_pending_tx_hashes(&self) -> Vec<Vec<u8>> {
+        self.pending_txs.get_all_hashes()
+    }
+
+    /// Abandona una pending tx enviada por alguna de nuestras wallets.
+    /// La saca de PendingTxs, liberando sus inputs para que puedan usarse en una nueva transaccion,
+    /// y la marca como abandonada en los metadatos de las wallets involucradas (ver WalletMetaState).
+    /// Si la transaccion se confirma igual mas adelante, el movimiento abandonado se reemplaza por el confirmado.
+    /// Los anuncios de la transaccion que hagan los peers despues se ignoran (ver is_abandoned).
+    /// Devuelve CustomError si:
+    /// - La transaccion no esta en PendingTxs.
+    /// - La transaccion no fue enviada por ninguna de nuestras wallets.
+    /// - Algun peer anuncio la transaccion recientemente.
+    pub fn abandon_transaction(&mut self, tx_hash: &Vec<u8>) -> Result<(), CustomError> {
+        let Some(transaction) = self.pending_txs.get_pending_tx(tx_hash) else { return Err(CustomError::TransactionNotFound) };
+
+        if !self.wallets.is_authored(&transaction, &self.utxo)? {
+            return Err(CustomError::Validation(
+                "Only transactions sent by our wallets can be abandoned".to_string(),
+            ));
+        }
+        if self.pending_txs.was_relayed_recently(tx_hash)? {
+            return Err(CustomError::Validation(
+                "Transaction was recently announced by peers".to_string(),
+            ));
+        }
+
+        let affected = self.wallets.mark_abandoned(&transaction, &self.utxo)?;
+        for pubkey in affected {
+            self.wallet_meta
+                .update(&pubkey, tx_hash, |meta| meta.abandoned = true)?;
+        }
+        self.pending_txs.remove_pending_tx(tx_hash)?;
+        self.fee_estimator.forget(tx_hash);
+        self.abandoned_txs.insert(tx_hash.clone());
+
+        self.gui_sender.send(GUIEvents::TransactionAbandoned)?;
+        self.notify_mempool_changed()?;
+        send_log(
+            &self.logger_sender,
+            Log::Message("Pending transaction abandoned".to_string()),
+        );
+
+        Ok(())
+    }
+
+    /// Devuelve true si la transaccion fue abandonada (ver abandon_transaction), por lo que no se acepta de los peers.
+    pub fn is_abandoned(&self, tx_hash: &[u8]) -> bool {
+        self.abandoned_txs.contains(tx_hash)
+    }
+
+    /// Maneja el rechazo de una transaccion por el peer en address (ver Reject). Si es una pending tx que creamos y
+    /// se la enviamos a ese peer la saca de PendingTxs, junto a las pendientes que gastan sus outputs, con lo que dejan
+    /// de figurar en los movimientos pendientes de las wallets y se liberan sus inputs, y le muestra al usuario el motivo
+    /// del rechazo. Los rechazos por duplicada (REJECT_DUPLICATE) no la sacan: el peer ya la tenia.
+    /// Devuelve true si se saco la transaccion.
+    pub fn transaction_rejected(
+        &mut self,
+        address: SocketAddrV6,
+        reject: &Reject,
+    ) -> Result<bool, CustomError> {
+        let Some(tx_hash) = &reject.data else { return Ok(false) };
+        if reject.ccode == REJECT_DUPLICATE || !self.pending_txs.was_sent_to(tx_hash, &address) {
+            return Ok(false);
+        }
+
+        for removed in self.pending_txs.remove_with_descendants(tx_hash)? {
+            self.fee_estimator.forget(&removed);
+        }
+        self.gui_sender
+            .send(GUIEvents::TransactionRejected(reject.describe()))?;
+        self.notify_mempool_changed()?;
+        Ok(true)
+    }
+
+    /// Activa o desactiva el modo SPV (ver spv_mode).
+    pub fn set_spv_mode(&mut self, spv_mode: bool) {
+        self.spv_mode = spv_mode;
+    }
+
+    /// Devuelve true si el nodo es un cliente SPV.
+    pub fn is_spv_mode(&self) -> bool {
+        self.spv_mode
+    }
+
+    /// Indica si el nodo corre en una red regtest (ver regtest).
+    pub fn set_regtest(&mut self, regtest: bool) {
+        self.regtest = regtest;
+        self.headers.set_regtest(regtest);
+    }
+
+    /// Cambia el origen de la cotizacion en moneda fiat (ver Pricing). Con None no se muestran montos en fiat.
+    pub fn set_price_source(&mut self, source: Option<PriceSource>) {
+        self.pricing = Pricing::new(source);
+    }
+
+    /// Devuelve la cotizacion actual de BTC en moneda fiat, o None si no hay (ver Pricing::current_rate).
+    pub fn current_rate(&mut self) -> Option<FiatRate> {
+        self.pricing.current_rate()
+    }
+
+    /// Devuelve true si la cotizacion es manual y se puede cambiar desde la interfaz grafica.
+    pub fn is_manual_rate(&self) -> bool {
+        self.pricing.is_manual()
+    }
+
+    /// Cambia la cotizacion manual (ver Pricing::set_manual_rate).
+    pub fn set_manual_rate(&mut self, rate: f64) -> Result<(), CustomError> {
+        self.pricing.set_manual_rate(rate)
+    }
+
+    /// Cambia cada cuantos bloques se guarda un snapshot completo de las UTXO (ver UTXO::set_snapshot_interval).
+    pub fn set_utxo_snapshot_interval(&mut self, snapshot_interval: u32) {
+        self.utxo.set_snapshot_interval(snapshot_interval);
+    }
+
+    /// Aplica los valores recargados del config que maneja el NodeState (ver ConfigDelta), el resto se ignoran.
+    pub fn apply_config_delta(&mut self, delta: &ConfigDelta) -> Result<(), CustomError> {
+        if let Some(dust_threshold) = delta.dust_threshold {
+            self.set_dust_threshold(dust_threshold);
+        }
+        if let Some(min_spend_confirmations) = delta.min_spend_confirmations {
+            self.set_min_spend_confirmations(min_spend_confirmations)?;
+        }
+        if let Some(alert_threshold_sats) = delta.alert_threshold_sats {
+            self.set_alert_threshold(alert_threshold_sats);
+        }
+        if let Some(stale_block_timeout_secs) = delta.stale_block_timeout_secs {
+            self.set_stale_block_timeout(Duration::from_secs(stale_block_timeout_secs))?;
+        }
+        if let Some(max_notfound_retries) = delta.max_notfound_retries {
+            self.set_max_not_found_retries(max_notfound_retries)?;
+        }
+        if let Some(broadcast_peers) = delta.broadcast_peers {
+            self.set_broadcast_peers(broadcast_peers);
+        }
+        if let Some(utxo_snapshot_interval) = delta.utxo_snapshot_interval {
+            self.set_utxo_snapshot_interval(utxo_snapshot_interval);
+        }
+        if let Some(max_relay_tx_io) = delta.max_relay_tx_io {
+            self.set_max_relay_tx_io(max_relay_tx_io);
+        }
+        if let Some(rebroadcast_blocks) = delta.rebroadcast_blocks {
+            self.set_rebroadcast_blocks(rebroadcast_blocks);
+        }
+        if let Some(price_source) = &delta.price_source {
+            self.set_price_source(price_source.clone());
+        }
+        Ok(())
+    }
+
+    /// Cambia la cantidad maxima de inputs, y de outputs, de las transacciones que se aceptan de los peers.
+    pub fn set_max_relay_tx_io(&mut self, max_relay_tx_io: usize) {
+        self.max_relay_tx_io = max_relay_tx_io;
+    }
+
+    /// Devuelve la cantidad maxima de inputs, y de outputs, de las transacciones que se aceptan de los peers.
+    pub fn get_max_relay_tx_io(&self) -> usize {
+        self.max_relay_tx_io
+    }
+
+    /// Cambia la cantidad de peers a los que se envian las transacciones que creamos (0 las envia a todos sin verificar que se propaguen)
+    pub fn set_broadcast_peers(&mut self, broadcast_peers: usize) {
+        self.broadcast_peers = broadcast_peers;
+    }
+
+    /// Envia una transaccion que creamos a los peers.
+    /// Registra la altura de la cadena en la que se envio, para reenviarla si no se confirma en rebroadcast_blocks bloques.
+    /// Si broadcast_peers es 0 la envia a todos, si no la envia a los primeros broadcast_peers y registra a cuales
+    /// para verificar que se propague (ver transactions_announced y check_tx_propagation).
+    /// Encola un evento para el webhook por cada wallet cuyos fondos gasta.
+    /// Devuelve los peers a desconectar porque no se les pudo enviar la transaccion.
+    pub fn push_transaction(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Vec<(SocketAddrV6, DisconnectReason)> {
+        if let Err(error) = self.queue_outgoing_events(transaction) {
+            send_log(&self.logger_sender, Log::Error(error));
+        }
+        self.pending_txs
+            .mark_own(&transaction.hash(), self.headers.get_all().len());
+        let addresses = self.broadcast_targets();
+        if self.broadcast_peers > 0 {
+            self.pending_txs.start_broadcast(
+                &transaction.hash(),
+                addresses.clone(),
+                Instant::now(),
+            );
+        }
+        self.send_transaction_to(transaction, &addresses)
+    }
+
+    /// Devuelve los peers a los que push_transaction envia las transacciones que creamos:
+    /// todos si broadcast_peers es 0, si no los primeros broadcast_peers.
+    pub fn broadcast_targets(&self) -> Vec<SocketAddrV6> {
+        let mut addresses: Vec<SocketAddrV6> = self.peers.iter().map(|p| p.address).collect();
+        if self.broadcast_peers > 0 {
+            addresses.truncate(self.broadcast_peers);
+        }
+        addresses
+    }
+
+    /// Deshace una transaccion que creamos y que no se le pudo enviar a ningun peer: la saca de PendingTxs,
+    /// liberando sus inputs para que otro envio pueda usarlos.
+    pub fn discard_unsent_transaction(&mut self, tx_hash: &Vec<u8>) -> Result<(), CustomError> {
+        self.pending_txs.remove_pending_tx(tx_hash)?;
+        self.fee_estimator.forget(tx_hash);
+        self.gui_sender.send(GUIEvents::NewPendingTx)?;
+        self.notify_mempool_changed()
+    }
+
+    /// Devuelve los inventarios anunciados por un peer en un inv que hay que pedirle con getdata.
+    /// Se descartan las transacciones que ya estan en las pending txs o que se abandonaron y, mientras el nodo no esta sincronizado, los bloques.
+    /// Luego de sincronizar se piden los bloques cuyo header no conocemos (en spv_mode, como bloques filtrados),
+    /// que quedan como huerfanos hasta que llega su header.
+    /// Cada hash se pide a un unico peer a la vez (ver InventoryTracker).
+    pub fn inventories_to_request(
+        &mut self,
+        address: SocketAddrV6,
+        inventories: Vec<Inventory>,
+    ) -> Vec<Inventory> {
+        let is_synced = self.is_synced();
+        let inventories = inventories
+            .into_iter()
+            .filter_map(|inventory| match inventory.inventory_type {
+                InventoryType::Tx
+                    if self.pending_txs.get_pending_tx(&inventory.hash).is_none()
+                        && !self.is_abandoned(&inventory.hash) =>
+                {
+                    Some(inventory)
+                }
+                InventoryType::Block if is_synced && !self.headers.contains(&inventory.hash) => {
+                    match self.spv_mode {
+                        true => Some(Inventory::new(InventoryType::FilteredBlock, inventory.hash)),
+                        false => Some(inventory),
+                    }
+                }
+                _ => None,
+            })
+            .collect();
+        self.inventory_tracker
+            .to_request(address, inventories, Instant::now())
+    }
+
+    /// Olvida las peticiones de los inventarios que un peer no pudo enviar, para que se puedan volver a pedir.
+    pub fn inventories_not_found(&mut self, inventories: &[Inventory]) {
+        for inventory in inventories {
+            self.inventory_tracker.not_found(&inventory.hash);
+        }
+    }
+
+    /// Registra que un peer respondio notfound a inventarios que le pedimos y devuelve los que se vuelven a pedir.
+    /// Las transacciones no se vuelven a pedir. Los bloques pendientes se vuelven a pedir hasta MAX_NOTFOUND_RETRIES
+    /// veces (ver PendingBlocks::block_not_found); despues se deja de pedirlos y se avisa una unica vez en el log.
+    /// Si el bloque abandonado no es de nuestra cadena (por ejemplo, de una rama que quedo atras en un reorg) se quita
+    /// de los pendientes para que se puedan sincronizar los bloques. Si es de nuestra cadena sigue pendiente, ya que
+    /// sin el no se pueden generar las UTXO, y solo se vuelve a pedir cuando vence su peticion.
+    pub fn data_not_found(
+        &mut self,
+        inventories: &[Inventory],
+    ) -> Result<Vec<Inventory>, CustomError> {
+        self.inventories_not_found(inventories);
+
+        let mut to_retry = vec![];
+        let mut abandoned = false;
+        let mut pending_blocks = self.blocks.pending_blocks_ref.lock()?;
+        for inventory in inventories {
+            if matches!(
+                inventory.inventory_type,
+                InventoryType::Tx | InventoryType::WitnessTx
+            ) {
+                continue;
+            }
+            match pending_blocks.block_not_found(&inventory.hash) {
+                NotFoundAction::Retry => to_retry.push(inventory.clone()),
+                NotFoundAction::GiveUp => {
+                    send_log(
+                        &self.logger_sender,
+                        Log::Warning(format!(
+                            "no peer has block {}, it is no longer requested",
+                            display_hash(&inventory.hash)
+                        )),
+                    );
+                    if !self.headers.contains(&inventory.hash) {
+                        pending_blocks.remove_block(&inventory.hash)?;
+                        abandoned = true;
+                    }
+                }
+                NotFoundAction::Ignore => (),
+            }
+        }
+        drop(pending_blocks);
+
+        if abandoned {
+            self.verify_sync()?;
+        }
+        Ok(to_retry)
+    }
+
+    /// Registra las transacciones que anuncio un peer.
+    /// Si alguna es una transaccion que creamos y es la primera vez que la anuncia un peer al que no se la enviamos,
+    /// se propago por la red y se avisa a la interfaz.
+    pub fn transactions_announced(
+        &mut self,
+        address: SocketAddrV6,
+        tx_hashes: &[Vec<u8>],
+    ) -> Result<(), CustomError> {
+        for tx_hash in tx_hashes {
+            if !self.pending_txs.announced_by(tx_hash, address) {
+                continue;
+            }
+            let txid = hash_as_string(tx_hash.clone());
+            send_log(
+                &self.logger_sender,
+                Log::Message(format!(
+                    "Transaction {txid} was announced by peer {address}"
+                )),
+            );
+            self.gui_sender
+                .send(GUIEvents::TransactionSeenOnNetwork { txid })?;
+        }
+        Ok(())
+    }
+
+    /// Revisa las transacciones que creamos que ningun peer anuncio dentro del timeout desde su ultimo envio.
+    /// Las reenvia a broadcast_peers peers que todavia no las recibieron o, si se agotaron los reenvios,
+    /// avisa a la interfaz que pueden no haberse propagado.
+    /// Devuelve los peers a desconectar porque no se les pudo reenviar alguna transaccion.
+    pub fn check_tx_propagation(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Vec<(SocketAddrV6, DisconnectReason)>, CustomError> {
+        let connected: Vec<SocketAddrV6> = self.peers.iter().map(|p| p.address).collect();
+        let checks = self.pending_txs.check_broadcasts(
+            Instant::now(),
+            timeout,
+            &connected,
+            self.broadcast_peers,
+        );
+
+        let mut failed_peers = vec![];
+        for check in checks {
+            match check {
+                BroadcastCheck::Escalate(tx_hash, addresses) => {
+                    let Some(transaction) = self.pending_txs.get_pending_tx(&tx_hash) else { continue };
+                    send_log(
+                        &self.logger_sender,
+                        Log::Message(format!(
+                            "No peer announced transaction {}, sending it to {} more peers",
+                            hash_as_string(tx_hash),
+                            addresses.len()
+                        )),
+                    );
+                    failed_peers.extend(self.send_transaction_to(&transaction, &addresses));
+                }
+                BroadcastCheck::PossiblyStuck(tx_hash) => {
+                    let txid = hash_as_string(tx_hash);
+                    send_log(
+                        &self.logger_sender,
+                        Log::Message(format!("Transaction {txid} may not have propagated")),
+                    );
+                    self.gui_sender
+                        .send(GUIEvents::TransactionMayNotHavePropagated { txid })?;
+                }
+            }
+        }
+        Ok(failed_peers)
+    }
+
+    fn send_transaction_to(
+        &mut self,
+        transaction: &Transaction,
+        addresses: &[SocketAddrV6],
+    ) -> Vec<(SocketAddrV6, DisconnectReason)> {
+        let mut failed_peers = vec![];
+        let mut sent_to = vec![];
+        for peer in self.peers.iter_mut() {
+            if !addresses.contains(&peer.address) {
+                continue;
+            }
+            match peer.send(transaction) {
+                Ok(()) => sent_to.push(peer.address),
+                Err(error) => {
+                    failed_peers.push((peer.address, DisconnectReason::from_error(&error)))
+                }
+            }
+        }
+        self.pending_txs.mark_sent_to(&transaction.hash(), &sent_to);
+        failed_peers
+    }
+
+    /// Reconstruye un compact block con las pending txs de PendingTxs
+    pub fn reconstruct_compact_block(
+        &self,
+        compact_block: &CompactBlock,
+    ) -> Result<PartialBlock, CustomError> {
+        self.pending_txs.reconstruct_block(compact_block)
+    }
+
+    /********************     MINING     ********************/
+
+    /// Arma un BlockTemplate sobre la punta de la cadena de headers, con una coinbase que le paga a address el subsidio
+    /// mas las fees de las pending txs incluidas (las de fee conocido, ver select_transactions).
+    /// El timestamp es el tiempo de red, o el siguiente al median time past de los ultimos bloques si es mayor.
+    /// Devuelve CustomError::RegtestOnly si el nodo no corre en regtest, o CustomError si address no es una direccion valida.
+    pub fn create_block_template(&self, address: &str) -> Result<BlockTemplate, CustomError> {
+        if !self.regtest {
+            return Err(CustomError::RegtestOnly);
+        }
+        let script_pubkey = get_script_pubkey(address.to_string())?;
+        let prev_block_hash = self.get_last_header_hash().unwrap_or(GENESIS.to_vec());
+        let timestamp = self
+            .network_adjusted_time()?
+            .max(self.median_time_past() + 1);
+
+        let candidates = self
+            .pending_txs
+            .get_all_hashes()
+            .iter()
+            .filter_map(|tx_hash| {
+                let transaction = self.pending_txs.get_pending_tx(tx_hash)?;
+                // las de valores que no entran en un u64 son invalidas y no se incluyen
+                let Ok(Some(fee)) = self.fee_of(&transaction) else { return None };
+                Some(TemplateCandidate::new(transaction, fee))
+            })
+            .collect();
+
+        Ok(BlockTemplate::new(
+            prev_block_hash,
+            self.get_tip_height() + 1,
+            timestamp,
+            script_pubkey,
+            candidates,
+        ))
+    }
+
+    /// Agrega un bloque minado por fuera de la red (por ejemplo con mine_block) como si lo hubiera enviado un peer:
+    /// agrega su header y luego el bloque, actualizando las UTXO, las wallets y las pending txs.
+    /// Antes de agregar el header verifica que extienda la punta de la cadena, que cumpla la proof of work de regtest,
+    /// que su timestamp sea posterior al median time past y no este adelantado mas de MAX_FUTURE_BLOCK_TIME al tiempo de red,
+    /// su estructura (ver check_block) y, con las UTXO generadas, sus scripts y el valor de su coinbase.
+    /// Devuelve el hash del bloque, CustomError::RegtestOnly si el nodo no corre en regtest,
+    /// o CustomError con el motivo por el que se rechazo.
+    pub fn submit_block(&mut self, block: Block) -> Result<Vec<u8>, CustomError> {
+        if !self.regtest {
+            return Err(CustomError::RegtestOnly);
+        }
+        let block_hash = block.header.hash().clone();
+        if self.headers.contains(&block_hash) {
+            return Err(CustomError::InvalidBlock("duplicate block".to_string()));
+        }
+        let tip = self.get_last_header_hash().unwrap_or(GENESIS.to_vec());
+        if block.header.prev_block_hash != tip {
+            return Err(CustomError::InvalidBlock(
+                "block does not extend the tip of the chain".to_string(),
+            ));
+        }
+        if block.header.bits != REGTEST_BITS {
+            return Err(CustomError::InvalidBlock(
+                "block is not mined with the regtest difficulty".to_string(),
+            ));
+        }
+        block.header.verify_proof_of_work()?;
+
+        let now = self.network_adjusted_time()?;
+        if block.header.timestamp <= self.median_time_past()
+            || block.header.timestamp > now.saturating_add(MAX_FUTURE_BLOCK_TIME)
+        {
+            return Err(CustomError::InvalidBlock(
+                "block timestamp out of range".to_string(),
+            ));
+        }
+        check_block(&block)?;
+
+        // se verifica antes de agregar el header, para no dejar en la cadena un header cuyo bloque se rechaza
+        if self.utxo.is_synced() && !self.spv_mode {
+            self.utxo.verify_block_scripts(&block)?;
+            block.validate_coinbase(&self.utxo, self.get_tip_height() + 1)?;
+        }
+
+        self.append_headers(&Headers {
+            headers: vec![block.header.clone()],
+        })?;
+        self.append_block(block_hash.clone(), &block)?;
+        send_log(
+            &self.logger_sender,
+            Log::Message(format!("Submitted block {}", display_hash(&block_hash))),
+        );
+        Ok(block_hash)
+    }
+
+    /// Devuelve el tiempo de red: el reloj del nodo ajustado segun los relojes de los peers (ver network_time_offset).
+    fn network_adjusted_time(&self) -> Result<u32, CustomError> {
+        let offsets: Vec<i64> = self.peers.iter().map(|peer| peer.time_offset).collect();
+        let now = get_current_timestamp()? as i64 + network_time_offset(&offsets);
+        Ok(now.clamp(0, u32::MAX as i64) as u32)
+    }
+
+    /// Devuelve la mediana de los timestamps de los ultimos MEDIAN_TIME_SPAN headers.
+    fn median_time_past(&self) -> u32 {
+        let timestamps: Vec<u32> = self
+            .headers
+            .get_all()
+            .iter()
+            .rev()
+            .take(MEDIAN_TIME_SPAN)
+            .map(|header| header.timestamp)
+            .collect();
+        median_time_past(&timestamps)
+    }
+
     /********************     PENDING BLOCKS     ********************/
 
     /// Agrega un pending block nuevo a PendingBlocks
@@ -325,12 +2427,110 @@ impl NodeState {
         Ok(())
     }
 
-    // /// Devuelve los pending blocks de PendingBlocks
-    pub fn get_stale_requests(&self) -> Result<Vec<Vec<u8>>, CustomError> {
+    /// Devuelve los pending blocks de PendingBlocks cuya peticion vencio, junto al peer al que se le habian pedido
+    pub fn get_stale_requests(&self) -> Result<Vec<(Vec<u8>, Option<SocketAddrV6>)>, CustomError> {
         let mut pending_blocks = self.blocks.pending_blocks_ref.lock()?;
         pending_blocks.get_stale_requests()
     }
 
+    /// Guarda en el store la sesion de descarga de los bloques pendientes (ver PendingBlocks::save_session)
+    pub fn save_download_session(&self) -> Result<(), CustomError> {
+        self.blocks.pending_blocks_ref.lock()?.save_session()
+    }
+
+    /// Pide bloques a los peers conectados, repartiendolos por turnos (round-robin) en getdata de hasta BLOCKS_PER_GETDATA inventories.
+    /// Cada inventory viene con el peer al que se le habia pedido antes si la peticion vencio, para pedirselo a otro peer si hay alguno conectado.
+    /// En spv_mode se piden como bloques filtrados (merkleblock), solo a los peers que anuncian NODE_BLOOM.
+    /// Agrega los bloques a PendingBlocks con el peer al que se le pidieron (o sin peer si no hay ninguno conectado, para reintentarlos cuando venzan).
+    /// Devuelve los peers a desconectar porque no se les pudo enviar el getdata.
+    pub fn request_blocks(
+        &mut self,
+        requests: Vec<(Inventory, Option<SocketAddrV6>)>,
+    ) -> Result<Vec<(SocketAddrV6, DisconnectReason)>, CustomError> {
+        let mut failed_peers = vec![];
+        for chunk in requests.chunks(BLOCKS_PER_GETDATA) {
+            let previous_peers: Vec<SocketAddrV6> =
+                chunk.iter().filter_map(|(_, peer)| *peer).collect();
+            let peer_index = self.next_block_peer(&previous_peers);
+            let address = peer_index.map(|index| self.peers[index].address);
+
+            let mut pending_blocks = self.blocks.pending_blocks_ref.lock()?;
+            for (inventory, _) in chunk {
+                pending_blocks.append_block_from(inventory.hash.clone(), address)?;
+            }
+            drop(pending_blocks);
+
+            let Some(peer_index) = peer_index else { continue };
+            let inventories = chunk
+                .iter()
+                .map(|(inventory, _)| match self.spv_mode {
+                    true => Inventory::new(InventoryType::FilteredBlock, inventory.hash.clone()),
+                    false => inventory.clone(),
+                })
+                .collect();
+            let peer = &mut self.peers[peer_index];
+            if let Err(error) = peer.send(&GetData::new(inventories)) {
+                failed_peers.push((peer.address, DisconnectReason::from_error(&error)));
+            }
+        }
+        Ok(failed_peers)
+    }
+
+    /// Devuelve el indice del proximo peer al que pedirle bloques, salteando los de avoid mientras haya otro conectado.
+    /// En spv_mode solo considera a los peers que anuncian NODE_BLOOM.
+    /// Devuelve None si no hay peers conectados a los que pedirselos.
+    fn next_block_peer(&mut self, avoid: &[SocketAddrV6]) -> Option<usize> {
+        let count = self.peers.len();
+        if count == 0 {
+            return None;
+        }
+        let start = self.next_block_peer % count;
+        let candidates: Vec<usize> = (0..count)
+            .map(|offset| (start + offset) % count)
+            .filter(|index| !self.spv_mode || serves_bloom(self.peers[*index].services))
+            .collect();
+        let index = candidates
+            .iter()
+            .find(|index| !avoid.contains(&self.peers[**index].address))
+            .or(candidates.first())
+            .copied()?;
+        self.next_block_peer = index + 1;
+        Some(index)
+    }
+
+    /// Cambia el tiempo tras el cual se vuelve a pedir un bloque que no se recibio
+    pub fn set_stale_block_timeout(&self, stale_timeout: Duration) -> Result<(), CustomError> {
+        let mut pending_blocks = self.blocks.pending_blocks_ref.lock()?;
+        pending_blocks.set_stale_timeout(stale_timeout);
+        Ok(())
+    }
+
+    /// Establece el sender por el que PendingBlocks avisa al maintenance_loop que se agrego un bloque cuando no habia ninguno pendiente
+    pub fn watch_pending_blocks(
+        &self,
+        nudge_sender: mpsc::Sender<MaintenanceAction>,
+    ) -> Result<(), CustomError> {
+        let mut pending_blocks = self.blocks.pending_blocks_ref.lock()?;
+        pending_blocks.set_nudge_sender(nudge_sender);
+        Ok(())
+    }
+
+    /// Devuelve cuanto esperar hasta la proxima revision de los pending blocks, como maximo poll_interval si hay alguno
+    pub fn next_pending_blocks_check(
+        &self,
+        poll_interval: Duration,
+    ) -> Result<Duration, CustomError> {
+        let pending_blocks = self.blocks.pending_blocks_ref.lock()?;
+        Ok(pending_blocks.next_check_in(Instant::now(), poll_interval))
+    }
+
+    /// Cambia la cantidad de veces que se vuelve a pedir un bloque al que los peers responden notfound
+    pub fn set_max_not_found_retries(&self, max_not_found_retries: u32) -> Result<(), CustomError> {
+        let mut pending_blocks = self.blocks.pending_blocks_ref.lock()?;
+        pending_blocks.set_max_not_found_retries(max_not_found_retries);
+        Ok(())
+    }
+
     // /// Devuelve true si el bloque esta en PendingBlocks
     pub fn is_block_pending(&self, block_hash: &Vec<u8>) -> Result<bool, CustomError> {
         let pending_blocks = self.blocks.pending_blocks_ref.lock()?;
@@ -343,75 +2543,402 @@ impl NodeState {
         Ok(pending_blocks.is_empty())
     }
 
-    /********************     TRANSACTIONS     ********************/
+    /********************     WEBHOOKS     ********************/
 
-    /// Realiza una transaccion nueva para la active wallet de WalletsState
-    /// con los outputs y el fee recibidos por parametro
-    /// Devuelve la transaccion creada
-    /// Si no hay una wallet activa, devuelve un error
-    /// Si no hay suficientes fondos, devuelve un error
-    pub fn make_transaction(
+    /// Activa la cola de eventos de las wallets que envia la tarea del webhook, con capacidad para capacity eventos.
+    pub fn enable_webhooks(&mut self, capacity: usize) {
+        self.webhooks = Some(WebhookQueue::new(capacity));
+    }
+
+    /// Saca de la cola hasta batch eventos para enviar al webhook (ver WebhookQueue::take_due).
+    pub fn take_due_webhook_events(
         &mut self,
-        mut outputs: HashMap<String, u64>,
-        fee: u64,
-    ) -> Result<Transaction, CustomError> {
-        let Some(active_wallet) = self.get_active_wallet() else { return Err(CustomError::WalletNotFound) };
+        now: Instant,
+        batch: usize,
+    ) -> Vec<QueuedWebhookEvent> {
+        match &mut self.webhooks {
+            Some(webhooks) => webhooks.take_due(now, batch),
+            None => vec![],
+        }
+    }
+
+    /// Registra que fallo el envio de un evento y devuelve el evento si agoto sus intentos (ver WebhookQueue::failed).
+    pub fn webhook_event_failed(
+        &mut self,
+        queued: QueuedWebhookEvent,
+        now: Instant,
+    ) -> Option<QueuedWebhookEvent> {
+        match &mut self.webhooks {
+            Some(webhooks) => webhooks.failed(queued, now),
+            None => None,
+        }
+    }
+
+    /// Devuelve la cantidad de eventos del webhook descartados por encontrar la cola llena.
+    pub fn webhook_events_dropped(&self) -> u64 {
+        self.webhooks
+            .as_ref()
+            .map_or(0, |webhooks| webhooks.dropped())
+    }
+
+    /// Devuelve los movimientos que genera una transaccion en cada wallet, con el nombre de la wallet.
+    /// Si no estan activos ni los webhooks ni las alertas de pagos grandes devuelve un vector vacio sin calcularlos.
+    fn wallet_movements(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<Vec<(String, Movement)>, CustomError> {
+        if self.webhooks.is_none() && !self.payment_alerts.is_enabled() {
+            return Ok(vec![]);
+        }
+        let mut movements = vec![];
+        for wallet in self.wallets.get_all() {
+            if let Some(movement) =
+                transaction.get_movement(&wallet.get_pubkey_hash_set()?, &self.utxo)?
+            {
+                movements.push((wallet.name.clone(), movement));
+            }
+        }
+        Ok(movements)
+    }
+
+    fn queue_outgoing_events(&mut self, transaction: &Transaction) -> Result<(), CustomError> {
+        for (wallet, movement) in self.wallet_movements(transaction)? {
+            if movement.value < 0 {
+                self.queue_webhook_event(
+                    WebhookEventKind::OutgoingBroadcast,
+                    wallet,
+                    &movement,
+                    0,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Devuelve los movimientos del bloque en el historial de las wallets affected, con el nombre de la wallet.
+    /// Si no estan activos ni los webhooks ni las alertas de pagos grandes devuelve un vector vacio sin buscarlos.
+    fn block_movements(&self, block_hash: &[u8], affected: &[String]) -> Vec<(String, Movement)> {
+        if self.webhooks.is_none() && !self.payment_alerts.is_enabled() {
+            return vec![];
+        }
+        self.wallets
+            .get_all()
+            .iter()
+            .filter(|wallet| affected.contains(&wallet.pubkey))
+            .flat_map(|wallet| {
+                wallet
+                    .history
+                    .iter()
+                    .filter(|movement| movement.block_hash.as_deref() == Some(block_hash))
+                    .map(|movement| (wallet.name.clone(), movement.clone()))
+            })
+            .collect()
+    }
+
+    fn queue_confirmation_events(
+        &mut self,
+        movements: &[(String, Movement)],
+    ) -> Result<(), CustomError> {
+        for (wallet, movement) in movements {
+            if !movement.provisional {
+                self.queue_webhook_event(
+                    WebhookEventKind::Confirmation,
+                    wallet.clone(),
+                    movement,
+                    1,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Si el movimiento es un pago recibido por la wallet de al menos ALERT_THRESHOLD_SATS que todavia no alerto
+    /// en la sesion (ver PaymentAlerts), avisa a la interfaz grafica con LargePaymentAlert, lo loguea como advertencia
+    /// y encola un evento para el webhook.
+    fn alert_large_payment(
+        &mut self,
+        wallet: String,
+        movement: &Movement,
+        confirmed: bool,
+    ) -> Result<(), CustomError> {
+        if !self.payment_alerts.should_alert(&wallet, movement) {
+            return Ok(());
+        }
+        let tx_hash = display_hash(&movement.tx_hash);
+        send_log(
+            &self.logger_sender,
+            Log::Warning(format!(
+                "wallet {wallet} received a large payment of {} satoshis in transaction {tx_hash}",
+                movement.value
+            )),
+        );
+        self.queue_webhook_event(
+            WebhookEventKind::LargePayment,
+            wallet.clone(),
+            movement,
+            u32::from(confirmed),
+        )?;
+        self.gui_sender.send(GUIEvents::LargePaymentAlert {
+            wallet,
+            value: movement.value as u64,
+            tx_hash,
+            confirmed,
+        })?;
+        Ok(())
+    }
+
+    fn queue_webhook_event(
+        &mut self,
+        kind: WebhookEventKind,
+        wallet: String,
+        movement: &Movement,
+        confirmations: u32,
+    ) -> Result<(), CustomError> {
+        let Some(webhooks) = &mut self.webhooks else { return Ok(()) };
+        let event = WebhookEvent {
+            kind,
+            wallet,
+            txid: display_hash(&movement.tx_hash),
+            value: movement.value,
+            confirmations,
+            timestamp: get_current_timestamp()?,
+        };
+        webhooks.push(event, Instant::now());
+        Ok(())
+    }
+
+    /********************     TRANSACTIONS     ********************/
 
-        let total_value = self.calculate_total_value(fee, &outputs)?;
-        let mut active_wallet_utxo = self.get_active_wallet_utxo()?;
+    /// Cambia el monto minimo de los pagos recibidos que alertan, 0 desactiva las alertas (ver PaymentAlerts).
+    pub fn set_alert_threshold(&mut self, alert_threshold: u64) {
+        self.payment_alerts.set_threshold(alert_threshold);
+    }
 
-        active_wallet_utxo.sort_by(|a, b| b.1.tx_out.value.cmp(&a.1.tx_out.value));
-        let (inputs, total_input_value) = calculate_inputs(&active_wallet_utxo, total_value);
+    /// Cambia el valor minimo del cambio de las transacciones que creamos
+    pub fn set_dust_threshold(&mut self, dust_threshold: u64) {
+        self.dust_threshold = dust_threshold;
+    }
 
-        let change = total_input_value - total_value;
-        if change > 0 {
-            outputs.insert(active_wallet.pubkey.clone(), change);
+    /// Cambia las confirmaciones minimas que necesita una UTXO para usarse como input de las transacciones que creamos
+    /// Devuelve un error si no esta entre 1 y MAX_MIN_SPEND_CONFIRMATIONS
+    pub fn set_min_spend_confirmations(
+        &mut self,
+        min_confirmations: u32,
+    ) -> Result<(), CustomError> {
+        if !(1..=MAX_MIN_SPEND_CONFIRMATIONS).contains(&min_confirmations) {
+            return Err(CustomError::Validation(format!(
+                "Minimum confirmations must be between 1 and {MAX_MIN_SPEND_CONFIRMATIONS}"
+            )));
         }
+        self.min_spend_confirmations = min_confirmations;
+        Ok(())
+    }
 
-        Transaction::create(active_wallet, inputs, outputs)
+    /// Devuelve las confirmaciones minimas que necesita una UTXO para usarse como input de las transacciones que creamos
+    pub fn get_min_spend_confirmations(&self) -> u32 {
+        self.min_spend_confirmations
     }
 
-    fn calculate_total_value(
+    /// Planifica una transaccion de la active wallet para el pedido recibido, sin firmarla ni reservar sus inputs
+    /// Solo usa como inputs las UTXOs de la wallet que no gasta ninguna pending tx y que tienen al menos min_spend_confirmations
+    /// confirmaciones (las de coinbase COINBASE_MATURITY), el cambio vuelve a la wallet
+    /// salvo que sea menor al dust threshold, en cuyo caso se suma al fee
+    /// Devuelve SendManyError si no hay wallet activa, algun output o input elegido es invalido o no hay suficientes fondos
+    /// (InsufficientConfirmedFunds si alcanzarian contando las UTXOs con menos confirmaciones)
+    pub fn plan_transaction(
         &self,
+        request: &SendManyRequest,
+    ) -> Result<TransactionPlan, SendManyError> {
+        let Some(active_wallet) = self.get_active_wallet() else { return Err(CustomError::WalletNotFound.into()) };
+
+        let spent_outpoints = self.pending_txs.spent_outpoints();
+        let available: Vec<(OutPoint, UTXOValue, u32)> = self
+            .get_active_wallet_utxo_with_confirmations()?
+            .into_iter()
+            .filter(|(out_point, _, _)| !spent_outpoints.contains(out_point))
+            .collect();
+
+        plan_transaction(
+            request,
+            available,
+            &active_wallet.pubkey,
+            p2pkh_input_size(active_wallet.compressed_pubkey),
+            self.dust_threshold,
+            self.min_spend_confirmations,
+        )
+    }
+
+    /// Realiza un sendmany con la active wallet: planifica la transaccion y, si el pedido no es dry_run, la firma
+    /// y la agrega a PendingTxs en la misma llamada, reservando sus inputs para que ningun otro envio los seleccione
+    /// Como NodeState se comparte detras de un Mutex, dos envios simultaneos nunca eligen las mismas UTXOs
+    /// La transaccion devuelta todavia tiene que ser enviada a los peers
+    /// Si la wallet activa es watch-only, solo se puede planificar (dry_run): si no, devuelve WalletIsWatchOnly antes de planificar
+    /// Mientras el nodo no esta sincronizado tampoco se puede enviar (NodeNotSynced), ya que las UTXO estan incompletas
+    pub fn send_many(&mut self, request: SendManyRequest) -> Result<SendManyResult, SendManyError> {
+        let watch_only = self
+            .get_active_wallet()
+            .is_some_and(|wallet| wallet.is_watch_only());
+        if watch_only && !request.dry_run {
+            return Err(CustomError::WalletIsWatchOnly.into());
+        }
+        let plan = self.plan_transaction(&request)?;
+        if request.dry_run {
+            return Ok(SendManyResult::Planned(plan));
+        }
+
+        let Some(active_wallet) = self.get_active_wallet() else { return Err(CustomError::WalletNotFound.into()) };
+        if self.wallets.is_locked() {
+            return Err(CustomError::WalletsLocked.into());
+        }
+        if !self.is_synced() {
+            return Err(CustomError::NodeNotSynced.into());
+        }
+        let pubkey = active_wallet.pubkey.clone();
+        let transaction = plan.sign(active_wallet)?;
+        self.append_pending_tx(transaction.clone())?;
+        self.wallet_meta
+            .update(&pubkey, &transaction.hash(), |meta| {
+                meta.fee = Some(plan.fee)
+            })?;
+
+        Ok(SendManyResult::Sent {
+            txid: hash_as_string(transaction.hash()),
+            transaction,
+        })
+    }
+
+    /// Realiza una transaccion nueva para la active wallet de WalletsState
+    /// con los outputs, el fee y las opciones (lock time y si es reemplazable) recibidos por parametro (ver send_many)
+    /// Los outputs respetan el orden recibido y una misma direccion puede aparecer mas de una vez, el cambio (si no es dust) se agrega en una posicion aleatoria
+    /// Devuelve la transaccion creada, que ya quedo agregada a PendingTxs
+    /// Si no hay una wallet activa devuelve un error, y si es watch-only devuelve WalletIsWatchOnly
+    /// Si algun output tiene valor 0 o una direccion invalida, devuelve un error
+    /// Si no hay suficientes fondos, devuelve un error
+    pub fn make_transaction(
+        &mut self,
+        outputs: Vec<(String, u64)>,
         fee: u64,
-        outputs: &HashMap<String, u64>,
-    ) -> Result<u64, CustomError> {
-        let mut total_value = fee;
-        for output in outputs.values() {
-            total_value += output;
-        }
-        let wallet_balance = self.get_active_wallet_balance()?;
-        if total_value > wallet_balance {
-            return Err(CustomError::InsufficientFunds);
+        options: TransactionOptions,
+    ) -> Result<Transaction, CustomError> {
+        let mut request = SendManyRequest::new(outputs, fee);
+        request.options = options;
+        match self.send_many(request)? {
+            SendManyResult::Sent { transaction, .. } => Ok(transaction),
+            SendManyResult::Planned(_) => Err(CustomError::Validation(
+                "Transaction was planned but not created".to_string(),
+            )),
         }
-        Ok(total_value)
     }
-}
 
-fn calculate_inputs(
-    active_wallet_utxo: &[(OutPoint, UTXOValue)],
-    total_value: u64,
-) -> (Vec<OutPoint>, u64) {
-    let mut inputs = vec![];
-    let mut total_input_value = 0;
-    for (out_point, tx_out) in active_wallet_utxo.iter() {
-        inputs.push(out_point.clone());
-        total_input_value += tx_out.tx_out.value;
-        if total_input_value >= total_value {
-            break;
+    /// Reemplaza una pending tx reemplazable de la active wallet por otra que gasta los mismos inputs y paga new_fee (RBF).
+    /// Los outputs se mantienen y la diferencia de fee se descuenta del cambio, si el cambio que queda es dust
+    /// no se crea su output y se suma al fee. El lock time y los sequence de los inputs son los de la original.
+    /// La original sale de PendingTxs y la nueva queda en su lugar, todavia tiene que ser enviada a los peers.
+    /// Devuelve CustomError si:
+    /// - La transaccion no esta en PendingTxs.
+    /// - La wallet activa es watch-only, las wallets estan bloqueadas o no hay wallet activa.
+    /// - La transaccion no señaliza que se puede reemplazar o no gasta solo UTXO de la active wallet.
+    /// - new_fee no es mayor al fee actual, o el cambio no alcanza para pagar la diferencia.
+    pub fn replace_transaction(
+        &mut self,
+        tx_hash: &Vec<u8>,
+        new_fee: u64,
+    ) -> Result<Transaction, CustomError> {
+        let Some(original) = self.pending_txs.get_pending_tx(tx_hash) else { return Err(CustomError::TransactionNotFound) };
+        let Some(active_wallet) = self.get_active_wallet() else { return Err(CustomError::WalletNotFound) };
+        if active_wallet.is_watch_only() {
+            return Err(CustomError::WalletIsWatchOnly);
+        }
+        if self.wallets.is_locked() {
+            return Err(CustomError::WalletsLocked);
+        }
+        if !original.is_replaceable() {
+            return Err(CustomError::Validation(
+                "Transaction does not signal that it can be replaced".to_string(),
+            ));
+        }
+
+        let wallet_script_pubkey = active_wallet.get_script_pubkey()?;
+        let mut inputs_value = 0;
+        for input in &original.inputs {
+            match self.utxo.tx_set.get(&input.previous_output) {
+                Some(value) if value.tx_out.script_pubkey == wallet_script_pubkey => {
+                    inputs_value += value.tx_out.value
+                }
+                _ => {
+                    return Err(CustomError::Validation(
+                        "Only transactions that spend UTXO of the active wallet can be replaced"
+                            .to_string(),
+                    ))
+                }
+            }
+        }
+        let outputs_value: u64 = original.outputs.iter().map(|output| output.value).sum();
+        let fee = inputs_value.saturating_sub(outputs_value);
+        if new_fee <= fee {
+            return Err(CustomError::Validation(format!(
+                "The new fee must be higher than the current one ({fee} satoshis)"
+            )));
         }
+
+        let mut replacement = original;
+        let change_index = replacement
+            .outputs
+            .iter()
+            .position(|output| output.script_pubkey == wallet_script_pubkey)
+            .ok_or(CustomError::InsufficientFunds)?;
+        let change = replacement.outputs[change_index]
+            .value
+            .checked_sub(new_fee - fee)
+            .ok_or(CustomError::InsufficientFunds)?;
+        if change < self.dust_threshold {
+            replacement.outputs.remove(change_index);
+        } else {
+            replacement.outputs[change_index].value = change;
+        }
+        if replacement.outputs.is_empty() {
+            return Err(CustomError::InsufficientFunds);
+        }
+        let prev_script_pubkeys = vec![wallet_script_pubkey; replacement.inputs.len()];
+        replacement.sign(active_wallet, &prev_script_pubkeys)?;
+        let pubkey = active_wallet.pubkey.clone();
+        let paid_fee = inputs_value
+            - replacement
+                .outputs
+                .iter()
+                .map(|output| output.value)
+                .sum::<u64>();
+
+        self.pending_txs.remove_pending_tx(tx_hash)?;
+        self.fee_estimator.forget(tx_hash);
+        self.append_pending_tx(replacement.clone())?;
+        self.wallet_meta
+            .update(&pubkey, &replacement.hash(), |meta| {
+                meta.fee = Some(paid_fee)
+            })?;
+
+        send_log(
+            &self.logger_sender,
+            Log::Message(format!(
+                "Pending transaction {} replaced by {}",
+                hash_as_string(tx_hash.clone()),
+                hash_as_string(replacement.hash())
+            )),
+        );
+        Ok(replacement)
     }
-    (inputs, total_input_value)
 }
 
-fn create_store_dir(path: &String) -> Result<(), CustomError> {
-    let path = Path::new(path);
+fn create_store_dir(store_path: &String) -> Result<(), CustomError> {
+    let path = Path::new(store_path);
     if !path.exists() {
         fs::create_dir(path)?;
     }
     let blocks_path = path.join("blocks");
     if !blocks_path.exists() {
         fs::create_dir(blocks_path)?;
+        // un store nuevo ya guarda sus bloques en el formato actual
+        write_store_version(store_path, BLOCK_STORE_VERSION)?;
     }
     Ok(())
 }
@@ -28,13 +28,22 @@ impl BufferParser {
         self.buffer.len() - self.pos == 0
     }
 
+    /// Devuelve el proximo byte del buffer sin avanzar la posicion, o None si fue recorrido completamente.
+    pub fn peek_u8(&self) -> Option<u8> {
+        self.buffer.get(self.pos).copied()
+    }
+
     /// Extrae un buffer de tamaño size del buffer.
     pub fn extract_buffer(&mut self, size: usize) -> Result<&[u8], CustomError> {
-        let buffer = match self.buffer.get(self.pos..(self.pos + size)) {
+        let end = self
+            .pos
+            .checked_add(size)
+            .ok_or(CustomError::SerializedBufferIsInvalid)?;
+        let buffer = match self.buffer.get(self.pos..end) {
             Some(buffer) => Ok(buffer),
             None => return Err(CustomError::SerializedBufferIsInvalid),
         };
-        self.pos += size;
+        self.pos = end;
         buffer
     }
 
@@ -119,6 +128,8 @@ impl BufferParser {
     }
 
     /// extrae un varint del buffer
+    /// Devuelve CustomError si el varint no usa la codificacion mas corta posible,
+    /// ya que volver a serializarlo no daria los mismos bytes.
     pub fn extract_varint(&mut self) -> Result<u64, CustomError> {
         let first_byte = self.extract_u8()?;
         let slice = match first_byte {
@@ -150,6 +161,15 @@ impl BufferParser {
             }
             _ => u64::from_le_bytes([first_byte, 0, 0, 0, 0, 0, 0, 0]),
         };
+        let minimum = match first_byte {
+            0xFF_u8 => 0x100000000,
+            0xFE_u8 => 0x10000,
+            0xFD_u8 => 0xFD,
+            _ => 0,
+        };
+        if slice < minimum {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
         Ok(slice)
     }
 
@@ -298,8 +318,29 @@ mod tests {
         assert_eq!(buffer.extract_varint().unwrap(), 0x010203);
 
         let mut buffer =
-            BufferParser::new(vec![0xFF, 0x03, 0x02, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]);
-        assert_eq!(buffer.extract_varint().unwrap(), 0x00000000010203);
+            BufferParser::new(vec![0xFF, 0x03, 0x02, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00]);
+        assert_eq!(buffer.extract_varint().unwrap(), 0x0000000100010203);
+    }
+
+    #[test]
+    fn extract_non_canonical_varint_returns_error() {
+        let mut buffer = BufferParser::new(vec![0xFD, 0x03, 0x00]);
+        assert!(buffer.extract_varint().is_err());
+
+        let mut buffer = BufferParser::new(vec![0xFE, 0xFF, 0xFF, 0x00, 0x00]);
+        assert!(buffer.extract_varint().is_err());
+
+        let mut buffer =
+            BufferParser::new(vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00]);
+        assert!(buffer.extract_varint().is_err());
+    }
+
+    #[test]
+    fn extract_buffer_with_overflowing_size_returns_error() {
+        let mut buffer = BufferParser::new(vec![0x01, 0x02, 0x03]);
+        buffer.extract_u8().unwrap();
+        assert!(buffer.extract_buffer(usize::MAX).is_err());
+        assert_eq!(buffer.len(), 2);
     }
 
     #[test]
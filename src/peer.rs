@@ -1,7 +1,13 @@
 use std::{
-    net::{SocketAddr, SocketAddrV6, TcpStream},
-    sync::{mpsc, Arc, Mutex},
+    cmp, fmt,
+    io::{Read, Seek},
+    net::{Shutdown, SocketAddrV6, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
     thread,
+    time::{Duration, Instant},
 };
 
 use chrono::Local;
@@ -11,14 +17,23 @@ use crate::{
     logger::{send_log, Log},
     loops::{
         node_action_loop::NodeAction,
-        peer_action_loop::{PeerAction, PeerActionLoop},
+        peer_action_loop::{PeerActionLoop, PeerActionReceiver},
         peer_stream_loop::PeerStreamLoop,
     },
-    message::{Message, MessageHeader},
+    message::{send_streamed, Message, MessageHeader},
     messages::{
-        get_headers::GetHeaders, send_headers::SendHeaders, ver_ack::VerAck, version::Version,
+        addr::{Addr, NetworkAddress},
+        get_addr::GetAddr,
+        get_headers::GetHeaders,
+        ping_pong::Ping,
+        send_compact::{SendCompact, COMPACT_BLOCKS_PROTOCOL_VERSION, COMPACT_BLOCKS_VERSION},
+        send_headers::SendHeaders,
+        ver_ack::VerAck,
+        version::Version,
     },
-    utils::{get_address_v6, open_stream},
+    services::{serves_blocks, service_names},
+    structs::bloom_filter::BloomFilter,
+    utils::get_address_v6,
 };
 
 /// GENESIS es el hash del bloque genesis de la blockchain de Bitcoin.
@@ -27,6 +42,344 @@ pub const GENESIS: [u8; 32] = [
     132, 233, 14, 173, 1, 234, 51, 9, 0, 0, 0, 0,
 ];
 
+/// Timestamp del bloque genesis de testnet.
+pub const GENESIS_TIMESTAMP: u32 = 1296688602;
+
+/// Tiempo sin recibir mensajes de un peer tras el cual se lo desconecta (los peers envian pings cada 2 minutos).
+const PEER_READ_TIMEOUT: Duration = Duration::from_secs(20 * 60);
+
+/// Tiempo maximo que puede bloquearse el envio de un mensaje a un peer antes de desconectarlo.
+const PEER_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Tiempo que se espera cada mensaje del handshake cuando el peer es el que llama al nodo.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Mensajes que un peer puede enviar entre su version y su verack, se descartan sin procesarlos.
+const HANDSHAKE_TOLERATED_COMMANDS: [&str; 3] = ["wtxidrelay", "sendaddrv2", "sendtxrcncl"];
+
+/// Cantidad maxima de mensajes fuera de HANDSHAKE_TOLERATED_COMMANDS que puede enviar un peer antes de su verack.
+pub const MAX_EARLY_HANDSHAKE_MESSAGES: usize = 10;
+
+/// Puntaje de mal comportamiento asignado a un peer que envia un mensaje invalido.
+pub const INVALID_MESSAGE_SCORE: u32 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// DisconnectReason representa el motivo por el que se desconecto un peer.
+/// Los motivos son:
+/// - RemoteClosed: El peer cerro la conexion.
+/// - ReadTimeout: El peer no envio mensajes durante PEER_READ_TIMEOUT.
+/// - HandshakeFailed: Fallo el handshake con el peer.
+/// - Misbehavior: El peer envio un mensaje invalido, contiene el puntaje asignado.
+/// - SendQueueFull: El peer no recibe los mensajes que le enviamos (se supero PEER_WRITE_TIMEOUT).
+/// - PingTimeout: El peer no respondio un ping a tiempo.
+/// - Terminated: El nodo termino.
+/// - Replaced: Se conecto un nuevo peer con la misma direccion.
+/// - ProtocolViolation: El peer violo el protocolo despues del handshake (por ejemplo, volvio a enviar version o verack).
+pub enum DisconnectReason {
+    RemoteClosed,
+    ReadTimeout,
+    HandshakeFailed,
+    Misbehavior(u32),
+    SendQueueFull,
+    PingTimeout,
+    Terminated,
+    Replaced,
+    ProtocolViolation,
+}
+
+impl DisconnectReason {
+    /// Obtiene el motivo de desconexion a partir del error con el que termino alguno de los loops del peer.
+    /// Los errores de lectura o escritura del stream y los errores transitorios (ver CustomError::is_retryable)
+    /// indican que el peer cerro la conexion, el resto de los errores se deben a mensajes invalidos.
+    pub fn from_error(error: &CustomError) -> Self {
+        match error {
+            CustomError::StreamReadTimeout => Self::ReadTimeout,
+            CustomError::StreamWriteTimeout => Self::SendQueueFull,
+            CustomError::PeerClosedConnection
+            | CustomError::CannotReadMessageHeader
+            | CustomError::CannotReadStream
+            | CustomError::CannotSendToStream
+            | CustomError::CannotSendMessageToChannel(_) => Self::RemoteClosed,
+            error if error.is_retryable() => Self::RemoteClosed,
+            _ => Self::Misbehavior(INVALID_MESSAGE_SCORE),
+        }
+    }
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::RemoteClosed => write!(f, "remote closed the connection"),
+            Self::ReadTimeout => write!(f, "read timeout"),
+            Self::HandshakeFailed => write!(f, "handshake failed"),
+            Self::Misbehavior(score) => write!(f, "misbehavior (score {})", score),
+            Self::SendQueueFull => write!(f, "send queue full"),
+            Self::PingTimeout => write!(f, "ping timeout"),
+            Self::Terminated => write!(f, "node terminated"),
+            Self::Replaced => write!(f, "replaced by a new connection"),
+            Self::ProtocolViolation => write!(f, "protocol violation"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// DisconnectEvent registra la desconexion de un peer.
+/// Los elementos son:
+/// - address: Direccion del peer.
+/// - reason: Motivo de la desconexion.
+/// - connected_for: Tiempo que estuvo conectado el peer.
+/// - bytes_transferred: Bytes enviados y recibidos por los loops del peer (sin contar el handshake).
+/// - timestamp: Momento de la desconexion.
+pub struct DisconnectEvent {
+    pub address: SocketAddrV6,
+    pub reason: DisconnectReason,
+    pub connected_for: Duration,
+    pub bytes_transferred: u64,
+    pub timestamp: u32,
+}
+
+impl DisconnectEvent {
+    /// Crea el evento de un peer con el que no se llego a completar el handshake.
+    pub fn handshake_failed(address: SocketAddrV6) -> Self {
+        Self {
+            address,
+            reason: DisconnectReason::HandshakeFailed,
+            connected_for: Duration::ZERO,
+            bytes_transferred: 0,
+            timestamp: Local::now().timestamp() as u32,
+        }
+    }
+}
+
+impl fmt::Display for DisconnectEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Peer {} disconnected: {} (connected {}s, {} bytes)",
+            self.address,
+            self.reason,
+            self.connected_for.as_secs(),
+            self.bytes_transferred
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// PeerSummary resume la conexion con un peer conectado, para mostrarla en la GUI.
+/// Los elementos son:
+/// - address: Direccion del peer.
+/// - version: Version del protocolo negociada en el handshake.
+/// - services: Nombres de los servicios que anuncia el peer (ver service_names).
+/// - inbound: Indica si el peer nos llamo o si lo llamamos nosotros.
+/// - uptime: Tiempo que lleva conectado el peer.
+/// - avg_latency_ms: Promedio movil de la latencia de los pings al peer en milisegundos, None si todavia no respondio ninguno.
+pub struct PeerSummary {
+    pub address: SocketAddrV6,
+    pub version: i32,
+    pub services: Vec<String>,
+    pub inbound: bool,
+    pub uptime: Duration,
+    pub avg_latency_ms: Option<u64>,
+}
+
+impl PeerSummary {
+    /// Crea el resumen de un peer, decodificando los bits de sus servicios.
+    pub fn new(
+        address: SocketAddrV6,
+        version: i32,
+        services: u64,
+        inbound: bool,
+        uptime: Duration,
+    ) -> Self {
+        Self {
+            address,
+            version,
+            services: service_names(services),
+            inbound,
+            uptime,
+            avg_latency_ms: None,
+        }
+    }
+
+    /// Devuelve "inbound" si el peer nos llamo y "outbound" si lo llamamos nosotros.
+    pub fn direction(&self) -> &'static str {
+        match self.inbound {
+            true => "inbound",
+            false => "outbound",
+        }
+    }
+}
+
+/// Ventana minima sobre la que se calcula el throughput de un peer, para que pocos bytes recibidos
+/// justo despues del handshake no parezcan una descarga rapida.
+const MIN_THROUGHPUT_WINDOW: Duration = Duration::from_secs(1);
+
+/// Peso de cada nueva medicion en el promedio movil de la latencia de los pings de un peer.
+const PING_LATENCY_WEIGHT: f64 = 0.25;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// PeerStats contiene las estadisticas de descarga de un peer, utilizadas para elegir el peer mas rapido.
+/// Los elementos son:
+/// - bytes_received: Bytes de los mensajes recibidos del peer desde el handshake.
+/// - messages_received: Cantidad de mensajes recibidos del peer desde el handshake.
+/// - last_message_at: Momento en el que se termino de recibir el ultimo mensaje.
+/// - handshake_latency: Tiempo que tardo el handshake con el peer.
+/// - measured_since: Momento desde el que se miden las estadisticas (el fin del handshake).
+/// - ping_latency: Promedio movil del tiempo entre cada ping y su pong, None si el peer todavia no respondio ninguno.
+pub struct PeerStats {
+    pub bytes_received: u64,
+    pub messages_received: u64,
+    pub last_message_at: Option<Instant>,
+    pub handshake_latency: Duration,
+    pub measured_since: Instant,
+    pub ping_latency: Option<Duration>,
+}
+
+impl PeerStats {
+    /// Crea las estadisticas de un peer con el tiempo que tardo su handshake, midiendo desde ahora.
+    pub fn new(handshake_latency: Duration) -> Self {
+        Self {
+            bytes_received: 0,
+            messages_received: 0,
+            last_message_at: None,
+            handshake_latency,
+            measured_since: Instant::now(),
+            ping_latency: None,
+        }
+    }
+
+    /// Registra un mensaje de bytes recibido en el momento now.
+    pub fn record_message(&mut self, bytes: usize, now: Instant) {
+        self.bytes_received += bytes as u64;
+        self.messages_received += 1;
+        self.last_message_at = Some(now);
+    }
+
+    /// Devuelve los bytes por segundo recibidos desde el handshake hasta el ultimo mensaje,
+    /// o None si todavia no se recibio ninguno.
+    pub fn throughput(&self) -> Option<f64> {
+        let last_message_at = self.last_message_at?;
+        let elapsed = last_message_at
+            .saturating_duration_since(self.measured_since)
+            .max(MIN_THROUGHPUT_WINDOW);
+        Some(self.bytes_received as f64 / elapsed.as_secs_f64())
+    }
+
+    /// Registra el tiempo entre un ping y su pong en el promedio movil de la latencia,
+    /// con peso PING_LATENCY_WEIGHT para la nueva medicion. La primera medicion es el promedio.
+    pub fn record_ping_latency(&mut self, latency: Duration) {
+        self.ping_latency = Some(match self.ping_latency {
+            Some(average) => {
+                average.mul_f64(1.0 - PING_LATENCY_WEIGHT) + latency.mul_f64(PING_LATENCY_WEIGHT)
+            }
+            None => latency,
+        });
+    }
+
+    /// Compara la velocidad de dos peers, devuelve Greater si self es mas rapido.
+    /// Los peers de los que ya se recibieron datos se comparan por throughput y son mas rapidos que los que no.
+    /// Entre los que todavia no enviaron datos es mas rapido el de menor latencia de los pings, que se mide durante
+    /// toda la conexion, y si alguno no respondio ninguno el de menor latencia de handshake.
+    /// Si empatan es mas rapido el de menor latencia de los pings, y el que ya respondio alguno que el que no.
+    pub fn cmp_speed(&self, other: &PeerStats) -> cmp::Ordering {
+        let by_speed = match (self.throughput(), other.throughput()) {
+            (Some(throughput), Some(other_throughput)) => throughput.total_cmp(&other_throughput),
+            (Some(_), None) => cmp::Ordering::Greater,
+            (None, Some(_)) => cmp::Ordering::Less,
+            (None, None) => match (self.ping_latency, other.ping_latency) {
+                (Some(latency), Some(other_latency)) => other_latency.cmp(&latency),
+                _ => other.handshake_latency.cmp(&self.handshake_latency),
+            },
+        };
+        by_speed.then_with(|| match (self.ping_latency, other.ping_latency) {
+            (Some(latency), Some(other_latency)) => other_latency.cmp(&latency),
+            (Some(_), None) => cmp::Ordering::Greater,
+            (None, Some(_)) => cmp::Ordering::Less,
+            (None, None) => cmp::Ordering::Equal,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+/// PeerConnection contiene el estado de la conexion con un peer, compartido entre el Peer y sus loops.
+/// Los elementos son:
+/// - bytes_transferred: Bytes enviados y recibidos con el peer (sin contar el handshake).
+/// - stopped: Indica que el nodo cerro la conexion, por lo que los loops no deben reportar el error del stream.
+/// - relay_txs: Indica si se piden y procesan las transacciones que anuncia el peer, solo una vez que el nodo esta sincronizado.
+pub struct PeerConnection {
+    bytes_transferred: AtomicU64,
+    stopped: AtomicBool,
+    relay_txs: AtomicBool,
+}
+
+impl PeerConnection {
+    /// Suma bytes enviados o recibidos con el peer.
+    pub fn add_bytes(&self, bytes: u64) {
+        self.bytes_transferred.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Devuelve los bytes enviados y recibidos con el peer.
+    pub fn bytes_transferred(&self) -> u64 {
+        self.bytes_transferred.load(Ordering::Relaxed)
+    }
+
+    /// Devuelve true si el nodo cerro la conexion con el peer.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Habilita o deshabilita el relay de transacciones con el peer.
+    pub fn set_relay_txs(&self, relay_txs: bool) {
+        self.relay_txs.store(relay_txs, Ordering::Relaxed);
+    }
+
+    /// Devuelve true si se piden y procesan las transacciones que anuncia el peer.
+    pub fn relays_txs(&self) -> bool {
+        self.relay_txs.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// PeerSender le envia mensajes a un peer sin necesitar el Peer, para que el nodo pueda escribir en su stream
+/// despues de soltar el lock del NodeState: un peer que no lee su socket bloquea el envio hasta PEER_WRITE_TIMEOUT.
+/// Los envios de los PeerSender de un peer se hacen de a uno por el lock de writer, para que no se mezclen los mensajes.
+/// Por eso, una vez hecho el handshake, todo lo que se le escribe al peer (tambien desde sus loops) pasa por un PeerSender.
+/// Los elementos son:
+/// - address: Direccion del peer.
+/// - send_headers: Booleano que indica si el peer soporta el envio de headers, al momento de crear el PeerSender.
+/// - requested_headers: Booleano que indica si el peer ya nos solicito headers, al momento de crear el PeerSender.
+/// - writer: Stream del peer por el que escribe el nodo.
+/// - connection: Estado de la conexion compartido con el peer, donde se suman los bytes enviados.
+pub struct PeerSender {
+    pub address: SocketAddrV6,
+    pub send_headers: bool,
+    pub requested_headers: bool,
+    writer: Arc<Mutex<TcpStream>>,
+    connection: Arc<PeerConnection>,
+}
+
+impl PeerSender {
+    /// Envia un mensaje al peer.
+    pub fn send(&self, message: &impl Message) -> Result<(), CustomError> {
+        let mut writer = self.writer.lock()?;
+        let bytes = message.send_sized(&mut *writer)?;
+        self.connection.add_bytes(bytes);
+        Ok(())
+    }
+
+    /// Envia un mensaje cuyo payload esta guardado en disco, leyendolo y enviandolo por fragmentos.
+    pub fn send_streamed(
+        &self,
+        command: &str,
+        payload: &mut (impl Read + Seek),
+    ) -> Result<(), CustomError> {
+        let mut writer = self.writer.lock()?;
+        let bytes = send_streamed(&mut *writer, command, payload)?;
+        self.connection.add_bytes(bytes);
+        Ok(())
+    }
+}
+
 /// Peer es una representacion de los Peers a los que nos conectamos, contiene los elementos necesarios para manejar la conexion con el peer.
 /// Cada peer tiene dos threads asociados:
 /// - peer_action_thread: Thread que escucha las acciones a realizar por el peer.
@@ -38,8 +391,17 @@ pub const GENESIS: [u8; 32] = [
 /// - version: Version del peer.
 /// - send_headers: Booleano que indica si el peer soporta el envio de headers.
 /// - requested_headers: Booleano que indica si el peer ya nos solicito headers.
+/// - bloom_filter: Filtro que cargo el peer con filterload, con el que se arman los bloques filtrados que nos pide (BIP 37).
 /// - stream: Stream del peer.
-/// - benchmark: Velocidad de handshake con el peer, utilizado para elegir el mejor peer.
+/// - writer: Stream del peer por el que escribe el nodo, compartido con sus PeerSender (ver Peer::sender).
+/// - stats: Estadisticas de descarga del peer, utilizadas para elegir el mejor peer.
+/// - best_height: Altura de la cadena del peer, la start_height de su version actualizada con los headers que nos envia.
+/// - time_offset: Segundos que el reloj del peer esta adelantado respecto del nuestro, segun el timestamp de su version.
+/// - connected_at: Momento en el que se establecio la conexion.
+/// - inbound: Indica si el peer nos llamo (ver TcpListenerLoop) o si lo llamamos nosotros.
+/// - connection: Estado de la conexion compartido con sus loops.
+/// - ping_nonce: Nonce del ultimo ping enviado al peer, mientras no llegue su pong.
+/// - ping_sent_at: Momento en el que se envio el ultimo ping al peer.
 /// - peer_action_thread: Thread que escucha las acciones a realizar por el peer.
 /// - peer_stream_thread: Thread que escucha el stream del peer.
 ///
@@ -49,49 +411,68 @@ pub struct Peer {
     pub version: i32,
     pub send_headers: bool,
     pub requested_headers: bool,
+    pub bloom_filter: Option<BloomFilter>,
     pub stream: TcpStream,
-    pub benchmark: i64,
+    pub writer: Arc<Mutex<TcpStream>>,
+    pub stats: PeerStats,
+    pub best_height: usize,
+    pub time_offset: i64,
+    pub connected_at: Instant,
+    pub inbound: bool,
+    pub connection: Arc<PeerConnection>,
+    pub ping_nonce: Option<u64>,
+    pub ping_sent_at: Option<Instant>,
     pub peer_action_thread: Option<thread::JoinHandle<Result<(), CustomError>>>,
     pub peer_stream_thread: Option<thread::JoinHandle<Result<(), CustomError>>>,
 }
 
 impl Peer {
-    /// Llama a un peer de la red de Bitcoin y crea un Peer.
-    /// Realiza el handshake con el peer y crea los threads asociados.
+    /// Llama a un peer de la red de Bitcoin y crea un Peer, a partir del stream abierto hacia el
+    /// (ver PrivateNetwork::connect).
+    /// Realiza el handshake con el peer, presentandose con el nonce y el user agent del nodo, y crea los threads asociados.
+    #[allow(clippy::too_many_arguments)]
     pub fn call(
-        address: SocketAddr,
+        stream: TcpStream,
         sender_address: SocketAddrV6,
         services: u64,
         version: i32,
-        peer_action_receiver: Arc<Mutex<mpsc::Receiver<PeerAction>>>,
+        nonce: u64,
+        user_agent: &str,
+        peer_action_receiver: PeerActionReceiver,
         logger_sender: mpsc::Sender<Log>,
         node_action_sender: mpsc::Sender<NodeAction>,
     ) -> Result<Self, CustomError> {
-        let stream = open_stream(address)?;
-
         let mut peer = Self {
-            address: get_address_v6(address),
+            address: get_address_v6(stream.peer_addr()?),
             peer_action_thread: None,
             peer_stream_thread: None,
             services,
             version,
+            writer: Arc::new(Mutex::new(stream.try_clone()?)),
             stream,
-            benchmark: 99999,
+            stats: PeerStats::new(Duration::ZERO),
+            best_height: 0,
+            time_offset: 0,
+            connected_at: Instant::now(),
+            inbound: false,
+            connection: Arc::new(PeerConnection::default()),
+            ping_nonce: None,
+            ping_sent_at: None,
             send_headers: false,
             requested_headers: false,
+            bloom_filter: None,
         };
 
-        let timestamp_before_handshake = Local::now().timestamp_millis();
-        peer.call_handshake(sender_address)?;
-        let timestamp_after_handshake = Local::now().timestamp_millis();
-        peer.benchmark = timestamp_after_handshake - timestamp_before_handshake;
+        let handshake_started_at = Instant::now();
+        peer.call_handshake(sender_address, nonce, user_agent)?;
+        peer.stats = PeerStats::new(handshake_started_at.elapsed());
 
         send_log(
             &logger_sender,
             Log::Message(format!(
                 "Successful handshake with {} in {}ms",
                 peer.address.ip(),
-                peer.benchmark
+                peer.stats.handshake_latency.as_millis()
             )),
         );
 
@@ -100,13 +481,16 @@ impl Peer {
     }
 
     /// Atiende el llamado de un peer de la red de Bitcoin y crea un Peer.
-    /// Realiza el handshake con el peer y crea los threads asociados.
+    /// Realiza el handshake con el peer, presentandose con el nonce y el user agent del nodo, y crea los threads asociados.
+    #[allow(clippy::too_many_arguments)]
     pub fn answer(
         stream: TcpStream,
         sender_address: SocketAddrV6,
         services: u64,
         version: i32,
-        peer_action_receiver: Arc<Mutex<mpsc::Receiver<PeerAction>>>,
+        nonce: u64,
+        user_agent: &str,
+        peer_action_receiver: PeerActionReceiver,
         logger_sender: mpsc::Sender<Log>,
         node_action_sender: mpsc::Sender<NodeAction>,
     ) -> Result<Self, CustomError> {
@@ -116,23 +500,31 @@ impl Peer {
             peer_stream_thread: None,
             services,
             version,
+            writer: Arc::new(Mutex::new(stream.try_clone()?)),
             stream,
-            benchmark: 99999,
+            stats: PeerStats::new(Duration::ZERO),
+            best_height: 0,
+            time_offset: 0,
+            connected_at: Instant::now(),
+            inbound: true,
+            connection: Arc::new(PeerConnection::default()),
+            ping_nonce: None,
+            ping_sent_at: None,
             send_headers: false,
             requested_headers: false,
+            bloom_filter: None,
         };
 
-        let timestamp_before_handshake = Local::now().timestamp_millis();
-        peer.answer_handshake(sender_address)?;
-        let timestamp_after_handshake = Local::now().timestamp_millis();
-        peer.benchmark = timestamp_after_handshake - timestamp_before_handshake;
+        let handshake_started_at = Instant::now();
+        peer.answer_handshake(sender_address, nonce, user_agent)?;
+        peer.stats = PeerStats::new(handshake_started_at.elapsed());
 
         send_log(
             &logger_sender,
             Log::Message(format!(
                 "Successful handshake with {} in {}ms",
                 peer.address.ip(),
-                peer.benchmark
+                peer.stats.handshake_latency.as_millis()
             )),
         );
 
@@ -141,58 +533,155 @@ impl Peer {
     }
 
     /// Realiza el handshake de Node con el Peer, cuando el Node es el que llama al Peer.
-    fn call_handshake(&mut self, sender_address: SocketAddrV6) -> Result<(), CustomError> {
-        Version::new(self.address, sender_address, self.version, self.services)
-            .send(&mut self.stream)?;
+    /// Tras el handshake negocia el envio de headers y de compact blocks, y le pide direcciones de otros nodos con getaddr.
+    /// Si el nodo envia bloques, tambien le anuncia nuestra direccion con nuestros servicios (ver self_advertisement).
+    /// Devuelve CustomError::ConnectedToSelf si el peer responde con nuestro nonce.
+    fn call_handshake(
+        &mut self,
+        sender_address: SocketAddrV6,
+        nonce: u64,
+        user_agent: &str,
+    ) -> Result<(), CustomError> {
+        let local_services = self.services;
+        Version::new(
+            self.address,
+            sender_address,
+            self.version,
+            local_services,
+            nonce,
+        )
+        .with_user_agent(user_agent)
+        .send(&mut self.stream)?;
 
         let response_header = MessageHeader::read(&mut self.stream)?;
-        let version_response = Version::read(&mut self.stream, response_header.payload_size)
+        let version_response = Version::read_with_header(&mut self.stream, &response_header)
             .map_err(|_| CustomError::CannotHandshakeNode)?;
+        if version_response.nonce == nonce {
+            return Err(CustomError::ConnectedToSelf);
+        }
         self.version = version_response.version;
         self.services = version_response.services;
+        self.best_height = version_response.start_height.max(0) as usize;
+        self.time_offset = version_response.timestamp as i64 - Local::now().timestamp();
 
         let response_header = MessageHeader::read(&mut self.stream)?;
-        VerAck::read(&mut self.stream, response_header.payload_size)
+        VerAck::read_with_header(&mut self.stream, &response_header)
             .map_err(|_| CustomError::CannotHandshakeNode)?;
 
         VerAck::new().send(&mut self.stream)?;
         SendHeaders::new().send(&mut self.stream)?;
+        self.send_compact()?;
+        GetAddr::new().send(&mut self.stream)?;
+
+        let advertisement = self_advertisement(
+            local_services,
+            sender_address,
+            version_response.receiver_address,
+        );
+        if let Some(addr) = advertisement {
+            addr.send(&mut self.stream)?;
+        }
 
         Ok(())
     }
 
     /// Realiza el handshake de Node con el Peer, cuando el Peer es el que llama al Node.
-    fn answer_handshake(&mut self, sender_address: SocketAddrV6) -> Result<(), CustomError> {
+    /// Cada mensaje del peer se espera hasta HANDSHAKE_TIMEOUT, y los que envie antes de su verack no se procesan
+    /// (ver read_verack).
+    /// Devuelve CustomError::ConnectedToSelf, sin responder el version, si el peer envia nuestro nonce.
+    fn answer_handshake(
+        &mut self,
+        sender_address: SocketAddrV6,
+        nonce: u64,
+        user_agent: &str,
+    ) -> Result<(), CustomError> {
+        self.stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
         let response_header = MessageHeader::read(&mut self.stream)?;
-        let version_response = Version::read(&mut self.stream, response_header.payload_size)
+        let version_response = Version::read_with_header(&mut self.stream, &response_header)
             .map_err(|_| CustomError::CannotHandshakeNode)?;
+        if version_response.nonce == nonce {
+            return Err(CustomError::ConnectedToSelf);
+        }
 
-        Version::new(self.address, sender_address, self.version, self.services)
-            .send(&mut self.stream)?;
+        Version::new(
+            self.address,
+            sender_address,
+            self.version,
+            self.services,
+            nonce,
+        )
+        .with_user_agent(user_agent)
+        .send(&mut self.stream)?;
         self.version = version_response.version;
         self.services = version_response.services;
+        self.best_height = version_response.start_height.max(0) as usize;
+        self.time_offset = version_response.timestamp as i64 - Local::now().timestamp();
 
         VerAck::new().send(&mut self.stream)?;
 
-        let response_header = MessageHeader::read(&mut self.stream)?;
-        VerAck::read(&mut self.stream, response_header.payload_size)
-            .map_err(|_| CustomError::CannotHandshakeNode)?;
+        self.read_verack()?;
         SendHeaders::new().send(&mut self.stream)?;
+        self.send_compact()?;
 
         Ok(())
     }
 
+    /// Lee los mensajes del peer hasta su verack, cuando el peer es el que llama al nodo.
+    /// Los mensajes de HANDSHAKE_TOLERATED_COMMANDS se descartan. Cualquier otro mensaje tampoco se procesa: se descarta
+    /// y vuelve a empezar la espera de HANDSHAKE_TIMEOUT, hasta MAX_EARLY_HANDSHAKE_MESSAGES mensajes.
+    /// Devuelve CustomError::CannotHandshakeNode si el peer envia mas mensajes o no envia su verack a tiempo.
+    fn read_verack(&mut self) -> Result<(), CustomError> {
+        let mut deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        let mut early_messages = 0;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(CustomError::CannotHandshakeNode);
+            }
+            self.stream.set_read_timeout(Some(remaining))?;
+
+            let response_header = MessageHeader::read(&mut self.stream)?;
+            if response_header.command == "verack" {
+                VerAck::read_with_header(&mut self.stream, &response_header)
+                    .map_err(|_| CustomError::CannotHandshakeNode)?;
+                return Ok(());
+            }
+            response_header.read_payload(&mut self.stream)?;
+            if HANDSHAKE_TOLERATED_COMMANDS.contains(&response_header.command.as_str()) {
+                continue;
+            }
+
+            early_messages += 1;
+            if early_messages > MAX_EARLY_HANDSHAKE_MESSAGES {
+                return Err(CustomError::CannotHandshakeNode);
+            }
+            deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        }
+    }
+
+    /// Negocia compact blocks con el peer enviandole sendcmpct, solo si su version del protocolo los soporta.
+    fn send_compact(&mut self) -> Result<(), CustomError> {
+        if self.version < COMPACT_BLOCKS_PROTOCOL_VERSION {
+            return Ok(());
+        }
+        SendCompact::new(false, COMPACT_BLOCKS_VERSION).send(&mut self.stream)
+    }
+
     fn spawn_threads(
         &mut self,
-        peer_action_receiver: Arc<Mutex<mpsc::Receiver<PeerAction>>>,
+        peer_action_receiver: PeerActionReceiver,
         node_action_sender: mpsc::Sender<NodeAction>,
         logger_sender: mpsc::Sender<Log>,
     ) -> Result<(), CustomError> {
+        self.stream.set_read_timeout(Some(PEER_READ_TIMEOUT))?;
+        self.stream.set_write_timeout(Some(PEER_WRITE_TIMEOUT))?;
+
         //thread que escucha al nodo
         self.peer_action_thread = Some(PeerActionLoop::spawn(
             self.address,
             self.version,
-            self.stream.try_clone()?,
+            self.sender(),
+            self.connection.clone(),
             logger_sender.clone(),
             peer_action_receiver,
             node_action_sender.clone(),
@@ -203,23 +692,169 @@ impl Peer {
             self.version,
             self.address,
             self.stream.try_clone()?,
+            self.sender(),
+            self.connection.clone(),
             logger_sender,
             node_action_sender,
         ));
         Ok(())
     }
 
+    /// Vuelve a crear el thread de PeerActions si termino (por ejemplo por un panic) y la conexion sigue abierta.
+    /// Devuelve true si se creo un nuevo thread.
+    pub fn respawn_action_thread(
+        &mut self,
+        peer_action_receiver: PeerActionReceiver,
+        node_action_sender: mpsc::Sender<NodeAction>,
+        logger_sender: mpsc::Sender<Log>,
+    ) -> Result<bool, CustomError> {
+        let finished = self
+            .peer_action_thread
+            .as_ref()
+            .map_or(true, |thread| thread.is_finished());
+        if !finished || self.connection.is_stopped() {
+            return Ok(false);
+        }
+
+        join_peer_thread(self.peer_action_thread.take());
+        self.peer_action_thread = Some(PeerActionLoop::spawn(
+            self.address,
+            self.version,
+            self.sender(),
+            self.connection.clone(),
+            logger_sender,
+            peer_action_receiver,
+            node_action_sender,
+        ));
+        Ok(true)
+    }
+
     /// Envia un mensaje al peer.
-    pub fn send(&mut self, message: impl Message) -> Result<(), CustomError> {
-        message.send(&mut self.stream)
+    pub fn send(&mut self, message: &impl Message) -> Result<(), CustomError> {
+        self.sender().send(message)
+    }
+
+    /// Envia un mensaje cuyo payload esta guardado en disco, leyendolo y enviandolo por fragmentos.
+    pub fn send_streamed(
+        &mut self,
+        command: &str,
+        payload: &mut (impl Read + Seek),
+    ) -> Result<(), CustomError> {
+        self.sender().send_streamed(command, payload)
+    }
+
+    /// Devuelve un PeerSender para enviarle mensajes al peer sin tener el lock del NodeState.
+    pub fn sender(&self) -> PeerSender {
+        PeerSender {
+            address: self.address,
+            send_headers: self.send_headers,
+            requested_headers: self.requested_headers,
+            writer: self.writer.clone(),
+            connection: self.connection.clone(),
+        }
+    }
+
+    /// Envia un ping con el nonce recibido y registra el momento del envio.
+    pub fn ping(&mut self, nonce: u64) -> Result<(), CustomError> {
+        self.send(&Ping { nonce })?;
+        self.ping_nonce = Some(nonce);
+        self.ping_sent_at = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Registra el pong del peer y el tiempo que tardo en responder el ping en el promedio de su latencia
+    /// (ver avg_latency_ms). Devuelve false, sin registrar nada, si no responde al ultimo ping enviado.
+    pub fn receive_pong(&mut self, nonce: u64) -> bool {
+        if self.ping_nonce != Some(nonce) {
+            return false;
+        }
+        self.ping_nonce = None;
+        if let Some(sent_at) = self.ping_sent_at {
+            self.stats.record_ping_latency(sent_at.elapsed());
+        }
+        true
+    }
+
+    /// Devuelve el promedio movil de la latencia de los pings al peer en milisegundos,
+    /// o None si todavia no respondio ninguno.
+    pub fn avg_latency_ms(&self) -> Option<u64> {
+        self.stats
+            .ping_latency
+            .map(|latency| latency.as_millis() as u64)
+    }
+
+    /// Devuelve true si el peer no respondio el ultimo ping dentro del timeout.
+    pub fn is_ping_expired(&self, timeout: Duration) -> bool {
+        match (self.ping_nonce, self.ping_sent_at) {
+            (Some(_), Some(sent_at)) => sent_at.elapsed() > timeout,
+            _ => false,
+        }
+    }
+
+    /// Devuelve true si hay que enviarle un nuevo ping al peer:
+    /// no tiene un ping sin responder y paso el intervalo desde el ultimo.
+    pub fn needs_ping(&self, interval: Duration) -> bool {
+        if self.ping_nonce.is_some() {
+            return false;
+        }
+        match self.ping_sent_at {
+            Some(sent_at) => sent_at.elapsed() >= interval,
+            None => true,
+        }
+    }
+
+    /// Devuelve el resumen de la conexion con el peer que se muestra en la GUI.
+    pub fn summary(&self) -> PeerSummary {
+        PeerSummary {
+            avg_latency_ms: self.avg_latency_ms(),
+            ..PeerSummary::new(
+                self.address,
+                self.version,
+                self.services,
+                self.inbound,
+                self.connected_at.elapsed(),
+            )
+        }
+    }
+
+    /// Cierra la conexion con el peer y espera a que terminen sus threads, luego devuelve el evento de desconexion.
+    pub fn stop(&mut self, reason: DisconnectReason) -> DisconnectEvent {
+        let event = self.close(reason);
+        join_peer_thread(self.peer_stream_thread.take());
+        join_peer_thread(self.peer_action_thread.take());
+        event
+    }
+
+    /// Cierra la conexion con el peer sin esperar a sus threads y devuelve el evento de desconexion.
+    /// El PeerStreamLoop termina al cerrarse el stream y el PeerActionLoop al ver que la conexion fue cerrada
+    /// (a lo sumo STOPPED_CHECK_INTERVAL despues). Cerrar el stream tambien hace fallar a los envios bloqueados de sus PeerSender.
+    pub fn close(&mut self, reason: DisconnectReason) -> DisconnectEvent {
+        self.connection.stopped.store(true, Ordering::Relaxed);
+        let _ = self.stream.shutdown(Shutdown::Both);
+        DisconnectEvent {
+            address: self.address,
+            reason,
+            connected_for: self.connected_at.elapsed(),
+            bytes_transferred: self.connection.bytes_transferred(),
+            timestamp: Local::now().timestamp() as u32,
+        }
     }
 }
 
-/// Se encarga de solicitar a un peer los headers siguientes a su ultimo header.
+/// Espera a que termine un thread del peer, salvo que se llame desde ese mismo thread.
+fn join_peer_thread(thread: Option<thread::JoinHandle<Result<(), CustomError>>>) {
+    if let Some(thread) = thread {
+        if thread.thread().id() != thread::current().id() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Se encarga de solicitar a un peer los headers siguientes a su ultimo header, por su PeerSender.
 pub fn request_headers(
     last_header: Option<Vec<u8>>,
     version: i32,
-    stream: &mut TcpStream,
+    sender: &PeerSender,
     logger_sender: &mpsc::Sender<Log>,
     node_action_sender: &mpsc::Sender<NodeAction>,
 ) -> Result<(), CustomError> {
@@ -228,7 +863,7 @@ pub fn request_headers(
         None => [GENESIS.to_vec()].to_vec(),
     };
 
-    let request = GetHeaders::new(version, block_header_hashes, vec![0; 32]).send(stream);
+    let request = sender.send(&GetHeaders::new(version, block_header_hashes, vec![0; 32]));
     if request.is_err() {
         send_log(
             logger_sender,
@@ -238,3 +873,151 @@ pub fn request_headers(
     }
     Ok(())
 }
+
+/// Devuelve el mensaje addr con el que le anunciamos nuestra direccion a un peer, o None si no hay que anunciarla:
+/// si el nodo no envia bloques, si no acepta conexiones entrantes (puerto 0) o si el peer no sabe nuestra ip.
+/// La ip es la que el peer indico en su version (como nos ve) y el puerto el que escucha el nodo.
+fn self_advertisement(
+    local_services: u64,
+    local_address: SocketAddrV6,
+    seen_as: SocketAddrV6,
+) -> Option<Addr> {
+    if !serves_blocks(local_services) || local_address.port() == 0 || seen_as.ip().is_unspecified()
+    {
+        return None;
+    }
+    let timestamp = chrono::Utc::now().timestamp() as u32;
+    let address = SocketAddrV6::new(*seen_as.ip(), local_address.port(), 0, 0);
+    Some(Addr::new(vec![NetworkAddress::new(
+        timestamp,
+        local_services,
+        address,
+    )]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn synthetic_stats(latency_ms: u64, bytes: usize, elapsed: Option<Duration>) -> PeerStats {
+        let mut stats = PeerStats::new(Duration::from_millis(latency_ms));
+        if let Some(elapsed) = elapsed {
+            stats.record_message(bytes, stats.measured_since + elapsed);
+        }
+        stats
+    }
+
+    fn fastest(stats: &[PeerStats]) -> Option<usize> {
+        (0..stats.len()).max_by(|a, b| stats[*a].cmp_speed(&stats[*b]))
+    }
+
+    #[test]
+    fn peer_stats_throughput() {
+        let mut stats = PeerStats::new(Duration::from_millis(50));
+        assert_eq!(stats.throughput(), None);
+
+        let start = stats.measured_since;
+        stats.record_message(1000, start + Duration::from_secs(2));
+        stats.record_message(3000, start + Duration::from_secs(4));
+        assert_eq!(stats.bytes_received, 4000);
+        assert_eq!(stats.messages_received, 2);
+        assert_eq!(stats.throughput(), Some(1000.0));
+
+        // pocos bytes apenas terminado el handshake se miden sobre MIN_THROUGHPUT_WINDOW
+        let stats = synthetic_stats(50, 500, Some(Duration::from_millis(1)));
+        assert_eq!(stats.throughput(), Some(500.0));
+    }
+
+    #[test]
+    fn fastest_peer_uses_lowest_latency_without_data() {
+        let stats = [
+            synthetic_stats(300, 0, None),
+            synthetic_stats(80, 0, None),
+            synthetic_stats(150, 0, None),
+        ];
+        assert_eq!(fastest(&stats), Some(1));
+        assert_eq!(fastest(&[]), None);
+    }
+
+    #[test]
+    fn fastest_peer_prefers_observed_throughput() {
+        let stats = [
+            // el handshake mas rapido, pero sin datos recibidos
+            synthetic_stats(10, 0, None),
+            synthetic_stats(200, 10_000, Some(Duration::from_secs(10))),
+            synthetic_stats(400, 50_000, Some(Duration::from_secs(10))),
+            synthetic_stats(20, 2_000, Some(Duration::from_secs(10))),
+        ];
+        assert_eq!(fastest(&stats), Some(2));
+        assert_eq!(fastest(&[stats[0], stats[3]]), Some(1));
+    }
+
+    #[test]
+    fn ping_latency_is_a_rolling_average() {
+        let mut stats = synthetic_stats(50, 0, None);
+        assert_eq!(stats.ping_latency, None);
+
+        stats.record_ping_latency(Duration::from_millis(100));
+        assert_eq!(stats.ping_latency, Some(Duration::from_millis(100)));
+        stats.record_ping_latency(Duration::from_millis(200));
+        assert_eq!(stats.ping_latency, Some(Duration::from_millis(125)));
+    }
+
+    #[test]
+    fn fastest_peer_breaks_ties_by_ping_latency() {
+        let mut stats = [
+            synthetic_stats(100, 0, None),
+            synthetic_stats(100, 0, None),
+            synthetic_stats(100, 0, None),
+        ];
+        // sin pings respondidos los peers empatan, max_by devuelve el ultimo
+        assert_eq!(fastest(&stats), Some(2));
+
+        stats[0].record_ping_latency(Duration::from_millis(300));
+        stats[1].record_ping_latency(Duration::from_millis(40));
+        assert_eq!(fastest(&stats), Some(1));
+        // sin datos recibidos la latencia de los pings pesa mas que la del handshake
+        let mut slow_handshake = synthetic_stats(500, 0, None);
+        slow_handshake.record_ping_latency(Duration::from_millis(20));
+        assert_eq!(fastest(&[slow_handshake, stats[1]]), Some(0));
+        // el throughput sigue pesando mas que la latencia de los pings
+        assert_eq!(
+            fastest(&[
+                stats[1],
+                synthetic_stats(100, 1_000, Some(Duration::from_secs(1)))
+            ]),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn peer_summary_decodes_services_and_direction() {
+        use crate::services::{NODE_NETWORK_LIMITED, NODE_WITNESS};
+        use std::net::Ipv6Addr;
+
+        let address = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 18333, 0, 0);
+        let summary = PeerSummary::new(
+            address,
+            70016,
+            NODE_WITNESS | NODE_NETWORK_LIMITED,
+            true,
+            Duration::from_secs(90),
+        );
+        assert_eq!(summary.address, address);
+        assert_eq!(summary.version, 70016);
+        assert_eq!(
+            summary.services,
+            vec![
+                "NODE_WITNESS".to_string(),
+                "NODE_NETWORK_LIMITED".to_string()
+            ]
+        );
+        assert_eq!(summary.direction(), "inbound");
+        assert_eq!(summary.uptime, Duration::from_secs(90));
+        assert_eq!(summary.avg_latency_ms, None);
+
+        let outbound = PeerSummary::new(address, 70015, 0, false, Duration::ZERO);
+        assert!(outbound.services.is_empty());
+        assert_eq!(outbound.direction(), "outbound");
+    }
+}
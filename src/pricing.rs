@@ -0,0 +1,313 @@
+use std::{
+    fs,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use chrono::NaiveDateTime;
+
+use crate::{error::CustomError, utils::get_current_timestamp};
+
+/// Moneda de la cotizacion manual si el config no la indica.
+pub const DEFAULT_FIAT_CURRENCY: &str = "USD";
+
+/// Segundos desde la cotizacion a partir de los cuales se muestra como desactualizada.
+pub const STALE_RATE_AGE: u64 = 60 * 60;
+
+/// Cotizacion de un BTC en moneda fiat: valor, moneda y timestamp del momento de la cotizacion.
+pub type FiatRate = (f64, String, u64);
+
+#[derive(Debug, Clone, PartialEq)]
+/// PriceSource es el origen de la cotizacion de BTC en moneda fiat, que se lee del config (PRICE_SOURCE).
+/// Los origenes son:
+/// - Manual: Cotizacion fija ingresada por el usuario (manual:<valor> o manual:<valor>:<moneda>), que se puede cambiar desde la interfaz grafica.
+/// - File: Archivo JSON que actualiza el usuario (file:<path>), que se vuelve a leer cuando cambia su fecha de modificacion.
+pub enum PriceSource {
+    Manual { rate: f64, currency: String },
+    File(String),
+}
+
+impl PriceSource {
+    /// Lee el origen de la cotizacion del valor de PRICE_SOURCE.
+    /// Devuelve la descripcion del problema si no es manual:<valor>[:<moneda>] con un valor positivo, o file:<path>.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        if let Some(path) = value.strip_prefix("file:") {
+            if path.trim().is_empty() {
+                return Err(String::from("PRICE_SOURCE file: needs a path"));
+            }
+            return Ok(Self::File(path.trim().to_string()));
+        }
+
+        let Some(manual) = value.strip_prefix("manual:") else {
+            return Err(format!(
+                "PRICE_SOURCE must be manual:<rate> or file:<path>, found '{value}'"
+            ));
+        };
+        let (rate, currency) = manual
+            .split_once(':')
+            .unwrap_or((manual, DEFAULT_FIAT_CURRENCY));
+        let rate = parse_rate(rate.trim())
+            .ok_or_else(|| format!("invalid rate for PRICE_SOURCE: '{}'", rate.trim()))?;
+        Ok(Self::Manual {
+            rate,
+            currency: currency.trim().to_uppercase(),
+        })
+    }
+}
+
+/// Pricing mantiene la cotizacion de BTC en moneda fiat que se muestra junto a los montos en la interfaz grafica.
+/// Es solo para mostrar: los montos de las transacciones siempre se manejan en satoshis.
+/// Los elementos son:
+/// - source: Origen de la cotizacion, None si el config no indica PRICE_SOURCE (no se muestran montos en fiat).
+/// - rate: Ultima cotizacion valida.
+/// - file_modified: Fecha de modificacion del archivo de la ultima lectura, para releerlo solo cuando cambia.
+pub struct Pricing {
+    source: Option<PriceSource>,
+    rate: Option<FiatRate>,
+    file_modified: Option<SystemTime>,
+}
+
+impl Pricing {
+    /// Crea el Pricing con el origen recibido. La cotizacion manual toma como fecha el momento de creacion.
+    pub fn new(source: Option<PriceSource>) -> Self {
+        let rate = match &source {
+            Some(PriceSource::Manual { rate, currency }) => Some((
+                *rate,
+                currency.clone(),
+                get_current_timestamp().unwrap_or(0),
+            )),
+            _ => None,
+        };
+        Self {
+            source,
+            rate,
+            file_modified: None,
+        }
+    }
+
+    /// Devuelve true si la cotizacion es manual y se puede cambiar desde la interfaz grafica.
+    pub fn is_manual(&self) -> bool {
+        matches!(self.source, Some(PriceSource::Manual { .. }))
+    }
+
+    /// Cambia la cotizacion manual, con la fecha actual.
+    /// Devuelve CustomError::Validation si el origen no es manual o si rate no es un numero positivo.
+    pub fn set_manual_rate(&mut self, rate: f64) -> Result<(), CustomError> {
+        let Some(PriceSource::Manual { currency, .. }) = &self.source else {
+            return Err(CustomError::Validation(String::from(
+                "The rate can only be changed with PRICE_SOURCE=manual",
+            )));
+        };
+        if !(rate.is_finite() && rate > 0.0) {
+            return Err(CustomError::Validation(String::from(
+                "The rate must be a positive number",
+            )));
+        }
+        self.rate = Some((rate, currency.clone(), get_current_timestamp()?));
+        Ok(())
+    }
+
+    /// Devuelve la cotizacion actual (valor de un BTC, moneda y timestamp de la cotizacion), o None si no hay origen
+    /// o si el archivo nunca se pudo leer. Si el origen es un archivo, lo vuelve a leer cuando cambia su fecha de
+    /// modificacion; si la lectura falla se mantiene la ultima cotizacion valida.
+    pub fn current_rate(&mut self) -> Option<FiatRate> {
+        if let Some(PriceSource::File(path)) = &self.source {
+            let modified = fs::metadata(path).and_then(|metadata| metadata.modified());
+            if let Ok(modified) = modified {
+                if self.file_modified != Some(modified) {
+                    if let Ok(rate) = read_rate_file(path, modified) {
+                        self.rate = Some(rate);
+                        self.file_modified = Some(modified);
+                    }
+                }
+            }
+        }
+        self.rate.clone()
+    }
+}
+
+/// Lee la cotizacion del archivo del origen file: (ver parse_rate_file).
+fn read_rate_file(path: &str, modified: SystemTime) -> Result<FiatRate, CustomError> {
+    let content = fs::read_to_string(path)?;
+    let modified = modified
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    parse_rate_file(&content, modified)
+}
+
+/// Lee la cotizacion de un objeto JSON plano como {"rate": 30000.5, "currency": "USD", "as_of": 1690000000}.
+/// currency es opcional (DEFAULT_FIAT_CURRENCY) y as_of tambien: si falta se usa modified, la fecha de modificacion del archivo.
+/// Devuelve CustomError::Validation si falta rate o algun valor es invalido.
+pub fn parse_rate_file(content: &str, modified: u64) -> Result<FiatRate, CustomError> {
+    let invalid = |field: &str| CustomError::Validation(format!("Invalid {field} in price file"));
+
+    let rate = json_field(content, "rate")
+        .and_then(parse_rate)
+        .ok_or_else(|| invalid("rate"))?;
+    let currency = match json_field(content, "currency") {
+        Some(currency) if !currency.is_empty() => currency.to_uppercase(),
+        Some(_) => return Err(invalid("currency")),
+        None => DEFAULT_FIAT_CURRENCY.to_string(),
+    };
+    let as_of = match json_field(content, "as_of") {
+        Some(as_of) => as_of.parse().map_err(|_| invalid("as_of"))?,
+        None => modified,
+    };
+    Ok((rate, currency, as_of))
+}
+
+/// Devuelve el valor (sin comillas) de un campo de un objeto JSON plano, o None si no esta.
+fn json_field<'a>(content: &'a str, name: &str) -> Option<&'a str> {
+    let key = format!("\"{name}\"");
+    let start = content.find(&key)? + key.len();
+    let value = content[start..]
+        .trim_start()
+        .strip_prefix(':')?
+        .trim_start();
+    match value.strip_prefix('"') {
+        Some(quoted) => quoted.split('"').next(),
+        None => value.split([',', '}']).next().map(str::trim),
+    }
+}
+
+/// Lee una cotizacion, que tiene que ser un numero positivo.
+fn parse_rate(value: &str) -> Option<f64> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|rate| rate.is_finite() && *rate > 0.0)
+}
+
+/// Formatea el equivalente en fiat de un monto en satoshis, por ejemplo "≈ 12.34 USD".
+pub fn format_fiat(satoshis: i64, rate: &FiatRate) -> String {
+    let (value, currency, _) = rate;
+    let btc = satoshis as f64 / 100_000_000.0;
+    format!("≈ {:.2} {}", btc * value, currency)
+}
+
+/// Formatea la cotizacion con su fecha (UTC), indicando si tiene mas de STALE_RATE_AGE segundos en now.
+pub fn format_rate(rate: &FiatRate, now: u64) -> String {
+    let (value, currency, as_of) = rate;
+    let date = NaiveDateTime::from_timestamp_opt(*as_of as i64, 0)
+        .map(|date| date.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_default();
+    let stale = match now.saturating_sub(*as_of) > STALE_RATE_AGE {
+        true => " (stale)",
+        false => "",
+    };
+    format!("1 BTC = {:.2} {} as of {}{}", value, currency, date, stale)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn price_source_parse() {
+        assert_eq!(
+            PriceSource::parse("manual:30000.5"),
+            Ok(PriceSource::Manual {
+                rate: 30000.5,
+                currency: "USD".to_string()
+            })
+        );
+        assert_eq!(
+            PriceSource::parse("manual:28000:eur"),
+            Ok(PriceSource::Manual {
+                rate: 28000.0,
+                currency: "EUR".to_string()
+            })
+        );
+        assert_eq!(
+            PriceSource::parse("file:prices.json"),
+            Ok(PriceSource::File("prices.json".to_string()))
+        );
+        assert!(PriceSource::parse("manual:-1").is_err());
+        assert!(PriceSource::parse("file:").is_err());
+        assert!(PriceSource::parse("http://prices").is_err());
+    }
+
+    #[test]
+    fn parse_rate_file_fields() {
+        let content = r#"{ "currency": "eur", "rate": 27500.25, "as_of": 1690000000 }"#;
+        assert_eq!(
+            parse_rate_file(content, 5).unwrap(),
+            (27500.25, "EUR".to_string(), 1690000000)
+        );
+        assert_eq!(
+            parse_rate_file(r#"{"rate":30000}"#, 5).unwrap(),
+            (30000.0, "USD".to_string(), 5)
+        );
+        assert!(parse_rate_file(r#"{"currency": "USD"}"#, 5).is_err());
+        assert!(parse_rate_file(r#"{"rate": "abc"}"#, 5).is_err());
+        assert!(parse_rate_file(r#"{"rate": 1, "as_of": -3}"#, 5).is_err());
+    }
+
+    #[test]
+    fn file_rate_is_reread_when_modified() {
+        let path = "tests/test_price_rate.json";
+        fs::write(path, r#"{"rate": 30000, "as_of": 1}"#).unwrap();
+        let mut pricing = Pricing::new(Some(PriceSource::File(path.to_string())));
+        assert!(!pricing.is_manual());
+        assert_eq!(
+            pricing.current_rate(),
+            Some((30000.0, "USD".to_string(), 1))
+        );
+
+        // sin cambiar la fecha de modificacion no se vuelve a leer
+        let modified = fs::metadata(path).unwrap().modified().unwrap();
+        fs::write(path, r#"{"rate": 31000, "as_of": 2}"#).unwrap();
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(modified).unwrap();
+        assert_eq!(
+            pricing.current_rate(),
+            Some((30000.0, "USD".to_string(), 1))
+        );
+
+        file.set_modified(modified + Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(
+            pricing.current_rate(),
+            Some((31000.0, "USD".to_string(), 2))
+        );
+
+        // un archivo invalido mantiene la ultima cotizacion
+        fs::write(path, "{}").unwrap();
+        file.set_modified(modified + Duration::from_secs(20))
+            .unwrap();
+        assert_eq!(
+            pricing.current_rate(),
+            Some((31000.0, "USD".to_string(), 2))
+        );
+
+        fs::remove_file(path).unwrap();
+        assert!(Pricing::new(None).current_rate().is_none());
+    }
+
+    #[test]
+    fn format_fiat_and_stale_rate() {
+        let rate = (30000.0, "USD".to_string(), 1690000000);
+        assert_eq!(format_fiat(150_000, &rate), "≈ 45.00 USD");
+        assert_eq!(format_fiat(-100_000_000, &rate), "≈ -30000.00 USD");
+        assert_eq!(
+            format_rate(&rate, 1690000000 + STALE_RATE_AGE),
+            "1 BTC = 30000.00 USD as of 2023-07-22 04:26 UTC"
+        );
+        assert_eq!(
+            format_rate(&rate, 1690000000 + STALE_RATE_AGE + 1),
+            "1 BTC = 30000.00 USD as of 2023-07-22 04:26 UTC (stale)"
+        );
+
+        let mut pricing = Pricing::new(Some(PriceSource::Manual {
+            rate: 30000.0,
+            currency: "USD".to_string(),
+        }));
+        assert!(pricing.is_manual());
+        pricing.set_manual_rate(32000.0).unwrap();
+        assert_eq!(pricing.current_rate().unwrap().0, 32000.0);
+        assert!(pricing.set_manual_rate(0.0).is_err());
+        assert!(Pricing::new(None).set_manual_rate(1.0).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    io::{Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use crate::{
+    error::CustomError,
+    rng::random_bytes,
+    utils::open_stream,
+    wallet_crypto::{constant_time_eq, from_hex, hmac_sha256},
+};
+
+/// Largo en bytes de la clave compartida de la red privada, del challenge y de su respuesta (HMAC-SHA256).
+pub const PRIVATE_NETWORK_KEY_LEN: usize = 32;
+const CHALLENGE_LEN: usize = 32;
+const RESPONSE_LEN: usize = 32;
+
+/// Tiempo maximo que se espera el challenge o su respuesta antes de cortar la conexion.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Default)]
+/// PrivateNetwork autentica las conexiones de los nodos de una red privada con una clave compartida,
+/// antes del mensaje version: quien acepta la conexion envia un challenge aleatorio y quien llama responde
+/// con HMAC-SHA256(clave, challenge). Sin clave no autentica nada (red publica).
+/// Los elementos son:
+/// - key: Clave compartida de la red privada (PRIVATE_NETWORK_KEY), o None si el nodo es publico.
+/// - peers: Direcciones de los nodos de la red privada (PRIVATE_PEERS), a las que se llama autenticandose.
+pub struct PrivateNetwork {
+    key: Option<Vec<u8>>,
+    peers: Vec<SocketAddr>,
+}
+
+impl PrivateNetwork {
+    /// Crea la red privada con la clave y las direcciones de sus nodos. Sin clave, las direcciones se ignoran.
+    pub fn new(key: Option<Vec<u8>>, peers: Vec<SocketAddr>) -> Self {
+        Self { key, peers }
+    }
+
+    /// Devuelve las direcciones de los nodos de la red privada, si el nodo tiene clave.
+    pub fn peers(&self) -> Vec<SocketAddr> {
+        match self.key {
+            Some(_) => self.peers.clone(),
+            None => vec![],
+        }
+    }
+
+    /// Abre un stream a la direccion recibida. Si es un nodo de la red privada, responde su challenge
+    /// antes de devolverlo, el resto de las conexiones salientes no cambian.
+    /// Devuelve CustomError::CannotConnectToNode si no se puede conectar y CustomError::CannotHandshakeNode
+    /// si no envia el challenge a tiempo.
+    pub fn connect(&self, address: SocketAddr) -> Result<TcpStream, CustomError> {
+        let mut stream = open_stream(address)?;
+        let Some(key) = &self.key else { return Ok(stream) };
+        if !self.peers.contains(&address) {
+            return Ok(stream);
+        }
+
+        let answered = respond_challenge(&mut stream, key);
+        if answered.is_err() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        answered.map(|_| stream)
+    }
+
+    /// Autentica una conexion entrante enviandole un challenge. Si el nodo no tiene clave acepta todas.
+    /// Si la respuesta no llega a tiempo o no coincide cierra la conexion, sin procesar ningun mensaje,
+    /// y devuelve CustomError::CannotHandshakeNode.
+    pub fn accept(&self, stream: &mut TcpStream) -> Result<(), CustomError> {
+        let Some(key) = &self.key else { return Ok(()) };
+
+        let accepted = send_challenge(stream, key);
+        if accepted.is_err() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        accepted
+    }
+}
+
+/// Lee la clave compartida de la red privada, en hexadecimal (PRIVATE_NETWORK_KEY_LEN bytes).
+pub fn parse_private_network_key(value: &str) -> Option<Vec<u8>> {
+    from_hex(value).filter(|key| key.len() == PRIVATE_NETWORK_KEY_LEN)
+}
+
+/// Lado del que acepta la conexion: envia un challenge aleatorio y verifica la respuesta.
+fn send_challenge(stream: &mut TcpStream, key: &[u8]) -> Result<(), CustomError> {
+    stream.set_read_timeout(Some(AUTH_TIMEOUT))?;
+    let challenge = random_bytes(CHALLENGE_LEN);
+    stream.write_all(&challenge)?;
+
+    let mut response = [0; RESPONSE_LEN];
+    stream
+        .read_exact(&mut response)
+        .map_err(|_| CustomError::CannotHandshakeNode)?;
+    if !constant_time_eq(&hmac_sha256(key, &challenge), &response) {
+        return Err(CustomError::CannotHandshakeNode);
+    }
+    stream.set_read_timeout(None)?;
+    Ok(())
+}
+
+/// Lado del que llama: lee el challenge y responde HMAC-SHA256(clave, challenge).
+fn respond_challenge(stream: &mut TcpStream, key: &[u8]) -> Result<(), CustomError> {
+    stream.set_read_timeout(Some(AUTH_TIMEOUT))?;
+    let mut challenge = [0; CHALLENGE_LEN];
+    stream
+        .read_exact(&mut challenge)
+        .map_err(|_| CustomError::CannotHandshakeNode)?;
+    stream.write_all(&hmac_sha256(key, &challenge))?;
+    stream.set_read_timeout(None)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+
+    /// Conecta un nodo que llama con la clave caller_key (None si es publico) a otro que escucha con listener_key.
+    /// Devuelve si el que escucha acepto la autenticacion y si el que llama obtuvo el stream.
+    fn authenticate(listener_key: [u8; 32], caller_key: Option<[u8; 32]>) -> (bool, bool) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let listener_thread = thread::spawn(move || {
+            let network = PrivateNetwork::new(Some(listener_key.to_vec()), vec![]);
+            let (mut stream, _) = listener.accept().unwrap();
+            network.accept(&mut stream).is_ok()
+        });
+
+        let caller = PrivateNetwork::new(caller_key.map(|key| key.to_vec()), vec![address]);
+        let called = caller.connect(address).and_then(|mut stream| {
+            // un nodo publico no responde el challenge y envia directamente el mensaje version
+            if caller_key.is_none() {
+                stream.write_all(&[0; RESPONSE_LEN])?;
+            }
+            Ok(stream)
+        });
+        (listener_thread.join().unwrap(), called.is_ok())
+    }
+
+    #[test]
+    fn challenge_response_with_matching_keys() {
+        assert_eq!(authenticate([7; 32], Some([7; 32])), (true, true));
+    }
+
+    #[test]
+    fn challenge_response_with_different_keys() {
+        assert!(!authenticate([7; 32], Some([8; 32])).0);
+    }
+
+    #[test]
+    fn public_callers_are_rejected_by_private_nodes() {
+        assert_eq!(authenticate([7; 32], None), (false, true));
+    }
+
+    #[test]
+    fn private_network_key_must_have_32_bytes() {
+        assert_eq!(
+            parse_private_network_key(&"ab".repeat(32)),
+            Some(vec![0xab; 32])
+        );
+        assert_eq!(parse_private_network_key(&"ab".repeat(16)), None);
+        assert_eq!(parse_private_network_key("not hex"), None);
+    }
+}
@@ -0,0 +1,101 @@
+use std::{
+    collections::hash_map::RandomState,
+    fs::File,
+    hash::{BuildHasher, Hasher},
+    io::Read,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+
+/// Archivo del sistema operativo del que se leen los bytes aleatorios.
+const OS_RANDOM_SOURCE: &str = "/dev/urandom";
+
+/// Contador que se mezcla en los bytes de respaldo, para que dos llamadas en el mismo instante no coincidan.
+static FALLBACK_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// random_bytes devuelve len bytes aleatorios leidos de OS_RANDOM_SOURCE.
+/// Si no se puede leer (por ejemplo en Windows) los genera con las claves aleatorias de RandomState,
+/// que tambien toma el sistema operativo, mezcladas con el momento actual y un contador.
+pub fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; len];
+    if File::open(OS_RANDOM_SOURCE)
+        .and_then(|mut file| file.read_exact(&mut bytes))
+        .is_ok()
+    {
+        return bytes;
+    }
+
+    bytes.clear();
+    while bytes.len() < len {
+        bytes.extend(fallback_u64().to_le_bytes());
+    }
+    bytes.truncate(len);
+    bytes
+}
+
+/// random_nonce devuelve un u64 aleatorio, para los nonces de los mensajes version y ping y los tweaks de los filtros.
+pub fn random_nonce() -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&random_bytes(8));
+    u64::from_le_bytes(bytes)
+}
+
+/// random_below devuelve un numero aleatorio menor a bound, o 0 si bound es 0.
+/// El sesgo del modulo es despreciable para los bounds que se usan (tiempos y posiciones).
+pub fn random_below(bound: u64) -> u64 {
+    match bound {
+        0 => 0,
+        bound => random_nonce() % bound,
+    }
+}
+
+/// random_jitter devuelve una duracion aleatoria menor a max, para que los reintentos de
+/// varios nodos (o de varias direcciones) no se sincronicen.
+pub fn random_jitter(max: Duration) -> Duration {
+    let max_nanos = u64::try_from(max.as_nanos()).unwrap_or(u64::MAX);
+    Duration::from_nanos(random_below(max_nanos))
+}
+
+fn fallback_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(elapsed) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(elapsed.as_nanos());
+    }
+    hasher.write_u64(FALLBACK_COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn random_nonces_are_different() {
+        let nonces: HashSet<u64> = (0..100).map(|_| random_nonce()).collect();
+        assert_eq!(nonces.len(), 100);
+    }
+
+    #[test]
+    fn fallback_values_are_different_in_the_same_instant() {
+        assert_ne!(fallback_u64(), fallback_u64());
+    }
+
+    #[test]
+    fn random_bytes_have_the_requested_length() {
+        assert!(random_bytes(0).is_empty());
+        assert_eq!(random_bytes(13).len(), 13);
+        assert_ne!(random_bytes(32), random_bytes(32));
+    }
+
+    #[test]
+    fn random_below_and_jitter_are_bounded() {
+        assert_eq!(random_below(0), 0);
+        assert_eq!(random_jitter(Duration::ZERO), Duration::ZERO);
+        for _ in 0..100 {
+            assert!(random_below(7) < 7);
+            assert!(random_jitter(Duration::from_millis(5)) < Duration::from_millis(5));
+        }
+    }
+}
@@ -0,0 +1,1116 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{IpAddr, TcpListener, TcpStream},
+    sync::{mpsc, Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+use crate::{
+    error::CustomError,
+    logger::{send_log, Log},
+    loops::node_action_loop::NodeAction,
+    message::Message,
+    messages::{
+        block::Block,
+        transaction::{Transaction, TransactionOptions},
+    },
+    mining::BlockTemplate,
+    node_state::NodeState,
+    rpc_auth::{rpc_error_json, RpcAccess, NOTIFICATION_AUTH_PREFIX},
+    send_many::{FeeSpec, SendManyRequest, SendManyResult, TransactionPlan},
+    structs::{
+        block_header::{display_hash, hash_as_string, parse_display_hash, BlockHeader},
+        outpoint::OutPoint,
+    },
+    wallet_crypto::{from_hex, to_hex},
+    webhooks::json_escape,
+};
+
+/// Puerto en el que escucha el RPC si el config no indica RPC_PORT.
+pub const DEFAULT_RPC_PORT: u16 = 18332;
+
+/// Flag con el que el nodo corre sin interfaz grafica, manejandose solo por el RPC.
+pub const HEADLESS_FLAG: &str = "--headless";
+
+/// Largo maximo en bytes de una linea (un pedido) que envia un cliente del RPC.
+pub const MAX_RPC_LINE_SIZE: usize = 64 * 1024;
+
+/// Profundidad maxima de arrays y objetos anidados en un pedido.
+const MAX_JSON_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+/// JsonValue es un valor JSON de los pedidos que recibe el RPC.
+/// Los numeros se guardan con su texto original para no perder precision al leer montos en satoshis.
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    /// Lee un valor JSON que ocupa todo el texto recibido. Devuelve None si el texto no es JSON valido.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut parser = JsonParser {
+            bytes: text.as_bytes(),
+            position: 0,
+        };
+        let value = parser.value(0)?;
+        parser.skip_whitespace();
+        (parser.position == parser.bytes.len()).then_some(value)
+    }
+
+    /// Codifica el valor en JSON, para devolver el id del pedido en la respuesta.
+    pub fn to_json(&self) -> String {
+        match self {
+            Self::Null => String::from("null"),
+            Self::Bool(value) => value.to_string(),
+            Self::Number(value) => value.clone(),
+            Self::String(value) => format!("\"{}\"", json_escape(value)),
+            Self::Array(values) => format!(
+                "[{}]",
+                values
+                    .iter()
+                    .map(Self::to_json)
+                    .collect::<Vec<String>>()
+                    .join(",")
+            ),
+            Self::Object(fields) => format!(
+                "{{{}}}",
+                fields
+                    .iter()
+                    .map(|(name, value)| format!("\"{}\":{}", json_escape(name), value.to_json()))
+                    .collect::<Vec<String>>()
+                    .join(",")
+            ),
+        }
+    }
+}
+
+/// Parser de JSON recursivo sobre los bytes de un pedido.
+struct JsonParser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl JsonParser<'_> {
+    fn value(&mut self, depth: usize) -> Option<JsonValue> {
+        self.skip_whitespace();
+        match self.bytes.get(self.position)? {
+            b'n' => self.literal("null", JsonValue::Null),
+            b't' => self.literal("true", JsonValue::Bool(true)),
+            b'f' => self.literal("false", JsonValue::Bool(false)),
+            b'"' => self.string().map(JsonValue::String),
+            b'-' | b'0'..=b'9' => self.number(),
+            b'[' if depth < MAX_JSON_DEPTH => self.array(depth + 1),
+            b'{' if depth < MAX_JSON_DEPTH => self.object(depth + 1),
+            _ => None,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(
+            self.bytes.get(self.position),
+            Some(b' ' | b'\t' | b'\r' | b'\n')
+        ) {
+            self.position += 1;
+        }
+    }
+
+    /// Avanza si el proximo byte es expected.
+    fn eat(&mut self, expected: u8) -> bool {
+        self.skip_whitespace();
+        if self.bytes.get(self.position) == Some(&expected) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn literal(&mut self, literal: &str, value: JsonValue) -> Option<JsonValue> {
+        if !self.bytes[self.position..].starts_with(literal.as_bytes()) {
+            return None;
+        }
+        self.position += literal.len();
+        Some(value)
+    }
+
+    fn number(&mut self) -> Option<JsonValue> {
+        let start = self.position;
+        while matches!(
+            self.bytes.get(self.position),
+            Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')
+        ) {
+            self.position += 1;
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.position]).ok()?;
+        text.parse::<f64>().ok()?;
+        Some(JsonValue::Number(text.to_string()))
+    }
+
+    /// Lee un string desde su comilla de apertura, resolviendo los escapes.
+    fn string(&mut self) -> Option<String> {
+        self.position += 1;
+        let mut value = vec![];
+        loop {
+            let byte = *self.bytes.get(self.position)?;
+            self.position += 1;
+            match byte {
+                b'"' => return String::from_utf8(value).ok(),
+                b'\\' => {
+                    let escaped = *self.bytes.get(self.position)?;
+                    self.position += 1;
+                    match escaped {
+                        b'"' | b'\\' | b'/' => value.push(escaped),
+                        b'n' => value.push(b'\n'),
+                        b't' => value.push(b'\t'),
+                        b'r' => value.push(b'\r'),
+                        b'b' => value.push(0x08),
+                        b'f' => value.push(0x0c),
+                        b'u' => {
+                            let hex = self.bytes.get(self.position..self.position + 4)?;
+                            let code =
+                                u32::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?;
+                            self.position += 4;
+                            let mut buffer = [0; 4];
+                            value.extend_from_slice(
+                                char::from_u32(code)?.encode_utf8(&mut buffer).as_bytes(),
+                            );
+                        }
+                        _ => return None,
+                    }
+                }
+                byte => value.push(byte),
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Option<JsonValue> {
+        self.position += 1;
+        let mut values = vec![];
+        if self.eat(b']') {
+            return Some(JsonValue::Array(values));
+        }
+        loop {
+            values.push(self.value(depth)?);
+            if self.eat(b']') {
+                return Some(JsonValue::Array(values));
+            }
+            if !self.eat(b',') {
+                return None;
+            }
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Option<JsonValue> {
+        self.position += 1;
+        let mut fields = vec![];
+        if self.eat(b'}') {
+            return Some(JsonValue::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.bytes.get(self.position) != Some(&b'"') {
+                return None;
+            }
+            let name = self.string()?;
+            if !self.eat(b':') {
+                return None;
+            }
+            fields.push((name, self.value(depth)?));
+            if self.eat(b'}') {
+                return Some(JsonValue::Object(fields));
+            }
+            if !self.eat(b',') {
+                return None;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// RpcRequest es un pedido JSON-RPC recibido en una linea: {"id": ..., "method": "...", "params": [...]}.
+/// Los elementos son:
+/// - id: Id del pedido, que se devuelve en la respuesta (null si no tiene).
+/// - method: Nombre del metodo.
+/// - params: Parametros posicionales del metodo.
+pub struct RpcRequest {
+    pub id: JsonValue,
+    pub method: String,
+    pub params: Vec<JsonValue>,
+}
+
+impl RpcRequest {
+    /// Lee un pedido de una linea. Devuelve CustomError::RpcInvalidRequest si no es un objeto JSON con
+    /// method (string) y, opcionalmente, params (array).
+    pub fn parse(line: &str) -> Result<Self, CustomError> {
+        let Some(JsonValue::Object(fields)) = JsonValue::parse(line) else { return Err(CustomError::RpcInvalidRequest) };
+        let mut request = Self {
+            id: JsonValue::Null,
+            method: String::new(),
+            params: vec![],
+        };
+        for (name, value) in fields {
+            match (name.as_str(), value) {
+                ("id", id) => request.id = id,
+                ("method", JsonValue::String(method)) => request.method = method,
+                ("params", JsonValue::Array(params)) => request.params = params,
+                ("method" | "params", _) => return Err(CustomError::RpcInvalidRequest),
+                _ => (),
+            }
+        }
+        if request.method.is_empty() {
+            return Err(CustomError::RpcInvalidRequest);
+        }
+        Ok(request)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// RpcCall es un metodo del RPC con sus parametros ya validados, que se ejecuta en el NodeActionLoop.
+/// Los montos son en satoshis. Los metodos son:
+/// - GetBlockCount: Altura de la cadena de headers.
+/// - GetBalance: Balance de la wallet activa.
+/// - ListUtxos: UTXO de la wallet activa con sus confirmaciones.
+/// - SendToAddress: Envia amount a address desde la wallet activa pagando fee, devuelve el hash de la transaccion.
+/// - GetPeerInfo: Peers conectados.
+/// - GetPendingTxs: Transacciones pendientes de la wallet activa, con el valor que mueven.
+/// - GetBlockTemplate: Bloque candidato sobre la punta de la cadena con una coinbase que le paga a address (ver BlockTemplate).
+/// - SubmitBlock: Bloque serializado que se agrega como si llegara de la red (ver NodeState::submit_block).
+/// - SendMany: Envia a varias direcciones en una unica transaccion (ver NodeState::send_many), devuelve su hash
+///   o, si el pedido es dry_run, la transaccion planificada.
+/// - GetBlockHash: Hash del bloque con ese height.
+/// - GetBlockHeader: Campos decodificados del header del bloque con ese hash, con su height y confirmaciones.
+/// - GetBlock: Bloque guardado con ese hash, serializado en hexadecimal o, si es verbose, los campos de su header
+///   junto a su tamaño y sus transacciones.
+pub enum RpcCall {
+    GetBlockCount,
+    GetBalance,
+    ListUtxos,
+    SendToAddress {
+        address: String,
+        amount: u64,
+        fee: u64,
+    },
+    GetPeerInfo,
+    GetPendingTxs,
+    GetBlockTemplate {
+        address: String,
+    },
+    SubmitBlock {
+        block: Vec<u8>,
+    },
+    SendMany {
+        request: SendManyRequest,
+    },
+    GetBlockHash {
+        height: usize,
+    },
+    GetBlockHeader {
+        hash: Vec<u8>,
+    },
+    GetBlock {
+        hash: Vec<u8>,
+        verbose: bool,
+    },
+}
+
+/// RpcResponse es el resultado de ejecutar un RpcCall.
+/// Los elementos son:
+/// - result: Resultado del metodo codificado en JSON.
+/// - transaction: Transaccion creada por el metodo, que el NodeActionLoop envia a los peers antes de responder.
+/// - block_header: Header del bloque agregado por el metodo, que el NodeActionLoop anuncia a los peers.
+pub struct RpcResponse {
+    pub result: String,
+    pub transaction: Option<Transaction>,
+    pub block_header: Option<BlockHeader>,
+}
+
+impl RpcCall {
+    /// Arma la llamada al metodo del pedido con sus parametros.
+    /// Devuelve CustomError::RpcUnknownMethod si el metodo no existe,
+    /// o CustomError::RpcInvalidParams si los parametros no son los que espera el metodo.
+    pub fn from_request(request: &RpcRequest) -> Result<Self, CustomError> {
+        let call = match request.method.as_str() {
+            "getblockcount" => Self::GetBlockCount,
+            "getbalance" => Self::GetBalance,
+            "listutxos" => Self::ListUtxos,
+            "getpeerinfo" => Self::GetPeerInfo,
+            "getpendingtxs" => Self::GetPendingTxs,
+            "sendtoaddress" => {
+                let [address, amount, fee] = request.params.as_slice() else {
+                    return Err(CustomError::RpcInvalidParams(
+                        "sendtoaddress expects address, amount and fee".to_string(),
+                    ));
+                };
+                let JsonValue::String(address) = address else {
+                    return Err(CustomError::RpcInvalidParams(
+                        "address must be a string".to_string(),
+                    ));
+                };
+                return Ok(Self::SendToAddress {
+                    address: address.clone(),
+                    amount: satoshis_param(amount, "amount")?,
+                    fee: satoshis_param(fee, "fee")?,
+                });
+            }
+            "getblocktemplate" => {
+                let [JsonValue::String(address)] = request.params.as_slice() else {
+                    return Err(CustomError::RpcInvalidParams(
+                        "getblocktemplate expects the address the coinbase pays to".to_string(),
+                    ));
+                };
+                return Ok(Self::GetBlockTemplate {
+                    address: address.clone(),
+                });
+            }
+            "submitblock" => {
+                let block = match request.params.as_slice() {
+                    [JsonValue::String(block)] => from_hex(block),
+                    _ => None,
+                };
+                let Some(block) = block else {
+                    return Err(CustomError::RpcInvalidParams(
+                        "submitblock expects the hex encoded block".to_string(),
+                    ));
+                };
+                return Ok(Self::SubmitBlock { block });
+            }
+            "sendmany" => {
+                let [amounts, options] = request.params.as_slice() else {
+                    return Err(CustomError::RpcInvalidParams(
+                        "sendmany expects the amounts by address and the options".to_string(),
+                    ));
+                };
+                return Ok(Self::SendMany {
+                    request: send_many_request(amounts, options)?,
+                });
+            }
+            "getblockhash" => {
+                let [height] = request.params.as_slice() else {
+                    return Err(CustomError::RpcInvalidParams(
+                        "getblockhash expects the height of the block".to_string(),
+                    ));
+                };
+                return Ok(Self::GetBlockHash {
+                    height: height_param(height)?,
+                });
+            }
+            "getblockheader" => {
+                let [hash] = request.params.as_slice() else {
+                    return Err(CustomError::RpcInvalidParams(
+                        "getblockheader expects the hash of the block".to_string(),
+                    ));
+                };
+                return Ok(Self::GetBlockHeader {
+                    hash: hash_param(hash)?,
+                });
+            }
+            "getblock" => {
+                let (hash, verbose) = match request.params.as_slice() {
+                    [hash] => (hash, true),
+                    [hash, JsonValue::Number(verbosity)] if verbosity == "0" => (hash, false),
+                    [hash, JsonValue::Number(verbosity)] if verbosity == "1" => (hash, true),
+                    _ => {
+                        return Err(CustomError::RpcInvalidParams(
+                            "getblock expects the hash of the block and optionally the verbosity (0 or 1)"
+                                .to_string(),
+                        ))
+                    }
+                };
+                return Ok(Self::GetBlock {
+                    hash: hash_param(hash)?,
+                    verbose,
+                });
+            }
+            method => return Err(CustomError::RpcUnknownMethod(method.to_string())),
+        };
+        if !request.params.is_empty() {
+            return Err(CustomError::RpcInvalidParams(format!(
+                "{} takes no params",
+                request.method
+            )));
+        }
+        Ok(call)
+    }
+
+    /// Ejecuta la llamada sobre el estado del nodo. SendToAddress y SendMany crean la transaccion (ver NodeState::send_many)
+    /// y la devuelven en la respuesta para que se envie a los peers, SubmitBlock devuelve el header del bloque agregado
+    /// para que se anuncie y responde null, como bitcoind.
+    /// Devuelve CustomError si no hay una wallet activa o no se pudo crear la transaccion.
+    pub fn execute(self, node_state: &mut NodeState) -> Result<RpcResponse, CustomError> {
+        let result = match self {
+            Self::GetBlockCount => node_state.get_tip_height().to_string(),
+            Self::GetBalance => node_state.get_active_wallet_balance()?.to_string(),
+            Self::ListUtxos => json_array(
+                node_state
+                    .get_active_wallet_utxo_with_confirmations()?
+                    .into_iter()
+                    .map(|(out_point, value, confirmations)| {
+                        format!(
+                            "{{\"txid\":\"{}\",\"vout\":{},\"amount\":{},\"confirmations\":{},\"coinbase\":{}}}",
+                            hash_as_string(out_point.hash),
+                            out_point.index,
+                            value.tx_out.value,
+                            confirmations,
+                            value.is_coinbase
+                        )
+                    }),
+            ),
+            Self::SendToAddress {
+                address,
+                amount,
+                fee,
+            } => {
+                let transaction = node_state.make_transaction(
+                    vec![(address, amount)],
+                    fee,
+                    TransactionOptions::default(),
+                )?;
+                return Ok(RpcResponse {
+                    result: format!("\"{}\"", hash_as_string(transaction.hash())),
+                    transaction: Some(transaction),
+                    block_header: None,
+                });
+            }
+            Self::GetPeerInfo => json_array(node_state.get_peers().iter().map(|peer| {
+                format!(
+                    "{{\"addr\":\"{}\",\"services\":{},\"version\":{},\"startingheight\":{},\"bytesrecv\":{},\"conntime\":{}}}",
+                    peer.address,
+                    peer.services,
+                    peer.version,
+                    peer.best_height,
+                    peer.stats.bytes_received,
+                    peer.connected_at.elapsed().as_secs()
+                )
+            })),
+            Self::GetPendingTxs => json_array(
+                node_state
+                    .get_active_wallet_pending_txs()?
+                    .into_iter()
+                    .map(|movement| {
+                        format!(
+                            "{{\"txid\":\"{}\",\"amount\":{}}}",
+                            hash_as_string(movement.tx_hash),
+                            movement.value
+                        )
+                    }),
+            ),
+            Self::GetBlockTemplate { address } => {
+                block_template_json(&node_state.create_block_template(&address)?)
+            }
+            Self::SubmitBlock { block } => {
+                let block = Block::parse(block).map_err(|_| {
+                    CustomError::RpcInvalidParams("block cannot be parsed".to_string())
+                })?;
+                let block_header = block.header.clone();
+                node_state.submit_block(block)?;
+                return Ok(RpcResponse {
+                    result: String::from("null"),
+                    transaction: None,
+                    block_header: Some(block_header),
+                });
+            }
+            Self::GetBlockHash { height } => match node_state.block_hash_at_height(height) {
+                Some(hash) => format!("\"{}\"", display_hash(&hash)),
+                None => return Err(block_not_found()),
+            },
+            Self::GetBlockHeader { hash } => {
+                let height = node_state.height_of_block(&hash).ok_or_else(block_not_found)?;
+                let (header, confirmations) = node_state
+                    .get_header_at_height(height)
+                    .ok_or_else(block_not_found)?;
+                block_header_json(&header, height, confirmations, &[])
+            }
+            Self::GetBlock { hash, verbose } => {
+                if !node_state.is_block_stored(&hash) {
+                    return Err(block_not_found());
+                }
+                let block = node_state.get_block(&hash)?;
+                match verbose {
+                    true => {
+                        let height = node_state.height_of_block(&hash).ok_or_else(block_not_found)?;
+                        let confirmations = node_state
+                            .get_header_at_height(height)
+                            .map_or(0, |(_, confirmations)| confirmations);
+                        block_json(&block, height, confirmations)
+                    }
+                    false => format!("\"{}\"", to_hex(&block.serialize())),
+                }
+            }
+            Self::SendMany { request } => match node_state.send_many(request)? {
+                SendManyResult::Planned(plan) => transaction_plan_json(&plan),
+                SendManyResult::Sent { txid, transaction } => {
+                    return Ok(RpcResponse {
+                        result: format!("\"{txid}\""),
+                        transaction: Some(transaction),
+                        block_header: None,
+                    });
+                }
+            },
+        };
+        Ok(RpcResponse {
+            result,
+            transaction: None,
+            block_header: None,
+        })
+    }
+}
+
+/// Lee un monto en satoshis, que debe ser un numero entero no negativo.
+fn satoshis_param(value: &JsonValue, name: &str) -> Result<u64, CustomError> {
+    match value {
+        JsonValue::Number(number) => number.parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| CustomError::RpcInvalidParams(format!("{name} must be an amount of satoshis")))
+}
+
+/// Lee un height, que debe ser un numero entero no negativo.
+fn height_param(value: &JsonValue) -> Result<usize, CustomError> {
+    match value {
+        JsonValue::Number(number) => number.parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| CustomError::RpcInvalidParams("height must be a block height".to_string()))
+}
+
+/// Lee el hash de un bloque como lo muestran los exploradores (ver parse_display_hash).
+fn hash_param(value: &JsonValue) -> Result<Vec<u8>, CustomError> {
+    match value {
+        JsonValue::String(hash) => parse_display_hash(hash),
+        _ => None,
+    }
+    .ok_or_else(|| CustomError::RpcInvalidParams("hash must be a block hash".to_string()))
+}
+
+fn block_not_found() -> CustomError {
+    CustomError::RpcInvalidParams("block not found".to_string())
+}
+
+/// Codifica los campos decodificados de un header (ver BlockHeader::fields, los mismos que muestra el detalle
+/// de un bloque en la interfaz grafica) con los nombres de getblockheader de bitcoind, seguidos de extra.
+fn block_header_json(
+    header: &BlockHeader,
+    height: usize,
+    confirmations: u32,
+    extra: &[(&str, String)],
+) -> String {
+    let fields = header
+        .fields(height, confirmations)
+        .into_iter()
+        .map(|(name, value)| match name {
+            "Hash" => format!("\"hash\":\"{value}\""),
+            "Height" => format!("\"height\":{value}"),
+            "Confirmations" => format!("\"confirmations\":{value}"),
+            "Version" => format!("\"version\":{value}"),
+            "Previous block" => format!("\"previousblockhash\":\"{value}\""),
+            "Merkle root" => format!("\"merkleroot\":\"{value}\""),
+            "Time" => format!("\"time\":{value}"),
+            "Bits" => format!("\"bits\":\"{value}\""),
+            "Nonce" => format!("\"nonce\":{value}"),
+            name => format!("\"{}\":\"{}\"", json_escape(name), json_escape(&value)),
+        })
+        .chain(
+            extra
+                .iter()
+                .map(|(name, value)| format!("\"{name}\":{value}")),
+        );
+    format!("{{{}}}", fields.collect::<Vec<String>>().join(","))
+}
+
+/// Codifica un bloque como el resultado de getblock de bitcoind con verbosidad 1: los campos de su header,
+/// su tamaño, la cantidad de transacciones y sus hashes.
+fn block_json(block: &Block, height: usize, confirmations: u32) -> String {
+    let transactions = json_array(
+        block
+            .transactions
+            .iter()
+            .map(|transaction| format!("\"{}\"", display_hash(&transaction.hash()))),
+    );
+    block_header_json(
+        &block.header,
+        height,
+        confirmations,
+        &[
+            ("size", block.serialize().len().to_string()),
+            ("nTx", block.transactions.len().to_string()),
+            ("tx", transactions),
+        ],
+    )
+}
+
+/// Arma el pedido de sendmany a partir de sus parametros:
+/// - amounts: Objeto con el monto en satoshis de cada direccion, los outputs se crean en ese orden.
+/// - options: Objeto con el fee, fijo ("fee") o por byte ("fee_rate"), y opcionalmente los inputs a gastar
+///   ("inputs", con el "txid" y el "vout" de cada uno como los muestra listutxos), "locktime", "replaceable" y "dry_run".
+/// Devuelve CustomError::RpcInvalidParams si falta el fee, se indican los dos o algun valor no es del tipo esperado.
+fn send_many_request(
+    amounts: &JsonValue,
+    options: &JsonValue,
+) -> Result<SendManyRequest, CustomError> {
+    let invalid = |message: &str| CustomError::RpcInvalidParams(message.to_string());
+    let JsonValue::Object(amounts) = amounts else { return Err(invalid("amounts must be an object of address: satoshis")) };
+    let JsonValue::Object(options) = options else { return Err(invalid("options must be an object")) };
+
+    let outputs = amounts
+        .iter()
+        .map(|(address, amount)| Ok((address.clone(), satoshis_param(amount, address)?)))
+        .collect::<Result<Vec<(String, u64)>, CustomError>>()?;
+    let mut request = SendManyRequest::new(outputs, 0);
+    let mut fee = None;
+    for (name, value) in options {
+        match (name.as_str(), value) {
+            ("fee", _) if fee.is_none() => fee = Some(FeeSpec::Fixed(satoshis_param(value, name)?)),
+            ("fee_rate", _) if fee.is_none() => {
+                fee = Some(FeeSpec::Rate(satoshis_param(value, name)?))
+            }
+            ("fee" | "fee_rate", _) => return Err(invalid("fee and fee_rate cannot be combined")),
+            ("inputs", JsonValue::Array(inputs)) => {
+                request.inputs = Some(
+                    inputs
+                        .iter()
+                        .map(outpoint_param)
+                        .collect::<Result<Vec<OutPoint>, CustomError>>()?,
+                )
+            }
+            ("locktime", _) => {
+                let lock_time = satoshis_param(value, name)
+                    .ok()
+                    .and_then(|lock_time| u32::try_from(lock_time).ok())
+                    .ok_or_else(|| invalid("locktime must be a height or a timestamp"))?;
+                request.options.lock_time = Some(lock_time);
+            }
+            ("replaceable", JsonValue::Bool(replaceable)) => {
+                request.options.replaceable = *replaceable
+            }
+            ("dry_run", JsonValue::Bool(dry_run)) => request.dry_run = *dry_run,
+            (name, _) => {
+                return Err(CustomError::RpcInvalidParams(format!(
+                    "invalid sendmany option: {name}"
+                )))
+            }
+        }
+    }
+    request.fee = fee.ok_or_else(|| invalid("sendmany expects a fee or a fee_rate"))?;
+    Ok(request)
+}
+
+/// Lee un input de sendmany: un objeto con el "txid" (como lo muestra listutxos) y el "vout" del output a gastar.
+fn outpoint_param(value: &JsonValue) -> Result<OutPoint, CustomError> {
+    let invalid =
+        || CustomError::RpcInvalidParams("inputs must be objects with txid and vout".to_string());
+    let JsonValue::Object(fields) = value else { return Err(invalid()) };
+    let [(txid_name, JsonValue::String(txid)), (vout_name, vout)] = fields.as_slice() else { return Err(invalid()) };
+    if txid_name != "txid" || vout_name != "vout" {
+        return Err(invalid());
+    }
+    let hash = from_hex(txid)
+        .filter(|hash| hash.len() == 32)
+        .ok_or_else(invalid)?;
+    let index = satoshis_param(vout, vout_name)
+        .ok()
+        .and_then(|index| u32::try_from(index).ok())
+        .ok_or_else(invalid)?;
+    Ok(OutPoint { hash, index })
+}
+
+/// Codifica una transaccion planificada por sendmany con dry_run: sus inputs, sus outputs (incluido el cambio),
+/// el fee, el cambio y el tamaño estimado en bytes.
+fn transaction_plan_json(plan: &TransactionPlan) -> String {
+    let inputs = json_array(plan.inputs.iter().map(|(out_point, prev_output)| {
+        format!(
+            "{{\"txid\":\"{}\",\"vout\":{},\"amount\":{}}}",
+            hash_as_string(out_point.hash.clone()),
+            out_point.index,
+            prev_output.value
+        )
+    }));
+    let outputs = json_array(plan.outputs.iter().map(|(address, amount)| {
+        format!(
+            "{{\"address\":\"{}\",\"amount\":{}}}",
+            json_escape(address),
+            amount
+        )
+    }));
+    format!(
+        "{{\"inputs\":{},\"outputs\":{},\"fee\":{},\"change\":{},\"size\":{}}}",
+        inputs,
+        outputs,
+        plan.fee,
+        plan.change,
+        plan.estimated_size()
+    )
+}
+
+/// Codifica el template como el resultado simplificado de getblocktemplate de bitcoind, con la coinbase ya armada
+/// (coinbasetxn). Los hashes se muestran como en los exploradores y las transacciones se envian serializadas en hexadecimal.
+fn block_template_json(template: &BlockTemplate) -> String {
+    let transactions = json_array(template.transactions.iter().map(|transaction| {
+        format!(
+            "{{\"data\":\"{}\",\"txid\":\"{}\"}}",
+            to_hex(&transaction.serialize()),
+            display_hash(&transaction.hash())
+        )
+    }));
+    format!(
+        "{{\"version\":{},\"previousblockhash\":\"{}\",\"height\":{},\"curtime\":{},\"bits\":\"{:08x}\",\"coinbasevalue\":{},\"fees\":{},\"coinbasetxn\":{{\"data\":\"{}\"}},\"transactions\":{}}}",
+        template.version,
+        display_hash(&template.prev_block_hash),
+        template.height,
+        template.timestamp,
+        template.bits,
+        template.coinbase.outputs[0].value,
+        template.fees,
+        to_hex(&template.coinbase.serialize()),
+        transactions
+    )
+}
+
+fn json_array(values: impl Iterator<Item = String>) -> String {
+    format!("[{}]", values.collect::<Vec<String>>().join(","))
+}
+
+/// RpcServer es el servidor del RPC: acepta conexiones TCP y atiende cada una en su propio thread.
+/// Cada linea que envia un cliente es un pedido JSON-RPC (ver RpcRequest) y se responde con una linea
+/// {"result": ..., "error": ..., "id": ...}. El cliente se autentica enviando antes una linea con
+/// NOTIFICATION_AUTH_PREFIX seguido del header de HTTP basic auth ("AUTH Basic <base64 de usuario:password>"),
+/// y cada pedido pasa por RpcAccess. Los pedidos se ejecutan en el NodeActionLoop con NodeAction::Rpc,
+/// el mismo canal que usa la interfaz grafica, asi que se bloquea el estado del nodo igual que con la GUI.
+/// Los elementos son:
+/// - listener: Socket en el que se aceptan las conexiones.
+/// - access: Control de acceso compartido por todas las conexiones.
+/// - node_action_sender: Sender para enviar acciones al nodo.
+/// - logger_sender: Sender para enviar logs al logger.
+pub struct RpcServer {
+    listener: TcpListener,
+    access: Arc<Mutex<RpcAccess>>,
+    node_action_sender: mpsc::Sender<NodeAction>,
+    logger_sender: mpsc::Sender<Log>,
+}
+
+impl RpcServer {
+    #[must_use]
+    /// Inicializa el servidor en un thread, atendiendo las conexiones del listener.
+    pub fn spawn(
+        listener: TcpListener,
+        access: RpcAccess,
+        node_action_sender: mpsc::Sender<NodeAction>,
+        logger_sender: mpsc::Sender<Log>,
+    ) -> JoinHandle<()> {
+        thread::spawn(move || {
+            let server = Self {
+                listener,
+                access: Arc::new(Mutex::new(access)),
+                node_action_sender,
+                logger_sender,
+            };
+            server.event_loop()
+        })
+    }
+
+    fn event_loop(&self) {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    send_log(
+                        &self.logger_sender,
+                        Log::Message(format!("Error accepting RPC connection: {error}")),
+                    );
+                    continue;
+                }
+            };
+            let connection = RpcConnection {
+                access: self.access.clone(),
+                node_action_sender: self.node_action_sender.clone(),
+            };
+            let logger_sender = self.logger_sender.clone();
+            thread::spawn(move || {
+                if let Err(error) = connection.serve(stream) {
+                    send_log(
+                        &logger_sender,
+                        Log::Message(format!("Error on RPC connection: {error}")),
+                    );
+                }
+            });
+        }
+    }
+}
+
+/// Conexion de un cliente del RPC.
+struct RpcConnection {
+    access: Arc<Mutex<RpcAccess>>,
+    node_action_sender: mpsc::Sender<NodeAction>,
+}
+
+impl RpcConnection {
+    /// Responde los pedidos del cliente hasta que cierra la conexion o envia una linea mas larga que MAX_RPC_LINE_SIZE.
+    fn serve(&self, stream: TcpStream) -> Result<(), CustomError> {
+        let ip = stream.peer_addr()?.ip();
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        let mut authorization: Option<String> = None;
+        loop {
+            let mut line = String::new();
+            let read = (&mut reader)
+                .take(MAX_RPC_LINE_SIZE as u64 + 1)
+                .read_line(&mut line)?;
+            if read == 0 {
+                return Ok(());
+            }
+            if read > MAX_RPC_LINE_SIZE {
+                let response = rpc_error_json(&CustomError::RpcInvalidRequest, "null");
+                writer.write_all(format!("{response}\n").as_bytes())?;
+                return Ok(());
+            }
+
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(value) = line.strip_prefix(NOTIFICATION_AUTH_PREFIX) {
+                authorization = Some(value.to_string());
+                continue;
+            }
+            let response = self.respond(ip, authorization.as_deref(), line);
+            writer.write_all(format!("{response}\n").as_bytes())?;
+        }
+    }
+
+    fn respond(&self, ip: IpAddr, authorization: Option<&str>, line: &str) -> String {
+        let request = match RpcRequest::parse(line) {
+            Ok(request) => request,
+            Err(error) => return rpc_error_json(&error, "null"),
+        };
+        let id = request.id.to_json();
+        match self.dispatch(ip, authorization, &request) {
+            Ok(result) => format!("{{\"result\":{result},\"error\":null,\"id\":{id}}}"),
+            Err(error) => rpc_error_json(&error, &id),
+        }
+    }
+
+    /// Autoriza el pedido y lo ejecuta en el NodeActionLoop, esperando su resultado.
+    fn dispatch(
+        &self,
+        ip: IpAddr,
+        authorization: Option<&str>,
+        request: &RpcRequest,
+    ) -> Result<String, CustomError> {
+        self.access
+            .lock()?
+            .authorize(ip, authorization, &request.method, Instant::now())?;
+        let call = RpcCall::from_request(request)?;
+        let (response_sender, response_receiver) = mpsc::channel();
+        self.node_action_sender
+            .send(NodeAction::Rpc((call, response_sender)))?;
+        response_receiver.recv()?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(line: &str) -> RpcRequest {
+        RpcRequest::parse(line).unwrap()
+    }
+
+    #[test]
+    fn json_values_are_parsed_and_encoded() {
+        let value =
+            JsonValue::parse(r#" {"a": [1, -2.5e3, true, null], "b": "x\"\\\u00e9\n", "c": {}} "#)
+                .unwrap();
+        assert_eq!(
+            value,
+            JsonValue::Object(vec![
+                (
+                    "a".to_string(),
+                    JsonValue::Array(vec![
+                        JsonValue::Number("1".to_string()),
+                        JsonValue::Number("-2.5e3".to_string()),
+                        JsonValue::Bool(true),
+                        JsonValue::Null,
+                    ])
+                ),
+                ("b".to_string(), JsonValue::String("x\"\\é\n".to_string())),
+                ("c".to_string(), JsonValue::Object(vec![])),
+            ])
+        );
+        assert_eq!(
+            value.to_json(),
+            r#"{"a":[1,-2.5e3,true,null],"b":"x\"\\é\u000a","c":{}}"#
+        );
+
+        for invalid in ["", "{", "[1,]", "{\"a\" 1}", "tru", "\"abc", "1 2", "{1:2}"] {
+            assert_eq!(JsonValue::parse(invalid), None, "{invalid}");
+        }
+        let nested = "[".repeat(MAX_JSON_DEPTH + 1) + &"]".repeat(MAX_JSON_DEPTH + 1);
+        assert_eq!(JsonValue::parse(&nested), None);
+    }
+
+    #[test]
+    fn requests_are_turned_into_calls() {
+        let getbalance = request(r#"{"id":7,"method":"getbalance"}"#);
+        assert_eq!(getbalance.id, JsonValue::Number("7".to_string()));
+        assert_eq!(
+            RpcCall::from_request(&getbalance).unwrap(),
+            RpcCall::GetBalance
+        );
+
+        let send = request(
+            r#"{"id":"a","method":"sendtoaddress","params":["mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm",5000,200]}"#,
+        );
+        assert_eq!(
+            RpcCall::from_request(&send).unwrap(),
+            RpcCall::SendToAddress {
+                address: "mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm".to_string(),
+                amount: 5000,
+                fee: 200,
+            }
+        );
+
+        let template = request(r#"{"method":"getblocktemplate","params":["addr"]}"#);
+        assert_eq!(
+            RpcCall::from_request(&template).unwrap(),
+            RpcCall::GetBlockTemplate {
+                address: "addr".to_string()
+            }
+        );
+        let submit = request(r#"{"method":"submitblock","params":["00ff"]}"#);
+        assert_eq!(
+            RpcCall::from_request(&submit).unwrap(),
+            RpcCall::SubmitBlock {
+                block: vec![0, 255]
+            }
+        );
+
+        let txid = "AB".repeat(32);
+        let send_many = request(&format!(
+            r#"{{"method":"sendmany","params":[{{"a":5000,"b":700,"a":10}},{{"fee_rate":3,"inputs":[{{"txid":"{txid}","vout":1}}],"locktime":500,"replaceable":true,"dry_run":true}}]}}"#
+        ));
+        let mut expected = SendManyRequest::new(
+            vec![
+                ("a".to_string(), 5000),
+                ("b".to_string(), 700),
+                ("a".to_string(), 10),
+            ],
+            0,
+        );
+        expected.fee = FeeSpec::Rate(3);
+        expected.inputs = Some(vec![OutPoint {
+            hash: vec![0xab; 32],
+            index: 1,
+        }]);
+        expected.options = TransactionOptions {
+            lock_time: Some(500),
+            replaceable: true,
+        };
+        expected.dry_run = true;
+        assert_eq!(
+            RpcCall::from_request(&send_many).unwrap(),
+            RpcCall::SendMany { request: expected }
+        );
+
+        let fixed_fee = request(r#"{"method":"sendmany","params":[{"a":5000},{"fee":200}]}"#);
+        assert_eq!(
+            RpcCall::from_request(&fixed_fee).unwrap(),
+            RpcCall::SendMany {
+                request: SendManyRequest::new(vec![("a".to_string(), 5000)], 200)
+            }
+        );
+
+        let block_hash = request(r#"{"method":"getblockhash","params":[12]}"#);
+        assert_eq!(
+            RpcCall::from_request(&block_hash).unwrap(),
+            RpcCall::GetBlockHash { height: 12 }
+        );
+        let hash = format!("{}01", "00".repeat(31));
+        let mut expected_hash = vec![0; 32];
+        expected_hash[0] = 1;
+        let header = request(&format!(
+            r#"{{"method":"getblockheader","params":["{hash}"]}}"#
+        ));
+        assert_eq!(
+            RpcCall::from_request(&header).unwrap(),
+            RpcCall::GetBlockHeader {
+                hash: expected_hash.clone()
+            }
+        );
+        for (params, verbose) in [
+            (format!(r#""{hash}""#), true),
+            (format!(r#""{hash}",1"#), true),
+            (format!(r#""{hash}",0"#), false),
+        ] {
+            let block = request(&format!(r#"{{"method":"getblock","params":[{params}]}}"#));
+            assert_eq!(
+                RpcCall::from_request(&block).unwrap(),
+                RpcCall::GetBlock {
+                    hash: expected_hash.clone(),
+                    verbose
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_requests_are_rejected() {
+        for line in [
+            "not json",
+            "[1]",
+            r#"{"id":1}"#,
+            r#"{"method":5}"#,
+            r#"{"method":"getbalance","params":{}}"#,
+        ] {
+            assert!(matches!(
+                RpcRequest::parse(line),
+                Err(CustomError::RpcInvalidRequest)
+            ));
+        }
+
+        let unknown = request(r#"{"method":"stop"}"#);
+        assert!(matches!(
+            RpcCall::from_request(&unknown),
+            Err(CustomError::RpcUnknownMethod(method)) if method == "stop"
+        ));
+        for line in [
+            r#"{"method":"getbalance","params":[1]}"#,
+            r#"{"method":"sendtoaddress","params":["addr",5000]}"#,
+            r#"{"method":"sendtoaddress","params":["addr",-1,200]}"#,
+            r#"{"method":"sendtoaddress","params":["addr",0.5,200]}"#,
+            r#"{"method":"sendtoaddress","params":[1,5000,200]}"#,
+            r#"{"method":"getblocktemplate"}"#,
+            r#"{"method":"submitblock","params":["zz"]}"#,
+            r#"{"method":"sendmany","params":[{"a":5000}]}"#,
+            r#"{"method":"sendmany","params":[{"a":5000},{}]}"#,
+            r#"{"method":"sendmany","params":[{"a":5000},{"fee":200,"fee_rate":3}]}"#,
+            r#"{"method":"sendmany","params":[{"a":-5},{"fee":200}]}"#,
+            r#"{"method":"sendmany","params":[["a",5000],{"fee":200}]}"#,
+            r#"{"method":"sendmany","params":[{"a":5000},{"fee":200,"inputs":[{"txid":"ab","vout":0}]}]}"#,
+            r#"{"method":"sendmany","params":[{"a":5000},{"fee":200,"locktime":4294967296}]}"#,
+            r#"{"method":"sendmany","params":[{"a":5000},{"fee":200,"subtract_fee":true}]}"#,
+            r#"{"method":"getblockhash"}"#,
+            r#"{"method":"getblockhash","params":["12"]}"#,
+            r#"{"method":"getblockhash","params":[-1]}"#,
+            r#"{"method":"getblockheader","params":["abcd"]}"#,
+            r#"{"method":"getblockheader","params":[12]}"#,
+            r#"{"method":"getblock","params":[]}"#,
+            r#"{"method":"getblock","params":["zz"]}"#,
+            r#"{"method":"getblock","params":["0000000000000000000000000000000000000000000000000000000000000001",2]}"#,
+        ] {
+            assert!(matches!(
+                RpcCall::from_request(&request(line)),
+                Err(CustomError::RpcInvalidParams(_))
+            ));
+        }
+
+        let response = rpc_error_json(&CustomError::RpcUnknownMethod("stop".to_string()), "3");
+        assert_eq!(
+            response,
+            r#"{"result":null,"error":{"code":-32601,"message":"method not found: stop"},"id":3}"#
+        );
+    }
+}
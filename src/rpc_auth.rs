@@ -0,0 +1,427 @@
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    net::IpAddr,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::CustomError,
+    rng::random_bytes,
+    wallet_crypto::{constant_time_eq, to_hex},
+};
+
+/// Archivo del store con las credenciales de la sesion, si el config no indica RPC_AUTH.
+pub const RPC_COOKIE_FILE: &str = ".cookie";
+
+/// Usuario de las credenciales de la cookie, como el .cookie de bitcoind.
+pub const RPC_COOKIE_USER: &str = "__cookie__";
+
+/// Largo en bytes del password aleatorio de la cookie.
+const RPC_COOKIE_PASSWORD_LEN: usize = 32;
+
+/// Intentos fallidos seguidos desde una IP tras los cuales se rechazan sus pedidos durante RPC_AUTH_COOLDOWN.
+pub const MAX_RPC_AUTH_FAILURES: u32 = 5;
+pub const RPC_AUTH_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// Prefijo de la linea con la que se autentica un cliente del socket de notificaciones antes de suscribirse.
+pub const NOTIFICATION_AUTH_PREFIX: &str = "AUTH ";
+
+#[derive(Debug, Clone, PartialEq)]
+/// RpcCredentials son el usuario y el password que se exigen con HTTP basic auth en cada pedido RPC.
+/// Los elementos son:
+/// - user: Usuario.
+/// - password: Password.
+pub struct RpcCredentials {
+    pub user: String,
+    pub password: String,
+}
+
+impl RpcCredentials {
+    /// Lee las credenciales con el formato usuario:password. Ninguno de los dos puede estar vacio.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (user, password) = value.split_once(':')?;
+        if user.is_empty() || password.is_empty() {
+            return None;
+        }
+        Some(Self {
+            user: user.to_string(),
+            password: password.to_string(),
+        })
+    }
+
+    /// Genera credenciales nuevas para la sesion y las escribe en RPC_COOKIE_FILE dentro del store como
+    /// RPC_COOKIE_USER:password, con permisos 0600 para que solo las lea el usuario que corre el nodo.
+    /// Reemplaza la cookie de una sesion anterior.
+    pub fn create_cookie(store_path: &str) -> Result<Self, CustomError> {
+        let credentials = Self {
+            user: RPC_COOKIE_USER.to_string(),
+            password: to_hex(&random_bytes(RPC_COOKIE_PASSWORD_LEN)),
+        };
+
+        let path = Path::new(store_path).join(RPC_COOKIE_FILE);
+        let _ = fs::remove_file(&path);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path)?;
+        file.write_all(format!("{}:{}", credentials.user, credentials.password).as_bytes())?;
+        Ok(credentials)
+    }
+
+    /// Devuelve true si el header Authorization del pedido ("Basic " seguido de usuario:password en base64)
+    /// tiene estas credenciales. El password se compara sin cortar en el primer byte distinto.
+    pub fn matches_basic_auth(&self, authorization: &str) -> bool {
+        let Some(encoded) = authorization.trim().strip_prefix("Basic ") else { return false };
+        let Some(decoded) = decode_base64(encoded.trim()) else { return false };
+        let Some(credentials) = String::from_utf8(decoded)
+            .ok()
+            .and_then(|value| Self::parse(&value))
+        else {
+            return false;
+        };
+        credentials.user == self.user
+            && constant_time_eq(credentials.password.as_bytes(), self.password.as_bytes())
+    }
+}
+
+/// Borra la cookie de la sesion del store, al cerrar el nodo.
+pub fn remove_cookie(store_path: &str) {
+    let _ = fs::remove_file(Path::new(store_path).join(RPC_COOKIE_FILE));
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+/// Intentos de autenticacion fallidos seguidos desde una IP.
+struct AuthFailures {
+    count: u32,
+    blocked_until: Option<Instant>,
+}
+
+#[derive(Debug)]
+/// RpcAccess decide si se atiende un pedido RPC antes de despacharlo: verifica las credenciales,
+/// rechaza las IP con demasiados intentos fallidos seguidos y aplica la lista de metodos permitidos.
+/// Los elementos son:
+/// - credentials: Credenciales que se exigen (RPC_AUTH o la cookie de la sesion).
+/// - whitelist: Metodos permitidos (RPC_WHITELIST), o None si se permiten todos.
+/// - failures: Intentos fallidos por IP.
+pub struct RpcAccess {
+    credentials: RpcCredentials,
+    whitelist: Option<Vec<String>>,
+    failures: HashMap<IpAddr, AuthFailures>,
+}
+
+impl RpcAccess {
+    /// Crea el control de acceso con las credenciales y los metodos permitidos.
+    pub fn new(credentials: RpcCredentials, whitelist: Option<Vec<String>>) -> Self {
+        Self {
+            credentials,
+            whitelist,
+            failures: HashMap::new(),
+        }
+    }
+
+    /// Autoriza un pedido del metodo method desde ip con el header Authorization recibido (None si no tiene).
+    /// Devuelve:
+    /// - CustomError::RpcAuthCooldown si la IP acumulo MAX_RPC_AUTH_FAILURES fallos seguidos hace menos de
+    ///   RPC_AUTH_COOLDOWN, sin verificar las credenciales.
+    /// - CustomError::RpcUnauthorized si faltan las credenciales o no coinciden.
+    /// - CustomError::RpcMethodNotAllowed si el metodo no esta en la whitelist.
+    pub fn authorize(
+        &mut self,
+        ip: IpAddr,
+        authorization: Option<&str>,
+        method: &str,
+        now: Instant,
+    ) -> Result<(), CustomError> {
+        let failures = self.failures.entry(ip).or_default();
+        if let Some(blocked_until) = failures.blocked_until {
+            if now < blocked_until {
+                return Err(CustomError::RpcAuthCooldown);
+            }
+            *failures = AuthFailures::default();
+        }
+
+        let authenticated = authorization.map_or(false, |authorization| {
+            self.credentials.matches_basic_auth(authorization)
+        });
+        if !authenticated {
+            failures.count += 1;
+            if failures.count >= MAX_RPC_AUTH_FAILURES {
+                failures.blocked_until = Some(now + RPC_AUTH_COOLDOWN);
+            }
+            return Err(CustomError::RpcUnauthorized);
+        }
+        self.failures.remove(&ip);
+
+        match &self.whitelist {
+            Some(whitelist) if !whitelist.iter().any(|allowed| allowed == method) => {
+                Err(CustomError::RpcMethodNotAllowed(method.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Devuelve el status HTTP y el cuerpo JSON-RPC con el que se responde un pedido rechazado por RpcAccess.
+/// El resto de los errores se responden como error interno.
+pub fn rpc_error_response(error: &CustomError) -> (u16, String) {
+    let status = match error {
+        CustomError::RpcUnauthorized => 401,
+        CustomError::RpcAuthCooldown => 429,
+        CustomError::RpcMethodNotAllowed(_) => 403,
+        CustomError::RpcUnknownMethod(_) => 404,
+        CustomError::RpcInvalidRequest | CustomError::RpcInvalidParams(_) => 400,
+        _ => 500,
+    };
+    (status, rpc_error_json(error, "null"))
+}
+
+/// Devuelve la respuesta JSON-RPC de un pedido que fallo con error, con el id del pedido ya codificado en JSON.
+/// El codigo sigue JSON-RPC 2.0: -32600 pedido invalido o no autorizado, -32601 metodo inexistente o no permitido,
+/// -32602 parametros invalidos y -32603 para el resto de los errores.
+pub fn rpc_error_json(error: &CustomError, id: &str) -> String {
+    let code = match error {
+        CustomError::RpcUnauthorized
+        | CustomError::RpcAuthCooldown
+        | CustomError::RpcInvalidRequest => -32600,
+        CustomError::RpcMethodNotAllowed(_) | CustomError::RpcUnknownMethod(_) => -32601,
+        CustomError::RpcInvalidParams(_) => -32602,
+        _ => -32603,
+    };
+    let message = match error {
+        CustomError::RpcMethodNotAllowed(detail)
+        | CustomError::RpcUnknownMethod(detail)
+        | CustomError::RpcInvalidParams(detail)
+        | CustomError::InvalidBlock(detail) => format!("{}: {}", error.description(), detail),
+        _ => error.description().to_string(),
+    };
+    let message = message.replace('\\', "\\\\").replace('"', "\\\"");
+    format!(
+        "{{\"result\":null,\"error\":{{\"code\":{code},\"message\":\"{message}\"}},\"id\":{id}}}"
+    )
+}
+
+/// Verifica la primera linea de un cliente del socket de notificaciones, que debe ser
+/// NOTIFICATION_AUTH_PREFIX seguido del token compartido (NOTIFY_TOKEN), antes de aceptar suscripciones.
+/// Devuelve CustomError::RpcUnauthorized si la linea no tiene el token.
+pub fn check_notification_token(line: &str, token: &str) -> Result<(), CustomError> {
+    match line.trim_end().strip_prefix(NOTIFICATION_AUTH_PREFIX) {
+        Some(received) if constant_time_eq(received.as_bytes(), token.as_bytes()) => Ok(()),
+        _ => Err(CustomError::RpcUnauthorized),
+    }
+}
+
+/// Decodifica base64 estandar (RFC 4648, con padding), como lo envian los clientes en HTTP basic auth.
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let encoded = encoded.as_bytes();
+    if encoded.len() % 4 != 0 {
+        return None;
+    }
+
+    let mut decoded = vec![];
+    for (index, chunk) in encoded.chunks(4).enumerate() {
+        let is_last = index == encoded.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&byte| byte == b'=').count();
+        if padding > 2 || (padding > 0 && !is_last) {
+            return None;
+        }
+
+        let mut group: u32 = 0;
+        for &byte in &chunk[..4 - padding] {
+            group = (group << 6) | base64_value(byte)?;
+        }
+        group <<= 6 * padding as u32;
+        decoded.extend(&group.to_be_bytes()[1..4 - padding]);
+    }
+    Some(decoded)
+}
+
+fn base64_value(byte: u8) -> Option<u32> {
+    let value = match byte {
+        b'A'..=b'Z' => byte - b'A',
+        b'a'..=b'z' => byte - b'a' + 26,
+        b'0'..=b'9' => byte - b'0' + 52,
+        b'+' => 62,
+        b'/' => 63,
+        _ => return None,
+    };
+    Some(value as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn access(whitelist: Option<Vec<String>>) -> RpcAccess {
+        RpcAccess::new(RpcCredentials::parse("alice:secret").unwrap(), whitelist)
+    }
+
+    #[test]
+    fn base64_decodes_basic_auth_values() {
+        // "alice:secret"
+        assert_eq!(
+            decode_base64("YWxpY2U6c2VjcmV0"),
+            Some(b"alice:secret".to_vec())
+        );
+        assert_eq!(decode_base64("YQ=="), Some(b"a".to_vec()));
+        assert_eq!(decode_base64("YWI="), Some(b"ab".to_vec()));
+        assert_eq!(decode_base64("YQ==YQ=="), None);
+        assert_eq!(decode_base64("YQ"), None);
+        assert_eq!(decode_base64("Y!=="), None);
+    }
+
+    #[test]
+    fn rpc_requires_the_configured_credentials() {
+        let mut access = access(None);
+        let now = Instant::now();
+
+        assert!(matches!(
+            access.authorize(LOCALHOST, None, "getblockcount", now),
+            Err(CustomError::RpcUnauthorized)
+        ));
+        // "alice:wrong"
+        assert!(matches!(
+            access.authorize(
+                LOCALHOST,
+                Some("Basic YWxpY2U6d3Jvbmc="),
+                "getblockcount",
+                now
+            ),
+            Err(CustomError::RpcUnauthorized)
+        ));
+        assert!(matches!(
+            access.authorize(
+                LOCALHOST,
+                Some("Bearer YWxpY2U6c2VjcmV0"),
+                "getblockcount",
+                now
+            ),
+            Err(CustomError::RpcUnauthorized)
+        ));
+        assert!(access
+            .authorize(
+                LOCALHOST,
+                Some("Basic YWxpY2U6c2VjcmV0"),
+                "getblockcount",
+                now
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn rpc_whitelist_denies_other_methods() {
+        let whitelist = vec!["getblockcount".to_string(), "getbalance".to_string()];
+        let mut access = access(Some(whitelist));
+        let now = Instant::now();
+
+        assert!(access
+            .authorize(LOCALHOST, Some("Basic YWxpY2U6c2VjcmV0"), "getbalance", now)
+            .is_ok());
+        let error = access
+            .authorize(
+                LOCALHOST,
+                Some("Basic YWxpY2U6c2VjcmV0"),
+                "sendtoaddress",
+                now,
+            )
+            .unwrap_err();
+        assert!(
+            matches!(&error, CustomError::RpcMethodNotAllowed(method) if method == "sendtoaddress")
+        );
+
+        let (status, body) = rpc_error_response(&error);
+        assert_eq!(status, 403);
+        assert!(body.contains("\"code\":-32601"));
+        assert!(body.contains("method not allowed: sendtoaddress"));
+    }
+
+    #[test]
+    fn repeated_auth_failures_get_a_cooldown() {
+        let mut access = access(None);
+        let other_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let now = Instant::now();
+
+        for _ in 0..MAX_RPC_AUTH_FAILURES {
+            assert!(matches!(
+                access.authorize(
+                    LOCALHOST,
+                    Some("Basic YWxpY2U6d3Jvbmc="),
+                    "getblockcount",
+                    now
+                ),
+                Err(CustomError::RpcUnauthorized)
+            ));
+        }
+        // durante el cooldown se rechaza incluso con las credenciales correctas, solo a esa IP
+        assert!(matches!(
+            access.authorize(
+                LOCALHOST,
+                Some("Basic YWxpY2U6c2VjcmV0"),
+                "getblockcount",
+                now
+            ),
+            Err(CustomError::RpcAuthCooldown)
+        ));
+        assert!(access
+            .authorize(
+                other_ip,
+                Some("Basic YWxpY2U6c2VjcmV0"),
+                "getblockcount",
+                now
+            )
+            .is_ok());
+
+        let after_cooldown = now + RPC_AUTH_COOLDOWN;
+        assert!(access
+            .authorize(
+                LOCALHOST,
+                Some("Basic YWxpY2U6c2VjcmV0"),
+                "getblockcount",
+                after_cooldown
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn cookie_is_generated_per_session_with_owner_only_permissions() {
+        let store_path = "tests/store_rpc_cookie";
+        let _ = fs::remove_dir_all(store_path);
+        fs::create_dir_all(store_path).unwrap();
+
+        let credentials = RpcCredentials::create_cookie(store_path).unwrap();
+        let cookie_path = Path::new(store_path).join(RPC_COOKIE_FILE);
+        let content = fs::read_to_string(&cookie_path).unwrap();
+        assert_eq!(
+            content,
+            format!("{RPC_COOKIE_USER}:{}", credentials.password)
+        );
+        assert_eq!(credentials.password.len(), RPC_COOKIE_PASSWORD_LEN * 2);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&cookie_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let next_session = RpcCredentials::create_cookie(store_path).unwrap();
+        assert_ne!(credentials.password, next_session.password);
+
+        remove_cookie(store_path);
+        assert!(!cookie_path.exists());
+        fs::remove_dir_all(store_path).unwrap();
+    }
+
+    #[test]
+    fn notification_socket_requires_the_token() {
+        assert!(check_notification_token("AUTH token123\n", "token123").is_ok());
+        assert!(check_notification_token("AUTH token12\n", "token123").is_err());
+        assert!(check_notification_token("SUBSCRIBE blocks\n", "token123").is_err());
+    }
+}
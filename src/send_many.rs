@@ -0,0 +1,1136 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    error::CustomError,
+    messages::transaction::{Transaction, TransactionOptions},
+    rng::random_below,
+    states::utxo_state::UTXOValue,
+    structs::{outpoint::OutPoint, tx_output::TransactionOutput},
+    wallet::{get_pubkey_hash, Wallet},
+};
+
+/// Valor minimo en satoshis de un output de cambio, por debajo se suma al fee.
+pub const DEFAULT_DUST_THRESHOLD: u64 = 546;
+
+/// Tamaño en bytes de una transaccion sin inputs ni outputs (version, lock time y cantidades de inputs y outputs).
+const TX_OVERHEAD_SIZE: u64 = 10;
+
+/// Tamaño estimado en bytes de un input P2PKH firmado con una public key comprimida.
+pub const P2PKH_INPUT_SIZE: u64 = 148;
+
+/// Tamaño estimado en bytes de un input P2PKH firmado con una public key sin comprimir (65 bytes en vez de 33).
+pub const UNCOMPRESSED_P2PKH_INPUT_SIZE: u64 = 180;
+
+/// Tamaño en bytes de un output P2PKH.
+const P2PKH_OUTPUT_SIZE: u64 = 34;
+
+/// Tamaño maximo estimado en bytes de una transaccion que creamos, por encima los nodos no la retransmiten por no ser estandar.
+pub const MAX_STANDARD_TX_SIZE: u64 = 100_000;
+
+/// Cantidad de confirmaciones por defecto que necesita una UTXO para poder gastarse.
+pub const DEFAULT_MIN_SPEND_CONFIRMATIONS: u32 = 1;
+
+/// Cantidad maxima de confirmaciones que se puede exigir para gastar una UTXO.
+pub const MAX_MIN_SPEND_CONFIRMATIONS: u32 = 6;
+
+/// Cantidad de confirmaciones que necesita un output de una coinbase para poder gastarse, sin importar el minimo elegido.
+pub const COINBASE_MATURITY: u32 = 100;
+
+/// Cantidad maxima de ramas que recorre la busqueda branch and bound de la seleccion de inputs.
+const BNB_MAX_TRIES: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq)]
+/// FeeSpec es el fee que paga una transaccion.
+/// - Fixed: Fee fijo en satoshis.
+/// - Rate: Satoshis por byte del tamaño estimado de la transaccion, por lo que cada input que se agrega cuesta mas fee.
+pub enum FeeSpec {
+    Fixed(u64),
+    Rate(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// CoinMaturity indica si una UTXO se puede gastar con el minimo de confirmaciones elegido.
+/// - Spendable: Tiene las confirmaciones suficientes.
+/// - BelowThreshold: Tiene menos confirmaciones que el minimo elegido.
+/// - ImmatureCoinbase: Es un output de una coinbase con menos de COINBASE_MATURITY confirmaciones.
+pub enum CoinMaturity {
+    Spendable,
+    BelowThreshold,
+    ImmatureCoinbase,
+}
+
+impl CoinMaturity {
+    /// Calcula la madurez de una UTXO a partir de sus confirmaciones.
+    pub fn new(value: &UTXOValue, confirmations: u32, min_confirmations: u32) -> Self {
+        if value.is_coinbase && confirmations < COINBASE_MATURITY {
+            return Self::ImmatureCoinbase;
+        }
+        if confirmations < min_confirmations {
+            return Self::BelowThreshold;
+        }
+        Self::Spendable
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// SendManyRequest es un pedido de envio a varias direcciones en una unica transaccion (semantica sendmany).
+/// Los elementos son:
+/// - outputs: Direcciones y montos a enviar, en el orden en el que se crean los outputs.
+/// - fee: Fee de la transaccion, fijo o por byte.
+/// - inputs: Outpoints a gastar elegidos por el usuario (coin control), o None para seleccionarlos automaticamente.
+/// - options: Lock time de la transaccion y si se puede reemplazar (ver TransactionOptions).
+/// - dry_run: Si es true unicamente se planifica la transaccion, sin firmarla ni reservar sus inputs.
+pub struct SendManyRequest {
+    pub outputs: Vec<(String, u64)>,
+    pub fee: FeeSpec,
+    pub inputs: Option<Vec<OutPoint>>,
+    pub options: TransactionOptions,
+    pub dry_run: bool,
+}
+
+impl SendManyRequest {
+    /// Crea un pedido con fee fijo, seleccion automatica de inputs, sin lock time y no reemplazable, que firma y reserva la transaccion.
+    pub fn new(outputs: Vec<(String, u64)>, fee: u64) -> Self {
+        Self {
+            outputs,
+            fee: FeeSpec::Fixed(fee),
+            inputs: None,
+            options: TransactionOptions::default(),
+            dry_run: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// OutputError es un output de un SendManyRequest que no paso la validacion.
+/// Los elementos son:
+/// - index: Posicion del output en el pedido (empezando en 0).
+/// - address: Direccion del output.
+/// - reason: Motivo por el que es invalido.
+pub struct OutputError {
+    pub index: usize,
+    pub address: String,
+    pub reason: String,
+}
+
+#[derive(Debug)]
+/// SendManyError son los errores de un sendmany.
+/// - InvalidOutputs: Todos los outputs que no pasaron la validacion.
+/// - InvalidInputs: Outpoints elegidos que no son de la wallet, estan repetidos o ya los gasta una pending tx.
+/// - Failed: Cualquier otro error (no hay wallet activa, fondos insuficientes, etc).
+pub enum SendManyError {
+    InvalidOutputs(Vec<OutputError>),
+    InvalidInputs(Vec<OutPoint>),
+    Failed(CustomError),
+}
+
+impl From<CustomError> for SendManyError {
+    fn from(error: CustomError) -> Self {
+        Self::Failed(error)
+    }
+}
+
+impl From<SendManyError> for CustomError {
+    fn from(error: SendManyError) -> Self {
+        match error {
+            SendManyError::InvalidOutputs(errors) => {
+                let reasons: Vec<String> = errors
+                    .iter()
+                    .map(|error| format!("Output {}: {}", error.index + 1, error.reason))
+                    .collect();
+                CustomError::Validation(reasons.join(", "))
+            }
+            SendManyError::InvalidInputs(_) => {
+                CustomError::Validation("Selected inputs cannot be spent".to_string())
+            }
+            SendManyError::Failed(error) => error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// TransactionPlan es una transaccion planificada, todavia sin firmar.
+/// Los elementos son:
+/// - inputs: Outpoints a gastar con el output que gasta cada uno (su valor y su script pubkey, necesario para firmarlo).
+/// - outputs: Outputs de la transaccion en orden, con el cambio (si hay) en una posicion aleatoria.
+/// - fee: Fee de la transaccion, incluyendo el cambio que se descarto por ser dust.
+/// - change: Valor del cambio que vuelve a la wallet.
+/// - options: Lock time de la transaccion y si se puede reemplazar.
+/// - input_size: Tamaño estimado de cada input firmado, segun la public key de la wallet (ver p2pkh_input_size).
+pub struct TransactionPlan {
+    pub inputs: Vec<(OutPoint, TransactionOutput)>,
+    pub outputs: Vec<(String, u64)>,
+    pub fee: u64,
+    pub change: u64,
+    pub options: TransactionOptions,
+    pub input_size: u64,
+}
+
+impl TransactionPlan {
+    /// Crea la transaccion planificada y la firma con la wallet recibida.
+    pub fn sign(&self, wallet: &Wallet) -> Result<Transaction, CustomError> {
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|(out_point, prev_output)| (out_point.clone(), prev_output.script_pubkey.clone()))
+            .collect();
+        Transaction::create(wallet, inputs, self.outputs.clone(), self.options)
+    }
+
+    /// Devuelve el tamaño estimado en bytes de la transaccion firmada.
+    pub fn estimated_size(&self) -> u64 {
+        estimate_size(self.inputs.len(), self.outputs.len(), self.input_size)
+    }
+
+    /// Devuelve el fee por byte (sat/vB) de la transaccion segun su tamaño estimado.
+    pub fn fee_rate(&self) -> f64 {
+        self.fee as f64 / self.estimated_size() as f64
+    }
+}
+
+#[derive(Debug)]
+/// SendManyResult es el resultado de un sendmany.
+/// - Planned: La transaccion planificada, cuando el pedido es dry_run.
+/// - Sent: El txid y la transaccion firmada, que ya reservo sus inputs como pending tx.
+pub enum SendManyResult {
+    Planned(TransactionPlan),
+    Sent {
+        txid: String,
+        transaction: Transaction,
+    },
+}
+
+/// Valida los outputs de un pedido, devolviendo todos los que son invalidos (y no solo el primero).
+/// Un output es invalido si su valor es 0 o si su direccion no esta bien formada.
+pub fn validate_outputs(outputs: &[(String, u64)]) -> Vec<OutputError> {
+    let mut errors = vec![];
+    for (index, (address, value)) in outputs.iter().enumerate() {
+        let reason = if *value == 0 {
+            "Output value must be greater than 0".to_string()
+        } else {
+            match get_pubkey_hash(address.clone()) {
+                Ok(_) => continue,
+                Err(CustomError::Validation(reason)) => reason,
+                Err(error) => error.to_string(),
+            }
+        };
+        errors.push(OutputError {
+            index,
+            address: address.clone(),
+            reason,
+        });
+    }
+    errors
+}
+
+/// Planifica la transaccion de un pedido a partir de las UTXO disponibles (las de la wallet que no gasta ninguna pending tx),
+/// cada una con sus confirmaciones.
+/// Solo se gastan las UTXO con al menos min_confirmations confirmaciones (y las de coinbase con COINBASE_MATURITY),
+/// tanto en la seleccion automatica con select_coins como en los inputs elegidos por el usuario.
+/// El cambio se envia a change_address en una posicion aleatoria entre los outputs, para que no se pueda reconocer
+/// por ser el ultimo, salvo que sea menor a dust_threshold: en ese caso no se crea el output de cambio y su valor se suma al fee.
+/// Si los fondos no alcanzan pero alcanzarian contando las UTXO con menos confirmaciones, devuelve InsufficientConfirmedFunds.
+/// Si la transaccion firmada superaria MAX_STANDARD_TX_SIZE bytes devuelve TransactionTooLarge, con la cantidad de
+/// transacciones en las que habria que consolidar los inputs necesarios (ver consolidation_txs).
+/// Las UTXO P2WPKH (segwit) todavia no se pueden firmar, por lo que nunca se seleccionan: si el usuario elige alguna,
+/// o si los fondos no alcanzan sin ellas, devuelve SegwitSpendUnsupported.
+/// El tamaño de cada input, del que dependen el fee por byte y el limite de tamaño, es input_size (ver p2pkh_input_size).
+pub fn plan_transaction(
+    request: &SendManyRequest,
+    available: Vec<(OutPoint, UTXOValue, u32)>,
+    change_address: &str,
+    input_size: u64,
+    dust_threshold: u64,
+    min_confirmations: u32,
+) -> Result<TransactionPlan, SendManyError> {
+    if request.outputs.is_empty() {
+        return Err(CustomError::Validation("At least one output is required".to_string()).into());
+    }
+    let errors = validate_outputs(&request.outputs);
+    if !errors.is_empty() {
+        return Err(SendManyError::InvalidOutputs(errors));
+    }
+
+    let prev_outputs: HashMap<OutPoint, TransactionOutput> = available
+        .iter()
+        .map(|(out_point, value, _)| (out_point.clone(), value.tx_out.clone()))
+        .collect();
+    let mut spendable: Vec<(OutPoint, u64)> = vec![];
+    let mut locked: Vec<(OutPoint, u64)> = vec![];
+    let mut segwit: Vec<(OutPoint, u64)> = vec![];
+    for (out_point, value, confirmations) in available {
+        if value.tx_out.get_p2wpkh_hash().is_some() {
+            segwit.push((out_point, value.tx_out.value));
+            continue;
+        }
+        match CoinMaturity::new(&value, confirmations, min_confirmations) {
+            CoinMaturity::Spendable => spendable.push((out_point, value.tx_out.value)),
+            _ => locked.push((out_point, value.tx_out.value)),
+        }
+    }
+    let outputs_value = request
+        .outputs
+        .iter()
+        .try_fold(0_u64, |total, (_, value)| total.checked_add(*value))
+        .ok_or(CustomError::InvalidTransferFields)?;
+    let costs = FeeCosts::new(&request.fee, request.outputs.len(), input_size)?;
+
+    let inputs = match &request.inputs {
+        Some(selected) => {
+            let segwit_value: u64 = segwit
+                .iter()
+                .filter(|(out_point, _)| selected.contains(out_point))
+                .map(|(_, value)| value)
+                .sum();
+            if segwit_value > 0 {
+                return Err(CustomError::SegwitSpendUnsupported { segwit_value }.into());
+            }
+            select_inputs(selected, &spendable)?
+        }
+        None => select_coins(
+            &largest_first(spendable.clone()),
+            outputs_value,
+            &costs,
+            dust_threshold,
+        ),
+    };
+
+    let inputs_value: u64 = inputs.iter().map(|(_, value)| value).sum();
+    let Some((fee, change)) = settle_change(
+        inputs_value,
+        inputs.len(),
+        outputs_value,
+        &costs,
+        dust_threshold,
+    )?
+    else {
+        if request.inputs.is_some() {
+            return Err(CustomError::InsufficientFunds.into());
+        }
+        let error = match locked.is_empty() {
+            true => CustomError::InsufficientFunds,
+            false => insufficient_confirmed_funds(
+                spendable,
+                locked,
+                outputs_value,
+                &costs,
+                dust_threshold,
+                min_confirmations,
+            ),
+        };
+        let segwit_value: u64 = segwit.iter().map(|(_, value)| value).sum();
+        return Err(match error {
+            CustomError::InsufficientFunds if segwit_value > 0 => {
+                CustomError::SegwitSpendUnsupported { segwit_value }
+            }
+            error => error,
+        }
+        .into());
+    };
+
+    let estimated_size = estimate_size(
+        inputs.len(),
+        request.outputs.len() + usize::from(change > 0),
+        input_size,
+    );
+    if estimated_size > MAX_STANDARD_TX_SIZE {
+        return Err(CustomError::TransactionTooLarge {
+            estimated_size,
+            max_size: MAX_STANDARD_TX_SIZE,
+            inputs: inputs.len(),
+            consolidation_txs: consolidation_txs(inputs.len(), input_size),
+        }
+        .into());
+    }
+
+    let mut outputs = request.outputs.clone();
+    if change > 0 {
+        let position = random_below(outputs.len() as u64 + 1) as usize;
+        outputs.insert(position, (change_address.to_string(), change));
+    }
+
+    let inputs = inputs
+        .into_iter()
+        .filter_map(|(out_point, _)| {
+            let prev_output = prev_outputs.get(&out_point)?.clone();
+            Some((out_point, prev_output))
+        })
+        .collect();
+
+    Ok(TransactionPlan {
+        inputs,
+        outputs,
+        fee,
+        change,
+        options: request.options,
+        input_size,
+    })
+}
+
+/// Devuelve el error de un pedido que no alcanza a cubrirse con las UTXO gastables.
+/// Si sumando las UTXO que todavia no tienen las confirmaciones suficientes el pedido se podria cubrir,
+/// devuelve InsufficientConfirmedFunds para que el usuario baje el minimo de confirmaciones o espere nuevos bloques.
+fn insufficient_confirmed_funds(
+    mut spendable: Vec<(OutPoint, u64)>,
+    locked: Vec<(OutPoint, u64)>,
+    outputs_value: u64,
+    costs: &FeeCosts,
+    dust_threshold: u64,
+    min_confirmations: u32,
+) -> CustomError {
+    let spendable_value: u64 = spendable.iter().map(|(_, value)| value).sum();
+    let locked_value: u64 = locked.iter().map(|(_, value)| value).sum();
+
+    spendable.extend(locked);
+    let inputs = select_coins(
+        &largest_first(spendable),
+        outputs_value,
+        costs,
+        dust_threshold,
+    );
+    let inputs_value: u64 = inputs.iter().map(|(_, value)| value).sum();
+    match settle_change(
+        inputs_value,
+        inputs.len(),
+        outputs_value,
+        costs,
+        dust_threshold,
+    ) {
+        Ok(Some(_)) => CustomError::InsufficientConfirmedFunds {
+            min_confirmations,
+            spendable: spendable_value,
+            balance: spendable_value + locked_value,
+        },
+        Ok(None) => CustomError::InsufficientFunds,
+        Err(error) => error,
+    }
+}
+
+/// Devuelve la cantidad de transacciones, de un unico output a la propia wallet y sin superar MAX_STANDARD_TX_SIZE,
+/// que hacen falta para consolidar inputs UTXO de input_size bytes en unas pocas de mayor valor.
+pub fn consolidation_txs(inputs: usize, input_size: u64) -> usize {
+    let max_inputs = (MAX_STANDARD_TX_SIZE - estimate_size(0, 1, input_size)) / input_size;
+    inputs.div_ceil(max_inputs as usize)
+}
+
+/// Ordena las UTXO de mayor a menor valor, como las espera select_coins.
+fn largest_first(mut coins: Vec<(OutPoint, u64)>) -> Vec<(OutPoint, u64)> {
+    coins.sort_by(|a, b| b.1.cmp(&a.1));
+    coins
+}
+
+/// Devuelve el tamaño estimado en bytes de un input P2PKH firmado con la public key comprimida o sin comprimir de una wallet.
+pub fn p2pkh_input_size(compressed_pubkey: bool) -> u64 {
+    match compressed_pubkey {
+        true => P2PKH_INPUT_SIZE,
+        false => UNCOMPRESSED_P2PKH_INPUT_SIZE,
+    }
+}
+
+/// Estima el tamaño en bytes de una transaccion P2PKH firmada con la cantidad de inputs y outputs recibida,
+/// con inputs de input_size bytes.
+pub fn estimate_size(inputs: usize, outputs: usize, input_size: u64) -> u64 {
+    TX_OVERHEAD_SIZE + input_size * inputs as u64 + P2PKH_OUTPUT_SIZE * outputs as u64
+}
+
+/// FeeCosts es lo que aporta al fee cada parte de la transaccion.
+/// Con un fee fijo todo el fee esta en base, con un fee por byte cada input y el output de cambio suman su tamaño.
+struct FeeCosts {
+    base: u64,
+    per_input: u64,
+    change_output: u64,
+}
+
+impl FeeCosts {
+    /// Devuelve CustomError::InvalidTransferFields si con el fee por byte alguno de los costos no entra en un u64.
+    fn new(fee: &FeeSpec, outputs: usize, input_size: u64) -> Result<Self, CustomError> {
+        let rate = match fee {
+            FeeSpec::Fixed(fee) => {
+                return Ok(Self {
+                    base: *fee,
+                    per_input: 0,
+                    change_output: 0,
+                })
+            }
+            FeeSpec::Rate(rate) => rate,
+        };
+        match (
+            rate.checked_mul(estimate_size(0, outputs, input_size)),
+            rate.checked_mul(input_size),
+            rate.checked_mul(P2PKH_OUTPUT_SIZE),
+        ) {
+            (Some(base), Some(per_input), Some(change_output)) => Ok(Self {
+                base,
+                per_input,
+                change_output,
+            }),
+            _ => Err(CustomError::InvalidTransferFields),
+        }
+    }
+}
+
+/// Calcula el fee y el cambio de una transaccion con los inputs elegidos, o None si no alcanzan.
+/// Si el cambio (descontando lo que cuesta su output) no llega a dust_threshold, se suma al fee.
+/// Devuelve CustomError::InvalidTransferFields si el fee, o los outputs mas el fee, no entran en un u64.
+fn settle_change(
+    inputs_value: u64,
+    inputs: usize,
+    outputs_value: u64,
+    costs: &FeeCosts,
+    dust_threshold: u64,
+) -> Result<Option<(u64, u64)>, CustomError> {
+    let total = costs
+        .per_input
+        .checked_mul(inputs as u64)
+        .and_then(|inputs_fee| inputs_fee.checked_add(costs.base))
+        .and_then(|fee| Some((fee, outputs_value.checked_add(fee)?)));
+    let Some((fee, total)) = total else { return Err(CustomError::InvalidTransferFields) };
+    let Some(excess) = inputs_value.checked_sub(total) else { return Ok(None) };
+
+    // fee + excess no supera inputs_value, por lo que las sumas no pueden desbordarse
+    match excess.checked_sub(costs.change_output) {
+        Some(change) if change > 0 && change >= dust_threshold => {
+            Ok(Some((fee + costs.change_output, change)))
+        }
+        _ => Ok(Some((fee + excess, 0))),
+    }
+}
+
+fn select_inputs(
+    selected: &[OutPoint],
+    available: &[(OutPoint, u64)],
+) -> Result<Vec<(OutPoint, u64)>, SendManyError> {
+    let mut inputs = vec![];
+    let mut invalid = vec![];
+    let mut used = HashSet::new();
+    for out_point in selected {
+        let utxo = available
+            .iter()
+            .find(|(candidate, _)| candidate == out_point);
+        match utxo {
+            Some((_, value)) if used.insert(out_point) => {
+                inputs.push((out_point.clone(), *value));
+            }
+            _ => invalid.push(out_point.clone()),
+        }
+    }
+
+    if !invalid.is_empty() {
+        return Err(SendManyError::InvalidInputs(invalid));
+    }
+    Ok(inputs)
+}
+
+/// Selecciona los inputs de una transaccion entre las UTXO disponibles, ordenadas de mayor a menor valor.
+/// Se usa el valor efectivo de cada UTXO (su valor menos lo que cuesta incluirla), descartando las que no aportan.
+/// Primero busca con branch and bound un conjunto que cubra outputs y fee sin sobrar mas que dust_threshold,
+/// de modo que no haga falta un output de cambio. Si no lo encuentra, toma las UTXO de mayor valor hasta cubrirlos.
+fn select_coins(
+    available: &[(OutPoint, u64)],
+    outputs_value: u64,
+    costs: &FeeCosts,
+    dust_threshold: u64,
+) -> Vec<(OutPoint, u64)> {
+    let effective: Vec<(&(OutPoint, u64), u64)> = available
+        .iter()
+        .filter(|(_, value)| *value > costs.per_input)
+        .map(|utxo| (utxo, utxo.1 - costs.per_input))
+        .collect();
+    let target = outputs_value.saturating_add(costs.base);
+
+    let values: Vec<u64> = effective.iter().map(|(_, value)| *value).collect();
+    if let Some(selection) =
+        branch_and_bound(&values, target, target.saturating_add(dust_threshold))
+    {
+        return selection
+            .into_iter()
+            .map(|index| effective[index].0.clone())
+            .collect();
+    }
+
+    let mut inputs = vec![];
+    let mut total_value = 0;
+    for (utxo, value) in effective {
+        inputs.push(utxo.clone());
+        total_value += value;
+        if total_value >= target {
+            break;
+        }
+    }
+    inputs
+}
+
+/// Busca un conjunto de valores (ordenados de mayor a menor) cuya suma quede entre target y upper.
+/// Recorre en profundidad incluyendo o excluyendo cada valor, descartando las ramas que se pasan de upper
+/// o que ya no pueden llegar a target, con un maximo de BNB_MAX_TRIES intentos.
+/// Devuelve los indices de los valores elegidos.
+fn branch_and_bound(values: &[u64], target: u64, upper: u64) -> Option<Vec<usize>> {
+    let mut search = BranchAndBound {
+        values,
+        target,
+        upper,
+        tries: 0,
+    };
+    let mut selection = vec![];
+    let remaining = values.iter().sum();
+    search
+        .explore(0, 0, remaining, &mut selection)
+        .then_some(selection)
+}
+
+struct BranchAndBound<'a> {
+    values: &'a [u64],
+    target: u64,
+    upper: u64,
+    tries: usize,
+}
+
+impl BranchAndBound<'_> {
+    fn explore(
+        &mut self,
+        index: usize,
+        sum: u64,
+        remaining: u64,
+        selection: &mut Vec<usize>,
+    ) -> bool {
+        if sum > self.upper {
+            return false;
+        }
+        if sum >= self.target {
+            return true;
+        }
+        if index == self.values.len()
+            || sum + remaining < self.target
+            || self.tries >= BNB_MAX_TRIES
+        {
+            return false;
+        }
+        self.tries += 1;
+
+        let value = self.values[index];
+        selection.push(index);
+        if self.explore(index + 1, sum + value, remaining - value, selection) {
+            return true;
+        }
+        selection.pop();
+        self.explore(index + 1, sum, remaining - value, selection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::structs::tx_output::TransactionOutput;
+
+    use super::*;
+
+    const ADDRESS: &str = "mscatccDgq7azndWHFTzvEuZuywCsUvTRu";
+    const CHANGE_ADDRESS: &str = "mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm";
+
+    fn utxo(index: u32, value: u64) -> (OutPoint, UTXOValue, u32) {
+        utxo_with_depth(index, value, MAX_MIN_SPEND_CONFIRMATIONS, false)
+    }
+
+    fn utxo_with_depth(
+        index: u32,
+        value: u64,
+        confirmations: u32,
+        is_coinbase: bool,
+    ) -> (OutPoint, UTXOValue, u32) {
+        let out_point = OutPoint {
+            hash: vec![1; 32],
+            index,
+        };
+        let value = UTXOValue {
+            tx_out: TransactionOutput {
+                value,
+                script_pubkey: vec![],
+            },
+            block_hash: vec![2; 32],
+            block_timestamp: 0,
+            is_coinbase,
+        };
+        (out_point, value, confirmations)
+    }
+
+    /// UTXO de un output P2WPKH, que todavia no se puede gastar.
+    fn segwit_utxo(index: u32, value: u64) -> (OutPoint, UTXOValue, u32) {
+        let (out_point, mut value, confirmations) = utxo(index, value);
+        value.tx_out.script_pubkey = [vec![0x00, 0x14], vec![3; 20]].concat();
+        (out_point, value, confirmations)
+    }
+
+    /// UTXO con 1, 2, 3, 5 y 6 confirmaciones, y una coinbase con 50.
+    fn utxo_at_depths() -> Vec<(OutPoint, UTXOValue, u32)> {
+        vec![
+            utxo_with_depth(0, 1000, 1, false),
+            utxo_with_depth(1, 2000, 2, false),
+            utxo_with_depth(2, 3000, 3, false),
+            utxo_with_depth(3, 4000, 5, false),
+            utxo_with_depth(4, 5000, 6, false),
+            utxo_with_depth(5, 50000, 50, true),
+        ]
+    }
+
+    fn indexes(plan: &TransactionPlan) -> Vec<u32> {
+        plan.inputs
+            .iter()
+            .map(|(out_point, _)| out_point.index)
+            .collect()
+    }
+
+    #[test]
+    fn plan_selects_largest_utxos_and_adds_change() {
+        let request = SendManyRequest::new(vec![(ADDRESS.to_string(), 7000)], 100);
+        let available = vec![utxo(0, 3000), utxo(1, 5000), utxo(2, 4000)];
+
+        let plan = plan_transaction(
+            &request,
+            available,
+            CHANGE_ADDRESS,
+            P2PKH_INPUT_SIZE,
+            DEFAULT_DUST_THRESHOLD,
+            DEFAULT_MIN_SPEND_CONFIRMATIONS,
+        )
+        .unwrap();
+
+        assert_eq!(indexes(&plan), vec![1, 2]);
+        assert_eq!(plan.change, 1900);
+        assert_eq!(plan.outputs.len(), 2);
+        assert!(plan.outputs.contains(&(ADDRESS.to_string(), 7000)));
+        assert!(plan.outputs.contains(&(CHANGE_ADDRESS.to_string(), 1900)));
+    }
+
+    #[test]
+    fn plan_keeps_the_order_of_the_requested_outputs() {
+        let request = SendManyRequest::new(
+            vec![
+                (ADDRESS.to_string(), 1000),
+                (CHANGE_ADDRESS.to_string(), 2000),
+            ],
+            100,
+        );
+        for _ in 0..10 {
+            let plan = plan_transaction(
+                &request,
+                vec![utxo(0, 10000)],
+                "change",
+                P2PKH_INPUT_SIZE,
+                DEFAULT_DUST_THRESHOLD,
+                DEFAULT_MIN_SPEND_CONFIRMATIONS,
+            )
+            .unwrap();
+            let requested: Vec<_> = plan
+                .outputs
+                .into_iter()
+                .filter(|(address, _)| address != "change")
+                .collect();
+            assert_eq!(requested, request.outputs);
+        }
+    }
+
+    #[test]
+    fn plan_prefers_exact_match_without_change() {
+        let request = SendManyRequest::new(vec![(ADDRESS.to_string(), 4900)], 100);
+        let available = vec![utxo(0, 6000), utxo(1, 3000), utxo(2, 2000)];
+
+        let plan = plan_transaction(
+            &request,
+            available,
+            CHANGE_ADDRESS,
+            P2PKH_INPUT_SIZE,
+            DEFAULT_DUST_THRESHOLD,
+            DEFAULT_MIN_SPEND_CONFIRMATIONS,
+        )
+        .unwrap();
+
+        assert_eq!(indexes(&plan), vec![1, 2]);
+        assert_eq!(plan.change, 0);
+        assert_eq!(plan.fee, 100);
+        assert_eq!(plan.outputs, vec![(ADDRESS.to_string(), 4900)]);
+    }
+
+    #[test]
+    fn plan_adds_dust_change_to_fee() {
+        let request = SendManyRequest::new(vec![(ADDRESS.to_string(), 5000)], 100);
+
+        let plan = plan_transaction(
+            &request,
+            vec![utxo(0, 5300)],
+            CHANGE_ADDRESS,
+            P2PKH_INPUT_SIZE,
+            DEFAULT_DUST_THRESHOLD,
+            DEFAULT_MIN_SPEND_CONFIRMATIONS,
+        )
+        .unwrap();
+
+        assert_eq!(plan.change, 0);
+        assert_eq!(plan.fee, 300);
+        assert_eq!(plan.outputs.len(), 1);
+    }
+
+    #[test]
+    fn plan_with_insufficient_funds() {
+        let request = SendManyRequest::new(vec![(ADDRESS.to_string(), 5000)], 100);
+        let available = vec![utxo(0, 1000), utxo(1, 2000)];
+
+        let result = plan_transaction(
+            &request,
+            available,
+            CHANGE_ADDRESS,
+            P2PKH_INPUT_SIZE,
+            DEFAULT_DUST_THRESHOLD,
+            DEFAULT_MIN_SPEND_CONFIRMATIONS,
+        );
+
+        assert!(matches!(
+            result,
+            Err(SendManyError::Failed(CustomError::InsufficientFunds))
+        ));
+    }
+
+    #[test]
+    fn plan_with_overflowing_amounts_is_invalid() {
+        let available = vec![utxo(0, 1000), utxo(1, 2000)];
+        let overflowing_outputs = SendManyRequest::new(
+            vec![(ADDRESS.to_string(), u64::MAX), (ADDRESS.to_string(), 1)],
+            100,
+        );
+        let overflowing_fee = SendManyRequest::new(vec![(ADDRESS.to_string(), 5000)], u64::MAX);
+        let mut overflowing_rate = SendManyRequest::new(vec![(ADDRESS.to_string(), 5000)], 0);
+        overflowing_rate.fee = FeeSpec::Rate(u64::MAX / 100);
+
+        for request in [overflowing_outputs, overflowing_fee, overflowing_rate] {
+            let result = plan_transaction(
+                &request,
+                available.clone(),
+                CHANGE_ADDRESS,
+                P2PKH_INPUT_SIZE,
+                DEFAULT_DUST_THRESHOLD,
+                DEFAULT_MIN_SPEND_CONFIRMATIONS,
+            );
+            assert!(matches!(
+                result,
+                Err(SendManyError::Failed(CustomError::InvalidTransferFields))
+            ));
+        }
+    }
+
+    #[test]
+    fn plan_never_selects_segwit_utxos() {
+        let request = SendManyRequest::new(vec![(ADDRESS.to_string(), 3000)], 100);
+        let available = vec![segwit_utxo(0, 9000), utxo(1, 4000)];
+
+        let plan = plan_transaction(
+            &request,
+            available,
+            CHANGE_ADDRESS,
+            P2PKH_INPUT_SIZE,
+            DEFAULT_DUST_THRESHOLD,
+            DEFAULT_MIN_SPEND_CONFIRMATIONS,
+        )
+        .unwrap();
+
+        assert_eq!(indexes(&plan), vec![1]);
+    }
+
+    #[test]
+    fn plan_reports_segwit_funds_that_cannot_be_spent() {
+        let request = SendManyRequest::new(vec![(ADDRESS.to_string(), 5000)], 100);
+        let available = vec![segwit_utxo(0, 9000), utxo(1, 2000)];
+
+        let result = plan_transaction(
+            &request,
+            available,
+            CHANGE_ADDRESS,
+            P2PKH_INPUT_SIZE,
+            DEFAULT_DUST_THRESHOLD,
+            DEFAULT_MIN_SPEND_CONFIRMATIONS,
+        );
+
+        assert!(matches!(
+            result,
+            Err(SendManyError::Failed(CustomError::SegwitSpendUnsupported {
+                segwit_value: 9000
+            }))
+        ));
+    }
+
+    #[test]
+    fn plan_rejects_selected_segwit_utxos() {
+        let mut request = SendManyRequest::new(vec![(ADDRESS.to_string(), 1000)], 100);
+        request.inputs = Some(vec![segwit_utxo(0, 9000).0, utxo(1, 4000).0]);
+        let available = vec![segwit_utxo(0, 9000), utxo(1, 4000)];
+
+        let result = plan_transaction(
+            &request,
+            available,
+            CHANGE_ADDRESS,
+            P2PKH_INPUT_SIZE,
+            DEFAULT_DUST_THRESHOLD,
+            DEFAULT_MIN_SPEND_CONFIRMATIONS,
+        );
+
+        assert!(matches!(
+            result,
+            Err(SendManyError::Failed(CustomError::SegwitSpendUnsupported {
+                segwit_value: 9000
+            }))
+        ));
+    }
+
+    #[test]
+    fn plan_with_fee_rate_pays_for_its_estimated_size() {
+        let mut request = SendManyRequest::new(vec![(ADDRESS.to_string(), 5000)], 0);
+        request.fee = FeeSpec::Rate(2);
+
+        let plan = plan_transaction(
+            &request,
+            vec![utxo(0, 10000)],
+            CHANGE_ADDRESS,
+            P2PKH_INPUT_SIZE,
+            DEFAULT_DUST_THRESHOLD,
+            DEFAULT_MIN_SPEND_CONFIRMATIONS,
+        )
+        .unwrap();
+
+        assert_eq!(plan.estimated_size(), estimate_size(1, 2, P2PKH_INPUT_SIZE));
+        assert_eq!(plan.fee, 2 * plan.estimated_size());
+        assert_eq!(plan.change, 10000 - 5000 - plan.fee);
+        assert_eq!(plan.fee_rate(), 2.0);
+    }
+
+    #[test]
+    fn plan_reports_every_invalid_output() {
+        let request = SendManyRequest::new(
+            vec![
+                (ADDRESS.to_string(), 0),
+                (ADDRESS.to_string(), 100),
+                ("0OIl".to_string(), 100),
+            ],
+            10,
+        );
+
+        let result = plan_transaction(
+            &request,
+            vec![utxo(0, 1000)],
+            CHANGE_ADDRESS,
+            P2PKH_INPUT_SIZE,
+            DEFAULT_DUST_THRESHOLD,
+            DEFAULT_MIN_SPEND_CONFIRMATIONS,
+        );
+
+        let Err(SendManyError::InvalidOutputs(errors)) = result else { panic!("expected InvalidOutputs") };
+        let indexes: Vec<usize> = errors.iter().map(|error| error.index).collect();
+        assert_eq!(indexes, vec![0, 2]);
+        assert_eq!(errors[1].address, "0OIl");
+    }
+
+    #[test]
+    fn plan_with_coin_control_only_uses_selected_inputs() {
+        let mut request = SendManyRequest::new(vec![(ADDRESS.to_string(), 250)], 50);
+        request.inputs = Some(vec![utxo(0, 300).0]);
+        let available = vec![utxo(0, 300), utxo(1, 500)];
+
+        let plan = plan_transaction(
+            &request,
+            available.clone(),
+            CHANGE_ADDRESS,
+            P2PKH_INPUT_SIZE,
+            DEFAULT_DUST_THRESHOLD,
+            DEFAULT_MIN_SPEND_CONFIRMATIONS,
+        )
+        .unwrap();
+        assert_eq!(plan.inputs, vec![(utxo(0, 300).0, utxo(0, 300).1.tx_out)]);
+        assert_eq!(plan.change, 0);
+
+        request.inputs = Some(vec![utxo(1, 500).0, utxo(3, 500).0, utxo(1, 500).0]);
+        let result = plan_transaction(
+            &request,
+            available,
+            CHANGE_ADDRESS,
+            P2PKH_INPUT_SIZE,
+            DEFAULT_DUST_THRESHOLD,
+            DEFAULT_MIN_SPEND_CONFIRMATIONS,
+        );
+        let Err(SendManyError::InvalidInputs(invalid)) = result else { panic!("expected InvalidInputs") };
+        assert_eq!(invalid, vec![utxo(3, 500).0, utxo(1, 500).0]);
+    }
+
+    #[test]
+    fn plan_excludes_utxos_below_min_confirmations() {
+        let cases = [
+            (1, 15000, vec![4, 3, 2, 1, 0]),
+            (3, 12000, vec![4, 3, 2]),
+            (6, 5000, vec![4]),
+        ];
+
+        for (min_confirmations, spendable, expected) in cases {
+            let request = SendManyRequest::new(vec![(ADDRESS.to_string(), spendable - 100)], 100);
+            let plan = plan_transaction(
+                &request,
+                utxo_at_depths(),
+                CHANGE_ADDRESS,
+                P2PKH_INPUT_SIZE,
+                DEFAULT_DUST_THRESHOLD,
+                min_confirmations,
+            )
+            .unwrap();
+            assert_eq!(indexes(&plan), expected);
+            assert_eq!(plan.change, 0);
+        }
+    }
+
+    #[test]
+    fn plan_reports_insufficient_funds_at_min_confirmations() {
+        for (min_confirmations, spendable) in [(1, 15000), (3, 12000), (6, 5000)] {
+            let request = SendManyRequest::new(vec![(ADDRESS.to_string(), spendable)], 100);
+            let result = plan_transaction(
+                &request,
+                utxo_at_depths(),
+                CHANGE_ADDRESS,
+                P2PKH_INPUT_SIZE,
+                DEFAULT_DUST_THRESHOLD,
+                min_confirmations,
+            );
+
+            let Err(SendManyError::Failed(CustomError::InsufficientConfirmedFunds {
+                min_confirmations: threshold,
+                spendable: spendable_value,
+                balance,
+            })) = result
+            else {
+                panic!("expected InsufficientConfirmedFunds")
+            };
+            assert_eq!(threshold, min_confirmations);
+            assert_eq!(spendable_value, spendable);
+            assert_eq!(balance, 65000);
+        }
+
+        let request = SendManyRequest::new(vec![(ADDRESS.to_string(), 65000)], 100);
+        let result = plan_transaction(
+            &request,
+            utxo_at_depths(),
+            CHANGE_ADDRESS,
+            P2PKH_INPUT_SIZE,
+            DEFAULT_DUST_THRESHOLD,
+            DEFAULT_MIN_SPEND_CONFIRMATIONS,
+        );
+        assert!(matches!(
+            result,
+            Err(SendManyError::Failed(CustomError::InsufficientFunds))
+        ));
+    }
+
+    #[test]
+    fn plan_never_spends_immature_coinbase() {
+        let mut request = SendManyRequest::new(vec![(ADDRESS.to_string(), 10000)], 100);
+        request.inputs = Some(vec![utxo_at_depths()[5].0.clone()]);
+
+        let result = plan_transaction(
+            &request,
+            utxo_at_depths(),
+            CHANGE_ADDRESS,
+            P2PKH_INPUT_SIZE,
+            DEFAULT_DUST_THRESHOLD,
+            DEFAULT_MIN_SPEND_CONFIRMATIONS,
+        );
+        let Err(SendManyError::InvalidInputs(invalid)) = result else { panic!("expected InvalidInputs") };
+        assert_eq!(invalid, vec![utxo_at_depths()[5].0.clone()]);
+
+        let mature_coinbase = utxo_with_depth(5, 50000, COINBASE_MATURITY, true);
+        let plan = plan_transaction(
+            &request,
+            vec![mature_coinbase.clone()],
+            CHANGE_ADDRESS,
+            P2PKH_INPUT_SIZE,
+            DEFAULT_DUST_THRESHOLD,
+            MAX_MIN_SPEND_CONFIRMATIONS,
+        )
+        .unwrap();
+        assert_eq!(
+            plan.inputs,
+            vec![(mature_coinbase.0, mature_coinbase.1.tx_out)]
+        );
+    }
+
+    #[test]
+    fn plan_over_the_standard_size_suggests_consolidation() {
+        let dusty_wallet: Vec<_> = (0..1000).map(|index| utxo(index, 1000)).collect();
+        let request = SendManyRequest::new(vec![(ADDRESS.to_string(), 800_000)], 100);
+
+        let result = plan_transaction(
+            &request,
+            dusty_wallet.clone(),
+            CHANGE_ADDRESS,
+            P2PKH_INPUT_SIZE,
+            DEFAULT_DUST_THRESHOLD,
+            DEFAULT_MIN_SPEND_CONFIRMATIONS,
+        );
+
+        let Err(SendManyError::Failed(CustomError::TransactionTooLarge {
+            estimated_size,
+            max_size,
+            inputs,
+            consolidation_txs: txs,
+        })) = result
+        else {
+            panic!("expected TransactionTooLarge")
+        };
+        assert!(inputs >= 800);
+        assert_eq!(max_size, MAX_STANDARD_TX_SIZE);
+        assert!(estimated_size > MAX_STANDARD_TX_SIZE);
+        assert_eq!(txs, 2);
+        assert_eq!(txs, consolidation_txs(inputs, P2PKH_INPUT_SIZE));
+
+        // un pago que entra en el limite se sigue planificando con la misma wallet
+        let request = SendManyRequest::new(vec![(ADDRESS.to_string(), 500_000)], 100);
+        let plan = plan_transaction(
+            &request,
+            dusty_wallet,
+            CHANGE_ADDRESS,
+            P2PKH_INPUT_SIZE,
+            DEFAULT_DUST_THRESHOLD,
+            DEFAULT_MIN_SPEND_CONFIRMATIONS,
+        )
+        .unwrap();
+        assert!(plan.estimated_size() <= MAX_STANDARD_TX_SIZE);
+    }
+
+    #[test]
+    fn plan_sizes_inputs_by_the_wallet_key_type() {
+        let mut request = SendManyRequest::new(vec![(ADDRESS.to_string(), 5000)], 0);
+        request.fee = FeeSpec::Rate(2);
+
+        let plan_with = |input_size| {
+            plan_transaction(
+                &request,
+                vec![utxo(0, 100_000)],
+                CHANGE_ADDRESS,
+                input_size,
+                DEFAULT_DUST_THRESHOLD,
+                DEFAULT_MIN_SPEND_CONFIRMATIONS,
+            )
+            .unwrap()
+        };
+        let compressed = plan_with(p2pkh_input_size(true));
+        let uncompressed = plan_with(p2pkh_input_size(false));
+
+        assert_eq!(compressed.estimated_size(), 226);
+        assert_eq!(uncompressed.estimated_size(), 258);
+        assert_eq!(compressed.fee, 2 * 226);
+        assert_eq!(uncompressed.fee, 2 * 258);
+    }
+
+    #[test]
+    fn consolidation_txs_fit_in_the_standard_size() {
+        assert_eq!(consolidation_txs(0, P2PKH_INPUT_SIZE), 0);
+        assert_eq!(consolidation_txs(675, P2PKH_INPUT_SIZE), 1);
+        assert_eq!(consolidation_txs(676, P2PKH_INPUT_SIZE), 2);
+        assert!(estimate_size(675, 1, P2PKH_INPUT_SIZE) <= MAX_STANDARD_TX_SIZE);
+
+        // con public keys sin comprimir entran menos inputs por transaccion
+        assert_eq!(consolidation_txs(555, UNCOMPRESSED_P2PKH_INPUT_SIZE), 1);
+        assert_eq!(consolidation_txs(556, UNCOMPRESSED_P2PKH_INPUT_SIZE), 2);
+        assert!(estimate_size(555, 1, UNCOMPRESSED_P2PKH_INPUT_SIZE) <= MAX_STANDARD_TX_SIZE);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::config::Config;
+
+/// NODE_NETWORK indica que el nodo puede enviar cualquier bloque de la cadena, desde el genesis.
+pub const NODE_NETWORK: u64 = 1;
+
+/// NODE_GETUTXO indica que el nodo responde consultas de UTXO (BIP 64).
+pub const NODE_GETUTXO: u64 = 1 << 1;
+
+/// NODE_BLOOM indica que el nodo acepta filtros bloom (BIP 111).
+pub const NODE_BLOOM: u64 = 1 << 2;
+
+/// NODE_WITNESS indica que el nodo puede enviar bloques y transacciones con sus witness (BIP 144).
+pub const NODE_WITNESS: u64 = 1 << 3;
+
+/// NODE_COMPACT_FILTERS indica que el nodo envia filtros compactos de bloques (BIP 157).
+pub const NODE_COMPACT_FILTERS: u64 = 1 << 6;
+
+/// NODE_NETWORK_LIMITED indica que el nodo solo puede enviar los ultimos NETWORK_LIMITED_BLOCKS bloques (BIP 159).
+pub const NODE_NETWORK_LIMITED: u64 = 1 << 10;
+
+/// NODE_P2P_V2 indica que el nodo soporta el transporte encriptado v2 (BIP 324).
+pub const NODE_P2P_V2: u64 = 1 << 11;
+
+/// Nombres de los servicios conocidos, en el orden de sus bits.
+const SERVICE_NAMES: [(u64, &str); 7] = [
+    (NODE_NETWORK, "NODE_NETWORK"),
+    (NODE_GETUTXO, "NODE_GETUTXO"),
+    (NODE_BLOOM, "NODE_BLOOM"),
+    (NODE_WITNESS, "NODE_WITNESS"),
+    (NODE_COMPACT_FILTERS, "NODE_COMPACT_FILTERS"),
+    (NODE_NETWORK_LIMITED, "NODE_NETWORK_LIMITED"),
+    (NODE_P2P_V2, "NODE_P2P_V2"),
+];
+
+/// Cantidad de bloques desde la punta que un nodo con NODE_NETWORK_LIMITED debe poder enviar.
+pub const NETWORK_LIMITED_BLOCKS: usize = 288;
+
+/// El nodo solo descarga los bloques posteriores a START_DATE_IBD, por lo que nunca tiene la cadena completa.
+const STORES_FULL_HISTORY: bool = false;
+
+/// Los bloques y transacciones se envian sin witness.
+const SERVES_WITNESS: bool = false;
+
+/// El nodo no implementa filtros bloom.
+const SERVES_BLOOM: bool = false;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// ServiceCapabilities son las capacidades reales del nodo, de las que se derivan los servicios que anuncia.
+/// Es el unico lugar donde se calculan los servicios, para no anunciar capacidades que no tenemos.
+/// Los elementos son:
+/// - client_only: El nodo no envia bloques a otros nodos (tampoco en spv_mode, donde solo guarda bloques filtrados).
+/// - full_history: El nodo tiene todos los bloques desde el genesis (si no, solo los mas recientes).
+/// - witness: El nodo puede enviar los datos de witness.
+/// - bloom: El nodo acepta filtros bloom.
+pub struct ServiceCapabilities {
+    pub client_only: bool,
+    pub full_history: bool,
+    pub witness: bool,
+    pub bloom: bool,
+}
+
+impl ServiceCapabilities {
+    /// Devuelve las capacidades del nodo con la configuracion recibida.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            client_only: config.client_only || config.spv_mode,
+            full_history: STORES_FULL_HISTORY,
+            witness: SERVES_WITNESS,
+            bloom: SERVES_BLOOM,
+        }
+    }
+
+    /// Devuelve los bits de servicios que se anuncian en los mensajes version y addr.
+    /// - NODE_NETWORK: si no es client_only y tiene todos los bloques.
+    /// - NODE_NETWORK_LIMITED: si no es client_only y solo tiene los bloques recientes.
+    /// - NODE_WITNESS: si envia bloques con witness.
+    /// - NODE_BLOOM: si acepta filtros bloom.
+    pub fn services(&self) -> u64 {
+        let mut services = 0;
+        if !self.client_only {
+            services |= match self.full_history {
+                true => NODE_NETWORK,
+                false => NODE_NETWORK_LIMITED,
+            };
+        }
+        if self.witness && !self.client_only {
+            services |= NODE_WITNESS;
+        }
+        if self.bloom {
+            services |= NODE_BLOOM;
+        }
+        services
+    }
+}
+
+/// Devuelve true si los servicios indican que el nodo envia bloques.
+pub fn serves_blocks(services: u64) -> bool {
+    services & (NODE_NETWORK | NODE_NETWORK_LIMITED) != 0
+}
+
+/// Devuelve true si los servicios indican que el nodo acepta filtros bloom.
+pub fn serves_bloom(services: u64) -> bool {
+    services & NODE_BLOOM != 0
+}
+
+/// Decodifica los bits de servicios que anuncia un nodo en los nombres de los servicios, en el orden de sus bits.
+/// Los bits desconocidos se muestran como UNKNOWN(numero de bit).
+pub fn service_names(services: u64) -> Vec<String> {
+    (0..u64::BITS)
+        .map(|bit| 1 << bit)
+        .filter(|flag| services & flag != 0)
+        .map(
+            |flag| match SERVICE_NAMES.iter().find(|(known, _)| *known == flag) {
+                Some((_, name)) => name.to_string(),
+                None => format!("UNKNOWN({})", flag.trailing_zeros()),
+            },
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities(client_only: bool, full_history: bool) -> ServiceCapabilities {
+        ServiceCapabilities {
+            client_only,
+            full_history,
+            witness: false,
+            bloom: false,
+        }
+    }
+
+    #[test]
+    fn client_only_nodes_advertise_no_block_services() {
+        assert_eq!(capabilities(true, true).services(), 0);
+        assert_eq!(capabilities(true, false).services(), 0);
+
+        let with_witness = ServiceCapabilities {
+            witness: true,
+            ..capabilities(true, true)
+        };
+        assert_eq!(with_witness.services(), 0);
+    }
+
+    #[test]
+    fn full_nodes_advertise_network_or_network_limited() {
+        assert_eq!(capabilities(false, true).services(), NODE_NETWORK);
+        assert_eq!(capabilities(false, false).services(), NODE_NETWORK_LIMITED);
+        assert!(serves_blocks(capabilities(false, false).services()));
+        assert!(!serves_blocks(capabilities(true, false).services()));
+    }
+
+    #[test]
+    fn witness_and_bloom_are_advertised_only_when_supported() {
+        let capabilities = ServiceCapabilities {
+            witness: true,
+            bloom: true,
+            ..capabilities(false, true)
+        };
+        assert_eq!(
+            capabilities.services(),
+            NODE_NETWORK | NODE_WITNESS | NODE_BLOOM
+        );
+        assert!(serves_bloom(capabilities.services()));
+        assert!(!serves_bloom(NODE_NETWORK | NODE_WITNESS));
+    }
+
+    #[test]
+    fn service_bits_are_decoded_into_names() {
+        assert!(service_names(0).is_empty());
+        assert_eq!(
+            service_names(NODE_NETWORK | NODE_WITNESS | NODE_NETWORK_LIMITED),
+            vec!["NODE_NETWORK", "NODE_WITNESS", "NODE_NETWORK_LIMITED"]
+        );
+        assert_eq!(
+            service_names(NODE_BLOOM | NODE_GETUTXO | NODE_COMPACT_FILTERS | NODE_P2P_V2),
+            vec![
+                "NODE_GETUTXO",
+                "NODE_BLOOM",
+                "NODE_COMPACT_FILTERS",
+                "NODE_P2P_V2"
+            ]
+        );
+        assert_eq!(
+            service_names(NODE_NETWORK | 1 << 24),
+            vec!["NODE_NETWORK", "UNKNOWN(24)"]
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Read, Write},
+};
+
+use crate::{
+    error::CustomError,
+    message::Message,
+    messages::block::Block,
+    parser::BufferParser,
+    utils::{open_file, OpenMode},
+};
+
+/// Tamaño de cada registro guardado en disco: hash del bloque (32), cantidad de transacciones (4),
+/// tamaño del bloque (4) y valor total de los outputs (8).
+const METADATA_RECORD_SIZE: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// BlockMetadata es el resumen de un bloque, que se registra al recibirlo para mostrarlo sin abrir su archivo.
+/// Los elementos son:
+/// - tx_count: Cantidad de transacciones del bloque.
+/// - size: Tamaño en bytes del bloque serializado.
+/// - total_output_value: Suma de los outputs de todas las transacciones del bloque, en satoshis.
+pub struct BlockMetadata {
+    pub tx_count: u32,
+    pub size: u32,
+    pub total_output_value: u64,
+}
+
+impl BlockMetadata {
+    /// Calcula el resumen de un bloque.
+    pub fn from_block(block: &Block) -> Self {
+        let total_output_value = block
+            .transactions
+            .iter()
+            .flat_map(|tx| tx.outputs.iter())
+            .map(|output| output.value)
+            .sum();
+
+        Self {
+            tx_count: block.transactions.len() as u32,
+            size: block.serialize().len() as u32,
+            total_output_value,
+        }
+    }
+
+    /// Devuelve los campos del resumen como pares (nombre, valor), para mostrarlos junto a los del header.
+    /// Si el resumen todavia no esta disponible (metadata None) los valores se muestran como pendientes.
+    pub fn fields(metadata: Option<&Self>) -> Vec<(&'static str, String)> {
+        match metadata {
+            Some(metadata) => vec![
+                ("Transactions", metadata.tx_count.to_string()),
+                ("Size", format!("{} bytes", metadata.size)),
+                (
+                    "Total output",
+                    format!("{} sats", metadata.total_output_value),
+                ),
+            ],
+            None => vec![
+                ("Transactions", "pending".to_string()),
+                ("Size", "pending".to_string()),
+                ("Total output", "pending".to_string()),
+            ],
+        }
+    }
+
+    fn serialize(&self, block_hash: &[u8]) -> Vec<u8> {
+        let mut buffer = block_hash.to_vec();
+        buffer.extend(self.tx_count.to_le_bytes());
+        buffer.extend(self.size.to_le_bytes());
+        buffer.extend(self.total_output_value.to_le_bytes());
+        buffer
+    }
+
+    fn parse(parser: &mut BufferParser) -> Result<(Vec<u8>, Self), CustomError> {
+        let block_hash = parser.extract_buffer(32)?.to_vec();
+        let metadata = Self {
+            tx_count: parser.extract_u32()?,
+            size: parser.extract_u32()?,
+            total_output_value: parser.extract_u64()?,
+        };
+        Ok((block_hash, metadata))
+    }
+}
+
+/// BlockMetadataState guarda el BlockMetadata de los bloques descargados, para que los resumenes de la interfaz grafica
+/// no tengan que leer y parsear cada archivo de bloque.
+/// Cada resumen se agrega al final del archivo como un registro de METADATA_RECORD_SIZE bytes.
+/// Los elementos son:
+/// - metadata: Resumen de cada bloque, por hash.
+/// - path: Archivo donde se guardan los resumenes.
+pub struct BlockMetadataState {
+    metadata: HashMap<Vec<u8>, BlockMetadata>,
+    path: String,
+}
+
+impl BlockMetadataState {
+    /// Restaura los resumenes guardados en el archivo recibido, que se crea si no existe.
+    /// Un registro incompleto al final (por ejemplo si se corto la escritura) se descarta del archivo,
+    /// para que los registros que se agreguen despues queden alineados.
+    pub fn new(path: String) -> Result<Self, CustomError> {
+        let mut file = open_file(&path, OpenMode::ReadCreate)?;
+        let mut buffer = vec![];
+        file.read_to_end(&mut buffer)?;
+
+        let complete_len = buffer.len() - buffer.len() % METADATA_RECORD_SIZE;
+        if complete_len != buffer.len() {
+            buffer.truncate(complete_len);
+            fs::write(&path, &buffer)?;
+        }
+
+        let mut parser = BufferParser::new(buffer);
+        let mut metadata = HashMap::new();
+        while parser.len() >= METADATA_RECORD_SIZE {
+            let (block_hash, block_metadata) = BlockMetadata::parse(&mut parser)?;
+            metadata.insert(block_hash, block_metadata);
+        }
+
+        Ok(Self { metadata, path })
+    }
+
+    /// Registra el resumen de un bloque y lo agrega al archivo, salvo que ya estuviera registrado.
+    pub fn append(
+        &mut self,
+        block_hash: &[u8],
+        block_metadata: BlockMetadata,
+    ) -> Result<(), CustomError> {
+        if self.metadata.contains_key(block_hash) {
+            return Ok(());
+        }
+
+        let mut file = open_file(&self.path, OpenMode::Append)?;
+        file.write_all(&block_metadata.serialize(block_hash))?;
+        self.metadata.insert(block_hash.to_vec(), block_metadata);
+        Ok(())
+    }
+
+    /// Devuelve el resumen de un bloque, o None si todavia no se registro.
+    pub fn get(&self, block_hash: &[u8]) -> Option<BlockMetadata> {
+        self.metadata.get(block_hash).copied()
+    }
+
+    /// Devuelve true si el resumen del bloque ya esta registrado.
+    pub fn contains(&self, block_hash: &[u8]) -> bool {
+        self.metadata.contains_key(block_hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        messages::transaction::Transaction, structs::block_header::BlockHeader,
+        structs::tx_output::TransactionOutput,
+    };
+
+    use super::*;
+
+    fn test_block() -> Block {
+        let outputs = vec![
+            TransactionOutput {
+                value: 1000,
+                script_pubkey: vec![],
+            },
+            TransactionOutput {
+                value: 500,
+                script_pubkey: vec![],
+            },
+        ];
+        let transaction = Transaction {
+            version: 1,
+            inputs: vec![],
+            outputs,
+            lock_time: 0,
+        };
+        let header = BlockHeader {
+            version: 1,
+            prev_block_hash: vec![0; 32],
+            merkle_root: transaction.hash(),
+            timestamp: 0,
+            bits: 0,
+            nonce: 0,
+            hash: vec![7; 32],
+            block_downloaded: true,
+            broadcasted: true,
+        };
+        Block::new(header, vec![transaction])
+    }
+
+    #[test]
+    fn block_metadata_from_block() {
+        let block = test_block();
+        let metadata = BlockMetadata::from_block(&block);
+
+        assert_eq!(metadata.tx_count, 1);
+        assert_eq!(metadata.size as usize, block.serialize().len());
+        assert_eq!(metadata.total_output_value, 1500);
+        assert_eq!(BlockMetadata::fields(None)[0].1, "pending");
+        assert_eq!(BlockMetadata::fields(Some(&metadata))[0].1, "1");
+    }
+
+    #[test]
+    fn block_metadata_records_round_trip() {
+        let path = "tests/block_metadata_round_trip.bin".to_string();
+        let _ = fs::remove_file(&path);
+        let block = test_block();
+        let metadata = BlockMetadata::from_block(&block);
+
+        let mut state = BlockMetadataState::new(path.clone()).unwrap();
+        assert!(state.get(&[7; 32]).is_none());
+        state.append(&[7; 32], metadata).unwrap();
+        state.append(&[7; 32], metadata).unwrap();
+        state
+            .append(
+                &[8; 32],
+                BlockMetadata {
+                    tx_count: 3,
+                    size: 900,
+                    total_output_value: 42,
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            fs::metadata(&path).unwrap().len() as usize,
+            2 * METADATA_RECORD_SIZE
+        );
+
+        // un registro cortado al final se descarta
+        let mut file = open_file(&path, OpenMode::Append).unwrap();
+        file.write_all(&[9; 20]).unwrap();
+
+        let restored = BlockMetadataState::new(path.clone()).unwrap();
+        assert_eq!(restored.get(&[7; 32]), Some(metadata));
+        assert_eq!(restored.get(&[8; 32]).unwrap().size, 900);
+        assert!(!restored.contains(&[9; 32]));
+        assert_eq!(
+            fs::metadata(&path).unwrap().len() as usize,
+            2 * METADATA_RECORD_SIZE
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+}
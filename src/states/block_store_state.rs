@@ -0,0 +1,525 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{
+    error::CustomError,
+    message::Message,
+    messages::block::Block,
+    parser::BufferParser,
+    utils::{open_file, write_file_atomic, OpenMode},
+};
+
+/// Tamaño a partir del cual no se agregan mas bloques a un archivo blk y se empieza el siguiente.
+pub const MAX_BLOCK_FILE_SIZE: u64 = 128 * 1024 * 1024;
+
+/// Nombre del indice de los bloques dentro de la carpeta blocks del store.
+pub const BLOCK_INDEX_FILE: &str = "index.dat";
+
+/// Bytes con los que empieza cada bloque dentro de un archivo blk (el magic de testnet), para detectar registros rotos.
+const BLOCK_RECORD_MAGIC: [u8; 4] = [0x0b, 0x11, 0x09, 0x07];
+
+/// Largo de los hashes de los bloques.
+const BLOCK_HASH_SIZE: usize = 32;
+
+/// Tamaño del encabezado de cada bloque dentro de un archivo blk: magic, hash y largo del bloque serializado.
+pub const BLOCK_RECORD_HEADER_SIZE: u64 = 4 + BLOCK_HASH_SIZE as u64 + 4;
+
+/// Tamaño de cada registro del indice: hash, numero de archivo, offset y largo del bloque.
+const INDEX_RECORD_SIZE: usize = BLOCK_HASH_SIZE + 4 + 8 + 4;
+
+/// Devuelve el path del archivo blk con el numero recibido.
+pub fn block_file_path(store_path: &str, file_number: u32) -> String {
+    format!("{}/blocks/blk{:05}.dat", store_path, file_number)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// BlockLocation es la ubicacion de un bloque serializado dentro de los archivos blk.
+/// Los elementos son:
+/// - file_number: Numero del archivo blk.
+/// - offset: Posicion del bloque dentro del archivo, luego de su encabezado.
+/// - length: Largo del bloque serializado.
+pub struct BlockLocation {
+    pub file_number: u32,
+    pub offset: u64,
+    pub length: u32,
+}
+
+/// BlockStore guarda los bloques descargados uno detras de otro en archivos blkNNNNN.dat, empezando uno nuevo
+/// cuando el actual supera max_file_size, en lugar de un archivo por bloque.
+/// Cada bloque se guarda precedido por BLOCK_RECORD_MAGIC, su hash y su largo. El indice (hash -> BlockLocation)
+/// se mantiene en memoria y se agrega a BLOCK_INDEX_FILE luego de escribir cada bloque, por lo que un corte puede
+/// dejar bloques sin indexar pero nunca un indice que apunte a datos que no estan: al abrir el store se agregan
+/// al indice los bloques que faltan recorriendo el final de los archivos blk (ver recover).
+/// Los elementos son:
+/// - store_path: Carpeta del store.
+/// - index: Ubicacion de cada bloque guardado.
+/// - max_file_size: Tamaño a partir del cual se empieza un nuevo archivo blk.
+/// - current_file: Numero del archivo blk al que se agregan los bloques.
+/// - current_size: Tamaño del archivo blk actual.
+pub struct BlockStore {
+    store_path: String,
+    index: HashMap<Vec<u8>, BlockLocation>,
+    max_file_size: u64,
+    current_file: u32,
+    current_size: u64,
+}
+
+impl BlockStore {
+    /// Abre los bloques guardados en la carpeta blocks del store (ver with_max_file_size), con MAX_BLOCK_FILE_SIZE.
+    pub fn open(store_path: &str) -> Result<Self, CustomError> {
+        Self::with_max_file_size(store_path, MAX_BLOCK_FILE_SIZE)
+    }
+
+    /// Abre los bloques guardados en la carpeta blocks del store, creandola si no existe.
+    /// Restaura el indice y lo completa con los bloques de los archivos blk que no llego a registrar (ver recover).
+    pub fn with_max_file_size(store_path: &str, max_file_size: u64) -> Result<Self, CustomError> {
+        fs::create_dir_all(format!("{}/blocks", store_path))?;
+        let mut block_store = Self {
+            store_path: store_path.to_string(),
+            index: HashMap::new(),
+            max_file_size,
+            current_file: 0,
+            current_size: 0,
+        };
+        block_store.restore_index()?;
+        block_store.recover()?;
+        Ok(block_store)
+    }
+
+    /// Agrega un bloque serializado al archivo blk actual y lo registra en el indice.
+    /// Un bloque se guarda una unica vez: si ya esta guardado devuelve CustomError::BlockAlreadyStored.
+    /// Si la escritura falla se descarta lo escrito, para que no quede un bloque incompleto.
+    pub fn append(
+        &mut self,
+        block_hash: &[u8],
+        block: &[u8],
+    ) -> Result<BlockLocation, CustomError> {
+        if block_hash.len() != BLOCK_HASH_SIZE {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+        if self.index.contains_key(block_hash) {
+            return Err(CustomError::BlockAlreadyStored);
+        }
+        let length =
+            u32::try_from(block.len()).map_err(|_| CustomError::SerializedBufferIsInvalid)?;
+        let record_size = BLOCK_RECORD_HEADER_SIZE + length as u64;
+        if self.current_size > 0 && self.current_size + record_size > self.max_file_size {
+            self.current_file += 1;
+            self.current_size = 0;
+        }
+
+        let mut record = Vec::with_capacity(record_size as usize);
+        record.extend(BLOCK_RECORD_MAGIC);
+        record.extend(block_hash);
+        record.extend(length.to_le_bytes());
+        record.extend(block);
+        let mut file = open_file(
+            &block_file_path(&self.store_path, self.current_file),
+            OpenMode::Append,
+        )?;
+        if let Err(error) = file.write_all(&record) {
+            file.set_len(self.current_size)?;
+            return Err(error.into());
+        }
+
+        let location = BlockLocation {
+            file_number: self.current_file,
+            offset: self.current_size + BLOCK_RECORD_HEADER_SIZE,
+            length,
+        };
+        self.current_size += record_size;
+        self.append_index_record(block_hash, location)?;
+        self.index.insert(block_hash.to_vec(), location);
+        Ok(location)
+    }
+
+    /// Devuelve el bloque guardado con el hash recibido.
+    /// Devuelve CustomError::CannotOpenFile si no esta guardado, o un error de parseo si esta roto.
+    pub fn get(&self, block_hash: &[u8]) -> Result<Block, CustomError> {
+        let mut buffer = vec![];
+        self.open_block(block_hash)?.read_to_end(&mut buffer)?;
+        Block::parse(buffer)
+    }
+
+    /// Abre un bloque guardado para leerlo sin cargarlo completo en memoria (ver BlockReader).
+    /// Devuelve CustomError::CannotOpenFile si no esta guardado.
+    pub fn open_block(&self, block_hash: &[u8]) -> Result<BlockReader, CustomError> {
+        let location = self
+            .index
+            .get(block_hash)
+            .ok_or(CustomError::CannotOpenFile)?;
+        let file = open_file(
+            &block_file_path(&self.store_path, location.file_number),
+            OpenMode::Read,
+        )?;
+        BlockReader::new(file, location.offset, location.length as u64)
+    }
+
+    /// Devuelve la ubicacion de un bloque guardado.
+    pub fn location(&self, block_hash: &[u8]) -> Option<BlockLocation> {
+        self.index.get(block_hash).copied()
+    }
+
+    /// Devuelve true si el bloque esta guardado.
+    pub fn contains(&self, block_hash: &[u8]) -> bool {
+        self.index.contains_key(block_hash)
+    }
+
+    /// Devuelve la cantidad de bloques guardados, sin leer el disco (sale del indice en memoria).
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Devuelve true si no hay bloques guardados.
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    fn index_path(&self) -> String {
+        format!("{}/blocks/{}", self.store_path, BLOCK_INDEX_FILE)
+    }
+
+    /// Lee el indice guardado. Un registro incompleto al final (un corte mientras se escribia) se descarta,
+    /// su bloque se vuelve a indexar desde el archivo blk.
+    fn restore_index(&mut self) -> Result<(), CustomError> {
+        let mut index_file = open_file(&self.index_path(), OpenMode::ReadCreate)?;
+        let mut buffer = vec![];
+        index_file.read_to_end(&mut buffer)?;
+
+        for record in buffer.chunks_exact(INDEX_RECORD_SIZE) {
+            let (block_hash, location) = parse_index_record(record)?;
+            self.index.insert(block_hash, location);
+        }
+        let complete = buffer.len() - buffer.len() % INDEX_RECORD_SIZE;
+        if complete != buffer.len() {
+            index_file.set_len(complete as u64)?;
+        }
+        Ok(())
+    }
+
+    /// Completa el indice con los archivos blk: descarta las ubicaciones que apuntan mas alla del final de su archivo
+    /// y agrega los bloques escritos despues del ultimo indexado (o todos si no habia indice).
+    /// Un bloque incompleto al final del ultimo archivo (un corte mientras se escribia) se borra.
+    fn recover(&mut self) -> Result<(), CustomError> {
+        let mut file_sizes: HashMap<u32, u64> = HashMap::new();
+        for location in self.index.values() {
+            if !file_sizes.contains_key(&location.file_number) {
+                let path = block_file_path(&self.store_path, location.file_number);
+                let size = fs::metadata(path).map_or(0, |metadata| metadata.len());
+                file_sizes.insert(location.file_number, size);
+            }
+        }
+        let indexed = self.index.len();
+        self.index.retain(|_, location| {
+            location.offset + location.length as u64 <= file_sizes[&location.file_number]
+        });
+        if self.index.len() != indexed {
+            self.rewrite_index()?;
+        }
+
+        let last_indexed = self
+            .index
+            .values()
+            .max_by_key(|location| (location.file_number, location.offset));
+        let (mut file_number, mut start) = match last_indexed {
+            Some(location) => (
+                location.file_number,
+                location.offset + location.length as u64,
+            ),
+            None => (0, 0),
+        };
+        loop {
+            let size = self.recover_block_file(file_number, start)?;
+            self.current_file = file_number;
+            self.current_size = size;
+            if !Path::new(&block_file_path(&self.store_path, file_number + 1)).exists() {
+                return Ok(());
+            }
+            file_number += 1;
+            start = 0;
+        }
+    }
+
+    /// Agrega al indice los bloques de un archivo blk a partir de start y devuelve el tamaño del archivo.
+    /// Si encuentra un bloque incompleto o roto corta el archivo en ese punto.
+    fn recover_block_file(&mut self, file_number: u32, start: u64) -> Result<u64, CustomError> {
+        let path = block_file_path(&self.store_path, file_number);
+        if !Path::new(&path).exists() {
+            return Ok(0);
+        }
+        let mut file = open_file(&path, OpenMode::ReadCreate)?;
+        let size = file.metadata()?.len();
+
+        let mut offset = start;
+        while offset < size {
+            let Some((block_hash, length)) = read_record_header(&mut file, offset, size)? else {
+                file.set_len(offset)?;
+                break;
+            };
+            let location = BlockLocation {
+                file_number,
+                offset: offset + BLOCK_RECORD_HEADER_SIZE,
+                length,
+            };
+            self.append_index_record(&block_hash, location)?;
+            self.index.insert(block_hash, location);
+            offset = location.offset + length as u64;
+        }
+        Ok(offset)
+    }
+
+    fn append_index_record(
+        &self,
+        block_hash: &[u8],
+        location: BlockLocation,
+    ) -> Result<(), CustomError> {
+        let mut index_file = open_file(&self.index_path(), OpenMode::Append)?;
+        index_file.write_all(&serialize_index_record(block_hash, location))?;
+        Ok(())
+    }
+
+    fn rewrite_index(&self) -> Result<(), CustomError> {
+        let mut buffer = Vec::with_capacity(self.index.len() * INDEX_RECORD_SIZE);
+        for (block_hash, location) in &self.index {
+            buffer.extend(serialize_index_record(block_hash, *location));
+        }
+        write_file_atomic(&self.index_path(), &buffer)
+    }
+}
+
+/// BlockReader lee un bloque dentro de su archivo blk como si fuera un archivo propio: la posicion 0 es el inicio
+/// del bloque y el final es el final del bloque. Permite enviarlo a un peer por fragmentos (ver send_streamed).
+pub struct BlockReader {
+    file: File,
+    start: u64,
+    length: u64,
+    position: u64,
+}
+
+impl BlockReader {
+    fn new(mut file: File, start: u64, length: u64) -> Result<Self, CustomError> {
+        file.seek(SeekFrom::Start(start))?;
+        Ok(Self {
+            file,
+            start,
+            length,
+            position: 0,
+        })
+    }
+}
+
+impl Read for BlockReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.length - self.position;
+        let max = remaining.min(buf.len() as u64) as usize;
+        let read = self.file.read(&mut buf[..max])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for BlockReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.length.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        let Some(position) = position else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            ));
+        };
+        let position = position.min(self.length);
+        self.file.seek(SeekFrom::Start(self.start + position))?;
+        self.position = position;
+        Ok(position)
+    }
+}
+
+/// Lee el encabezado del bloque que empieza en offset y devuelve su hash y largo,
+/// o None si el encabezado no tiene el magic o el bloque no termina antes de size.
+fn read_record_header(
+    file: &mut File,
+    offset: u64,
+    size: u64,
+) -> Result<Option<(Vec<u8>, u32)>, CustomError> {
+    if offset + BLOCK_RECORD_HEADER_SIZE > size {
+        return Ok(None);
+    }
+    let mut header = [0; BLOCK_RECORD_HEADER_SIZE as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut header)?;
+    if header[..4] != BLOCK_RECORD_MAGIC {
+        return Ok(None);
+    }
+    let block_hash = header[4..4 + BLOCK_HASH_SIZE].to_vec();
+    let mut length = [0; 4];
+    length.copy_from_slice(&header[4 + BLOCK_HASH_SIZE..]);
+    let length = u32::from_le_bytes(length);
+    if offset + BLOCK_RECORD_HEADER_SIZE + length as u64 > size {
+        return Ok(None);
+    }
+    Ok(Some((block_hash, length)))
+}
+
+fn serialize_index_record(block_hash: &[u8], location: BlockLocation) -> Vec<u8> {
+    let mut record = Vec::with_capacity(INDEX_RECORD_SIZE);
+    record.extend(block_hash);
+    record.extend(location.file_number.to_le_bytes());
+    record.extend(location.offset.to_le_bytes());
+    record.extend(location.length.to_le_bytes());
+    record
+}
+
+fn parse_index_record(record: &[u8]) -> Result<(Vec<u8>, BlockLocation), CustomError> {
+    let mut parser = BufferParser::new(record.to_vec());
+    let block_hash = parser.extract_buffer(BLOCK_HASH_SIZE)?.to_vec();
+    let location = BlockLocation {
+        file_number: parser.extract_u32()?,
+        offset: parser.extract_u64()?,
+        length: parser.extract_u32()?,
+    };
+    Ok((block_hash, location))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+
+    use super::*;
+
+    fn create_test_store(store_path: &str) {
+        let _ = fs::remove_dir_all(store_path);
+        fs::create_dir_all(store_path).unwrap();
+    }
+
+    fn read_block(block_store: &BlockStore, block_hash: &[u8]) -> Vec<u8> {
+        let mut buffer = vec![];
+        block_store
+            .open_block(block_hash)
+            .unwrap()
+            .read_to_end(&mut buffer)
+            .unwrap();
+        buffer
+    }
+
+    #[test]
+    fn block_store_appends_and_reads_blocks() {
+        let store_path = "tests/store_block_store_append";
+        create_test_store(store_path);
+        let block_bytes = fs::read("tests/blocks/test_block.bin").unwrap();
+        let mut block_store = BlockStore::open(store_path).unwrap();
+
+        block_store.append(&[1; 32], &block_bytes).unwrap();
+        block_store.append(&[2; 32], &[7; 10]).unwrap();
+
+        assert_eq!(block_store.len(), 2);
+        assert!(block_store.contains(&[1; 32]));
+        assert_eq!(block_store.get(&[1; 32]).unwrap().serialize(), block_bytes);
+        assert_eq!(read_block(&block_store, &[2; 32]), vec![7; 10]);
+        assert!(matches!(
+            block_store.append(&[2; 32], &[8; 10]),
+            Err(CustomError::BlockAlreadyStored)
+        ));
+        assert!(matches!(
+            block_store.get(&[3; 32]),
+            Err(CustomError::CannotOpenFile)
+        ));
+
+        let mut reader = block_store.open_block(&[2; 32]).unwrap();
+        assert_eq!(reader.seek(SeekFrom::End(0)).unwrap(), 10);
+        reader.seek(SeekFrom::Start(4)).unwrap();
+        let mut tail = vec![];
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, vec![7; 6]);
+
+        fs::remove_dir_all(store_path).unwrap();
+    }
+
+    #[test]
+    fn block_store_rolls_over_to_a_new_file() {
+        let store_path = "tests/store_block_store_rollover";
+        create_test_store(store_path);
+        let record_size = BLOCK_RECORD_HEADER_SIZE + 100;
+        let mut block_store = BlockStore::with_max_file_size(store_path, record_size * 2).unwrap();
+
+        for byte in 1..=5_u8 {
+            block_store.append(&[byte; 32], &[byte; 100]).unwrap();
+        }
+
+        let files: Vec<u32> = (1..=5_u8)
+            .map(|byte| block_store.location(&[byte; 32]).unwrap().file_number)
+            .collect();
+        assert_eq!(files, vec![0, 0, 1, 1, 2]);
+        assert_eq!(
+            fs::metadata(block_file_path(store_path, 1)).unwrap().len(),
+            record_size * 2
+        );
+        for byte in 1..=5_u8 {
+            assert_eq!(read_block(&block_store, &[byte; 32]), vec![byte; 100]);
+        }
+
+        // al reabrir se sigue agregando al ultimo archivo
+        drop(block_store);
+        let mut block_store = BlockStore::with_max_file_size(store_path, record_size * 2).unwrap();
+        assert_eq!(block_store.len(), 5);
+        let location = block_store.append(&[6; 32], &[6; 100]).unwrap();
+        assert_eq!(location.file_number, 2);
+        assert_eq!(location.offset, record_size + BLOCK_RECORD_HEADER_SIZE);
+        assert_eq!(read_block(&block_store, &[3; 32]), vec![3; 100]);
+
+        fs::remove_dir_all(store_path).unwrap();
+    }
+
+    #[test]
+    fn block_store_recovers_index_after_restart() {
+        let store_path = "tests/store_block_store_recovery";
+        create_test_store(store_path);
+        let record_size = BLOCK_RECORD_HEADER_SIZE + 50;
+        let mut block_store = BlockStore::with_max_file_size(store_path, record_size * 2).unwrap();
+        for byte in 1..=3_u8 {
+            block_store.append(&[byte; 32], &[byte; 50]).unwrap();
+        }
+        drop(block_store);
+        let index_path = format!("{}/blocks/{}", store_path, BLOCK_INDEX_FILE);
+
+        // corte mientras se escribia el indice: el ultimo bloque se vuelve a indexar desde su archivo
+        let index = fs::read(&index_path).unwrap();
+        fs::write(&index_path, &index[..index.len() - 10]).unwrap();
+        let block_store = BlockStore::with_max_file_size(store_path, record_size * 2).unwrap();
+        assert_eq!(block_store.len(), 3);
+        assert_eq!(read_block(&block_store, &[3; 32]), vec![3; 50]);
+        drop(block_store);
+        assert_eq!(fs::read(&index_path).unwrap().len(), INDEX_RECORD_SIZE * 3);
+
+        // sin indice se reconstruye completo recorriendo los archivos blk
+        fs::remove_file(&index_path).unwrap();
+        let block_store = BlockStore::with_max_file_size(store_path, record_size * 2).unwrap();
+        assert_eq!(block_store.len(), 3);
+        for byte in 1..=3_u8 {
+            assert_eq!(read_block(&block_store, &[byte; 32]), vec![byte; 50]);
+        }
+        drop(block_store);
+
+        // corte mientras se escribia un bloque: se descarta y el siguiente se agrega en su lugar
+        let last_file = block_file_path(store_path, 1);
+        let mut file = OpenOptions::new().append(true).open(&last_file).unwrap();
+        file.write_all(&BLOCK_RECORD_MAGIC).unwrap();
+        file.write_all(&[4; 20]).unwrap();
+        drop(file);
+        let mut block_store = BlockStore::with_max_file_size(store_path, record_size * 2).unwrap();
+        assert_eq!(block_store.len(), 3);
+        assert_eq!(fs::metadata(&last_file).unwrap().len(), record_size);
+        block_store.append(&[4; 32], &[4; 50]).unwrap();
+        assert_eq!(read_block(&block_store, &[4; 32]), vec![4; 50]);
+        assert_eq!(block_store.location(&[4; 32]).unwrap().file_number, 1);
+
+        fs::remove_dir_all(store_path).unwrap();
+    }
+}
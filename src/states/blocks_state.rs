@@ -1,16 +1,140 @@
 use std::{
-    fs::read_dir,
+    fs::{self, read_dir},
+    path::{Path, PathBuf},
     sync::{mpsc::Sender, Arc, Mutex},
 };
 
+use gtk::glib;
+
 use crate::{
     error::CustomError,
+    gui::init::GUIEvents,
     logger::{send_log, Log},
+    message::Message,
     messages::block::Block,
+    structs::block_header::parse_display_hash,
     utils::get_current_timestamp_millis,
 };
 
-use super::pending_blocks_state::PendingBlocks;
+use super::{
+    block_store_state::{BlockReader, BlockStore},
+    pending_blocks_state::PendingBlocks,
+};
+
+/// Version del formato de los bloques del store. En la version 1 se guardaba un archivo por bloque nombrado con el hash
+/// en el orden interno de sus bytes (ver hash_as_string), en la version 2 con el hash como lo muestran los exploradores
+/// (ver display_hash), y desde la version 3 se guardan en los archivos blk del BlockStore.
+pub const BLOCK_STORE_VERSION: u32 = 3;
+
+/// Cantidad de archivos importados entre cada log de progreso de la importacion.
+const IMPORT_LOG_INTERVAL: usize = 1000;
+
+/// Devuelve el path del manifest del store, donde se guarda la version del formato de los bloques.
+fn manifest_path(store_path: &str) -> String {
+    format!("{}/manifest.txt", store_path)
+}
+
+/// Devuelve la version del formato de los bloques del store. Los stores sin manifest son de la version 1.
+pub fn read_store_version(store_path: &str) -> u32 {
+    let Ok(manifest) = fs::read_to_string(manifest_path(store_path)) else { return 1 };
+    manifest
+        .lines()
+        .find_map(|line| line.strip_prefix("BLOCK_STORE_VERSION="))
+        .and_then(|version| version.trim().parse().ok())
+        .unwrap_or(1)
+}
+
+/// Guarda en el manifest del store la version del formato de los bloques.
+pub fn write_store_version(store_path: &str, version: u32) -> Result<(), CustomError> {
+    fs::write(
+        manifest_path(store_path),
+        format!("BLOCK_STORE_VERSION={}\n", version),
+    )?;
+    Ok(())
+}
+
+/// Devuelve el hash de un archivo de bloque de las versiones 1 (hexadecimal en mayuscula, en el orden interno de los bytes)
+/// o 2 (como lo muestran los exploradores) del store, o None si el archivo no es un bloque.
+fn block_file_hash(path: &Path) -> Option<Vec<u8>> {
+    if path.extension()? != "bin" {
+        return None;
+    }
+    let name = path.file_stem()?.to_str()?;
+    if name.len() != 64 || !name.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    if name != name.to_lowercase() {
+        return (0..name.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&name[i..i + 2], 16).ok())
+            .collect();
+    }
+    parse_display_hash(name)
+}
+
+/// Importa al BlockStore los bloques que las versiones 1 y 2 del store guardaban en un archivo por bloque,
+/// y actualiza el manifest. Cada archivo se borra despues de agregar su bloque, por lo que la importacion se puede
+/// interrumpir en cualquier momento y continua en la proxima ejecucion; si un bloque ya esta en el BlockStore
+/// solo se borra su archivo.
+/// Devuelve la cantidad de archivos importados.
+pub fn import_block_files(
+    store_path: &str,
+    block_store: &mut BlockStore,
+    logger_sender: &Sender<Log>,
+) -> Result<usize, CustomError> {
+    if read_store_version(store_path) >= BLOCK_STORE_VERSION {
+        return Ok(0);
+    }
+
+    let block_files: Vec<(PathBuf, Vec<u8>)> = read_dir(format!("{}/blocks", store_path))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            block_file_hash(&path).map(|block_hash| (path, block_hash))
+        })
+        .collect();
+    let total = block_files.len();
+    send_log(
+        logger_sender,
+        Log::Message(format!(
+            "Importing {} block files into the block store...",
+            total
+        )),
+    );
+
+    for (imported, (path, block_hash)) in block_files.iter().enumerate() {
+        if !block_store.contains(block_hash) {
+            block_store.append(block_hash, &fs::read(path)?)?;
+        }
+        fs::remove_file(path)?;
+        if (imported + 1) % IMPORT_LOG_INTERVAL == 0 {
+            send_log(
+                logger_sender,
+                Log::Message(format!(
+                    "Block files import: {}/{} imported",
+                    imported + 1,
+                    total
+                )),
+            );
+        }
+    }
+
+    write_store_version(store_path, BLOCK_STORE_VERSION)?;
+    send_log(
+        logger_sender,
+        Log::Message("Block files import completed".to_string()),
+    );
+    Ok(total)
+}
+
+/// Devuelve el porcentaje de blocks_downloaded sobre total_blocks, como maximo 100.
+/// Si no hay bloques para descargar (total_blocks es 0) la descarga esta completa.
+fn download_percentage(blocks_downloaded: usize, total_blocks: usize) -> usize {
+    match total_blocks {
+        0 => 100,
+        total_blocks => (blocks_downloaded * 100 / total_blocks).min(100),
+    }
+}
 
 /// BlocksIBDStats es una estructura que contiene los elementos necesarios para manejar las
 /// estadisticas de la descarga masiva de bloques.
@@ -29,68 +153,105 @@ struct BlocksIBDStats {
     checkpoint_downloads: u128,
 }
 
+impl BlocksIBDStats {
+    /// Registra un bloque descargado, con blocks_downloaded bloques en total, a los now milisegundos.
+    /// total_blocks es la cantidad de bloques a descargar en ese momento, que cambia mientras siguen llegando headers.
+    /// Si el porcentaje descargado supera al del ultimo checkpoint, registra un nuevo checkpoint y devuelve
+    /// el porcentaje (como maximo 100) y los bloques por segundo desde el checkpoint anterior.
+    /// Si el total crece el porcentaje puede bajar, en ese caso no se informa hasta superar el ultimo informado,
+    /// para que el progreso nunca retroceda.
+    fn checkpoint(
+        &mut self,
+        blocks_downloaded: usize,
+        total_blocks: usize,
+        now: u128,
+    ) -> Option<(usize, u128)> {
+        self.blocks_downloaded = blocks_downloaded;
+        self.checkpoint_downloads += 1;
+
+        let percentage = download_percentage(blocks_downloaded, total_blocks);
+        if percentage <= self.checkpoint_percentage {
+            return None;
+        }
+        // dos bloques pueden llegar en el mismo milisegundo
+        let checkpoint_time = now.saturating_sub(self.checkpoint_timestamp).max(1);
+        let blocks_per_second = self.checkpoint_downloads * 1000 / checkpoint_time;
+
+        self.checkpoint_percentage = percentage;
+        self.checkpoint_timestamp = now;
+        self.checkpoint_downloads = 0;
+        Some((percentage, blocks_per_second))
+    }
+}
+
 /// BlocksState es una estructura que contiene los elementos necesarios para manejar los bloques.
 /// Los elementos son:
 /// - ibd_stats: Option<BLocksIBDStats> solamente se inicializa cuando corresponde.
-/// - store_path: Path de la carpeta donde se crea el directorio donde se encuentran los bloques.
+/// - store: BlockStore donde se guardan los bloques descargados.
 /// - logger_sender: Sender para enviar logs al logger.
+/// - gui_sender: Sender para enviar el progreso de la descarga de bloques a la interfaz grafica.
 /// - pending_blocks_ref: Referencia a los bloques pendientes.
 /// - sync: Booleano que indica si el nodo esta sincronizado.
+/// - synced_headers_count: Cantidad de headers que habia al momento de declarar los bloques sincronizados.
 pub struct BlocksState {
     ibd_stats: Option<BlocksIBDStats>,
-    store_path: String,
+    store: BlockStore,
     logger_sender: Sender<Log>,
+    gui_sender: glib::Sender<GUIEvents>,
     pub pending_blocks_ref: Arc<Mutex<PendingBlocks>>,
     sync: bool,
+    synced_headers_count: usize,
 }
 
 impl BlocksState {
     /// Inicializa el estado de los bloques.
     pub fn new(
-        store_path: String,
+        store: BlockStore,
         logger_sender: Sender<Log>,
+        gui_sender: glib::Sender<GUIEvents>,
         pending_blocks_ref: Arc<Mutex<PendingBlocks>>,
     ) -> Self {
         Self {
             ibd_stats: None,
             pending_blocks_ref,
-            store_path,
+            store,
             logger_sender,
+            gui_sender,
             sync: false,
+            synced_headers_count: 0,
         }
     }
 
-    /// Se encarga de guardar en disco el bloque y eliminarlo de los bloques pendientes.
+    /// Se encarga de guardar el bloque en el BlockStore (si no estaba guardado, por ejemplo si llego de dos peers)
+    /// y eliminarlo de los bloques pendientes.
     /// Si la cantidad de bloques a descargar es mayor al 2% de los headers posteriores al START_DATE_IBD
-    /// comienza los stats de la descarga.
+    /// comienza los stats de la descarga. La cantidad de bloques descargados sale del indice del BlockStore,
+    /// que esta en memoria, por lo que no se recorre el disco en cada bloque.
     pub fn append_block(
         &mut self,
         block_hash: &Vec<u8>,
         block: &Block,
         total_blocks: usize,
     ) -> Result<(), CustomError> {
-        let path = format!(
-            "{}/blocks/{}.bin",
-            self.store_path,
-            block.header.hash_as_string()
-        );
-        block.save(path)?;
+        if !self.store.contains(block_hash) {
+            self.store.append(block_hash, &block.serialize())?;
+        }
 
+        let blocks_downloaded = self.store.len();
         if self.ibd_stats.is_none() {
-            let blocks_downloaded = read_dir(format!("{}/blocks", self.store_path))?.count();
-            let percentage = (blocks_downloaded * 100) / total_blocks;
+            let percentage = download_percentage(blocks_downloaded, total_blocks);
 
             if percentage < 98_usize {
                 self.ibd_stats = Some(BlocksIBDStats {
                     blocks_downloaded,
-                    checkpoint_timestamp: block.header.timestamp as u128 * 1000,
+                    checkpoint_timestamp: get_current_timestamp_millis()?,
                     checkpoint_percentage: percentage,
                     checkpoint_downloads: 0,
                 })
             }
         }
 
-        self.print_status(total_blocks)?;
+        self.print_status(blocks_downloaded, total_blocks)?;
 
         let mut pending_blocks = self.pending_blocks_ref.lock()?;
         pending_blocks.remove_block(block_hash)?;
@@ -99,50 +260,62 @@ impl BlocksState {
         Ok(())
     }
 
-    fn print_status(&mut self, total_blocks: usize) -> Result<(), CustomError> {
+    fn print_status(
+        &mut self,
+        blocks_downloaded: usize,
+        total_blocks: usize,
+    ) -> Result<(), CustomError> {
         if self.is_synced() || self.ibd_stats.is_none() {
             send_log(
                 &self.logger_sender,
                 Log::Message(String::from("New block received")),
             );
         } else {
-            self.print_stats(total_blocks)?;
+            self.print_stats(blocks_downloaded, total_blocks)?;
         }
 
         Ok(())
     }
 
-    fn print_stats(&mut self, total_blocks: usize) -> Result<(), CustomError> {
-        if let Some(ibd_stats) = &mut self.ibd_stats {
-            ibd_stats.blocks_downloaded += 1;
-            ibd_stats.checkpoint_downloads += 1;
-
-            let percentage = (ibd_stats.blocks_downloaded * 100) / total_blocks;
-            if percentage > ibd_stats.checkpoint_percentage {
-                let now = get_current_timestamp_millis()?;
-                let checkpoint_time = now - ibd_stats.checkpoint_timestamp;
-                let blocks_per_second = ibd_stats.checkpoint_downloads * 1000 / checkpoint_time;
-
-                send_log(
-                    &self.logger_sender,
-                    Log::Message(format!(
-                        "Blocks sync {}% at {} blocks/s... total {}",
-                        percentage, blocks_per_second, ibd_stats.blocks_downloaded
-                    )),
-                );
-
-                ibd_stats.checkpoint_percentage = percentage;
-                ibd_stats.checkpoint_timestamp = now;
-                ibd_stats.checkpoint_downloads = 0;
-            }
+    /// Cada vez que aumenta el porcentaje descargado lo informa en el log y en la interfaz grafica (BlocksProgress).
+    /// total_blocks se recalcula en cada bloque con los headers que hay en ese momento (ver BlocksIBDStats::checkpoint).
+    fn print_stats(
+        &mut self,
+        blocks_downloaded: usize,
+        total_blocks: usize,
+    ) -> Result<(), CustomError> {
+        let Some(ibd_stats) = &mut self.ibd_stats else { return Ok(()) };
+        let now = get_current_timestamp_millis()?;
+        let Some((percentage, blocks_per_second)) =
+            ibd_stats.checkpoint(blocks_downloaded, total_blocks, now)
+        else {
+            return Ok(());
+        };
+
+        send_log(
+            &self.logger_sender,
+            Log::Message(format!(
+                "Blocks sync {}% at {} blocks/s... total {}",
+                percentage, blocks_per_second, blocks_downloaded
+            )),
+        );
+        let progress = GUIEvents::BlocksProgress {
+            percentage,
+            rate: blocks_per_second as usize,
+        };
+        if let Err(error) = self.gui_sender.send(progress) {
+            send_log(
+                &self.logger_sender,
+                Log::Message(format!("Error sending blocks progress to gui: {error}")),
+            );
         }
-
         Ok(())
     }
 
     /// Verifica si los bloques estan sincronizado.
     /// Para esto se necesita que no haya bloques pendientes.
-    pub fn verify_sync(&mut self) -> Result<(), CustomError> {
+    /// Registra la cantidad de headers recibida para saber respecto de que headers se sincronizo.
+    pub fn verify_sync(&mut self, headers_count: usize) -> Result<(), CustomError> {
         if self.sync {
             return Ok(());
         }
@@ -151,6 +324,7 @@ impl BlocksState {
         self.sync = pending_blocks.is_empty();
 
         if self.sync {
+            self.synced_headers_count = headers_count;
             pending_blocks.drain();
             send_log(
                 &self.logger_sender,
@@ -161,64 +335,194 @@ impl BlocksState {
     }
 
     /// Devuelve el bloque correspondiente al hash pasado por parametro.
-    pub fn get_block(&self, block_string_hash: String) -> Result<Block, CustomError> {
-        let path = format!("{}/blocks/{}.bin", self.store_path, block_string_hash);
-        Block::restore(path)
+    pub fn get_block(&self, block_hash: &[u8]) -> Result<Block, CustomError> {
+        self.store.get(block_hash)
+    }
+
+    /// Abre un bloque guardado en disco, para enviarlo sin cargarlo completo en memoria (ver BlockReader).
+    pub fn get_block_file(&self, block_hash: &[u8]) -> Result<BlockReader, CustomError> {
+        self.store.open_block(block_hash)
+    }
+
+    /// Devuelve true si el bloque ya esta guardado en disco.
+    pub fn is_stored(&self, block_hash: &[u8]) -> bool {
+        self.store.contains(block_hash)
+    }
+
+    /// Devuelve el BlockStore donde se guardan los bloques.
+    pub fn store(&self) -> &BlockStore {
+        &self.store
     }
 
     /// Retorna el estado de sincronizacion de los bloques.
     pub fn is_synced(&self) -> bool {
         self.sync
     }
+
+    /// Retorna true si los bloques estan sincronizados y no llegaron headers nuevos desde entonces.
+    pub fn is_synced_at(&self, headers_count: usize) -> bool {
+        self.sync && self.synced_headers_count == headers_count
+    }
+
+    /// Invalida la sincronizacion de los bloques si llegaron headers posteriores a los que
+    /// habia al momento de sincronizar, para que se vuelva a verificar cuando lleguen sus bloques.
+    pub fn invalidate_sync(&mut self, headers_count: usize) {
+        if self.sync && headers_count > self.synced_headers_count {
+            self.sync = false;
+            send_log(
+                &self.logger_sender,
+                Log::Message("new headers received, waiting for their blocks".to_string()),
+            );
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use std::{fs, path::Path, sync::mpsc};
+    use std::{fs, io::Read, path::Path, sync::mpsc};
+
+    use gtk::glib::Priority;
+
+    use crate::structs::block_header::display_hash;
 
     use super::*;
 
+    fn create_blocks_state(store_path: &str) -> BlocksState {
+        let _ = fs::remove_dir_all(store_path);
+        let (logger_sender, _) = mpsc::channel();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let pending_blocks_ref = PendingBlocks::new(&store_path.to_string(), &vec![], |_| false);
+        BlocksState::new(
+            BlockStore::open(store_path).unwrap(),
+            logger_sender,
+            gui_sender,
+            pending_blocks_ref,
+        )
+    }
+
     #[test]
     fn blocks_state_append() {
-        let store_path = "tests".to_string();
-        let (logger_sender, _) = mpsc::channel();
-        let pending_blocks_ref = PendingBlocks::new(&store_path, &vec![]);
-        let mut blocks_state =
-            BlocksState::new(store_path.clone(), logger_sender, pending_blocks_ref);
+        let store_path = "tests/store_blocks_state_append";
+        let mut blocks_state = create_blocks_state(store_path);
+        let block_hash = vec![1; 32];
 
         let mut pending = blocks_state.pending_blocks_ref.lock().unwrap();
-        pending.append_block(vec![1, 2, 3]).unwrap();
+        pending.append_block(block_hash.clone()).unwrap();
         drop(pending);
 
-        let mut block = blocks_state.get_block("test_block".to_string()).unwrap();
-        block.header.hash = vec![1, 2, 3];
+        let mut block = Block::restore("tests/blocks/test_block.bin".to_string()).unwrap();
+        block.header.hash = block_hash.clone();
 
-        blocks_state
-            .append_block(&vec![1, 2, 3], &block, 1)
-            .unwrap();
+        blocks_state.append_block(&block_hash, &block, 1).unwrap();
+        // si el bloque llega de otro peer no se vuelve a guardar
+        blocks_state.append_block(&block_hash, &block, 1).unwrap();
 
         let pending = blocks_state.pending_blocks_ref.lock().unwrap();
         assert_eq!(pending.is_empty(), true);
+        drop(pending);
+
+        assert!(blocks_state.is_stored(&block_hash));
+        assert_eq!(blocks_state.store().len(), 1);
+        assert_eq!(
+            blocks_state.get_block(&block_hash).unwrap().serialize(),
+            block.serialize()
+        );
+        fs::remove_dir_all(store_path).unwrap();
+    }
+
+    #[test]
+    fn blocks_state_append_many_blocks() {
+        let store_path = "tests/store_blocks_state_append_many";
+        let mut blocks_state = create_blocks_state(store_path);
+        let mut block = Block::restore("tests/blocks/test_block.bin".to_string()).unwrap();
+
+        // sin bloques para descargar no divide por 0 ni inicia los stats
+        block.header.hash = vec![0; 32];
+        blocks_state.append_block(&vec![0; 32], &block, 0).unwrap();
+        assert!(blocks_state.ibd_stats.is_none());
+
+        for i in 1..1000_u32 {
+            let mut block_hash = vec![0; 28];
+            block_hash.extend(i.to_le_bytes());
+            block.header.hash = block_hash.clone();
+            blocks_state
+                .append_block(&block_hash, &block, 2000)
+                .unwrap();
+        }
 
-        assert!(Path::new(&format!("{}/blocks/010203.bin", store_path)).exists());
-        fs::remove_file(format!("{}/blocks/010203.bin", store_path)).unwrap();
+        let ibd_stats = blocks_state.ibd_stats.as_ref().unwrap();
+        assert_eq!(ibd_stats.blocks_downloaded, 1000);
+        assert_eq!(ibd_stats.checkpoint_percentage, 50);
+        assert_eq!(blocks_state.store().len(), 1000);
+        fs::remove_dir_all(store_path).unwrap();
+    }
+
+    #[test]
+    fn download_percentage_is_bounded() {
+        assert_eq!(download_percentage(0, 0), 100);
+        assert_eq!(download_percentage(5, 0), 100);
+        assert_eq!(download_percentage(1, 3), 33);
+        assert_eq!(download_percentage(10, 8), 100);
+    }
+
+    #[test]
+    fn ibd_progress_never_goes_back_nor_exceeds_100() {
+        let mut ibd_stats = BlocksIBDStats {
+            blocks_downloaded: 0,
+            checkpoint_timestamp: 1_000,
+            checkpoint_percentage: 0,
+            checkpoint_downloads: 0,
+        };
+
+        // el total crece mientras llegan headers y se achica si un reorg descarta headers
+        let totals = [100, 100, 200, 200, 150, 400, 400, 90, 90, 120, 120, 120];
+        let mut reported = vec![];
+        for (index, total_blocks) in totals.iter().enumerate() {
+            let blocks_downloaded = (index + 1) * 10;
+            // varios bloques llegan en el mismo milisegundo
+            let now = 1_000 + index as u128 / 3;
+            if let Some((percentage, _)) =
+                ibd_stats.checkpoint(blocks_downloaded, *total_blocks, now)
+            {
+                reported.push(percentage);
+            }
+        }
+
+        assert_eq!(reported, vec![10, 20, 33, 88, 100]);
+        assert!(reported.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(reported.iter().all(|percentage| *percentage <= 100));
+        assert_eq!(ibd_stats.checkpoint_percentage, 100);
+    }
+
+    #[test]
+    fn ibd_blocks_per_second_since_last_checkpoint() {
+        let mut ibd_stats = BlocksIBDStats {
+            blocks_downloaded: 0,
+            checkpoint_timestamp: 10_000,
+            checkpoint_percentage: 0,
+            checkpoint_downloads: 0,
+        };
+
+        assert_eq!(ibd_stats.checkpoint(0, 100, 10_500), None);
+        assert_eq!(ibd_stats.checkpoint(1, 100, 12_000), Some((1, 1)));
+        // en el mismo milisegundo del checkpoint no divide por 0
+        assert_eq!(ibd_stats.checkpoint(2, 100, 12_000), Some((2, 1000)));
+        // un reloj que retrocede no hace overflow
+        assert_eq!(ibd_stats.checkpoint(3, 100, 11_000), Some((3, 1000)));
     }
 
     #[test]
     fn blocks_state_verify_sync() {
-        let store_path = "tests".to_string();
-        let (logger_sender, _) = mpsc::channel();
-        let pending_blocks_ref = PendingBlocks::new(&store_path, &vec![]);
-        let mut blocks_state =
-            BlocksState::new(store_path.clone(), logger_sender, pending_blocks_ref);
+        let store_path = "tests/store_blocks_state_verify_sync";
+        let mut blocks_state = create_blocks_state(store_path);
 
         let mut pending = blocks_state.pending_blocks_ref.lock().unwrap();
         pending.append_block(vec![1, 2, 3]).unwrap();
         drop(pending);
 
         assert_eq!(blocks_state.is_synced(), false);
-        blocks_state.verify_sync().unwrap();
+        blocks_state.verify_sync(1).unwrap();
         assert_eq!(blocks_state.is_synced(), false);
 
         let mut pending = blocks_state.pending_blocks_ref.lock().unwrap();
@@ -226,7 +530,7 @@ mod tests {
         drop(pending);
 
         assert_eq!(blocks_state.is_synced(), false);
-        blocks_state.verify_sync().unwrap();
+        blocks_state.verify_sync(1).unwrap();
         assert_eq!(blocks_state.is_synced(), true);
 
         let mut pending = blocks_state.pending_blocks_ref.lock().unwrap();
@@ -234,7 +538,88 @@ mod tests {
         drop(pending);
 
         assert_eq!(blocks_state.is_synced(), true);
-        blocks_state.verify_sync().unwrap();
+        blocks_state.verify_sync(1).unwrap();
+        assert_eq!(blocks_state.is_synced(), true);
+        fs::remove_dir_all(store_path).unwrap();
+    }
+
+    #[test]
+    fn blocks_state_invalidate_sync() {
+        let store_path = "tests/store_blocks_state_invalidate_sync";
+        let mut blocks_state = create_blocks_state(store_path);
+
+        blocks_state.verify_sync(10).unwrap();
+        assert_eq!(blocks_state.is_synced_at(10), true);
+        assert_eq!(blocks_state.is_synced_at(11), false);
+
+        blocks_state.invalidate_sync(10);
         assert_eq!(blocks_state.is_synced(), true);
+
+        blocks_state.invalidate_sync(12);
+        assert_eq!(blocks_state.is_synced(), false);
+
+        blocks_state.verify_sync(12).unwrap();
+        assert_eq!(blocks_state.is_synced_at(12), true);
+        fs::remove_dir_all(store_path).unwrap();
+    }
+
+    #[test]
+    fn blocks_state_imports_block_files_of_older_stores() {
+        let store_path = "tests/store_block_import";
+        let _ = fs::remove_dir_all(store_path);
+        fs::create_dir_all(format!("{}/blocks", store_path)).unwrap();
+        let block_bytes = fs::read("tests/blocks/test_block.bin").unwrap();
+        let legacy_hash = vec![0xAB; 32];
+        let display_hashes: Vec<Vec<u8>> = (1..=3_u8)
+            .map(|i| (0..32).map(|j| i.wrapping_mul(j + 7)).collect())
+            .collect();
+        // version 1: hexadecimal en mayuscula en el orden interno de los bytes
+        let legacy_path = format!("{}/blocks/{}.bin", store_path, "AB".repeat(32));
+        fs::write(&legacy_path, &block_bytes).unwrap();
+        // version 2: hash como lo muestran los exploradores
+        for hash in &display_hashes {
+            let path = format!("{}/blocks/{}.bin", store_path, display_hash(hash));
+            fs::write(path, hash).unwrap();
+        }
+        fs::write(format!("{}/blocks/notes.txt", store_path), "not a block").unwrap();
+        assert_eq!(read_store_version(store_path), 1);
+
+        // una importacion interrumpida deja bloques en el BlockStore cuyo archivo no se llego a borrar
+        let mut block_store = BlockStore::open(store_path).unwrap();
+        block_store
+            .append(&display_hashes[0], &display_hashes[0])
+            .unwrap();
+
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        assert_eq!(
+            import_block_files(store_path, &mut block_store, &logger_sender).unwrap(),
+            4
+        );
+
+        assert_eq!(block_store.len(), 4);
+        assert_eq!(
+            block_store.get(&legacy_hash).unwrap().serialize(),
+            block_bytes
+        );
+        for hash in &display_hashes {
+            let mut stored = vec![];
+            block_store
+                .open_block(hash)
+                .unwrap()
+                .read_to_end(&mut stored)
+                .unwrap();
+            assert_eq!(&stored, hash);
+        }
+        assert!(!Path::new(&legacy_path).exists());
+        assert!(Path::new(&format!("{}/blocks/notes.txt", store_path)).exists());
+        assert_eq!(read_store_version(store_path), BLOCK_STORE_VERSION);
+
+        // una vez importados no vuelve a recorrer los archivos
+        assert_eq!(
+            import_block_files(store_path, &mut block_store, &logger_sender).unwrap(),
+            0
+        );
+
+        fs::remove_dir_all(store_path).unwrap();
     }
 }
@@ -0,0 +1,220 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use crate::messages::block::Block;
+
+/// Cantidad maxima de muestras que guarda el FeeEstimator. Al superarla se descarta la mas antigua,
+/// para que la estimacion siga a la congestion actual de la red.
+pub const MAX_FEE_SAMPLES: usize = 1000;
+
+/// Cantidad de bloques luego de la cual se deja de seguir una transaccion pendiente que no se confirmo.
+const MAX_TRACKED_BLOCKS: usize = 1008;
+
+/// Fee por byte (sat/vB) que se estima mientras no hay muestras: el minimo para que los nodos retransmitan la transaccion.
+pub const DEFAULT_FEE_RATE: u64 = 1;
+
+/// Porcentaje de las muestras con un fee por byte mayor o igual que tiene que haberse confirmado dentro del objetivo.
+const SUCCESS_PERCENT: usize = 85;
+
+/// Objetivos en bloques de los fees rapido, normal y lento que se ofrecen al enviar una transaccion.
+pub const FAST_TARGET_BLOCKS: u16 = 1;
+pub const NORMAL_TARGET_BLOCKS: u16 = 3;
+pub const SLOW_TARGET_BLOCKS: u16 = 6;
+
+/// FeeSample es una transaccion pendiente que se confirmo.
+/// Los elementos son:
+/// - fee_rate: Fee por byte (sat/vB) que pagaba.
+/// - blocks: Cantidad de bloques que tardo en confirmarse desde que se recibio (al menos 1).
+struct FeeSample {
+    fee_rate: u64,
+    blocks: usize,
+}
+
+/// FeeEstimator estima el fee por byte necesario para confirmar una transaccion en una cantidad de bloques,
+/// a partir de cuanto tardaron en confirmarse las transacciones pendientes que se recibieron.
+/// No se guarda en disco: al reiniciar el nodo vuelve a juntar muestras.
+/// Los elementos son:
+/// - tracked: Hash de las transacciones pendientes seguidas, con su fee por byte y el height en el que se recibieron.
+/// - samples: Muestras de las transacciones que se confirmaron, de la mas antigua a la mas reciente.
+/// - capacity: Cantidad maxima de muestras.
+pub struct FeeEstimator {
+    tracked: HashMap<Vec<u8>, (u64, usize)>,
+    samples: VecDeque<FeeSample>,
+    capacity: usize,
+}
+
+impl FeeEstimator {
+    /// Crea un FeeEstimator sin muestras que guarda hasta capacity muestras.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            tracked: HashMap::new(),
+            samples: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Empieza a seguir una transaccion pendiente recibida cuando la cadena tenia ese height.
+    /// Si ya se seguia, conserva el height en el que se recibio por primera vez.
+    pub fn track(&mut self, tx_hash: Vec<u8>, fee_rate: u64, height: usize) {
+        self.tracked.entry(tx_hash).or_insert((fee_rate, height));
+    }
+
+    /// Deja de seguir una transaccion pendiente que se descarto sin confirmarse.
+    pub fn forget(&mut self, tx_hash: &Vec<u8>) {
+        self.tracked.remove(tx_hash);
+    }
+
+    /// Registra una muestra por cada transaccion seguida que esta en el bloque con ese height,
+    /// y deja de seguir las que llevan mas de MAX_TRACKED_BLOCKS bloques sin confirmarse.
+    pub fn confirm_block(&mut self, block: &Block, height: usize) {
+        for transaction in &block.transactions {
+            let Some((fee_rate, seen_at)) = self.tracked.remove(&transaction.hash()) else { continue };
+            self.samples.push_back(FeeSample {
+                fee_rate,
+                blocks: height.saturating_sub(seen_at).max(1),
+            });
+            if self.samples.len() > self.capacity {
+                self.samples.pop_front();
+            }
+        }
+        self.tracked
+            .retain(|_, (_, seen_at)| height.saturating_sub(*seen_at) <= MAX_TRACKED_BLOCKS);
+    }
+
+    /// Devuelve la cantidad de muestras.
+    pub fn samples(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Estima el fee por byte (sat/vB) para confirmar una transaccion en target_blocks bloques.
+    /// Agrupa las muestras por fee por byte y las recorre de mayor a menor fee, devolviendo el menor fee con el que
+    /// al menos SUCCESS_PERCENT de las muestras con ese fee o mas se confirmaron dentro del objetivo.
+    /// Si ni siquiera las de mayor fee lo cumplen devuelve el mayor fee observado, y sin muestras DEFAULT_FEE_RATE.
+    pub fn estimate_fee_rate(&self, target_blocks: u16) -> u64 {
+        let mut buckets: BTreeMap<u64, (usize, usize)> = BTreeMap::new();
+        for sample in &self.samples {
+            let (total, within_target) = buckets.entry(sample.fee_rate).or_insert((0, 0));
+            *total += 1;
+            if sample.blocks <= target_blocks as usize {
+                *within_target += 1;
+            }
+        }
+        let Some(highest) = buckets.keys().next_back() else { return DEFAULT_FEE_RATE };
+
+        let mut estimate = *highest;
+        let mut total = 0;
+        let mut within_target = 0;
+        for (fee_rate, (bucket_total, bucket_within_target)) in buckets.iter().rev() {
+            total += bucket_total;
+            within_target += bucket_within_target;
+            if within_target * 100 < total * SUCCESS_PERCENT {
+                break;
+            }
+            estimate = *fee_rate;
+        }
+        estimate.max(DEFAULT_FEE_RATE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        messages::transaction::Transaction,
+        structs::{block_header::BlockHeader, tx_output::TransactionOutput},
+    };
+
+    use super::*;
+
+    fn transaction(id: u64) -> Transaction {
+        Transaction {
+            version: 1,
+            inputs: vec![],
+            outputs: vec![TransactionOutput {
+                value: id,
+                script_pubkey: vec![],
+            }],
+            lock_time: 0,
+        }
+    }
+
+    fn block(transactions: Vec<Transaction>) -> Block {
+        let header = BlockHeader {
+            version: 1,
+            prev_block_hash: vec![],
+            merkle_root: vec![],
+            timestamp: 0,
+            bits: 0,
+            nonce: 0,
+            hash: vec![],
+            block_downloaded: true,
+            broadcasted: true,
+        };
+        Block::new(header, transactions)
+    }
+
+    /// Simula un bloque en el que entran las transacciones con fee por byte de al menos min_rate,
+    /// recibidas en el bloque anterior a height: cada una tarda lo que espero desde que se recibio.
+    fn mine(
+        estimator: &mut FeeEstimator,
+        pending: &mut Vec<(Transaction, u64)>,
+        height: usize,
+        min_rate: u64,
+    ) {
+        let (confirmed, waiting): (Vec<_>, Vec<_>) =
+            pending.drain(..).partition(|(_, rate)| *rate >= min_rate);
+        *pending = waiting;
+        estimator.confirm_block(
+            &block(confirmed.into_iter().map(|(tx, _)| tx).collect()),
+            height,
+        );
+    }
+
+    #[test]
+    fn estimate_without_samples_is_the_default() {
+        let estimator = FeeEstimator::new(MAX_FEE_SAMPLES);
+        assert_eq!(
+            estimator.estimate_fee_rate(FAST_TARGET_BLOCKS),
+            DEFAULT_FEE_RATE
+        );
+    }
+
+    #[test]
+    fn estimate_responds_to_congestion() {
+        let mut estimator = FeeEstimator::new(100);
+        let mut pending = vec![];
+        let mut id = 0;
+
+        // sin congestion todas las transacciones entran en el bloque siguiente
+        for height in 1..=10 {
+            for rate in 1..=10 {
+                id += 1;
+                let tx = transaction(id);
+                estimator.track(tx.hash(), rate, height - 1);
+                pending.push((tx, rate));
+            }
+            mine(&mut estimator, &mut pending, height, 0);
+        }
+        assert_eq!(estimator.estimate_fee_rate(FAST_TARGET_BLOCKS), 1);
+
+        // con congestion solo entran las de 8 sat/vB o mas, el resto espera hasta que cada 5 bloques entran todas
+        for height in 11..=40 {
+            for rate in 1..=10 {
+                id += 1;
+                let tx = transaction(id);
+                estimator.track(tx.hash(), rate, height - 1);
+                pending.push((tx, rate));
+            }
+            let min_rate = if height % 5 == 0 { 0 } else { 8 };
+            mine(&mut estimator, &mut pending, height, min_rate);
+        }
+        assert_eq!(estimator.samples(), 100);
+        assert_eq!(estimator.estimate_fee_rate(FAST_TARGET_BLOCKS), 8);
+        assert!(estimator.estimate_fee_rate(SLOW_TARGET_BLOCKS) < 8);
+
+        // las transacciones descartadas no generan muestras
+        let tx = transaction(id + 1);
+        estimator.track(tx.hash(), 100, 40);
+        estimator.forget(&tx.hash());
+        estimator.confirm_block(&block(vec![tx]), 41);
+        assert_eq!(estimator.estimate_fee_rate(FAST_TARGET_BLOCKS), 8);
+    }
+}
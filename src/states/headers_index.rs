@@ -0,0 +1,471 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fs,
+    io::{Read, Seek, SeekFrom},
+};
+
+use crate::{
+    error::CustomError,
+    structs::block_header::BlockHeader,
+    utils::{open_file, write_file_atomic, OpenMode},
+};
+
+/// Cantidad de headers mas recientes que se buscan en memoria (los de los bloques que se descargan y se anuncian).
+pub const HOT_HEADERS: usize = 500_000;
+
+/// El archivo del indice de los headers viejos comienza con COLD_INDEX_MAGIC, la cantidad de headers que incluye
+/// y el hash del ultimo de ellos, para detectar si quedo desactualizado respecto del archivo de headers.
+const COLD_INDEX_MAGIC: [u8; 4] = *b"HIDX";
+const COLD_INDEX_PREFIX_BYTES: usize = 4 + 4 + HASH_BYTES;
+const COLD_ENTRY_BYTES: usize = HASH_BYTES + 4;
+const HASH_BYTES: usize = 32;
+
+/// HeadersIndex es el indice por hash de los headers del nodo: devuelve la posicion de un header en el vector de
+/// headers sin recorrerlo.
+/// Los hot_capacity headers mas recientes se indexan en memoria por los primeros 8 bytes de su hash (un u64), que
+/// apuntan a su posicion; como el hash completo ya esta en el header, se compara contra el del vector en lugar de
+/// guardarlo otra vez. Los prefijos repetidos, que son muy poco probables, se guardan aparte en collisions.
+/// Los headers anteriores se buscan con una busqueda binaria sobre un archivo de pares (hash, posicion) ordenados
+/// por hash, que se reescribe cada vez que se acumulan hot_capacity / 5 headers por encima de hot_capacity.
+/// Para no leer el archivo por cada hash que no se tiene (por ejemplo, al recibir un anuncio) se guardan en memoria
+/// los primeros 4 bytes de sus hashes, ordenados.
+/// Los elementos son:
+/// - path: Path del archivo con el indice de los headers viejos.
+/// - hot_capacity: Cantidad de headers mas recientes que se indexan en memoria.
+/// - cold_len: Cantidad de headers del principio del vector que se buscan en el archivo.
+/// - cold_keys: Primeros 4 bytes de los hashes de los headers del archivo, ordenados.
+/// - hot: Posicion de cada header reciente segun los primeros 8 bytes de su hash.
+/// - collisions: Posiciones de los headers recientes cuyo prefijo ya estaba en hot.
+pub struct HeadersIndex {
+    path: String,
+    hot_capacity: usize,
+    cold_len: usize,
+    cold_keys: Vec<u32>,
+    hot: HashMap<u64, u32>,
+    collisions: HashMap<u64, Vec<u32>>,
+}
+
+impl HeadersIndex {
+    /// Crea un indice vacio que guarda los headers viejos en path y busca en memoria los ultimos hot_capacity.
+    pub fn new(path: String, hot_capacity: usize) -> Self {
+        Self {
+            path,
+            hot_capacity: hot_capacity.max(1),
+            cold_len: 0,
+            cold_keys: vec![],
+            hot: HashMap::new(),
+            collisions: HashMap::new(),
+        }
+    }
+
+    /// Arma el indice de los headers restaurados. Si el archivo de los headers viejos corresponde a estos headers
+    /// se reutiliza, sino se vuelve a generar. Los headers recientes se indexan en memoria.
+    pub fn restore(&mut self, headers: &[BlockHeader]) -> Result<(), CustomError> {
+        self.cold_len = self.read_cold_len(headers).unwrap_or(0);
+        let cold_len = self.cold_target(headers.len());
+        if cold_len != self.cold_len || cold_len == 0 {
+            self.write_cold(headers, cold_len)?;
+        }
+        self.cold_keys = sorted_cold_keys(&headers[..self.cold_len]);
+
+        self.hot.clear();
+        self.collisions.clear();
+        for (index, header) in headers.iter().enumerate().skip(self.cold_len) {
+            self.insert(index, header.hash());
+        }
+        Ok(())
+    }
+
+    /// Agrega al indice el header que se encuentra en la posicion index.
+    pub fn insert(&mut self, index: usize, hash: &[u8]) {
+        let key = hot_key(hash);
+        let index = index as u32;
+        match self.hot.get(&key) {
+            Some(_) => self.collisions.entry(key).or_default().push(index),
+            None => {
+                self.hot.insert(key, index);
+            }
+        }
+    }
+
+    /// Quita del indice los headers posteriores a los primeros keep, antes de eliminarlos del vector de headers.
+    /// Si alguno de ellos estaba en el archivo de los headers viejos (una reorganizacion de mas de hot_capacity
+    /// headers), el indice se vuelve a generar con los primeros keep.
+    pub fn truncate(&mut self, headers: &[BlockHeader], keep: usize) -> Result<(), CustomError> {
+        if keep < self.cold_len {
+            return self.restore(&headers[..keep]);
+        }
+        for (index, header) in headers.iter().enumerate().skip(keep) {
+            self.remove(index, header.hash());
+        }
+        Ok(())
+    }
+
+    /// Pasa al archivo de los headers viejos los que quedaron fuera de los ultimos hot_capacity, si ya se acumularon
+    /// suficientes para justificar reescribirlo.
+    pub fn compact(&mut self, headers: &[BlockHeader]) -> Result<(), CustomError> {
+        let cold_len = self.cold_target(headers.len());
+        if cold_len <= self.cold_len {
+            return Ok(());
+        }
+
+        for (index, header) in headers
+            .iter()
+            .enumerate()
+            .take(cold_len)
+            .skip(self.cold_len)
+        {
+            self.remove(index, header.hash());
+        }
+        self.write_cold(headers, cold_len)?;
+        self.cold_keys = sorted_cold_keys(&headers[..cold_len]);
+        self.hot.shrink_to_fit();
+        Ok(())
+    }
+
+    /// Devuelve la posicion del header con ese hash en headers, o None si no esta.
+    /// Los headers recientes se buscan en memoria y los viejos en el archivo, solo si el prefijo de su hash esta entre
+    /// los de los headers viejos.
+    pub fn position(
+        &self,
+        headers: &[BlockHeader],
+        hash: &[u8],
+    ) -> Result<Option<usize>, CustomError> {
+        let key = hot_key(hash);
+        let candidates = self
+            .hot
+            .get(&key)
+            .into_iter()
+            .chain(self.collisions.get(&key).into_iter().flatten());
+        for index in candidates {
+            let index = *index as usize;
+            if is_at(headers, index, hash) {
+                return Ok(Some(index));
+            }
+        }
+
+        if self.cold_keys.binary_search(&cold_key(hash)).is_err() {
+            return Ok(None);
+        }
+        let index = self.cold_position(hash)?;
+        Ok(index.filter(|index| is_at(headers, *index, hash)))
+    }
+
+    /// Devuelve la cantidad de headers que se buscan en memoria.
+    pub fn hot_len(&self) -> usize {
+        self.hot.len() + self.collisions.values().map(Vec::len).sum::<usize>()
+    }
+
+    /// Devuelve la cantidad de headers que se buscan en el archivo.
+    pub fn cold_len(&self) -> usize {
+        self.cold_len
+    }
+
+    /// Quita del indice en memoria el header que se encuentra en la posicion index.
+    fn remove(&mut self, index: usize, hash: &[u8]) {
+        let key = hot_key(hash);
+        let index = index as u32;
+        if self.hot.get(&key) == Some(&index) {
+            match self.collisions.get_mut(&key).and_then(Vec::pop) {
+                Some(other) => self.hot.insert(key, other),
+                None => self.hot.remove(&key),
+            };
+        } else if let Some(others) = self.collisions.get_mut(&key) {
+            others.retain(|other| *other != index);
+        }
+        if self.collisions.get(&key).is_some_and(Vec::is_empty) {
+            self.collisions.remove(&key);
+        }
+    }
+
+    /// Devuelve cuantos headers del principio deberian estar en el archivo para len headers: todos menos los ultimos
+    /// hot_capacity, pero solo cuando hay al menos hot_capacity / 5 de mas, para no reescribirlo en cada agregado.
+    fn cold_target(&self, len: usize) -> usize {
+        let threshold = self.hot_capacity + self.hot_capacity / 5;
+        match len - self.cold_len.min(len) > threshold {
+            true => len - self.hot_capacity,
+            false => self.cold_len.min(len),
+        }
+    }
+
+    /// Devuelve cuantos headers incluye el archivo de los headers viejos si corresponde a estos headers.
+    fn read_cold_len(&self, headers: &[BlockHeader]) -> Option<usize> {
+        let mut file = open_file(&self.path, OpenMode::Read).ok()?;
+        let mut prefix = [0; COLD_INDEX_PREFIX_BYTES];
+        file.read_exact(&mut prefix).ok()?;
+        if prefix[..4] != COLD_INDEX_MAGIC {
+            return None;
+        }
+
+        let cold_len = u32::from_le_bytes(prefix[4..8].try_into().ok()?) as usize;
+        let last_header = headers.get(cold_len.checked_sub(1)?)?;
+        let size = file.metadata().ok()?.len() as usize;
+        match padded_hash(last_header.hash()) == prefix[8..]
+            && size == COLD_INDEX_PREFIX_BYTES + cold_len * COLD_ENTRY_BYTES
+        {
+            true => Some(cold_len),
+            false => None,
+        }
+    }
+
+    /// Reescribe el archivo de los headers viejos con los primeros cold_len headers, ordenados por hash.
+    /// Si no hay headers viejos, se elimina.
+    fn write_cold(&mut self, headers: &[BlockHeader], cold_len: usize) -> Result<(), CustomError> {
+        self.cold_len = cold_len;
+        let Some(last_header) = headers[..cold_len].last() else {
+            let _ = fs::remove_file(&self.path);
+            return Ok(());
+        };
+
+        let mut entries: Vec<([u8; HASH_BYTES], u32)> = headers[..cold_len]
+            .iter()
+            .enumerate()
+            .map(|(index, header)| (padded_hash(header.hash()), index as u32))
+            .collect();
+        entries.sort_unstable();
+
+        let mut buffer = Vec::with_capacity(COLD_INDEX_PREFIX_BYTES + cold_len * COLD_ENTRY_BYTES);
+        buffer.extend(COLD_INDEX_MAGIC);
+        buffer.extend((cold_len as u32).to_le_bytes());
+        buffer.extend(padded_hash(last_header.hash()));
+        for (hash, index) in entries {
+            buffer.extend(hash);
+            buffer.extend(index.to_le_bytes());
+        }
+        write_file_atomic(&self.path, &buffer)
+    }
+
+    /// Busca el hash en el archivo de los headers viejos con una busqueda binaria.
+    fn cold_position(&self, hash: &[u8]) -> Result<Option<usize>, CustomError> {
+        let hash = padded_hash(hash);
+        let mut file = open_file(&self.path, OpenMode::Read)?;
+        let mut entry = [0; COLD_ENTRY_BYTES];
+
+        let (mut low, mut high) = (0, self.cold_len);
+        while low < high {
+            let middle = (low + high) / 2;
+            file.seek(SeekFrom::Start(
+                (COLD_INDEX_PREFIX_BYTES + middle * COLD_ENTRY_BYTES) as u64,
+            ))?;
+            file.read_exact(&mut entry)?;
+            match entry[..HASH_BYTES].cmp(&hash) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => {
+                    let index = u32::from_le_bytes([entry[32], entry[33], entry[34], entry[35]]);
+                    return Ok(Some(index as usize));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Devuelve true si el header de la posicion index tiene ese hash.
+fn is_at(headers: &[BlockHeader], index: usize, hash: &[u8]) -> bool {
+    headers
+        .get(index)
+        .is_some_and(|header| header.hash() == hash)
+}
+
+/// Devuelve el hash completado con ceros hasta HASH_BYTES, como se guarda en el archivo.
+fn padded_hash(hash: &[u8]) -> [u8; HASH_BYTES] {
+    let mut padded = [0; HASH_BYTES];
+    let len = hash.len().min(HASH_BYTES);
+    padded[..len].copy_from_slice(&hash[..len]);
+    padded
+}
+
+/// Devuelve los primeros 8 bytes del hash, con los que se indexan los headers recientes.
+fn hot_key(hash: &[u8]) -> u64 {
+    let padded = padded_hash(hash);
+    u64::from_le_bytes([
+        padded[0], padded[1], padded[2], padded[3], padded[4], padded[5], padded[6], padded[7],
+    ])
+}
+
+/// Devuelve los primeros 4 bytes del hash, con los que se descartan los hashes que no estan en el archivo.
+fn cold_key(hash: &[u8]) -> u32 {
+    let padded = padded_hash(hash);
+    u32::from_be_bytes([padded[0], padded[1], padded[2], padded[3]])
+}
+
+/// Devuelve los primeros 4 bytes de los hashes de los headers, ordenados.
+fn sorted_cold_keys(headers: &[BlockHeader]) -> Vec<u32> {
+    let mut keys: Vec<u32> = headers
+        .iter()
+        .map(|header| cold_key(header.hash()))
+        .collect();
+    keys.sort_unstable();
+    keys.shrink_to_fit();
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin_hashes::{sha256d, Hash};
+
+    use super::*;
+
+    fn test_headers(count: usize) -> Vec<BlockHeader> {
+        (0..count)
+            .map(|index| BlockHeader {
+                version: 1,
+                prev_block_hash: vec![],
+                merkle_root: vec![],
+                timestamp: index as u32,
+                bits: 0,
+                nonce: 0,
+                hash: test_hash(index),
+                broadcasted: false,
+                block_downloaded: false,
+            })
+            .collect()
+    }
+
+    fn test_hash(index: usize) -> Vec<u8> {
+        sha256d::Hash::hash(&index.to_le_bytes())
+            .to_byte_array()
+            .to_vec()
+    }
+
+    fn assert_all_found(index: &HeadersIndex, headers: &[BlockHeader]) {
+        for (position, header) in headers.iter().enumerate() {
+            assert_eq!(
+                index.position(headers, header.hash()).unwrap(),
+                Some(position)
+            );
+        }
+    }
+
+    #[test]
+    fn every_header_is_found_in_memory_and_on_disk() {
+        let path = "tests/test_headers_index_found.bin";
+        let headers = test_headers(1000);
+        let mut index = HeadersIndex::new(path.to_string(), 100);
+        index.restore(&headers).unwrap();
+
+        assert_eq!(index.cold_len(), 900);
+        assert_eq!(index.hot_len(), 100);
+        assert_all_found(&index, &headers);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn absent_hashes_are_not_found() {
+        let path = "tests/test_headers_index_absent.bin";
+        let headers = test_headers(1000);
+        let mut index = HeadersIndex::new(path.to_string(), 100);
+        index.restore(&headers).unwrap();
+
+        for absent in 1000..2000 {
+            assert_eq!(index.position(&headers, &test_hash(absent)).unwrap(), None);
+        }
+        assert_eq!(index.position(&headers, &[0; 32]).unwrap(), None);
+        assert_eq!(index.position(&headers, &[1, 2, 3]).unwrap(), None);
+        // el hash de un header viejo que ya no esta en el vector
+        assert_eq!(
+            index
+                .position(&headers[..500], headers[700].hash())
+                .unwrap(),
+            None
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn headers_are_moved_to_disk_as_they_are_added() {
+        let path = "tests/test_headers_index_compact.bin";
+        let _ = fs::remove_file(path);
+        let headers = test_headers(500);
+        let mut index = HeadersIndex::new(path.to_string(), 100);
+        index.restore(&headers[..0]).unwrap();
+
+        for (position, header) in headers.iter().enumerate() {
+            index.insert(position, header.hash());
+            index.compact(&headers[..=position]).unwrap();
+            assert!(index.hot_len() <= 120);
+        }
+        assert_eq!(index.cold_len() + index.hot_len(), 500);
+        assert_all_found(&index, &headers);
+
+        // al reiniciar se reutiliza el archivo
+        let mut restored = HeadersIndex::new(path.to_string(), 100);
+        restored.restore(&headers).unwrap();
+        assert_eq!(restored.cold_len(), index.cold_len());
+        assert_all_found(&restored, &headers);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn outdated_file_is_rebuilt() {
+        let path = "tests/test_headers_index_outdated.bin";
+        let headers = test_headers(1000);
+        let mut index = HeadersIndex::new(path.to_string(), 100);
+        index.restore(&headers).unwrap();
+
+        // el archivo de headers se corto antes del ultimo header del indice
+        let mut restored = HeadersIndex::new(path.to_string(), 100);
+        restored.restore(&headers[..850]).unwrap();
+        assert_eq!(restored.cold_len(), 750);
+        assert_all_found(&restored, &headers[..850]);
+        assert_eq!(
+            restored
+                .position(&headers[..850], headers[900].hash())
+                .unwrap(),
+            None
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn truncate_removes_disconnected_headers() {
+        let path = "tests/test_headers_index_truncate.bin";
+        let mut headers = test_headers(1000);
+        let mut index = HeadersIndex::new(path.to_string(), 100);
+        index.restore(&headers).unwrap();
+
+        index.truncate(&headers, 950).unwrap();
+        let disconnected = headers.split_off(950);
+        assert_eq!(index.hot_len(), 50);
+        assert_all_found(&index, &headers);
+        for header in &disconnected {
+            assert_eq!(index.position(&headers, header.hash()).unwrap(), None);
+        }
+
+        // una reorganizacion que llega a los headers del archivo
+        index.truncate(&headers, 100).unwrap();
+        headers.truncate(100);
+        assert_eq!(index.cold_len(), 0);
+        assert_all_found(&index, &headers);
+
+        assert!(fs::metadata(path).is_err());
+    }
+
+    #[test]
+    fn repeated_prefixes_are_kept_apart() {
+        let mut headers = test_headers(3);
+        headers[1].hash = headers[0].hash.clone();
+        headers[1].hash[31] ^= 1;
+        headers[2].hash = headers[0].hash.clone();
+        headers[2].hash[20] ^= 1;
+        let mut index = HeadersIndex::new("tests/test_headers_index_prefixes.bin".to_string(), 100);
+        for (position, header) in headers.iter().enumerate() {
+            index.insert(position, header.hash());
+        }
+        assert_eq!(index.hot.len(), 1);
+        assert_all_found(&index, &headers);
+
+        index.truncate(&headers, 1).unwrap();
+        assert_eq!(index.hot_len(), 1);
+        assert_all_found(&index, &headers[..1]);
+        index.truncate(&headers, 0).unwrap();
+        assert_eq!(index.hot_len(), 0);
+        assert!(index.collisions.is_empty());
+    }
+}
@@ -1,22 +1,70 @@
 use std::{
-    io::{Read, Write},
+    collections::HashMap,
+    io::{Read, Seek, SeekFrom, Write},
+    ops::Range,
     sync::mpsc::Sender,
 };
 
+use gtk::glib;
+
 use crate::{
     error::CustomError,
+    gui::init::GUIEvents,
     logger::{send_log, Log},
-    messages::get_headers::GetHeaders,
+    messages::{
+        get_blocks::GetBlocks,
+        get_headers::GetHeaders,
+        headers::{serialize_header_entry, HEADER_ENTRY_BYTES},
+    },
+    mining::REGTEST_BITS,
     parser::BufferParser,
-    peer::GENESIS,
-    structs::block_header::BlockHeader,
+    peer::{GENESIS, GENESIS_TIMESTAMP},
+    structs::block_header::{difficulty_from_bits, retarget_bits, BlockHeader, MAX_TARGET_BITS},
     utils::{
         calculate_index_from_timestamp, get_current_timestamp, get_current_timestamp_millis,
-        open_new_file,
+        open_file, write_file_atomic, OpenMode,
     },
 };
 
-use super::utxo_state::START_DATE_IBD;
+use super::{
+    headers_index::{HeadersIndex, HOT_HEADERS},
+    utxo_state::START_DATE_IBD,
+};
+
+/// Los archivos de headers v2 comienzan con HEADERS_BACKUP_MAGIC seguido del byte de version.
+/// Los archivos v1 no tienen prefijo y guardan cada header completo en HEADER_BACKUP_V1_BYTES bytes.
+const HEADERS_BACKUP_MAGIC: [u8; 4] = *b"HDRS";
+const HEADERS_BACKUP_V2: u8 = 2;
+const HEADER_BACKUP_V1_BYTES: usize = 112;
+
+/// Cantidad maxima de headers que se envian en un mensaje headers.
+/// Tambien es el tamaño de los segmentos del cache de headers serializados.
+const HEADERS_SEGMENT_SIZE: usize = 2000;
+
+/// Cantidad maxima de hashes de bloques que se envian en respuesta a un mensaje getblocks.
+pub const MAX_GETBLOCKS_HASHES: usize = 500;
+
+/// Cantidad de bloques entre cada ajuste de la dificultad.
+pub const RETARGET_INTERVAL: usize = 2016;
+
+/// Segundos esperados entre bloques.
+pub const TARGET_BLOCK_SPACING: u32 = 600;
+
+#[derive(Debug, Clone, PartialEq)]
+/// DifficultyInfo es la dificultad actual de la cadena y la estimacion del proximo ajuste.
+/// Los elementos son:
+/// - height: Height del ultimo header.
+/// - difficulty: Dificultad vigente de la cadena (ver HeadersState::last_non_min_difficulty_bits).
+/// - blocks_since_retarget: Bloques desde el ultimo ajuste (height mod RETARGET_INTERVAL).
+/// - blocks_until_retarget: Bloques que faltan para el proximo ajuste.
+/// - estimated_retarget: Timestamp estimado del proximo ajuste.
+pub struct DifficultyInfo {
+    pub height: usize,
+    pub difficulty: f64,
+    pub blocks_since_retarget: usize,
+    pub blocks_until_retarget: usize,
+    pub estimated_retarget: u32,
+}
 
 /// HeaderIBDStats es una estructura que contiene los elementos necesarios para manejar las
 /// estadisticas de la descarga masiva de headers.
@@ -36,28 +84,47 @@ struct HeaderIBDStats {
 /// Los elementos son:
 /// - headers: Headers del nodo.
 /// - logger_sender: Sender para enviar logs al logger.
+/// - gui_sender: Sender para enviar el progreso de la descarga de headers a la interfaz grafica.
 /// - path: Path del archivo donde se guardan los headers.
 /// - ibd_stats: Option<HeaderIBDStats> solamente se inicializa cuando corresponde.
 /// - sync: Indica si los headers del nodo estan sincronizados con la red.
+/// - legacy_backup: Indica si el archivo de headers esta en formato v1 y debe reescribirse completo en v2.
+/// - wire_segments: Cache de los headers serializados en formato de red, por segmento completo de HEADERS_SEGMENT_SIZE headers.
+/// - index: Indice por hash de los headers (ver HeadersIndex), que se guarda junto al archivo de headers.
+/// - regtest: Indica si el nodo corre en una red regtest, donde todos los headers tienen la dificultad REGTEST_BITS.
 pub struct HeadersState {
     headers: Vec<BlockHeader>,
     logger_sender: Sender<Log>,
+    gui_sender: glib::Sender<GUIEvents>,
     path: String,
     ibd_stats: Option<HeaderIBDStats>,
     sync: bool,
+    legacy_backup: bool,
+    wire_segments: HashMap<usize, Vec<u8>>,
+    index: HeadersIndex,
+    regtest: bool,
 }
 
 impl HeadersState {
     /// Inicializa los headers del nodo.
     /// Si el archivo donde se guardan los headers no existe, se crea.
     /// Si el archivo existe, se restauran los headers.
-    pub fn new(path: String, logger_sender: Sender<Log>) -> Result<Self, CustomError> {
+    pub fn new(
+        path: String,
+        logger_sender: Sender<Log>,
+        gui_sender: glib::Sender<GUIEvents>,
+    ) -> Result<Self, CustomError> {
         let mut headers = Self {
             headers: Vec::new(),
             logger_sender: logger_sender.clone(),
+            gui_sender,
+            index: HeadersIndex::new(format!("{path}.index"), HOT_HEADERS),
             path,
             ibd_stats: None,
             sync: false,
+            legacy_backup: false,
+            wire_segments: HashMap::new(),
+            regtest: false,
         };
 
         headers.restore()?;
@@ -69,33 +136,106 @@ impl HeadersState {
         Ok(headers)
     }
 
+    /// Restaura los headers del archivo, detectando si esta en formato v1 o v2.
+    /// Si el archivo termina en un header incompleto (el proceso se corto mientras se guardaba) o en uno roto,
+    /// se conservan los headers anteriores y el archivo se corta despues del ultimo valido, avisando en el log.
+    /// Una vez restaurados se arma su indice por hash.
     fn restore(&mut self) -> Result<(), CustomError> {
         let mut buffer = vec![];
-        let mut file = open_new_file(self.path.clone(), true)?;
+        let mut file = open_file(&self.path, OpenMode::ReadCreate)?;
         file.read_to_end(&mut buffer)?;
+        let size = buffer.len();
+
+        let valid_size = if buffer.starts_with(&HEADERS_BACKUP_MAGIC) {
+            self.restore_v2(buffer)?
+        } else {
+            self.restore_v1(buffer)
+        };
+
+        if valid_size < size {
+            file.set_len(valid_size as u64)?;
+            send_log(
+                &self.logger_sender,
+                Log::Warning(format!(
+                    "discarded the last {} bytes of the headers file, they were incomplete or corrupted (kept {} headers)",
+                    size - valid_size,
+                    self.headers.len()
+                )),
+            );
+        }
+        self.index.restore(&self.headers)
+    }
+
+    /// Restaura los headers de un archivo v1 hasta el primer registro incompleto, que no se puede leer
+    /// o cuyo prev_block_hash no es el hash del header anterior. Devuelve el tamaño de los registros validos.
+    fn restore_v1(&mut self, buffer: Vec<u8>) -> usize {
+        for record in buffer.chunks_exact(HEADER_BACKUP_V1_BYTES) {
+            let Ok(header) = BlockHeader::parse_from_backup(record.to_vec()) else { break };
+            let expected_prev_hash = self
+                .headers
+                .last()
+                .map(|last_header| last_header.hash().clone())
+                .unwrap_or(GENESIS.to_vec());
+            if header.prev_block_hash != expected_prev_hash {
+                break;
+            }
+            self.headers.push(header);
+        }
+
+        self.legacy_backup = !self.headers.is_empty();
+        self.headers.len() * HEADER_BACKUP_V1_BYTES
+    }
 
+    /// Restaura los headers de un archivo v2 hasta el primer registro que no se puede leer.
+    /// En v2 el prev_block_hash no se guarda sino que es el hash del header anterior, asi que la cadena
+    /// siempre es consistente, y un registro roto se detecta al verificar su proof of work.
+    /// Devuelve el tamaño del archivo hasta el ultimo registro valido.
+    fn restore_v2(&mut self, buffer: Vec<u8>) -> Result<usize, CustomError> {
+        let size = buffer.len();
         let mut parser = BufferParser::new(buffer);
-        if parser.len() % 112 != 0 {
+        parser.extract_buffer(HEADERS_BACKUP_MAGIC.len())?;
+        if parser.extract_u8()? != HEADERS_BACKUP_V2 {
             return Err(CustomError::SerializedBufferIsInvalid);
         }
 
+        let mut valid_size = size - parser.len();
         while !parser.is_empty() {
-            let header = BlockHeader::parse_from_backup(parser.extract_buffer(112)?.to_vec())?;
+            let Ok(header) = BlockHeader::parse_from_backup_v2(&mut parser, self.headers.last())
+            else {
+                break;
+            };
             self.headers.push(header);
+            valid_size = size - parser.len();
         }
 
-        Ok(())
+        Ok(valid_size)
     }
 
-    fn save(&self, headers: &Vec<BlockHeader>) -> Result<(), CustomError> {
-        let mut file = open_new_file(self.path.clone(), true)?;
-        let mut buffer = vec![];
-        for header in headers {
-            let header_buffer: Vec<u8> = header.serialize_for_backup();
-            buffer.extend(header_buffer);
+    /// Guarda los headers nuevos agregandolos al final del archivo.
+    /// Si el archivo todavia no tiene headers o esta en formato v1, se reescribe completo en v2.
+    fn save(&mut self, headers: &Vec<BlockHeader>) -> Result<(), CustomError> {
+        if self.legacy_backup || self.headers.is_empty() {
+            return self.save_all(headers);
         }
 
+        let mut file = open_file(&self.path, OpenMode::Append)?;
+        file.seek(SeekFrom::End(0))?;
+        let buffer = serialize_headers(self.headers.last(), headers.iter());
         file.write_all(buffer.as_slice())?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Reescribe el archivo completo en formato v2 con los headers del nodo seguidos de los nuevos.
+    /// Se escribe primero en un archivo temporal para no perder los headers si se corta la escritura (ver write_file_atomic).
+    fn save_all(&mut self, headers: &[BlockHeader]) -> Result<(), CustomError> {
+        let mut buffer = HEADERS_BACKUP_MAGIC.to_vec();
+        buffer.push(HEADERS_BACKUP_V2);
+        buffer.extend(serialize_headers(None, self.headers.iter().chain(headers)));
+
+        write_file_atomic(&self.path, &buffer)?;
+
+        self.legacy_backup = false;
         Ok(())
     }
 
@@ -108,6 +248,12 @@ impl HeadersState {
         self.len() - calculate_index_from_timestamp(&self.headers, START_DATE_IBD)
     }
 
+    /// Devuelve el porcentaje de headers descargados, calculado a partir del timestamp del ultimo header.
+    pub fn get_progress(&self) -> Result<u64, CustomError> {
+        let last_timestamp = self.headers.last().map(|h| h.timestamp).unwrap_or(0);
+        self.calculate_percentage_downloaded(last_timestamp)
+    }
+
     /// Devuelve todos los headers del nodo.
     pub fn get_all(&self) -> &Vec<BlockHeader> {
         &self.headers
@@ -116,17 +262,61 @@ impl HeadersState {
     /// Devuelve la posicion de un header en el vector de headers del nodo dado el hash del mismo.
     /// Si no se encuentra el header, devuelve 0.
     pub fn get_header_index(&self, block_hash: &Vec<u8>) -> usize {
-        let position_from_end = self
-            .headers
-            .iter()
-            .rev()
-            .position(|header| header.hash() == block_hash);
+        self.position(block_hash).unwrap_or(0)
+    }
+
+    /// Devuelve la posicion del header con ese hash en el vector de headers del nodo, buscandolo en el indice.
+    /// Si no se puede leer el archivo del indice, se avisa en el log y se busca recorriendo los headers.
+    fn position(&self, block_hash: &[u8]) -> Option<usize> {
+        match self.index.position(&self.headers, block_hash) {
+            Ok(position) => position,
+            Err(error) => {
+                send_log(
+                    &self.logger_sender,
+                    Log::Message(format!("Error reading the headers index: {error}")),
+                );
+                self.headers
+                    .iter()
+                    .rposition(|header| header.hash() == block_hash)
+            }
+        }
+    }
+
+    /// Agrega un header al final de los headers del nodo y a su indice.
+    fn push(&mut self, header: BlockHeader) {
+        self.index.insert(self.headers.len(), header.hash());
+        self.headers.push(header);
+    }
 
-        if let Some(position_from_end) = position_from_end {
-            return self.headers.len() - position_from_end - 1;
+    /// Devuelve la cantidad de confirmaciones del bloque con ese hash: 1 si es el ultimo header del nodo,
+    /// y una mas por cada header posterior. Si el bloque no esta en la cadena del nodo, devuelve 0.
+    pub fn get_confirmations(&self, block_hash: &[u8]) -> u32 {
+        self.position(block_hash)
+            .map_or(0, |position| (self.headers.len() - position) as u32)
+    }
+
+    /// Devuelve el hash del bloque con ese height (0 es genesis), o None si el nodo todavia no lo tiene.
+    pub fn get_hash_at_height(&self, height: usize) -> Option<Vec<u8>> {
+        match height {
+            0 => Some(GENESIS.to_vec()),
+            _ => self
+                .headers
+                .get(height - 1)
+                .map(|header| header.hash().clone()),
         }
+    }
 
-        0
+    /// Devuelve el height del bloque con ese hash (0 para genesis), o None si no esta en la cadena del nodo.
+    pub fn get_height(&self, block_hash: &[u8]) -> Option<usize> {
+        if block_hash == GENESIS {
+            return Some(0);
+        }
+        self.position(block_hash).map(|index| index + 1)
+    }
+
+    /// Devuelve el header del bloque con ese height, o None si es genesis o el nodo todavia no lo tiene.
+    pub fn get_header_at_height(&self, height: usize) -> Option<&BlockHeader> {
+        self.headers.get(height.checked_sub(1)?)
     }
 
     /// Devuelve el hash del ultimo header del nodo.
@@ -134,6 +324,48 @@ impl HeadersState {
         self.headers.last().map(|header| header.hash().clone())
     }
 
+    /// Devuelve los bits de la dificultad vigente en ese height, como GetNextWorkRequired de Bitcoin Core:
+    /// en testnet un bloque que llega mas de 20 minutos despues del anterior puede tener la dificultad minima
+    /// (MAX_TARGET_BITS), por lo que se recorren hacia atras esos headers hasta uno con otra dificultad
+    /// o hasta el header del ultimo ajuste. Devuelve None si el nodo no tiene el header de ese height.
+    pub fn last_non_min_difficulty_bits(&self, height: usize) -> Option<u32> {
+        non_min_difficulty_bits(|height| self.get_header_at_height(height), height)
+    }
+
+    /// Cambia si el nodo corre en una red regtest (ver regtest).
+    pub fn set_regtest(&mut self, regtest: bool) {
+        self.regtest = regtest;
+    }
+
+    /// Devuelve la dificultad actual y la estimacion del proximo ajuste, o None si el nodo no tiene headers.
+    /// La dificultad no es la del ultimo header si este tiene la dificultad minima de testnet (ver last_non_min_difficulty_bits).
+    /// El proximo ajuste se estima con el tiempo promedio entre los bloques desde el ultimo ajuste, segun sus timestamps.
+    /// Si todavia no hay bloques desde el ultimo ajuste (o fue genesis) se usa TARGET_BLOCK_SPACING.
+    pub fn get_difficulty_info(&self) -> Option<DifficultyInfo> {
+        let last = self.headers.last()?;
+        let height = self.headers.len();
+        let blocks_since_retarget = height % RETARGET_INTERVAL;
+        let blocks_until_retarget = RETARGET_INTERVAL - blocks_since_retarget;
+
+        let spacing = match self.get_header_at_height(height - blocks_since_retarget) {
+            Some(retarget) if blocks_since_retarget > 0 => {
+                last.timestamp.saturating_sub(retarget.timestamp) / blocks_since_retarget as u32
+            }
+            _ => TARGET_BLOCK_SPACING,
+        };
+        let estimated_retarget = last
+            .timestamp
+            .saturating_add(spacing.saturating_mul(blocks_until_retarget as u32));
+
+        Some(DifficultyInfo {
+            height,
+            difficulty: difficulty_from_bits(self.last_non_min_difficulty_bits(height)?),
+            blocks_since_retarget,
+            blocks_until_retarget,
+            estimated_retarget,
+        })
+    }
+
     /// Devuelve los ultimos count headers del nodo junto a su height.
     pub fn get_last_headers(&self, count: usize) -> Vec<(usize, BlockHeader)> {
         let mut last_headers = vec![];
@@ -149,33 +381,120 @@ impl HeadersState {
     }
 
     /// Agrega los headers al nodo y los almacena.
+    /// Antes de guardarlos verifica la proof of work y la dificultad de cada uno (ver verify_difficulty),
+    /// asi el backup nunca contiene headers invalidos.
+    /// Si el primer header no continua el ultimo del nodo sino uno anterior (reorg), se descartan los headers que ya se tienen
+    /// y, si la nueva rama es mas larga que la actual a partir del punto de fork, se eliminan los headers de la rama vieja
+    /// (en memoria y en el archivo) y se agrega la nueva. Si no es mas larga, la nueva rama se ignora.
+    /// Devuelve los hashes de los bloques desconectados, empezando por el ultimo.
     /// Tambien verifica si con los nuevos queda sincronizado con la red
-    pub fn append_headers(&mut self, mut headers: Vec<BlockHeader>) -> Result<(), CustomError> {
-        if let Some(first_header) = headers.first() {
-            let last_header = self.headers.last();
-            let last_header_hash = last_header
-                .map(|header| header.hash().clone())
-                .unwrap_or(GENESIS.to_vec());
+    pub fn append_headers(
+        &mut self,
+        mut headers: Vec<BlockHeader>,
+    ) -> Result<Vec<Vec<u8>>, CustomError> {
+        for header in &headers {
+            header.verify_proof_of_work()?;
+        }
+        let received_count = headers.len();
+        let mut disconnected = vec![];
 
-            if last_header_hash != first_header.prev_block_hash {
-                return Err(CustomError::BlockChainBroken);
+        if let Some(first_header) = headers.first() {
+            let fork_point = self.find_fork_point(&first_header.prev_block_hash)?;
+            let known_count = self.headers[fork_point..]
+                .iter()
+                .zip(&headers)
+                .take_while(|(known, header)| known.hash() == header.hash())
+                .count();
+            headers.drain(..known_count);
+
+            let keep = fork_point + known_count;
+            self.verify_difficulty(keep, &headers)?;
+            let stale_count = self.headers.len() - keep;
+            if stale_count > 0 {
+                if headers.len() > stale_count {
+                    disconnected = self.disconnect_headers(keep)?;
+                } else {
+                    headers.clear();
+                }
             }
+        }
 
+        if let Some(first_header) = headers.first() {
             let percentage = self.calculate_percentage_downloaded(first_header.timestamp)?;
             if self.ibd_stats.is_none() && percentage < 95_u64 {
                 self.start_stats_printing()?;
             }
         }
 
-        self.save(&headers)?;
+        if disconnected.is_empty() {
+            self.save(&headers)?;
+        } else {
+            self.save_all(&headers)?;
+        }
         let headers_count = headers.len();
-        self.headers.append(&mut headers);
+        for header in headers {
+            self.push(header);
+        }
+        self.index.compact(&self.headers)?;
 
         self.print_status(headers_count)?;
-        self.verify_headers_sync(headers_count)?;
+        self.verify_headers_sync(received_count)?;
+        Ok(disconnected)
+    }
+
+    /// Verifica que cada header tenga los bits de la dificultad que le corresponde en la cadena formada por los primeros
+    /// keep headers del nodo seguidos de headers (ver expected_bits). En regtest todos deben tener REGTEST_BITS.
+    /// Devuelve CustomError::UnexpectedDifficulty si alguno no los tiene.
+    fn verify_difficulty(&self, keep: usize, headers: &[BlockHeader]) -> Result<(), CustomError> {
+        let header_at = |height: usize| match height {
+            height if height <= keep => self.get_header_at_height(height),
+            height => headers.get(height - keep - 1),
+        };
+        for (offset, header) in headers.iter().enumerate() {
+            let expected = match self.regtest {
+                true => Some(REGTEST_BITS),
+                false => expected_bits(header_at, keep + offset + 1, header.timestamp),
+            };
+            if expected != Some(header.bits) {
+                return Err(CustomError::UnexpectedDifficulty);
+            }
+        }
         Ok(())
     }
 
+    /// Devuelve la cantidad de headers del nodo hasta el header prev_block_hash inclusive (0 si es el genesis).
+    /// Devuelve CustomError::BlockChainBroken si el nodo no tiene ese header.
+    fn find_fork_point(&self, prev_block_hash: &[u8]) -> Result<usize, CustomError> {
+        if prev_block_hash == GENESIS {
+            return Ok(0);
+        }
+
+        match self.position(prev_block_hash) {
+            Some(position) => Ok(position + 1),
+            None => Err(CustomError::BlockChainBroken),
+        }
+    }
+
+    /// Elimina de memoria los headers posteriores a los primeros keep, junto con su indice y los segmentos del cache
+    /// que los incluian. Devuelve los hashes eliminados, empezando por el ultimo.
+    fn disconnect_headers(&mut self, keep: usize) -> Result<Vec<Vec<u8>>, CustomError> {
+        self.wire_segments
+            .retain(|segment, _| (segment + 1) * HEADERS_SEGMENT_SIZE <= keep);
+        self.index.truncate(&self.headers, keep)?;
+
+        Ok(self
+            .headers
+            .drain(keep..)
+            .rev()
+            .map(|header| header.hash().clone())
+            .collect())
+    }
+
+    /// Devuelve true si el nodo tiene el header con ese hash en su cadena.
+    pub fn contains(&self, block_hash: &[u8]) -> bool {
+        self.position(block_hash).is_some()
+    }
+
     fn calculate_percentage_downloaded(&self, received_timestamp: u32) -> Result<u64, CustomError> {
         let first_timestamp = self
             .headers
@@ -219,6 +538,7 @@ impl HeadersState {
         Ok(())
     }
 
+    /// Cada vez que cambia el porcentaje descargado lo informa en el log y en la interfaz grafica (HeadersProgress).
     fn print_stats(&mut self, headers_count: usize) -> Result<(), CustomError> {
         let last_timestamp = self.headers.last().map(|h| h.timestamp).unwrap_or(0);
         let percentage = self.calculate_percentage_downloaded(last_timestamp)?;
@@ -244,6 +564,16 @@ impl HeadersState {
                         self.headers.len(),
                     )),
                 );
+                let progress = GUIEvents::HeadersProgress {
+                    percentage,
+                    rate: headers_per_second as usize,
+                };
+                if let Err(error) = self.gui_sender.send(progress) {
+                    send_log(
+                        &self.logger_sender,
+                        Log::Message(format!("Error sending headers progress to gui: {error}")),
+                    );
+                }
 
                 ibd_stats.checkpoint_downloads = 0;
                 ibd_stats.checkpoint_percentage = percentage;
@@ -256,14 +586,8 @@ impl HeadersState {
 
     /// Registra que un header tiene su bloque descargado.
     pub fn set_downloaded(&mut self, block_hash: &Vec<u8>) {
-        let downloaded_block = self
-            .headers
-            .iter_mut()
-            .rev()
-            .find(|header| header.hash() == block_hash);
-
-        if let Some(header) = downloaded_block {
-            header.block_downloaded = true;
+        if let Some(position) = self.position(block_hash) {
+            self.headers[position].block_downloaded = true;
         }
     }
 
@@ -313,78 +637,190 @@ impl HeadersState {
         self.sync
     }
 
-    /// Ante un mensaje get headers, devuelve los headers esperados de acuerdo al protocolo btc.
+    /// Ante un mensaje get headers, devuelve los headers esperados de acuerdo al protocolo btc (ver requested_range).
     pub fn get_headers(&self, get_headers: GetHeaders) -> Vec<BlockHeader> {
-        let peer_last_header = get_headers
-            .block_locator_hashes
-            .last()
-            .unwrap_or(&GENESIS.to_vec())
-            .clone();
-        if let Some(last_header) = self.headers.last() {
-            if peer_last_header == *last_header.hash() {
-                return vec![];
-            }
-        }
+        self.headers[self.requested_range(get_headers)].to_vec()
+    }
 
-        if peer_last_header == GENESIS.to_vec() {
-            return self.first_headers(get_headers.hash_stop);
+    /// Ante un mensaje get headers, devuelve los headers esperados ya serializados en formato de red (ver RawHeaders).
+    /// Los segmentos completos de headers no cambian una vez agregados, por lo que se serializan una unica vez
+    /// y las respuestas siguientes se arman copiando rangos de bytes.
+    pub fn get_serialized_headers(&mut self, get_headers: GetHeaders) -> Vec<u8> {
+        let range = self.requested_range(get_headers);
+        let mut entries = Vec::with_capacity(range.len() * HEADER_ENTRY_BYTES);
+
+        let mut index = range.start;
+        while index < range.end {
+            let segment = index / HEADERS_SEGMENT_SIZE;
+            let segment_start = segment * HEADERS_SEGMENT_SIZE;
+            let segment_end = segment_start + HEADERS_SEGMENT_SIZE;
+            let chunk_end = segment_end.min(range.end);
+
+            if segment_end <= self.headers.len() {
+                let headers = &self.headers;
+                let segment_bytes = self.wire_segments.entry(segment).or_insert_with(|| {
+                    serialize_wire_headers(&headers[segment_start..segment_end])
+                });
+                entries.extend_from_slice(
+                    &segment_bytes[(index - segment_start) * HEADER_ENTRY_BYTES
+                        ..(chunk_end - segment_start) * HEADER_ENTRY_BYTES],
+                );
+            } else {
+                entries.extend(serialize_wire_headers(&self.headers[index..chunk_end]));
+            }
+            index = chunk_end;
         }
 
-        self.get_requested_headers(peer_last_header, get_headers.hash_stop)
+        entries
     }
 
-    fn get_requested_headers(
-        &self,
-        peer_last_header: Vec<u8>,
-        hash_stop: Vec<u8>,
-    ) -> Vec<BlockHeader> {
-        let mut headers = vec![];
-        let mut found = false;
-        for header in &self.headers {
-            if header.prev_block_hash == peer_last_header {
-                found = true;
-            }
-            if found {
-                headers.push(header.clone());
-            }
-            if headers.len() == 2000 || *header.hash() == hash_stop {
+    /// Ante un mensaje getblocks, devuelve los hashes de hasta MAX_GETBLOCKS_HASHES bloques siguientes
+    /// al primer hash del block locator que pertenezca a nuestra cadena.
+    /// Si el block locator esta vacio o ninguno de sus hashes es conocido, se empieza desde genesis.
+    /// El hash_stop se incluye en la respuesta y corta la busqueda.
+    pub fn get_block_hashes(&self, get_blocks: &GetBlocks) -> Vec<Vec<u8>> {
+        let start = self.fork_point(&get_blocks.block_locator_hashes);
+
+        let mut block_hashes = vec![];
+        for header in self.headers[start..].iter().take(MAX_GETBLOCKS_HASHES) {
+            block_hashes.push(header.hash().clone());
+            if *header.hash() == get_blocks.hash_stop {
                 break;
             }
         }
+        block_hashes
+    }
 
-        if !found {
-            return self.first_headers(hash_stop);
-        }
-        headers
+    /// Devuelve el rango de headers con el que se responde un getheaders: hasta HEADERS_SEGMENT_SIZE headers siguientes
+    /// al punto de bifurcacion del block locator (ver fork_point). El hash_stop se incluye en la respuesta y la corta.
+    fn requested_range(&self, get_headers: GetHeaders) -> Range<usize> {
+        let start = self.fork_point(&get_headers.block_locator_hashes);
+        let end = self.headers[start..]
+            .iter()
+            .take(HEADERS_SEGMENT_SIZE)
+            .position(|header| *header.hash() == get_headers.hash_stop)
+            .map(|position| start + position + 1)
+            .unwrap_or((start + HEADERS_SEGMENT_SIZE).min(self.headers.len()));
+        start..end
     }
 
-    fn first_headers(&self, hash_stop: Vec<u8>) -> Vec<BlockHeader> {
-        self.headers
+    /// Devuelve el indice del primer header que le falta a un peer segun su block locator, que va del hash mas nuevo
+    /// al mas viejo: el siguiente al primer hash del locator que pertenezca a nuestra cadena.
+    /// Si el block locator esta vacio o ninguno de sus hashes es conocido, se empieza desde genesis.
+    fn fork_point(&self, block_locator_hashes: &[Vec<u8>]) -> usize {
+        block_locator_hashes
             .iter()
-            .take(2000)
-            .take_while(|block| block.hash != hash_stop)
-            .cloned()
-            .collect()
+            .find_map(|locator_hash| self.get_height(locator_hash))
+            .unwrap_or(0)
     }
 }
 
+/// Serializa los headers en el formato del mensaje headers, uno a continuacion del otro.
+fn serialize_wire_headers(headers: &[BlockHeader]) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(headers.len() * HEADER_ENTRY_BYTES);
+    for header in headers {
+        buffer.extend(serialize_header_entry(header));
+    }
+    buffer
+}
+
+/// Serializa los headers en formato v2, codificando cada uno como diferencia con el anterior.
+fn serialize_headers<'a>(
+    mut prev: Option<&'a BlockHeader>,
+    headers: impl Iterator<Item = &'a BlockHeader>,
+) -> Vec<u8> {
+    let mut buffer = vec![];
+    for header in headers {
+        buffer.extend(header.serialize_for_backup_v2(prev));
+        prev = Some(header);
+    }
+    buffer
+}
+
+/// Devuelve los bits que debe tener el header de height con ese timestamp, como GetNextWorkRequired de Bitcoin Core
+/// para testnet: cada RETARGET_INTERVAL bloques se ajusta la dificultad segun lo que tardaron los anteriores
+/// (ver retarget_bits), y entre ajustes se mantiene la vigente (ver non_min_difficulty_bits), salvo que el header
+/// llegue mas de 20 minutos despues del anterior, en cuyo caso tiene la dificultad minima.
+/// header_at devuelve el header de cada height de la cadena (None para genesis).
+/// Devuelve None si falta alguno de los headers anteriores.
+fn expected_bits<'a>(
+    header_at: impl Fn(usize) -> Option<&'a BlockHeader>,
+    height: usize,
+    timestamp: u32,
+) -> Option<u32> {
+    let prev_height = height.checked_sub(1)?;
+    let timestamp_at = |height: usize| match height {
+        0 => Some(GENESIS_TIMESTAMP),
+        height => header_at(height).map(|header| header.timestamp),
+    };
+    let prev_timestamp = timestamp_at(prev_height)?;
+
+    if height % RETARGET_INTERVAL == 0 {
+        let prev_bits = header_at(prev_height).map_or(MAX_TARGET_BITS, |header| header.bits);
+        let first_timestamp = timestamp_at(height - RETARGET_INTERVAL)?;
+        let timespan = prev_timestamp as i64 - first_timestamp as i64;
+        return Some(retarget_bits(
+            prev_bits,
+            timespan,
+            RETARGET_INTERVAL as u32 * TARGET_BLOCK_SPACING,
+        ));
+    }
+    if timestamp as u64 > prev_timestamp as u64 + 2 * TARGET_BLOCK_SPACING as u64 {
+        return Some(MAX_TARGET_BITS);
+    }
+    match prev_height {
+        0 => Some(MAX_TARGET_BITS),
+        prev_height => non_min_difficulty_bits(header_at, prev_height),
+    }
+}
+
+/// Devuelve los bits de la dificultad vigente en height recorriendo hacia atras los headers con la dificultad minima
+/// (ver HeadersState::last_non_min_difficulty_bits). header_at devuelve el header de cada height (None para genesis).
+fn non_min_difficulty_bits<'a>(
+    header_at: impl Fn(usize) -> Option<&'a BlockHeader>,
+    height: usize,
+) -> Option<u32> {
+    let mut header = header_at(height)?;
+    let mut height = height;
+    while height % RETARGET_INTERVAL != 0 && header.bits == MAX_TARGET_BITS {
+        height -= 1;
+        // genesis tiene la dificultad minima
+        let Some(prev_header) = header_at(height) else { return Some(MAX_TARGET_BITS) };
+        header = prev_header;
+    }
+    Some(header.bits)
+}
+
 #[cfg(test)]
 mod tests {
 
     use std::{
+        cell::RefCell,
         fs::{self, remove_file},
+        rc::Rc,
         sync::mpsc,
     };
 
-    use crate::messages::headers::Headers;
+    use gtk::glib::Priority;
+
+    use crate::{
+        message::Message,
+        messages::headers::{Headers, RawHeaders},
+        mining::mine_header,
+    };
 
     use super::*;
 
     #[test]
     fn headers_creation_empty() {
         let (logger_sender, _) = mpsc::channel();
-        let headers =
-            HeadersState::new("tests/non_existing_headers.bin".to_string(), logger_sender).unwrap();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let headers = HeadersState::new(
+            "tests/non_existing_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
         assert_eq!(headers.headers.len(), 0);
 
         remove_file("tests/non_existing_headers.bin").unwrap();
@@ -393,8 +829,13 @@ mod tests {
     #[test]
     fn headers_to_download() {
         let (logger_sender, _) = mpsc::channel();
-        let headers =
-            HeadersState::new("tests/test_headers.bin".to_string(), logger_sender).unwrap();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
         let headers_to_download = headers.total_headers_to_download();
         // 2 headers in the file, only one after START_DATE_IBD
         assert_eq!(headers_to_download, 1);
@@ -403,8 +844,13 @@ mod tests {
     #[test]
     fn headers_get_header_index() {
         let (logger_sender, _) = mpsc::channel();
-        let headers =
-            HeadersState::new("tests/test_headers.bin".to_string(), logger_sender).unwrap();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
 
         let first_hash = headers.headers[0].hash.clone();
         let second_hash = headers.headers[1].hash.clone();
@@ -417,8 +863,13 @@ mod tests {
     #[test]
     fn headers_get_last_headers() {
         let (logger_sender, _) = mpsc::channel();
-        let headers =
-            HeadersState::new("tests/test_headers.bin".to_string(), logger_sender).unwrap();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
 
         let first_hash = headers.headers[0].hash.clone();
         let second_hash = headers.headers[1].hash.clone();
@@ -449,8 +900,13 @@ mod tests {
     #[test]
     fn headers_set_downloaded() {
         let (logger_sender, _) = mpsc::channel();
-        let mut headers =
-            HeadersState::new("tests/test_headers.bin".to_string(), logger_sender).unwrap();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let mut headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
 
         headers.headers[1].block_downloaded = false;
 
@@ -462,8 +918,13 @@ mod tests {
     #[test]
     fn headers_get_headers_to_send_with_only_one_downloaded() {
         let (logger_sender, _) = mpsc::channel();
-        let mut headers =
-            HeadersState::new("tests/test_headers.bin".to_string(), logger_sender).unwrap();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let mut headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
 
         let first_hash = headers.headers[0].hash.clone();
         headers.headers[0].block_downloaded = false;
@@ -482,8 +943,13 @@ mod tests {
     #[test]
     fn headers_get_headers_to_send_with_first_prev_broadcasted() {
         let (logger_sender, _) = mpsc::channel();
-        let mut headers =
-            HeadersState::new("tests/test_headers.bin".to_string(), logger_sender).unwrap();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let mut headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
 
         let second_hash = headers.headers[1].hash.clone();
         headers.headers[0].block_downloaded = true;
@@ -502,8 +968,13 @@ mod tests {
     #[test]
     fn headers_get_headers_to_send_with_second_prev_downloaded() {
         let (logger_sender, _) = mpsc::channel();
-        let mut headers =
-            HeadersState::new("tests/test_headers.bin".to_string(), logger_sender).unwrap();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let mut headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
 
         let first_hash = headers.headers[0].hash.clone();
         let second_hash = headers.headers[1].hash.clone();
@@ -524,8 +995,13 @@ mod tests {
     #[test]
     fn headers_get_headers_to_send_without_prev_broadcasted() {
         let (logger_sender, _) = mpsc::channel();
-        let mut headers =
-            HeadersState::new("tests/test_headers.bin".to_string(), logger_sender).unwrap();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let mut headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
 
         let second_hash = headers.headers[1].hash.clone();
         headers.headers[0].block_downloaded = false;
@@ -543,29 +1019,221 @@ mod tests {
     #[test]
     fn headers_creation_with_restore() {
         let (mut logger_sender, _) = mpsc::channel();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
         let headers = HeadersState::new(
             "tests/test_headers.bin".to_string(),
             Sender::clone(&mut logger_sender),
+            gui_sender,
         )
         .unwrap();
         assert_eq!(headers.headers.len(), 2);
     }
 
     #[test]
-    fn headers_creation_with_restore_error() {
-        let (mut logger_sender, _) = mpsc::channel();
+    fn headers_creation_with_restore_truncated() {
+        let (logger_sender, logger_receiver) = mpsc::channel();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        // un header completo seguido de uno cortado a la mitad
+        let path = "tests/test_headers_truncated.bin";
+        let fixture = fs::read("tests/test_headers.bin").unwrap();
+        fs::write(path, &fixture[..HEADER_BACKUP_V1_BYTES + 50]).unwrap();
+
+        let headers =
+            HeadersState::new(path.to_string(), logger_sender.clone(), gui_sender.clone()).unwrap();
+        assert_eq!(headers.headers.len(), 1);
+        assert_eq!(
+            fs::metadata(path).unwrap().len(),
+            HEADER_BACKUP_V1_BYTES as u64
+        );
+        assert!(logger_receiver
+            .try_iter()
+            .any(|log| matches!(log, Log::Warning(message) if message.starts_with("discarded"))));
+
+        // el primer header del fixture roto ya no es valido, no se conserva ninguno
+        fs::copy("tests/test_headers_error.bin", path).unwrap();
+        let headers = HeadersState::new(path.to_string(), logger_sender, gui_sender).unwrap();
+        assert!(headers.headers.is_empty());
+        assert_eq!(fs::metadata(path).unwrap().len(), 0);
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn headers_creation_with_restore_unlinked() {
+        let (logger_sender, _) = mpsc::channel();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let fixture = fs::read("tests/test_headers.bin").unwrap();
+        let mut unlinked = fixture[HEADER_BACKUP_V1_BYTES..].to_vec();
+        unlinked.extend(&fixture[..HEADER_BACKUP_V1_BYTES]);
+        fs::write("tests/test_headers_unlinked.bin", unlinked).unwrap();
+
         let headers = HeadersState::new(
-            "tests/test_headers_error.bin".to_string(),
-            Sender::clone(&mut logger_sender),
+            "tests/test_headers_unlinked.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
+        assert!(headers.headers.is_empty());
+        assert_eq!(
+            fs::metadata("tests/test_headers_unlinked.bin")
+                .unwrap()
+                .len(),
+            0
+        );
+
+        remove_file("tests/test_headers_unlinked.bin").unwrap();
+    }
+
+    #[test]
+    fn headers_creation_with_restore_corrupted_middle_record() {
+        let (logger_sender, _) = mpsc::channel();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let path = "tests/test_headers_corrupted.bin";
+        let chain = mined_headers_state(path, 3).get_all().to_vec();
+        let mut buffer: Vec<u8> = chain
+            .iter()
+            .flat_map(|header| header.serialize_for_backup())
+            .collect();
+        // se rompe el prev_block_hash del header del medio
+        buffer[HEADER_BACKUP_V1_BYTES + 4] ^= 0xff;
+        fs::write(path, buffer).unwrap();
+
+        let headers = HeadersState::new(path.to_string(), logger_sender, gui_sender).unwrap();
+        assert_eq!(headers.headers.len(), 1);
+        assert_eq!(headers.get_last_header_hash(), Some(chain[0].hash.clone()));
+        assert_eq!(
+            fs::metadata(path).unwrap().len(),
+            HEADER_BACKUP_V1_BYTES as u64
+        );
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn headers_backup_v2_truncated_keeps_whole_headers() {
+        let (logger_sender, _) = mpsc::channel();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let path = "tests/test_headers_v2_truncated.bin";
+        let chain = mined_headers_state(path, 3).get_all().to_vec();
+        let mut headers =
+            HeadersState::new(path.to_string(), logger_sender.clone(), gui_sender.clone()).unwrap();
+        headers.set_regtest(true);
+        headers.append_headers(chain.clone()).unwrap();
+
+        let buffer = fs::read(path).unwrap();
+        let cut = buffer.len() - 10;
+        fs::write(path, &buffer[..cut]).unwrap();
+
+        let mut restored =
+            HeadersState::new(path.to_string(), logger_sender.clone(), gui_sender.clone()).unwrap();
+        assert_eq!(restored.headers.len(), 2);
+        let valid_size = fs::metadata(path).unwrap().len() as usize;
+        assert!(valid_size < cut);
+
+        // los headers nuevos se agregan despues del ultimo valido
+        restored.set_regtest(true);
+        restored.append_headers(chain[2..].to_vec()).unwrap();
+        assert_eq!(fs::read(path).unwrap(), buffer);
+        let restored = HeadersState::new(path.to_string(), logger_sender, gui_sender).unwrap();
+        assert_eq!(restored.headers.len(), 3);
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn headers_backup_v1_rewritten_as_v2() {
+        let (logger_sender, _) = mpsc::channel();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let path = "tests/test_headers_v1_to_v2.bin";
+        fs::copy("tests/test_headers.bin", path).unwrap();
+
+        let mut headers =
+            HeadersState::new(path.to_string(), logger_sender.clone(), gui_sender.clone()).unwrap();
+        assert!(headers.legacy_backup);
+        headers.save(&vec![]).unwrap();
+        assert!(!headers.legacy_backup);
+
+        let v1_buffer = fs::read("tests/test_headers.bin").unwrap();
+        let v2_buffer = fs::read(path).unwrap();
+        assert!(v2_buffer.starts_with(&HEADERS_BACKUP_MAGIC));
+        assert!(v2_buffer.len() < v1_buffer.len());
+
+        let restored = HeadersState::new(path.to_string(), logger_sender, gui_sender).unwrap();
+        assert!(!restored.legacy_backup);
+        assert_eq!(restored.headers.len(), headers.headers.len());
+        for (restored, original) in restored.headers.iter().zip(headers.headers.iter()) {
+            assert_eq!(restored.serialize(), original.serialize());
+            assert_eq!(restored.hash, original.hash);
+        }
+
+        let back_to_v1: Vec<u8> = restored
+            .headers
+            .iter()
+            .flat_map(|header| header.serialize_for_backup())
+            .collect();
+        assert_eq!(back_to_v1, v1_buffer);
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn headers_backup_v2_size_reduction() {
+        let (logger_sender, _) = mpsc::channel();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let path = "tests/test_headers_v2_size.bin";
+        let _ = remove_file(path);
+
+        let mut chain: Vec<BlockHeader> = vec![];
+        for height in 0..10_000_u32 {
+            let prev_block_hash = chain
+                .last()
+                .map(|header| header.hash().clone())
+                .unwrap_or(GENESIS.to_vec());
+            chain.push(mine_test_header(prev_block_hash, 1296688602 + height * 600));
+        }
+
+        let mut headers =
+            HeadersState::new(path.to_string(), logger_sender.clone(), gui_sender.clone()).unwrap();
+        headers.set_regtest(true);
+        headers.append_headers(chain.clone()).unwrap();
+
+        let v1_size = chain.len() * HEADER_BACKUP_V1_BYTES;
+        let v2_size = fs::metadata(path).unwrap().len() as usize;
+        assert!(v2_size * 2 < v1_size);
+
+        let restored = HeadersState::new(path.to_string(), logger_sender, gui_sender).unwrap();
+        assert_eq!(restored.headers.len(), chain.len());
+        assert_eq!(
+            restored.get_last_header_hash(),
+            Some(chain[9_999].hash.clone())
         );
-        assert_eq!(headers.is_err(), true);
+
+        remove_file(path).unwrap();
+    }
+
+    fn mine_test_header(prev_block_hash: Vec<u8>, timestamp: u32) -> BlockHeader {
+        let merkle_root = timestamp.to_le_bytes().repeat(8);
+        mine_header(
+            1,
+            &prev_block_hash,
+            &merkle_root,
+            timestamp,
+            REGTEST_BITS,
+            u32::MAX,
+        )
+        .unwrap()
     }
 
     #[test]
     fn headers_get_all() {
         let (logger_sender, _) = mpsc::channel();
-        let headers =
-            HeadersState::new("tests/test_headers.bin".to_string(), logger_sender).unwrap();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
 
         assert_eq!(headers.get_all().len(), 2);
     }
@@ -573,8 +1241,13 @@ mod tests {
     #[test]
     fn headers_get_last_header_hash() {
         let (logger_sender, _) = mpsc::channel();
-        let headers =
-            HeadersState::new("tests/test_headers.bin".to_string(), logger_sender).unwrap();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
 
         assert_eq!(
             headers.get_last_header_hash().unwrap(),
@@ -588,25 +1261,24 @@ mod tests {
     #[test]
     fn headers_append_headers() {
         let (logger_sender, _) = mpsc::channel();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
         fs::copy("tests/test_headers.bin", "tests/test_headers_append.bin").unwrap();
-        let mut headers =
-            HeadersState::new("tests/test_headers_append.bin".to_string(), logger_sender).unwrap();
+        let mut headers = HeadersState::new(
+            "tests/test_headers_append.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
+        headers.set_regtest(true);
 
         let mut new_headers = Headers::new();
-        new_headers.headers.push(BlockHeader {
-            prev_block_hash: vec![
+        new_headers.headers.push(mine_test_header(
+            vec![
                 32, 120, 42, 0, 82, 85, 182, 87, 105, 110, 160, 87, 213, 185, 143, 52, 222, 252,
                 247, 81, 150, 246, 79, 110, 234, 200, 2, 108, 0, 0, 0, 0,
             ],
-            merkle_root: vec![],
-            version: 0,
-            timestamp: 1677449562,
-            bits: 0,
-            nonce: 0,
-            hash: vec![],
-            block_downloaded: true,
-            broadcasted: true,
-        });
+            1677449562,
+        ));
 
         headers.append_headers(new_headers.headers).unwrap();
         assert_eq!(headers.headers.len(), 3);
@@ -614,12 +1286,235 @@ mod tests {
         remove_file("tests/test_headers_append.bin").unwrap();
     }
 
+    #[test]
+    fn headers_append_headers_sends_progress_on_percentage_change() {
+        let (logger_sender, _) = mpsc::channel();
+        let (gui_sender, gui_receiver) = glib::MainContext::channel(Priority::default());
+        let context = glib::MainContext::new();
+        let progress_events = Rc::new(RefCell::new(vec![]));
+        let events = progress_events.clone();
+        gui_receiver.attach(Some(&context), move |event| {
+            if let GUIEvents::HeadersProgress { percentage, .. } = event {
+                events.borrow_mut().push(percentage);
+            }
+            glib::Continue(true)
+        });
+
+        let path = "tests/test_headers_progress.bin";
+        fs::copy("tests/test_headers.bin", path).unwrap();
+        let mut headers = HeadersState::new(path.to_string(), logger_sender, gui_sender).unwrap();
+        headers.set_regtest(true);
+        let first_timestamp = headers.headers[0].timestamp;
+        let elapsed = get_current_timestamp().unwrap() as u32 - first_timestamp;
+
+        // lotes completos de 2000 headers para que los headers no queden sincronizados hasta el ultimo
+        for (count, per_mille) in [(2000, 305), (2000, 305), (1999, 605)] {
+            let timestamp = first_timestamp + elapsed / 1000 * per_mille;
+            let mut batch: Vec<BlockHeader> = vec![];
+            for _ in 0..count {
+                let prev_block_hash = batch
+                    .last()
+                    .map(|header| header.hash().clone())
+                    .unwrap_or_else(|| headers.get_last_header_hash().unwrap());
+                batch.push(mine_test_header(prev_block_hash, timestamp));
+            }
+            headers.append_headers(batch).unwrap();
+        }
+        while context.iteration(false) {}
+
+        // el segundo lote no cambia el porcentaje, por lo que no se envia otro evento
+        assert_eq!(*progress_events.borrow(), vec![30, 60]);
+        assert!(headers.is_synced());
+
+        remove_file(path).unwrap();
+    }
+
+    fn mine_test_branch(prev_block_hash: Vec<u8>, timestamp: u32) -> Vec<BlockHeader> {
+        let mut branch: Vec<BlockHeader> = vec![];
+        for i in 0..3 {
+            let prev_block_hash = branch
+                .last()
+                .map(|header| header.hash().clone())
+                .unwrap_or(prev_block_hash.clone());
+            branch.push(mine_test_header(prev_block_hash, timestamp + i * 600));
+        }
+        branch
+    }
+
+    #[test]
+    fn headers_append_headers_follows_longer_branch() {
+        let (logger_sender, _) = mpsc::channel();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let path = "tests/test_headers_reorg.bin";
+        fs::copy("tests/test_headers.bin", path).unwrap();
+        let mut headers =
+            HeadersState::new(path.to_string(), logger_sender.clone(), gui_sender.clone()).unwrap();
+        headers.set_regtest(true);
+        let base_hash = headers.get_last_header_hash().unwrap();
+
+        let branch_a = mine_test_branch(base_hash.clone(), 1677449562);
+        assert!(headers.append_headers(branch_a.clone()).unwrap().is_empty());
+        assert_eq!(headers.headers.len(), 5);
+
+        // una rama de igual largo desde el mismo punto no reemplaza a la actual
+        let branch_b = mine_test_branch(base_hash, 1677500000);
+        assert!(headers.append_headers(branch_b).unwrap().is_empty());
+        assert_eq!(headers.headers.len(), 5);
+        assert_eq!(
+            headers.get_last_header_hash(),
+            Some(branch_a[2].hash().clone())
+        );
+
+        // una rama de 3 headers desde el primero de la rama actual deja una cadena mas larga
+        let branch_c = mine_test_branch(branch_a[0].hash().clone(), 1677600000);
+        let disconnected = headers.append_headers(branch_c.clone()).unwrap();
+        assert_eq!(
+            disconnected,
+            vec![branch_a[2].hash().clone(), branch_a[1].hash().clone()]
+        );
+        assert_eq!(headers.headers.len(), 6);
+        assert_eq!(headers.headers[2].hash(), branch_a[0].hash());
+        assert_eq!(
+            headers.get_last_header_hash(),
+            Some(branch_c[2].hash().clone())
+        );
+
+        // los headers que ya se tienen se ignoran
+        let mut repeated = branch_c.clone();
+        repeated.push(mine_test_header(branch_c[2].hash().clone(), 1677700000));
+        assert!(headers.append_headers(repeated).unwrap().is_empty());
+        assert_eq!(headers.headers.len(), 7);
+
+        let restored = HeadersState::new(path.to_string(), logger_sender, gui_sender).unwrap();
+        assert_eq!(restored.headers.len(), 7);
+        assert_eq!(restored.headers[3].hash(), branch_c[0].hash());
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn headers_append_headers_invalid_pow() {
+        let (logger_sender, _) = mpsc::channel();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        fs::copy("tests/test_headers.bin", "tests/test_headers_append3.bin").unwrap();
+        let mut headers = HeadersState::new(
+            "tests/test_headers_append3.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
+        let backup_size = fs::metadata("tests/test_headers_append3.bin")
+            .unwrap()
+            .len();
+
+        let first = mine_test_header(headers.get_last_header_hash().unwrap(), 1677449562);
+        let mut second = mine_test_header(first.hash().clone(), 1677450162);
+        second.nonce = second.nonce.wrapping_add(1);
+
+        let result = headers.append_headers(vec![first, second]);
+        assert!(matches!(result, Err(CustomError::InvalidProofOfWork)));
+        assert_eq!(headers.headers.len(), 2);
+        assert_eq!(
+            fs::metadata("tests/test_headers_append3.bin")
+                .unwrap()
+                .len(),
+            backup_size
+        );
+
+        remove_file("tests/test_headers_append3.bin").unwrap();
+    }
+
+    #[test]
+    fn headers_append_headers_unexpected_difficulty() {
+        let (logger_sender, _) = mpsc::channel();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let path = "tests/test_headers_difficulty.bin";
+        fs::copy("tests/test_headers.bin", path).unwrap();
+        let mut headers = HeadersState::new(path.to_string(), logger_sender, gui_sender).unwrap();
+        let backup_size = fs::metadata(path).unwrap().len();
+
+        // fuera de regtest, un header minado con la dificultad de regtest no tiene la dificultad esperada
+        let header = mine_test_header(headers.get_last_header_hash().unwrap(), 1677449562);
+        let result = headers.append_headers(vec![header.clone()]);
+        assert!(matches!(result, Err(CustomError::UnexpectedDifficulty)));
+        assert_eq!(headers.headers.len(), 2);
+        assert_eq!(fs::metadata(path).unwrap().len(), backup_size);
+
+        headers.set_regtest(true);
+        headers.append_headers(vec![header]).unwrap();
+        assert_eq!(headers.headers.len(), 3);
+
+        remove_file(path).unwrap();
+    }
+
+    /// Devuelve los bits esperados para el header de height de chain (chain[0] es el de height 1)
+    /// si llega delay segundos despues del anterior.
+    fn expected_bits_after(chain: &[BlockHeader], height: usize, delay: u32) -> Option<u32> {
+        let prev_timestamp = match height {
+            1 => GENESIS_TIMESTAMP,
+            height => chain[height - 2].timestamp,
+        };
+        expected_bits(
+            |height| chain.get(height.checked_sub(1)?),
+            height,
+            prev_timestamp + delay,
+        )
+    }
+
+    #[test]
+    fn expected_bits_follows_testnet_rules() {
+        // bloques cada 5 minutos con la dificultad minima hasta el primer ajuste
+        let mut chain: Vec<BlockHeader> = vec![];
+        for height in 1..=RETARGET_INTERVAL as u32 + 1 {
+            let mut header = mine_test_header(vec![0; 32], GENESIS_TIMESTAMP + height * 300);
+            header.bits = MAX_TARGET_BITS;
+            chain.push(header);
+        }
+        assert_eq!(expected_bits_after(&chain, 1, 600), Some(MAX_TARGET_BITS));
+        assert_eq!(expected_bits_after(&chain, 5, 600), Some(MAX_TARGET_BITS));
+
+        // el ajuste depende de lo que tardaron los bloques desde genesis, la mitad de lo esperado
+        let retarget = retarget_bits(
+            MAX_TARGET_BITS,
+            (RETARGET_INTERVAL as i64 - 1) * 300,
+            RETARGET_INTERVAL as u32 * TARGET_BLOCK_SPACING,
+        );
+        assert_ne!(retarget, MAX_TARGET_BITS);
+        assert_eq!(
+            expected_bits_after(&chain, RETARGET_INTERVAL, 600),
+            Some(retarget)
+        );
+
+        // entre ajustes se mantiene la dificultad vigente, salvo que el bloque llegue mas de 20 minutos despues
+        chain[RETARGET_INTERVAL - 1].bits = retarget;
+        assert_eq!(
+            expected_bits_after(&chain, RETARGET_INTERVAL + 1, 1200),
+            Some(retarget)
+        );
+        assert_eq!(
+            expected_bits_after(&chain, RETARGET_INTERVAL + 1, 1201),
+            Some(MAX_TARGET_BITS)
+        );
+
+        // despues de un bloque con la dificultad minima se vuelve a la del ultimo ajuste
+        chain[RETARGET_INTERVAL].bits = MAX_TARGET_BITS;
+        assert_eq!(
+            expected_bits_after(&chain, RETARGET_INTERVAL + 2, 600),
+            Some(retarget)
+        );
+    }
+
     #[test]
     fn headers_append_headers_blockchain_broken() {
         let (logger_sender, _) = mpsc::channel();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
         fs::copy("tests/test_headers.bin", "tests/test_headers_append2.bin").unwrap();
-        let mut headers =
-            HeadersState::new("tests/test_headers_append2.bin".to_string(), logger_sender).unwrap();
+        let mut headers = HeadersState::new(
+            "tests/test_headers_append2.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
 
         let mut new_headers = Headers::new();
         new_headers.headers.push(BlockHeader {
@@ -644,8 +1539,13 @@ mod tests {
     #[test]
     fn headers_verify_headers_sync() {
         let (logger_sender, _) = mpsc::channel();
-        let mut headers =
-            HeadersState::new("tests/test_headers.bin".to_string(), logger_sender).unwrap();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let mut headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
         assert_eq!(headers.is_synced(), false);
 
         headers.verify_headers_sync(2000).unwrap();
@@ -661,8 +1561,13 @@ mod tests {
     #[test]
     fn headers_get_headers_from_genesis() {
         let (logger_sender, _) = mpsc::channel();
-        let headers =
-            HeadersState::new("tests/test_headers.bin".to_string(), logger_sender).unwrap();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
 
         let getheaders = GetHeaders::new(1, vec![], vec![0; 32]);
         assert_eq!(headers.get_headers(getheaders).len(), 2);
@@ -674,8 +1579,13 @@ mod tests {
     #[test]
     fn headers_get_headers_from_last() {
         let (logger_sender, _) = mpsc::channel();
-        let headers =
-            HeadersState::new("tests/test_headers.bin".to_string(), logger_sender).unwrap();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
 
         let getheaders = GetHeaders::new(
             1,
@@ -688,8 +1598,13 @@ mod tests {
     #[test]
     fn headers_get_headers_from_first() {
         let (logger_sender, _) = mpsc::channel();
-        let mut headers =
-            HeadersState::new("tests/test_headers.bin".to_string(), logger_sender).unwrap();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let mut headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
 
         let second_hash = headers.headers[1].hash.clone();
 
@@ -707,7 +1622,7 @@ mod tests {
             block_downloaded: true,
             broadcasted: true,
         };
-        headers.headers.push(new_header.clone());
+        headers.push(new_header.clone());
 
         let getheaders = GetHeaders::new(1, vec![headers.headers[0].hash.clone()], vec![0; 32]);
         let getheaders = headers.get_headers(getheaders);
@@ -719,8 +1634,13 @@ mod tests {
     #[test]
     fn headers_get_headers_with_hash_stop() {
         let (logger_sender, _) = mpsc::channel();
-        let mut headers =
-            HeadersState::new("tests/test_headers.bin".to_string(), logger_sender).unwrap();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let mut headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
 
         let second_hash = headers.headers[1].hash.clone();
 
@@ -738,7 +1658,7 @@ mod tests {
             block_downloaded: true,
             broadcasted: true,
         };
-        headers.headers.push(new_header.clone());
+        headers.push(new_header.clone());
 
         let getheaders = GetHeaders::new(
             1,
@@ -753,8 +1673,13 @@ mod tests {
     #[test]
     fn headers_get_headers_with_wrong_block_locator_hashes() {
         let (logger_sender, _) = mpsc::channel();
-        let headers =
-            HeadersState::new("tests/test_headers.bin".to_string(), logger_sender).unwrap();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
 
         let first_hash = headers.headers[0].hash.clone();
         let second_hash = headers.headers[1].hash.clone();
@@ -765,4 +1690,303 @@ mod tests {
         assert_eq!(getheaders[0].hash, first_hash);
         assert_eq!(getheaders[1].hash, second_hash);
     }
+
+    #[test]
+    fn headers_get_headers_use_first_known_locator_hash() {
+        let (logger_sender, _) = mpsc::channel();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
+
+        let first_hash = headers.headers[0].hash.clone();
+        let second_hash = headers.headers[1].hash.clone();
+
+        // el primer hash conocido es el punto de bifurcacion, aunque los siguientes no se conozcan
+        let getheaders = GetHeaders::new(1, vec![first_hash.clone(), vec![1; 32]], vec![0; 32]);
+        let getheaders = headers.get_headers(getheaders);
+        assert_eq!(getheaders.len(), 1);
+        assert_eq!(getheaders[0].hash, second_hash);
+
+        let getheaders = GetHeaders::new(
+            1,
+            vec![vec![1; 32], first_hash.clone(), GENESIS.to_vec()],
+            vec![0; 32],
+        );
+        assert_eq!(headers.get_headers(getheaders)[0].hash, second_hash);
+
+        let getheaders = GetHeaders::new(1, vec![vec![1; 32], GENESIS.to_vec()], vec![0; 32]);
+        assert_eq!(headers.get_headers(getheaders)[0].hash, first_hash);
+    }
+
+    #[test]
+    fn headers_get_headers_pages_from_fork_point() {
+        let headers = mined_headers_state("tests/test_headers_locator_pages.bin", 2500);
+        let hash_at = |index: usize| headers.headers[index].hash.clone();
+
+        let page = headers.get_headers(GetHeaders::new(
+            1,
+            vec![vec![1; 32], hash_at(100), hash_at(50)],
+            vec![0; 32],
+        ));
+        assert_eq!(page.len(), HEADERS_SEGMENT_SIZE);
+        assert_eq!(page[0].hash, hash_at(101));
+        assert_eq!(page[HEADERS_SEGMENT_SIZE - 1].hash, hash_at(2100));
+
+        let until_stop = headers.get_headers(GetHeaders::new(1, vec![hash_at(100)], hash_at(150)));
+        assert_eq!(until_stop.len(), 50);
+        assert_eq!(until_stop[49].hash, hash_at(150));
+
+        // un hash_stop anterior al punto de bifurcacion o fuera de la pagina no la corta
+        let stop_before = headers.get_headers(GetHeaders::new(1, vec![hash_at(100)], hash_at(10)));
+        assert_eq!(stop_before.len(), HEADERS_SEGMENT_SIZE);
+        let last_page = headers.get_headers(GetHeaders::new(1, vec![hash_at(2100)], vec![0; 32]));
+        assert_eq!(last_page.len(), 399);
+    }
+
+    #[test]
+    fn headers_get_block_hashes_follow_block_locator() {
+        let (logger_sender, _) = mpsc::channel();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
+
+        let first_hash = headers.headers[0].hash.clone();
+        let second_hash = headers.headers[1].hash.clone();
+
+        let from_genesis = GetBlocks::new(1, vec![], vec![0; 32]);
+        assert_eq!(
+            headers.get_block_hashes(&from_genesis),
+            vec![first_hash.clone(), second_hash.clone()]
+        );
+
+        let unknown_locator = GetBlocks::new(1, vec![vec![1; 32], GENESIS.to_vec()], vec![0; 32]);
+        assert_eq!(
+            headers.get_block_hashes(&unknown_locator),
+            vec![first_hash.clone(), second_hash.clone()]
+        );
+
+        let after_first = GetBlocks::new(1, vec![vec![1; 32], first_hash.clone()], vec![0; 32]);
+        assert_eq!(
+            headers.get_block_hashes(&after_first),
+            vec![second_hash.clone()]
+        );
+
+        let after_tip = GetBlocks::new(1, vec![second_hash, first_hash.clone()], vec![0; 32]);
+        assert!(headers.get_block_hashes(&after_tip).is_empty());
+
+        let until_stop = GetBlocks::new(1, vec![], first_hash.clone());
+        assert_eq!(headers.get_block_hashes(&until_stop), vec![first_hash]);
+    }
+
+    #[test]
+    fn headers_get_confirmations_counts_from_tip() {
+        let (logger_sender, _) = mpsc::channel();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
+
+        assert_eq!(headers.get_confirmations(&headers.headers[1].hash), 1);
+        assert_eq!(headers.get_confirmations(&headers.headers[0].hash), 2);
+        assert_eq!(headers.get_confirmations(&[1; 32]), 0);
+    }
+
+    #[test]
+    fn headers_hash_and_height_round_trip() {
+        let (logger_sender, _) = mpsc::channel();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let headers = HeadersState::new(
+            "tests/test_headers.bin".to_string(),
+            logger_sender,
+            gui_sender,
+        )
+        .unwrap();
+
+        for height in 0..=2 {
+            let hash = headers.get_hash_at_height(height).unwrap();
+            assert_eq!(headers.get_height(&hash), Some(height));
+        }
+        assert_eq!(headers.get_hash_at_height(0), Some(GENESIS.to_vec()));
+        assert_eq!(
+            headers.get_hash_at_height(2),
+            Some(headers.headers[1].hash.clone())
+        );
+        assert_eq!(
+            headers.get_header_at_height(1).unwrap().hash,
+            headers.headers[0].hash
+        );
+
+        assert!(headers.get_hash_at_height(3).is_none());
+        assert!(headers.get_header_at_height(0).is_none());
+        assert!(headers.get_height(&[1; 32]).is_none());
+    }
+
+    #[test]
+    fn headers_get_block_hashes_are_limited() {
+        let headers = mined_headers_state("tests/test_headers_getblocks.bin", 600);
+
+        let get_blocks = GetBlocks::new(1, vec![headers.headers[9].hash.clone()], vec![0; 32]);
+        let block_hashes = headers.get_block_hashes(&get_blocks);
+        assert_eq!(block_hashes.len(), MAX_GETBLOCKS_HASHES);
+        assert_eq!(block_hashes[0], headers.headers[10].hash);
+        assert_eq!(block_hashes[499], headers.headers[509].hash);
+    }
+
+    fn mined_headers_state(path: &str, count: u32) -> HeadersState {
+        let (logger_sender, _) = mpsc::channel();
+        let (gui_sender, _) = glib::MainContext::channel(Priority::default());
+        let _ = remove_file(path);
+        let mut headers = HeadersState::new(path.to_string(), logger_sender, gui_sender).unwrap();
+        remove_file(path).unwrap();
+        for height in 0..count {
+            let prev_block_hash = headers.get_last_header_hash().unwrap_or(GENESIS.to_vec());
+            let header = mine_test_header(prev_block_hash, 1296688602 + height * 600);
+            headers.push(header);
+        }
+        headers
+    }
+
+    #[test]
+    fn headers_difficulty_info_estimates_next_retarget() {
+        let mut headers = mined_headers_state("tests/test_headers_difficulty.bin", 0);
+        assert!(headers.get_difficulty_info().is_none());
+
+        // los headers estan separados por 900 segundos
+        let start = 1296688602;
+        for height in 1..=RETARGET_INTERVAL as u32 + 4 {
+            let prev_block_hash = headers.get_last_header_hash().unwrap_or(GENESIS.to_vec());
+            headers.push(mine_test_header(prev_block_hash, start + height * 900));
+
+            if height == RETARGET_INTERVAL as u32 {
+                let info = headers.get_difficulty_info().unwrap();
+                assert_eq!(info.blocks_since_retarget, 0);
+                assert_eq!(info.blocks_until_retarget, RETARGET_INTERVAL);
+                assert_eq!(
+                    info.estimated_retarget,
+                    start + height * 900 + RETARGET_INTERVAL as u32 * TARGET_BLOCK_SPACING
+                );
+            }
+        }
+
+        let info = headers.get_difficulty_info().unwrap();
+        assert_eq!(info.height, RETARGET_INTERVAL + 4);
+        assert_eq!(info.difficulty, difficulty_from_bits(0x207fffff));
+        assert_eq!(info.blocks_since_retarget, 4);
+        assert_eq!(info.blocks_until_retarget, RETARGET_INTERVAL - 4);
+        let last_timestamp = start + (RETARGET_INTERVAL as u32 + 4) * 900;
+        assert_eq!(
+            info.estimated_retarget,
+            last_timestamp + (RETARGET_INTERVAL as u32 - 4) * 900
+        );
+    }
+
+    #[test]
+    fn headers_difficulty_info_skips_min_difficulty_headers() {
+        let mut headers = mined_headers_state(
+            "tests/test_headers_min_difficulty.bin",
+            RETARGET_INTERVAL as u32 - 3,
+        );
+        let start = 1296688602 + RETARGET_INTERVAL as u32 * 600;
+        let push_min_difficulty = |headers: &mut HeadersState, height: u32| {
+            let prev_block_hash = headers.get_last_header_hash().unwrap();
+            let mut header = mine_test_header(prev_block_hash, start + height * 1200);
+            header.bits = MAX_TARGET_BITS;
+            headers.push(header);
+        };
+
+        // los bloques de dificultad minima no cambian la dificultad vigente
+        push_min_difficulty(&mut headers, 0);
+        push_min_difficulty(&mut headers, 1);
+        let info = headers.get_difficulty_info().unwrap();
+        assert_eq!(info.height, RETARGET_INTERVAL - 1);
+        assert_eq!(info.difficulty, difficulty_from_bits(0x207fffff));
+
+        // el header de un ajuste fija la dificultad aunque sea la minima
+        push_min_difficulty(&mut headers, 2);
+        push_min_difficulty(&mut headers, 3);
+        let info = headers.get_difficulty_info().unwrap();
+        assert_eq!(info.height, RETARGET_INTERVAL + 1);
+        assert_eq!(info.difficulty, 1.0);
+    }
+
+    #[test]
+    fn headers_serialized_headers_match_headers_message() {
+        let mut headers = mined_headers_state("tests/test_headers_serialized.bin", 4500);
+        let hash_at = |headers: &HeadersState, index: usize| headers.headers[index].hash.clone();
+
+        let requests = vec![
+            (vec![], vec![0; 32]),
+            (vec![GENESIS.to_vec()], hash_at(&headers, 10)),
+            (vec![hash_at(&headers, 0)], vec![0; 32]),
+            (vec![hash_at(&headers, 1998)], vec![0; 32]),
+            (vec![hash_at(&headers, 2500)], hash_at(&headers, 3999)),
+            (vec![hash_at(&headers, 3000)], vec![0; 32]),
+            (vec![hash_at(&headers, 4498)], vec![0; 32]),
+            (vec![hash_at(&headers, 4499)], vec![0; 32]),
+            (vec![vec![1, 2, 3]], vec![0; 32]),
+        ];
+
+        // se repite para usar tanto la serializacion directa como el cache de segmentos
+        for _ in 0..2 {
+            for (locator, hash_stop) in &requests {
+                let expected = Headers {
+                    headers: headers.get_headers(GetHeaders::new(
+                        1,
+                        locator.clone(),
+                        hash_stop.clone(),
+                    )),
+                }
+                .serialize();
+                let entries = headers.get_serialized_headers(GetHeaders::new(
+                    1,
+                    locator.clone(),
+                    hash_stop.clone(),
+                ));
+                assert_eq!(RawHeaders::new(entries).unwrap().serialize(), expected);
+            }
+        }
+        assert_eq!(headers.wire_segments.len(), 2);
+    }
+
+    #[test]
+    #[ignore]
+    fn headers_serve_getheaders_benchmark() {
+        let mut headers = mined_headers_state("tests/test_headers_benchmark.bin", 20_000);
+        let requests = 1000;
+
+        let start = std::time::Instant::now();
+        for i in 0..requests {
+            let locator = vec![headers.headers[(i * 17) % 18_000].hash.clone()];
+            let message = Headers {
+                headers: headers.get_headers(GetHeaders::new(1, locator, vec![0; 32])),
+            };
+            assert_eq!(message.serialize().len(), 3 + 2000 * HEADER_ENTRY_BYTES);
+        }
+        let cloning = start.elapsed();
+
+        let start = std::time::Instant::now();
+        for i in 0..requests {
+            let locator = vec![headers.headers[(i * 17) % 18_000].hash.clone()];
+            let entries = headers.get_serialized_headers(GetHeaders::new(1, locator, vec![0; 32]));
+            let message = RawHeaders::new(entries).unwrap();
+            assert_eq!(message.serialize().len(), 3 + 2000 * HEADER_ENTRY_BYTES);
+        }
+        let cached = start.elapsed();
+
+        println!(
+            "{} respuestas de 2000 headers: clonando {:?}, con cache {:?}",
+            requests, cloning, cached
+        );
+    }
 }
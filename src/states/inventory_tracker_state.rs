@@ -0,0 +1,180 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddrV6,
+    time::{Duration, Instant},
+};
+
+use crate::structs::inventory::Inventory;
+
+/// Tiempo durante el que un inventario pedido a un peer no se vuelve a pedir a otro.
+pub const INVENTORY_REQUEST_EXPIRY: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// InventoryRequest es la peticion de un inventario anunciado por un peer.
+/// Los elementos son:
+/// - peer: Peer al que se le pidio.
+/// - requested_at: Momento en el que se pidio.
+pub struct InventoryRequest {
+    pub peer: SocketAddrV6,
+    pub requested_at: Instant,
+}
+
+/// InventoryTracker registra los inventarios (transacciones y bloques) anunciados con inv que se pidieron hace poco,
+/// para que cada hash se pida a un unico peer a la vez aunque lo anuncien varios.
+/// Un hash se puede volver a pedir cuando vence su peticion o cuando el peer responde notfound.
+/// Los elementos son:
+/// - requests: Peticiones vigentes por hash.
+/// - expiry: Tiempo luego del que una peticion vence.
+pub struct InventoryTracker {
+    requests: HashMap<Vec<u8>, InventoryRequest>,
+    expiry: Duration,
+}
+
+impl Default for InventoryTracker {
+    fn default() -> Self {
+        Self::new(INVENTORY_REQUEST_EXPIRY)
+    }
+}
+
+impl InventoryTracker {
+    /// Crea un InventoryTracker vacio cuyas peticiones vencen luego de expiry.
+    pub fn new(expiry: Duration) -> Self {
+        Self {
+            requests: HashMap::new(),
+            expiry,
+        }
+    }
+
+    /// Devuelve los inventarios anunciados por peer que hay que pedirle y los registra como pedidos en now.
+    /// Se descartan los que ya se pidieron (a este u otro peer) y todavia no vencieron, y los repetidos en el anuncio.
+    pub fn to_request(
+        &mut self,
+        peer: SocketAddrV6,
+        inventories: Vec<Inventory>,
+        now: Instant,
+    ) -> Vec<Inventory> {
+        self.remove_expired(now);
+        inventories
+            .into_iter()
+            .filter(|inventory| {
+                if self.requests.contains_key(&inventory.hash) {
+                    return false;
+                }
+                let request = InventoryRequest {
+                    peer,
+                    requested_at: now,
+                };
+                self.requests.insert(inventory.hash.clone(), request);
+                true
+            })
+            .collect()
+    }
+
+    /// Olvida la peticion de un hash (por ejemplo porque el peer respondio notfound) para poder volver a pedirlo.
+    pub fn not_found(&mut self, hash: &[u8]) {
+        self.requests.remove(hash);
+    }
+
+    /// Devuelve la peticion vigente de un hash en now, si tiene.
+    pub fn get_request(&self, hash: &[u8], now: Instant) -> Option<InventoryRequest> {
+        self.requests
+            .get(hash)
+            .filter(|request| !self.is_expired(request, now))
+            .copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    /// Devuelve true si no hay peticiones registradas.
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    fn is_expired(&self, request: &InventoryRequest, now: Instant) -> bool {
+        now.saturating_duration_since(request.requested_at) >= self.expiry
+    }
+
+    fn remove_expired(&mut self, now: Instant) {
+        let expiry = self.expiry;
+        self.requests
+            .retain(|_, request| now.saturating_duration_since(request.requested_at) < expiry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use crate::structs::inventory::InventoryType;
+
+    use super::*;
+
+    fn peer(port: u16) -> SocketAddrV6 {
+        SocketAddrV6::new(Ipv6Addr::LOCALHOST, port, 0, 0)
+    }
+
+    fn tx_inventory(byte: u8) -> Inventory {
+        Inventory::new(InventoryType::Tx, vec![byte; 32])
+    }
+
+    #[test]
+    fn inventory_tracker_requests_each_hash_once() {
+        let mut tracker = InventoryTracker::default();
+        let now = Instant::now();
+
+        let first = tracker.to_request(peer(1), vec![tx_inventory(1), tx_inventory(1)], now);
+        assert_eq!(first, vec![tx_inventory(1)]);
+
+        let second = tracker.to_request(peer(2), vec![tx_inventory(1), tx_inventory(2)], now);
+        assert_eq!(second, vec![tx_inventory(2)]);
+        assert_eq!(tracker.len(), 2);
+        assert_eq!(
+            tracker
+                .get_request(&[1; 32], now)
+                .map(|request| request.peer),
+            Some(peer(1))
+        );
+    }
+
+    #[test]
+    fn inventory_tracker_allows_request_after_expiry() {
+        let mut tracker = InventoryTracker::new(Duration::from_secs(120));
+        let now = Instant::now();
+        tracker.to_request(peer(1), vec![tx_inventory(1)], now);
+
+        let before_expiry = now + Duration::from_secs(119);
+        assert!(tracker.get_request(&[1; 32], before_expiry).is_some());
+        assert!(tracker
+            .to_request(peer(2), vec![tx_inventory(1)], before_expiry)
+            .is_empty());
+
+        let after_expiry = now + Duration::from_secs(120);
+        assert!(tracker.get_request(&[1; 32], after_expiry).is_none());
+        assert_eq!(
+            tracker.to_request(peer(2), vec![tx_inventory(1)], after_expiry),
+            vec![tx_inventory(1)]
+        );
+        assert_eq!(
+            tracker
+                .get_request(&[1; 32], after_expiry)
+                .map(|request| request.peer),
+            Some(peer(2))
+        );
+    }
+
+    #[test]
+    fn inventory_tracker_allows_request_after_not_found() {
+        let mut tracker = InventoryTracker::default();
+        let now = Instant::now();
+        tracker.to_request(peer(1), vec![tx_inventory(1)], now);
+
+        tracker.not_found(&[1; 32]);
+        assert!(tracker.is_empty());
+        assert_eq!(
+            tracker.to_request(peer(2), vec![tx_inventory(1)], now),
+            vec![tx_inventory(1)]
+        );
+    }
+}
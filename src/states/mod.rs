@@ -1,6 +1,15 @@
+pub mod block_metadata_state;
+pub mod block_store_state;
 pub mod blocks_state;
+pub mod fee_state;
+pub mod headers_index;
 pub mod headers_state;
+pub mod inventory_tracker_state;
+pub mod orphans_state;
+pub mod payment_alerts_state;
+pub mod peer_addresses_state;
 pub mod pending_blocks_state;
 pub mod pending_txs_state;
 pub mod utxo_state;
+pub mod wallet_meta_state;
 pub mod wallets_state;
@@ -0,0 +1,80 @@
+use std::collections::VecDeque;
+
+/// Cantidad maxima de entradas de cada OrphanPool, para que los peers no puedan hacerlo crecer sin limite.
+pub const MAX_ORPHANS: usize = 100;
+
+/// OrphanPool guarda los elementos (transacciones o bloques) que llegaron antes que aquello de lo que dependen,
+/// junto a la clave de lo que esperan, hasta que llegue y se puedan volver a procesar.
+/// Los elementos son:
+/// - entries: Clave esperada y elemento, del mas antiguo al mas reciente.
+/// - capacity: Cantidad maxima de entradas. Al superarla se descarta la mas antigua.
+pub struct OrphanPool<K, V> {
+    entries: VecDeque<(K, V)>,
+    capacity: usize,
+}
+
+impl<K: PartialEq, V> OrphanPool<K, V> {
+    /// Crea un OrphanPool vacio de hasta capacity entradas.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Agrega un elemento que espera a key. Si se supera la capacidad, descarta y devuelve el mas antiguo.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.entries.push_back((key, value));
+        if self.entries.len() > self.capacity {
+            return self.entries.pop_front().map(|(_, value)| value);
+        }
+        None
+    }
+
+    /// Saca y devuelve los elementos que esperan a key y cumplen matches, en el orden en el que llegaron.
+    pub fn take(&mut self, key: &K, mut matches: impl FnMut(&V) -> bool) -> Vec<V> {
+        let mut taken = vec![];
+        let mut kept = VecDeque::with_capacity(self.entries.len());
+        for (entry_key, value) in self.entries.drain(..) {
+            match entry_key == *key && matches(&value) {
+                true => taken.push(value),
+                false => kept.push_back((entry_key, value)),
+            }
+        }
+        self.entries = kept;
+        taken
+    }
+
+    /// Saca y devuelve todos los elementos, en el orden en el que llegaron.
+    pub fn drain(&mut self) -> Vec<V> {
+        self.entries.drain(..).map(|(_, value)| value).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Devuelve true si no hay elementos esperando.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orphan_pool_evicts_oldest() {
+        let mut pool = OrphanPool::new(2);
+        assert_eq!(pool.insert(1, "a"), None);
+        assert_eq!(pool.insert(2, "b"), None);
+        assert_eq!(pool.insert(1, "c"), Some("a"));
+        assert_eq!(pool.len(), 2);
+
+        assert_eq!(pool.take(&1, |_| true), vec!["c"]);
+        assert_eq!(pool.take(&2, |value| *value == "x"), Vec::<&str>::new());
+        assert_eq!(pool.drain(), vec!["b"]);
+        assert!(pool.is_empty());
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::HashSet;
+
+use crate::structs::movement::Movement;
+
+#[derive(Debug, Default)]
+/// PaymentAlerts decide que pagos recibidos por las wallets generan una alerta de pago grande (ver GUIEvents::LargePaymentAlert),
+/// sea o no la wallet activa. El movimiento de una transaccion ya suma todos sus outputs a la wallet, por lo que cada
+/// transaccion alerta a lo sumo una vez por wallet. Las alertas no se repiten en la sesion: la transaccion que alerto
+/// como pendiente no vuelve a alertar al confirmarse.
+/// Los elementos son:
+/// - threshold: Monto minimo en satoshis de un pago que alerta, 0 desactiva las alertas (ALERT_THRESHOLD_SATS del config).
+/// - alerted: Nombre de la wallet y hash de cada transaccion que ya alerto.
+pub struct PaymentAlerts {
+    threshold: u64,
+    alerted: HashSet<(String, Vec<u8>)>,
+}
+
+impl PaymentAlerts {
+    /// Cambia el monto minimo de los pagos que alertan, 0 desactiva las alertas.
+    pub fn set_threshold(&mut self, threshold: u64) {
+        self.threshold = threshold;
+    }
+
+    /// Devuelve true si las alertas estan activas.
+    pub fn is_enabled(&self) -> bool {
+        self.threshold > 0
+    }
+
+    /// Devuelve true si el movimiento de la wallet es un pago recibido de al menos threshold satoshis que todavia
+    /// no alerto, y lo registra como alertado.
+    pub fn should_alert(&mut self, wallet: &str, movement: &Movement) -> bool {
+        // los pagos recibidos son positivos, se comparan como u64 para no truncar el threshold
+        let received = u64::try_from(movement.value).unwrap_or(0);
+        if !self.is_enabled() || received < self.threshold {
+            return false;
+        }
+        self.alerted
+            .insert((wallet.to_string(), movement.tx_hash.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn movement(tx_hash: u8, value: i64) -> Movement {
+        Movement {
+            tx_hash: vec![tx_hash; 32],
+            value,
+            block_hash: None,
+            provisional: false,
+        }
+    }
+
+    #[test]
+    fn payments_alert_from_the_threshold() {
+        let mut alerts = PaymentAlerts::default();
+        alerts.set_threshold(100_000);
+
+        assert!(!alerts.should_alert("treasury", &movement(1, 99_999)));
+        assert!(alerts.should_alert("treasury", &movement(2, 100_000)));
+        assert!(alerts.should_alert("treasury", &movement(3, 250_000)));
+        assert!(!alerts.should_alert("treasury", &movement(4, -500_000)));
+
+        // un threshold que no entra en un i64 no alerta todos los pagos
+        alerts.set_threshold(u64::MAX);
+        assert!(!alerts.should_alert("treasury", &movement(5, i64::MAX)));
+    }
+
+    #[test]
+    fn each_transaction_alerts_once_per_wallet() {
+        let mut alerts = PaymentAlerts::default();
+        alerts.set_threshold(100_000);
+
+        assert!(alerts.should_alert("treasury", &movement(1, 100_000)));
+        let mut confirmed = movement(1, 100_000);
+        confirmed.block_hash = Some(vec![7; 32]);
+        assert!(!alerts.should_alert("treasury", &confirmed));
+        assert!(alerts.should_alert("savings", &movement(1, 100_000)));
+    }
+
+    #[test]
+    fn zero_threshold_disables_alerts() {
+        let mut alerts = PaymentAlerts::default();
+        assert!(!alerts.is_enabled());
+        assert!(!alerts.should_alert("treasury", &movement(1, 1_000_000)));
+
+        alerts.set_threshold(1);
+        assert!(alerts.is_enabled());
+        assert!(alerts.should_alert("treasury", &movement(1, 1)));
+    }
+}
@@ -0,0 +1,336 @@
+use std::{
+    io::Read,
+    net::{SocketAddr, SocketAddrV6},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::CustomError,
+    parser::BufferParser,
+    rng::random_jitter,
+    utils::{get_address_v6, get_socket_address, open_file, write_file_atomic, OpenMode},
+};
+
+/// Cantidad maxima de direcciones conocidas, para que los mensajes addr de los peers no las hagan crecer sin limite.
+pub const MAX_KNOWN_ADDRESSES: usize = 2000;
+
+/// Tamaño de cada direccion guardada en disco: ip (16) y puerto (2).
+const STORED_ADDRESS_SIZE: usize = 18;
+
+/// Espera antes de volver a intentar conectarse a una direccion, se duplica con cada intento fallido.
+const RECONNECT_BASE_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Espera maxima entre los intentos de conexion a una misma direccion.
+const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+/// Cantidad de fallos a partir de la cual la espera deja de duplicarse (5s * 2^7 supera RECONNECT_MAX_BACKOFF).
+const MAX_BACKOFF_EXPONENT: u32 = 7;
+
+#[derive(Debug, Clone)]
+/// AddressAttempts registra los intentos de conexion a una direccion.
+/// Los elementos son:
+/// - address: Direccion obtenida del seed DNS, de un mensaje addr o del archivo de direcciones.
+/// - failures: Intentos fallidos consecutivos.
+/// - last_attempt: Momento del ultimo intento de conexion.
+/// - jitter: Espera aleatoria que se suma al backoff, para que los reintentos de varias direcciones no se sincronicen.
+struct AddressAttempts {
+    address: SocketAddr,
+    failures: u32,
+    last_attempt: Option<Instant>,
+    jitter: Duration,
+}
+
+impl AddressAttempts {
+    /// Devuelve el tiempo que hay que esperar desde el ultimo intento para volver a intentar.
+    fn backoff(&self) -> Duration {
+        self.base_backoff() + self.jitter
+    }
+
+    /// Devuelve el backoff sin jitter, que depende solo de los fallos consecutivos.
+    fn base_backoff(&self) -> Duration {
+        let backoff = RECONNECT_BASE_BACKOFF * 2_u32.pow(self.failures.min(MAX_BACKOFF_EXPONENT));
+        backoff.min(RECONNECT_MAX_BACKOFF)
+    }
+
+    /// Devuelve true si ya paso el backoff desde el ultimo intento (o si nunca se intento).
+    fn is_ready(&self, now: Instant) -> bool {
+        match self.last_attempt {
+            Some(last_attempt) => now.saturating_duration_since(last_attempt) >= self.backoff(),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// PeerAddressesState guarda las direcciones conocidas (del seed DNS y de los mensajes addr de los peers)
+/// y los intentos de conexion a cada una, para reemplazar a los peers que se desconectan sin insistir
+/// con las direcciones que no responden.
+/// Los elementos son:
+/// - addresses: Direcciones conocidas, en el orden en el que se conocieron. Son como maximo MAX_KNOWN_ADDRESSES.
+/// - path: Archivo donde se guardan las direcciones, None si no se guardan en disco.
+pub struct PeerAddressesState {
+    addresses: Vec<AddressAttempts>,
+    path: Option<String>,
+}
+
+impl PeerAddressesState {
+    /// Crea el estado sin direcciones conocidas y sin guardarlas en disco.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Crea el estado con las direcciones guardadas en el archivo recibido, que se crea si no existe.
+    /// Las direcciones que se agreguen despues se guardan con save.
+    pub fn restore(path: String) -> Result<Self, CustomError> {
+        let mut file = open_file(&path, OpenMode::ReadCreate)?;
+        let mut buffer = vec![];
+        file.read_to_end(&mut buffer)?;
+
+        let mut parser = BufferParser::new(buffer);
+        let mut addresses = vec![];
+        while parser.len() >= STORED_ADDRESS_SIZE {
+            addresses.push(get_socket_address(parser.extract_address()?));
+        }
+
+        let mut state = Self {
+            addresses: vec![],
+            path: Some(path),
+        };
+        state.add_addresses(&addresses);
+        Ok(state)
+    }
+
+    /// Guarda las direcciones conocidas en el archivo del estado, si tiene uno.
+    /// Los intentos de conexion no se guardan: al reiniciar el nodo se vuelven a intentar todas.
+    pub fn save(&self) -> Result<(), CustomError> {
+        let Some(path) = &self.path else { return Ok(()) };
+
+        let mut buffer = vec![];
+        for known in &self.addresses {
+            let address = get_address_v6(known.address);
+            buffer.extend(address.ip().octets());
+            buffer.extend(address.port().to_be_bytes());
+        }
+        write_file_atomic(path, &buffer)
+    }
+
+    /// Agrega las direcciones que todavia no se conocian, conservando los intentos de las demas.
+    /// Si ya hay MAX_KNOWN_ADDRESSES, cada direccion nueva reemplaza a la que mas fallos consecutivos tiene,
+    /// y se descarta si ninguna fallo. Devuelve la cantidad de direcciones agregadas.
+    pub fn add_addresses(&mut self, addresses: &[SocketAddr]) -> usize {
+        let mut added = 0;
+        for address in addresses {
+            if self.addresses.iter().any(|known| known.address == *address) {
+                continue;
+            }
+            if self.addresses.len() >= MAX_KNOWN_ADDRESSES && !self.evict_failing_address() {
+                continue;
+            }
+            self.addresses.push(AddressAttempts {
+                address: *address,
+                failures: 0,
+                last_attempt: None,
+                jitter: Duration::ZERO,
+            });
+            added += 1;
+        }
+        added
+    }
+
+    /// Devuelve las direcciones conocidas, en el orden en el que se conocieron.
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        self.addresses.iter().map(|known| known.address).collect()
+    }
+
+    /// Descarta la direccion con mas fallos consecutivos. Devuelve false si ninguna direccion fallo.
+    fn evict_failing_address(&mut self) -> bool {
+        let worst = self
+            .addresses
+            .iter()
+            .enumerate()
+            .filter(|(_, known)| known.failures > 0)
+            .max_by_key(|(_, known)| known.failures)
+            .map(|(index, _)| index);
+        match worst {
+            Some(index) => {
+                self.addresses.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Registra un intento de conexion a una direccion conocida.
+    /// Si la conexion fallo se duplica la espera hasta el proximo intento (mas un jitter de hasta su decima parte),
+    /// si no se reinicia.
+    pub fn record_attempt(&mut self, address: SocketAddr, connected: bool, now: Instant) {
+        let known = self
+            .addresses
+            .iter_mut()
+            .find(|known| known.address == address);
+        if let Some(known) = known {
+            known.last_attempt = Some(now);
+            known.failures = match connected {
+                true => 0,
+                false => known.failures.saturating_add(1),
+            };
+            known.jitter = match connected {
+                true => Duration::ZERO,
+                false => random_jitter(known.base_backoff() / 10),
+            };
+        }
+    }
+
+    /// Devuelve hasta count direcciones a las que intentar conectarse: las que no estan conectadas
+    /// y cuyo ultimo intento fue hace mas que su backoff.
+    pub fn candidates(
+        &self,
+        connected: &[SocketAddrV6],
+        count: usize,
+        now: Instant,
+    ) -> Vec<SocketAddr> {
+        self.addresses
+            .iter()
+            .filter(|known| !connected.contains(&get_address_v6(known.address)))
+            .filter(|known| known.is_ready(now))
+            .take(count)
+            .map(|known| known.address)
+            .collect()
+    }
+
+    /// Devuelve la cantidad de direcciones conocidas.
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    /// Devuelve true si no se conoce ninguna direccion.
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        net::{Ipv4Addr, Ipv6Addr, SocketAddrV4},
+    };
+
+    use super::*;
+
+    fn address(last_byte: u8) -> SocketAddr {
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, last_byte), 18333))
+    }
+
+    #[test]
+    fn peer_addresses_are_added_once() {
+        let mut state = PeerAddressesState::new();
+        state.add_addresses(&[address(1), address(2)]);
+        state.add_addresses(&[address(2), address(3)]);
+        assert_eq!(state.len(), 3);
+
+        let candidates = state.candidates(&[], 10, Instant::now());
+        assert_eq!(candidates, vec![address(1), address(2), address(3)]);
+    }
+
+    #[test]
+    fn peer_addresses_skip_connected_and_limit_count() {
+        let mut state = PeerAddressesState::new();
+        state.add_addresses(&[address(1), address(2), address(3)]);
+
+        let connected = [get_address_v6(address(1))];
+        let candidates = state.candidates(&connected, 1, Instant::now());
+        assert_eq!(candidates, vec![address(2)]);
+    }
+
+    #[test]
+    fn peer_addresses_back_off_exponentially_after_failures() {
+        let mut state = PeerAddressesState::new();
+        state.add_addresses(&[address(1)]);
+        let start = Instant::now();
+
+        state.record_attempt(address(1), false, start);
+        assert!(state
+            .candidates(&[], 1, start + Duration::from_secs(9))
+            .is_empty());
+        assert_eq!(
+            state.candidates(&[], 1, start + Duration::from_secs(11)),
+            vec![address(1)]
+        );
+
+        state.record_attempt(address(1), false, start);
+        assert!(state
+            .candidates(&[], 1, start + Duration::from_secs(19))
+            .is_empty());
+        assert_eq!(
+            state
+                .candidates(&[], 1, start + Duration::from_secs(22))
+                .len(),
+            1
+        );
+
+        for _ in 0..20 {
+            state.record_attempt(address(1), false, start);
+        }
+        assert!(state
+            .candidates(&[], 1, start + Duration::from_secs(599))
+            .is_empty());
+        assert_eq!(
+            state
+                .candidates(&[], 1, start + RECONNECT_MAX_BACKOFF * 11 / 10)
+                .len(),
+            1
+        );
+
+        // una conexion exitosa reinicia la espera
+        state.record_attempt(address(1), true, start);
+        assert_eq!(
+            state.candidates(&[], 1, start + RECONNECT_BASE_BACKOFF),
+            vec![address(1)]
+        );
+    }
+
+    #[test]
+    fn peer_addresses_are_bounded_and_evict_failing_ones() {
+        let mut state = PeerAddressesState::new();
+        let addresses: Vec<SocketAddr> = (0..MAX_KNOWN_ADDRESSES as u32)
+            .map(|index| SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::from(index), 18333)))
+            .collect();
+        assert_eq!(state.add_addresses(&addresses), MAX_KNOWN_ADDRESSES);
+
+        // sin direcciones que fallaron, las nuevas se descartan
+        assert_eq!(state.add_addresses(&[address(1)]), 0);
+        assert_eq!(state.len(), MAX_KNOWN_ADDRESSES);
+
+        state.record_attempt(addresses[5], false, Instant::now());
+        assert_eq!(state.add_addresses(&[address(1)]), 1);
+        assert_eq!(state.len(), MAX_KNOWN_ADDRESSES);
+        assert!(!state.addresses().contains(&addresses[5]));
+        assert!(state.addresses().contains(&address(1)));
+    }
+
+    #[test]
+    fn peer_addresses_are_persisted() {
+        let path = String::from("tests/peer_addresses_are_persisted.bin");
+        let _ = fs::remove_file(&path);
+        let ipv6 = SocketAddr::V6(SocketAddrV6::new(
+            Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1),
+            8333,
+            0,
+            0,
+        ));
+
+        let mut state = PeerAddressesState::restore(path.clone()).unwrap();
+        assert!(state.is_empty());
+        state.add_addresses(&[address(1), ipv6, address(2)]);
+        state.record_attempt(address(1), false, Instant::now());
+        state.save().unwrap();
+
+        let restored = PeerAddressesState::restore(path.clone()).unwrap();
+        assert_eq!(restored.addresses(), vec![address(1), ipv6, address(2)]);
+        // los intentos no se guardan, por lo que todas son candidatas
+        assert_eq!(restored.candidates(&[], 10, Instant::now()).len(), 3);
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,84 +1,310 @@
 use std::{
     collections::HashMap,
-    path::Path,
-    sync::{Arc, Mutex},
+    fs,
+    net::SocketAddrV6,
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 use crate::{
     error::CustomError,
+    loops::{maintenance_loop::MaintenanceAction, pending_blocks_task::PENDING_BLOCKS_TASK},
+    parser::BufferParser,
     structs::block_header::BlockHeader,
-    utils::{calculate_index_from_timestamp, get_current_timestamp},
+    utils::calculate_index_from_timestamp,
 };
 
-use super::utxo_state::START_DATE_IBD;
+use super::utxo_state::{imported_base, START_DATE_IBD};
+
+/// Segundos que debe pasar para que una peticion de bloque se considere vencida si el config no indica STALE_BLOCK_TIMEOUT_SECS.
+pub const DEFAULT_STALE_BLOCK_TIMEOUT: u64 = 5;
+
+/// Tiempo entre revisiones de los bloques pendientes mientras no hay ninguno.
+pub const IDLE_PENDING_BLOCKS_POLL: Duration = Duration::from_secs(30);
+
+/// Cantidad de veces que se vuelve a pedir un bloque al que los peers responden notfound si el config no indica
+/// MAX_NOTFOUND_RETRIES.
+pub const DEFAULT_MAX_NOTFOUND_RETRIES: u32 = 5;
+
+/// Version del formato del archivo de la sesion de descarga. Si el archivo tiene otra version se ignora.
+const DOWNLOAD_SESSION_VERSION: u8 = 1;
+
+/// Tamaño de cada bloque guardado en la sesion de descarga: hash (32), reintentos (4), flag del peer (1), ip (16) y puerto (2).
+const STORED_RETRY_SIZE: usize = 55;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// BlockRequest es la peticion de un bloque pendiente.
+/// Los elementos son:
+/// - requested_at: Momento en el que se pidio el bloque.
+/// - peer: Peer al que se le pidio el bloque (None si se pidio a cualquier peer mediante PeerAction::GetData).
+pub struct BlockRequest {
+    pub requested_at: Instant,
+    pub peer: Option<SocketAddrV6>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+/// BlockRetry registra las peticiones vencidas de un bloque pendiente.
+/// Los elementos son:
+/// - count: Cantidad de peticiones del bloque que vencieron sin recibirlo.
+/// - last_failed_peer: Ultimo peer que no envio el bloque a tiempo.
+pub struct BlockRetry {
+    pub count: u32,
+    pub last_failed_peer: Option<SocketAddrV6>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// NotFoundAction indica que hacer con un bloque al que un peer respondio notfound (ver PendingBlocks::block_not_found).
+/// - Retry: Se vuelve a pedir.
+/// - GiveUp: Ya se volvio a pedir max_not_found_retries veces, se deja de pedir.
+/// - Ignore: Ya se habia dejado de pedir, o el bloque no esta pendiente.
+pub enum NotFoundAction {
+    Retry,
+    GiveUp,
+    Ignore,
+}
 
 /// PendingBlocks es una estructura para manejar los bloques solicitados pendientes de recibir.
 /// Los elementos son:
-/// - blocks: HashMap que contiene los bloques pendientes de recibir y un timestamp del momento en el que se generó.
-/// - stale_time: Tiempo en segundos que debe pasar para que una peticion de bloque sea considerada como vencida.
+/// - blocks: HashMap que contiene los bloques pendientes de recibir y su BlockRequest (None si nunca se pidieron).
+/// - retries: Reintentos de los bloques pendientes cuyas peticiones vencieron.
+/// - stale_timeout: Tiempo que debe pasar para que una peticion de bloque sea considerada como vencida.
+/// - session_path: Archivo donde se guarda la sesion de descarga (los reintentos) entre ejecuciones.
+/// - not_found: Cantidad de notfound que respondieron los peers a cada bloque pendiente.
+/// - max_not_found_retries: Cantidad de veces que se vuelve a pedir un bloque al que los peers responden notfound.
+/// - nudge_sender: Sender para despertar a la tarea de los bloques pendientes del maintenance_loop cuando se agrega un bloque y no habia ninguno pendiente.
 pub struct PendingBlocks {
-    blocks: HashMap<Vec<u8>, u64>,
-    stale_time: u64,
+    blocks: HashMap<Vec<u8>, Option<BlockRequest>>,
+    retries: HashMap<Vec<u8>, BlockRetry>,
+    stale_timeout: Duration,
+    session_path: String,
+    not_found: HashMap<Vec<u8>, u32>,
+    max_not_found_retries: u32,
+    nudge_sender: Option<mpsc::Sender<MaintenanceAction>>,
 }
 
 impl PendingBlocks {
     #[must_use]
-    /// Inicializa la estructura.
-    pub fn new(store_path: &String, headers: &Vec<BlockHeader>) -> Arc<Mutex<Self>> {
+    /// Inicializa la estructura con los bloques posteriores al START_DATE_IBD que no estan guardados en disco (segun is_stored).
+    /// Si en el store se importo un snapshot de las UTXO, solo los posteriores a su bloque.
+    /// Se revisa el disco y no el flag block_downloaded ya que los headers restaurados del backup siempre lo tienen en true.
+    /// Restaura los reintentos de la sesion de descarga guardada en el store (ver save_session), descartando los de
+    /// los bloques que ya estan en disco: los bloques completos nunca se vuelven a pedir.
+    pub fn new(
+        store_path: &String,
+        headers: &Vec<BlockHeader>,
+        is_stored: impl Fn(&[u8]) -> bool,
+    ) -> Arc<Mutex<Self>> {
         let mut blocks = HashMap::new();
-        let starting_index = calculate_index_from_timestamp(headers, START_DATE_IBD) + 1;
+        let mut starting_index = calculate_index_from_timestamp(headers, START_DATE_IBD) + 1;
+        if let Some(base_hash) = imported_base(store_path) {
+            if let Some(base_index) = headers
+                .iter()
+                .position(|header| *header.hash() == base_hash)
+            {
+                starting_index = starting_index.max(base_index + 1);
+            }
+        }
 
         for header in headers.iter().skip(starting_index) {
-            let path = format!("{}/blocks/{}.bin", store_path, header.hash_as_string());
-
-            if !Path::new(&path).exists() {
-                blocks.insert(header.hash().clone(), 0_u64);
+            if !is_stored(header.hash()) {
+                blocks.insert(header.hash().clone(), None);
             }
         }
 
+        let session_path = format!("{}/pending_blocks.bin", store_path);
+        let retries = restore_session(&session_path)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(block_hash, _)| blocks.contains_key(block_hash))
+            .collect();
+
         Arc::new(Mutex::new(Self {
             blocks,
-            stale_time: 5,
+            retries,
+            stale_timeout: Duration::from_secs(DEFAULT_STALE_BLOCK_TIMEOUT),
+            session_path,
+            not_found: HashMap::new(),
+            max_not_found_retries: DEFAULT_MAX_NOTFOUND_RETRIES,
+            nudge_sender: None,
         }))
     }
 
-    /// Agrega un bloque a la lista de bloques pendientes de recibir con el timestamp actual.
+    /// Guarda en el store la sesion de descarga: los reintentos y el ultimo peer que fallo de cada bloque pendiente,
+    /// para que al reiniciar no se le vuelvan a pedir primero al mismo peer.
+    /// Los bloques ya descargados no se guardan, se deducen de los archivos en disco.
+    pub fn save_session(&self) -> Result<(), CustomError> {
+        let mut buffer = vec![DOWNLOAD_SESSION_VERSION];
+        for (block_hash, retry) in &self.retries {
+            buffer.extend(block_hash);
+            buffer.extend(retry.count.to_le_bytes());
+            match retry.last_failed_peer {
+                Some(peer) => {
+                    buffer.push(1);
+                    buffer.extend(peer.ip().octets());
+                    buffer.extend(peer.port().to_be_bytes());
+                }
+                None => buffer.extend([0; 19]),
+            }
+        }
+        fs::write(&self.session_path, buffer)?;
+        Ok(())
+    }
+
+    /// Cambia el tiempo que debe pasar para que una peticion de bloque sea considerada como vencida.
+    pub fn set_stale_timeout(&mut self, stale_timeout: Duration) {
+        self.stale_timeout = stale_timeout;
+    }
+
+    /// Cambia la cantidad de veces que se vuelve a pedir un bloque al que los peers responden notfound.
+    pub fn set_max_not_found_retries(&mut self, max_not_found_retries: u32) {
+        self.max_not_found_retries = max_not_found_retries;
+    }
+
+    /// Establece el sender por el que se avisa al maintenance_loop que se agrego un bloque cuando no habia ninguno pendiente.
+    pub fn set_nudge_sender(&mut self, nudge_sender: mpsc::Sender<MaintenanceAction>) {
+        self.nudge_sender = Some(nudge_sender);
+    }
+
+    /// Registra que un peer respondio notfound a la peticion de un bloque y devuelve que hacer con el (ver NotFoundAction):
+    /// se vuelve a pedir hasta max_not_found_retries veces, y el siguiente notfound indica que se deje de pedir.
+    /// Los bloques que no estan pendientes no se vuelven a pedir, ya no se esperan.
+    pub fn block_not_found(&mut self, block_hash: &Vec<u8>) -> NotFoundAction {
+        if !self.is_block_pending(block_hash) {
+            return NotFoundAction::Ignore;
+        }
+        let count = self.not_found.entry(block_hash.clone()).or_default();
+        *count += 1;
+        match *count {
+            count if count <= self.max_not_found_retries => NotFoundAction::Retry,
+            count if count == self.max_not_found_retries + 1 => NotFoundAction::GiveUp,
+            _ => NotFoundAction::Ignore,
+        }
+    }
+
+    /// Agrega un bloque a la lista de bloques pendientes de recibir con el momento actual.
     pub fn append_block(&mut self, block_hash: Vec<u8>) -> Result<(), CustomError> {
-        let current_time = get_current_timestamp()?;
-        self.blocks.insert(block_hash, current_time);
+        self.append_block_from(block_hash, None)
+    }
+
+    /// Agrega un bloque a la lista de bloques pendientes de recibir con el momento actual y el peer al que se le pidio.
+    pub fn append_block_from(
+        &mut self,
+        block_hash: Vec<u8>,
+        peer: Option<SocketAddrV6>,
+    ) -> Result<(), CustomError> {
+        let was_empty = self.blocks.is_empty();
+        let request = BlockRequest {
+            requested_at: Instant::now(),
+            peer,
+        };
+        self.blocks.insert(block_hash, Some(request));
+        if was_empty {
+            if let Some(nudge_sender) = &self.nudge_sender {
+                // si el maintenance_loop termino no hay a quien avisarle
+                let _ =
+                    nudge_sender.send(MaintenanceAction::RunNow(PENDING_BLOCKS_TASK.to_string()));
+            }
+        }
         Ok(())
     }
 
-    /// Elimina un bloque de la lista de bloques pendientes de recibir.
+    /// Elimina un bloque de la lista de bloques pendientes de recibir, junto a sus reintentos y sus notfound.
     pub fn remove_block(&mut self, block_hash: &Vec<u8>) -> Result<(), CustomError> {
         self.blocks.remove(block_hash);
+        self.retries.remove(block_hash);
+        self.not_found.remove(block_hash);
         Ok(())
     }
 
     /// Elimina todos los bloques de la lista de bloques pendientes de recibir.
     pub fn drain(&mut self) {
         self.blocks.drain();
+        self.retries.clear();
+        self.not_found.clear();
     }
 
     pub fn len(&self) -> usize {
         self.blocks.len()
     }
 
-    /// Devuelve los bloques pendientes de recibir que ya vencieron.
-    pub fn get_stale_requests(&mut self) -> Result<Vec<Vec<u8>>, CustomError> {
-        let mut to_remove = Vec::new();
+    /// Devuelve los hashes de los bloques pendientes de recibir.
+    pub fn block_hashes(&self) -> Vec<Vec<u8>> {
+        self.blocks.keys().cloned().collect()
+    }
+
+    /// Devuelve los bloques pendientes de recibir que ya vencieron, junto al peer al que se le habian pedido, y los quita de la lista.
+    pub fn get_stale_requests(
+        &mut self,
+    ) -> Result<Vec<(Vec<u8>, Option<SocketAddrV6>)>, CustomError> {
+        Ok(self.get_stale_requests_at(Instant::now()))
+    }
 
-        for (block_hash, timestamp) in &self.blocks {
-            if *timestamp + self.stale_time < get_current_timestamp()? {
-                to_remove.push(block_hash.clone());
-            }
-        }
+    /// Devuelve los bloques pendientes que en el momento now ya vencieron, junto al peer al que se le habian pedido, y los quita de la lista.
+    /// Cada peticion vencida que se le habia hecho a un peer suma un reintento al bloque.
+    /// Los bloques que nunca se pidieron (por ejemplo al reiniciar el nodo) vuelven con el ultimo peer que fallo, si hay uno.
+    pub fn get_stale_requests_at(&mut self, now: Instant) -> Vec<(Vec<u8>, Option<SocketAddrV6>)> {
+        let stale_requests: Vec<(Vec<u8>, Option<SocketAddrV6>)> = self
+            .blocks
+            .iter()
+            .filter(|(_, request)| self.time_to_stale(**request, now).is_zero())
+            .map(|(block_hash, request)| (block_hash.clone(), request.and_then(|r| r.peer)))
+            .collect();
+
+        stale_requests
+            .into_iter()
+            .map(|(block_hash, peer)| {
+                self.blocks.remove(&block_hash);
+                if let Some(peer) = peer {
+                    let retry = self.retries.entry(block_hash.clone()).or_default();
+                    retry.count += 1;
+                    retry.last_failed_peer = Some(peer);
+                }
+                let peer = peer.or_else(|| self.last_failed_peer(&block_hash));
+                (block_hash, peer)
+            })
+            .collect()
+    }
 
-        for block_hash in &to_remove {
-            self.blocks.remove(block_hash);
+    /// Devuelve los reintentos de un bloque pendiente (cero si ninguna peticion vencio).
+    pub fn retries(&self, block_hash: &Vec<u8>) -> u32 {
+        self.retries.get(block_hash).map_or(0, |retry| retry.count)
+    }
+
+    /// Devuelve el ultimo peer que no envio a tiempo un bloque pendiente.
+    pub fn last_failed_peer(&self, block_hash: &Vec<u8>) -> Option<SocketAddrV6> {
+        self.retries.get(block_hash)?.last_failed_peer
+    }
+
+    /// Devuelve el peer al que se le pidio un bloque pendiente, o None si no esta pendiente o no se pidio a un peer en particular.
+    pub fn requested_from(&self, block_hash: &Vec<u8>) -> Option<SocketAddrV6> {
+        self.blocks.get(block_hash).copied().flatten()?.peer
+    }
+
+    /// Devuelve cuanto esperar desde now hasta la proxima revision de los bloques pendientes.
+    /// Si no hay bloques pendientes espera IDLE_PENDING_BLOCKS_POLL, si no espera hasta que venza
+    /// la primera peticion, como maximo poll_interval.
+    pub fn next_check_in(&self, now: Instant, poll_interval: Duration) -> Duration {
+        let first_stale = self
+            .blocks
+            .values()
+            .map(|request| self.time_to_stale(*request, now))
+            .min();
+
+        match first_stale {
+            Some(first_stale) => first_stale.min(poll_interval),
+            None => IDLE_PENDING_BLOCKS_POLL,
         }
+    }
 
-        Ok(to_remove)
+    /// Devuelve cuanto falta desde now para que venza una peticion (cero si ya vencio).
+    /// Los bloques que nunca se pidieron ya estan vencidos.
+    fn time_to_stale(&self, request: Option<BlockRequest>, now: Instant) -> Duration {
+        match request {
+            Some(request) => {
+                (request.requested_at + self.stale_timeout).saturating_duration_since(now)
+            }
+            None => Duration::ZERO,
+        }
     }
 
     /// Devuelve true si el bloque esta en la lista de bloques pendientes de recibir.
@@ -92,16 +318,44 @@ impl PendingBlocks {
     }
 }
 
+/// Lee los reintentos de la sesion de descarga guardada con save_session.
+/// Devuelve None si el archivo no existe, es de otra version o esta corrupto: la sesion se descarta entera.
+fn restore_session(path: &str) -> Option<HashMap<Vec<u8>, BlockRetry>> {
+    let buffer = fs::read(path).ok()?;
+    let mut parser = BufferParser::new(buffer);
+    if parser.extract_u8().ok()? != DOWNLOAD_SESSION_VERSION
+        || parser.len() % STORED_RETRY_SIZE != 0
+    {
+        return None;
+    }
+
+    let mut retries = HashMap::new();
+    while !parser.is_empty() {
+        let block_hash = parser.extract_buffer(32).ok()?.to_vec();
+        let count = parser.extract_u32().ok()?;
+        let has_peer = parser.extract_u8().ok()? == 1;
+        let address = parser.extract_address().ok()?;
+        let last_failed_peer = has_peer.then_some(address);
+        retries.insert(
+            block_hash,
+            BlockRetry {
+                count,
+                last_failed_peer,
+            },
+        );
+    }
+    Some(retries)
+}
+
 #[cfg(test)]
 mod tests {
 
-    use std::{thread, time::Duration};
-
     use super::*;
+    use crate::states::block_store_state::BlockStore;
 
     #[test]
     fn pending_blocks_creation() {
-        let pending_blocks = PendingBlocks::new(&"".to_string(), &vec![]);
+        let pending_blocks = PendingBlocks::new(&"".to_string(), &vec![], |_| false);
         let pending_blocks = pending_blocks.lock().unwrap();
 
         assert_eq!(pending_blocks.is_empty(), true);
@@ -109,7 +363,7 @@ mod tests {
 
     #[test]
     fn append_block() {
-        let pending_blocks = PendingBlocks::new(&"".to_string(), &vec![]);
+        let pending_blocks = PendingBlocks::new(&"".to_string(), &vec![], |_| false);
         let mut pending_blocks = pending_blocks.lock().unwrap();
 
         let block_hash = vec![1, 2, 3, 4, 5];
@@ -121,7 +375,7 @@ mod tests {
 
     #[test]
     fn remove_block() {
-        let pending_blocks = PendingBlocks::new(&"".to_string(), &vec![]);
+        let pending_blocks = PendingBlocks::new(&"".to_string(), &vec![], |_| false);
         let mut pending_blocks = pending_blocks.lock().unwrap();
 
         let block_hash = vec![1, 2, 3, 4, 5];
@@ -135,7 +389,7 @@ mod tests {
 
     #[test]
     fn drain() {
-        let pending_blocks = PendingBlocks::new(&"".to_string(), &vec![]);
+        let pending_blocks = PendingBlocks::new(&"".to_string(), &vec![], |_| false);
         let mut pending_blocks = pending_blocks.lock().unwrap();
 
         let block_hash = vec![1, 2, 3, 4, 5];
@@ -149,22 +403,127 @@ mod tests {
     }
 
     #[test]
-    fn get_stale_requests() {
-        let pending_blocks = PendingBlocks::new(&"".to_string(), &vec![]);
+    fn block_not_found_is_retried_up_to_the_max() {
+        let pending_blocks = PendingBlocks::new(&"".to_string(), &vec![], |_| false);
         let mut pending_blocks = pending_blocks.lock().unwrap();
+        pending_blocks.set_max_not_found_retries(2);
 
         let block_hash = vec![1, 2, 3, 4, 5];
+        assert_eq!(
+            pending_blocks.block_not_found(&block_hash),
+            NotFoundAction::Ignore
+        );
 
         pending_blocks.append_block(block_hash.clone()).unwrap();
+        let actions: Vec<NotFoundAction> = (0..4)
+            .map(|_| pending_blocks.block_not_found(&block_hash))
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                NotFoundAction::Retry,
+                NotFoundAction::Retry,
+                NotFoundAction::GiveUp,
+                NotFoundAction::Ignore
+            ]
+        );
+        assert!(pending_blocks.is_block_pending(&block_hash));
+
+        // al recibir el bloque se olvidan sus notfound
+        pending_blocks.remove_block(&block_hash).unwrap();
+        pending_blocks.append_block(block_hash.clone()).unwrap();
+        assert_eq!(
+            pending_blocks.block_not_found(&block_hash),
+            NotFoundAction::Retry
+        );
+    }
+
+    #[test]
+    fn get_stale_requests() {
+        let pending_blocks = PendingBlocks::new(&"".to_string(), &vec![], |_| false);
+        let mut pending_blocks = pending_blocks.lock().unwrap();
+        pending_blocks.set_stale_timeout(Duration::from_secs(5));
 
-        let stale_requests = pending_blocks.get_stale_requests().unwrap();
-        assert_eq!(stale_requests.len(), 0);
-        pending_blocks.stale_time = 0;
-        thread::sleep(Duration::from_secs(1));
+        let block_hash = vec![1, 2, 3, 4, 5];
+        let peer = SocketAddrV6::new(std::net::Ipv6Addr::LOCALHOST, 18333, 0, 0);
+        pending_blocks
+            .append_block_from(block_hash.clone(), Some(peer))
+            .unwrap();
+        assert_eq!(pending_blocks.requested_from(&block_hash), Some(peer));
+        let requested_at = pending_blocks.blocks[&block_hash].unwrap().requested_at;
+
+        let now = requested_at + Duration::from_secs(4);
+        assert_eq!(pending_blocks.get_stale_requests_at(now).len(), 0);
+
+        let now = requested_at + Duration::from_secs(5);
+        let stale_requests = pending_blocks.get_stale_requests_at(now);
+        assert_eq!(stale_requests, vec![(block_hash, Some(peer))]);
+        assert!(pending_blocks.is_empty());
+    }
 
-        let stale_requests = pending_blocks.get_stale_requests().unwrap();
-        assert_eq!(stale_requests.len(), 1);
-        assert_eq!(stale_requests[0], block_hash);
+    #[test]
+    fn next_check_adapts_to_pending_blocks() {
+        let pending_blocks = PendingBlocks::new(&"".to_string(), &vec![], |_| false);
+        let mut pending_blocks = pending_blocks.lock().unwrap();
+        pending_blocks.set_stale_timeout(Duration::from_secs(5));
+        let poll_interval = Duration::from_secs(1);
+        let start = Instant::now();
+
+        // sin bloques pendientes se revisa con poca frecuencia
+        assert_eq!(
+            pending_blocks.next_check_in(start, poll_interval),
+            IDLE_PENDING_BLOCKS_POLL
+        );
+
+        // con bloques pendientes se revisa cada poll_interval, o antes si alguno vence
+        let request = BlockRequest {
+            requested_at: start,
+            peer: None,
+        };
+        pending_blocks.blocks.insert(vec![1], Some(request));
+        assert_eq!(
+            pending_blocks.next_check_in(start, poll_interval),
+            poll_interval
+        );
+        assert_eq!(
+            pending_blocks.next_check_in(start + Duration::from_millis(4500), poll_interval),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            pending_blocks.next_check_in(start + Duration::from_secs(6), poll_interval),
+            Duration::ZERO
+        );
+
+        // un bloque que nunca se pidio ya esta vencido
+        pending_blocks.blocks.insert(vec![2], None);
+        assert_eq!(
+            pending_blocks.next_check_in(start, poll_interval),
+            Duration::ZERO
+        );
+        assert_eq!(
+            pending_blocks.get_stale_requests_at(start),
+            vec![(vec![2], None)]
+        );
+    }
+
+    #[test]
+    fn append_block_nudges_when_empty() {
+        let pending_blocks = PendingBlocks::new(&"".to_string(), &vec![], |_| false);
+        let mut pending_blocks = pending_blocks.lock().unwrap();
+        let (nudge_sender, nudge_receiver) = mpsc::channel();
+        pending_blocks.set_nudge_sender(nudge_sender);
+
+        pending_blocks.append_block(vec![1]).unwrap();
+        pending_blocks.append_block(vec![2]).unwrap();
+        let nudges: Vec<MaintenanceAction> = nudge_receiver.try_iter().collect();
+        assert!(matches!(
+            nudges.as_slice(),
+            [MaintenanceAction::RunNow(name)] if name == PENDING_BLOCKS_TASK
+        ));
+
+        pending_blocks.drain();
+        pending_blocks.append_block(vec![3]).unwrap();
+        assert_eq!(nudge_receiver.try_iter().count(), 1);
     }
 
     #[test]
@@ -196,12 +555,80 @@ mod tests {
             broadcasted: true,
         };
 
-        let pending_blocks =
-            PendingBlocks::new(&"".to_string(), &vec![old_header, lost_header.clone()]);
+        let pending_blocks = PendingBlocks::new(
+            &"".to_string(),
+            &vec![old_header, lost_header.clone()],
+            |_| false,
+        );
 
         let pending_blocks = pending_blocks.lock().unwrap();
         assert_eq!(pending_blocks.is_empty(), false);
         assert_eq!(pending_blocks.len(), 1);
         assert_eq!(pending_blocks.is_block_pending(&lost_header.hash), true);
     }
+
+    #[test]
+    fn restore_half_finished_download_session() {
+        let store_path = "tests/store_pending_blocks_session".to_string();
+        let _ = fs::remove_dir_all(&store_path);
+
+        let headers: Vec<BlockHeader> = (0..4_u8)
+            .map(|index| BlockHeader {
+                bits: 0,
+                nonce: 0,
+                prev_block_hash: vec![],
+                timestamp: START_DATE_IBD + index as u32,
+                version: 0,
+                hash: vec![index; 32],
+                merkle_root: vec![],
+                block_downloaded: false,
+                broadcasted: true,
+            })
+            .collect();
+        let peer = SocketAddrV6::new(std::net::Ipv6Addr::LOCALHOST, 18333, 0, 0);
+
+        let mut block_store = BlockStore::open(&store_path).unwrap();
+        let pending_blocks =
+            PendingBlocks::new(&store_path, &headers, |hash| block_store.contains(hash));
+        let mut pending_blocks = pending_blocks.lock().unwrap();
+        assert_eq!(pending_blocks.len(), 3);
+        for header in headers.iter().skip(1) {
+            pending_blocks
+                .append_block_from(header.hash.clone(), Some(peer))
+                .unwrap();
+        }
+        let now = Instant::now() + Duration::from_secs(DEFAULT_STALE_BLOCK_TIMEOUT);
+        assert_eq!(pending_blocks.get_stale_requests_at(now).len(), 3);
+        pending_blocks.save_session().unwrap();
+        drop(pending_blocks);
+
+        // el bloque 1 se guardo en disco pero la sesion no llego a registrarlo: el disco gana
+        block_store.append(&headers[1].hash, &[]).unwrap();
+
+        let restored = PendingBlocks::new(&store_path, &headers, |hash| block_store.contains(hash));
+        let mut restored = restored.lock().unwrap();
+        assert_eq!(restored.len(), 2);
+        assert!(!restored.is_block_pending(&headers[1].hash));
+        assert_eq!(restored.retries(&headers[1].hash), 0);
+        assert_eq!(restored.retries(&headers[2].hash), 1);
+        assert_eq!(restored.last_failed_peer(&headers[3].hash), Some(peer));
+
+        let mut stale_requests = restored.get_stale_requests_at(Instant::now());
+        stale_requests.sort();
+        assert_eq!(
+            stale_requests,
+            vec![
+                (headers[2].hash.clone(), Some(peer)),
+                (headers[3].hash.clone(), Some(peer)),
+            ]
+        );
+
+        // una sesion de otra version se descarta
+        fs::write(format!("{}/pending_blocks.bin", store_path), [0]).unwrap();
+        let discarded =
+            PendingBlocks::new(&store_path, &headers, |hash| block_store.contains(hash));
+        assert_eq!(discarded.lock().unwrap().retries(&headers[2].hash), 0);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
 }
@@ -1,99 +1,500 @@
 use std::{
-    collections::{hash_map, HashMap},
+    collections::{hash_map, HashMap, HashSet},
+    io::Read,
+    net::SocketAddrV6,
+    time::{Duration, Instant},
     vec,
 };
 
 use crate::{
     error::CustomError,
-    messages::{block::Block, transaction::Transaction},
-    structs::movement::Movement,
+    message::Message,
+    messages::{
+        block::Block,
+        compact_block::{CompactBlock, PartialBlock},
+        transaction::Transaction,
+    },
+    parser::BufferParser,
+    structs::{movement::Movement, outpoint::OutPoint},
+    utils::{get_current_timestamp, open_file, write_file_atomic, OpenMode},
     wallet::Wallet,
 };
 
 use super::utxo_state::UTXO;
 
-/// PendingTxs es una estructura que contiene los elementos necesarios para manejar las transacciones pendientes.
+/// Tiempo en segundos luego del cual una transaccion pendiente restaurada se descarta, igual que en el mempool de Bitcoin Core.
+const PENDING_TX_EXPIRY: u64 = 14 * 24 * 60 * 60;
+
+/// Tiempo en segundos durante el cual se considera que una transaccion anunciada por un peer sigue propagandose.
+const RECENTLY_RELAYED_WINDOW: u64 = 60 * 60;
+
+/// Cantidad maxima de veces que se reenvia una transaccion propia a peers nuevos antes de avisar que puede no haberse propagado.
+const MAX_BROADCAST_ESCALATIONS: u32 = 2;
+
+/// Cantidad de bloques por defecto que se espera a que se confirme una transaccion que creamos antes de volver a enviarla.
+pub const DEFAULT_REBROADCAST_BLOCKS: usize = 3;
+
+/// PendingTx es una transaccion pendiente junto al timestamp en el que se recibio
+/// y el de la ultima vez que un peer nos la volvio a enviar (no se guarda en disco, al restaurar es received_at).
+/// Si es una transaccion que creamos y se esta verificando su propagacion, broadcast contiene su TxBroadcast.
+/// Si es una transaccion que creamos, own contiene las alturas en las que se envio (las relayed por los peers no lo tienen).
+struct PendingTx {
+    transaction: Transaction,
+    received_at: u64,
+    last_seen_at: u64,
+    broadcast: Option<TxBroadcast>,
+    own: Option<OwnTx>,
+}
+
+/// OwnTx registra los envios de una transaccion que creamos, para reenviarla si no se confirma.
+/// No se guarda en disco: al generar las UTXO el NodeState la vuelve a registrar con la altura de ese momento (ver mark_own).
 /// Los elementos son:
-/// - tx_set: HashMap que contiene los hashes de las transacciones pendientes con su Transaction.
-pub struct PendingTxs {
-    tx_set: HashMap<Vec<u8>, Transaction>,
+/// - first_broadcast_height: Altura de la cadena cuando se envio por primera vez.
+/// - last_broadcast_height: Altura de la cadena cuando se envio por ultima vez, en el primer envio o en un reenvio.
+/// - sent_to: Peers a los que se la enviamos, los unicos cuyo reject se tiene en cuenta.
+struct OwnTx {
+    first_broadcast_height: usize,
+    last_broadcast_height: usize,
+    sent_to: HashSet<SocketAddrV6>,
 }
 
-impl Default for PendingTxs {
-    fn default() -> Self {
-        PendingTxs::new()
+/// TxBroadcast registra el envio de una transaccion propia para verificar que se propague por la red (no se guarda en disco).
+/// La transaccion se considera vista en la red cuando la anuncia un peer al que no se la enviamos.
+/// Los elementos son:
+/// - pushed_to: Peers a los que les enviamos la transaccion.
+/// - announced_by: Peers que la anunciaron sin que se la hayamos enviado.
+/// - last_push_at: Momento del ultimo envio.
+/// - escalations: Cantidad de veces que se reenvio a peers nuevos porque nadie la anuncio.
+/// - possibly_stuck: Se agotaron los reenvios sin que nadie la anuncie.
+struct TxBroadcast {
+    pushed_to: HashSet<SocketAddrV6>,
+    announced_by: HashSet<SocketAddrV6>,
+    last_push_at: Instant,
+    escalations: u32,
+    possibly_stuck: bool,
+}
+
+impl TxBroadcast {
+    fn status(&self) -> PropagationStatus {
+        if !self.announced_by.is_empty() {
+            PropagationStatus::SeenOnNetwork
+        } else if self.possibly_stuck {
+            PropagationStatus::PossiblyStuck
+        } else {
+            PropagationStatus::Sent
+        }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// PropagationStatus es el estado de la propagacion de una transaccion propia:
+/// - Sent: Se envio a algunos peers y todavia no la anuncio ningun otro.
+/// - SeenOnNetwork: La anuncio un peer al que no se la enviamos, por lo que se propago.
+/// - PossiblyStuck: Se reenvio a otros peers sin que nadie la anuncie, puede no haberse propagado.
+pub enum PropagationStatus {
+    Sent,
+    SeenOnNetwork,
+    PossiblyStuck,
+}
+
+#[derive(Debug, PartialEq)]
+/// BroadcastCheck es el resultado de revisar una transaccion propia que nadie anuncio dentro del timeout, contiene su hash:
+/// - Escalate: Hay que enviarla a los peers indicados, que todavia no la recibieron.
+/// - PossiblyStuck: Se agotaron los reenvios (o no quedan peers nuevos), puede no haberse propagado.
+pub enum BroadcastCheck {
+    Escalate(Vec<u8>, Vec<SocketAddrV6>),
+    PossiblyStuck(Vec<u8>),
+}
+
+/// PendingTxs es una estructura que contiene los elementos necesarios para manejar las transacciones pendientes.
+/// Los elementos son:
+/// - tx_set: HashMap que contiene los hashes de las transacciones pendientes con su PendingTx.
+/// - path: Path del archivo donde se guardan las transacciones pendientes.
+pub struct PendingTxs {
+    tx_set: HashMap<Vec<u8>, PendingTx>,
+    path: String,
+}
+
 impl PendingTxs {
-    /// Inicializa la estructura.
-    pub fn new() -> Self {
-        PendingTxs {
+    /// Inicializa las transacciones pendientes.
+    /// Si el archivo donde se guardan no existe, se crea.
+    /// Si el archivo existe, se restauran las transacciones que no hayan expirado.
+    pub fn new(path: String) -> Result<Self, CustomError> {
+        let mut pending_txs = Self {
             tx_set: HashMap::new(),
+            path,
+        };
+        pending_txs.restore()?;
+        Ok(pending_txs)
+    }
+
+    fn restore(&mut self) -> Result<(), CustomError> {
+        let mut file = open_file(&self.path, OpenMode::ReadCreate)?;
+        let mut buffer = vec![];
+        file.read_to_end(&mut buffer)?;
+        let mut parser = BufferParser::new(buffer);
+
+        let now = get_current_timestamp()?;
+        while !parser.is_empty() {
+            let received_at = parser.extract_u64()?;
+            let transaction = Transaction::parse_from_parser(&mut parser)?;
+            if received_at + PENDING_TX_EXPIRY >= now {
+                self.tx_set.insert(
+                    transaction.hash(),
+                    PendingTx {
+                        transaction,
+                        received_at,
+                        last_seen_at: received_at,
+                        broadcast: None,
+                        own: None,
+                    },
+                );
+            }
         }
+
+        Ok(())
+    }
+
+    fn save(&self) -> Result<(), CustomError> {
+        let mut buffer = vec![];
+        for pending_tx in self.tx_set.values() {
+            buffer.extend(pending_tx.received_at.to_le_bytes());
+            buffer.extend(pending_tx.transaction.serialize());
+        }
+
+        write_file_atomic(&self.path, &buffer)
+    }
+
+    /// Devuelve la cantidad de transacciones pendientes.
+    pub fn len(&self) -> usize {
+        self.tx_set.len()
+    }
+
+    /// Devuelve true si no hay transacciones pendientes.
+    pub fn is_empty(&self) -> bool {
+        self.tx_set.is_empty()
     }
 
     /// Agrega una transaccion a la lista de transacciones pendientes, devuelve true si es una transaccion que no teniamos.
-    pub fn append_pending_tx(&mut self, transaction: Transaction) -> bool {
+    /// Si es nueva, guarda las transacciones pendientes. Si ya la teniamos, registra que se volvio a ver.
+    pub fn append_pending_tx(&mut self, transaction: Transaction) -> Result<bool, CustomError> {
         let tx_hash = transaction.hash();
+        let now = get_current_timestamp()?;
+
+        match self.tx_set.entry(tx_hash) {
+            hash_map::Entry::Vacant(e) => {
+                e.insert(PendingTx {
+                    transaction,
+                    received_at: now,
+                    last_seen_at: now,
+                    broadcast: None,
+                    own: None,
+                });
+                self.save()?;
+                Ok(true)
+            }
+            hash_map::Entry::Occupied(mut e) => {
+                e.get_mut().last_seen_at = now;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Elimina una transaccion pendiente y guarda las transacciones pendientes.
+    /// Devuelve la transaccion eliminada, o None si no estaba.
+    pub fn remove_pending_tx(
+        &mut self,
+        tx_hash: &Vec<u8>,
+    ) -> Result<Option<Transaction>, CustomError> {
+        let Some(pending_tx) = self.tx_set.remove(tx_hash) else { return Ok(None) };
+        self.save()?;
+        Ok(Some(pending_tx.transaction))
+    }
+
+    /// Elimina una transaccion pendiente junto a las pendientes que gastan sus outputs, directa o indirectamente,
+    /// que sin ella no pueden confirmarse. Guarda las transacciones pendientes una sola vez.
+    /// Devuelve los hashes de las transacciones eliminadas, empezando por la indicada (vacio si no estaba).
+    pub fn remove_with_descendants(
+        &mut self,
+        tx_hash: &Vec<u8>,
+    ) -> Result<Vec<Vec<u8>>, CustomError> {
+        if !self.tx_set.contains_key(tx_hash) {
+            return Ok(vec![]);
+        }
+        let mut removed = vec![tx_hash.clone()];
+        let mut index = 0;
+        while index < removed.len() {
+            let parent = removed[index].clone();
+            let children: Vec<Vec<u8>> = self
+                .tx_set
+                .iter()
+                .filter(|(hash, pending_tx)| {
+                    !removed.contains(hash)
+                        && pending_tx
+                            .transaction
+                            .inputs
+                            .iter()
+                            .any(|input| input.previous_output.hash == parent)
+                })
+                .map(|(hash, _)| hash.clone())
+                .collect();
+            removed.extend(children);
+            index += 1;
+        }
 
-        if let hash_map::Entry::Vacant(e) = self.tx_set.entry(tx_hash) {
-            e.insert(transaction);
-            return true;
+        for hash in &removed {
+            self.tx_set.remove(hash);
         }
-        false
+        self.save()?;
+        Ok(removed)
+    }
+
+    /// Devuelve true si la transaccion pendiente se recibio o se volvio a ver en la ultima RECENTLY_RELAYED_WINDOW.
+    pub fn was_relayed_recently(&self, tx_hash: &Vec<u8>) -> Result<bool, CustomError> {
+        let Some(pending_tx) = self.tx_set.get(tx_hash) else { return Ok(false) };
+        Ok(pending_tx.last_seen_at + RECENTLY_RELAYED_WINDOW > get_current_timestamp()?)
+    }
+
+    /// Registra que se envio una transaccion propia a los peers pushed_to, para verificar que se propague.
+    pub fn start_broadcast(
+        &mut self,
+        tx_hash: &Vec<u8>,
+        pushed_to: Vec<SocketAddrV6>,
+        now: Instant,
+    ) {
+        let Some(pending_tx) = self.tx_set.get_mut(tx_hash) else { return };
+        pending_tx.broadcast = Some(TxBroadcast {
+            pushed_to: pushed_to.into_iter().collect(),
+            announced_by: HashSet::new(),
+            last_push_at: now,
+            escalations: 0,
+            possibly_stuck: false,
+        });
+    }
+
+    /// Registra que un peer anuncio una transaccion pendiente.
+    /// Devuelve true si es una transaccion propia que se ve por primera vez en la red,
+    /// los anuncios de los peers a los que se la enviamos no cuentan.
+    pub fn announced_by(&mut self, tx_hash: &Vec<u8>, address: SocketAddrV6) -> bool {
+        let broadcast = self
+            .tx_set
+            .get_mut(tx_hash)
+            .and_then(|pending_tx| pending_tx.broadcast.as_mut());
+        let Some(broadcast) = broadcast else { return false };
+        if broadcast.pushed_to.contains(&address) {
+            return false;
+        }
+
+        let first_echo = broadcast.announced_by.is_empty();
+        broadcast.announced_by.insert(address);
+        first_echo
+    }
+
+    /// Devuelve el estado de la propagacion de una transaccion propia, o None si no se esta verificando.
+    pub fn propagation_status(&self, tx_hash: &Vec<u8>) -> Option<PropagationStatus> {
+        let pending_tx = self.tx_set.get(tx_hash)?;
+        pending_tx.broadcast.as_ref().map(TxBroadcast::status)
+    }
+
+    /// Revisa las transacciones propias que nadie anuncio en el timeout desde su ultimo envio.
+    /// Si quedan reenvios y hay peers conectados que todavia no la recibieron, se reenvia a hasta count de ellos;
+    /// si no, se marca como posiblemente trabada y no se vuelve a revisar (salvo que algun peer la anuncie).
+    pub fn check_broadcasts(
+        &mut self,
+        now: Instant,
+        timeout: Duration,
+        connected: &[SocketAddrV6],
+        count: usize,
+    ) -> Vec<BroadcastCheck> {
+        let mut checks = vec![];
+        for (tx_hash, pending_tx) in self.tx_set.iter_mut() {
+            let Some(broadcast) = pending_tx.broadcast.as_mut() else { continue };
+            if broadcast.status() != PropagationStatus::Sent
+                || now.saturating_duration_since(broadcast.last_push_at) < timeout
+            {
+                continue;
+            }
+
+            let new_peers: Vec<SocketAddrV6> = connected
+                .iter()
+                .filter(|address| !broadcast.pushed_to.contains(address))
+                .take(count)
+                .copied()
+                .collect();
+            if broadcast.escalations >= MAX_BROADCAST_ESCALATIONS || new_peers.is_empty() {
+                broadcast.possibly_stuck = true;
+                checks.push(BroadcastCheck::PossiblyStuck(tx_hash.clone()));
+            } else {
+                broadcast.pushed_to.extend(&new_peers);
+                broadcast.last_push_at = now;
+                broadcast.escalations += 1;
+                checks.push(BroadcastCheck::Escalate(tx_hash.clone(), new_peers));
+            }
+        }
+        checks
+    }
+
+    /// Registra que se envio una transaccion que creamos con la cadena en height, para reenviarla si no se confirma
+    /// (ver due_rebroadcasts). Si ya estaba registrada solo actualiza la altura del ultimo envio.
+    pub fn mark_own(&mut self, tx_hash: &Vec<u8>, height: usize) {
+        let Some(pending_tx) = self.tx_set.get_mut(tx_hash) else { return };
+        match pending_tx.own.as_mut() {
+            Some(own) => own.last_broadcast_height = height,
+            None => {
+                pending_tx.own = Some(OwnTx {
+                    first_broadcast_height: height,
+                    last_broadcast_height: height,
+                    sent_to: HashSet::new(),
+                })
+            }
+        }
+    }
+
+    /// Registra los peers a los que se envio una transaccion que creamos (ver mark_own).
+    pub fn mark_sent_to(&mut self, tx_hash: &Vec<u8>, addresses: &[SocketAddrV6]) {
+        let own = self
+            .tx_set
+            .get_mut(tx_hash)
+            .and_then(|pending_tx| pending_tx.own.as_mut());
+        let Some(own) = own else { return };
+        own.sent_to.extend(addresses);
+    }
+
+    /// Devuelve true si es una transaccion que creamos y se la enviamos al peer.
+    pub fn was_sent_to(&self, tx_hash: &Vec<u8>, address: &SocketAddrV6) -> bool {
+        self.tx_set
+            .get(tx_hash)
+            .and_then(|pending_tx| pending_tx.own.as_ref())
+            .map_or(false, |own| own.sent_to.contains(address))
+    }
+
+    /// Devuelve la altura de la cadena cuando se envio por primera vez una transaccion que creamos,
+    /// o None si no es una transaccion pendiente que creamos.
+    pub fn first_broadcast_height(&self, tx_hash: &Vec<u8>) -> Option<usize> {
+        let pending_tx = self.tx_set.get(tx_hash)?;
+        pending_tx
+            .own
+            .as_ref()
+            .map(|own| own.first_broadcast_height)
+    }
+
+    /// Devuelve los hashes de las transacciones que creamos que siguen sin confirmarse interval bloques despues de su
+    /// ultimo envio, y registra que se vuelven a enviar con la cadena en height.
+    /// Asi cada transaccion se reenvia una unica vez por intervalo, mientras siga pendiente.
+    pub fn due_rebroadcasts(&mut self, height: usize, interval: usize) -> Vec<Vec<u8>> {
+        let mut due = vec![];
+        for (tx_hash, pending_tx) in self.tx_set.iter_mut() {
+            let Some(own) = pending_tx.own.as_mut() else { continue };
+            if height >= own.last_broadcast_height + interval {
+                own.last_broadcast_height = height;
+                due.push(tx_hash.clone());
+            }
+        }
+        due
+    }
+
+    /// Devuelve los outpoints que gastan las transacciones pendientes,
+    /// que no deben usarse como inputs de una transaccion nueva.
+    pub fn spent_outpoints(&self) -> HashSet<&OutPoint> {
+        self.tx_set
+            .values()
+            .flat_map(|pending_tx| &pending_tx.transaction.inputs)
+            .map(|tx_in| &tx_in.previous_output)
+            .collect()
+    }
+
+    /// Devuelve true si el outpoint es un output de alguna transaccion pendiente.
+    pub fn contains_output(&self, outpoint: &OutPoint) -> bool {
+        self.tx_set.get(&outpoint.hash).map_or(false, |pending_tx| {
+            pending_tx.transaction.outputs.len() > outpoint.index as usize
+        })
     }
 
     /// Actualiza la lista de transacciones pendientes, eliminando las transacciones que esten en el bloque.
+    /// Si se elimino alguna, guarda las transacciones pendientes.
     pub fn update_pending_tx(&mut self, block: &Block) -> Result<(), CustomError> {
+        let mut updated = false;
         for tx in &block.transactions {
-            if self.tx_set.contains_key(&tx.hash()) {
-                self.tx_set.remove(&tx.hash());
-            }
+            updated |= self.tx_set.remove(&tx.hash()).is_some();
         }
 
+        if updated {
+            self.save()?;
+        }
         Ok(())
     }
 
+    /// Devuelve el timestamp en el que se recibio la transaccion pendiente mas antigua.
+    pub fn oldest_received_at(&self) -> Option<u64> {
+        self.tx_set
+            .values()
+            .map(|pending_tx| pending_tx.received_at)
+            .min()
+    }
+
     /// Devuelve las transacciones pendientes que pertenecen a la wallet.
     pub fn from_wallet(&self, wallet: &Wallet, utxo: &UTXO) -> Result<Vec<Movement>, CustomError> {
-        let pubkey_hash = wallet.get_pubkey_hash()?;
+        let pubkey_hashes = wallet.get_pubkey_hash_set()?;
         let mut pending_movements = vec![];
 
-        for tx in self.tx_set.values() {
-            if let Some(mov) = tx.get_movement(&pubkey_hash, utxo)? {
+        for pending_tx in self.tx_set.values() {
+            if let Some(mov) = pending_tx.transaction.get_movement(&pubkey_hashes, utxo)? {
                 pending_movements.push(mov);
             }
         }
         Ok(pending_movements)
     }
 
+    /// Devuelve los hashes de todas las transacciones pendientes.
+    pub fn get_all_hashes(&self) -> Vec<Vec<u8>> {
+        self.tx_set.keys().cloned().collect()
+    }
+
     pub fn get_pending_tx(&self, tx_hash: &Vec<u8>) -> Option<Transaction> {
-        self.tx_set.get(tx_hash).cloned()
+        self.tx_set
+            .get(tx_hash)
+            .map(|pending_tx| pending_tx.transaction.clone())
+    }
+
+    /// Reconstruye un compact block con las transacciones pendientes, ver CompactBlock::reconstruct.
+    pub fn reconstruct_block(
+        &self,
+        compact_block: &CompactBlock,
+    ) -> Result<PartialBlock, CustomError> {
+        compact_block.reconstruct(
+            self.tx_set
+                .iter()
+                .map(|(tx_hash, pending_tx)| (tx_hash, &pending_tx.transaction)),
+        )
     }
 }
 
 #[cfg(test)]
 mod tests {
 
+    use std::fs::{self, remove_file};
+
     use crate::{
         states::wallets_state::WalletsState,
-        structs::{block_header::BlockHeader, tx_output::TransactionOutput},
+        structs::{
+            block_header::BlockHeader, outpoint::OutPoint, tx_input::TransactionInput,
+            tx_output::TransactionOutput,
+        },
     };
 
     use super::*;
 
     #[test]
     fn pendings_txs_creation() {
-        let pending_txs = PendingTxs::new();
-        assert_eq!(pending_txs.tx_set.len(), 0);
-        let pending_txs = PendingTxs::default();
+        let pending_txs = PendingTxs::new("tests/pending_txs_creation.bin".to_string()).unwrap();
         assert_eq!(pending_txs.tx_set.len(), 0);
+
+        remove_file("tests/pending_txs_creation.bin").unwrap();
     }
 
     #[test]
     fn append_pending_tx() {
-        let mut pending_txs = PendingTxs::new();
+        let mut pending_txs = PendingTxs::new("tests/pending_txs_append.bin".to_string()).unwrap();
         let tx = Transaction {
             version: 1,
             inputs: vec![],
@@ -101,14 +502,17 @@ mod tests {
             lock_time: 0,
         };
         let tx_hash = tx.hash();
-        pending_txs.append_pending_tx(tx);
+        pending_txs.append_pending_tx(tx).unwrap();
         assert_eq!(pending_txs.tx_set.len(), 1);
         assert_eq!(pending_txs.tx_set.contains_key(&tx_hash), true);
+
+        remove_file("tests/pending_txs_append.bin").unwrap();
     }
 
     #[test]
     fn append_existing_pending_tx() {
-        let mut pending_txs = PendingTxs::new();
+        let mut pending_txs =
+            PendingTxs::new("tests/pending_txs_append_existing.bin".to_string()).unwrap();
         let tx = Transaction {
             version: 1,
             inputs: vec![],
@@ -117,18 +521,158 @@ mod tests {
         };
         let tx_hash = tx.hash();
 
-        let updated = pending_txs.append_pending_tx(tx.clone());
+        let updated = pending_txs.append_pending_tx(tx.clone()).unwrap();
         assert_eq!(updated, true);
-        let updated = pending_txs.append_pending_tx(tx);
+        let updated = pending_txs.append_pending_tx(tx).unwrap();
         assert_eq!(updated, false);
 
         assert_eq!(pending_txs.tx_set.len(), 1);
         assert_eq!(pending_txs.tx_set.contains_key(&tx_hash), true);
+
+        remove_file("tests/pending_txs_append_existing.bin").unwrap();
+    }
+
+    #[test]
+    fn save_and_restore_pending_txs() {
+        let path = "tests/pending_txs_save.bin".to_string();
+        let _ = remove_file(&path);
+        let tx = Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output: OutPoint {
+                    hash: vec![1; 32],
+                    index: 0,
+                },
+                script_sig: vec![],
+                sequence: 4294967295,
+            }],
+            outputs: vec![TransactionOutput {
+                value: 100,
+                script_pubkey: vec![],
+            }],
+            lock_time: 0,
+        };
+        let mut confirmed_tx = tx.clone();
+        confirmed_tx.lock_time = 1;
+
+        let mut pending_txs = PendingTxs::new(path.clone()).unwrap();
+        pending_txs.append_pending_tx(tx.clone()).unwrap();
+        pending_txs.append_pending_tx(confirmed_tx.clone()).unwrap();
+
+        let restored = PendingTxs::new(path.clone()).unwrap();
+        assert_eq!(restored.len(), 2);
+        assert!(restored.get_pending_tx(&tx.hash()).is_some());
+        assert_eq!(
+            restored.oldest_received_at(),
+            pending_txs.oldest_received_at()
+        );
+
+        let block = Block::new(
+            BlockHeader {
+                version: 1,
+                prev_block_hash: vec![],
+                merkle_root: vec![],
+                timestamp: 0,
+                bits: 0,
+                nonce: 0,
+                hash: vec![],
+                block_downloaded: true,
+                broadcasted: true,
+            },
+            vec![confirmed_tx.clone()],
+        );
+        pending_txs.update_pending_tx(&block).unwrap();
+
+        let restored = PendingTxs::new(path.clone()).unwrap();
+        assert_eq!(restored.len(), 1);
+        assert!(restored.get_pending_tx(&confirmed_tx.hash()).is_none());
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn restore_drops_expired_pending_txs() {
+        let path = "tests/pending_txs_expired.bin".to_string();
+        let tx = Transaction {
+            version: 1,
+            inputs: vec![],
+            outputs: vec![],
+            lock_time: 0,
+        };
+        let mut buffer = vec![];
+        buffer.extend(0_u64.to_le_bytes());
+        buffer.extend(tx.serialize());
+        fs::write(&path, buffer).unwrap();
+
+        let pending_txs = PendingTxs::new(path.clone()).unwrap();
+        assert_eq!(pending_txs.len(), 0);
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn remove_pending_tx_releases_spent_outpoints() {
+        let path = "tests/pending_txs_remove.bin".to_string();
+        let _ = remove_file(&path);
+        let out_point = OutPoint {
+            hash: vec![1; 32],
+            index: 0,
+        };
+        let tx = Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output: out_point.clone(),
+                script_sig: vec![],
+                sequence: 4294967295,
+            }],
+            outputs: vec![],
+            lock_time: 0,
+        };
+
+        let mut pending_txs = PendingTxs::new(path.clone()).unwrap();
+        pending_txs.append_pending_tx(tx.clone()).unwrap();
+        assert!(pending_txs.spent_outpoints().contains(&out_point));
+        assert!(pending_txs.was_relayed_recently(&tx.hash()).unwrap());
+
+        let removed = pending_txs.remove_pending_tx(&tx.hash()).unwrap();
+        assert_eq!(removed.map(|tx| tx.hash()), Some(tx.hash()));
+        assert!(pending_txs.spent_outpoints().is_empty());
+        assert!(pending_txs.remove_pending_tx(&tx.hash()).unwrap().is_none());
+
+        let restored = PendingTxs::new(path.clone()).unwrap();
+        assert!(restored.is_empty());
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn pending_tx_not_relayed_recently() {
+        let path = "tests/pending_txs_relayed.bin".to_string();
+        let tx = Transaction {
+            version: 1,
+            inputs: vec![],
+            outputs: vec![],
+            lock_time: 0,
+        };
+        let received_at = get_current_timestamp().unwrap() - RECENTLY_RELAYED_WINDOW - 1;
+        let mut buffer = vec![];
+        buffer.extend(received_at.to_le_bytes());
+        buffer.extend(tx.serialize());
+        fs::write(&path, buffer).unwrap();
+
+        let mut pending_txs = PendingTxs::new(path.clone()).unwrap();
+        assert!(!pending_txs.was_relayed_recently(&tx.hash()).unwrap());
+
+        // un peer la vuelve a anunciar
+        assert!(!pending_txs.append_pending_tx(tx.clone()).unwrap());
+        assert!(pending_txs.was_relayed_recently(&tx.hash()).unwrap());
+
+        remove_file(path).unwrap();
     }
 
     #[test]
     fn update_pendings() {
-        let mut pending_txs = PendingTxs::new();
+        let mut pending_txs = PendingTxs::new("tests/pending_txs_update.bin".to_string()).unwrap();
         let tx = Transaction {
             version: 1,
             inputs: vec![],
@@ -152,22 +696,26 @@ mod tests {
             transactions: vec![tx.clone()],
         };
 
-        let updated = pending_txs.append_pending_tx(tx);
+        let updated = pending_txs.append_pending_tx(tx).unwrap();
         assert_eq!(updated, true);
         assert_eq!(pending_txs.tx_set.len(), 1);
 
         pending_txs.update_pending_tx(&block).unwrap();
         assert_eq!(pending_txs.tx_set.len(), 0);
+
+        remove_file("tests/pending_txs_update.bin").unwrap();
     }
 
     #[test]
     fn pendings_from_wallet() {
-        let mut wallets = WalletsState::new("tests/test_wallets.bin".to_string()).unwrap();
+        fs::copy("tests/test_wallets.bin", "tests/test_wallets_pendings.bin").unwrap();
+        let mut wallets = WalletsState::new("tests/test_wallets_pendings.bin".to_string()).unwrap();
         wallets
             .set_active("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm")
             .unwrap();
 
-        let mut pending_txs = PendingTxs::new();
+        let mut pending_txs =
+            PendingTxs::new("tests/pending_txs_from_wallet.bin".to_string()).unwrap();
         let tx = Transaction {
             version: 1,
             inputs: vec![],
@@ -181,7 +729,7 @@ mod tests {
             lock_time: 0,
         };
 
-        pending_txs.append_pending_tx(tx);
+        pending_txs.append_pending_tx(tx).unwrap();
 
         let pendings_from_wallet = pending_txs
             .from_wallet(
@@ -191,5 +739,260 @@ mod tests {
             .unwrap();
         assert_eq!(pendings_from_wallet.len(), 1);
         assert_eq!(pendings_from_wallet[0].value, 100);
+
+        remove_file("tests/pending_txs_from_wallet.bin").unwrap();
+        remove_file("tests/test_wallets_pendings.bin").unwrap();
+    }
+
+    fn peer(port: u16) -> SocketAddrV6 {
+        SocketAddrV6::new(std::net::Ipv6Addr::LOCALHOST, port, 0, 0)
+    }
+
+    #[test]
+    fn broadcast_seen_on_network() {
+        let path = "tests/pending_txs_broadcast_seen.bin".to_string();
+        let mut pending_txs = PendingTxs::new(path.clone()).unwrap();
+        let tx = Transaction {
+            version: 1,
+            inputs: vec![],
+            outputs: vec![],
+            lock_time: 0,
+        };
+        let tx_hash = tx.hash();
+        pending_txs.append_pending_tx(tx).unwrap();
+        assert_eq!(pending_txs.propagation_status(&tx_hash), None);
+        assert!(!pending_txs.announced_by(&tx_hash, peer(2)));
+
+        let start = Instant::now();
+        pending_txs.start_broadcast(&tx_hash, vec![peer(1)], start);
+        assert_eq!(
+            pending_txs.propagation_status(&tx_hash),
+            Some(PropagationStatus::Sent)
+        );
+
+        // el peer al que se la enviamos no prueba que se propago
+        assert!(!pending_txs.announced_by(&tx_hash, peer(1)));
+        assert_eq!(
+            pending_txs.propagation_status(&tx_hash),
+            Some(PropagationStatus::Sent)
+        );
+
+        assert!(pending_txs.announced_by(&tx_hash, peer(2)));
+        assert!(!pending_txs.announced_by(&tx_hash, peer(3)));
+        assert_eq!(
+            pending_txs.propagation_status(&tx_hash),
+            Some(PropagationStatus::SeenOnNetwork)
+        );
+
+        // una transaccion vista en la red no se reenvia
+        let checks = pending_txs.check_broadcasts(
+            start + Duration::from_secs(60),
+            Duration::from_secs(60),
+            &[peer(1), peer(4)],
+            1,
+        );
+        assert!(checks.is_empty());
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn broadcast_escalates_until_possibly_stuck() {
+        let path = "tests/pending_txs_broadcast_stuck.bin".to_string();
+        let mut pending_txs = PendingTxs::new(path.clone()).unwrap();
+        let tx = Transaction {
+            version: 1,
+            inputs: vec![],
+            outputs: vec![],
+            lock_time: 0,
+        };
+        let tx_hash = tx.hash();
+        pending_txs.append_pending_tx(tx).unwrap();
+
+        let timeout = Duration::from_secs(60);
+        let connected = [peer(1), peer(2), peer(3), peer(4)];
+        let start = Instant::now();
+        pending_txs.start_broadcast(&tx_hash, vec![peer(1)], start);
+
+        let checks =
+            pending_txs.check_broadcasts(start + Duration::from_secs(59), timeout, &connected, 1);
+        assert!(checks.is_empty());
+
+        let now = start + timeout;
+        let checks = pending_txs.check_broadcasts(now, timeout, &connected, 1);
+        assert_eq!(
+            checks,
+            vec![BroadcastCheck::Escalate(tx_hash.clone(), vec![peer(2)])]
+        );
+
+        // el timeout vuelve a correr desde el ultimo envio
+        let checks =
+            pending_txs.check_broadcasts(now + Duration::from_secs(30), timeout, &connected, 1);
+        assert!(checks.is_empty());
+
+        let now = now + timeout;
+        let checks = pending_txs.check_broadcasts(now, timeout, &connected, 1);
+        assert_eq!(
+            checks,
+            vec![BroadcastCheck::Escalate(tx_hash.clone(), vec![peer(3)])]
+        );
+
+        let now = now + timeout;
+        let checks = pending_txs.check_broadcasts(now, timeout, &connected, 1);
+        assert_eq!(checks, vec![BroadcastCheck::PossiblyStuck(tx_hash.clone())]);
+        assert_eq!(
+            pending_txs.propagation_status(&tx_hash),
+            Some(PropagationStatus::PossiblyStuck)
+        );
+        let checks = pending_txs.check_broadcasts(now + timeout, timeout, &connected, 1);
+        assert!(checks.is_empty());
+
+        // si algun peer la anuncia despues, se vio en la red
+        assert!(pending_txs.announced_by(&tx_hash, peer(4)));
+        assert_eq!(
+            pending_txs.propagation_status(&tx_hash),
+            Some(PropagationStatus::SeenOnNetwork)
+        );
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn broadcast_possibly_stuck_without_new_peers() {
+        let path = "tests/pending_txs_broadcast_no_peers.bin".to_string();
+        let mut pending_txs = PendingTxs::new(path.clone()).unwrap();
+        let tx = Transaction {
+            version: 1,
+            inputs: vec![],
+            outputs: vec![],
+            lock_time: 0,
+        };
+        let tx_hash = tx.hash();
+        pending_txs.append_pending_tx(tx).unwrap();
+
+        let start = Instant::now();
+        pending_txs.start_broadcast(&tx_hash, vec![peer(1)], start);
+        let checks = pending_txs.check_broadcasts(
+            start + Duration::from_secs(60),
+            Duration::from_secs(60),
+            &[peer(1)],
+            1,
+        );
+        assert_eq!(checks, vec![BroadcastCheck::PossiblyStuck(tx_hash)]);
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn own_tx_is_rebroadcast_once_per_interval() {
+        let path = "tests/pending_txs_rebroadcast.bin".to_string();
+        let mut pending_txs = PendingTxs::new(path.clone()).unwrap();
+        let own_tx = Transaction {
+            version: 1,
+            inputs: vec![],
+            outputs: vec![],
+            lock_time: 0,
+        };
+        let relayed_tx = Transaction {
+            version: 2,
+            inputs: vec![],
+            outputs: vec![],
+            lock_time: 0,
+        };
+        let tx_hash = own_tx.hash();
+        pending_txs.append_pending_tx(own_tx).unwrap();
+        pending_txs.append_pending_tx(relayed_tx.clone()).unwrap();
+        pending_txs.mark_own(&tx_hash, 10);
+        assert_eq!(pending_txs.first_broadcast_height(&tx_hash), Some(10));
+        assert_eq!(pending_txs.first_broadcast_height(&relayed_tx.hash()), None);
+
+        // las transacciones relayed por los peers nunca se reenvian
+        let rebroadcasts: Vec<Vec<Vec<u8>>> = (11..=19)
+            .map(|height| pending_txs.due_rebroadcasts(height, DEFAULT_REBROADCAST_BLOCKS))
+            .collect();
+        let expected: Vec<Vec<Vec<u8>>> = (11..=19)
+            .map(|height| match height {
+                13 | 16 | 19 => vec![tx_hash.clone()],
+                _ => vec![],
+            })
+            .collect();
+        assert_eq!(rebroadcasts, expected);
+
+        // un reenvio manual reinicia el intervalo, sin cambiar el primer envio
+        pending_txs.mark_own(&tx_hash, 20);
+        assert!(pending_txs.due_rebroadcasts(22, 3).is_empty());
+        assert_eq!(pending_txs.due_rebroadcasts(23, 3), vec![tx_hash.clone()]);
+        assert_eq!(pending_txs.first_broadcast_height(&tx_hash), Some(10));
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn own_tx_remembers_the_peers_it_was_sent_to() {
+        let path = "tests/pending_txs_sent_to.bin".to_string();
+        let mut pending_txs = PendingTxs::new(path.clone()).unwrap();
+        let tx = Transaction {
+            version: 1,
+            inputs: vec![],
+            outputs: vec![],
+            lock_time: 0,
+        };
+        let tx_hash = tx.hash();
+        pending_txs.append_pending_tx(tx).unwrap();
+
+        // solo se registran los envios de las transacciones que creamos
+        pending_txs.mark_sent_to(&tx_hash, &[peer(1)]);
+        assert!(!pending_txs.was_sent_to(&tx_hash, &peer(1)));
+
+        pending_txs.mark_own(&tx_hash, 10);
+        pending_txs.mark_sent_to(&tx_hash, &[peer(1)]);
+        pending_txs.mark_own(&tx_hash, 13);
+        pending_txs.mark_sent_to(&tx_hash, &[peer(2)]);
+        assert!(pending_txs.was_sent_to(&tx_hash, &peer(1)));
+        assert!(pending_txs.was_sent_to(&tx_hash, &peer(2)));
+        assert!(!pending_txs.was_sent_to(&tx_hash, &peer(3)));
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn remove_with_descendants_drops_the_txs_spending_its_outputs() {
+        let path = "tests/pending_txs_descendants.bin".to_string();
+        let _ = remove_file(&path);
+        let spending = |hash: Vec<u8>, version: u32| Transaction {
+            version,
+            inputs: vec![TransactionInput {
+                previous_output: OutPoint { hash, index: 0 },
+                script_sig: vec![],
+                sequence: 4294967295,
+            }],
+            outputs: vec![],
+            lock_time: 0,
+        };
+        let parent = spending(vec![1; 32], 1);
+        let child = spending(parent.hash(), 1);
+        let grandchild = spending(child.hash(), 1);
+        let unrelated = spending(vec![2; 32], 2);
+
+        let mut pending_txs = PendingTxs::new(path.clone()).unwrap();
+        for tx in [&parent, &child, &grandchild, &unrelated] {
+            pending_txs.append_pending_tx(tx.clone()).unwrap();
+        }
+
+        let removed = pending_txs.remove_with_descendants(&parent.hash()).unwrap();
+        assert_eq!(
+            removed,
+            vec![parent.hash(), child.hash(), grandchild.hash()]
+        );
+        assert_eq!(pending_txs.get_all_hashes(), vec![unrelated.hash()]);
+        assert!(pending_txs
+            .remove_with_descendants(&parent.hash())
+            .unwrap()
+            .is_empty());
+
+        let restored = PendingTxs::new(path.clone()).unwrap();
+        assert_eq!(restored.len(), 1);
+
+        remove_file(path).unwrap();
     }
 }
@@ -1,16 +1,19 @@
 use crate::{
     error::CustomError,
     logger::{send_log, Log},
-    messages::block::Block,
+    messages::block::{block_subsidy, Block},
     parser::BufferParser,
     structs::tx_output::TransactionOutput,
-    structs::{block_header::BlockHeader, outpoint::OutPoint},
-    utils::{calculate_index_from_timestamp, open_new_file},
+    structs::{
+        block_header::{display_hash, BlockHeader},
+        outpoint::OutPoint,
+    },
+    utils::{calculate_index_from_timestamp, open_file, retry_io, write_file_atomic, OpenMode},
     wallet::Wallet,
 };
 use std::{
-    collections::HashMap,
-    fs::remove_file,
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{self, remove_file, rename},
     io::{Read, Write},
     path::Path,
     process::exit,
@@ -18,18 +21,219 @@ use std::{
     vec,
 };
 
+use super::block_store_state::BlockStore;
+
 pub const START_DATE_IBD: u32 = 1681095630;
 
+/// Los archivos de UTXO comienzan con UTXO_BACKUP_MAGIC seguido del byte de version.
+/// Los archivos sin prefijo son de la version anterior (sin el flag de coinbase) y se regeneran.
+/// Los v3 guardan ademas el valor total de las UTXO, los v2 se siguen leyendo calculandolo.
+const UTXO_BACKUP_MAGIC: [u8; 4] = *b"UTXO";
+const UTXO_BACKUP_V2: u8 = 2;
+const UTXO_BACKUP_V3: u8 = 3;
+
+/// Cantidad de bloques aplicados de los que se guarda lo necesario para deshacerlos ante un reorg.
+const MAX_REORG_DEPTH: usize = 100;
+
+/// El snapshot se escribe primero en un archivo con este sufijo y luego se renombra sobre el anterior,
+/// para que un corte durante el guardado nunca deje sin snapshot.
+const UTXO_TMP_SUFFIX: &str = ".tmp";
+
+/// El journal se guarda junto al snapshot, en un archivo con este sufijo.
+const UTXO_JOURNAL_SUFFIX: &str = ".journal";
+
+/// Los journals comienzan con UTXO_JOURNAL_MAGIC, el byte de version y el hash del snapshot sobre el que se aplican.
+const UTXO_JOURNAL_MAGIC: [u8; 4] = *b"UTXJ";
+const UTXO_JOURNAL_V1: u8 = 1;
+
+/// Los BlockUndo se guardan junto al snapshot, en un archivo con este sufijo, para poder deshacer un reorg
+/// de bloques aplicados antes de reiniciar el nodo.
+const UTXO_UNDO_SUFFIX: &str = ".undo";
+
+/// Los archivos de undo comienzan con UTXO_UNDO_MAGIC seguido del byte de version.
+const UTXO_UNDO_MAGIC: [u8; 4] = *b"UTXR";
+const UTXO_UNDO_V1: u8 = 1;
+
+/// Archivo del store con el hash del bloque de un snapshot importado (ver install_snapshot).
+/// Los bloques hasta ese bloque no se descargan: sus cambios ya estan incluidos en el snapshot.
+const UTXO_IMPORT_BASE_FILE: &str = "utxo_import_base.bin";
+
+/// Cantidad de bloques cada cuantos se guarda un snapshot completo de las UTXO si el config no indica
+/// UTXO_SNAPSHOT_INTERVAL. Entre snapshots, cada bloque solo agrega un registro al journal.
+pub const DEFAULT_UTXO_SNAPSHOT_INTERVAL: u32 = 100;
+
 #[derive(Debug, PartialEq, Clone)]
 /// UTXOValue es una estructura que contiene los valores que necesitamos guardar de las UTXO.
 /// Los elementos son:
 /// - tx_out: TransactionOutput.
 /// - block_hash: Hash del bloque donde se encuentra el UTXO.
 /// - block_timestamp: Timestamp del bloque donde se encuentra el UTXO.
+/// - is_coinbase: Indica si el UTXO es un output de una coinbase, que solo se puede gastar luego de COINBASE_MATURITY confirmaciones.
 pub struct UTXOValue {
     pub tx_out: TransactionOutput,
     pub block_hash: Vec<u8>,
     pub block_timestamp: u32,
+    pub is_coinbase: bool,
+}
+
+#[derive(Debug, PartialEq)]
+/// BlockUndo contiene lo necesario para deshacer un bloque aplicado a las UTXO.
+/// Los elementos son:
+/// - block_hash: Hash del bloque.
+/// - prev_block_hash: Hash del bloque anterior.
+/// - created: OutPoints creados por el bloque.
+/// - spent: UTXOs que gasto el bloque, con sus valores.
+struct BlockUndo {
+    block_hash: Vec<u8>,
+    prev_block_hash: Vec<u8>,
+    created: Vec<OutPoint>,
+    spent: Vec<(OutPoint, UTXOValue)>,
+}
+
+impl BlockUndo {
+    fn serialize(&self) -> Vec<u8> {
+        let mut buffer = vec![];
+        buffer.extend(&self.block_hash);
+        buffer.extend(&self.prev_block_hash);
+        buffer.extend((self.created.len() as u64).to_le_bytes());
+        for out_point in &self.created {
+            buffer.extend(out_point.serialize());
+        }
+        buffer.extend((self.spent.len() as u64).to_le_bytes());
+        for (out_point, value) in &self.spent {
+            serialize_utxo_entry(&mut buffer, out_point, value);
+        }
+        buffer
+    }
+
+    fn parse(buffer: Vec<u8>) -> Result<Self, CustomError> {
+        let mut parser = BufferParser::new(buffer);
+        let block_hash = parser.extract_buffer(32)?.to_vec();
+        let prev_block_hash = parser.extract_buffer(32)?.to_vec();
+        let mut created = vec![];
+        for _ in 0..parser.extract_u64()? {
+            created.push(OutPoint::parse(parser.extract_buffer(36)?.to_vec())?);
+        }
+        let mut spent = vec![];
+        for _ in 0..parser.extract_u64()? {
+            spent.push(parse_utxo_entry(&mut parser)?);
+        }
+        if !parser.is_empty() {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+        Ok(Self {
+            block_hash,
+            prev_block_hash,
+            created,
+            spent,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+/// JournalRecord es el cambio neto que produjo sobre las UTXO aplicar (o deshacer) un bloque.
+/// Como guarda los valores de las UTXO eliminadas, sirve tanto para rehacer el cambio como para deshacerlo.
+/// Los elementos son:
+/// - block_hash: Hash del ultimo bloque procesado luego del cambio.
+/// - removed: UTXOs que se eliminaron, con sus valores.
+/// - added: UTXOs que se agregaron, con sus valores.
+struct JournalRecord {
+    block_hash: Vec<u8>,
+    removed: Vec<(OutPoint, UTXOValue)>,
+    added: Vec<(OutPoint, UTXOValue)>,
+}
+
+impl JournalRecord {
+    /// Genera el registro de un bloque recien aplicado. Los outputs creados y gastados dentro del mismo bloque
+    /// no aparecen, ya que no cambian las UTXO.
+    fn applied(block_undo: &BlockUndo, tx_set: &HashMap<OutPoint, UTXOValue>) -> Self {
+        let created: HashSet<&OutPoint> = block_undo.created.iter().collect();
+        Self {
+            block_hash: block_undo.block_hash.clone(),
+            removed: block_undo
+                .spent
+                .iter()
+                .filter(|(out_point, _)| !created.contains(out_point))
+                .cloned()
+                .collect(),
+            added: block_undo
+                .created
+                .iter()
+                .filter_map(|out_point| {
+                    let value = tx_set.get(out_point)?;
+                    Some((out_point.clone(), value.clone()))
+                })
+                .collect(),
+        }
+    }
+
+    /// Genera el registro de deshacer un bloque, antes de modificar las UTXO.
+    fn disconnected(block_undo: &BlockUndo, tx_set: &HashMap<OutPoint, UTXOValue>) -> Self {
+        let created: HashSet<&OutPoint> = block_undo.created.iter().collect();
+        Self {
+            block_hash: block_undo.prev_block_hash.clone(),
+            removed: block_undo
+                .created
+                .iter()
+                .filter_map(|out_point| {
+                    let value = tx_set.get(out_point)?;
+                    Some((out_point.clone(), value.clone()))
+                })
+                .collect(),
+            added: block_undo
+                .spent
+                .iter()
+                .filter(|(out_point, _)| !created.contains(out_point))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Aplica el cambio sobre las UTXO.
+    fn redo(&self, tx_set: &mut HashMap<OutPoint, UTXOValue>) {
+        for (out_point, _) in &self.removed {
+            tx_set.remove(out_point);
+        }
+        for (out_point, value) in &self.added {
+            tx_set.insert(out_point.clone(), value.clone());
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut buffer = vec![];
+        buffer.extend(&self.block_hash);
+        for entries in [&self.removed, &self.added] {
+            buffer.extend((entries.len() as u64).to_le_bytes());
+            for (out_point, value) in entries {
+                serialize_utxo_entry(&mut buffer, out_point, value);
+            }
+        }
+        buffer
+    }
+
+    fn parse(buffer: Vec<u8>) -> Result<Self, CustomError> {
+        let mut parser = BufferParser::new(buffer);
+        let block_hash = parser.extract_buffer(32)?.to_vec();
+        let mut entries = vec![];
+        for _ in 0..2 {
+            let len = parser.extract_u64()? as usize;
+            let mut parsed = vec![];
+            for _ in 0..len {
+                parsed.push(parse_utxo_entry(&mut parser)?);
+            }
+            entries.push(parsed);
+        }
+        if !parser.is_empty() {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+        let added = entries.pop().unwrap_or_default();
+        let removed = entries.pop().unwrap_or_default();
+        Ok(Self {
+            block_hash,
+            removed,
+            added,
+        })
+    }
 }
 
 #[derive(PartialEq)]
@@ -39,13 +243,52 @@ pub struct UTXOValue {
 /// - sync: Indica si las UTXO estan sincronizadas con la red.
 /// - store_path: Path de la carpeta store.
 /// - path: Path del archivo donde se guardan las UTXO.
+/// - undo: BlockUndo de los ultimos MAX_REORG_DEPTH bloques aplicados, se guardan en el archivo de undo.
+/// - undo_records: Cantidad de registros del archivo de undo, que se reescribe con solo los de undo al llegar a 2 * MAX_REORG_DEPTH.
+/// - snapshot_interval: Cantidad de bloques cada cuantos se guarda un snapshot completo (0 o 1 lo guardan en cada bloque).
+/// - snapshot_block_hash: Hash del ultimo bloque procesado del ultimo snapshot guardado o restaurado.
+/// - journaled_blocks: Cantidad de registros del journal desde el ultimo snapshot.
+/// - total_value: Suma de los valores de todas las UTXO, se actualiza con cada cambio y sirve para detectar un archivo corrupto.
 /// El UTXO tiene un sistema de guardado tipo checkpoint
-/// donde cada vez que se actualiza genera un archivo donde lista los utxo del momento y el timestamp del ultimo bloque procesado.
+/// donde cada snapshot lista los utxo del momento y el hash del ultimo bloque procesado.
+/// Entre snapshots, cada bloque agrega al journal un JournalRecord con su cambio, que se aplica al restaurar.
 pub struct UTXO {
     pub tx_set: HashMap<OutPoint, UTXOValue>,
     sync: bool,
     store_path: String,
     path: String,
+    undo: VecDeque<BlockUndo>,
+    undo_records: usize,
+    snapshot_interval: u32,
+    snapshot_block_hash: Vec<u8>,
+    journaled_blocks: u32,
+    total_value: u64,
+}
+
+#[derive(Debug, PartialEq)]
+/// SupplyAnomaly indica que aplicar un bloque aumento el valor total de las UTXO mas de lo posible:
+/// si se conocen todos los outputs que gasta el bloque, el aumento no puede superar el subsidio maximo
+/// (las fees solo pasan valor de los inputs a la coinbase), y nunca puede superar el valor de los outputs que crea.
+/// Los elementos son:
+/// - block_hash: Hash del bloque aplicado.
+/// - increase: Aumento del valor total de las UTXO.
+/// - max_increase: Aumento maximo posible para el bloque.
+pub struct SupplyAnomaly {
+    pub block_hash: Vec<u8>,
+    pub increase: u64,
+    pub max_increase: u64,
+}
+
+impl SupplyAnomaly {
+    /// Devuelve el aviso que se muestra en el log.
+    pub fn message(&self) -> String {
+        format!(
+            "block {} increased the UTXO total by {} satoshis, more than the possible {}",
+            display_hash(&self.block_hash),
+            self.increase,
+            self.max_increase
+        )
+    }
 }
 
 impl UTXO {
@@ -57,15 +300,31 @@ impl UTXO {
             sync: false,
             store_path,
             path,
+            undo: VecDeque::new(),
+            undo_records: 0,
+            snapshot_interval: DEFAULT_UTXO_SNAPSHOT_INTERVAL,
+            snapshot_block_hash: vec![],
+            journaled_blocks: 0,
+            total_value: 0,
         })
     }
 
+    /// Devuelve la suma de los valores de todas las UTXO (ver total_value).
+    pub fn total_value(&self) -> u64 {
+        self.total_value
+    }
+
+    /// Cambia cada cuantos bloques se guarda un snapshot completo (ver snapshot_interval).
+    pub fn set_snapshot_interval(&mut self, snapshot_interval: u32) {
+        self.snapshot_interval = snapshot_interval;
+    }
+
     /// Devuelve el balance de una wallet.
     pub fn wallet_balance(&self, wallet: &Wallet) -> Result<u64, CustomError> {
         let mut balance = 0;
-        let pubkey_hash = wallet.get_pubkey_hash()?;
+        let pubkey_hashes = wallet.get_pubkey_hash_set()?;
         for value in self.tx_set.values() {
-            if value.tx_out.is_sent_to_key(&pubkey_hash)? {
+            if value.tx_out.is_sent_to_any_key(&pubkey_hashes) {
                 balance += value.tx_out.value;
             }
         }
@@ -77,11 +336,11 @@ impl UTXO {
         &self,
         wallet: &Wallet,
     ) -> Result<Vec<(OutPoint, UTXOValue)>, CustomError> {
-        let pubkey_hash = wallet.get_pubkey_hash()?;
+        let pubkey_hashes = wallet.get_pubkey_hash_set()?;
 
         let mut active_wallet_utxo = vec![];
         for (out_point, value) in &self.tx_set {
-            if value.tx_out.is_sent_to_key(&pubkey_hash)? {
+            if value.tx_out.is_sent_to_any_key(&pubkey_hashes) {
                 active_wallet_utxo.push((out_point.clone(), value.clone()));
             }
         }
@@ -97,21 +356,42 @@ impl UTXO {
     /// Genera las UTXO a partir de los headers.
     /// Si el archivo donde se guardan las UTXO no existe, se crea.
     /// Si el archivo existe, se restauran las UTXO hasta ese punto y se recorren unicamente
-    /// los bloques posteriores al bloque del hash guardado en el archivo.
+    /// los bloques posteriores al bloque del hash guardado en el archivo, que se leen del BlockStore.
+    /// Tambien se restauran los BlockUndo guardados, para poder deshacer un reorg de bloques aplicados antes del reinicio.
     pub fn generate(
         &mut self,
         headers: &Vec<BlockHeader>,
+        blocks: &BlockStore,
         logger_sender: &mut Sender<Log>,
     ) -> Result<(), CustomError> {
-        let last_block_hash = self.restore_utxo()?.unwrap_or_else(|| {
+        let restored = match self.restore_utxo() {
+            Err(error @ CustomError::UtxoTotalMismatch { .. }) => {
+                send_log(
+                    logger_sender,
+                    Log::Warning(format!(
+                        "{}, the UTXO set is generated again from the stored blocks",
+                        error.description()
+                    )),
+                );
+                self.tx_set = HashMap::new();
+                self.total_value = 0;
+                None
+            }
+            restored => restored?,
+        };
+        if let Some(last_block_hash) = &restored {
+            self.restore_undo(last_block_hash)?;
+        }
+        let last_block_hash = restored.unwrap_or_else(|| {
             let first_block_index = calculate_index_from_timestamp(headers, START_DATE_IBD);
             headers[first_block_index].hash().clone()
         });
 
-        let new_last_block_hash = self.update(headers, last_block_hash, logger_sender)?;
+        let new_last_block_hash = self.update(headers, blocks, last_block_hash, logger_sender)?;
 
         self.sync = true;
         self.save(new_last_block_hash)?;
+        self.save_undo()?;
 
         send_log(
             logger_sender,
@@ -125,21 +405,104 @@ impl UTXO {
     }
 
     /// Restaura las UTXO a partir del archivo donde se guardan.
-    /// Obtiene los datos del UTXO.
+    /// Obtiene los datos del UTXO del snapshot y le aplica los registros de su journal.
     /// Obtiene el hash del ultimo bloque procesado y lo retorna
+    /// Devuelve CustomError::UtxoTotalMismatch si el valor total guardado no coincide con el de las UTXO del snapshot.
     fn restore_utxo(&mut self) -> Result<Option<Vec<u8>>, CustomError> {
-        let path = format!("{}/{}", self.store_path, self.path);
-        let mut file = open_new_file(path, false)?;
+        let mut file = open_file(&self.snapshot_path(), OpenMode::ReadCreate)?;
 
         let mut saved_utxo_buffer = vec![];
         file.read_to_end(&mut saved_utxo_buffer)?;
 
-        let (last_block_hash, tx_set) = match Self::parse(saved_utxo_buffer) {
-            Ok((last_block_hash, tx_set)) => (Some(last_block_hash), tx_set),
-            Err(_) => (None, HashMap::new()),
+        let (snapshot_block_hash, tx_set) = match Self::parse(saved_utxo_buffer) {
+            Ok((snapshot_block_hash, tx_set)) => (snapshot_block_hash, tx_set),
+            Err(error @ CustomError::UtxoTotalMismatch { .. }) => return Err(error),
+            Err(_) => {
+                self.tx_set = HashMap::new();
+                self.total_value = 0;
+                return Ok(None);
+            }
         };
 
         self.tx_set = tx_set;
+        self.snapshot_block_hash = snapshot_block_hash;
+        self.journaled_blocks = 0;
+        let last_block_hash = self.replay_journal()?;
+        self.total_value = total_value(&self.tx_set);
+        Ok(Some(last_block_hash))
+    }
+
+    /// Lee las UTXO guardadas (el snapshot con su journal) sin modificar las actuales.
+    /// Devuelve el hash del ultimo bloque procesado y las UTXO, o None si no hay UTXO guardadas.
+    pub fn read_saved(
+        &self,
+    ) -> Result<Option<(Vec<u8>, HashMap<OutPoint, UTXOValue>)>, CustomError> {
+        let mut saved = Self::new(self.store_path.clone(), self.path.clone())?;
+        Ok(saved
+            .restore_utxo()?
+            .map(|last_block_hash| (last_block_hash, saved.tx_set)))
+    }
+
+    /// Reemplaza las UTXO por las de un snapshot importado, correspondientes al bloque block_hash, y las guarda.
+    /// Registra en el store el bloque del snapshot para que los bloques hasta el no se descarguen (ver imported_base).
+    /// Las UTXO siguen sin sincronizar: al descargar los bloques posteriores, generate las continua desde el snapshot.
+    pub fn install_snapshot(
+        &mut self,
+        block_hash: Vec<u8>,
+        tx_set: HashMap<OutPoint, UTXOValue>,
+    ) -> Result<(), CustomError> {
+        self.tx_set = tx_set;
+        self.total_value = total_value(&self.tx_set);
+        self.undo.clear();
+        self.save(block_hash.clone())?;
+        self.save_undo()?;
+
+        let base_path = format!("{}/{}", self.store_path, UTXO_IMPORT_BASE_FILE);
+        retry_io(|| Ok(fs::write(&base_path, &block_hash)?))
+    }
+
+    /// Aplica sobre las UTXO restauradas los registros del journal, si es del snapshot restaurado, en el orden en el que se agregaron.
+    /// Un registro incompleto o invalido (un corte mientras se escribia) se ignora junto con los siguientes, y el journal
+    /// se trunca despues del ultimo registro valido para que los proximos se agreguen a continuacion de el.
+    /// Devuelve el hash del ultimo bloque procesado.
+    fn replay_journal(&mut self) -> Result<Vec<u8>, CustomError> {
+        let mut last_block_hash = self.snapshot_block_hash.clone();
+        let journal_path = self.journal_path();
+        if !Path::new(&journal_path).exists() {
+            return Ok(last_block_hash);
+        }
+
+        let journal = retry_io(|| Ok(fs::read(&journal_path)?))?;
+        let journal_len = journal.len();
+        let mut parser = BufferParser::new(journal);
+        let is_snapshot_journal = parser
+            .extract_buffer(UTXO_JOURNAL_MAGIC.len())
+            .map_or(false, |magic| magic == UTXO_JOURNAL_MAGIC)
+            && parser.extract_u8().ok() == Some(UTXO_JOURNAL_V1)
+            && parser
+                .extract_buffer(32)
+                .map_or(false, |hash| hash == self.snapshot_block_hash.as_slice());
+        if !is_snapshot_journal {
+            return Ok(last_block_hash);
+        }
+
+        let mut valid_len = journal_len - parser.len();
+        while let Ok(record_len) = parser.extract_u32() {
+            let Ok(record_buffer) = parser.extract_buffer(record_len as usize) else { break };
+            let Ok(record) = JournalRecord::parse(record_buffer.to_vec()) else { break };
+            record.redo(&mut self.tx_set);
+            last_block_hash = record.block_hash;
+            self.journaled_blocks += 1;
+            valid_len = journal_len - parser.len();
+        }
+
+        if valid_len < journal_len {
+            retry_io(|| {
+                let file = open_file(&journal_path, OpenMode::Append)?;
+                file.set_len(valid_len as u64)?;
+                Ok(file.sync_data()?)
+            })?;
+        }
         Ok(last_block_hash)
     }
 
@@ -149,6 +512,7 @@ impl UTXO {
     fn update(
         &mut self,
         headers: &Vec<BlockHeader>,
+        blocks: &BlockStore,
         last_block_hash: Vec<u8>,
         logger_sender: &mut Sender<Log>,
     ) -> Result<Vec<u8>, CustomError> {
@@ -172,7 +536,13 @@ impl UTXO {
             )),
         );
 
-        self.update_from_headers(headers, starting_index, logger_sender, &mut last_block_hash)?;
+        self.update_from_headers(
+            headers,
+            blocks,
+            starting_index,
+            logger_sender,
+            &mut last_block_hash,
+        )?;
         Ok(last_block_hash)
     }
 
@@ -181,6 +551,7 @@ impl UTXO {
     fn update_from_headers(
         &mut self,
         headers: &Vec<BlockHeader>,
+        blocks: &BlockStore,
         starting_index: usize,
         logger_sender: &mut Sender<Log>,
         last_block_hash: &mut Vec<u8>,
@@ -197,10 +568,9 @@ impl UTXO {
                     );
                     i = 0;
                 }
-                let path = format!("{}/blocks/{}.bin", self.store_path, header.hash_as_string());
-                let block = match Block::restore(path) {
+                let block = match blocks.get(header.hash()) {
                     Ok(block) => block,
-                    Err(_) => {
+                    _ => {
                         send_log(
                         logger_sender,
                         Log::Message(String::from(
@@ -210,7 +580,9 @@ impl UTXO {
                         exit(0);
                     }
                 };
-                self.update_from_block(&block, false)?;
+                if let Some(anomaly) = self.update_from_block(&block, false)? {
+                    send_log(logger_sender, Log::Warning(anomaly.message()));
+                }
                 drop(block);
                 *last_block_hash = header.hash().clone();
                 i += 1;
@@ -221,14 +593,14 @@ impl UTXO {
     /// Serializa las utxo y el hash del ultimo bloque procesado.
     fn serialize(&mut self, block_hash: Vec<u8>) -> Vec<u8> {
         let mut buffer = vec![];
+        buffer.extend(UTXO_BACKUP_MAGIC);
+        buffer.push(UTXO_BACKUP_V3);
         buffer.extend(block_hash);
         buffer.extend((self.tx_set.len() as u64).to_le_bytes());
+        buffer.extend(self.total_value.to_le_bytes());
 
         for (out_point, value) in &self.tx_set {
-            buffer.extend(out_point.serialize());
-            buffer.extend(value.tx_out.serialize());
-            buffer.extend(value.block_hash.clone());
-            buffer.extend(value.block_timestamp.to_le_bytes());
+            serialize_utxo_entry(&mut buffer, out_point, value);
         }
         buffer
     }
@@ -236,64 +608,414 @@ impl UTXO {
     pub fn parse(buffer: Vec<u8>) -> Result<(Vec<u8>, HashMap<OutPoint, UTXOValue>), CustomError> {
         let mut parser = BufferParser::new(buffer);
 
+        let magic = parser.extract_buffer(UTXO_BACKUP_MAGIC.len())?.to_vec();
+        let version = parser.extract_u8()?;
+        if magic != UTXO_BACKUP_MAGIC || ![UTXO_BACKUP_V2, UTXO_BACKUP_V3].contains(&version) {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
         let last_block_hash = parser.extract_buffer(32)?.to_vec();
         let tx_set_len = parser.extract_u64()? as usize;
+        let saved_total = match version {
+            UTXO_BACKUP_V3 => Some(parser.extract_u64()?),
+            _ => None,
+        };
         let mut tx_set: HashMap<OutPoint, UTXOValue> = HashMap::new();
 
         for _i in 0..tx_set_len {
-            let out_point = OutPoint::parse(parser.extract_buffer(36)?.to_vec())?;
-
-            let value = UTXOValue {
-                tx_out: TransactionOutput::parse(&mut parser)?,
-                block_hash: parser.extract_buffer(32)?.to_vec(),
-                block_timestamp: parser.extract_u32()?,
-            };
+            let (out_point, value) = parse_utxo_entry(&mut parser)?;
             tx_set.insert(out_point, value);
         }
 
+        let computed = total_value(&tx_set);
+        if let Some(saved) = saved_total.filter(|saved| *saved != computed) {
+            return Err(CustomError::UtxoTotalMismatch { saved, computed });
+        }
         Ok((last_block_hash, tx_set))
     }
 
     /// Actualiza las UTXO a partir de un bloque, eliminando los outputs gastados y agregando los nuevos outputs.
-    /// Si save es true, guarda el UTXO actualizado en disco.
-    pub fn update_from_block(&mut self, block: &Block, save: bool) -> Result<(), CustomError> {
-        for tx in &block.transactions {
+    /// Las transacciones se aplican en el orden del bloque, por lo que si una transaccion gasta un output creado
+    /// por una transaccion posterior del mismo bloque devuelve MissingInput sin modificar las UTXO.
+    /// Si save es true, guarda el cambio en disco (ver save_change) junto con lo necesario para deshacerlo (ver append_undo).
+    /// Devuelve un SupplyAnomaly si el bloque aumento el valor total de las UTXO mas de lo posible.
+    pub fn update_from_block(
+        &mut self,
+        block: &Block,
+        save: bool,
+    ) -> Result<Option<SupplyAnomaly>, CustomError> {
+        let anomaly = self.apply_block(block, false)?;
+
+        if save {
+            if let Some(block_undo) = self.undo.back() {
+                let record = JournalRecord::applied(block_undo, &self.tx_set);
+                self.save_change(record)?;
+                self.append_undo()?;
+            }
+        }
+
+        Ok(anomaly)
+    }
+
+    /// Igual que update_from_block (sin guardar), pero ademas exige que todos los inputs que no son de una coinbase
+    /// gasten una UTXO existente o un output anterior del mismo bloque.
+    /// Solo tiene sentido si las UTXO estan completas, ya que en el nodo arrancan en START_DATE_IBD y los inputs
+    /// pueden gastar outputs anteriores. Se usa en los tests para verificar la consistencia de las UTXO.
+    pub fn update_from_block_checked(&mut self, block: &Block) -> Result<(), CustomError> {
+        self.apply_block(block, true)?;
+        Ok(())
+    }
+
+    /// Aplica el bloque sobre las UTXO actualizando su valor total,
+    /// y devuelve un SupplyAnomaly si el aumento del total no es posible (ver SupplyAnomaly).
+    fn apply_block(
+        &mut self,
+        block: &Block,
+        require_inputs: bool,
+    ) -> Result<Option<SupplyAnomaly>, CustomError> {
+        let tx_hashes: Vec<Vec<u8>> = block.transactions.iter().map(|tx| tx.hash()).collect();
+        self.verify_block_inputs(block, &tx_hashes, require_inputs)?;
+
+        let mut block_undo = BlockUndo {
+            block_hash: block.header.hash().clone(),
+            prev_block_hash: block.header.prev_block_hash.clone(),
+            created: vec![],
+            spent: vec![],
+        };
+        let total_before = self.total_value;
+        let mut created_value: u64 = 0;
+        let mut unknown_inputs = false;
+        for (tx, tx_hash) in block.transactions.iter().zip(tx_hashes) {
+            let is_coinbase = tx.is_coinbase();
             for tx_in in &tx.inputs {
-                self.tx_set.remove(&tx_in.previous_output);
+                if let Some(value) = self.tx_set.remove(&tx_in.previous_output) {
+                    self.total_value = self.total_value.saturating_sub(value.tx_out.value);
+                    block_undo
+                        .spent
+                        .push((tx_in.previous_output.clone(), value));
+                } else if !is_coinbase {
+                    unknown_inputs = true;
+                }
             }
             for (index, tx_out) in tx.outputs.iter().enumerate() {
                 let out_point = OutPoint {
-                    hash: tx.hash().clone(),
+                    hash: tx_hash.clone(),
                     index: index as u32,
                 };
                 let value = UTXOValue {
                     tx_out: tx_out.clone(),
                     block_hash: block.header.hash().clone(),
                     block_timestamp: block.header.timestamp,
+                    is_coinbase,
                 };
-                self.tx_set.insert(out_point.clone(), value);
+                created_value = created_value.saturating_add(tx_out.value);
+                self.total_value = self.total_value.saturating_add(tx_out.value);
+                block_undo.created.push(out_point.clone());
+                if let Some(replaced) = self.tx_set.insert(out_point, value) {
+                    self.total_value = self.total_value.saturating_sub(replaced.tx_out.value);
+                }
             }
         }
 
-        if save {
-            self.save(block.header.hash().clone())?;
+        if self.undo.len() == MAX_REORG_DEPTH {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(block_undo);
+
+        let increase = self.total_value.saturating_sub(total_before);
+        let max_increase = match unknown_inputs {
+            true => created_value,
+            false => created_value.min(block_subsidy(0)),
+        };
+        if increase > max_increase {
+            return Ok(Some(SupplyAnomaly {
+                block_hash: block.header.hash().clone(),
+                increase,
+                max_increase,
+            }));
+        }
+        Ok(None)
+    }
+
+    /// Deshace los bloques recibidos, que quedaron fuera de la cadena por un reorg: vuelve a agregar las UTXO que gastaron
+    /// y elimina las que crearon. Se deshacen en el orden inverso al que se aplicaron.
+    /// Solo se pueden deshacer los ultimos MAX_REORG_DEPTH bloques aplicados desde que inicio el nodo,
+    /// los bloques que no se aplicaron (o que se aplicaron antes) se ignoran.
+    /// Si las UTXO estan sincronizadas, guarda el cambio de cada bloque deshecho (ver save_change) y los BlockUndo que quedan.
+    pub fn disconnect_blocks(&mut self, block_hashes: &[Vec<u8>]) -> Result<(), CustomError> {
+        let undo_len = self.undo.len();
+        for position in (0..self.undo.len()).rev() {
+            if !block_hashes.contains(&self.undo[position].block_hash) {
+                continue;
+            }
+            let Some(block_undo) = self.undo.remove(position) else { continue };
+            let record = JournalRecord::disconnected(&block_undo, &self.tx_set);
+            for (out_point, value) in block_undo.spent {
+                self.total_value = self.total_value.saturating_add(value.tx_out.value);
+                if let Some(replaced) = self.tx_set.insert(out_point, value) {
+                    self.total_value = self.total_value.saturating_sub(replaced.tx_out.value);
+                }
+            }
+            for out_point in &block_undo.created {
+                if let Some(value) = self.tx_set.remove(out_point) {
+                    self.total_value = self.total_value.saturating_sub(value.tx_out.value);
+                }
+            }
+            if self.sync {
+                self.save_change(record)?;
+            }
+        }
+        if self.sync && self.undo.len() != undo_len {
+            self.save_undo()?;
+        }
+        Ok(())
+    }
+
+    /// Verifica que los scripts de las transacciones del bloque cumplan los outputs que gastan, sin modificar las UTXO
+    /// (ver verify_block_inputs). Permite rechazar un bloque antes de guardarlo.
+    pub fn verify_block_scripts(&self, block: &Block) -> Result<(), CustomError> {
+        let tx_hashes: Vec<Vec<u8>> = block.transactions.iter().map(|tx| tx.hash()).collect();
+        self.verify_block_inputs(block, &tx_hashes, false)
+    }
+
+    /// Verifica, antes de modificar las UTXO, que ninguna transaccion del bloque gaste un output creado
+    /// por ella misma o por una transaccion posterior del bloque.
+    /// Si require_inputs es true, tambien verifica que el resto de los inputs gasten una UTXO existente.
+    /// Ademas verifica el script sig de cada input que no es de una coinbase contra el script pubkey P2PKH del output
+    /// que gasta (ver Transaction::verify_input_script). Los inputs que gastan outputs desconocidos (anteriores a
+    /// START_DATE_IBD) no se pueden verificar.
+    fn verify_block_inputs(
+        &self,
+        block: &Block,
+        tx_hashes: &[Vec<u8>],
+        require_inputs: bool,
+    ) -> Result<(), CustomError> {
+        let created_in_block: HashMap<&Vec<u8>, usize> = tx_hashes
+            .iter()
+            .enumerate()
+            .map(|(position, tx_hash)| (tx_hash, position))
+            .collect();
+
+        for (position, tx) in block.transactions.iter().enumerate() {
+            let is_coinbase = tx.is_coinbase();
+            for (input_index, tx_in) in tx.inputs.iter().enumerate() {
+                let out_point = &tx_in.previous_output;
+                let (exists, prev_output) = match created_in_block.get(&out_point.hash) {
+                    Some(created_position) => {
+                        let prev_output = match *created_position < position {
+                            true => block.transactions[*created_position]
+                                .outputs
+                                .get(out_point.index as usize),
+                            false => None,
+                        };
+                        (prev_output.is_some(), prev_output)
+                    }
+                    None => {
+                        let prev_output = self.tx_set.get(out_point).map(|value| &value.tx_out);
+                        let exists = !require_inputs
+                            || is_coinbase_input(out_point)
+                            || prev_output.is_some();
+                        (exists, prev_output)
+                    }
+                };
+                if !exists {
+                    return Err(CustomError::MissingInput {
+                        outpoint: out_point.clone(),
+                    });
+                }
+                if let (false, Some(prev_output)) = (is_coinbase, prev_output) {
+                    tx.verify_input_script(input_index, &prev_output.script_pubkey)?;
+                }
+            }
         }
 
         Ok(())
     }
 
-    fn save(&mut self, block_hash: Vec<u8>) -> Result<(), CustomError> {
-        let buffer = self.serialize(block_hash);
+    fn snapshot_path(&self) -> String {
+        format!("{}/{}", self.store_path, self.path)
+    }
+
+    fn journal_path(&self) -> String {
+        format!("{}{}", self.snapshot_path(), UTXO_JOURNAL_SUFFIX)
+    }
+
+    fn undo_path(&self) -> String {
+        format!("{}{}", self.snapshot_path(), UTXO_UNDO_SUFFIX)
+    }
+
+    /// Restaura los BlockUndo guardados de la cadena que termina en last_block_hash, hasta MAX_REORG_DEPTH.
+    /// Un registro incompleto o invalido (un corte mientras se escribia) se ignora junto con los siguientes,
+    /// y los registros de bloques que ya no estan en esa cadena se descartan.
+    fn restore_undo(&mut self, last_block_hash: &[u8]) -> Result<(), CustomError> {
+        self.undo.clear();
+        let undo_path = self.undo_path();
+        if !Path::new(&undo_path).exists() {
+            return Ok(());
+        }
+
+        let mut parser = BufferParser::new(retry_io(|| Ok(fs::read(&undo_path)?))?);
+        let is_undo_file = parser
+            .extract_buffer(UTXO_UNDO_MAGIC.len())
+            .map_or(false, |magic| magic == UTXO_UNDO_MAGIC)
+            && parser.extract_u8().ok() == Some(UTXO_UNDO_V1);
+        if !is_undo_file {
+            return Ok(());
+        }
 
-        let path = format!("{}/{}", self.store_path, self.path);
-        if Path::new(&path).exists() {
-            remove_file(path.clone())?;
+        let mut saved: HashMap<Vec<u8>, BlockUndo> = HashMap::new();
+        while let Ok(record_len) = parser.extract_u32() {
+            let Ok(record_buffer) = parser.extract_buffer(record_len as usize) else { break };
+            let Ok(block_undo) = BlockUndo::parse(record_buffer.to_vec()) else { break };
+            saved.insert(block_undo.block_hash.clone(), block_undo);
         }
-        let mut file = open_new_file(path, false)?;
 
-        file.write_all(&buffer)?;
+        let mut block_hash = last_block_hash.to_vec();
+        while self.undo.len() < MAX_REORG_DEPTH {
+            let Some(block_undo) = saved.remove(&block_hash) else { break };
+            block_hash = block_undo.prev_block_hash.clone();
+            self.undo.push_front(block_undo);
+        }
         Ok(())
     }
+
+    /// Reescribe el archivo de undo con los BlockUndo actuales.
+    fn save_undo(&mut self) -> Result<(), CustomError> {
+        let mut buffer = vec![];
+        buffer.extend(UTXO_UNDO_MAGIC);
+        buffer.push(UTXO_UNDO_V1);
+        for block_undo in &self.undo {
+            let record_buffer = block_undo.serialize();
+            buffer.extend((record_buffer.len() as u32).to_le_bytes());
+            buffer.extend(record_buffer);
+        }
+        write_file_atomic(&self.undo_path(), &buffer)?;
+        self.undo_records = self.undo.len();
+        Ok(())
+    }
+
+    /// Agrega al final del archivo de undo el BlockUndo del ultimo bloque aplicado.
+    /// Si el archivo no existe o ya tiene 2 * MAX_REORG_DEPTH registros, lo reescribe con los BlockUndo actuales (ver save_undo).
+    fn append_undo(&mut self) -> Result<(), CustomError> {
+        let undo_path = self.undo_path();
+        if !Path::new(&undo_path).exists() || self.undo_records >= 2 * MAX_REORG_DEPTH {
+            return self.save_undo();
+        }
+        let Some(block_undo) = self.undo.back() else { return Ok(()) };
+
+        let record_buffer = block_undo.serialize();
+        let mut buffer = (record_buffer.len() as u32).to_le_bytes().to_vec();
+        buffer.extend(record_buffer);
+        retry_io(|| {
+            let mut file = open_file(&undo_path, OpenMode::Append)?;
+            file.write_all(&buffer)?;
+            Ok(file.sync_data()?)
+        })?;
+        self.undo_records += 1;
+        Ok(())
+    }
+
+    /// Guarda el cambio de un bloque. Si desde el ultimo snapshot ya hay snapshot_interval bloques (o no hay snapshot),
+    /// guarda un snapshot completo; si no, solo agrega el registro al final del journal.
+    fn save_change(&mut self, record: JournalRecord) -> Result<(), CustomError> {
+        if self.snapshot_block_hash.is_empty()
+            || self.journaled_blocks + 1 >= self.snapshot_interval
+        {
+            return self.save(record.block_hash);
+        }
+
+        let mut buffer = vec![];
+        let append = self.journaled_blocks > 0;
+        if !append {
+            // un journal que haya quedado es de un snapshot anterior, se reemplaza
+            buffer.extend(UTXO_JOURNAL_MAGIC);
+            buffer.push(UTXO_JOURNAL_V1);
+            buffer.extend(&self.snapshot_block_hash);
+        }
+        let record_buffer = record.serialize();
+        buffer.extend((record_buffer.len() as u32).to_le_bytes());
+        buffer.extend(record_buffer);
+
+        let journal_path = self.journal_path();
+        retry_io(|| {
+            let mode = match append {
+                true => OpenMode::Append,
+                false => OpenMode::Truncate,
+            };
+            let mut file = open_file(&journal_path, mode)?;
+            file.write_all(&buffer)?;
+            Ok(file.sync_data()?)
+        })?;
+        self.journaled_blocks += 1;
+        Ok(())
+    }
+
+    /// Guarda un snapshot completo de las UTXO y borra el journal, que ya esta incluido.
+    /// El snapshot se escribe en un archivo temporal que luego se renombra sobre el anterior.
+    fn save(&mut self, block_hash: Vec<u8>) -> Result<(), CustomError> {
+        let tmp_path = self.write_snapshot(block_hash.clone())?;
+        rename(tmp_path, self.snapshot_path())?;
+        self.snapshot_block_hash = block_hash;
+        self.journaled_blocks = 0;
+
+        let journal_path = self.journal_path();
+        if Path::new(&journal_path).exists() {
+            remove_file(journal_path)?;
+        }
+        Ok(())
+    }
+
+    /// Escribe el snapshot en el archivo temporal, sin reemplazar el anterior, y devuelve su path.
+    fn write_snapshot(&mut self, block_hash: Vec<u8>) -> Result<String, CustomError> {
+        let buffer = self.serialize(block_hash);
+        let tmp_path = format!("{}{}", self.snapshot_path(), UTXO_TMP_SUFFIX);
+        retry_io(|| {
+            let mut file = open_file(&tmp_path, OpenMode::Truncate)?;
+            file.write_all(&buffer)?;
+            Ok(file.sync_all()?)
+        })?;
+        Ok(tmp_path)
+    }
+}
+
+/// Devuelve el hash del bloque del snapshot importado en el store (ver UTXO::install_snapshot), si hay uno.
+pub fn imported_base(store_path: &str) -> Option<Vec<u8>> {
+    fs::read(format!("{}/{}", store_path, UTXO_IMPORT_BASE_FILE))
+        .ok()
+        .filter(|block_hash| block_hash.len() == 32)
+}
+
+/// Serializa una UTXO, como se guarda en los snapshots.
+pub fn serialize_utxo_entry(buffer: &mut Vec<u8>, out_point: &OutPoint, value: &UTXOValue) {
+    buffer.extend(out_point.serialize());
+    buffer.extend(value.tx_out.serialize());
+    buffer.extend(value.block_hash.clone());
+    buffer.extend(value.block_timestamp.to_le_bytes());
+    buffer.push(value.is_coinbase as u8);
+}
+
+/// Deserializa una UTXO serializada con serialize_utxo_entry.
+pub fn parse_utxo_entry(parser: &mut BufferParser) -> Result<(OutPoint, UTXOValue), CustomError> {
+    let out_point = OutPoint::parse(parser.extract_buffer(36)?.to_vec())?;
+
+    let value = UTXOValue {
+        tx_out: TransactionOutput::parse(parser)?,
+        block_hash: parser.extract_buffer(32)?.to_vec(),
+        block_timestamp: parser.extract_u32()?,
+        is_coinbase: parser.extract_u8()? == 1,
+    };
+    Ok((out_point, value))
+}
+
+/// Devuelve la suma de los valores de las UTXO.
+fn total_value(tx_set: &HashMap<OutPoint, UTXOValue>) -> u64 {
+    tx_set
+        .values()
+        .fold(0, |total, value| total.saturating_add(value.tx_out.value))
+}
+
+/// Devuelve true si el outpoint es el de una coinbase, que no gasta ningun output.
+fn is_coinbase_input(out_point: &OutPoint) -> bool {
+    out_point.index == u32::MAX && out_point.hash.iter().all(|byte| *byte == 0)
 }
 
 #[cfg(test)]
@@ -305,9 +1027,14 @@ mod tests {
     use gtk::glib::{self, Priority};
 
     use crate::{
-        logger::Logger, messages::transaction::Transaction, structs::tx_input::TransactionInput,
+        logger::Logger,
+        message::Message,
+        messages::transaction::Transaction,
+        structs::tx_input::TransactionInput,
+        synthetic_chain::{SyntheticChain, SYNTHETIC_MIN_DIFFICULTY_BITS},
         wallet::get_script_pubkey,
     };
+    use std::sync::mpsc;
 
     use super::*;
 
@@ -337,6 +1064,7 @@ mod tests {
                 24, 25, 26, 27, 28, 29, 30, 31, 32,
             ],
             block_timestamp: 1680000000,
+            is_coinbase: false,
         };
         let key2 = OutPoint {
             hash: vec![
@@ -358,6 +1086,7 @@ mod tests {
                 24, 25, 26, 27, 28, 29, 30, 31, 32,
             ],
             block_timestamp: 1680000001,
+            is_coinbase: false,
         };
         let key3 = OutPoint {
             hash: vec![
@@ -379,10 +1108,12 @@ mod tests {
                 24, 25, 26, 27, 28, 29, 30, 31, 32,
             ],
             block_timestamp: 1680000002,
+            is_coinbase: false,
         };
         utxo_set.tx_set.insert(key1, value1);
         utxo_set.tx_set.insert(key2, value2);
         utxo_set.tx_set.insert(key3, value3);
+        utxo_set.total_value = total_value(&utxo_set.tx_set);
 
         assert_eq!(utxo_set.tx_set.len(), 3);
 
@@ -490,13 +1221,19 @@ mod tests {
             },
             block_hash: block_hash.clone(),
             block_timestamp: 1680000000,
+            is_coinbase: false,
         };
         utxo_set.tx_set.insert(key, value);
+        utxo_set.total_value = total_value(&utxo_set.tx_set);
 
         let buffer = utxo_set.serialize(block_hash.clone());
         let (last_block_hash, parsed_tx_set) = UTXO::parse(buffer).unwrap();
         assert_eq!(last_block_hash, block_hash);
         assert_eq!(utxo_set.tx_set, parsed_tx_set);
+
+        // los archivos sin el prefijo de la version actual no se restauran y las UTXO se regeneran
+        let buffer = utxo_set.serialize(block_hash);
+        assert!(UTXO::parse(buffer[5..].to_vec()).is_err());
     }
 
     #[test]
@@ -543,8 +1280,12 @@ mod tests {
 
         // bloque con 42 inputs y outputs en 20 txs
         let block = Block::restore(path).unwrap();
-        let real_path = format!("tests/blocks/{}.bin", block.header.hash_as_string());
-        block.save(real_path.clone()).unwrap();
+        let blocks_store_path = "tests/store_utxo_generation";
+        let _ = fs::remove_dir_all(blocks_store_path);
+        let mut block_store = BlockStore::open(blocks_store_path).unwrap();
+        block_store
+            .append(block.header.hash(), &block.serialize())
+            .unwrap();
 
         if Path::new("tests/test_utxo.bin").exists() {
             fs::remove_file("tests/test_utxo.bin").unwrap();
@@ -555,7 +1296,7 @@ mod tests {
 
         let headers = vec![block_old.header.clone(), block.header.clone()];
         utxo_set
-            .generate(&headers, &mut logger_sender.clone())
+            .generate(&headers, &block_store, &mut logger_sender.clone())
             .unwrap();
 
         // // solo tienen que estar los utxo del segundo bloque
@@ -564,7 +1305,7 @@ mod tests {
 
         fs::remove_file("tests/test_log.txt").unwrap();
         fs::remove_file("tests/test_utxo.bin").unwrap();
-        fs::remove_file(real_path).unwrap();
+        fs::remove_dir_all(blocks_store_path).unwrap();
     }
 
     #[test]
@@ -593,6 +1334,7 @@ mod tests {
             tx_out: tx_out1.clone(),
             block_hash: vec![],
             block_timestamp: 1680000000,
+            is_coinbase: false,
         };
         let key2 = OutPoint {
             hash: vec![],
@@ -607,6 +1349,7 @@ mod tests {
             tx_out: tx_out2.clone(),
             block_hash: vec![],
             block_timestamp: 1680000001,
+            is_coinbase: false,
         };
         let tx_out3 = TransactionOutput {
             value: 100,
@@ -622,6 +1365,7 @@ mod tests {
             tx_out: tx_out3.clone(),
             block_hash: vec![],
             block_timestamp: 1680000002,
+            is_coinbase: false,
         };
         utxo_set.tx_set.insert(key1.clone(), value1.clone());
         utxo_set.tx_set.insert(key2.clone(), value2.clone());
@@ -665,6 +1409,7 @@ mod tests {
             },
             block_hash: vec![],
             block_timestamp: 1680000000,
+            is_coinbase: false,
         };
         let key2 = OutPoint {
             hash: vec![],
@@ -680,6 +1425,7 @@ mod tests {
             },
             block_hash: vec![],
             block_timestamp: 1680000001,
+            is_coinbase: false,
         };
         let key3 = OutPoint {
             hash: vec![],
@@ -695,10 +1441,471 @@ mod tests {
             },
             block_hash: vec![],
             block_timestamp: 1680000002,
+            is_coinbase: false,
         };
         utxo_set.tx_set.insert(key1, value1);
         utxo_set.tx_set.insert(key2, value2);
         utxo_set.tx_set.insert(key3, value3);
         assert_eq!(utxo_set.wallet_balance(&wallet).unwrap(), 300);
     }
+
+    fn dependency_test_transactions() -> (Transaction, Transaction) {
+        let funding_tx = Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output: OutPoint {
+                    hash: vec![0; 32],
+                    index: u32::MAX,
+                },
+                script_sig: vec![],
+                sequence: u32::MAX,
+            }],
+            outputs: vec![
+                TransactionOutput {
+                    value: 100,
+                    script_pubkey: vec![1],
+                },
+                TransactionOutput {
+                    value: 200,
+                    script_pubkey: vec![2],
+                },
+            ],
+            lock_time: 0,
+        };
+        let spending_tx = Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output: OutPoint {
+                    hash: funding_tx.hash(),
+                    index: 0,
+                },
+                script_sig: vec![],
+                sequence: u32::MAX,
+            }],
+            outputs: vec![TransactionOutput {
+                value: 90,
+                script_pubkey: vec![3],
+            }],
+            lock_time: 0,
+        };
+        (funding_tx, spending_tx)
+    }
+
+    fn dependency_test_block(transactions: Vec<Transaction>) -> Block {
+        Block::new(
+            BlockHeader {
+                version: 1,
+                prev_block_hash: vec![],
+                merkle_root: vec![],
+                timestamp: 1680000000,
+                bits: 0,
+                nonce: 0,
+                hash: vec![7; 32],
+                block_downloaded: true,
+                broadcasted: true,
+            },
+            transactions,
+        )
+    }
+
+    #[test]
+    fn update_from_block_in_order() {
+        let (funding_tx, spending_tx) = dependency_test_transactions();
+        let block = dependency_test_block(vec![funding_tx.clone(), spending_tx.clone()]);
+
+        let mut utxo_set = UTXO::new(String::from("tests"), String::from("unused.bin")).unwrap();
+        utxo_set.update_from_block(&block, false).unwrap();
+
+        // mismo resultado que aplicar cada transaccion en orden sin verificar los inputs
+        let mut expected: HashMap<OutPoint, UTXOValue> = HashMap::new();
+        for tx in &block.transactions {
+            for tx_in in &tx.inputs {
+                expected.remove(&tx_in.previous_output);
+            }
+            for (index, tx_out) in tx.outputs.iter().enumerate() {
+                let out_point = OutPoint {
+                    hash: tx.hash(),
+                    index: index as u32,
+                };
+                let value = UTXOValue {
+                    tx_out: tx_out.clone(),
+                    block_hash: block.header.hash().clone(),
+                    block_timestamp: block.header.timestamp,
+                    is_coinbase: tx.is_coinbase(),
+                };
+                expected.insert(out_point, value);
+            }
+        }
+        assert_eq!(utxo_set.tx_set, expected);
+        assert_eq!(utxo_set.tx_set.len(), 2);
+        assert!(
+            utxo_set.tx_set[&OutPoint {
+                hash: funding_tx.hash(),
+                index: 1,
+            }]
+                .is_coinbase
+        );
+        assert!(!utxo_set.tx_set.contains_key(&OutPoint {
+            hash: funding_tx.hash(),
+            index: 0,
+        }));
+
+        let mut checked_utxo_set =
+            UTXO::new(String::from("tests"), String::from("unused.bin")).unwrap();
+        checked_utxo_set.update_from_block_checked(&block).unwrap();
+        assert_eq!(checked_utxo_set.tx_set, expected);
+    }
+
+    #[test]
+    fn update_from_block_out_of_order() {
+        let (funding_tx, spending_tx) = dependency_test_transactions();
+        let block = dependency_test_block(vec![spending_tx, funding_tx.clone()]);
+
+        let mut utxo_set = UTXO::new(String::from("tests"), String::from("unused.bin")).unwrap();
+        let result = utxo_set.update_from_block(&block, false);
+
+        let Err(CustomError::MissingInput { outpoint }) = result else { panic!("expected MissingInput") };
+        assert_eq!(
+            outpoint,
+            OutPoint {
+                hash: funding_tx.hash(),
+                index: 0,
+            }
+        );
+        assert!(utxo_set.tx_set.is_empty());
+    }
+
+    #[test]
+    fn update_from_block_checked_requires_existing_inputs() {
+        let (_, spending_tx) = dependency_test_transactions();
+        let block = dependency_test_block(vec![spending_tx]);
+
+        let mut utxo_set = UTXO::new(String::from("tests"), String::from("unused.bin")).unwrap();
+        assert!(matches!(
+            utxo_set.update_from_block_checked(&block),
+            Err(CustomError::MissingInput { .. })
+        ));
+        assert!(utxo_set.tx_set.is_empty());
+
+        utxo_set.update_from_block(&block, false).unwrap();
+        assert_eq!(utxo_set.tx_set.len(), 1);
+    }
+
+    #[test]
+    fn disconnect_blocks_restores_spent_outputs() {
+        let (funding_tx, spending_tx) = dependency_test_transactions();
+        let funding_block = dependency_test_block(vec![funding_tx]);
+        let mut spending_block = dependency_test_block(vec![spending_tx]);
+        spending_block.header.hash = vec![8; 32];
+        spending_block.header.prev_block_hash = vec![7; 32];
+
+        let mut utxo_set = UTXO::new(String::from("tests"), String::from("unused.bin")).unwrap();
+        utxo_set.update_from_block(&funding_block, false).unwrap();
+        let before_spending = utxo_set.tx_set.clone();
+
+        utxo_set.update_from_block(&spending_block, false).unwrap();
+        assert_ne!(utxo_set.tx_set, before_spending);
+
+        utxo_set.disconnect_blocks(&[vec![8; 32]]).unwrap();
+        assert_eq!(utxo_set.tx_set, before_spending);
+
+        // un bloque que no se aplico no modifica las UTXO
+        utxo_set.disconnect_blocks(&[vec![9; 32]]).unwrap();
+        assert_eq!(utxo_set.tx_set, before_spending);
+
+        utxo_set.disconnect_blocks(&[vec![7; 32]]).unwrap();
+        assert!(utxo_set.tx_set.is_empty());
+    }
+
+    /// Devuelve una transaccion que paga a la wallet de prueba y otra, firmada por la wallet, que gasta ese output.
+    fn signed_test_transactions() -> (Transaction, Transaction) {
+        let utxo_set = UTXO::new(String::from("tests"), String::from("unused.bin")).unwrap();
+        let wallet = Wallet::new(
+            String::from("test"),
+            String::from("mscatccDgq7azndWHFTzvEuZuywCsUvTRu"),
+            String::from("cNpwEsaVLhju18SJowLtdCNaJtvMvqL4jtFLm2FXw7vZjg4sRWvH"),
+            &utxo_set,
+        )
+        .unwrap();
+        let script_pubkey = wallet.get_script_pubkey().unwrap();
+
+        let (mut funding_tx, mut spending_tx) = dependency_test_transactions();
+        funding_tx.outputs[0].script_pubkey = script_pubkey.clone();
+        spending_tx.inputs[0].previous_output.hash = funding_tx.hash();
+        spending_tx.sign(&wallet, &[script_pubkey]).unwrap();
+        (funding_tx, spending_tx)
+    }
+
+    #[test]
+    fn update_from_block_accepts_valid_signature() {
+        let (funding_tx, spending_tx) = signed_test_transactions();
+        let block = dependency_test_block(vec![funding_tx, spending_tx.clone()]);
+
+        let mut utxo_set = UTXO::new(String::from("tests"), String::from("unused.bin")).unwrap();
+        utxo_set.verify_block_scripts(&block).unwrap();
+        utxo_set.update_from_block(&block, false).unwrap();
+        assert!(utxo_set.tx_set.contains_key(&OutPoint {
+            hash: spending_tx.hash(),
+            index: 0,
+        }));
+    }
+
+    #[test]
+    fn update_from_block_rejects_tampered_transaction() {
+        let (funding_tx, mut spending_tx) = signed_test_transactions();
+        // se cambia el output despues de firmar, la firma ya no corresponde al sighash
+        spending_tx.outputs[0].value = 95;
+        let block = dependency_test_block(vec![funding_tx, spending_tx]);
+
+        let mut utxo_set = UTXO::new(String::from("tests"), String::from("unused.bin")).unwrap();
+        assert!(matches!(
+            utxo_set.update_from_block(&block, false),
+            Err(CustomError::InvalidTransactionScript)
+        ));
+        assert!(utxo_set.tx_set.is_empty());
+    }
+
+    #[test]
+    fn interrupted_save_keeps_previous_snapshot() {
+        let store_path = String::from("tests/store_utxo_interrupted_save");
+        fs::create_dir_all(&store_path).unwrap();
+        let (funding_tx, spending_tx) = dependency_test_transactions();
+        let funding_block = dependency_test_block(vec![funding_tx]);
+
+        let mut utxo_set = UTXO::new(store_path.clone(), String::from("utxo.bin")).unwrap();
+        utxo_set.update_from_block(&funding_block, false).unwrap();
+        utxo_set.save(vec![7; 32]).unwrap();
+        let saved_tx_set = utxo_set.tx_set.clone();
+
+        // el nodo se corta despues de escribir el archivo temporal y antes de renombrarlo
+        let mut spending_block = dependency_test_block(vec![spending_tx]);
+        spending_block.header.hash = vec![8; 32];
+        utxo_set.update_from_block(&spending_block, false).unwrap();
+        utxo_set.write_snapshot(vec![8; 32]).unwrap();
+
+        let mut restored = UTXO::new(store_path.clone(), String::from("utxo.bin")).unwrap();
+        assert_eq!(restored.restore_utxo().unwrap(), Some(vec![7; 32]));
+        assert_eq!(restored.tx_set, saved_tx_set);
+
+        fs::remove_dir_all(store_path).unwrap();
+    }
+
+    #[test]
+    fn journal_replay_matches_fresh_generate() {
+        let store_path = String::from("tests/store_utxo_journal");
+        let _ = fs::remove_dir_all(&store_path);
+        let chain = SyntheticChain::generate(10, 6, 1, SYNTHETIC_MIN_DIFFICULTY_BITS).unwrap();
+        let mut block_store = BlockStore::open(&store_path).unwrap();
+        for block in &chain.blocks {
+            block_store
+                .append(block.header.hash(), &block.serialize())
+                .unwrap();
+        }
+        let (mut logger_sender, _logger_receiver) = mpsc::channel();
+
+        let mut fresh = UTXO::new(store_path.clone(), String::from("fresh.bin")).unwrap();
+        fresh
+            .generate(&chain.headers, &block_store, &mut logger_sender)
+            .unwrap();
+
+        // snapshot hasta el tercer bloque, el resto solo se agrega al journal
+        let mut journaled = UTXO::new(store_path.clone(), String::from("utxo.bin")).unwrap();
+        journaled
+            .generate(
+                &chain.headers[..7].to_vec(),
+                &block_store,
+                &mut logger_sender,
+            )
+            .unwrap();
+        let mut journal_lens = vec![];
+        for block in &chain.blocks[3..] {
+            journaled.update_from_block(block, true).unwrap();
+            journal_lens.push(fs::metadata(journaled.journal_path()).map_or(0, |m| m.len()));
+        }
+        assert_eq!(journaled.journaled_blocks, 3);
+        assert_eq!(journaled.snapshot_block_hash, *chain.headers[6].hash());
+
+        let mut restored = UTXO::new(store_path.clone(), String::from("utxo.bin")).unwrap();
+        let last_block_hash = restored.restore_utxo().unwrap();
+        assert_eq!(last_block_hash.as_ref(), Some(chain.headers[9].hash()));
+        assert_eq!(restored.tx_set, fresh.tx_set);
+        assert_eq!(restored.journaled_blocks, 3);
+
+        // un registro incompleto al final del journal se ignora y se trunca
+        let journal_path = restored.journal_path();
+        let journal = fs::read(&journal_path).unwrap();
+        let valid_len = journal_lens[journal_lens.len() - 2] as usize;
+        fs::write(&journal_path, &journal[..journal.len() - 1]).unwrap();
+        let mut restored = UTXO::new(store_path.clone(), String::from("utxo.bin")).unwrap();
+        let last_block_hash = restored.restore_utxo().unwrap();
+        assert_eq!(last_block_hash.as_ref(), Some(chain.headers[8].hash()));
+        assert_eq!(restored.journaled_blocks, 2);
+        assert_eq!(fs::read(&journal_path).unwrap(), journal[..valid_len]);
+
+        // el siguiente bloque se agrega despues del ultimo registro valido
+        restored
+            .update_from_block(chain.blocks.last().unwrap(), true)
+            .unwrap();
+        let mut restored = UTXO::new(store_path.clone(), String::from("utxo.bin")).unwrap();
+        let last_block_hash = restored.restore_utxo().unwrap();
+        assert_eq!(last_block_hash.as_ref(), Some(chain.headers[9].hash()));
+        assert_eq!(restored.tx_set, fresh.tx_set);
+        assert_eq!(restored.journaled_blocks, 3);
+
+        fs::remove_dir_all(store_path).unwrap();
+    }
+
+    #[test]
+    fn journal_record_with_leftover_bytes_is_invalid() {
+        let record = JournalRecord {
+            block_hash: vec![1; 32],
+            removed: vec![],
+            added: vec![],
+        };
+        let mut buffer = record.serialize();
+        assert_eq!(JournalRecord::parse(buffer.clone()).unwrap(), record);
+
+        buffer.push(0);
+        assert!(JournalRecord::parse(buffer).is_err());
+    }
+
+    #[test]
+    fn total_value_follows_applied_and_disconnected_blocks() {
+        let chain = SyntheticChain::generate(11, 10, 1, SYNTHETIC_MIN_DIFFICULTY_BITS).unwrap();
+        let mut utxo_set = UTXO::new(String::from("tests"), String::from("unused.bin")).unwrap();
+
+        // las pseudo-transacciones crean valores al azar, por lo que el total puede subir mas que el subsidio
+        let mut totals = vec![];
+        for block in &chain.blocks {
+            utxo_set.update_from_block(block, false).unwrap();
+            assert_eq!(utxo_set.total_value(), total_value(&utxo_set.tx_set));
+            totals.push(utxo_set.total_value());
+        }
+        assert!(utxo_set.total_value() > 0);
+
+        let last_hashes: Vec<Vec<u8>> = chain.blocks[7..]
+            .iter()
+            .map(|block| block.header.hash().clone())
+            .collect();
+        utxo_set.disconnect_blocks(&last_hashes).unwrap();
+        assert_eq!(utxo_set.total_value(), totals[6]);
+        assert_eq!(utxo_set.total_value(), total_value(&utxo_set.tx_set));
+    }
+
+    #[test]
+    fn disconnect_blocks_after_restart_uses_saved_undo() {
+        let store_path = String::from("tests/store_utxo_undo");
+        let _ = fs::remove_dir_all(&store_path);
+        let chain = SyntheticChain::generate(10, 6, 1, SYNTHETIC_MIN_DIFFICULTY_BITS).unwrap();
+        let mut block_store = BlockStore::open(&store_path).unwrap();
+        for block in &chain.blocks {
+            block_store
+                .append(block.header.hash(), &block.serialize())
+                .unwrap();
+        }
+        let (mut logger_sender, _logger_receiver) = mpsc::channel();
+
+        let mut utxo_set = UTXO::new(store_path.clone(), String::from("utxo.bin")).unwrap();
+        utxo_set
+            .generate(
+                &chain.headers[..7].to_vec(),
+                &block_store,
+                &mut logger_sender,
+            )
+            .unwrap();
+        let before_reorg = utxo_set.tx_set.clone();
+        for block in &chain.blocks[3..] {
+            utxo_set.update_from_block(block, true).unwrap();
+        }
+
+        let mut restarted = UTXO::new(store_path.clone(), String::from("utxo.bin")).unwrap();
+        restarted
+            .generate(&chain.headers, &block_store, &mut logger_sender)
+            .unwrap();
+        assert_eq!(restarted.undo, utxo_set.undo);
+
+        let last_hashes: Vec<Vec<u8>> = chain.headers[7..]
+            .iter()
+            .rev()
+            .map(|header| header.hash().clone())
+            .collect();
+        restarted.disconnect_blocks(&last_hashes).unwrap();
+        assert_eq!(restarted.tx_set, before_reorg);
+
+        // el archivo de undo ya no tiene los bloques deshechos
+        let mut restored = UTXO::new(store_path.clone(), String::from("utxo.bin")).unwrap();
+        restored.restore_undo(chain.headers[6].hash()).unwrap();
+        assert_eq!(restored.undo, restarted.undo);
+
+        // un BlockUndo con bytes de mas es invalido
+        let mut buffer = restarted.undo[0].serialize();
+        assert_eq!(BlockUndo::parse(buffer.clone()).unwrap(), restarted.undo[0]);
+        buffer.push(0);
+        assert!(BlockUndo::parse(buffer).is_err());
+
+        fs::remove_dir_all(store_path).unwrap();
+    }
+
+    #[test]
+    fn update_from_block_flags_supply_anomaly() {
+        let (mut funding_tx, spending_tx) = dependency_test_transactions();
+        let block = dependency_test_block(vec![funding_tx.clone(), spending_tx]);
+        let mut utxo_set = UTXO::new(String::from("tests"), String::from("unused.bin")).unwrap();
+        assert_eq!(utxo_set.update_from_block(&block, false).unwrap(), None);
+        assert_eq!(utxo_set.total_value(), 290);
+
+        // una coinbase que crea mas que el subsidio maximo, sin inputs desconocidos
+        funding_tx.outputs[0].value = block_subsidy(0) + 1;
+        let mut block = dependency_test_block(vec![funding_tx]);
+        block.header.hash = vec![8; 32];
+        let anomaly = utxo_set.update_from_block(&block, false).unwrap().unwrap();
+        assert_eq!(anomaly.block_hash, vec![8; 32]);
+        assert_eq!(anomaly.max_increase, block_subsidy(0));
+        assert!(anomaly.increase > anomaly.max_increase);
+    }
+
+    #[test]
+    fn corrupted_utxo_value_is_detected_on_restore() {
+        let store_path = String::from("tests/store_utxo_corrupted_total");
+        let _ = fs::remove_dir_all(&store_path);
+        let chain = SyntheticChain::generate(10, 6, 1, SYNTHETIC_MIN_DIFFICULTY_BITS).unwrap();
+        let mut block_store = BlockStore::open(&store_path).unwrap();
+        for block in &chain.blocks {
+            block_store
+                .append(block.header.hash(), &block.serialize())
+                .unwrap();
+        }
+        let (mut logger_sender, logger_receiver) = mpsc::channel();
+
+        let mut utxo_set = UTXO::new(store_path.clone(), String::from("utxo.bin")).unwrap();
+        utxo_set
+            .generate(&chain.headers, &block_store, &mut logger_sender)
+            .unwrap();
+
+        // se cambia el valor de la primera UTXO: despues de magic, version, hash, cantidad, total y outpoint
+        let snapshot_path = utxo_set.snapshot_path();
+        let mut buffer = fs::read(&snapshot_path).unwrap();
+        buffer[4 + 1 + 32 + 8 + 8 + 36] ^= 0x01;
+        fs::write(&snapshot_path, buffer).unwrap();
+
+        let mut restored = UTXO::new(store_path.clone(), String::from("utxo.bin")).unwrap();
+        assert!(matches!(
+            restored.restore_utxo(),
+            Err(CustomError::UtxoTotalMismatch { .. })
+        ));
+
+        // al generar, las UTXO se vuelven a calcular desde los bloques guardados
+        let mut regenerated = UTXO::new(store_path.clone(), String::from("utxo.bin")).unwrap();
+        regenerated
+            .generate(&chain.headers, &block_store, &mut logger_sender)
+            .unwrap();
+        assert_eq!(regenerated.tx_set, utxo_set.tx_set);
+        assert_eq!(regenerated.total_value(), utxo_set.total_value());
+        assert!(logger_receiver.try_iter().any(|log| matches!(
+            log,
+            Log::Warning(message) if message.contains("generated again")
+        )));
+
+        fs::remove_dir_all(store_path).unwrap();
+    }
 }
@@ -0,0 +1,440 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{ErrorKind, Read},
+    path::Path,
+};
+
+use crate::{
+    error::CustomError, parser::BufferParser, structs::movement::Movement,
+    utils::write_file_atomic, wallet::Wallet,
+};
+
+/// Carpeta del store donde se guarda un archivo de metadatos por wallet, <pubkey>.bin.
+pub const WALLET_META_DIR: &str = "wallet_meta";
+
+/// Largo maximo en bytes del nombre y de la categoria de un movimiento.
+pub const MAX_MOVEMENT_LABEL_LEN: usize = 255;
+
+/// Largo maximo en bytes de las notas de un movimiento.
+pub const MAX_MOVEMENT_NOTES_LEN: usize = u16::MAX as usize;
+
+/// Los archivos de metadatos comienzan con WALLET_META_MAGIC y el byte de version, seguidos de la cantidad de
+/// movimientos (u32) y de cada tx_hash (precedido por su largo) con su MovementMeta.
+const WALLET_META_MAGIC: [u8; 4] = *b"WMTA";
+const WALLET_META_V1: u8 = 1;
+
+/// Bits del byte de estado de un MovementMeta.
+const META_ABANDONED: u8 = 1;
+const META_HAS_FEE: u8 = 2;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+/// MovementMeta contiene los datos de un movimiento que agrega el nodo o el usuario y que no salen de la transaccion,
+/// por lo que se guardan aparte del historial de la wallet.
+/// Los elementos son:
+/// - label: Nombre del movimiento, vacio si no tiene.
+/// - fee: Fee que pagamos, solo para las transacciones que creamos.
+/// - category: Categoria que le asigno el usuario, vacia si no tiene.
+/// - abandoned: Indica si la transaccion fue abandonada por el usuario mientras estaba pendiente.
+/// - notes: Notas del usuario.
+pub struct MovementMeta {
+    pub label: String,
+    pub fee: Option<u64>,
+    pub category: String,
+    pub abandoned: bool,
+    pub notes: String,
+}
+
+impl MovementMeta {
+    /// Devuelve true si el movimiento no tiene ningun metadato, en ese caso no se guarda.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Devuelve CustomError::Validation si el nombre o la categoria tienen mas de MAX_MOVEMENT_LABEL_LEN bytes,
+    /// o las notas mas de MAX_MOVEMENT_NOTES_LEN.
+    pub fn validate(&self) -> Result<(), CustomError> {
+        if self.label.len() > MAX_MOVEMENT_LABEL_LEN || self.category.len() > MAX_MOVEMENT_LABEL_LEN
+        {
+            return Err(CustomError::Validation(format!(
+                "The name and the category can have at most {MAX_MOVEMENT_LABEL_LEN} bytes"
+            )));
+        }
+        if self.notes.len() > MAX_MOVEMENT_NOTES_LEN {
+            return Err(CustomError::Validation(format!(
+                "The notes can have at most {MAX_MOVEMENT_NOTES_LEN} bytes"
+            )));
+        }
+        Ok(())
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut buffer = vec![];
+        let mut flags = 0;
+        if self.abandoned {
+            flags |= META_ABANDONED;
+        }
+        if self.fee.is_some() {
+            flags |= META_HAS_FEE;
+        }
+        buffer.push(flags);
+        if let Some(fee) = self.fee {
+            buffer.extend(fee.to_le_bytes());
+        }
+        buffer.push(self.label.len() as u8);
+        buffer.extend(self.label.as_bytes());
+        buffer.push(self.category.len() as u8);
+        buffer.extend(self.category.as_bytes());
+        buffer.extend((self.notes.len() as u16).to_le_bytes());
+        buffer.extend(self.notes.as_bytes());
+        buffer
+    }
+
+    fn parse(parser: &mut BufferParser) -> Result<Self, CustomError> {
+        let flags = parser.extract_u8()?;
+        let fee = match flags & META_HAS_FEE {
+            0 => None,
+            _ => Some(parser.extract_u64()?),
+        };
+        let label_len = parser.extract_u8()? as usize;
+        let label = parser.extract_string(label_len)?;
+        let category_len = parser.extract_u8()? as usize;
+        let category = parser.extract_string(category_len)?;
+        let notes_len = parser.extract_u16()? as usize;
+        let notes = parser.extract_string(notes_len)?;
+        Ok(Self {
+            label,
+            fee,
+            category,
+            abandoned: flags & META_ABANDONED != 0,
+            notes,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+/// MovementView es un movimiento del historial junto a sus metadatos, como se muestra en la interfaz grafica.
+/// Los elementos son:
+/// - movement: Movimiento del historial de la wallet.
+/// - meta: Metadatos del movimiento, vacios si no tiene.
+pub struct MovementView {
+    pub movement: Movement,
+    pub meta: MovementMeta,
+}
+
+impl MovementView {
+    /// Devuelve true si la transaccion se abandono y no se confirmo despues.
+    pub fn is_abandoned(&self) -> bool {
+        self.meta.abandoned && self.movement.block_hash.is_none()
+    }
+}
+
+/// WalletMeta son los metadatos de los movimientos de una wallet, guardados en su propio archivo.
+/// Los elementos son:
+/// - entries: Metadatos de cada movimiento que tiene alguno, por tx_hash.
+/// - path: Path del archivo donde se guardan.
+pub struct WalletMeta {
+    entries: HashMap<Vec<u8>, MovementMeta>,
+    path: String,
+}
+
+impl WalletMeta {
+    /// Lee los metadatos del archivo recibido. Si no existe no tiene metadatos, el archivo se crea al guardarlos.
+    pub fn load(path: String) -> Result<Self, CustomError> {
+        let mut entries = HashMap::new();
+        let mut buffer = vec![];
+        match File::open(&path) {
+            Ok(mut file) => file.read_to_end(&mut buffer)?,
+            Err(error) if error.kind() == ErrorKind::NotFound => 0,
+            Err(error) => return Err(error.into()),
+        };
+
+        if !buffer.is_empty() {
+            if buffer.len() <= WALLET_META_MAGIC.len()
+                || buffer[..WALLET_META_MAGIC.len()] != WALLET_META_MAGIC
+                || buffer[WALLET_META_MAGIC.len()] != WALLET_META_V1
+            {
+                return Err(CustomError::Validation(format!(
+                    "{path} is not a wallet metadata file"
+                )));
+            }
+            let mut parser = BufferParser::new(buffer);
+            parser.extract_buffer(WALLET_META_MAGIC.len() + 1)?;
+            for _ in 0..parser.extract_u32()? {
+                let tx_hash_len = parser.extract_u8()? as usize;
+                let tx_hash = parser.extract_buffer(tx_hash_len)?.to_vec();
+                entries.insert(tx_hash, MovementMeta::parse(&mut parser)?);
+            }
+        }
+        Ok(Self { entries, path })
+    }
+
+    /// Guarda los metadatos en su archivo, sin tocar el de las wallets ni los de otras wallets.
+    pub fn save(&self) -> Result<(), CustomError> {
+        if let Some(dir) = Path::new(&self.path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut buffer = vec![];
+        buffer.extend(WALLET_META_MAGIC);
+        buffer.push(WALLET_META_V1);
+        buffer.extend((self.entries.len() as u32).to_le_bytes());
+        for (tx_hash, meta) in &self.entries {
+            buffer.push(tx_hash.len() as u8);
+            buffer.extend(tx_hash);
+            buffer.extend(meta.serialize());
+        }
+        write_file_atomic(&self.path, &buffer)
+    }
+
+    /// Devuelve los metadatos del movimiento, vacios si no tiene.
+    pub fn get(&self, tx_hash: &[u8]) -> MovementMeta {
+        self.entries.get(tx_hash).cloned().unwrap_or_default()
+    }
+
+    /// Reemplaza los metadatos del movimiento. Si quedan vacios se eliminan. No los guarda (ver save).
+    pub fn set(&mut self, tx_hash: &[u8], meta: MovementMeta) {
+        match meta.is_empty() {
+            true => self.entries.remove(tx_hash),
+            false => self.entries.insert(tx_hash.to_vec(), meta),
+        };
+    }
+
+    /// Devuelve la cantidad de movimientos con metadatos.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Devuelve true si ningun movimiento tiene metadatos.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// WalletMetaState guarda los metadatos de los movimientos de todas las wallets, en un archivo por wallet en
+/// WALLET_META_DIR, para que el historial de las wallets (ver Movement) solo tenga lo que sale de las transacciones.
+/// Los metadatos de cada wallet se leen la primera vez que se piden y se guardan cada vez que cambian.
+/// Los elementos son:
+/// - dir: Carpeta donde se guardan los archivos de metadatos.
+/// - wallets: Metadatos de las wallets ya leidas, por public key.
+pub struct WalletMetaState {
+    dir: String,
+    wallets: HashMap<String, WalletMeta>,
+}
+
+impl WalletMetaState {
+    /// Crea el estado para el store recibido, sin leer ningun archivo.
+    pub fn new(store_path: &str) -> Self {
+        Self {
+            dir: format!("{store_path}/{WALLET_META_DIR}"),
+            wallets: HashMap::new(),
+        }
+    }
+
+    /// Devuelve true si los metadatos de la wallet ya se leyeron de su archivo.
+    pub fn is_loaded(&self, pubkey: &str) -> bool {
+        self.wallets.contains_key(pubkey)
+    }
+
+    /// Devuelve los metadatos de la wallet, leyendolos de su archivo si es la primera vez que se piden.
+    pub fn wallet(&mut self, pubkey: &str) -> Result<&mut WalletMeta, CustomError> {
+        if !self.wallets.contains_key(pubkey) {
+            let meta = WalletMeta::load(format!("{}/{pubkey}.bin", self.dir))?;
+            self.wallets.insert(pubkey.to_string(), meta);
+        }
+        self.wallets
+            .get_mut(pubkey)
+            .ok_or(CustomError::WalletNotFound)
+    }
+
+    /// Borra los metadatos de la wallet, junto a su archivo si existe (por ejemplo al quitar la wallet).
+    pub fn remove(&mut self, pubkey: &str) -> Result<(), CustomError> {
+        self.wallets.remove(pubkey);
+        match fs::remove_file(format!("{}/{pubkey}.bin", self.dir)) {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Devuelve los metadatos de un movimiento de la wallet, vacios si no tiene.
+    pub fn get(&mut self, pubkey: &str, tx_hash: &[u8]) -> Result<MovementMeta, CustomError> {
+        Ok(self.wallet(pubkey)?.get(tx_hash))
+    }
+
+    /// Reemplaza los metadatos de un movimiento de la wallet y guarda el archivo de la wallet.
+    /// Devuelve CustomError::Validation si los metadatos no son validos (ver MovementMeta::validate).
+    pub fn set(
+        &mut self,
+        pubkey: &str,
+        tx_hash: &[u8],
+        meta: MovementMeta,
+    ) -> Result<(), CustomError> {
+        meta.validate()?;
+        let wallet = self.wallet(pubkey)?;
+        wallet.set(tx_hash, meta);
+        wallet.save()
+    }
+
+    /// Modifica los metadatos de un movimiento de la wallet con update y guarda el archivo de la wallet.
+    pub fn update(
+        &mut self,
+        pubkey: &str,
+        tx_hash: &[u8],
+        update: impl FnOnce(&mut MovementMeta),
+    ) -> Result<(), CustomError> {
+        let mut meta = self.get(pubkey, tx_hash)?;
+        update(&mut meta);
+        self.set(pubkey, tx_hash, meta)
+    }
+
+    /// Devuelve el historial de la wallet con los metadatos de cada movimiento, en el mismo orden.
+    pub fn history_view(&mut self, wallet: &Wallet) -> Result<Vec<MovementView>, CustomError> {
+        let meta = self.wallet(&wallet.pubkey)?;
+        Ok(wallet
+            .history
+            .iter()
+            .map(|movement| MovementView {
+                movement: movement.clone(),
+                meta: meta.get(&movement.tx_hash),
+            })
+            .collect())
+    }
+
+    /// Pasa a los archivos de metadatos los que estaban guardados en los archivos de wallets anteriores: por ahora
+    /// solo las transacciones abandonadas (ver Movement::parse_legacy), por public key de su wallet.
+    /// Devuelve la cantidad de movimientos migrados.
+    pub fn migrate_legacy(
+        &mut self,
+        legacy_abandoned: &HashMap<String, Vec<Vec<u8>>>,
+    ) -> Result<usize, CustomError> {
+        let mut migrated = 0;
+        for (pubkey, tx_hashes) in legacy_abandoned {
+            let wallet = self.wallet(pubkey)?;
+            for tx_hash in tx_hashes {
+                let mut meta = wallet.get(tx_hash);
+                meta.abandoned = true;
+                wallet.set(tx_hash, meta);
+                migrated += 1;
+            }
+            wallet.save()?;
+        }
+        Ok(migrated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBKEY: &str = "mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm";
+
+    fn movement(byte: u8, block_hash: Option<Vec<u8>>) -> Movement {
+        Movement {
+            tx_hash: vec![byte; 32],
+            value: 1000,
+            block_hash,
+            provisional: false,
+        }
+    }
+
+    fn labeled(label: &str) -> MovementMeta {
+        MovementMeta {
+            label: label.to_string(),
+            fee: Some(250),
+            category: String::from("rent"),
+            abandoned: false,
+            notes: String::from("paid in advance"),
+        }
+    }
+
+    #[test]
+    fn wallet_meta_is_loaded_lazily() {
+        let store_path = "tests/wallet_meta_lazy";
+        let mut meta = WalletMetaState::new(store_path);
+        assert!(!meta.is_loaded(PUBKEY));
+        assert_eq!(meta.get(PUBKEY, &[1; 32]).unwrap(), MovementMeta::default());
+        assert!(meta.is_loaded(PUBKEY));
+        // leer una wallet sin metadatos no crea su archivo
+        assert!(!Path::new(store_path).exists());
+    }
+
+    #[test]
+    fn wallet_meta_is_saved_independently() {
+        let store_path = "tests/wallet_meta_saved";
+        let mut meta = WalletMetaState::new(store_path);
+        meta.set(PUBKEY, &[1; 32], labeled("landlord")).unwrap();
+        meta.update("other", &[2; 32], |meta| meta.abandoned = true)
+            .unwrap();
+
+        let mut restored = WalletMetaState::new(store_path);
+        assert_eq!(restored.get(PUBKEY, &[1; 32]).unwrap(), labeled("landlord"));
+        assert!(!restored.is_loaded("other"));
+        assert_eq!(restored.wallet(PUBKEY).unwrap().len(), 1);
+
+        // vaciar los metadatos de un movimiento lo elimina del archivo
+        restored
+            .set(PUBKEY, &[1; 32], MovementMeta::default())
+            .unwrap();
+        let mut restored = WalletMetaState::new(store_path);
+        assert!(restored.wallet(PUBKEY).unwrap().is_empty());
+        assert!(restored.get("other", &[2; 32]).unwrap().abandoned);
+
+        fs::remove_dir_all(store_path).unwrap();
+    }
+
+    #[test]
+    fn wallet_meta_rejects_long_labels() {
+        let mut meta = WalletMetaState::new("tests/wallet_meta_long");
+        let long = labeled(&"a".repeat(MAX_MOVEMENT_LABEL_LEN + 1));
+        assert!(matches!(
+            meta.set(PUBKEY, &[1; 32], long),
+            Err(CustomError::Validation(_))
+        ));
+        assert!(!Path::new("tests/wallet_meta_long").exists());
+    }
+
+    #[test]
+    fn history_view_merges_meta_into_movements() {
+        let store_path = "tests/wallet_meta_view";
+        let mut wallet = Wallet::watch_only(String::from("wallet"), PUBKEY.to_string()).unwrap();
+        wallet.history = vec![
+            movement(1, Some(vec![9; 32])),
+            movement(2, None),
+            movement(3, Some(vec![9; 32])),
+        ];
+        let mut meta = WalletMetaState::new(store_path);
+        meta.set(PUBKEY, &[1; 32], labeled("salary")).unwrap();
+        meta.update(PUBKEY, &[2; 32], |meta| meta.abandoned = true)
+            .unwrap();
+        // una transaccion abandonada que se confirmo despues ya no se muestra como abandonada
+        meta.update(PUBKEY, &[3; 32], |meta| meta.abandoned = true)
+            .unwrap();
+
+        let view = meta.history_view(&wallet).unwrap();
+        fs::remove_dir_all(store_path).unwrap();
+        assert_eq!(view.len(), 3);
+        assert_eq!(view[0].movement.tx_hash, vec![1; 32]);
+        assert_eq!(view[0].meta, labeled("salary"));
+        assert!(!view[0].is_abandoned());
+        assert!(view[1].is_abandoned());
+        assert!(!view[2].is_abandoned());
+    }
+
+    #[test]
+    fn legacy_abandoned_movements_are_migrated() {
+        let store_path = "tests/wallet_meta_migration";
+        let mut meta = WalletMetaState::new(store_path);
+        meta.set(PUBKEY, &[1; 32], labeled("kept")).unwrap();
+
+        let legacy = HashMap::from([(PUBKEY.to_string(), vec![vec![1; 32], vec![2; 32]])]);
+        assert_eq!(meta.migrate_legacy(&legacy).unwrap(), 2);
+
+        let mut restored = WalletMetaState::new(store_path);
+        let kept = restored.get(PUBKEY, &[1; 32]).unwrap();
+        let abandoned = restored.get(PUBKEY, &[2; 32]).unwrap();
+        fs::remove_dir_all(store_path).unwrap();
+        assert!(kept.abandoned);
+        assert_eq!(kept.label, "kept");
+        assert!(abandoned.abandoned);
+        assert_eq!(abandoned.fee, None);
+    }
+}
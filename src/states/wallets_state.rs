@@ -1,31 +1,86 @@
-use std::io::{Read, Write};
+use std::{collections::HashMap, io::Read};
 
 use crate::{
-    error::CustomError, messages::block::Block, parser::BufferParser, utils::open_new_file,
-    wallet::Wallet,
+    bip32::XpubAccount,
+    error::CustomError,
+    messages::{block::Block, transaction::Transaction},
+    parser::BufferParser,
+    structs::movement::Movement,
+    utils::{open_file, write_file_atomic, OpenMode},
+    wallet::{validate_address, Wallet},
+    wallet_crypto::WalletCipher,
 };
 
-use super::utxo_state::UTXO;
+use super::{utxo_state::UTXO, wallet_meta_state::WalletMetaState};
+
+/// Los archivos de wallets comienzan con WALLETS_MAGIC, el byte de version y la public key de la wallet activa
+/// (precedida por su largo, 0 si no hay wallet activa).
+/// Los archivos sin prefijo son de la version anterior, que solo guardaba las wallets.
+/// Desde WALLETS_V3, despues del byte de version va un byte que indica si las private keys estan cifradas,
+/// seguido (si lo estan) del salt, las iteraciones de PBKDF2 y el verificador del passphrase.
+/// Desde WALLETS_V4, despues de la wallet activa van las etiquetas de direcciones: la cantidad (u32) y cada
+/// direccion y su etiqueta, precedidas por su largo.
+/// Desde WALLETS_V5, despues de las etiquetas van las cuentas xpub: la cantidad (u32) y de cada una la public key de
+/// su wallet y el xpub, precedidos por su largo, el gap limit y la cantidad de direcciones derivadas de recepcion y de
+/// cambio (u32), con las que se vuelven a derivar sus direcciones.
+/// Desde WALLETS_V6, despues de las cuentas xpub van las public keys de las wallets que firman con la public key
+/// sin comprimir (ver Wallet::compressed_pubkey): la cantidad (u32) y cada una precedida por su largo.
+/// Las wallets de versiones anteriores firman con la public key comprimida.
+/// Los archivos de versiones anteriores se siguen leyendo y se guardan con la version actual.
+const WALLETS_MAGIC: [u8; 4] = *b"WLTS";
+const WALLETS_V2: u8 = 2;
+const WALLETS_V3: u8 = 3;
+const WALLETS_V4: u8 = 4;
+const WALLETS_V5: u8 = 5;
+const WALLETS_V6: u8 = 6;
+const WALLETS_PLAIN: u8 = 0;
+const WALLETS_ENCRYPTED: u8 = 1;
+
+/// Largo maximo en bytes del nombre de una direccion.
+pub const MAX_LABEL_LEN: usize = 255;
+
+/// WalletsEncryption contiene lo necesario para cifrar las private keys de las wallets con un passphrase.
+/// Los elementos son:
+/// - salt: Salt de PBKDF2.
+/// - iterations: Iteraciones de PBKDF2.
+/// - verifier: Permite verificar el passphrase al desbloquear las wallets.
+/// - cipher: Clave derivada del passphrase, None mientras las wallets estan bloqueadas.
+struct WalletsEncryption {
+    salt: Vec<u8>,
+    iterations: u32,
+    verifier: Vec<u8>,
+    cipher: Option<WalletCipher>,
+}
 
 /// Wallets es una estructura que contiene los elementos necesarios para manejar los wallets.
 /// Los elementos son:
-/// - wallets: Vector de wallets.
-/// - active_pubkey: Public key del wallet activo.
+/// - wallets: Vector de wallets. Mientras estan bloqueadas, las private keys son las cifradas que se leyeron del archivo.
+/// - active_pubkey: Public key del wallet activo, se guarda junto a las wallets para restaurarla al reiniciar el nodo.
+/// - encryption: Cifrado de las private keys, None si las wallets no tienen passphrase.
+/// - labels: Nombres que el usuario le asigno a direcciones con las que opera (por ejemplo desde Counterparties).
+/// - legacy_abandoned: Movimientos que el archivo guardaba como abandonados, por public key, hasta migrarlos
+///   a WalletMetaState (ver migrate_metadata).
 /// - path: Path del archivo donde se guardan los wallets.
 pub struct WalletsState {
     wallets: Vec<Wallet>,
     active_pubkey: Option<String>,
+    encryption: Option<WalletsEncryption>,
+    labels: HashMap<String, String>,
+    legacy_abandoned: HashMap<String, Vec<Vec<u8>>>,
     path: String,
 }
 
 impl WalletsState {
     /// Inicializa los wallets del nodo.
     /// Si el archivo donde se guardan los wallets no existe, se crea.
-    /// Si el archivo existe, se restauran los wallets.
+    /// Si el archivo existe, se restauran los wallets y la wallet activa, si todavia existe.
     pub fn new(path: String) -> Result<Self, CustomError> {
         let mut wallets = Self {
             wallets: Vec::new(),
             active_pubkey: None,
+            encryption: None,
+            labels: HashMap::new(),
+            legacy_abandoned: HashMap::new(),
             path,
         };
         wallets.restore()?;
@@ -33,41 +88,241 @@ impl WalletsState {
     }
 
     fn restore(&mut self) -> Result<(), CustomError> {
-        let mut file = open_new_file(self.path.clone(), false)?;
+        let mut file = open_file(&self.path, OpenMode::ReadCreate)?;
         let mut buffer = vec![];
         file.read_to_end(&mut buffer)?;
+        let version = match buffer.len() > WALLETS_MAGIC.len()
+            && buffer[..WALLETS_MAGIC.len()] == WALLETS_MAGIC
+        {
+            true => Some(buffer[WALLETS_MAGIC.len()]).filter(|version| {
+                [WALLETS_V2, WALLETS_V3, WALLETS_V4, WALLETS_V5, WALLETS_V6].contains(version)
+            }),
+            false => None,
+        };
         let mut parser = BufferParser::new(buffer);
 
+        let mut active_pubkey = None;
+        let mut encryption = None;
+        let mut labels = HashMap::new();
+        let mut accounts = HashMap::new();
+        let mut uncompressed_pubkeys = vec![];
+        if let Some(version) = version {
+            parser.extract_buffer(WALLETS_MAGIC.len() + 1)?;
+            if version >= WALLETS_V3 && parser.extract_u8()? == WALLETS_ENCRYPTED {
+                let salt_len = parser.extract_u8()? as usize;
+                let salt = parser.extract_buffer(salt_len)?.to_vec();
+                let iterations = parser.extract_u32()?;
+                let verifier_len = parser.extract_u8()? as usize;
+                let verifier = parser.extract_buffer(verifier_len)?.to_vec();
+                encryption = Some(WalletsEncryption {
+                    salt,
+                    iterations,
+                    verifier,
+                    cipher: None,
+                });
+            }
+            let active_pubkey_len = parser.extract_u8()? as usize;
+            if active_pubkey_len > 0 {
+                active_pubkey = Some(parser.extract_string(active_pubkey_len)?);
+            }
+            if version >= WALLETS_V4 {
+                for _ in 0..parser.extract_u32()? {
+                    let address_len = parser.extract_u8()? as usize;
+                    let address = parser.extract_string(address_len)?;
+                    let label_len = parser.extract_u8()? as usize;
+                    labels.insert(address, parser.extract_string(label_len)?);
+                }
+            }
+            if version >= WALLETS_V5 {
+                for _ in 0..parser.extract_u32()? {
+                    let pubkey_len = parser.extract_u8()? as usize;
+                    let pubkey = parser.extract_string(pubkey_len)?;
+                    let xpub_len = parser.extract_u8()? as usize;
+                    let xpub = parser.extract_string(xpub_len)?;
+                    let gap_limit = parser.extract_u32()?;
+                    let external_count = parser.extract_u32()?;
+                    let change_count = parser.extract_u32()?;
+                    let account =
+                        XpubAccount::restore(&xpub, gap_limit, external_count, change_count)?;
+                    accounts.insert(pubkey, account);
+                }
+            }
+            if version >= WALLETS_V6 {
+                for _ in 0..parser.extract_u32()? {
+                    let pubkey_len = parser.extract_u8()? as usize;
+                    uncompressed_pubkeys.push(parser.extract_string(pubkey_len)?);
+                }
+            }
+        }
+
         let mut wallets = vec![];
+        let mut legacy_abandoned = HashMap::new();
         while !parser.is_empty() {
-            let wallet = Wallet::parse(&mut parser)?;
+            let (mut wallet, abandoned) = Wallet::parse_legacy(&mut parser)?;
+            wallet.account = accounts.remove(&wallet.pubkey);
+            wallet.compressed_pubkey = !uncompressed_pubkeys.contains(&wallet.pubkey);
+            if !abandoned.is_empty() {
+                legacy_abandoned.insert(wallet.pubkey.clone(), abandoned);
+            }
             wallets.push(wallet);
         }
 
         self.wallets = wallets;
+        self.encryption = encryption;
+        self.labels = labels;
+        self.legacy_abandoned = legacy_abandoned;
+        self.active_pubkey = active_pubkey.filter(|pubkey| self.contains(pubkey));
         Ok(())
     }
 
+    /// Guarda las wallets. Si tienen passphrase, las private keys se cifran antes de serializarlas
+    /// (mientras estan bloqueadas ya son las cifradas).
     fn save(&self) -> Result<(), CustomError> {
-        let mut file = open_new_file(self.path.clone(), false)?;
-
         let mut buffer = vec![];
+        buffer.extend(WALLETS_MAGIC);
+        buffer.push(WALLETS_V6);
+        match &self.encryption {
+            Some(encryption) => {
+                buffer.push(WALLETS_ENCRYPTED);
+                buffer.push(encryption.salt.len() as u8);
+                buffer.extend(&encryption.salt);
+                buffer.extend(encryption.iterations.to_le_bytes());
+                buffer.push(encryption.verifier.len() as u8);
+                buffer.extend(&encryption.verifier);
+            }
+            None => buffer.push(WALLETS_PLAIN),
+        }
+        match &self.active_pubkey {
+            Some(pubkey) => {
+                buffer.push(pubkey.len() as u8);
+                buffer.extend(pubkey.as_bytes());
+            }
+            None => buffer.push(0),
+        }
+        buffer.extend((self.labels.len() as u32).to_le_bytes());
+        for (address, label) in &self.labels {
+            buffer.push(address.len() as u8);
+            buffer.extend(address.as_bytes());
+            buffer.push(label.len() as u8);
+            buffer.extend(label.as_bytes());
+        }
+        let accounts: Vec<(&String, &XpubAccount)> = self
+            .wallets
+            .iter()
+            .filter_map(|wallet| Some((&wallet.pubkey, wallet.account.as_ref()?)))
+            .collect();
+        buffer.extend((accounts.len() as u32).to_le_bytes());
+        for (pubkey, account) in accounts {
+            let (external_count, change_count) = account.derived_counts();
+            buffer.push(pubkey.len() as u8);
+            buffer.extend(pubkey.as_bytes());
+            buffer.push(account.xpub.len() as u8);
+            buffer.extend(account.xpub.as_bytes());
+            buffer.extend(account.gap_limit.to_le_bytes());
+            buffer.extend(external_count.to_le_bytes());
+            buffer.extend(change_count.to_le_bytes());
+        }
+        let uncompressed_pubkeys: Vec<&String> = self
+            .wallets
+            .iter()
+            .filter(|wallet| !wallet.compressed_pubkey)
+            .map(|wallet| &wallet.pubkey)
+            .collect();
+        buffer.extend((uncompressed_pubkeys.len() as u32).to_le_bytes());
+        for pubkey in uncompressed_pubkeys {
+            buffer.push(pubkey.len() as u8);
+            buffer.extend(pubkey.as_bytes());
+        }
+        let cipher = self
+            .encryption
+            .as_ref()
+            .and_then(|encryption| encryption.cipher.as_ref());
         for wallet in &self.wallets {
-            buffer.append(&mut wallet.serialize());
+            match cipher {
+                Some(cipher) if !wallet.is_watch_only() => {
+                    let mut encrypted = wallet.clone();
+                    encrypted.privkey = cipher.encrypt(&wallet.privkey);
+                    buffer.append(&mut encrypted.serialize());
+                }
+                _ => buffer.append(&mut wallet.serialize()),
+            }
         }
 
-        file.write_all(&buffer)?;
+        write_file_atomic(&self.path, &buffer)
+    }
+
+    /// Devuelve true si las wallets tienen passphrase y todavia no se desbloquearon con unlock.
+    /// Mientras estan bloqueadas no se pueden firmar transacciones ni agregar wallets con private key.
+    pub fn is_locked(&self) -> bool {
+        self.encryption
+            .as_ref()
+            .is_some_and(|encryption| encryption.cipher.is_none())
+    }
+
+    /// Devuelve true si las private keys de las wallets se guardan cifradas.
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption.is_some()
+    }
+
+    /// Establece el passphrase con el que se cifran las private keys y vuelve a guardar las wallets cifradas.
+    /// Si las wallets ya tienen passphrase devuelve un error de validacion.
+    pub fn set_passphrase(&mut self, passphrase: &str) -> Result<(), CustomError> {
+        if self.encryption.is_some() {
+            return Err(CustomError::Validation(
+                "Wallets already have a passphrase".to_string(),
+            ));
+        }
+        let cipher = WalletCipher::create(passphrase)?;
+        self.encryption = Some(WalletsEncryption {
+            salt: cipher.salt.clone(),
+            iterations: cipher.iterations,
+            verifier: cipher.verifier(),
+            cipher: Some(cipher),
+        });
+        self.save()
+    }
+
+    /// Desbloquea las wallets descifrando sus private keys con el passphrase.
+    /// Devuelve CustomError::InvalidPassphrase si el passphrase no es el correcto, sin desbloquear ninguna wallet.
+    pub fn unlock(&mut self, passphrase: &str) -> Result<(), CustomError> {
+        let Some(encryption) = &mut self.encryption else { return Ok(()) };
+        if encryption.cipher.is_some() {
+            return Ok(());
+        }
+        let cipher =
+            WalletCipher::derive(passphrase, encryption.salt.clone(), encryption.iterations);
+        cipher.verify(&encryption.verifier)?;
+
+        let mut privkeys = vec![];
+        for wallet in &self.wallets {
+            match wallet.is_watch_only() {
+                true => privkeys.push(String::new()),
+                false => privkeys.push(cipher.decrypt(&wallet.privkey)?),
+            }
+        }
+        for (wallet, privkey) in self.wallets.iter_mut().zip(privkeys) {
+            wallet.privkey = privkey;
+        }
+        encryption.cipher = Some(cipher);
         Ok(())
     }
 
     /// Establece la wallet activa.
+    /// Si no existe una wallet con esa public key, devuelve WalletNotFound y no cambia la wallet activa.
     pub fn set_active(&mut self, public_key: &str) -> Result<(), CustomError> {
-        self.active_pubkey = self
+        let wallet = self
             .wallets
             .iter()
             .find(|wallet| wallet.pubkey == public_key)
-            .map(|wallet| wallet.pubkey.clone());
-        Ok(())
+            .ok_or(CustomError::WalletNotFound)?;
+        self.active_pubkey = Some(wallet.pubkey.clone());
+        self.save()
+    }
+
+    /// Deja al nodo sin wallet activa.
+    pub fn clear_active(&mut self) -> Result<(), CustomError> {
+        self.active_pubkey = None;
+        self.save()
     }
 
     /// Devuelve todas las wallets.
@@ -75,22 +330,167 @@ impl WalletsState {
         &self.wallets
     }
 
+    /// Devuelve true si ya existe una wallet con esa public key.
+    pub fn contains(&self, public_key: &str) -> bool {
+        self.wallets
+            .iter()
+            .any(|wallet| wallet.pubkey == public_key)
+    }
+
     /// Agrega una wallet a la lista de wallets.
+    /// Si las wallets estan bloqueadas devuelve CustomError::WalletsLocked, ya que su private key no se podria cifrar.
     pub fn append(&mut self, new_wallet: Wallet) -> Result<(), CustomError> {
-        if self
-            .wallets
-            .iter()
-            .any(|wallet| wallet.pubkey == new_wallet.pubkey)
-        {
+        if self.contains(&new_wallet.pubkey) {
             return Err(CustomError::Validation(
                 "Public key already exists".to_string(),
             ));
         }
+        if self.is_locked() && !new_wallet.is_watch_only() {
+            return Err(CustomError::WalletsLocked);
+        }
         self.wallets.push(new_wallet);
         self.save()?;
         Ok(())
     }
 
+    /// Cambia el nombre de la wallet con esa public key por new_name, sin los espacios del principio y del final.
+    /// Devuelve CustomError::Validation si el nombre queda vacio o tiene mas de MAX_LABEL_LEN bytes,
+    /// y WalletNotFound si no existe la wallet.
+    pub fn rename(&mut self, public_key: &str, new_name: &str) -> Result<(), CustomError> {
+        let new_name = new_name.trim();
+        if new_name.is_empty() {
+            return Err(CustomError::Validation(
+                "Name must not be empty".to_string(),
+            ));
+        }
+        if new_name.len() > MAX_LABEL_LEN {
+            return Err(CustomError::Validation(format!(
+                "The name can have at most {MAX_LABEL_LEN} bytes"
+            )));
+        }
+        let wallet = self
+            .wallets
+            .iter_mut()
+            .find(|wallet| wallet.pubkey == public_key)
+            .ok_or(CustomError::WalletNotFound)?;
+        wallet.name = new_name.to_string();
+        self.save()
+    }
+
+    /// Quita la wallet con esa public key junto a su historial, su cuenta xpub, los nombres asignados a sus direcciones
+    /// y sus metadatos en meta, sin modificar las demas.
+    /// Los metadatos se borran despues de guardar las wallets, para no perderlos si el nodo se detiene en el medio.
+    /// Si era la wallet activa, el nodo queda sin wallet activa.
+    /// Devuelve true si la wallet quitada era la activa, o WalletNotFound si no existe.
+    pub fn remove(
+        &mut self,
+        public_key: &str,
+        meta: &mut WalletMetaState,
+    ) -> Result<bool, CustomError> {
+        let index = self
+            .wallets
+            .iter()
+            .position(|wallet| wallet.pubkey == public_key)
+            .ok_or(CustomError::WalletNotFound)?;
+        let wallet = self.wallets.remove(index);
+        let pubkey_hashes = wallet.get_pubkey_hash_set()?;
+        self.labels.retain(|address, _| {
+            validate_address(address).map_or(true, |hash| !pubkey_hashes.contains(&hash))
+        });
+        self.legacy_abandoned.remove(public_key);
+        let was_active = self.active_pubkey.as_deref() == Some(public_key);
+        if was_active {
+            self.active_pubkey = None;
+        }
+        self.save()?;
+        meta.remove(public_key)?;
+        Ok(was_active)
+    }
+
+    /// Agrega de una sola vez un conjunto de wallets watch-only, ignorando las que ya existen.
+    /// El historial de todas se completa con una unica pasada por las UTXO en lugar de una por wallet,
+    /// y las wallets se guardan una sola vez. Las cuentas xpub con UTXO cerca del final de sus direcciones derivadas
+    /// extienden su gap (ver extend_account_gap).
+    /// Devuelve las public keys de las wallets agregadas.
+    pub fn import_watch_only(
+        &mut self,
+        new_wallets: Vec<Wallet>,
+        utxo: &UTXO,
+    ) -> Result<Vec<String>, CustomError> {
+        let mut imported: Vec<Wallet> = vec![];
+        let mut by_pubkey_hash: HashMap<Vec<u8>, usize> = HashMap::new();
+        for wallet in new_wallets {
+            let pubkey_hashes = wallet.get_pubkey_hashes()?;
+            if self.contains(&wallet.pubkey)
+                || pubkey_hashes
+                    .iter()
+                    .any(|pubkey_hash| by_pubkey_hash.contains_key(pubkey_hash))
+            {
+                continue;
+            }
+            for pubkey_hash in pubkey_hashes {
+                by_pubkey_hash.insert(pubkey_hash, imported.len());
+            }
+            imported.push(wallet);
+        }
+
+        let mut used: Vec<Vec<Vec<u8>>> = vec![vec![]; imported.len()];
+        for (outpoint, value) in &utxo.tx_set {
+            let Some(pubkey_hash) = value.tx_out.get_pubkey_hash() else { continue };
+            let Some(index) = by_pubkey_hash.get(&pubkey_hash) else { continue };
+            imported[*index].history.push(Movement {
+                tx_hash: outpoint.hash.clone(),
+                value: value.tx_out.value as i64,
+                block_hash: Some(value.block_hash.clone()),
+                provisional: false,
+            });
+            used[*index].push(pubkey_hash);
+        }
+        for (wallet, used) in imported.iter_mut().zip(used) {
+            extend_account_gap(wallet, used, utxo)?;
+        }
+
+        let pubkeys: Vec<String> = imported
+            .iter()
+            .map(|wallet| wallet.pubkey.clone())
+            .collect();
+        if !imported.is_empty() {
+            self.wallets.extend(imported);
+            self.save()?;
+        }
+        Ok(pubkeys)
+    }
+
+    /// Asigna un nombre a una direccion P2PKH de testnet, un nombre vacio le quita el que tenia.
+    /// Devuelve CustomError::Validation si la direccion no es valida o el nombre tiene mas de MAX_LABEL_LEN bytes.
+    pub fn set_label(&mut self, address: &str, label: &str) -> Result<(), CustomError> {
+        validate_address(address)?;
+        let label = label.trim();
+        if label.len() > MAX_LABEL_LEN {
+            return Err(CustomError::Validation(format!(
+                "The name can have at most {MAX_LABEL_LEN} bytes"
+            )));
+        }
+        match label.is_empty() {
+            true => self.labels.remove(address),
+            false => self.labels.insert(address.to_string(), label.to_string()),
+        };
+        self.save()
+    }
+
+    /// Devuelve el nombre de cada direccion conocida: los de las direcciones watch-only importadas con etiqueta
+    /// (el nombre de su wallet) y los asignados con set_label, que tienen prioridad.
+    pub fn get_labels(&self) -> HashMap<String, String> {
+        let mut labels: HashMap<String, String> = self
+            .wallets
+            .iter()
+            .filter(|wallet| wallet.is_watch_only() && wallet.name != wallet.pubkey)
+            .map(|wallet| (wallet.pubkey.clone(), wallet.name.clone()))
+            .collect();
+        labels.extend(self.labels.clone());
+        labels
+    }
+
     /// Devuelve la wallet activa.
     pub fn get_active(&self) -> Option<&Wallet> {
         match self.active_pubkey {
@@ -103,36 +503,215 @@ impl WalletsState {
     }
 
     /// Actualiza las wallets con la informacion del nuevo bloque.
-    pub fn update(&mut self, block: &Block, utxo: &UTXO) -> Result<bool, CustomError> {
-        let mut wallets_updated = false;
+    /// Los movimientos provisionales del bloque se reemplazan por los del bloque completo: se confirman los que estan
+    /// en el bloque y se eliminan los que no.
+    /// Devuelve las public keys de las wallets que tuvieron movimientos en el bloque, sin importar si son la wallet activa.
+    pub fn update(&mut self, block: &Block, utxo: &UTXO) -> Result<Vec<String>, CustomError> {
+        let mut affected: Vec<String> = vec![];
+        let block_hash = block.header.hash();
+        for wallet in &mut self.wallets {
+            if wallet.remove_provisional_movements(block_hash) {
+                affected.push(wallet.pubkey.clone());
+            }
+        }
 
         for tx in &block.transactions {
             for wallet in &mut self.wallets {
-                let movement = tx.get_movement(&wallet.get_pubkey_hash()?, utxo)?;
+                let movement = tx.get_movement(&wallet.get_pubkey_hash_set()?, utxo)?;
                 if let Some(mut movement) = movement {
                     movement.block_hash = Some(block.header.hash().clone());
                     wallet.update_history(movement);
-                    wallets_updated = true;
+                    if !affected.contains(&wallet.pubkey) {
+                        affected.push(wallet.pubkey.clone());
+                    }
+                }
+            }
+        }
+        if !affected.is_empty() {
+            self.save()?;
+        }
+        Ok(affected)
+    }
+
+    /// Extiende el gap de las cuentas xpub que recibieron outputs del bloque cerca del final de sus direcciones
+    /// derivadas (ver extend_account_gap). Se llama antes de update, con las UTXO anteriores al bloque, para que update
+    /// ya registre los movimientos del bloque en las direcciones nuevas.
+    /// Devuelve las public keys de las wallets que derivaron direcciones nuevas, que hay que agregar al filtro bloom.
+    pub fn extend_xpub_gaps(
+        &mut self,
+        block: &Block,
+        utxo: &UTXO,
+    ) -> Result<Vec<String>, CustomError> {
+        let received: Vec<Vec<u8>> = block
+            .transactions
+            .iter()
+            .flat_map(|tx| &tx.outputs)
+            .filter_map(|output| output.get_pubkey_hash())
+            .collect();
+        if received.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut extended: Vec<String> = vec![];
+        for wallet in &mut self.wallets {
+            if wallet.account.is_some() && extend_account_gap(wallet, received.clone(), utxo)? {
+                extended.push(wallet.pubkey.clone());
+            }
+        }
+        if !extended.is_empty() {
+            self.save()?;
+        }
+        Ok(extended)
+    }
+
+    /// Agrega a las wallets movimientos provisionales con las transacciones de un bloque filtrado (merkleblock),
+    /// que todavia no se verificaron con el bloque completo (ver update).
+    /// Devuelve las public keys de las wallets afectadas.
+    pub fn update_provisional(
+        &mut self,
+        block: &Block,
+        utxo: &UTXO,
+    ) -> Result<Vec<String>, CustomError> {
+        let mut affected: Vec<String> = vec![];
+
+        for tx in &block.transactions {
+            for wallet in &mut self.wallets {
+                let movement = tx.get_movement(&wallet.get_pubkey_hash_set()?, utxo)?;
+                if let Some(mut movement) = movement {
+                    movement.block_hash = Some(block.header.hash().clone());
+                    if wallet.add_provisional_movement(movement)
+                        && !affected.contains(&wallet.pubkey)
+                    {
+                        affected.push(wallet.pubkey.clone());
+                    }
+                }
+            }
+        }
+        if !affected.is_empty() {
+            self.save()?;
+        }
+        Ok(affected)
+    }
+
+    /// Elimina del historial de las wallets los movimientos de los bloques desconectados por un reorg.
+    /// Devuelve las public keys de las wallets afectadas.
+    pub fn disconnect_blocks(
+        &mut self,
+        block_hashes: &[Vec<u8>],
+    ) -> Result<Vec<String>, CustomError> {
+        let mut affected: Vec<String> = vec![];
+
+        for wallet in &mut self.wallets {
+            if wallet.remove_block_movements(block_hashes) {
+                affected.push(wallet.pubkey.clone());
+            }
+        }
+        if !affected.is_empty() {
+            self.save()?;
+        }
+        Ok(affected)
+    }
+
+    /// Devuelve true si alguno de los inputs de la transaccion gasta una UTXO de alguna de las wallets.
+    pub fn is_authored(&self, transaction: &Transaction, utxo: &UTXO) -> Result<bool, CustomError> {
+        for wallet in &self.wallets {
+            let pubkey_hashes = wallet.get_pubkey_hash_set()?;
+            for tx_in in &transaction.inputs {
+                if let Some(value) = utxo.tx_set.get(&tx_in.previous_output) {
+                    if value.tx_out.is_sent_to_any_key(&pubkey_hashes) {
+                        return Ok(true);
+                    }
                 }
             }
         }
-        if wallets_updated {
+        Ok(false)
+    }
+
+    /// Pasa a meta los datos que los archivos de wallets anteriores guardaban en el historial (por ahora las
+    /// transacciones abandonadas, ver Wallet::parse_legacy) y vuelve a guardar las wallets sin ellos.
+    /// Los metadatos se guardan antes que las wallets, para no perderlos si el nodo se detiene en el medio.
+    /// Devuelve la cantidad de movimientos migrados.
+    pub fn migrate_metadata(&mut self, meta: &mut WalletMetaState) -> Result<usize, CustomError> {
+        if self.legacy_abandoned.is_empty() {
+            return Ok(0);
+        }
+        let migrated = meta.migrate_legacy(&self.legacy_abandoned)?;
+        self.legacy_abandoned.clear();
+        self.save()?;
+        Ok(migrated)
+    }
+
+    /// Registra en el historial de las wallets con movimientos en la transaccion que fue abandonada, como un
+    /// movimiento sin bloque. Que se abandono se guarda en WalletMetaState (ver NodeState::abandon_transaction).
+    /// Devuelve las public keys de las wallets afectadas.
+    pub fn mark_abandoned(
+        &mut self,
+        transaction: &Transaction,
+        utxo: &UTXO,
+    ) -> Result<Vec<String>, CustomError> {
+        let mut affected: Vec<String> = vec![];
+
+        for wallet in &mut self.wallets {
+            let movement = transaction.get_movement(&wallet.get_pubkey_hash_set()?, utxo)?;
+            if let Some(movement) = movement {
+                wallet.update_history(movement);
+                affected.push(wallet.pubkey.clone());
+            }
+        }
+        if !affected.is_empty() {
             self.save()?;
         }
-        Ok(wallets_updated && self.active_pubkey.is_some())
+        Ok(affected)
+    }
+}
+
+/// Deriva mas direcciones de la cuenta xpub de la wallet mientras alguna de las usadas (por hash de su public key)
+/// quede a menos de gap_limit direcciones del final de su cadena, y completa el historial con un rescan de las UTXO
+/// solo para las direcciones nuevas, cuyas UTXO tambien se agregan a las usadas.
+/// Devuelve true si se derivaron direcciones.
+fn extend_account_gap(
+    wallet: &mut Wallet,
+    mut used: Vec<Vec<u8>>,
+    utxo: &UTXO,
+) -> Result<bool, CustomError> {
+    let Some(account) = &mut wallet.account else { return Ok(false) };
+    let mut extended = false;
+    loop {
+        let new_hashes = account.extend_gap(&used)?;
+        if new_hashes.is_empty() {
+            return Ok(extended);
+        }
+        extended = true;
+        for (outpoint, value) in &utxo.tx_set {
+            let Some(pubkey_hash) = value.tx_out.get_pubkey_hash() else { continue };
+            if !new_hashes.contains(&pubkey_hash) {
+                continue;
+            }
+            wallet.history.push(Movement {
+                tx_hash: outpoint.hash.clone(),
+                value: value.tx_out.value as i64,
+                block_hash: Some(value.block_hash.clone()),
+                provisional: false,
+            });
+            used.push(pubkey_hash);
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::fs::{self, remove_file};
+    use std::{
+        fs::{self, remove_file},
+        path::Path,
+    };
 
     use crate::{
-        messages::transaction::Transaction,
+        states::utxo_state::UTXOValue,
         structs::{
             block_header::BlockHeader, outpoint::OutPoint, tx_input::TransactionInput,
             tx_output::TransactionOutput,
         },
+        wallet::get_pubkey_hash,
     };
 
     use super::*;
@@ -143,6 +722,11 @@ mod tests {
         assert_eq!(wallets.wallets.len(), 0);
         assert_eq!(wallets.active_pubkey, None);
 
+        // sin wallet elegida en el primer inicio, tampoco hay wallet activa al reiniciar
+        wallets.save().unwrap();
+        let wallets = WalletsState::new("tests/wallets_empty.bin".to_string()).unwrap();
+        assert_eq!(wallets.active_pubkey, None);
+
         remove_file("tests/wallets_empty.bin".to_string()).unwrap();
     }
 
@@ -178,6 +762,29 @@ mod tests {
         remove_file("tests/test_wallets_append.bin".to_string()).unwrap();
     }
 
+    #[test]
+    fn uncompressed_pubkey_wallets_are_restored() {
+        let path = "tests/test_wallets_uncompressed.bin".to_string();
+        fs::copy("tests/test_wallets.bin", &path).unwrap();
+        let mut wallets = WalletsState::new(path.clone()).unwrap();
+
+        let mut new_wallet = Wallet::new(
+            String::from("wallet 2"),
+            String::from("mhh2JbY3QmNaX9a1faSdk5xBpbLTLd12jK"),
+            String::from("cNpwEsaVLhju18SJowLtdCNaJtvMvqL4jtFLm2FXw7vZjg4sRWvH"),
+            &UTXO::new(String::from("tests"), String::from("test_utxo.bin")).unwrap(),
+        )
+        .unwrap();
+        new_wallet.compressed_pubkey = false;
+        wallets.append(new_wallet).unwrap();
+
+        let restored = WalletsState::new(path.clone()).unwrap();
+        assert!(restored.wallets[0].compressed_pubkey);
+        assert!(!restored.wallets[1].compressed_pubkey);
+
+        remove_file(path).unwrap();
+    }
+
     #[test]
     fn append_wallet_duplicated_wallet() {
         fs::copy(
@@ -226,6 +833,188 @@ mod tests {
         remove_file("tests/save_wallets.bin".to_string()).unwrap();
     }
 
+    #[test]
+    fn address_labels_are_saved_with_the_wallets() {
+        let path = "tests/test_wallets_labels.bin";
+        let _ = remove_file(path);
+        let mut wallets = WalletsState::new(path.to_string()).unwrap();
+        wallets
+            .append(
+                Wallet::watch_only(
+                    String::from("exchange"),
+                    String::from("mscatccDgq7azndWHFTzvEuZuywCsUvTRu"),
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        wallets
+            .set_label("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm", " Alice ")
+            .unwrap();
+        assert!(wallets.set_label("invalid address", "Bob").is_err());
+        assert!(wallets
+            .set_label("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm", &"x".repeat(256))
+            .is_err());
+
+        let mut restored = WalletsState::new(path.to_string()).unwrap();
+        let labels = restored.get_labels();
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels["mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm"], "Alice");
+        assert_eq!(labels["mscatccDgq7azndWHFTzvEuZuywCsUvTRu"], "exchange");
+
+        // el nombre asignado tiene prioridad sobre el de la wallet watch-only, y un nombre vacio lo quita
+        restored
+            .set_label("mscatccDgq7azndWHFTzvEuZuywCsUvTRu", "Exchange deposits")
+            .unwrap();
+        restored
+            .set_label("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm", "")
+            .unwrap();
+        let labels = WalletsState::new(path.to_string()).unwrap().get_labels();
+        assert_eq!(labels.len(), 1);
+        assert_eq!(
+            labels["mscatccDgq7azndWHFTzvEuZuywCsUvTRu"],
+            "Exchange deposits"
+        );
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rename_wallet() {
+        let path = "tests/test_wallets_rename.bin";
+        fs::copy("tests/test_wallets.bin", path).unwrap();
+        let mut wallets = WalletsState::new(path.to_string()).unwrap();
+
+        wallets
+            .rename("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm", " savings ")
+            .unwrap();
+        assert!(matches!(
+            wallets.rename("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm", "  "),
+            Err(CustomError::Validation(_))
+        ));
+        assert!(matches!(
+            wallets.rename("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm", &"x".repeat(256)),
+            Err(CustomError::Validation(_))
+        ));
+        assert!(matches!(
+            wallets.rename("unknown pubkey", "other"),
+            Err(CustomError::WalletNotFound)
+        ));
+
+        let restored = WalletsState::new(path.to_string()).unwrap();
+        assert_eq!(restored.wallets.len(), 1);
+        assert_eq!(restored.wallets[0].name, "savings");
+        assert_eq!(
+            restored.wallets[0].pubkey,
+            "mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm"
+        );
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn remove_active_wallet_keeps_the_others() {
+        let path = "tests/test_wallets_remove.bin";
+        fs::copy("tests/test_wallets.bin", path).unwrap();
+        let mut wallets = WalletsState::new(path.to_string()).unwrap();
+        let mut other = Wallet::watch_only(
+            String::from("exchange"),
+            String::from("mscatccDgq7azndWHFTzvEuZuywCsUvTRu"),
+        )
+        .unwrap();
+        other.update_history(Movement {
+            tx_hash: vec![7; 32],
+            value: 5000,
+            block_hash: Some(vec![8; 32]),
+            provisional: false,
+        });
+        wallets.append(other).unwrap();
+        wallets
+            .set_active("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm")
+            .unwrap();
+        wallets
+            .set_label("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm", "Alice")
+            .unwrap();
+        wallets
+            .set_label("mscatccDgq7azndWHFTzvEuZuywCsUvTRu", "Exchange deposits")
+            .unwrap();
+        let store_path = "tests/wallet_meta_remove";
+        let mut meta = WalletMetaState::new(store_path);
+        let removed_meta_path =
+            format!("{store_path}/wallet_meta/mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm.bin");
+        meta.update("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm", &[7; 32], |meta| {
+            meta.abandoned = true
+        })
+        .unwrap();
+        meta.update("mscatccDgq7azndWHFTzvEuZuywCsUvTRu", &[7; 32], |meta| {
+            meta.abandoned = true
+        })
+        .unwrap();
+        assert!(Path::new(&removed_meta_path).exists());
+
+        assert!(wallets
+            .remove("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm", &mut meta)
+            .unwrap());
+        assert!(wallets.get_active().is_none());
+        assert!(matches!(
+            wallets.remove("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm", &mut meta),
+            Err(CustomError::WalletNotFound)
+        ));
+
+        // se borran los metadatos y los nombres de las direcciones de la wallet quitada, pero no los de la otra
+        assert!(!Path::new(&removed_meta_path).exists());
+        let mut restored_meta = WalletMetaState::new(store_path);
+        assert!(
+            !restored_meta
+                .get("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm", &[7; 32])
+                .unwrap()
+                .abandoned
+        );
+        assert!(
+            restored_meta
+                .get("mscatccDgq7azndWHFTzvEuZuywCsUvTRu", &[7; 32])
+                .unwrap()
+                .abandoned
+        );
+        let labels = WalletsState::new(path.to_string()).unwrap().get_labels();
+        assert_eq!(labels.len(), 1);
+        assert_eq!(
+            labels["mscatccDgq7azndWHFTzvEuZuywCsUvTRu"],
+            "Exchange deposits"
+        );
+
+        // el archivo se vuelve a leer sin la wallet quitada, sin wallet activa y con el historial de la otra
+        let mut restored = WalletsState::new(path.to_string()).unwrap();
+        assert_eq!(restored.wallets.len(), 1);
+        assert_eq!(restored.active_pubkey, None);
+        assert_eq!(restored.wallets[0].name, "exchange");
+        assert_eq!(restored.wallets[0].history.len(), 1);
+        assert_eq!(restored.wallets[0].history[0].value, 5000);
+
+        // quitar una wallet que no es la activa no cambia la activa
+        restored
+            .set_active("mscatccDgq7azndWHFTzvEuZuywCsUvTRu")
+            .unwrap();
+        restored
+            .append(
+                Wallet::watch_only(
+                    String::from("test"),
+                    String::from("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm"),
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        assert!(!restored
+            .remove("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm", &mut meta)
+            .unwrap());
+        assert_eq!(
+            restored.get_active().unwrap().pubkey,
+            "mscatccDgq7azndWHFTzvEuZuywCsUvTRu"
+        );
+
+        remove_file(path).unwrap();
+        fs::remove_dir_all(store_path).unwrap();
+    }
+
     #[test]
     fn get_wallets() {
         let wallets = WalletsState::new("tests/test_wallets.bin".to_string()).unwrap();
@@ -240,7 +1029,13 @@ mod tests {
 
     #[test]
     fn set_active_wallet() {
-        let mut wallets = WalletsState::new("tests/test_wallets.bin".to_string()).unwrap();
+        fs::copy(
+            "tests/test_wallets.bin",
+            "tests/test_wallets_set_active.bin",
+        )
+        .unwrap();
+        let mut wallets =
+            WalletsState::new("tests/test_wallets_set_active.bin".to_string()).unwrap();
         assert_eq!(wallets.active_pubkey, None);
 
         wallets
@@ -250,11 +1045,117 @@ mod tests {
             wallets.active_pubkey,
             Some("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm".to_string())
         );
+
+        remove_file("tests/test_wallets_set_active.bin").unwrap();
+    }
+
+    #[test]
+    fn set_active_wallet_unknown_pubkey() {
+        fs::copy(
+            "tests/test_wallets.bin",
+            "tests/test_wallets_set_active_unknown.bin",
+        )
+        .unwrap();
+        let mut wallets =
+            WalletsState::new("tests/test_wallets_set_active_unknown.bin".to_string()).unwrap();
+        wallets
+            .set_active("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm")
+            .unwrap();
+
+        let result = wallets.set_active("unknown pubkey");
+        assert!(matches!(result, Err(CustomError::WalletNotFound)));
+        assert_eq!(
+            wallets.active_pubkey,
+            Some("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm".to_string())
+        );
+
+        remove_file("tests/test_wallets_set_active_unknown.bin").unwrap();
+    }
+
+    #[test]
+    fn clear_active_wallet() {
+        fs::copy(
+            "tests/test_wallets.bin",
+            "tests/test_wallets_clear_active.bin",
+        )
+        .unwrap();
+        let mut wallets =
+            WalletsState::new("tests/test_wallets_clear_active.bin".to_string()).unwrap();
+        wallets
+            .set_active("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm")
+            .unwrap();
+        assert!(wallets.get_active().is_some());
+
+        wallets.clear_active().unwrap();
+        assert_eq!(wallets.active_pubkey, None);
+        assert!(wallets.get_active().is_none());
+
+        remove_file("tests/test_wallets_clear_active.bin").unwrap();
+    }
+
+    #[test]
+    fn restore_active_wallet() {
+        fs::copy(
+            "tests/test_wallets.bin",
+            "tests/test_wallets_restore_active.bin",
+        )
+        .unwrap();
+        let mut wallets =
+            WalletsState::new("tests/test_wallets_restore_active.bin".to_string()).unwrap();
+        wallets
+            .set_active("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm")
+            .unwrap();
+
+        let mut restored =
+            WalletsState::new("tests/test_wallets_restore_active.bin".to_string()).unwrap();
+        assert_eq!(restored.wallets.len(), 1);
+        assert_eq!(
+            restored.active_pubkey,
+            Some("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm".to_string())
+        );
+
+        // sin wallet activa el archivo se acorta, sin dejar restos de la public key anterior
+        restored.clear_active().unwrap();
+        let restored =
+            WalletsState::new("tests/test_wallets_restore_active.bin".to_string()).unwrap();
+        assert_eq!(restored.wallets.len(), 1);
+        assert_eq!(restored.active_pubkey, None);
+
+        remove_file("tests/test_wallets_restore_active.bin").unwrap();
+    }
+
+    #[test]
+    fn restore_active_wallet_that_no_longer_exists() {
+        fs::copy(
+            "tests/test_wallets.bin",
+            "tests/test_wallets_restore_deleted.bin",
+        )
+        .unwrap();
+        let mut wallets =
+            WalletsState::new("tests/test_wallets_restore_deleted.bin".to_string()).unwrap();
+        wallets
+            .set_active("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm")
+            .unwrap();
+        wallets.wallets.clear();
+        wallets.save().unwrap();
+
+        let restored =
+            WalletsState::new("tests/test_wallets_restore_deleted.bin".to_string()).unwrap();
+        assert!(restored.wallets.is_empty());
+        assert_eq!(restored.active_pubkey, None);
+
+        remove_file("tests/test_wallets_restore_deleted.bin").unwrap();
     }
 
     #[test]
     fn get_active_wallet() {
-        let mut wallets = WalletsState::new("tests/test_wallets.bin".to_string()).unwrap();
+        fs::copy(
+            "tests/test_wallets.bin",
+            "tests/test_wallets_get_active.bin",
+        )
+        .unwrap();
+        let mut wallets =
+            WalletsState::new("tests/test_wallets_get_active.bin".to_string()).unwrap();
         assert_eq!(wallets.active_pubkey, None);
 
         assert!(wallets.get_active().is_none());
@@ -270,6 +1171,8 @@ mod tests {
         let active_wallet = wallets.get_active().unwrap();
         assert_eq!(active_wallet.name, "wallet 1");
         assert_eq!(active_wallet.pubkey, "mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm");
+
+        remove_file("tests/test_wallets_get_active.bin").unwrap();
     }
 
     #[test]
@@ -324,11 +1227,533 @@ mod tests {
 
         let utxo = UTXO::new(String::from("tests"), String::from("test_utxo.bin")).unwrap();
 
-        let updated = wallets.update(&block, &utxo).unwrap();
+        let affected = wallets.update(&block, &utxo).unwrap();
 
-        assert_eq!(updated, true);
+        assert_eq!(
+            affected,
+            vec!["mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm".to_string()]
+        );
         assert_eq!(wallets.get_active().unwrap().history.len(), 1);
 
         remove_file("tests/test_wallets_update.bin".to_string()).unwrap();
     }
+
+    #[test]
+    fn update_wallets_without_active_wallet() {
+        fs::copy(
+            "tests/test_wallets.bin".to_string(),
+            "tests/test_wallets_update_inactive.bin".to_string(),
+        )
+        .unwrap();
+
+        let mut wallets =
+            WalletsState::new("tests/test_wallets_update_inactive.bin".to_string()).unwrap();
+        assert_eq!(wallets.active_pubkey, None);
+
+        assert_eq!(wallets.get_all()[0].history.len(), 0);
+
+        let block = Block {
+            header: BlockHeader {
+                version: 536887296,
+                prev_block_hash: vec![],
+                merkle_root: vec![],
+                timestamp: 1686626483,
+                bits: 421617023,
+                nonce: 3878826733,
+                hash: vec![],
+                block_downloaded: true,
+                broadcasted: true,
+            },
+            transactions: vec![Transaction {
+                version: 1,
+                inputs: vec![TransactionInput {
+                    previous_output: OutPoint {
+                        hash: vec![],
+                        index: 4294967295,
+                    },
+                    script_sig: vec![],
+                    sequence: 4294967295,
+                }],
+                outputs: vec![TransactionOutput {
+                    value: 2366975,
+                    script_pubkey: vec![
+                        118, 169, 20, 27, 40, 219, 33, 69, 20, 4, 108, 105, 234, 87, 71, 50, 50,
+                        154, 22, 16, 220, 64, 85, 136, 172,
+                    ],
+                }],
+                lock_time: 0,
+            }],
+        };
+
+        let utxo = UTXO::new(String::from("tests"), String::from("test_utxo.bin")).unwrap();
+
+        let affected = wallets.update(&block, &utxo).unwrap();
+
+        assert_eq!(
+            affected,
+            vec!["mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm".to_string()]
+        );
+        assert_eq!(wallets.get_all()[0].history.len(), 1);
+
+        remove_file("tests/test_wallets_update_inactive.bin".to_string()).unwrap();
+    }
+
+    #[test]
+    fn import_watch_only_rescans_utxo() {
+        fs::copy(
+            "tests/test_wallets.bin".to_string(),
+            "tests/test_wallets_import.bin".to_string(),
+        )
+        .unwrap();
+        let mut wallets = WalletsState::new("tests/test_wallets_import.bin".to_string()).unwrap();
+
+        // el segundo output le paga en P2WPKH a la misma clave que la direccion P2PKH de deposit 2
+        let mut p2wpkh_script = vec![0x00, 0x14];
+        p2wpkh_script
+            .extend(get_pubkey_hash("mxz3drZtkg4R3u1RDL7zRPLsizvhmGWfr3".to_string()).unwrap());
+        let block = Block {
+            header: BlockHeader {
+                version: 536887296,
+                prev_block_hash: vec![],
+                merkle_root: vec![],
+                timestamp: 1686626483,
+                bits: 421617023,
+                nonce: 3878826733,
+                hash: vec![],
+                block_downloaded: true,
+                broadcasted: true,
+            },
+            transactions: vec![Transaction {
+                version: 1,
+                inputs: vec![TransactionInput {
+                    previous_output: OutPoint {
+                        hash: vec![],
+                        index: 4294967295,
+                    },
+                    script_sig: vec![],
+                    sequence: 4294967295,
+                }],
+                outputs: vec![
+                    TransactionOutput {
+                        value: 5000,
+                        script_pubkey: vec![
+                            118, 169, 20, 132, 178, 35, 78, 47, 170, 110, 26, 117, 29, 126, 82,
+                            132, 235, 16, 204, 230, 247, 81, 246, 136, 172,
+                        ],
+                    },
+                    TransactionOutput {
+                        value: 7000,
+                        script_pubkey: p2wpkh_script,
+                    },
+                ],
+                lock_time: 0,
+            }],
+        };
+        let mut utxo = UTXO::new(String::from("tests"), String::from("unused.bin")).unwrap();
+        utxo.update_from_block(&block, false).unwrap();
+
+        let new_wallets = vec![
+            Wallet::watch_only(
+                String::from("deposit 1"),
+                String::from("mscatccDgq7azndWHFTzvEuZuywCsUvTRu"),
+            )
+            .unwrap(),
+            Wallet::watch_only(
+                String::from("deposit 2"),
+                String::from("mxz3drZtkg4R3u1RDL7zRPLsizvhmGWfr3"),
+            )
+            .unwrap(),
+            // ya existe en test_wallets.bin
+            Wallet::watch_only(
+                String::from("deposit 3"),
+                String::from("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm"),
+            )
+            .unwrap(),
+        ];
+
+        let imported = wallets.import_watch_only(new_wallets, &utxo).unwrap();
+        assert_eq!(
+            imported,
+            vec![
+                "mscatccDgq7azndWHFTzvEuZuywCsUvTRu".to_string(),
+                "mxz3drZtkg4R3u1RDL7zRPLsizvhmGWfr3".to_string()
+            ]
+        );
+        assert_eq!(wallets.get_all().len(), 3);
+        assert_eq!(wallets.get_all()[1].history.len(), 1);
+        assert_eq!(wallets.get_all()[1].history[0].value, 5000);
+        assert_eq!(wallets.get_all()[2].history.len(), 1);
+        assert_eq!(wallets.get_all()[2].history[0].value, 7000);
+
+        let restored = WalletsState::new("tests/test_wallets_import.bin".to_string()).unwrap();
+        assert_eq!(restored.get_all().len(), 3);
+        assert!(restored.get_all()[1].is_watch_only());
+
+        remove_file("tests/test_wallets_import.bin".to_string()).unwrap();
+    }
+
+    #[test]
+    fn abandoned_transaction_reconciled_on_confirmation() {
+        fs::copy(
+            "tests/test_wallets.bin".to_string(),
+            "tests/test_wallets_abandoned.bin".to_string(),
+        )
+        .unwrap();
+        let mut wallets =
+            WalletsState::new("tests/test_wallets_abandoned.bin".to_string()).unwrap();
+        let script_pubkey = vec![
+            118, 169, 20, 27, 40, 219, 33, 69, 20, 4, 108, 105, 234, 87, 71, 50, 50, 154, 22, 16,
+            220, 64, 85, 136, 172,
+        ];
+
+        let wallet_out_point = OutPoint {
+            hash: vec![1; 32],
+            index: 0,
+        };
+        let mut utxo = UTXO::new(String::from("tests"), String::from("unused.bin")).unwrap();
+        utxo.tx_set.insert(
+            wallet_out_point.clone(),
+            UTXOValue {
+                tx_out: TransactionOutput {
+                    value: 1000,
+                    script_pubkey,
+                },
+                block_hash: vec![2; 32],
+                block_timestamp: 0,
+                is_coinbase: false,
+            },
+        );
+
+        let tx = Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output: wallet_out_point,
+                script_sig: vec![],
+                sequence: 4294967295,
+            }],
+            outputs: vec![TransactionOutput {
+                value: 900,
+                script_pubkey: vec![],
+            }],
+            lock_time: 0,
+        };
+        let mut foreign_tx = tx.clone();
+        foreign_tx.inputs[0].previous_output.hash = vec![3; 32];
+
+        assert!(wallets.is_authored(&tx, &utxo).unwrap());
+        assert!(!wallets.is_authored(&foreign_tx, &utxo).unwrap());
+
+        let affected = wallets.mark_abandoned(&tx, &utxo).unwrap();
+        assert_eq!(
+            affected,
+            vec!["mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm".to_string()]
+        );
+        let history = wallets.get_all()[0].get_history();
+        let abandoned = history.last().unwrap();
+        assert_eq!(abandoned.block_hash, None);
+        assert_eq!(abandoned.value, -1000);
+
+        let restored = WalletsState::new("tests/test_wallets_abandoned.bin".to_string()).unwrap();
+        let restored_history = restored.get_all()[0].get_history();
+        assert_eq!(restored_history.last().unwrap().tx_hash, tx.hash());
+        assert_eq!(restored_history.last().unwrap().block_hash, None);
+
+        // la transaccion se confirma igual luego de abandonarla
+        let block = Block {
+            header: BlockHeader {
+                version: 536887296,
+                prev_block_hash: vec![],
+                merkle_root: vec![],
+                timestamp: 1686626483,
+                bits: 421617023,
+                nonce: 3878826733,
+                hash: vec![4; 32],
+                block_downloaded: true,
+                broadcasted: true,
+            },
+            transactions: vec![tx.clone()],
+        };
+        wallets.update(&block, &utxo).unwrap();
+
+        let history = wallets.get_all()[0].get_history();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].tx_hash, tx.hash());
+        assert_eq!(history[0].block_hash, Some(vec![4; 32]));
+
+        remove_file("tests/test_wallets_abandoned.bin".to_string()).unwrap();
+    }
+
+    #[test]
+    fn legacy_abandoned_movements_are_migrated_to_wallet_meta() {
+        let path = "tests/test_wallets_legacy_abandoned.bin";
+        let store_path = "tests/wallet_meta_legacy_wallets";
+        let pubkey = "mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm";
+        let mut wallet = Wallet::watch_only(String::from("legacy"), pubkey.to_string()).unwrap();
+        wallet.history.push(Movement {
+            tx_hash: vec![5; 32],
+            value: -1000,
+            block_hash: None,
+            provisional: false,
+        });
+        let mut buffer = wallet.serialize();
+        // los archivos anteriores guardaban los movimientos abandonados con el byte de estado 2
+        *buffer.last_mut().unwrap() = 2;
+        fs::write(path, buffer).unwrap();
+
+        let mut wallets = WalletsState::new(path.to_string()).unwrap();
+        let mut meta = WalletMetaState::new(store_path);
+        assert_eq!(wallets.migrate_metadata(&mut meta).unwrap(), 1);
+        assert_eq!(wallets.migrate_metadata(&mut meta).unwrap(), 0);
+
+        // las wallets se volvieron a guardar sin el dato, que ahora esta en los metadatos
+        let mut restored = WalletsState::new(path.to_string()).unwrap();
+        let mut restored_meta = WalletMetaState::new(store_path);
+        assert_eq!(restored.migrate_metadata(&mut restored_meta).unwrap(), 0);
+        assert_eq!(restored.get_all()[0].history.len(), 1);
+        assert!(restored_meta.get(pubkey, &[5; 32]).unwrap().abandoned);
+
+        remove_file(path).unwrap();
+        fs::remove_dir_all(store_path).unwrap();
+    }
+
+    #[test]
+    fn provisional_movements_reconciled_with_full_block() {
+        fs::copy(
+            "tests/test_wallets.bin".to_string(),
+            "tests/test_wallets_provisional.bin".to_string(),
+        )
+        .unwrap();
+        let mut wallets =
+            WalletsState::new("tests/test_wallets_provisional.bin".to_string()).unwrap();
+        let utxo = UTXO::new(String::from("tests"), String::from("unused.bin")).unwrap();
+
+        let received_tx = |value: u64| Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output: OutPoint {
+                    hash: vec![value as u8; 32],
+                    index: 0,
+                },
+                script_sig: vec![],
+                sequence: 4294967295,
+            }],
+            outputs: vec![TransactionOutput {
+                value,
+                script_pubkey: vec![
+                    118, 169, 20, 27, 40, 219, 33, 69, 20, 4, 108, 105, 234, 87, 71, 50, 50, 154,
+                    22, 16, 220, 64, 85, 136, 172,
+                ],
+            }],
+            lock_time: 0,
+        };
+        let confirmed_tx = received_tx(1000);
+        let missing_tx = received_tx(2000);
+        let header = BlockHeader {
+            version: 536887296,
+            prev_block_hash: vec![],
+            merkle_root: vec![],
+            timestamp: 1686626483,
+            bits: 421617023,
+            nonce: 3878826733,
+            hash: vec![5; 32],
+            block_downloaded: true,
+            broadcasted: true,
+        };
+
+        let filtered_block = Block {
+            header: header.clone(),
+            transactions: vec![confirmed_tx.clone(), missing_tx],
+        };
+        let affected = wallets.update_provisional(&filtered_block, &utxo).unwrap();
+        assert_eq!(
+            affected,
+            vec!["mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm".to_string()]
+        );
+        let history = wallets.get_all()[0].get_history();
+        assert_eq!(history.len(), 2);
+        assert!(history.iter().all(|movement| movement.provisional));
+
+        // un merkleblock repetido no duplica los movimientos
+        assert!(wallets
+            .update_provisional(&filtered_block, &utxo)
+            .unwrap()
+            .is_empty());
+
+        // el bloque completo confirma la transaccion que tiene y descarta la otra
+        let block = Block {
+            header,
+            transactions: vec![confirmed_tx.clone()],
+        };
+        wallets.update(&block, &utxo).unwrap();
+        let history = wallets.get_all()[0].get_history();
+        assert_eq!(history.len(), 1);
+        assert!(!history[0].provisional);
+        assert_eq!(history[0].tx_hash, confirmed_tx.hash());
+        assert_eq!(history[0].value, 1000);
+
+        remove_file("tests/test_wallets_provisional.bin").unwrap();
+    }
+
+    #[test]
+    fn encrypted_wallets_round_trip() {
+        let path = "tests/test_wallets_encrypted.bin".to_string();
+        fs::copy("tests/test_wallets.bin", &path).unwrap();
+
+        let mut wallets = WalletsState::new(path.clone()).unwrap();
+        assert!(!wallets.is_encrypted());
+        wallets.set_passphrase("my passphrase").unwrap();
+        assert!(!wallets.is_locked());
+        assert_eq!(wallets.wallets[0].privkey, "private key 1");
+
+        // la private key no queda en texto plano en el archivo
+        let file = fs::read(&path).unwrap();
+        assert!(!file
+            .windows("private key 1".len())
+            .any(|window| window == b"private key 1"));
+
+        let mut restored = WalletsState::new(path.clone()).unwrap();
+        assert!(restored.is_locked());
+        assert_ne!(restored.wallets[0].privkey, "private key 1");
+
+        let new_wallet = Wallet::new(
+            String::from("wallet 2"),
+            String::from("mxz3drZtkg4R3u1RDL7zRPLsizvhmGWfr3"),
+            String::from("private key 2"),
+            &UTXO::new(String::from("tests"), String::from("test_utxo.bin")).unwrap(),
+        )
+        .unwrap();
+        assert!(matches!(
+            restored.append(new_wallet.clone()),
+            Err(CustomError::WalletsLocked)
+        ));
+
+        assert!(matches!(
+            restored.unlock("wrong passphrase"),
+            Err(CustomError::InvalidPassphrase)
+        ));
+        assert!(restored.is_locked());
+
+        restored.unlock("my passphrase").unwrap();
+        assert!(!restored.is_locked());
+        assert_eq!(restored.wallets[0].privkey, "private key 1");
+
+        // las wallets agregadas despues de desbloquear tambien se guardan cifradas
+        restored.append(new_wallet).unwrap();
+        let mut restored = WalletsState::new(path.clone()).unwrap();
+        restored.unlock("my passphrase").unwrap();
+        assert_eq!(restored.wallets[1].privkey, "private key 2");
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn plaintext_wallets_upgraded_on_save() {
+        let path = "tests/test_wallets_upgraded.bin".to_string();
+        fs::copy("tests/test_wallets.bin", &path).unwrap();
+
+        let wallets = WalletsState::new(path.clone()).unwrap();
+        assert!(!wallets.is_locked());
+        wallets.save().unwrap();
+
+        let file = fs::read(&path).unwrap();
+        assert_eq!(file[..WALLETS_MAGIC.len()], WALLETS_MAGIC);
+        assert_eq!(file[WALLETS_MAGIC.len()], WALLETS_V6);
+        assert_eq!(file[WALLETS_MAGIC.len() + 1], WALLETS_PLAIN);
+
+        let restored = WalletsState::new(path.clone()).unwrap();
+        assert!(!restored.is_encrypted());
+        assert_eq!(restored.wallets[0].privkey, "private key 1");
+
+        remove_file(path).unwrap();
+    }
+
+    const TPUB: &str = "tpubD8eQVK4Kdxg3gHrF62jGP7dKVCoYiEB8dFSpuTawkL5YxTus5j5pf83vaKnii4bc6v2NVEy81P2gYrJczYne3QNNwMTS53p5uzDyHvnw2jm";
+
+    fn coinbase_block(outputs: Vec<(&Vec<u8>, u64)>) -> Block {
+        Block {
+            header: BlockHeader {
+                version: 536887296,
+                prev_block_hash: vec![],
+                merkle_root: vec![],
+                timestamp: 1686626483,
+                bits: 421617023,
+                nonce: 3878826733,
+                hash: vec![],
+                block_downloaded: true,
+                broadcasted: true,
+            },
+            transactions: vec![Transaction {
+                version: 1,
+                inputs: vec![TransactionInput {
+                    previous_output: OutPoint {
+                        hash: vec![],
+                        index: 4294967295,
+                    },
+                    script_sig: vec![],
+                    sequence: 4294967295,
+                }],
+                outputs: outputs
+                    .into_iter()
+                    .map(|(pubkey_hash, value)| {
+                        let mut script_pubkey = vec![0x76, 0xa9, 0x14];
+                        script_pubkey.extend(pubkey_hash);
+                        script_pubkey.extend([0x88, 0xac]);
+                        TransactionOutput {
+                            value,
+                            script_pubkey,
+                        }
+                    })
+                    .collect(),
+                lock_time: 0,
+            }],
+        }
+    }
+
+    #[test]
+    fn xpub_wallet_extends_gap_and_aggregates_balance() {
+        let path = "tests/test_wallets_xpub.bin".to_string();
+        let _ = remove_file(&path);
+        let mut wallets = WalletsState::new(path.clone()).unwrap();
+        // con gap limit 6, las primeras 6 direcciones de recepcion y la primera de cambio
+        let hashes = XpubAccount::new(TPUB, 6).unwrap().pubkey_hashes();
+        let (external, change) = (&hashes[..6], &hashes[6]);
+
+        let mut utxo = UTXO::new(String::from("tests"), String::from("unused.bin")).unwrap();
+        utxo.update_from_block(
+            &coinbase_block(vec![(&external[2], 1000), (change, 500)]),
+            false,
+        )
+        .unwrap();
+
+        let wallet = Wallet::from_xpub(String::from("account"), TPUB, 3).unwrap();
+        let pubkey = wallet.pubkey.clone();
+        wallets.import_watch_only(vec![wallet], &utxo).unwrap();
+        let wallet = &wallets.get_all()[0];
+        // la ultima direccion de recepcion tenia UTXO, asi que se derivaron 3 mas
+        assert_eq!(wallet.account.as_ref().unwrap().derived_counts(), (6, 4));
+        assert_eq!(wallet.history.len(), 2);
+        assert_eq!(utxo.wallet_balance(wallet).unwrap(), 1500);
+
+        let block = coinbase_block(vec![(&external[5], 700)]);
+        let extended = wallets.extend_xpub_gaps(&block, &utxo).unwrap();
+        assert_eq!(extended, vec![pubkey.clone()]);
+        let affected = wallets.update(&block, &utxo).unwrap();
+        assert_eq!(affected, vec![pubkey]);
+        utxo.update_from_block(&block, false).unwrap();
+
+        let wallet = &wallets.get_all()[0];
+        assert_eq!(wallet.account.as_ref().unwrap().derived_counts(), (9, 4));
+        assert_eq!(wallet.history.len(), 3);
+        assert_eq!(utxo.wallet_balance(wallet).unwrap(), 2200);
+
+        let restored = WalletsState::new(path.clone()).unwrap();
+        let account = restored.get_all()[0].account.as_ref().unwrap();
+        assert_eq!(account.derived_counts(), (9, 4));
+        assert_eq!(
+            account.pubkey_hashes(),
+            wallet.account.as_ref().unwrap().pubkey_hashes()
+        );
+
+        remove_file(path).unwrap();
+    }
 }
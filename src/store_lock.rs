@@ -0,0 +1,54 @@
+use std::fs::{self, File, TryLockError};
+
+use crate::{
+    error::CustomError,
+    utils::{open_file, OpenMode},
+};
+
+/// Archivo del store sobre el que se toma el lock mientras el nodo lo usa.
+pub const STORE_LOCK_FILE: &str = ".lock";
+
+/// StoreLock es el lock exclusivo del store que tiene el nodo mientras corre, para que dos procesos
+/// no escriban los mismos archivos a la vez. Es un lock del sistema operativo sobre STORE_LOCK_FILE,
+/// asi que se libera al soltar el StoreLock o cuando termina el proceso, aunque sea abruptamente.
+pub struct StoreLock {
+    _file: File,
+}
+
+impl StoreLock {
+    /// Toma el lock del store, creando su directorio si no existe.
+    /// Devuelve CustomError::StoreLocked si otro proceso ya lo tiene.
+    pub fn acquire(store_path: &str) -> Result<Self, CustomError> {
+        fs::create_dir_all(store_path)?;
+        let file = open_file(
+            &format!("{store_path}/{STORE_LOCK_FILE}"),
+            OpenMode::ReadCreate,
+        )?;
+        match file.try_lock() {
+            Ok(()) => Ok(Self { _file: file }),
+            Err(TryLockError::WouldBlock) => Err(CustomError::StoreLocked),
+            Err(TryLockError::Error(error)) => Err(error.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_can_only_be_locked_once() {
+        let store_path = "tests/store_lock";
+        let _ = fs::remove_dir_all(store_path);
+
+        let lock = StoreLock::acquire(store_path).unwrap();
+        assert!(matches!(
+            StoreLock::acquire(store_path),
+            Err(CustomError::StoreLocked)
+        ));
+        drop(lock);
+        assert!(StoreLock::acquire(store_path).is_ok());
+
+        fs::remove_dir_all(store_path).unwrap();
+    }
+}
@@ -1,6 +1,16 @@
 use bitcoin_hashes::{sha256d, Hash};
 
-use crate::{error::CustomError, parser::BufferParser};
+use crate::{
+    error::CustomError,
+    parser::{BufferParser, VarIntSerialize},
+    peer::GENESIS,
+};
+
+const BACKUP_VERSION_CHANGED: u8 = 0b01;
+const BACKUP_BITS_CHANGED: u8 = 0b10;
+
+/// Bits del target maximo, el de dificultad 1 de mainnet y testnet.
+pub const MAX_TARGET_BITS: u32 = 0x1d00ffff;
 
 #[derive(Debug, Clone)]
 ///Esta estructura representa el header de un bloque, el cual contiene la siguiente información:
@@ -49,15 +59,49 @@ impl BlockHeader {
         buffer
     }
 
+    /// Serializa el header para el backup v2, codificado como diferencia con el header anterior (prev):
+    /// - flags: Un byte que indica si la version y los bits cambiaron respecto del header anterior.
+    /// - version: Solo si cambio.
+    /// - timestamp: Diferencia con el timestamp anterior como varint (en zigzag, ya que puede ser negativa).
+    /// - bits: Solo si cambiaron.
+    /// - merkle_root y nonce.
+    /// El prev_block_hash y el hash no se guardan, se derivan del header anterior al restaurar.
+    /// Si no hay header anterior se guardan la version y los bits y el timestamp completo.
+    pub fn serialize_for_backup_v2(&self, prev: Option<&BlockHeader>) -> Vec<u8> {
+        let mut flags = 0;
+        if prev.map(|prev| prev.version) != Some(self.version) {
+            flags |= BACKUP_VERSION_CHANGED;
+        }
+        if prev.map(|prev| prev.bits) != Some(self.bits) {
+            flags |= BACKUP_BITS_CHANGED;
+        }
+        let prev_timestamp = prev.map(|prev| prev.timestamp).unwrap_or(0);
+        let timestamp_delta = self.timestamp as i64 - prev_timestamp as i64;
+
+        let mut buffer: Vec<u8> = vec![flags];
+        if flags & BACKUP_VERSION_CHANGED != 0 {
+            buffer.extend(&self.version.to_le_bytes());
+        }
+        buffer.extend((zigzag_encode(timestamp_delta) as usize).to_varint_bytes());
+        if flags & BACKUP_BITS_CHANGED != 0 {
+            buffer.extend(&self.bits.to_le_bytes());
+        }
+        buffer.extend(&self.merkle_root);
+        buffer.extend(&self.nonce.to_le_bytes());
+
+        buffer
+    }
+
     ///Esta funcion se encarga de dado un vector de bytes, parsearlo a un BlockHeader con todos sus campos correspondientes
     /// Tambien se encarga de validar que el header sea valido, es decir, que cumpla con la proof of work, esto solo lo hace si el parametro validate es true.
+    /// Devuelve CustomError si el buffer no tiene exactamente 80 bytes, ya que el hash se calcula sobre el buffer completo.
     pub fn parse(buffer: Vec<u8>) -> Result<Self, CustomError> {
+        if buffer.len() != 80 {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
         let hash = sha256d::Hash::hash(&buffer).to_byte_array().to_vec();
 
         let mut parser = BufferParser::new(buffer);
-        if parser.len() < 80 {
-            return Err(CustomError::SerializedBufferIsInvalid);
-        }
 
         let block_header = BlockHeader {
             version: parser.extract_i32()?,
@@ -103,29 +147,68 @@ impl BlockHeader {
         Ok(block_header)
     }
 
+    /// Parsea un header del backup v2 a partir del header anterior (prev), o del genesis si no hay.
+    /// El hash se recalcula con el prev_block_hash derivado y se valida la proof of work,
+    /// por lo que un encadenamiento incorrecto devuelve error.
+    pub fn parse_from_backup_v2(
+        parser: &mut BufferParser,
+        prev: Option<&BlockHeader>,
+    ) -> Result<Self, CustomError> {
+        let flags = parser.extract_u8()?;
+        let version = match prev {
+            Some(prev) if flags & BACKUP_VERSION_CHANGED == 0 => prev.version,
+            _ => parser.extract_i32()?,
+        };
+        let prev_timestamp = prev.map(|prev| prev.timestamp).unwrap_or(0);
+        let timestamp_delta = zigzag_decode(parser.extract_varint()?);
+        let timestamp = (prev_timestamp as i64)
+            .checked_add(timestamp_delta)
+            .and_then(|timestamp| u32::try_from(timestamp).ok())
+            .ok_or(CustomError::SerializedBufferIsInvalid)?;
+        let bits = match prev {
+            Some(prev) if flags & BACKUP_BITS_CHANGED == 0 => prev.bits,
+            _ => parser.extract_u32()?,
+        };
+        let prev_block_hash = prev
+            .map(|prev| prev.hash().clone())
+            .unwrap_or(GENESIS.to_vec());
+
+        let mut buffer: Vec<u8> = vec![];
+        buffer.extend(&version.to_le_bytes());
+        buffer.extend(&prev_block_hash);
+        buffer.extend(parser.extract_buffer(32)?);
+        buffer.extend(&timestamp.to_le_bytes());
+        buffer.extend(&bits.to_le_bytes());
+        buffer.extend(parser.extract_buffer(4)?);
+
+        let mut block_header = Self::parse(buffer)?;
+        block_header.block_downloaded = true;
+        block_header.broadcasted = true;
+        Ok(block_header)
+    }
+
     ///Esta funcion se encarga de validar la proof of work de un bloque.
     fn validate(&self) -> bool {
-        let hash = self.hash();
-        let bits_vec = self.bits.to_be_bytes().to_vec();
+        meets_target(self.hash(), self.bits)
+    }
 
-        let leading_zeros_start = bits_vec[0] as usize;
-        let leading_zeros = hash[leading_zeros_start..32].to_vec();
+    /// Verifica la proof of work del header: recalcula el hash a partir de sus campos,
+    /// verifica que coincida con el hash guardado y que no supere el target que indican los bits.
+    /// Devuelve CustomError::InvalidProofOfWork si alguna de las verificaciones falla.
+    pub fn verify_proof_of_work(&self) -> Result<(), CustomError> {
+        let hash = sha256d::Hash::hash(&self.serialize())
+            .to_byte_array()
+            .to_vec();
 
-        if leading_zeros.iter().any(|zero| *zero != 0_u8) {
-            return false;
+        if hash != self.hash || !meets_target(&hash, self.bits) {
+            return Err(CustomError::InvalidProofOfWork);
         }
+        Ok(())
+    }
 
-        let mut significants = hash[(leading_zeros_start - 3)..leading_zeros_start].to_vec();
-        significants.reverse();
-
-        let mut bits_vec_pos = 1;
-        for hash_byte in significants {
-            if hash_byte != bits_vec[bits_vec_pos] {
-                return hash_byte < bits_vec[bits_vec_pos];
-            }
-            bits_vec_pos += 1;
-        }
-        false
+    /// Devuelve la dificultad del header (ver difficulty_from_bits).
+    pub fn difficulty(&self) -> f64 {
+        difficulty_from_bits(self.bits)
     }
 
     /// Esta funcion se encarga de calcular el hash del header de un bloque
@@ -137,6 +220,137 @@ impl BlockHeader {
     pub fn hash_as_string(&self) -> String {
         hash_as_string(self.hash().clone())
     }
+
+    /// Devuelve los campos decodificados del header, junto a su height y confirmaciones, como pares (nombre, valor).
+    /// Los hashes se muestran como en los exploradores (invertidos y en minuscula) y los bits en hexadecimal.
+    pub fn fields(&self, height: usize, confirmations: u32) -> Vec<(&'static str, String)> {
+        vec![
+            ("Hash", display_hash(self.hash())),
+            ("Height", height.to_string()),
+            ("Confirmations", confirmations.to_string()),
+            ("Version", self.version.to_string()),
+            ("Previous block", display_hash(&self.prev_block_hash)),
+            ("Merkle root", display_hash(&self.merkle_root)),
+            ("Time", self.timestamp.to_string()),
+            ("Bits", format!("{:08x}", self.bits)),
+            ("Nonce", self.nonce.to_string()),
+        ]
+    }
+}
+
+/// Convierte los bits (formato compacto: 1 byte de exponente y 3 de mantisa) en el target de 256 bits en big endian.
+/// Devuelve None si el target es negativo, cero o no entra en 256 bits.
+fn target_from_bits(bits: u32) -> Option<[u8; 32]> {
+    if bits & 0x00800000 != 0 {
+        return None;
+    }
+    let exponent = (bits >> 24) as i32;
+    let mantissa = bits.to_be_bytes();
+
+    let mut target = [0_u8; 32];
+    for (i, byte) in mantissa[1..].iter().enumerate() {
+        let position = exponent - 1 - i as i32;
+        if position < 0 {
+            continue;
+        }
+        if position >= 32 {
+            if *byte != 0 {
+                return None;
+            }
+            continue;
+        }
+        target[31 - position as usize] = *byte;
+    }
+
+    if target.iter().all(|byte| *byte == 0) {
+        return None;
+    }
+    Some(target)
+}
+
+/// Devuelve la dificultad que indican los bits: cuantas veces menor es su target que el de MAX_TARGET_BITS.
+/// Devuelve 0 si los bits no indican un target valido.
+pub fn difficulty_from_bits(bits: u32) -> f64 {
+    let (Some(max_target), Some(target)) =
+        (target_from_bits(MAX_TARGET_BITS), target_from_bits(bits))
+    else {
+        return 0.0;
+    };
+    target_as_f64(&max_target) / target_as_f64(&target)
+}
+
+/// Devuelve los bits del ajuste de dificultad como CalculateNextWorkRequired de Bitcoin Core: el target de bits se multiplica
+/// por timespan (limitado entre un cuarto y el cuadruple de target_timespan), se divide por target_timespan y no puede
+/// superar el target de MAX_TARGET_BITS. Como en Bitcoin Core, se pierde la precision que no entra en el formato compacto.
+pub fn retarget_bits(bits: u32, timespan: i64, target_timespan: u32) -> u32 {
+    let (Some(max_target), Some(mut target)) =
+        (target_from_bits(MAX_TARGET_BITS), target_from_bits(bits))
+    else {
+        return MAX_TARGET_BITS;
+    };
+    let target_timespan = target_timespan.max(1) as u64;
+    let timespan = timespan.clamp(target_timespan as i64 / 4, target_timespan as i64 * 4) as u64;
+
+    let mut carry: u64 = 0;
+    for byte in target.iter_mut().rev() {
+        let value = *byte as u64 * timespan + carry;
+        *byte = value as u8;
+        carry = value >> 8;
+    }
+    if carry != 0 {
+        return MAX_TARGET_BITS;
+    }
+    let mut remainder: u64 = 0;
+    for byte in target.iter_mut() {
+        let value = (remainder << 8) | *byte as u64;
+        *byte = (value / target_timespan) as u8;
+        remainder = value % target_timespan;
+    }
+
+    if target > max_target {
+        return MAX_TARGET_BITS;
+    }
+    bits_from_target(&target)
+}
+
+/// Convierte un target de 256 bits en big endian a bits (formato compacto), como GetCompact de Bitcoin Core.
+fn bits_from_target(target: &[u8; 32]) -> u32 {
+    let Some(first) = target.iter().position(|byte| *byte != 0) else { return 0 };
+    let mut size = (32 - first) as u32;
+    let mut mantissa = (0..3).fold(0_u32, |mantissa, i| {
+        (mantissa << 8) | *target.get(first + i).unwrap_or(&0) as u32
+    });
+    // el bit mas alto de la mantisa es el signo, por lo que se corre un byte
+    if mantissa & 0x00800000 != 0 {
+        mantissa >>= 8;
+        size += 1;
+    }
+    (size << 24) | mantissa
+}
+
+/// Convierte un target de 256 bits en big endian a f64.
+fn target_as_f64(target: &[u8; 32]) -> f64 {
+    target
+        .iter()
+        .fold(0.0, |value, byte| value * 256.0 + *byte as f64)
+}
+
+/// Devuelve true si el hash (en little endian, como se transmite) no supera el target que indican los bits.
+fn meets_target(hash: &[u8], bits: u32) -> bool {
+    let Some(target) = target_from_bits(bits) else { return false };
+    if hash.len() != 32 {
+        return false;
+    }
+    let hash_be: Vec<u8> = hash.iter().rev().copied().collect();
+    hash_be.as_slice() <= target.as_slice()
+}
+
+fn zigzag_encode(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn zigzag_decode(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
 }
 
 /// Esta funcion se encarga de convertir un vector de bytes en hexa que forma un hash a un string
@@ -148,9 +362,40 @@ pub fn hash_as_string(hash: Vec<u8>) -> String {
     filename
 }
 
+/// Devuelve el hash como lo muestran los exploradores: con los bytes invertidos y en hexadecimal en minuscula.
+pub fn display_hash(hash: &[u8]) -> String {
+    hash.iter()
+        .rev()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Convierte un hash ingresado como lo muestran los exploradores (ver display_hash) al orden interno de sus bytes.
+/// Devuelve None si no son 64 caracteres hexadecimales.
+pub fn parse_display_hash(hash: &str) -> Option<Vec<u8>> {
+    if hash.len() != 64 || !hash.is_ascii() {
+        return None;
+    }
+    let mut bytes = (0..hash.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hash[i..i + 2], 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    bytes.reverse();
+    Some(bytes)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::structs::block_header::BlockHeader;
+    use bitcoin_hashes::{sha256d, Hash};
+
+    use crate::{
+        error::CustomError,
+        parser::{BufferParser, VarIntSerialize},
+        structs::block_header::{
+            bits_from_target, difficulty_from_bits, display_hash, parse_display_hash,
+            retarget_bits, target_from_bits, BlockHeader,
+        },
+    };
 
     #[test]
     fn blockheader_serialize_and_parse() {
@@ -170,6 +415,58 @@ mod tests {
         assert_eq!(buffer_clone, serialized_block_header);
     }
 
+    #[test]
+    fn blockheader_backup_v2_roundtrip() {
+        let buffer = vec![
+            1, 0, 0, 0, 5, 159, 141, 74, 195, 4, 19, 253, 127, 1, 148, 149, 222, 143, 237, 24, 27,
+            124, 186, 34, 123, 241, 216, 166, 203, 239, 86, 108, 0, 0, 0, 0, 233, 233, 109, 115,
+            249, 241, 6, 200, 176, 73, 10, 24, 28, 209, 102, 159, 255, 179, 239, 72, 185, 225, 10,
+            14, 219, 74, 174, 208, 207, 59, 18, 12, 170, 7, 195, 79, 255, 255, 0, 29, 14, 171, 58,
+            61,
+        ];
+        let block_header = BlockHeader::parse(buffer).unwrap();
+        let mut prev = block_header.clone();
+        prev.hash = block_header.prev_block_hash.clone();
+        prev.timestamp = block_header.timestamp + 100;
+
+        let serialized = block_header.serialize_for_backup_v2(Some(&prev));
+        // flags + timestamp delta + merkle root + nonce
+        assert_eq!(serialized.len(), 1 + 1 + 32 + 4);
+
+        let mut parser = BufferParser::new(serialized);
+        let parsed = BlockHeader::parse_from_backup_v2(&mut parser, Some(&prev)).unwrap();
+        assert!(parser.is_empty());
+        assert_eq!(parsed.serialize(), block_header.serialize());
+        assert_eq!(parsed.hash, block_header.hash);
+    }
+
+    #[test]
+    fn blockheader_backup_v2_overflowing_timestamp_delta_is_invalid() {
+        let buffer = vec![
+            1, 0, 0, 0, 5, 159, 141, 74, 195, 4, 19, 253, 127, 1, 148, 149, 222, 143, 237, 24, 27,
+            124, 186, 34, 123, 241, 216, 166, 203, 239, 86, 108, 0, 0, 0, 0, 233, 233, 109, 115,
+            249, 241, 6, 200, 176, 73, 10, 24, 28, 209, 102, 159, 255, 179, 239, 72, 185, 225, 10,
+            14, 219, 74, 174, 208, 207, 59, 18, 12, 170, 7, 195, 79, 255, 255, 0, 29, 14, 171, 58,
+            61,
+        ];
+        let prev = BlockHeader::parse(buffer).unwrap();
+
+        let mut serialized = vec![0];
+        serialized.extend((super::zigzag_encode(i64::MAX) as usize).to_varint_bytes());
+        serialized.extend([0; 36]);
+
+        let mut parser = BufferParser::new(serialized);
+        let parsed = BlockHeader::parse_from_backup_v2(&mut parser, Some(&prev));
+        assert!(parsed.is_err());
+    }
+
+    #[test]
+    fn zigzag_roundtrip() {
+        for value in [0, 1, -1, 600, -7200, i64::MAX, i64::MIN] {
+            assert_eq!(super::zigzag_decode(super::zigzag_encode(value)), value);
+        }
+    }
+
     #[test]
     fn blockheader_too_short_buffer() {
         let buffer = vec![1, 0];
@@ -179,6 +476,22 @@ mod tests {
         assert!(block_header.is_err());
     }
 
+    #[test]
+    fn blockheader_with_trailing_bytes_is_invalid() {
+        let mut buffer = vec![
+            1, 0, 0, 0, 5, 159, 141, 74, 195, 4, 19, 253, 127, 1, 148, 149, 222, 143, 237, 24, 27,
+            124, 186, 34, 123, 241, 216, 166, 203, 239, 86, 108, 0, 0, 0, 0, 233, 233, 109, 115,
+            249, 241, 6, 200, 176, 73, 10, 24, 28, 209, 102, 159, 255, 179, 239, 72, 185, 225, 10,
+            14, 219, 74, 174, 208, 207, 59, 18, 12, 170, 7, 195, 79, 255, 255, 0, 29, 14, 171, 58,
+            61,
+        ];
+        buffer.push(0);
+
+        let block_header = BlockHeader::parse(buffer);
+
+        assert!(block_header.is_err());
+    }
+
     #[test]
     fn blockheader_invalid_buffer() {
         let buffer = vec![
@@ -245,4 +558,158 @@ mod tests {
 
         assert!(!valid_header.validate());
     }
+
+    fn testnet_header() -> BlockHeader {
+        let mut buffer = vec![];
+        buffer.extend(2_i32.to_le_bytes());
+        buffer.extend([
+            61, 8, 52, 163, 234, 98, 255, 92, 186, 170, 164, 90, 56, 131, 46, 171, 52, 239, 104,
+            223, 166, 65, 183, 217, 36, 6, 53, 63, 0, 0, 0, 0,
+        ]);
+        buffer.extend([
+            45, 107, 6, 225, 181, 124, 4, 88, 86, 174, 58, 59, 113, 215, 174, 42, 209, 149, 142,
+            110, 166, 53, 244, 88, 6, 76, 228, 77, 7, 10, 189, 126,
+        ]);
+        buffer.extend(1347149007_u32.to_le_bytes());
+        buffer.extend(476726600_u32.to_le_bytes());
+        buffer.extend(240236131_u32.to_le_bytes());
+        BlockHeader::parse(buffer).unwrap()
+    }
+
+    #[test]
+    fn verify_proof_of_work_testnet_header() {
+        let header = testnet_header();
+        assert!(header.verify_proof_of_work().is_ok());
+    }
+
+    #[test]
+    fn verify_proof_of_work_corrupted_nonce() {
+        let mut header = testnet_header();
+        header.nonce = 123123;
+        assert!(matches!(
+            header.verify_proof_of_work(),
+            Err(CustomError::InvalidProofOfWork)
+        ));
+
+        // aunque se recalcule el hash guardado, no cumple con el target
+        header.hash = sha256d::Hash::hash(&header.serialize())
+            .to_byte_array()
+            .to_vec();
+        assert!(matches!(
+            header.verify_proof_of_work(),
+            Err(CustomError::InvalidProofOfWork)
+        ));
+    }
+
+    #[test]
+    fn target_from_bits_compact_format() {
+        let target = target_from_bits(0x1d00ffff).unwrap();
+        assert_eq!(target[..4], [0, 0, 0, 0]);
+        assert_eq!(target[4..6], [255, 255]);
+        assert!(target[6..].iter().all(|byte| *byte == 0));
+
+        let target = target_from_bits(0x207fffff).unwrap();
+        assert_eq!(target[..3], [127, 255, 255]);
+
+        assert!(target_from_bits(0x20ffffff).is_none());
+        assert!(target_from_bits(0x1d000000).is_none());
+        assert!(target_from_bits(0x22010000).is_none());
+    }
+
+    #[test]
+    fn retarget_bits_matches_bitcoin_core() {
+        // casos de pow_tests de Bitcoin Core: (bits, primer timestamp, ultimo timestamp, bits esperados)
+        let cases = [
+            (0x1d00ffff, 1261130161, 1262152739, 0x1d00d86a),
+            // el nuevo target supera el maximo
+            (0x1d00ffff, 1231006505, 1233061996, 0x1d00ffff),
+            // se limita a un cuarto y al cuadruple del tiempo esperado
+            (0x1c05a3f4, 1279008237, 1279297671, 0x1c0168fd),
+            (0x1c387f6f, 1263163443, 1269211443, 0x1d00e1fd),
+        ];
+        for (bits, first, last, expected) in cases {
+            assert_eq!(retarget_bits(bits, last - first, 2016 * 600), expected);
+        }
+
+        for bits in [0x1d00ffff, 0x1c05a3f4, 0x1b04864c, 0x207fffff] {
+            assert_eq!(bits_from_target(&target_from_bits(bits).unwrap()), bits);
+        }
+    }
+
+    #[test]
+    fn difficulty_from_bits_matches_known_values() {
+        // (bits, dificultad): target maximo (genesis de mainnet y testnet), mainnet 100000, ejemplo de la wiki,
+        // mainnet 800000 y regtest
+        let known = [
+            (0x1d00ffff, 1.0),
+            (0x1b04864c, 14484.1623612254),
+            (0x1a44b9f2, 244112.48777434),
+            (0x17053894, 53911173001054.59),
+            (0x207fffff, 4.6565423739069247e-10),
+        ];
+        for (bits, expected) in known {
+            let difficulty = difficulty_from_bits(bits);
+            assert!(
+                (difficulty - expected).abs() <= expected * 1e-12,
+                "{bits:08x}: {difficulty} != {expected}"
+            );
+        }
+
+        assert_eq!(difficulty_from_bits(0x1d000000), 0.0);
+        assert_eq!(difficulty_from_bits(0x20ffffff), 0.0);
+    }
+
+    #[test]
+    fn blockheader_fields_are_decoded() {
+        let buffer = vec![
+            1, 0, 0, 0, 5, 159, 141, 74, 195, 4, 19, 253, 127, 1, 148, 149, 222, 143, 237, 24, 27,
+            124, 186, 34, 123, 241, 216, 166, 203, 239, 86, 108, 0, 0, 0, 0, 233, 233, 109, 115,
+            249, 241, 6, 200, 176, 73, 10, 24, 28, 209, 102, 159, 255, 179, 239, 72, 185, 225, 10,
+            14, 219, 74, 174, 208, 207, 59, 18, 12, 170, 7, 195, 79, 255, 255, 0, 29, 14, 171, 58,
+            61,
+        ];
+        let block_header = BlockHeader::parse(buffer).unwrap();
+
+        let fields = block_header.fields(2, 5);
+        let expected = vec![
+            (
+                "Hash",
+                "00000000904167e7ac3953720af45ad7ffad72307d5067cae2cc4d60855f7a83",
+            ),
+            ("Height", "2"),
+            ("Confirmations", "5"),
+            ("Version", "1"),
+            (
+                "Previous block",
+                "000000006c56efcba6d8f17b22ba7c1b18ed8fde9594017ffd1304c34a8d9f05",
+            ),
+            (
+                "Merkle root",
+                "0c123bcfd0ae4adb0e0ae1b948efb3ff9f66d11c180a49b0c806f1f9736de9e9",
+            ),
+            ("Time", "1338181546"),
+            ("Bits", "1d00ffff"),
+            ("Nonce", "1027255054"),
+        ];
+        let fields: Vec<(&str, &str)> = fields
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+            .collect();
+        assert_eq!(fields, expected);
+    }
+
+    #[test]
+    fn display_hash_round_trip() {
+        let hash: Vec<u8> = (0..32).collect();
+        let display = display_hash(&hash);
+        assert!(display.starts_with("1f1e1d"));
+        assert_eq!(parse_display_hash(&display), Some(hash));
+        assert_eq!(
+            parse_display_hash(&display.to_uppercase()).unwrap().len(),
+            32
+        );
+
+        assert!(parse_display_hash("1f1e").is_none());
+        assert!(parse_display_hash(&"zz".repeat(32)).is_none());
+    }
 }
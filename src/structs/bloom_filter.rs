@@ -0,0 +1,388 @@
+use crate::{
+    error::CustomError,
+    messages::transaction::Transaction,
+    parser::{BufferParser, VarIntSerialize},
+    structs::outpoint::OutPoint,
+};
+
+/// Tamaño maximo en bytes de un filtro bloom (BIP 37).
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36000;
+
+/// Cantidad maxima de funciones de hash de un filtro bloom (BIP 37).
+pub const MAX_HASH_FUNCS: u32 = 50;
+
+/// El peer no agrega nada al filtro cuando una transaccion coincide.
+pub const BLOOM_UPDATE_NONE: u8 = 0;
+
+/// El peer agrega al filtro el outpoint de cada output que coincide, para detectar cuando se gasta.
+pub const BLOOM_UPDATE_ALL: u8 = 1;
+
+/// Constante que separa las funciones de hash del filtro, definida por BIP 37.
+const HASH_FUNC_SEED_MULTIPLIER: u32 = 0xFBA4C795;
+
+const LN2_SQUARED: f64 = std::f64::consts::LN_2 * std::f64::consts::LN_2;
+
+#[derive(Debug, Clone, PartialEq)]
+/// BloomFilter es un filtro bloom de BIP 37, con el que los peers solo nos envian las transacciones que nos interesan.
+/// Puede dar falsos positivos (con la probabilidad elegida al crearlo) pero nunca falsos negativos.
+/// Los elementos son:
+/// - data: Bits del filtro.
+/// - hash_funcs: Cantidad de funciones de hash con las que se inserta cada elemento.
+/// - tweak: Valor aleatorio que se suma a la semilla de cada funcion de hash.
+/// - flags: Como actualiza el peer el filtro cuando una transaccion coincide (BLOOM_UPDATE_NONE o BLOOM_UPDATE_ALL).
+pub struct BloomFilter {
+    data: Vec<u8>,
+    hash_funcs: u32,
+    tweak: u32,
+    flags: u8,
+}
+
+impl BloomFilter {
+    /// Crea un filtro vacio dimensionado para elements elementos con una probabilidad de falso positivo fp_rate,
+    /// con el mismo calculo que Bitcoin Core para que el tamaño sea el esperado por los peers.
+    pub fn new(elements: usize, fp_rate: f64, tweak: u32, flags: u8) -> Self {
+        let elements = elements.max(1);
+        let bits = (-1.0 / LN2_SQUARED * elements as f64 * fp_rate.ln()) as usize;
+        let size = bits.min(MAX_BLOOM_FILTER_SIZE * 8) / 8;
+        let hash_funcs = ((size * 8 / elements) as f64 * std::f64::consts::LN_2) as u32;
+
+        Self {
+            data: vec![0; size],
+            hash_funcs: hash_funcs.min(MAX_HASH_FUNCS),
+            tweak,
+            flags,
+        }
+    }
+
+    /// Agrega un elemento al filtro.
+    pub fn insert(&mut self, element: &[u8]) {
+        if self.data.is_empty() {
+            return;
+        }
+        for hash_num in 0..self.hash_funcs {
+            let index = self.bit_index(hash_num, element);
+            self.data[index >> 3] |= 1 << (index & 7);
+        }
+    }
+
+    /// Devuelve true si el elemento puede estar en el filtro, false si seguro no esta.
+    /// Un filtro sin bits coincide con todo.
+    pub fn contains(&self, element: &[u8]) -> bool {
+        if self.data.is_empty() {
+            return true;
+        }
+        (0..self.hash_funcs).all(|hash_num| {
+            let index = self.bit_index(hash_num, element);
+            self.data[index >> 3] & (1 << (index & 7)) != 0
+        })
+    }
+
+    /// Devuelve true si la transaccion coincide con el filtro segun BIP 37: su hash, algun dato de los scripts de sus outputs,
+    /// el outpoint que gasta alguno de sus inputs o algun dato de sus script sigs.
+    /// Con BLOOM_UPDATE_ALL agrega al filtro el outpoint de cada output que coincide, para que coincida tambien la
+    /// transaccion que lo gaste.
+    pub fn matches_transaction(&mut self, transaction: &Transaction) -> bool {
+        let tx_hash = transaction.hash();
+        let mut matches = self.contains(&tx_hash);
+
+        for (index, output) in transaction.outputs.iter().enumerate() {
+            if script_data(&output.script_pubkey)
+                .iter()
+                .any(|data| self.contains(data))
+            {
+                matches = true;
+                if self.flags == BLOOM_UPDATE_ALL {
+                    let out_point = OutPoint {
+                        hash: tx_hash.clone(),
+                        index: index as u32,
+                    };
+                    self.insert(&out_point.serialize());
+                }
+            }
+        }
+        if matches {
+            return true;
+        }
+
+        transaction.inputs.iter().any(|input| {
+            self.contains(&input.previous_output.serialize())
+                || script_data(&input.script_sig)
+                    .iter()
+                    .any(|data| self.contains(data))
+        })
+    }
+
+    /// Devuelve la cantidad de funciones de hash del filtro.
+    pub fn hash_funcs(&self) -> u32 {
+        self.hash_funcs
+    }
+
+    /// Devuelve el tamaño del filtro en bytes.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Devuelve true si el filtro no tiene bits.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn bit_index(&self, hash_num: u32, element: &[u8]) -> usize {
+        let seed = hash_num
+            .wrapping_mul(HASH_FUNC_SEED_MULTIPLIER)
+            .wrapping_add(self.tweak);
+        murmur3(seed, element) as usize % (self.data.len() * 8)
+    }
+
+    /// Serializa el filtro como en el payload de un mensaje filterload.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buffer = vec![];
+        buffer.extend(self.data.len().to_varint_bytes());
+        buffer.extend(&self.data);
+        buffer.extend(self.hash_funcs.to_le_bytes());
+        buffer.extend(self.tweak.to_le_bytes());
+        buffer.push(self.flags);
+        buffer
+    }
+
+    /// Parsea un filtro serializado con serialize.
+    /// Devuelve CustomError si el filtro supera MAX_BLOOM_FILTER_SIZE o MAX_HASH_FUNCS.
+    pub fn parse(parser: &mut BufferParser) -> Result<Self, CustomError> {
+        let size = parser.extract_varint()? as usize;
+        if size > MAX_BLOOM_FILTER_SIZE {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+        let data = parser.extract_buffer(size)?.to_vec();
+        let hash_funcs = parser.extract_u32()?;
+        if hash_funcs > MAX_HASH_FUNCS {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+        let tweak = parser.extract_u32()?;
+        let flags = parser.extract_u8()?;
+
+        Ok(Self {
+            data,
+            hash_funcs,
+            tweak,
+            flags,
+        })
+    }
+}
+
+/// Devuelve los datos que agregan al stack los pushes de un script, salteando el resto de las operaciones.
+/// Si el script esta truncado devuelve los datos leidos hasta ese punto.
+fn script_data(script: &[u8]) -> Vec<&[u8]> {
+    let mut data = vec![];
+    let mut position = 0;
+    while position < script.len() {
+        let opcode = script[position];
+        let (length, data_start) = match opcode {
+            0x01..=0x4b => (opcode as usize, position + 1),
+            0x4c => match script.get(position + 1) {
+                Some(length) => (*length as usize, position + 2),
+                None => break,
+            },
+            0x4d => match script.get(position + 1..position + 3) {
+                Some(length) => (
+                    u16::from_le_bytes([length[0], length[1]]) as usize,
+                    position + 3,
+                ),
+                None => break,
+            },
+            0x4e => match script.get(position + 1..position + 5) {
+                Some(length) => (
+                    u32::from_le_bytes([length[0], length[1], length[2], length[3]]) as usize,
+                    position + 5,
+                ),
+                None => break,
+            },
+            _ => {
+                position += 1;
+                continue;
+            }
+        };
+        let Some(push) = script.get(data_start..data_start + length) else { break };
+        data.push(push);
+        position = data_start + length;
+    }
+    data
+}
+
+/// Calcula el hash MurmurHash3 (x86, 32 bits) de data con la semilla recibida, el que usa BIP 37.
+pub fn murmur3(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+
+    let mut hash = seed;
+    let mut blocks = data.chunks_exact(4);
+    for block in &mut blocks {
+        let mut k = u32::from_le_bytes([block[0], block[1], block[2], block[3]]);
+        k = k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        hash ^= k;
+        hash = hash
+            .rotate_left(13)
+            .wrapping_mul(5)
+            .wrapping_add(0xe6546b64);
+    }
+
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        let mut k: u32 = 0;
+        for (index, byte) in tail.iter().enumerate() {
+            k ^= (*byte as u32) << (8 * index);
+        }
+        k = k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        hash ^= k;
+    }
+
+    hash ^= data.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85ebca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2ae35);
+    hash ^= hash >> 16;
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::structs::{tx_input::TransactionInput, tx_output::TransactionOutput};
+
+    use super::*;
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn murmur3_matches_reference_values() {
+        assert_eq!(murmur3(0x00000000, &[]), 0x00000000);
+        assert_eq!(murmur3(0xFBA4C795, &[]), 0x6a396f08);
+        assert_eq!(murmur3(0xffffffff, &[]), 0x81f16f39);
+        assert_eq!(murmur3(0x00000000, &[0x00]), 0x514e28b7);
+        assert_eq!(murmur3(0xFBA4C795, &[0x00]), 0xea3f0b17);
+        assert_eq!(murmur3(0x00000000, &[0xff]), 0xfd6cf10d);
+        assert_eq!(murmur3(0x00000000, &[0x00, 0x11]), 0x16c6b7ab);
+        assert_eq!(murmur3(0x00000000, &[0x00, 0x11, 0x22]), 0x8eb51c3d);
+        assert_eq!(murmur3(0x00000000, &[0x00, 0x11, 0x22, 0x33]), 0xb4471bf8);
+        assert_eq!(
+            murmur3(0x00000000, &[0x00, 0x11, 0x22, 0x33, 0x44]),
+            0xe2301fa8
+        );
+    }
+
+    fn bip37_filter(tweak: u32) -> BloomFilter {
+        let mut filter = BloomFilter::new(3, 0.01, tweak, BLOOM_UPDATE_ALL);
+        filter.insert(&from_hex("99108ad8ed9bb6274d3980bab5a85c048f0950c8"));
+        filter.insert(&from_hex("b5a2c786d9ef4658287ced5914b37a1b4aa32eee"));
+        filter.insert(&from_hex("b9300670b4c5366e95b2699e8b18bc75e5f729c5"));
+        filter
+    }
+
+    #[test]
+    fn bloom_filter_matches_reference_serialization() {
+        let filter = bip37_filter(0);
+        assert_eq!(filter.len(), 3);
+        assert_eq!(filter.hash_funcs(), 5);
+        assert_eq!(filter.serialize(), from_hex("03614e9b050000000000000001"));
+
+        let filter = bip37_filter(2147483649);
+        assert_eq!(filter.serialize(), from_hex("03ce4299050000000100008001"));
+    }
+
+    #[test]
+    fn bloom_filter_contains_inserted_elements() {
+        let filter = bip37_filter(0);
+        assert!(filter.contains(&from_hex("99108ad8ed9bb6274d3980bab5a85c048f0950c8")));
+        assert!(!filter.contains(&from_hex("19108ad8ed9bb6274d3980bab5a85c048f0950c8")));
+        assert!(filter.contains(&from_hex("b5a2c786d9ef4658287ced5914b37a1b4aa32eee")));
+    }
+
+    #[test]
+    fn bloom_filter_size_is_bounded() {
+        let filter = BloomFilter::new(1_000_000, 0.000001, 0, BLOOM_UPDATE_NONE);
+        assert_eq!(filter.len(), MAX_BLOOM_FILTER_SIZE);
+        assert!(filter.hash_funcs() <= MAX_HASH_FUNCS);
+
+        // un filtro sin bits coincide con todo
+        let mut filter = BloomFilter::new(1, 0.9, 0, BLOOM_UPDATE_NONE);
+        assert!(filter.is_empty());
+        filter.insert(&[1, 2, 3]);
+        assert!(filter.contains(&[4, 5, 6]));
+    }
+
+    fn p2pkh_transaction(pubkey_hash: &[u8], previous_output: OutPoint) -> Transaction {
+        let mut script_pubkey = vec![0x76, 0xa9, 0x14];
+        script_pubkey.extend(pubkey_hash);
+        script_pubkey.extend([0x88, 0xac]);
+        Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output,
+                script_sig: vec![0x02, 0xaa, 0xbb],
+                sequence: u32::MAX,
+            }],
+            outputs: vec![TransactionOutput {
+                value: 1000,
+                script_pubkey,
+            }],
+            lock_time: 0,
+        }
+    }
+
+    #[test]
+    fn bloom_filter_matches_transactions() {
+        let pubkey_hash = from_hex("99108ad8ed9bb6274d3980bab5a85c048f0950c8");
+        let mut filter = BloomFilter::new(2, 0.000001, 0, BLOOM_UPDATE_ALL);
+        filter.insert(&pubkey_hash);
+        let unrelated = p2pkh_transaction(
+            &[7; 20],
+            OutPoint {
+                hash: vec![1; 32],
+                index: 0,
+            },
+        );
+        assert!(!filter.matches_transaction(&unrelated));
+
+        // coincide por el script de su output y se agrega su outpoint al filtro
+        let paying = p2pkh_transaction(
+            &pubkey_hash,
+            OutPoint {
+                hash: vec![1; 32],
+                index: 0,
+            },
+        );
+        assert!(filter.matches_transaction(&paying));
+
+        // la transaccion que la gasta coincide por el outpoint
+        let spending = p2pkh_transaction(
+            &[7; 20],
+            OutPoint {
+                hash: paying.hash(),
+                index: 0,
+            },
+        );
+        assert!(filter.matches_transaction(&spending));
+
+        let mut filter = BloomFilter::new(2, 0.000001, 0, BLOOM_UPDATE_NONE);
+        filter.insert(&unrelated.hash());
+        assert!(filter.matches_transaction(&unrelated));
+        assert!(!filter.matches_transaction(&paying));
+    }
+
+    #[test]
+    fn bloom_filter_round_trip() {
+        let filter = bip37_filter(7);
+        let mut parser = BufferParser::new(filter.serialize());
+        assert_eq!(BloomFilter::parse(&mut parser).unwrap(), filter);
+        assert!(parser.is_empty());
+
+        let mut oversized = (MAX_BLOOM_FILTER_SIZE + 1).to_varint_bytes();
+        oversized.extend(vec![0; MAX_BLOOM_FILTER_SIZE + 1]);
+        oversized.extend([0; 9]);
+        assert!(BloomFilter::parse(&mut BufferParser::new(oversized)).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 pub mod block_header;
+pub mod bloom_filter;
 pub mod inventory;
 pub mod movement;
 pub mod outpoint;
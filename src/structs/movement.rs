@@ -6,14 +6,21 @@ use crate::{error::CustomError, parser::BufferParser};
 /// - tx_hash: Hash de la transaccion
 /// - value: Valor de la transaccion
 /// - block_hash: Hash del bloque en el que se encuentra la transaccion, en caso de una transaccion pendiente, no validada, este campo es None
+///   (en el historial solo quedan sin bloque las transacciones abandonadas)
+/// - provisional: Indica si el movimiento salio de un merkleblock y todavia no se verifico con el bloque completo
+///
+/// Los datos que no salen de la transaccion (nombre, fee, notas, si se abandono) se guardan aparte, ver WalletMetaState.
 pub struct Movement {
     pub tx_hash: Vec<u8>,
     pub value: i64,
     pub block_hash: Option<Vec<u8>>,
+    pub provisional: bool,
 }
 
 impl Movement {
     /// Esta funcion se encarga de serializar un movement en un vector de bytes.
+    /// Luego del valor se guarda un byte de estado: 0 sin bloque, 1 con bloque (seguido del block_hash)
+    /// y 3 provisional (seguido del block_hash). El 2 lo usaban los archivos anteriores para los abandonados.
     pub fn serialize(&self) -> Vec<u8> {
         let mut buffer = Vec::new();
         buffer.push(self.tx_hash.len() as u8);
@@ -21,7 +28,7 @@ impl Movement {
         buffer.extend(self.value.to_le_bytes());
         match self.block_hash.clone() {
             Some(block_hash) => {
-                buffer.push(1);
+                buffer.push(if self.provisional { 3 } else { 1 });
                 buffer.push(block_hash.len() as u8);
                 buffer.extend(block_hash);
             }
@@ -36,13 +43,19 @@ impl Movement {
     /// Devuelve CustomError si:
     /// - Falla alguna extraccion del BufferParser
     pub fn parse(parser: &mut BufferParser) -> Result<Self, CustomError> {
+        Ok(Self::parse_legacy(parser)?.0)
+    }
+
+    /// Como parse, pero devuelve tambien si el movimiento se guardo como abandonado (byte de estado 2),
+    /// para migrar ese dato a WalletMetaState.
+    pub fn parse_legacy(parser: &mut BufferParser) -> Result<(Self, bool), CustomError> {
         let tx_hash_len = parser.extract_u8()? as usize;
         let tx_hash = parser.extract_buffer(tx_hash_len)?.to_vec();
         let value = parser.extract_i64()?;
         let block_hash_present = parser.extract_u8()?;
         let block_hash = match block_hash_present {
-            0 => None,
-            1 => {
+            0 | 2 => None,
+            1 | 3 => {
                 let block_hash_len = parser.extract_u8()? as usize;
                 Some(parser.extract_buffer(block_hash_len)?.to_vec())
             }
@@ -53,11 +66,13 @@ impl Movement {
             }
         };
 
-        Ok(Self {
+        let movement = Self {
             tx_hash,
             value,
             block_hash,
-        })
+            provisional: block_hash_present == 3,
+        };
+        Ok((movement, block_hash_present == 2))
     }
 }
 
@@ -77,6 +92,7 @@ mod tests {
                 167, 131, 118, 190, 70, 199, 31, 2, 255, 135, 123, 36, 232, 182, 60, 178, 165, 110,
                 47, 11, 50, 1, 133, 106, 59, 195, 153, 210, 59, 21, 163, 41,
             ]),
+            provisional: false,
         };
         let serialized_movement = movement.serialize();
         let mut parser = BufferParser::new(serialized_movement);
@@ -107,6 +123,7 @@ mod tests {
             ],
             value: 500,
             block_hash: None,
+            provisional: false,
         };
         let serialized_movement = movement.serialize();
         let mut parser = BufferParser::new(serialized_movement);
@@ -121,4 +138,35 @@ mod tests {
         assert_eq!(parsed_movement.value, 500);
         assert_eq!(parsed_movement.block_hash, None);
     }
+
+    #[test]
+    fn legacy_abandoned_movement_is_parsed() {
+        let mut serialized_movement = vec![32];
+        serialized_movement.extend(vec![1; 32]);
+        serialized_movement.extend((-500_i64).to_le_bytes());
+        serialized_movement.push(2);
+        let mut parser = BufferParser::new(serialized_movement);
+        let (parsed_movement, abandoned) = Movement::parse_legacy(&mut parser).unwrap();
+        assert_eq!(parsed_movement.value, -500);
+        assert_eq!(parsed_movement.block_hash, None);
+        assert!(abandoned);
+
+        // al volver a guardarlo queda como un movimiento sin bloque
+        let mut parser = BufferParser::new(parsed_movement.serialize());
+        assert!(!Movement::parse_legacy(&mut parser).unwrap().1);
+    }
+
+    #[test]
+    fn provisional_movement_serialization() {
+        let movement = Movement {
+            tx_hash: vec![1; 32],
+            value: 500,
+            block_hash: Some(vec![2; 32]),
+            provisional: true,
+        };
+        let mut parser = BufferParser::new(movement.serialize());
+        let parsed_movement = Movement::parse(&mut parser).unwrap();
+        assert_eq!(parsed_movement.block_hash, Some(vec![2; 32]));
+        assert!(parsed_movement.provisional);
+    }
 }
@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use crate::{
     error::CustomError,
     parser::{BufferParser, VarIntSerialize},
@@ -34,14 +36,47 @@ impl TransactionOutput {
         })
     }
 
-    /// Esta funcion se encarga de verificar si un output esta enviado a una clave publica del tipo P2PKH.
+    /// Esta funcion se encarga de verificar si un output esta enviado a una clave publica del tipo P2PKH o P2WPKH.
     pub fn is_sent_to_key(&self, public_key_hash: &Vec<u8>) -> Result<bool, CustomError> {
+        if let Some(hash) = self.get_p2wpkh_hash() {
+            return Ok(hash == *public_key_hash);
+        }
         let parser = &mut BufferParser::new(self.script_pubkey.clone());
         match parser.extract_u8() {
             Ok(0x76) => compare_p2pkh(parser, public_key_hash),
             _ => Ok(false),
         }
     }
+
+    /// Esta funcion se encarga de verificar si un output esta enviado a alguna de las claves publicas del tipo P2PKH o P2WPKH.
+    /// Extrae una sola vez el hash del script pubkey y lo busca en el conjunto de hashes.
+    pub fn is_sent_to_any_key(&self, public_key_hashes: &HashSet<Vec<u8>>) -> bool {
+        self.get_pubkey_hash()
+            .map_or(false, |hash| public_key_hashes.contains(&hash))
+    }
+
+    /// Esta funcion devuelve el hash de la clave publica si el output esta enviado a una clave publica del tipo P2PKH.
+    pub fn get_p2pkh_hash(&self) -> Option<Vec<u8>> {
+        match self.script_pubkey.as_slice() {
+            [0x76, 0xa9, 0x14, hash @ ..] if hash.len() >= 20 => Some(hash[..20].to_vec()),
+            _ => None,
+        }
+    }
+
+    /// Esta funcion devuelve el hash de la clave publica (el witness program) si el output esta enviado a una
+    /// clave publica del tipo P2WPKH (OP_0 seguido de 20 bytes).
+    pub fn get_p2wpkh_hash(&self) -> Option<Vec<u8>> {
+        match self.script_pubkey.as_slice() {
+            [0x00, 0x14, hash @ ..] if hash.len() == 20 => Some(hash.to_vec()),
+            _ => None,
+        }
+    }
+
+    /// Esta funcion devuelve el hash de la clave publica si el output esta enviado a una clave publica del tipo
+    /// P2PKH o P2WPKH.
+    pub fn get_pubkey_hash(&self) -> Option<Vec<u8>> {
+        self.get_p2pkh_hash().or_else(|| self.get_p2wpkh_hash())
+    }
 }
 
 /// Esta funcion se encarga de comparar un script pubkey con una clave publica del tipo P2PKH.
@@ -64,9 +99,14 @@ fn compare_p2pkh(
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashSet;
+
     use crate::{
-        messages::transaction::Transaction, parser::BufferParser, states::utxo_state::UTXO,
-        structs::tx_output::TransactionOutput, wallet::Wallet,
+        messages::transaction::Transaction,
+        parser::BufferParser,
+        states::utxo_state::UTXO,
+        structs::tx_output::TransactionOutput,
+        wallet::{get_pubkey_hash, get_script_pubkey, Wallet},
     };
 
     #[test]
@@ -122,4 +162,51 @@ mod tests {
         }
         assert_eq!(found, false);
     }
+
+    #[test]
+    fn is_sent_to_key_recognizes_p2wpkh_outputs() {
+        let address = String::from("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx");
+        let public_key_hash = get_pubkey_hash(address.clone()).unwrap();
+        let p2wpkh = TransactionOutput {
+            value: 100,
+            script_pubkey: get_script_pubkey(address).unwrap(),
+        };
+        assert!(p2wpkh.is_sent_to_key(&public_key_hash).unwrap());
+        assert!(!p2wpkh.is_sent_to_key(&vec![0; 20]).unwrap());
+        assert_eq!(p2wpkh.get_p2pkh_hash(), None);
+        assert_eq!(p2wpkh.get_pubkey_hash(), Some(public_key_hash.clone()));
+
+        // OP_0 seguido de 32 bytes es P2WSH, no P2WPKH
+        let p2wsh = TransactionOutput {
+            value: 100,
+            script_pubkey: [vec![0x00, 0x20], vec![0; 32]].concat(),
+        };
+        assert!(!p2wsh.is_sent_to_key(&public_key_hash).unwrap());
+        assert_eq!(p2wsh.get_pubkey_hash(), None);
+    }
+
+    #[test]
+    fn is_sent_to_any_key_looks_up_p2pkh_and_p2wpkh_hashes() {
+        let p2wpkh_address = String::from("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx");
+        let p2pkh_address = String::from("mrSeAixYDXL17YZm6VEcxn9REZ6jK8DDgL");
+        let p2wpkh = TransactionOutput {
+            value: 100,
+            script_pubkey: get_script_pubkey(p2wpkh_address.clone()).unwrap(),
+        };
+        let p2pkh = TransactionOutput {
+            value: 100,
+            script_pubkey: get_script_pubkey(p2pkh_address.clone()).unwrap(),
+        };
+
+        let mut public_key_hashes = HashSet::from([vec![0; 20]]);
+        assert!(!p2wpkh.is_sent_to_any_key(&public_key_hashes));
+        assert!(!p2pkh.is_sent_to_any_key(&public_key_hashes));
+
+        public_key_hashes.insert(get_pubkey_hash(p2wpkh_address).unwrap());
+        assert!(p2wpkh.is_sent_to_any_key(&public_key_hashes));
+        assert!(!p2pkh.is_sent_to_any_key(&public_key_hashes));
+
+        public_key_hashes.insert(get_pubkey_hash(p2pkh_address).unwrap());
+        assert!(p2pkh.is_sent_to_any_key(&public_key_hashes));
+    }
 }
@@ -0,0 +1,255 @@
+use crate::{
+    error::CustomError,
+    message::Message,
+    messages::{
+        block::{block_subsidy, calculate_merkle_root, Block},
+        transaction::Transaction,
+    },
+    mining::{mine_header, REGTEST_BITS},
+    peer::GENESIS,
+    states::utxo_state::START_DATE_IBD,
+    structs::{
+        block_header::BlockHeader, outpoint::OutPoint, tx_input::TransactionInput,
+        tx_output::TransactionOutput,
+    },
+};
+
+/// Dificultad minima (la de regtest): aproximadamente la mitad de los nonces cumplen la proof of work.
+pub const SYNTHETIC_MIN_DIFFICULTY_BITS: u32 = REGTEST_BITS;
+
+/// Segundos entre los timestamps de dos headers consecutivos de la cadena sintetica.
+const SYNTHETIC_BLOCK_INTERVAL: u32 = 600;
+
+/// Tamaño del script_sig de las pseudo-transacciones, el de una firma con su public key.
+const SYNTHETIC_SCRIPT_SIG_SIZE: usize = 107;
+
+/// Tamaño del script_pubkey de los outputs de las pseudo-transacciones (P2PKH).
+const SYNTHETIC_SCRIPT_PUBKEY_SIZE: usize = 25;
+
+/// Tamaño aproximado de un header y los contadores de un bloque serializado, sin contar las transacciones.
+const BLOCK_OVERHEAD_SIZE: usize = 83;
+
+/// SyntheticChain es una cadena de headers y bloques generada en memoria de forma determinista,
+/// para medir el IBD y para los tests que necesitan una cadena valida.
+/// Los headers estan enlazados desde el GENESIS y cumplen la proof of work de sus bits.
+/// Los ultimos bloques son posteriores a START_DATE_IBD (los que el nodo descarga) y son los unicos que se generan.
+/// Los elementos son:
+/// - headers: Todos los headers de la cadena, en orden.
+/// - blocks: Los bloques de los headers posteriores a START_DATE_IBD, en orden.
+pub struct SyntheticChain {
+    pub headers: Vec<BlockHeader>,
+    pub blocks: Vec<Block>,
+}
+
+impl SyntheticChain {
+    /// Genera una cadena de n_headers headers cuyos ultimos n_blocks son posteriores a START_DATE_IBD y tienen su bloque,
+    /// de aproximadamente avg_block_kb kilobytes cada uno. Los headers se minan con la dificultad de bits.
+    /// Cada bloque tiene una coinbase y pseudo-transacciones que gastan los outputs de las del bloque anterior.
+    /// Como las UTXO se generan a partir del ultimo header anterior a START_DATE_IBD, tiene que haber al menos uno
+    /// (n_blocks menor a n_headers).
+    /// Devuelve CustomError::InvalidBenchArguments si no hay bloques o si no hay headers anteriores a START_DATE_IBD.
+    pub fn generate(
+        n_headers: usize,
+        n_blocks: usize,
+        avg_block_kb: usize,
+        bits: u32,
+    ) -> Result<Self, CustomError> {
+        if n_blocks == 0 || n_blocks >= n_headers {
+            return Err(CustomError::InvalidBenchArguments);
+        }
+
+        let first_block_index = n_headers - n_blocks;
+        let first_timestamp = (START_DATE_IBD + 1)
+            .saturating_sub(first_block_index as u32 * SYNTHETIC_BLOCK_INTERVAL);
+        let target_size = avg_block_kb * 1024;
+
+        let mut random = SyntheticRandom::new(n_headers as u64);
+        let mut headers: Vec<BlockHeader> = vec![];
+        let mut blocks: Vec<Block> = vec![];
+        let mut previous_tx_hashes: Vec<Vec<u8>> = vec![];
+        for height in 0..n_headers {
+            let prev_block_hash = headers
+                .last()
+                .map(|header| header.hash().clone())
+                .unwrap_or(GENESIS.to_vec());
+            let timestamp = first_timestamp + height as u32 * SYNTHETIC_BLOCK_INTERVAL;
+
+            if height < first_block_index {
+                let merkle_root = random.bytes(32);
+                headers.push(mine_synthetic_header(
+                    &prev_block_hash,
+                    &merkle_root,
+                    timestamp,
+                    bits,
+                )?);
+                continue;
+            }
+
+            let transactions =
+                synthetic_transactions(height, target_size, &previous_tx_hashes, &mut random);
+            previous_tx_hashes = transactions.iter().map(|tx| tx.hash()).collect();
+
+            let merkle_root =
+                calculate_merkle_root(&transactions).ok_or(CustomError::InvalidMerkleRoot)?;
+            let header = mine_synthetic_header(&prev_block_hash, &merkle_root, timestamp, bits)?;
+
+            headers.push(header.clone());
+            blocks.push(Block::new(header, transactions));
+        }
+
+        Ok(Self { headers, blocks })
+    }
+
+    /// Devuelve el tamaño total de los bloques serializados, en bytes.
+    pub fn blocks_size(&self) -> usize {
+        self.blocks
+            .iter()
+            .map(|block| block.serialize().len())
+            .sum()
+    }
+}
+
+/// Busca el primer nonce con el que el header cumple la proof of work de bits (ver mine_header).
+/// Devuelve CustomError::InvalidBenchArguments si ningun nonce sirve, porque bits es demasiado dificil.
+fn mine_synthetic_header(
+    prev_block_hash: &[u8],
+    merkle_root: &[u8],
+    timestamp: u32,
+    bits: u32,
+) -> Result<BlockHeader, CustomError> {
+    mine_header(1, prev_block_hash, merkle_root, timestamp, bits, u32::MAX)
+        .ok_or(CustomError::InvalidBenchArguments)
+}
+
+/// Genera las transacciones de un bloque: la coinbase y las pseudo-transacciones necesarias para llegar a target_size.
+/// La pseudo-transaccion i gasta el primer output de la transaccion i del bloque anterior, si existe.
+fn synthetic_transactions(
+    height: usize,
+    target_size: usize,
+    previous_tx_hashes: &[Vec<u8>],
+    random: &mut SyntheticRandom,
+) -> Vec<Transaction> {
+    let coinbase = Transaction {
+        version: 1,
+        inputs: vec![TransactionInput {
+            previous_output: OutPoint {
+                hash: vec![0; 32],
+                index: u32::MAX,
+            },
+            script_sig: (height as u64).to_le_bytes().to_vec(),
+            sequence: u32::MAX,
+        }],
+        outputs: vec![random.output(block_subsidy(height))],
+        lock_time: 0,
+    };
+    let tx_size = coinbase.serialize().len();
+    let available = target_size.saturating_sub(BLOCK_OVERHEAD_SIZE + tx_size);
+
+    let mut transactions = vec![coinbase];
+    let mut size = 0;
+    while size < available {
+        let index = transactions.len();
+        let previous_hash = previous_tx_hashes
+            .get(index)
+            .cloned()
+            .unwrap_or_else(|| random.bytes(32));
+        let value = random.next_u64() % 100_000_000;
+        let transaction = Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output: OutPoint {
+                    hash: previous_hash,
+                    index: 0,
+                },
+                script_sig: random.bytes(SYNTHETIC_SCRIPT_SIG_SIZE),
+                sequence: u32::MAX,
+            }],
+            outputs: vec![random.output(value)],
+            lock_time: 0,
+        };
+        size += transaction.serialize().len();
+        transactions.push(transaction);
+    }
+    transactions
+}
+
+/// Generador pseudo-aleatorio (xorshift64) para que la cadena sea siempre la misma para los mismos parametros.
+struct SyntheticRandom(u64);
+
+impl SyntheticRandom {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E3779B97F4A7C15) | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next_u64() as u8).collect()
+    }
+
+    fn output(&mut self, value: u64) -> TransactionOutput {
+        TransactionOutput {
+            value,
+            script_pubkey: self.bytes(SYNTHETIC_SCRIPT_PUBKEY_SIZE),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn synthetic_chain_is_valid() {
+        let chain = SyntheticChain::generate(12, 4, 2, SYNTHETIC_MIN_DIFFICULTY_BITS).unwrap();
+
+        assert_eq!(chain.headers.len(), 12);
+        assert_eq!(chain.blocks.len(), 4);
+        assert_eq!(chain.headers[0].prev_block_hash, GENESIS.to_vec());
+        for pair in chain.headers.windows(2) {
+            assert_eq!(&pair[1].prev_block_hash, pair[0].hash());
+            assert!(pair[1].timestamp > pair[0].timestamp);
+        }
+        for header in &chain.headers {
+            header.verify_proof_of_work().unwrap();
+        }
+
+        let first_block_index = chain.headers.len() - chain.blocks.len();
+        assert!(chain.headers[first_block_index - 1].timestamp <= START_DATE_IBD);
+        for (block, header) in chain.blocks.iter().zip(&chain.headers[first_block_index..]) {
+            assert!(header.timestamp > START_DATE_IBD);
+            assert_eq!(block.header.hash(), header.hash());
+            block.create_merkle_root().unwrap();
+            assert!(block.transactions[0].is_coinbase());
+
+            let size = block.serialize().len();
+            assert!((1536..=2560).contains(&size), "block of {size} bytes");
+            let parsed = Block::parse(block.serialize()).unwrap();
+            assert_eq!(parsed.header.hash(), header.hash());
+        }
+
+        // las pseudo-transacciones gastan los outputs del bloque anterior
+        let spent = &chain.blocks[1].transactions[1].inputs[0].previous_output;
+        assert_eq!(spent.hash, chain.blocks[0].transactions[1].hash());
+    }
+
+    #[test]
+    fn synthetic_chain_is_deterministic() {
+        let chain = SyntheticChain::generate(5, 2, 1, SYNTHETIC_MIN_DIFFICULTY_BITS).unwrap();
+        let other = SyntheticChain::generate(5, 2, 1, SYNTHETIC_MIN_DIFFICULTY_BITS).unwrap();
+
+        assert_eq!(
+            chain.headers.last().unwrap().hash(),
+            other.headers.last().unwrap().hash()
+        );
+        assert!(matches!(
+            SyntheticChain::generate(5, 5, 1, SYNTHETIC_MIN_DIFFICULTY_BITS),
+            Err(CustomError::InvalidBenchArguments)
+        ));
+    }
+}
@@ -1,12 +1,16 @@
 use std::{
-    fs::OpenOptions,
-    net::{SocketAddr, SocketAddrV6, TcpStream, ToSocketAddrs},
+    fs::{self, File, OpenOptions},
+    io::Write,
+    net::{IpAddr, SocketAddr, SocketAddrV6, TcpStream, ToSocketAddrs},
     time::{Duration, SystemTime},
     vec::IntoIter,
 };
 
 use crate::{error::CustomError, structs::block_header::BlockHeader};
 
+/// Cantidad maxima de intentos de una operacion de disco que falla con un error transitorio.
+const IO_RETRY_ATTEMPTS: usize = 3;
+
 /// get_addresses resuelve la direccion del seed y devuelve un iterador de direcciones.
 pub fn get_addresses(seed: String, port: u16) -> Result<IntoIter<SocketAddr>, CustomError> {
     (seed, port)
@@ -32,19 +36,71 @@ pub fn get_address_v6(address: SocketAddr) -> SocketAddrV6 {
     SocketAddrV6::new(ip_v6, address.port(), 0, 0)
 }
 
-/// open_new_file abre un archivo en la ubicacion recibida.
-/// Si el archivo no existe, lo crea.
-/// Si el archivo existe, lo abre.
-/// Si append es true, escribe al final del archivo.
-/// Si append es false, sobreescribe el archivo.
-pub fn open_new_file(path_to_file: String, append: bool) -> Result<std::fs::File, CustomError> {
-    let file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .append(append)
-        .open(path_to_file)?;
-    Ok(file)
+/// get_socket_address devuelve el address de una direccion ipv6.
+/// Si la direccion es un ipv4 mapeado a ipv6, devuelve el address ipv4 (la inversa de get_address_v6).
+pub fn get_socket_address(address: SocketAddrV6) -> SocketAddr {
+    match address.ip().to_ipv4_mapped() {
+        Some(ip_v4) => SocketAddr::new(IpAddr::V4(ip_v4), address.port()),
+        None => SocketAddr::V6(address),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// OpenMode indica para que se abre un archivo con open_file:
+/// - Read: Leerlo. Falla si no existe.
+/// - ReadCreate: Leerlo, creandolo vacio si no existe (los estados que se restauran al iniciar el nodo).
+/// - Append: Agregar registros al final, creandolo si no existe (headers, journal de las UTXO, resumenes de bloques).
+/// - Truncate: Reemplazar su contenido, creandolo si no existe. Para que un corte no deje el archivo a medias usar write_file_atomic.
+/// - CreateNew: Escribir un archivo que no debe existir (bloques). Falla si ya existe, para detectar que se guarda dos veces.
+pub enum OpenMode {
+    Read,
+    ReadCreate,
+    Append,
+    Truncate,
+    CreateNew,
+}
+
+/// open_file abre un archivo en la ubicacion recibida segun el OpenMode.
+/// Si la apertura se interrumpe, se reintenta (ver retry_io).
+pub fn open_file(path_to_file: &str, mode: OpenMode) -> Result<File, CustomError> {
+    let mut options = OpenOptions::new();
+    match mode {
+        OpenMode::Read => options.read(true),
+        OpenMode::ReadCreate => options.read(true).write(true).create(true),
+        OpenMode::Append => options.read(true).append(true).create(true),
+        OpenMode::Truncate => options.write(true).truncate(true).create(true),
+        OpenMode::CreateNew => options.write(true).create_new(true),
+    };
+    retry_io(|| Ok(options.open(path_to_file)?))
+}
+
+/// write_file_atomic reemplaza el contenido de un archivo por buffer.
+/// Se escribe primero en un archivo temporal que luego se renombra sobre el anterior,
+/// para que un corte durante la escritura no deje el archivo a medias.
+pub fn write_file_atomic(path_to_file: &str, buffer: &[u8]) -> Result<(), CustomError> {
+    let tmp_path = format!("{path_to_file}.tmp");
+    retry_io(|| {
+        let mut file = open_file(&tmp_path, OpenMode::Truncate)?;
+        file.write_all(buffer)?;
+        Ok(file.sync_data()?)
+    })?;
+    fs::rename(tmp_path, path_to_file)?;
+    Ok(())
+}
+
+/// retry_io ejecuta la operacion recibida y la reintenta mientras falle con un error transitorio
+/// (ver CustomError::is_retryable), hasta IO_RETRY_ATTEMPTS veces.
+/// Si no se pudo completar devuelve el ultimo error.
+pub fn retry_io<T>(
+    mut operation: impl FnMut() -> Result<T, CustomError>,
+) -> Result<T, CustomError> {
+    let mut attempts = 1;
+    loop {
+        match operation() {
+            Err(error) if error.is_retryable() && attempts < IO_RETRY_ATTEMPTS => attempts += 1,
+            result => return result,
+        }
+    }
 }
 
 /// get_current_timestamp devuelve el timestamp actual.
@@ -78,9 +134,10 @@ pub fn calculate_index_from_timestamp(headers: &Vec<BlockHeader>, last_timestamp
 
 mod tests {
     use std::{
-        fs::{self, remove_file},
-        io::Write,
+        fs::remove_file,
+        io::{ErrorKind, Read},
         net::Ipv6Addr,
+        path::Path,
     };
 
     use super::*;
@@ -113,6 +170,15 @@ mod tests {
         assert_eq!(address_v6.port(), 8333);
     }
 
+    #[test]
+    fn get_socket_address_unmaps_ipv4_addresses() {
+        let address = SocketAddr::from(([127, 0, 0, 1], 8333));
+        assert_eq!(get_socket_address(get_address_v6(address)), address);
+
+        let address = SocketAddr::from(([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1], 8333));
+        assert_eq!(get_socket_address(get_address_v6(address)), address);
+    }
+
     #[test]
     fn get_address_v6_with_ipv6_address_returns_the_same_address() {
         let address = SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 8333));
@@ -131,21 +197,81 @@ mod tests {
     }
 
     #[test]
-    fn test_open_new_file_creates_new_if_doesnt_exist() {
-        let mut file = open_new_file("tests/does_not_exist.txt".to_string(), false).unwrap();
+    fn open_file_read_create_creates_new_if_doesnt_exist() {
+        let path = "tests/does_not_exist.txt";
+        let mut file = open_file(path, OpenMode::ReadCreate).unwrap();
 
         assert!(file.write_all(b"test").is_ok());
 
-        remove_file("tests/does_not_exist.txt").unwrap();
+        remove_file(path).unwrap();
     }
 
     #[test]
-    fn test_open_new_file_existing_file() {
-        fs::copy("tests/does_exist.txt", "tests/does_exist_copy.txt").unwrap();
-        let mut file = open_new_file("tests/does_exist_copy.txt".to_string(), true).unwrap();
+    fn open_file_read_does_not_create_missing_file() {
+        let path = "tests/open_file_read_missing.txt";
 
-        assert!(file.write_all(b"test").is_ok());
+        assert!(open_file(path, OpenMode::Read).is_err());
+        assert!(!Path::new(path).exists());
+    }
+
+    #[test]
+    fn open_file_append_writes_after_existing_content() {
+        let path = "tests/open_file_append.txt";
+        fs::copy("tests/does_exist.txt", path).unwrap();
+        let existing = fs::read(path).unwrap();
+
+        let mut file = open_file(path, OpenMode::Append).unwrap();
+        file.write_all(b"test").unwrap();
+        let mut buffer = vec![];
+        open_file(path, OpenMode::Read)
+            .unwrap()
+            .read_to_end(&mut buffer)
+            .unwrap();
+
+        assert_eq!(buffer, [existing, b"test".to_vec()].concat());
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn open_file_truncate_replaces_longer_content() {
+        let path = "tests/open_file_truncate.txt";
+        fs::write(path, b"a longer previous content").unwrap();
+
+        open_file(path, OpenMode::Truncate)
+            .unwrap()
+            .write_all(b"test")
+            .unwrap();
+
+        assert_eq!(fs::read(path).unwrap(), b"test");
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn open_file_create_new_fails_if_file_exists() {
+        let path = "tests/open_file_create_new.txt";
+        let _ = remove_file(path);
+
+        open_file(path, OpenMode::CreateNew)
+            .unwrap()
+            .write_all(b"test")
+            .unwrap();
+        let Err(CustomError::Io(source)) = open_file(path, OpenMode::CreateNew) else { panic!("the second CreateNew should fail") };
+
+        let io_error = source.downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(io_error.kind(), ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(path).unwrap(), b"test");
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn write_file_atomic_replaces_content_without_leaving_temp_file() {
+        let path = "tests/write_file_atomic.txt";
+        fs::write(path, b"a longer previous content").unwrap();
+
+        write_file_atomic(path, b"test").unwrap();
 
-        remove_file("tests/does_exist_copy.txt").unwrap();
+        assert_eq!(fs::read(path).unwrap(), b"test");
+        assert!(!Path::new("tests/write_file_atomic.txt.tmp").exists());
+        remove_file(path).unwrap();
     }
 }
@@ -0,0 +1,343 @@
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use bitcoin_hashes::{sha256, Hash};
+use gtk::glib::{self, Priority};
+
+use crate::{
+    config::Config,
+    error::CustomError,
+    logger::Log,
+    node_state::NodeState,
+    parser::BufferParser,
+    states::utxo_state::{parse_utxo_entry, serialize_utxo_entry, UTXOValue},
+    structs::{block_header::hash_as_string, outpoint::OutPoint},
+    utils::retry_io,
+};
+
+/// Flags con los que se exporta o importa un snapshot de las UTXO en lugar de correr el nodo.
+pub const EXPORT_UTXO_FLAG: &str = "--export-utxo";
+pub const IMPORT_UTXO_FLAG: &str = "--import-utxo";
+
+const UTXO_SNAPSHOT_USAGE: &str =
+    "usage: configpath --export-utxo <file> | configpath --import-utxo <file>";
+
+/// Los snapshots comienzan con UTXO_SNAPSHOT_MAGIC, el byte de version, el hash y el height del bloque,
+/// la cantidad de UTXO y el sha256 de las UTXO serializadas que siguen.
+const UTXO_SNAPSHOT_MAGIC: [u8; 4] = *b"UTXS";
+const UTXO_SNAPSHOT_V1: u8 = 1;
+
+/// Modelo de confianza de los snapshots, se muestra al exportarlos e importarlos.
+pub const UTXO_SNAPSHOT_TRUST_NOTICE: &str = "Trust model: a node that imports this snapshot does not verify the blocks \
+before it. It only checks that the snapshot's block is on its best header chain and that the file is intact, \
+so it trusts whoever created the snapshot to have built it from a fully verified chain.";
+
+#[derive(Debug, Clone, PartialEq)]
+/// UtxoSnapshot son las UTXO de un nodo sincronizado en un bloque, para que otro nodo arranque desde ese bloque
+/// sin descargar ni procesar los anteriores.
+/// Los elementos son:
+/// - block_hash: Hash del ultimo bloque incluido en las UTXO.
+/// - height: Height de ese bloque.
+/// - tx_set: Las UTXO.
+pub struct UtxoSnapshot {
+    pub block_hash: Vec<u8>,
+    pub height: usize,
+    pub tx_set: HashMap<OutPoint, UTXOValue>,
+}
+
+impl UtxoSnapshot {
+    /// Serializa el snapshot.
+    /// Las UTXO se ordenan por su serializacion, para que dos nodos con las mismas UTXO generen el mismo archivo
+    /// (el orden del HashMap cambia en cada ejecucion).
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut entries: Vec<Vec<u8>> = self
+            .tx_set
+            .iter()
+            .map(|(out_point, value)| {
+                let mut entry = vec![];
+                serialize_utxo_entry(&mut entry, out_point, value);
+                entry
+            })
+            .collect();
+        entries.sort_unstable();
+        let body = entries.concat();
+
+        let mut buffer = vec![];
+        buffer.extend(UTXO_SNAPSHOT_MAGIC);
+        buffer.push(UTXO_SNAPSHOT_V1);
+        buffer.extend(&self.block_hash);
+        buffer.extend((self.height as u32).to_le_bytes());
+        buffer.extend((self.tx_set.len() as u64).to_le_bytes());
+        buffer.extend(sha256::Hash::hash(&body).as_byte_array());
+        buffer.extend(body);
+        buffer
+    }
+
+    /// Deserializa un snapshot.
+    /// Devuelve CustomError::InvalidUtxoSnapshot si no es un snapshot, si el sha256 de las UTXO no coincide
+    /// o si no tiene la cantidad de UTXO indicada.
+    pub fn parse(buffer: Vec<u8>) -> Result<Self, CustomError> {
+        let mut parser = BufferParser::new(buffer);
+        let is_snapshot = parser
+            .extract_buffer(UTXO_SNAPSHOT_MAGIC.len())
+            .map_or(false, |magic| magic == UTXO_SNAPSHOT_MAGIC)
+            && parser.extract_u8().ok() == Some(UTXO_SNAPSHOT_V1);
+        if !is_snapshot {
+            return Err(CustomError::InvalidUtxoSnapshot);
+        }
+
+        let block_hash = parser.extract_buffer(32)?.to_vec();
+        let height = parser.extract_u32()? as usize;
+        let tx_set_len = parser.extract_u64()? as usize;
+        let body_hash = parser.extract_buffer(32)?.to_vec();
+        let body_len = parser.len();
+        let body = parser.extract_buffer(body_len)?.to_vec();
+        if sha256::Hash::hash(&body).as_byte_array().as_slice() != body_hash {
+            return Err(CustomError::InvalidUtxoSnapshot);
+        }
+
+        let mut parser = BufferParser::new(body);
+        let mut tx_set = HashMap::new();
+        while !parser.is_empty() {
+            let (out_point, value) =
+                parse_utxo_entry(&mut parser).map_err(|_| CustomError::InvalidUtxoSnapshot)?;
+            tx_set.insert(out_point, value);
+        }
+        if tx_set.len() != tx_set_len {
+            return Err(CustomError::InvalidUtxoSnapshot);
+        }
+
+        Ok(Self {
+            block_hash,
+            height,
+            tx_set,
+        })
+    }
+}
+
+/// Exporta o importa un snapshot de las UTXO del store del config segun los argumentos (ver UTXO_SNAPSHOT_USAGE)
+/// e imprime el resultado junto al modelo de confianza.
+/// Devuelve el codigo de salida del proceso: 1 si fallo.
+pub fn run_from_args(config: &Config, args: &[String]) -> i32 {
+    let result = match (
+        flag_value(args, EXPORT_UTXO_FLAG),
+        flag_value(args, IMPORT_UTXO_FLAG),
+    ) {
+        (Some(path), None) => export_to_file(&config.store_path, path),
+        (None, Some(path)) => import_from_file(&config.store_path, path),
+        _ => Err(CustomError::Validation(UTXO_SNAPSHOT_USAGE.to_string())),
+    };
+
+    match result {
+        Ok(summary) => {
+            println!("{summary}\n{UTXO_SNAPSHOT_TRUST_NOTICE}");
+            0
+        }
+        Err(error) => {
+            match error {
+                CustomError::Validation(message) => println!("ERROR: {message}"),
+                error => println!("ERROR: {}", error.with_sources()),
+            }
+            1
+        }
+    }
+}
+
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a String> {
+    let position = args.iter().position(|arg| arg == flag)?;
+    args.get(position + 1)
+}
+
+/// Exporta las UTXO guardadas en el store al archivo path.
+fn export_to_file(store_path: &str, path: &str) -> Result<String, CustomError> {
+    let snapshot = with_node_state(store_path, |node_state| node_state.export_utxo_snapshot())?;
+    let buffer = snapshot.serialize();
+    retry_io(|| Ok(fs::write(path, &buffer)?))?;
+
+    Ok(format!(
+        "Exported {} UTXOs at block {} (height {}) to {}",
+        snapshot.tx_set.len(),
+        hash_as_string(snapshot.block_hash),
+        snapshot.height,
+        path
+    ))
+}
+
+/// Importa en el store el snapshot del archivo path.
+fn import_from_file(store_path: &str, path: &str) -> Result<String, CustomError> {
+    let snapshot = UtxoSnapshot::parse(retry_io(|| Ok(fs::read(path)?))?)?;
+    let summary = format!(
+        "Imported {} UTXOs at block {} (height {}), only the blocks after it will be downloaded",
+        snapshot.tx_set.len(),
+        hash_as_string(snapshot.block_hash.clone()),
+        snapshot.height
+    );
+    with_node_state(store_path, |node_state| {
+        node_state.import_utxo_snapshot(snapshot)
+    })?;
+    Ok(summary)
+}
+
+/// Corre action sobre el NodeState del store, sin interfaz grafica ni peers. Los logs se descartan.
+fn with_node_state<T>(
+    store_path: &str,
+    action: impl FnOnce(&mut NodeState) -> Result<T, CustomError>,
+) -> Result<T, CustomError> {
+    let (logger_sender, logger_receiver) = mpsc::channel::<Log>();
+    thread::spawn(move || for _ in logger_receiver {});
+    let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+    let node_state_ref: Arc<Mutex<NodeState>> =
+        NodeState::new(logger_sender, gui_sender, &store_path.to_string())?;
+
+    let mut node_state = node_state_ref.lock()?;
+    action(&mut node_state)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        messages::headers::Headers,
+        structs::tx_output::TransactionOutput,
+        synthetic_chain::{SyntheticChain, SYNTHETIC_MIN_DIFFICULTY_BITS},
+    };
+
+    use super::*;
+
+    fn node_state(
+        store_path: &str,
+    ) -> (
+        Arc<Mutex<NodeState>>,
+        glib::Receiver<crate::gui::init::GUIEvents>,
+    ) {
+        let _ = fs::remove_dir_all(store_path);
+        let (logger_sender, logger_receiver) = mpsc::channel::<Log>();
+        thread::spawn(move || for _ in logger_receiver {});
+        let (gui_sender, gui_receiver) = glib::MainContext::channel(Priority::default());
+        let node_state_ref =
+            NodeState::new(logger_sender, gui_sender, &store_path.to_string()).unwrap();
+        // la cadena sintetica tiene la dificultad de regtest
+        node_state_ref.lock().unwrap().set_regtest(true);
+        (node_state_ref, gui_receiver)
+    }
+
+    fn append_blocks(
+        node_state: &mut NodeState,
+        chain: &SyntheticChain,
+        blocks: std::ops::Range<usize>,
+    ) {
+        for block in &chain.blocks[blocks] {
+            node_state
+                .append_block(block.header.hash().clone(), block)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn imported_snapshot_continues_like_a_full_sync() {
+        let chain = SyntheticChain::generate(12, 6, 1, SYNTHETIC_MIN_DIFFICULTY_BITS).unwrap();
+
+        // A se sincroniza hasta el height 9 (los primeros 3 bloques) y exporta sus UTXO
+        let (exporter_ref, _exporter_gui) = node_state("tests/store_utxo_snapshot_export");
+        let mut exporter = exporter_ref.lock().unwrap();
+        exporter
+            .append_headers(&Headers {
+                headers: chain.headers[..9].to_vec(),
+            })
+            .unwrap();
+        append_blocks(&mut exporter, &chain, 0..3);
+        assert!(exporter.is_synced());
+        let snapshot = exporter.export_utxo_snapshot().unwrap();
+        assert_eq!(snapshot.height, 9);
+        assert_eq!(&snapshot.block_hash, chain.headers[8].hash());
+
+        let buffer = snapshot.serialize();
+        let mut tampered = buffer.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(matches!(
+            UtxoSnapshot::parse(tampered),
+            Err(CustomError::InvalidUtxoSnapshot)
+        ));
+        let snapshot = UtxoSnapshot::parse(buffer).unwrap();
+
+        // B tiene todos los headers, importa el snapshot y solo descarga los bloques posteriores
+        let (importer_ref, _importer_gui) = node_state("tests/store_utxo_snapshot_import");
+        let mut importer = importer_ref.lock().unwrap();
+        importer
+            .append_headers(&Headers {
+                headers: chain.headers.clone(),
+            })
+            .unwrap();
+        let foreign = UtxoSnapshot {
+            block_hash: vec![1; 32],
+            ..snapshot.clone()
+        };
+        assert!(matches!(
+            importer.import_utxo_snapshot(foreign),
+            Err(CustomError::UtxoSnapshotNotOnChain)
+        ));
+        importer.import_utxo_snapshot(snapshot).unwrap();
+        assert!(!importer.is_synced());
+        append_blocks(&mut importer, &chain, 3..6);
+        assert!(importer.is_synced());
+
+        // C se sincroniza desde cero con toda la cadena
+        let (full_ref, _full_gui) = node_state("tests/store_utxo_snapshot_full");
+        let mut full = full_ref.lock().unwrap();
+        full.append_headers(&Headers {
+            headers: chain.headers.clone(),
+        })
+        .unwrap();
+        append_blocks(&mut full, &chain, 0..6);
+        assert!(full.is_synced());
+
+        assert_eq!(
+            importer.export_utxo_snapshot().unwrap(),
+            full.export_utxo_snapshot().unwrap()
+        );
+
+        drop((exporter, importer, full));
+        for store_path in [
+            "tests/store_utxo_snapshot_export",
+            "tests/store_utxo_snapshot_import",
+            "tests/store_utxo_snapshot_full",
+        ] {
+            fs::remove_dir_all(store_path).unwrap();
+        }
+    }
+
+    #[test]
+    fn serialize_does_not_depend_on_the_utxo_order() {
+        let entries: Vec<(OutPoint, UTXOValue)> = (0..64_u8)
+            .map(|index| {
+                let out_point = OutPoint {
+                    hash: vec![index; 32],
+                    index: index as u32,
+                };
+                let value = UTXOValue {
+                    tx_out: TransactionOutput {
+                        value: index as u64 * 1_000,
+                        script_pubkey: vec![index; 25],
+                    },
+                    block_hash: vec![1; 32],
+                    block_timestamp: 0,
+                    is_coinbase: false,
+                };
+                (out_point, value)
+            })
+            .collect();
+        let snapshot = |entries: Vec<(OutPoint, UTXOValue)>| UtxoSnapshot {
+            block_hash: vec![1; 32],
+            height: 1,
+            tx_set: entries.into_iter().collect(),
+        };
+
+        let forward = snapshot(entries.clone());
+        let backward = snapshot(entries.into_iter().rev().collect());
+        assert_eq!(forward.serialize(), backward.serialize());
+    }
+}
@@ -1,19 +1,39 @@
+use bitcoin_hashes::{hash160, sha256d, Hash};
+use secp256k1::Secp256k1;
+use std::collections::HashSet;
+
 use crate::{
-    error::CustomError, parser::BufferParser, states::utxo_state::UTXO, structs::movement::Movement,
+    bech32::{encode_segwit_address, is_segwit_address, p2wpkh_program, TESTNET_HRP},
+    bip32::XpubAccount,
+    error::CustomError,
+    parser::BufferParser,
+    states::utxo_state::UTXO,
+    structs::movement::Movement,
 };
 
+/// Byte de version de las direcciones P2PKH de testnet.
+pub const TESTNET_P2PKH_VERSION: u8 = 0x6f;
+
+/// Largo en bytes de una direccion decodificada (version, hash de la public key y checksum).
+const DECODED_ADDRESS_LEN: usize = 25;
+
 #[derive(Clone, Debug)]
 /// Wallet es una estructura que contiene los elementos necesarios para manejar las wallets.
 /// Los elementos son:
 /// - name: Nombre de la wallet.
 /// - pubkey: Public key de la wallet.
-/// - privkey: Private key de la wallet.
+/// - privkey: Private key de la wallet (vacia si la wallet es watch-only).
 /// - history: Historial de Movements de la wallet.
+/// - account: Cuenta xpub de la que se derivan las direcciones que sigue la wallet, None si sigue solo a pubkey.
+/// - compressed_pubkey: Indica si pubkey es la direccion de la public key comprimida de privkey, con la que se firma
+///   (ver pubkey_is_compressed). Es false si es la de la public key sin comprimir.
 pub struct Wallet {
     pub name: String,
     pub pubkey: String,
     pub privkey: String,
     pub history: Vec<Movement>,
+    pub account: Option<XpubAccount>,
+    pub compressed_pubkey: bool,
 }
 
 impl Wallet {
@@ -30,9 +50,11 @@ impl Wallet {
                 "Name, public key and private key must not be empty".to_string(),
             ));
         }
-        if pubkey.len() != 34 {
+        if is_segwit_address(&pubkey) {
+            p2wpkh_program(&pubkey)?;
+        } else if pubkey.len() != 34 {
             return Err(CustomError::Validation(
-                "Public key must be 34 characters long".to_string(),
+                "Public key must be 34 characters long or a bech32 (P2WPKH) address".to_string(),
             ));
         }
         let mut wallet = Self {
@@ -40,6 +62,8 @@ impl Wallet {
             pubkey,
             privkey,
             history: vec![],
+            account: None,
+            compressed_pubkey: true,
         };
         for (outpoint, value) in &utxo_set.tx_set {
             if value.tx_out.is_sent_to_key(&wallet.get_pubkey_hash()?)? {
@@ -47,12 +71,58 @@ impl Wallet {
                     tx_hash: outpoint.hash.clone(),
                     value: value.tx_out.value as i64,
                     block_hash: Some(value.block_hash.clone()),
+                    provisional: false,
                 });
             }
         }
         Ok(wallet)
     }
 
+    /// Inicializa una wallet watch-only, que solo sigue los movimientos de una direccion y no puede firmar transacciones.
+    /// La direccion debe ser una direccion P2PKH o P2WPKH de testnet valida (ver validate_address).
+    /// El historial arranca vacio, se completa con el rescan de WalletsState::import_watch_only.
+    pub fn watch_only(name: String, pubkey: String) -> Result<Self, CustomError> {
+        if name.is_empty() {
+            return Err(CustomError::Validation(
+                "Name must not be empty".to_string(),
+            ));
+        }
+        validate_address(&pubkey)?;
+        Ok(Self {
+            name,
+            pubkey,
+            privkey: String::new(),
+            history: vec![],
+            account: None,
+            compressed_pubkey: true,
+        })
+    }
+
+    /// Inicializa una wallet watch-only que sigue las direcciones derivadas de una extended public key (xpub o tpub),
+    /// las de recepcion (m/0/i) y las de cambio (m/1/i), con gap_limit direcciones sin usar en cada cadena.
+    /// Su public key es la primera direccion de recepcion. Como en watch_only, el historial arranca vacio.
+    pub fn from_xpub(name: String, xpub: &str, gap_limit: u32) -> Result<Self, CustomError> {
+        if name.is_empty() {
+            return Err(CustomError::Validation(
+                "Name must not be empty".to_string(),
+            ));
+        }
+        let account = XpubAccount::new(xpub, gap_limit)?;
+        Ok(Self {
+            name,
+            pubkey: account.first_address(),
+            privkey: String::new(),
+            history: vec![],
+            account: Some(account),
+            compressed_pubkey: true,
+        })
+    }
+
+    /// Devuelve true si la wallet no tiene private key.
+    pub fn is_watch_only(&self) -> bool {
+        self.privkey.is_empty()
+    }
+
     /// Serializa la wallet.
     pub fn serialize(&self) -> Vec<u8> {
         let mut buffer = Vec::new();
@@ -71,6 +141,12 @@ impl Wallet {
 
     /// Deserializa la wallet.
     pub fn parse(parser: &mut BufferParser) -> Result<Self, CustomError> {
+        Ok(Self::parse_legacy(parser)?.0)
+    }
+
+    /// Como parse, pero devuelve tambien los tx_hash de los movimientos que se guardaron como abandonados
+    /// (ver Movement::parse_legacy), para migrarlos a WalletMetaState.
+    pub fn parse_legacy(parser: &mut BufferParser) -> Result<(Self, Vec<Vec<u8>>), CustomError> {
         let name_len = parser.extract_u8()? as usize;
         let name = parser.extract_string(name_len)?;
 
@@ -82,16 +158,24 @@ impl Wallet {
 
         let history_len = parser.extract_u32()? as usize;
         let mut history = Vec::new();
+        let mut abandoned = Vec::new();
         for _ in 0..history_len {
-            history.push(Movement::parse(parser)?);
+            let (movement, is_abandoned) = Movement::parse_legacy(parser)?;
+            if is_abandoned {
+                abandoned.push(movement.tx_hash.clone());
+            }
+            history.push(movement);
         }
 
-        Ok(Self {
+        let wallet = Self {
             name,
             pubkey,
             privkey,
             history,
-        })
+            account: None,
+            compressed_pubkey: true,
+        };
+        Ok((wallet, abandoned))
     }
 
     /// Devuelve el hash de la public key de la wallet.
@@ -99,6 +183,20 @@ impl Wallet {
         get_pubkey_hash(self.pubkey.clone())
     }
 
+    /// Devuelve los hashes de las public keys de todas las direcciones que sigue la wallet: las derivadas de su
+    /// cuenta xpub o solo la de su public key.
+    pub fn get_pubkey_hashes(&self) -> Result<Vec<Vec<u8>>, CustomError> {
+        match &self.account {
+            Some(account) => Ok(account.pubkey_hashes()),
+            None => Ok(vec![self.get_pubkey_hash()?]),
+        }
+    }
+
+    /// Devuelve los mismos hashes que get_pubkey_hashes como conjunto, para buscar en el los hashes de los outputs.
+    pub fn get_pubkey_hash_set(&self) -> Result<HashSet<Vec<u8>>, CustomError> {
+        Ok(self.get_pubkey_hashes()?.into_iter().collect())
+    }
+
     /// Devuelve el hash de la private key de la wallet.
     pub fn get_privkey_hash(&self) -> Result<Vec<u8>, CustomError> {
         get_privkey_hash(self.privkey.clone())
@@ -110,8 +208,54 @@ impl Wallet {
     }
 
     /// Actualiza el historial de la wallet.
+    /// Si ya hay un movimiento sin bloque (abandonado) o provisional de la misma transaccion (por ejemplo, porque
+    /// se confirmo luego de abandonarla), lo reemplaza en lugar de duplicarlo.
     pub fn update_history(&mut self, movement: Movement) {
+        let replaced = self.history.iter_mut().find(|existing| {
+            (existing.block_hash.is_none() || existing.provisional)
+                && existing.tx_hash == movement.tx_hash
+        });
+
+        match replaced {
+            Some(existing) => *existing = movement,
+            None => self.history.push(movement),
+        }
+    }
+
+    /// Agrega al historial un movimiento provisional, salvo que ya haya un movimiento de la misma transaccion.
+    /// Devuelve true si se agrego.
+    pub fn add_provisional_movement(&mut self, mut movement: Movement) -> bool {
+        if self
+            .history
+            .iter()
+            .any(|existing| existing.tx_hash == movement.tx_hash)
+        {
+            return false;
+        }
+        movement.provisional = true;
         self.history.push(movement);
+        true
+    }
+
+    /// Elimina del historial los movimientos provisionales del bloque, que se reemplazan por los del bloque completo.
+    /// Devuelve true si se elimino alguno.
+    pub fn remove_provisional_movements(&mut self, block_hash: &Vec<u8>) -> bool {
+        let history_len = self.history.len();
+        self.history.retain(|movement| {
+            !movement.provisional || movement.block_hash.as_ref() != Some(block_hash)
+        });
+        history_len != self.history.len()
+    }
+
+    /// Elimina del historial los movimientos confirmados en alguno de los bloques recibidos.
+    /// Devuelve true si se elimino alguno.
+    pub fn remove_block_movements(&mut self, block_hashes: &[Vec<u8>]) -> bool {
+        let history_len = self.history.len();
+        self.history.retain(|movement| match &movement.block_hash {
+            Some(block_hash) => !block_hashes.contains(block_hash),
+            None => true,
+        });
+        history_len != self.history.len()
     }
 
     /// Devuelve el historial de la wallet.
@@ -121,7 +265,11 @@ impl Wallet {
 }
 
 /// Devuelve el hash de una public key.
+/// Si es una direccion bech32 devuelve su witness program P2WPKH, que tambien es el hash160 de la public key.
 pub fn get_pubkey_hash(pubkey: String) -> Result<Vec<u8>, CustomError> {
+    if is_segwit_address(&pubkey) {
+        return p2wpkh_program(&pubkey);
+    }
     let decoded_pubkey = bs58::decode(pubkey)
         .into_vec()
         .map_err(|_| CustomError::Validation(String::from("User PubKey incorrectly formatted")))?;
@@ -134,6 +282,89 @@ pub fn get_pubkey_hash(pubkey: String) -> Result<Vec<u8>, CustomError> {
     }
 }
 
+/// Valida una direccion P2PKH de testnet (codificacion base58, largo, checksum y byte de version)
+/// o una direccion P2WPKH de testnet (bech32, ver p2wpkh_program).
+/// Devuelve el hash de la public key de la direccion.
+pub fn validate_address(address: &str) -> Result<Vec<u8>, CustomError> {
+    if is_segwit_address(address) {
+        return p2wpkh_program(address);
+    }
+    let decoded = bs58::decode(address)
+        .into_vec()
+        .map_err(|_| CustomError::Validation(String::from("Address is not valid base58")))?;
+    if decoded.len() != DECODED_ADDRESS_LEN {
+        return Err(CustomError::Validation(String::from(
+            "Address has an invalid length",
+        )));
+    }
+
+    let (payload, checksum) = decoded.split_at(DECODED_ADDRESS_LEN - 4);
+    if sha256d::Hash::hash(payload)[..4] != *checksum {
+        return Err(CustomError::Validation(String::from(
+            "Address checksum does not match",
+        )));
+    }
+    if payload[0] != TESTNET_P2PKH_VERSION {
+        return Err(CustomError::Validation(String::from(
+            "Address is not a testnet P2PKH address",
+        )));
+    }
+
+    Ok(payload[1..].to_vec())
+}
+
+/// Devuelve la direccion P2PKH de testnet (codificacion base58 con checksum) del hash de una public key.
+/// Es la inversa de validate_address.
+pub fn address_from_pubkey_hash(pubkey_hash: &[u8]) -> String {
+    let mut payload = vec![TESTNET_P2PKH_VERSION];
+    payload.extend(pubkey_hash);
+    let checksum = sha256d::Hash::hash(&payload)[..4].to_vec();
+    payload.extend(checksum);
+    bs58::encode(payload).into_string()
+}
+
+/// Devuelve las direcciones P2PKH de testnet de una private key (WIF): la de su public key comprimida y la de su
+/// public key sin comprimir. Son distintas aunque correspondan a la misma key, y los outputs le pueden pagar a cualquiera.
+pub fn addresses_from_privkey(privkey: &str) -> Result<(String, String), CustomError> {
+    let secret_key = secp256k1::SecretKey::from_slice(&get_privkey_hash(privkey.to_string())?)
+        .map_err(|_| CustomError::Validation(String::from("User PrivKey incorrectly formatted")))?;
+    let public_key = secp256k1::PublicKey::from_secret_key(&Secp256k1::new(), &secret_key);
+
+    let compressed = hash160::Hash::hash(&public_key.serialize());
+    let uncompressed = hash160::Hash::hash(&public_key.serialize_uncompressed());
+    Ok((
+        address_from_pubkey_hash(compressed.as_byte_array()),
+        address_from_pubkey_hash(uncompressed.as_byte_array()),
+    ))
+}
+
+/// Devuelve true si address es la direccion de la public key comprimida de privkey, o false si es la de la
+/// public key sin comprimir, que es la forma en la que la wallet tiene que serializar su public key al firmar.
+/// Si address es una direccion P2WPKH tiene que ser la de la public key comprimida, la unica que admite segwit.
+/// Si no es ninguna devuelve CustomError::Validation con las direcciones de la key, para que el usuario
+/// pueda identificar la que recibio sus fondos.
+pub fn pubkey_is_compressed(address: &str, privkey: &str) -> Result<bool, CustomError> {
+    let (compressed, uncompressed) = addresses_from_privkey(privkey)?;
+    if address == compressed {
+        return Ok(true);
+    }
+    if address == uncompressed {
+        return Ok(false);
+    }
+    if is_segwit_address(address) {
+        let segwit = encode_segwit_address(TESTNET_HRP, 0, &validate_address(&compressed)?);
+        if address.to_lowercase() == segwit {
+            return Ok(true);
+        }
+        return Err(CustomError::Validation(format!(
+            "Public key does not match the private key, its P2WPKH address is {segwit}"
+        )));
+    }
+    Err(CustomError::Validation(format!(
+        "Public key does not match the private key, its addresses are {compressed} (compressed) and {uncompressed} (uncompressed)"
+    )))
+}
+
 /// Devuelve el hash de una private key.
 pub fn get_privkey_hash(privkey: String) -> Result<Vec<u8>, CustomError> {
     let decoded_privkey = bs58::decode(privkey)
@@ -148,8 +379,14 @@ pub fn get_privkey_hash(privkey: String) -> Result<Vec<u8>, CustomError> {
     }
 }
 
-/// Devuelve el script pubkey de una public key.
+/// Devuelve el script pubkey de una public key: P2PKH (OP_DUP OP_HASH160 <hash> OP_EQUALVERIFY OP_CHECKSIG),
+/// o P2WPKH (OP_0 <hash>) si es una direccion bech32.
 pub fn get_script_pubkey(pubkey: String) -> Result<Vec<u8>, CustomError> {
+    if is_segwit_address(&pubkey) {
+        let mut script_pubkey = vec![0x00, 0x14];
+        script_pubkey.extend(p2wpkh_program(&pubkey)?);
+        return Ok(script_pubkey);
+    }
     let mut script_pubkey = Vec::new();
     script_pubkey.push(0x76);
     script_pubkey.push(0xa9);
@@ -241,6 +478,8 @@ mod tests {
             pubkey: String::from("pubkey"),
             privkey: String::from("privkey"),
             history: vec![],
+            account: None,
+            compressed_pubkey: true,
         };
         let serialized_wallet = wallet.serialize();
         let mut parser = BufferParser::new(serialized_wallet);
@@ -257,6 +496,8 @@ mod tests {
             pubkey: String::from("pubkey"),
             privkey: String::from("privkey"),
             history: vec![],
+            account: None,
+            compressed_pubkey: true,
         };
         wallet.update_history(Movement {
             tx_hash: vec![
@@ -268,6 +509,7 @@ mod tests {
                 167, 131, 118, 190, 70, 199, 31, 2, 255, 135, 123, 36, 232, 182, 60, 178, 98, 181,
                 242, 112, 111, 183, 22, 128, 11, 0, 0, 0, 0, 0, 0, 0,
             ]),
+            provisional: false,
         });
         let serialized_wallet = wallet.serialize();
         let mut parser = BufferParser::new(serialized_wallet);
@@ -288,6 +530,8 @@ mod tests {
             pubkey: String::from("mscatccDgq7azndWHFTzvEuZuywCsUvTRu"),
             privkey: String::from("privkey"),
             history: vec![],
+            account: None,
+            compressed_pubkey: true,
         };
         let pubkey_hash = wallet.get_pubkey_hash().unwrap();
         assert_eq!(
@@ -306,6 +550,8 @@ mod tests {
             pubkey: String::from("mscatccDgq7azndWHFTzvEuZuywCsUvTRu"),
             privkey: String::from("privkey"),
             history: vec![],
+            account: None,
+            compressed_pubkey: true,
         };
         let script_pubkey = wallet.get_script_pubkey().unwrap();
         assert_eq!(
@@ -324,6 +570,8 @@ mod tests {
             pubkey: String::from("pubkey"),
             privkey: String::from("cNpwEsaVLhju18SJowLtdCNaJtvMvqL4jtFLm2FXw7vZjg4sRWvH"),
             history: vec![],
+            account: None,
+            compressed_pubkey: true,
         };
         let privkey_hash = wallet.get_privkey_hash().unwrap();
         assert_eq!(
@@ -342,8 +590,195 @@ mod tests {
             pubkey: String::from("pubkey"),
             privkey: String::from("test"),
             history: vec![],
+            account: None,
+            compressed_pubkey: true,
         };
         let privkey_hash = wallet.get_privkey_hash();
         assert!(privkey_hash.is_err());
     }
+
+    #[test]
+    fn watch_only_wallet_creation() {
+        let wallet = Wallet::watch_only(
+            String::from("deposits"),
+            String::from("mscatccDgq7azndWHFTzvEuZuywCsUvTRu"),
+        )
+        .unwrap();
+        assert!(wallet.is_watch_only());
+        assert_eq!(wallet.history.len(), 0);
+
+        let mut parser = BufferParser::new(wallet.serialize());
+        let parsed_wallet = Wallet::parse(&mut parser).unwrap();
+        assert!(parsed_wallet.is_watch_only());
+        assert_eq!(parsed_wallet.name, String::from("deposits"));
+    }
+
+    #[test]
+    fn xpub_wallet_follows_derived_addresses() {
+        let wallet = Wallet::from_xpub(
+            String::from("account"),
+            "tpubD8eQVK4Kdxg3gHrF62jGP7dKVCoYiEB8dFSpuTawkL5YxTus5j5pf83vaKnii4bc6v2NVEy81P2gYrJczYne3QNNwMTS53p5uzDyHvnw2jm",
+            2,
+        )
+        .unwrap();
+        assert!(wallet.is_watch_only());
+        assert_eq!(wallet.pubkey, "mrSeAixYDXL17YZm6VEcxn9REZ6jK8DDgL");
+        let pubkey_hashes = wallet.get_pubkey_hashes().unwrap();
+        assert_eq!(pubkey_hashes.len(), 4);
+        assert_eq!(pubkey_hashes[0], wallet.get_pubkey_hash().unwrap());
+
+        assert!(Wallet::from_xpub(
+            String::from("account"),
+            "mrSeAixYDXL17YZm6VEcxn9REZ6jK8DDgL",
+            2
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn validate_addresses() {
+        assert_eq!(
+            validate_address("mscatccDgq7azndWHFTzvEuZuywCsUvTRu").unwrap(),
+            vec![
+                132, 178, 35, 78, 47, 170, 110, 26, 117, 29, 126, 82, 132, 235, 16, 204, 230, 247,
+                81, 246
+            ]
+        );
+        // checksum alterado
+        assert!(validate_address("mscatccDgq7azndWHFTzvEuZuywCsUvTRv").is_err());
+        // direccion de mainnet
+        assert!(validate_address("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa").is_err());
+        assert!(validate_address("invalid_address").is_err());
+        assert!(validate_address("mscatccDgq7azndWHFTzvEuZuyw").is_err());
+
+        assert_eq!(
+            validate_address("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx").unwrap(),
+            validate_address("TB1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KXPJZSX").unwrap()
+        );
+        // P2WSH
+        assert!(
+            validate_address("tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn script_pubkey_of_p2pkh_and_p2wpkh_addresses() {
+        let p2pkh = get_script_pubkey(String::from("mscatccDgq7azndWHFTzvEuZuywCsUvTRu")).unwrap();
+        assert_eq!(p2pkh[..3], [0x76, 0xa9, 0x14]);
+        assert_eq!(p2pkh[23..], [0x88, 0xac]);
+
+        let p2wpkh =
+            get_script_pubkey(String::from("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx")).unwrap();
+        assert_eq!(
+            p2wpkh,
+            vec![
+                0x00, 0x14, 0x75, 0x1e, 0x76, 0xe8, 0x19, 0x91, 0x96, 0xd4, 0x54, 0x94, 0x1c, 0x45,
+                0xd1, 0xb3, 0xa3, 0x23, 0xf1, 0x43, 0x3b, 0xd6
+            ]
+        );
+        assert!(
+            get_script_pubkey(String::from("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsy")).is_err()
+        );
+    }
+
+    #[test]
+    fn wallet_creation_with_bech32_pubkey() {
+        let utxo_set = UTXO::new(String::from("tests"), String::from("test_utxo.bin")).unwrap();
+        let wallet = Wallet::new(
+            String::from("segwit"),
+            String::from("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"),
+            String::from("privkey"),
+            &utxo_set,
+        )
+        .unwrap();
+        assert_eq!(
+            wallet.get_pubkey_hash().unwrap(),
+            validate_address("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx").unwrap()
+        );
+
+        for pubkey in [
+            "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsy",
+            "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+        ] {
+            let wallet = Wallet::new(
+                String::from("segwit"),
+                String::from(pubkey),
+                String::from("privkey"),
+                &utxo_set,
+            );
+            assert!(matches!(wallet, Err(CustomError::Validation(_))));
+        }
+    }
+
+    #[test]
+    fn address_from_pubkey_hash_round_trip() {
+        let pubkey_hash = validate_address("mscatccDgq7azndWHFTzvEuZuywCsUvTRu").unwrap();
+        assert_eq!(
+            address_from_pubkey_hash(&pubkey_hash),
+            "mscatccDgq7azndWHFTzvEuZuywCsUvTRu"
+        );
+    }
+
+    #[test]
+    fn privkey_addresses_in_both_pubkey_forms() {
+        let compressed_wif = "cNpwEsaVLhju18SJowLtdCNaJtvMvqL4jtFLm2FXw7vZjg4sRWvH";
+        let uncompressed_wif = "91sHNGY9goqgsnBieNNj4DYd6QzrbrTyajRYfXBzkyrHw69CkSf";
+        let addresses = (
+            String::from("mscatccDgq7azndWHFTzvEuZuywCsUvTRu"),
+            String::from("mhh2JbY3QmNaX9a1faSdk5xBpbLTLd12jK"),
+        );
+        // el WIF de la key sin comprimir deriva las mismas direcciones
+        assert_eq!(addresses_from_privkey(compressed_wif).unwrap(), addresses);
+        assert_eq!(addresses_from_privkey(uncompressed_wif).unwrap(), addresses);
+
+        for wif in [compressed_wif, uncompressed_wif] {
+            assert!(pubkey_is_compressed(&addresses.0, wif).unwrap());
+            assert!(!pubkey_is_compressed(&addresses.1, wif).unwrap());
+        }
+
+        // segwit solo admite la public key comprimida
+        let segwit = "tb1qsjezxn304fhp5aga0efgf6csenn0w50khsvwxp";
+        assert!(pubkey_is_compressed(segwit, compressed_wif).unwrap());
+        let result =
+            pubkey_is_compressed("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx", compressed_wif);
+        assert!(matches!(
+            result,
+            Err(CustomError::Validation(message)) if message.contains(segwit)
+        ));
+
+        let result = pubkey_is_compressed("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm", compressed_wif);
+        assert!(matches!(
+            result,
+            Err(CustomError::Validation(message))
+                if message.contains(&addresses.0) && message.contains(&addresses.1)
+        ));
+    }
+
+    #[test]
+    fn wallet_remove_block_movements() {
+        let mut wallet = Wallet {
+            name: String::from("test"),
+            pubkey: String::from("pubkey"),
+            privkey: String::from("privkey"),
+            history: vec![],
+            account: None,
+            compressed_pubkey: true,
+        };
+        for (tx_byte, block_hash) in [(1, Some(vec![1; 32])), (2, Some(vec![2; 32])), (3, None)] {
+            wallet.update_history(Movement {
+                tx_hash: vec![tx_byte; 32],
+                value: 500,
+                block_hash,
+                provisional: false,
+            });
+        }
+
+        assert!(wallet.remove_block_movements(&[vec![2; 32]]));
+        assert_eq!(wallet.history.len(), 2);
+        assert_eq!(wallet.history[0].tx_hash, vec![1; 32]);
+        assert_eq!(wallet.history[1].block_hash, None);
+
+        assert!(!wallet.remove_block_movements(&[vec![2; 32]]));
+    }
 }
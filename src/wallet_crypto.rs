@@ -0,0 +1,192 @@
+use bitcoin_hashes::{hmac, sha256, Hash, HashEngine};
+
+use crate::{error::CustomError, rng::random_bytes};
+
+/// Iteraciones de PBKDF2 con las que se deriva la clave de las wallets a partir del passphrase.
+pub const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// Largo en bytes del salt de PBKDF2 y del nonce de cada private key cifrada.
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 16;
+
+/// Largo en bytes del tag que autentica cada private key cifrada.
+const TAG_LEN: usize = 32;
+
+/// Mensaje que se autentica con la clave derivada para verificar el passphrase sin descifrar ninguna private key.
+const VERIFIER_MESSAGE: &[u8] = b"wallets passphrase check";
+
+/// WalletCipher cifra y descifra las private keys de las wallets con una clave derivada de un passphrase.
+/// De la clave derivada con PBKDF2-HMAC-SHA256 salen dos claves: una para el keystream (HMAC-SHA256 en modo contador)
+/// y otra para el tag HMAC-SHA256 que detecta un passphrase incorrecto o un archivo alterado.
+/// Los elementos son:
+/// - salt: Salt con el que se derivo la clave, se guarda junto a las wallets.
+/// - iterations: Iteraciones de PBKDF2 con las que se derivo la clave.
+/// - encryption_key: Clave del keystream.
+/// - authentication_key: Clave de los tags.
+#[derive(Clone)]
+pub struct WalletCipher {
+    pub salt: Vec<u8>,
+    pub iterations: u32,
+    encryption_key: [u8; 32],
+    authentication_key: [u8; 32],
+}
+
+impl WalletCipher {
+    /// Deriva la clave de un passphrase nuevo, con un salt aleatorio.
+    pub fn create(passphrase: &str) -> Result<Self, CustomError> {
+        if passphrase.is_empty() {
+            return Err(CustomError::Validation(
+                "Passphrase must not be empty".to_string(),
+            ));
+        }
+        Ok(Self::derive(
+            passphrase,
+            random_bytes(SALT_LEN),
+            PBKDF2_ITERATIONS,
+        ))
+    }
+
+    /// Deriva la clave de un passphrase con el salt y las iteraciones guardadas.
+    pub fn derive(passphrase: &str, salt: Vec<u8>, iterations: u32) -> Self {
+        let key = pbkdf2_sha256(passphrase.as_bytes(), &salt, iterations);
+        Self {
+            salt,
+            iterations,
+            encryption_key: hmac_sha256(&key, b"encryption"),
+            authentication_key: hmac_sha256(&key, b"authentication"),
+        }
+    }
+
+    /// Devuelve el valor que se guarda junto a las wallets para verificar el passphrase al desbloquearlas.
+    pub fn verifier(&self) -> Vec<u8> {
+        hmac_sha256(&self.authentication_key, VERIFIER_MESSAGE).to_vec()
+    }
+
+    /// Verifica que el passphrase del que se derivo la clave sea el de verifier.
+    /// Devuelve CustomError::InvalidPassphrase si no lo es.
+    pub fn verify(&self, verifier: &[u8]) -> Result<(), CustomError> {
+        match constant_time_eq(&self.verifier(), verifier) {
+            true => Ok(()),
+            false => Err(CustomError::InvalidPassphrase),
+        }
+    }
+
+    /// Cifra una private key y la devuelve en hexadecimal: el nonce, la private key cifrada y el tag.
+    pub fn encrypt(&self, privkey: &str) -> String {
+        let nonce = random_bytes(NONCE_LEN);
+        let mut buffer = nonce.clone();
+        buffer.extend(self.apply_keystream(&nonce, privkey.as_bytes()));
+        let tag = hmac_sha256(&self.authentication_key, &buffer);
+        buffer.extend(tag);
+        to_hex(&buffer)
+    }
+
+    /// Descifra una private key cifrada con encrypt.
+    /// Devuelve CustomError::InvalidPassphrase si el tag no coincide (passphrase incorrecto o archivo alterado).
+    pub fn decrypt(&self, encrypted: &str) -> Result<String, CustomError> {
+        let buffer = from_hex(encrypted).ok_or(CustomError::SerializedBufferIsInvalid)?;
+        if buffer.len() < NONCE_LEN + TAG_LEN {
+            return Err(CustomError::SerializedBufferIsInvalid);
+        }
+        let (data, tag) = buffer.split_at(buffer.len() - TAG_LEN);
+        if !constant_time_eq(&hmac_sha256(&self.authentication_key, data), tag) {
+            return Err(CustomError::InvalidPassphrase);
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        String::from_utf8(self.apply_keystream(nonce, ciphertext))
+            .map_err(|_| CustomError::SerializedBufferIsInvalid)
+    }
+
+    /// Aplica (XOR) el keystream del nonce: el bloque i es HMAC-SHA256(encryption_key, nonce || i).
+    fn apply_keystream(&self, nonce: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(data.len());
+        for (counter, chunk) in data.chunks(32).enumerate() {
+            let mut block_input = nonce.to_vec();
+            block_input.extend((counter as u32).to_le_bytes());
+            let keystream = hmac_sha256(&self.encryption_key, &block_input);
+            output.extend(chunk.iter().zip(keystream).map(|(byte, key)| byte ^ key));
+        }
+        output
+    }
+}
+
+/// PBKDF2-HMAC-SHA256 con un unico bloque de salida (32 bytes).
+fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    // el engine con la clave ya procesada se clona en cada iteracion para no volver a procesarla
+    let keyed_engine = hmac::HmacEngine::<sha256::Hash>::new(password);
+
+    let mut engine = keyed_engine.clone();
+    engine.input(salt);
+    engine.input(&1_u32.to_be_bytes());
+    let mut block = hmac::Hmac::<sha256::Hash>::from_engine(engine).to_byte_array();
+    let mut result = block;
+    for _ in 1..iterations {
+        let mut engine = keyed_engine.clone();
+        engine.input(&block);
+        block = hmac::Hmac::<sha256::Hash>::from_engine(engine).to_byte_array();
+        for (result_byte, block_byte) in result.iter_mut().zip(block) {
+            *result_byte ^= block_byte;
+        }
+    }
+    result
+}
+
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(key);
+    engine.input(data);
+    hmac::Hmac::<sha256::Hash>::from_engine(engine).to_byte_array()
+}
+
+/// Compara sin cortar en el primer byte distinto, para no filtrar por tiempo cuantos bytes coinciden.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pbkdf2_matches_rfc_7914_vector() {
+        let key = pbkdf2_sha256(b"passwd", b"salt", 1);
+        assert_eq!(
+            to_hex(&key),
+            "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc"
+        );
+    }
+
+    #[test]
+    fn encrypt_decrypt_round_trip() {
+        let cipher = WalletCipher::derive("correct horse", b"salt".to_vec(), 10);
+        let privkey = "cSM1NQcoCMDP8jy2AMQWNTaSqNPZmRd6AV4a5F3A4Lq3UgJrPpER";
+
+        let encrypted = cipher.encrypt(privkey);
+        assert!(!encrypted.contains(privkey));
+        assert_eq!(cipher.decrypt(&encrypted).unwrap(), privkey);
+
+        let wrong = WalletCipher::derive("wrong horse", b"salt".to_vec(), 10);
+        assert!(matches!(
+            wrong.decrypt(&encrypted),
+            Err(CustomError::InvalidPassphrase)
+        ));
+        assert!(matches!(
+            wrong.verify(&cipher.verifier()),
+            Err(CustomError::InvalidPassphrase)
+        ));
+        cipher.verify(&cipher.verifier()).unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    collections::HashSet,
+    fs,
+    sync::{Arc, Mutex},
+};
+
+use crate::{error::CustomError, node_state::NodeState, wallet::Wallet};
+
+/// Cada cuantas direcciones validadas se informa el progreso de la importacion.
+pub const IMPORT_PROGRESS_STEP: usize = 50;
+
+#[derive(Debug, Clone, PartialEq)]
+/// AddressEntry es una linea de la lista de direcciones a importar.
+/// Los elementos son:
+/// - line: Numero de linea en el archivo (empezando en 1).
+/// - address: Direccion a vigilar.
+/// - label: Etiqueta opcional, escrita luego de una coma.
+pub struct AddressEntry {
+    pub line: usize,
+    pub address: String,
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// SkippedAddress es una direccion de la lista que no se importo.
+/// Los elementos son:
+/// - line: Numero de linea en el archivo.
+/// - address: Direccion salteada.
+/// - reason: Motivo por el que no se importo.
+pub struct SkippedAddress {
+    pub line: usize,
+    pub address: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// ImportSummary es el resultado de importar una lista de direcciones.
+/// Los elementos son:
+/// - imported: Direcciones importadas como wallets watch-only.
+/// - skipped: Direcciones salteadas, ordenadas por linea.
+pub struct ImportSummary {
+    pub imported: Vec<String>,
+    pub skipped: Vec<SkippedAddress>,
+}
+
+impl ImportSummary {
+    /// Devuelve un texto con la cantidad de direcciones importadas y salteadas y el motivo de cada salteada.
+    pub fn describe(&self) -> String {
+        let mut description = format!(
+            "Imported {} addresses, skipped {}.",
+            self.imported.len(),
+            self.skipped.len()
+        );
+        for skipped in &self.skipped {
+            description.push_str(&format!(
+                "\nLine {}: {} ({})",
+                skipped.line, skipped.address, skipped.reason
+            ));
+        }
+        description
+    }
+}
+
+/// Parsea una lista de direcciones con una direccion por linea y una etiqueta opcional luego de una coma.
+/// Las lineas vacias se ignoran.
+pub fn parse_address_list(content: &str) -> Vec<AddressEntry> {
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let line_content = line.trim();
+            if line_content.is_empty() {
+                return None;
+            }
+            let (address, label) = match line_content.split_once(',') {
+                Some((address, label)) => (address.trim(), Some(label.trim().to_string())),
+                None => (line_content, None),
+            };
+            Some(AddressEntry {
+                line: index + 1,
+                address: address.to_string(),
+                label: label.filter(|label| !label.is_empty()),
+            })
+        })
+        .collect()
+}
+
+/// Valida las direcciones de la lista y crea una wallet watch-only por cada direccion valida,
+/// usando la etiqueta como nombre (o la direccion si no tiene etiqueta).
+/// Devuelve las wallets creadas junto con su numero de linea, y las direcciones invalidas o repetidas con su motivo.
+/// on_progress recibe la cantidad de direcciones validadas y el total, cada IMPORT_PROGRESS_STEP direcciones y al terminar.
+pub fn validate_address_list(
+    entries: Vec<AddressEntry>,
+    mut on_progress: impl FnMut(usize, usize),
+) -> (Vec<(usize, Wallet)>, Vec<SkippedAddress>) {
+    let total = entries.len();
+    let mut wallets = vec![];
+    let mut skipped = vec![];
+    let mut seen: HashSet<String> = HashSet::new();
+
+    for (index, entry) in entries.into_iter().enumerate() {
+        if !seen.insert(entry.address.clone()) {
+            skipped.push(SkippedAddress {
+                line: entry.line,
+                address: entry.address,
+                reason: String::from("Duplicated address in the list"),
+            });
+        } else {
+            let name = entry.label.unwrap_or_else(|| entry.address.clone());
+            match Wallet::watch_only(name, entry.address.clone()) {
+                Ok(wallet) => wallets.push((entry.line, wallet)),
+                Err(error) => skipped.push(SkippedAddress {
+                    line: entry.line,
+                    address: entry.address,
+                    reason: skip_reason(error),
+                }),
+            }
+        }
+
+        let validated = index + 1;
+        if validated % IMPORT_PROGRESS_STEP == 0 || validated == total {
+            on_progress(validated, total);
+        }
+    }
+
+    (wallets, skipped)
+}
+
+fn skip_reason(error: CustomError) -> String {
+    match error {
+        CustomError::Validation(reason) => reason,
+        error => error.description().to_string(),
+    }
+}
+
+/// Importa como wallets watch-only las direcciones del archivo recibido (ver parse_address_list).
+/// La validacion se hace sin tomar el lock del node state, y luego todas las wallets se registran
+/// con un unico rescan de las UTXO. Las direcciones que ya tenia el nodo se saltean.
+/// Pensada para correr en un thread aparte, informando el progreso con on_progress.
+pub fn import_address_file(
+    path: &str,
+    node_state_ref: &Arc<Mutex<NodeState>>,
+    on_progress: impl FnMut(usize, usize),
+) -> Result<ImportSummary, CustomError> {
+    let content = fs::read_to_string(path)?;
+    let entries = parse_address_list(&content);
+    let (wallets, mut skipped) = validate_address_list(entries, on_progress);
+
+    let lines: Vec<(usize, String)> = wallets
+        .iter()
+        .map(|(line, wallet)| (*line, wallet.pubkey.clone()))
+        .collect();
+    let wallets = wallets.into_iter().map(|(_, wallet)| wallet).collect();
+
+    let mut node_state = node_state_ref.lock()?;
+    let imported = node_state.import_watch_only_wallets(wallets)?;
+    drop(node_state);
+
+    for (line, address) in lines {
+        if !imported.contains(&address) {
+            skipped.push(SkippedAddress {
+                line,
+                address,
+                reason: String::from("Address is already watched"),
+            });
+        }
+    }
+    skipped.sort_by_key(|skipped| skipped.line);
+
+    Ok(ImportSummary { imported, skipped })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS_LIST: &str = "mscatccDgq7azndWHFTzvEuZuywCsUvTRu, deposit 1
+mxz3drZtkg4R3u1RDL7zRPLsizvhmGWfr3
+
+1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa,mainnet
+mscatccDgq7azndWHFTzvEuZuywCsUvTRv
+mscatccDgq7azndWHFTzvEuZuywCsUvTRu,repeated
+";
+
+    #[test]
+    fn parse_address_list_with_labels() {
+        let entries = parse_address_list(ADDRESS_LIST);
+        assert_eq!(entries.len(), 5);
+        assert_eq!(
+            entries[0],
+            AddressEntry {
+                line: 1,
+                address: String::from("mscatccDgq7azndWHFTzvEuZuywCsUvTRu"),
+                label: Some(String::from("deposit 1")),
+            }
+        );
+        assert_eq!(entries[1].label, None);
+        assert_eq!(entries[2].line, 4);
+    }
+
+    #[test]
+    fn validate_address_list_skips_invalid_and_duplicated() {
+        let mut progress = vec![];
+        let (wallets, skipped) =
+            validate_address_list(parse_address_list(ADDRESS_LIST), |validated, total| {
+                progress.push((validated, total))
+            });
+
+        assert_eq!(wallets.len(), 2);
+        assert_eq!(wallets[0].1.name, String::from("deposit 1"));
+        assert_eq!(
+            wallets[1].1.name,
+            String::from("mxz3drZtkg4R3u1RDL7zRPLsizvhmGWfr3")
+        );
+        assert!(wallets.iter().all(|(_, wallet)| wallet.is_watch_only()));
+
+        let skipped_lines: Vec<usize> = skipped.iter().map(|skipped| skipped.line).collect();
+        assert_eq!(skipped_lines, vec![4, 5, 6]);
+        assert_eq!(
+            skipped[2].reason,
+            String::from("Duplicated address in the list")
+        );
+        assert_eq!(progress, vec![(5, 5)]);
+    }
+}
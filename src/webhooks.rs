@@ -0,0 +1,572 @@
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::CustomError,
+    rng::random_jitter,
+    utils::{open_file, OpenMode},
+    wallet_crypto::{hmac_sha256, to_hex},
+};
+
+/// Cantidad maxima de eventos esperando ser enviados, los que llegan con la cola llena se descartan.
+pub const MAX_WEBHOOK_QUEUE: usize = 256;
+
+/// Cantidad de intentos de envio de cada evento antes de guardarlo en el dead letter log.
+pub const MAX_WEBHOOK_ATTEMPTS: u32 = 3;
+
+/// Espera antes del primer reintento de un evento, se duplica en cada reintento.
+pub const WEBHOOK_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Tiempo maximo para conectarse al webhook y para cada lectura o escritura de la peticion.
+pub const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Tiempo maximo de cada envio completo, para que un webhook que responde de a poco no lo demore indefinidamente.
+pub const WEBHOOK_DEADLINE: Duration = Duration::from_secs(5);
+
+/// Header con la firma HMAC-SHA256 del body, si el config indica WEBHOOK_SECRET.
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+/// Archivo del store donde se guardan los eventos que no se pudieron enviar, uno por linea.
+pub const WEBHOOK_DEAD_LETTER_FILE: &str = "webhook_dead_letter.log";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// WebhookEventKind es el tipo de movimiento de una wallet que se avisa al webhook:
+/// - IncomingPending: Llego una pending tx que le envia fondos a la wallet.
+/// - Confirmation: Se confirmo en un bloque una transaccion de la wallet.
+/// - OutgoingBroadcast: Se envio a los peers una transaccion creada desde la wallet.
+/// - LargePayment: La wallet recibio un pago de al menos ALERT_THRESHOLD_SATS (ver PaymentAlerts).
+pub enum WebhookEventKind {
+    IncomingPending,
+    Confirmation,
+    OutgoingBroadcast,
+    LargePayment,
+}
+
+impl WebhookEventKind {
+    /// Devuelve el nombre del tipo de evento que se envia en el campo event.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::IncomingPending => "incoming_pending",
+            Self::Confirmation => "confirmation",
+            Self::OutgoingBroadcast => "outgoing_broadcast",
+            Self::LargePayment => "large_payment",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// WebhookEvent es un movimiento de una wallet que se envia al webhook como JSON.
+/// Los elementos son:
+/// - kind: Tipo de evento.
+/// - wallet: Nombre de la wallet.
+/// - txid: Hash de la transaccion como lo muestran los exploradores.
+/// - value: Variacion del balance de la wallet en satoshis, negativa si la transaccion gasta sus fondos.
+/// - confirmations: Confirmaciones de la transaccion al generarse el evento.
+/// - timestamp: Timestamp en segundos del momento en el que se genero el evento.
+pub struct WebhookEvent {
+    pub kind: WebhookEventKind,
+    pub wallet: String,
+    pub txid: String,
+    pub value: i64,
+    pub confirmations: u32,
+    pub timestamp: u64,
+}
+
+impl WebhookEvent {
+    /// Devuelve el evento como el JSON que se envia en el body de la peticion.
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"event\":\"{}\",\"wallet\":\"{}\",\"txid\":\"{}\",\"value\":{},\"confirmations\":{},\"timestamp\":{}}}",
+            self.kind.as_str(),
+            json_escape(&self.wallet),
+            self.txid,
+            self.value,
+            self.confirmations,
+            self.timestamp
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// QueuedWebhookEvent es un evento que espera ser enviado al webhook.
+/// Los elementos son:
+/// - event: Evento a enviar.
+/// - attempts: Intentos de envio que fallaron.
+/// - next_attempt: Momento a partir del cual se puede volver a intentar.
+pub struct QueuedWebhookEvent {
+    pub event: WebhookEvent,
+    pub attempts: u32,
+    pub next_attempt: Instant,
+}
+
+/// WebhookQueue es la cola acotada de eventos que espera enviar el webhook_loop.
+/// Si esta llena los eventos nuevos se descartan y se cuentan, para no acumular memoria con el webhook caido.
+/// Los elementos son:
+/// - events: Eventos a enviar, del mas antiguo al mas reciente.
+/// - capacity: Cantidad maxima de eventos en la cola.
+/// - dropped: Cantidad de eventos descartados por encontrar la cola llena.
+pub struct WebhookQueue {
+    events: VecDeque<QueuedWebhookEvent>,
+    capacity: usize,
+    dropped: u64,
+}
+
+impl WebhookQueue {
+    /// Crea una cola vacia que acepta hasta capacity eventos.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+            dropped: 0,
+        }
+    }
+
+    /// Agrega un evento para enviarlo a partir de now. Devuelve false si la cola estaba llena y se descarto.
+    pub fn push(&mut self, event: WebhookEvent, now: Instant) -> bool {
+        self.enqueue(QueuedWebhookEvent {
+            event,
+            attempts: 0,
+            next_attempt: now,
+        })
+    }
+
+    /// Saca de la cola hasta batch eventos que ya se pueden enviar en now, en el orden en el que se agregaron.
+    pub fn take_due(&mut self, now: Instant, batch: usize) -> Vec<QueuedWebhookEvent> {
+        let mut due = vec![];
+        let mut waiting = VecDeque::with_capacity(self.capacity);
+        for queued in self.events.drain(..) {
+            if due.len() < batch && queued.next_attempt <= now {
+                due.push(queued);
+            } else {
+                waiting.push_back(queued);
+            }
+        }
+        self.events = waiting;
+        due
+    }
+
+    /// Registra que fallo el envio de un evento sacado con take_due.
+    /// Si le quedan intentos lo vuelve a agregar, esperando WEBHOOK_RETRY_BACKOFF duplicado por cada intento previo
+    /// mas un jitter aleatorio de hasta la decima parte de esa espera, y si no lo devuelve para guardarlo en el dead letter log.
+    pub fn failed(
+        &mut self,
+        mut queued: QueuedWebhookEvent,
+        now: Instant,
+    ) -> Option<QueuedWebhookEvent> {
+        queued.attempts += 1;
+        if queued.attempts >= MAX_WEBHOOK_ATTEMPTS {
+            return Some(queued);
+        }
+        let backoff = WEBHOOK_RETRY_BACKOFF * 2_u32.pow(queued.attempts - 1);
+        queued.next_attempt = now + backoff + random_jitter(backoff / 10);
+        self.enqueue(queued);
+        None
+    }
+
+    /// Devuelve la cantidad de eventos descartados por encontrar la cola llena.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Devuelve true si no hay eventos esperando ser enviados.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    fn enqueue(&mut self, queued: QueuedWebhookEvent) -> bool {
+        if self.events.len() >= self.capacity {
+            self.dropped += 1;
+            return false;
+        }
+        self.events.push_back(queued);
+        true
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// WebhookUrl es la direccion a la que se envian los eventos, leida del config (WEBHOOK_URL).
+/// Solo se admite http://host[:puerto][/path], el puerto por defecto es 80 y el path /.
+pub struct WebhookUrl {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl WebhookUrl {
+    /// Lee la direccion del valor de WEBHOOK_URL.
+    /// Devuelve la descripcion del problema si no empieza con http://, no tiene host o el puerto es invalido.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let Some(rest) = value.strip_prefix("http://") else {
+            return Err(format!(
+                "WEBHOOK_URL must start with http://, found '{value}'"
+            ));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse::<u16>()
+                    .map_err(|_| format!("invalid port for WEBHOOK_URL: '{port}'"))?;
+                (host, port)
+            }
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("WEBHOOK_URL has no host, found '{value}'"));
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Webhook envia los eventos de las wallets con un POST HTTP a la direccion del config.
+/// Los elementos son:
+/// - url: Direccion del webhook.
+/// - secret: Clave con la que se firma el body en el header WEBHOOK_SIGNATURE_HEADER, si el config indica WEBHOOK_SECRET.
+/// - dead_letter_path: Archivo donde se guardan los eventos que agotaron sus intentos.
+pub struct Webhook {
+    pub url: WebhookUrl,
+    secret: Option<String>,
+    dead_letter_path: String,
+}
+
+impl Webhook {
+    /// Crea el webhook, guardando los eventos que no se pudieron enviar en WEBHOOK_DEAD_LETTER_FILE dentro de store_path.
+    pub fn new(url: WebhookUrl, secret: Option<String>, store_path: &str) -> Self {
+        Self {
+            url,
+            secret,
+            dead_letter_path: format!("{}/{}", store_path, WEBHOOK_DEAD_LETTER_FILE),
+        }
+    }
+
+    /// Envia un evento al webhook.
+    /// Devuelve CustomError si no se pudo conectar o si la respuesta no tiene un status 2xx (WebhookRejected).
+    pub fn deliver(&self, event: &WebhookEvent) -> Result<(), CustomError> {
+        let status = self.post(&event.to_json())?;
+        if !(200..300).contains(&status) {
+            return Err(CustomError::WebhookRejected(status));
+        }
+        Ok(())
+    }
+
+    /// Agrega al dead letter log un evento que agoto sus intentos, con el JSON del evento y el ultimo error.
+    pub fn dead_letter(
+        &self,
+        event: &WebhookEvent,
+        error: &CustomError,
+    ) -> Result<(), CustomError> {
+        let mut file = open_file(&self.dead_letter_path, OpenMode::Append)?;
+        writeln!(file, "{} {}", event.to_json(), error)?;
+        Ok(())
+    }
+
+    /// Hace el POST del body y devuelve el status de la respuesta.
+    /// Devuelve un error TimedOut si no se recibe la linea de status dentro de WEBHOOK_DEADLINE.
+    fn post(&self, body: &str) -> Result<u16, CustomError> {
+        let deadline = Instant::now() + WEBHOOK_DEADLINE;
+        let address = (self.url.host.as_str(), self.url.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or(CustomError::WebhookUnreachable)?;
+        let mut stream = TcpStream::connect_timeout(&address, WEBHOOK_TIMEOUT)?;
+        stream.set_write_timeout(Some(WEBHOOK_TIMEOUT))?;
+
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.url.path,
+            self.url.host,
+            self.url.port,
+            body.len()
+        );
+        if let Some(secret) = &self.secret {
+            request.push_str(&format!(
+                "{}: {}\r\n",
+                WEBHOOK_SIGNATURE_HEADER,
+                signature(secret, body)
+            ));
+        }
+        request.push_str("\r\n");
+        request.push_str(body);
+        stream.write_all(request.as_bytes())?;
+
+        let mut response = vec![];
+        let mut chunk = [0; 256];
+        while !response.contains(&b'\n') {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::Error::from(ErrorKind::TimedOut).into());
+            }
+            stream.set_read_timeout(Some(remaining.min(WEBHOOK_TIMEOUT)))?;
+            let read = stream.read(&mut chunk)?;
+            if read == 0 {
+                break;
+            }
+            response.extend(&chunk[..read]);
+        }
+        let response = String::from_utf8_lossy(&response);
+        let status_line = response.lines().next().unwrap_or_default();
+        status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or(CustomError::WebhookInvalidResponse)
+    }
+}
+
+/// Devuelve la firma del body que se envia en WEBHOOK_SIGNATURE_HEADER: sha256= seguido del HMAC-SHA256 en hexadecimal.
+pub fn signature(secret: &str, body: &str) -> String {
+    format!(
+        "sha256={}",
+        to_hex(&hmac_sha256(secret.as_bytes(), body.as_bytes()))
+    )
+}
+
+pub fn json_escape(value: &str) -> String {
+    value
+        .chars()
+        .map(|character| match character {
+            '"' => String::from("\\\""),
+            '\\' => String::from("\\\\"),
+            character if character.is_control() => format!("\\u{:04x}", character as u32),
+            character => character.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        sync::mpsc::{self, Receiver},
+        thread,
+    };
+
+    use super::*;
+
+    fn event(kind: WebhookEventKind) -> WebhookEvent {
+        WebhookEvent {
+            kind,
+            wallet: String::from("savings \"main\""),
+            txid: String::from("ab01"),
+            value: -1500,
+            confirmations: 1,
+            timestamp: 1_700_000_000,
+        }
+    }
+
+    /// Servidor HTTP que responde cada peticion con el siguiente status de statuses y envia por el channel
+    /// los headers (en minuscula) y el body de cada una.
+    fn http_server(statuses: Vec<u16>) -> (WebhookUrl, Receiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut headers = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    headers.push_str(&line.to_lowercase());
+                }
+                let length: usize = headers
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length: "))
+                    .unwrap()
+                    .trim()
+                    .parse()
+                    .unwrap();
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let response = format!("HTTP/1.1 {status} Status\r\nContent-Length: 0\r\n\r\n");
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+                sender
+                    .send((headers, String::from_utf8(body).unwrap()))
+                    .unwrap();
+            }
+        });
+        let url = WebhookUrl::parse(&format!("http://127.0.0.1:{port}/hooks/wallet")).unwrap();
+        (url, receiver)
+    }
+
+    #[test]
+    fn webhook_url_parses_host_port_and_path() {
+        assert_eq!(
+            WebhookUrl::parse("http://example.com:8080/hooks").unwrap(),
+            WebhookUrl {
+                host: String::from("example.com"),
+                port: 8080,
+                path: String::from("/hooks"),
+            }
+        );
+        let url = WebhookUrl::parse("http://example.com").unwrap();
+        assert_eq!((url.port, url.path.as_str()), (80, "/"));
+        assert!(WebhookUrl::parse("https://example.com").is_err());
+        assert!(WebhookUrl::parse("http://example.com:port").is_err());
+        assert!(WebhookUrl::parse("http://:80/hooks").is_err());
+    }
+
+    #[test]
+    fn webhook_posts_event_json() {
+        let (url, receiver) = http_server(vec![200]);
+        let webhook = Webhook::new(url, None, "tests");
+
+        webhook
+            .deliver(&event(WebhookEventKind::Confirmation))
+            .unwrap();
+
+        let (headers, body) = receiver.recv().unwrap();
+        assert!(headers.starts_with("post /hooks/wallet http/1.1\r\n"));
+        assert!(headers.contains("content-type: application/json\r\n"));
+        assert!(!headers.contains("x-webhook-signature"));
+        assert_eq!(
+            body,
+            "{\"event\":\"confirmation\",\"wallet\":\"savings \\\"main\\\"\",\"txid\":\"ab01\",\"value\":-1500,\"confirmations\":1,\"timestamp\":1700000000}"
+        );
+    }
+
+    #[test]
+    fn webhook_signs_body_with_secret() {
+        let (url, receiver) = http_server(vec![204]);
+        let webhook = Webhook::new(url, Some(String::from("secret")), "tests");
+
+        webhook
+            .deliver(&event(WebhookEventKind::IncomingPending))
+            .unwrap();
+
+        let (headers, body) = receiver.recv().unwrap();
+        let expected = to_hex(&hmac_sha256(b"secret", body.as_bytes()));
+        assert!(headers.contains(&format!("x-webhook-signature: sha256={expected}\r\n")));
+    }
+
+    #[test]
+    fn webhook_event_is_retried_after_server_error() {
+        let (url, receiver) = http_server(vec![500, 200]);
+        let webhook = Webhook::new(url, None, "tests");
+        let mut queue = WebhookQueue::new(MAX_WEBHOOK_QUEUE);
+        let now = Instant::now();
+        queue.push(event(WebhookEventKind::OutgoingBroadcast), now);
+
+        let queued = queue.take_due(now, 10).pop().unwrap();
+        let error = webhook.deliver(&queued.event).unwrap_err();
+        assert!(matches!(error, CustomError::WebhookRejected(500)));
+        assert!(queue.failed(queued, now).is_none());
+
+        assert!(queue.take_due(now, 10).is_empty());
+        assert!(queue
+            .take_due(now + WEBHOOK_RETRY_BACKOFF - Duration::from_millis(1), 10)
+            .is_empty());
+        let queued = queue
+            .take_due(now + WEBHOOK_RETRY_BACKOFF * 11 / 10, 10)
+            .pop()
+            .unwrap();
+        assert_eq!(queued.attempts, 1);
+        webhook.deliver(&queued.event).unwrap();
+        assert_eq!(receiver.iter().take(2).count(), 2);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn webhook_event_is_dead_lettered_after_max_attempts() {
+        let store_path = "tests/webhook_dead_letter_test";
+        fs::create_dir_all(store_path).unwrap();
+        let webhook = Webhook::new(
+            WebhookUrl::parse("http://localhost").unwrap(),
+            None,
+            store_path,
+        );
+        let mut queue = WebhookQueue::new(MAX_WEBHOOK_QUEUE);
+        let mut now = Instant::now();
+        queue.push(event(WebhookEventKind::Confirmation), now);
+
+        let mut exhausted = None;
+        for attempt in 1..=MAX_WEBHOOK_ATTEMPTS {
+            let queued = queue.take_due(now, 10).pop().unwrap();
+            exhausted = queue.failed(queued, now);
+            assert_eq!(exhausted.is_some(), attempt == MAX_WEBHOOK_ATTEMPTS);
+            now += WEBHOOK_RETRY_BACKOFF * 4;
+        }
+        let exhausted = exhausted.unwrap();
+        webhook
+            .dead_letter(&exhausted.event, &CustomError::WebhookRejected(500))
+            .unwrap();
+
+        let content =
+            fs::read_to_string(format!("{store_path}/{WEBHOOK_DEAD_LETTER_FILE}")).unwrap();
+        fs::remove_dir_all(store_path).unwrap();
+        assert!(queue.is_empty());
+        assert!(content.starts_with(&exhausted.event.to_json()));
+        assert!(content.ends_with("500\n"));
+    }
+
+    #[test]
+    fn webhook_queue_drops_events_when_full() {
+        let mut queue = WebhookQueue::new(2);
+        let now = Instant::now();
+
+        assert!(queue.push(event(WebhookEventKind::IncomingPending), now));
+        assert!(queue.push(event(WebhookEventKind::Confirmation), now));
+        assert!(!queue.push(event(WebhookEventKind::OutgoingBroadcast), now));
+
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.dropped(), 1);
+        let kinds: Vec<_> = queue
+            .take_due(now, 10)
+            .into_iter()
+            .map(|queued| queued.event.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                WebhookEventKind::IncomingPending,
+                WebhookEventKind::Confirmation
+            ]
+        );
+    }
+
+    #[test]
+    fn webhook_delivery_stops_at_the_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // responde de a un byte, sin llegar nunca al final de la linea de status
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            for _ in 0..WEBHOOK_DEADLINE.as_millis() / 100 + 20 {
+                if stream.write_all(b"H").is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+        });
+        let url = WebhookUrl::parse(&format!("http://127.0.0.1:{port}/hooks")).unwrap();
+        let webhook = Webhook::new(url, None, "tests");
+
+        let start = Instant::now();
+        let result = webhook.deliver(&event(WebhookEventKind::Confirmation));
+
+        assert!(result.is_err());
+        assert!(start.elapsed() < WEBHOOK_DEADLINE + Duration::from_secs(1));
+    }
+}
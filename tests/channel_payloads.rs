@@ -0,0 +1,142 @@
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        fs,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc,
+        },
+        thread,
+    };
+
+    use bitcoin::{
+        loops::{
+            node_action_loop::{NodeAction, NodeActionLoop},
+            peer_action_loop::peer_action_channel,
+        },
+        message::Message,
+        messages::{block::Block, transaction::Transaction},
+        node_state::NodeState,
+        structs::{
+            block_header::BlockHeader, outpoint::OutPoint, tx_input::TransactionInput,
+            tx_output::TransactionOutput,
+        },
+    };
+    use gtk::glib::{self, Priority};
+
+    /// Allocator que cuenta los bytes pedidos, para medir las copias de los payloads de los canales.
+    /// Este archivo de tests tiene un unico test, ya que el allocator es global al binario.
+    struct CountingAllocator;
+
+    static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::SeqCst);
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: CountingAllocator = CountingAllocator;
+
+    const BLOCKS: u8 = 100;
+    const TRANSACTIONS_PER_BLOCK: u32 = 500;
+
+    fn large_block(seed: u8) -> Block {
+        let transactions = (0..TRANSACTIONS_PER_BLOCK)
+            .map(|lock_time| Transaction {
+                version: 1,
+                inputs: vec![TransactionInput {
+                    previous_output: OutPoint {
+                        hash: vec![seed; 32],
+                        index: lock_time,
+                    },
+                    script_sig: vec![seed; 100],
+                    sequence: 4294967295,
+                }],
+                outputs: vec![TransactionOutput {
+                    value: 1000,
+                    script_pubkey: vec![seed; 25],
+                }],
+                lock_time,
+            })
+            .collect();
+
+        let header = BlockHeader {
+            version: 1,
+            prev_block_hash: vec![0; 32],
+            merkle_root: vec![0; 32],
+            timestamp: 1686626483,
+            bits: 0x207fffff,
+            nonce: 0,
+            hash: vec![seed; 32],
+            block_downloaded: false,
+            broadcasted: false,
+        };
+        Block::new(header, transactions)
+    }
+
+    #[test]
+    fn block_actions_do_not_copy_blocks() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        let (peer_action_sender, _peer_action_receiver) = peer_action_channel();
+        let (node_action_sender, node_action_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_channel_payloads");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref =
+            NodeState::new(logger_sender.clone(), gui_sender.clone(), &store_path).unwrap();
+
+        let blocks: Vec<Arc<Block>> = (0..BLOCKS)
+            .map(|seed| Arc::new(large_block(seed)))
+            .collect();
+        let block_size = blocks[0].serialize().len();
+
+        let loop_node_state_ref = node_state_ref.clone();
+        let node_action_loop = thread::spawn(move || {
+            NodeActionLoop::start(
+                gui_sender,
+                node_action_receiver,
+                peer_action_sender,
+                logger_sender,
+                loop_node_state_ref,
+            )
+        });
+
+        // los bloques no estan pendientes, por lo que el loop los guarda como huerfanos sin copiarlos:
+        // lo que se mide es unicamente el paso de los payloads por los canales y el loop
+        let allocated_before = ALLOCATED_BYTES.load(Ordering::SeqCst);
+        for block in &blocks {
+            node_action_sender
+                .send(NodeAction::Block((
+                    block.header.hash().clone(),
+                    block.clone(),
+                )))
+                .unwrap();
+        }
+        node_action_sender.send(NodeAction::Terminate).unwrap();
+        node_action_loop.join().unwrap();
+        let allocated = ALLOCATED_BYTES.load(Ordering::SeqCst) - allocated_before;
+
+        assert!(
+            allocated < block_size,
+            "{allocated} bytes allocated for {BLOCKS} blocks of {block_size} bytes"
+        );
+        // los huerfanos comparten el bloque enviado, que se libera con el NodeState
+        drop(node_state_ref);
+        for (seed, block) in blocks.iter().enumerate() {
+            assert_eq!(Arc::strong_count(block), 1);
+            assert_eq!(block.transactions.len(), TRANSACTIONS_PER_BLOCK as usize);
+            assert_eq!(block.header.hash(), &vec![seed as u8; 32]);
+        }
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+}
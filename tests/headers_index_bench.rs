@@ -0,0 +1,158 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Bytes reservados en este momento por el programa.
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+/// Allocator del sistema que lleva la cuenta de los bytes reservados, para medir cuanta memoria ocupa cada indice.
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs, time::Instant};
+
+    use bitcoin::{
+        states::headers_index::{HeadersIndex, HOT_HEADERS},
+        structs::block_header::BlockHeader,
+    };
+    use bitcoin_hashes::{sha256d, Hash};
+
+    use super::*;
+
+    const SYNTHETIC_HEADERS: usize = 2_000_000;
+    const LOOKUPS: usize = 20_000;
+
+    fn synthetic_hash(index: usize) -> Vec<u8> {
+        sha256d::Hash::hash(&index.to_le_bytes())
+            .to_byte_array()
+            .to_vec()
+    }
+
+    fn synthetic_headers(count: usize) -> Vec<BlockHeader> {
+        (0..count)
+            .map(|index| BlockHeader {
+                version: 1,
+                prev_block_hash: vec![],
+                merkle_root: vec![],
+                timestamp: index as u32,
+                bits: 0,
+                nonce: 0,
+                hash: synthetic_hash(index),
+                broadcasted: false,
+                block_downloaded: false,
+            })
+            .collect()
+    }
+
+    /// Devuelve lo que arma build junto con los bytes que siguen reservados al terminar.
+    fn retained_bytes<T>(build: impl FnOnce() -> T) -> (T, usize) {
+        let before = ALLOCATED.load(Ordering::Relaxed);
+        let built = build();
+        (built, ALLOCATED.load(Ordering::Relaxed) - before)
+    }
+
+    /// Devuelve el tiempo promedio en nanosegundos de buscar cada hash con lookup.
+    fn average_nanos(hashes: &[Vec<u8>], lookup: impl Fn(&[u8]) -> Option<usize>) -> u128 {
+        let start = Instant::now();
+        for hash in hashes {
+            std::hint::black_box(lookup(hash));
+        }
+        start.elapsed().as_nanos() / hashes.len() as u128
+    }
+
+    /// Compara el HashMap<Vec<u8>, usize> con todos los hashes contra HeadersIndex sobre una cadena sintetica de
+    /// SYNTHETIC_HEADERS headers: memoria por header (contada por CountingAllocator) y latencia de las busquedas
+    /// de headers recientes, viejos y que no estan. Se corre con
+    /// cargo test --release --test headers_index_bench -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn headers_index_memory_and_lookup_benchmark() {
+        let path = "tests/test_headers_index_bench.bin";
+        let headers = synthetic_headers(SYNTHETIC_HEADERS);
+
+        let (flat, flat_bytes) = retained_bytes(|| {
+            headers
+                .iter()
+                .enumerate()
+                .map(|(index, header)| (header.hash.clone(), index))
+                .collect::<HashMap<Vec<u8>, usize>>()
+        });
+        let (index, index_bytes) = retained_bytes(|| {
+            let mut index = HeadersIndex::new(path.to_string(), HOT_HEADERS);
+            index.restore(&headers).unwrap();
+            index
+        });
+        let file_bytes = fs::metadata(path).unwrap().len();
+
+        let step = SYNTHETIC_HEADERS / LOOKUPS;
+        let recent: Vec<Vec<u8>> = (SYNTHETIC_HEADERS - HOT_HEADERS..SYNTHETIC_HEADERS)
+            .step_by(HOT_HEADERS / LOOKUPS)
+            .map(|index| headers[index].hash.clone())
+            .collect();
+        let old: Vec<Vec<u8>> = (0..SYNTHETIC_HEADERS - HOT_HEADERS)
+            .step_by(step)
+            .map(|index| headers[index].hash.clone())
+            .collect();
+        let absent: Vec<Vec<u8>> = (SYNTHETIC_HEADERS..SYNTHETIC_HEADERS + LOOKUPS)
+            .map(synthetic_hash)
+            .collect();
+
+        for hashes in [&recent, &old] {
+            for hash in hashes {
+                let position = index.position(&headers, hash).unwrap();
+                assert_eq!(position, flat.get(hash).copied());
+                assert!(position.is_some());
+            }
+        }
+        for hash in &absent {
+            assert_eq!(index.position(&headers, hash).unwrap(), None);
+        }
+
+        let flat_lookup = |hash: &[u8]| flat.get(hash).copied();
+        let index_lookup = |hash: &[u8]| index.position(&headers, hash).unwrap();
+        println!("{SYNTHETIC_HEADERS} headers, {HOT_HEADERS} en memoria");
+        println!(
+            "memoria: HashMap {} bytes/header, HeadersIndex {} bytes/header (archivo {} bytes por header viejo)",
+            flat_bytes / SYNTHETIC_HEADERS,
+            index_bytes / SYNTHETIC_HEADERS,
+            file_bytes as usize / index.cold_len()
+        );
+        for (name, hashes) in [
+            ("recientes", &recent),
+            ("viejos", &old),
+            ("ausentes", &absent),
+        ] {
+            println!(
+                "busqueda de headers {name}: HashMap {} ns, HeadersIndex {} ns",
+                average_nanos(hashes, flat_lookup),
+                average_nanos(hashes, index_lookup)
+            );
+        }
+
+        assert!(index_bytes * 2 < flat_bytes);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,27 +1,102 @@
+// el ejemplo sync_headers tambien se compila con los tests, para correrlo contra un peer falso (ver
+// sync_headers_example_syncs_from_a_fake_peer) y que no quede desactualizado respecto de la biblioteca
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "../examples/sync_headers.rs"]
+mod sync_headers_example;
+
 #[cfg(test)]
 mod tests {
     use std::{
+        collections::HashSet,
+        env,
         fs::{self, File},
-        io::{BufRead, BufReader},
-        net::{Ipv6Addr, SocketAddrV6},
+        io::{BufRead, BufReader, Read, Write},
+        net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, TcpListener, TcpStream},
+        process::Command,
         sync::{mpsc, Arc, Mutex},
         thread,
-        time::Duration,
+        time::{Duration, Instant},
     };
 
     use bitcoin::{
         config::Config,
-        logger::Logger,
+        config_reload::ConfigReloader,
+        crash_report::{
+            exit_code, install_panic_hook, EXIT_CONFIG_ERROR, EXIT_PANIC, EXIT_STORE_CORRUPT,
+            EXIT_STORE_LOCKED,
+        },
+        error::CustomError,
+        gui::init::GUIEvents,
+        logger::{Log, LogHistory, Logger, LOG_HISTORY_LINES},
         loops::{
-            peer_action_loop::PeerAction, pending_blocks_loop::pending_blocks_loop,
+            block_metadata_task::backfill_block_metadata,
+            maintenance_loop::{maintenance_loop, Scheduler},
+            node_action_loop::{NodeAction, NodeActionLoop},
+            peer_action_loop::{peer_action_channel, PeerAction, PeerActionReceiver},
+            peer_stream_loop::UNNEGOTIATED_MESSAGE_SCORE,
+            pending_blocks_task::{pending_blocks_task, PENDING_BLOCKS_TASK},
+            ping_loop::ping_loop,
+            reconnect_loop::ReconnectLoop,
             tcp_listener_loop::TcpListenerLoop,
+            tx_propagation_loop::tx_propagation_loop,
+        },
+        message::{Message, MessageHeader},
+        messages::{
+            addr::{Addr, NetworkAddress},
+            block::{block_subsidy, Block},
+            block_txn::{BlockTxn, GetBlockTxn},
+            compact_block::CompactBlock,
+            filter_load::{FilterClear, FilterLoad},
+            get_data::GetData,
+            get_headers::GetHeaders,
+            headers::Headers,
+            inv::Inv,
+            mempool::Mempool,
+            merkle_block::MerkleBlock,
+            ping_pong::{Ping, Pong},
+            reject::{Reject, REJECT_DUPLICATE, REJECT_INSUFFICIENT_FEE},
+            send_compact::{SendCompact, COMPACT_BLOCKS_PROTOCOL_VERSION},
+            transaction::{Transaction, TransactionOptions},
+            ver_ack::VerAck,
+            version::{Version, DEFAULT_USER_AGENT},
         },
+        mining::mine_block,
         node::Node,
-        node_state::NodeState,
-        peer::Peer,
-        utils::get_addresses,
+        node_state::{NodeState, MAX_DISCONNECT_EVENTS},
+        peer::{
+            DisconnectEvent, DisconnectReason, Peer, GENESIS, INVALID_MESSAGE_SCORE,
+            MAX_EARLY_HANDSHAKE_MESSAGES,
+        },
+        private_network::PrivateNetwork,
+        rng::random_nonce,
+        rpc::{RpcCall, RpcRequest, RpcServer},
+        rpc_auth::{RpcAccess, RpcCredentials},
+        send_many::{SendManyError, SendManyRequest, SendManyResult},
+        services::{ServiceCapabilities, NODE_BLOOM, NODE_NETWORK, NODE_NETWORK_LIMITED},
+        states::{
+            block_store_state::{block_file_path, BlockStore, BLOCK_RECORD_HEADER_SIZE},
+            pending_blocks_state::IDLE_PENDING_BLOCKS_POLL,
+            utxo_state::START_DATE_IBD,
+        },
+        store_lock::StoreLock,
+        structs::{
+            block_header::{display_hash, hash_as_string, BlockHeader},
+            bloom_filter::{BloomFilter, BLOOM_UPDATE_NONE},
+            inventory::{Inventory, InventoryType},
+            outpoint::OutPoint,
+            tx_input::TransactionInput,
+            tx_output::TransactionOutput,
+        },
+        synthetic_chain::{SyntheticChain, SYNTHETIC_MIN_DIFFICULTY_BITS},
+        utils::{get_address_v6, get_addresses, get_current_timestamp, open_stream},
+        wallet::{get_pubkey_hash, get_script_pubkey, Wallet},
+        wallet_crypto::to_hex,
+        wallet_import::import_address_file,
     };
+    use bitcoin_hashes::{sha256d, Hash};
     use gtk::glib::{self, Priority};
+    use std::{cell::RefCell, rc::Rc};
 
     #[test]
     fn node_and_state_creation() {
@@ -46,17 +121,18 @@ mod tests {
         let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
         let mut addresses =
             get_addresses("seed.testnet.bitcoin.sprovoost.nl".to_string(), 18333).unwrap();
-        let (_peer_action_sender, receiver) = mpsc::channel();
-        let peer_action_receiver = Arc::new(Mutex::new(receiver));
+        let (_peer_action_sender, peer_action_receiver) = peer_action_channel();
         let (node_action_sender, _node_action_receiver) = mpsc::channel();
         let logger = Logger::new(&String::from("tests/test_log2.txt"), gui_sender.clone()).unwrap();
         let logger_sender = logger.get_sender();
 
         let peer = Peer::call(
-            addresses.next().unwrap(),
+            open_stream(addresses.next().unwrap()).unwrap(),
             SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), 18333, 0, 0),
             0,
             70012,
+            random_nonce(),
+            DEFAULT_USER_AGENT,
             peer_action_receiver.clone(),
             logger_sender.clone(),
             node_action_sender.clone(),
@@ -66,10 +142,12 @@ mod tests {
         peer.unwrap();
 
         let peer2 = Peer::call(
-            addresses.next().unwrap(),
+            open_stream(addresses.next().unwrap()).unwrap(),
             SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), 18333, 0, 0),
             0,
             70012,
+            random_nonce(),
+            DEFAULT_USER_AGENT,
             peer_action_receiver,
             logger_sender,
             node_action_sender,
@@ -99,31 +177,83 @@ mod tests {
         let logger = Logger::new(&String::from("tests/test_log3.txt"), gui_sender.clone()).unwrap();
         let logger_sender = logger.get_sender();
 
-        let (peer_action_sender, receiver) = mpsc::channel();
-        let peer_action_receiver = Arc::new(Mutex::new(receiver));
+        let (node_action_sender, _node_action_receiver) = mpsc::channel();
+        let (getdata_sender, getdata_receiver) = mpsc::channel();
+        let peer = connect_synthetic_peer(0, node_action_sender, record_getdata(0, getdata_sender));
 
         let store_path = String::from("tests");
         let node_state_ref =
             NodeState::new(logger_sender.clone(), gui_sender, &store_path).unwrap();
         let node_state = node_state_ref.clone();
         let mut node_state = node_state.lock().unwrap();
-        node_state.append_pending_block(vec![1, 2, 3]).unwrap();
+        node_state
+            .set_stale_block_timeout(Duration::from_millis(100))
+            .unwrap();
+        node_state.append_peers(vec![peer]).unwrap();
+        node_state.append_pending_block(vec![1; 32]).unwrap();
         assert_eq!(node_state.is_pending_blocks_empty().unwrap(), false);
         drop(node_state);
 
-        pending_blocks_loop(node_state_ref, peer_action_sender, logger_sender);
+        let started = Instant::now();
+        spawn_pending_blocks_task(&node_state_ref, logger_sender, Duration::from_millis(20));
 
-        thread::sleep(Duration::from_secs(5));
+        let (_, hashes) = getdata_receiver
+            .recv_timeout(Duration::from_secs(1))
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(hashes, vec![vec![1; 32]]);
 
-        let message = peer_action_receiver.lock().unwrap().recv().unwrap();
-        if let PeerAction::GetData(_) = message {
-            assert!(true);
-        } else {
-            assert!(false);
-        }
+        node_state_ref
+            .lock()
+            .unwrap()
+            .remove_all_peers(DisconnectReason::Terminated)
+            .unwrap();
         fs::remove_file("tests/test_log3.txt").unwrap();
     }
 
+    #[test]
+    fn pending_blocks_task_wakes_up_when_a_block_is_requested() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let (node_action_sender, _node_action_receiver) = mpsc::channel();
+        let (getdata_sender, getdata_receiver) = mpsc::channel();
+        let peer = connect_synthetic_peer(0, node_action_sender, record_getdata(0, getdata_sender));
+
+        let store_path = String::from("tests");
+        let node_state_ref =
+            NodeState::new(logger_sender.clone(), gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state
+            .set_stale_block_timeout(Duration::from_millis(100))
+            .unwrap();
+        node_state.append_peers(vec![peer]).unwrap();
+        drop(node_state);
+
+        // sin bloques pendientes la tarea espera IDLE_PENDING_BLOCKS_POLL hasta su proxima revision
+        spawn_pending_blocks_task(&node_state_ref, logger_sender, Duration::from_millis(20));
+        thread::sleep(Duration::from_millis(200));
+        assert!(getdata_receiver.try_recv().is_err());
+
+        let started = Instant::now();
+        node_state_ref
+            .lock()
+            .unwrap()
+            .append_pending_block(vec![2; 32])
+            .unwrap();
+        let (_, hashes) = getdata_receiver
+            .recv_timeout(Duration::from_secs(2))
+            .unwrap();
+        assert!(started.elapsed() < IDLE_PENDING_BLOCKS_POLL);
+        assert_eq!(hashes, vec![vec![2; 32]]);
+
+        node_state_ref
+            .lock()
+            .unwrap()
+            .remove_all_peers(DisconnectReason::Terminated)
+            .unwrap();
+    }
+
     #[test]
     fn node_anwsers_handshakes() {
         let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
@@ -131,8 +261,7 @@ mod tests {
         let logger = Logger::new(&String::from("tests/test_log4.txt"), gui_sender.clone()).unwrap();
         let logger_sender = logger.get_sender();
 
-        let (_peer_action_sender, receiver) = mpsc::channel();
-        let peer_action_receiver = Arc::new(Mutex::new(receiver));
+        let (_peer_action_sender, peer_action_receiver) = peer_action_channel();
 
         let (node_action_sender, _node_action_receiver) = mpsc::channel();
 
@@ -146,17 +275,22 @@ mod tests {
             SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), 18334, 0, 0),
             123,
             70015,
+            random_nonce(),
+            DEFAULT_USER_AGENT.to_string(),
             peer_action_receiver.clone(),
             node_action_sender.clone(),
+            PrivateNetwork::default(),
         );
         let mut addresses = get_addresses("127.0.0.1".to_string(), 18334).unwrap();
         thread::sleep(Duration::from_secs(5));
 
         let peer = Peer::call(
-            addresses.next().unwrap(),
+            open_stream(addresses.next().unwrap()).unwrap(),
             SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 2), 18334, 0, 0),
             1,
             13,
+            random_nonce(),
+            DEFAULT_USER_AGENT,
             peer_action_receiver.clone(),
             logger_sender.clone(),
             node_action_sender.clone(),
@@ -177,4 +311,4714 @@ mod tests {
 
         fs::remove_file("tests/test_log4.txt").unwrap();
     }
+
+    /// Un nodo de una red privada escucha en el puerto con listener_key y otro lo llama con caller_key,
+    /// despues de que silent_connections clientes se conectan sin contestar el desafio.
+    /// Devuelve si el nodo que llamo completo el handshake, cuanto tardo y los peers y desconexiones del que escucha.
+    fn connect_private_nodes(
+        store_path: &str,
+        port: u16,
+        listener_key: [u8; 32],
+        caller_key: [u8; 32],
+        silent_connections: usize,
+    ) -> (bool, Duration, usize, Vec<DisconnectEvent>) {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (listener_state, listener_sender, listener_receiver, _listener_loop) =
+            spawn_node_with_loop(&store_path.to_string(), gui_sender);
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        let _tcp_listener = TcpListenerLoop::spawn(
+            logger_sender,
+            listener_state.clone(),
+            SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, port, 0, 0),
+            0,
+            70015,
+            random_nonce(),
+            DEFAULT_USER_AGENT.to_string(),
+            listener_receiver,
+            listener_sender,
+            PrivateNetwork::new(Some(listener_key.to_vec()), vec![]),
+        );
+        thread::sleep(Duration::from_millis(500));
+
+        let listener_address = SocketAddr::from(([127, 0, 0, 1], port));
+        let _silent_streams: Vec<TcpStream> = (0..silent_connections)
+            .map(|_| TcpStream::connect(listener_address).unwrap())
+            .collect();
+        let caller_network = PrivateNetwork::new(Some(caller_key.to_vec()), vec![listener_address]);
+        let (_peer_action_sender, peer_action_receiver) = peer_action_channel();
+        let (node_action_sender, _node_action_receiver) = mpsc::channel();
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        let started = Instant::now();
+        let caller_peer = caller_network
+            .connect(listener_address)
+            .and_then(|stream| {
+                Peer::call(
+                    stream,
+                    SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0),
+                    0,
+                    70015,
+                    random_nonce(),
+                    DEFAULT_USER_AGENT,
+                    peer_action_receiver,
+                    logger_sender,
+                    node_action_sender,
+                )
+            })
+            .ok();
+        let connected_in = started.elapsed();
+        thread::sleep(Duration::from_millis(500));
+
+        // se cuentan los peers antes de cerrar la conexion del caller, que el listener registraria como desconexion
+        let called = caller_peer.is_some();
+        let mut node_state = listener_state.lock().unwrap();
+        let peers = node_state.get_peers().len();
+        let disconnects = node_state.recent_disconnects();
+        if let Some(mut peer) = caller_peer {
+            peer.stop(DisconnectReason::Terminated);
+        }
+        node_state
+            .remove_all_peers(DisconnectReason::Terminated)
+            .unwrap();
+        drop(node_state);
+        fs::remove_dir_all(store_path).unwrap();
+        (called, connected_in, peers, disconnects)
+    }
+
+    #[test]
+    fn private_nodes_with_the_same_key_connect() {
+        let (called, _, peers, disconnects) = connect_private_nodes(
+            "tests/store_private_network_match",
+            18335,
+            [7; 32],
+            [7; 32],
+            0,
+        );
+        assert!(called);
+        assert_eq!(peers, 1);
+        assert!(disconnects.is_empty());
+    }
+
+    #[test]
+    fn private_nodes_reject_a_different_key() {
+        let (called, _, peers, disconnects) = connect_private_nodes(
+            "tests/store_private_network_mismatch",
+            18336,
+            [7; 32],
+            [8; 32],
+            0,
+        );
+        assert!(!called);
+        assert_eq!(peers, 0);
+        assert_eq!(disconnects.len(), 1);
+        assert_eq!(disconnects[0].reason, DisconnectReason::HandshakeFailed);
+    }
+
+    #[test]
+    fn private_nodes_connect_while_another_client_stays_silent() {
+        let (called, connected_in, peers, disconnects) = connect_private_nodes(
+            "tests/store_private_network_silent",
+            18339,
+            [7; 32],
+            [7; 32],
+            1,
+        );
+        assert!(called);
+        // el desafio del cliente silencioso expira a los 5 segundos, pero no frena al que llama despues
+        assert!(connected_in < Duration::from_secs(2));
+        assert_eq!(peers, 1);
+        assert!(disconnects.is_empty());
+    }
+
+    /// Comienza un maintenance_loop que solo tiene la tarea de los bloques pendientes, sin jitter, registrada como en el
+    /// nodo con el intervalo IDLE_PENDING_BLOCKS_POLL.
+    fn spawn_pending_blocks_task(
+        node_state_ref: &Arc<Mutex<NodeState>>,
+        logger_sender: mpsc::Sender<Log>,
+        poll_interval: Duration,
+    ) {
+        let mut scheduler = Scheduler::new(false);
+        scheduler.register(
+            PENDING_BLOCKS_TASK,
+            IDLE_PENDING_BLOCKS_POLL,
+            Instant::now(),
+            pending_blocks_task(node_state_ref.clone(), logger_sender.clone(), poll_interval),
+        );
+        scheduler.run_now(PENDING_BLOCKS_TASK, Instant::now());
+        let (maintenance_sender, maintenance_receiver) = mpsc::channel();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state
+            .watch_pending_blocks(maintenance_sender.clone())
+            .unwrap();
+        node_state.enable_maintenance(maintenance_sender);
+        drop(node_state);
+        maintenance_loop(
+            scheduler,
+            maintenance_receiver,
+            node_state_ref.clone(),
+            logger_sender,
+        );
+    }
+
+    fn build_test_chain(count: u32) -> Vec<BlockHeader> {
+        let mut headers: Vec<BlockHeader> = vec![];
+        let mut prev_block_hash = GENESIS.to_vec();
+
+        for i in 0..count {
+            let mut nonce: u32 = 0;
+            let header = loop {
+                let mut buffer = vec![];
+                buffer.extend(1_i32.to_le_bytes());
+                buffer.extend(&prev_block_hash);
+                buffer.extend([0; 32]);
+                buffer.extend((START_DATE_IBD + 1 + i).to_le_bytes());
+                buffer.extend(0x207fffff_u32.to_le_bytes());
+                buffer.extend(nonce.to_le_bytes());
+                if let Ok(header) = BlockHeader::parse(buffer) {
+                    break header;
+                }
+                nonce += 1;
+            };
+            prev_block_hash = header.hash().clone();
+            headers.push(header);
+        }
+
+        headers
+    }
+
+    fn assert_not_synced_with_missing_blocks(node_state: &NodeState) {
+        if node_state.is_synced() {
+            let headers = node_state.get_last_headers(usize::MAX);
+            assert!(headers.iter().all(|(_, header, _)| header.block_downloaded));
+        }
+    }
+
+    #[test]
+    fn node_state_sync_is_atomic_with_header_appends() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_sync_race");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        node_state_ref.lock().unwrap().set_regtest(true);
+
+        let chain = build_test_chain(60);
+        let (blocks_sender, blocks_receiver) = mpsc::channel::<Vec<BlockHeader>>();
+
+        let headers_node_state = node_state_ref.clone();
+        let headers_thread = thread::spawn(move || {
+            for batch in chain.chunks(5) {
+                let headers = Headers {
+                    headers: batch.to_vec(),
+                };
+                let mut node_state = headers_node_state.lock().unwrap();
+                node_state.append_headers(&headers).unwrap();
+                assert_not_synced_with_missing_blocks(&node_state);
+                drop(node_state);
+
+                thread::yield_now();
+
+                let mut node_state = headers_node_state.lock().unwrap();
+                for header in batch {
+                    node_state
+                        .append_pending_block(header.hash().clone())
+                        .unwrap();
+                }
+                drop(node_state);
+
+                blocks_sender.send(batch.to_vec()).unwrap();
+
+                let mut node_state = headers_node_state.lock().unwrap();
+                node_state.verify_sync().unwrap();
+                assert_not_synced_with_missing_blocks(&node_state);
+                drop(node_state);
+            }
+        });
+
+        let blocks_node_state = node_state_ref.clone();
+        let blocks_thread = thread::spawn(move || {
+            for batch in blocks_receiver {
+                for header in batch {
+                    let block = Block::new(header.clone(), vec![]);
+                    let mut node_state = blocks_node_state.lock().unwrap();
+                    if node_state.is_block_pending(header.hash()).unwrap() {
+                        node_state
+                            .append_block(header.hash().clone(), &block)
+                            .unwrap();
+                    }
+                    assert_not_synced_with_missing_blocks(&node_state);
+                    drop(node_state);
+                    thread::yield_now();
+                }
+            }
+        });
+
+        headers_thread.join().unwrap();
+        blocks_thread.join().unwrap();
+
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state.verify_sync().unwrap();
+        assert!(node_state.is_synced());
+        drop(node_state);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_state_stops_requesting_blocks_no_peer_has() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_notfound");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state.set_regtest(true);
+        node_state.set_max_not_found_retries(2).unwrap();
+
+        let chain = build_test_chain(3);
+        node_state
+            .append_headers(&Headers {
+                headers: chain.clone(),
+            })
+            .unwrap();
+        for header in &chain {
+            node_state
+                .append_pending_block(header.hash().clone())
+                .unwrap();
+            node_state
+                .append_block(header.hash().clone(), &Block::new(header.clone(), vec![]))
+                .unwrap();
+        }
+
+        // un bloque de una rama que quedo atras, que ningun peer tiene
+        let orphan_hash = vec![7; 32];
+        node_state
+            .append_pending_block(orphan_hash.clone())
+            .unwrap();
+        let orphan = [Inventory::new(InventoryType::Block, orphan_hash.clone())];
+        let tx = [Inventory::new(InventoryType::Tx, vec![8; 32])];
+
+        assert!(node_state.data_not_found(&tx).unwrap().is_empty());
+        for _ in 0..2 {
+            assert_eq!(node_state.data_not_found(&orphan).unwrap(), orphan.to_vec());
+            assert!(node_state.is_block_pending(&orphan_hash).unwrap());
+        }
+        assert!(node_state.data_not_found(&orphan).unwrap().is_empty());
+        assert!(!node_state.is_block_pending(&orphan_hash).unwrap());
+        assert!(node_state.data_not_found(&orphan).unwrap().is_empty());
+
+        node_state.verify_sync().unwrap();
+        assert!(node_state.is_synced());
+        drop(node_state);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_state_make_transaction_rejects_invalid_outputs() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_invalid_outputs");
+        let _ = fs::remove_dir_all(&store_path);
+        fs::create_dir(&store_path).unwrap();
+        fs::copy(
+            "tests/test_wallets.bin",
+            format!("{}/wallets.bin", store_path),
+        )
+        .unwrap();
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state
+            .change_wallet("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm".to_string())
+            .unwrap();
+
+        let zero_value = vec![
+            ("mscatccDgq7azndWHFTzvEuZuywCsUvTRu".to_string(), 1000),
+            ("mscatccDgq7azndWHFTzvEuZuywCsUvTRu".to_string(), 0),
+        ];
+        let result = node_state.make_transaction(zero_value, 10, TransactionOptions::default());
+        assert!(matches!(result, Err(CustomError::Validation(_))));
+
+        let invalid_address = vec![("0OIl".to_string(), 1000)];
+        let result =
+            node_state.make_transaction(invalid_address, 10, TransactionOptions::default());
+        assert!(matches!(result, Err(CustomError::Validation(_))));
+        drop(node_state);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    const FIXTURE_PUBKEY: &str = "mscatccDgq7azndWHFTzvEuZuywCsUvTRu";
+    const FIXTURE_PRIVKEY: &str = "cNpwEsaVLhju18SJowLtdCNaJtvMvqL4jtFLm2FXw7vZjg4sRWvH";
+    const RECEIVER_PUBKEY: &str = "mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm";
+    /// Direccion de la public key sin comprimir de FIXTURE_PRIVKEY.
+    const FIXTURE_UNCOMPRESSED_PUBKEY: &str = "mhh2JbY3QmNaX9a1faSdk5xBpbLTLd12jK";
+    /// FIXTURE_PRIVKEY en el formato WIF de las keys sin comprimir.
+    const FIXTURE_UNCOMPRESSED_PRIVKEY: &str =
+        "91sHNGY9goqgsnBieNNj4DYd6QzrbrTyajRYfXBzkyrHw69CkSf";
+
+    /// Ejecuta un pedido del RPC sobre el estado del nodo y devuelve su resultado.
+    fn rpc_execute(node_state: &mut NodeState, line: &str) -> Result<String, CustomError> {
+        let request = RpcRequest::parse(line)?;
+        Ok(RpcCall::from_request(&request)?.execute(node_state)?.result)
+    }
+
+    #[test]
+    fn node_state_looks_up_blocks_by_height() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_block_heights");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state.set_regtest(true);
+
+        let chain = build_test_chain(5);
+        node_state
+            .append_headers(&Headers {
+                headers: chain.clone(),
+            })
+            .unwrap();
+
+        assert_eq!(node_state.block_hash_at_height(0), Some(GENESIS.to_vec()));
+        assert_eq!(node_state.height_of_block(&GENESIS), Some(0));
+        for (index, header) in chain.iter().enumerate() {
+            let hash = node_state.block_hash_at_height(index + 1).unwrap();
+            assert_eq!(&hash, header.hash());
+            assert_eq!(node_state.height_of_block(&hash), Some(index + 1));
+        }
+        assert!(node_state.block_hash_at_height(6).is_none());
+        assert!(node_state.height_of_block(&[1; 32]).is_none());
+
+        let (header, confirmations) = node_state.get_header_at_height(2).unwrap();
+        assert_eq!(confirmations, 4);
+        let fields = header.fields(2, confirmations);
+        assert_eq!(fields[0], ("Hash", display_hash(chain[1].hash())));
+        assert_eq!(fields[1], ("Height", "2".to_string()));
+        assert_eq!(fields[2], ("Confirmations", "4".to_string()));
+        assert_eq!(fields[4], ("Previous block", display_hash(chain[0].hash())));
+        assert_eq!(fields[6], ("Time", (START_DATE_IBD + 2).to_string()));
+        assert_eq!(fields[7], ("Bits", "207fffff".to_string()));
+
+        let hash = display_hash(chain[1].hash());
+        let result = rpc_execute(&mut node_state, r#"{"method":"getblockhash","params":[2]}"#);
+        assert_eq!(result.unwrap(), format!("\"{hash}\""));
+        let result = rpc_execute(
+            &mut node_state,
+            &format!(r#"{{"method":"getblockheader","params":["{hash}"]}}"#),
+        );
+        assert_eq!(
+            result.unwrap(),
+            format!(
+                r#"{{"hash":"{hash}","height":2,"confirmations":4,"version":1,"previousblockhash":"{}","merkleroot":"{}","time":{},"bits":"207fffff","nonce":{}}}"#,
+                display_hash(chain[0].hash()),
+                display_hash(&chain[1].merkle_root),
+                START_DATE_IBD + 2,
+                chain[1].nonce
+            )
+        );
+        for line in [
+            r#"{"method":"getblockhash","params":[6]}"#.to_string(),
+            format!(
+                r#"{{"method":"getblockheader","params":["{}"]}}"#,
+                "11".repeat(32)
+            ),
+            format!(r#"{{"method":"getblock","params":["{hash}"]}}"#),
+        ] {
+            assert!(matches!(
+                rpc_execute(&mut node_state, &line),
+                Err(CustomError::RpcInvalidParams(_))
+            ));
+        }
+
+        // con el bloque guardado getblock devuelve su header con el height y sus transacciones
+        let block = Block::new(chain[1].clone(), vec![]);
+        node_state
+            .append_pending_block(chain[1].hash().clone())
+            .unwrap();
+        node_state
+            .append_block(chain[1].hash().clone(), &block)
+            .unwrap();
+        let result = rpc_execute(
+            &mut node_state,
+            &format!(r#"{{"method":"getblock","params":["{hash}"]}}"#),
+        )
+        .unwrap();
+        assert!(result.starts_with(&format!(r#"{{"hash":"{hash}","height":2,"#)));
+        assert!(result.ends_with(&format!(
+            r#""size":{},"nTx":0,"tx":[]}}"#,
+            block.serialize().len()
+        )));
+        let result = rpc_execute(
+            &mut node_state,
+            &format!(r#"{{"method":"getblock","params":["{hash}",0]}}"#),
+        );
+        assert_eq!(
+            result.unwrap(),
+            format!("\"{}\"", to_hex(&block.serialize()))
+        );
+        drop(node_state);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    /// Sincroniza el nodo con una cadena de 3 bloques, donde el segundo le paga a la wallet fixture un output por cada valor.
+    fn sync_with_funded_fixture_wallet(node_state: &mut NodeState, values: &[u64]) {
+        node_state
+            .append_wallet(
+                "fixture".to_string(),
+                FIXTURE_PUBKEY.to_string(),
+                FIXTURE_PRIVKEY.to_string(),
+            )
+            .unwrap();
+        node_state
+            .change_wallet(FIXTURE_PUBKEY.to_string())
+            .unwrap();
+        sync_with_funded_fixture_address(node_state, values);
+    }
+
+    fn sync_with_funded_fixture_address(node_state: &mut NodeState, values: &[u64]) {
+        let outputs: Vec<(&str, u64)> = values
+            .iter()
+            .map(|value| (FIXTURE_PUBKEY, *value))
+            .collect();
+        sync_with_funded_addresses(node_state, &outputs);
+    }
+
+    fn sync_with_funded_addresses(node_state: &mut NodeState, outputs: &[(&str, u64)]) {
+        // la cadena de prueba tiene la dificultad de regtest
+        node_state.set_regtest(true);
+        // no es una coinbase, ya que sus outputs no se podrian gastar hasta tener COINBASE_MATURITY confirmaciones
+        let funding_tx = Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output: OutPoint {
+                    hash: vec![9; 32],
+                    index: 0,
+                },
+                script_sig: vec![],
+                sequence: 0xffffffff,
+            }],
+            outputs: outputs
+                .iter()
+                .map(|(address, value)| TransactionOutput {
+                    value: *value,
+                    script_pubkey: get_script_pubkey(address.to_string()).unwrap(),
+                })
+                .collect(),
+            lock_time: 0,
+        };
+
+        let chain = build_test_chain(3);
+        node_state
+            .append_headers(&Headers {
+                headers: chain.clone(),
+            })
+            .unwrap();
+        for header in &chain {
+            node_state
+                .append_pending_block(header.hash().clone())
+                .unwrap();
+        }
+        for (index, header) in chain.iter().enumerate() {
+            let transactions = match index {
+                1 => vec![funding_tx.clone()],
+                _ => vec![],
+            };
+            let block = Block::new(header.clone(), transactions);
+            node_state
+                .append_block(header.hash().clone(), &block)
+                .unwrap();
+        }
+        assert!(node_state.is_synced());
+    }
+
+    /// Las direcciones de la public key comprimida y sin comprimir de una misma key reciben fondos por separado:
+    /// cada wallet ve el balance de su direccion sin importar el formato del WIF, y firma con la forma de su direccion.
+    #[test]
+    fn wallets_spend_from_both_pubkey_forms_of_a_key() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_pubkey_forms");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state.set_regtest(true);
+        sync_with_funded_addresses(
+            &mut node_state,
+            &[
+                (FIXTURE_PUBKEY, 30_000),
+                (FIXTURE_UNCOMPRESSED_PUBKEY, 50_000),
+            ],
+        );
+
+        let result = node_state.append_wallet(
+            "mismatch".to_string(),
+            RECEIVER_PUBKEY.to_string(),
+            FIXTURE_PRIVKEY.to_string(),
+        );
+        assert!(matches!(
+            result,
+            Err(CustomError::Validation(message))
+                if message.contains(FIXTURE_PUBKEY) && message.contains(FIXTURE_UNCOMPRESSED_PUBKEY)
+        ));
+
+        let wallets = [
+            (FIXTURE_PUBKEY, FIXTURE_UNCOMPRESSED_PRIVKEY, 30_000),
+            (FIXTURE_UNCOMPRESSED_PUBKEY, FIXTURE_PRIVKEY, 50_000),
+        ];
+        for (pubkey, privkey, balance) in wallets {
+            node_state
+                .append_wallet(pubkey.to_string(), pubkey.to_string(), privkey.to_string())
+                .unwrap();
+            node_state.change_wallet(pubkey.to_string()).unwrap();
+            assert_eq!(node_state.get_active_wallet_balance().unwrap(), balance);
+            node_state
+                .make_transaction(
+                    vec![(RECEIVER_PUBKEY.to_string(), 10_000)],
+                    1_000,
+                    TransactionOptions::default(),
+                )
+                .unwrap();
+        }
+        let compressed = node_state.get_wallets()[0].compressed_pubkey;
+        let uncompressed = node_state.get_wallets()[1].compressed_pubkey;
+        assert!(compressed && !uncompressed);
+
+        // con las UTXO generadas submit_block verifica los scripts de ambas transacciones
+        let template = node_state.create_block_template(RECEIVER_PUBKEY).unwrap();
+        assert_eq!(template.transactions.len(), 2);
+        node_state
+            .submit_block(mine_block(&template, 10_000).unwrap())
+            .unwrap();
+        for (pubkey, _, balance) in wallets {
+            node_state.change_wallet(pubkey.to_string()).unwrap();
+            assert_eq!(
+                node_state.get_active_wallet_balance().unwrap(),
+                balance - 11_000
+            );
+        }
+        drop(node_state);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    /// Una transaccion cuyos outputs suman mas de lo que entra en un u64 se descarta al recibirla,
+    /// por lo que no llega al template de un bloque.
+    #[test]
+    fn node_state_rejects_transactions_whose_values_overflow() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_value_overflow");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state.set_regtest(true);
+        sync_with_funded_fixture_wallet(&mut node_state, &[10_000]);
+        let (outpoint, _) = node_state.get_active_wallet_utxo().unwrap().remove(0);
+
+        let spend = |values: &[u64]| Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output: outpoint.clone(),
+                script_sig: vec![],
+                sequence: 0xffffffff,
+            }],
+            outputs: values
+                .iter()
+                .map(|value| TransactionOutput {
+                    value: *value,
+                    script_pubkey: get_script_pubkey(RECEIVER_PUBKEY.to_string()).unwrap(),
+                })
+                .collect(),
+            lock_time: 0,
+        };
+        assert!(!node_state.append_pending_tx(spend(&[u64::MAX, 1])).unwrap());
+        assert!(node_state.get_pending_tx_hashes().is_empty());
+        let template = node_state.create_block_template(RECEIVER_PUBKEY).unwrap();
+        assert!(template.transactions.is_empty());
+
+        let valid = spend(&[9_000]);
+        assert!(node_state.append_pending_tx(valid.clone()).unwrap());
+        let template = node_state.create_block_template(RECEIVER_PUBKEY).unwrap();
+        assert_eq!(template.fees, 1_000);
+        assert_eq!(template.transactions[0].hash(), valid.hash());
+        drop(node_state);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    /// Mina 101 bloques que le pagan a la wallet fixture, para que madure la coinbase del primero,
+    /// y gasta parte de ella en un bloque enviado por submitblock.
+    #[test]
+    fn regtest_mined_coinbase_can_be_spent_after_maturity() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_regtest_mining");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state
+            .append_wallet(
+                "fixture".to_string(),
+                FIXTURE_PUBKEY.to_string(),
+                FIXTURE_PRIVKEY.to_string(),
+            )
+            .unwrap();
+        node_state
+            .change_wallet(FIXTURE_PUBKEY.to_string())
+            .unwrap();
+        assert!(matches!(
+            node_state.create_block_template(FIXTURE_PUBKEY),
+            Err(CustomError::RegtestOnly)
+        ));
+        node_state.set_regtest(true);
+
+        for _ in 0..101 {
+            let template = node_state.create_block_template(FIXTURE_PUBKEY).unwrap();
+            let block = mine_block(&template, 10_000).unwrap();
+            node_state.submit_block(block).unwrap();
+        }
+        assert_eq!(node_state.get_tip_height(), 101);
+        assert!(node_state.is_synced());
+        let mined = 101 * block_subsidy(1);
+        assert_eq!(node_state.get_active_wallet_balance().unwrap(), mined);
+
+        let transaction = node_state
+            .make_transaction(
+                vec![(RECEIVER_PUBKEY.to_string(), 1_000_000)],
+                10_000,
+                TransactionOptions::default(),
+            )
+            .unwrap();
+        let template = node_state.create_block_template(RECEIVER_PUBKEY).unwrap();
+        assert_eq!(template.fees, 10_000);
+        assert_eq!(template.transactions.len(), 1);
+        assert_eq!(template.transactions[0].hash(), transaction.hash());
+
+        let block = mine_block(&template, 10_000).unwrap();
+        let response = RpcCall::SubmitBlock {
+            block: block.serialize(),
+        }
+        .execute(&mut node_state)
+        .unwrap();
+        assert_eq!(response.result, "null");
+        assert_eq!(response.block_header.unwrap().hash(), block.header.hash());
+        assert!(node_state.get_pending_tx_hashes().is_empty());
+        assert_eq!(
+            node_state.get_active_wallet_balance().unwrap(),
+            mined - 1_010_000
+        );
+
+        let resubmitted = node_state.submit_block(mine_block(&template, 10_000).unwrap());
+        assert!(matches!(resubmitted, Err(CustomError::InvalidBlock(_))));
+        let next = node_state.create_block_template(FIXTURE_PUBKEY).unwrap();
+        let sibling = node_state.create_block_template(RECEIVER_PUBKEY).unwrap();
+        node_state
+            .submit_block(mine_block(&next, 10_000).unwrap())
+            .unwrap();
+        let fork = node_state.submit_block(mine_block(&sibling, 10_000).unwrap());
+        assert!(matches!(fork, Err(CustomError::InvalidBlock(_))));
+        drop(node_state);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    /// Mina en regtest un bloque con una transaccion de la wallet y luego recibe una rama mas larga sin ella:
+    /// la transaccion del bloque desconectado vuelve a las pending txs.
+    #[test]
+    fn reorg_returns_disconnected_transactions_to_pending() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_reorg_pending_txs");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state
+            .append_wallet(
+                "fixture".to_string(),
+                FIXTURE_PUBKEY.to_string(),
+                FIXTURE_PRIVKEY.to_string(),
+            )
+            .unwrap();
+        node_state
+            .change_wallet(FIXTURE_PUBKEY.to_string())
+            .unwrap();
+        node_state.set_regtest(true);
+        for _ in 0..101 {
+            let template = node_state.create_block_template(FIXTURE_PUBKEY).unwrap();
+            node_state
+                .submit_block(mine_block(&template, 10_000).unwrap())
+                .unwrap();
+        }
+
+        let mut fork_template = node_state.create_block_template(RECEIVER_PUBKEY).unwrap();
+        let transaction = node_state
+            .make_transaction(
+                vec![(RECEIVER_PUBKEY.to_string(), 1_000_000)],
+                10_000,
+                TransactionOptions::default(),
+            )
+            .unwrap();
+        let template = node_state.create_block_template(FIXTURE_PUBKEY).unwrap();
+        node_state
+            .submit_block(mine_block(&template, 10_000).unwrap())
+            .unwrap();
+        assert!(node_state.get_pending_tx_hashes().is_empty());
+
+        let first_fork_block = mine_block(&fork_template, 10_000).unwrap();
+        fork_template.prev_block_hash = first_fork_block.header.hash().clone();
+        fork_template.height += 1;
+        fork_template.timestamp += 1;
+        let second_fork_block = mine_block(&fork_template, 10_000).unwrap();
+        node_state
+            .append_headers(&Headers {
+                headers: vec![first_fork_block.header, second_fork_block.header],
+            })
+            .unwrap();
+
+        assert_eq!(node_state.get_tip_height(), 103);
+        assert_eq!(node_state.get_pending_tx_hashes(), vec![transaction.hash()]);
+        drop(node_state);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    /// Una transaccion abandonada no se vuelve a pedir ni a aceptar cuando un peer la anuncia.
+    /// La transaccion se crea en otro store y se restaura como recibida hace dos horas, ya que una transaccion
+    /// anunciada recientemente no se puede abandonar.
+    #[test]
+    fn abandoned_transaction_announcements_are_ignored() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        let sender_store_path = String::from("tests/store_abandoned_announcements_sender");
+        let _ = fs::remove_dir_all(&sender_store_path);
+        let sender_ref = NodeState::new(logger_sender, gui_sender, &sender_store_path).unwrap();
+        let mut sender = sender_ref.lock().unwrap();
+        sync_with_funded_fixture_wallet(&mut sender, &[2_000_000]);
+        let transaction = sender
+            .make_transaction(
+                vec![(RECEIVER_PUBKEY.to_string(), 1_000_000)],
+                10_000,
+                TransactionOptions::default(),
+            )
+            .unwrap();
+        drop(sender);
+        fs::remove_dir_all(&sender_store_path).unwrap();
+
+        let store_path = String::from("tests/store_abandoned_announcements");
+        let _ = fs::remove_dir_all(&store_path);
+        fs::create_dir_all(&store_path).unwrap();
+        let received_at = get_current_timestamp().unwrap() - 2 * 60 * 60;
+        let mut buffer = received_at.to_le_bytes().to_vec();
+        buffer.extend(transaction.serialize());
+        fs::write(format!("{store_path}/pending_txs.bin"), buffer).unwrap();
+
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        sync_with_funded_fixture_wallet(&mut node_state, &[2_000_000]);
+        let tx_hash = transaction.hash();
+        assert!(node_state.get_pending_tx(&tx_hash).is_some());
+        assert!(!node_state.is_abandoned(&tx_hash));
+
+        node_state.abandon_transaction(&tx_hash).unwrap();
+        assert!(node_state.is_abandoned(&tx_hash));
+        let address = SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), 18333, 0, 0);
+        let announced = vec![Inventory::new(InventoryType::Tx, tx_hash.clone())];
+        assert!(node_state
+            .inventories_to_request(address, announced)
+            .is_empty());
+        drop(node_state);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_state_send_many_never_selects_the_same_utxo_twice() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_send_many");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        sync_with_funded_fixture_wallet(&mut node_state, &[10_000; 4]);
+        assert_eq!(node_state.get_active_wallet_utxo().unwrap().len(), 4);
+        drop(node_state);
+
+        // 3 envios reales y 5 dry runs concurrentes: siempre queda una UTXO libre para los dry runs
+        let outputs = vec![
+            (RECEIVER_PUBKEY.to_string(), 6_000),
+            (RECEIVER_PUBKEY.to_string(), 2_000),
+        ];
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let node_state_ref = node_state_ref.clone();
+                let mut request = SendManyRequest::new(outputs.clone(), 500);
+                request.dry_run = i >= 3;
+                thread::spawn(move || {
+                    let mut node_state = node_state_ref.lock().unwrap();
+                    node_state.send_many(request)
+                })
+            })
+            .collect();
+
+        let mut spent = HashSet::new();
+        let mut planned = 0;
+        for handle in handles {
+            match handle.join().unwrap().unwrap() {
+                SendManyResult::Sent { txid, transaction } => {
+                    assert_eq!(txid, hash_as_string(transaction.hash()));
+                    assert_eq!(transaction.outputs.len(), 3);
+                    for input in transaction.inputs {
+                        assert!(spent.insert(input.previous_output));
+                    }
+                }
+                SendManyResult::Planned(plan) => {
+                    assert_eq!(plan.inputs.len(), 1);
+                    assert_eq!(plan.change, 1_500);
+                    planned += 1;
+                }
+            }
+        }
+        assert_eq!(spent.len(), 3);
+        assert_eq!(planned, 5);
+
+        let mut node_state = node_state_ref.lock().unwrap();
+        assert_eq!(node_state.get_pending_tx_hashes().len(), 3);
+
+        let mut request = SendManyRequest::new(outputs.clone(), 500);
+        let reserved = spent.iter().next().unwrap().clone();
+        request.inputs = Some(vec![reserved.clone()]);
+        let result = node_state.send_many(request);
+        let Err(SendManyError::InvalidInputs(invalid)) = result else { panic!("expected InvalidInputs") };
+        assert_eq!(invalid, vec![reserved]);
+
+        let request = SendManyRequest::new(
+            vec![
+                (RECEIVER_PUBKEY.to_string(), 1_000),
+                (RECEIVER_PUBKEY.to_string(), 0),
+                ("0OIl".to_string(), 1_000),
+            ],
+            500,
+        );
+        let result = node_state.send_many(request);
+        let Err(SendManyError::InvalidOutputs(errors)) = result else { panic!("expected InvalidOutputs") };
+        let indexes: Vec<usize> = errors.iter().map(|error| error.index).collect();
+        assert_eq!(indexes, vec![1, 2]);
+
+        let result = node_state.send_many(SendManyRequest::new(outputs.clone(), 500));
+        let Ok(SendManyResult::Sent { transaction, .. }) = result else { panic!("expected Sent") };
+        assert!(spent.insert(transaction.inputs[0].previous_output.clone()));
+
+        let result = node_state.send_many(SendManyRequest::new(outputs.clone(), 500));
+        assert!(matches!(
+            result,
+            Err(SendManyError::Failed(CustomError::InsufficientFunds))
+        ));
+
+        // si no se pudo enviar a ningun peer, la transaccion se deshace y su input vuelve a estar disponible
+        node_state
+            .discard_unsent_transaction(&transaction.hash())
+            .unwrap();
+        let dry_run = RpcRequest::parse(&format!(
+            r#"{{"method":"sendmany","params":[{{"{RECEIVER_PUBKEY}":6000}},{{"fee":500,"dry_run":true}}]}}"#
+        ))
+        .unwrap();
+        let response = RpcCall::from_request(&dry_run)
+            .unwrap()
+            .execute(&mut node_state)
+            .unwrap();
+        assert!(response.transaction.is_none());
+        assert!(response.result.contains(&format!(
+            r#""inputs":[{{"txid":"{}","vout":{},"amount":10000}}]"#,
+            hash_as_string(transaction.inputs[0].previous_output.hash.clone()),
+            transaction.inputs[0].previous_output.index
+        )));
+        assert!(response.result.contains(r#""fee":500,"change":3500"#));
+
+        let result = node_state.send_many(SendManyRequest::new(outputs, 500));
+        let Ok(SendManyResult::Sent { transaction: resent, .. }) = result else { panic!("expected Sent") };
+        assert_eq!(
+            resent.inputs[0].previous_output,
+            transaction.inputs[0].previous_output
+        );
+        drop(node_state);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    /// Envia un pedido al RPC y devuelve la linea de la respuesta.
+    fn rpc_call(stream: &mut TcpStream, reader: &mut BufReader<TcpStream>, line: &str) -> String {
+        stream.write_all(format!("{line}\n").as_bytes()).unwrap();
+        let mut response = String::new();
+        reader.read_line(&mut response).unwrap();
+        response.trim_end().to_string()
+    }
+
+    #[test]
+    fn rpc_serves_balance_and_utxos_through_the_node_action_loop() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_rpc");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref =
+            NodeState::new(logger_sender.clone(), gui_sender.clone(), &store_path).unwrap();
+        sync_with_funded_fixture_wallet(&mut node_state_ref.lock().unwrap(), &[10_000, 20_000]);
+
+        let (node_action_sender, node_action_receiver) = mpsc::channel();
+        let (peer_action_sender, _peer_action_receiver) = peer_action_channel();
+        let loop_node_state_ref = node_state_ref.clone();
+        let loop_logger_sender = logger_sender.clone();
+        let node_action_loop = thread::spawn(move || {
+            NodeActionLoop::start(
+                gui_sender,
+                node_action_receiver,
+                peer_action_sender,
+                loop_logger_sender,
+                loop_node_state_ref,
+            )
+        });
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let rpc_address = listener.local_addr().unwrap();
+        let access = RpcAccess::new(RpcCredentials::parse("alice:secret").unwrap(), None);
+        let _rpc_thread =
+            RpcServer::spawn(listener, access, node_action_sender.clone(), logger_sender);
+
+        let mut stream = TcpStream::connect(rpc_address).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        // sin AUTH el pedido se rechaza antes de llegar al nodo
+        let response = rpc_call(
+            &mut stream,
+            &mut reader,
+            r#"{"id":1,"method":"getbalance"}"#,
+        );
+        assert!(response.contains("\"code\":-32600"));
+        assert!(response.contains("missing or invalid credentials"));
+
+        // "alice:secret" en base64
+        stream.write_all(b"AUTH Basic YWxpY2U6c2VjcmV0\n").unwrap();
+        assert_eq!(
+            rpc_call(
+                &mut stream,
+                &mut reader,
+                r#"{"id":2,"method":"getbalance"}"#
+            ),
+            r#"{"result":30000,"error":null,"id":2}"#
+        );
+
+        let response = rpc_call(
+            &mut stream,
+            &mut reader,
+            r#"{"id":"utxos","method":"listutxos","params":[]}"#,
+        );
+        assert!(response.starts_with(r#"{"result":[{"txid":""#));
+        assert!(response.ends_with(r#"}],"error":null,"id":"utxos"}"#));
+        let mut utxos: Vec<&str> = response
+            .split("\"vout\":")
+            .skip(1)
+            .map(|utxo| utxo.split('}').next().unwrap())
+            .collect();
+        utxos.sort();
+        assert_eq!(
+            utxos,
+            vec![
+                r#"0,"amount":10000,"confirmations":2,"coinbase":false"#,
+                r#"1,"amount":20000,"confirmations":2,"coinbase":false"#,
+            ]
+        );
+
+        let response = rpc_call(
+            &mut stream,
+            &mut reader,
+            r#"{"id":3,"method":"listutxos","params":[1]}"#,
+        );
+        assert!(response.contains("\"code\":-32602"));
+        assert!(response.ends_with("\"id\":3}"));
+
+        // los metodos de bloques y sendmany tambien pasan por el mismo servidor
+        let response = rpc_call(
+            &mut stream,
+            &mut reader,
+            r#"{"id":4,"method":"getblockcount"}"#,
+        );
+        let height = response
+            .strip_prefix(r#"{"result":"#)
+            .and_then(|rest| rest.strip_suffix(r#","error":null,"id":4}"#))
+            .unwrap()
+            .to_string();
+        let response = rpc_call(
+            &mut stream,
+            &mut reader,
+            &format!(r#"{{"id":5,"method":"getblockhash","params":[{height}]}}"#),
+        );
+        let hash = response
+            .strip_prefix(r#"{"result":""#)
+            .and_then(|rest| rest.strip_suffix(r#"","error":null,"id":5}"#))
+            .unwrap()
+            .to_string();
+        let response = rpc_call(
+            &mut stream,
+            &mut reader,
+            &format!(r#"{{"id":6,"method":"getblockheader","params":["{hash}"]}}"#),
+        );
+        assert!(response.contains(&format!(r#""hash":"{hash}""#)));
+        assert!(response.contains(&format!(r#""height":{height}"#)));
+        assert!(response.contains(r#""confirmations":1"#));
+
+        let response = rpc_call(
+            &mut stream,
+            &mut reader,
+            &format!(
+                r#"{{"id":7,"method":"sendmany","params":[{{"{RECEIVER_PUBKEY}":6000}},{{"fee":500,"dry_run":true}}]}}"#
+            ),
+        );
+        assert!(response.contains(r#""fee":500,"change":"#));
+        assert!(response.ends_with(r#""error":null,"id":7}"#));
+
+        node_action_sender.send(NodeAction::Terminate).unwrap();
+        node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_state_watch_only_wallet_tracks_balance_but_cannot_send() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_watch_only_wallet");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state
+            .append_watch_only_wallet("deposits".to_string(), FIXTURE_PUBKEY.to_string())
+            .unwrap();
+        let result = node_state
+            .append_watch_only_wallet("duplicated".to_string(), FIXTURE_PUBKEY.to_string());
+        assert!(matches!(result, Err(CustomError::Validation(_))));
+        node_state
+            .change_wallet(FIXTURE_PUBKEY.to_string())
+            .unwrap();
+        sync_with_funded_fixture_address(&mut node_state, &[10_000, 5_000]);
+
+        assert_eq!(node_state.get_active_wallet_balance().unwrap(), 15_000);
+        assert_eq!(node_state.get_active_wallet_utxo().unwrap().len(), 2);
+        assert_eq!(node_state.get_active_wallet().unwrap().history.len(), 1);
+
+        let outputs = vec![(RECEIVER_PUBKEY.to_string(), 6_000)];
+        let result =
+            node_state.make_transaction(outputs.clone(), 500, TransactionOptions::default());
+        assert!(matches!(result, Err(CustomError::WalletIsWatchOnly)));
+        assert!(node_state.get_pending_tx_hashes().is_empty());
+
+        // planificar sin enviar sigue permitido
+        let mut request = SendManyRequest::new(outputs, 500);
+        request.dry_run = true;
+        let result = node_state.send_many(request);
+        assert!(matches!(result, Ok(SendManyResult::Planned(_))));
+        drop(node_state);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_state_replaces_replaceable_pending_transaction_with_higher_fee() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_replace_transaction");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        sync_with_funded_fixture_wallet(&mut node_state, &[10_000, 10_000]);
+
+        // sin opciones la transaccion no se puede reemplazar
+        let final_tx = node_state
+            .make_transaction(
+                vec![(RECEIVER_PUBKEY.to_string(), 4_000)],
+                500,
+                TransactionOptions::default(),
+            )
+            .unwrap();
+        assert!(!final_tx.is_replaceable());
+        let result = node_state.replace_transaction(&final_tx.hash(), 1_000);
+        assert!(matches!(result, Err(CustomError::Validation(_))));
+
+        let options = TransactionOptions {
+            lock_time: Some(3),
+            replaceable: true,
+        };
+        let original = node_state
+            .make_transaction(vec![(RECEIVER_PUBKEY.to_string(), 6_000)], 500, options)
+            .unwrap();
+        assert!(original.is_replaceable());
+        assert_eq!(original.lock_time, 3);
+
+        let result = node_state.replace_transaction(&original.hash(), 500);
+        assert!(matches!(result, Err(CustomError::Validation(_))));
+
+        let replacement = node_state
+            .replace_transaction(&original.hash(), 1_000)
+            .unwrap();
+        let outpoints = |tx: &Transaction| {
+            tx.inputs
+                .iter()
+                .map(|input| input.previous_output.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_ne!(replacement.hash(), original.hash());
+        assert_eq!(outpoints(&replacement), outpoints(&original));
+        assert_eq!(replacement.lock_time, original.lock_time);
+        assert!(replacement.is_replaceable());
+        // el cambio puede estar en cualquier posicion, el pago se mantiene igual
+        let payment = |tx: &Transaction| {
+            tx.outputs
+                .iter()
+                .find(|output| output.value == 6_000)
+                .cloned()
+        };
+        assert!(payment(&original).is_some());
+        assert_eq!(payment(&replacement), payment(&original));
+        let value = |tx: &Transaction| tx.outputs.iter().map(|output| output.value).sum::<u64>();
+        assert_eq!(value(&original) - value(&replacement), 500);
+
+        assert!(node_state.get_pending_tx(&original.hash()).is_none());
+        assert!(node_state.get_pending_tx(&replacement.hash()).is_some());
+        assert!(node_state.get_pending_tx(&final_tx.hash()).is_some());
+        let result = node_state.replace_transaction(&original.hash(), 2_000);
+        assert!(matches!(result, Err(CustomError::TransactionNotFound)));
+        drop(node_state);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_state_rebroadcasts_own_unconfirmed_transaction_once_per_interval() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_rebroadcast");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref =
+            NodeState::new(logger_sender.clone(), gui_sender.clone(), &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        sync_with_funded_fixture_wallet(&mut node_state, &[10_000]);
+        let (rebroadcast_sender, rebroadcast_receiver) = mpsc::channel();
+        node_state.enable_rebroadcasts(rebroadcast_sender);
+
+        let tx = node_state
+            .make_transaction(
+                vec![(RECEIVER_PUBKEY.to_string(), 6_000)],
+                500,
+                TransactionOptions::default(),
+            )
+            .unwrap();
+        assert!(node_state.push_transaction(&tx).is_empty());
+
+        // la transaccion se envio con la cadena en 3, se reenvia en 6 y en 9
+        let chain = build_test_chain(9);
+        node_state
+            .append_headers(&Headers {
+                headers: chain[3..].to_vec(),
+            })
+            .unwrap();
+        for (index, header) in chain.iter().enumerate().skip(3) {
+            let block = Block::new(header.clone(), vec![]);
+            node_state
+                .append_block(header.hash().clone(), &block)
+                .unwrap();
+
+            let height = index + 1;
+            match rebroadcast_receiver.try_recv() {
+                Ok(NodeAction::RebroadcastTransactions(tx_hashes)) => {
+                    assert!(height == 6 || height == 9, "rebroadcast at height {height}");
+                    assert_eq!(tx_hashes, vec![tx.hash()]);
+                }
+                Ok(_) => panic!("expected RebroadcastTransactions"),
+                Err(_) => assert!(
+                    height != 6 && height != 9,
+                    "missing rebroadcast at {height}"
+                ),
+            }
+            assert!(rebroadcast_receiver.try_recv().is_err());
+        }
+
+        // al reiniciar se reconoce como propia y se reenvia contando desde la altura del reinicio
+        drop(node_state);
+        drop(node_state_ref);
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state.set_regtest(true);
+        node_state
+            .append_headers(&Headers { headers: vec![] })
+            .unwrap();
+        node_state.verify_sync().unwrap();
+        assert!(node_state.is_synced());
+        let (rebroadcast_sender, rebroadcast_receiver) = mpsc::channel();
+        node_state.enable_rebroadcasts(rebroadcast_sender);
+        let chain = build_test_chain(16);
+        node_state
+            .append_headers(&Headers {
+                headers: chain[9..].to_vec(),
+            })
+            .unwrap();
+        for (index, header) in chain.iter().enumerate().take(12).skip(9) {
+            let block = Block::new(header.clone(), vec![]);
+            node_state
+                .append_block(header.hash().clone(), &block)
+                .unwrap();
+            if index + 1 < 12 {
+                assert!(rebroadcast_receiver.try_recv().is_err());
+            }
+        }
+        let Ok(NodeAction::RebroadcastTransactions(tx_hashes)) = rebroadcast_receiver.try_recv() else { panic!("missing rebroadcast") };
+        assert_eq!(tx_hashes, vec![tx.hash()]);
+
+        // una vez confirmada no se vuelve a enviar
+        let block = Block::new(chain[12].clone(), vec![tx.clone()]);
+        node_state
+            .append_block(chain[12].hash().clone(), &block)
+            .unwrap();
+        for header in &chain[13..] {
+            let block = Block::new(header.clone(), vec![]);
+            node_state
+                .append_block(header.hash().clone(), &block)
+                .unwrap();
+        }
+        assert!(rebroadcast_receiver.try_recv().is_err());
+        drop(node_state);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    /// Un pago de varios outputs a una wallet que no es la activa alerta una unica vez por su total, desde el umbral,
+    /// y no vuelve a alertar al confirmarse. Un pago que llega directamente en un bloque alerta como confirmado.
+    #[test]
+    fn node_state_alerts_large_payments_once_per_transaction() {
+        let (gui_sender, gui_receiver) = glib::MainContext::channel(Priority::default());
+        let context = glib::MainContext::new();
+        let alerts = Rc::new(RefCell::new(vec![]));
+        let events = alerts.clone();
+        gui_receiver.attach(Some(&context), move |event| {
+            if let GUIEvents::LargePaymentAlert {
+                wallet,
+                value,
+                confirmed,
+                ..
+            } = event
+            {
+                events.borrow_mut().push((wallet, value, confirmed));
+            }
+            glib::Continue(true)
+        });
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_large_payments");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        sync_with_funded_fixture_wallet(&mut node_state, &[100_000, 100_000]);
+        node_state
+            .append_watch_only_wallet("treasury".to_string(), RECEIVER_PUBKEY.to_string())
+            .unwrap();
+        node_state.set_alert_threshold(7_000);
+
+        // los dos outputs suman exactamente el umbral
+        let large = node_state
+            .make_transaction(
+                vec![
+                    (RECEIVER_PUBKEY.to_string(), 3_000),
+                    (RECEIVER_PUBKEY.to_string(), 4_000),
+                ],
+                500,
+                TransactionOptions::default(),
+            )
+            .unwrap();
+        let small = node_state
+            .make_transaction(
+                vec![(RECEIVER_PUBKEY.to_string(), 6_999)],
+                500,
+                TransactionOptions::default(),
+            )
+            .unwrap();
+
+        let chain = build_test_chain(5);
+        node_state
+            .append_headers(&Headers {
+                headers: chain[3..].to_vec(),
+            })
+            .unwrap();
+        let block = Block::new(chain[3].clone(), vec![large, small]);
+        node_state
+            .append_block(chain[3].hash().clone(), &block)
+            .unwrap();
+
+        let direct = Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output: OutPoint {
+                    hash: vec![8; 32],
+                    index: 0,
+                },
+                script_sig: vec![],
+                sequence: 0xffffffff,
+            }],
+            outputs: vec![TransactionOutput {
+                value: 9_000,
+                script_pubkey: get_script_pubkey(RECEIVER_PUBKEY.to_string()).unwrap(),
+            }],
+            lock_time: 0,
+        };
+        let block = Block::new(chain[4].clone(), vec![direct]);
+        node_state
+            .append_block(chain[4].hash().clone(), &block)
+            .unwrap();
+        drop(node_state);
+
+        wait_for_gui_events(&context, &alerts, 2);
+        while context.iteration(false) {}
+        assert_eq!(
+            *alerts.borrow(),
+            vec![
+                ("treasury".to_string(), 7_000, false),
+                ("treasury".to_string(), 9_000, true),
+            ]
+        );
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_state_drops_own_transaction_rejected_by_a_peer() {
+        let (gui_sender, gui_receiver) = glib::MainContext::channel(Priority::default());
+        let context = glib::MainContext::new();
+        let rejections = Rc::new(RefCell::new(vec![]));
+        let events = rejections.clone();
+        gui_receiver.attach(Some(&context), move |event| {
+            if let GUIEvents::TransactionRejected(reason) = event {
+                events.borrow_mut().push(reason);
+            }
+            glib::Continue(true)
+        });
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_tx_rejected");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        sync_with_funded_fixture_wallet(&mut node_state, &[10_000]);
+
+        let tx = node_state
+            .make_transaction(
+                vec![(RECEIVER_PUBKEY.to_string(), 6_000)],
+                500,
+                TransactionOptions::default(),
+            )
+            .unwrap();
+        let reject = Reject {
+            message: "tx".to_string(),
+            ccode: REJECT_INSUFFICIENT_FEE,
+            reason: "min relay fee not met".to_string(),
+            data: Some(tx.hash()),
+        };
+
+        let (node_action_sender, _node_action_receiver) = mpsc::channel();
+        let peer = connect_synthetic_peer(0, node_action_sender, keep_connection_open);
+        let peer_address = peer.address;
+        node_state.append_peers(vec![peer]).unwrap();
+
+        // mientras no se envio a los peers no es una transaccion que creamos
+        assert!(!node_state
+            .transaction_rejected(peer_address, &reject)
+            .unwrap());
+        assert!(node_state.get_pending_tx(&tx.hash()).is_some());
+
+        assert!(node_state.push_transaction(&tx).is_empty());
+        assert_eq!(node_state.get_active_wallet_pending_txs().unwrap().len(), 1);
+        // una transaccion de un peer que gasta el cambio depende de la rechazada
+        let child = Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output: OutPoint {
+                    hash: tx.hash(),
+                    index: 1,
+                },
+                script_sig: vec![],
+                sequence: u32::MAX,
+            }],
+            outputs: vec![TransactionOutput {
+                value: 1_000,
+                script_pubkey: vec![],
+            }],
+            lock_time: 0,
+        };
+        assert!(node_state.append_pending_tx(child.clone()).unwrap());
+
+        // no cuentan los rechazos de un peer al que no se la enviamos ni los de duplicada
+        let other_peer = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 18444, 0, 0);
+        assert!(!node_state
+            .transaction_rejected(other_peer, &reject)
+            .unwrap());
+        let duplicate = Reject {
+            ccode: REJECT_DUPLICATE,
+            ..reject.clone()
+        };
+        assert!(!node_state
+            .transaction_rejected(peer_address, &duplicate)
+            .unwrap());
+        assert!(node_state.get_pending_tx(&tx.hash()).is_some());
+
+        assert!(node_state
+            .transaction_rejected(peer_address, &reject)
+            .unwrap());
+        assert!(node_state.get_pending_tx(&tx.hash()).is_none());
+        assert!(node_state.get_pending_tx(&child.hash()).is_none());
+        assert!(node_state
+            .get_active_wallet_pending_txs()
+            .unwrap()
+            .is_empty());
+        assert!(!node_state
+            .transaction_rejected(peer_address, &reject)
+            .unwrap());
+
+        // los inputs quedan libres para una nueva transaccion
+        assert!(node_state
+            .make_transaction(
+                vec![(RECEIVER_PUBKEY.to_string(), 6_000)],
+                1_000,
+                TransactionOptions::default(),
+            )
+            .is_ok());
+        node_state
+            .remove_all_peers(DisconnectReason::Terminated)
+            .unwrap();
+        drop(node_state);
+
+        wait_for_gui_events(&context, &rejections, 1);
+        assert_eq!(*rejections.borrow(), vec![reject.describe()]);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn config_reload_applies_safe_settings_while_running() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let store_path = String::from("tests/store_config_reload");
+        let _ = fs::remove_dir_all(&store_path);
+        fs::create_dir(&store_path).unwrap();
+        let log_path = format!("{}/log.txt", store_path);
+        let logger = Logger::new(&log_path, gui_sender.clone()).unwrap();
+        let logger_sender = logger.get_sender();
+
+        let node_state_ref =
+            NodeState::new(logger_sender.clone(), gui_sender.clone(), &store_path).unwrap();
+        let (node_action_sender, node_action_receiver) = mpsc::channel();
+        let (peer_action_sender, _peer_action_receiver) = peer_action_channel();
+        let loop_node_state_ref = node_state_ref.clone();
+        let loop_logger_sender = logger_sender.clone();
+        let node_action_loop = thread::spawn(move || {
+            NodeActionLoop::start(
+                gui_sender,
+                node_action_receiver,
+                peer_action_sender,
+                loop_logger_sender,
+                loop_node_state_ref,
+            )
+        });
+
+        let config_path = format!("{}/node.conf", store_path);
+        let base = "SEED=seed.test\nPROTOCOL_VERSION=70015\nPORT=18333\n";
+        fs::write(&config_path, base).unwrap();
+        let reloader = ConfigReloader::new(
+            &config_path,
+            Config::from_file(&config_path).unwrap(),
+            node_action_sender.clone(),
+            logger_sender.clone(),
+        );
+        logger_sender
+            .send(Log::Message("logged before the reload".to_string()))
+            .unwrap();
+
+        fs::write(
+            &config_path,
+            format!("{base}LOG_LEVEL=error\nMIN_SPEND_CONFIRMATIONS=3\nPORT=18444\n"),
+        )
+        .unwrap();
+        let delta = reloader.reload().unwrap();
+        assert_eq!(
+            delta.applied(),
+            vec!["LOG_LEVEL", "MIN_SPEND_CONFIRMATIONS"]
+        );
+        assert_eq!(delta.restart_required, vec!["PORT"]);
+
+        let mut applied = false;
+        for _ in 0..50 {
+            applied = node_state_ref.lock().unwrap().get_min_spend_confirmations() == 3;
+            if applied {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(applied);
+
+        // el nivel del logger se cambia despues de aplicar el resto, se espera a que el loop termine de procesar el cambio
+        node_action_sender.send(NodeAction::Terminate).unwrap();
+        node_action_loop.join().unwrap();
+
+        // con LOG_LEVEL=error el logger descarta los mensajes pero sigue registrando los errores
+        logger_sender
+            .send(Log::Message("logged after the reload".to_string()))
+            .unwrap();
+        logger_sender
+            .send(Log::Error(CustomError::InvalidFee))
+            .unwrap();
+        logger_sender.send(Log::Terminate).unwrap();
+        logger.thread.join().unwrap().unwrap();
+
+        let log = fs::read_to_string(&log_path).unwrap();
+        assert!(log.contains("logged before the reload"));
+        assert!(log.contains("need a restart to take effect"));
+        assert!(
+            log.contains("Config reloaded, applied changes to LOG_LEVEL, MIN_SPEND_CONFIRMATIONS")
+        );
+        assert!(!log.contains("logged after the reload"));
+        assert!(log.contains("[ERROR] Error: invalid fee"));
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_state_change_wallet_only_notifies_on_success() {
+        let (gui_sender, gui_receiver) = glib::MainContext::channel(Priority::default());
+        let context = glib::MainContext::new();
+        let wallet_changed_events = Rc::new(RefCell::new(0));
+        let events_counter = wallet_changed_events.clone();
+        gui_receiver.attach(Some(&context), move |event| {
+            if let GUIEvents::WalletChanged = event {
+                *events_counter.borrow_mut() += 1;
+            }
+            glib::Continue(true)
+        });
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_change_wallet");
+        let _ = fs::remove_dir_all(&store_path);
+        fs::create_dir(&store_path).unwrap();
+        fs::copy(
+            "tests/test_wallets.bin",
+            format!("{}/wallets.bin", store_path),
+        )
+        .unwrap();
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+
+        let result = node_state.change_wallet("unknown pubkey".to_string());
+        assert!(matches!(result, Err(CustomError::WalletNotFound)));
+        while context.iteration(false) {}
+        assert_eq!(*wallet_changed_events.borrow(), 0);
+        assert!(node_state.get_active_wallet().is_none());
+
+        node_state
+            .change_wallet("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm".to_string())
+            .unwrap();
+        context.iteration(false);
+        assert_eq!(*wallet_changed_events.borrow(), 1);
+        assert!(node_state.get_active_wallet().is_some());
+        drop(node_state);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_state_removing_the_active_wallet_notifies_wallet_changed() {
+        let (gui_sender, gui_receiver) = glib::MainContext::channel(Priority::default());
+        let context = glib::MainContext::new();
+        let events = Rc::new(RefCell::new(vec![]));
+        let received = events.clone();
+        gui_receiver.attach(Some(&context), move |event| {
+            match event {
+                GUIEvents::WalletChanged => received.borrow_mut().push("changed"),
+                GUIEvents::WalletListChanged => received.borrow_mut().push("list"),
+                _ => {}
+            }
+            glib::Continue(true)
+        });
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_remove_wallet");
+        let _ = fs::remove_dir_all(&store_path);
+        fs::create_dir(&store_path).unwrap();
+        fs::copy(
+            "tests/test_wallets.bin",
+            format!("{}/wallets.bin", store_path),
+        )
+        .unwrap();
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state
+            .append_watch_only_wallet(
+                String::from("exchange"),
+                String::from("mscatccDgq7azndWHFTzvEuZuywCsUvTRu"),
+            )
+            .unwrap();
+        node_state
+            .change_wallet("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm".to_string())
+            .unwrap();
+        while context.iteration(false) {}
+        events.borrow_mut().clear();
+
+        node_state
+            .rename_wallet("mscatccDgq7azndWHFTzvEuZuywCsUvTRu", "deposits")
+            .unwrap();
+        while context.iteration(false) {}
+        assert_eq!(*events.borrow(), vec!["list"]);
+
+        // quitar una wallet que no es la activa no cambia la wallet activa
+        node_state
+            .remove_wallet("mscatccDgq7azndWHFTzvEuZuywCsUvTRu")
+            .unwrap();
+        while context.iteration(false) {}
+        assert_eq!(*events.borrow(), vec!["list", "list"]);
+        assert!(node_state.get_active_wallet().is_some());
+
+        node_state
+            .remove_wallet("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm")
+            .unwrap();
+        while context.iteration(false) {}
+        assert_eq!(*events.borrow(), vec!["list", "list", "list", "changed"]);
+        assert!(node_state.get_active_wallet().is_none());
+        assert!(node_state.get_wallets().is_empty());
+        drop(node_state);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_state_notifies_wallets_updated_for_non_active_wallet() {
+        let (gui_sender, gui_receiver) = glib::MainContext::channel(Priority::default());
+        let context = glib::MainContext::new();
+        let affected_events: Rc<RefCell<Vec<Vec<String>>>> = Rc::new(RefCell::new(vec![]));
+        let events = affected_events.clone();
+        gui_receiver.attach(Some(&context), move |event| {
+            if let GUIEvents::WalletsUpdated { affected } = event {
+                events.borrow_mut().push(affected);
+            }
+            glib::Continue(true)
+        });
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_wallets_updated");
+        let _ = fs::remove_dir_all(&store_path);
+        fs::create_dir(&store_path).unwrap();
+        fs::copy(
+            "tests/test_wallets.bin",
+            format!("{}/wallets.bin", store_path),
+        )
+        .unwrap();
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        assert!(node_state.get_active_wallet().is_none());
+
+        let block = Block {
+            header: BlockHeader {
+                version: 536887296,
+                prev_block_hash: vec![],
+                merkle_root: vec![],
+                timestamp: 1686626483,
+                bits: 421617023,
+                nonce: 3878826733,
+                hash: vec![],
+                block_downloaded: true,
+                broadcasted: true,
+            },
+            transactions: vec![Transaction {
+                version: 1,
+                inputs: vec![TransactionInput {
+                    previous_output: OutPoint {
+                        hash: vec![],
+                        index: 4294967295,
+                    },
+                    script_sig: vec![],
+                    sequence: 4294967295,
+                }],
+                outputs: vec![TransactionOutput {
+                    value: 2366975,
+                    script_pubkey: vec![
+                        118, 169, 20, 27, 40, 219, 33, 69, 20, 4, 108, 105, 234, 87, 71, 50, 50,
+                        154, 22, 16, 220, 64, 85, 136, 172,
+                    ],
+                }],
+                lock_time: 0,
+            }],
+        };
+
+        node_state.update_wallets(&block).unwrap();
+        drop(node_state);
+        while context.iteration(false) {}
+
+        assert_eq!(
+            *affected_events.borrow(),
+            vec![vec!["mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm".to_string()]]
+        );
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_state_imports_watch_only_address_list() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_import_addresses");
+        let _ = fs::remove_dir_all(&store_path);
+        fs::create_dir(&store_path).unwrap();
+        fs::copy(
+            "tests/test_wallets.bin",
+            format!("{}/wallets.bin", store_path),
+        )
+        .unwrap();
+        let list_path = format!("{}/addresses.txt", store_path);
+        fs::write(
+            &list_path,
+            "mscatccDgq7azndWHFTzvEuZuywCsUvTRu,deposit 1
+mxz3drZtkg4R3u1RDL7zRPLsizvhmGWfr3,deposit 2
+mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun
+1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa,mainnet
+mscatccDgq7azndWHFTzvEuZuywCsUvTRv,typo
+mxz3drZtkg4R3u1RDL7zRPLsizvhmGWfr3,repeated
+mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm,already watched
+",
+        )
+        .unwrap();
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+
+        let mut progress = vec![];
+        let summary = import_address_file(&list_path, &node_state_ref, |validated, total| {
+            progress.push((validated, total))
+        })
+        .unwrap();
+
+        assert_eq!(summary.imported.len(), 3);
+        let skipped_lines: Vec<usize> =
+            summary.skipped.iter().map(|skipped| skipped.line).collect();
+        assert_eq!(skipped_lines, vec![4, 5, 6, 7]);
+        assert_eq!(progress.last(), Some(&(7, 7)));
+
+        let mut node_state = node_state_ref.lock().unwrap();
+        assert_eq!(node_state.get_wallets().len(), 4);
+        assert!(node_state.get_wallets()[1..]
+            .iter()
+            .all(|wallet| wallet.is_watch_only()));
+
+        let block = build_test_block(vec![Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output: OutPoint {
+                    hash: vec![],
+                    index: 4294967295,
+                },
+                script_sig: vec![],
+                sequence: 4294967295,
+            }],
+            outputs: vec![TransactionOutput {
+                value: 5000,
+                script_pubkey: vec![
+                    118, 169, 20, 132, 178, 35, 78, 47, 170, 110, 26, 117, 29, 126, 82, 132, 235,
+                    16, 204, 230, 247, 81, 246, 136, 172,
+                ],
+            }],
+            lock_time: 0,
+        }]);
+        node_state.update_wallets(&block).unwrap();
+        let deposit = node_state
+            .get_wallets()
+            .iter()
+            .find(|wallet| wallet.pubkey == "mscatccDgq7azndWHFTzvEuZuywCsUvTRu")
+            .unwrap();
+        assert_eq!(deposit.history.len(), 1);
+        assert_eq!(deposit.history[0].value, 5000);
+        drop(node_state);
+
+        let summary = import_address_file(&list_path, &node_state_ref, |_, _| {}).unwrap();
+        assert_eq!(summary.imported.len(), 0);
+        assert_eq!(summary.skipped.len(), 7);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    fn read_message(stream: &mut TcpStream) -> (String, Vec<u8>) {
+        let header = MessageHeader::read(stream).unwrap();
+        let mut payload = vec![0; header.payload_size as usize];
+        stream.read_exact(&mut payload).unwrap();
+        (header.command, payload)
+    }
+
+    /// Atiende el handshake de un peer sintetico y devuelve el mensaje sendcmpct que envia el nodo.
+    /// Tambien consume el getaddr que el nodo envia al final del handshake.
+    fn synthetic_peer_handshake(stream: &mut TcpStream) -> SendCompact {
+        synthetic_peer_handshake_at(stream, 0)
+    }
+
+    fn synthetic_peer_handshake_at(stream: &mut TcpStream, start_height: i32) -> SendCompact {
+        synthetic_peer_handshake_with_version(stream, start_height, 70015).unwrap()
+    }
+
+    /// Atiende el handshake de un peer sintetico con esa version del protocolo y devuelve el mensaje sendcmpct
+    /// que envia el nodo, que no lo envia si la version no soporta compact blocks.
+    fn synthetic_peer_handshake_with_version(
+        stream: &mut TcpStream,
+        start_height: i32,
+        protocol_version: i32,
+    ) -> Option<SendCompact> {
+        synthetic_peer_handshake_with_services(
+            stream,
+            start_height,
+            protocol_version,
+            SYNTHETIC_PEER_SERVICES,
+        )
+    }
+
+    /// Servicios que anuncian los peers sinteticos salvo que se indiquen otros.
+    const SYNTHETIC_PEER_SERVICES: u64 = NODE_NETWORK | NODE_BLOOM;
+
+    /// Como synthetic_peer_handshake_with_version, pero el peer sintetico anuncia esos servicios.
+    fn synthetic_peer_handshake_with_services(
+        stream: &mut TcpStream,
+        start_height: i32,
+        protocol_version: i32,
+        services: u64,
+    ) -> Option<SendCompact> {
+        let (command, _) = read_message(stream);
+        assert_eq!(command, "version");
+        let address = SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), 18333, 0, 0);
+        let mut version = Version::new(address, address, protocol_version, services, 0);
+        version.start_height = start_height;
+        version.send(stream).unwrap();
+        VerAck::new().send(stream).unwrap();
+
+        let (command, _) = read_message(stream);
+        assert_eq!(command, "verack");
+        let (command, _) = read_message(stream);
+        assert_eq!(command, "sendheaders");
+        let mut send_compact = None;
+        if protocol_version >= COMPACT_BLOCKS_PROTOCOL_VERSION {
+            let (command, payload) = read_message(stream);
+            assert_eq!(command, "sendcmpct");
+            send_compact = Some(SendCompact::parse(payload).unwrap());
+        }
+        let (command, _) = read_message(stream);
+        assert_eq!(command, "getaddr");
+        send_compact
+    }
+
+    /// Envia un mensaje con ese comando y payload, sin parsearlo.
+    fn send_raw_message(stream: &mut TcpStream, command: &str, payload: &[u8]) {
+        let header = MessageHeader::from_payload(command.to_string(), payload);
+        stream.write_all(&header.serialize()).unwrap();
+        stream.write_all(payload).unwrap();
+    }
+
+    fn test_transaction(lock_time: u32) -> Transaction {
+        Transaction {
+            version: 1,
+            inputs: vec![TransactionInput {
+                previous_output: OutPoint {
+                    hash: vec![lock_time as u8; 32],
+                    index: 0,
+                },
+                script_sig: vec![],
+                sequence: 4294967295,
+            }],
+            outputs: vec![TransactionOutput {
+                value: 1000,
+                script_pubkey: vec![],
+            }],
+            lock_time,
+        }
+    }
+
+    fn build_test_block(transactions: Vec<Transaction>) -> Block {
+        build_test_block_at(transactions, START_DATE_IBD + 1)
+    }
+
+    fn build_test_block_at(transactions: Vec<Transaction>, timestamp: u32) -> Block {
+        let mut hashes: Vec<Vec<u8>> = transactions.iter().map(Transaction::hash).collect();
+        while hashes.len() > 1 {
+            hashes = hashes
+                .chunks(2)
+                .map(|pair| {
+                    let mut buffer = pair[0].clone();
+                    buffer.extend(pair.get(1).unwrap_or(&pair[0]));
+                    sha256d::Hash::hash(&buffer).to_byte_array().to_vec()
+                })
+                .collect();
+        }
+
+        let mut nonce: u32 = 0;
+        let header = loop {
+            let mut buffer = vec![];
+            buffer.extend(1_i32.to_le_bytes());
+            buffer.extend(GENESIS);
+            buffer.extend(&hashes[0]);
+            buffer.extend(timestamp.to_le_bytes());
+            buffer.extend(0x207fffff_u32.to_le_bytes());
+            buffer.extend(nonce.to_le_bytes());
+            if let Ok(header) = BlockHeader::parse(buffer) {
+                break header;
+            }
+            nonce += 1;
+        };
+
+        Block::new(header, transactions)
+    }
+
+    #[test]
+    fn peer_negotiates_compact_blocks_after_handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let listener_address = listener.local_addr().unwrap();
+        let synthetic_peer = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            synthetic_peer_handshake(&mut stream)
+        });
+
+        let (_peer_action_sender, receiver) = peer_action_channel();
+        let (node_action_sender, _node_action_receiver) = mpsc::channel();
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        let _peer = Peer::call(
+            open_stream(listener_address).unwrap(),
+            SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), 18333, 0, 0),
+            0,
+            70015,
+            random_nonce(),
+            DEFAULT_USER_AGENT,
+            receiver,
+            logger_sender,
+            node_action_sender,
+        )
+        .unwrap();
+
+        let send_compact = synthetic_peer.join().unwrap();
+        assert!(!send_compact.announce);
+        assert_eq!(send_compact.version, 1);
+    }
+
+    /// Nodo conectado a un peer sintetico, con un NodeActionLoop corriendo.
+    /// synthetic_peer devuelve lo que el test necesite verificar de los mensajes que recibio del nodo.
+    /// El receiver de la interfaz grafica se mantiene para que el nodo pueda enviarle eventos.
+    /// Al terminar hay que esperar a node_action_loop antes de borrar el store, porque al cerrar guarda la sesion de descarga.
+    struct SyntheticPeerTest<R> {
+        node_state_ref: Arc<Mutex<NodeState>>,
+        _gui_receiver: glib::Receiver<GUIEvents>,
+        node_action_sender: mpsc::Sender<NodeAction>,
+        peer_action_receiver: PeerActionReceiver,
+        synthetic_peer: thread::JoinHandle<R>,
+        node_action_loop: thread::JoinHandle<()>,
+    }
+
+    fn start_synthetic_peer_test<R: Send + 'static>(
+        store_path: &str,
+        block: &Block,
+        known_txs: Vec<Transaction>,
+        synthetic_peer: impl FnOnce(TcpStream) -> R + Send + 'static,
+    ) -> SyntheticPeerTest<R> {
+        let (gui_sender, gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let _ = fs::remove_dir_all(store_path);
+        let node_state_ref = NodeState::new(
+            logger_sender.clone(),
+            gui_sender.clone(),
+            &store_path.to_string(),
+        )
+        .unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state.set_regtest(true);
+        node_state
+            .append_headers(&Headers {
+                headers: vec![block.header.clone()],
+            })
+            .unwrap();
+        for tx in known_txs {
+            node_state.append_pending_tx(tx).unwrap();
+        }
+        drop(node_state);
+
+        attach_synthetic_peer(
+            node_state_ref,
+            gui_sender,
+            gui_receiver,
+            logger_sender,
+            synthetic_peer,
+        )
+    }
+
+    /// Conecta un peer sintetico a un NodeState ya inicializado y arranca el NodeActionLoop.
+    fn attach_synthetic_peer<R: Send + 'static>(
+        node_state_ref: Arc<Mutex<NodeState>>,
+        gui_sender: glib::Sender<GUIEvents>,
+        gui_receiver: glib::Receiver<GUIEvents>,
+        logger_sender: mpsc::Sender<Log>,
+        synthetic_peer: impl FnOnce(TcpStream) -> R + Send + 'static,
+    ) -> SyntheticPeerTest<R> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let listener_address = listener.local_addr().unwrap();
+        let synthetic_peer = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            synthetic_peer_handshake(&mut stream);
+            synthetic_peer(stream)
+        });
+
+        let (_unused_peer_action_sender, receiver) = peer_action_channel();
+        let (node_action_sender, node_action_receiver) = mpsc::channel();
+        let peer = Peer::call(
+            open_stream(listener_address).unwrap(),
+            SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), 18333, 0, 0),
+            0,
+            70015,
+            random_nonce(),
+            DEFAULT_USER_AGENT,
+            receiver,
+            logger_sender.clone(),
+            node_action_sender.clone(),
+        )
+        .unwrap();
+        node_state_ref
+            .lock()
+            .unwrap()
+            .append_peers(vec![peer])
+            .unwrap();
+
+        let (peer_action_sender, peer_action_receiver) = peer_action_channel();
+        let loop_node_state_ref = node_state_ref.clone();
+        let node_action_loop = thread::spawn(move || {
+            NodeActionLoop::start(
+                gui_sender,
+                node_action_receiver,
+                peer_action_sender,
+                logger_sender,
+                loop_node_state_ref,
+            )
+        });
+
+        SyntheticPeerTest {
+            node_state_ref,
+            _gui_receiver: gui_receiver,
+            node_action_sender,
+            peer_action_receiver,
+            synthetic_peer,
+            node_action_loop,
+        }
+    }
+
+    #[test]
+    fn node_reconstructs_compact_block_requesting_missing_txs() {
+        let transactions = vec![
+            test_transaction(0),
+            test_transaction(1),
+            test_transaction(2),
+        ];
+        let block = build_test_block(transactions.clone());
+        let block_hash = block.header.hash().clone();
+
+        let compact_block = CompactBlock::from_block(&block, 5, &[0]);
+        let missing_tx = transactions[2].clone();
+        let store_path = String::from("tests/store_compact_block");
+        let test = start_synthetic_peer_test(
+            &store_path,
+            &block,
+            vec![transactions[1].clone()],
+            move |mut stream| {
+                compact_block.send(&mut stream).unwrap();
+                let (command, payload) = read_message(&mut stream);
+                assert_eq!(command, "getblocktxn");
+                let get_block_txn = GetBlockTxn::parse(payload).unwrap();
+                BlockTxn::new(get_block_txn.block_hash, vec![missing_tx])
+                    .send(&mut stream)
+                    .unwrap();
+                get_block_txn.indexes
+            },
+        );
+
+        assert_eq!(test.synthetic_peer.join().unwrap(), vec![2]);
+
+        let mut block_received = false;
+        for _ in 0..50 {
+            let node_state = test.node_state_ref.lock().unwrap();
+            if !node_state.is_block_pending(&block_hash).unwrap() {
+                block_received = true;
+                break;
+            }
+            drop(node_state);
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert!(block_received);
+
+        let node_state = test.node_state_ref.lock().unwrap();
+        let stored_block = node_state.get_block(&block_hash).unwrap();
+        assert_eq!(stored_block.transactions.len(), 3);
+        drop(node_state);
+
+        test.node_action_sender.send(NodeAction::Terminate).unwrap();
+        test.node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_requests_full_block_when_compact_block_cannot_be_reconstructed() {
+        let transactions = vec![
+            test_transaction(0),
+            test_transaction(1),
+            test_transaction(2),
+        ];
+        let block = build_test_block(transactions.clone());
+        let block_hash = block.header.hash().clone();
+
+        let compact_block = CompactBlock::from_block(&block, 5, &[0]);
+        let store_path = String::from("tests/store_compact_block_fallback");
+        let test = start_synthetic_peer_test(
+            &store_path,
+            &block,
+            vec![transactions[1].clone()],
+            move |mut stream| {
+                compact_block.send(&mut stream).unwrap();
+                let (command, payload) = read_message(&mut stream);
+                assert_eq!(command, "getblocktxn");
+                let get_block_txn = GetBlockTxn::parse(payload).unwrap();
+                BlockTxn::new(get_block_txn.block_hash, vec![test_transaction(3)])
+                    .send(&mut stream)
+                    .unwrap();
+                get_block_txn.indexes
+            },
+        );
+
+        assert_eq!(test.synthetic_peer.join().unwrap(), vec![2]);
+
+        let message = test
+            .peer_action_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .unwrap();
+        let PeerAction::GetData(inventories) = message else { panic!("expected GetData") };
+        assert_eq!(inventories.len(), 1);
+        assert_eq!(inventories[0].inventory_type, InventoryType::Block);
+        assert_eq!(inventories[0].hash, block_hash);
+        assert!(test
+            .node_state_ref
+            .lock()
+            .unwrap()
+            .is_block_pending(&block_hash)
+            .unwrap());
+
+        test.node_action_sender.send(NodeAction::Terminate).unwrap();
+        test.node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_shows_provisional_history_from_merkleblocks_during_ibd() {
+        let address = String::from("mscatccDgq7azndWHFTzvEuZuywCsUvTRu");
+        let mut wallet_tx = test_transaction(1);
+        wallet_tx.outputs[0].script_pubkey = get_script_pubkey(address.clone()).unwrap();
+        let block = build_test_block(vec![test_transaction(0), wallet_tx.clone()]);
+        let block_hash = block.header.hash().clone();
+
+        let merkle_block = MerkleBlock::new(&block, &[false, true]);
+        let served_tx = wallet_tx.clone();
+        let store_path = String::from("tests/store_provisional_history");
+        let test = start_synthetic_peer_test(&store_path, &block, vec![], move |mut stream| {
+            let (command, payload) = read_message(&mut stream);
+            assert_eq!(command, "filterload");
+            let filter_load = FilterLoad::parse(payload).unwrap();
+            let (command, payload) = read_message(&mut stream);
+            assert_eq!(command, "getdata");
+            let getdata = GetData::parse(payload).unwrap();
+            let (command, _) = read_message(&mut stream);
+            assert_eq!(command, "filterclear");
+
+            merkle_block.send(&mut stream).unwrap();
+            served_tx.send(&mut stream).unwrap();
+            (filter_load, getdata.get_inventories().clone())
+        });
+
+        // la wallet se importa a mitad del IBD: headers sincronizados y el bloque sin descargar
+        let mut node_state = test.node_state_ref.lock().unwrap();
+        assert!(node_state.is_block_pending(&block_hash).unwrap());
+        assert_eq!(node_state.wallet_history_progress().unwrap(), Some(0));
+        node_state
+            .import_watch_only_wallets(vec![Wallet::watch_only(
+                String::from("imported"),
+                address.clone(),
+            )
+            .unwrap()])
+            .unwrap();
+        drop(node_state);
+
+        let (filter_load, inventories) = test.synthetic_peer.join().unwrap();
+        let pubkey_hash = get_pubkey_hash(address.clone()).unwrap();
+        assert!(filter_load.filter.contains(&pubkey_hash));
+        assert_eq!(
+            inventories,
+            vec![Inventory::new(
+                InventoryType::FilteredBlock,
+                block_hash.clone()
+            )]
+        );
+
+        let history = |node_state_ref: &Arc<Mutex<NodeState>>| {
+            node_state_ref.lock().unwrap().get_wallets()[0].get_history()
+        };
+        let mut provisional = vec![];
+        for _ in 0..50 {
+            provisional = history(&test.node_state_ref);
+            if !provisional.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(provisional.len(), 1);
+        assert!(provisional[0].provisional);
+        assert_eq!(provisional[0].tx_hash, wallet_tx.hash());
+        assert_eq!(provisional[0].block_hash, Some(block_hash.clone()));
+        assert!(test
+            .node_state_ref
+            .lock()
+            .unwrap()
+            .is_block_pending(&block_hash)
+            .unwrap());
+
+        // el bloque completo confirma el movimiento provisional
+        test.node_action_sender
+            .send(NodeAction::Block((block_hash.clone(), Arc::new(block))))
+            .unwrap();
+        let mut reconciled = vec![];
+        for _ in 0..50 {
+            reconciled = history(&test.node_state_ref);
+            if reconciled.iter().all(|movement| !movement.provisional) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(reconciled.len(), 1);
+        assert!(!reconciled[0].provisional);
+        assert_eq!(reconciled[0].value, 1000);
+
+        test.node_action_sender.send(NodeAction::Terminate).unwrap();
+        test.node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_answers_mempool_with_pending_tx_hashes() {
+        let pending_txs = vec![test_transaction(1), test_transaction(2)];
+        let block = build_test_block(vec![test_transaction(0)]);
+        let block_bytes = block.serialize();
+
+        let store_path = String::from("tests/store_mempool");
+        let test =
+            start_synthetic_peer_test(&store_path, &block, pending_txs.clone(), |mut stream| {
+                // durante el IBD no se responde el mempool, se espera a que el nodo se sincronice con el bloque
+                Block::parse(block_bytes)
+                    .unwrap()
+                    .send(&mut stream)
+                    .unwrap();
+                while read_message(&mut stream).0 != "mempool" {}
+
+                Mempool::new().send(&mut stream).unwrap();
+                loop {
+                    let (command, payload) = read_message(&mut stream);
+                    if command == "inv" {
+                        return Inv::parse(payload).unwrap();
+                    }
+                }
+            });
+
+        let inv = test.synthetic_peer.join().unwrap();
+        assert!(inv
+            .inventories
+            .iter()
+            .all(|inventory| inventory.inventory_type == InventoryType::Tx));
+        let mut received: Vec<Vec<u8>> = inv
+            .inventories
+            .into_iter()
+            .map(|inventory| inventory.hash)
+            .collect();
+        received.sort();
+        let mut expected: Vec<Vec<u8>> = pending_txs.iter().map(Transaction::hash).collect();
+        expected.sort();
+        assert_eq!(received, expected);
+
+        test.node_action_sender.send(NodeAction::Terminate).unwrap();
+        test.node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    /// Lee mensajes del nodo hasta recibir command, devolviendo los commands recibidos antes.
+    fn read_until(stream: &mut TcpStream, command: &str) -> Vec<String> {
+        let mut received = vec![];
+        loop {
+            let (received_command, _) = read_message(stream);
+            if received_command == command {
+                return received;
+            }
+            received.push(received_command);
+        }
+    }
+
+    #[test]
+    fn node_ignores_transactions_until_synced() {
+        let announced_tx = test_transaction(1);
+        let unsolicited_tx = test_transaction(2);
+        let block = build_test_block(vec![test_transaction(0)]);
+        let block_bytes = block.serialize();
+        let block_hash = block.header.hash().clone();
+
+        let store_path = String::from("tests/store_ibd_tx_gating");
+        let announced_hash = announced_tx.hash();
+        let unsolicited = unsolicited_tx.clone();
+        let test = start_synthetic_peer_test(&store_path, &block, vec![], move |mut stream| {
+            // el anuncio no se pide: el pong llega sin un getdata antes
+            Inv::new(vec![Inventory::new(InventoryType::Tx, announced_hash)])
+                .send(&mut stream)
+                .unwrap();
+            Ping { nonce: 7 }.send(&mut stream).unwrap();
+            let before_pong = read_until(&mut stream, "pong");
+
+            // la transaccion no pedida se descarta, el mempool no se responde y el getdata de la tx da notfound
+            unsolicited.send(&mut stream).unwrap();
+            Mempool::new().send(&mut stream).unwrap();
+            GetData::new(vec![Inventory::new(InventoryType::Tx, unsolicited.hash())])
+                .send(&mut stream)
+                .unwrap();
+            let before_notfound = read_until(&mut stream, "notfound");
+
+            // al sincronizarse el nodo pide el mempool a sus peers
+            Block::parse(block_bytes)
+                .unwrap()
+                .send(&mut stream)
+                .unwrap();
+            let before_mempool = read_until(&mut stream, "mempool");
+            (before_pong, before_notfound, before_mempool)
+        });
+
+        let (before_pong, before_notfound, before_mempool) = test.synthetic_peer.join().unwrap();
+        assert!(!before_pong.contains(&"getdata".to_string()));
+        assert!(!before_notfound.contains(&"inv".to_string()));
+        assert!(!before_notfound.contains(&"tx".to_string()));
+        assert!(!before_mempool.contains(&"tx".to_string()));
+
+        let node_state = test.node_state_ref.lock().unwrap();
+        assert!(node_state.is_synced());
+        assert!(!node_state.is_block_pending(&block_hash).unwrap());
+        assert!(node_state.get_pending_tx_hashes().is_empty());
+        assert_eq!(
+            node_state.get_block_metadata(&block_hash).unwrap().tx_count,
+            1
+        );
+        drop(node_state);
+
+        test.node_action_sender.send(NodeAction::Terminate).unwrap();
+        test.node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_requests_announced_transaction_once() {
+        let announced_tx = test_transaction(1);
+        let block = build_test_block(vec![test_transaction(0)]);
+        let block_bytes = block.serialize();
+
+        let store_path = String::from("tests/store_inv_dedup");
+        let announced_hash = announced_tx.hash();
+        let unknown_hash = test_transaction(2).hash();
+        let test = start_synthetic_peer_test(&store_path, &block, vec![], move |mut stream| {
+            Block::parse(block_bytes)
+                .unwrap()
+                .send(&mut stream)
+                .unwrap();
+            read_until(&mut stream, "mempool");
+
+            // la misma transaccion anunciada dos veces se pide una sola vez,
+            // el getdata de una tx desconocida da notfound despues de procesar ambos anuncios
+            for _ in 0..2 {
+                Inv::new(vec![Inventory::new(
+                    InventoryType::Tx,
+                    announced_hash.clone(),
+                )])
+                .send(&mut stream)
+                .unwrap();
+            }
+            GetData::new(vec![Inventory::new(InventoryType::Tx, unknown_hash)])
+                .send(&mut stream)
+                .unwrap();
+            read_until(&mut stream, "notfound")
+        });
+
+        let before_notfound = test.synthetic_peer.join().unwrap();
+        let getdata_count = before_notfound
+            .iter()
+            .filter(|command| *command == "getdata")
+            .count();
+        assert_eq!(getdata_count, 1);
+
+        test.node_action_sender.send(NodeAction::Terminate).unwrap();
+        test.node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_does_not_request_blocks_already_stored() {
+        let (gui_sender, gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        let store_path = String::from("tests/store_stored_blocks");
+        let _ = fs::remove_dir_all(&store_path);
+
+        // el store ya tiene el bloque guardado, como tras un IBD interrumpido antes de guardar los headers
+        let block = build_test_block(vec![test_transaction(0)]);
+        let block_hash = block.header.hash().clone();
+        BlockStore::open(&store_path)
+            .unwrap()
+            .append(&block_hash, &block.serialize())
+            .unwrap();
+        let node_state_ref = NodeState::new(
+            logger_sender.clone(),
+            gui_sender.clone(),
+            &store_path.to_string(),
+        )
+        .unwrap();
+        node_state_ref.lock().unwrap().set_regtest(true);
+
+        let header = block.header.clone();
+        let test = attach_synthetic_peer(
+            node_state_ref,
+            gui_sender,
+            gui_receiver,
+            logger_sender,
+            move |mut stream| {
+                Headers {
+                    headers: vec![header],
+                }
+                .send(&mut stream)
+                .unwrap();
+                read_until(&mut stream, "mempool")
+            },
+        );
+
+        let before_mempool = test.synthetic_peer.join().unwrap();
+        assert!(!before_mempool.contains(&"getdata".to_string()));
+
+        let node_state = test.node_state_ref.lock().unwrap();
+        assert!(node_state.is_synced());
+        assert!(!node_state.is_block_pending(&block_hash).unwrap());
+        drop(node_state);
+
+        test.node_action_sender.send(NodeAction::Terminate).unwrap();
+        test.node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn block_metadata_is_backfilled_lazily() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        let store_path = String::from("tests/store_block_metadata");
+        let _ = fs::remove_dir_all(&store_path);
+
+        // un store anterior a los resumenes: el bloque esta guardado pero no tiene su resumen
+        let block = build_test_block(vec![test_transaction(0), test_transaction(1)]);
+        let block_hash = block.header.hash().clone();
+        BlockStore::open(&store_path)
+            .unwrap()
+            .append(&block_hash, &block.serialize())
+            .unwrap();
+        let node_state_ref =
+            NodeState::new(logger_sender.clone(), gui_sender.clone(), &store_path).unwrap();
+        node_state_ref.lock().unwrap().set_regtest(true);
+        node_state_ref
+            .lock()
+            .unwrap()
+            .append_headers(&Headers {
+                headers: vec![block.header.clone()],
+            })
+            .unwrap();
+
+        // los resumenes nunca leen el bloque guardado: con el bloque roto se muestran como pendientes
+        let path = block_file_path(&store_path, 0);
+        let stored = fs::read(&path).unwrap();
+        let mut broken = stored.clone();
+        broken[BLOCK_RECORD_HEADER_SIZE as usize..].fill(0);
+        fs::write(&path, broken).unwrap();
+        let node_state = node_state_ref.lock().unwrap();
+        let last_headers = node_state.get_last_headers(1);
+        assert_eq!(last_headers[0].1.hash(), &block_hash);
+        assert!(last_headers[0].2.is_none());
+        assert!(node_state.get_block_metadata(&block_hash).is_none());
+        drop(node_state);
+
+        // el bloque roto se saltea y no se vuelve a buscar
+        let mut unreadable = HashSet::new();
+        let backfill = |unreadable: &mut HashSet<Vec<u8>>| {
+            backfill_block_metadata(&node_state_ref, 10, unreadable, &logger_sender).unwrap()
+        };
+        assert_eq!(backfill(&mut unreadable), 0);
+        assert_eq!(unreadable, HashSet::from([block_hash.clone()]));
+        fs::write(&path, stored).unwrap();
+        assert_eq!(backfill(&mut unreadable), 0);
+
+        // al reiniciar se vuelve a intentar
+        let mut unreadable = HashSet::new();
+        assert_eq!(backfill(&mut unreadable), 1);
+        assert_eq!(backfill(&mut unreadable), 0);
+        assert!(unreadable.is_empty());
+        let metadata = node_state_ref.lock().unwrap().get_last_headers(1)[0].2;
+        assert_eq!(metadata.unwrap().tx_count, 2);
+        assert_eq!(metadata.unwrap().size as usize, block.serialize().len());
+        assert_eq!(metadata.unwrap().total_output_value, 2000);
+
+        // el resumen se guarda en disco y no se vuelve a calcular al reiniciar
+        drop(node_state_ref);
+        let node_state_ref =
+            NodeState::new(logger_sender.clone(), gui_sender, &store_path).unwrap();
+        let node_state = node_state_ref.lock().unwrap();
+        assert_eq!(node_state.get_block_metadata(&block_hash), metadata);
+        drop(node_state);
+        assert_eq!(
+            backfill_block_metadata(&node_state_ref, 10, &mut unreadable, &logger_sender).unwrap(),
+            0
+        );
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_learns_and_persists_announced_addresses() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        let store_path = String::from("tests/store_announced_addresses");
+        let _ = fs::remove_dir_all(&store_path);
+
+        let announced = SocketAddrV6::new(Ipv4Addr::new(10, 0, 0, 7).to_ipv6_mapped(), 18333, 0, 0);
+        let unroutable = SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 18333, 0, 0);
+        let (node_action_sender, node_action_receiver) = mpsc::channel();
+        let _peer = connect_synthetic_peer(0, node_action_sender, move |mut stream| {
+            let addr = Addr::new(vec![
+                NetworkAddress::new(0, 1, announced),
+                NetworkAddress::new(0, 1, unroutable),
+            ]);
+            addr.send(&mut stream).unwrap();
+            keep_connection_open(stream);
+        });
+
+        let addresses = loop {
+            let action = node_action_receiver
+                .recv_timeout(Duration::from_secs(2))
+                .unwrap();
+            if let NodeAction::NewAddresses(addresses) = action {
+                break addresses;
+            }
+        };
+        assert_eq!(addresses, vec![announced, unroutable]);
+
+        let node_state_ref =
+            NodeState::new(logger_sender.clone(), gui_sender.clone(), &store_path).unwrap();
+        let added = node_state_ref
+            .lock()
+            .unwrap()
+            .add_announced_addresses(&addresses)
+            .unwrap();
+        assert_eq!(added, 1);
+        drop(node_state_ref);
+
+        // al reiniciar el nodo las direcciones se restauran de peers.bin
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        assert_eq!(
+            node_state_ref.lock().unwrap().known_peer_addresses(),
+            vec![SocketAddr::from(([10, 0, 0, 7], 18333))]
+        );
+        drop(node_state_ref);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn version_and_self_advertisement_carry_the_derived_services() {
+        let services = ServiceCapabilities {
+            client_only: false,
+            full_history: false,
+            witness: false,
+            bloom: false,
+        }
+        .services();
+        assert_eq!(services, NODE_NETWORK_LIMITED);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let listener_address = listener.local_addr().unwrap();
+        let seen_as = SocketAddrV6::new(Ipv4Addr::new(203, 0, 113, 5).to_ipv6_mapped(), 0, 0, 0);
+        let synthetic_peer = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (command, payload) = read_message(&mut stream);
+            assert_eq!(command, "version");
+            let version = Version::parse(payload).unwrap();
+
+            Version::new(seen_as, seen_as, 70015, 0, 0)
+                .send(&mut stream)
+                .unwrap();
+            VerAck::new().send(&mut stream).unwrap();
+            for expected in ["verack", "sendheaders", "sendcmpct", "getaddr"] {
+                assert_eq!(read_message(&mut stream).0, expected);
+            }
+            let (command, payload) = read_message(&mut stream);
+            assert_eq!(command, "addr");
+            (version, Addr::parse(payload).unwrap())
+        });
+
+        let (_unused_peer_action_sender, receiver) = peer_action_channel();
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        let (node_action_sender, _node_action_receiver) = mpsc::channel();
+        let _peer = Peer::call(
+            open_stream(listener_address).unwrap(),
+            SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 18333, 0, 0),
+            services,
+            70015,
+            random_nonce(),
+            DEFAULT_USER_AGENT,
+            receiver,
+            logger_sender,
+            node_action_sender,
+        )
+        .unwrap();
+
+        let (version, addr) = synthetic_peer.join().unwrap();
+        assert_eq!(version.services, services);
+        assert_eq!(version.sender_services, services);
+        assert_eq!(addr.addresses.len(), 1);
+        assert_eq!(addr.addresses[0].services, services);
+        assert_eq!(
+            addr.addresses[0].address,
+            SocketAddrV6::new(*seen_as.ip(), 18333, 0, 0)
+        );
+    }
+
+    #[test]
+    fn node_state_restores_pending_txs() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_restore_pending_txs");
+        let _ = fs::remove_dir_all(&store_path);
+        fs::create_dir(&store_path).unwrap();
+        fs::copy(
+            "tests/test_wallets.bin",
+            format!("{}/wallets.bin", store_path),
+        )
+        .unwrap();
+
+        let mut wallet_tx = test_transaction(1);
+        wallet_tx.outputs[0].script_pubkey = vec![
+            118, 169, 20, 27, 40, 219, 33, 69, 20, 4, 108, 105, 234, 87, 71, 50, 50, 154, 22, 16,
+            220, 64, 85, 136, 172,
+        ];
+        let confirmed_tx = test_transaction(2);
+
+        let node_state_ref =
+            NodeState::new(logger_sender.clone(), gui_sender.clone(), &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state.set_regtest(true);
+        node_state.append_pending_tx(wallet_tx.clone()).unwrap();
+        node_state.append_pending_tx(confirmed_tx.clone()).unwrap();
+
+        // El bloque con confirmed_tx queda guardado pero el nodo se cierra antes de actualizar las pending txs
+        let timestamp = get_current_timestamp().unwrap() as u32;
+        let block = build_test_block_at(vec![test_transaction(0), confirmed_tx.clone()], timestamp);
+        let mut headers = Headers::new();
+        headers.headers.push(block.header.clone());
+        node_state.append_headers(&headers).unwrap();
+        BlockStore::open(&store_path)
+            .unwrap()
+            .append(block.header.hash(), &block.serialize())
+            .unwrap();
+        drop(node_state);
+        drop(node_state_ref);
+
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state.set_regtest(true);
+        node_state
+            .change_wallet("mhzZUxRkPzNpCsQHemTakuJa5xhCajxyVm".to_string())
+            .unwrap();
+
+        let pending_movements = node_state.get_active_wallet_pending_txs().unwrap();
+        assert_eq!(pending_movements.len(), 1);
+        assert_eq!(pending_movements[0].value, 1000);
+        assert!(node_state.get_pending_tx(&wallet_tx.hash()).is_some());
+        assert!(node_state.get_pending_tx(&confirmed_tx.hash()).is_none());
+        drop(node_state);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    fn spend_first_output(parent: &Transaction) -> Transaction {
+        let mut transaction = test_transaction(0);
+        transaction.inputs[0].previous_output = OutPoint {
+            hash: parent.hash(),
+            index: 0,
+        };
+        transaction
+    }
+
+    #[test]
+    fn node_state_accepts_orphans_after_their_parents_arrive() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_orphans");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state.set_regtest(true);
+
+        let chain = build_test_chain(6);
+        node_state
+            .append_headers(&Headers {
+                headers: chain[..3].to_vec(),
+            })
+            .unwrap();
+        for header in &chain[..3] {
+            let block = Block::new(header.clone(), vec![]);
+            node_state
+                .append_block(header.hash().clone(), &block)
+                .unwrap();
+        }
+        assert!(node_state.is_synced());
+
+        // las transacciones llegan antes que el bloque con la transaccion que gastan
+        node_state
+            .append_headers(&Headers {
+                headers: chain[3..4].to_vec(),
+            })
+            .unwrap();
+        let parent = test_transaction(5);
+        let child = spend_first_output(&parent);
+        let grandchild = spend_first_output(&child);
+        assert!(!node_state.append_pending_tx(grandchild.clone()).unwrap());
+        assert!(!node_state.append_pending_tx(child.clone()).unwrap());
+        assert!(node_state.get_pending_tx_hashes().is_empty());
+
+        let block = Block::new(chain[3].clone(), vec![parent]);
+        node_state
+            .append_block(chain[3].hash().clone(), &block)
+            .unwrap();
+        assert!(node_state.get_pending_tx(&child.hash()).is_some());
+        assert!(node_state.get_pending_tx(&grandchild.hash()).is_some());
+
+        // el bloque llega antes que su header y el de su bloque anterior
+        let orphan_block = Arc::new(Block::new(chain[5].clone(), vec![]));
+        node_state.append_orphan_block(orphan_block);
+        assert!(!node_state.is_block_pending(chain[5].hash()).unwrap());
+        node_state
+            .append_headers(&Headers {
+                headers: chain[4..].to_vec(),
+            })
+            .unwrap();
+        assert!(node_state.is_block_pending(chain[4].hash()).unwrap());
+        assert!(!node_state.is_block_pending(chain[5].hash()).unwrap());
+        assert!(node_state.get_block(chain[5].hash()).is_ok());
+        drop(node_state);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    /// Un peer que pide headers sin leer su socket bloquea al NodeActionLoop en el envio de la respuesta,
+    /// pero no al lock del NodeState, por lo que el resto del nodo (por ejemplo la GUI) sigue respondiendo.
+    #[test]
+    fn stalled_peer_does_not_block_the_node_state() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_stalled_peer");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref =
+            NodeState::new(logger_sender.clone(), gui_sender.clone(), &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state.set_regtest(true);
+        node_state
+            .append_wallet(
+                "fixture".to_string(),
+                FIXTURE_PUBKEY.to_string(),
+                FIXTURE_PRIVKEY.to_string(),
+            )
+            .unwrap();
+        node_state
+            .change_wallet(FIXTURE_PUBKEY.to_string())
+            .unwrap();
+        node_state
+            .append_headers(&Headers {
+                headers: build_test_chain(2000),
+            })
+            .unwrap();
+        drop(node_state);
+
+        let (node_action_sender, node_action_receiver) = mpsc::channel();
+        let (peer_action_sender, _peer_action_receiver) = peer_action_channel();
+        let loop_node_state_ref = node_state_ref.clone();
+        let node_action_loop = thread::spawn(move || {
+            NodeActionLoop::start(
+                gui_sender,
+                node_action_receiver,
+                peer_action_sender,
+                logger_sender,
+                loop_node_state_ref,
+            )
+        });
+
+        // el peer pide 2000 headers muchas veces y nunca lee las respuestas, hasta que se le indica cerrar la conexion
+        let (start_sender, start_receiver) = mpsc::channel::<()>();
+        let (close_sender, close_receiver) = mpsc::channel::<()>();
+        let stalled_peer =
+            connect_synthetic_peer(0, node_action_sender.clone(), move |mut stream| {
+                start_receiver.recv().unwrap();
+                let getheaders = GetHeaders::new(70015, vec![GENESIS.to_vec()], vec![0; 32]);
+                for _ in 0..200 {
+                    getheaders.send(&mut stream).unwrap();
+                }
+                close_receiver.recv().unwrap();
+            });
+        node_state_ref
+            .lock()
+            .unwrap()
+            .append_peers(vec![stalled_peer])
+            .unwrap();
+        start_sender.send(()).unwrap();
+        thread::sleep(Duration::from_millis(500));
+
+        let (response_sender, response_receiver) = mpsc::channel();
+        node_action_sender
+            .send(NodeAction::Rpc((RpcCall::GetBlockCount, response_sender)))
+            .unwrap();
+        assert!(response_receiver
+            .recv_timeout(Duration::from_millis(200))
+            .is_err());
+        for _ in 0..5 {
+            let started_at = Instant::now();
+            let balance = node_state_ref
+                .lock()
+                .unwrap()
+                .get_active_wallet_balance()
+                .unwrap();
+            assert_eq!(balance, 0);
+            assert!(started_at.elapsed() < Duration::from_millis(50));
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        // al cerrarse la conexion falla el envio bloqueado y el loop sigue con el resto de las acciones
+        close_sender.send(()).unwrap();
+        let response = response_receiver
+            .recv_timeout(Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(response.unwrap(), "2000");
+        assert!(node_state_ref.lock().unwrap().get_peers().is_empty());
+
+        node_action_sender.send(NodeAction::Terminate).unwrap();
+        node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    /// Conecta un peer sintetico que anuncia start_height en su version y mantiene la conexion abierta.
+    fn connect_peer_at_height(start_height: i32) -> Peer {
+        let (node_action_sender, _node_action_receiver) = mpsc::channel();
+        connect_synthetic_peer(start_height, node_action_sender, keep_connection_open)
+    }
+
+    fn keep_connection_open(mut stream: TcpStream) {
+        let mut buffer = [0; 1024];
+        while matches!(stream.read(&mut buffer), Ok(read) if read > 0) {}
+    }
+
+    /// Conecta un peer sintetico que anuncia start_height en su version y, tras el handshake, ejecuta synthetic_peer con la conexion.
+    fn connect_synthetic_peer(
+        start_height: i32,
+        node_action_sender: mpsc::Sender<NodeAction>,
+        synthetic_peer: impl FnOnce(TcpStream) + Send + 'static,
+    ) -> Peer {
+        connect_synthetic_peer_with_version(start_height, 70015, node_action_sender, synthetic_peer)
+    }
+
+    /// Como connect_synthetic_peer, pero el peer sintetico anuncia esa version del protocolo.
+    fn connect_synthetic_peer_with_version(
+        start_height: i32,
+        protocol_version: i32,
+        node_action_sender: mpsc::Sender<NodeAction>,
+        synthetic_peer: impl FnOnce(TcpStream) + Send + 'static,
+    ) -> Peer {
+        connect_synthetic_peer_with_services(
+            start_height,
+            protocol_version,
+            SYNTHETIC_PEER_SERVICES,
+            node_action_sender,
+            synthetic_peer,
+        )
+    }
+
+    /// Como connect_synthetic_peer_with_version, pero el peer sintetico anuncia esos servicios.
+    fn connect_synthetic_peer_with_services(
+        start_height: i32,
+        protocol_version: i32,
+        services: u64,
+        node_action_sender: mpsc::Sender<NodeAction>,
+        synthetic_peer: impl FnOnce(TcpStream) + Send + 'static,
+    ) -> Peer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let listener_address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            synthetic_peer_handshake_with_services(
+                &mut stream,
+                start_height,
+                protocol_version,
+                services,
+            );
+            synthetic_peer(stream);
+        });
+
+        let (_unused_peer_action_sender, receiver) = peer_action_channel();
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        Peer::call(
+            open_stream(listener_address).unwrap(),
+            SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), 18333, 0, 0),
+            0,
+            70015,
+            random_nonce(),
+            DEFAULT_USER_AGENT,
+            receiver,
+            logger_sender,
+            node_action_sender,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn node_state_warns_when_behind_network_median_height() {
+        let (gui_sender, gui_receiver) = glib::MainContext::channel(Priority::default());
+        let context = glib::MainContext::new();
+        let network_events = Rc::new(RefCell::new(vec![]));
+        let events = network_events.clone();
+        gui_receiver.attach(Some(&context), move |event| {
+            match event {
+                GUIEvents::BehindNetwork { blocks_behind } => {
+                    events.borrow_mut().push(Some(blocks_behind))
+                }
+                GUIEvents::CaughtUpWithNetwork => events.borrow_mut().push(None),
+                _ => {}
+            }
+            glib::Continue(true)
+        });
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_behind_network");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state.set_regtest(true);
+
+        // tres peers a la altura del umbral: no alcanza para estar atrasado
+        let peers = (0..3).map(|_| connect_peer_at_height(3)).collect();
+        assert!(!node_state.append_peers(peers).unwrap());
+        assert_eq!(node_state.get_network_height(), Some(3));
+        assert!(!node_state.is_catching_up());
+
+        // con mayoria de peers a altura 10 la mediana pasa a 10
+        let peers: Vec<Peer> = (0..4).map(|_| connect_peer_at_height(10)).collect();
+        let tall_peers: Vec<SocketAddrV6> = peers.iter().map(|peer| peer.address).collect();
+        assert!(node_state.append_peers(peers).unwrap());
+        assert_eq!(node_state.get_network_height(), Some(10));
+        assert_eq!(node_state.get_blocks_behind(), 10);
+        assert!(node_state.is_catching_up());
+
+        let chain = build_test_chain(10);
+        node_state
+            .append_headers(&Headers {
+                headers: chain[..8].to_vec(),
+            })
+            .unwrap();
+        assert_eq!(node_state.get_blocks_behind(), 2);
+        assert!(node_state.is_catching_up());
+
+        // al desconectarse los peers a altura 10 la mediana vuelve a 3 y el nodo ya no esta atrasado
+        for address in tall_peers {
+            node_state
+                .remove_peer(address, DisconnectReason::Terminated)
+                .unwrap();
+        }
+        assert_eq!(node_state.get_network_height(), Some(3));
+        assert!(!node_state.is_catching_up());
+
+        // con 4 peers a altura 20 queda atrasado otra vez, y los headers que recibe no repiten el aviso
+        let peers = (0..4).map(|_| connect_peer_at_height(20)).collect();
+        assert!(node_state.append_peers(peers).unwrap());
+        node_state
+            .append_headers(&Headers {
+                headers: chain[8..].to_vec(),
+            })
+            .unwrap();
+        assert_eq!(node_state.get_blocks_behind(), 10);
+        assert!(node_state.is_catching_up());
+        node_state
+            .remove_all_peers(DisconnectReason::Terminated)
+            .unwrap();
+        drop(node_state);
+
+        while context.iteration(false) {}
+        assert_eq!(*network_events.borrow(), vec![Some(10), None, Some(12)]);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_state_records_peer_disconnect_reasons() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_disconnects");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref =
+            NodeState::new(logger_sender.clone(), gui_sender.clone(), &store_path).unwrap();
+
+        let (node_action_sender, node_action_receiver) = mpsc::channel();
+        let (peer_action_sender, _peer_action_receiver) = peer_action_channel();
+        let loop_node_state_ref = node_state_ref.clone();
+        let node_action_loop = thread::spawn(move || {
+            NodeActionLoop::start(
+                gui_sender,
+                node_action_receiver,
+                peer_action_sender,
+                logger_sender,
+                loop_node_state_ref,
+            )
+        });
+
+        // los peers sinteticos esperan a ser agregados al nodo antes de cerrar la conexion o enviar el mensaje invalido
+        let (close_sender, close_receiver) = mpsc::channel::<()>();
+        let closing_peer = connect_synthetic_peer(0, node_action_sender.clone(), move |stream| {
+            close_receiver.recv().unwrap();
+            drop(stream);
+        });
+        let (misbehave_sender, misbehave_receiver) = mpsc::channel::<()>();
+        let misbehaving_peer =
+            connect_synthetic_peer(0, node_action_sender.clone(), move |mut stream| {
+                misbehave_receiver.recv().unwrap();
+                // anuncia 5 headers pero no envia ninguno
+                let payload = [5];
+                let header = MessageHeader::from_payload(String::from("headers"), &payload);
+                stream.write_all(&header.serialize()).unwrap();
+                stream.write_all(&payload).unwrap();
+                keep_connection_open(stream);
+            });
+        let replaced_peer =
+            connect_synthetic_peer(0, node_action_sender.clone(), keep_connection_open);
+        let mut replacing_peer =
+            connect_synthetic_peer(0, node_action_sender.clone(), keep_connection_open);
+        replacing_peer.address = replaced_peer.address;
+        let addresses = [
+            closing_peer.address,
+            misbehaving_peer.address,
+            replaced_peer.address,
+        ];
+
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state
+            .append_peers(vec![closing_peer, misbehaving_peer, replaced_peer])
+            .unwrap();
+        node_state.append_peers(vec![replacing_peer]).unwrap();
+        drop(node_state);
+        close_sender.send(()).unwrap();
+        misbehave_sender.send(()).unwrap();
+
+        let mut disconnects = vec![];
+        for _ in 0..50 {
+            disconnects = node_state_ref.lock().unwrap().recent_disconnects();
+            if disconnects.len() == 3 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(disconnects.len(), 3);
+        let event_of = |address: SocketAddrV6| {
+            disconnects
+                .iter()
+                .find(|event| event.address == address)
+                .unwrap()
+        };
+        assert_eq!(
+            event_of(addresses[0]).reason,
+            DisconnectReason::RemoteClosed
+        );
+        assert_eq!(
+            event_of(addresses[1]).reason,
+            DisconnectReason::Misbehavior(INVALID_MESSAGE_SCORE)
+        );
+        assert_eq!(event_of(addresses[1]).bytes_transferred, 25);
+        assert_eq!(event_of(addresses[2]).reason, DisconnectReason::Replaced);
+
+        // solo se recuerdan las ultimas MAX_DISCONNECT_EVENTS desconexiones
+        let mut node_state = node_state_ref.lock().unwrap();
+        assert_eq!(node_state.get_peers().len(), 1);
+        for _ in 0..MAX_DISCONNECT_EVENTS {
+            node_state
+                .record_disconnect(DisconnectEvent::handshake_failed(addresses[0]))
+                .unwrap();
+        }
+        let disconnects = node_state.recent_disconnects();
+        assert_eq!(disconnects.len(), MAX_DISCONNECT_EVENTS);
+        assert!(disconnects
+            .iter()
+            .all(|event| event.reason == DisconnectReason::HandshakeFailed));
+        drop(node_state);
+
+        node_action_sender.send(NodeAction::Terminate).unwrap();
+        node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    /// Lee los mensajes que envia el nodo hasta su pong y devuelve el nonce.
+    fn read_until_pong(stream: &mut TcpStream) -> u64 {
+        loop {
+            let (command, payload) = read_message(stream);
+            if command == "pong" {
+                return Pong::parse(payload).unwrap().nonce;
+            }
+        }
+    }
+
+    #[test]
+    fn node_state_disconnects_peers_that_violate_the_protocol() {
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+
+        let store_path = String::from("tests/store_protocol_violations");
+        let _ = fs::remove_dir_all(&store_path);
+        let node_state_ref =
+            NodeState::new(logger_sender.clone(), gui_sender.clone(), &store_path).unwrap();
+
+        let (node_action_sender, node_action_receiver) = mpsc::channel();
+        let (peer_action_sender, _peer_action_receiver) = peer_action_channel();
+        let loop_node_state_ref = node_state_ref.clone();
+        let node_action_loop = thread::spawn(move || {
+            NodeActionLoop::start(
+                gui_sender,
+                node_action_receiver,
+                peer_action_sender,
+                logger_sender,
+                loop_node_state_ref,
+            )
+        });
+
+        // los peers sinteticos esperan a ser agregados al nodo antes de enviar sus mensajes
+        let (start_sender, start_receiver) = mpsc::channel::<()>();
+        let start_receiver = Arc::new(Mutex::new(start_receiver));
+        let wait_start = move || {
+            let start_receiver = start_receiver.clone();
+            move || start_receiver.lock().unwrap().recv().unwrap()
+        };
+        let (pong_sender, pong_receiver) = mpsc::channel::<(u64, u64)>();
+
+        let start = wait_start();
+        let version_peer =
+            connect_synthetic_peer(0, node_action_sender.clone(), move |mut stream| {
+                start();
+                let address = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 18333, 0, 0);
+                Version::new(address, address, 70015, 0, 1)
+                    .send(&mut stream)
+                    .unwrap();
+                keep_connection_open(stream);
+            });
+        let start = wait_start();
+        let verack_peer =
+            connect_synthetic_peer(0, node_action_sender.clone(), move |mut stream| {
+                start();
+                VerAck::new().send(&mut stream).unwrap();
+                keep_connection_open(stream);
+            });
+        // no soporta compact blocks: los cmpctblock se descartan hasta llegar al puntaje de desconexion
+        let start = wait_start();
+        let compact_pong_sender = pong_sender.clone();
+        let compact_peer = connect_synthetic_peer_with_version(
+            0,
+            70012,
+            node_action_sender.clone(),
+            move |mut stream| {
+                start();
+                let ignored = INVALID_MESSAGE_SCORE / UNNEGOTIATED_MESSAGE_SCORE - 1;
+                for _ in 0..ignored {
+                    send_raw_message(&mut stream, "cmpctblock", &[0; 8]);
+                }
+                Ping { nonce: 1 }.send(&mut stream).unwrap();
+                let _ = compact_pong_sender.send((1, read_until_pong(&mut stream)));
+                send_raw_message(&mut stream, "cmpctblock", &[0; 8]);
+                keep_connection_open(stream);
+            },
+        );
+        let start = wait_start();
+        let compliant_peer =
+            connect_synthetic_peer(0, node_action_sender.clone(), move |mut stream| {
+                start();
+                send_raw_message(&mut stream, "sendheaders", &[]);
+                Ping { nonce: 2 }.send(&mut stream).unwrap();
+                let _ = pong_sender.send((2, read_until_pong(&mut stream)));
+                keep_connection_open(stream);
+            });
+        let compliant_address = compliant_peer.address;
+        let addresses = [
+            version_peer.address,
+            verack_peer.address,
+            compact_peer.address,
+        ];
+
+        node_state_ref
+            .lock()
+            .unwrap()
+            .append_peers(vec![
+                version_peer,
+                verack_peer,
+                compact_peer,
+                compliant_peer,
+            ])
+            .unwrap();
+        for _ in 0..4 {
+            start_sender.send(()).unwrap();
+        }
+
+        // los peers siguen respondiendo los pings despues de los mensajes descartados
+        for _ in 0..2 {
+            let (sent, received) = pong_receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(sent, received);
+        }
+
+        let mut disconnects = vec![];
+        for _ in 0..50 {
+            disconnects = node_state_ref.lock().unwrap().recent_disconnects();
+            if disconnects.len() == 3 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(disconnects.len(), 3);
+        let reason_of = |address: SocketAddrV6| {
+            disconnects
+                .iter()
+                .find(|event| event.address == address)
+                .unwrap()
+                .reason
+        };
+        assert_eq!(reason_of(addresses[0]), DisconnectReason::ProtocolViolation);
+        assert_eq!(reason_of(addresses[1]), DisconnectReason::ProtocolViolation);
+        assert_eq!(
+            reason_of(addresses[2]),
+            DisconnectReason::Misbehavior(INVALID_MESSAGE_SCORE)
+        );
+
+        let mut node_state = node_state_ref.lock().unwrap();
+        let peers = node_state.get_peers();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].address, compliant_address);
+        drop(node_state);
+
+        node_action_sender.send(NodeAction::Terminate).unwrap();
+        node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    /// Llama al nodo como un peer sintetico: envia su version, ejecuta early_messages antes de su verack
+    /// y devuelve los comandos que envia el nodo despues del verack.
+    fn call_node_with_early_messages(
+        early_messages: impl FnOnce(&mut TcpStream) + Send + 'static,
+    ) -> (Result<(), CustomError>, Vec<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let listener_address = listener.local_addr().unwrap();
+        let calling = thread::spawn(move || {
+            let mut stream = open_stream(listener_address).unwrap();
+            let address = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 18333, 0, 0);
+            Version::new(address, address, 70015, 0, random_nonce())
+                .send(&mut stream)
+                .unwrap();
+            let (command, _) = read_message(&mut stream);
+            assert_eq!(command, "version");
+            let (command, _) = read_message(&mut stream);
+            assert_eq!(command, "verack");
+
+            early_messages(&mut stream);
+            if VerAck::new().send(&mut stream).is_err() {
+                return vec![];
+            }
+            stream
+                .set_read_timeout(Some(Duration::from_millis(500)))
+                .unwrap();
+            let mut commands = vec![];
+            while let Ok(header) = MessageHeader::read(&mut stream) {
+                if header.read_payload(&mut stream).is_err() {
+                    break;
+                }
+                commands.push(header.command);
+            }
+            commands
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let (_peer_action_sender, peer_action_receiver) = peer_action_channel();
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        let (node_action_sender, _node_action_receiver) = mpsc::channel();
+        let answered = Peer::answer(
+            stream,
+            SocketAddrV6::new(Ipv6Addr::LOCALHOST, 18333, 0, 0),
+            0,
+            70015,
+            random_nonce(),
+            DEFAULT_USER_AGENT,
+            peer_action_receiver,
+            logger_sender,
+            node_action_sender,
+        )
+        .map(|mut peer| {
+            peer.stop(DisconnectReason::Terminated);
+        });
+        (answered, calling.join().unwrap())
+    }
+
+    #[test]
+    fn answered_handshake_skips_messages_sent_before_the_verack() {
+        let (answered, commands) = call_node_with_early_messages(|stream| {
+            send_raw_message(stream, "wtxidrelay", &[]);
+            send_raw_message(stream, "sendaddrv2", &[]);
+            Ping { nonce: 7 }.send(stream).unwrap();
+        });
+        assert!(answered.is_ok());
+        // el ping enviado antes del verack no se proceso
+        assert_eq!(commands, vec!["sendheaders", "sendcmpct"]);
+
+        let (answered, _) = call_node_with_early_messages(|stream| {
+            for nonce in 0..=MAX_EARLY_HANDSHAKE_MESSAGES as u64 {
+                Ping { nonce }.send(stream).unwrap();
+            }
+        });
+        assert!(matches!(answered, Err(CustomError::CannotHandshakeNode)));
+    }
+
+    /// Crea un NodeState con su NodeActionLoop, devolviendo tambien el sender de acciones del nodo,
+    /// el receiver compartido de acciones de peers (su sender se mantiene vivo) y el thread del loop.
+    fn spawn_node_with_loop(
+        store_path: &String,
+        gui_sender: glib::Sender<GUIEvents>,
+    ) -> (
+        Arc<Mutex<NodeState>>,
+        mpsc::Sender<NodeAction>,
+        PeerActionReceiver,
+        thread::JoinHandle<()>,
+    ) {
+        let _ = fs::remove_dir_all(store_path);
+        spawn_node_with_loop_on_store(store_path, gui_sender)
+    }
+
+    /// Igual que spawn_node_with_loop pero sin borrar el store, para iniciar el nodo sobre un store ya armado.
+    fn spawn_node_with_loop_on_store(
+        store_path: &String,
+        gui_sender: glib::Sender<GUIEvents>,
+    ) -> (
+        Arc<Mutex<NodeState>>,
+        mpsc::Sender<NodeAction>,
+        PeerActionReceiver,
+        thread::JoinHandle<()>,
+    ) {
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        let node_state_ref =
+            NodeState::new(logger_sender.clone(), gui_sender.clone(), store_path).unwrap();
+        node_state_ref.lock().unwrap().set_regtest(true);
+
+        let (node_action_sender, node_action_receiver) = mpsc::channel();
+        let (peer_action_sender, peer_action_receiver) = peer_action_channel();
+        let loop_node_state_ref = node_state_ref.clone();
+        let node_action_loop = thread::spawn(move || {
+            NodeActionLoop::start(
+                gui_sender,
+                node_action_receiver,
+                peer_action_sender,
+                logger_sender,
+                loop_node_state_ref,
+            )
+        });
+        (
+            node_state_ref,
+            node_action_sender,
+            peer_action_receiver,
+            node_action_loop,
+        )
+    }
+
+    #[test]
+    fn nodes_sharing_a_nonce_reject_the_connection() {
+        let nonce = random_nonce();
+        let local_address = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 18333, 0, 0);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let listener_address = listener.local_addr().unwrap();
+        let answering = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let (_peer_action_sender, peer_action_receiver) = peer_action_channel();
+            let (logger_sender, _logger_receiver) = mpsc::channel();
+            let (node_action_sender, _node_action_receiver) = mpsc::channel();
+            Peer::answer(
+                stream,
+                local_address,
+                0,
+                70015,
+                nonce,
+                DEFAULT_USER_AGENT,
+                peer_action_receiver,
+                logger_sender,
+                node_action_sender,
+            )
+            .err()
+        });
+
+        let (_peer_action_sender, peer_action_receiver) = peer_action_channel();
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        let (node_action_sender, _node_action_receiver) = mpsc::channel();
+        let calling = Peer::call(
+            open_stream(listener_address).unwrap(),
+            local_address,
+            0,
+            70015,
+            nonce,
+            DEFAULT_USER_AGENT,
+            peer_action_receiver,
+            logger_sender,
+            node_action_sender,
+        );
+
+        assert!(calling.is_err());
+        assert!(matches!(
+            answering.join().unwrap(),
+            Some(CustomError::ConnectedToSelf)
+        ));
+    }
+
+    #[test]
+    fn node_removes_peer_when_remote_node_disconnects() {
+        let answering_path = String::from("tests/store_remote_disconnect_answer");
+        let calling_path = String::from("tests/store_remote_disconnect_call");
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (answering_state, answering_sender, answering_receiver, answering_loop) =
+            spawn_node_with_loop(&answering_path, gui_sender.clone());
+        let (calling_state, calling_sender, calling_receiver, calling_loop) =
+            spawn_node_with_loop(&calling_path, gui_sender);
+        let local_address = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 18333, 0, 0);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let listener_address = listener.local_addr().unwrap();
+        let answer_sender = answering_sender.clone();
+        let answering = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let (logger_sender, _logger_receiver) = mpsc::channel();
+            Peer::answer(
+                stream,
+                local_address,
+                0,
+                70015,
+                random_nonce(),
+                DEFAULT_USER_AGENT,
+                answering_receiver,
+                logger_sender,
+                answer_sender,
+            )
+            .unwrap()
+        });
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        let calling_peer = Peer::call(
+            open_stream(listener_address).unwrap(),
+            local_address,
+            0,
+            70015,
+            random_nonce(),
+            DEFAULT_USER_AGENT,
+            calling_receiver,
+            logger_sender,
+            calling_sender.clone(),
+        )
+        .unwrap();
+        let answering_peer = answering.join().unwrap();
+        let answered_address = answering_peer.address;
+
+        answering_state
+            .lock()
+            .unwrap()
+            .append_peers(vec![answering_peer])
+            .unwrap();
+        calling_state
+            .lock()
+            .unwrap()
+            .append_peers(vec![calling_peer])
+            .unwrap();
+
+        // el nodo que llamo termina: cierra la conexion y espera a que terminen los threads del peer
+        calling_state
+            .lock()
+            .unwrap()
+            .remove_all_peers(DisconnectReason::Terminated)
+            .unwrap();
+        assert!(calling_state.lock().unwrap().get_peers().is_empty());
+
+        let mut peers_left = 1;
+        for _ in 0..50 {
+            peers_left = answering_state.lock().unwrap().get_peers().len();
+            if peers_left == 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(peers_left, 0);
+        let disconnects = answering_state.lock().unwrap().recent_disconnects();
+        assert_eq!(disconnects.len(), 1);
+        assert_eq!(disconnects[0].address, answered_address);
+        assert_eq!(disconnects[0].reason, DisconnectReason::RemoteClosed);
+
+        // el peer ya no esta, removerlo de nuevo no hace nada
+        answering_state
+            .lock()
+            .unwrap()
+            .remove_peer(answered_address, DisconnectReason::RemoteClosed)
+            .unwrap();
+        assert_eq!(
+            answering_state.lock().unwrap().recent_disconnects().len(),
+            1
+        );
+
+        for (sender, node_action_loop) in [
+            (answering_sender, answering_loop),
+            (calling_sender, calling_loop),
+        ] {
+            sender.send(NodeAction::Terminate).unwrap();
+            node_action_loop.join().unwrap();
+        }
+        drop(answering_state);
+        drop(calling_state);
+        fs::remove_dir_all(&answering_path).unwrap();
+        fs::remove_dir_all(&calling_path).unwrap();
+    }
+
+    /// Peer sintetico que responde los pings que recibe e ignora el resto de los mensajes.
+    fn answer_pings(stream: TcpStream) {
+        answer_pings_after(stream, Duration::ZERO);
+    }
+
+    /// Como answer_pings, pero espera delay antes de responder cada ping.
+    fn answer_pings_after(mut stream: TcpStream, delay: Duration) {
+        while let Ok(header) = MessageHeader::read(&mut stream) {
+            if header.command == "ping" {
+                let Ok(ping) = Ping::read(&mut stream, header.payload_size) else { return };
+                thread::sleep(delay);
+                if (Pong { nonce: ping.nonce }).send(&mut stream).is_err() {
+                    return;
+                }
+            } else {
+                let mut payload = vec![0; header.payload_size as usize];
+                if stream.read_exact(&mut payload).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn node_evicts_peers_that_do_not_answer_pings() {
+        let store_path = String::from("tests/store_ping_timeout");
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (node_state_ref, node_action_sender, _peer_action_receiver, node_action_loop) =
+            spawn_node_with_loop(&store_path, gui_sender);
+
+        let silent_peer =
+            connect_synthetic_peer(0, node_action_sender.clone(), keep_connection_open);
+        let answering_peer = connect_synthetic_peer(0, node_action_sender.clone(), answer_pings);
+        let silent_address = silent_peer.address;
+        let answering_address = answering_peer.address;
+        node_state_ref
+            .lock()
+            .unwrap()
+            .append_peers(vec![silent_peer, answering_peer])
+            .unwrap();
+
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        ping_loop(
+            node_state_ref.clone(),
+            node_action_sender.clone(),
+            logger_sender,
+            Duration::from_millis(100),
+            Duration::from_millis(300),
+        );
+
+        let mut disconnects = vec![];
+        for _ in 0..50 {
+            disconnects = node_state_ref.lock().unwrap().recent_disconnects();
+            if !disconnects.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(disconnects.len(), 1);
+        assert_eq!(disconnects[0].address, silent_address);
+        assert_eq!(disconnects[0].reason, DisconnectReason::PingTimeout);
+
+        // el peer que responde los pings sigue conectado luego de varios intervalos
+        thread::sleep(Duration::from_millis(800));
+        let mut node_state = node_state_ref.lock().unwrap();
+        assert_eq!(node_state.recent_disconnects().len(), 1);
+        let peers = node_state.get_peers();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].address, answering_address);
+        assert!(peers[0].ping_sent_at.is_some());
+        node_state
+            .remove_all_peers(DisconnectReason::Terminated)
+            .unwrap();
+        drop(node_state);
+
+        node_action_sender.send(NodeAction::Terminate).unwrap();
+        node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_measures_peer_ping_latency() {
+        let store_path = String::from("tests/store_ping_latency");
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (node_state_ref, node_action_sender, _peer_action_receiver, node_action_loop) =
+            spawn_node_with_loop(&store_path, gui_sender);
+
+        let slow_peer = connect_synthetic_peer(0, node_action_sender.clone(), |stream| {
+            answer_pings_after(stream, Duration::from_millis(200))
+        });
+        let slow_address = slow_peer.address;
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state.append_peers(vec![slow_peer]).unwrap();
+        assert_eq!(node_state.get_peers()[0].avg_latency_ms(), None);
+        let dead_peers = node_state.ping_peers(Duration::from_secs(600), Duration::from_secs(5));
+        assert!(dead_peers.is_empty());
+        drop(node_state);
+
+        let mut latency = None;
+        for _ in 0..50 {
+            latency = node_state_ref.lock().unwrap().get_peers()[0].avg_latency_ms();
+            if latency.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let latency = latency.unwrap();
+        assert!((200..1000).contains(&latency), "{latency} ms");
+
+        // un pong que no responde al ultimo ping no cambia la latencia
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state.peer_pong(slow_address, 42).unwrap();
+        let peers = node_state.get_peers();
+        assert_eq!(peers[0].avg_latency_ms(), Some(latency));
+        assert_eq!(peers[0].summary().avg_latency_ms, Some(latency));
+        node_state
+            .remove_all_peers(DisconnectReason::Terminated)
+            .unwrap();
+        drop(node_state);
+
+        node_action_sender.send(NodeAction::Terminate).unwrap();
+        node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_throttles_peer_updates_from_pongs() {
+        let store_path = String::from("tests/store_pong_updates");
+        let (gui_sender, gui_receiver) = glib::MainContext::channel(Priority::default());
+        let context = glib::MainContext::new();
+        let peers_updates = Rc::new(RefCell::new(0));
+        let updates = peers_updates.clone();
+        gui_receiver.attach(Some(&context), move |event| {
+            if let GUIEvents::PeersUpdated { .. } = event {
+                *updates.borrow_mut() += 1;
+            }
+            glib::Continue(true)
+        });
+        let (node_state_ref, node_action_sender, _peer_action_receiver, node_action_loop) =
+            spawn_node_with_loop(&store_path, gui_sender);
+
+        let peers: Vec<Peer> = (0..3)
+            .map(|_| {
+                connect_synthetic_peer(0, node_action_sender.clone(), |stream| {
+                    answer_pings_after(stream, Duration::ZERO)
+                })
+            })
+            .collect();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state.append_peers(peers).unwrap();
+        while context.iteration(false) {}
+        *peers_updates.borrow_mut() = 0;
+        let dead_peers = node_state.ping_peers(Duration::from_secs(600), Duration::from_secs(5));
+        assert!(dead_peers.is_empty());
+        drop(node_state);
+
+        // los pongs de los tres peers llegan dentro de PONG_UPDATE_INTERVAL y se avisan a la GUI una sola vez
+        for _ in 0..50 {
+            let answered = node_state_ref
+                .lock()
+                .unwrap()
+                .get_peers()
+                .iter()
+                .all(|peer| peer.avg_latency_ms().is_some());
+            if answered {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        while context.iteration(false) {}
+        assert_eq!(*peers_updates.borrow(), 1);
+
+        node_state_ref
+            .lock()
+            .unwrap()
+            .remove_all_peers(DisconnectReason::Terminated)
+            .unwrap();
+        node_action_sender.send(NodeAction::Terminate).unwrap();
+        node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_replaces_disconnected_peers() {
+        let store_path = String::from("tests/store_reconnect");
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (node_state_ref, node_action_sender, peer_action_receiver, node_action_loop) =
+            spawn_node_with_loop(&store_path, gui_sender);
+
+        // direccion del seed que atiende un peer sintetico
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let seed_address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            synthetic_peer_handshake_at(&mut stream, 0);
+            keep_connection_open(stream);
+        });
+
+        let (reconnect_sender, reconnect_receiver) = mpsc::channel();
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state.enable_reconnects(reconnect_sender, 2);
+        node_state.add_peer_addresses(&[seed_address]).unwrap();
+        let peers: Vec<Peer> = (0..2).map(|_| connect_peer_at_height(0)).collect();
+        let addresses: Vec<SocketAddrV6> = peers.iter().map(|peer| peer.address).collect();
+        node_state.append_peers(peers).unwrap();
+        assert_eq!(node_state.missing_peers(), 0);
+
+        // reemplazar un peer por una nueva conexion no pide reconectar
+        let mut replacing_peer = connect_peer_at_height(0);
+        replacing_peer.address = addresses[0];
+        node_state.append_peers(vec![replacing_peer]).unwrap();
+        assert!(reconnect_receiver.try_recv().is_err());
+
+        node_state
+            .remove_peer(addresses[1], DisconnectReason::RemoteClosed)
+            .unwrap();
+        assert_eq!(reconnect_receiver.try_recv(), Ok(1));
+        assert_eq!(node_state.reconnect_candidates(1), vec![seed_address]);
+        drop(node_state);
+
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        let call_logger_sender = logger_sender.clone();
+        let call_node_action_sender = node_action_sender.clone();
+        // se vuelve a pedir la reconexion, ya que el pedido anterior se consumio en el test
+        let (reconnect_sender, reconnect_receiver) = mpsc::channel();
+        reconnect_sender.send(1).unwrap();
+        ReconnectLoop::spawn(
+            logger_sender,
+            node_state_ref.clone(),
+            node_action_sender.clone(),
+            reconnect_receiver,
+            move |candidate| {
+                Peer::call(
+                    open_stream(candidate)?,
+                    SocketAddrV6::new(Ipv6Addr::LOCALHOST, 18333, 0, 0),
+                    0,
+                    70015,
+                    random_nonce(),
+                    DEFAULT_USER_AGENT,
+                    peer_action_receiver.clone(),
+                    call_logger_sender.clone(),
+                    call_node_action_sender.clone(),
+                )
+            },
+        );
+
+        let mut peers_count = 0;
+        for _ in 0..50 {
+            peers_count = node_state_ref.lock().unwrap().get_peers().len();
+            if peers_count == 2 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(peers_count, 2);
+        let mut node_state = node_state_ref.lock().unwrap();
+        let seed_address_v6 = get_address_v6(seed_address);
+        assert!(node_state
+            .get_peers()
+            .iter()
+            .any(|peer| peer.address == seed_address_v6));
+        // la direccion ya se intento recientemente, no vuelve a ser candidata
+        assert!(node_state.reconnect_candidates(1).is_empty());
+        node_state
+            .remove_all_peers(DisconnectReason::Terminated)
+            .unwrap();
+        drop(node_state);
+        drop(reconnect_sender);
+
+        node_action_sender.send(NodeAction::Terminate).unwrap();
+        node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    /// Peer sintetico que avisa por pushes_sender el hash de cada transaccion que le envia el nodo, junto a su indice.
+    fn record_pushed_txs(
+        index: usize,
+        pushes_sender: mpsc::Sender<(usize, Vec<u8>)>,
+    ) -> impl FnOnce(TcpStream) + Send + 'static {
+        move |mut stream| {
+            while let Ok(header) = MessageHeader::read(&mut stream) {
+                let mut payload = vec![0; header.payload_size as usize];
+                if stream.read_exact(&mut payload).is_err() {
+                    return;
+                }
+                if header.command == "tx" {
+                    let tx = Transaction::parse(payload).unwrap();
+                    let _ = pushes_sender.send((index, tx.hash()));
+                }
+            }
+        }
+    }
+
+    /// Peer sintetico que avisa por getdata_sender los hashes de cada getdata que le envia el nodo, junto a su indice.
+    fn record_getdata(
+        index: usize,
+        getdata_sender: mpsc::Sender<(usize, Vec<Vec<u8>>)>,
+    ) -> impl FnOnce(TcpStream) + Send + 'static {
+        move |mut stream| {
+            while let Ok(header) = MessageHeader::read(&mut stream) {
+                let mut payload = vec![0; header.payload_size as usize];
+                if stream.read_exact(&mut payload).is_err() {
+                    return;
+                }
+                if header.command == "getdata" {
+                    let getdata = GetData::parse(payload).unwrap();
+                    let hashes = getdata
+                        .get_inventories()
+                        .iter()
+                        .map(|inventory| inventory.hash.clone())
+                        .collect();
+                    let _ = getdata_sender.send((index, hashes));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn node_distributes_block_requests_across_peers() {
+        let store_path = String::from("tests/store_parallel_blocks");
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (node_state_ref, node_action_sender, _peer_action_receiver, node_action_loop) =
+            spawn_node_with_loop(&store_path, gui_sender);
+
+        let (getdata_sender, getdata_receiver) = mpsc::channel();
+        let peers = (0..2)
+            .map(|index| {
+                connect_synthetic_peer(
+                    0,
+                    node_action_sender.clone(),
+                    record_getdata(index, getdata_sender.clone()),
+                )
+            })
+            .collect();
+        node_state_ref.lock().unwrap().append_peers(peers).unwrap();
+
+        let headers = build_test_chain(10);
+        let address = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 18333, 0, 0);
+        node_action_sender
+            .send(NodeAction::NewHeaders(
+                address,
+                Headers {
+                    headers: headers.clone(),
+                },
+            ))
+            .unwrap();
+
+        // cada peer recibe un getdata con la mitad de los bloques
+        let mut peers_asked = HashSet::new();
+        let mut requested = HashSet::new();
+        for _ in 0..2 {
+            let (index, hashes) = getdata_receiver
+                .recv_timeout(Duration::from_secs(2))
+                .unwrap();
+            assert_eq!(hashes.len(), 5);
+            peers_asked.insert(index);
+            requested.extend(hashes);
+        }
+        assert_eq!(peers_asked, HashSet::from([0, 1]));
+        assert_eq!(
+            requested,
+            headers
+                .iter()
+                .map(|header| header.hash().clone())
+                .collect::<HashSet<_>>()
+        );
+
+        node_state_ref
+            .lock()
+            .unwrap()
+            .remove_all_peers(DisconnectReason::Terminated)
+            .unwrap();
+        node_action_sender.send(NodeAction::Terminate).unwrap();
+        node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn spv_node_requests_filtered_blocks_only_from_bloom_peers() {
+        let store_path = String::from("tests/store_spv_bloom_peers");
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (node_state_ref, node_action_sender, _peer_action_receiver, node_action_loop) =
+            spawn_node_with_loop(&store_path, gui_sender);
+        node_state_ref.lock().unwrap().set_spv_mode(true);
+
+        // el primer peer no anuncia NODE_BLOOM
+        let (getdata_sender, getdata_receiver) = mpsc::channel();
+        let peers = [NODE_NETWORK, NODE_NETWORK | NODE_BLOOM]
+            .into_iter()
+            .enumerate()
+            .map(|(index, services)| {
+                connect_synthetic_peer_with_services(
+                    0,
+                    70015,
+                    services,
+                    node_action_sender.clone(),
+                    record_getdata(index, getdata_sender.clone()),
+                )
+            })
+            .collect();
+        node_state_ref.lock().unwrap().append_peers(peers).unwrap();
+
+        let headers = build_test_chain(10);
+        let address = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 18333, 0, 0);
+        node_action_sender
+            .send(NodeAction::NewHeaders(
+                address,
+                Headers {
+                    headers: headers.clone(),
+                },
+            ))
+            .unwrap();
+
+        let mut requested = HashSet::new();
+        while let Ok((index, hashes)) = getdata_receiver.recv_timeout(Duration::from_secs(1)) {
+            assert_eq!(index, 1);
+            requested.extend(hashes);
+        }
+        assert_eq!(
+            requested,
+            headers
+                .iter()
+                .map(|header| header.hash().clone())
+                .collect::<HashSet<_>>()
+        );
+
+        node_state_ref
+            .lock()
+            .unwrap()
+            .remove_all_peers(DisconnectReason::Terminated)
+            .unwrap();
+        node_action_sender.send(NodeAction::Terminate).unwrap();
+        node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_retries_stale_block_requests_on_another_peer() {
+        let store_path = String::from("tests/store_stale_block_retry");
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (node_state_ref, node_action_sender, _peer_action_receiver, node_action_loop) =
+            spawn_node_with_loop(&store_path, gui_sender);
+
+        // ningun peer responde los getdata, el bloque vence y se pide al otro peer
+        let (getdata_sender, getdata_receiver) = mpsc::channel();
+        let peers = (0..2)
+            .map(|index| {
+                connect_synthetic_peer(
+                    0,
+                    node_action_sender.clone(),
+                    record_getdata(index, getdata_sender.clone()),
+                )
+            })
+            .collect();
+
+        let block_hash = vec![7; 32];
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state
+            .set_stale_block_timeout(Duration::from_millis(100))
+            .unwrap();
+        node_state.append_peers(peers).unwrap();
+        let failed_peers = node_state
+            .request_blocks(vec![(
+                Inventory::new(InventoryType::Block, block_hash.clone()),
+                None,
+            )])
+            .unwrap();
+        assert!(failed_peers.is_empty());
+        drop(node_state);
+
+        let (first_peer, hashes) = getdata_receiver
+            .recv_timeout(Duration::from_secs(2))
+            .unwrap();
+        assert_eq!(hashes, vec![block_hash.clone()]);
+
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        spawn_pending_blocks_task(&node_state_ref, logger_sender, Duration::from_millis(20));
+
+        let (retry_peer, hashes) = getdata_receiver
+            .recv_timeout(Duration::from_secs(2))
+            .unwrap();
+        assert_ne!(retry_peer, first_peer);
+        assert_eq!(hashes, vec![block_hash]);
+
+        node_state_ref
+            .lock()
+            .unwrap()
+            .remove_all_peers(DisconnectReason::Terminated)
+            .unwrap();
+        node_action_sender.send(NodeAction::Terminate).unwrap();
+        node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_serves_stored_block_on_getdata() {
+        let store_path = String::from("tests/store_serve_block");
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+
+        // se guarda con el nombre de la version 1 del store, el nodo lo importa al iniciar
+        let _ = fs::remove_dir_all(&store_path);
+        fs::create_dir_all(format!("{}/blocks", store_path)).unwrap();
+        let block = Block::parse(fs::read("tests/blocks/test_block.bin").unwrap()).unwrap();
+        block
+            .save(format!(
+                "{}/blocks/{}.bin",
+                store_path,
+                block.header.hash_as_string()
+            ))
+            .unwrap();
+        let (node_state_ref, node_action_sender, _peer_action_receiver, node_action_loop) =
+            spawn_node_with_loop_on_store(&store_path, gui_sender);
+
+        // el peer pide el bloque guardado y uno que no tenemos
+        let block_hash = block.header.hash().clone();
+        let (served_sender, served_receiver) = mpsc::channel();
+        let peer = connect_synthetic_peer(0, node_action_sender.clone(), move |mut stream| {
+            GetData::new(vec![
+                Inventory::new(InventoryType::Block, block_hash),
+                Inventory::new(InventoryType::Block, vec![0; 32]),
+            ])
+            .send(&mut stream)
+            .unwrap();
+            while let Ok(header) = MessageHeader::read(&mut stream) {
+                let mut payload = vec![0; header.payload_size as usize];
+                if stream.read_exact(&mut payload).is_err() {
+                    return;
+                }
+                if header.command == "block" || header.command == "notfound" {
+                    let _ = served_sender.send((header.command, payload));
+                }
+            }
+        });
+        node_state_ref
+            .lock()
+            .unwrap()
+            .append_peers(vec![peer])
+            .unwrap();
+
+        let (command, payload) = served_receiver
+            .recv_timeout(Duration::from_secs(2))
+            .unwrap();
+        assert_eq!(command, "block");
+        assert_eq!(payload, block.serialize());
+        let (command, _) = served_receiver
+            .recv_timeout(Duration::from_secs(2))
+            .unwrap();
+        assert_eq!(command, "notfound");
+
+        node_state_ref
+            .lock()
+            .unwrap()
+            .remove_all_peers(DisconnectReason::Terminated)
+            .unwrap();
+        node_action_sender.send(NodeAction::Terminate).unwrap();
+        node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_serves_filtered_blocks_with_the_peer_filter() {
+        let store_path = String::from("tests/store_serve_filtered_block");
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let _ = fs::remove_dir_all(&store_path);
+        let block = Block::parse(fs::read("tests/blocks/test_block.bin").unwrap()).unwrap();
+        BlockStore::open(&store_path)
+            .unwrap()
+            .append(block.header.hash(), &block.serialize())
+            .unwrap();
+        let (node_state_ref, node_action_sender, _peer_action_receiver, node_action_loop) =
+            spawn_node_with_loop_on_store(&store_path, gui_sender);
+
+        // el peer carga un filtro con la segunda transaccion, pide el bloque filtrado y luego lo vuelve a pedir sin filtro
+        let matched_tx = block.transactions[1].clone();
+        let mut filter = BloomFilter::new(1, 0.000001, 0, BLOOM_UPDATE_NONE);
+        filter.insert(&matched_tx.hash());
+        let block_hash = block.header.hash().clone();
+        let (served_sender, served_receiver) = mpsc::channel();
+        let peer = connect_synthetic_peer(0, node_action_sender.clone(), move |mut stream| {
+            let filtered_block = Inventory::new(InventoryType::FilteredBlock, block_hash);
+            FilterLoad::new(filter).send(&mut stream).unwrap();
+            GetData::new(vec![filtered_block.clone()])
+                .send(&mut stream)
+                .unwrap();
+            FilterClear::new().send(&mut stream).unwrap();
+            GetData::new(vec![
+                filtered_block,
+                Inventory::new(InventoryType::FilteredBlock, vec![0; 32]),
+            ])
+            .send(&mut stream)
+            .unwrap();
+            while let Ok(header) = MessageHeader::read(&mut stream) {
+                let mut payload = vec![0; header.payload_size as usize];
+                if stream.read_exact(&mut payload).is_err() {
+                    return;
+                }
+                if ["merkleblock", "tx", "notfound"].contains(&header.command.as_str()) {
+                    let _ = served_sender.send((header.command, payload));
+                }
+            }
+        });
+        node_state_ref
+            .lock()
+            .unwrap()
+            .append_peers(vec![peer])
+            .unwrap();
+
+        let mut served = vec![];
+        for _ in 0..4 {
+            served.push(
+                served_receiver
+                    .recv_timeout(Duration::from_secs(2))
+                    .unwrap(),
+            );
+        }
+        let commands: Vec<&str> = served.iter().map(|(command, _)| command.as_str()).collect();
+        assert_eq!(
+            commands,
+            vec!["merkleblock", "tx", "merkleblock", "notfound"]
+        );
+
+        let merkle_block = MerkleBlock::parse(served[0].1.clone()).unwrap();
+        assert_eq!(merkle_block.header.hash(), block.header.hash());
+        assert_eq!(
+            merkle_block.extract_matches().unwrap(),
+            vec![matched_tx.hash()]
+        );
+        assert_eq!(served[1].1, matched_tx.serialize());
+
+        // sin filtro el merkleblock solo tiene el header
+        let merkle_block = MerkleBlock::parse(served[2].1.clone()).unwrap();
+        assert!(merkle_block.extract_matches().unwrap().is_empty());
+
+        node_state_ref
+            .lock()
+            .unwrap()
+            .remove_all_peers(DisconnectReason::Terminated)
+            .unwrap();
+        node_action_sender.send(NodeAction::Terminate).unwrap();
+        node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    /// Registra los eventos de propagacion de transacciones que recibe la interfaz, como ("seen" | "stuck", txid).
+    fn record_propagation_events(
+        gui_receiver: glib::Receiver<GUIEvents>,
+        context: &glib::MainContext,
+    ) -> Rc<RefCell<Vec<(&'static str, String)>>> {
+        let propagation_events = Rc::new(RefCell::new(vec![]));
+        let events = propagation_events.clone();
+        gui_receiver.attach(Some(context), move |event| {
+            match event {
+                GUIEvents::TransactionSeenOnNetwork { txid } => {
+                    events.borrow_mut().push(("seen", txid))
+                }
+                GUIEvents::TransactionMayNotHavePropagated { txid } => {
+                    events.borrow_mut().push(("stuck", txid))
+                }
+                _ => {}
+            }
+            glib::Continue(true)
+        });
+        propagation_events
+    }
+
+    fn wait_for_gui_events<T>(
+        context: &glib::MainContext,
+        events: &Rc<RefCell<Vec<T>>>,
+        count: usize,
+    ) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while events.borrow().len() < count && Instant::now() < deadline {
+            while context.iteration(false) {}
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn node_marks_transaction_seen_when_another_peer_announces_it() {
+        let store_path = String::from("tests/store_tx_echo");
+        let (gui_sender, gui_receiver) = glib::MainContext::channel(Priority::default());
+        let context = glib::MainContext::new();
+        let propagation_events = record_propagation_events(gui_receiver, &context);
+        let (node_state_ref, node_action_sender, _peer_action_receiver, node_action_loop) =
+            spawn_node_with_loop(&store_path, gui_sender);
+
+        // el segundo peer anuncia de vuelta la transaccion que recibio el primero
+        let (pushes_sender, pushes_receiver) = mpsc::channel();
+        let (echo_sender, echo_receiver) = mpsc::channel();
+        let pushed_peer = connect_synthetic_peer(
+            0,
+            node_action_sender.clone(),
+            record_pushed_txs(0, pushes_sender.clone()),
+        );
+        let echo_pushes_sender = pushes_sender.clone();
+        let echo_peer = connect_synthetic_peer(0, node_action_sender.clone(), move |mut stream| {
+            let tx_hash: Vec<u8> = echo_receiver.recv().unwrap();
+            Inv::new(vec![Inventory::new(InventoryType::Tx, tx_hash)])
+                .send(&mut stream)
+                .unwrap();
+            record_pushed_txs(1, echo_pushes_sender)(stream);
+        });
+        let idle_peer = connect_synthetic_peer(
+            0,
+            node_action_sender.clone(),
+            record_pushed_txs(2, pushes_sender),
+        );
+
+        let tx = test_transaction(1);
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state.set_broadcast_peers(1);
+        node_state
+            .append_peers(vec![pushed_peer, echo_peer, idle_peer])
+            .unwrap();
+        node_state.append_pending_tx(tx.clone()).unwrap();
+        assert!(node_state.push_transaction(&tx).is_empty());
+        drop(node_state);
+
+        let push = pushes_receiver
+            .recv_timeout(Duration::from_secs(2))
+            .unwrap();
+        assert_eq!(push, (0, tx.hash()));
+        echo_sender.send(tx.hash()).unwrap();
+
+        wait_for_gui_events(&context, &propagation_events, 1);
+        assert_eq!(
+            *propagation_events.borrow(),
+            vec![("seen", hash_as_string(tx.hash()))]
+        );
+
+        // una transaccion vista en la red no se reenvia
+        let mut node_state = node_state_ref.lock().unwrap();
+        assert!(node_state
+            .check_tx_propagation(Duration::ZERO)
+            .unwrap()
+            .is_empty());
+        drop(node_state);
+        assert!(pushes_receiver
+            .recv_timeout(Duration::from_millis(300))
+            .is_err());
+        while context.iteration(false) {}
+        assert_eq!(propagation_events.borrow().len(), 1);
+
+        node_state_ref
+            .lock()
+            .unwrap()
+            .remove_all_peers(DisconnectReason::Terminated)
+            .unwrap();
+        node_action_sender.send(NodeAction::Terminate).unwrap();
+        node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    #[test]
+    fn node_escalates_transaction_nobody_announces() {
+        let store_path = String::from("tests/store_tx_no_echo");
+        let (gui_sender, gui_receiver) = glib::MainContext::channel(Priority::default());
+        let context = glib::MainContext::new();
+        let propagation_events = record_propagation_events(gui_receiver, &context);
+        let (node_state_ref, node_action_sender, _peer_action_receiver, node_action_loop) =
+            spawn_node_with_loop(&store_path, gui_sender);
+
+        let (pushes_sender, pushes_receiver) = mpsc::channel();
+        let peers = (0..3)
+            .map(|index| {
+                connect_synthetic_peer(
+                    0,
+                    node_action_sender.clone(),
+                    record_pushed_txs(index, pushes_sender.clone()),
+                )
+            })
+            .collect();
+
+        let tx = test_transaction(2);
+        let mut node_state = node_state_ref.lock().unwrap();
+        node_state.set_broadcast_peers(1);
+        node_state.append_peers(peers).unwrap();
+        node_state.append_pending_tx(tx.clone()).unwrap();
+        assert!(node_state.push_transaction(&tx).is_empty());
+        drop(node_state);
+
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        tx_propagation_loop(
+            node_state_ref.clone(),
+            node_action_sender.clone(),
+            logger_sender,
+            Duration::from_millis(100),
+        );
+
+        // sin anuncios se reenvia a un peer nuevo por vez, hasta avisar que puede no haberse propagado
+        let pushes: Vec<(usize, Vec<u8>)> = (0..3)
+            .map(|_| {
+                pushes_receiver
+                    .recv_timeout(Duration::from_secs(2))
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(pushes, vec![(0, tx.hash()), (1, tx.hash()), (2, tx.hash())]);
+
+        wait_for_gui_events(&context, &propagation_events, 1);
+        assert_eq!(
+            *propagation_events.borrow(),
+            vec![("stuck", hash_as_string(tx.hash()))]
+        );
+        assert!(pushes_receiver
+            .recv_timeout(Duration::from_millis(300))
+            .is_err());
+
+        node_state_ref
+            .lock()
+            .unwrap()
+            .remove_all_peers(DisconnectReason::Terminated)
+            .unwrap();
+        node_action_sender.send(NodeAction::Terminate).unwrap();
+        node_action_loop.join().unwrap();
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    /// Escribe en el store el config de un nodo de la red privada de los tests de dos nodos y lo lee.
+    fn two_nodes_config(store_path: &str, settings: &str) -> Config {
+        let config_path = format!("{store_path}/node.conf");
+        let key = "07".repeat(32);
+        fs::write(
+            &config_path,
+            format!("SEED=seed.test\nPROTOCOL_VERSION=70015\nNPEERS=1\nREGTEST=true\nPRIVATE_NETWORK_KEY={key}\n{settings}"),
+        )
+        .unwrap();
+        Config::from_file(&config_path).unwrap()
+    }
+
+    /// Devuelve el bloque guardado en el store del nodo, tal como esta en su archivo blk.
+    fn stored_block(node_state: &NodeState, block_hash: &[u8]) -> Vec<u8> {
+        let mut buffer = vec![];
+        node_state
+            .get_block_file(block_hash)
+            .unwrap()
+            .read_to_end(&mut buffer)
+            .unwrap();
+        buffer
+    }
+
+    /// Un nodo sincronizado con una cadena sintetica atiende a un nodo nuevo, que lo conoce solo por PRIVATE_PEERS,
+    /// y este se sincroniza unicamente con lo que le envia: handshake, headers, bloques y generacion de las UTXO.
+    /// Tarda mas que el resto de los tests, se corre con cargo test -- --ignored.
+    #[test]
+    #[ignore]
+    fn fresh_node_syncs_from_another_node_over_localhost() {
+        let serving_path = String::from("tests/store_two_nodes_serving");
+        let fresh_path = String::from("tests/store_two_nodes_fresh");
+        for store_path in [&serving_path, &fresh_path] {
+            let _ = fs::remove_dir_all(store_path);
+            fs::create_dir(store_path).unwrap();
+        }
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let chain = SyntheticChain::generate(400, 300, 4, SYNTHETIC_MIN_DIFFICULTY_BITS).unwrap();
+
+        // A tiene el store sincronizado con la cadena y acepta conexiones
+        let serving_logger =
+            Logger::new(&format!("{serving_path}/log.txt"), gui_sender.clone()).unwrap();
+        let serving_state = NodeState::new(
+            serving_logger.get_sender(),
+            gui_sender.clone(),
+            &serving_path,
+        )
+        .unwrap();
+        let mut node_state = serving_state.lock().unwrap();
+        node_state
+            .append_headers(&Headers {
+                headers: chain.headers.clone(),
+            })
+            .unwrap();
+        for block in &chain.blocks {
+            node_state
+                .append_block(block.header.hash().clone(), block)
+                .unwrap();
+        }
+        assert!(node_state.is_synced());
+        drop(node_state);
+
+        let serving_config = two_nodes_config(&serving_path, "PORT=18337\nLISTEN=true\n");
+        let serving_node =
+            Node::new(&serving_config, &serving_logger, serving_state.clone()).unwrap();
+        let serving_action_sender = serving_node.node_action_sender.clone();
+        let serving_thread = serving_node.spawn(vec![].into_iter(), gui_sender.clone());
+        thread::sleep(Duration::from_millis(500));
+
+        // B arranca con el store vacio y sin seed, solo conoce a A como nodo de la red privada
+        let fresh_logger =
+            Logger::new(&format!("{fresh_path}/log.txt"), gui_sender.clone()).unwrap();
+        let fresh_state =
+            NodeState::new(fresh_logger.get_sender(), gui_sender.clone(), &fresh_path).unwrap();
+        let fresh_config = two_nodes_config(
+            &fresh_path,
+            "PORT=18338\nLISTEN=false\nPRIVATE_PEERS=127.0.0.1:18337\n",
+        );
+        let fresh_node = Node::new(&fresh_config, &fresh_logger, fresh_state.clone()).unwrap();
+        let fresh_action_sender = fresh_node.node_action_sender.clone();
+        let fresh_thread = fresh_node.spawn(vec![].into_iter(), gui_sender);
+
+        let mut synced = false;
+        for _ in 0..120 {
+            synced = fresh_state.lock().unwrap().is_synced();
+            if synced {
+                break;
+            }
+            thread::sleep(Duration::from_millis(500));
+        }
+        assert!(synced);
+
+        let serving = serving_state.lock().unwrap();
+        let fresh = fresh_state.lock().unwrap();
+        let tip = chain.headers.last().unwrap().hash();
+        assert_eq!(fresh.get_last_header_hash().as_ref(), Some(tip));
+        assert_eq!(fresh.height_of_block(tip), Some(chain.headers.len()));
+        assert_eq!(fresh.block_hash_at_height(chain.headers.len() + 1), None);
+
+        let sampled = chain.blocks.iter().step_by(50).chain(chain.blocks.last());
+        for block in sampled {
+            let block_hash = block.header.hash();
+            assert_eq!(
+                stored_block(&fresh, block_hash),
+                stored_block(&serving, block_hash)
+            );
+        }
+
+        let serving_utxo = serving.export_utxo_snapshot().unwrap();
+        assert_eq!(serving_utxo.height, chain.headers.len());
+        assert_eq!(
+            fresh.export_utxo_snapshot().unwrap().serialize(),
+            serving_utxo.serialize()
+        );
+        drop((serving, fresh));
+
+        for (action_sender, node_thread) in [
+            (fresh_action_sender, fresh_thread),
+            (serving_action_sender, serving_thread),
+        ] {
+            action_sender.send(NodeAction::Terminate).unwrap();
+            node_thread.join().unwrap().unwrap();
+        }
+        for logger in [fresh_logger, serving_logger] {
+            logger.get_sender().send(Log::Terminate).unwrap();
+            logger.thread.join().unwrap().unwrap();
+        }
+        fs::remove_dir_all(&serving_path).unwrap();
+        fs::remove_dir_all(&fresh_path).unwrap();
+    }
+
+    #[test]
+    fn startup_failures_map_to_exit_codes() {
+        let store_path = String::from("tests/store_exit_codes");
+        let _ = fs::remove_dir_all(&store_path);
+        fs::create_dir_all(&store_path).unwrap();
+
+        let missing = Config::from_file(&format!("{store_path}/missing.conf"))
+            .err()
+            .unwrap();
+        assert_eq!(exit_code(&missing), EXIT_CONFIG_ERROR);
+
+        let config_path = format!("{store_path}/invalid.conf");
+        fs::write(
+            &config_path,
+            "SEED=seed.test\nPROTOCOL_VERSION=70015\nPORT=abc\n",
+        )
+        .unwrap();
+        let invalid = Config::from_file(&config_path).err().unwrap();
+        assert_eq!(exit_code(&invalid), EXIT_CONFIG_ERROR);
+
+        let store_lock = StoreLock::acquire(&store_path).unwrap();
+        let locked = StoreLock::acquire(&store_path).err().unwrap();
+        assert_eq!(exit_code(&locked), EXIT_STORE_LOCKED);
+        drop(store_lock);
+
+        // una pending tx cortada a la mitad no se puede recuperar
+        fs::write(format!("{store_path}/pending_txs.bin"), [1, 2, 3]).unwrap();
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        let Err(corrupt) = NodeState::new(logger_sender, gui_sender, &store_path) else { panic!("expected a corrupt store") };
+        assert_eq!(exit_code(&corrupt), EXIT_STORE_CORRUPT);
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    /// Lo ejecuta node_writes_crash_report_on_panic en otro proceso, ya que el panic hook termina el proceso.
+    #[test]
+    #[ignore]
+    fn crash_report_harness() {
+        let Ok(store_path) = env::var("CRASH_REPORT_STORE") else { return };
+        let log_history = LogHistory::default();
+        for index in 0..LOG_HISTORY_LINES + 50 {
+            log_history.push(format!("log line {index}"));
+        }
+        let node_state_slot = install_panic_hook(store_path.clone(), log_history);
+
+        let (gui_sender, _gui_receiver) = glib::MainContext::channel(Priority::default());
+        let (logger_sender, _logger_receiver) = mpsc::channel();
+        let node_state_ref = NodeState::new(logger_sender, gui_sender, &store_path).unwrap();
+        let _ = node_state_slot.set(node_state_ref);
+
+        let _ = thread::Builder::new()
+            .name("crashing-thread".to_string())
+            .spawn(|| panic!("controlled panic"))
+            .unwrap()
+            .join();
+        unreachable!("the panic hook ends the process");
+    }
+
+    #[test]
+    fn node_writes_crash_report_on_panic() {
+        let store_path = String::from("tests/store_crash_report");
+        let _ = fs::remove_dir_all(&store_path);
+
+        let status = Command::new(env::current_exe().unwrap())
+            .args(["tests::crash_report_harness", "--exact", "--ignored"])
+            .env("CRASH_REPORT_STORE", &store_path)
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(EXIT_PANIC));
+
+        let reports: Vec<_> = fs::read_dir(&store_path)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .starts_with("crash-")
+            })
+            .collect();
+        assert_eq!(reports.len(), 1);
+        let report = fs::read_to_string(&reports[0]).unwrap();
+        assert!(report.starts_with("Crash report\nTimestamp: "));
+        assert!(report.contains("\nThread: crashing-thread\n"));
+        assert!(report.contains("\nPanic: controlled panic at tests/integration_tests.rs:"));
+        // el nodo no tiene headers, el estado se pudo obtener aunque el thread entro en panico
+        assert!(report.contains("\nStatus: [headers] "));
+        assert!(report.contains(" | peers 0 | height 0 | mempool 0\n"));
+        assert!(report.contains(&format!(
+            "\nLast {LOG_HISTORY_LINES} log lines:\nlog line 50\n"
+        )));
+        assert!(report.ends_with(&format!("log line {}\n", LOG_HISTORY_LINES + 49)));
+        assert!(!report.contains("\nlog line 49\n"));
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
+
+    /// Peer falso para el ejemplo sync_headers: atiende el handshake del nodo, responde el primer getheaders
+    /// con headers y los siguientes sin headers, e ignora el resto de los mensajes hasta que el nodo se desconecta.
+    fn serve_headers_to_one_node(listener: TcpListener, headers: Vec<BlockHeader>) {
+        let (mut stream, _) = listener.accept().unwrap();
+        let start_height = headers.len() as i32;
+        let mut headers = Some(headers);
+        while let Ok(header) = MessageHeader::read(&mut stream) {
+            let mut payload = vec![0; header.payload_size as usize];
+            if stream.read_exact(&mut payload).is_err() {
+                break;
+            }
+            let sent = match header.command.as_str() {
+                "version" => {
+                    let address =
+                        SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), 18333, 0, 0);
+                    let mut version = Version::new(address, address, 70015, 0, 0);
+                    version.start_height = start_height;
+                    version
+                        .send(&mut stream)
+                        .and_then(|_| VerAck::new().send(&mut stream))
+                }
+                "getheaders" => Headers {
+                    headers: headers.take().unwrap_or_default(),
+                }
+                .send(&mut stream),
+                "ping" => Pong {
+                    nonce: Ping::parse(payload).unwrap().nonce,
+                }
+                .send(&mut stream),
+                _ => Ok(()),
+            };
+            if sent.is_err() {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn sync_headers_example_syncs_from_a_fake_peer() {
+        let store_path = String::from("tests/store_example_sync_headers");
+        let _ = fs::remove_dir_all(&store_path);
+        fs::create_dir(&store_path).unwrap();
+        let headers = build_test_chain(10);
+        let tip_hash = display_hash(headers[9].hash());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || serve_headers_to_one_node(listener, headers));
+
+        // el nodo solo conoce al peer falso, como seed
+        let config_path = format!("{store_path}/node.conf");
+        fs::write(
+            &config_path,
+            format!("SEED=127.0.0.1\nPORT={port}\nPROTOCOL_VERSION=70015\nNPEERS=1\nLISTEN=false\nREGTEST=true\nSTORE_PATH={store_path}\nLOG={store_path}/log.txt\n"),
+        )
+        .unwrap();
+
+        let tip = super::sync_headers_example::run(&config_path, Duration::from_secs(30)).unwrap();
+        assert_eq!(tip, (10, tip_hash));
+
+        fs::remove_dir_all(&store_path).unwrap();
+    }
 }
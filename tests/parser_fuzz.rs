@@ -0,0 +1,370 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+
+/// Tamaño maximo de una unica reserva de memoria en estos tests.
+/// Los buffers generados tienen a lo sumo unos pocos KB, asi que un parser que reserve mas
+/// esta confiando en un largo leido del buffer: el test aborta en vez de quedarse sin memoria.
+const ALLOCATION_CAP: usize = 16 * 1024 * 1024;
+
+/// Allocator del sistema que rechaza las reservas que superan ALLOCATION_CAP.
+struct CappedAllocator;
+
+unsafe impl GlobalAlloc for CappedAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() > ALLOCATION_CAP {
+            return std::ptr::null_mut();
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if new_size > ALLOCATION_CAP {
+            return std::ptr::null_mut();
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CappedAllocator = CappedAllocator;
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv6Addr, SocketAddrV6};
+
+    use bitcoin::{
+        message::Message,
+        messages::{
+            block::Block,
+            block_txn::{BlockTxn, GetBlockTxn},
+            compact_block::CompactBlock,
+            get_blocks::GetBlocks,
+            get_data::GetData,
+            get_headers::GetHeaders,
+            headers::Headers,
+            inv::Inv,
+            mempool::Mempool,
+            not_found::NotFound,
+            ping_pong::{Ping, Pong},
+            send_compact::SendCompact,
+            send_headers::SendHeaders,
+            transaction::Transaction,
+            ver_ack::VerAck,
+            version::Version,
+        },
+        mining,
+        structs::{
+            block_header::BlockHeader,
+            inventory::{Inventory, InventoryType},
+            outpoint::OutPoint,
+            tx_input::TransactionInput,
+            tx_output::TransactionOutput,
+        },
+    };
+    use proptest::{array::uniform32, collection::vec, prelude::*, sample::Index};
+
+    /// Bits de dificultad minima, para que minar un header en los tests sea inmediato.
+    const TEST_BITS: u32 = 0x207fffff;
+
+    fn arb_address() -> impl Strategy<Value = SocketAddrV6> {
+        (any::<[u16; 8]>(), any::<u16>()).prop_map(|(segments, port)| {
+            let ip = Ipv6Addr::new(
+                segments[0],
+                segments[1],
+                segments[2],
+                segments[3],
+                segments[4],
+                segments[5],
+                segments[6],
+                segments[7],
+            );
+            SocketAddrV6::new(ip, port, 0, 0)
+        })
+    }
+
+    fn arb_version() -> impl Strategy<Value = Version> {
+        (
+            any::<(i32, u64, u64, u64, u64, u64, i32)>(),
+            arb_address(),
+            arb_address(),
+            "[a-zA-Z0-9/:. ]{0,64}",
+        )
+            .prop_map(
+                |(
+                    (
+                        version,
+                        services,
+                        timestamp,
+                        receiver_services,
+                        sender_services,
+                        nonce,
+                        start_height,
+                    ),
+                    receiver_address,
+                    sender_address,
+                    user_agent,
+                )| Version {
+                    version,
+                    services,
+                    timestamp,
+                    receiver_services,
+                    receiver_address,
+                    sender_services,
+                    sender_address,
+                    nonce,
+                    user_agent_length: user_agent.len() as u8,
+                    user_agent,
+                    start_height,
+                },
+            )
+    }
+
+    fn arb_inventory() -> impl Strategy<Value = Inventory> {
+        let inventory_type = prop_oneof![
+            Just(InventoryType::Tx),
+            Just(InventoryType::Block),
+            Just(InventoryType::FilteredBlock),
+            Just(InventoryType::CompactBlock),
+            Just(InventoryType::WitnessTx),
+            Just(InventoryType::WitnessBlock),
+            Just(InventoryType::FilteredWitnessBlock),
+        ];
+        (inventory_type, uniform32(any::<u8>()))
+            .prop_map(|(inventory_type, hash)| Inventory::new(inventory_type, hash.to_vec()))
+    }
+
+    fn arb_input() -> impl Strategy<Value = TransactionInput> {
+        (
+            uniform32(any::<u8>()),
+            any::<u32>(),
+            vec(any::<u8>(), 0..300),
+            any::<u32>(),
+        )
+            .prop_map(|(hash, index, script_sig, sequence)| TransactionInput {
+                previous_output: OutPoint {
+                    hash: hash.to_vec(),
+                    index,
+                },
+                script_sig,
+                sequence,
+            })
+    }
+
+    fn arb_output() -> impl Strategy<Value = TransactionOutput> {
+        (any::<u64>(), vec(any::<u8>(), 0..300)).prop_map(|(value, script_pubkey)| {
+            TransactionOutput {
+                value,
+                script_pubkey,
+            }
+        })
+    }
+
+    /// Las transacciones sin inputs no se generan: en la red se leerian como el marker de segwit.
+    fn arb_transaction() -> impl Strategy<Value = Transaction> {
+        (
+            any::<u32>(),
+            vec(arb_input(), 1..4),
+            vec(arb_output(), 0..4),
+            any::<u32>(),
+        )
+            .prop_map(|(version, inputs, outputs, lock_time)| Transaction {
+                version,
+                inputs,
+                outputs,
+                lock_time,
+            })
+    }
+
+    /// Busca el primer nonce que cumple la proof of work con TEST_BITS.
+    fn mine_header(
+        version: i32,
+        prev_block_hash: &[u8],
+        merkle_root: &[u8],
+        timestamp: u32,
+    ) -> BlockHeader {
+        mining::mine_header(
+            version,
+            prev_block_hash,
+            merkle_root,
+            timestamp,
+            TEST_BITS,
+            u32::MAX,
+        )
+        .unwrap()
+    }
+
+    fn arb_header() -> impl Strategy<Value = BlockHeader> {
+        (
+            any::<i32>(),
+            uniform32(any::<u8>()),
+            uniform32(any::<u8>()),
+            any::<u32>(),
+        )
+            .prop_map(|(version, prev_block_hash, merkle_root, timestamp)| {
+                mine_header(version, &prev_block_hash, &merkle_root, timestamp)
+            })
+    }
+
+    fn arb_block() -> impl Strategy<Value = Block> {
+        (arb_header(), vec(arb_transaction(), 0..4))
+            .prop_map(|(header, transactions)| Block::new(header, transactions))
+    }
+
+    /// Mensajes validos serializados de cada tipo, para truncarlos o modificarlos.
+    fn arb_serialized_message() -> impl Strategy<Value = (&'static str, Vec<u8>)> {
+        prop_oneof![
+            arb_version().prop_map(|version| ("version", version.serialize())),
+            vec(arb_header(), 0..4)
+                .prop_map(|headers| ("headers", Headers { headers }.serialize())),
+            vec(arb_inventory(), 0..8)
+                .prop_map(|inventories| ("inv", Inv::new(inventories).serialize())),
+            vec(arb_inventory(), 0..8)
+                .prop_map(|inventories| { ("getdata", GetData::new(inventories).serialize()) }),
+            arb_block().prop_map(|block| ("block", block.serialize())),
+            arb_transaction().prop_map(|transaction| ("tx", transaction.serialize())),
+        ]
+    }
+
+    /// Parsea el buffer con el parser del comando recibido.
+    fn parse_command(command: &str, buffer: Vec<u8>) -> bool {
+        match command {
+            "version" => Version::parse(buffer).is_ok(),
+            "headers" => Headers::parse(buffer).is_ok(),
+            "inv" => Inv::parse(buffer).is_ok(),
+            "getdata" => GetData::parse(buffer).is_ok(),
+            "block" => Block::parse(buffer).is_ok(),
+            "tx" => Transaction::parse(buffer).is_ok(),
+            _ => false,
+        }
+    }
+
+    /// Pasa el buffer por todos los parsers del protocolo. Solo importa que ninguno entre en panic.
+    fn parse_with_every_parser(buffer: &[u8]) {
+        let _ = Version::parse(buffer.to_vec());
+        let _ = VerAck::parse(buffer.to_vec());
+        let _ = Ping::parse(buffer.to_vec());
+        let _ = Pong::parse(buffer.to_vec());
+        let _ = SendHeaders::parse(buffer.to_vec());
+        let _ = SendCompact::parse(buffer.to_vec());
+        let _ = Mempool::parse(buffer.to_vec());
+        let _ = GetHeaders::parse(buffer.to_vec());
+        let _ = GetBlocks::parse(buffer.to_vec());
+        let _ = Headers::parse(buffer.to_vec());
+        let _ = Inv::parse(buffer.to_vec());
+        let _ = GetData::parse(buffer.to_vec());
+        let _ = NotFound::parse(buffer.to_vec());
+        let _ = Block::parse(buffer.to_vec());
+        let _ = Transaction::parse(buffer.to_vec());
+        let _ = CompactBlock::parse(buffer.to_vec());
+        let _ = GetBlockTxn::parse(buffer.to_vec());
+        let _ = BlockTxn::parse(buffer.to_vec());
+        let _ = BlockHeader::parse(buffer.to_vec());
+        let _ = OutPoint::parse(buffer.to_vec());
+        let _ = Inventory::parse(buffer.to_vec());
+    }
+
+    proptest! {
+        #[test]
+        fn version_roundtrip(version in arb_version()) {
+            let parsed = Version::parse(version.serialize()).unwrap();
+            prop_assert_eq!(parsed, version);
+        }
+
+        #[test]
+        fn headers_roundtrip(headers in vec(arb_header(), 0..8)) {
+            let headers = Headers { headers };
+            let serialized = headers.serialize();
+            let parsed = Headers::parse(serialized.clone()).unwrap();
+            prop_assert_eq!(parsed.headers.len(), headers.headers.len());
+            for (parsed, header) in parsed.headers.iter().zip(&headers.headers) {
+                prop_assert_eq!(parsed.hash(), header.hash());
+            }
+            prop_assert_eq!(parsed.serialize(), serialized);
+        }
+
+        #[test]
+        fn inv_roundtrip(inventories in vec(arb_inventory(), 0..16)) {
+            let inv = Inv::new(inventories);
+            prop_assert_eq!(Inv::parse(inv.serialize()).unwrap(), inv);
+        }
+
+        #[test]
+        fn get_data_roundtrip(inventories in vec(arb_inventory(), 0..16)) {
+            let get_data = GetData::new(inventories.clone());
+            let parsed = GetData::parse(get_data.serialize()).unwrap();
+            prop_assert_eq!(parsed.get_inventories(), &inventories);
+        }
+
+        #[test]
+        fn transaction_roundtrip(transaction in arb_transaction()) {
+            let serialized = transaction.serialize();
+            let parsed = Transaction::parse(serialized.clone()).unwrap();
+            prop_assert_eq!(parsed.hash(), transaction.hash());
+            prop_assert_eq!(parsed.serialize(), serialized);
+        }
+
+        #[test]
+        fn block_roundtrip(block in arb_block()) {
+            let serialized = block.serialize();
+            let parsed = Block::parse(serialized.clone()).unwrap();
+            prop_assert_eq!(parsed.header.hash(), block.header.hash());
+            prop_assert_eq!(parsed.transactions.len(), block.transactions.len());
+            prop_assert_eq!(parsed.serialize(), serialized);
+        }
+
+        #[test]
+        fn random_bytes_never_panic(buffer in vec(any::<u8>(), 0..1024)) {
+            parse_with_every_parser(&buffer);
+        }
+
+        #[test]
+        fn mutated_messages_never_panic(
+            (_, mut buffer) in arb_serialized_message(),
+            index in any::<Index>(),
+            value in any::<u8>(),
+        ) {
+            if !buffer.is_empty() {
+                let index = index.index(buffer.len());
+                buffer[index] ^= value;
+            }
+            parse_with_every_parser(&buffer);
+        }
+
+        #[test]
+        fn truncated_messages_are_rejected(
+            (command, buffer) in arb_serialized_message(),
+            index in any::<Index>(),
+        ) {
+            let truncated = buffer[..index.index(buffer.len())].to_vec();
+            prop_assert!(!parse_command(command, truncated));
+        }
+
+        #[test]
+        fn trailing_bytes_are_rejected(
+            (command, mut buffer) in arb_serialized_message(),
+            trailing in vec(any::<u8>(), 1..64),
+        ) {
+            // version no se valida: la especificacion permite campos opcionales al final (relay)
+            prop_assume!(command != "version");
+            buffer.extend(trailing);
+            prop_assert!(!parse_command(command, buffer));
+        }
+
+        #[test]
+        fn parsed_transactions_serialize_to_the_same_bytes(
+            transaction in arb_transaction(),
+            index in any::<Index>(),
+            value in any::<u8>(),
+        ) {
+            let mut buffer = transaction.serialize();
+            let index = index.index(buffer.len());
+            buffer[index] ^= value;
+            if let Ok(parsed) = Transaction::parse(buffer.clone()) {
+                prop_assert_eq!(parsed.serialize(), buffer);
+            }
+        }
+    }
+}